@@ -0,0 +1,72 @@
+//! Passthrough Example - switching between VR and AR
+//!
+//! Run:   cargo run -p passthrough                          (native shell)
+//! Quest: cargo run -p passthrough -- build --target quest  (APK in dist/)
+//!
+//! Space, or A/X on a controller, switches between a solid background and a
+//! transparent one. The transparent background shows the real world on
+//! Quest (passthrough) and the desktop behind the native shell's window;
+//! shells that can't show what's behind the app keep the solid one.
+
+use fastn::{
+    App, BackgroundData, Command, DebugCommand, EnvironmentCommand, Event, InputEvent, KeyboardEvent, LifecycleEvent,
+    LogLevel, MeshResource, ModelEntity, RealityViewContent, SimpleMaterial, XrEvent, FEATURE_TRANSPARENT_BACKGROUND,
+};
+
+/// The A/X button's index in the `xr-standard` layout
+const PRIMARY_BUTTON: usize = 4;
+
+const SOLID_BACKGROUND: [f32; 4] = [0.1, 0.1, 0.2, 1.0];
+
+#[derive(Default)]
+struct Passthrough {
+    /// Whether the shell can show what's behind the app
+    supported: bool,
+    transparent: bool,
+    /// Whether the left and right controllers' A/X buttons were down
+    pressed: [bool; 2],
+}
+
+#[fastn::app]
+impl App for Passthrough {
+    fn init(&mut self, content: &mut RealityViewContent) {
+        // At eye height, in front of the user
+        let cube = ModelEntity::new(MeshResource::generate_box(0.3), SimpleMaterial::new().color(0.2, 0.6, 0.9))
+            .position(0.0, 1.4, -1.0);
+        content.add(cube);
+    }
+
+    fn update(&mut self, event: &Event) -> Vec<Command> {
+        let toggle = match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                self.supported = init.features.iter().any(|f| f == FEATURE_TRANSPARENT_BACKGROUND);
+                false
+            }
+            Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyDown(key))) => key.code == "Space" && !key.repeat,
+            // Controllers report their buttons every frame: toggle on press
+            Event::Xr(XrEvent::ControllerPose(controller)) => {
+                let down = controller.buttons.get(PRIMARY_BUTTON).is_some_and(|&(_, pressed)| pressed);
+                let was_down = std::mem::replace(&mut self.pressed[controller.hand as usize], down);
+                down && !was_down
+            }
+            _ => false,
+        };
+        if !toggle {
+            return vec![];
+        }
+        if !self.supported {
+            return vec![Command::Debug(DebugCommand::Log {
+                level: LogLevel::Info,
+                message: "This shell can't show what's behind the app".to_string(),
+            })];
+        }
+
+        self.transparent = !self.transparent;
+        let background = if self.transparent {
+            BackgroundData::Transparent
+        } else {
+            BackgroundData::Color(SOLID_BACKGROUND)
+        };
+        vec![Command::Environment(EnvironmentCommand::SetBackground(background))]
+    }
+}
@@ -0,0 +1,199 @@
+//! Texture atlas packing
+//!
+//! Every directory `atlases/<name>/` of an app is packed into
+//! `assets/<name>.atlas.png` plus `assets/<name>.atlas.json`, which the app
+//! loads with `fastn::TextureAtlas::from_json`. Regions are named after the
+//! PNG files' paths within the directory, without the extension, so
+//! `atlases/ui/coin/0.png` becomes region `coin/0` of atlas `ui`.
+//!
+//! Images are packed on shelves, tallest first, with a transparent gap
+//! between them so filtering doesn't bleed neighbours into each other. An
+//! atlas is only repacked when its directory changed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Transparent pixels between packed images
+const PADDING: u32 = 2;
+
+/// Largest atlas side
+const MAX_ATLAS_SIZE: u32 = 4096;
+
+/// An RGBA8 image to pack
+struct Sprite {
+    name: String,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Pack each `atlases/<name>/` directory under `root` whose atlas is missing
+/// or older than its images
+pub fn pack_atlases(root: &Path) -> Result<(), String> {
+    let atlases_dir = root.join("atlases");
+    if !atlases_dir.is_dir() {
+        return Ok(());
+    }
+    let mut dirs: Vec<PathBuf> = fs::read_dir(&atlases_dir)
+        .map_err(|e| format!("Failed to read atlases/: {}", e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+
+    let assets_dir = root.join("assets");
+    for dir in dirs {
+        let name = dir.file_name().and_then(|n| n.to_str()).ok_or("Invalid atlas directory name")?;
+        let image_path = assets_dir.join(format!("{}.atlas.png", name));
+        let json_path = assets_dir.join(format!("{}.atlas.json", name));
+        if is_up_to_date(&dir, &[&image_path, &json_path]) {
+            continue;
+        }
+        println!("  Packing atlas {}...", name);
+        let sprites = read_sprites(&dir)?;
+        if sprites.is_empty() {
+            return Err(format!("Atlas {} has no PNG images", dir.display()));
+        }
+        let Layout { width, height, positions } = pack(&sprites).ok_or_else(|| {
+            format!("Atlas {} does not fit in {}x{}", name, MAX_ATLAS_SIZE, MAX_ATLAS_SIZE)
+        })?;
+
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        let mut regions = serde_json::Map::new();
+        for (sprite, (x, y)) in sprites.iter().zip(&positions) {
+            for row in 0..sprite.height {
+                let src = (row * sprite.width * 4) as usize;
+                let dst = (((y + row) * width + x) * 4) as usize;
+                let len = (sprite.width * 4) as usize;
+                pixels[dst..dst + len].copy_from_slice(&sprite.pixels[src..src + len]);
+            }
+            regions.insert(sprite.name.clone(), serde_json::json!([x, y, sprite.width, sprite.height]));
+        }
+
+        fs::create_dir_all(&assets_dir).map_err(|e| format!("Failed to create assets/: {}", e))?;
+        write_png(&image_path, width, height, &pixels)?;
+        let json = serde_json::json!({
+            "image": format!("{}.atlas.png", name),
+            "width": width,
+            "height": height,
+            "regions": regions,
+        });
+        let json = serde_json::to_string_pretty(&json).map_err(|e| e.to_string())?;
+        fs::write(&json_path, json).map_err(|e| format!("Failed to write {}: {}", json_path.display(), e))?;
+        println!("    {} images into {}x{}", sprites.len(), width, height);
+    }
+    Ok(())
+}
+
+/// Whether all outputs are newer than the directory and everything in it
+fn is_up_to_date(dir: &Path, outputs: &[&Path]) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let Some(oldest_output) = outputs.iter().map(|path| modified(path)).collect::<Option<Vec<_>>>() else {
+        return false;
+    };
+    let oldest_output = oldest_output.into_iter().min().unwrap_or(SystemTime::UNIX_EPOCH);
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .all(|entry| modified(entry.path()).is_some_and(|time| time <= oldest_output))
+}
+
+fn read_sprites(dir: &Path) -> Result<Vec<Sprite>, String> {
+    let mut sprites = vec![];
+    for entry in walkdir::WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.map_err(|e| format!("Failed to walk {}: {}", dir.display(), e))?;
+        let path = entry.path();
+        if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+            continue;
+        }
+        let name = path
+            .strip_prefix(dir)
+            .map_err(|e| e.to_string())?
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let (width, height, pixels) = read_png(path)?;
+        sprites.push(Sprite {
+            name,
+            width,
+            height,
+            pixels,
+        });
+    }
+    Ok(sprites)
+}
+
+/// Atlas size and the position of every sprite
+struct Layout {
+    width: u32,
+    height: u32,
+    positions: Vec<(u32, u32)>,
+}
+
+/// Find an atlas size and a position for every sprite: shelves filled left
+/// to right, tallest sprites first, in the narrowest power-of-two width
+/// whose shelves end up no taller than wide
+fn pack(sprites: &[Sprite]) -> Option<Layout> {
+    let mut order: Vec<usize> = (0..sprites.len()).collect();
+    order.sort_by_key(|&i| (std::cmp::Reverse(sprites[i].height), sprites[i].name.clone()));
+
+    let area: u64 = sprites
+        .iter()
+        .map(|s| (s.width + PADDING) as u64 * (s.height + PADDING) as u64)
+        .sum();
+    let widest = sprites.iter().map(|s| s.width + PADDING).max()?;
+    let mut width = ((area as f64).sqrt() as u32).max(widest).next_power_of_two();
+
+    while width <= MAX_ATLAS_SIZE {
+        let mut positions = vec![(0, 0); sprites.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for &i in &order {
+            let sprite = &sprites[i];
+            if x + sprite.width > width {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+            positions[i] = (x, y);
+            x += sprite.width + PADDING;
+            shelf_height = shelf_height.max(sprite.height + PADDING);
+        }
+        let height = (y + shelf_height).next_power_of_two();
+        if height <= width || (width == MAX_ATLAS_SIZE && height <= MAX_ATLAS_SIZE) {
+            return Some(Layout { width, height, positions });
+        }
+        width *= 2;
+    }
+    None
+}
+
+/// Decode a PNG into RGBA8
+fn read_png(path: &Path) -> Result<(u32, u32, Vec<u8>), String> {
+    let file = fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16 | png::Transformations::ALPHA);
+    let mut reader = decoder.read_info().map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut buffer = vec![0; reader.output_buffer_size().ok_or("PNG too large")?];
+    let info = reader.next_frame(&mut buffer).map_err(|e| format!("{}: {}", path.display(), e))?;
+    buffer.truncate(info.buffer_size());
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        other => return Err(format!("{}: unsupported PNG color type {:?}", path.display(), other)),
+    };
+    Ok((info.width, info.height, pixels))
+}
+
+fn write_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> Result<(), String> {
+    let file = fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| format!("{}: {}", path.display(), e))?;
+    writer
+        .write_image_data(pixels)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
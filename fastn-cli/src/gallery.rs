@@ -0,0 +1,222 @@
+//! Example gallery (`fastn examples`)
+//!
+//! Finds the example apps of the workspace (cdylib crates under `examples/`)
+//! and runs them all, to check the framework against every sample after a
+//! change:
+//! - web: builds each example and serves a gallery page linking to them
+//! - native: opens the native shell, Tab switches to the next example
+
+use crate::{CrateInfo, cargo_metadata, cmd_build, is_fastn_app, serve_directory};
+use std::fs;
+use std::path::PathBuf;
+
+/// An example app of the workspace
+struct Example {
+    info: CrateInfo,
+    description: String,
+}
+
+/// Outcome of building an example for the gallery
+struct GalleryEntry<'a> {
+    example: &'a Example,
+    result: Result<(), String>,
+}
+
+pub(crate) fn cmd_examples(list: bool, native: bool, release: bool, port: u16) -> Result<(), String> {
+    let examples = find_examples()?;
+    if examples.is_empty() {
+        return Err("No examples found (expected cdylib crates under examples/)".to_string());
+    }
+
+    if list {
+        for example in &examples {
+            println!("{:<16} {}", example.info.name, example.description);
+        }
+        return Ok(());
+    }
+
+    match native {
+        true => run_native(&examples, release),
+        false => serve_gallery(&examples, release, port),
+    }
+}
+
+/// Example apps in the workspace, sorted by name
+fn find_examples() -> Result<Vec<Example>, String> {
+    let metadata = cargo_metadata()?;
+
+    let target_dir = metadata
+        .get("target_directory")
+        .and_then(|v| v.as_str())
+        .map(PathBuf::from)
+        .ok_or("Could not find target_directory in cargo metadata")?;
+
+    let examples_dir = metadata
+        .get("workspace_root")
+        .and_then(|v| v.as_str())
+        .map(|root| PathBuf::from(root).join("examples"))
+        .ok_or("Could not find workspace_root in cargo metadata")?;
+
+    let packages = metadata
+        .get("packages")
+        .and_then(|v| v.as_array())
+        .ok_or("Could not find packages in cargo metadata")?;
+
+    let mut examples = vec![];
+    for pkg in packages.iter().filter(|pkg| is_fastn_app(pkg)) {
+        let Some(root) = pkg
+            .get("manifest_path")
+            .and_then(|v| v.as_str())
+            .and_then(|path| PathBuf::from(path).parent().map(|p| p.to_path_buf()))
+        else {
+            continue;
+        };
+        if !root.starts_with(&examples_dir) {
+            continue;
+        }
+        let name = pkg
+            .get("name")
+            .and_then(|v| v.as_str())
+            .ok_or("Could not find package name")?
+            .to_string();
+        let description = pkg
+            .get("description")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        examples.push(Example {
+            info: CrateInfo {
+                name,
+                root,
+                target_dir: target_dir.clone(),
+            },
+            description,
+        });
+    }
+
+    examples.sort_by(|a, b| a.info.name.cmp(&b.info.name));
+    Ok(examples)
+}
+
+/// Build every example into one directory and serve it with an index page.
+/// Examples that fail to build are listed with their error.
+fn serve_gallery(examples: &[Example], release: bool, port: u16) -> Result<(), String> {
+    let gallery_dir = examples[0].info.target_dir.join("fastn-examples");
+    let _ = fs::remove_dir_all(&gallery_dir);
+    fs::create_dir_all(&gallery_dir).map_err(|e| format!("Failed to create gallery directory: {}", e))?;
+
+    let mut entries = vec![];
+    for example in examples {
+        let output = gallery_dir.join(&example.info.name);
+        let output = output.to_str().ok_or("Invalid gallery path")?;
+        let result = cmd_build(&example.info, release, output);
+        if let Err(e) = &result {
+            eprintln!("Failed to build {}: {}", example.info.name, e);
+        }
+        entries.push(GalleryEntry { example, result });
+    }
+
+    fs::write(gallery_dir.join("index.html"), gallery_html(&entries))
+        .map_err(|e| format!("Failed to write gallery index.html: {}", e))?;
+
+    let failed = entries.iter().filter(|e| e.result.is_err()).count();
+    println!("\nBuilt {} of {} examples", entries.len() - failed, entries.len());
+    println!("Gallery on http://localhost:{}", port);
+    println!("Press Ctrl+C to stop\n");
+
+    serve_directory(&gallery_dir, port, None)
+}
+
+#[cfg(feature = "native-shell")]
+fn run_native(examples: &[Example], release: bool) -> Result<(), String> {
+    let mut wasm_paths = vec![];
+    for example in examples {
+        println!("Building {} for native...", example.info.name);
+        let wasm_path = crate::build_wasm(&example.info, release)?;
+        wasm_paths.push(wasm_path.to_str().ok_or("Invalid WASM path")?.to_string());
+    }
+
+    println!("Running native shell (Tab: next example, Esc: quit)...\n");
+    fastn_shell::run_gallery(wasm_paths)
+}
+
+#[cfg(not(feature = "native-shell"))]
+fn run_native(_examples: &[Example], _release: bool) -> Result<(), String> {
+    Err("Native shell support is not enabled. Build with --features native-shell or use default features.".to_string())
+}
+
+fn gallery_html(entries: &[GalleryEntry]) -> String {
+    let mut cards = String::new();
+    for entry in entries {
+        let name = escape_html(&entry.example.info.name);
+        let description = escape_html(&entry.example.description);
+        let card = match &entry.result {
+            Ok(()) => format!(
+                "        <a class=\"card\" href=\"./{name}/\">\n            <h2>{name}</h2>\n            <p>{description}</p>\n        </a>\n"
+            ),
+            Err(e) => format!(
+                "        <div class=\"card failed\">\n            <h2>{name}</h2>\n            <p>{description}</p>\n            <pre>{}</pre>\n        </div>\n",
+                escape_html(e)
+            ),
+        };
+        cards.push_str(&card);
+    }
+    GALLERY_HTML_TEMPLATE.replace("{{CARDS}}", &cards)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Gallery index page, {{CARDS}} is replaced by one card per example
+const GALLERY_HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>Examples - fastn</title>
+    <style>
+        * { margin: 0; padding: 0; box-sizing: border-box; }
+        body {
+            background: #1a1a2e;
+            color: #ddd;
+            font-family: monospace;
+            padding: 32px;
+        }
+        h1 { font-size: 20px; margin-bottom: 24px; }
+        .gallery {
+            display: grid;
+            grid-template-columns: repeat(auto-fill, minmax(240px, 1fr));
+            gap: 16px;
+        }
+        .card {
+            display: block;
+            background: #16213e;
+            border: 1px solid #2a3a5e;
+            border-radius: 6px;
+            color: inherit;
+            padding: 16px;
+            text-decoration: none;
+        }
+        a.card:hover { border-color: #6b9fff; }
+        .card h2 { font-size: 16px; margin-bottom: 8px; }
+        .card p { color: #888; font-size: 13px; }
+        .card.failed { border-color: #ff6b6b; }
+        .card pre {
+            color: #ff6b6b;
+            font-size: 12px;
+            margin-top: 8px;
+            white-space: pre-wrap;
+        }
+    </style>
+</head>
+<body>
+    <h1>fastn examples</h1>
+    <div class="gallery">
+{{CARDS}}    </div>
+</body>
+</html>
+"#;
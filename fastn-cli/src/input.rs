@@ -0,0 +1,60 @@
+//! Input automation from the command line
+//!
+//! `run --listen` starts the native shell accepting input scripts on a
+//! loopback port, and `input <script>` sends one to it. `run --script` and
+//! `run --record` play a script from the start and record the session.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpStream};
+use std::path::{Path, PathBuf};
+
+/// Input script options of `run`
+#[derive(clap::Args, Default)]
+pub(crate) struct AutomationArgs {
+    /// Accept input scripts from `input` on this loopback port (default
+    /// 7878, `fastn_protocol::DEFAULT_AUTOMATION_PORT`)
+    #[arg(long, value_name = "PORT", num_args = 0..=1, default_missing_value = "7878")]
+    pub listen: Option<u16>,
+
+    /// Play an input script once the app is loaded
+    #[arg(long, value_name = "FILE")]
+    pub script: Option<PathBuf>,
+
+    /// Record the session's events and commands; played as an input
+    /// script, a recording sends its input again
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Replay a recorded session: its events go to the app instead of the
+    /// live ones, a frame at a time
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+}
+
+/// `input`: check a script and send it to the shell listening on `port`,
+/// which answers once it has scheduled the events
+pub(crate) fn cmd_input(script: &Path, port: u16) -> Result<(), String> {
+    let text = fs::read_to_string(script).map_err(|e| format!("Failed to read {}: {}", script.display(), e))?;
+    let events = fastn_protocol::parse_input_script(&text).map_err(|e| format!("{}: {}", script.display(), e))?;
+
+    let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).map_err(|e| {
+        format!("No shell is listening on port {} ({}); start one with `cargo run -- run --listen`", port, e)
+    })?;
+    stream
+        .write_all(text.as_bytes())
+        .and_then(|()| stream.shutdown(Shutdown::Write))
+        .map_err(|e| format!("Failed to send the script: {}", e))?;
+
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .map_err(|e| format!("Failed to read the shell's reply: {}", e))?;
+    match reply.trim().strip_prefix("error: ") {
+        Some(error) => Err(format!("{}: {}", script.display(), error)),
+        None => {
+            println!("Sent {} events to the shell on port {}", events.len(), port);
+            Ok(())
+        }
+    }
+}
@@ -12,11 +12,24 @@
 //! Then run:
 //! - `cargo run` - Run native shell (default)
 //! - `cargo run -- build` - Build for web (creates dist/)
+//! - `cargo run -- build --all` - Build every app of a `fastn-workspace.toml`
+//! - `cargo run -- build --target quest` - Build an APK for Meta Quest
 //! - `cargo run -- serve` - Build and serve web version
-
+//! - `cargo run -- serve --watch` - Also rebuild on changes and reload the page
+//! - `cargo run -- examples` - Build all workspace examples and serve a gallery
+//! - `cargo run -- atlas` - Pack `atlases/<name>/` into texture atlases
+//! - `cargo run -- input <script>` - Send an input script to a shell started
+//!   with `run --listen`
+
+mod atlas;
+mod gallery;
+mod input;
+mod live_reload;
+mod quest;
 mod web_shell;
+mod workspace;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -32,7 +45,7 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Build the app for web (creates dist/ folder)
+    /// Build the app for web (creates dist/ folder) or Quest
     Build {
         /// Build in release mode
         #[arg(long, default_value = "true")]
@@ -41,6 +54,15 @@ enum Commands {
         /// Output directory
         #[arg(short, long, default_value = "dist")]
         output: String,
+
+        /// Platform to build for
+        #[arg(long, value_enum, default_value_t = BuildTarget::Web)]
+        target: BuildTarget,
+
+        /// Build every app of the workspace (fastn-workspace.toml) into its
+        /// dist, sharing common assets
+        #[arg(long)]
+        all: bool,
     },
     /// Build and serve the web version
     Serve {
@@ -51,20 +73,98 @@ enum Commands {
         /// Build in release mode
         #[arg(long, default_value = "true")]
         release: bool,
+
+        /// Rebuild when the app's sources change and reload open pages
+        #[arg(long)]
+        watch: bool,
     },
     /// Run the native shell (default if no subcommand)
     Run {
         /// Build in release mode
         #[arg(long, default_value = "true")]
         release: bool,
+
+        #[command(flatten)]
+        automation: input::AutomationArgs,
+    },
+    /// Build all example apps of the workspace and serve a gallery of them
+    Examples {
+        /// Only list the examples
+        #[arg(long)]
+        list: bool,
+
+        /// Cycle through the examples in the native shell (Tab switches)
+        #[arg(long)]
+        native: bool,
+
+        /// Port to serve the gallery on
+        #[arg(short, long, default_value = "8080")]
+        port: u16,
+
+        /// Build in release mode
+        #[arg(long, default_value = "true")]
+        release: bool,
+    },
+    /// Pack the images in atlases/<name>/ into assets/<name>.atlas.png and
+    /// .atlas.json (also done by every build)
+    Atlas,
+    /// Send an input script (JSON Lines of synthetic input) to a native
+    /// shell started with `run --listen`
+    Input {
+        /// The script
+        script: PathBuf,
+
+        /// Port the shell listens on
+        #[arg(short, long, default_value_t = fastn_protocol::DEFAULT_AUTOMATION_PORT)]
+        port: u16,
     },
 }
 
+/// Platforms `build` can build for
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BuildTarget {
+    /// dist/ folder with the web shell
+    Web,
+    /// Android APK for Meta Quest headsets (needs cargo-apk)
+    Quest,
+}
+
 /// Main entry point for fastn CLI
 /// Called from app's main.rs: `fn main() { fastn::main(); }`
 pub fn main() {
     let cli = Cli::parse();
 
+    // Examples span the whole workspace, not the current crate
+    if let Some(Commands::Examples { list, native, port, release }) = cli.command {
+        if let Err(e) = gallery::cmd_examples(list, native, release, port) {
+            eprintln!("Examples failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Scripts go to a running shell, whatever the current crate
+    if let Some(Commands::Input { script, port }) = &cli.command {
+        if let Err(e) = input::cmd_input(script, *port) {
+            eprintln!("Input failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Workspace builds span several apps
+    if let Some(Commands::Build { release, all: true, target, .. }) = cli.command {
+        if target != BuildTarget::Web {
+            eprintln!("Build failed: --all only builds for the web");
+            std::process::exit(1);
+        }
+        if let Err(e) = workspace::cmd_build_all(release) {
+            eprintln!("Build failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Get crate info
     let crate_info = match get_crate_info() {
         Ok(info) => info,
@@ -76,27 +176,38 @@ pub fn main() {
     };
 
     match cli.command {
-        Some(Commands::Build { release, output }) => {
-            if let Err(e) = cmd_build(&crate_info, release, &output) {
+        Some(Commands::Build { release, output, target, .. }) => {
+            let result = match target {
+                BuildTarget::Web => cmd_build(&crate_info, release, &output),
+                BuildTarget::Quest => quest::cmd_build_quest(&crate_info, release, &output),
+            };
+            if let Err(e) = result {
                 eprintln!("Build failed: {}", e);
                 std::process::exit(1);
             }
         }
-        Some(Commands::Serve { port, release }) => {
-            if let Err(e) = cmd_serve(&crate_info, release, port) {
+        Some(Commands::Serve { port, release, watch }) => {
+            if let Err(e) = cmd_serve(crate_info, release, port, watch) {
                 eprintln!("Serve failed: {}", e);
                 std::process::exit(1);
             }
         }
-        Some(Commands::Run { release }) => {
-            if let Err(e) = cmd_run(&crate_info, release) {
+        Some(Commands::Run { release, automation }) => {
+            if let Err(e) = cmd_run(&crate_info, release, automation) {
                 eprintln!("Run failed: {}", e);
                 std::process::exit(1);
             }
         }
+        Some(Commands::Atlas) => {
+            if let Err(e) = atlas::pack_atlases(&crate_info.root) {
+                eprintln!("Atlas packing failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Examples { .. } | Commands::Input { .. }) => unreachable!("handled above"),
         None => {
             // Default: run with release=true
-            if let Err(e) = cmd_run(&crate_info, true) {
+            if let Err(e) = cmd_run(&crate_info, true, input::AutomationArgs::default()) {
                 eprintln!("Run failed: {}", e);
                 std::process::exit(1);
             }
@@ -117,17 +228,7 @@ fn get_crate_info() -> Result<CrateInfo, String> {
     }
 
     // Use cargo metadata to get complete information about the project
-    let output = Command::new("cargo")
-        .args(["metadata", "--format-version=1", "--no-deps"])
-        .output()
-        .map_err(|e| format!("Failed to run cargo metadata: {}", e))?;
-
-    if !output.status.success() {
-        return Err("Failed to get cargo metadata".to_string());
-    }
-
-    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout)
-        .map_err(|e| format!("Failed to parse cargo metadata: {}", e))?;
+    let metadata = cargo_metadata()?;
 
     let target_dir = metadata
         .get("target_directory")
@@ -147,29 +248,7 @@ fn get_crate_info() -> Result<CrateInfo, String> {
         .and_then(|v| v.as_array())
         .ok_or("Could not find packages in cargo metadata")?;
 
-    // Filter to packages that have cdylib target (i.e., are fastn apps)
-    // Exclude "fastn" itself since it's a library, not an app
-    let cdylib_packages: Vec<_> = packages
-        .iter()
-        .filter(|pkg| {
-            // Skip the fastn library itself
-            if pkg.get("name").and_then(|v| v.as_str()) == Some("fastn") {
-                return false;
-            }
-            pkg.get("targets")
-                .and_then(|t| t.as_array())
-                .map(|targets| {
-                    targets.iter().any(|target| {
-                        target
-                            .get("crate_types")
-                            .and_then(|ct| ct.as_array())
-                            .map(|types| types.iter().any(|t| t.as_str() == Some("cdylib")))
-                            .unwrap_or(false)
-                    })
-                })
-                .unwrap_or(false)
-        })
-        .collect();
+    let cdylib_packages: Vec<_> = packages.iter().filter(|pkg| is_fastn_app(pkg)).collect();
 
     let (name, root) = if cdylib_packages.len() == 1 {
         // Single cdylib package - use it
@@ -221,6 +300,40 @@ fn get_crate_info() -> Result<CrateInfo, String> {
     })
 }
 
+/// Run `cargo metadata` for the workspace (without dependencies)
+fn cargo_metadata() -> Result<serde_json::Value, String> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version=1", "--no-deps"])
+        .output()
+        .map_err(|e| format!("Failed to run cargo metadata: {}", e))?;
+
+    if !output.status.success() {
+        return Err("Failed to get cargo metadata".to_string());
+    }
+
+    serde_json::from_slice(&output.stdout).map_err(|e| format!("Failed to parse cargo metadata: {}", e))
+}
+
+/// Whether a cargo metadata package is a fastn app: it has a cdylib target
+/// (the WASM core) and isn't the fastn library itself
+fn is_fastn_app(pkg: &serde_json::Value) -> bool {
+    if pkg.get("name").and_then(|v| v.as_str()) == Some("fastn") {
+        return false;
+    }
+    pkg.get("targets")
+        .and_then(|t| t.as_array())
+        .map(|targets| {
+            targets.iter().any(|target| {
+                target
+                    .get("crate_types")
+                    .and_then(|ct| ct.as_array())
+                    .map(|types| types.iter().any(|t| t.as_str() == Some("cdylib")))
+                    .unwrap_or(false)
+            })
+        })
+        .unwrap_or(false)
+}
+
 fn cmd_build(crate_info: &CrateInfo, release: bool, output: &str) -> Result<(), String> {
     println!("Building {} for web...", crate_info.name);
 
@@ -242,6 +355,7 @@ fn cmd_build(crate_info: &CrateInfo, release: bool, output: &str) -> Result<(),
     let dist_wasm = dist_dir.join(&wasm_filename);
     fs::copy(&wasm_path, &dist_wasm).map_err(|e| format!("Failed to copy WASM: {}", e))?;
     println!("  Created {}", wasm_filename);
+    remove_old_wasm(&dist_dir, &crate_info.name, &wasm_filename)?;
 
     // Write shell JS files
     fs::write(dist_dir.join("shell-common.js"), web_shell::SHELL_COMMON_JS)
@@ -278,20 +392,25 @@ fn cmd_build(crate_info: &CrateInfo, release: bool, output: &str) -> Result<(),
     Ok(())
 }
 
-fn cmd_serve(crate_info: &CrateInfo, release: bool, port: u16) -> Result<(), String> {
+fn cmd_serve(crate_info: CrateInfo, release: bool, port: u16, watch: bool) -> Result<(), String> {
     // First build
-    cmd_build(crate_info, release, "dist")?;
+    cmd_build(&crate_info, release, "dist")?;
 
     let dist_dir = crate_info.root.join("dist");
 
     println!("\nStarting HTTP server on http://localhost:{}", port);
+    let reloader = watch.then(live_reload::Reloader::default);
+    if let Some(reloader) = reloader.clone() {
+        println!("Watching {} for changes", crate_info.root.display());
+        std::thread::spawn(move || live_reload::watch(&crate_info, release, "dist", &reloader));
+    }
     println!("Press Ctrl+C to stop\n");
 
-    serve_directory(&dist_dir, port)
+    serve_directory(&dist_dir, port, reloader.as_ref())
 }
 
 #[cfg(feature = "native-shell")]
-fn cmd_run(crate_info: &CrateInfo, release: bool) -> Result<(), String> {
+fn cmd_run(crate_info: &CrateInfo, release: bool, automation: input::AutomationArgs) -> Result<(), String> {
     println!("Building {} for native...", crate_info.name);
 
     // Build WASM first
@@ -300,16 +419,25 @@ fn cmd_run(crate_info: &CrateInfo, release: bool) -> Result<(), String> {
     println!("Running native shell...\n");
 
     // Call fastn-shell directly as a library
-    fastn_shell::run(wasm_path.to_str().ok_or("Invalid WASM path")?)
+    let automation = fastn_shell::AutomationOptions {
+        listen: automation.listen,
+        script: automation.script,
+        record: automation.record,
+        replay: automation.replay,
+    };
+    fastn_shell::run_with(wasm_path.to_str().ok_or("Invalid WASM path")?, automation)
 }
 
 #[cfg(not(feature = "native-shell"))]
-fn cmd_run(_crate_info: &CrateInfo, _release: bool) -> Result<(), String> {
+fn cmd_run(_crate_info: &CrateInfo, _release: bool, _automation: input::AutomationArgs) -> Result<(), String> {
     Err("Native shell support is not enabled. Build with --features native-shell or use default features.\n\
          For CI builds that only need 'build' or 'serve', use: cargo run --no-default-features -- build".to_string())
 }
 
 fn build_wasm(crate_info: &CrateInfo, release: bool) -> Result<PathBuf, String> {
+    // Apps include the atlas descriptions, so pack before compiling
+    atlas::pack_atlases(&crate_info.root)?;
+
     let mut cmd = Command::new("cargo");
     cmd.arg("build")
         .arg("--lib") // Only build library target (not binary)
@@ -350,6 +478,24 @@ fn build_wasm(crate_info: &CrateInfo, release: bool) -> Result<PathBuf, String>
     println!("  Built {:?}", wasm_path);
     Ok(wasm_path)
 }
+/// Remove the app's WASM files from earlier builds, e.g. left by
+/// `serve --watch`
+fn remove_old_wasm(dist_dir: &Path, name: &str, current: &str) -> Result<(), String> {
+    let prefix = format!("{}-", name);
+    let entries = fs::read_dir(dist_dir).map_err(|e| format!("Failed to read {}: {}", dist_dir.display(), e))?;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let is_old = file_name != current
+            && file_name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".wasm"))
+                .is_some_and(|hash| hash.len() == 8 && hash.chars().all(|c| c.is_ascii_hexdigit()));
+        if is_old {
+            fs::remove_file(entry.path()).map_err(|e| format!("Failed to remove {}: {}", file_name, e))?;
+        }
+    }
+    Ok(())
+}
 
 fn compute_hash(data: &[u8]) -> String {
     let mut hasher = Sha256::new();
@@ -357,50 +503,83 @@ fn compute_hash(data: &[u8]) -> String {
     format!("{:x}", hasher.finalize())
 }
 
-fn copy_assets(crate_info: &CrateInfo, dist_dir: &Path) -> Result<(), String> {
-    let assets_dir = crate_info.root.join("assets");
-    if !assets_dir.exists() {
-        return Ok(());
+/// The files bundled with an app, as paths relative to the bundle root and
+/// the files they come from: the workspace's shared assets, then the app's
+/// `assets/` (which win)
+fn asset_files(crate_info: &CrateInfo) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let workspace = workspace::Workspace::find(&crate_info.root)?;
+    let dirs = [
+        workspace.as_ref().and_then(|w| w.assets()).map(Path::to_path_buf),
+        Some(crate_info.root.join("assets")),
+    ];
+
+    let mut files = std::collections::BTreeMap::new();
+    for assets_dir in dirs.into_iter().flatten().filter(|dir| dir.is_dir()) {
+        for entry in walkdir::WalkDir::new(&assets_dir) {
+            let entry = entry.map_err(|e| format!("Failed to walk assets: {}", e))?;
+            let path = entry.path();
+
+            if path.is_file() {
+                let relative = path
+                    .strip_prefix(&assets_dir)
+                    .map_err(|e| format!("Failed to get relative path: {}", e))?;
+                files.insert(relative.to_path_buf(), path.to_path_buf());
+            }
+        }
     }
+    Ok(files.into_iter().collect())
+}
 
-    println!("  Copying assets...");
-
-    for entry in walkdir::WalkDir::new(&assets_dir) {
-        let entry = entry.map_err(|e| format!("Failed to walk assets: {}", e))?;
-        let path = entry.path();
-
-        if path.is_file() {
-            let relative = path
-                .strip_prefix(&assets_dir)
-                .map_err(|e| format!("Failed to get relative path: {}", e))?;
-            let dest = dist_dir.join(relative);
+fn copy_assets(crate_info: &CrateInfo, dist_dir: &Path) -> Result<(), String> {
+    let files = asset_files(crate_info)?;
+    if !files.is_empty() {
+        println!("  Copying assets...");
+    }
 
-            if let Some(parent) = dest.parent() {
-                fs::create_dir_all(parent)
-                    .map_err(|e| format!("Failed to create directory: {}", e))?;
-            }
+    for (relative, path) in files {
+        let dest = dist_dir.join(&relative);
 
-            fs::copy(path, &dest)
-                .map_err(|e| format!("Failed to copy asset {:?}: {}", path, e))?;
-            println!("    {}", relative.display());
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
         }
+
+        fs::copy(&path, &dest)
+            .map_err(|e| format!("Failed to copy asset {:?}: {}", path, e))?;
+        println!("    {}", relative.display());
     }
 
+    // Nothing is shared with other apps; `build --all` rewrites it
+    fs::write(dist_dir.join(workspace::ASSET_MAP_FILE), "{}")
+        .map_err(|e| format!("Failed to write {}: {}", workspace::ASSET_MAP_FILE, e))?;
+
     Ok(())
 }
 
-fn serve_directory(dir: &Path, port: u16) -> Result<(), String> {
+/// Serve `dir` over HTTP. With a `reloader`, pages get the live reload
+/// script and can connect to `live_reload::RELOAD_ENDPOINT`.
+fn serve_directory(dir: &Path, port: u16, reloader: Option<&live_reload::Reloader>) -> Result<(), String> {
     let server = tiny_http::Server::http(format!("0.0.0.0:{}", port))
         .map_err(|e| format!("Failed to start HTTP server: {}", e))?;
 
     for request in server.incoming_requests() {
         let url = request.url().to_string();
+        if let Some(reloader) = reloader.filter(|_| url == live_reload::RELOAD_ENDPOINT) {
+            reloader.accept(request);
+            continue;
+        }
         let path = if url == "/" { "/index.html" } else { &url };
-        let file_path = dir.join(&path[1..]); // Remove leading /
+        let mut file_path = dir.join(&path[1..]); // Remove leading /
+        if file_path.is_dir() {
+            file_path = file_path.join("index.html");
+        }
 
         let response = if file_path.exists() && file_path.is_file() {
-            let content = fs::read(&file_path).unwrap_or_default();
+            let mut content = fs::read(&file_path).unwrap_or_default();
             let content_type = get_content_type(&file_path);
+            if reloader.is_some() && content_type == "text/html" {
+                content = live_reload::Reloader::inject(content);
+            }
 
             tiny_http::Response::from_data(content)
                 .with_header(
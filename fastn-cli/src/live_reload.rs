@@ -0,0 +1,132 @@
+//! Live reload for `serve --watch`
+//!
+//! The app's sources are polled for changes, cargo-watch style. Once a burst
+//! of changes settles, the app is rebuilt and every open page is told to
+//! reload over server-sent events at `RELOAD_ENDPOINT`; the reloaded page loads the
+//! new hashed WASM file. `serve_directory` adds the script that listens for
+//! it to HTML pages. A build that fails is reported and the last good one
+//! stays up.
+
+use crate::{CrateInfo, cmd_build, workspace};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Event stream pages follow for reload signals
+pub(crate) const RELOAD_ENDPOINT: &str = "/_fastn/reload";
+
+/// How often the sources are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Start of the event stream: no length, and reconnect after a second
+const STREAM_HEAD: &[u8] = b"HTTP/1.1 200 OK\r\n\
+    Content-Type: text/event-stream\r\n\
+    Cache-Control: no-cache\r\n\
+    Connection: close\r\n\
+    \r\n\
+    retry: 1000\n\n";
+
+/// Reloads the page on a signal, and once the server is back after a
+/// restart (`EventSource` reconnects by itself)
+const RELOAD_SCRIPT: &str = r#"<script>
+(() => {
+    let connected = false;
+    const events = new EventSource("/_fastn/reload");
+    events.onopen = () => connected ? location.reload() : connected = true;
+    events.onmessage = () => location.reload();
+})();
+</script>
+"#;
+
+/// Pages connected to `RELOAD_ENDPOINT`
+#[derive(Clone, Default)]
+pub(crate) struct Reloader {
+    pages: Arc<Mutex<Vec<Box<dyn Write + Send>>>>,
+}
+
+impl Reloader {
+    /// Answer a request to `RELOAD_ENDPOINT`: start an event stream and
+    /// keep it open for `reload`
+    pub(crate) fn accept(&self, request: tiny_http::Request) {
+        let mut page = request.into_writer();
+        if page.write_all(STREAM_HEAD).and_then(|()| page.flush()).is_ok() {
+            self.pages.lock().unwrap().push(page);
+        }
+    }
+
+    /// Tell every connected page to reload, forgetting those that closed
+    pub(crate) fn reload(&self) {
+        let mut pages = self.pages.lock().unwrap();
+        pages.retain_mut(|page| page.write_all(b"data: reload\n\n").and_then(|()| page.flush()).is_ok());
+        println!("  Reloading {} page(s)", pages.len());
+    }
+
+    /// Add the reload script to an HTML page, at the end of its body
+    pub(crate) fn inject(html: Vec<u8>) -> Vec<u8> {
+        let html = String::from_utf8_lossy(&html);
+        match html.rfind("</body>") {
+            Some(end) => format!("{}{}{}", &html[..end], RELOAD_SCRIPT, &html[end..]).into_bytes(),
+            None => format!("{}{}", html, RELOAD_SCRIPT).into_bytes(),
+        }
+    }
+}
+
+/// Rebuild the app into `output` whenever its sources change, then reload
+/// the pages. Runs until the process exits.
+pub(crate) fn watch(crate_info: &CrateInfo, release: bool, output: &str, reloader: &Reloader) {
+    let mut built = sources(crate_info, output);
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let mut current = sources(crate_info, output);
+        if current == built {
+            continue;
+        }
+        // Let a burst of saves (or a branch switch) finish first
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let next = sources(crate_info, output);
+            if next == current {
+                break;
+            }
+            current = next;
+        }
+        built = current;
+
+        println!("\nSources changed, rebuilding...");
+        match cmd_build(crate_info, release, output) {
+            Ok(()) => reloader.reload(),
+            Err(e) => eprintln!("Build failed: {}\nStill serving the previous build", e),
+        }
+    }
+}
+
+/// Every source file with its modification time and size: the app's
+/// directory without build output and hidden files, and the workspace's
+/// shared assets
+fn sources(crate_info: &CrateInfo, output: &str) -> Vec<(PathBuf, SystemTime, u64)> {
+    let skipped = [crate_info.root.join(output), crate_info.target_dir.clone(), crate_info.root.join("target")];
+    let shared_assets = workspace::Workspace::find(&crate_info.root)
+        .ok()
+        .flatten()
+        .and_then(|w| w.assets().map(Path::to_path_buf));
+
+    let mut files = vec![];
+    for dir in std::iter::once(crate_info.root.clone()).chain(shared_assets) {
+        let entries = walkdir::WalkDir::new(&dir).into_iter().filter_entry(|entry| {
+            let hidden = entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.');
+            !hidden && !skipped.iter().any(|skip| entry.path() == skip)
+        });
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_file() {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                files.push((entry.into_path(), modified, metadata.len()));
+            }
+        }
+    }
+    files.sort();
+    files
+}
@@ -0,0 +1,228 @@
+//! Quest builds (`build --target quest`)
+//!
+//! The app's core is bundled with the Quest shell (`fastn-shell-quest/`), an
+//! OpenXR app for Android, into an installable APK:
+//!
+//! 1. the core is built for wasm32-unknown-unknown, as for the web
+//! 2. a cargo-apk package for the shell is generated in
+//!    `<target>/fastn-quest/<app>/`, with the app's manifest and assets, and
+//!    the core as the `app.wasm` asset
+//! 3. `cargo apk build` builds it, and the APK is copied to
+//!    `<output>/<app>.apk`
+//!
+//! The manifest can be adjusted in the app's Cargo.toml:
+//!
+//! ```toml
+//! [package.metadata.fastn.quest]
+//! package = "com.example.viewer" # default: com.fastn.<app>
+//! label = "Viewer"               # default: the app's name
+//! ```
+//!
+//! Meta's OpenXR loader can't be redistributed, so apps provide it in
+//! `libs/arm64-v8a/libopenxr_loader.so` (see quest-test/README.md).
+
+use crate::{asset_files, build_wasm, CrateInfo};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Shell sources, written into the generated package
+const SHELL_LIB_RS: &str = include_str!("../../fastn-shell-quest/lib.rs");
+const SHELL_WASM_CORE_RS: &str = include_str!("../../fastn-shell-quest/wasm_core.rs");
+const SHELL_INPUT_RS: &str = include_str!("../../fastn-shell-quest/input.rs");
+const SHELL_PASSTHROUGH_RS: &str = include_str!("../../fastn-shell-quest/passthrough.rs");
+const SHELL_RENDERER_RS: &str = include_str!("../../fastn-shell-quest/renderer.rs");
+const SHELL_SHADER: &str = include_str!("../../fastn-shell-quest/shader.wgsl");
+
+/// Cargo.toml of the generated package, with TOML string placeholders:
+/// {{APP_NAME}}, {{VERSION}}, {{PACKAGE_ID}}, {{LABEL}}, {{RUNTIME_LIBS}}
+/// and {{FASTN_PROTOCOL}}
+const CARGO_TOML_TEMPLATE: &str = include_str!("../../fastn-shell-quest/Cargo.toml.tmpl");
+
+/// The core's name among the APK's assets, read by the shell at launch
+const APP_WASM: &str = "app.wasm";
+
+/// Where apps keep Meta's OpenXR loader
+const OPENXR_LOADER: &str = "libs/arm64-v8a/libopenxr_loader.so";
+
+/// What goes into the Android manifest
+struct Manifest {
+    /// Android package name, e.g. `com.fastn.cube`
+    package: String,
+    label: String,
+    version: String,
+}
+
+impl Manifest {
+    /// Read `[package.metadata.fastn.quest]` from the app's Cargo.toml
+    fn load(crate_info: &CrateInfo) -> Result<Self, String> {
+        let path = crate_info.root.join("Cargo.toml");
+        let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let parsed: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        let package = parsed.get("package");
+        let quest = package
+            .and_then(|p| p.get("metadata"))
+            .and_then(|m| m.get("fastn"))
+            .and_then(|f| f.get("quest"));
+        let setting = |key: &str| quest.and_then(|q| q.get(key)).and_then(|v| v.as_str()).map(str::to_string);
+
+        let manifest = Self {
+            package: setting("package")
+                .unwrap_or_else(|| format!("com.fastn.{}", crate_info.name.replace('-', "_"))),
+            label: setting("label").unwrap_or_else(|| crate_info.name.clone()),
+            // `version.workspace = true` isn't a string
+            version: package
+                .and_then(|p| p.get("version"))
+                .and_then(|v| v.as_str())
+                .unwrap_or("0.1.0")
+                .to_string(),
+        };
+        validate_package(&manifest.package)?;
+        Ok(manifest)
+    }
+}
+
+/// Android package names are two or more dot-separated Java identifiers
+fn validate_package(package: &str) -> Result<(), String> {
+    let valid = package.split('.').count() >= 2
+        && package.split('.').all(|part| {
+            part.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid Android package name {:?}; set package.metadata.fastn.quest.package in Cargo.toml, \
+             e.g. \"com.example.myapp\"",
+            package
+        ))
+    }
+}
+
+pub(crate) fn cmd_build_quest(crate_info: &CrateInfo, release: bool, output: &str) -> Result<(), String> {
+    println!("Building {} for Quest...", crate_info.name);
+
+    let manifest = Manifest::load(crate_info)?;
+    let loader = crate_info.root.join(OPENXR_LOADER);
+    if !loader.is_file() {
+        return Err(format!(
+            "Meta's OpenXR loader not found at {}.\n\
+             Download the Oculus OpenXR Mobile SDK from \
+             https://developer.oculus.com/downloads/package/oculus-openxr-mobile-sdk/\n\
+             and copy OpenXR/Libs/Android/arm64-v8a/Release/libopenxr_loader.so there.",
+            loader.display()
+        ));
+    }
+    check_cargo_apk()?;
+
+    let wasm_path = build_wasm(crate_info, release)?;
+    let project = generate_project(crate_info, &manifest, &wasm_path)?;
+
+    let mut cmd = Command::new("cargo");
+    cmd.args(["apk", "build", "--lib"]).current_dir(&project);
+    if release {
+        cmd.arg("--release");
+    }
+    // The package has its own target directory
+    cmd.env_remove("CARGO_TARGET_DIR");
+    println!(
+        "  Running cargo apk build --lib{} in {}",
+        if release { " --release" } else { "" },
+        project.display()
+    );
+    let status = cmd.status().map_err(|e| format!("Failed to run cargo apk: {}", e))?;
+    if !status.success() {
+        return Err("APK build failed".to_string());
+    }
+
+    let profile = if release { "release" } else { "debug" };
+    let apk_name = format!("{}.apk", crate_info.name);
+    let apk = project.join("target").join(profile).join("apk").join(&apk_name);
+    if !apk.is_file() {
+        return Err(format!("APK not found at {}", apk.display()));
+    }
+
+    let dist_dir = crate_info.root.join(output);
+    fs::create_dir_all(&dist_dir).map_err(|e| format!("Failed to create {}: {}", dist_dir.display(), e))?;
+    let dist_apk = dist_dir.join(&apk_name);
+    fs::copy(&apk, &dist_apk).map_err(|e| format!("Failed to copy APK: {}", e))?;
+
+    println!("\nBuild complete! Install with:");
+    println!("  adb install -r {}", dist_apk.display());
+    Ok(())
+}
+
+/// Fail early, with install instructions, if cargo-apk is missing
+fn check_cargo_apk() -> Result<(), String> {
+    let installed = Command::new("cargo")
+        .args(["apk", "--version"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if installed {
+        Ok(())
+    } else {
+        Err("cargo-apk not found. Install it, the Android NDK and the Android target with:\n  \
+             cargo install cargo-apk\n  \
+             rustup target add aarch64-linux-android\n\
+             and point ANDROID_HOME and ANDROID_NDK_ROOT at the SDK and NDK."
+            .to_string())
+    }
+}
+
+/// Write the shell's package for the app, returning its directory
+fn generate_project(crate_info: &CrateInfo, manifest: &Manifest, wasm_path: &Path) -> Result<PathBuf, String> {
+    let project = crate_info.target_dir.join("fastn-quest").join(&crate_info.name);
+    let src = project.join("src");
+    fs::create_dir_all(&src).map_err(|e| format!("Failed to create {}: {}", src.display(), e))?;
+
+    let fastn_protocol = Path::new(env!("CARGO_MANIFEST_DIR")).join("../fastn-protocol");
+    let fastn_protocol = fs::canonicalize(&fastn_protocol)
+        .map_err(|e| format!("fastn-protocol not found at {}: {}", fastn_protocol.display(), e))?;
+    let runtime_libs = crate_info.root.join("libs");
+    let cargo_toml = CARGO_TOML_TEMPLATE
+        .replace("{{APP_NAME}}", &toml_string(&crate_info.name))
+        .replace("{{VERSION}}", &toml_string(&manifest.version))
+        .replace("{{PACKAGE_ID}}", &toml_string(&manifest.package))
+        .replace("{{LABEL}}", &toml_string(&manifest.label))
+        .replace("{{RUNTIME_LIBS}}", &toml_string(&runtime_libs.to_string_lossy()))
+        .replace("{{FASTN_PROTOCOL}}", &toml_string(&fastn_protocol.to_string_lossy()));
+
+    let files = [
+        (project.join("Cargo.toml"), cargo_toml.as_str()),
+        (src.join("lib.rs"), SHELL_LIB_RS),
+        (src.join("wasm_core.rs"), SHELL_WASM_CORE_RS),
+        (src.join("input.rs"), SHELL_INPUT_RS),
+        (src.join("passthrough.rs"), SHELL_PASSTHROUGH_RS),
+        (src.join("renderer.rs"), SHELL_RENDERER_RS),
+        (src.join("shader.wgsl"), SHELL_SHADER),
+    ];
+    for (path, content) in files {
+        fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    println!("  Generated {}", project.display());
+
+    // Assets go into the APK; start over so removed ones don't linger
+    let assets = project.join("assets");
+    if assets.exists() {
+        fs::remove_dir_all(&assets).map_err(|e| format!("Failed to clear {}: {}", assets.display(), e))?;
+    }
+    fs::create_dir_all(&assets).map_err(|e| format!("Failed to create {}: {}", assets.display(), e))?;
+    for (relative, path) in asset_files(crate_info)? {
+        let dest = assets.join(&relative);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        fs::copy(&path, &dest).map_err(|e| format!("Failed to copy asset {:?}: {}", path, e))?;
+    }
+    // After the app's assets, so one of the same name can't replace the core
+    fs::copy(wasm_path, assets.join(APP_WASM)).map_err(|e| format!("Failed to copy WASM: {}", e))?;
+    Ok(project)
+}
+
+/// A quoted TOML string
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
@@ -0,0 +1,256 @@
+//! Multi-app workspaces (`fastn-workspace.toml`)
+//!
+//! A directory with a `fastn-workspace.toml` groups the fastn apps below it
+//! (members of the cargo workspace):
+//!
+//! ```toml
+//! # Assets every app can load as bundle:// paths; an app's own assets/
+//! # win over them
+//! assets = "shared-assets"
+//! # Where `build --all` writes, one folder per app
+//! dist = "dist"
+//!
+//! [apps.viewer]
+//! output = "3d-viewer" # folder under dist (default: the app's name)
+//! ```
+//!
+//! `build --all` builds every app into the cargo target directory they
+//! share, so dependencies compile once. An asset that ends up in more than
+//! one app (same contents, whatever its path) is written once to
+//! `<dist>/shared/`, and each app's `asset-map.json` points the web shell
+//! to it.
+
+use crate::{CrateInfo, cargo_metadata, cmd_build, compute_hash, is_fastn_app};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+pub(crate) const WORKSPACE_FILE: &str = "fastn-workspace.toml";
+
+/// Folder under the workspace's dist for assets shared by several apps
+const SHARED_DIR: &str = "shared";
+
+/// Per-app file mapping bundle paths to the shared copies, read by the web
+/// shell
+pub(crate) const ASSET_MAP_FILE: &str = "asset-map.json";
+
+pub(crate) struct Workspace {
+    root: PathBuf,
+    /// Shared assets directory
+    assets: Option<PathBuf>,
+    dist: PathBuf,
+    /// Output folders under `dist` by app name
+    outputs: HashMap<String, PathBuf>,
+}
+
+impl Workspace {
+    /// The workspace `dir` is in: the nearest `fastn-workspace.toml` in it or
+    /// a parent
+    pub(crate) fn find(dir: &Path) -> Result<Option<Self>, String> {
+        let dir = fs::canonicalize(dir).map_err(|e| format!("Failed to resolve {}: {}", dir.display(), e))?;
+        match dir.ancestors().find(|d| d.join(WORKSPACE_FILE).is_file()) {
+            Some(root) => Self::load(root).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn load(root: &Path) -> Result<Self, String> {
+        let path = root.join(WORKSPACE_FILE);
+        let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let parsed: toml::Table = content
+            .parse()
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+        let string = |value: &toml::Value, key: &str| {
+            value
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| format!("{}: `{}` must be a string", WORKSPACE_FILE, key))
+        };
+        let mut workspace = Self {
+            root: root.to_path_buf(),
+            assets: None,
+            dist: root.join("dist"),
+            outputs: HashMap::new(),
+        };
+        for (key, value) in &parsed {
+            match key.as_str() {
+                "assets" => workspace.assets = Some(root.join(string(value, key)?)),
+                "dist" => workspace.dist = root.join(string(value, key)?),
+                "apps" => {
+                    let apps = value
+                        .as_table()
+                        .ok_or_else(|| format!("{}: `apps` must be a table", WORKSPACE_FILE))?;
+                    for (name, app) in apps {
+                        let Some(output) = app.get("output") else {
+                            continue;
+                        };
+                        let output = PathBuf::from(string(output, &format!("apps.{}.output", name))?);
+                        if !output.components().all(|c| matches!(c, Component::Normal(_))) {
+                            return Err(format!(
+                                "{}: apps.{}.output must be a relative path within dist",
+                                WORKSPACE_FILE, name
+                            ));
+                        }
+                        workspace.outputs.insert(name.clone(), output);
+                    }
+                }
+                _ => return Err(format!("{}: unknown key `{}`", WORKSPACE_FILE, key)),
+            }
+        }
+        Ok(workspace)
+    }
+
+    /// The shared assets directory, if the workspace has one
+    pub(crate) fn assets(&self) -> Option<&Path> {
+        self.assets.as_deref().filter(|dir| dir.is_dir())
+    }
+
+    /// Folder of an app's build, relative to `dist`
+    fn output(&self, app: &str) -> PathBuf {
+        self.outputs.get(app).cloned().unwrap_or_else(|| PathBuf::from(app))
+    }
+
+    /// The fastn apps of the cargo workspace inside this workspace, sorted by
+    /// name
+    fn find_apps(&self) -> Result<Vec<CrateInfo>, String> {
+        let metadata = cargo_metadata()?;
+
+        let target_dir = metadata
+            .get("target_directory")
+            .and_then(|v| v.as_str())
+            .map(PathBuf::from)
+            .ok_or("Could not find target_directory in cargo metadata")?;
+
+        let packages = metadata
+            .get("packages")
+            .and_then(|v| v.as_array())
+            .ok_or("Could not find packages in cargo metadata")?;
+
+        let mut apps = vec![];
+        for pkg in packages.iter().filter(|pkg| is_fastn_app(pkg)) {
+            let Some(root) = pkg
+                .get("manifest_path")
+                .and_then(|v| v.as_str())
+                .and_then(|path| PathBuf::from(path).parent().map(|p| p.to_path_buf()))
+            else {
+                continue;
+            };
+            if !root.starts_with(&self.root) {
+                continue;
+            }
+            let name = pkg
+                .get("name")
+                .and_then(|v| v.as_str())
+                .ok_or("Could not find package name")?
+                .to_string();
+            apps.push(CrateInfo {
+                name,
+                root,
+                target_dir: target_dir.clone(),
+            });
+        }
+
+        apps.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(apps)
+    }
+}
+
+/// `build --all`: build every app of the workspace the current directory is
+/// in, then share the assets they have in common
+pub(crate) fn cmd_build_all(release: bool) -> Result<(), String> {
+    let workspace = Workspace::find(Path::new("."))?
+        .ok_or_else(|| format!("build --all needs a {} in this or a parent directory", WORKSPACE_FILE))?;
+    let apps = workspace.find_apps()?;
+    if apps.is_empty() {
+        return Err(format!("No fastn apps found under {}", workspace.root.display()));
+    }
+
+    let mut outputs = vec![];
+    for app in &apps {
+        let output = workspace.output(&app.name);
+        let dir = workspace.dist.join(&output);
+        cmd_build(app, release, dir.to_str().ok_or("Invalid dist path")?)?;
+        outputs.push((app, output));
+    }
+
+    let shared = share_assets(&workspace, &outputs)?;
+    println!(
+        "\nBuilt {} apps into {}/ ({} shared assets)",
+        apps.len(),
+        workspace.dist.display(),
+        shared
+    );
+    Ok(())
+}
+
+/// Move assets with the same contents in several apps' builds to
+/// `<dist>/shared/<name>-<hash>.<ext>` and write each app's asset map.
+/// Returns how many shared files were written.
+fn share_assets(workspace: &Workspace, outputs: &[(&CrateInfo, PathBuf)]) -> Result<usize, String> {
+    // Which app output has which asset path, by contents
+    let mut copies: HashMap<String, Vec<(usize, PathBuf)>> = HashMap::new();
+    for (index, (app, _)) in outputs.iter().enumerate() {
+        for (relative, source) in crate::asset_files(app)? {
+            let bytes = fs::read(&source).map_err(|e| format!("Failed to read asset {:?}: {}", source, e))?;
+            copies.entry(compute_hash(&bytes)).or_default().push((index, relative));
+        }
+    }
+
+    let shared_dir = workspace.dist.join(SHARED_DIR);
+    let _ = fs::remove_dir_all(&shared_dir);
+    let mut maps = vec![serde_json::Map::new(); outputs.len()];
+    let mut shared = 0;
+    let mut hashes: Vec<_> = copies.into_iter().collect();
+    hashes.sort();
+    for (hash, copies) in hashes {
+        let first_app = copies[0].0;
+        if copies.iter().all(|(index, _)| *index == first_app) {
+            continue;
+        }
+
+        let first = copies[0].1.clone();
+        let stem = first.file_stem().and_then(|s| s.to_str()).unwrap_or("asset");
+        let filename = match first.extension().and_then(|e| e.to_str()) {
+            Some(ext) => format!("{}-{}.{}", stem, &hash[..8], ext),
+            None => format!("{}-{}", stem, &hash[..8]),
+        };
+        fs::create_dir_all(&shared_dir).map_err(|e| format!("Failed to create shared directory: {}", e))?;
+        let first_copy = workspace.dist.join(&outputs[first_app].1).join(&first);
+        fs::copy(&first_copy, shared_dir.join(&filename))
+            .map_err(|e| format!("Failed to share asset {:?}: {}", first_copy, e))?;
+        shared += 1;
+
+        for (index, relative) in copies {
+            let output = &outputs[index].1;
+            let copy = workspace.dist.join(output).join(&relative);
+            fs::remove_file(&copy).map_err(|e| format!("Failed to remove {:?}: {}", copy, e))?;
+            // Drop the directories left empty (remove_dir fails on the others)
+            for dir in relative.ancestors().skip(1).filter(|d| !d.as_os_str().is_empty()) {
+                let _ = fs::remove_dir(workspace.dist.join(output).join(dir));
+            }
+            let up = "../".repeat(output.components().count());
+            maps[index].insert(
+                bundle_path(&relative),
+                serde_json::Value::String(format!("{}{}/{}", up, SHARED_DIR, filename)),
+            );
+        }
+        println!("  Shared {} as {}/{}", first.display(), SHARED_DIR, filename);
+    }
+
+    for ((_, output), map) in outputs.iter().zip(maps) {
+        let path = workspace.dist.join(output).join(ASSET_MAP_FILE);
+        let json = serde_json::to_string_pretty(&map).map_err(|e| format!("Failed to encode asset map: {}", e))?;
+        fs::write(&path, json).map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    }
+    Ok(shared)
+}
+
+/// An asset's path as apps write it after `bundle://`
+fn bundle_path(relative: &Path) -> String {
+    relative
+        .components()
+        .filter_map(|c| c.as_os_str().to_str())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,66 @@
+//! WASM runtime for ACL modules
+//!
+//! `_access.wasm`, `_read.wasm`, `_write.wasm` and `_admin.wasm` are plain
+//! WebAssembly modules with no imports. The hub passes the `AccessContext`
+//! as JSON and the module answers allow or deny:
+//!
+//! ```text
+//! (export "memory" (memory ...))
+//! (export "alloc"   (func (param $len i32) (result i32)))           ;; buffer for the context
+//! (export "allowed" (func (param $ptr i32) (param $len i32) (result i32)))  ;; non-zero = allow
+//! ```
+//!
+//! Every call runs in a fresh store with a fuel budget, a wall-clock deadline
+//! and a memory cap, so a buggy or hostile module can only deny access, never
+//! stall the hub. Modules run on the hub's `WasmPool`, with the pool's
+//! `WasmRuntime`: the engine and module cache kosha handlers use too.
+//! Compiled modules are cached by their location on disk and recompiled when
+//! the file content changes. A call whose cancellation token is cancelled
+//! stops at the next epoch tick.
+
+use fastn_kosha::{WasmLimits, WasmRuntime};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Resource limits for a single ACL module call
+#[derive(Debug, Clone, Copy)]
+pub struct AclLimits {
+    /// Fuel units (roughly one per WASM instruction)
+    pub fuel: u64,
+    /// Wall-clock limit for instantiation plus the `allowed` call
+    pub timeout: Duration,
+    /// Maximum linear memory a module may grow to
+    pub max_memory_bytes: usize,
+}
+
+impl Default for AclLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000,
+            timeout: Duration::from_millis(100),
+            max_memory_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Run the ACL module stored at `location` and return whether it allows
+/// access.
+///
+/// Blocking: call from a blocking-capable thread (a `WasmPool` job, with the
+/// pool's runtime).
+pub fn check(
+    runtime: &WasmRuntime,
+    limits: AclLimits,
+    location: &str,
+    wasm_bytes: &[u8],
+    ctx_json: &[u8],
+    cancel: &CancellationToken,
+) -> Result<bool, String> {
+    let limits = WasmLimits {
+        fuel: limits.fuel,
+        timeout: limits.timeout,
+        max_memory_bytes: limits.max_memory_bytes,
+    };
+    let mut call = runtime.prepare(location, wasm_bytes, ctx_json, limits, cancel)?;
+    Ok(call.call::<i32>("allowed")? != 0)
+}
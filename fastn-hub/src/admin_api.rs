@@ -0,0 +1,260 @@
+//! Owner-only JSON API over the admin dashboard's data
+//!
+//! Requests use the normal signed hub protocol with `app: "hub"` (the
+//! instance is ignored, use `"self"`), so third-party dashboards and mobile
+//! admin apps authenticate exactly like a spoke does. Only the hub owner's
+//! spokes are served; everyone else gets `AccessDenied`.
+//!
+//! Commands:
+//! - `describe` - hub identity, version and available commands
+//! - `metrics` - request counters and collection sizes
+//! - `list_spokes`, `list_pending_spokes`, `list_koshas` - paginated
+//! - `create_kosha`, `fork_kosha`, `delete_kosha` - manage the hub's koshas
+//! - `quota_status` - configured limits, request budgets and kosha storage use
+//! - `export_kosha`, `read_export` - snapshot a kosha and download it in
+//!   chunks, for backups
+//!
+//! Every response carries `schema_version`. Fields are only ever added within
+//! a schema version; removing or changing a field bumps it.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub use crate::metrics::{HubMetrics, RequestOutcome};
+
+/// Version of the response schemas below
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// App name for admin API requests
+pub const APP_NAME: &str = "hub";
+
+/// Commands served by the admin API
+pub const COMMANDS: &[&str] = &[
+    "describe",
+    "metrics",
+    "list_spokes",
+    "list_pending_spokes",
+    "list_koshas",
+    "create_kosha",
+    "fork_kosha",
+    "delete_kosha",
+    "quota_status",
+    "export_kosha",
+    "read_export",
+];
+
+/// Page size when the request doesn't specify `limit`
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Largest page size a request may ask for
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// How long an export stays in FASTN_HOME/exports/ for download; older
+/// ones are removed when the next is made
+pub const EXPORT_EXPIRY: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Pagination parameters (the request payload of `list_*` commands)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PageRequest {
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// One page of a collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub schema_version: u32,
+    pub items: Vec<T>,
+    pub offset: usize,
+    /// Size of the whole collection
+    pub total: usize,
+    /// Offset of the next page, `None` on the last page
+    pub next_offset: Option<usize>,
+}
+
+impl<T> Page<T> {
+    /// Slice a sorted collection according to the request
+    pub fn slice(all: Vec<T>, request: &PageRequest) -> Page<T> {
+        let total = all.len();
+        let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let offset = request.offset.min(total);
+        let items: Vec<T> = all.into_iter().skip(offset).take(limit).collect();
+        let end = offset + items.len();
+        Page {
+            schema_version: SCHEMA_VERSION,
+            items,
+            offset,
+            total,
+            next_offset: (end < total).then_some(end),
+        }
+    }
+
+    /// Same page with its items replaced (e.g. enriched with more data)
+    pub fn with_items<U>(self, items: Vec<U>) -> Page<U> {
+        Page {
+            schema_version: self.schema_version,
+            items,
+            offset: self.offset,
+            total: self.total,
+            next_offset: self.next_offset,
+        }
+    }
+}
+
+/// Response of `describe`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HubDescription {
+    pub schema_version: u32,
+    pub hub_id52: String,
+    /// fastn-hub crate version
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+    /// Apps requests can be routed to
+    pub apps: Vec<String>,
+    /// Commands of this API
+    pub commands: Vec<String>,
+}
+
+/// Response of `metrics`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HubMetricsSnapshot {
+    pub schema_version: u32,
+    pub uptime_secs: i64,
+    /// Requests handled since start (all apps, including this API)
+    pub requests_total: u64,
+    /// Requests rejected as unauthorized or access denied
+    pub requests_denied: u64,
+    /// Requests that failed for any other reason
+    pub requests_failed: u64,
+    /// Requests refused by an ACL or by this API
+    pub acl_denials: u64,
+    /// File bytes read from and written to koshas
+    pub kosha_read_bytes: u64,
+    pub kosha_written_bytes: u64,
+    /// Open push sessions
+    pub push_subscriptions: u64,
+    pub spokes: usize,
+    pub pending_spokes: usize,
+    pub koshas: usize,
+}
+
+/// Item of `list_spokes`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpokeSummary {
+    pub id52: String,
+    pub alias: String,
+}
+
+/// Item of `list_pending_spokes`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingSpokeSummary {
+    pub id52: String,
+    pub alias: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Item of `list_koshas`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KoshaSummary {
+    pub alias: String,
+    /// `None` if the kosha's storage couldn't be read
+    pub stats: Option<fastn_kosha::KoshaStats>,
+}
+
+/// Request payload of `create_kosha` and `delete_kosha`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KoshaRequest {
+    pub alias: String,
+}
+
+/// Request payload of `fork_kosha`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkKoshaRequest {
+    /// Alias of the kosha to fork
+    pub source: String,
+    /// Alias of the new kosha
+    pub alias: String,
+}
+
+/// Response of `create_kosha`, `fork_kosha` and `delete_kosha`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KoshaChanged {
+    pub schema_version: u32,
+    pub alias: String,
+}
+
+/// Response of `export_kosha`
+///
+/// Download the snapshot with `read_export` until `size` bytes are read,
+/// then check them against `sha256`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KoshaExport {
+    pub schema_version: u32,
+    /// Names the snapshot in `read_export`
+    pub export_id: String,
+    pub alias: String,
+    /// Size of the snapshot file in bytes
+    pub size: u64,
+    /// Hex SHA-256 of the snapshot file
+    pub sha256: String,
+    pub manifest: fastn_kosha::SnapshotManifest,
+}
+
+/// Request payload of `read_export`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadExportRequest {
+    pub export_id: String,
+    #[serde(default)]
+    pub offset: u64,
+    /// At most `MAX_CHUNK_SIZE`, the default
+    #[serde(default)]
+    pub length: Option<u64>,
+}
+
+/// Response of `read_export`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportChunk {
+    pub schema_version: u32,
+    pub export_id: String,
+    pub offset: u64,
+    /// Base64 of the bytes read, empty past the end
+    pub content: String,
+    /// Size of the whole snapshot file
+    pub size: u64,
+}
+
+/// Response of `quota_status`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaStatus {
+    pub schema_version: u32,
+    /// As configured (see `limits`)
+    pub limits: crate::Limits,
+    /// Identities that made requests recently or have a rate of their own,
+    /// sorted by ID52
+    pub identities: Vec<IdentityQuota>,
+    /// Every kosha, sorted by alias
+    pub koshas: Vec<KoshaQuota>,
+}
+
+/// Item of `QuotaStatus::identities`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityQuota {
+    pub id52: String,
+    /// `None` if the identity isn't rate limited
+    pub requests_per_sec: Option<f64>,
+    /// Requests it could make right now, `None` if not limited
+    pub available: Option<f64>,
+}
+
+/// Item of `QuotaStatus::koshas`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KoshaQuota {
+    pub alias: String,
+    /// `None` if the kosha's storage couldn't be read
+    pub used_bytes: Option<u64>,
+    /// `None` if the kosha has no quota
+    pub quota_bytes: Option<u64>,
+}
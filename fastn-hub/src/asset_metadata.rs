@@ -0,0 +1,102 @@
+//! Metadata extraction for 3D assets uploaded to koshas
+//!
+//! When a GLB/glTF file is written to a kosha, the hub parses it and stores a
+//! `meta.json` sidecar as derived content. Spokes (file browsers, the admin
+//! dashboard) read the sidecar via the kosha `read_derived` command instead of
+//! downloading the whole model.
+//!
+//! Only the glTF JSON and accessor bounds are inspected; buffers are never
+//! decoded, so external `.bin` files referenced by `.gltf` don't need to exist.
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the derived artifact holding model metadata
+pub const METADATA_NAME: &str = "meta.json";
+
+/// Summary of a GLB/glTF model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    pub mesh_count: u32,
+    pub primitive_count: u32,
+    pub animation_count: u32,
+    pub skeleton_count: u32,
+    pub material_count: u32,
+    pub vertex_count: u64,
+    pub triangle_count: u64,
+    /// Union of primitive bounds in mesh space (node transforms not applied)
+    pub bounding_box: Option<BoundingBox>,
+}
+
+/// Axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl BoundingBox {
+    fn union(self, other: BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: std::array::from_fn(|i| self.min[i].min(other.min[i])),
+            max: std::array::from_fn(|i| self.max[i].max(other.max[i])),
+        }
+    }
+}
+
+/// Check if a kosha path is a model we extract metadata for
+pub fn is_model_path(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    lower.ends_with(".glb") || lower.ends_with(".gltf")
+}
+
+/// Parse a GLB/glTF file and summarize its contents
+pub fn extract_model_metadata(bytes: &[u8]) -> Result<ModelMetadata, String> {
+    let gltf = gltf::Gltf::from_slice(bytes).map_err(|e| format!("invalid glTF: {}", e))?;
+    let document = &gltf.document;
+
+    let mut metadata = ModelMetadata {
+        mesh_count: document.meshes().count() as u32,
+        primitive_count: 0,
+        animation_count: document.animations().count() as u32,
+        skeleton_count: document.skins().count() as u32,
+        material_count: document.materials().count() as u32,
+        vertex_count: 0,
+        triangle_count: 0,
+        bounding_box: None,
+    };
+
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            metadata.primitive_count += 1;
+
+            let positions = primitive.get(&gltf::Semantic::Positions);
+            let vertices = positions.as_ref().map(|a| a.count() as u64).unwrap_or(0);
+            metadata.vertex_count += vertices;
+
+            let elements = primitive.indices().map(|a| a.count() as u64).unwrap_or(vertices);
+            metadata.triangle_count += match primitive.mode() {
+                gltf::mesh::Mode::Triangles => elements / 3,
+                gltf::mesh::Mode::TriangleStrip | gltf::mesh::Mode::TriangleFan => {
+                    elements.saturating_sub(2)
+                }
+                _ => 0,
+            };
+
+            // POSITION accessors are required to carry min/max bounds
+            if let Some(bounds) = positions.as_ref().and_then(accessor_bounds) {
+                metadata.bounding_box = Some(match metadata.bounding_box {
+                    Some(existing) => existing.union(bounds),
+                    None => bounds,
+                });
+            }
+        }
+    }
+
+    Ok(metadata)
+}
+
+fn accessor_bounds(accessor: &gltf::Accessor) -> Option<BoundingBox> {
+    let min = serde_json::from_value::<[f32; 3]>(accessor.min()?).ok()?;
+    let max = serde_json::from_value::<[f32; 3]>(accessor.max()?).ok()?;
+    Some(BoundingBox { min, max })
+}
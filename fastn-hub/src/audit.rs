@@ -0,0 +1,313 @@
+//! Audit log: who asked for what, and what they got
+//!
+//! Every signed request the hub handles, and every push subscription, is
+//! appended to `FASTN_HOME/audit/audit.log` as one JSON line (`AuditEntry`):
+//! the sender's ID52 and network address, the app, instance, command and
+//! path, the result and how long it took. Requests from unknown senders and requests over their
+//! rate limit are logged too; requests whose signature doesn't verify have
+//! no sender and are not.
+//!
+//! When the log would grow past `max_file_bytes` it is renamed to
+//! `audit-<timestamp>.log` and a new one started; only the newest
+//! `max_files` of those are kept. Both are set in the `audit` section of
+//! `config.json`:
+//!
+//! ```json
+//! "audit": { "max_file_bytes": 10485760, "max_files": 10 }
+//! ```
+//!
+//! `fastn-hub audit` queries the log (see `AuditQuery`).
+
+use chrono::{DateTime, Utc};
+use fastn_net::HubError;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+
+/// Size the log grows to before it is rotated, unless configured
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Rotated logs kept, unless configured
+pub const DEFAULT_MAX_FILES: usize = 10;
+
+/// Name of the log being written, in the audit directory
+pub const LOG_FILE: &str = "audit.log";
+
+/// Log rotation (the `audit` section of config.json)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Rotate the log before it grows past this, default
+    /// `DEFAULT_MAX_FILE_BYTES`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_bytes: Option<u64>,
+    /// Rotated logs to keep, default `DEFAULT_MAX_FILES`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<usize>,
+}
+
+impl AuditConfig {
+    pub fn is_default(&self) -> bool {
+        *self == AuditConfig::default()
+    }
+
+    pub fn file_bytes(&self) -> u64 {
+        self.max_file_bytes.unwrap_or(DEFAULT_MAX_FILE_BYTES)
+    }
+
+    pub fn files(&self) -> usize {
+        self.max_files.unwrap_or(DEFAULT_MAX_FILES)
+    }
+}
+
+/// How a request ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Ok,
+    /// Unknown sender, or refused by an ACL
+    Denied,
+    /// Over the sender's request rate
+    RateLimited,
+    Failed,
+}
+
+impl AuditResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditResult::Ok => "ok",
+            AuditResult::Denied => "denied",
+            AuditResult::RateLimited => "rate_limited",
+            AuditResult::Failed => "failed",
+        }
+    }
+}
+
+impl std::str::FromStr for AuditResult {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "ok" => Ok(AuditResult::Ok),
+            "denied" => Ok(AuditResult::Denied),
+            "rate_limited" => Ok(AuditResult::RateLimited),
+            "failed" => Ok(AuditResult::Failed),
+            _ => Err(format!("Unknown result {:?} (use ok, denied, rate_limited or failed)", s)),
+        }
+    }
+}
+
+/// One request, as logged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the request arrived
+    pub time: DateTime<Utc>,
+    /// ID52 that signed the request
+    pub sender: String,
+    /// Address the request came from, behind trusted proxies the one they
+    /// report (see `listen`); none for requests made in-process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<IpAddr>,
+    /// "self", or the alias of the hub the request was forwarded to
+    pub target_hub: String,
+    pub app: String,
+    pub instance: String,
+    pub command: String,
+    /// File or database the command is about, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub result: AuditResult,
+    /// What went wrong, for results other than `Ok`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_us: u64,
+}
+
+impl AuditEntry {
+    /// Fill in the outcome of a request
+    pub fn finish<T>(mut self, result: &Result<T, HubError>, started: std::time::Instant) -> Self {
+        self.duration_us = started.elapsed().as_micros() as u64;
+        if let Err(e) = result {
+            self.result = match e {
+                HubError::Unauthorized | HubError::AccessDenied { .. } => AuditResult::Denied,
+                HubError::QuotaExceeded {
+                    retry_after_ms: Some(_),
+                    ..
+                } => AuditResult::RateLimited,
+                _ => AuditResult::Failed,
+            };
+            self.error = Some(error_message(e));
+        }
+        self
+    }
+}
+
+/// Which entries `AuditLog::query` returns; unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub sender: Option<String>,
+    pub client: Option<IpAddr>,
+    pub app: Option<String>,
+    pub instance: Option<String>,
+    pub command: Option<String>,
+    /// Entries whose path starts with this (leading `/` or not)
+    pub path_prefix: Option<String>,
+    pub result: Option<AuditResult>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only the most recent this many matches
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.sender.as_ref().is_none_or(|sender| entry.sender == *sender)
+            && self.client.is_none_or(|client| entry.client == Some(client))
+            && self.app.as_ref().is_none_or(|app| entry.app == *app)
+            && self.instance.as_ref().is_none_or(|instance| entry.instance == *instance)
+            && self.command.as_ref().is_none_or(|command| entry.command == *command)
+            && self.path_prefix.as_ref().is_none_or(|prefix| {
+                // Leading slashes are optional in requests
+                entry
+                    .path
+                    .as_ref()
+                    .is_some_and(|path| path.trim_start_matches('/').starts_with(prefix.trim_start_matches('/')))
+            })
+            && self.result.is_none_or(|result| entry.result == result)
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time < until)
+    }
+}
+
+/// The log being written
+struct Writer {
+    file: tokio::fs::File,
+    size: u64,
+}
+
+/// Append-only request log in FASTN_HOME/audit/
+pub struct AuditLog {
+    dir: PathBuf,
+    config: AuditConfig,
+    /// Opened on the first entry
+    writer: tokio::sync::Mutex<Option<Writer>>,
+}
+
+impl AuditLog {
+    pub fn new(dir: PathBuf, config: AuditConfig) -> Self {
+        Self {
+            dir,
+            config,
+            writer: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Append an entry; failing to is logged, never fails the request
+    pub async fn record(&self, entry: &AuditEntry) {
+        if let Err(e) = self.append(entry).await {
+            tracing::error!("Failed to write audit log: {}", e);
+        }
+    }
+
+    async fn append(&self, entry: &AuditEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().await;
+        let mut current = match writer.take() {
+            Some(current) => current,
+            None => self.open().await?,
+        };
+        if current.size > 0 && current.size + line.len() as u64 > self.config.file_bytes() {
+            drop(current);
+            self.rotate().await?;
+            current = self.open().await?;
+        }
+        current.file.write_all(&line).await?;
+        current.file.flush().await?;
+        current.size += line.len() as u64;
+        *writer = Some(current);
+        Ok(())
+    }
+
+    async fn open(&self) -> std::io::Result<Writer> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(LOG_FILE))
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok(Writer { file, size })
+    }
+
+    /// Move the log aside and drop the oldest rotated logs
+    async fn rotate(&self) -> std::io::Result<()> {
+        let rotated = format!("audit-{}.log", Utc::now().format("%Y%m%dT%H%M%S%.6fZ"));
+        tokio::fs::rename(self.dir.join(LOG_FILE), self.dir.join(rotated)).await?;
+        let files = self.rotated_files().await?;
+        let excess = files.len().saturating_sub(self.config.files());
+        for file in &files[..excess] {
+            tokio::fs::remove_file(file).await?;
+        }
+        Ok(())
+    }
+
+    /// Rotated logs, oldest first (their names sort by time)
+    async fn rotated_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        let mut dir = match tokio::fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with("audit-") && name.ends_with(".log") {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Entries matching the query, oldest first, from the rotated logs and
+    /// the current one
+    ///
+    /// Lines that don't parse (e.g. cut short by a crash) are skipped.
+    pub async fn query(&self, query: &AuditQuery) -> std::io::Result<Vec<AuditEntry>> {
+        let mut files = self.rotated_files().await?;
+        files.push(self.dir.join(LOG_FILE));
+
+        let mut entries = vec![];
+        for file in files {
+            let text = match tokio::fs::read_to_string(&file).await {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            entries.extend(
+                text.lines()
+                    .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+                    .filter(|entry| query.matches(entry)),
+            );
+        }
+        if let Some(limit) = query.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        Ok(entries)
+    }
+}
+
+/// A short description of a hub error for the log
+fn error_message(e: &HubError) -> String {
+    match e {
+        HubError::Unauthorized => "Unauthorized".to_string(),
+        HubError::AccessDenied { app, instance, .. } => format!("Access denied to {}/{}", app, instance),
+        HubError::AppNotFound { app } => format!("App not found: {}", app),
+        HubError::InstanceNotFound { app, instance } => format!("Instance not found: {}/{}", app, instance),
+        HubError::AppError { message } | HubError::Conflict { message, .. } | HubError::QuotaExceeded { message, .. } => {
+            message.clone()
+        }
+    }
+}
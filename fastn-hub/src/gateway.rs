@@ -0,0 +1,415 @@
+//! REST gateway: plain HTTP access to koshas for tools that can't sign
+//! requests
+//!
+//! When `rest_gateway` is set in config.json, the hub also serves
+//!
+//! - `GET /api/kosha/<alias>/<path>`: the file's content, with its
+//!   content type and `Last-Modified`
+//! - `GET /api/kosha/<alias>/<dir>/` (trailing slash, or no path): the
+//!   directory listing as `{ "entries": [...] }`
+//! - `PUT /api/kosha/<alias>/<path>`: write the request body to the file,
+//!   answering `{ "modified": timestamp }`
+//! - `DELETE /api/kosha/<alias>/<path>`: delete the file (204), or with a
+//!   trailing slash the directory (`{ "deleted": [path, ...] }`)
+//!
+//! Requests carry `Authorization: Bearer <token>`, a capability token the
+//! owner mints with `Hub::create_token` for one kosha, optionally only a
+//! path under it, read-only unless it may write, until it expires or is
+//! revoked. Only the token's SHA-256 is kept, in `FASTN_HOME/tokens.json`,
+//! which is read on every request, so revoking takes effect at once.
+//!
+//! Each request becomes the kosha command it names and goes through
+//! `Hub::handle_request`'s rate limits and audit log as `token:<name>` (so
+//! `identity_rates` can name it). The token acts for the owner within its
+//! scope, but unlike the owner's spokes it doesn't skip ACL modules: they
+//! see `spoke_id52` `token:<name>`, and directories and databases are
+//! checked as for any other non-owner. Mounts (`crate::mounts`) are checked
+//! against the scope once resolved, so a token never reads another kosha
+//! through one.
+
+use crate::listen::ClientIp;
+use crate::{Error, Hub, HubError, Request, Response, Result, SenderIdentity};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::IntoResponse;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Where the gateway is served
+pub const REST_PREFIX: &str = "/api/kosha";
+
+/// Tokens, in FASTN_HOME
+pub const TOKENS_FILE: &str = "tokens.json";
+
+/// Start of every token, so they are easy to spot in logs and configs
+pub const TOKEN_PREFIX: &str = "fastn_";
+
+/// A capability token, as the hub keeps it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapabilityToken {
+    /// Names the token in the audit log, rate limits and ACL modules
+    pub name: String,
+    /// SHA-256 of the token, lowercase hex
+    pub sha256: String,
+    /// The kosha it opens
+    pub kosha: String,
+    /// The file or directory it opens, empty for the whole kosha
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path_prefix: String,
+    /// Whether it may change things, or only read
+    pub write: bool,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CapabilityToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    /// Whether the request is within the token's scope
+    ///
+    /// Commands without a path (KV, for one) need a whole-kosha token.
+    pub fn allows(&self, request: &Request) -> bool {
+        if request.app != "kosha" || request.instance != self.kosha {
+            return false;
+        }
+        match Hub::command_category(&request.command) {
+            Some("read") => {}
+            Some("write") if self.write => {}
+            _ => return false,
+        }
+        let paths: Vec<&str> = ["path", "from", "to", "database"]
+            .iter()
+            .filter_map(|field| request.payload.get(*field)?.as_str())
+            .collect();
+        match self.path_prefix.is_empty() {
+            true => true,
+            false => !paths.is_empty() && paths.iter().all(|path| self.covers(path)),
+        }
+    }
+
+    fn covers(&self, path: &str) -> bool {
+        let Ok(path) = fastn_kosha::clean_path(path) else {
+            return false;
+        };
+        let path = path.trim_matches('/');
+        path == self.path_prefix || path.starts_with(&format!("{}/", self.path_prefix))
+    }
+}
+
+/// How a token request appears in the audit log and rate limits
+pub(crate) fn token_sender(name: &str) -> String {
+    format!("token:{}", name)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Hub {
+    fn tokens_path(&self) -> PathBuf {
+        self.home.join(TOKENS_FILE)
+    }
+
+    /// Whether the REST gateway is served
+    pub fn rest_gateway(&self) -> bool {
+        self.config.rest_gateway
+    }
+
+    /// Serve the REST gateway from the next start on, or stop, and save it
+    /// to config.json
+    pub async fn set_rest_gateway(&mut self, enabled: bool) -> Result<()> {
+        self.config.rest_gateway = enabled;
+        self.save_config().await
+    }
+
+    /// Mint a token for `kosha` (under `path_prefix` if not empty), able to
+    /// write if `write`, and return it with the token itself, which the hub
+    /// doesn't keep
+    pub async fn create_token(
+        &self,
+        name: &str,
+        kosha: &str,
+        path_prefix: &str,
+        write: bool,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(CapabilityToken, String)> {
+        Self::validate_kosha_alias(name).map_err(|_| Error::InvalidTokenName(name.to_string()))?;
+        if self.get_kosha(kosha).await?.is_none() {
+            return Err(Error::InstanceNotFound("kosha".to_string(), kosha.to_string()));
+        }
+        let path_prefix = fastn_kosha::clean_path(path_prefix)?.trim_matches('/').to_string();
+        let mut tokens = self.list_tokens().await?;
+        if tokens.iter().any(|t| t.name == name) {
+            return Err(Error::TokenExists(name.to_string()));
+        }
+
+        let secret = format!("{}{}", TOKEN_PREFIX, rand::random::<[u8; 32]>().map(|b| format!("{:02x}", b)).concat());
+        let token = CapabilityToken {
+            name: name.to_string(),
+            sha256: sha256_hex(secret.as_bytes()),
+            kosha: kosha.to_string(),
+            path_prefix,
+            write,
+            created_at: Utc::now(),
+            expires_at,
+        };
+        tokens.push(token.clone());
+        self.save_tokens(&tokens).await?;
+        tracing::info!("Created token {} for kosha {}", name, kosha);
+        Ok((token, secret))
+    }
+
+    /// Every token, in the order they were made
+    pub async fn list_tokens(&self) -> Result<Vec<CapabilityToken>> {
+        match tokio::fs::read(self.tokens_path()).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Revoke a token; false if there is none by that name
+    pub async fn revoke_token(&self, name: &str) -> Result<bool> {
+        let mut tokens = self.list_tokens().await?;
+        let count = tokens.len();
+        tokens.retain(|t| t.name != name);
+        if tokens.len() == count {
+            return Ok(false);
+        }
+        self.save_tokens(&tokens).await?;
+        tracing::info!("Revoked token {}", name);
+        Ok(true)
+    }
+
+    /// The token a client presented, if the hub has it (expired or not)
+    pub async fn find_token(&self, secret: &str) -> Result<Option<CapabilityToken>> {
+        let sha256 = sha256_hex(secret.as_bytes());
+        Ok(self.list_tokens().await?.into_iter().find(|t| t.sha256 == sha256))
+    }
+
+    async fn save_tokens(&self, tokens: &[CapabilityToken]) -> Result<()> {
+        let path = self.tokens_path();
+        let temp = path.with_extension("json.tmp");
+        tokio::fs::write(&temp, serde_json::to_vec_pretty(tokens)?).await?;
+        tokio::fs::rename(&temp, &path).await?;
+        Ok(())
+    }
+
+    /// Handle a request made with a token, like `handle_request` does a
+    /// signed one: rate limited and audited as `token:<name>`
+    pub async fn handle_token_request(
+        &self,
+        token: &CapabilityToken,
+        request: Request,
+    ) -> std::result::Result<Response, HubError> {
+        self.metered_request(&token_sender(&token.name), request, Some(token), None).await
+    }
+
+    pub(crate) async fn route_token_request(
+        &self,
+        token: &CapabilityToken,
+        request: Request,
+    ) -> std::result::Result<Response, HubError> {
+        if token.is_expired() {
+            return Err(HubError::Unauthorized);
+        }
+        let denied = || HubError::AccessDenied {
+            app: request.app.clone(),
+            instance: request.instance.clone(),
+            trace: None,
+        };
+        if request.target_hub != "self" || !token.allows(&request) {
+            return Err(denied());
+        }
+
+        // ACL modules, as for a non-owner
+        let identity = SenderIdentity::Token {
+            name: token.name.clone(),
+        };
+        // A mount can't take the token outside its scope
+        let (request, mount_points) = self.resolve_mounts(request).await?;
+        if !token.allows(&request) {
+            return Err(HubError::AccessDenied {
+                app: request.app,
+                instance: request.instance,
+                trace: None,
+            });
+        }
+        self.authorize_kosha_request(&identity, &request).await?;
+        let response = self.handle_kosha_request(&identity, request).await?;
+        Ok(Self::add_mount_points(response, mount_points))
+    }
+}
+
+/// The gateway's routes, for `Hub::serve`
+pub(crate) fn routes(hub: Arc<RwLock<Hub>>) -> axum::Router {
+    let handler = axum::routing::any(serve_rest);
+    axum::Router::new()
+        .route(&format!("{}/{{kosha}}", REST_PREFIX), handler.clone())
+        .route(&format!("{}/{{kosha}}/", REST_PREFIX), handler.clone())
+        .route(&format!("{}/{{kosha}}/{{*path}}", REST_PREFIX), handler)
+        .with_state(hub)
+}
+
+/// What a REST request became, to shape its response
+enum Operation {
+    ReadFile { path: String },
+    ListDir,
+    WriteFile,
+    Delete,
+    DeleteDir,
+}
+
+async fn serve_rest(
+    State(hub): State<Arc<RwLock<Hub>>>,
+    method: Method,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+    client: Option<axum::Extension<ClientIp>>,
+    body: Bytes,
+) -> axum::response::Response {
+    let kosha = params.get("kosha").cloned().unwrap_or_default();
+    let path = params.get("path").cloned().unwrap_or_default();
+    let secret = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+    let Some(secret) = secret else {
+        return error_response(StatusCode::UNAUTHORIZED, "Missing bearer token");
+    };
+
+    let hub = hub.read().await;
+    let token = match hub.find_token(secret).await {
+        Ok(Some(token)) => token,
+        Ok(None) => return error_response(StatusCode::UNAUTHORIZED, "Unknown token"),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let (operation, request) = match rest_request(&method, &kosha, &path, &body) {
+        Ok(converted) => converted,
+        Err((status, message)) => return error_response(status, message),
+    };
+    let client = client.map(|axum::Extension(ClientIp(client))| client);
+    match hub.metered_request(&token_sender(&token.name), request, Some(&token), client).await {
+        Ok(response) => success_response(operation, response.payload),
+        Err(e) => hub_error_response(e),
+    }
+}
+
+/// The kosha command a REST request names
+fn rest_request(
+    method: &Method,
+    kosha: &str,
+    path: &str,
+    body: &[u8],
+) -> std::result::Result<(Operation, Request), (StatusCode, &'static str)> {
+    use base64::Engine;
+
+    let dir = path.is_empty() || path.ends_with('/');
+    let trimmed = path.trim_end_matches('/');
+    let (operation, command, payload) = match (method.clone(), dir) {
+        (Method::GET, true) => (Operation::ListDir, "list_dir", serde_json::json!({ "path": trimmed })),
+        (Method::GET, false) => {
+            let operation = Operation::ReadFile { path: path.to_string() };
+            (operation, "read_file", serde_json::json!({ "path": path }))
+        }
+        (Method::PUT, false) => {
+            let content = base64::engine::general_purpose::STANDARD.encode(body);
+            let payload = serde_json::json!({ "path": path, "content": content });
+            (Operation::WriteFile, "write_file", payload)
+        }
+        (Method::DELETE, false) => (Operation::Delete, "delete", serde_json::json!({ "path": path })),
+        (Method::DELETE, true) if !trimmed.is_empty() => {
+            (Operation::DeleteDir, "delete_dir", serde_json::json!({ "path": trimmed }))
+        }
+        (Method::PUT | Method::DELETE, true) => return Err((StatusCode::BAD_REQUEST, "Expected a file path")),
+        _ => return Err((StatusCode::METHOD_NOT_ALLOWED, "Use GET, PUT or DELETE")),
+    };
+    let request = Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: kosha.to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    };
+    Ok((operation, request))
+}
+
+fn success_response(operation: Operation, payload: serde_json::Value) -> axum::response::Response {
+    use base64::Engine;
+
+    match operation {
+        Operation::ReadFile { path } => {
+            let Some(content) = payload["content"]
+                .as_str()
+                .and_then(|c| base64::engine::general_purpose::STANDARD.decode(c).ok())
+            else {
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Invalid file content");
+            };
+            let mut response = content.into_response();
+            let headers = response.headers_mut();
+            let content_type = fastn_kosha::content_type_for_extension(&path);
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+            let modified = payload["modified"].as_str().and_then(|m| m.parse::<DateTime<Utc>>().ok());
+            if let Some(modified) = modified
+                && let Ok(value) = HeaderValue::from_str(&modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+            {
+                headers.insert(header::LAST_MODIFIED, value);
+            }
+            response
+        }
+        Operation::Delete => StatusCode::NO_CONTENT.into_response(),
+        Operation::ListDir | Operation::WriteFile | Operation::DeleteDir => axum::Json(payload).into_response(),
+    }
+}
+
+fn hub_error_response(error: HubError) -> axum::response::Response {
+    let (status, message) = match error {
+        HubError::Unauthorized => (StatusCode::UNAUTHORIZED, "Token expired".to_string()),
+        HubError::AccessDenied { app, instance, .. } => {
+            (StatusCode::FORBIDDEN, format!("Access denied to {}/{}", app, instance))
+        }
+        HubError::AppNotFound { app } => (StatusCode::NOT_FOUND, format!("Application not found: {}", app)),
+        HubError::InstanceNotFound { app, instance } => {
+            (StatusCode::NOT_FOUND, format!("Instance not found: {}/{}", app, instance))
+        }
+        HubError::AppError { message } if message.starts_with("File not found") => (StatusCode::NOT_FOUND, message),
+        HubError::AppError { message } => (StatusCode::BAD_REQUEST, message),
+        HubError::Conflict { message, .. } => (StatusCode::CONFLICT, message),
+        HubError::QuotaExceeded {
+            message,
+            retry_after_ms: Some(retry_after_ms),
+        } => {
+            let mut response = error_response(StatusCode::TOO_MANY_REQUESTS, &message);
+            let retry_after = retry_after_ms.div_ceil(1000).to_string();
+            if let Ok(value) = HeaderValue::from_str(&retry_after) {
+                response.headers_mut().insert(header::RETRY_AFTER, value);
+            }
+            return response;
+        }
+        HubError::QuotaExceeded { message, .. } => (StatusCode::INSUFFICIENT_STORAGE, message),
+    };
+    error_response(status, &message)
+}
+
+/// `{ "error": message }`, asking for a bearer token on 401
+fn error_response(status: StatusCode, message: &str) -> axum::response::Response {
+    let mut response = (status, axum::Json(serde_json::json!({ "error": message }))).into_response();
+    let headers = response.headers_mut();
+    match status {
+        StatusCode::UNAUTHORIZED => headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer")),
+        StatusCode::METHOD_NOT_ALLOWED => headers.insert(header::ALLOW, HeaderValue::from_static("GET, PUT, DELETE")),
+        _ => None,
+    };
+    response
+}
@@ -0,0 +1,172 @@
+//! Spoke invitations: one-time tokens instead of `add-spoke <id52>`
+//!
+//! The owner mints an invite with `Hub::create_invite` (`fastn-hub invite`)
+//! and hands the token to whoever should join. The token is signed by the
+//! hub (`fastn_net::InviteToken`), so the spoke learns the hub's ID52 from
+//! it; `fastn-spoke join <hub-url> <token>` then sends a signed `join`
+//! request (app `hub`) with the token, and the hub adds the sender to
+//! spokes.txt under the alias it asks for.
+//!
+//! Invites are kept in the root kosha's `invites.json` until they are used,
+//! revoked or expire; expired ones are dropped whenever the file is saved.
+
+use crate::{Error, Hub, HubError, Request, Response, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Invites, in the root kosha
+pub const INVITES_FILE: &str = "invites.json";
+
+/// How long an invite is accepted when the owner doesn't say
+pub const DEFAULT_INVITE_TTL: Duration = Duration::hours(1);
+
+/// Command (app `hub`) spokes join with
+pub const JOIN_COMMAND: &str = "join";
+
+/// An invite, as the hub keeps it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invite {
+    /// `InviteToken::invite_id` of its token
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Invite {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
+
+/// Payload of a `join` request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub token: String,
+    /// What to call the spoke in spokes.txt
+    pub alias: String,
+}
+
+/// Response of `join`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Joined {
+    pub hub_id52: String,
+    pub spoke_id52: String,
+    pub alias: String,
+}
+
+impl Hub {
+    /// Mint an invite accepted for `ttl`, and return it with its token,
+    /// which only the invited spoke should see
+    pub async fn create_invite(&self, ttl: Duration) -> Result<(Invite, String)> {
+        let id = rand::random::<[u8; 8]>();
+        let now = Utc::now();
+        let invite = Invite {
+            id: id.map(|b| format!("{:02x}", b)).concat(),
+            created_at: now,
+            expires_at: now + ttl,
+        };
+        let token = fastn_net::InviteToken::sign(&self.secret_key, id, invite.expires_at.timestamp());
+        let mut invites = self.list_invites().await?;
+        invites.push(invite.clone());
+        self.save_invites(invites).await?;
+        tracing::info!("Created invite {} (expires {})", invite.id, invite.expires_at);
+        Ok((invite, token))
+    }
+
+    /// Invites not used or revoked yet, expired ones included, oldest first
+    pub async fn list_invites(&self) -> Result<Vec<Invite>> {
+        match self.root_kosha.read_file(INVITES_FILE).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(fastn_kosha::Error::NotFound(_)) => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Revoke an invite; false if there is none by that ID
+    pub async fn revoke_invite(&self, id: &str) -> Result<bool> {
+        let mut invites = self.list_invites().await?;
+        let count = invites.len();
+        invites.retain(|invite| invite.id != id);
+        if invites.len() == count {
+            return Ok(false);
+        }
+        self.save_invites(invites).await?;
+        tracing::info!("Revoked invite {}", id);
+        Ok(true)
+    }
+
+    async fn save_invites(&self, mut invites: Vec<Invite>) -> Result<()> {
+        invites.retain(|invite| !invite.is_expired());
+        self.root_kosha
+            .write_file(INVITES_FILE, &serde_json::to_vec_pretty(&invites)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Authorize `spoke_id52` as `alias` with an invite token, using the
+    /// invite up
+    pub async fn join_with_invite(&mut self, spoke_id52: &str, alias: &str, token: &str) -> Result<()> {
+        let rejected = |reason: &str| Error::InviteRejected(reason.to_string());
+        fastn_net::from_id52(spoke_id52).map_err(|_| Error::InvalidId52(spoke_id52.to_string()))?;
+        Self::validate_kosha_alias(alias)
+            .map_err(|_| rejected(&format!("invalid alias {:?} (use lowercase letters, digits, '-' and '_')", alias)))?;
+        let token = fastn_net::InviteToken::verify(token).map_err(|e| rejected(&e.to_string()))?;
+        if token.hub_id52 != self.id52() {
+            return Err(rejected("it is for another hub"));
+        }
+
+        let mut invites = self.list_invites().await?;
+        let Some(index) = invites.iter().position(|invite| invite.id == token.invite_id) else {
+            return Err(rejected("it was already used or revoked"));
+        };
+        let invite = invites.remove(index);
+        // Used up either way
+        self.save_invites(invites).await?;
+        if invite.is_expired() {
+            return Err(rejected("it has expired"));
+        }
+
+        self.spokes.add(spoke_id52, alias);
+        self.save_spokes().await?;
+        self.pending_spokes.remove(spoke_id52);
+        tracing::info!("Spoke {} ({}) joined with invite {}", alias, spoke_id52, invite.id);
+        self.notify(fastn_net::PushEvent::SpokeAuthorized {
+            spoke_id52: spoke_id52.to_string(),
+            alias: alias.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Handle a verified `join` request from `sender_id52`, who isn't a
+    /// spoke yet
+    ///
+    /// Joining changes spokes.txt, so unlike other requests this needs the
+    /// hub to itself; the server routes `join` here instead of to
+    /// `handle_request`.
+    pub async fn handle_join(&mut self, sender_id52: &str, request: Request) -> std::result::Result<Response, HubError> {
+        if let Err(retry_after) = self.rate_limiter.check(sender_id52, &self.config.limits) {
+            return Err(HubError::QuotaExceeded {
+                message: format!("Rate limit exceeded for {}", sender_id52),
+                retry_after_ms: Some(retry_after.as_millis().max(1) as u64),
+            });
+        }
+        let join: JoinRequest = serde_json::from_value(request.payload).map_err(|e| HubError::AppError {
+            message: format!("Invalid join request: {}", e),
+        })?;
+        self.join_with_invite(sender_id52, &join.alias, &join.token)
+            .await
+            .map_err(Self::hub_error)?;
+        let joined = Joined {
+            hub_id52: self.id52().to_string(),
+            spoke_id52: sender_id52.to_string(),
+            alias: join.alias,
+        };
+        let payload = serde_json::to_value(joined).map_err(|e| HubError::AppError { message: e.to_string() })?;
+        Ok(Response { payload })
+    }
+
+    /// Whether a request is a `join`, for `handle_join`
+    pub fn is_join(request: &Request) -> bool {
+        request.app == crate::admin_api::APP_NAME && request.command == JOIN_COMMAND
+    }
+}
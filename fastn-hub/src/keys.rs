@@ -0,0 +1,77 @@
+//! The hub's key: how hub.key is stored, and rotating it
+//!
+//! hub.key starts as the raw key. `Hub::protect_key` (`fastn-hub key
+//! protect`) rewrites it encrypted with a passphrase, which `Hub::load`
+//! then reads from `FASTN_KEY_PASSPHRASE`, or moves the key to the OS
+//! keychain (feature `keychain`); see `fastn_net::key_file`.
+//!
+//! `Hub::rotate_key` (`fastn-hub rotate-key`) replaces the key, and with it
+//! the hub's ID52. The old key signs a `KeyRotation` naming the new ID52,
+//! kept in config.json. Requests spokes still address to the old ID52 are
+//! rejected with it, and spokes that trust the old key follow it to the new
+//! one. The old key file is kept in `retired-keys/`. Other hubs that list
+//! this one in their .hubs files have to be told the new ID52 by their
+//! owners.
+
+use crate::{Error, Hub, Result};
+use fastn_net::key_file::{self, Protection};
+use fastn_net::{KeyRotation, SecretKey};
+use std::path::Path;
+
+/// The hub's key, in FASTN_HOME
+pub const KEY_FILE: &str = "hub.key";
+
+/// Keys the hub rotated away from, as `<id52>.key`, in FASTN_HOME
+pub const RETIRED_KEYS_DIR: &str = "retired-keys";
+
+impl Hub {
+    /// How hub.key at `home` stores the key, without unlocking it
+    pub fn key_protection(home: &Path) -> Result<Protection> {
+        if !Self::is_initialized(home) {
+            return Err(Error::NotInitialized);
+        }
+        Ok(key_file::protection(&home.join(KEY_FILE))?)
+    }
+
+    /// Rewrite hub.key with `protection`
+    ///
+    /// A key moved out of the keychain is removed from it.
+    pub fn protect_key(&mut self, protection: Protection) -> Result<()> {
+        key_file::write(&self.home.join(KEY_FILE), &self.secret_key, &protection)?;
+        if self.key_protection == Protection::Keychain && protection != Protection::Keychain {
+            key_file::forget(self.id52())?;
+        }
+        tracing::info!("hub.key is now {}", protection.name());
+        self.key_protection = protection;
+        Ok(())
+    }
+
+    /// Keys the hub rotated away from, oldest first
+    pub fn key_rotations(&self) -> &[KeyRotation] {
+        &self.config.key_rotations
+    }
+
+    /// Replace the hub's key with a new one, stored like the old one
+    ///
+    /// Run with the hub stopped: a running hub keeps answering as the ID52
+    /// it started with.
+    pub async fn rotate_key(&mut self) -> Result<KeyRotation> {
+        let new_key = SecretKey::generate();
+        let rotation = KeyRotation::sign(&self.secret_key, &new_key.id52(), fastn_net::unix_time());
+
+        // Keep the old key; a keychain entry stays where it is, the file
+        // naming it moves
+        let retired = self.home.join(RETIRED_KEYS_DIR);
+        tokio::fs::create_dir_all(&retired).await?;
+        tokio::fs::copy(self.home.join(KEY_FILE), retired.join(format!("{}.key", rotation.old_id52))).await?;
+
+        key_file::write(&self.home.join(KEY_FILE), &new_key, &self.key_protection)?;
+        self.secret_key = new_key;
+        self.config.hub_id52 = rotation.new_id52.clone();
+        self.config.key_rotations.push(rotation.clone());
+        self.save_config().await?;
+
+        tracing::info!("Rotated hub key {} -> {}", rotation.old_id52, rotation.new_id52);
+        Ok(rotation)
+    }
+}
@@ -10,16 +10,47 @@
 //!
 //! See README.md for full documentation.
 
+pub mod acl_wasm;
+pub mod admin_api;
+pub mod asset_metadata;
+pub mod audit;
+pub mod gateway;
+pub mod invite;
+pub mod keys;
+pub mod limits;
+pub mod listen;
+pub mod metrics;
+pub mod mirror;
+pub mod mounts;
+pub mod push;
+
+pub use acl_wasm::AclLimits;
+pub use audit::{AuditConfig, AuditEntry, AuditQuery, AuditResult};
+pub use gateway::CapabilityToken;
+pub use invite::{DEFAULT_INVITE_TTL, Invite};
+pub use limits::Limits;
+pub use listen::{ServeConfig, TlsConfig};
+pub use metrics::{HubMetrics, METRICS_ENDPOINT};
+pub use mirror::{Mirror, MirrorSync, MirroredFile};
+
 use chrono::{DateTime, Utc};
-use fastn_kosha::Kosha;
+use fastn_kosha::{Kosha, WasmPool};
+use fastn_net::key_file::{self, Protection};
 use rust_embed::Embed;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use thiserror::Error;
+use tracing::Instrument;
+use tokio_util::sync::CancellationToken;
 
 pub use fastn_net::SecretKey;
-use fastn_net::{SignedRequest, SignedResponse, ResponseEnvelope, ENDPOINT, HubResponse};
+use fastn_net::{AclDecision, Audience, AclTrace, AclTraceStep, ReplayGuard, SignedResponse, ResponseEnvelope, ENDPOINT, HubResponse};
+
+// Spokes size chunks with the fastn-net constant, the kosha enforces its own
+const _: () = assert!(fastn_net::MAX_CHUNK_SIZE == fastn_kosha::MAX_CHUNK_SIZE);
 
 #[derive(Embed)]
 #[folder = "static/"]
@@ -55,8 +86,35 @@ pub enum Error {
     #[error("Invalid ID52: {0}")]
     InvalidId52(String),
 
+    #[error("Invalid kosha alias: {0:?} (use lowercase letters, digits, '-' and '_')")]
+    InvalidKoshaAlias(String),
+
+    #[error("Kosha already exists: {0}")]
+    KoshaExists(String),
+
+    #[error("The root kosha can't be deleted")]
+    RootKoshaDeletion,
+
     #[error("Kosha error: {0}")]
     Kosha(#[from] fastn_kosha::Error),
+
+    #[error("Mirror error: {0}")]
+    Mirror(String),
+
+    #[error("Invalid token name: {0:?} (use lowercase letters, digits, '-' and '_')")]
+    InvalidTokenName(String),
+
+    #[error("Token already exists: {0}")]
+    TokenExists(String),
+
+    #[error("Hub authorization error: {0}")]
+    HubAuth(String),
+
+    #[error("Invite rejected: {0}")]
+    InviteRejected(String),
+
+    #[error("Cannot serve: {0}")]
+    Serve(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -68,6 +126,10 @@ pub enum SenderIdentity {
     OwnSpoke { spoke_id52: String },
     /// Sender is a remote hub forwarding a request
     RemoteHub { hub_id52: String, alias: String },
+    /// Sender presented a capability token to the REST gateway (see
+    /// `gateway`); it acts for the owner within the token's scope, ACL
+    /// modules included
+    Token { name: String },
 }
 
 impl SenderIdentity {
@@ -81,7 +143,7 @@ impl SenderIdentity {
     /// For remote hubs, this returns the hub's ID52
     pub fn requester_hub_id(&self) -> Option<&str> {
         match self {
-            SenderIdentity::OwnSpoke { .. } => None,
+            SenderIdentity::OwnSpoke { .. } | SenderIdentity::Token { .. } => None,
             SenderIdentity::RemoteHub { hub_id52, .. } => Some(hub_id52),
         }
     }
@@ -95,6 +157,36 @@ pub struct HubConfig {
     /// Optional password for spoke registration (if None, registration is disabled)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub spoke_password: Option<String>,
+    /// Request rate limits and kosha storage quotas (see `limits`)
+    #[serde(default, skip_serializing_if = "Limits::is_unlimited")]
+    pub limits: Limits,
+    /// Audit log rotation (see `audit`)
+    #[serde(default, skip_serializing_if = "AuditConfig::is_default")]
+    pub audit: AuditConfig,
+    /// Serve the REST gateway for capability tokens (see `gateway`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rest_gateway: bool,
+    /// Address, port, TLS and trusted proxies of `Hub::serve` (see
+    /// `listen`)
+    #[serde(default, skip_serializing_if = "ServeConfig::is_default")]
+    pub serve: ServeConfig,
+    /// Keys the hub has rotated away from, oldest first, each signed by
+    /// the key it retired (see `keys`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_rotations: Vec<fastn_net::KeyRotation>,
+}
+
+/// Outcome of `Hub::migrate_kosha`
+#[derive(Debug, Clone)]
+pub struct KoshaMigration {
+    pub alias: String,
+    /// Format version before migrating
+    pub from: u32,
+    /// Migrations pending (dry run) or applied, empty if the kosha is up to
+    /// date
+    pub migrations: Vec<fastn_kosha::PlannedMigration>,
+    /// Where the kosha was backed up to, if it was migrated
+    pub backup: Option<PathBuf>,
 }
 
 /// Response for /hub-info endpoint (public info)
@@ -103,6 +195,22 @@ pub struct HubInfo {
     pub hub_id52: String,
 }
 
+/// Path of the health endpoint (public, for systemd or Docker healthchecks)
+pub const HEALTH_ENDPOINT: &str = "/_fastn/health";
+
+/// Response for the health endpoint (public info)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HubHealth {
+    /// Always "ok"; an unhealthy hub answers 503 instead
+    pub status: String,
+    pub hub_id52: String,
+    /// fastn-hub version
+    pub version: String,
+    pub uptime_secs: i64,
+    /// Aliases of the koshas the hub serves, sorted
+    pub koshas: Vec<String>,
+}
+
 /// Request for /register-spoke endpoint
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegisterSpokeRequest {
@@ -204,6 +312,8 @@ impl SpokesConfig {
 //
 // File format (same as spokes.txt):
 //   <id52>: <alias>     - authorize a hub with given alias
+//   <id52>: <alias> [<url>] [iroh:<endpoint>]
+//                       - ... and say how to reach it, for forwarding
 //   # comment           - comments are ignored
 //   @<filename>         - include all hubs from another file
 //   @ROOT/<alias>       - include from root kosha's hubs/<alias>.txt
@@ -218,8 +328,13 @@ impl SpokesConfig {
 /// An entry in a hub authorization file
 #[derive(Debug, Clone)]
 pub enum HubAuthEntry {
-    /// Direct hub authorization: <id52>: <alias> [<url>]
-    Hub { id52: String, alias: String, url: Option<String> },
+    /// Direct hub authorization: <id52>: <alias> [<url>] [iroh:<endpoint>]
+    Hub {
+        id52: String,
+        alias: String,
+        url: Option<String>,
+        iroh: Option<String>,
+    },
     /// Include another file: @<filename> (relative to current kosha)
     Include(String),
     /// Include from root kosha: @ROOT/<alias>
@@ -274,19 +389,25 @@ impl HubAuthFile {
                     return Some(HubAuthEntry::Include(include.to_string()));
                 }
 
-                // Parse id52: alias [url] format
+                // Parse id52: alias [url] [iroh:endpoint] format
                 let parts: Vec<&str> = line.splitn(2, ':').collect();
                 if parts.len() == 2 {
                     let id52 = parts[0].trim().to_string();
                     let rest = parts[1].trim();
-                    // Split by whitespace to get alias and optional URL
+                    // Split by whitespace to get alias and optional addresses
                     let mut tokens = rest.split_whitespace();
                     let alias = tokens.next().unwrap_or("").to_string();
-                    let url = tokens.next().map(|s| s.to_string());
+                    let (mut url, mut iroh) = (None, None);
+                    for token in tokens {
+                        match token.strip_prefix("iroh:") {
+                            Some(endpoint) => iroh = Some(endpoint.to_string()),
+                            None => url = Some(token.to_string()),
+                        }
+                    }
                     if alias.is_empty() {
                         None
                     } else {
-                        Some(HubAuthEntry::Hub { id52, alias, url })
+                        Some(HubAuthEntry::Hub { id52, alias, url, iroh })
                     }
                 } else {
                     None
@@ -301,12 +422,15 @@ impl HubAuthFile {
         self.entries
             .iter()
             .map(|e| match e {
-                HubAuthEntry::Hub { id52, alias, url } => {
+                HubAuthEntry::Hub { id52, alias, url, iroh } => {
+                    let mut line = format!("{}: {}", id52, alias);
                     if let Some(u) = url {
-                        format!("{}: {} {}", id52, alias, u)
-                    } else {
-                        format!("{}: {}", id52, alias)
+                        line.push_str(&format!(" {}", u));
+                    }
+                    if let Some(endpoint) = iroh {
+                        line.push_str(&format!(" iroh:{}", endpoint));
                     }
+                    line
                 }
                 HubAuthEntry::Include(name) => format!("@{}", name),
                 HubAuthEntry::IncludeRoot(name) => format!("@ROOT/{}", name),
@@ -326,10 +450,108 @@ pub struct ResolvedHubAuth {
     pub alias: String,
     /// The hub's URL (for forwarding requests)
     pub url: Option<String>,
+    /// The hub's iroh endpoint (for forwarding requests)
+    pub iroh: Option<String>,
     /// The file path where this hub was defined (for debugging)
     pub source_file: String,
 }
 
+impl ResolvedHubAuth {
+    /// How to reach the hub
+    pub fn address(&self) -> fastn_net::HubAddress {
+        fastn_net::HubAddress {
+            url: self.url.clone(),
+            iroh: self.iroh.clone(),
+        }
+    }
+}
+
+/// An entry of a .hubs file once its @includes are expanded, before
+/// `#alias` references are looked up
+enum CollectedHubAuth {
+    Hub {
+        hub: ResolvedHubAuth,
+        /// The alias in the hub's line, which `#alias` refers to
+        defined_alias: String,
+    },
+    AliasRef {
+        alias: String,
+        /// The alias the referenced hub gets (the includer's, if included)
+        resolved_alias: String,
+        source_file: String,
+    },
+}
+
+/// Look up `#alias` references among the collected hubs
+///
+/// A reference finds the hub defined with that alias, or the hub another
+/// reference gave that alias when included; references are looked up
+/// until no more can be. What is left either refers to an alias nothing
+/// defines or is part of a cycle of references, and both are errors.
+fn link_alias_refs(collected: Vec<CollectedHubAuth>) -> Result<Vec<ResolvedHubAuth>> {
+    let mut by_alias: HashMap<String, ResolvedHubAuth> = HashMap::new();
+    for entry in &collected {
+        if let CollectedHubAuth::Hub { hub, defined_alias } = entry {
+            by_alias.entry(defined_alias.clone()).or_insert_with(|| hub.clone());
+        }
+    }
+
+    let mut resolved: Vec<Option<ResolvedHubAuth>> = collected
+        .iter()
+        .map(|entry| match entry {
+            CollectedHubAuth::Hub { hub, .. } => Some(hub.clone()),
+            CollectedHubAuth::AliasRef { .. } => None,
+        })
+        .collect();
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for (entry, slot) in collected.iter().zip(resolved.iter_mut()) {
+            if let CollectedHubAuth::AliasRef { alias, resolved_alias, source_file } = entry
+                && slot.is_none()
+                && let Some(hub) = by_alias.get(alias)
+            {
+                let hub = ResolvedHubAuth {
+                    alias: resolved_alias.clone(),
+                    source_file: source_file.clone(),
+                    ..hub.clone()
+                };
+                by_alias.entry(resolved_alias.clone()).or_insert_with(|| hub.clone());
+                *slot = Some(hub);
+                progress = true;
+            }
+        }
+    }
+
+    let unresolved: Vec<(&str, &str, &str)> = collected
+        .iter()
+        .zip(&resolved)
+        .filter_map(|(entry, slot)| match entry {
+            CollectedHubAuth::AliasRef { alias, resolved_alias, source_file } if slot.is_none() => {
+                Some((alias.as_str(), resolved_alias.as_str(), source_file.as_str()))
+            }
+            _ => None,
+        })
+        .collect();
+    if let Some(&(alias, _, source_file)) = unresolved.first() {
+        // Follow the references that would give each missing alias
+        let mut chain = vec![(alias, source_file)];
+        let mut current = alias;
+        let giving = |alias: &str| unresolved.iter().find(|(refers, given, _)| *given == alias && refers != given);
+        while let Some(&(next, _, file)) = giving(current) {
+            let cycle = chain.iter().any(|(seen, _)| *seen == next);
+            chain.push((next, file));
+            if cycle {
+                let chain: Vec<String> = chain.iter().map(|(alias, file)| format!("#{} ({})", alias, file)).collect();
+                return Err(Error::HubAuth(format!("Alias references form a cycle: {}", chain.join(" -> "))));
+            }
+            current = next;
+        }
+        return Err(Error::HubAuth(format!("Unknown hub alias #{} in {}", alias, source_file)));
+    }
+    Ok(resolved.into_iter().flatten().collect())
+}
+
 /// Hub authorization resolver - resolves @includes recursively
 pub struct HubAuthResolver<'a> {
     /// The root kosha for @ROOT includes
@@ -359,16 +581,17 @@ impl<'a> HubAuthResolver<'a> {
         }
     }
 
-    /// Resolve a hub authorization file, returning all authorized hubs
+    /// Collect the hubs of a hub authorization file, expanding @includes;
+    /// `#alias` references are looked up once every file is collected
     ///
     /// The `file_path` is relative to the hubs/ or _hubs/ folder.
     /// The `override_alias` is used when this file is included via @include.
-    pub fn resolve<'b>(
+    fn collect<'b>(
         &'b self,
         file_path: &'b str,
         override_alias: Option<&'b str>,
         visited: &'b mut std::collections::HashSet<String>,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<ResolvedHubAuth>>> + Send + 'b>> {
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Vec<CollectedHubAuth>>> + Send + 'b>> {
         Box::pin(async move {
             // Prevent infinite loops
             let full_path = if self.is_root {
@@ -411,21 +634,25 @@ impl<'a> HubAuthResolver<'a> {
 
             for entry in file.entries {
                 match entry {
-                    HubAuthEntry::Hub { id52, alias, url } => {
+                    HubAuthEntry::Hub { id52, alias, url, iroh } => {
                         // Use override alias if provided, otherwise use the original alias
                         let final_alias = override_alias.unwrap_or(&alias);
-                        results.push(ResolvedHubAuth {
-                            id52,
-                            alias: final_alias.to_string(),
-                            url,
-                            source_file: path.clone(),
+                        results.push(CollectedHubAuth::Hub {
+                            hub: ResolvedHubAuth {
+                                id52,
+                                alias: final_alias.to_string(),
+                                url,
+                                iroh,
+                                source_file: path.clone(),
+                            },
+                            defined_alias: alias,
                         });
                     }
                     HubAuthEntry::Include(name) => {
                         // Include from same folder
                         let include_path = format!("{}.hubs", name);
                         let included = self
-                            .resolve(&include_path, Some(file_alias), visited)
+                            .collect(&include_path, Some(file_alias), visited)
                             .await?;
                         results.extend(included);
                     }
@@ -434,19 +661,16 @@ impl<'a> HubAuthResolver<'a> {
                         let root_resolver = HubAuthResolver::for_root(self.root_kosha);
                         let include_path = format!("{}.hubs", name);
                         let included = root_resolver
-                            .resolve(&include_path, Some(file_alias), visited)
+                            .collect(&include_path, Some(file_alias), visited)
                             .await?;
                         results.extend(included);
                     }
                     HubAuthEntry::AliasRef(alias) => {
-                        // Reference a single hub by alias - look it up in all resolved hubs
-                        // For now, we defer this - the alias lookup happens at a higher level
-                        // after all files are resolved. We store it as a placeholder.
-                        // TODO: Implement proper alias lookup during resolution
-                        results.push(ResolvedHubAuth {
-                            id52: format!("@alias:{}", alias), // Placeholder for alias lookup
-                            alias: override_alias.unwrap_or(&alias).to_string(),
-                            url: None,
+                        // Reference a single hub by alias, looked up in
+                        // link_alias_refs once every file is collected
+                        results.push(CollectedHubAuth::AliasRef {
+                            resolved_alias: override_alias.unwrap_or(&alias).to_string(),
+                            alias,
                             source_file: path.clone(),
                         });
                     }
@@ -458,6 +682,9 @@ impl<'a> HubAuthResolver<'a> {
     }
 
     /// Resolve all hub authorizations from a folder
+    ///
+    /// Fails if a `#alias` reference names no hub, or references form a
+    /// cycle.
     pub async fn resolve_all(&self) -> Result<Vec<ResolvedHubAuth>> {
         let kosha = if self.is_root {
             self.root_kosha
@@ -474,17 +701,17 @@ impl<'a> HubAuthResolver<'a> {
             Err(e) => return Err(Error::Kosha(e)),
         };
 
-        let mut all_results = Vec::new();
+        let mut collected = Vec::new();
         let mut visited = std::collections::HashSet::new();
 
         for entry in entries {
             if entry.name.ends_with(".hubs") && !entry.is_dir {
-                let results = self.resolve(&entry.name, None, &mut visited).await?;
-                all_results.extend(results);
+                let results = self.collect(&entry.name, None, &mut visited).await?;
+                collected.extend(results);
             }
         }
 
-        Ok(all_results)
+        link_alias_refs(collected)
     }
 
     /// Check if a hub ID52 is authorized
@@ -509,6 +736,8 @@ pub struct Hub {
     home: PathBuf,
     /// Hub's secret key
     secret_key: SecretKey,
+    /// How hub.key stores it
+    key_protection: Protection,
     /// Configuration
     config: HubConfig,
     /// Authorized spokes
@@ -518,10 +747,29 @@ pub struct Hub {
     pending_spokes: HashMap<String, PendingSpoke>,
     /// Root kosha for system configuration
     root_kosha: Kosha,
-    /// Registered koshas by alias
-    koshas: HashMap<String, Kosha>,
+    /// Opened koshas by alias
+    ///
+    /// Every directory in `FASTN_HOME/koshas/` is a kosha; it is opened on
+    /// first use and stays open.
+    koshas: tokio::sync::RwLock<HashMap<String, Kosha>>,
     /// ACLs by (app, instance) -> Acl
     acls: HashMap<(String, String), Acl>,
+    /// Resource limits for ACL WASM modules
+    acl_limits: AclLimits,
+    /// Threads (and the engine) ACL modules and kosha handlers run on
+    wasm_pool: Arc<WasmPool>,
+    /// Request counters for the admin API
+    metrics: HubMetrics,
+    /// Events for push subscribers (see `push`)
+    events: tokio::sync::broadcast::Sender<fastn_net::PushEvent>,
+    /// Request budgets per sender identity
+    rate_limiter: limits::RateLimiter,
+    /// Request budgets per client address
+    client_rate_limiter: limits::RateLimiter,
+    /// Log of every request (see `audit`)
+    audit: audit::AuditLog,
+    /// Aliases of the koshas that mirror other hubs' (see `mirror`)
+    mirrors: std::collections::HashSet<String>,
 }
 
 impl Hub {
@@ -541,7 +789,7 @@ impl Hub {
 
     /// Check if hub is initialized at a specific path
     pub fn is_initialized(home: &std::path::Path) -> bool {
-        home.join("hub.key").exists()
+        home.join(keys::KEY_FILE).exists()
     }
 
     /// Get the hub's ID52
@@ -576,15 +824,18 @@ impl Hub {
         let hub_id52 = fastn_net::to_id52(&public_key);
 
         // Save secret key
-        let key_path = home.join("hub.key");
-        let key_bytes = secret_key.to_bytes();
-        tokio::fs::write(&key_path, key_bytes).await?;
+        key_file::write(&home.join(keys::KEY_FILE), &secret_key, &Protection::Plain)?;
 
         // Create and save config
         let config = HubConfig {
             hub_id52,
             created_at: Utc::now(),
             spoke_password: None,
+            limits: Limits::default(),
+            audit: AuditConfig::default(),
+            rest_gateway: false,
+            serve: ServeConfig::default(),
+            key_rotations: vec![],
         };
         let config_path = home.join("config.json");
         let config_json = serde_json::to_string_pretty(&config)?;
@@ -595,7 +846,9 @@ impl Hub {
 
         // Create root kosha at FASTN_HOME/koshas/root/
         let root_kosha_path = home.join("koshas").join("root");
-        let root_kosha = Kosha::open(root_kosha_path, "root".to_string()).await?;
+        let root_kosha = Kosha::open(root_kosha_path, "root".to_string())
+            .await?
+            .with_actor_id(&config.hub_id52);
 
         // Write empty spokes.txt to root kosha
         let spokes_content = b"# Authorized spokes (one per line)\n# Format: <id52>: <alias>\n";
@@ -608,6 +861,7 @@ impl Hub {
 # Each .hubs file can contain:\n\
 #\n\
 #   <id52>: <alias>    - authorize a hub with given alias\n\
+#   <id52>: <alias> <url> iroh:<endpoint>  - and how to reach it (either or both)\n\
 #   <id52>: <alias> # comment  - inline comments supported\n\
 #   # full line comment        - lines starting with '# ' are comments\n\
 #   @<filename>        - include all hubs from <filename>.hubs\n\
@@ -639,19 +893,28 @@ impl Hub {
 
         let spokes = SpokesConfig::default();
 
-        // Register root kosha in the koshas map so it can be accessed via "root" instance
-        let mut koshas = HashMap::new();
-        koshas.insert("root".to_string(), root_kosha.clone());
+        // The root kosha is always open; other koshas are opened on first use
+        let koshas = HashMap::from([("root".to_string(), root_kosha.clone())]);
 
+        let audit = audit::AuditLog::new(home.join("audit"), config.audit.clone());
         Ok(Self {
             home,
             secret_key,
+            key_protection: Protection::Plain,
             config,
             spokes,
             pending_spokes: HashMap::new(),
             root_kosha,
-            koshas,
+            koshas: tokio::sync::RwLock::new(koshas),
             acls: HashMap::new(),
+            acl_limits: AclLimits::default(),
+            wasm_pool: WasmPool::shared(),
+            metrics: HubMetrics::default(),
+            events: tokio::sync::broadcast::channel(push::EVENT_BUFFER).0,
+            rate_limiter: limits::RateLimiter::default(),
+            client_rate_limiter: limits::RateLimiter::default(),
+            audit,
+            mirrors: Default::default(),
         })
     }
 
@@ -666,16 +929,9 @@ impl Hub {
 
         let home = home.to_path_buf();
 
-        // Load secret key
-        let key_path = home.join("hub.key");
-        let key_bytes = tokio::fs::read(&key_path).await?;
-        let key_array: [u8; 32] = key_bytes
-            .try_into()
-            .map_err(|_| Error::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid key file: expected 32 bytes",
-            )))?;
-        let secret_key = SecretKey::from_bytes(&key_array);
+        // Load secret key, with FASTN_KEY_PASSPHRASE if it is encrypted
+        let (secret_key, key_protection) =
+            key_file::read(&home.join(keys::KEY_FILE), key_file::env_passphrase().as_deref())?;
 
         // Load config
         let config_path = home.join("config.json");
@@ -684,7 +940,10 @@ impl Hub {
 
         // Load root kosha
         let root_kosha_path = home.join("koshas").join("root");
-        let root_kosha = Kosha::open(root_kosha_path, "root".to_string()).await?;
+        let root_kosha = Kosha::open(root_kosha_path, "root".to_string())
+            .await?
+            .with_actor_id(&config.hub_id52)
+            .with_storage_quota(config.limits.kosha_quota("root"));
 
         // Load spokes.txt from root kosha
         let spokes = match root_kosha.read_file("spokes.txt").await {
@@ -696,19 +955,29 @@ impl Hub {
             Err(e) => return Err(Error::Kosha(e)),
         };
 
-        // Register root kosha in the koshas map so it can be accessed via "root" instance
-        let mut koshas = HashMap::new();
-        koshas.insert("root".to_string(), root_kosha.clone());
+        // The root kosha is always open; other koshas are opened on first use
+        let koshas = HashMap::from([("root".to_string(), root_kosha.clone())]);
 
+        let audit = audit::AuditLog::new(home.join("audit"), config.audit.clone());
+        let mirrors = mirror::load_aliases(&home.join("mirrors")).await?;
         Ok(Self {
             home,
             secret_key,
+            key_protection,
             config,
             spokes,
             pending_spokes: HashMap::new(),
             root_kosha,
-            koshas,
+            koshas: tokio::sync::RwLock::new(koshas),
             acls: HashMap::new(),
+            acl_limits: AclLimits::default(),
+            wasm_pool: WasmPool::shared(),
+            metrics: HubMetrics::default(),
+            events: tokio::sync::broadcast::channel(push::EVENT_BUFFER).0,
+            rate_limiter: limits::RateLimiter::default(),
+            client_rate_limiter: limits::RateLimiter::default(),
+            audit,
+            mirrors,
         })
     }
 
@@ -778,6 +1047,11 @@ impl Hub {
         // Remove from pending
         self.pending_spokes.remove(id52);
 
+        self.notify(fastn_net::PushEvent::SpokeAuthorized {
+            spoke_id52: id52.to_string(),
+            alias: alias.clone(),
+        });
+
         Ok(alias)
     }
 
@@ -818,6 +1092,10 @@ impl Hub {
         self.save_spokes().await?;
 
         tracing::info!("Registered new spoke: {} ({})", alias, spoke_id52);
+        self.notify(fastn_net::PushEvent::SpokeAuthorized {
+            spoke_id52: spoke_id52.to_string(),
+            alias: alias.to_string(),
+        });
         Ok(())
     }
 
@@ -828,6 +1106,42 @@ impl Hub {
         }
     }
 
+    /// Get hub health for the health endpoint
+    ///
+    /// Fails if FASTN_HOME/koshas/ can't be read.
+    pub async fn health(&self) -> Result<HubHealth> {
+        Ok(HubHealth {
+            status: "ok".to_string(),
+            hub_id52: self.config.hub_id52.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: (Utc::now() - self.metrics.started_at).num_seconds(),
+            koshas: self.list_koshas().await?,
+        })
+    }
+
+    /// Request counters and latencies, kosha traffic and push sessions
+    /// (see `metrics`)
+    pub fn metrics(&self) -> &HubMetrics {
+        &self.metrics
+    }
+
+    /// Flush pending kosha writes before the hub stops
+    ///
+    /// Open database transactions are rolled back, so no database is left
+    /// locked; returns how many there were. Everything else a kosha writes
+    /// is already on disk when its request completes.
+    pub async fn shutdown(&self) -> usize {
+        let mut rolled_back = 0;
+        for (alias, kosha) in self.koshas.read().await.iter() {
+            let count = kosha.db_rollback_all().await;
+            if count > 0 {
+                tracing::info!("Rolled back {} open transaction(s) in kosha '{}'", count, alias);
+            }
+            rolled_back += count;
+        }
+        rolled_back
+    }
+
     /// Set the spoke registration password
     pub async fn set_spoke_password(&mut self, password: Option<String>) -> Result<()> {
         self.config.spoke_password = password;
@@ -835,6 +1149,37 @@ impl Hub {
     }
 
     /// Save config to disk
+    /// Change the rate limits and storage quotas, and save them to
+    /// config.json
+    ///
+    /// Open koshas get their new quota right away.
+    pub async fn set_limits(&mut self, limits: Limits) -> Result<()> {
+        self.config.limits = limits;
+        self.root_kosha = self.root_kosha.clone().with_storage_quota(self.config.limits.kosha_quota("root"));
+        for (alias, kosha) in self.koshas.get_mut().iter_mut() {
+            *kosha = kosha.clone().with_storage_quota(self.config.limits.kosha_quota(alias));
+        }
+        self.save_config().await
+    }
+
+    /// Current rate limits and storage quotas
+    pub fn limits(&self) -> &Limits {
+        &self.config.limits
+    }
+
+    /// Change where and how the hub serves, from its next start, and save
+    /// it to config.json
+    pub async fn set_serve_config(&mut self, serve: ServeConfig) -> Result<()> {
+        serve.validate().map_err(Error::Serve)?;
+        self.config.serve = serve;
+        self.save_config().await
+    }
+
+    /// Where and how the hub serves, as configured
+    pub fn serve_config(&self) -> &ServeConfig {
+        &self.config.serve
+    }
+
     async fn save_config(&self) -> Result<()> {
         let config_path = self.home.join("config.json");
         let config_json = serde_json::to_string_pretty(&self.config)?;
@@ -868,19 +1213,334 @@ impl Hub {
         Ok(())
     }
 
-    /// Register a kosha
+    /// Register a kosha stored outside FASTN_HOME/koshas/
+    ///
+    /// KV writes made through this hub are stamped with the hub's ID52.
+    /// The registration lasts until the hub stops.
     pub fn register_kosha(&mut self, kosha: Kosha) {
-        self.koshas.insert(kosha.alias().to_string(), kosha);
+        let quota = self.config.limits.kosha_quota(kosha.alias());
+        let kosha = kosha.with_actor_id(self.id52()).with_storage_quota(quota);
+        self.koshas.get_mut().insert(kosha.alias().to_string(), kosha);
+    }
+
+    /// Check that an alias can name a kosha directory
+    ///
+    /// Aliases are 1-64 characters of lowercase letters, digits, '-' and
+    /// '_', starting with a letter or digit.
+    pub fn validate_kosha_alias(alias: &str) -> Result<()> {
+        let valid = alias.len() <= 64
+            && alias.chars().next().is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && alias.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        match valid {
+            true => Ok(()),
+            false => Err(Error::InvalidKoshaAlias(alias.to_string())),
+        }
+    }
+
+    /// Directory of the kosha with the given (valid) alias
+    fn kosha_path(&self, alias: &str) -> PathBuf {
+        self.home.join("koshas").join(alias)
+    }
+
+    /// Get a kosha by alias, opening it on first use
+    pub async fn get_kosha(&self, alias: &str) -> Result<Option<Kosha>> {
+        if let Some(kosha) = self.koshas.read().await.get(alias) {
+            return Ok(Some(kosha.clone()));
+        }
+        if Self::validate_kosha_alias(alias).is_err() {
+            return Ok(None);
+        }
+
+        let mut koshas = self.koshas.write().await;
+        // Another request may have opened it while we waited for the lock
+        if let Some(kosha) = koshas.get(alias) {
+            return Ok(Some(kosha.clone()));
+        }
+        let path = self.kosha_path(alias);
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        let kosha = Kosha::open(path, alias.to_string())
+            .await?
+            .with_actor_id(self.id52())
+            .with_worker_pool(self.wasm_pool.clone())
+            .with_storage_quota(self.config.limits.kosha_quota(alias));
+        tracing::debug!("Opened kosha {}", alias);
+        if kosha.format_version() < fastn_kosha::FORMAT_VERSION {
+            tracing::warn!(
+                "Kosha {} has format version {}, run 'fastn-hub migrate --apply' to bring it to {}",
+                alias,
+                kosha.format_version(),
+                fastn_kosha::FORMAT_VERSION
+            );
+        }
+        koshas.insert(alias.to_string(), kosha.clone());
+        Ok(Some(kosha))
+    }
+
+    /// List kosha aliases, sorted
+    ///
+    /// Includes every kosha in FASTN_HOME/koshas/, opened or not.
+    pub async fn list_koshas(&self) -> Result<Vec<String>> {
+        let mut aliases: Vec<String> = self.koshas.read().await.keys().cloned().collect();
+        let mut entries = tokio::fs::read_dir(self.home.join("koshas")).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            if let Some(alias) = entry.file_name().to_str()
+                && Self::validate_kosha_alias(alias).is_ok()
+            {
+                aliases.push(alias.to_string());
+            }
+        }
+        aliases.sort();
+        aliases.dedup();
+        Ok(aliases)
+    }
+
+    /// Create a new kosha at FASTN_HOME/koshas/<alias>/
+    pub async fn create_kosha(&self, alias: &str) -> Result<Kosha> {
+        Self::validate_kosha_alias(alias)?;
+        let mut koshas = self.koshas.write().await;
+        let path = self.kosha_path(alias);
+        if koshas.contains_key(alias) || tokio::fs::try_exists(&path).await? {
+            return Err(Error::KoshaExists(alias.to_string()));
+        }
+        let kosha = Kosha::open(path, alias.to_string())
+            .await?
+            .with_actor_id(self.id52())
+            .with_worker_pool(self.wasm_pool.clone())
+            .with_storage_quota(self.config.limits.kosha_quota(alias));
+        koshas.insert(alias.to_string(), kosha.clone());
+        tracing::info!("Created kosha {}", alias);
+        Ok(kosha)
+    }
+
+    /// Fork a kosha into a new one at FASTN_HOME/koshas/<new_alias>/
+    ///
+    /// The fork shares its files and history with the source until either
+    /// side writes (see `Kosha::fork`), so it is cheap even for large koshas:
+    /// use it to experiment on a copy, or to snapshot a kosha before risky
+    /// bulk changes.
+    pub async fn fork_kosha(&self, source: &str, new_alias: &str) -> Result<Kosha> {
+        Self::validate_kosha_alias(new_alias)?;
+        let Some(source_kosha) = self.get_kosha(source).await? else {
+            return Err(Error::InstanceNotFound("kosha".to_string(), source.to_string()));
+        };
+        let mut koshas = self.koshas.write().await;
+        let path = self.kosha_path(new_alias);
+        if koshas.contains_key(new_alias) || tokio::fs::try_exists(&path).await? {
+            return Err(Error::KoshaExists(new_alias.to_string()));
+        }
+        let kosha = source_kosha
+            .fork(path, new_alias.to_string())
+            .await?
+            .with_actor_id(self.id52())
+            .with_worker_pool(self.wasm_pool.clone())
+            .with_storage_quota(self.config.limits.kosha_quota(new_alias));
+        koshas.insert(new_alias.to_string(), kosha.clone());
+        tracing::info!("Forked kosha {} into {}", source, new_alias);
+        Ok(kosha)
+    }
+
+    /// Delete a kosha and all its files, history and data
+    ///
+    /// Returns false if there is no such kosha. The root kosha can't be
+    /// deleted.
+    pub async fn delete_kosha(&self, alias: &str) -> Result<bool> {
+        if alias == "root" {
+            return Err(Error::RootKoshaDeletion);
+        }
+        // Holding the lock keeps get_kosha from reopening it halfway through
+        let mut koshas = self.koshas.write().await;
+        let registered = koshas.remove(alias).is_some();
+        if Self::validate_kosha_alias(alias).is_err() {
+            return Ok(registered);
+        }
+        let path = self.kosha_path(alias);
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(registered);
+        }
+        tokio::fs::remove_dir_all(&path).await?;
+        tracing::info!("Deleted kosha {}", alias);
+        Ok(true)
     }
 
-    /// Get a registered kosha by alias
-    pub fn get_kosha(&self, alias: &str) -> Option<&Kosha> {
-        self.koshas.get(alias)
+    /// Bring a kosha's on-disk format up to date, or with `apply` false only
+    /// report what that would do
+    ///
+    /// Before changing anything, the kosha is backed up to
+    /// FASTN_HOME/backups/<alias>-<timestamp>/ (hard-linked like a fork, so
+    /// it is cheap). Run it with the hub server stopped.
+    pub async fn migrate_kosha(&self, alias: &str, apply: bool) -> Result<KoshaMigration> {
+        Self::validate_kosha_alias(alias)?;
+        let path = self.kosha_path(alias);
+        if !tokio::fs::try_exists(&path).await? {
+            return Err(Error::InstanceNotFound("kosha".to_string(), alias.to_string()));
+        }
+        let from = fastn_kosha::read_format(&path).await?.version;
+        let planned = fastn_kosha::plan_migrations(&path).await?;
+        if !apply || planned.is_empty() {
+            return Ok(KoshaMigration {
+                alias: alias.to_string(),
+                from,
+                migrations: planned,
+                backup: None,
+            });
+        }
+
+        let backup = self
+            .home
+            .join("backups")
+            .join(format!("{}-{}", alias, Utc::now().format("%Y%m%dT%H%M%SZ")));
+        tokio::fs::create_dir_all(self.home.join("backups")).await?;
+        fastn_kosha::backup(&path, &backup).await?;
+        tracing::info!("Backed up kosha {} to {:?}", alias, backup);
+
+        let migrations = fastn_kosha::migrate(&path).await?;
+        tracing::info!("Migrated kosha {} from format {} to {}", alias, from, fastn_kosha::FORMAT_VERSION);
+        Ok(KoshaMigration {
+            alias: alias.to_string(),
+            from,
+            migrations,
+            backup: Some(backup),
+        })
     }
 
-    /// List registered kosha aliases
-    pub fn list_koshas(&self) -> Vec<&str> {
-        self.koshas.keys().map(|s| s.as_str()).collect()
+    /// Remove the blobs a kosha no longer refers to (deleted files, pruned
+    /// history), or with `apply` false only count them
+    ///
+    /// Run it with the hub server stopped: only writes within this process
+    /// wait for it.
+    pub async fn gc_kosha(&self, alias: &str, apply: bool) -> Result<fastn_kosha::GcReport> {
+        let kosha = self
+            .get_kosha(alias)
+            .await?
+            .ok_or_else(|| Error::InstanceNotFound("kosha".to_string(), alias.to_string()))?;
+        let report = kosha.gc(apply).await?;
+        if apply && report.removed > 0 {
+            tracing::info!(
+                "Removed {} unreferenced blobs ({} bytes) from kosha {}",
+                report.removed,
+                report.freed_bytes,
+                alias
+            );
+        }
+        Ok(report)
+    }
+
+    /// Write a snapshot of a kosha to `to`, for backups or moving it to
+    /// another hub (see `fastn_kosha::export_snapshot`)
+    ///
+    /// Safe while the hub serves the kosha: the snapshot is taken from a
+    /// hard-linked copy.
+    pub async fn export_kosha(&self, alias: &str, to: &Path) -> Result<fastn_kosha::SnapshotManifest> {
+        let kosha = self
+            .get_kosha(alias)
+            .await?
+            .ok_or_else(|| Error::InstanceNotFound("kosha".to_string(), alias.to_string()))?;
+        let manifest = fastn_kosha::export_snapshot(kosha.path(), alias, to).await?;
+        tracing::info!("Exported kosha {} to {:?} ({} files)", alias, to, manifest.files);
+        Ok(manifest)
+    }
+
+    /// Create a kosha at FASTN_HOME/koshas/<alias>/ from a snapshot made by
+    /// `export_kosha`, on this hub or another
+    ///
+    /// The alias needn't be the one the kosha was exported under.
+    pub async fn import_kosha(&self, alias: &str, from: &Path) -> Result<Kosha> {
+        Self::validate_kosha_alias(alias)?;
+        let mut koshas = self.koshas.write().await;
+        let path = self.kosha_path(alias);
+        if koshas.contains_key(alias) || tokio::fs::try_exists(&path).await? {
+            return Err(Error::KoshaExists(alias.to_string()));
+        }
+        let manifest = fastn_kosha::import_snapshot(from, &path).await?;
+        let kosha = Kosha::open(path, alias.to_string())
+            .await?
+            .with_actor_id(self.id52())
+            .with_worker_pool(self.wasm_pool.clone())
+            .with_storage_quota(self.config.limits.kosha_quota(alias));
+        koshas.insert(alias.to_string(), kosha.clone());
+        tracing::info!("Imported kosha {} from {:?} (exported as {})", alias, from, manifest.alias);
+        Ok(kosha)
+    }
+
+    /// Export a kosha to FASTN_HOME/exports/ for `read_export` to hand out,
+    /// first removing exports older than `EXPORT_EXPIRY`
+    async fn create_export(&self, alias: &str) -> Result<admin_api::KoshaExport> {
+        Self::validate_kosha_alias(alias)?;
+        let dir = self.home.join("exports");
+        tokio::fs::create_dir_all(&dir).await?;
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let age = entry.metadata().await?.modified()?.elapsed().unwrap_or_default();
+            if age > admin_api::EXPORT_EXPIRY {
+                let _ = tokio::fs::remove_file(entry.path()).await;
+            }
+        }
+
+        let export_id = format!("{}-{}.tar.zst", alias, Utc::now().format("%Y%m%dT%H%M%S%.3fZ"));
+        let path = dir.join(&export_id);
+        let manifest = self.export_kosha(alias, &path).await?;
+
+        use sha2::{Digest, Sha256};
+        use tokio::io::AsyncReadExt;
+        let mut file = tokio::fs::File::open(&path).await?;
+        let (mut hasher, mut buf, mut size) = (Sha256::new(), vec![0; 64 * 1024], 0);
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            size += n as u64;
+        }
+        Ok(admin_api::KoshaExport {
+            schema_version: admin_api::SCHEMA_VERSION,
+            export_id,
+            alias: alias.to_string(),
+            size,
+            sha256: hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect(),
+            manifest,
+        })
+    }
+
+    /// Read part of an export made by `create_export`
+    async fn read_export(&self, request: &admin_api::ReadExportRequest) -> Result<admin_api::ExportChunk> {
+        use base64::Engine;
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        // Export ids are plain file names in FASTN_HOME/exports/
+        let id = &request.export_id;
+        let not_found = || Error::InstanceNotFound("export".to_string(), id.clone());
+        if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+            return Err(not_found());
+        }
+        let mut file = match tokio::fs::File::open(self.home.join("exports").join(id)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let size = file.metadata().await?.len();
+        let length = request
+            .length
+            .unwrap_or(fastn_net::MAX_CHUNK_SIZE as u64)
+            .min(fastn_net::MAX_CHUNK_SIZE as u64)
+            .min(size.saturating_sub(request.offset));
+        let mut content = vec![0; length as usize];
+        if length > 0 {
+            file.seek(std::io::SeekFrom::Start(request.offset)).await?;
+            file.read_exact(&mut content).await?;
+        }
+        Ok(admin_api::ExportChunk {
+            schema_version: admin_api::SCHEMA_VERSION,
+            export_id: id.clone(),
+            offset: request.offset,
+            content: base64::engine::general_purpose::STANDARD.encode(content),
+            size,
+        })
     }
 
     /// Grant access to (app, instance) for a spoke
@@ -957,15 +1617,119 @@ impl Hub {
     /// Request routing:
     /// - `target_hub == "self"`: Handle locally, ACL skipped for owner
     /// - `target_hub != "self"`: Forward to target hub via its URL
+    ///
+    /// Senders over their request rate (see `limits`) get
+    /// `HubError::QuotaExceeded` before anything else is looked at. Every
+    /// request is recorded in the audit log (see `audit`).
     pub async fn handle_request(
         &self,
         sender_id52: &str,
         request: Request,
+    ) -> std::result::Result<Response, HubError> {
+        self.metered_request(sender_id52, request, None, None).await
+    }
+
+    /// Handle a request that came over the network from `client`, which is
+    /// also rate limited and audited (see `listen`)
+    pub async fn handle_request_from(
+        &self,
+        sender_id52: &str,
+        request: Request,
+        client: IpAddr,
+    ) -> std::result::Result<Response, HubError> {
+        self.metered_request(sender_id52, request, None, Some(client)).await
+    }
+
+    /// Rate limit, route, count and audit a request from `sender_id52`, or
+    /// from `token` through the REST gateway (`sender_id52` is then
+    /// `token:<name>`), made by `client` if it came over the network
+    pub(crate) async fn metered_request(
+        &self,
+        sender_id52: &str,
+        request: Request,
+        token: Option<&CapabilityToken>,
+        client: Option<IpAddr>,
+    ) -> std::result::Result<Response, HubError> {
+        let started = std::time::Instant::now();
+        let span = tracing::info_span!(
+            "handle_request",
+            sender = sender_id52,
+            app = %request.app,
+            instance = %request.instance,
+            command = %request.command,
+        );
+        let entry = AuditEntry {
+            time: Utc::now(),
+            sender: sender_id52.to_string(),
+            client,
+            target_hub: request.target_hub.clone(),
+            app: request.app.clone(),
+            instance: request.instance.clone(),
+            command: request.command.clone(),
+            path: Self::extract_path_from_payload(&request.command, &request.payload),
+            result: AuditResult::Ok,
+            error: None,
+            duration_us: 0,
+        };
+        let result = async {
+            self.check_client_rate(client)?;
+            match self.rate_limiter.check(sender_id52, &self.config.limits) {
+                Ok(()) => match token {
+                    Some(token) => self.route_token_request(token, request).await,
+                    None => self.route_request(sender_id52, request).await,
+                },
+                Err(retry_after) => Err(HubError::QuotaExceeded {
+                    message: format!("Rate limit exceeded for {}", sender_id52),
+                    retry_after_ms: Some(retry_after.as_millis().max(1) as u64),
+                }),
+            }
+        }
+        .instrument(span.clone())
+        .await;
+        let outcome = match &result {
+            Ok(_) => metrics::RequestOutcome::Ok,
+            Err(HubError::Unauthorized) => metrics::RequestOutcome::Unauthorized,
+            Err(HubError::AccessDenied { .. }) => metrics::RequestOutcome::Denied,
+            Err(_) => metrics::RequestOutcome::Failed,
+        };
+        span.in_scope(|| tracing::debug!(outcome = ?outcome, duration_us = started.elapsed().as_micros() as u64, "Request handled"));
+        self.metrics.record(&entry.app, &entry.command, outcome, started.elapsed());
+        self.audit.record(&entry.finish(&result, started)).await;
+        result
+    }
+
+    /// Take a request from `client`'s budget (`Limits::client_requests_per_sec`);
+    /// requests made in-process have none
+    pub(crate) fn check_client_rate(&self, client: Option<IpAddr>) -> std::result::Result<(), HubError> {
+        let Some(client) = client else {
+            return Ok(());
+        };
+        self.client_rate_limiter
+            .check_client(client, &self.config.limits)
+            .map_err(|retry_after| HubError::QuotaExceeded {
+                message: format!("Rate limit exceeded for {}", client),
+                retry_after_ms: Some(retry_after.as_millis().max(1) as u64),
+            })
+    }
+
+    /// Entries of the audit log matching a query, oldest first
+    pub async fn query_audit_log(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        Ok(self.audit.query(query).await?)
+    }
+
+    async fn route_request(
+        &self,
+        sender_id52: &str,
+        request: Request,
     ) -> std::result::Result<Response, HubError> {
         // Identify the sender from their cryptographic identity
         // This replaces the old "trust the from_hub field" approach
-        let sender_identity = self.identify_sender(sender_id52).await
-            .map_err(|_| HubError::Unauthorized)?;
+        let sender_identity = self.identify_sender(sender_id52).await.map_err(|e| {
+            if !matches!(e, Error::Unauthorized(_)) {
+                tracing::warn!("Failed to identify {}: {}", sender_id52, e);
+            }
+            HubError::Unauthorized
+        })?;
 
         // Check if this is a cross-hub forwarding request
         if request.target_hub != "self" {
@@ -989,42 +1753,507 @@ impl Hub {
             return self.forward_request(&target_hub, request).await;
         }
 
+        // Reads under a mount point go to the mounted kosha, and are
+        // authorized there
+        let (request, mount_points) = self.resolve_mounts(request).await?;
+
         // Local request - check authorization based on sender identity
         match &sender_identity {
             SenderIdentity::OwnSpoke { .. } => {
                 // Owner's spoke has full access to their own hub - skip ACL
             }
             SenderIdentity::RemoteHub { hub_id52, .. } => {
-                // Cross-hub access: the sender is already verified as an authorized hub
-                // (identify_sender checked .hubs files), but we log for debugging
+                // identify_sender found the hub in a .hubs file; the ACL
+                // modules decide what it may do
                 tracing::debug!("Cross-hub access from hub {}", hub_id52);
-                // Note: For now we use simple .hubs file authorization.
-                // Future: Check WASM-based ACL modules for fine-grained access control.
+                if request.app == "kosha" {
+                    self.authorize_kosha_request(&sender_identity, &request).await?;
+                }
+            }
+            // Tokens only come through the gateway (route_token_request)
+            SenderIdentity::Token { .. } => return Err(HubError::Unauthorized),
+        }
+
+        // The admin API is built into the hub and only served to the owner
+        if request.app == admin_api::APP_NAME {
+            return self.handle_admin_command(&sender_identity, &request).await;
+        }
+
+        // Owner asked for an ACL explanation: evaluate as a non-owner would be
+        // evaluated, and report the trace if that would be denied
+        if request.explain && sender_identity.is_owner() {
+            let trace = self.explain_access(&self.explain_context(&request)).await;
+            if trace.decision == Some(AclDecision::Denied) {
+                return Err(HubError::AccessDenied {
+                    app: request.app.clone(),
+                    instance: request.instance.clone(),
+                    trace: Some(trace),
+                });
             }
         }
 
         // Route based on hardcoded app name
         match request.app.as_str() {
             "kosha" => {
-                // Find the kosha by instance name (alias)
-                let kosha = self.koshas.get(&request.instance).ok_or_else(|| {
-                    HubError::InstanceNotFound {
-                        app: request.app.clone(),
-                        instance: request.instance.clone(),
-                    }
-                })?;
+                let response = self.handle_kosha_request(&sender_identity, request).await?;
+                Ok(Self::add_mount_points(response, mount_points))
+            }
+            _ => Err(HubError::AppNotFound {
+                app: request.app.clone(),
+            }),
+        }
+    }
 
-                // Forward to kosha's handle_command
-                let payload = kosha
-                    .handle_command(&request.command, request.payload)
-                    .await
-                    .map_err(|e| HubError::AppError { message: e })?;
+    /// Check a non-owner's kosha request against the cascading ACL
+    /// (`check_access`); directory operations are checked file by file
+    /// when they run
+    async fn authorize_kosha_request(
+        &self,
+        sender_identity: &SenderIdentity,
+        request: &Request,
+    ) -> std::result::Result<(), HubError> {
+        if Self::tree_destination(&request.command, &request.payload).is_some() {
+            return Ok(());
+        }
+        // Only an explicit allow lets the request through; check_access
+        // already allows hubs in .hubs files when no level has a module
+        match self.check_access(&self.access_context(sender_identity, request)).await {
+            AccessResult::Allowed => Ok(()),
+            denied => {
+                tracing::debug!("Access denied to {:?}: {:?}", sender_identity, denied);
+                Err(HubError::AccessDenied {
+                    app: request.app.clone(),
+                    instance: request.instance.clone(),
+                    trace: None,
+                })
+            }
+        }
+    }
+
+    /// Run a kosha command for an identified sender, checking the database
+    /// and directory ACLs unless it's the owner
+    async fn handle_kosha_request(
+        &self,
+        sender_identity: &SenderIdentity,
+        request: Request,
+    ) -> std::result::Result<Response, HubError> {
+        // Find the kosha by instance name (alias)
+        let kosha = self
+            .get_kosha(&request.instance)
+            .await
+            .map_err(|e| HubError::AppError { message: e.to_string() })?
+            .ok_or_else(|| HubError::InstanceNotFound {
+                app: request.app.clone(),
+                instance: request.instance.clone(),
+            })?;
+
+        // Mirrors only change by syncing (see `mirror`)
+        if self.is_mirror(&request.instance) && !mirror::is_read_command(&request.command) {
+            return Err(HubError::AppError {
+                message: format!("Kosha {} is a read-only mirror", request.instance),
+            });
+        }
 
-                Ok(Response { payload })
+        // get/post run the kosha's WASM handlers with the caller's identity
+        if matches!(request.command.as_str(), "get" | "post") {
+            let ctx = self.request_context(sender_identity, &request)?;
+            let response = match request.command.as_str() {
+                "get" => kosha.get(&ctx).await,
+                _ => kosha.post(&ctx).await,
             }
-            _ => Err(HubError::AppNotFound {
+            .map_err(|e| HubError::AppError { message: e.to_string() })?;
+            let payload = serde_json::to_value(response)
+                .map_err(|e| HubError::AppError { message: e.to_string() })?;
+            return Ok(Response { payload });
+        }
+
+        // Databases are guarded by the nearest _db.wasm; the owner skips it
+        if !sender_identity.is_owner()
+            && let Some(ctx) = self.db_access_context(sender_identity, &request)
+            && let AccessResult::Denied(reason) = self.check_db_access(&kosha, &ctx).await
+        {
+            tracing::debug!("Database access denied: {}", reason);
+            return Err(HubError::AccessDenied {
+                app: request.app.clone(),
+                instance: request.instance.clone(),
+                trace: None,
+            });
+        }
+
+        // Directory operations are checked file by file; the owner skips it
+        if !sender_identity.is_owner()
+            && let Some(to) = Self::tree_destination(&request.command, &request.payload)
+            && let AccessResult::Denied(reason) = self
+                .check_tree_access(&kosha, &self.access_context(sender_identity, &request), to)
+                .await
+        {
+            tracing::debug!("Directory operation denied: {}", reason);
+            return Err(HubError::AccessDenied {
+                app: request.app.clone(),
+                instance: request.instance.clone(),
+                trace: None,
+            });
+        }
+
+        // Uploaded models get a metadata sidecar once the write succeeds
+        let written_model = match request.command.as_str() {
+            "write_file" | "commit_upload" => Self::extract_path_from_payload(&request.command, &request.payload)
+                .filter(|path| asset_metadata::is_model_path(path)),
+            _ => None,
+        };
+
+        // Subscribers hear about changes once the command succeeds
+        let mut events = Self::change_events(&request.instance, &request.command, &request.payload);
+
+        let search_ctx = (request.command == "search" && !sender_identity.is_owner())
+            .then(|| self.access_context(sender_identity, &request));
+
+        // Forward to kosha's handle_command
+        let written = Self::content_len(&request.command, &request.payload, &["write_file", "upload_chunk"]);
+        let payload = kosha
+            .handle_command(&request.command, request.payload)
+            .instrument(tracing::debug_span!("kosha", instance = %request.instance, command = %request.command))
+            .await
+            .map_err(Self::kosha_error)?;
+        let read = Self::content_len(&request.command, &payload, &["read_file", "read_version", "read_derived", "read_range"]);
+        self.metrics.record_kosha_bytes(read, written);
+
+        // Search hits in files the sender can't read are left out
+        let payload = match search_ctx {
+            Some(ctx) => self.readable_search_hits(&ctx, payload).await,
+            None => payload,
+        };
+
+        events.extend(Self::response_events(&request.instance, &request.command, &payload));
+        for event in events {
+            self.notify(event);
+        }
+
+        if let Some(path) = written_model
+            && let Err(e) = Self::write_model_metadata(&kosha, &path).await
+        {
+            tracing::warn!("Failed to extract metadata for {}: {}", path, e);
+        }
+
+        Ok(Response { payload })
+    }
+
+    /// Serve an admin API command (see `admin_api`)
+    async fn handle_admin_command(
+        &self,
+        sender_identity: &SenderIdentity,
+        request: &Request,
+    ) -> std::result::Result<Response, HubError> {
+        use admin_api::*;
+
+        if !sender_identity.is_owner() {
+            return Err(HubError::AccessDenied {
                 app: request.app.clone(),
+                instance: request.instance.clone(),
+                trace: None,
+            });
+        }
+
+        let page_request = || -> std::result::Result<PageRequest, HubError> {
+            if request.payload.is_null() {
+                return Ok(PageRequest::default());
+            }
+            serde_json::from_value(request.payload.clone()).map_err(|e| HubError::AppError {
+                message: format!("Invalid pagination: {}", e),
+            })
+        };
+
+        let kosha_request = || -> std::result::Result<KoshaRequest, HubError> {
+            serde_json::from_value(request.payload.clone()).map_err(|e| HubError::AppError {
+                message: format!("Invalid kosha request: {}", e),
+            })
+        };
+
+        let payload = match request.command.as_str() {
+            "describe" => serde_json::to_value(HubDescription {
+                schema_version: SCHEMA_VERSION,
+                hub_id52: self.id52().to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                started_at: self.metrics.started_at,
+                apps: vec!["kosha".to_string(), APP_NAME.to_string()],
+                commands: COMMANDS.iter().map(|c| c.to_string()).collect(),
+            }),
+            "metrics" => serde_json::to_value(HubMetricsSnapshot {
+                schema_version: SCHEMA_VERSION,
+                uptime_secs: (Utc::now() - self.metrics.started_at).num_seconds(),
+                requests_total: self.metrics.requests_total(),
+                requests_denied: self.metrics.requests_denied(),
+                requests_failed: self.metrics.requests_failed(),
+                acl_denials: self.metrics.acl_denials(),
+                kosha_read_bytes: self.metrics.kosha_read_bytes(),
+                kosha_written_bytes: self.metrics.kosha_written_bytes(),
+                push_subscriptions: self.metrics.push_subscriptions(),
+                spokes: self.spokes.spokes.len(),
+                pending_spokes: self.pending_spokes.len(),
+                koshas: self.list_koshas().await.map_err(Self::hub_error)?.len(),
             }),
+            "list_spokes" => {
+                let mut spokes: Vec<SpokeSummary> = self
+                    .list_spokes()
+                    .iter()
+                    .map(|s| SpokeSummary {
+                        id52: s.id52.clone(),
+                        alias: s.alias.clone(),
+                    })
+                    .collect();
+                spokes.sort_by(|a, b| (&a.alias, &a.id52).cmp(&(&b.alias, &b.id52)));
+                serde_json::to_value(Page::slice(spokes, &page_request()?))
+            }
+            "list_pending_spokes" => {
+                let mut pending: Vec<PendingSpokeSummary> = self
+                    .pending_spokes
+                    .values()
+                    .map(|p| PendingSpokeSummary {
+                        id52: p.id52.clone(),
+                        alias: p.alias.clone(),
+                        first_seen: p.first_seen,
+                        last_seen: p.last_seen,
+                    })
+                    .collect();
+                pending.sort_by(|a, b| (a.first_seen, &a.id52).cmp(&(b.first_seen, &b.id52)));
+                serde_json::to_value(Page::slice(pending, &page_request()?))
+            }
+            "list_koshas" => {
+                let aliases = self.list_koshas().await.map_err(Self::hub_error)?;
+                // Stats walk the disk, so only compute them for the requested page
+                let page = Page::slice(aliases, &page_request()?);
+                let mut items = Vec::with_capacity(page.items.len());
+                for alias in &page.items {
+                    let kosha = self.get_kosha(alias).await.map_err(Self::hub_error)?;
+                    items.push(Self::kosha_summary(alias, kosha.as_ref()).await);
+                }
+                serde_json::to_value(page.with_items(items))
+            }
+            "create_kosha" => {
+                let alias = kosha_request()?.alias;
+                self.create_kosha(&alias).await.map_err(Self::hub_error)?;
+                serde_json::to_value(KoshaChanged {
+                    schema_version: SCHEMA_VERSION,
+                    alias,
+                })
+            }
+            "fork_kosha" => {
+                let fork: ForkKoshaRequest =
+                    serde_json::from_value(request.payload.clone()).map_err(|e| HubError::AppError {
+                        message: format!("Invalid kosha request: {}", e),
+                    })?;
+                if let Err(e) = self.fork_kosha(&fork.source, &fork.alias).await {
+                    return Err(match e {
+                        Error::InstanceNotFound(app, instance) => HubError::InstanceNotFound { app, instance },
+                        e => Self::hub_error(e),
+                    });
+                }
+                serde_json::to_value(KoshaChanged {
+                    schema_version: SCHEMA_VERSION,
+                    alias: fork.alias,
+                })
+            }
+            "delete_kosha" => {
+                let alias = kosha_request()?.alias;
+                if !self.delete_kosha(&alias).await.map_err(Self::hub_error)? {
+                    return Err(HubError::InstanceNotFound {
+                        app: "kosha".to_string(),
+                        instance: alias,
+                    });
+                }
+                serde_json::to_value(KoshaChanged {
+                    schema_version: SCHEMA_VERSION,
+                    alias,
+                })
+            }
+            "export_kosha" => {
+                let alias = kosha_request()?.alias;
+                match self.create_export(&alias).await {
+                    Ok(export) => serde_json::to_value(export),
+                    Err(Error::InstanceNotFound(app, instance)) => {
+                        return Err(HubError::InstanceNotFound { app, instance });
+                    }
+                    Err(e) => return Err(Self::hub_error(e)),
+                }
+            }
+            "read_export" => {
+                let read: ReadExportRequest =
+                    serde_json::from_value(request.payload.clone()).map_err(|e| HubError::AppError {
+                        message: format!("Invalid export request: {}", e),
+                    })?;
+                match self.read_export(&read).await {
+                    Ok(chunk) => serde_json::to_value(chunk),
+                    Err(Error::InstanceNotFound(app, instance)) => {
+                        return Err(HubError::InstanceNotFound { app, instance });
+                    }
+                    Err(e) => return Err(Self::hub_error(e)),
+                }
+            }
+            "quota_status" => {
+                let limits = &self.config.limits;
+                let mut ids = self.rate_limiter.identities();
+                ids.extend(limits.identity_rates.keys().cloned());
+                ids.sort();
+                ids.dedup();
+                let identities = ids
+                    .into_iter()
+                    .map(|id52| IdentityQuota {
+                        requests_per_sec: limits.rate(&id52).filter(|rate| *rate > 0.0),
+                        available: self.rate_limiter.available(&id52, limits),
+                        id52,
+                    })
+                    .collect();
+                let mut koshas = Vec::new();
+                for alias in self.list_koshas().await.map_err(Self::hub_error)? {
+                    let kosha = self.get_kosha(&alias).await.map_err(Self::hub_error)?;
+                    koshas.push(Self::kosha_quota(&alias, kosha.as_ref()).await);
+                }
+                serde_json::to_value(QuotaStatus {
+                    schema_version: SCHEMA_VERSION,
+                    limits: limits.clone(),
+                    identities,
+                    koshas,
+                })
+            }
+            other => {
+                return Err(HubError::AppError {
+                    message: format!("Unknown hub command: {}", other),
+                });
+            }
+        }
+        .map_err(|e| HubError::AppError { message: e.to_string() })?;
+
+        Ok(Response { payload })
+    }
+
+    /// Summarize a kosha for the admin API
+    async fn kosha_summary(alias: &str, kosha: Option<&Kosha>) -> admin_api::KoshaSummary {
+        let stats = match kosha {
+            Some(kosha) => match kosha.stats().await {
+                Ok(stats) => Some(stats),
+                Err(e) => {
+                    tracing::warn!("Failed to read stats for kosha {}: {}", alias, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        admin_api::KoshaSummary {
+            alias: alias.to_string(),
+            stats,
+        }
+    }
+
+    /// Storage use of a kosha against its quota, for the admin API
+    async fn kosha_quota(alias: &str, kosha: Option<&Kosha>) -> admin_api::KoshaQuota {
+        let used_bytes = match kosha {
+            Some(kosha) => match kosha.storage_used().await {
+                Ok(used) => Some(used),
+                Err(e) => {
+                    tracing::warn!("Failed to read storage use of kosha {}: {}", alias, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        admin_api::KoshaQuota {
+            alias: alias.to_string(),
+            used_bytes,
+            quota_bytes: kosha.and_then(|kosha| kosha.storage_quota()),
+        }
+    }
+
+    /// Convert a hub error to a protocol error
+    fn hub_error(e: Error) -> HubError {
+        HubError::AppError { message: e.to_string() }
+    }
+
+    /// Convert a kosha command error to a hub error
+    fn kosha_error(e: fastn_kosha::CommandError) -> HubError {
+        match e {
+            fastn_kosha::CommandError::Conflict { ref current, .. } => HubError::Conflict {
+                message: e.to_string(),
+                current: current.as_ref().and_then(|v| serde_json::to_value(v).ok()),
+            },
+            fastn_kosha::CommandError::QuotaExceeded(message) => HubError::QuotaExceeded {
+                message,
+                retry_after_ms: None,
+            },
+            fastn_kosha::CommandError::Failed(message) => HubError::AppError { message },
+        }
+    }
+
+    /// Parse a model stored in a kosha and store its metadata sidecar
+    async fn write_model_metadata(kosha: &Kosha, path: &str) -> std::result::Result<(), String> {
+        let bytes = kosha.read_file(path).await.map_err(|e| e.to_string())?;
+        let metadata = asset_metadata::extract_model_metadata(&bytes)?;
+        let json = serde_json::to_vec_pretty(&metadata).map_err(|e| e.to_string())?;
+        kosha
+            .write_derived(path, asset_metadata::METADATA_NAME, &json)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Build the context for a kosha get/post request
+    ///
+    /// Payload: `{ path, query?, payload? }`
+    fn request_context(
+        &self,
+        sender_identity: &SenderIdentity,
+        request: &Request,
+    ) -> std::result::Result<RequestContext, HubError> {
+        let path = request
+            .payload
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| HubError::AppError {
+                message: "missing 'path' field".to_string(),
+            })?;
+        let (requester_hub_id, spoke_id52) = match sender_identity {
+            SenderIdentity::OwnSpoke { spoke_id52 } => (self.id52().to_string(), spoke_id52.clone()),
+            // The forwarding hub doesn't tell us which of its spokes asked
+            SenderIdentity::RemoteHub { hub_id52, .. } => (hub_id52.clone(), String::new()),
+            SenderIdentity::Token { name } => (self.id52().to_string(), gateway::token_sender(name)),
+        };
+        Ok(RequestContext {
+            requester_hub_id,
+            current_hub_id: self.id52().to_string(),
+            spoke_id52,
+            method: request.command.to_ascii_uppercase(),
+            path: path.to_string(),
+            query: request.payload.get("query").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            payload: request.payload.get("payload").cloned(),
+        })
+    }
+
+    /// Access context for a request, as its sender
+    fn access_context(&self, sender_identity: &SenderIdentity, request: &Request) -> AccessContext {
+        let (requester_hub_id, spoke_id52) = match sender_identity {
+            SenderIdentity::OwnSpoke { spoke_id52 } => (self.id52().to_string(), spoke_id52.clone()),
+            SenderIdentity::RemoteHub { hub_id52, .. } => (hub_id52.clone(), String::new()),
+            SenderIdentity::Token { name } => (self.id52().to_string(), gateway::token_sender(name)),
+        };
+        AccessContext {
+            requester_hub_id,
+            current_hub_id: self.id52().to_string(),
+            spoke_id52,
+            app: request.app.clone(),
+            instance: request.instance.clone(),
+            command: request.command.clone(),
+            path: Self::extract_path_from_payload(&request.command, &request.payload),
+        }
+    }
+
+    /// Access context used for `explain` requests: an anonymous non-owner
+    fn explain_context(&self, request: &Request) -> AccessContext {
+        AccessContext {
+            requester_hub_id: String::new(),
+            current_hub_id: self.id52().to_string(),
+            spoke_id52: String::new(),
+            app: request.app.clone(),
+            instance: request.instance.clone(),
+            command: request.command.clone(),
+            path: Self::extract_path_from_payload(&request.command, &request.payload),
         }
     }
 
@@ -1033,6 +2262,13 @@ impl Hub {
         &self.secret_key
     }
 
+    /// Derive a sub-identity of the hub for a specific purpose, e.g.
+    /// `app:inbox` for an app's service identity. Its signatures verify
+    /// against the hub's ID52 (see `fastn_net::DerivedSignature`).
+    pub fn derived_identity(&self, path: &str) -> Result<fastn_net::DerivedIdentity> {
+        Ok(self.secret_key.derive(path)?)
+    }
+
     /// Look up a known hub by alias from the .hubs files in root kosha
     ///
     /// Returns the hub's ID52 and URL if found.
@@ -1060,19 +2296,20 @@ impl Hub {
         target_hub: &ResolvedHubAuth,
         mut request: Request,
     ) -> std::result::Result<Response, HubError> {
-        let url = target_hub.url.as_ref().ok_or_else(|| {
-            HubError::AppError {
-                message: format!("Hub '{}' has no URL configured", target_hub.alias),
-            }
-        })?;
+        let address = target_hub.address();
+        if address.is_empty() {
+            return Err(HubError::AppError {
+                message: format!("Hub '{}' has no URL or iroh endpoint configured", target_hub.alias),
+            });
+        }
 
         // Create a client to forward the request
         // The client signs the request with our hub's key, so the remote hub
         // knows the request came from us (and can check if we're authorized)
-        let client = fastn_net::client::Client::new(
+        let client = fastn_net::client::Client::with_address(
             self.secret_key.clone(),
             target_hub.id52.clone(),
-            url.clone(),
+            address,
         );
 
         // Change target_hub to "self" for the forwarded request (we're now at the target)
@@ -1087,21 +2324,40 @@ impl Hub {
         result
     }
 
+    /// Run the hub server on `port`, otherwise as config.json says (see
+    /// [`Hub::serve_with`])
+    pub async fn serve(self, port: u16) -> Result<()> {
+        self.serve_with(ServeConfig {
+            port: Some(port),
+            ..ServeConfig::default()
+        })
+        .await
+    }
+
     /// Run the hub server
     ///
-    /// Starts an HTTP server and listens for signed JSON requests.
-    /// Default port is 3000 unless overridden.
-    pub async fn serve(self, port: u16) -> Result<()> {
+    /// Listens for signed JSON requests where `overrides` says, or where
+    /// the `serve` section of config.json does for what it leaves unset
+    /// (by default HTTP on `0.0.0.0:3000`; see `listen`).
+    ///
+    /// On SIGINT or SIGTERM the hub stops accepting connections, finishes
+    /// in-flight requests, then flushes pending kosha writes (see
+    /// [`Hub::shutdown`]) and returns.
+    pub async fn serve_with(self, overrides: ServeConfig) -> Result<()> {
         use axum::{
-            extract::Path,
+            extract::{ConnectInfo, Path},
             http::{header, StatusCode},
             response::{Html, IntoResponse, Response},
             routing::{get, post},
-            Json, Router,
+            Extension, Json, Router,
         };
+        use listen::{ClientIp, Peer};
         use std::sync::Arc;
         use tokio::sync::RwLock;
 
+        let serve = overrides.or(&self.config.serve);
+        let proxies = Arc::new(listen::TrustedProxies::parse(&serve.trusted_proxies).map_err(Error::Serve)?);
+        let listener = listen::HubListener::bind(&serve).await?;
         let hub = Arc::new(RwLock::new(self));
 
         // Get hub info for printing before we move hub into closures
@@ -1109,11 +2365,19 @@ impl Hub {
         let home = hub.read().await.home.clone();
         let secret_key = hub.read().await.secret_key.clone();
 
+        let base_url = serve.base_url();
+        let ws_url = base_url.replacen("http", "ws", 1);
         println!("Hub ID52: {}", hub_id52);
         println!("FASTN_HOME: {:?}", home);
-        println!("Listening on http://0.0.0.0:{}", port);
-        println!("  Web UI: http://0.0.0.0:{}/", port);
-        println!("  API: http://0.0.0.0:{}{}", port, ENDPOINT);
+        println!("Listening on {}", base_url);
+        println!("  Web UI: {}/", base_url);
+        println!("  API: {}{}", base_url, ENDPOINT);
+        println!("  Push: {}{}", ws_url, fastn_net::WS_ENDPOINT);
+        println!("  Health: {}{}", base_url, HEALTH_ENDPOINT);
+        println!("  Metrics: {}{}", base_url, METRICS_ENDPOINT);
+        if !serve.trusted_proxies.is_empty() {
+            println!("  Trusting X-Forwarded-For from: {}", serve.trusted_proxies.join(", "));
+        }
 
         // Static file handler
         async fn serve_static(Path(path): Path<String>) -> Response {
@@ -1141,8 +2405,19 @@ impl Hub {
 
         // Clone hub for each endpoint
         let hub_for_info = hub.clone();
+        let hub_for_health = hub.clone();
+        let hub_for_metrics = hub.clone();
         let hub_for_register = hub.clone();
         let hub_for_fastn = hub.clone();
+        let hub_for_push = hub.clone();
+        // Nonces seen recently, per sender
+        let replay_guard = Arc::new(ReplayGuard::new());
+        let push_replay_guard = replay_guard.clone();
+        // Signed requests must be addressed to this hub's endpoint
+        let audience = Arc::new(Audience::new(hub_id52.clone(), ENDPOINT));
+        let push_audience = Arc::new(Audience::new(hub_id52.clone(), fastn_net::WS_ENDPOINT));
+        let push_secret_key = secret_key.clone();
+        let key_rotations = Arc::new(hub.read().await.key_rotations().to_vec());
 
         let app = Router::new()
             .route("/", get(serve_index))
@@ -1154,11 +2429,39 @@ impl Hub {
                     Json(hub.hub_info())
                 }
             }))
+            // Health endpoint (public, 503 if the hub can't list its koshas)
+            .route(HEALTH_ENDPOINT, get(move || {
+                let hub = hub_for_health.clone();
+                async move {
+                    match hub.read().await.health().await {
+                        Ok(health) => (StatusCode::OK, Json(serde_json::json!(health))),
+                        Err(e) => (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            Json(serde_json::json!({"status": "error", "error": e.to_string()})),
+                        ),
+                    }
+                }
+            }))
+            // Prometheus metrics (public, counters only; see `metrics`)
+            .route(METRICS_ENDPOINT, get(move || {
+                let hub = hub_for_metrics.clone();
+                async move {
+                    let text = hub.read().await.metrics.render_prometheus();
+                    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text)
+                }
+            }))
             // Register spoke endpoint (checks password)
-            .route("/register-spoke", post(move |Json(req): Json<RegisterSpokeRequest>| {
+            .route("/register-spoke", post(move |Extension(ClientIp(client)): Extension<ClientIp>, Json(req): Json<RegisterSpokeRequest>| {
                 let hub = hub_for_register.clone();
                 async move {
                     let mut hub = hub.write().await;
+                    // Password guesses count against the client's rate
+                    if let Err(HubError::QuotaExceeded { message, .. }) = hub.check_client_rate(Some(client)) {
+                        return Json(RegisterSpokeResponse {
+                            success: false,
+                            error: Some(message),
+                        });
+                    }
                     match hub.register_spoke_with_password(&req.spoke_id52, &req.alias, &req.password).await {
                         Ok(()) => Json(RegisterSpokeResponse {
                             success: true,
@@ -1172,27 +2475,77 @@ impl Hub {
                 }
             }))
             .route("/{*path}", get(serve_static))
-            .route(ENDPOINT, post(move |Json(signed_req): Json<SignedRequest>| {
+            // Push channel (WebSocket, signed subscription handshake)
+            .route(fastn_net::WS_ENDPOINT, get(move |client: Option<Extension<ClientIp>>, upgrade: axum::extract::ws::WebSocketUpgrade| {
+                let hub = hub_for_push.clone();
+                let secret_key = push_secret_key.clone();
+                let replay_guard = push_replay_guard.clone();
+                let audience = push_audience.clone();
+                let client = client.map(|Extension(ClientIp(client))| client);
+                async move {
+                    fastn_net::ws::accept(upgrade, move |socket| {
+                        push::run_session(hub, socket, secret_key, replay_guard, audience, client)
+                    })
+                }
+            }))
+            .route(ENDPOINT, post(move |Extension(ClientIp(client)): Extension<ClientIp>, headers: axum::http::HeaderMap, body: axum::body::Bytes| {
                 let hub = hub_for_fastn.clone();
                 let secret_key = secret_key.clone();
+                let replay_guard = replay_guard.clone();
+                let audience = audience.clone();
+                let key_rotations = key_rotations.clone();
                 async move {
-                    // Verify and extract the request
-                    let (sender_id52, request): (String, Request) = match signed_req.verify() {
+                    // JSON or CBOR, possibly compressed
+                    let incoming = match fastn_net::server::read_request(&headers, body) {
+                        Ok(incoming) => incoming,
+                        Err(e) => {
+                            tracing::warn!("Unreadable request: {}", e);
+                            return fastn_net::server::reject(&e);
+                        }
+                    };
+                    let signed_req = &incoming.request;
+
+                    // Verify and extract the request, reject requests signed
+                    // for another hub, then replays of captured requests
+                    // (stale timestamp or reused nonce)
+                    let verified = signed_req
+                        .verify()
+                        .and_then(|r| signed_req.check_audience(&audience).map(|()| r))
+                        .and_then(|r| replay_guard.check(signed_req).map(|()| r));
+                    let (sender_id52, request): (String, Request) = match verified {
                         Ok(r) => r,
                         Err(e) => {
                             tracing::warn!("Request verification failed: {}", e);
-                            return (
-                                StatusCode::BAD_REQUEST,
-                                Json(serde_json::json!({"error": e.to_string()})),
-                            );
+                            // Spokes still addressing a retired key learn
+                            // the one that replaced it
+                            let rotation = match (&e, &signed_req.audience) {
+                                (fastn_net::Error::WrongAudience { .. }, Some(addressed)) => {
+                                    key_rotations.iter().find(|rotation| rotation.old_id52 == addressed.hub)
+                                }
+                                _ => None,
+                            };
+                            return match rotation {
+                                Some(rotation) => fastn_net::server::reject_rotated(&e, rotation.clone()),
+                                None => fastn_net::server::reject(&e),
+                            };
                         }
                     };
 
                     // Handle the request
                     // The sender identity is derived from the signature (sender_id52),
                     // not from any untrusted field in the request
-                    let hub = hub.read().await;
-                    let result = hub.handle_request(&sender_id52, request).await;
+                    // WASM calls made for the request stop when it is dropped
+                    // (e.g. the client disconnects)
+                    let cancel = CancellationToken::new();
+                    let _cancel_on_drop = cancel.clone().drop_guard();
+                    let result = match Hub::is_join(&request) {
+                        // Joining with an invite changes spokes.txt
+                        true => hub.write().await.handle_join(&sender_id52, request).await,
+                        false => {
+                            let hub = hub.read().await;
+                            fastn_kosha::with_cancellation(cancel, hub.handle_request_from(&sender_id52, request, client)).await
+                        }
+                    };
 
                     // Wrap in envelope and sign response
                     let envelope: ResponseEnvelope<HubResponse, HubError> = match result {
@@ -1200,33 +2553,84 @@ impl Hub {
                         Err(err) => ResponseEnvelope::Err(err),
                     };
 
-                    let signed_res = match SignedResponse::new(&secret_key, &envelope) {
-                        Ok(r) => r,
-                        Err(e) => {
-                            tracing::error!("Failed to sign response: {}", e);
-                            return (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                Json(serde_json::json!({"error": "Failed to sign response"})),
-                            );
-                        }
-                    };
-
-                    (StatusCode::OK, Json(serde_json::to_value(signed_res).unwrap()))
+                    incoming.respond(SignedResponse::new_with(&secret_key, &envelope, signed_req.canonicalization()))
                 }
             }));
 
-        // Bind and serve
-        let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
-        let listener = tokio::net::TcpListener::bind(addr).await
-            .map_err(|e| Error::Io(e))?;
+        // Tools without signing keys use capability tokens (see `gateway`)
+        let app = match hub.read().await.rest_gateway() {
+            true => {
+                println!("  REST gateway: {}{}/<kosha>/<path>", base_url, gateway::REST_PREFIX);
+                app.merge(gateway::routes(hub.clone()))
+            }
+            false => app,
+        };
 
-        axum::serve(listener, app).await
-            .map_err(|e| Error::Io(e))?;
+        // Every request learns its client, from trusted proxies' headers
+        // or the connection
+        let app = app.layer(axum::middleware::from_fn(
+            move |ConnectInfo(Peer(peer)): ConnectInfo<Peer>, mut request: axum::extract::Request, next: axum::middleware::Next| {
+                let client = proxies.client_ip(peer.ip(), request.headers());
+                request.extensions_mut().insert(ClientIp(client));
+                next.run(request)
+            },
+        ));
+
+        // Mirrors sync in the background until the hub stops
+        let mirror_aliases: Vec<String> = hub.read().await.mirrors.iter().cloned().collect();
+        let mirrors: Vec<_> = mirror_aliases
+            .into_iter()
+            .map(|alias| tokio::spawn(mirror::run(hub.clone(), alias)))
+            .collect();
 
+        let served = axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .map_err(Error::Io);
+        mirrors.iter().for_each(|mirror| mirror.abort());
+        served?;
+
+        println!("Flushing pending kosha writes...");
+        let rolled_back = hub.read().await.shutdown().await;
+        if rolled_back > 0 {
+            println!("Rolled back {} open transaction(s)", rolled_back);
+        }
+        println!("Hub stopped");
         Ok(())
     }
 }
 
+/// Resolves on SIGINT (Ctrl+C) or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+    println!("Shutting down, finishing in-flight requests...");
+}
+
 // ============================================================================
 // Hub Protocol - Generic Application Router
 // ============================================================================
@@ -1339,30 +2743,7 @@ impl AccessContext {
 }
 
 /// Request context passed to get/post WASM handlers
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RequestContext {
-    /// Hub ID of the requesting spoke
-    pub requester_hub_id: String,
-    /// This hub's ID
-    pub current_hub_id: String,
-    /// The spoke requesting access
-    pub spoke_id52: String,
-    /// HTTP method: "GET" or "POST"
-    pub method: String,
-    /// Request path
-    pub path: String,
-    /// Query string (if any)
-    pub query: Option<String>,
-    /// POST payload (JSON)
-    pub payload: Option<serde_json::Value>,
-}
-
-impl RequestContext {
-    /// Check if the requester is the hub owner (same user)
-    pub fn is_owner(&self) -> bool {
-        self.requester_hub_id == self.current_hub_id
-    }
-}
+pub use fastn_kosha::RequestContext;
 
 /// Database access context for _db.wasm ACL
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1373,7 +2754,7 @@ pub struct DbAccessContext {
     pub current_hub_id: String,
     /// The spoke requesting access
     pub spoke_id52: String,
-    /// Database name (e.g., "users.db")
+    /// Database path within the kosha (e.g., "api/users.sqlite3")
     pub database: String,
     /// Operation: "query", "execute", "begin", "commit", "rollback"
     pub operation: String,
@@ -1406,23 +2787,47 @@ impl Hub {
     /// For write operations on ACL files (access.wasm, read.wasm, write.wasm, admin.wasm),
     /// an additional admin check is performed via admin.wasm.
     pub async fn check_access(&self, ctx: &AccessContext) -> AccessResult {
-        // Get the root kosha for ACL modules
-        let root = match self.koshas.get("root") {
-            Some(k) => k,
-            None => {
-                // No root kosha means no ACL configured - deny by default
-                return AccessResult::Denied("No root kosha configured".to_string());
-            }
+        let mut trace = AclTrace::default();
+        self.evaluate_access(ctx, &mut trace).await
+    }
+
+    /// Dry-run an access check and return the evaluation trace
+    ///
+    /// Records every ACL module consulted (including missing ones) and the
+    /// final decision, without running the command itself.
+    pub async fn explain_access(&self, ctx: &AccessContext) -> AclTrace {
+        let mut trace = AclTrace::default();
+        let (decision, reason) = match self.evaluate_access(ctx, &mut trace).await {
+            AccessResult::Allowed => (AclDecision::Allowed, None),
+            AccessResult::Denied(reason) => (AclDecision::Denied, Some(reason)),
+            AccessResult::NoModule => (AclDecision::NoModule, None),
         };
+        trace.decision = Some(decision);
+        trace.reason = reason;
+        trace
+    }
+
+    /// Cascading ACL evaluation shared by `check_access` and `explain_access`
+    async fn evaluate_access(&self, ctx: &AccessContext, trace: &mut AclTrace) -> AccessResult {
+        // Get the root kosha for ACL modules
+        let root = &self.root_kosha;
+        // Opening the target kosha can fail; it is then skipped like an unknown one
+        let target_kosha = self.get_kosha(&ctx.instance).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to open kosha {}: {}", ctx.instance, e);
+            None
+        });
 
         // Check if this is a write to a special file - requires admin access
-        let is_special_write = matches!(ctx.command.as_str(), "write_file" | "delete" | "rename")
+        let is_special_write = matches!(
+            ctx.command.as_str(),
+            "write_file" | "delete" | "rename" | "begin_upload" | "upload_chunk" | "commit_upload"
+        )
             && ctx.path.as_ref().map(|p| Self::is_special_file(p)).unwrap_or(false);
 
         if is_special_write {
             if let Some(ref path) = ctx.path {
-                if let Some(target_kosha) = self.koshas.get(&ctx.instance) {
-                    match self.check_admin_access(target_kosha, path, ctx).await {
+                if let Some(target_kosha) = &target_kosha {
+                    match self.admin_access(target_kosha, path, ctx, trace).await {
                         AccessResult::Allowed => {}
                         AccessResult::Denied(reason) => return AccessResult::Denied(reason),
                         AccessResult::NoModule => {
@@ -1439,7 +2844,7 @@ impl Hub {
         let mut found_any_module = false;
 
         // Level 1: Global ACL (root/access.wasm)
-        match self.check_level(root, "", category, ctx).await {
+        match self.check_level(root, "", category, ctx, trace).await {
             LevelResult::Denied(reason) => return AccessResult::Denied(reason),
             LevelResult::Allowed => found_any_module = true,
             LevelResult::NoModule => {}
@@ -1447,7 +2852,7 @@ impl Hub {
 
         // Level 2: App-level ACL (root/kosha/[access|read|write].wasm)
         let app_prefix = format!("{}/", ctx.app);
-        match self.check_level(root, &app_prefix, category, ctx).await {
+        match self.check_level(root, &app_prefix, category, ctx, trace).await {
             LevelResult::Denied(reason) => return AccessResult::Denied(reason),
             LevelResult::Allowed => found_any_module = true,
             LevelResult::NoModule => {}
@@ -1455,7 +2860,7 @@ impl Hub {
 
         // Level 3: Instance-level ACL (root/kosha/<instance>/[access|read|write].wasm)
         let instance_prefix = format!("{}/{}/", ctx.app, ctx.instance);
-        match self.check_level(root, &instance_prefix, category, ctx).await {
+        match self.check_level(root, &instance_prefix, category, ctx, trace).await {
             LevelResult::Denied(reason) => return AccessResult::Denied(reason),
             LevelResult::Allowed => found_any_module = true,
             LevelResult::NoModule => {}
@@ -1464,13 +2869,13 @@ impl Hub {
         // Level 4: Target kosha folder-level ACL (for file operations with paths)
         if let Some(ref path) = ctx.path {
             // Get the target kosha
-            if let Some(target_kosha) = self.koshas.get(&ctx.instance) {
+            if let Some(target_kosha) = &target_kosha {
                 // Check each folder level from root to parent of target file
                 let path_segments: Vec<&str> = path.split('/').collect();
                 let mut current_prefix = String::new();
 
                 // Check kosha root level
-                match self.check_level(target_kosha, "", category, ctx).await {
+                match self.check_level(target_kosha, "", category, ctx, trace).await {
                     LevelResult::Denied(reason) => return AccessResult::Denied(reason),
                     LevelResult::Allowed => found_any_module = true,
                     LevelResult::NoModule => {}
@@ -1484,7 +2889,7 @@ impl Hub {
                         current_prefix = format!("{}{}/", current_prefix, segment);
                     }
 
-                    match self.check_level(target_kosha, &current_prefix, category, ctx).await {
+                    match self.check_level(target_kosha, &current_prefix, category, ctx, trace).await {
                         LevelResult::Denied(reason) => return AccessResult::Denied(reason),
                         LevelResult::Allowed => found_any_module = true,
                         LevelResult::NoModule => {}
@@ -1496,9 +2901,12 @@ impl Hub {
         // All levels passed, but we need at least one module to have been found
         if found_any_module {
             AccessResult::Allowed
-        } else if ctx.is_owner() || self.spokes.is_authorized(&ctx.spoke_id52) {
-            // Trusted spokes (owner or in spokes.txt) are allowed by default
-            // when no ACL modules are configured
+        } else if ctx.is_owner()
+            || self.spokes.is_authorized(&ctx.spoke_id52)
+            || self.is_hub_authorized(&ctx.requester_hub_id).await.unwrap_or(false)
+        {
+            // Trusted spokes (owner or in spokes.txt) and hubs in .hubs files
+            // are allowed by default when no ACL modules are configured
             AccessResult::Allowed
         } else {
             AccessResult::Denied("No ACL module found at any level".to_string())
@@ -1513,11 +2921,12 @@ impl Hub {
         prefix: &str,
         category: Option<&str>,
         ctx: &AccessContext,
+        trace: &mut AclTrace,
     ) -> LevelResult {
         // First check category-specific module (_read.wasm or _write.wasm)
         if let Some(cat) = category {
             let path = format!("{}_{}.wasm", prefix, cat);
-            match self.run_access_wasm(kosha, &path, ctx).await {
+            match self.run_access_wasm(kosha, &path, ctx, trace).await {
                 AccessResult::Allowed => return LevelResult::Allowed,
                 AccessResult::Denied(reason) => return LevelResult::Denied(reason),
                 AccessResult::NoModule => {} // Continue to check _access.wasm
@@ -1526,37 +2935,125 @@ impl Hub {
 
         // Then check general _access.wasm
         let path = format!("{}_access.wasm", prefix);
-        match self.run_access_wasm(kosha, &path, ctx).await {
+        match self.run_access_wasm(kosha, &path, ctx, trace).await {
             AccessResult::Allowed => LevelResult::Allowed,
             AccessResult::Denied(reason) => LevelResult::Denied(reason),
             AccessResult::NoModule => LevelResult::NoModule,
         }
     }
 
+    /// Check a directory operation (delete_dir, copy, move) file by file
+    ///
+    /// `ctx.path` is the directory or file operated on. Each file under it
+    /// is checked as the single-file command it amounts to: `delete` for
+    /// delete_dir, `read_file` for copy and `rename` for move. For copy and
+    /// move, each file's destination under `to` is also checked as a
+    /// `write_file`. One denial denies the whole operation.
+    pub async fn check_tree_access(&self, kosha: &Kosha, ctx: &AccessContext, to: Option<&str>) -> AccessResult {
+        let Some(from) = ctx.path.as_deref().and_then(|path| fastn_kosha::clean_path(path).ok()) else {
+            return AccessResult::Denied("Invalid or missing path".to_string());
+        };
+        let from = from.trim_end_matches('/');
+        let to = to.map(|to| to.trim_start_matches('/').trim_end_matches('/'));
+        let source_command = match ctx.command.as_str() {
+            "copy" => "read_file",
+            "move" => "rename",
+            _ => "delete",
+        };
+
+        // A missing source fails in the kosha, with the proper error
+        let files = kosha.list_files(from).await.unwrap_or_default();
+        for file in files {
+            let destination = to.map(|to| ("write_file", fastn_kosha::relocated_path(&file, from, to)));
+            for (command, path) in std::iter::once((source_command, file.clone())).chain(destination) {
+                let file_ctx = AccessContext {
+                    command: command.to_string(),
+                    path: Some(path.clone()),
+                    ..ctx.clone()
+                };
+                if let AccessResult::Denied(reason) = self.check_access(&file_ctx).await {
+                    return AccessResult::Denied(format!("{} {}: {}", command, path, reason));
+                }
+            }
+        }
+        AccessResult::Allowed
+    }
+
+    /// A search response without the hits `ctx` may not read
+    async fn readable_search_hits(&self, ctx: &AccessContext, mut payload: serde_json::Value) -> serde_json::Value {
+        let Some(hits) = payload.get_mut("hits").and_then(|hits| hits.as_array_mut()) else {
+            return payload;
+        };
+        let mut readable = Vec::with_capacity(hits.len());
+        for hit in hits.drain(..) {
+            let hit_ctx = AccessContext {
+                command: "read_file".to_string(),
+                path: hit.get("path").and_then(|v| v.as_str()).map(String::from),
+                ..ctx.clone()
+            };
+            if hit_ctx.path.is_some() && !matches!(self.check_access(&hit_ctx).await, AccessResult::Denied(_)) {
+                readable.push(hit);
+            }
+        }
+        *hits = readable;
+        payload
+    }
+
+    /// For directory operations, the destination of a copy or move (`None`
+    /// for delete_dir); `None` for every other command
+    fn tree_destination<'a>(command: &str, payload: &'a serde_json::Value) -> Option<Option<&'a str>> {
+        match command {
+            "delete_dir" => Some(None),
+            "copy" | "move" => Some(payload.get("to").and_then(|v| v.as_str())),
+            _ => None,
+        }
+    }
+
     /// Map a command to its category (read, write, etc.)
     fn command_category(command: &str) -> Option<&'static str> {
         match command {
             // Read operations
-            "read_file" | "list_dir" | "get_versions" | "read_version" | "kv_get" => Some("read"),
+            "read_file" | "list_dir" | "get_versions" | "read_version" | "read_derived" | "kv_get"
+            | "kv_state" | "get" | "db_query" | "db_tx_query" | "read_range" | "file_hash" | "search" => {
+                Some("read")
+            }
             // Write operations
-            "write_file" | "rename" | "delete" | "kv_set" | "kv_delete" => Some("write"),
+            "write_file" | "rename" | "delete" | "kv_set" | "kv_delete" | "kv_merge" | "post" | "db_execute"
+            | "db_begin" | "db_tx_execute" | "db_commit" | "db_rollback" | "begin_upload" | "upload_chunk"
+            | "commit_upload" | "abort_upload" | "delete_dir" | "copy" | "move" => Some("write"),
             // Unknown commands don't have a category
             _ => None,
         }
     }
 
+    /// Size of the base64 `content` of a kosha command's payload or
+    /// response, if the command is one of `commands`
+    fn content_len(command: &str, value: &serde_json::Value, commands: &[&str]) -> u64 {
+        match commands.contains(&command) {
+            true => value.get("content").and_then(|v| v.as_str()).map_or(0, metrics::base64_len),
+            false => 0,
+        }
+    }
+
     /// Extract the path from a request payload for file operations
     /// Returns None for non-path operations (like kv_get, kv_set, etc.)
     fn extract_path_from_payload(command: &str, payload: &serde_json::Value) -> Option<String> {
         match command {
             // File operations that use "path" field
-            "read_file" | "write_file" | "list_dir" | "get_versions" | "read_version" | "delete" => {
+            "read_file" | "write_file" | "list_dir" | "get_versions" | "read_version" | "read_derived"
+            | "delete" | "get" | "post" | "read_range" | "file_hash" | "begin_upload" | "upload_chunk"
+            | "commit_upload" | "abort_upload" | "delete_dir" => {
                 payload.get("path").and_then(|v| v.as_str()).map(|s| s.to_string())
             }
-            // Rename uses "from" as the source path for ACL check
-            "rename" => {
+            // Search is checked on its prefix, then hit by hit
+            "search" => Some(payload.get("path_prefix").and_then(|v| v.as_str()).unwrap_or_default().to_string()),
+            // Rename, copy and move use "from" as the source path for ACL check
+            "rename" | "copy" | "move" => {
                 payload.get("from").and_then(|v| v.as_str()).map(|s| s.to_string())
             }
+            // Database operations check the database file
+            "db_query" | "db_execute" | "db_begin" | "db_tx_query" | "db_tx_execute" | "db_commit"
+            | "db_rollback" => payload.get("database").and_then(|v| v.as_str()).map(|s| s.to_string()),
             // KV operations and others don't have paths
             _ => None,
         }
@@ -1567,47 +3064,84 @@ impl Hub {
         &self,
         kosha: &Kosha,
         path: &str,
-        ctx: &AccessContext,
+        ctx: &impl Serialize,
+        trace: &mut AclTrace,
     ) -> AccessResult {
         // Try to read the WASM file
-        let wasm_bytes = match kosha.read_file(path).await {
-            Ok(bytes) => bytes,
-            Err(_) => return AccessResult::NoModule,
+        let result = match kosha.read_file(path).await {
+            Err(_) => AccessResult::NoModule,
+            // Run the WASM module
+            Ok(wasm_bytes) => match self.execute_access_wasm(kosha, path, wasm_bytes, ctx).await {
+                Ok(true) => AccessResult::Allowed,
+                Ok(false) => AccessResult::Denied(format!("Denied by {}", path)),
+                // WASM execution error - treat as deny for safety
+                Err(e) => AccessResult::Denied(format!("ACL WASM error in {}: {}", path, e)),
+            },
         };
 
-        // Run the WASM module
-        match self.execute_access_wasm(&wasm_bytes, ctx).await {
-            Ok(allowed) => {
-                if allowed {
-                    AccessResult::Allowed
-                } else {
-                    AccessResult::Denied(format!("Denied by {}", path))
-                }
-            }
-            Err(e) => {
-                // WASM execution error - treat as deny for safety
-                AccessResult::Denied(format!("ACL WASM error in {}: {}", path, e))
-            }
+        let (decision, reason) = match &result {
+            AccessResult::Allowed => (AclDecision::Allowed, None),
+            AccessResult::Denied(reason) => (AclDecision::Denied, Some(reason.clone())),
+            AccessResult::NoModule => (AclDecision::NoModule, None),
+        };
+        trace.steps.push(AclTraceStep {
+            kosha: kosha.alias().to_string(),
+            file: path.to_string(),
+            decision,
+            reason,
+        });
+
+        result
+    }
+
+    /// Replace the resource limits for ACL WASM modules
+    pub fn set_acl_limits(&mut self, limits: AclLimits) {
+        self.acl_limits = limits;
+    }
+
+    /// Run ACL modules and kosha handlers on this pool instead of the
+    /// process-wide one
+    pub async fn set_wasm_pool(&mut self, pool: Arc<WasmPool>) {
+        self.root_kosha = self.root_kosha.clone().with_worker_pool(pool.clone());
+        for kosha in self.koshas.write().await.values_mut() {
+            *kosha = kosha.clone().with_worker_pool(pool.clone());
         }
+        self.wasm_pool = pool;
     }
 
     /// Execute an access control WASM module and return the result
+    ///
+    /// The context (`AccessContext` or `DbAccessContext`) is passed as JSON to
+    /// the module's `allowed` export
+    /// (see `acl_wasm` for the ABI). Compilation and execution are CPU-bound,
+    /// so they run on the WASM worker pool, one module file at a time counting
+    /// against its concurrency limit.
     async fn execute_access_wasm(
         &self,
-        _wasm_bytes: &[u8],
-        _ctx: &AccessContext,
+        kosha: &Kosha,
+        path: &str,
+        wasm_bytes: Vec<u8>,
+        ctx: &impl Serialize,
     ) -> std::result::Result<bool, String> {
-        // TODO: Implement WASM execution
-        // The WASM module should export: fn allowed(ctx_json: &str) -> bool
-        // We serialize AccessContext to JSON and pass it to the function
-        todo!("execute_access_wasm - need WASM runtime integration")
+        let ctx_json = serde_json::to_vec(ctx).map_err(|e| e.to_string())?;
+        let location = kosha.path().join(path).to_string_lossy().into_owned();
+        let (runtime, limits) = (self.wasm_pool.runtime(), self.acl_limits);
+        let module = location.clone();
+        self.wasm_pool
+            .run(&module, move |cancel| acl_wasm::check(runtime, limits, &location, &wasm_bytes, &ctx_json, cancel))
+            .await
+            .map_err(|e| format!("ACL task failed: {}", e))?
     }
 
     /// Check if a path refers to a special WASM file (prefixed with `_`)
+    /// or the mount table (`_mounts.txt`)
     /// Note: index.wasm is NOT a special file - it's the directory handler
     fn is_special_file(path: &str) -> bool {
         let filename = path.rsplit('/').next().unwrap_or(path);
-        matches!(filename, "_access.wasm" | "_read.wasm" | "_write.wasm" | "_admin.wasm")
+        matches!(
+            filename,
+            "_access.wasm" | "_read.wasm" | "_write.wasm" | "_admin.wasm" | "_db.wasm" | fastn_kosha::MOUNTS_FILE
+        )
     }
 
     /// Check admin access for modifying ACL files
@@ -1621,6 +3155,17 @@ impl Hub {
     /// 3. If not found, check `_admin.wasm` (root)
     /// 4. If no _admin.wasm found anywhere, deny (only hub owner can modify)
     pub async fn check_admin_access(&self, kosha: &Kosha, path: &str, ctx: &AccessContext) -> AccessResult {
+        let mut trace = AclTrace::default();
+        self.admin_access(kosha, path, ctx, &mut trace).await
+    }
+
+    async fn admin_access(
+        &self,
+        kosha: &Kosha,
+        path: &str,
+        ctx: &AccessContext,
+        trace: &mut AclTrace,
+    ) -> AccessResult {
         // Get the directory containing the ACL file
         let dir = if let Some(idx) = path.rfind('/') {
             &path[..idx]
@@ -1637,7 +3182,7 @@ impl Hub {
                 format!("{}/_admin.wasm", current_dir)
             };
 
-            match self.run_access_wasm(kosha, &admin_path, ctx).await {
+            match self.run_access_wasm(kosha, &admin_path, ctx, trace).await {
                 AccessResult::Allowed => return AccessResult::Allowed,
                 AccessResult::Denied(reason) => return AccessResult::Denied(reason),
                 AccessResult::NoModule => {
@@ -1658,6 +3203,54 @@ impl Hub {
         // Only hub owner (checked separately) can modify ACL files
         AccessResult::Denied("No _admin.wasm found - only hub owner can modify ACL files".to_string())
     }
+
+    /// Check database access via _db.wasm
+    ///
+    /// The nearest `_db.wasm` wins: for `api/users.sqlite3` that is
+    /// `api/_db.wasm`, then `_db.wasm` at the kosha root. Returns NoModule
+    /// when neither exists.
+    pub async fn check_db_access(&self, kosha: &Kosha, ctx: &DbAccessContext) -> AccessResult {
+        let mut trace = AclTrace::default();
+        let mut current_dir = match ctx.database.rfind('/') {
+            Some(idx) => ctx.database[..idx].to_string(),
+            None => String::new(),
+        };
+        loop {
+            let db_path = if current_dir.is_empty() {
+                "_db.wasm".to_string()
+            } else {
+                format!("{}/_db.wasm", current_dir)
+            };
+
+            match self.run_access_wasm(kosha, &db_path, ctx, &mut trace).await {
+                AccessResult::NoModule if !current_dir.is_empty() => {
+                    current_dir = match current_dir.rfind('/') {
+                        Some(idx) => current_dir[..idx].to_string(),
+                        None => String::new(),
+                    };
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Build the _db.wasm context for a db_* command
+    fn db_access_context(&self, sender_identity: &SenderIdentity, request: &Request) -> Option<DbAccessContext> {
+        let database = Self::extract_path_from_payload(&request.command, &request.payload)?;
+        let operation = request.command.strip_prefix("db_tx_").or(request.command.strip_prefix("db_"))?;
+        let (requester_hub_id, spoke_id52) = match sender_identity {
+            SenderIdentity::OwnSpoke { spoke_id52 } => (self.id52().to_string(), spoke_id52.clone()),
+            SenderIdentity::RemoteHub { hub_id52, .. } => (hub_id52.clone(), String::new()),
+            SenderIdentity::Token { name } => (self.id52().to_string(), gateway::token_sender(name)),
+        };
+        Some(DbAccessContext {
+            requester_hub_id,
+            current_hub_id: self.id52().to_string(),
+            spoke_id52,
+            database,
+            operation: operation.to_string(),
+        })
+    }
 }
 
 /// Result of checking a single ACL level
@@ -0,0 +1,168 @@
+//! Request rate limits and kosha storage quotas
+//!
+//! Both are configured in the `limits` section of `config.json` (or with
+//! `Hub::set_limits`):
+//!
+//! ```json
+//! "limits": {
+//!   "requests_per_sec": 20,
+//!   "burst": 50,
+//!   "identity_rates": { "<id52>": 100, "<other-id52>": null },
+//!   "client_requests_per_sec": 50,
+//!   "kosha_bytes": 1073741824,
+//!   "kosha_quotas": { "media": 10737418240, "root": null }
+//! }
+//! ```
+//!
+//! Rates apply per signing identity, so each spoke and each forwarding hub
+//! gets its own budget. They are token buckets: an identity may send `burst`
+//! requests at once, then `requests_per_sec` on average. Identities cost
+//! nothing to make, so `client_requests_per_sec` also limits each client
+//! address, whatever it signs with (behind a reverse proxy, the address the
+//! proxy reports; see `listen`). Storage quotas are
+//! enforced by the koshas themselves (`Kosha::with_storage_quota`). In both
+//! maps `null` means unlimited; identities and koshas not listed get the
+//! defaults. Everything is unlimited unless configured.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before refilled ones are dropped
+const MAX_BUCKETS: usize = 4096;
+
+/// Rate limits and storage quotas (the `limits` section of config.json)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Requests per second each identity may make, `None` (or 0) for no
+    /// limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_sec: Option<f64>,
+    /// Requests an idle identity may make at once; defaults to one second's
+    /// worth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+    /// Rates of specific identities by ID52, overriding `requests_per_sec`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub identity_rates: BTreeMap<String, Option<f64>>,
+    /// Requests per second each client address may make, `None` (or 0)
+    /// for no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_requests_per_sec: Option<f64>,
+    /// Bytes each kosha may hold, `None` for no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kosha_bytes: Option<u64>,
+    /// Quotas of specific koshas by alias, overriding `kosha_bytes`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub kosha_quotas: BTreeMap<String, Option<u64>>,
+}
+
+impl Limits {
+    /// Nothing is limited
+    pub fn is_unlimited(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// Requests per second allowed to an identity
+    pub fn rate(&self, id52: &str) -> Option<f64> {
+        match self.identity_rates.get(id52) {
+            Some(rate) => *rate,
+            None => self.requests_per_sec,
+        }
+    }
+
+    /// Requests an idle identity may make at once at the given rate
+    pub fn burst(&self, rate: f64) -> f64 {
+        match self.burst {
+            Some(burst) => f64::from(burst.max(1)),
+            None => rate.ceil().max(1.0),
+        }
+    }
+
+    /// Storage quota of a kosha
+    pub fn kosha_quota(&self, alias: &str) -> Option<u64> {
+        match self.kosha_quotas.get(alias) {
+            Some(quota) => *quota,
+            None => self.kosha_bytes,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets per identity (or per client address), used by
+/// `Hub::handle_request`
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Take a request from `id52`'s budget, or say how long until it can
+    /// make one
+    pub fn check(&self, id52: &str, limits: &Limits) -> Result<(), Duration> {
+        self.take(id52, limits, |id52| limits.rate(id52))
+    }
+
+    /// Take a request from a client address's budget
+    /// (`Limits::client_requests_per_sec`)
+    pub fn check_client(&self, client: IpAddr, limits: &Limits) -> Result<(), Duration> {
+        self.take(&client.to_string(), limits, |_| limits.client_requests_per_sec)
+    }
+
+    fn take(&self, key: &str, limits: &Limits, rate_of: impl Fn(&str) -> Option<f64>) -> Result<(), Duration> {
+        let Some(rate) = rate_of(key).filter(|rate| *rate > 0.0) else {
+            return Ok(());
+        };
+        let burst = limits.burst(rate);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(key) {
+            // Identities that have been quiet long enough to refill lose nothing
+            buckets.retain(|key, bucket| match rate_of(key).filter(|rate| *rate > 0.0) {
+                Some(rate) => Self::refilled(bucket, now, rate, limits) < limits.burst(rate),
+                None => false,
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = Self::refilled(bucket, now, rate, limits);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+
+    /// Requests `id52` could make right now, `None` if it isn't limited
+    pub fn available(&self, id52: &str, limits: &Limits) -> Option<f64> {
+        let rate = limits.rate(id52).filter(|rate| *rate > 0.0)?;
+        let buckets = self.buckets.lock().unwrap();
+        Some(match buckets.get(id52) {
+            Some(bucket) => Self::refilled(bucket, Instant::now(), rate, limits),
+            None => limits.burst(rate),
+        })
+    }
+
+    /// Identities that made requests recently, sorted
+    pub fn identities(&self) -> Vec<String> {
+        let mut identities: Vec<String> = self.buckets.lock().unwrap().keys().cloned().collect();
+        identities.sort();
+        identities
+    }
+
+    /// Tokens in a bucket after refilling it up to `now`
+    fn refilled(bucket: &Bucket, now: Instant, rate: f64, limits: &Limits) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * rate).min(limits.burst(rate))
+    }
+}
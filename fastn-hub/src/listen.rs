@@ -0,0 +1,322 @@
+//! Where and how the hub listens: address, port, TLS and reverse proxies
+//!
+//! Configured in the `serve` section of `config.json`, which the flags of
+//! `fastn-hub serve` override:
+//!
+//! ```json
+//! "serve": {
+//!   "bind": "127.0.0.1",
+//!   "port": 8443,
+//!   "tls": { "cert": "/etc/fastn/cert.pem", "key": "/etc/fastn/key.pem" },
+//!   "trusted_proxies": ["127.0.0.1", "10.0.0.0/8"]
+//! }
+//! ```
+//!
+//! Without `tls` the hub serves plain HTTP, which is enough for the signed
+//! API but not for the web UI, the registration password or gateway
+//! tokens. `cert` is a PEM chain (leaf first) and `key` its PEM private key
+//! (PKCS#8, PKCS#1 or SEC1); both are read at startup.
+//!
+//! The client of a request is the connection's peer, unless the peer is a
+//! trusted proxy: then `X-Forwarded-For` is read from the right, skipping
+//! trusted proxies, and the first address that isn't one is the client. The
+//! client's address goes to the audit log and to the per-client rate limit
+//! (`Limits::client_requests_per_sec`). Headers from untrusted peers are
+//! ignored, as anyone can send them.
+
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls;
+
+/// Port served unless configured
+pub const DEFAULT_PORT: u16 = 3000;
+
+/// Address listened on unless configured: every IPv4 interface
+pub const DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// Time a client gets to finish the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepted connections waiting for the server
+const ACCEPT_BACKLOG: usize = 64;
+
+/// Where and how to listen (the `serve` section of config.json)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServeConfig {
+    /// Address to listen on, default `DEFAULT_BIND`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind: Option<IpAddr>,
+    /// Default `DEFAULT_PORT`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Serve HTTPS instead of HTTP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    /// Proxies whose `X-Forwarded-For` is believed: addresses or CIDR
+    /// ranges
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_proxies: Vec<String>,
+}
+
+/// Certificate and key for HTTPS, as PEM files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl ServeConfig {
+    pub fn is_default(&self) -> bool {
+        *self == ServeConfig::default()
+    }
+
+    /// These settings, with those left unset taken from `fallback`
+    pub fn or(self, fallback: &ServeConfig) -> ServeConfig {
+        ServeConfig {
+            bind: self.bind.or(fallback.bind),
+            port: self.port.or(fallback.port),
+            tls: self.tls.or_else(|| fallback.tls.clone()),
+            trusted_proxies: match self.trusted_proxies.is_empty() {
+                true => fallback.trusted_proxies.clone(),
+                false => self.trusted_proxies,
+            },
+        }
+    }
+
+    /// Address to listen on
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind.unwrap_or(DEFAULT_BIND), self.port.unwrap_or(DEFAULT_PORT))
+    }
+
+    /// Base URL of the hub as served, e.g. `https://0.0.0.0:3000`
+    pub fn base_url(&self) -> String {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        format!("{}://{}", scheme, self.addr())
+    }
+
+    /// Check every setting that can be wrong: the proxies parse, and the
+    /// certificate and key load
+    pub fn validate(&self) -> Result<(), String> {
+        TrustedProxies::parse(&self.trusted_proxies)?;
+        if let Some(tls) = &self.tls {
+            tls.server_config()?;
+        }
+        Ok(())
+    }
+}
+
+impl TlsConfig {
+    /// rustls configuration serving the certificate
+    fn server_config(&self) -> Result<rustls::ServerConfig, String> {
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+        let certs = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("Cannot read certificate {}: {}", self.cert.display(), e))?;
+        if certs.is_empty() {
+            return Err(format!("No certificate in {}", self.cert.display()));
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key)
+            .map_err(|e| format!("Cannot read private key {}: {}", self.key.display(), e))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+        // axum serves HTTP/1.1 only
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+/// Proxies trusted to report who their clients are
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+impl TrustedProxies {
+    /// Parse addresses (`127.0.0.1`, `::1`) and CIDR ranges (`10.0.0.0/8`)
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let invalid = |entry: &str| format!("Invalid trusted proxy {:?} (use an address or a CIDR range)", entry);
+        let mut ranges = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let (addr, bits) = match entry.split_once('/') {
+                Some((addr, bits)) => (addr, Some(bits)),
+                None => (entry.as_str(), None),
+            };
+            let addr: IpAddr = addr.trim().parse().map_err(|_| invalid(entry))?;
+            let addr = addr.to_canonical();
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            let bits = match bits {
+                Some(bits) => bits.trim().parse::<u8>().ok().filter(|bits| *bits <= max).ok_or_else(|| invalid(entry))?,
+                None => max,
+            };
+            ranges.push((addr, bits));
+        }
+        Ok(Self(ranges))
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|(range, bits)| match (range, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => prefix_matches(&range.octets(), &ip.octets(), *bits),
+            (IpAddr::V6(range), IpAddr::V6(ip)) => prefix_matches(&range.octets(), &ip.octets(), *bits),
+            _ => false,
+        })
+    }
+
+    /// The client behind a connection from `peer`, per the
+    /// `X-Forwarded-For` headers of its request
+    pub fn client_ip(&self, peer: IpAddr, headers: &axum::http::HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.contains(client) {
+            return client;
+        }
+        let hops: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in hops.into_iter().rev() {
+            match parse_hop(hop) {
+                Some(ip) => client = ip,
+                // Whoever wrote this isn't someone we trust
+                None => break,
+            }
+            if !self.contains(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Whether the first `bits` bits of two addresses are the same
+fn prefix_matches(range: &[u8], ip: &[u8], bits: u8) -> bool {
+    let bits = usize::from(bits);
+    let (whole, rest) = (bits / 8, bits % 8);
+    if range[..whole] != ip[..whole] {
+        return false;
+    }
+    rest == 0 || {
+        let mask = 0xffu8 << (8 - rest);
+        range[whole] & mask == ip[whole] & mask
+    }
+}
+
+/// An address in `X-Forwarded-For`, which some proxies write with a port
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+/// The client of a request, as resolved by `TrustedProxies::client_ip`;
+/// `Hub::serve` puts it in every request's extensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Address of the connection a request came over (axum connect info)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Peer(pub SocketAddr);
+
+impl Connected<IncomingStream<'_, HubListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, HubListener>) -> Self {
+        Peer(*stream.remote_addr())
+    }
+}
+
+/// A connection, plain or TLS
+pub(crate) trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Accepts connections for `Hub::serve`, finishing TLS handshakes in the
+/// background so a slow client doesn't hold up the others
+pub(crate) struct HubListener {
+    local_addr: SocketAddr,
+    incoming: tokio::sync::mpsc::Receiver<(Box<dyn Connection>, SocketAddr)>,
+    acceptor: tokio::task::JoinHandle<()>,
+}
+
+impl HubListener {
+    /// Listen as `config` says
+    pub(crate) async fn bind(config: &ServeConfig) -> crate::Result<Self> {
+        let tls = match &config.tls {
+            Some(tls) => Some(tokio_rustls::TlsAcceptor::from(Arc::new(
+                tls.server_config().map_err(crate::Error::Serve)?,
+            ))),
+            None => None,
+        };
+        let listener = tokio::net::TcpListener::bind(config.addr()).await?;
+        let local_addr = listener.local_addr()?;
+        let (sender, incoming) = tokio::sync::mpsc::channel(ACCEPT_BACKLOG);
+        let acceptor = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Out of file descriptors and the like: wait a bit
+                        tracing::warn!("Failed to accept a connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let Some(tls) = tls.clone() else {
+                    if sender.send((Box::new(stream) as Box<dyn Connection>, peer)).await.is_err() {
+                        return;
+                    }
+                    continue;
+                };
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((Box::new(stream) as Box<dyn Connection>, peer)).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", peer, e),
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            local_addr,
+            incoming,
+            acceptor,
+        })
+    }
+}
+
+impl Listener for HubListener {
+    type Io = Box<dyn Connection>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(accepted) => accepted,
+            // The acceptor only stops when this listener is dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Drop for HubListener {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+    }
+}
@@ -3,9 +3,22 @@
 //! Usage:
 //!   fastn-hub init     - Initialize a new hub (creates FASTN_HOME with secret key)
 //!   fastn-hub          - Run the hub server (requires init first)
+//!   fastn-hub serve [port] [--bind|--tls-cert|--tls-key|--trust-proxy] - Run it with these settings
 //!   fastn-hub id       - Show the hub's ID52
+//!   fastn-hub create-kosha <alias> - Create a kosha
+//!   fastn-hub export-kosha <alias> <file.tar.zst> - Write a kosha snapshot
+//!   fastn-hub import-kosha <alias> <file.tar.zst> - Create a kosha from a snapshot
+//!   fastn-hub migrate [--dry-run|--apply] [alias] - Update kosha storage formats
+//!   fastn-hub gc [--dry-run] [alias] - Remove blobs koshas no longer refer to
+//!   fastn-hub audit [filters] - Show who made which requests
+//!   fastn-hub mirror <add|status|sync|remove> - Mirror koshas of other hubs
+//!   fastn-hub token <create|list|revoke> - Manage REST gateway tokens
+//!   fastn-hub invite [--ttl <minutes>|list|revoke] - Invite spokes with one-time tokens
+//!   fastn-hub gateway <on|off> - Serve the REST gateway or not
+//!   fastn-hub key [protect|unprotect] - Show or change how hub.key is stored
+//!   fastn-hub rotate-key - Replace the hub's key, spokes follow
 
-use fastn_hub::Hub;
+use fastn_hub::{AuditQuery, Hub, ServeConfig, TlsConfig};
 use std::env;
 use std::path::PathBuf;
 
@@ -168,19 +181,504 @@ async fn main() {
                 }
             }
         }
+        Some("create-kosha") => {
+            let alias = match args.get(2) {
+                Some(alias) => alias,
+                None => {
+                    eprintln!("Usage: fastn-hub create-kosha <alias>");
+                    eprintln!();
+                    eprintln!("The alias names the kosha in requests and on disk:");
+                    eprintln!("1-64 lowercase letters, digits, '-' and '_'.");
+                    std::process::exit(1);
+                }
+            };
+
+            match Hub::load(&home).await {
+                Ok(hub) => {
+                    match hub.create_kosha(alias).await {
+                        Ok(kosha) => {
+                            println!("Kosha created successfully!");
+                            println!("Alias: {}", alias);
+                            println!("Path:  {:?}", kosha.path());
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to create kosha: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("fork-kosha") => {
+            let (source, alias) = match (args.get(2), args.get(3)) {
+                (Some(source), Some(alias)) => (source, alias),
+                _ => {
+                    eprintln!("Usage: fastn-hub fork-kosha <source> <alias>");
+                    eprintln!();
+                    eprintln!("Creates a kosha that starts as a copy of <source>. Files are");
+                    eprintln!("shared until either kosha changes them, so forking is fast and");
+                    eprintln!("takes no extra space even for large koshas.");
+                    std::process::exit(1);
+                }
+            };
+
+            match Hub::load(&home).await {
+                Ok(hub) => {
+                    match hub.fork_kosha(source, alias).await {
+                        Ok(kosha) => {
+                            println!("Kosha forked successfully!");
+                            println!("Source: {}", source);
+                            println!("Alias:  {}", alias);
+                            println!("Path:   {:?}", kosha.path());
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to fork kosha: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("delete-kosha") => {
+            let alias = match args.get(2) {
+                Some(alias) => alias,
+                None => {
+                    eprintln!("Usage: fastn-hub delete-kosha <alias>");
+                    eprintln!();
+                    eprintln!("Deletes the kosha's files, history and data.");
+                    std::process::exit(1);
+                }
+            };
+
+            match Hub::load(&home).await {
+                Ok(hub) => {
+                    match hub.delete_kosha(alias).await {
+                        Ok(true) => {
+                            println!("Kosha deleted: {}", alias);
+                        }
+                        Ok(false) => {
+                            eprintln!("Kosha not found: {}", alias);
+                            std::process::exit(1);
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to delete kosha: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("export-kosha") => {
+            let (alias, file) = match (args.get(2), args.get(3)) {
+                (Some(alias), Some(file)) => (alias, PathBuf::from(file)),
+                _ => {
+                    eprintln!("Usage: fastn-hub export-kosha <alias> <file.tar.zst>");
+                    eprintln!();
+                    eprintln!("Writes the kosha's files, history, key-value store and databases");
+                    eprintln!("to one compressed file, replacing <file.tar.zst>. The hub may keep");
+                    eprintln!("serving the kosha meanwhile.");
+                    std::process::exit(1);
+                }
+            };
+
+            match Hub::load(&home).await {
+                Ok(hub) => {
+                    match hub.export_kosha(alias, &file).await {
+                        Ok(manifest) => {
+                            println!("Kosha exported successfully!");
+                            println!("Alias:  {}", alias);
+                            println!("File:   {:?}", file);
+                            println!("Files:  {} ({} bytes)", manifest.files, manifest.bytes);
+                            println!("Format: {}", manifest.format_version);
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to export kosha: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("import-kosha") => {
+            let (alias, file) = match (args.get(2), args.get(3)) {
+                (Some(alias), Some(file)) => (alias, PathBuf::from(file)),
+                _ => {
+                    eprintln!("Usage: fastn-hub import-kosha <alias> <file.tar.zst>");
+                    eprintln!();
+                    eprintln!("Creates kosha <alias> from a file written by 'fastn-hub export-kosha'");
+                    eprintln!("on this hub or another. The alias must not be taken.");
+                    std::process::exit(1);
+                }
+            };
+
+            match Hub::load(&home).await {
+                Ok(hub) => {
+                    match hub.import_kosha(alias, &file).await {
+                        Ok(kosha) => {
+                            println!("Kosha imported successfully!");
+                            println!("Alias: {}", alias);
+                            println!("Path:  {:?}", kosha.path());
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to import kosha: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("list-koshas") => {
+            match Hub::load(&home).await {
+                Ok(hub) => {
+                    match hub.list_koshas().await {
+                        Ok(aliases) => {
+                            println!("Koshas:");
+                            for alias in aliases {
+                                println!("  {}", alias);
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to list koshas: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("migrate") => {
+            let mut apply = false;
+            let mut aliases = vec![];
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--dry-run" => apply = false,
+                    "--apply" => apply = true,
+                    alias if !alias.starts_with('-') => aliases.push(alias.to_string()),
+                    _ => {
+                        eprintln!("Usage: fastn-hub migrate [--dry-run|--apply] [alias...]");
+                        eprintln!();
+                        eprintln!("Brings koshas (all of them by default) to the storage format of");
+                        eprintln!("this build. --dry-run (the default) lists the changes; --apply");
+                        eprintln!("backs each kosha up to FASTN_HOME/backups/ and makes them.");
+                        eprintln!("Stop the hub server before applying.");
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            let hub = match Hub::load(&home).await {
+                Ok(hub) => hub,
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if aliases.is_empty() {
+                aliases = match hub.list_koshas().await {
+                    Ok(aliases) => aliases,
+                    Err(e) => {
+                        eprintln!("Failed to list koshas: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+            }
+
+            let (mut pending, mut failed) = (false, false);
+            for alias in aliases {
+                match hub.migrate_kosha(&alias, apply).await {
+                    Ok(migration) if migration.migrations.is_empty() => {
+                        println!("{}: up to date (format {})", alias, migration.from);
+                    }
+                    Ok(migration) => {
+                        pending = true;
+                        let to = migration.migrations.last().map(|m| m.version).unwrap_or(migration.from);
+                        let verb = if apply { "migrated" } else { "would migrate" };
+                        println!("{}: {} from format {} to {}", alias, verb, migration.from, to);
+                        for step in &migration.migrations {
+                            println!("  {} {}: {}", step.version, step.name, step.description);
+                            for change in &step.changes {
+                                println!("    {}", change);
+                            }
+                        }
+                        if let Some(backup) = migration.backup {
+                            println!("  Backup: {:?}", backup);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("{}: {}", alias, e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+            if pending && !apply {
+                println!();
+                println!("Dry run, nothing changed. Run 'fastn-hub migrate --apply' to migrate.");
+            }
+        }
+        Some("gc") => {
+            let mut apply = true;
+            let mut aliases = vec![];
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--dry-run" => apply = false,
+                    alias if !alias.starts_with('-') => aliases.push(alias.to_string()),
+                    _ => {
+                        eprintln!("Usage: fastn-hub gc [--dry-run] [alias...]");
+                        eprintln!();
+                        eprintln!("Removes the blobs that koshas (all of them by default) no longer");
+                        eprintln!("refer to: content of deleted files and pruned history. --dry-run");
+                        eprintln!("only counts them. Stop the hub server first.");
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            let hub = match Hub::load(&home).await {
+                Ok(hub) => hub,
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if aliases.is_empty() {
+                aliases = match hub.list_koshas().await {
+                    Ok(aliases) => aliases,
+                    Err(e) => {
+                        eprintln!("Failed to list koshas: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+            }
+
+            let mut failed = false;
+            for alias in aliases {
+                match hub.gc_kosha(&alias, apply).await {
+                    Ok(report) => {
+                        let verb = if apply { "removed" } else { "would remove" };
+                        println!(
+                            "{}: {} {} blobs ({} bytes), {} in use",
+                            alias, verb, report.removed, report.freed_bytes, report.kept
+                        );
+                    }
+                    Err(e) => {
+                        eprintln!("{}: {}", alias, e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Some("audit") => {
+            let (query, json) = match parse_audit_args(&args[2..]) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    eprintln!();
+                    print_audit_usage();
+                    std::process::exit(1);
+                }
+            };
+
+            let hub = match Hub::load(&home).await {
+                Ok(hub) => hub,
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            // Spokes can be given by alias
+            let mut query = query;
+            if let Some(sender) = &query.sender
+                && let Some(spoke) = hub.list_spokes().iter().find(|s| s.alias == *sender)
+            {
+                query.sender = Some(spoke.id52.clone());
+            }
+
+            let entries = match hub.query_audit_log(&query).await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("Failed to read audit log: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            for entry in entries {
+                if json {
+                    println!("{}", serde_json::to_string(&entry).unwrap_or_default());
+                    continue;
+                }
+                let sender = match hub.find_spoke(&entry.sender) {
+                    Some(spoke) => spoke.alias.clone(),
+                    None => entry.sender.clone(),
+                };
+                let target = match entry.target_hub.as_str() {
+                    "self" => String::new(),
+                    hub => format!("@{} ", hub),
+                };
+                let client = entry.client.map(|ip| format!(" ({})", ip)).unwrap_or_default();
+                let path = entry.path.map(|p| format!(" {}", p)).unwrap_or_default();
+                let error = entry.error.map(|e| format!(" ({})", e)).unwrap_or_default();
+                println!(
+                    "{}  {}{}  {}{}/{} {}{}  {}{}  {:.1}ms",
+                    entry.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+                    sender,
+                    client,
+                    target,
+                    entry.app,
+                    entry.instance,
+                    entry.command,
+                    path,
+                    entry.result.as_str(),
+                    error,
+                    entry.duration_us as f64 / 1000.0
+                );
+            }
+        }
+        Some("mirror") => {
+            let hub = match Hub::load(&home).await {
+                Ok(hub) => hub,
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = mirror_command(hub, &args[2..]).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("token") => {
+            let hub = match Hub::load(&home).await {
+                Ok(hub) => hub,
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = token_command(&hub, &args[2..]).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("invite") => {
+            let hub = match Hub::load(&home).await {
+                Ok(hub) => hub,
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = invite_command(&hub, &args[2..]).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("key") => {
+            if let Err(e) = key_command(&home, &args[2..]).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("rotate-key") => {
+            let mut hub = match Hub::load(&home).await {
+                Ok(hub) => hub,
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            match hub.rotate_key().await {
+                Ok(rotation) => {
+                    println!("Hub key rotated.");
+                    println!("Old ID52: {}", rotation.old_id52);
+                    println!("New ID52: {}", rotation.new_id52);
+                    println!();
+                    println!("Restart the hub. Spokes move to the new ID52 on their next request;");
+                    println!("hubs that list this one in their .hubs files need the new ID52.");
+                    println!("The old key is in {}/.", fastn_hub::keys::RETIRED_KEYS_DIR);
+                }
+                Err(e) => {
+                    eprintln!("Failed to rotate key: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("gateway") => {
+            let enabled = match args.get(2).map(|s| s.as_str()) {
+                Some("on") => true,
+                Some("off") => false,
+                _ => {
+                    eprintln!("Usage: fastn-hub gateway <on|off>");
+                    eprintln!();
+                    eprintln!("Serves koshas over plain HTTP at {}/<alias>/<path>", fastn_hub::gateway::REST_PREFIX);
+                    eprintln!("to holders of tokens (see 'fastn-hub token --help'), from the next start.");
+                    std::process::exit(1);
+                }
+            };
+
+            match Hub::load(&home).await {
+                Ok(mut hub) => {
+                    if let Err(e) = hub.set_rest_gateway(enabled).await {
+                        eprintln!("Failed to save config: {}", e);
+                        std::process::exit(1);
+                    }
+                    match enabled {
+                        true => println!("REST gateway on; restart the hub to serve it."),
+                        false => println!("REST gateway off from the next start."),
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to load hub: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
         Some("help") | Some("-h") | Some("--help") => {
             print_help();
         }
         Some("serve") => {
-            // Run the hub server with optional port
-            let port: u16 = args.get(2)
-                .and_then(|p| p.parse().ok())
-                .unwrap_or(3000);
+            // Flags override the serve section of config.json
+            let overrides = match parse_serve_args(&args[2..]) {
+                Ok(overrides) => overrides,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    eprintln!();
+                    print_serve_usage();
+                    std::process::exit(1);
+                }
+            };
 
             match Hub::load(&home).await {
                 Ok(hub) => {
                     println!("Starting hub server...");
-                    if let Err(e) = hub.serve(port).await {
+                    if let Err(e) = hub.serve_with(overrides).await {
                         eprintln!("Hub server error: {}", e);
                         std::process::exit(1);
                     }
@@ -193,11 +691,11 @@ async fn main() {
             }
         }
         None => {
-            // Run the hub server (default port 3000)
+            // Run the hub server as config.json says (default port 3000)
             match Hub::load(&home).await {
                 Ok(hub) => {
                     println!("Starting hub server...");
-                    if let Err(e) = hub.serve(3000).await {
+                    if let Err(e) = hub.serve_with(ServeConfig::default()).await {
                         eprintln!("Hub server error: {}", e);
                         std::process::exit(1);
                     }
@@ -223,19 +721,40 @@ fn print_help() {
     println!("Usage:");
     println!("  fastn-hub init                   Initialize a new hub");
     println!("  fastn-hub                        Run the hub server (port 3000)");
-    println!("  fastn-hub serve [port]           Run the hub server on specified port");
+    println!("  fastn-hub serve [port] [options] Run the hub server (see 'fastn-hub serve --help')");
     println!("  fastn-hub id                     Show the hub's ID52");
     println!("  fastn-hub info                   Show hub configuration");
     println!("  fastn-hub add-spoke <id52>       Authorize a spoke to connect");
     println!("  fastn-hub remove-spoke <id52>    Remove spoke authorization");
     println!("  fastn-hub list-spokes            List authorized spokes");
     println!("  fastn-hub list-pending           List pending (unauthorized) spokes");
+    println!("  fastn-hub create-kosha <alias>   Create a kosha");
+    println!("  fastn-hub fork-kosha <source> <alias>");
+    println!("                                   Create a kosha as a copy-on-write fork of another");
+    println!("  fastn-hub delete-kosha <alias>   Delete a kosha and all its data");
+    println!("  fastn-hub export-kosha <alias> <file.tar.zst>");
+    println!("                                   Write a kosha to a snapshot file, for backups");
+    println!("  fastn-hub import-kosha <alias> <file.tar.zst>");
+    println!("                                   Create a kosha from a snapshot file");
+    println!("  fastn-hub list-koshas            List koshas");
+    println!("  fastn-hub migrate [--dry-run|--apply] [alias...]");
+    println!("                                   Bring koshas to this build's storage format");
+    println!("  fastn-hub gc [--dry-run] [alias...]");
+    println!("                                   Remove blobs koshas no longer refer to");
+    println!("  fastn-hub audit [filters]        Show logged requests (see 'fastn-hub audit --help')");
+    println!("  fastn-hub mirror <command>       Mirror koshas of other hubs (see 'fastn-hub mirror --help')");
+    println!("  fastn-hub token <command>        Manage REST gateway tokens (see 'fastn-hub token --help')");
+    println!("  fastn-hub invite [--ttl <min>]   Invite a spoke with a one-time token (see 'fastn-hub invite --help')");
+    println!("  fastn-hub gateway <on|off>       Serve koshas over plain HTTP to token holders");
+    println!("  fastn-hub key [command]          Protect hub.key with a passphrase or the keychain (see 'fastn-hub key --help')");
+    println!("  fastn-hub rotate-key             Replace the hub's key and ID52; spokes follow");
     println!("  fastn-hub help                   Show this help message");
     println!();
     println!("Environment:");
     println!("  FASTN_HOME  Override the default home directory");
     println!("              Default: ~/.local/share/fastn (Linux)");
     println!("                       ~/Library/Application Support/com.fastn.fastn (macOS)");
+    println!("  FASTN_KEY_PASSPHRASE  Passphrase of an encrypted hub.key");
     println!();
     println!("Workflow:");
     println!("  1. Initialize the hub: fastn-hub init");
@@ -250,4 +769,431 @@ fn print_help() {
     println!();
     println!("  The alias defaults to the first 8 characters of the ID52.");
     println!("  To change aliases, edit spokes.txt directly.");
+    println!();
+    println!("Koshas:");
+    println!("  Every directory in FASTN_HOME/koshas/ is a kosha, named by its alias.");
+    println!("  Koshas are opened on their first request.");
+}
+
+fn print_serve_usage() {
+    println!("Usage: fastn-hub serve [port] [options]");
+    println!();
+    println!("Runs the hub server. Options override the 'serve' section of config.json.");
+    println!();
+    println!("Options:");
+    println!("  --bind <address>        Address to listen on (default {})", fastn_hub::listen::DEFAULT_BIND);
+    println!("  --port <port>           Port to listen on (default {})", fastn_hub::listen::DEFAULT_PORT);
+    println!("  --tls-cert <file.pem>   Serve HTTPS with this certificate chain...");
+    println!("  --tls-key <file.pem>    ...and its private key");
+    println!("  --trust-proxy <range>   Believe X-Forwarded-For from this address or CIDR");
+    println!("                          range (repeatable), for audit logs and rate limits");
+}
+
+/// Parse the arguments of `fastn-hub serve` into settings overriding
+/// config.json's
+fn parse_serve_args(args: &[String]) -> Result<ServeConfig, String> {
+    let mut serve = ServeConfig::default();
+    let (mut cert, mut key) = (None, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--bind" => {
+                let bind = value()?;
+                serve.bind = Some(bind.parse().map_err(|_| format!("Invalid address: {}", bind))?);
+            }
+            "--port" => {
+                let port = value()?;
+                serve.port = Some(port.parse().map_err(|_| format!("Invalid port: {}", port))?);
+            }
+            "--tls-cert" => cert = Some(PathBuf::from(value()?)),
+            "--tls-key" => key = Some(PathBuf::from(value()?)),
+            "--trust-proxy" => serve.trusted_proxies.push(value()?),
+            "-h" | "--help" => {
+                print_serve_usage();
+                std::process::exit(0);
+            }
+            // The port, as `fastn-hub serve 8080`
+            port if serve.port.is_none() && !port.starts_with('-') => {
+                serve.port = Some(port.parse().map_err(|_| format!("Invalid port: {}", port))?);
+            }
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+    serve.tls = match (cert, key) {
+        (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+        (None, None) => None,
+        _ => return Err("--tls-cert and --tls-key go together".to_string()),
+    };
+    fastn_hub::listen::TrustedProxies::parse(&serve.trusted_proxies)?;
+    Ok(serve)
+}
+
+fn print_mirror_usage() {
+    println!("Usage: fastn-hub mirror <command>");
+    println!();
+    println!("A mirror is a read-only kosha that follows a kosha on another hub. The");
+    println!("other hub must be in a .hubs file here, with its URL, and list this hub");
+    println!("in one of its own. While this hub serves, mirrors sync every interval");
+    println!("and whenever the other hub pushes a change.");
+    println!();
+    println!("Commands:");
+    println!("  add <alias> <hub> <kosha> [--interval <secs>]");
+    println!("                          Create kosha <alias> mirroring <kosha> on <hub>");
+    println!("                          (default interval {}s)", fastn_hub::mirror::DEFAULT_INTERVAL.as_secs());
+    println!("  status [alias...]       Show where mirrors are up to");
+    println!("  sync <alias...>         Pull changes now");
+    println!("  remove <alias>          Stop mirroring; the kosha keeps its files");
+}
+
+/// Run `fastn-hub mirror <command>`
+async fn mirror_command(mut hub: Hub, args: &[String]) -> Result<(), String> {
+    let command = args.first().map(|s| s.as_str());
+    let rest = args.get(1..).unwrap_or_default();
+    match command {
+        Some("add") => {
+            let (mut positional, mut interval) = (vec![], fastn_hub::mirror::DEFAULT_INTERVAL);
+            let mut args = rest.iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--interval" => {
+                        let secs = args.next().ok_or("--interval needs a value")?;
+                        let secs: u64 = secs.parse().map_err(|_| format!("Invalid interval: {}", secs))?;
+                        interval = std::time::Duration::from_secs(secs);
+                    }
+                    _ => positional.push(arg.as_str()),
+                }
+            }
+            let [alias, remote_hub, kosha] = positional[..] else {
+                print_mirror_usage();
+                return Err("Usage: fastn-hub mirror add <alias> <hub> <kosha> [--interval <secs>]".to_string());
+            };
+            let mirror = hub
+                .add_mirror(alias, remote_hub, kosha, interval)
+                .await
+                .map_err(|e| format!("Failed to add mirror: {}", e))?;
+            println!("Kosha {} mirrors {}/{}", mirror.alias, mirror.hub, mirror.kosha);
+            println!("Run 'fastn-hub mirror sync {}' or start the hub to pull it.", mirror.alias);
+        }
+        Some("status") => {
+            let mirrors = hub.list_mirrors().await.map_err(|e| format!("Failed to list mirrors: {}", e))?;
+            let mirrors: Vec<_> = mirrors
+                .into_iter()
+                .filter(|m| rest.is_empty() || rest.contains(&m.alias))
+                .collect();
+            if mirrors.is_empty() {
+                println!("No mirrors.");
+            }
+            let time = |t: Option<chrono::DateTime<chrono::Utc>>| match t {
+                Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+                None => "never".to_string(),
+            };
+            for mirror in mirrors {
+                println!("{} <- {}/{} (every {}s)", mirror.alias, mirror.hub, mirror.kosha, mirror.interval_secs);
+                println!("  Files:     {} ({} bytes)", mirror.files.len(), mirror.bytes());
+                println!("  Last sync: {}", time(mirror.last_sync));
+                if let Some(error) = &mirror.last_error {
+                    println!("  Failing:   {} (since {})", error, time(mirror.last_attempt));
+                }
+            }
+        }
+        Some("sync") if !rest.is_empty() => {
+            let mut failed = false;
+            for alias in rest {
+                match hub.sync_mirror(alias).await {
+                    Ok(sync) => println!(
+                        "{}: {} downloaded ({} bytes), {} renamed, {} deleted",
+                        alias,
+                        sync.downloaded.len(),
+                        sync.bytes,
+                        sync.renamed.len(),
+                        sync.deleted.len()
+                    ),
+                    Err(e) => {
+                        eprintln!("{}: {}", alias, e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                return Err("Some mirrors failed to sync".to_string());
+            }
+        }
+        Some("remove") if rest.len() == 1 => {
+            match hub.remove_mirror(&rest[0]).await {
+                Ok(true) => println!("Kosha {} no longer mirrors; its files stay", rest[0]),
+                Ok(false) => return Err(format!("Not a mirror: {}", rest[0])),
+                Err(e) => return Err(format!("Failed to remove mirror: {}", e)),
+            }
+        }
+        Some("-h") | Some("--help") => print_mirror_usage(),
+        _ => {
+            print_mirror_usage();
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn print_token_usage() {
+    println!("Usage: fastn-hub token <command>");
+    println!();
+    println!("Tokens let tools that can't sign requests use one kosha through the");
+    println!("REST gateway ('fastn-hub gateway on'), sending 'Authorization: Bearer");
+    println!("<token>'. Requests are rate limited, audited and checked by ACL modules");
+    println!("as token:<name>.");
+    println!();
+    println!("Commands:");
+    println!("  create <name> --kosha <alias> [--path <prefix>] [--write] [--expires <days>]");
+    println!("                          Mint a token, read-only unless --write; it is");
+    println!("                          shown once and only its hash is kept");
+    println!("  list                    Show tokens and what they open");
+    println!("  revoke <name>           Stop accepting a token");
+}
+
+/// Run `fastn-hub token <command>`
+async fn token_command(hub: &Hub, args: &[String]) -> Result<(), String> {
+    let command = args.first().map(|s| s.as_str());
+    let rest = args.get(1..).unwrap_or_default();
+    match command {
+        Some("create") => {
+            let (mut positional, mut kosha, mut path, mut write, mut expires_at) = (vec![], None, "", false, None);
+            let mut args = rest.iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--kosha" => kosha = Some(args.next().ok_or("--kosha needs a value")?.as_str()),
+                    "--path" => path = args.next().ok_or("--path needs a value")?.as_str(),
+                    "--write" => write = true,
+                    "--expires" => {
+                        let days = args.next().ok_or("--expires needs a value")?;
+                        let days: i64 = days.parse().map_err(|_| format!("Invalid number of days: {}", days))?;
+                        expires_at = Some(chrono::Utc::now() + chrono::Duration::days(days));
+                    }
+                    _ => positional.push(arg.as_str()),
+                }
+            }
+            let (&[name], Some(kosha)) = (&positional[..], kosha) else {
+                print_token_usage();
+                return Err("Usage: fastn-hub token create <name> --kosha <alias> [options]".to_string());
+            };
+            let (token, secret) = hub
+                .create_token(name, kosha, path, write, expires_at)
+                .await
+                .map_err(|e| format!("Failed to create token: {}", e))?;
+            println!("Token {} created. It won't be shown again:", token.name);
+            println!();
+            println!("  {}", secret);
+            println!();
+            if !hub.rest_gateway() {
+                println!("The REST gateway is off; turn it on with 'fastn-hub gateway on'.");
+            }
+        }
+        Some("list") => {
+            let tokens = hub.list_tokens().await.map_err(|e| format!("Failed to list tokens: {}", e))?;
+            if tokens.is_empty() {
+                println!("No tokens.");
+            }
+            for token in tokens {
+                let scope = match token.path_prefix.is_empty() {
+                    true => token.kosha.clone(),
+                    false => format!("{}/{}", token.kosha, token.path_prefix),
+                };
+                let access = if token.write { "read-write" } else { "read-only" };
+                let expires = match token.expires_at {
+                    Some(_) if token.is_expired() => "expired".to_string(),
+                    Some(t) => format!("expires {}", t.format("%Y-%m-%d %H:%M:%S")),
+                    None => "never expires".to_string(),
+                };
+                println!("{}: {} ({}, {})", token.name, scope, access, expires);
+            }
+        }
+        Some("revoke") if rest.len() == 1 => {
+            match hub.revoke_token(&rest[0]).await {
+                Ok(true) => println!("Token revoked: {}", rest[0]),
+                Ok(false) => return Err(format!("Token not found: {}", rest[0])),
+                Err(e) => return Err(format!("Failed to revoke token: {}", e)),
+            }
+        }
+        Some("-h") | Some("--help") => print_token_usage(),
+        _ => {
+            print_token_usage();
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn print_invite_usage() {
+    println!("Usage: fastn-hub invite [--ttl <minutes>]");
+    println!("       fastn-hub invite list");
+    println!("       fastn-hub invite revoke <id>");
+    println!();
+    println!("An invite is a token a spoke joins the hub with, instead of you running");
+    println!("'fastn-hub add-spoke <id52>': the spoke runs 'fastn-spoke join <hub-url>");
+    println!("<token>'. Each invite works once, for an hour unless --ttl says otherwise.");
+}
+
+/// Run `fastn-hub invite [command]`
+async fn invite_command(hub: &Hub, args: &[String]) -> Result<(), String> {
+    match args.first().map(|s| s.as_str()) {
+        None | Some("--ttl") => {
+            let ttl = match args.get(1) {
+                Some(minutes) => {
+                    let minutes: i64 = minutes.parse().map_err(|_| format!("Invalid number of minutes: {}", minutes))?;
+                    chrono::Duration::minutes(minutes)
+                }
+                None if args.is_empty() => fastn_hub::DEFAULT_INVITE_TTL,
+                None => return Err("--ttl needs a value".to_string()),
+            };
+            let (invite, token) = hub
+                .create_invite(ttl)
+                .await
+                .map_err(|e| format!("Failed to create invite: {}", e))?;
+            println!("Invite {} created, valid until {}.", invite.id, invite.expires_at.format("%Y-%m-%d %H:%M:%S UTC"));
+            println!("It works once. On the spoke, run:");
+            println!();
+            println!("  fastn-spoke join <hub-url> {}", token);
+        }
+        Some("list") => {
+            let invites = hub.list_invites().await.map_err(|e| format!("Failed to list invites: {}", e))?;
+            let invites: Vec<_> = invites.into_iter().filter(|invite| !invite.is_expired()).collect();
+            if invites.is_empty() {
+                println!("No open invites.");
+            }
+            for invite in invites {
+                println!("{}: expires {}", invite.id, invite.expires_at.format("%Y-%m-%d %H:%M:%S"));
+            }
+        }
+        Some("revoke") if args.len() == 2 => match hub.revoke_invite(&args[1]).await {
+            Ok(true) => println!("Invite revoked: {}", args[1]),
+            Ok(false) => return Err(format!("Invite not found: {}", args[1])),
+            Err(e) => return Err(format!("Failed to revoke invite: {}", e)),
+        },
+        Some("-h") | Some("--help") => print_invite_usage(),
+        _ => {
+            print_invite_usage();
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn print_key_usage() {
+    println!("Usage: fastn-hub key");
+    println!("       fastn-hub key protect --passphrase");
+    println!("       fastn-hub key protect --keychain");
+    println!("       fastn-hub key unprotect");
+    println!();
+    println!("hub.key holds the hub's secret key, as plain bytes unless protected:");
+    println!("  --passphrase  Encrypt it with the passphrase in FASTN_KEY_PASSPHRASE,");
+    println!("                which the hub then needs set to start");
+    println!("  --keychain    Move the key to the OS keychain (builds with the");
+    println!("                \"keychain\" feature)");
+    println!();
+    println!("To change the passphrase, unprotect with the old one, then protect with");
+    println!("the new one.");
+}
+
+/// Run `fastn-hub key [command]`
+async fn key_command(home: &std::path::Path, args: &[String]) -> Result<(), String> {
+    use fastn_net::key_file::{self, Protection};
+    let protection = match args.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        [] => {
+            let protection = Hub::key_protection(home).map_err(|e| format!("Failed to read hub.key: {}", e))?;
+            println!("hub.key: {}", protection.name());
+            return Ok(());
+        }
+        ["protect", "--passphrase"] => match key_file::env_passphrase() {
+            Some(passphrase) => Protection::Passphrase(passphrase),
+            None => return Err(format!("Set {} to the passphrase first", key_file::PASSPHRASE_ENV)),
+        },
+        ["protect", "--keychain"] => Protection::Keychain,
+        ["unprotect"] => Protection::Plain,
+        ["-h"] | ["--help"] => {
+            print_key_usage();
+            return Ok(());
+        }
+        _ => {
+            print_key_usage();
+            std::process::exit(1);
+        }
+    };
+    let mut hub = Hub::load(home).await.map_err(|e| format!("Failed to load hub: {}", e))?;
+    hub.protect_key(protection.clone())
+        .map_err(|e| format!("Failed to write hub.key: {}", e))?;
+    println!("hub.key is now {}.", protection.name());
+    Ok(())
+}
+
+/// Default number of entries `fastn-hub audit` shows
+const AUDIT_DEFAULT_LIMIT: usize = 100;
+
+fn print_audit_usage() {
+    println!("Usage: fastn-hub audit [filters] [--json]");
+    println!();
+    println!("Shows the most recent requests in the audit log, oldest first.");
+    println!();
+    println!("Filters:");
+    println!("  --sender <id52|alias>   Requests signed by this identity");
+    println!("  --client <address>      Requests from this network address");
+    println!("  --app <app>             e.g. kosha, hub, push");
+    println!("  --instance <name>       e.g. a kosha alias");
+    println!("  --command <command>     e.g. read_file, write_file");
+    println!("  --path <prefix>         Requests for paths starting with this");
+    println!("  --result <result>       ok, denied, rate_limited or failed");
+    println!("  --since <time>          RFC 3339 time or YYYY-MM-DD (UTC)");
+    println!("  --until <time>          RFC 3339 time or YYYY-MM-DD (UTC)");
+    println!("  --limit <n>             Show the last n matches (default {})", AUDIT_DEFAULT_LIMIT);
+    println!("  --all                   Show every match");
+    println!("  --json                  Print entries as JSON lines");
+}
+
+/// Parse the arguments of `fastn-hub audit` into a query, and whether to
+/// print JSON
+fn parse_audit_args(args: &[String]) -> Result<(AuditQuery, bool), String> {
+    let mut query = AuditQuery {
+        limit: Some(AUDIT_DEFAULT_LIMIT),
+        ..AuditQuery::default()
+    };
+    let mut json = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--sender" => query.sender = Some(value()?),
+            "--client" => {
+                let client = value()?;
+                query.client = Some(client.parse().map_err(|_| format!("Invalid address: {}", client))?);
+            }
+            "--app" => query.app = Some(value()?),
+            "--instance" => query.instance = Some(value()?),
+            "--command" => query.command = Some(value()?),
+            "--path" => query.path_prefix = Some(value()?),
+            "--result" => query.result = Some(value()?.parse()?),
+            "--since" => query.since = Some(parse_time(&value()?)?),
+            "--until" => query.until = Some(parse_time(&value()?)?),
+            "--limit" => {
+                let limit = value()?;
+                query.limit = Some(limit.parse().map_err(|_| format!("Invalid limit: {}", limit))?);
+            }
+            "--all" => query.limit = None,
+            "--json" => json = true,
+            "-h" | "--help" => {
+                print_audit_usage();
+                std::process::exit(0);
+            }
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+    Ok((query, json))
+}
+
+/// A time given as RFC 3339, or a UTC date
+fn parse_time(value: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.into());
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        .map_err(|_| format!("Invalid time: {} (use RFC 3339 or YYYY-MM-DD)", value))
 }
@@ -0,0 +1,243 @@
+//! Hub metrics, for the admin API and Prometheus
+//!
+//! `Hub::handle_request` records every request's app, command, outcome and
+//! latency; kosha commands also count the file bytes they read and wrote,
+//! and push sessions count themselves while subscribed. The admin API's
+//! `metrics` command reports the totals, and `GET /_fastn/metrics` (no auth)
+//! serves everything in the Prometheus text format:
+//!
+//! - `fastn_hub_requests_total{app, command, outcome}` - counter, `outcome`
+//!   is `ok`, `unauthorized`, `denied` or `failed`
+//! - `fastn_hub_request_duration_seconds{app, command}` - histogram
+//! - `fastn_hub_acl_denials_total` - counter
+//! - `fastn_hub_kosha_read_bytes_total`, `fastn_hub_kosha_written_bytes_total`:
+//!   counters of file contents
+//! - `fastn_hub_push_subscriptions` - gauge of open push sessions
+//! - `fastn_hub_uptime_seconds` - gauge
+//!
+//! Labels never name koshas or senders. Commands come from requests, so
+//! past `MAX_COMMANDS` distinct ones new commands are counted as `other`.
+
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Path of the metrics endpoint
+pub const METRICS_ENDPOINT: &str = "/_fastn/metrics";
+
+/// Distinct (app, command) pairs counted before the rest become `other`
+pub const MAX_COMMANDS: usize = 256;
+
+/// Upper bounds of the latency histogram buckets, in seconds
+pub const LATENCY_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// Request counters, updated by `Hub::handle_request`
+#[derive(Debug)]
+pub struct HubMetrics {
+    pub started_at: DateTime<Utc>,
+    requests_total: AtomicU64,
+    requests_denied: AtomicU64,
+    requests_failed: AtomicU64,
+    acl_denials: AtomicU64,
+    kosha_read_bytes: AtomicU64,
+    kosha_written_bytes: AtomicU64,
+    /// Shared with the `Subscription`s of open push sessions
+    subscriptions: Arc<AtomicU64>,
+    commands: Mutex<BTreeMap<(String, String), CommandStats>>,
+}
+
+impl Default for HubMetrics {
+    fn default() -> Self {
+        Self {
+            started_at: Utc::now(),
+            requests_total: AtomicU64::new(0),
+            requests_denied: AtomicU64::new(0),
+            requests_failed: AtomicU64::new(0),
+            acl_denials: AtomicU64::new(0),
+            kosha_read_bytes: AtomicU64::new(0),
+            kosha_written_bytes: AtomicU64::new(0),
+            subscriptions: Arc::new(AtomicU64::new(0)),
+            commands: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+/// Outcome of a request, for the counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOutcome {
+    Ok,
+    /// The sender isn't known to the hub
+    Unauthorized,
+    /// An ACL (or the admin API) refused the request
+    Denied,
+    Failed,
+}
+
+impl RequestOutcome {
+    fn label(self) -> &'static str {
+        match self {
+            RequestOutcome::Ok => "ok",
+            RequestOutcome::Unauthorized => "unauthorized",
+            RequestOutcome::Denied => "denied",
+            RequestOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// Counts and latencies of one (app, command)
+#[derive(Debug, Default)]
+struct CommandStats {
+    /// By outcome label
+    outcomes: BTreeMap<&'static str, u64>,
+    /// Requests per `LATENCY_BUCKETS` bucket (not cumulative), the last one
+    /// for slower requests
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    seconds: f64,
+    count: u64,
+}
+
+/// An open push session, counted until dropped
+#[derive(Debug)]
+pub struct Subscription(Arc<AtomicU64>);
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl HubMetrics {
+    /// Count a request and how long it took
+    pub fn record(&self, app: &str, command: &str, outcome: RequestOutcome, duration: Duration) {
+        self.requests_total.fetch_add(1, Ordering::Relaxed);
+        match outcome {
+            RequestOutcome::Ok => {}
+            RequestOutcome::Unauthorized => {
+                self.requests_denied.fetch_add(1, Ordering::Relaxed);
+            }
+            RequestOutcome::Denied => {
+                self.requests_denied.fetch_add(1, Ordering::Relaxed);
+                self.acl_denials.fetch_add(1, Ordering::Relaxed);
+            }
+            RequestOutcome::Failed => {
+                self.requests_failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let mut commands = self.commands.lock().unwrap_or_else(|e| e.into_inner());
+        let mut key = (app.to_string(), command.to_string());
+        if !commands.contains_key(&key) && commands.len() >= MAX_COMMANDS {
+            key = ("other".to_string(), "other".to_string());
+        }
+        let stats = commands.entry(key).or_default();
+        *stats.outcomes.entry(outcome.label()).or_default() += 1;
+        let seconds = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS.iter().position(|&le| seconds <= le).unwrap_or(LATENCY_BUCKETS.len());
+        stats.buckets[bucket] += 1;
+        stats.seconds += seconds;
+        stats.count += 1;
+    }
+
+    /// Count file bytes a kosha command read and wrote
+    pub fn record_kosha_bytes(&self, read: u64, written: u64) {
+        self.kosha_read_bytes.fetch_add(read, Ordering::Relaxed);
+        self.kosha_written_bytes.fetch_add(written, Ordering::Relaxed);
+    }
+
+    /// Count a push session until the returned guard is dropped
+    pub fn subscribe(&self) -> Subscription {
+        self.subscriptions.fetch_add(1, Ordering::Relaxed);
+        Subscription(self.subscriptions.clone())
+    }
+
+    pub fn requests_total(&self) -> u64 {
+        self.requests_total.load(Ordering::Relaxed)
+    }
+
+    /// Unauthorized and denied requests
+    pub fn requests_denied(&self) -> u64 {
+        self.requests_denied.load(Ordering::Relaxed)
+    }
+
+    pub fn requests_failed(&self) -> u64 {
+        self.requests_failed.load(Ordering::Relaxed)
+    }
+
+    pub fn acl_denials(&self) -> u64 {
+        self.acl_denials.load(Ordering::Relaxed)
+    }
+
+    pub fn kosha_read_bytes(&self) -> u64 {
+        self.kosha_read_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn kosha_written_bytes(&self) -> u64 {
+        self.kosha_written_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn push_subscriptions(&self) -> u64 {
+        self.subscriptions.load(Ordering::Relaxed)
+    }
+
+    /// Everything, in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        let commands = self.commands.lock().unwrap_or_else(|e| e.into_inner());
+
+        header(&mut out, "fastn_hub_requests_total", "counter", "Requests handled, by app, command and outcome");
+        for ((app, command), stats) in commands.iter() {
+            for (outcome, count) in &stats.outcomes {
+                let labels = format!("app=\"{}\",command=\"{}\",outcome=\"{}\"", escape(app), escape(command), outcome);
+                let _ = writeln!(out, "fastn_hub_requests_total{{{}}} {}", labels, count);
+            }
+        }
+
+        header(&mut out, "fastn_hub_request_duration_seconds", "histogram", "Time taken to handle requests");
+        for ((app, command), stats) in commands.iter() {
+            let labels = format!("app=\"{}\",command=\"{}\"", escape(app), escape(command));
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(&stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "fastn_hub_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, le, cumulative);
+            }
+            let _ = writeln!(out, "fastn_hub_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, stats.count);
+            let _ = writeln!(out, "fastn_hub_request_duration_seconds_sum{{{}}} {}", labels, stats.seconds);
+            let _ = writeln!(out, "fastn_hub_request_duration_seconds_count{{{}}} {}", labels, stats.count);
+        }
+        drop(commands);
+
+        let values = [
+            ("fastn_hub_acl_denials_total", "counter", "Requests refused by an ACL or the admin API", self.acl_denials()),
+            ("fastn_hub_kosha_read_bytes_total", "counter", "File bytes read from koshas", self.kosha_read_bytes()),
+            ("fastn_hub_kosha_written_bytes_total", "counter", "File bytes written to koshas", self.kosha_written_bytes()),
+            ("fastn_hub_push_subscriptions", "gauge", "Open push (WebSocket) sessions", self.push_subscriptions()),
+        ];
+        for (name, kind, help, value) in values {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        header(&mut out, "fastn_hub_uptime_seconds", "gauge", "Seconds since the hub started");
+        let _ = writeln!(out, "fastn_hub_uptime_seconds {}", (Utc::now() - self.started_at).num_seconds());
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value (backslash, double quote and newline)
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Length of the data a base64 string decodes to
+pub(crate) fn base64_len(encoded: &str) -> u64 {
+    let padding = encoded.bytes().rev().take_while(|&b| b == b'=').count();
+    (encoded.len() / 4 * 3).saturating_sub(padding) as u64
+}
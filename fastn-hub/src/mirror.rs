@@ -0,0 +1,527 @@
+//! Mirrors: read-only local copies of koshas on other hubs
+//!
+//! `Hub::add_mirror` creates a kosha and records where it mirrors from in
+//! `FASTN_HOME/mirrors/<alias>.json`, along with the replication cursor:
+//! each remote file's listed size, modification time and SHA-256 as of the
+//! pass that pulled it. A pass (`Hub::sync_mirror`) lists the remote kosha,
+//! signed as this hub (so the remote hub must list it in a .hubs file), and
+//! hashes only the files whose listing changed:
+//! - New or changed files are downloaded in `MAX_CHUNK_SIZE` ranges, pinned
+//!   to the hash, and written locally (the local kosha keeps their history).
+//! - Files gone from the remote kosha are deleted locally.
+//! - A new file with the content of one that is gone was renamed: it is
+//!   renamed locally, keeping its history, instead of downloaded again.
+//!
+//! A failed pass keeps what it pulled and notes the error; the next pass
+//! picks up from there. While the hub serves, each mirror syncs every
+//! `interval_secs` and `SETTLE_DELAY` after the remote hub pushes a change
+//! to the kosha (`Topic::Files`); without a push channel it only polls.
+//!
+//! Mirrors are read-only: the hub refuses commands that would change them,
+//! from its own spokes too. `Hub::remove_mirror` stops mirroring and leaves
+//! the kosha as an ordinary one.
+
+use crate::{Error, Hub, HubError, Request, Response, Result};
+use chrono::{DateTime, Utc};
+use fastn_kosha::{DirEntry, Kosha};
+use fastn_net::client::{Client, Subscription};
+use fastn_net::{PushEvent, Subscribe, Topic};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// How often a mirror syncs unless told otherwise
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How long a serving hub waits after a pushed change before syncing, so a
+/// burst of changes is pulled in one pass
+pub const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// Kosha commands that change nothing, the only ones a mirror serves
+const READ_COMMANDS: &[&str] = &[
+    "read_file",
+    "list_dir",
+    "get_versions",
+    "read_version",
+    "read_derived",
+    "read_range",
+    "file_hash",
+    "kv_get",
+    "kv_state",
+    "db_query",
+    "get",
+    "search",
+];
+
+/// A mirror's source and replication cursor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mirror {
+    /// The local kosha
+    pub alias: String,
+    /// The remote hub, by its alias in the .hubs files
+    pub hub: String,
+    /// The kosha on the remote hub
+    pub kosha: String,
+    /// Seconds between passes while the hub serves
+    pub interval_secs: u64,
+    pub created_at: DateTime<Utc>,
+    /// When the last pass that completed started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<DateTime<Utc>>,
+    /// When the last pass started, completed or not
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt: Option<DateTime<Utc>>,
+    /// Why the last pass failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Path -> the remote file as of the pass that pulled it
+    #[serde(default)]
+    pub files: BTreeMap<String, MirroredFile>,
+}
+
+impl Mirror {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    /// Size of the mirrored files
+    pub fn bytes(&self) -> u64 {
+        self.files.values().map(|f| f.size).sum()
+    }
+}
+
+/// A remote file as the mirror last pulled it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MirroredFile {
+    /// As listed by the remote hub
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+    /// Lowercase hex
+    pub sha256: String,
+}
+
+/// What a pass changed in the mirror
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MirrorSync {
+    /// New or changed files
+    pub downloaded: Vec<String>,
+    /// Files renamed on the remote hub, as (from, to)
+    pub renamed: Vec<(String, String)>,
+    pub deleted: Vec<String>,
+    /// Size of the downloaded files
+    pub bytes: u64,
+}
+
+impl MirrorSync {
+    pub fn is_empty(&self) -> bool {
+        self.downloaded.is_empty() && self.renamed.is_empty() && self.deleted.is_empty()
+    }
+
+    /// Events for the hub's push subscribers, as the kosha commands would
+    /// have pushed
+    fn events(&self, alias: &str) -> Vec<PushEvent> {
+        let renamed = self.renamed.iter().flat_map(|(from, to)| [from, to]);
+        self.downloaded
+            .iter()
+            .chain(renamed)
+            .chain(&self.deleted)
+            .map(|path| PushEvent::FileChanged {
+                kosha: alias.to_string(),
+                path: path.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Whether a mirror serves `command`
+pub fn is_read_command(command: &str) -> bool {
+    READ_COMMANDS.contains(&command)
+}
+
+impl Hub {
+    fn mirrors_dir(&self) -> PathBuf {
+        self.home.join("mirrors")
+    }
+
+    /// Whether the kosha is a mirror, and so read-only
+    pub fn is_mirror(&self, alias: &str) -> bool {
+        self.mirrors.contains(alias)
+    }
+
+    /// Create kosha `alias` as a mirror of kosha `kosha` on the hub known
+    /// as `hub` in the .hubs files, synced every `interval` while serving
+    ///
+    /// Nothing is pulled until the first pass.
+    pub async fn add_mirror(&mut self, alias: &str, hub: &str, kosha: &str, interval: Duration) -> Result<Mirror> {
+        Self::validate_kosha_alias(alias)?;
+        let remote = self
+            .lookup_hub_by_alias(hub)
+            .await?
+            .ok_or_else(|| Error::Mirror(format!("Unknown hub alias: '{}'. Add it to hubs/*.hubs", hub)))?;
+        if remote.address().is_empty() {
+            return Err(Error::Mirror(format!("Hub '{}' has no URL or iroh endpoint configured", hub)));
+        }
+        self.create_kosha(alias).await?;
+
+        let mirror = Mirror {
+            alias: alias.to_string(),
+            hub: hub.to_string(),
+            kosha: kosha.to_string(),
+            interval_secs: interval.as_secs().max(1),
+            created_at: Utc::now(),
+            last_sync: None,
+            last_attempt: None,
+            last_error: None,
+            files: BTreeMap::new(),
+        };
+        save(&self.mirrors_dir(), &mirror).await?;
+        self.mirrors.insert(alias.to_string());
+        tracing::info!("Mirroring {}/{} into kosha {}", hub, kosha, alias);
+        Ok(mirror)
+    }
+
+    /// Stop mirroring into kosha `alias`, which keeps its files and becomes
+    /// writable
+    ///
+    /// Returns false if it isn't a mirror.
+    pub async fn remove_mirror(&mut self, alias: &str) -> Result<bool> {
+        if Self::validate_kosha_alias(alias).is_err() {
+            return Ok(false);
+        }
+        let removed = match tokio::fs::remove_file(state_path(&self.mirrors_dir(), alias)).await {
+            Ok(()) => true,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(self.mirrors.remove(alias) || removed)
+    }
+
+    /// A mirror's source and cursor, as of its last pass
+    pub async fn get_mirror(&self, alias: &str) -> Result<Option<Mirror>> {
+        if Self::validate_kosha_alias(alias).is_err() {
+            return Ok(None);
+        }
+        load(&state_path(&self.mirrors_dir(), alias)).await
+    }
+
+    /// Every mirror, sorted by alias
+    pub async fn list_mirrors(&self) -> Result<Vec<Mirror>> {
+        let mut mirrors = vec![];
+        for alias in load_aliases(&self.mirrors_dir()).await? {
+            mirrors.extend(self.get_mirror(&alias).await?);
+        }
+        mirrors.sort_by(|a, b| a.alias.cmp(&b.alias));
+        Ok(mirrors)
+    }
+
+    /// Pull what changed on the remote hub since the last pass
+    ///
+    /// The outcome is also recorded in the mirror (`last_sync`,
+    /// `last_error`).
+    pub async fn sync_mirror(&self, alias: &str) -> Result<MirrorSync> {
+        self.puller(alias)
+            .await?
+            .ok_or_else(|| Error::InstanceNotFound("mirror".to_string(), alias.to_string()))?
+            .sync()
+            .await
+    }
+
+    /// What a pass for mirror `alias` needs from the hub; None if it isn't
+    /// a mirror
+    pub(crate) async fn puller(&self, alias: &str) -> Result<Option<Puller>> {
+        let Some(mirror) = self.get_mirror(alias).await? else {
+            return Ok(None);
+        };
+        let source = self.mirror_source(&mirror).await;
+        Ok(Some(Puller {
+            mirror,
+            dir: self.mirrors_dir(),
+            source: source.map_err(|e| e.to_string()),
+            events: self.events.clone(),
+        }))
+    }
+
+    async fn mirror_source(&self, mirror: &Mirror) -> Result<Source> {
+        let kosha = self
+            .get_kosha(&mirror.alias)
+            .await?
+            .ok_or_else(|| Error::InstanceNotFound("kosha".to_string(), mirror.alias.clone()))?;
+        let remote = self
+            .lookup_hub_by_alias(&mirror.hub)
+            .await?
+            .ok_or_else(|| Error::Mirror(format!("Unknown hub alias: '{}'. Add it to hubs/*.hubs", mirror.hub)))?;
+        let client = Client::with_address(self.secret_key.clone(), remote.id52.clone(), remote.address());
+        Ok(Source { kosha, client })
+    }
+}
+
+/// The local kosha and a client for the remote hub
+pub(crate) struct Source {
+    kosha: Kosha,
+    client: Client,
+}
+
+/// A pass for one mirror, with what it needs from the hub, so it runs
+/// without holding the hub
+pub(crate) struct Puller {
+    mirror: Mirror,
+    dir: PathBuf,
+    /// Or why the mirror can't be reached
+    source: std::result::Result<Source, String>,
+    events: tokio::sync::broadcast::Sender<PushEvent>,
+}
+
+impl Puller {
+    /// Run the pass and record its outcome
+    pub(crate) async fn sync(mut self) -> Result<MirrorSync> {
+        let started = Utc::now();
+        let mut summary = MirrorSync::default();
+        let result = self.pull(&mut summary).await;
+
+        self.mirror.last_attempt = Some(started);
+        match &result {
+            Ok(()) => {
+                self.mirror.last_sync = Some(started);
+                self.mirror.last_error = None;
+            }
+            Err(e) => self.mirror.last_error = Some(e.to_string()),
+        }
+        save(&self.dir, &self.mirror).await?;
+        // Sending only fails when nobody is subscribed
+        for event in summary.events(&self.mirror.alias) {
+            let _ = self.events.send(event);
+        }
+        result.map(|()| summary)
+    }
+
+    /// Follow changes to the remote kosha, if its hub has a push channel
+    async fn subscribe(&self) -> Result<Subscription<PushEvent>> {
+        let source = self.source()?;
+        let subscribe = Subscribe {
+            topics: vec![Topic::Files {
+                instance: self.mirror.kosha.clone(),
+                path_prefix: String::new(),
+            }],
+        };
+        let result: std::result::Result<_, HubError> = source.client.subscribe(&subscribe).await?;
+        result.map_err(|e| Error::Mirror(format!("Subscription to hub '{}' refused: {:?}", self.mirror.hub, e)))
+    }
+
+    fn source(&self) -> Result<&Source> {
+        self.source.as_ref().map_err(|e| Error::Mirror(e.clone()))
+    }
+
+    async fn pull(&mut self, summary: &mut MirrorSync) -> Result<()> {
+        let remote = self.list_remote().await?;
+        let mut gone: Vec<String> = self.mirror.files.keys().filter(|p| !remote.contains_key(*p)).cloned().collect();
+
+        for (path, entry) in remote {
+            let cursor = self.mirror.files.get(&path);
+            if cursor.is_some_and(|f| f.size == entry.size && f.modified == entry.modified) {
+                continue;
+            }
+            let file = MirroredFile {
+                size: entry.size,
+                modified: entry.modified,
+                sha256: self.remote_hash(&path).await?,
+            };
+            let unchanged = cursor.is_some_and(|f| f.sha256 == file.sha256);
+            let renamed_from = match cursor {
+                None => gone.iter().position(|g| self.mirror.files[g].sha256 == file.sha256),
+                Some(_) => None,
+            };
+            if unchanged {
+                // Touched, or listed differently: nothing to pull
+            } else if let Some(i) = renamed_from
+                && self.source()?.kosha.rename(&gone[i], &path).await.is_ok()
+            {
+                let from = gone.remove(i);
+                self.mirror.files.remove(&from);
+                summary.renamed.push((from, path.clone()));
+            } else {
+                let content = self.download(&path, &file.sha256).await?;
+                self.source()?.kosha.write_file(&path, &content).await?;
+                summary.bytes += content.len() as u64;
+                summary.downloaded.push(path.clone());
+            }
+            self.mirror.files.insert(path, file);
+        }
+
+        for path in gone {
+            match self.source()?.kosha.delete(&path).await {
+                Ok(()) | Err(fastn_kosha::Error::NotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+            self.mirror.files.remove(&path);
+            summary.deleted.push(path);
+        }
+        Ok(())
+    }
+
+    /// Every file in the remote kosha, by path
+    async fn list_remote(&self) -> Result<BTreeMap<String, DirEntry>> {
+        let mut files = BTreeMap::new();
+        let mut pending = vec![String::new()];
+        while let Some(dir) = pending.pop() {
+            let listed = self.call("list_dir", serde_json::json!({ "path": dir })).await?;
+            let entries: Vec<DirEntry> = serde_json::from_value(listed["entries"].clone())?;
+            for entry in entries {
+                let path = match dir.as_str() {
+                    "" => entry.name.clone(),
+                    dir => format!("{}/{}", dir, entry.name),
+                };
+                match entry.is_dir {
+                    true => pending.push(path),
+                    false => {
+                        files.insert(path, entry);
+                    }
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    async fn remote_hash(&self, path: &str) -> Result<String> {
+        let digest = self.call("file_hash", serde_json::json!({ "path": path })).await?;
+        digest["sha256"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| Error::Mirror(format!("No hash for {} from hub '{}'", path, self.mirror.hub)))
+    }
+
+    /// Download a file in ranges, failing if its content is no longer
+    /// `sha256`
+    async fn download(&self, path: &str, sha256: &str) -> Result<Vec<u8>> {
+        use base64::Engine;
+
+        let mut content = vec![];
+        loop {
+            let payload = serde_json::json!({ "path": path, "offset": content.len(), "sha256": sha256 });
+            let range = self.call("read_range", payload).await?;
+            let chunk = range["content"]
+                .as_str()
+                .and_then(|c| base64::engine::general_purpose::STANDARD.decode(c).ok())
+                .ok_or_else(|| Error::Mirror(format!("Invalid content for {} from hub '{}'", path, self.mirror.hub)))?;
+            content.extend_from_slice(&chunk);
+            let size = range["size"].as_u64().unwrap_or_default();
+            if chunk.is_empty() || content.len() as u64 >= size {
+                break;
+            }
+        }
+        let hash: String = Sha256::digest(&content).iter().map(|b| format!("{:02x}", b)).collect();
+        if hash != sha256 {
+            return Err(Error::Mirror(format!("{} from hub '{}' doesn't match its hash", path, self.mirror.hub)));
+        }
+        Ok(content)
+    }
+
+    /// Run a command on the remote kosha
+    async fn call(&self, command: &str, payload: serde_json::Value) -> Result<serde_json::Value> {
+        let request = Request {
+            target_hub: "self".to_string(),
+            app: "kosha".to_string(),
+            instance: self.mirror.kosha.clone(),
+            command: command.to_string(),
+            payload,
+            explain: false,
+        };
+        let result: std::result::Result<Response, HubError> = self.source()?.client.call(&request).await?;
+        result
+            .map(|response| response.payload)
+            .map_err(|e| Error::Mirror(format!("{} failed on hub '{}': {:?}", command, self.mirror.hub, e)))
+    }
+}
+
+/// Keep mirror `alias` in sync while the hub serves: a pass every interval,
+/// and one `SETTLE_DELAY` after the remote hub pushes a change
+///
+/// Ends once the mirror is removed.
+pub(crate) async fn run(hub: Arc<RwLock<Hub>>, alias: String) {
+    let mut changes: Option<Subscription<PushEvent>> = None;
+    loop {
+        let puller = match hub.read().await.puller(&alias).await {
+            Ok(Some(puller)) => puller,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!("Mirror {} stopped: {}", alias, e);
+                return;
+            }
+        };
+        // Subscribe before the pass, so no change falls in between
+        if changes.is_none() {
+            changes = puller
+                .subscribe()
+                .await
+                .inspect_err(|e| tracing::debug!("Mirror {} polls only: {}", alias, e))
+                .ok();
+        }
+        let interval = puller.mirror.interval();
+        match puller.sync().await {
+            Ok(summary) if !summary.is_empty() => tracing::info!(
+                "Mirror {}: {} downloaded, {} renamed, {} deleted",
+                alias,
+                summary.downloaded.len(),
+                summary.renamed.len(),
+                summary.deleted.len()
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Mirror {} sync failed: {}", alias, e),
+        }
+
+        let Some(subscription) = changes.as_mut() else {
+            tokio::time::sleep(interval).await;
+            continue;
+        };
+        match tokio::time::timeout(interval, subscription.next()).await {
+            Ok(Ok(Some(_))) => tokio::time::sleep(SETTLE_DELAY).await,
+            // Closed or broken: subscribe again on the next pass
+            Ok(_) => changes = None,
+            Err(_) => {}
+        }
+    }
+}
+
+fn state_path(dir: &Path, alias: &str) -> PathBuf {
+    dir.join(format!("{}.json", alias))
+}
+
+/// Aliases of the mirrors with state in `dir`
+pub(crate) async fn load_aliases(dir: &Path) -> Result<HashSet<String>> {
+    let mut aliases = HashSet::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(aliases),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(alias) = name.strip_suffix(".json")
+            && Hub::validate_kosha_alias(alias).is_ok()
+        {
+            aliases.insert(alias.to_string());
+        }
+    }
+    Ok(aliases)
+}
+
+async fn load(path: &Path) -> Result<Option<Mirror>> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write the mirror's state, replacing the old one in one step
+async fn save(dir: &Path, mirror: &Mirror) -> Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    let path = state_path(dir, &mirror.alias);
+    let temp = path.with_extension("json.tmp");
+    tokio::fs::write(&temp, serde_json::to_vec_pretty(mirror)?).await?;
+    tokio::fs::rename(&temp, &path).await?;
+    Ok(())
+}
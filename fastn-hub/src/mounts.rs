@@ -0,0 +1,148 @@
+//! Mount points: folders of a kosha that show another kosha's folder
+//!
+//! A kosha lists its mount points in `_mounts.txt` (`fastn_kosha::MountTable`),
+//! e.g. `photos: shared/photos` in the `family` kosha. The hub resolves them
+//! before it authorizes a request:
+//! - Reads under a mount point (`MOUNTED_COMMANDS`) become the same read of
+//!   the mounted kosha, checked against that kosha's ACL as if sent to it,
+//!   so a mount gives no one access they didn't already have. Gateway
+//!   tokens are held to their scope after resolving, so they only follow
+//!   mounts within their own kosha.
+//! - Listings of a folder holding mount points show them as folders.
+//! - Anything else touching a path under a mount point is refused: mounts
+//!   are read-only.
+//!
+//! Mounted koshas may have mount points of their own, followed up to
+//! `MAX_MOUNT_DEPTH` deep; a mount that leads back to itself is an error.
+//! `get`/`post` run the handlers of the kosha asked and `search` searches
+//! only that kosha; neither follows mounts. Writing `_mounts.txt` takes
+//! admin access, as for the ACL modules.
+
+use crate::{Hub, HubError, Request, Response};
+use chrono::Utc;
+use fastn_kosha::{DirEntry, MountTable};
+use std::collections::{BTreeSet, HashSet};
+
+/// Most mounts one request is resolved through
+pub const MAX_MOUNT_DEPTH: usize = 8;
+
+/// Commands served through mount points
+const MOUNTED_COMMANDS: &[&str] = &[
+    "read_file",
+    "list_dir",
+    "get_versions",
+    "read_version",
+    "read_derived",
+    "read_range",
+    "file_hash",
+];
+
+/// Commands that keep to the kosha asked
+const UNMOUNTED_COMMANDS: &[&str] = &["get", "post", "search"];
+
+/// Payload fields naming paths a command touches
+const PATH_FIELDS: &[&str] = &["path", "from", "to", "database"];
+
+impl Hub {
+    /// Follow the mount points under a kosha request's path
+    ///
+    /// Returns the request to run (on the mounted kosha, for a read under
+    /// a mount point) and, for `list_dir`, the mount points in the folder
+    /// listed. Koshas that don't exist are left for the request to report.
+    pub(crate) async fn resolve_mounts(&self, mut request: Request) -> Result<(Request, BTreeSet<String>), HubError> {
+        let command = request.command.as_str();
+        if request.app != "kosha" || UNMOUNTED_COMMANDS.contains(&command) {
+            return Ok((request, BTreeSet::new()));
+        }
+
+        if !MOUNTED_COMMANDS.contains(&command) {
+            for path in PATH_FIELDS.iter().filter_map(|field| request.payload.get(field)?.as_str()) {
+                let Ok(path) = fastn_kosha::clean_path(path) else {
+                    continue;
+                };
+                if let Some(mounts) = self.mount_table(&request.instance).await?
+                    && let Some((mount, _)) = mounts.resolve(path)
+                {
+                    return Err(HubError::AppError {
+                        message: format!("{} is mounted from kosha {} and read-only", mount.at, mount.kosha),
+                    });
+                }
+            }
+            return Ok((request, BTreeSet::new()));
+        }
+
+        let Some(path) = request.payload.get("path").and_then(|v| v.as_str()) else {
+            return Ok((request, BTreeSet::new()));
+        };
+        let Ok(path) = fastn_kosha::clean_path(path) else {
+            return Ok((request, BTreeSet::new()));
+        };
+        let mut path = path.to_string();
+        let mut instance = request.instance.clone();
+        let mut visited = HashSet::new();
+        let mut mount_points = BTreeSet::new();
+        while let Some(mounts) = self.mount_table(&instance).await? {
+            let Some((mount, target)) = mounts.resolve(&path) else {
+                if request.command == "list_dir" {
+                    mount_points = mounts.children(&path);
+                }
+                break;
+            };
+            if !visited.insert((instance.clone(), mount.at.clone())) {
+                return Err(HubError::AppError {
+                    message: format!("Mount loop: {}/{} leads back to itself", instance, mount.at),
+                });
+            }
+            if visited.len() > MAX_MOUNT_DEPTH {
+                return Err(HubError::AppError {
+                    message: format!("{} goes through more than {} mounts", request.instance, MAX_MOUNT_DEPTH),
+                });
+            }
+            tracing::debug!("{}/{} is mounted from {}/{}", instance, path, mount.kosha, target);
+            instance = mount.kosha.clone();
+            path = target;
+        }
+
+        request.instance = instance;
+        request.payload["path"] = serde_json::Value::String(path);
+        Ok((request, mount_points))
+    }
+
+    /// Mount points of a kosha, `None` if there is no such kosha
+    async fn mount_table(&self, alias: &str) -> Result<Option<MountTable>, HubError> {
+        let Some(kosha) = self.get_kosha(alias).await.map_err(|e| HubError::AppError { message: e.to_string() })? else {
+            return Ok(None);
+        };
+        let mounts = kosha.mounts().await.map_err(|e| HubError::AppError {
+            message: format!("Kosha {}: {}", alias, e),
+        })?;
+        Ok(Some(mounts))
+    }
+
+    /// Add the mount points (from `resolve_mounts`) missing from a
+    /// `list_dir` response, as folders
+    pub(crate) fn add_mount_points(mut response: Response, mount_points: BTreeSet<String>) -> Response {
+        if mount_points.is_empty() {
+            return response;
+        }
+        let Some(listed) = response.payload.get("entries").cloned() else {
+            return response;
+        };
+        let Ok(mut entries) = serde_json::from_value::<Vec<DirEntry>>(listed) else {
+            return response;
+        };
+        for name in mount_points {
+            if !entries.iter().any(|entry| entry.name == name) {
+                entries.push(DirEntry {
+                    name,
+                    is_dir: true,
+                    size: 0,
+                    modified: Utc::now(),
+                });
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        response.payload["entries"] = serde_json::json!(entries);
+        response
+    }
+}
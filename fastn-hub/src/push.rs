@@ -0,0 +1,399 @@
+//! Push channel: server-initiated notifications over WebSocket
+//!
+//! Spokes open a WebSocket at `fastn_net::WS_ENDPOINT` and send a
+//! `SignedRequest` with a `Subscribe` payload, addressed to
+//! `Audience::new(hub_id52, WS_ENDPOINT)`. The hub verifies it like any
+//! request (signature, audience, replay) and checks the sender may follow the
+//! topics, then answers with a signed `ResponseEnvelope<(), HubError>`.
+//! After that, every matching `PushEvent` arrives as a `SignedResponse` text
+//! message:
+//! - `file_changed` after `write_file`, `commit_upload`, `rename` and `delete`
+//! - `kv_updated` after `kv_set`, `kv_delete` and each key a `kv_merge` changed
+//! - `spoke_authorized` when a spoke is added (owner only)
+//! - `file_change` for `Topic::Files`: each created, modified, renamed or
+//!   deleted file under the followed prefix, with its version, straight from
+//!   the kosha's watchers (`Hub::watch_kosha`)
+//!
+//! The hub pings every `PING_INTERVAL`. A subscriber that falls more than
+//! `EVENT_BUFFER` events behind gets `missed` and should re-read what it
+//! keeps in sync.
+
+use crate::{AuditEntry, AuditResult, Hub, HubError};
+use fastn_kosha::{ChangeEvent, ChangeKind, Watcher};
+use axum::extract::ws::{Message, WebSocket};
+use fastn_net::{Audience, Canonicalization, FileChange, FileChangeKind, PushEvent, RejectedRequest, ReplayGuard, ResponseEnvelope, SecretKey, SignedRequest, SignedResponse, Subscribe, Topic};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::RwLock;
+
+/// Events kept for subscribers that are slow to read
+pub const EVENT_BUFFER: usize = 256;
+
+/// How often the hub pings subscribers, so idle connections stay open
+pub const PING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+impl Hub {
+    /// Receive every event the hub pushes, before any topic filtering
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<PushEvent> {
+        self.events.subscribe()
+    }
+
+    /// Push an event to the subscribers whose topics match
+    pub(crate) fn notify(&self, event: PushEvent) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    /// Check that a sender may follow a subscription's topics
+    ///
+    /// Everyone who may send requests (the owner's spokes, authorized hubs)
+    /// may follow existing koshas; only the owner's spokes hear about spokes.
+    pub async fn authorize_subscription(
+        &self,
+        sender_id52: &str,
+        subscribe: &Subscribe,
+    ) -> std::result::Result<(), HubError> {
+        let sender_identity = self
+            .identify_sender(sender_id52)
+            .await
+            .map_err(|_| HubError::Unauthorized)?;
+
+        for topic in &subscribe.topics {
+            match topic {
+                Topic::Spokes if !sender_identity.is_owner() => {
+                    return Err(HubError::AccessDenied {
+                        app: crate::admin_api::APP_NAME.to_string(),
+                        instance: "spokes".to_string(),
+                        trace: None,
+                    });
+                }
+                Topic::Spokes => {}
+                Topic::Kosha { instance } => {
+                    self.watch_kosha(instance, "").await?;
+                }
+                Topic::Files { instance, path_prefix } => {
+                    self.watch_kosha(instance, path_prefix).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a subscription in the audit log, as app `push`, command
+    /// `subscribe`, with the followed instances
+    async fn audit_subscription<T>(
+        &self,
+        sender_id52: &str,
+        client: Option<IpAddr>,
+        subscribe: &Subscribe,
+        result: &std::result::Result<T, HubError>,
+        started: std::time::Instant,
+    ) {
+        let mut instances: Vec<&str> = subscribe
+            .topics
+            .iter()
+            .map(|topic| match topic {
+                Topic::Spokes => "spokes",
+                Topic::Kosha { instance } | Topic::Files { instance, .. } => instance,
+            })
+            .collect();
+        instances.dedup();
+        let entry = AuditEntry {
+            time: chrono::Utc::now(),
+            sender: sender_id52.to_string(),
+            client,
+            target_hub: "self".to_string(),
+            app: "push".to_string(),
+            instance: instances.join(","),
+            command: "subscribe".to_string(),
+            path: None,
+            result: AuditResult::Ok,
+            error: None,
+            duration_us: 0,
+        };
+        self.audit.record(&entry.finish(result, started)).await;
+    }
+
+    /// Follow file changes under `path_prefix` in a kosha (see
+    /// `fastn_kosha::Kosha::watch`)
+    pub async fn watch_kosha(&self, alias: &str, path_prefix: &str) -> std::result::Result<Watcher, HubError> {
+        let kosha = self
+            .get_kosha(alias)
+            .await
+            .map_err(|e| HubError::AppError { message: e.to_string() })?
+            .ok_or_else(|| HubError::InstanceNotFound {
+                app: "kosha".to_string(),
+                instance: alias.to_string(),
+            })?;
+        kosha
+            .watch(path_prefix)
+            .map_err(|e| HubError::AppError { message: e.to_string() })
+    }
+
+    /// Events for a kosha command, pushed once it succeeds
+    ///
+    /// `kv_merge` and directory operations aren't covered: what they change
+    /// is only known from their response (see `response_events`).
+    pub(crate) fn change_events(kosha: &str, command: &str, payload: &serde_json::Value) -> Vec<PushEvent> {
+        let field = |name: &str| payload.get(name).and_then(|v| v.as_str()).map(str::to_string);
+        let (fields, file): (&[&str], bool) = match command {
+            "write_file" | "commit_upload" | "delete" => (&["path"], true),
+            "rename" => (&["from", "to"], true),
+            "kv_set" | "kv_delete" => (&["key"], false),
+            _ => return vec![],
+        };
+        fields
+            .iter()
+            .filter_map(|name| field(name))
+            .map(|value| match file {
+                true => PushEvent::FileChanged {
+                    kosha: kosha.to_string(),
+                    path: value,
+                },
+                false => PushEvent::KvUpdated {
+                    kosha: kosha.to_string(),
+                    key: value,
+                },
+            })
+            .collect()
+    }
+
+    /// Events for a kosha command that are only known from its response:
+    /// the keys `kv_merge` changed, and the files a directory operation
+    /// touched
+    pub(crate) fn response_events(kosha: &str, command: &str, response: &serde_json::Value) -> Vec<PushEvent> {
+        let strings = |field: &str| -> Vec<String> {
+            let values = response[field].as_array().into_iter().flatten();
+            values.filter_map(|v| v.as_str()).map(str::to_string).collect()
+        };
+        let file = |path: String| PushEvent::FileChanged {
+            kosha: kosha.to_string(),
+            path,
+        };
+        match command {
+            "kv_merge" => strings("changed")
+                .into_iter()
+                .map(|key| PushEvent::KvUpdated {
+                    kosha: kosha.to_string(),
+                    key,
+                })
+                .collect(),
+            "delete_dir" => strings("deleted").into_iter().map(file).collect(),
+            "copy" => strings("copied").into_iter().map(file).collect(),
+            "move" => {
+                let moved = response["moved"].as_array().into_iter().flatten();
+                moved
+                    .flat_map(|m| [&m["from"], &m["to"]])
+                    .filter_map(|path| path.as_str())
+                    .map(|path| file(path.to_string()))
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Wire form of a kosha change
+pub(crate) fn file_change(kosha: &str, event: ChangeEvent) -> PushEvent {
+    let kind = match event.kind {
+        ChangeKind::Created => FileChangeKind::Created,
+        ChangeKind::Modified => FileChangeKind::Modified,
+        ChangeKind::Deleted => FileChangeKind::Deleted,
+        ChangeKind::Renamed { from } => FileChangeKind::Renamed { from },
+    };
+    PushEvent::FileChange {
+        kosha: kosha.to_string(),
+        change: FileChange {
+            path: event.path,
+            kind,
+            version: event.version.timestamp.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+            size: event.version.size,
+        },
+    }
+}
+
+/// Forward a kosha's changes into a session until it ends
+fn forward_changes(
+    kosha: String,
+    mut watcher: Watcher,
+    changes: tokio::sync::mpsc::Sender<PushEvent>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let event = match watcher.next().await {
+                Ok(Some(event)) => file_change(&kosha, event),
+                Ok(None) => break,
+                Err(fastn_kosha::Error::WatchLagged(count)) => PushEvent::Missed { count },
+                Err(_) => break,
+            };
+            if changes.send(event).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Serve one WebSocket connection: the subscription handshake, then events
+/// until either side goes away; `client` is its address, for the audit log
+pub(crate) async fn run_session(
+    hub: Arc<RwLock<Hub>>,
+    mut socket: WebSocket,
+    secret_key: SecretKey,
+    replay_guard: Arc<ReplayGuard>,
+    audience: Arc<Audience>,
+    client: Option<IpAddr>,
+) {
+    let (subscribe, canonicalization, mut events, watchers) = match handshake(&hub, &mut socket, &secret_key, &replay_guard, &audience, client).await {
+        Ok(Some(accepted)) => accepted,
+        Ok(None) => {
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+        Err(e) => {
+            tracing::debug!("Push subscription failed: {}", e);
+            return;
+        }
+    };
+    // Counted as a subscription until the session ends
+    let _subscription = hub.read().await.metrics.subscribe();
+    // One watcher per kosha; the session filters by prefix, so overlapping
+    // topics don't repeat changes
+    let (changes_tx, mut changes) = tokio::sync::mpsc::channel(EVENT_BUFFER);
+    let forwarders: Vec<_> = watchers
+        .into_iter()
+        .map(|(kosha, watcher)| forward_changes(kosha, watcher, changes_tx.clone()))
+        .collect();
+    drop(changes_tx);
+    let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+
+    let result: fastn_net::Result<()> = async {
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) if event.matches(&subscribe.topics) => send_signed(&mut socket, &secret_key, canonicalization, &event).await?,
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(count)) => send_signed(&mut socket, &secret_key, canonicalization, &PushEvent::Missed { count }).await?,
+                    Err(RecvError::Closed) => return send(&mut socket, Message::Close(None)).await,
+                },
+                Some(change) = changes.recv() => match change {
+                    PushEvent::Missed { .. } => send_signed(&mut socket, &secret_key, canonicalization, &change).await?,
+                    change if change.matches(&subscribe.topics) => send_signed(&mut socket, &secret_key, canonicalization, &change).await?,
+                    _ => {}
+                },
+                // Reading also answers the subscriber's pings
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(ws_error(e)),
+                },
+                _ = ping_interval.tick() => send(&mut socket, Message::Ping(Default::default())).await?,
+            }
+        }
+    }
+    .await;
+    forwarders.iter().for_each(|forwarder| forwarder.abort());
+    if let Err(e) = result {
+        tracing::debug!("Push session ended: {}", e);
+    }
+}
+
+/// Read and check the subscription request, and answer it. Returns the
+/// subscription, its events and kosha watchers (all from before the answer,
+/// so none are lost), or None if the subscription was refused.
+async fn handshake(
+    hub: &RwLock<Hub>,
+    socket: &mut WebSocket,
+    secret_key: &SecretKey,
+    replay_guard: &ReplayGuard,
+    audience: &Audience,
+    client: Option<IpAddr>,
+) -> fastn_net::Result<Option<Accepted>> {
+    let text = loop {
+        match socket.recv().await {
+            Some(Ok(Message::Text(text))) => break text,
+            Some(Ok(Message::Close(_))) | None => return Ok(None),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(ws_error(e)),
+        }
+    };
+
+    // Same checks as for requests: signature, audience, then replays
+    let signed_req: SignedRequest = serde_json::from_str(&text)?;
+    let verified = signed_req
+        .verify()
+        .and_then(|r| signed_req.check_audience(audience).map(|()| r))
+        .and_then(|r| replay_guard.check(&signed_req).map(|()| r));
+    let (sender_id52, subscribe): (String, Subscribe) = match verified {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::warn!("Subscription verification failed: {}", e);
+            let rejected = serde_json::to_string(&RejectedRequest::from(&e))?;
+            send(socket, Message::Text(rejected.into())).await?;
+            return Ok(None);
+        }
+    };
+
+    let result = {
+        let hub = hub.read().await;
+        let started = std::time::Instant::now();
+        let result = accept(&hub, &sender_id52, &subscribe).await;
+        hub.audit_subscription(&sender_id52, client, &subscribe, &result, started).await;
+        result
+    };
+    let (envelope, accepted): (ResponseEnvelope<(), HubError>, _) = match result {
+        Ok((events, watchers)) => (
+            ResponseEnvelope::Ok(()),
+            Some((subscribe, signed_req.canonicalization(), events, watchers)),
+        ),
+        Err(err) => (ResponseEnvelope::Err(err), None),
+    };
+    send_signed(socket, secret_key, signed_req.canonicalization(), &envelope).await?;
+    Ok(accepted)
+}
+
+/// A subscription, how to sign what it's sent, its hub events and a watcher
+/// per followed kosha
+type Accepted = (
+    Subscribe,
+    Canonicalization,
+    tokio::sync::broadcast::Receiver<PushEvent>,
+    Vec<(String, Watcher)>,
+);
+
+async fn accept(
+    hub: &Hub,
+    sender_id52: &str,
+    subscribe: &Subscribe,
+) -> std::result::Result<(tokio::sync::broadcast::Receiver<PushEvent>, Vec<(String, Watcher)>), HubError> {
+    hub.authorize_subscription(sender_id52, subscribe).await?;
+    let events = hub.subscribe_events();
+    let mut watchers: Vec<(String, Watcher)> = vec![];
+    for topic in &subscribe.topics {
+        if let Topic::Files { instance, .. } = topic
+            && !watchers.iter().any(|(kosha, _)| kosha == instance)
+        {
+            watchers.push((instance.clone(), hub.watch_kosha(instance, "").await?));
+        }
+    }
+    Ok((events, watchers))
+}
+
+async fn send_signed<T: Serialize>(
+    socket: &mut WebSocket,
+    secret_key: &SecretKey,
+    canonicalization: Canonicalization,
+    payload: &T,
+) -> fastn_net::Result<()> {
+    let signed = SignedResponse::new_with(secret_key, payload, canonicalization)?;
+    send(socket, Message::Text(serde_json::to_string(&signed)?.into())).await
+}
+
+async fn send(socket: &mut WebSocket, message: Message) -> fastn_net::Result<()> {
+    socket.send(message).await.map_err(ws_error)
+}
+
+fn ws_error(e: axum::Error) -> fastn_net::Error {
+    fastn_net::Error::WebSocket(e.to_string())
+}
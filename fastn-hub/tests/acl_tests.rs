@@ -2,27 +2,15 @@
 //!
 //! Tests cross-hub authorization using .hubs files.
 
-use fastn_hub::{Hub, Request, HubError};
-use fastn_net::SecretKey;
-use std::path::PathBuf;
+mod common;
 
-/// Helper to create a test hub with its own temp directory
-async fn create_test_hub(name: &str, _port: u16) -> (Hub, PathBuf, String) {
-    let temp_dir = std::env::temp_dir().join(format!("fastn-acl-test-{}-{}", name, std::process::id()));
-
-    // Clean up any previous test directory
-    let _ = std::fs::remove_dir_all(&temp_dir);
-    std::fs::create_dir_all(&temp_dir).expect("Failed to create test directory");
-
-    // Use init to explicitly pass the path instead of relying on env var
-    let hub = Hub::init(temp_dir.clone()).await.expect("Failed to init hub");
-    let id52 = hub.id52().to_string();
-
-    (hub, temp_dir, id52)
-}
+use common::create_test_hub;
+use fastn_hub::{AccessContext, AccessResult, AclLimits, Error, Hub, HubAuthResolver, HubError, Request};
+use fastn_net::{AclDecision, SecretKey};
+use std::path::Path;
 
 /// Helper to write a .hubs file
-async fn write_hubs_file(hub_dir: &PathBuf, filename: &str, content: &str) {
+async fn write_hubs_file(hub_dir: &Path, filename: &str, content: &str) {
     let hubs_dir = hub_dir.join("koshas/root/files/hubs");
     tokio::fs::create_dir_all(&hubs_dir).await.expect("Failed to create hubs dir");
     let file_path = hubs_dir.join(filename);
@@ -30,7 +18,7 @@ async fn write_hubs_file(hub_dir: &PathBuf, filename: &str, content: &str) {
 }
 
 /// Helper to write a test file in the root kosha
-async fn write_test_file(hub_dir: &PathBuf, filename: &str, content: &str) {
+async fn write_test_file(hub_dir: &Path, filename: &str, content: &str) {
     let files_dir = hub_dir.join("koshas/root/files");
     tokio::fs::create_dir_all(&files_dir).await.expect("Failed to create files dir");
     let file_path = files_dir.join(filename);
@@ -38,7 +26,7 @@ async fn write_test_file(hub_dir: &PathBuf, filename: &str, content: &str) {
 }
 
 /// Helper to add a spoke to the hub's authorized spokes list
-async fn authorize_spoke(hub_dir: &PathBuf, spoke_id52: &str, alias: &str) {
+async fn authorize_spoke(hub_dir: &Path, spoke_id52: &str, alias: &str) {
     let files_dir = hub_dir.join("koshas/root/files");
     tokio::fs::create_dir_all(&files_dir).await.expect("Failed to create files dir");
     let spokes_file = files_dir.join("spokes.txt");
@@ -55,7 +43,7 @@ async fn authorize_spoke(hub_dir: &PathBuf, spoke_id52: &str, alias: &str) {
 async fn test_spoke_access_own_hub() {
     // Test: A spoke should be able to read files from its own hub
 
-    let (mut hub, hub_dir, _hub_id52) = create_test_hub("own-hub", 4000).await;
+    let (mut hub, hub_dir) = create_test_hub("own-hub").await;
 
     // Create a spoke key
     let spoke_key = SecretKey::generate();
@@ -75,6 +63,7 @@ async fn test_spoke_access_own_hub() {
         instance: "root".to_string(),
         command: "read_file".to_string(),
         payload: serde_json::json!({ "path": "hello.txt" }),
+        explain: false,
     };
 
     // Handle the request - sender identity derived from spoke_id52
@@ -102,7 +91,7 @@ async fn test_cross_hub_access_authorized() {
     // Test: Hub1 (forwarding for its spoke) should be able to read files from Hub2 when Hub1 is authorized
     // In the new design, cross-hub requests are signed by the forwarding hub, so we verify hub1's identity
 
-    let (hub2, hub2_dir, _hub2_id52) = create_test_hub("cross-hub2", 4002).await;
+    let (hub2, hub2_dir) = create_test_hub("cross-hub2").await;
 
     // Create Hub1's identity (we just need the ID52 for authorization)
     let hub1_key = SecretKey::generate();
@@ -127,6 +116,7 @@ async fn test_cross_hub_access_authorized() {
         instance: "root".to_string(),
         command: "read_file".to_string(),
         payload: serde_json::json!({ "path": "secret.txt" }),
+        explain: false,
     };
 
     // Handle the request at Hub2
@@ -152,7 +142,7 @@ async fn test_cross_hub_access_denied() {
     // Test: An unauthorized hub should be denied access
     // In the new design, the sender identity is verified from the signature
 
-    let (hub2, hub2_dir, _hub2_id52) = create_test_hub("deny-hub2", 4004).await;
+    let (hub2, hub2_dir) = create_test_hub("deny-hub2").await;
 
     // Create an unauthorized hub's identity
     let unauthorized_hub_key = SecretKey::generate();
@@ -171,6 +161,7 @@ async fn test_cross_hub_access_denied() {
         instance: "root".to_string(),
         command: "read_file".to_string(),
         payload: serde_json::json!({ "path": "protected.txt" }),
+        explain: false,
     };
 
     // Handle the request at Hub2
@@ -195,7 +186,7 @@ async fn test_cross_hub_access_denied() {
 async fn test_hub_forwarding_lookup() {
     // Test: Hub should be able to look up another hub by alias from .hubs files
 
-    let (hub, hub_dir, _hub_id52) = create_test_hub("forwarding", 4006).await;
+    let (hub, hub_dir) = create_test_hub("forwarding").await;
 
     // Create a remote hub's identity
     let remote_hub_key = SecretKey::generate();
@@ -225,11 +216,49 @@ async fn test_hub_forwarding_lookup() {
     let _ = std::fs::remove_dir_all(&hub_dir);
 }
 
+#[tokio::test]
+async fn test_hub_forwarding_address() {
+    let (hub, hub_dir) = create_test_hub("address").await;
+
+    let mesh_id52 = SecretKey::generate().public().id52();
+    let both_id52 = SecretKey::generate().public().id52();
+    let hubs_content = format!(
+        "{}: mesh-hub iroh:mesh-endpoint\n\
+         {}: both-hub http://localhost:4009 iroh:both-endpoint  # reachable either way\n",
+        mesh_id52, both_id52
+    );
+    write_hubs_file(&hub_dir, "known.hubs", &hubs_content).await;
+
+    let mesh = hub.lookup_hub_by_alias("mesh-hub").await.unwrap().unwrap();
+    assert_eq!(mesh.address().url, None);
+    assert_eq!(mesh.address().iroh.as_deref(), Some("mesh-endpoint"));
+
+    let both = hub.lookup_hub_by_alias("both-hub").await.unwrap().unwrap();
+    assert_eq!(both.address().url.as_deref(), Some("http://localhost:4009"));
+    assert_eq!(both.address().iroh.as_deref(), Some("both-endpoint"));
+
+    // Only HTTP is built in, so the iroh-only hub can't be reached
+    let request = Request {
+        target_hub: "mesh-hub".to_string(),
+        app: "kosha".to_string(),
+        instance: "root".to_string(),
+        command: "read_file".to_string(),
+        payload: serde_json::json!({ "path": "secret.txt" }),
+        explain: false,
+    };
+    match hub.forward_request(&mesh, request).await {
+        Err(HubError::AppError { message }) => assert!(message.contains("No transport"), "{}", message),
+        other => panic!("Expected a transport error, got {:?}", other),
+    }
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
 #[tokio::test]
 async fn test_is_hub_authorized() {
     // Test: Hub should correctly report authorization status
 
-    let (hub, hub_dir, _hub_id52) = create_test_hub("auth-check", 4008).await;
+    let (hub, hub_dir) = create_test_hub("auth-check").await;
 
     // Create two hubs - one authorized, one not
     let authorized_hub_key = SecretKey::generate();
@@ -258,3 +287,544 @@ async fn test_is_hub_authorized() {
     // Cleanup
     let _ = std::fs::remove_dir_all(&hub_dir);
 }
+
+#[tokio::test]
+async fn test_owner_explain_access_denied() {
+    // Test: An owner asking for an explanation gets the ACL evaluation trace
+    // showing how a non-owner request would be decided
+
+    let (_hub, hub_dir) = create_test_hub("explain").await;
+
+    let spoke_key = SecretKey::generate();
+    let spoke_id52 = spoke_key.public().id52();
+    authorize_spoke(&hub_dir, &spoke_id52, "laptop").await;
+    write_test_file(&hub_dir, "notes.txt", "Owner notes").await;
+
+    // Reload so spokes.txt is picked up
+    let hub = Hub::load(&hub_dir).await.expect("Failed to load hub");
+
+    let request = Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: "root".to_string(),
+        command: "read_file".to_string(),
+        payload: serde_json::json!({ "path": "notes.txt" }),
+        explain: true,
+    };
+
+    let result = hub.handle_request(&spoke_id52, request).await;
+
+    // No ACL modules exist, so a non-owner would be denied
+    match result.unwrap_err() {
+        HubError::AccessDenied { app, instance, trace } => {
+            assert_eq!(app, "kosha");
+            assert_eq!(instance, "root");
+            let trace = trace.expect("Owner should receive the ACL trace");
+            assert_eq!(trace.decision, Some(AclDecision::Denied));
+            assert!(trace.steps.iter().all(|s| s.decision == AclDecision::NoModule));
+            assert!(trace.steps.iter().any(|s| s.file == "kosha/root/_read.wasm"));
+            assert!(trace.steps.iter().any(|s| s.file == "kosha/root/_access.wasm"));
+        }
+        other => panic!("Expected AccessDenied error, got: {:?}", other),
+    }
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+/// Helper to compile a WAT module into the root kosha
+async fn write_wasm_module(hub_dir: &Path, path: &str, wat_source: &str) {
+    let wasm = wat::parse_str(wat_source).expect("Invalid WAT");
+    let file_path = hub_dir.join("koshas/root/files").join(path);
+    tokio::fs::create_dir_all(file_path.parent().unwrap()).await.expect("Failed to create dir");
+    tokio::fs::write(&file_path, wasm).await.expect("Failed to write WASM module");
+}
+
+/// ACL module that returns a constant decision
+fn constant_acl(allow: bool) -> String {
+    format!(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 1024)
+            (func (export "allowed") (param i32 i32) (result i32) i32.const {}))"#,
+        allow as i32
+    )
+}
+
+/// ACL module that only allows contexts containing `"command":"read_file"`
+const READ_ONLY_ACL: &str = r#"(module
+    (memory (export "memory") 1)
+    (data (i32.const 0) "\"command\":\"read_file\"")
+    (func (export "alloc") (param i32) (result i32) i32.const 1024)
+    (func (export "allowed") (param $ptr i32) (param $len i32) (result i32)
+        (local $i i32) (local $j i32)
+        (block $done
+            (loop $outer
+                (br_if $done (i32.gt_s (i32.add (local.get $i) (i32.const 21)) (local.get $len)))
+                (local.set $j (i32.const 0))
+                (block $mismatch
+                    (loop $inner
+                        (br_if $mismatch (i32.ne
+                            (i32.load8_u (i32.add (i32.add (local.get $ptr) (local.get $i)) (local.get $j)))
+                            (i32.load8_u (local.get $j))))
+                        (local.set $j (i32.add (local.get $j) (i32.const 1)))
+                        (br_if $inner (i32.lt_u (local.get $j) (i32.const 21))))
+                    (return (i32.const 1)))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $outer)))
+        (i32.const 0)))"#;
+
+/// ACL module whose `allowed` never returns
+const LOOPING_ACL: &str = r#"(module
+    (memory (export "memory") 1)
+    (func (export "alloc") (param i32) (result i32) i32.const 1024)
+    (func (export "allowed") (param i32 i32) (result i32)
+        (loop $forever (br $forever))
+        (i32.const 1)))"#;
+
+/// Access context for a request from an unknown remote hub
+fn remote_context(hub: &Hub, command: &str) -> AccessContext {
+    AccessContext {
+        requester_hub_id: "remote-hub".to_string(),
+        current_hub_id: hub.id52().to_string(),
+        spoke_id52: "remote-spoke".to_string(),
+        app: "kosha".to_string(),
+        instance: "root".to_string(),
+        command: command.to_string(),
+        path: Some("notes.txt".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_wasm_acl_receives_access_context() {
+    // Test: The module sees the AccessContext JSON and decides on it
+
+    let (hub, hub_dir) = create_test_hub("wasm-context").await;
+    write_wasm_module(&hub_dir, "_access.wasm", READ_ONLY_ACL).await;
+
+    let read = hub.check_access(&remote_context(&hub, "read_file")).await;
+    assert!(matches!(read, AccessResult::Allowed), "got {:?}", read);
+
+    match hub.check_access(&remote_context(&hub, "write_file")).await {
+        AccessResult::Denied(reason) => assert_eq!(reason, "Denied by _access.wasm"),
+        other => panic!("Expected Denied, got: {:?}", other),
+    }
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_wasm_acl_category_module_takes_precedence() {
+    // Test: _read.wasm decides reads even when _access.wasm would deny
+
+    let (hub, hub_dir) = create_test_hub("wasm-category").await;
+    write_wasm_module(&hub_dir, "_read.wasm", &constant_acl(true)).await;
+    write_wasm_module(&hub_dir, "_access.wasm", &constant_acl(false)).await;
+
+    let read = hub.check_access(&remote_context(&hub, "read_file")).await;
+    assert!(matches!(read, AccessResult::Allowed), "got {:?}", read);
+    let write = hub.check_access(&remote_context(&hub, "write_file")).await;
+    assert!(matches!(write, AccessResult::Denied(_)), "got {:?}", write);
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_wasm_acl_recompiles_changed_module() {
+    // Test: Replacing a module takes effect even though the old one is cached
+
+    let (hub, hub_dir) = create_test_hub("wasm-cache").await;
+    let ctx = remote_context(&hub, "read_file");
+
+    write_wasm_module(&hub_dir, "kosha/_access.wasm", &constant_acl(true)).await;
+    assert!(matches!(hub.check_access(&ctx).await, AccessResult::Allowed));
+    assert!(matches!(hub.check_access(&ctx).await, AccessResult::Allowed));
+
+    write_wasm_module(&hub_dir, "kosha/_access.wasm", &constant_acl(false)).await;
+    match hub.check_access(&ctx).await {
+        AccessResult::Denied(reason) => assert_eq!(reason, "Denied by kosha/_access.wasm"),
+        other => panic!("Expected Denied, got: {:?}", other),
+    }
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_wasm_acl_fuel_limit_denies() {
+    // Test: A module that runs out of fuel is treated as a deny
+
+    let (mut hub, hub_dir) = create_test_hub("wasm-fuel").await;
+    hub.set_acl_limits(AclLimits {
+        fuel: 100_000,
+        timeout: std::time::Duration::from_secs(30),
+        ..AclLimits::default()
+    });
+    write_wasm_module(&hub_dir, "_access.wasm", LOOPING_ACL).await;
+
+    match hub.check_access(&remote_context(&hub, "read_file")).await {
+        AccessResult::Denied(reason) => {
+            assert!(reason.contains("fuel limit exceeded"), "unexpected reason: {}", reason)
+        }
+        other => panic!("Expected Denied, got: {:?}", other),
+    }
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_wasm_acl_time_limit_denies() {
+    // Test: A module that exceeds its wall-clock deadline is treated as a deny
+
+    let (mut hub, hub_dir) = create_test_hub("wasm-timeout").await;
+    hub.set_acl_limits(AclLimits {
+        fuel: u64::MAX,
+        timeout: std::time::Duration::from_millis(20),
+        ..AclLimits::default()
+    });
+    write_wasm_module(&hub_dir, "_access.wasm", LOOPING_ACL).await;
+
+    match hub.check_access(&remote_context(&hub, "read_file")).await {
+        AccessResult::Denied(reason) => {
+            assert!(reason.contains("time limit exceeded"), "unexpected reason: {}", reason)
+        }
+        other => panic!("Expected Denied, got: {:?}", other),
+    }
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_wasm_acl_invalid_module_denies() {
+    // Test: Bytes that aren't a valid module deny instead of failing open
+
+    let (hub, hub_dir) = create_test_hub("wasm-invalid").await;
+    write_test_file(&hub_dir, "_access.wasm", "not a wasm module").await;
+
+    match hub.check_access(&remote_context(&hub, "read_file")).await {
+        AccessResult::Denied(reason) => {
+            assert!(reason.starts_with("ACL WASM error in _access.wasm"), "unexpected reason: {}", reason)
+        }
+        other => panic!("Expected Denied, got: {:?}", other),
+    }
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_db_acl_nearest_module_decides() {
+    // Test: The nearest _db.wasm guards db_* commands from other hubs
+
+    let (hub, hub_dir) = create_test_hub("db-acl").await;
+    let remote_key = SecretKey::generate();
+    let remote_id52 = remote_key.public().id52();
+    write_hubs_file(&hub_dir, "known.hubs", &format!("{}: remote\n", remote_id52)).await;
+    write_wasm_module(&hub_dir, "_db.wasm", &constant_acl(false)).await;
+    write_wasm_module(&hub_dir, "api/_db.wasm", &constant_acl(true)).await;
+
+    let db_request = |database: &str| Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: "root".to_string(),
+        command: "db_execute".to_string(),
+        payload: serde_json::json!({ "database": database, "sql": "CREATE TABLE t (x)" }),
+        explain: false,
+    };
+
+    let allowed = hub.handle_request(&remote_id52, db_request("api/users.sqlite3")).await;
+    assert!(allowed.is_ok(), "api/_db.wasm should allow: {:?}", allowed.err());
+
+    let denied = hub.handle_request(&remote_id52, db_request("users.sqlite3")).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_directory_operations_check_each_file() {
+    // Test: One locked file denies a directory operation that touches it
+
+    let (hub, hub_dir) = create_test_hub("tree-access").await;
+    write_wasm_module(&hub_dir, "_access.wasm", &constant_acl(true)).await;
+    write_wasm_module(&hub_dir, "docs/locked/_write.wasm", &constant_acl(false)).await;
+    let kosha = hub.get_kosha("root").await.unwrap().unwrap();
+    for path in ["docs/a.txt", "docs/locked/b.txt", "notes/c.txt"] {
+        kosha.write_file(path, b"content").await.unwrap();
+    }
+
+    let context = |command: &str, path: &str| AccessContext {
+        command: command.to_string(),
+        path: Some(path.to_string()),
+        ..remote_context(&hub, command)
+    };
+
+    // Deleting or moving docs/ would write in docs/locked/
+    match hub.check_tree_access(&kosha, &context("delete_dir", "docs"), None).await {
+        AccessResult::Denied(reason) => assert!(reason.starts_with("delete docs/locked/"), "got {}", reason),
+        other => panic!("Expected Denied, got: {:?}", other),
+    }
+    let moved = hub.check_tree_access(&kosha, &context("move", "docs"), Some("archive")).await;
+    assert!(matches!(moved, AccessResult::Denied(_)), "got {:?}", moved);
+
+    // So would moving notes/ into it; elsewhere is fine
+    let into = hub.check_tree_access(&kosha, &context("move", "notes"), Some("docs/locked/notes")).await;
+    assert!(matches!(into, AccessResult::Denied(_)), "got {:?}", into);
+    let elsewhere = hub.check_tree_access(&kosha, &context("move", "notes"), Some("archive/notes")).await;
+    assert!(matches!(elsewhere, AccessResult::Allowed), "got {:?}", elsewhere);
+
+    // Copying only reads the locked files
+    let copied = hub.check_tree_access(&kosha, &context("copy", "docs/locked/b.txt"), Some("b.txt")).await;
+    assert!(matches!(copied, AccessResult::Allowed), "got {:?}", copied);
+    let deleted = hub.check_tree_access(&kosha, &context("delete_dir", "notes"), None).await;
+    assert!(matches!(deleted, AccessResult::Allowed), "got {:?}", deleted);
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+/// Helper to compile a WAT module into a kosha, or remove it if `None`
+async fn set_wasm_module(hub_dir: &Path, kosha: &str, path: &str, wat_source: Option<&str>) {
+    let file_path = hub_dir.join("koshas").join(kosha).join("files").join(path);
+    match wat_source {
+        Some(wat_source) => {
+            let wasm = wat::parse_str(wat_source).expect("Invalid WAT");
+            tokio::fs::create_dir_all(file_path.parent().unwrap()).await.expect("Failed to create dir");
+            tokio::fs::write(&file_path, wasm).await.expect("Failed to write WASM module");
+        }
+        None => tokio::fs::remove_file(&file_path).await.expect("Failed to remove WASM module"),
+    }
+}
+
+#[tokio::test]
+async fn test_remote_hub_requests_check_every_acl_level() {
+    // Test: Requests from a known hub go through the cascading ACL, and a
+    // denial at any level denies them
+
+    let (hub, hub_dir) = create_test_hub("remote-levels").await;
+    let remote_id52 = SecretKey::generate().public().id52();
+    write_hubs_file(&hub_dir, "known.hubs", &format!("{}: remote\n", remote_id52)).await;
+    let notes = hub.create_kosha("notes").await.unwrap();
+    notes.write_file("docs/private/a.txt", b"secret").await.unwrap();
+
+    let request = |command: &str, payload: serde_json::Value| Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: "notes".to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    };
+    let read = || request("read_file", serde_json::json!({ "path": "docs/private/a.txt" }));
+    let write = || request("write_file", serde_json::json!({ "path": "docs/private/b.txt", "content": "aGk=" }));
+
+    // Without modules, being in a .hubs file is enough
+    assert!(hub.handle_request(&remote_id52, read()).await.is_ok());
+
+    let levels = [
+        ("root", "_access.wasm"),
+        ("root", "kosha/_access.wasm"),
+        ("root", "kosha/notes/_access.wasm"),
+        ("notes", "_access.wasm"),
+        ("notes", "docs/_access.wasm"),
+        ("notes", "docs/private/_access.wasm"),
+    ];
+    for (kosha, path) in levels {
+        set_wasm_module(&hub_dir, kosha, path, Some(&constant_acl(false))).await;
+        let denied = hub.handle_request(&remote_id52, read()).await;
+        assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "{}/{}: got {:?}", kosha, path, denied);
+
+        set_wasm_module(&hub_dir, kosha, path, Some(&constant_acl(true))).await;
+        let allowed = hub.handle_request(&remote_id52, read()).await;
+        assert!(allowed.is_ok(), "{}/{}: got {:?}", kosha, path, allowed.err());
+    }
+
+    // Every level now allows; one denial below them still denies
+    set_wasm_module(&hub_dir, "notes", "docs/private/_write.wasm", Some(&constant_acl(false))).await;
+    assert!(hub.handle_request(&remote_id52, read()).await.is_ok());
+    let denied = hub.handle_request(&remote_id52, write()).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+    assert!(notes.read_file("docs/private/b.txt").await.is_err());
+
+    // Commands without a path stop at the instance level
+    set_wasm_module(&hub_dir, "root", "kosha/notes/_access.wasm", Some(&constant_acl(false))).await;
+    let kv = hub.handle_request(&remote_id52, request("kv_get", serde_json::json!({ "key": "a" }))).await;
+    assert!(matches!(kv, Err(HubError::AccessDenied { .. })), "got {:?}", kv);
+    set_wasm_module(&hub_dir, "root", "kosha/notes/_access.wasm", None).await;
+    let kv = hub.handle_request(&remote_id52, request("kv_get", serde_json::json!({ "key": "a" }))).await;
+    assert!(!matches!(kv, Err(HubError::AccessDenied { .. })), "got {:?}", kv);
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_search_hits_check_read_acl() {
+    // Test: Search results leave out files the sender can't read
+
+    let (mut hub, hub_dir) = create_test_hub("search").await;
+    let remote_id52 = SecretKey::generate().public().id52();
+    write_hubs_file(&hub_dir, "known.hubs", &format!("{}: remote\n", remote_id52)).await;
+    let owner = SecretKey::generate().public().id52();
+    hub.add_spoke(&owner).await.unwrap();
+    let notes = hub.create_kosha("notes").await.unwrap();
+    notes.write_file("docs/plan.txt", b"garden plan").await.unwrap();
+    notes.write_file("docs/private/plan.txt", b"secret plan").await.unwrap();
+    set_wasm_module(&hub_dir, "notes", "docs/private/_read.wasm", Some(&constant_acl(false))).await;
+
+    let search = |path_prefix: &str| Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: "notes".to_string(),
+        command: "search".to_string(),
+        payload: serde_json::json!({ "query": "plan", "path_prefix": path_prefix }),
+        explain: false,
+    };
+    let paths = |response: fastn_hub::Response| -> Vec<String> {
+        let hits = response.payload["hits"].as_array().unwrap().clone();
+        let mut paths: Vec<String> = hits.iter().map(|hit| hit["path"].as_str().unwrap().to_string()).collect();
+        paths.sort();
+        paths
+    };
+
+    let remote = hub.handle_request(&remote_id52, search("docs")).await.unwrap();
+    assert_eq!(paths(remote), ["docs/plan.txt"]);
+    let owned = hub.handle_request(&owner, search("docs")).await.unwrap();
+    assert_eq!(paths(owned), ["docs/plan.txt", "docs/private/plan.txt"]);
+
+    let private = hub.handle_request(&remote_id52, search("docs/private")).await.unwrap();
+    assert!(paths(private).is_empty());
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_remote_hub_denied_without_modules_unless_known() {
+    // Test: The default allow only covers hubs in .hubs files; the owner
+    // skips the ACL modules altogether
+
+    let (mut hub, hub_dir) = create_test_hub("remote-default").await;
+    let hub_id52 = hub.id52().to_string();
+    let spoke_id52 = SecretKey::generate().public().id52();
+    hub.add_spoke(&spoke_id52).await.unwrap();
+    let remote_id52 = SecretKey::generate().public().id52();
+    write_hubs_file(&hub_dir, "known.hubs", &format!("{}: remote\n", remote_id52)).await;
+    write_test_file(&hub_dir, "notes.txt", "notes").await;
+
+    let ctx = |requester_hub_id: &str| AccessContext {
+        requester_hub_id: requester_hub_id.to_string(),
+        current_hub_id: hub_id52.clone(),
+        spoke_id52: String::new(),
+        app: "kosha".to_string(),
+        instance: "root".to_string(),
+        command: "read_file".to_string(),
+        path: Some("notes.txt".to_string()),
+    };
+    assert!(matches!(hub.check_access(&ctx(&remote_id52)).await, AccessResult::Allowed));
+    assert!(matches!(hub.check_access(&ctx("unknown-hub")).await, AccessResult::Denied(_)));
+
+    write_wasm_module(&hub_dir, "_access.wasm", &constant_acl(false)).await;
+    let read = Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: "root".to_string(),
+        command: "read_file".to_string(),
+        payload: serde_json::json!({ "path": "notes.txt" }),
+        explain: false,
+    };
+    let denied = hub.handle_request(&remote_id52, read.clone()).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+    assert!(hub.handle_request(&spoke_id52, read).await.is_ok());
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_alias_references_resolve_to_hubs() {
+    // Test: #alias names a hub defined in any .hubs file, or one another
+    // reference brought in under the includer's alias
+
+    let (hub, hub_dir) = create_test_hub("alias-ref").await;
+    let alice = SecretKey::generate().public().id52();
+    let bob = SecretKey::generate().public().id52();
+    write_hubs_file(&hub_dir, "friends.hubs", &format!("{}: alice http://alice.example\n{}: bob\n", alice, bob)).await;
+    write_hubs_file(&hub_dir, "trusted.hubs", "#alice   # just Alice\n").await;
+    write_hubs_file(&hub_dir, "group.hubs", "@inner\n").await;
+    write_hubs_file(&hub_dir, "inner.hubs", "#bob\n").await;
+    write_hubs_file(&hub_dir, "zz-chained.hubs", "#group\n").await;
+
+    let root = hub.get_kosha("root").await.unwrap().unwrap();
+    let all = HubAuthResolver::for_root(&root).resolve_all().await.unwrap();
+    assert!(all.iter().all(|h| h.id52 == alice || h.id52 == bob), "got {:?}", all);
+
+    let find = |source: &str| all.iter().find(|h| h.source_file == source).unwrap();
+    let trusted = find("hubs/trusted.hubs");
+    assert_eq!((trusted.id52.as_str(), trusted.alias.as_str()), (alice.as_str(), "alice"));
+    assert_eq!(trusted.url.as_deref(), Some("http://alice.example"));
+    let group = find("hubs/inner.hubs");
+    assert_eq!((group.id52.as_str(), group.alias.as_str()), (bob.as_str(), "group"));
+    let chained = find("hubs/zz-chained.hubs");
+    assert_eq!((chained.id52.as_str(), chained.alias.as_str()), (bob.as_str(), "group"));
+
+    // A non-root kosha can refer to hubs it includes from the root
+    let notes = hub.create_kosha("notes").await.unwrap();
+    notes.write_file("_hubs/allowed.hubs", b"@ROOT/friends\n#alice\n").await.unwrap();
+    let allowed = HubAuthResolver::for_kosha(&root, &notes).resolve_all().await.unwrap();
+    assert_eq!(allowed.last().unwrap().id52, alice);
+    assert_eq!(allowed.last().unwrap().alias, "alice");
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_alias_reference_errors() {
+    // Test: Unknown aliases and cycles of references are errors, and no
+    // hub is authorized while they are there
+
+    let (hub, hub_dir) = create_test_hub("alias-errors").await;
+    let alice = SecretKey::generate().public().id52();
+    write_hubs_file(&hub_dir, "friends.hubs", &format!("{}: alice\n", alice)).await;
+    write_hubs_file(&hub_dir, "trusted.hubs", "#nobody\n").await;
+    let root = hub.get_kosha("root").await.unwrap().unwrap();
+
+    match HubAuthResolver::for_root(&root).resolve_all().await {
+        Err(Error::HubAuth(message)) => {
+            assert_eq!(message, "Unknown hub alias #nobody in hubs/trusted.hubs")
+        }
+        other => panic!("Expected HubAuth error, got: {:?}", other),
+    }
+    let request = Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: "root".to_string(),
+        command: "list_dir".to_string(),
+        payload: serde_json::json!({ "path": "" }),
+        explain: false,
+    };
+    assert!(matches!(hub.handle_request(&alice, request).await, Err(HubError::Unauthorized)));
+
+    // a.hubs brings in b's reference as "a", c.hubs brings in d's as "c"
+    write_hubs_file(&hub_dir, "trusted.hubs", "@a\n").await;
+    write_hubs_file(&hub_dir, "a.hubs", "@b\n").await;
+    write_hubs_file(&hub_dir, "b.hubs", "#c\n").await;
+    write_hubs_file(&hub_dir, "c.hubs", "@d\n").await;
+    write_hubs_file(&hub_dir, "d.hubs", "#a\n").await;
+    match HubAuthResolver::for_root(&root).resolve_all().await {
+        Err(Error::HubAuth(message)) => assert_eq!(
+            message,
+            "Alias references form a cycle: #c (hubs/b.hubs) -> #a (hubs/d.hubs) -> #c (hubs/b.hubs)"
+        ),
+        other => panic!("Expected HubAuth error, got: {:?}", other),
+    }
+
+    // Cleanup
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
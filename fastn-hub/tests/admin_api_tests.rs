@@ -0,0 +1,303 @@
+//! Integration tests for the owner-only admin API (`app: "hub"`)
+
+mod common;
+
+use common::create_test_hub;
+use fastn_hub::{Hub, HubError, Request};
+use fastn_net::SecretKey;
+
+fn hub_request(command: &str, payload: serde_json::Value) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: "hub".to_string(),
+        instance: "self".to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    }
+}
+
+/// Authorize a spoke and return its ID52
+async fn add_owner_spoke(hub: &mut Hub) -> String {
+    let id52 = SecretKey::generate().public().id52();
+    hub.add_spoke(&id52).await.expect("Failed to add spoke");
+    id52
+}
+
+#[tokio::test]
+async fn test_describe_and_metrics() {
+    let (mut hub, hub_dir) = create_test_hub("describe").await;
+    let owner = add_owner_spoke(&mut hub).await;
+
+    let describe = hub.handle_request(&owner, hub_request("describe", serde_json::Value::Null)).await.unwrap();
+    assert_eq!(describe.payload["schema_version"], 1);
+    assert_eq!(describe.payload["hub_id52"], hub.id52());
+    assert!(describe.payload["commands"].as_array().unwrap().contains(&"list_koshas".into()));
+
+    // A denied request from an unknown spoke shows up in the counters
+    let stranger = SecretKey::generate().public().id52();
+    assert!(hub.handle_request(&stranger, hub_request("metrics", serde_json::Value::Null)).await.is_err());
+
+    let metrics = hub.handle_request(&owner, hub_request("metrics", serde_json::Value::Null)).await.unwrap();
+    assert_eq!(metrics.payload["schema_version"], 1);
+    assert_eq!(metrics.payload["requests_total"], 2);
+    assert_eq!(metrics.payload["requests_denied"], 1);
+    assert_eq!(metrics.payload["spokes"], 1);
+    assert_eq!(metrics.payload["koshas"], 1);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_list_spokes_paginates() {
+    let (mut hub, hub_dir) = create_test_hub("paginate").await;
+    let owner = add_owner_spoke(&mut hub).await;
+    for _ in 0..4 {
+        add_owner_spoke(&mut hub).await;
+    }
+
+    let first = hub
+        .handle_request(&owner, hub_request("list_spokes", serde_json::json!({ "limit": 2 })))
+        .await
+        .unwrap()
+        .payload;
+    assert_eq!(first["total"], 5);
+    assert_eq!(first["items"].as_array().unwrap().len(), 2);
+    assert_eq!(first["next_offset"], 2);
+
+    let last = hub
+        .handle_request(&owner, hub_request("list_spokes", serde_json::json!({ "offset": 4, "limit": 2 })))
+        .await
+        .unwrap()
+        .payload;
+    assert_eq!(last["items"].as_array().unwrap().len(), 1);
+    assert!(last["next_offset"].is_null());
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_list_koshas_includes_stats() {
+    let (mut hub, hub_dir) = create_test_hub("koshas").await;
+    let owner = add_owner_spoke(&mut hub).await;
+
+    let page = hub
+        .handle_request(&owner, hub_request("list_koshas", serde_json::Value::Null))
+        .await
+        .unwrap()
+        .payload;
+    let root = &page["items"][0];
+    assert_eq!(root["alias"], "root");
+    // add_spoke wrote spokes.txt into the root kosha
+    assert!(root["stats"]["file_count"].as_u64().unwrap() >= 1);
+    assert!(root["stats"]["file_bytes"].as_u64().unwrap() > 0);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_create_and_delete_koshas() {
+    let (mut hub, hub_dir) = create_test_hub("lifecycle").await;
+    let owner = add_owner_spoke(&mut hub).await;
+
+    let created = hub
+        .handle_request(&owner, hub_request("create_kosha", serde_json::json!({ "alias": "photos" })))
+        .await
+        .unwrap();
+    assert_eq!(created.payload["alias"], "photos");
+    assert!(hub_dir.join("koshas/photos/files").is_dir());
+
+    let duplicate = hub
+        .handle_request(&owner, hub_request("create_kosha", serde_json::json!({ "alias": "photos" })))
+        .await;
+    assert!(matches!(duplicate, Err(HubError::AppError { .. })), "got {:?}", duplicate);
+    let traversal = hub
+        .handle_request(&owner, hub_request("create_kosha", serde_json::json!({ "alias": "../escape" })))
+        .await;
+    assert!(matches!(traversal, Err(HubError::AppError { .. })), "got {:?}", traversal);
+
+    // The new kosha serves requests right away
+    let write = Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: "photos".to_string(),
+        command: "write_file".to_string(),
+        payload: serde_json::json!({ "path": "hello.txt", "content": "aGk=" }),
+        explain: false,
+    };
+    hub.handle_request(&owner, write.clone()).await.unwrap();
+
+    let page = hub
+        .handle_request(&owner, hub_request("list_koshas", serde_json::Value::Null))
+        .await
+        .unwrap()
+        .payload;
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["items"][0]["alias"], "photos");
+    assert_eq!(page["items"][0]["stats"]["file_count"], 1);
+
+    let root = hub
+        .handle_request(&owner, hub_request("delete_kosha", serde_json::json!({ "alias": "root" })))
+        .await;
+    assert!(matches!(root, Err(HubError::AppError { .. })), "got {:?}", root);
+
+    hub.handle_request(&owner, hub_request("delete_kosha", serde_json::json!({ "alias": "photos" })))
+        .await
+        .unwrap();
+    assert!(!hub_dir.join("koshas/photos").exists());
+    match hub.handle_request(&owner, write).await {
+        Err(HubError::InstanceNotFound { instance, .. }) => assert_eq!(instance, "photos"),
+        other => panic!("Expected InstanceNotFound, got: {:?}", other),
+    }
+    let missing = hub
+        .handle_request(&owner, hub_request("delete_kosha", serde_json::json!({ "alias": "photos" })))
+        .await;
+    assert!(matches!(missing, Err(HubError::InstanceNotFound { .. })), "got {:?}", missing);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_fork_kosha() {
+    let (mut hub, hub_dir) = create_test_hub("fork").await;
+    let owner = add_owner_spoke(&mut hub).await;
+    let photos = hub.create_kosha("photos").await.unwrap();
+    photos.write_file("cat.jpg", b"meow").await.unwrap();
+
+    let forked = hub
+        .handle_request(
+            &owner,
+            hub_request("fork_kosha", serde_json::json!({ "source": "photos", "alias": "photos-edit" })),
+        )
+        .await
+        .unwrap();
+    assert_eq!(forked.payload["alias"], "photos-edit");
+    let fork = hub.get_kosha("photos-edit").await.unwrap().expect("fork should be registered");
+    assert_eq!(fork.read_file("cat.jpg").await.unwrap(), b"meow");
+    fork.write_file("cat.jpg", b"purr").await.unwrap();
+    assert_eq!(photos.read_file("cat.jpg").await.unwrap(), b"meow");
+
+    let existing = hub
+        .handle_request(&owner, hub_request("fork_kosha", serde_json::json!({ "source": "photos", "alias": "root" })))
+        .await;
+    assert!(matches!(existing, Err(HubError::AppError { .. })), "got {:?}", existing);
+    let missing = hub
+        .handle_request(&owner, hub_request("fork_kosha", serde_json::json!({ "source": "videos", "alias": "copy" })))
+        .await;
+    assert!(matches!(missing, Err(HubError::InstanceNotFound { .. })), "got {:?}", missing);
+    assert!(!hub_dir.join("koshas/copy").exists());
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_export_kosha_download_and_import() {
+    use base64::Engine;
+    use sha2::{Digest, Sha256};
+
+    let (mut hub, hub_dir) = create_test_hub("export").await;
+    let owner = add_owner_spoke(&mut hub).await;
+    let photos = hub.create_kosha("photos").await.unwrap();
+    photos.write_file("cat.jpg", b"meow").await.unwrap();
+    photos.write_file("cat.jpg", b"purr").await.unwrap();
+    photos.kv_set("album", serde_json::json!("pets")).await.unwrap();
+
+    let export = hub
+        .handle_request(&owner, hub_request("export_kosha", serde_json::json!({ "alias": "photos" })))
+        .await
+        .unwrap()
+        .payload;
+    assert_eq!(export["alias"], "photos");
+    assert_eq!(export["manifest"]["alias"], "photos");
+    let export_id = export["export_id"].as_str().unwrap();
+    let size = export["size"].as_u64().unwrap();
+
+    // Download in small chunks, as a spoke would in 1 MiB ones
+    let mut snapshot = Vec::new();
+    while (snapshot.len() as u64) < size {
+        let chunk = hub
+            .handle_request(
+                &owner,
+                hub_request(
+                    "read_export",
+                    serde_json::json!({ "export_id": export_id, "offset": snapshot.len(), "length": 100 }),
+                ),
+            )
+            .await
+            .unwrap()
+            .payload;
+        assert_eq!(chunk["size"], size);
+        let content = base64::engine::general_purpose::STANDARD
+            .decode(chunk["content"].as_str().unwrap())
+            .unwrap();
+        assert!(!content.is_empty() && content.len() <= 100);
+        snapshot.extend(content);
+    }
+    let sha256: String = Sha256::digest(&snapshot).iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(export["sha256"], sha256);
+
+    let file = hub_dir.join("photos.tar.zst");
+    std::fs::write(&file, &snapshot).unwrap();
+    let restored = hub.import_kosha("photos-restored", &file).await.unwrap();
+    assert_eq!(restored.read_file("cat.jpg").await.unwrap(), b"purr");
+    assert_eq!(
+        restored.get_versions("cat.jpg").await.unwrap().len(),
+        photos.get_versions("cat.jpg").await.unwrap().len()
+    );
+    assert_eq!(restored.kv_get("album").await.unwrap(), Some(serde_json::json!("pets")));
+    assert!(matches!(
+        hub.import_kosha("photos", &file).await,
+        Err(fastn_hub::Error::KoshaExists(_))
+    ));
+
+    // Only exports can be read, and only existing koshas exported
+    let escape = hub
+        .handle_request(&owner, hub_request("read_export", serde_json::json!({ "export_id": "../hub.key" })))
+        .await;
+    assert!(matches!(escape, Err(HubError::InstanceNotFound { .. })), "got {:?}", escape);
+    let missing = hub
+        .handle_request(&owner, hub_request("export_kosha", serde_json::json!({ "alias": "videos" })))
+        .await;
+    assert!(matches!(missing, Err(HubError::InstanceNotFound { .. })), "got {:?}", missing);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_koshas_persist_across_restarts() {
+    let (hub, hub_dir) = create_test_hub("restart").await;
+    hub.create_kosha("notes").await.unwrap();
+    hub.get_kosha("notes").await.unwrap().unwrap().write_file("todo.txt", b"milk").await.unwrap();
+    drop(hub);
+
+    // A restarted hub finds the kosha on disk and opens it on first use
+    let hub = Hub::load(&hub_dir).await.unwrap();
+    assert_eq!(hub.list_koshas().await.unwrap(), vec!["notes", "root"]);
+    let notes = hub.get_kosha("notes").await.unwrap().expect("kosha should reopen");
+    assert_eq!(notes.read_file("todo.txt").await.unwrap(), b"milk");
+    assert!(hub.get_kosha("missing").await.unwrap().is_none());
+    assert!(hub.get_kosha("..").await.unwrap().is_none());
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_remote_hub_cannot_use_admin_api() {
+    let (hub, hub_dir) = create_test_hub("remote").await;
+
+    // Authorize another hub for cross-hub access
+    let remote_id52 = SecretKey::generate().public().id52();
+    let hubs_dir = hub_dir.join("koshas/root/files/hubs");
+    std::fs::create_dir_all(&hubs_dir).unwrap();
+    std::fs::write(hubs_dir.join("known.hubs"), format!("{}: friend\n", remote_id52)).unwrap();
+
+    match hub.handle_request(&remote_id52, hub_request("describe", serde_json::Value::Null)).await {
+        Err(HubError::AccessDenied { app, trace, .. }) => {
+            assert_eq!(app, "hub");
+            assert!(trace.is_none());
+        }
+        other => panic!("Expected AccessDenied, got: {:?}", other),
+    }
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
@@ -0,0 +1,163 @@
+//! Integration tests for the audit log
+
+mod common;
+
+use common::create_test_hub;
+use fastn_hub::{AuditQuery, AuditResult, Hub, Limits, Request};
+use fastn_net::SecretKey;
+
+/// Authorize a spoke and return its ID52
+async fn add_owner_spoke(hub: &mut Hub) -> String {
+    let id52 = SecretKey::generate().public().id52();
+    hub.add_spoke(&id52).await.expect("Failed to add spoke");
+    id52
+}
+
+fn request(app: &str, instance: &str, command: &str, payload: serde_json::Value) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: app.to_string(),
+        instance: instance.to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    }
+}
+
+fn write_file(path: &str, content: &[u8]) -> Request {
+    use base64::Engine;
+    let content = base64::engine::general_purpose::STANDARD.encode(content);
+    request("kosha", "docs", "write_file", serde_json::json!({ "path": path, "content": content }))
+}
+
+#[tokio::test]
+async fn test_requests_are_logged() {
+    let (mut hub, hub_dir) = create_test_hub("logged").await;
+    let owner = add_owner_spoke(&mut hub).await;
+    let stranger = SecretKey::generate().public().id52();
+    hub.create_kosha("docs").await.unwrap();
+
+    hub.handle_request(&owner, write_file("/notes/a.txt", b"hello")).await.unwrap();
+    hub.handle_request(&owner, request("kosha", "docs", "read_file", serde_json::json!({ "path": "notes/a.txt" })))
+        .await
+        .unwrap();
+    hub.handle_request(&owner, request("kosha", "missing", "list_dir", serde_json::json!({ "path": "" })))
+        .await
+        .unwrap_err();
+    hub.handle_request(&stranger, request("kosha", "docs", "read_file", serde_json::json!({ "path": "secret" })))
+        .await
+        .unwrap_err();
+
+    let all = hub.query_audit_log(&AuditQuery::default()).await.unwrap();
+    assert_eq!(all.len(), 4);
+    assert_eq!(all[0].sender, owner);
+    assert_eq!((all[0].app.as_str(), all[0].instance.as_str()), ("kosha", "docs"));
+    assert_eq!(all[0].command, "write_file");
+    assert_eq!(all[0].path.as_deref(), Some("/notes/a.txt"));
+    assert_eq!(all[0].result, AuditResult::Ok);
+    assert!(all[0].error.is_none());
+    assert_eq!(all[2].result, AuditResult::Failed);
+    assert!(all[2].error.as_deref().unwrap().contains("missing"));
+    assert_eq!(all[3].sender, stranger);
+    assert_eq!(all[3].result, AuditResult::Denied);
+    assert!(all.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+    // Filters
+    let by_stranger = AuditQuery {
+        sender: Some(stranger.clone()),
+        ..AuditQuery::default()
+    };
+    assert_eq!(hub.query_audit_log(&by_stranger).await.unwrap().len(), 1);
+    let notes = AuditQuery {
+        path_prefix: Some("notes/".to_string()),
+        ..AuditQuery::default()
+    };
+    assert_eq!(hub.query_audit_log(&notes).await.unwrap().len(), 2);
+    let reads = AuditQuery {
+        command: Some("read_file".to_string()),
+        result: Some(AuditResult::Ok),
+        ..AuditQuery::default()
+    };
+    assert_eq!(hub.query_audit_log(&reads).await.unwrap().len(), 1);
+    let last = AuditQuery {
+        limit: Some(2),
+        ..AuditQuery::default()
+    };
+    assert_eq!(hub.query_audit_log(&last).await.unwrap(), all[2..].to_vec());
+    let future = AuditQuery {
+        since: Some(chrono::Utc::now() + chrono::Duration::hours(1)),
+        ..AuditQuery::default()
+    };
+    assert!(hub.query_audit_log(&future).await.unwrap().is_empty());
+
+    // One JSON line per request
+    let log = std::fs::read_to_string(hub_dir.join("audit").join("audit.log")).unwrap();
+    assert_eq!(log.lines().count(), 4);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_rate_limited_requests_are_logged() {
+    let (mut hub, hub_dir) = create_test_hub("rate").await;
+    let owner = add_owner_spoke(&mut hub).await;
+    hub.set_limits(Limits {
+        requests_per_sec: Some(0.1),
+        burst: Some(1),
+        ..Limits::default()
+    })
+    .await
+    .unwrap();
+
+    let describe = || request("hub", "self", "describe", serde_json::Value::Null);
+    hub.handle_request(&owner, describe()).await.unwrap();
+    hub.handle_request(&owner, describe()).await.unwrap_err();
+
+    let results: Vec<AuditResult> = hub
+        .query_audit_log(&AuditQuery::default())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.result)
+        .collect();
+    assert_eq!(results, vec![AuditResult::Ok, AuditResult::RateLimited]);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_log_rotation() {
+    let (hub, hub_dir) = create_test_hub("rotation").await;
+    drop(hub);
+    let config_path = hub_dir.join("config.json");
+    let mut config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&config_path).unwrap()).unwrap();
+    config["audit"] = serde_json::json!({ "max_file_bytes": 600, "max_files": 2 });
+    std::fs::write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+    let mut hub = Hub::load(&hub_dir).await.unwrap();
+    let owner = add_owner_spoke(&mut hub).await;
+
+    let describe = || request("hub", "self", "describe", serde_json::Value::Null);
+    for _ in 0..20 {
+        hub.handle_request(&owner, describe()).await.unwrap();
+    }
+
+    let audit_dir = hub_dir.join("audit");
+    let mut rotated: Vec<String> = std::fs::read_dir(&audit_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name != "audit.log")
+        .collect();
+    rotated.sort();
+    assert_eq!(rotated.len(), 2, "{:?}", rotated);
+    assert!(rotated.iter().all(|name| name.starts_with("audit-") && name.ends_with(".log")));
+    for name in rotated.iter().map(String::as_str).chain(["audit.log"]) {
+        assert!(std::fs::metadata(audit_dir.join(name)).unwrap().len() <= 600);
+    }
+
+    // Queries read what is left, oldest first
+    let entries = hub.query_audit_log(&AuditQuery::default()).await.unwrap();
+    assert!(!entries.is_empty() && entries.len() < 20);
+    assert!(entries.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
@@ -0,0 +1,39 @@
+//! Helpers shared by the hub's integration tests
+
+// Each test file uses only some of them
+#![allow(dead_code)]
+
+use fastn_hub::Hub;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Helper to create a test hub with its own temp directory, named after the
+/// test file and `name`
+pub async fn create_test_hub(name: &str) -> (Hub, PathBuf) {
+    let temp_dir = std::env::temp_dir().join(format!(
+        "fastn-hub-{}-{}-{}",
+        env!("CARGO_CRATE_NAME"),
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    std::fs::create_dir_all(&temp_dir).expect("Failed to create test directory");
+    let hub = Hub::init(temp_dir.clone()).await.expect("Failed to init hub");
+    (hub, temp_dir)
+}
+
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Serve the hub on `port`, waiting for it to come up
+pub async fn serve(hub: Hub, port: u16) {
+    tokio::spawn(hub.serve(port));
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Hub did not come up");
+}
@@ -0,0 +1,214 @@
+//! Integration tests for the REST gateway and capability tokens
+//! (`fastn_hub::gateway`)
+
+mod common;
+
+use common::{create_test_hub, free_port, serve};
+use fastn_hub::{AuditQuery, Error, Hub, HubError, Limits, Request};
+
+fn kosha_request(instance: &str, command: &str, payload: serde_json::Value) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: instance.to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    }
+}
+
+#[tokio::test]
+async fn test_rest_gateway_reads_writes_and_deletes() {
+    let (mut hub, hub_dir) = create_test_hub("rest").await;
+    let notes = hub.create_kosha("notes").await.unwrap();
+    notes.write_file("docs/a.txt", b"# alpha").await.unwrap();
+    let (_, writer) = hub.create_token("sync", "notes", "", true, None).await.unwrap();
+    let (_, reader) = hub.create_token("viewer", "notes", "docs", false, None).await.unwrap();
+    hub.set_rest_gateway(true).await.unwrap();
+    let port = free_port();
+    serve(hub, port).await;
+
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("http://127.0.0.1:{}/api/kosha/notes/{}", port, path);
+
+    let response = client.get(url("docs/a.txt")).bearer_auth(&reader).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/plain");
+    assert!(response.headers().contains_key("last-modified"));
+    assert_eq!(response.bytes().await.unwrap().as_ref(), b"# alpha");
+
+    let response = client.put(url("docs/b.txt")).bearer_auth(&writer).body("# beta").send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(notes.read_file("docs/b.txt").await.unwrap(), b"# beta");
+
+    let response = client.get(url("docs/")).bearer_auth(&reader).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let listing: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(listing["entries"].as_array().unwrap().len(), 2);
+
+    let response = client.delete(url("docs/b.txt")).bearer_auth(&writer).send().await.unwrap();
+    assert_eq!(response.status(), 204);
+    assert!(notes.read_file("docs/b.txt").await.is_err());
+
+    // Missing and unknown tokens
+    let response = client.get(url("docs/a.txt")).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+    let response = client.get(url("docs/a.txt")).bearer_auth("fastn_nope").send().await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    // Outside the token's scope
+    let response = client.put(url("docs/c.txt")).bearer_auth(&reader).body("x").send().await.unwrap();
+    assert_eq!(response.status(), 403);
+    let response = client.get(url("")).bearer_auth(&reader).send().await.unwrap();
+    assert_eq!(response.status(), 403);
+    let other = format!("http://127.0.0.1:{}/api/kosha/root/", port);
+    let response = client.get(other).bearer_auth(&writer).send().await.unwrap();
+    assert_eq!(response.status(), 403);
+
+    let response = client.get(url("docs/missing.txt")).bearer_auth(&reader).send().await.unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client.post(url("docs/a.txt")).bearer_auth(&writer).send().await.unwrap();
+    assert_eq!(response.status(), 405);
+
+    // Revoking takes effect at once
+    let hub = Hub::load(&hub_dir).await.unwrap();
+    assert!(hub.revoke_token("viewer").await.unwrap());
+    let response = client.get(url("docs/a.txt")).bearer_auth(&reader).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_gateway_is_off_by_default() {
+    let (hub, hub_dir) = create_test_hub("off").await;
+    let (_, secret) = hub.create_token("sync", "root", "", true, None).await.unwrap();
+    assert!(!hub.rest_gateway());
+    let port = free_port();
+    serve(hub, port).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("http://127.0.0.1:{}/api/kosha/root/", port))
+        .bearer_auth(&secret)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_token_scope() {
+    let (hub, hub_dir) = create_test_hub("scope").await;
+    hub.create_kosha("notes").await.unwrap();
+    let (token, secret) = hub.create_token("docs", "notes", "/docs/", true, None).await.unwrap();
+    assert_eq!(token.path_prefix, "docs");
+    assert!(secret.starts_with(fastn_hub::gateway::TOKEN_PREFIX));
+    assert_eq!(hub.find_token(&secret).await.unwrap(), Some(token.clone()));
+    // Only the hash is kept
+    let saved = std::fs::read_to_string(hub_dir.join("tokens.json")).unwrap();
+    assert!(!saved.contains(&secret));
+
+    let write = |path: &str| {
+        kosha_request("notes", "write_file", serde_json::json!({ "path": path, "content": "aGk=" }))
+    };
+    hub.handle_token_request(&token, write("docs/a.txt")).await.unwrap();
+    hub.handle_token_request(&token, write("docs")).await.unwrap_err();
+    for denied in [
+        write("docsx/a.txt"),
+        write("docs/../a.txt"),
+        kosha_request("notes", "rename", serde_json::json!({ "from": "docs/a.txt", "to": "a.txt" })),
+        kosha_request("notes", "kv_get", serde_json::json!({ "key": "a" })),
+        kosha_request("root", "list_dir", serde_json::json!({ "path": "docs" })),
+    ] {
+        assert!(matches!(
+            hub.handle_token_request(&token, denied).await,
+            Err(HubError::AccessDenied { .. })
+        ));
+    }
+
+    // Names are unique and koshas must exist
+    assert!(matches!(
+        hub.create_token("docs", "notes", "", false, None).await,
+        Err(Error::TokenExists(_))
+    ));
+    assert!(matches!(
+        hub.create_token("bad name", "notes", "", false, None).await,
+        Err(Error::InvalidTokenName(_))
+    ));
+    assert!(hub.create_token("other", "missing", "", false, None).await.is_err());
+
+    // Expired tokens are refused
+    let expires_at = chrono::Utc::now() - chrono::Duration::seconds(1);
+    let (expired, _) = hub.create_token("old", "notes", "", false, Some(expires_at)).await.unwrap();
+    let list = kosha_request("notes", "list_dir", serde_json::json!({ "path": "" }));
+    assert!(matches!(
+        hub.handle_token_request(&expired, list).await,
+        Err(HubError::Unauthorized)
+    ));
+
+    assert_eq!(hub.list_tokens().await.unwrap().len(), 2);
+    assert!(hub.revoke_token("old").await.unwrap());
+    assert!(!hub.revoke_token("old").await.unwrap());
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_token_requests_are_rate_limited_and_audited() {
+    let (mut hub, hub_dir) = create_test_hub("limits").await;
+    let (token, _) = hub.create_token("busy", "root", "", false, None).await.unwrap();
+    hub.set_limits(Limits {
+        requests_per_sec: Some(0.1),
+        burst: Some(1),
+        ..Limits::default()
+    })
+    .await
+    .unwrap();
+
+    let list = || kosha_request("root", "list_dir", serde_json::json!({ "path": "" }));
+    hub.handle_token_request(&token, list()).await.unwrap();
+    assert!(matches!(
+        hub.handle_token_request(&token, list()).await,
+        Err(HubError::QuotaExceeded {
+            retry_after_ms: Some(_),
+            ..
+        })
+    ));
+
+    let by_token = AuditQuery {
+        sender: Some("token:busy".to_string()),
+        ..AuditQuery::default()
+    };
+    assert_eq!(hub.query_audit_log(&by_token).await.unwrap().len(), 2);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_token_cannot_read_other_koshas_through_mounts() {
+    let (hub, hub_dir) = create_test_hub("mounts").await;
+    let family = hub.create_kosha("family").await.unwrap();
+    let shared = hub.create_kosha("shared").await.unwrap();
+    shared.write_file("photos/beach.jpg", b"beach").await.unwrap();
+    family.write_file("photos-local/a.jpg", b"local").await.unwrap();
+    family
+        .write_file(fastn_kosha::MOUNTS_FILE, b"photos: shared/photos\nlatest: family/photos-local\n")
+        .await
+        .unwrap();
+    let (token, _) = hub.create_token("family", "family", "", false, None).await.unwrap();
+
+    let read = |path: &str| kosha_request("family", "read_file", serde_json::json!({ "path": path }));
+    let denied = hub.handle_token_request(&token, read("photos/beach.jpg")).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+    let listed = kosha_request("family", "list_dir", serde_json::json!({ "path": "photos" }));
+    let denied = hub.handle_token_request(&token, listed).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+
+    // Mounts within the token's kosha are fine
+    hub.handle_token_request(&token, read("latest/a.jpg")).await.unwrap();
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
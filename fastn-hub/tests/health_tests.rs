@@ -0,0 +1,49 @@
+//! Integration tests for the health endpoint and shutdown
+
+mod common;
+
+use common::create_test_hub;
+
+#[tokio::test]
+async fn test_health_reports_hub_state() {
+    let (hub, dir) = create_test_hub("state").await;
+    hub.create_kosha("photos").await.unwrap();
+
+    let health = hub.health().await.unwrap();
+    assert_eq!(health.status, "ok");
+    assert_eq!(health.hub_id52, hub.id52());
+    assert_eq!(health.version, env!("CARGO_PKG_VERSION"));
+    assert!(health.uptime_secs >= 0);
+    assert!(health.koshas.contains(&"photos".to_string()), "got {:?}", health.koshas);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_shutdown_rolls_back_open_transactions() {
+    let (hub, dir) = create_test_hub("shutdown").await;
+    let kosha = hub.create_kosha("notes").await.unwrap();
+    kosha
+        .db_execute("notes.sqlite3", "CREATE TABLE notes (body TEXT)", vec![])
+        .await
+        .unwrap();
+
+    let tx = kosha.db_begin("notes.sqlite3").await.unwrap();
+    kosha
+        .db_tx_execute(&tx, "INSERT INTO notes (body) VALUES ('draft')", vec![])
+        .await
+        .unwrap();
+
+    assert_eq!(hub.shutdown().await, 1);
+    assert!(kosha.db_transaction_database(&tx).is_none());
+
+    // The database isn't left locked
+    kosha
+        .db_execute("notes.sqlite3", "INSERT INTO notes (body) VALUES ('final')", vec![])
+        .await
+        .unwrap();
+    let rows = kosha.db_query("notes.sqlite3", "SELECT body FROM notes", vec![]).await.unwrap();
+    assert_eq!(rows, vec![serde_json::json!(["final"])]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
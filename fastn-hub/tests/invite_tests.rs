@@ -0,0 +1,116 @@
+//! Integration tests for spoke invitations (`fastn_hub::invite`)
+
+mod common;
+
+use common::{create_test_hub, free_port};
+use fastn_hub::{Error, Hub, HubError, Request, Response};
+use fastn_net::SecretKey;
+use std::time::Duration;
+
+fn join_request(token: &str, alias: &str) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: "hub".to_string(),
+        instance: "self".to_string(),
+        command: "join".to_string(),
+        payload: serde_json::json!({ "token": token, "alias": alias }),
+        explain: false,
+    }
+}
+
+#[tokio::test]
+async fn test_invite_is_single_use() {
+    let (mut hub, hub_dir) = create_test_hub("single-use").await;
+    let (invite, token) = hub.create_invite(chrono::Duration::minutes(10)).await.unwrap();
+    assert_eq!(hub.list_invites().await.unwrap(), vec![invite]);
+
+    let spoke = SecretKey::generate().public().id52();
+    let joined = hub.handle_join(&spoke, join_request(&token, "laptop")).await.unwrap();
+    assert_eq!(joined.payload["alias"], "laptop");
+    assert_eq!(joined.payload["hub_id52"], hub.id52());
+    assert!(hub.is_spoke_authorized(&spoke));
+    assert_eq!(hub.find_spoke(&spoke).unwrap().alias, "laptop");
+    assert!(hub.list_invites().await.unwrap().is_empty());
+
+    // The same token can't let anyone else in
+    let other = SecretKey::generate().public().id52();
+    let reused = hub.handle_join(&other, join_request(&token, "phone")).await;
+    assert!(matches!(reused, Err(HubError::AppError { .. })), "got {:?}", reused);
+    assert!(!hub.is_spoke_authorized(&other));
+
+    // Invites survive restarts, in the root kosha
+    let (_, token) = hub.create_invite(chrono::Duration::minutes(10)).await.unwrap();
+    drop(hub);
+    let mut hub = Hub::load(&hub_dir).await.unwrap();
+    assert_eq!(hub.list_invites().await.unwrap().len(), 1);
+    hub.handle_join(&other, join_request(&token, "phone")).await.unwrap();
+    assert!(hub.is_spoke_authorized(&other));
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_rejected_invites() {
+    let (mut hub, hub_dir) = create_test_hub("rejected").await;
+    let spoke = SecretKey::generate().public().id52();
+
+    let (_, expired) = hub.create_invite(chrono::Duration::seconds(-1)).await.unwrap();
+    assert!(matches!(hub.join_with_invite(&spoke, "laptop", &expired).await, Err(Error::InviteRejected(_))));
+
+    let (revoked, token) = hub.create_invite(chrono::Duration::minutes(10)).await.unwrap();
+    assert!(hub.revoke_invite(&revoked.id).await.unwrap());
+    assert!(!hub.revoke_invite(&revoked.id).await.unwrap());
+    assert!(matches!(hub.join_with_invite(&spoke, "laptop", &token).await, Err(Error::InviteRejected(_))));
+
+    // Signed by another hub, or tampered with
+    let (_, other_dir) = create_test_hub("rejected-other").await;
+    let other = Hub::load(&other_dir).await.unwrap();
+    let (_, foreign) = other.create_invite(chrono::Duration::minutes(10)).await.unwrap();
+    assert!(matches!(hub.join_with_invite(&spoke, "laptop", &foreign).await, Err(Error::InviteRejected(_))));
+    let (_, token) = hub.create_invite(chrono::Duration::minutes(10)).await.unwrap();
+    let flipped = if token.as_bytes()[60] == b'A' { "B" } else { "A" };
+    let tampered = format!("{}{}{}", &token[..60], flipped, &token[61..]);
+    assert!(matches!(hub.join_with_invite(&spoke, "laptop", &tampered).await, Err(Error::InviteRejected(_))));
+
+    // A bad alias doesn't use the invite up
+    assert!(matches!(hub.join_with_invite(&spoke, "My Laptop", &token).await, Err(Error::InviteRejected(_))));
+    hub.join_with_invite(&spoke, "laptop", &token).await.unwrap();
+    assert!(hub.is_spoke_authorized(&spoke));
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+    let _ = std::fs::remove_dir_all(&other_dir);
+}
+
+#[tokio::test]
+async fn test_join_over_http() {
+    let (hub, hub_dir) = create_test_hub("http").await;
+    let (_, token) = hub.create_invite(chrono::Duration::minutes(10)).await.unwrap();
+    let invite = fastn_net::InviteToken::verify(&token).unwrap();
+    assert_eq!(invite.hub_id52, hub.id52());
+
+    let port = free_port();
+    tokio::spawn(hub.serve(port));
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    // The spoke only knows the URL and the token
+    let key = SecretKey::generate();
+    let client = fastn_net::client::Client::new(key.clone(), invite.hub_id52, format!("http://127.0.0.1:{}", port));
+    let joined: Result<Response, HubError> = client.call(&join_request(&token, "laptop")).await.unwrap();
+    assert_eq!(joined.unwrap().payload["spoke_id52"], key.id52());
+
+    // Now it is the owner's spoke
+    let describe = Request {
+        command: "describe".to_string(),
+        payload: serde_json::Value::Null,
+        ..join_request("", "")
+    };
+    let described: Result<Response, HubError> = client.call(&describe).await.unwrap();
+    assert!(described.is_ok(), "got {:?}", described);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
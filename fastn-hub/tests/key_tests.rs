@@ -0,0 +1,85 @@
+//! Integration tests for hub.key protection and rotation (`fastn_hub::keys`)
+
+mod common;
+
+use common::create_test_hub;
+use fastn_hub::{Hub, HubError, Request, Response};
+use fastn_net::SecretKey;
+use fastn_net::key_file::{self, Protection};
+use std::time::Duration;
+
+fn describe() -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: "hub".to_string(),
+        instance: "self".to_string(),
+        command: "describe".to_string(),
+        payload: serde_json::Value::Null,
+        explain: false,
+    }
+}
+
+#[tokio::test]
+async fn test_protect_key() {
+    let (mut hub, hub_dir) = create_test_hub("protect").await;
+    let id52 = hub.id52().to_string();
+    assert_eq!(Hub::key_protection(&hub_dir).unwrap(), Protection::Plain);
+
+    hub.protect_key(Protection::Passphrase("hunter2".to_string())).unwrap();
+    assert_eq!(Hub::key_protection(&hub_dir).unwrap().name(), "passphrase");
+    let (key, _) = key_file::read(&hub_dir.join(fastn_hub::keys::KEY_FILE), Some("hunter2")).unwrap();
+    assert_eq!(key.id52(), id52);
+
+    // Loading without FASTN_KEY_PASSPHRASE says what's missing
+    let err = Hub::load(&hub_dir).await.err().expect("needs the passphrase");
+    assert!(matches!(err, fastn_hub::Error::Net(fastn_net::Error::PassphraseRequired(_))), "got {:?}", err);
+
+    hub.protect_key(Protection::Plain).unwrap();
+    let hub = Hub::load(&hub_dir).await.unwrap();
+    assert_eq!(hub.id52(), id52);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_rotate_key() {
+    let (mut hub, hub_dir) = create_test_hub("rotate").await;
+    let spoke = SecretKey::generate();
+    hub.add_spoke(&spoke.id52()).await.unwrap();
+    let old_id52 = hub.id52().to_string();
+
+    let rotation = hub.rotate_key().await.unwrap();
+    assert_eq!(rotation.old_id52, old_id52);
+    assert_eq!(hub.id52(), rotation.new_id52);
+    rotation.verify().unwrap();
+    assert!(hub_dir.join("retired-keys").join(format!("{}.key", old_id52)).exists());
+
+    // The new key and the rotation survive a restart
+    let hub = Hub::load(&hub_dir).await.unwrap();
+    assert_eq!(hub.id52(), rotation.new_id52);
+    assert_eq!(hub.key_rotations(), std::slice::from_ref(&rotation));
+    assert!(hub.is_spoke_authorized(&spoke.id52()));
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    tokio::spawn(hub.serve(port));
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let url = format!("http://127.0.0.1:{}", port);
+
+    // A spoke that still knows the old ID52 is told where the hub went
+    let client = fastn_net::client::Client::new(spoke.clone(), old_id52.clone(), url.clone());
+    match client.call::<_, Response, HubError>(&describe()).await {
+        Err(fastn_net::Error::HubKeyRotated(told)) => assert_eq!(*told, rotation),
+        other => panic!("expected a key rotation, got {:?}", other.map(|_| ())),
+    }
+
+    let client = fastn_net::client::Client::new(spoke, rotation.new_id52.clone(), url);
+    let described: Result<Response, HubError> = client.call(&describe()).await.unwrap();
+    assert!(described.is_ok(), "got {:?}", described);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
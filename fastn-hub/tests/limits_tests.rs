@@ -0,0 +1,120 @@
+//! Integration tests for request rate limits and kosha storage quotas
+
+mod common;
+
+use common::create_test_hub;
+use fastn_hub::{Hub, HubError, Limits, Request};
+use fastn_net::SecretKey;
+use std::collections::BTreeMap;
+
+/// Authorize a spoke and return its ID52
+async fn add_owner_spoke(hub: &mut Hub) -> String {
+    let id52 = SecretKey::generate().public().id52();
+    hub.add_spoke(&id52).await.expect("Failed to add spoke");
+    id52
+}
+
+fn request(app: &str, instance: &str, command: &str, payload: serde_json::Value) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: app.to_string(),
+        instance: instance.to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    }
+}
+
+fn write_file(path: &str, content: &[u8]) -> Request {
+    use base64::Engine;
+    let content = base64::engine::general_purpose::STANDARD.encode(content);
+    request("kosha", "docs", "write_file", serde_json::json!({ "path": path, "content": content }))
+}
+
+#[tokio::test]
+async fn test_rate_limit_per_identity() {
+    let (mut hub, hub_dir) = create_test_hub("rate").await;
+    let busy = add_owner_spoke(&mut hub).await;
+    let other = add_owner_spoke(&mut hub).await;
+    let trusted = add_owner_spoke(&mut hub).await;
+    hub.set_limits(Limits {
+        requests_per_sec: Some(0.5),
+        burst: Some(2),
+        identity_rates: BTreeMap::from([(trusted.clone(), None)]),
+        ..Limits::default()
+    })
+    .await
+    .unwrap();
+
+    let describe = || request("hub", "self", "describe", serde_json::Value::Null);
+    hub.handle_request(&busy, describe()).await.unwrap();
+    hub.handle_request(&busy, describe()).await.unwrap();
+    match hub.handle_request(&busy, describe()).await {
+        Err(HubError::QuotaExceeded { retry_after_ms: Some(ms), .. }) => assert!(ms > 1000 && ms <= 2000, "{}", ms),
+        other => panic!("expected a rate limit, got {:?}", other),
+    }
+
+    // Each identity has its own budget, and `null` lifts the limit
+    hub.handle_request(&other, describe()).await.unwrap();
+    for _ in 0..10 {
+        hub.handle_request(&trusted, describe()).await.unwrap();
+    }
+
+    let status = hub
+        .handle_request(&other, request("hub", "self", "quota_status", serde_json::Value::Null))
+        .await
+        .unwrap()
+        .payload;
+    let identities = status["identities"].as_array().unwrap();
+    let busy_status = identities.iter().find(|i| i["id52"] == busy.as_str()).unwrap();
+    assert_eq!(busy_status["requests_per_sec"], 0.5);
+    assert!(busy_status["available"].as_f64().unwrap() < 1.0);
+    let trusted_status = identities.iter().find(|i| i["id52"] == trusted.as_str()).unwrap();
+    assert!(trusted_status["requests_per_sec"].is_null());
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_kosha_quota() {
+    let (mut hub, hub_dir) = create_test_hub("quota").await;
+    let owner = add_owner_spoke(&mut hub).await;
+    hub.create_kosha("docs").await.unwrap();
+    hub.set_limits(Limits {
+        kosha_bytes: Some(1000),
+        kosha_quotas: BTreeMap::from([("root".to_string(), None)]),
+        ..Limits::default()
+    })
+    .await
+    .unwrap();
+
+    hub.handle_request(&owner, write_file("a.bin", &[0; 600])).await.unwrap();
+    let over = hub.handle_request(&owner, write_file("b.bin", &[0; 600])).await;
+    assert!(
+        matches!(over, Err(HubError::QuotaExceeded { retry_after_ms: None, .. })),
+        "got {:?}",
+        over
+    );
+
+    let status = hub
+        .handle_request(&owner, request("hub", "self", "quota_status", serde_json::Value::Null))
+        .await
+        .unwrap()
+        .payload;
+    assert_eq!(status["limits"]["kosha_bytes"], 1000);
+    let koshas = status["koshas"].as_array().unwrap();
+    assert_eq!(koshas[0]["alias"], "docs");
+    assert_eq!(koshas[0]["used_bytes"], 600);
+    assert_eq!(koshas[0]["quota_bytes"], 1000);
+    assert_eq!(koshas[1]["alias"], "root");
+    assert!(koshas[1]["quota_bytes"].is_null());
+
+    // Limits are saved with the hub's config
+    drop(hub);
+    let hub = Hub::load(&hub_dir).await.unwrap();
+    assert_eq!(hub.limits().kosha_quota("docs"), Some(1000));
+    let over = hub.handle_request(&owner, write_file("b.bin", &[0; 600])).await;
+    assert!(matches!(over, Err(HubError::QuotaExceeded { .. })), "got {:?}", over);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
@@ -0,0 +1,76 @@
+//! Integration tests for hub metrics and their Prometheus rendering
+
+mod common;
+
+use common::create_test_hub;
+use fastn_hub::Request;
+use fastn_net::SecretKey;
+
+fn request(app: &str, command: &str, payload: serde_json::Value) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: app.to_string(),
+        instance: "root".to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    }
+}
+
+#[tokio::test]
+async fn test_requests_and_kosha_bytes_are_counted() {
+    let (mut hub, hub_dir) = create_test_hub("counts").await;
+    let owner = SecretKey::generate().public().id52();
+    hub.add_spoke(&owner).await.unwrap();
+
+    use base64::Engine;
+    let content = base64::engine::general_purpose::STANDARD.encode([7u8; 10]);
+    let write = request("kosha", "write_file", serde_json::json!({ "path": "a.bin", "content": content }));
+    hub.handle_request(&owner, write).await.unwrap();
+    let read = request("kosha", "read_file", serde_json::json!({ "path": "a.bin" }));
+    hub.handle_request(&owner, read).await.unwrap();
+    let missing = request("kosha", "read_file", serde_json::json!({ "path": "missing.bin" }));
+    assert!(hub.handle_request(&owner, missing).await.is_err());
+
+    // Unknown senders are unauthorized
+    let stranger = SecretKey::generate().public().id52();
+    assert!(hub.handle_request(&stranger, request("hub", "describe", serde_json::Value::Null)).await.is_err());
+
+    let metrics = hub.metrics();
+    assert_eq!(metrics.requests_total(), 4);
+    assert_eq!(metrics.requests_denied(), 1);
+    assert_eq!(metrics.requests_failed(), 1);
+    assert_eq!(metrics.acl_denials(), 0);
+    assert_eq!(metrics.kosha_written_bytes(), 10);
+    assert_eq!(metrics.kosha_read_bytes(), 10);
+    assert_eq!(metrics.push_subscriptions(), 0);
+
+    let text = metrics.render_prometheus();
+    assert!(text.contains("# TYPE fastn_hub_requests_total counter"), "{}", text);
+    assert!(text.contains("fastn_hub_requests_total{app=\"kosha\",command=\"read_file\",outcome=\"ok\"} 1"), "{}", text);
+    assert!(text.contains("fastn_hub_requests_total{app=\"kosha\",command=\"read_file\",outcome=\"failed\"} 1"), "{}", text);
+    assert!(text.contains("fastn_hub_requests_total{app=\"hub\",command=\"describe\",outcome=\"unauthorized\"} 1"), "{}", text);
+    assert!(text.contains("fastn_hub_request_duration_seconds_bucket{app=\"kosha\",command=\"read_file\",le=\"+Inf\"} 2"), "{}", text);
+    assert!(text.contains("fastn_hub_request_duration_seconds_count{app=\"kosha\",command=\"write_file\"} 1"), "{}", text);
+    assert!(text.contains("fastn_hub_kosha_written_bytes_total 10"), "{}", text);
+    assert!(text.contains("fastn_hub_push_subscriptions 0"), "{}", text);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_label_values_are_escaped() {
+    let (mut hub, hub_dir) = create_test_hub("escape").await;
+    let owner = SecretKey::generate().public().id52();
+    hub.add_spoke(&owner).await.unwrap();
+
+    let odd = request("no\"such\napp", "x", serde_json::Value::Null);
+    assert!(hub.handle_request(&owner, odd).await.is_err());
+
+    let text = hub.metrics().render_prometheus();
+    assert!(text.contains("app=\"no\\\"such\\napp\""), "{}", text);
+    // Every sample is on its own line
+    assert!(text.lines().all(|line| line.starts_with("# ") || line.starts_with("fastn_hub_")), "{}", text);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
@@ -0,0 +1,108 @@
+//! Integration tests for kosha format migrations and blob collection
+
+mod common;
+
+use common::create_test_hub;
+use fastn_hub::Error;
+
+/// Turn a kosha back into one written before format records, with a history
+/// entry named the old way
+fn make_version_0(kosha_path: &std::path::Path) {
+    std::fs::remove_file(kosha_path.join(fastn_kosha::FORMAT_FILE)).unwrap();
+    std::fs::write(kosha_path.join("history/50%.txt__20240101T000000Z"), b"old").unwrap();
+}
+
+#[tokio::test]
+async fn test_migrate_dry_run_and_apply() {
+    let (hub, dir) = create_test_hub("apply").await;
+    let kosha = hub.create_kosha("old").await.unwrap();
+    make_version_0(kosha.path());
+
+    let dry_run = hub.migrate_kosha("old", false).await.unwrap();
+    assert_eq!(dry_run.from, 0);
+    assert_eq!(dry_run.migrations.len(), 2);
+    assert_eq!(dry_run.migrations[0].changes.len(), 1);
+    assert!(dry_run.backup.is_none());
+    assert!(kosha.path().join("history/50%.txt__20240101T000000Z").exists());
+
+    let applied = hub.migrate_kosha("old", true).await.unwrap();
+    assert_eq!(applied.from, 0);
+    assert_eq!(applied.migrations.len(), 2);
+    assert!(kosha.path().join("history/50%25.txt__20240101T000000Z").exists());
+    let record = fastn_kosha::read_format(kosha.path()).await.unwrap();
+    assert_eq!(record.version, fastn_kosha::FORMAT_VERSION);
+
+    // The backup has the kosha as it was, outside koshas/
+    let backup = applied.backup.expect("migrating makes a backup");
+    assert!(backup.starts_with(dir.join("backups")));
+    assert!(backup.join("history/50%.txt__20240101T000000Z").exists());
+    assert_eq!(hub.list_koshas().await.unwrap(), vec!["old".to_string(), "root".to_string()]);
+
+    // Up to date now: nothing to do and no backup
+    let again = hub.migrate_kosha("old", true).await.unwrap();
+    assert_eq!(again.from, fastn_kosha::FORMAT_VERSION);
+    assert!(again.migrations.is_empty());
+    assert!(again.backup.is_none());
+}
+
+#[tokio::test]
+async fn test_new_koshas_are_up_to_date() {
+    let (hub, _dir) = create_test_hub("new").await;
+    hub.create_kosha("fresh").await.unwrap();
+    for alias in ["root", "fresh"] {
+        let migration = hub.migrate_kosha(alias, false).await.unwrap();
+        assert_eq!(migration.from, fastn_kosha::FORMAT_VERSION);
+        assert!(migration.migrations.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_newer_format_is_not_served() {
+    let (hub, dir) = create_test_hub("newer").await;
+    let path = dir.join("koshas").join("future");
+    std::fs::create_dir_all(path.join("files")).unwrap();
+    let newer = fastn_kosha::FORMAT_VERSION + 1;
+    std::fs::write(path.join(fastn_kosha::FORMAT_FILE), format!(r#"{{"version": {}}}"#, newer)).unwrap();
+
+    assert!(matches!(
+        hub.get_kosha("future").await,
+        Err(Error::Kosha(fastn_kosha::Error::UnsupportedFormat { .. }))
+    ));
+    assert!(matches!(
+        hub.migrate_kosha("future", true).await,
+        Err(Error::Kosha(fastn_kosha::Error::UnsupportedFormat { .. }))
+    ));
+    assert!(!dir.join("backups").exists());
+}
+
+#[tokio::test]
+async fn test_migrate_unknown_kosha() {
+    let (hub, _dir) = create_test_hub("unknown").await;
+    assert!(matches!(
+        hub.migrate_kosha("missing", false).await,
+        Err(Error::InstanceNotFound(_, _))
+    ));
+}
+
+#[tokio::test]
+async fn test_gc_kosha() {
+    let (hub, _dir) = create_test_hub("gc").await;
+    let kosha = hub.create_kosha("notes").await.unwrap();
+    kosha.write_file("a.txt", b"draft").await.unwrap();
+    kosha.delete("a.txt").await.unwrap();
+    let history = std::fs::read_dir(kosha.path().join("history")).unwrap();
+    for entry in history {
+        std::fs::remove_file(entry.unwrap().path()).unwrap();
+    }
+
+    let dry_run = hub.gc_kosha("notes", false).await.unwrap();
+    assert_eq!((dry_run.removed, dry_run.freed_bytes), (1, 5));
+    let applied = hub.gc_kosha("notes", true).await.unwrap();
+    assert_eq!(applied, dry_run);
+    assert_eq!(hub.gc_kosha("notes", true).await.unwrap().removed, 0);
+
+    assert!(matches!(
+        hub.gc_kosha("missing", true).await,
+        Err(Error::InstanceNotFound(..))
+    ));
+}
@@ -0,0 +1,172 @@
+//! Integration tests for kosha mirrors (`fastn_hub::mirror`)
+
+mod common;
+
+use common::{create_test_hub, free_port, serve};
+use fastn_hub::mirror::DEFAULT_INTERVAL;
+use fastn_hub::{Error, Hub, HubError, MirrorSync, Request};
+use fastn_kosha::Kosha;
+use fastn_net::SecretKey;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Helper to write a .hubs file
+async fn write_hubs_file(hub_dir: &Path, filename: &str, content: &str) {
+    let hubs_dir = hub_dir.join("koshas/root/files/hubs");
+    tokio::fs::create_dir_all(&hubs_dir).await.expect("Failed to create hubs dir");
+    tokio::fs::write(hubs_dir.join(filename), content).await.expect("Failed to write .hubs file");
+}
+
+/// A remote hub sharing kosha "shared" with the local hub, which knows it
+/// as "alice"; `authorize` decides whether the remote hub lets it in
+async fn remote_hub(name: &str, local: &Hub, authorize: bool) -> (Kosha, PathBuf) {
+    let (remote, remote_dir) = create_test_hub(&format!("{}-remote", name)).await;
+    let shared = remote.create_kosha("shared").await.unwrap();
+    if authorize {
+        write_hubs_file(&remote_dir, "friends.hubs", &format!("{}: bob\n", local.id52())).await;
+    }
+    let port = free_port();
+    let address = format!("{}: alice http://127.0.0.1:{}\n", remote.id52(), port);
+    write_hubs_file(local.home(), "friends.hubs", &address).await;
+    serve(remote, port).await;
+    (shared, remote_dir)
+}
+
+fn kosha_request(instance: &str, command: &str, payload: serde_json::Value) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: instance.to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    }
+}
+
+#[tokio::test]
+async fn test_mirror_pulls_changes_renames_and_deletes() {
+    let (mut local, local_dir) = create_test_hub("pull").await;
+    let (shared, remote_dir) = remote_hub("pull", &local, true).await;
+    let big: Vec<u8> = (0..fastn_kosha::MAX_CHUNK_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+    shared.write_file("a.txt", b"alpha").await.unwrap();
+    shared.write_file("big.bin", &big).await.unwrap();
+    shared.write_file("docs/b.txt", b"beta").await.unwrap();
+
+    local.add_mirror("alice-shared", "alice", "shared", DEFAULT_INTERVAL).await.unwrap();
+    let sync = local.sync_mirror("alice-shared").await.unwrap();
+    assert_eq!(sync.downloaded, vec!["a.txt", "big.bin", "docs/b.txt"]);
+    assert_eq!(sync.bytes, 5 + big.len() as u64 + 4);
+
+    let mirror = local.get_kosha("alice-shared").await.unwrap().unwrap();
+    assert_eq!(mirror.read_file("big.bin").await.unwrap(), big);
+    assert_eq!(mirror.read_file("docs/b.txt").await.unwrap(), b"beta");
+
+    // Nothing changed, nothing pulled
+    assert_eq!(local.sync_mirror("alice-shared").await.unwrap(), MirrorSync::default());
+
+    shared.write_file("a.txt", b"alpha, edited").await.unwrap();
+    shared.rename("docs/b.txt", "docs/c.txt").await.unwrap();
+    shared.delete("big.bin").await.unwrap();
+    let sync = local.sync_mirror("alice-shared").await.unwrap();
+    assert_eq!(sync.downloaded, vec!["a.txt"]);
+    assert_eq!(sync.renamed, vec![("docs/b.txt".to_string(), "docs/c.txt".to_string())]);
+    assert_eq!(sync.deleted, vec!["big.bin"]);
+
+    assert_eq!(mirror.read_file("a.txt").await.unwrap(), b"alpha, edited");
+    assert_eq!(mirror.get_versions("a.txt").await.unwrap().len(), 2);
+    assert_eq!(mirror.read_file("docs/c.txt").await.unwrap(), b"beta");
+    assert!(mirror.read_file("docs/b.txt").await.is_err());
+    assert!(mirror.read_file("big.bin").await.is_err());
+
+    // The cursor is kept with the mirror
+    let status = local.get_mirror("alice-shared").await.unwrap().unwrap();
+    assert_eq!(status.files.keys().collect::<Vec<_>>(), vec!["a.txt", "docs/c.txt"]);
+    assert_eq!(status.bytes(), 13 + 4);
+    assert!(status.last_sync.is_some());
+    assert_eq!(status.last_error, None);
+
+    let _ = std::fs::remove_dir_all(&local_dir);
+    let _ = std::fs::remove_dir_all(&remote_dir);
+}
+
+#[tokio::test]
+async fn test_mirrors_are_read_only() {
+    let (mut local, local_dir) = create_test_hub("read-only").await;
+    let owner = SecretKey::generate().id52();
+    local.add_spoke(&owner).await.unwrap();
+    // A hub that can't be reached
+    write_hubs_file(&local_dir, "friends.hubs", "UNREACHABLE: alice http://127.0.0.1:1\n").await;
+    local.add_mirror("alice-shared", "alice", "shared", DEFAULT_INTERVAL).await.unwrap();
+
+    // Mirrors are known again after a restart
+    let mut local = Hub::load(&local_dir).await.unwrap();
+    assert!(local.is_mirror("alice-shared"));
+
+    let write = kosha_request("alice-shared", "write_file", serde_json::json!({ "path": "a.txt", "content": "aGk=" }));
+    assert!(matches!(
+        local.handle_request(&owner, write.clone()).await,
+        Err(HubError::AppError { .. })
+    ));
+    let list = kosha_request("alice-shared", "list_dir", serde_json::json!({ "path": "" }));
+    local.handle_request(&owner, list).await.unwrap();
+
+    // A failed pass is recorded
+    assert!(local.sync_mirror("alice-shared").await.is_err());
+    let status = local.get_mirror("alice-shared").await.unwrap().unwrap();
+    assert!(status.last_error.is_some());
+    assert_eq!(status.last_sync, None);
+
+    // Removing the mirror leaves a writable kosha
+    assert!(local.remove_mirror("alice-shared").await.unwrap());
+    assert!(!local.remove_mirror("alice-shared").await.unwrap());
+    assert!(local.list_mirrors().await.unwrap().is_empty());
+    local.handle_request(&owner, write).await.unwrap();
+
+    let _ = std::fs::remove_dir_all(&local_dir);
+}
+
+#[tokio::test]
+async fn test_mirror_needs_a_known_hub_that_lets_it_in() {
+    let (mut local, local_dir) = create_test_hub("unauthorized").await;
+    assert!(matches!(
+        local.add_mirror("alice-shared", "alice", "shared", DEFAULT_INTERVAL).await,
+        Err(Error::Mirror(_))
+    ));
+    assert!(local.get_kosha("alice-shared").await.unwrap().is_none());
+
+    let (shared, remote_dir) = remote_hub("unauthorized", &local, false).await;
+    shared.write_file("a.txt", b"alpha").await.unwrap();
+    local.add_mirror("alice-shared", "alice", "shared", DEFAULT_INTERVAL).await.unwrap();
+    assert!(matches!(local.sync_mirror("alice-shared").await, Err(Error::Mirror(_))));
+    assert!(local.get_mirror("alice-shared").await.unwrap().unwrap().files.is_empty());
+
+    let _ = std::fs::remove_dir_all(&local_dir);
+    let _ = std::fs::remove_dir_all(&remote_dir);
+}
+
+#[tokio::test]
+async fn test_serving_hub_follows_pushed_changes() {
+    let (mut local, local_dir) = create_test_hub("follow").await;
+    let (shared, remote_dir) = remote_hub("follow", &local, true).await;
+    shared.write_file("a.txt", b"alpha").await.unwrap();
+    // Only pushes can bring changes within the test
+    local.add_mirror("alice-shared", "alice", "shared", Duration::from_secs(3600)).await.unwrap();
+    let mirror = local.get_kosha("alice-shared").await.unwrap().unwrap();
+    serve(local, free_port()).await;
+
+    let wait_for = async |path: &str| {
+        for _ in 0..100 {
+            if mirror.read_file(path).await.is_ok() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("{} was not mirrored", path);
+    };
+    wait_for("a.txt").await;
+    shared.write_file("b.txt", b"beta").await.unwrap();
+    wait_for("b.txt").await;
+
+    let _ = std::fs::remove_dir_all(&local_dir);
+    let _ = std::fs::remove_dir_all(&remote_dir);
+}
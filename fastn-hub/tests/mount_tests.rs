@@ -0,0 +1,201 @@
+//! Integration tests for mount points between koshas (`fastn_hub::mounts`)
+
+mod common;
+
+use common::create_test_hub;
+use fastn_hub::{Hub, HubError, Request, Response};
+use fastn_kosha::MOUNTS_FILE;
+use fastn_net::SecretKey;
+use std::path::Path;
+
+fn kosha_request(instance: &str, command: &str, payload: serde_json::Value) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: instance.to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    }
+}
+
+fn read(instance: &str, path: &str) -> Request {
+    kosha_request(instance, "read_file", serde_json::json!({ "path": path }))
+}
+
+fn list(instance: &str, path: &str) -> Request {
+    kosha_request(instance, "list_dir", serde_json::json!({ "path": path }))
+}
+
+fn content(response: Response) -> String {
+    use base64::Engine;
+    let encoded = response.payload["content"].as_str().unwrap();
+    String::from_utf8(base64::engine::general_purpose::STANDARD.decode(encoded).unwrap()).unwrap()
+}
+
+fn names(response: Response) -> Vec<String> {
+    let entries = response.payload["entries"].as_array().unwrap();
+    entries.iter().map(|entry| entry["name"].as_str().unwrap().to_string()).collect()
+}
+
+/// ACL module allowing or denying everything
+fn constant_acl(allow: bool) -> Vec<u8> {
+    wat::parse_str(format!(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 1024)
+            (func (export "allowed") (param i32 i32) (result i32) i32.const {}))"#,
+        allow as i32
+    ))
+    .unwrap()
+}
+
+/// Own spoke of the hub, which skips the ACL
+async fn own_spoke(hub: &mut Hub) -> String {
+    let spoke_id52 = SecretKey::generate().public().id52();
+    hub.add_spoke(&spoke_id52).await.unwrap();
+    spoke_id52
+}
+
+/// Hub listed in a .hubs file, which goes through the ACL
+async fn remote_hub(hub_dir: &Path) -> String {
+    let remote_id52 = SecretKey::generate().public().id52();
+    let hubs_dir = hub_dir.join("koshas/root/files/hubs");
+    std::fs::create_dir_all(&hubs_dir).unwrap();
+    std::fs::write(hubs_dir.join("known.hubs"), format!("{}: remote\n", remote_id52)).unwrap();
+    remote_id52
+}
+
+#[tokio::test]
+async fn test_reads_and_listings_go_through_mounts() {
+    let (mut hub, hub_dir) = create_test_hub("reads").await;
+    let spoke = own_spoke(&mut hub).await;
+    let family = hub.create_kosha("family").await.unwrap();
+    let shared = hub.create_kosha("shared").await.unwrap();
+    family.write_file("notes.txt", b"family notes").await.unwrap();
+    shared.write_file("photos/beach.jpg", b"beach").await.unwrap();
+    shared.write_file("photos/2024/snow.jpg", b"snow").await.unwrap();
+    family
+        .write_file(MOUNTS_FILE, b"# From the shared kosha\nphotos: shared/photos\nmedia/old: shared\n")
+        .await
+        .unwrap();
+
+    let beach = hub.handle_request(&spoke, read("family", "photos/beach.jpg")).await.unwrap();
+    assert_eq!(content(beach), "beach");
+    let snow = hub.handle_request(&spoke, read("family", "media/old/photos/2024/snow.jpg")).await.unwrap();
+    assert_eq!(content(snow), "snow");
+    let hash = kosha_request("family", "file_hash", serde_json::json!({ "path": "photos/beach.jpg" }));
+    assert!(hub.handle_request(&spoke, hash).await.is_ok());
+
+    // Mount points show up as folders, and list the mounted folder
+    let root = hub.handle_request(&spoke, list("family", "")).await.unwrap();
+    assert_eq!(names(root), [MOUNTS_FILE, "media", "notes.txt", "photos"]);
+    let media = hub.handle_request(&spoke, list("family", "media")).await.unwrap();
+    assert_eq!(names(media), ["old"]);
+    let photos = hub.handle_request(&spoke, list("family", "photos")).await.unwrap();
+    assert_eq!(names(photos), ["2024", "beach.jpg"]);
+
+    // Missing files in the mounted kosha are missing
+    assert!(hub.handle_request(&spoke, read("family", "photos/none.jpg")).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_mounts_are_read_only() {
+    let (mut hub, hub_dir) = create_test_hub("read-only").await;
+    let spoke = own_spoke(&mut hub).await;
+    let family = hub.create_kosha("family").await.unwrap();
+    let shared = hub.create_kosha("shared").await.unwrap();
+    shared.write_file("photos/beach.jpg", b"beach").await.unwrap();
+    family.write_file("notes.txt", b"family notes").await.unwrap();
+    family.write_file(MOUNTS_FILE, b"photos: shared/photos\n").await.unwrap();
+
+    let writes = [
+        ("write_file", serde_json::json!({ "path": "photos/new.jpg", "content": "aGk=" })),
+        ("delete", serde_json::json!({ "path": "photos/beach.jpg" })),
+        ("delete_dir", serde_json::json!({ "path": "photos" })),
+        ("rename", serde_json::json!({ "from": "photos/beach.jpg", "to": "beach.jpg" })),
+        ("copy", serde_json::json!({ "from": "notes.txt", "to": "photos/notes.txt" })),
+    ];
+    for (command, payload) in writes {
+        let result = hub.handle_request(&spoke, kosha_request("family", command, payload)).await;
+        assert!(matches!(result, Err(HubError::AppError { .. })), "{}: got {:?}", command, result);
+    }
+    assert!(shared.read_file("photos/beach.jpg").await.is_ok());
+    assert!(shared.read_file("photos/new.jpg").await.is_err());
+
+    // The mounted kosha itself is writable
+    let write = kosha_request("shared", "write_file", serde_json::json!({ "path": "photos/new.jpg", "content": "aGk=" }));
+    assert!(hub.handle_request(&spoke, write).await.is_ok());
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_chained_mounts_and_loops() {
+    let (mut hub, hub_dir) = create_test_hub("chains").await;
+    let spoke = own_spoke(&mut hub).await;
+    let a = hub.create_kosha("a").await.unwrap();
+    let b = hub.create_kosha("b").await.unwrap();
+    let c = hub.create_kosha("c").await.unwrap();
+    a.write_file(MOUNTS_FILE, b"from-b: b/shared\n").await.unwrap();
+    b.write_file(MOUNTS_FILE, b"shared/from-c: c\n").await.unwrap();
+    c.write_file("deep.txt", b"deep").await.unwrap();
+
+    let deep = hub.handle_request(&spoke, read("a", "from-b/from-c/deep.txt")).await.unwrap();
+    assert_eq!(content(deep), "deep");
+    let listed = hub.handle_request(&spoke, list("a", "from-b")).await.unwrap();
+    assert_eq!(names(listed), ["from-c"]);
+
+    // c mounts a back: a/from-b/from-c/back -> a/from-b -> ... forever
+    c.write_file(MOUNTS_FILE, b"back: a\n").await.unwrap();
+    let looped = hub.handle_request(&spoke, read("a", "from-b/from-c/back/from-b/from-c/back/x.txt")).await;
+    assert!(matches!(&looped, Err(HubError::AppError { message }) if message.contains("loop")), "got {:?}", looped);
+
+    // Mounts of missing koshas are missing
+    a.write_file(MOUNTS_FILE, b"gone: nowhere\n").await.unwrap();
+    let missing = hub.handle_request(&spoke, read("a", "gone/x.txt")).await;
+    assert!(matches!(missing, Err(HubError::InstanceNotFound { .. })), "got {:?}", missing);
+
+    // A broken mount table fails reads rather than hiding the mounts
+    a.write_file(MOUNTS_FILE, b"gone\n").await.unwrap();
+    assert!(hub.handle_request(&spoke, read("a", "gone/x.txt")).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_mounted_reads_are_checked_against_the_mounted_kosha() {
+    let (hub, hub_dir) = create_test_hub("acl").await;
+    let remote = remote_hub(&hub_dir).await;
+    let family = hub.create_kosha("family").await.unwrap();
+    let shared = hub.create_kosha("shared").await.unwrap();
+    shared.write_file("photos/beach.jpg", b"beach").await.unwrap();
+    family.write_file(MOUNTS_FILE, b"photos: shared/photos\n").await.unwrap();
+    let (token, _) = hub.create_token("viewer", "family", "photos", false, None).await.unwrap();
+
+    // A token scoped to the mounting kosha doesn't reach the mounted one
+    assert!(hub.handle_request(&remote, read("family", "photos/beach.jpg")).await.is_ok());
+    let denied = hub.handle_token_request(&token, read("family", "photos/beach.jpg")).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+
+    // Locking the mounted folder locks it through the mount too
+    shared.write_file("photos/_read.wasm", &constant_acl(false)).await.unwrap();
+    let denied = hub.handle_request(&remote, read("family", "photos/beach.jpg")).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+    let denied = hub.handle_token_request(&token, read("family", "photos/beach.jpg")).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+
+    // The mounting kosha's modules don't matter to mounted reads
+    shared.write_file("photos/_read.wasm", &constant_acl(true)).await.unwrap();
+    family.write_file("_read.wasm", &constant_acl(false)).await.unwrap();
+    assert!(hub.handle_request(&remote, read("family", "photos/beach.jpg")).await.is_ok());
+
+    // Changing the mount table takes admin access
+    let remount = kosha_request("family", "write_file", serde_json::json!({ "path": MOUNTS_FILE, "content": "aGk=" }));
+    let denied = hub.handle_request(&remote, remount).await;
+    assert!(matches!(denied, Err(HubError::AccessDenied { .. })), "got {:?}", denied);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
@@ -0,0 +1,254 @@
+//! Integration tests for the push channel (`fastn_hub::push`)
+
+mod common;
+
+use common::create_test_hub;
+use fastn_hub::{Hub, HubError, Request, Response};
+use fastn_net::client::{Client, Subscription};
+use fastn_net::{FileChange, FileChangeKind, PushEvent, SecretKey, Subscribe, Topic};
+
+fn kosha_request(command: &str, payload: serde_json::Value) -> Request {
+    Request {
+        target_hub: "self".to_string(),
+        app: "kosha".to_string(),
+        instance: "root".to_string(),
+        command: command.to_string(),
+        payload,
+        explain: false,
+    }
+}
+
+fn subscribe(topics: Vec<Topic>) -> Subscribe {
+    Subscribe { topics }
+}
+
+fn root_topic() -> Topic {
+    Topic::Kosha {
+        instance: "root".to_string(),
+    }
+}
+
+fn file_changed(path: &str) -> PushEvent {
+    PushEvent::FileChanged {
+        kosha: "root".to_string(),
+        path: path.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_kosha_changes_are_pushed() {
+    let (mut hub, hub_dir) = create_test_hub("changes").await;
+    let owner = SecretKey::generate().public().id52();
+    hub.add_spoke(&owner).await.unwrap();
+    let mut events = hub.subscribe_events();
+
+    let write = kosha_request("write_file", serde_json::json!({ "path": "a.txt", "content": "aGk=" }));
+    hub.handle_request(&owner, write).await.unwrap();
+    let rename = kosha_request("rename", serde_json::json!({ "from": "a.txt", "to": "b.txt" }));
+    hub.handle_request(&owner, rename).await.unwrap();
+    let kv_set = kosha_request("kv_set", serde_json::json!({ "key": "theme", "value": "dark" }));
+    hub.handle_request(&owner, kv_set).await.unwrap();
+
+    // Reads and failed commands push nothing
+    let read = kosha_request("read_file", serde_json::json!({ "path": "b.txt" }));
+    hub.handle_request(&owner, read).await.unwrap();
+    let missing = kosha_request("delete", serde_json::json!({ "path": "missing.txt" }));
+    assert!(hub.handle_request(&owner, missing).await.is_err());
+    let delete = kosha_request("delete", serde_json::json!({ "path": "b.txt" }));
+    hub.handle_request(&owner, delete).await.unwrap();
+
+    let mut received = vec![];
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+    assert_eq!(
+        received,
+        vec![
+            file_changed("a.txt"),
+            file_changed("a.txt"),
+            file_changed("b.txt"),
+            PushEvent::KvUpdated {
+                kosha: "root".to_string(),
+                key: "theme".to_string(),
+            },
+            file_changed("b.txt"),
+        ]
+    );
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_spoke_authorization_is_pushed() {
+    let (mut hub, hub_dir) = create_test_hub("spokes").await;
+    let mut events = hub.subscribe_events();
+
+    let spoke = SecretKey::generate().public().id52();
+    let alias = hub.add_spoke(&spoke).await.unwrap();
+    assert_eq!(
+        events.try_recv().unwrap(),
+        PushEvent::SpokeAuthorized {
+            spoke_id52: spoke,
+            alias,
+        }
+    );
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_subscription_authorization() {
+    let (mut hub, hub_dir) = create_test_hub("authorize").await;
+    let owner = SecretKey::generate().public().id52();
+    hub.add_spoke(&owner).await.unwrap();
+
+    hub.authorize_subscription(&owner, &subscribe(vec![root_topic(), Topic::Spokes]))
+        .await
+        .unwrap();
+
+    // Unknown koshas and unknown senders are refused
+    let missing = Topic::Kosha {
+        instance: "missing".to_string(),
+    };
+    assert!(matches!(
+        hub.authorize_subscription(&owner, &subscribe(vec![missing])).await,
+        Err(HubError::InstanceNotFound { .. })
+    ));
+    let stranger = SecretKey::generate().public().id52();
+    assert!(matches!(
+        hub.authorize_subscription(&stranger, &subscribe(vec![root_topic()])).await,
+        Err(HubError::Unauthorized)
+    ));
+
+    // Authorized hubs may follow koshas, but not the hub's spokes
+    let remote = SecretKey::generate().public().id52();
+    let hubs_dir = hub_dir.join("koshas/root/files/hubs");
+    std::fs::create_dir_all(&hubs_dir).unwrap();
+    std::fs::write(hubs_dir.join("known.hubs"), format!("{}: remote\n", remote)).unwrap();
+    hub.authorize_subscription(&remote, &subscribe(vec![root_topic()]))
+        .await
+        .unwrap();
+    assert!(matches!(
+        hub.authorize_subscription(&remote, &subscribe(vec![Topic::Spokes])).await,
+        Err(HubError::AccessDenied { .. })
+    ));
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+/// Serve the hub on a free port and return a client for one of its spokes
+async fn serve(mut hub: Hub) -> Client {
+    let spoke_key = SecretKey::generate();
+    hub.add_spoke(&spoke_key.id52()).await.unwrap();
+    let hub_id52 = hub.id52().to_string();
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    tokio::spawn(hub.serve(port));
+    Client::new(spoke_key, hub_id52, format!("http://127.0.0.1:{}", port))
+}
+
+/// Subscribe, waiting for the server to come up
+async fn subscribe_when_up(client: &Client, topics: Vec<Topic>) -> Subscription<PushEvent> {
+    for _ in 0..50 {
+        match client.subscribe::<_, PushEvent, HubError>(&subscribe(topics.clone())).await {
+            Ok(accepted) => return accepted.unwrap(),
+            Err(_) => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+        }
+    }
+    panic!("Hub did not accept the subscription");
+}
+
+#[tokio::test]
+async fn test_push_over_websocket() {
+    let (hub, hub_dir) = create_test_hub("websocket").await;
+    let client = serve(hub).await;
+    let mut subscription = subscribe_when_up(&client, vec![root_topic()]).await;
+
+    let write = kosha_request("write_file", serde_json::json!({ "path": "pushed.txt", "content": "aGk=" }));
+    let written: Result<Response, HubError> = client.call(&write).await.unwrap();
+    written.unwrap();
+    assert_eq!(subscription.next().await.unwrap(), Some(file_changed("pushed.txt")));
+
+    // Subscriptions are refused like requests
+    let refused = client
+        .subscribe::<_, PushEvent, HubError>(&subscribe(vec![Topic::Kosha {
+            instance: "missing".to_string(),
+        }]))
+        .await
+        .unwrap();
+    assert!(matches!(refused, Err(HubError::InstanceNotFound { .. })));
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_watch_kosha_locally() {
+    let (mut hub, hub_dir) = create_test_hub("watch").await;
+    let owner = SecretKey::generate().public().id52();
+    hub.add_spoke(&owner).await.unwrap();
+    let mut watcher = hub.watch_kosha("root", "notes").await.unwrap();
+    assert!(matches!(
+        hub.watch_kosha("missing", "").await,
+        Err(HubError::InstanceNotFound { .. })
+    ));
+
+    let write = kosha_request("write_file", serde_json::json!({ "path": "notes/a.txt", "content": "aGk=" }));
+    hub.handle_request(&owner, write).await.unwrap();
+    let change = watcher.next().await.unwrap().unwrap();
+    assert_eq!(change.path, "notes/a.txt");
+    assert_eq!(change.kind, fastn_kosha::ChangeKind::Created);
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
+
+#[tokio::test]
+async fn test_file_changes_over_websocket() {
+    let (hub, hub_dir) = create_test_hub("files").await;
+    let client = serve(hub).await;
+    let docs = Topic::Files {
+        instance: "root".to_string(),
+        path_prefix: "docs".to_string(),
+    };
+    // Overlapping topics don't repeat changes
+    let whole = Topic::Files {
+        instance: "root".to_string(),
+        path_prefix: String::new(),
+    };
+    let mut docs_only = subscribe_when_up(&client, vec![docs.clone()]).await;
+    let mut everything = subscribe_when_up(&client, vec![docs, whole]).await;
+
+    let write = kosha_request("write_file", serde_json::json!({ "path": "docs/a.md", "content": "aGk=" }));
+    let written: Result<Response, HubError> = client.call(&write).await.unwrap();
+    let modified = written.unwrap().payload["modified"].as_str().unwrap().to_string();
+    let created = PushEvent::FileChange {
+        kosha: "root".to_string(),
+        change: FileChange {
+            path: "docs/a.md".to_string(),
+            kind: FileChangeKind::Created,
+            version: modified,
+            size: 2,
+        },
+    };
+    assert_eq!(docs_only.next().await.unwrap(), Some(created.clone()));
+    assert_eq!(everything.next().await.unwrap(), Some(created));
+
+    let other = kosha_request("write_file", serde_json::json!({ "path": "other.txt", "content": "aGk=" }));
+    let written: Result<Response, HubError> = client.call(&other).await.unwrap();
+    written.unwrap();
+    let rename = kosha_request("rename", serde_json::json!({ "from": "docs/a.md", "to": "old/a.md" }));
+    let renamed: Result<Response, HubError> = client.call(&rename).await.unwrap();
+    renamed.unwrap();
+
+    let renamed_kind = FileChangeKind::Renamed {
+        from: "docs/a.md".to_string(),
+    };
+    let kinds = |event: Option<PushEvent>| match event {
+        Some(PushEvent::FileChange { change, .. }) => (change.path, change.kind),
+        other => panic!("Expected a file change, got: {:?}", other),
+    };
+    assert_eq!(kinds(everything.next().await.unwrap()), ("other.txt".to_string(), FileChangeKind::Created));
+    assert_eq!(kinds(everything.next().await.unwrap()), ("old/a.md".to_string(), renamed_kind.clone()));
+    // other.txt isn't under docs/, but moving a file out of it is
+    assert_eq!(kinds(docs_only.next().await.unwrap()), ("old/a.md".to_string(), renamed_kind));
+
+    let _ = std::fs::remove_dir_all(&hub_dir);
+}
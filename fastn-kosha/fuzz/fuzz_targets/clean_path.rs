@@ -0,0 +1,21 @@
+//! Accepted paths must stay inside the kosha's files/ directory
+
+#![no_main]
+
+use fastn_kosha::clean_path;
+use libfuzzer_sys::fuzz_target;
+use std::path::{Component, Path};
+
+fuzz_target!(|path: &str| {
+    if let Ok(clean) = clean_path(path) {
+        assert!(!clean.contains('\0'));
+        for component in Path::new(clean).components() {
+            assert!(
+                matches!(component, Component::Normal(_) | Component::CurDir),
+                "{:?} has component {:?}",
+                path,
+                component
+            );
+        }
+    }
+});
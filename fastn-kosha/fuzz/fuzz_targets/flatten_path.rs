@@ -0,0 +1,12 @@
+//! History filenames must map back to exactly the path they came from
+
+#![no_main]
+
+use fastn_kosha::{flatten_path, unflatten_path};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|path: &str| {
+    let flat = flatten_path(path);
+    assert!(!flat.contains('/'));
+    assert_eq!(unflatten_path(&flat), path);
+});
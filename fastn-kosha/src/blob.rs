@@ -0,0 +1,204 @@
+//! Content-addressed blob store
+//!
+//! From format version 2 on, file content is stored once per distinct
+//! content, as `blobs/<first two hex digits>/<sha256>`. Entries in files/
+//! and history/ are small JSON manifests naming their blob, so versions
+//! with the same content, copies and forks share the bytes. A manifest's
+//! modification time is the version timestamp, as a file's was before, and
+//! manifests are replaced and linked into history/ the way files were (see
+//! `durable`).
+//!
+//! Databases, and the files SQLite keeps next to them, are modified in
+//! place and stay ordinary files. So does anything put into files/ without
+//! the kosha (like an edited `spokes.txt`): an entry that isn't a manifest
+//! is read as it is, and its next write stores the content in a blob.
+//!
+//! Blobs are written once and never changed. Deleting a file or pruning
+//! its history leaves the blob behind; `Kosha::gc` removes blobs that no
+//! manifest names.
+
+use crate::{Durability, Result, db, durable, history_source, transfer};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// First format version that stores content in blobs/
+pub(crate) const BLOB_FORMAT_VERSION: u32 = 2;
+
+/// Manifests are smaller than this; anything bigger is content
+const MAX_MANIFEST_SIZE: u64 = 256;
+
+/// What files/ and history/ entries hold
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Manifest {
+    /// Names the blob, lowercase hex
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Outcome of `Kosha::gc`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GcReport {
+    /// Blobs still named by a file or history entry
+    pub kept: u64,
+    /// Blobs nothing names, removed unless it was a dry run
+    pub removed: u64,
+    /// Size of the removed blobs
+    pub freed_bytes: u64,
+}
+
+/// An entry in files/ or history/
+pub(crate) struct Stored {
+    pub location: PathBuf,
+    pub history: bool,
+    /// A database or one of its side files, never a manifest
+    pub as_is: bool,
+}
+
+/// Where the blob with this hash is kept
+pub(crate) fn blob_path(blobs: &Path, sha256: &str) -> PathBuf {
+    blobs.join(&sha256[..2]).join(sha256)
+}
+
+/// Whether the file at kosha path `path` is stored as it is, blobs or not
+pub(crate) fn stored_as_is(path: &str) -> bool {
+    let name = std::ffi::OsStr::new(path.rsplit('/').next().unwrap_or(path));
+    db::is_database(name) || db::is_database_sidecar(name)
+}
+
+/// The manifest at `location`, `None` if the file holds its content itself
+pub(crate) async fn manifest_at(location: &Path) -> Result<Option<Manifest>> {
+    if tokio::fs::metadata(location).await?.len() > MAX_MANIFEST_SIZE {
+        return Ok(None);
+    }
+    let manifest = serde_json::from_slice::<Manifest>(&tokio::fs::read(location).await?).ok();
+    Ok(manifest.filter(|m| transfer::validate_sha256(&m.sha256).is_ok()))
+}
+
+/// Write a manifest to a new temp file, returning its path
+pub(crate) async fn write_manifest(tmp_dir: &Path, manifest: &Manifest, durability: Durability) -> Result<PathBuf> {
+    durable::write_temp(tmp_dir, &serde_json::to_vec(manifest)?, durability).await
+}
+
+/// Store `content`, unless a blob has it already, and return its manifest
+pub(crate) async fn store(blobs: &Path, tmp_dir: &Path, content: &[u8], durability: Durability) -> Result<Manifest> {
+    let manifest = Manifest {
+        sha256: transfer::hex(&Sha256::digest(content)),
+        size: content.len() as u64,
+    };
+    let blob = blob_path(blobs, &manifest.sha256);
+    if !tokio::fs::try_exists(&blob).await? {
+        if let Some(parent) = blob.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        durable::write_atomic(tmp_dir, &blob, content, durability).await?;
+    }
+    Ok(manifest)
+}
+
+/// Move the complete file `file`, whose content hashes to `sha256`, into
+/// the store (or drop it if a blob has that content already)
+pub(crate) async fn store_file(blobs: &Path, file: &Path, sha256: &str, durability: Durability) -> Result<Manifest> {
+    let size = tokio::fs::metadata(file).await?.len();
+    let blob = blob_path(blobs, sha256);
+    if tokio::fs::try_exists(&blob).await? {
+        tokio::fs::remove_file(file).await?;
+    } else {
+        if let Some(parent) = blob.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        durable::replace(file, &blob, durability).await?;
+    }
+    Ok(Manifest {
+        sha256: sha256.to_string(),
+        size,
+    })
+}
+
+/// Every entry in files/ and history/ of the kosha at `root`
+pub(crate) async fn stored_files(root: &Path) -> Result<Vec<Stored>> {
+    let mut stored = vec![];
+
+    let mut pending = vec![root.join("files")];
+    while let Some(dir) = pending.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                stored.push(Stored {
+                    as_is: stored_as_is(&entry.file_name().to_string_lossy()),
+                    location: entry.path(),
+                    history: false,
+                });
+            }
+        }
+    }
+
+    let mut entries = match tokio::fs::read_dir(root.join("history")).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stored),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        stored.push(Stored {
+            as_is: history_source(&name).is_some_and(|source| stored_as_is(&source)),
+            location: entry.path(),
+            history: true,
+        });
+    }
+    Ok(stored)
+}
+
+/// Blobs named by the manifests of the kosha at `root`
+pub(crate) async fn referenced(root: &Path) -> Result<HashSet<String>> {
+    let mut referenced = HashSet::new();
+    for stored in stored_files(root).await?.into_iter().filter(|s| !s.as_is) {
+        if let Some(manifest) = manifest_at(&stored.location).await? {
+            referenced.insert(manifest.sha256);
+        }
+    }
+    Ok(referenced)
+}
+
+/// Remove the blobs not in `referenced` (with `apply` false only count
+/// them), and the directories left empty
+pub(crate) async fn collect(blobs: &Path, referenced: &HashSet<String>, apply: bool) -> Result<GcReport> {
+    let mut report = GcReport::default();
+    let mut prefixes = tokio::fs::read_dir(blobs).await?;
+    while let Some(prefix) = prefixes.next_entry().await? {
+        if !prefix.file_type().await?.is_dir() {
+            continue;
+        }
+        let (mut entries, mut left) = (tokio::fs::read_dir(prefix.path()).await?, 0);
+        while let Some(entry) = entries.next_entry().await? {
+            if referenced.contains(entry.file_name().to_string_lossy().as_ref()) {
+                report.kept += 1;
+                left += 1;
+                continue;
+            }
+            report.removed += 1;
+            report.freed_bytes += entry.metadata().await?.len();
+            if apply {
+                tokio::fs::remove_file(entry.path()).await?;
+            } else {
+                left += 1;
+            }
+        }
+        if left == 0 {
+            tokio::fs::remove_dir(prefix.path()).await?;
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,236 @@
+//! SQLite databases stored in the kosha
+//!
+//! Any `*.sqlite3` file under `files/` is a database. Statements take
+//! positional `?` parameters as JSON values and rows come back as JSON arrays
+//! (one element per column):
+//!
+//! | SQLite    | JSON                                                |
+//! |-----------|-----------------------------------------------------|
+//! | `NULL`    | `null`                                              |
+//! | `INTEGER` | number (booleans are bound as 0/1)                  |
+//! | `REAL`    | number                                              |
+//! | `TEXT`    | string (arrays and objects are bound as JSON text)  |
+//! | `BLOB`    | base64 string                                       |
+//!
+//! Connections refuse `ATTACH`/`DETACH`, so a statement can only ever touch
+//! the database it was sent to.
+
+use crate::{Error, Result};
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{ffi, Connection, OpenFlags};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::hash::{BuildHasher, RandomState};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// File extension that marks a file as a database
+pub const DATABASE_EXTENSION: &str = ".sqlite3";
+
+/// Files SQLite keeps next to an open database. The copy made with
+/// `db::copy` already includes their committed content.
+const DATABASE_SIDECARS: [&str; 3] = ["-journal", "-wal", "-shm"];
+
+/// Default for `Kosha::with_transaction_timeout`
+pub const DEFAULT_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a statement waits for another connection's write lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Open a database file. Only `db_execute` and `db_begin` create it.
+pub(crate) fn open(path: &Path, create: bool) -> Result<Connection> {
+    let mut flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    if create {
+        flags |= OpenFlags::SQLITE_OPEN_CREATE;
+    }
+    let conn = Connection::open_with_flags(path, flags).map_err(db_error)?;
+    conn.busy_timeout(BUSY_TIMEOUT).map_err(db_error)?;
+    conn.authorizer(Some(|ctx: AuthContext<'_>| match ctx.action {
+        AuthAction::Attach { .. } | AuthAction::Detach { .. } => Authorization::Deny,
+        // Reported as unknown when the file name is a bound parameter
+        AuthAction::Unknown { code, .. } if code == ffi::SQLITE_ATTACH || code == ffi::SQLITE_DETACH => {
+            Authorization::Deny
+        }
+        _ => Authorization::Allow,
+    }));
+    Ok(conn)
+}
+
+/// Whether a file name marks a database
+pub(crate) fn is_database(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| name.ends_with(DATABASE_EXTENSION))
+}
+
+/// Whether a file name is one of SQLite's files next to a database
+pub(crate) fn is_database_sidecar(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| {
+        DATABASE_SIDECARS
+            .iter()
+            .any(|suffix| name.strip_suffix(suffix).is_some_and(|db| db.ends_with(DATABASE_EXTENSION)))
+    })
+}
+
+/// Run a read-only statement and return its rows
+pub(crate) fn query(conn: &Connection, sql: &str, params: &[serde_json::Value]) -> Result<Vec<serde_json::Value>> {
+    let mut stmt = conn.prepare(sql).map_err(db_error)?;
+    if !stmt.readonly() {
+        return Err(Error::Database("db_query only runs read-only statements, use db_execute".to_string()));
+    }
+    let columns = stmt.column_count();
+    let mut rows = stmt
+        .query(rusqlite::params_from_iter(params.iter().map(to_sql)))
+        .map_err(db_error)?;
+
+    let mut result = Vec::new();
+    while let Some(row) = rows.next().map_err(db_error)? {
+        let values = (0..columns)
+            .map(|i| row.get_ref(i).map(from_sql))
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)?;
+        result.push(serde_json::Value::Array(values));
+    }
+    Ok(result)
+}
+
+/// Run a statement that returns no rows and return the number of affected rows
+pub(crate) fn execute(conn: &Connection, sql: &str, params: &[serde_json::Value]) -> Result<usize> {
+    conn.execute(sql, rusqlite::params_from_iter(params.iter().map(to_sql)))
+        .map_err(db_error)
+}
+
+/// Copy a database to a new file, consistently even while other
+/// connections write to it
+pub(crate) fn copy(from: &Path, to: &Path) -> Result<()> {
+    // Not `open`: VACUUM INTO attaches the target, which its authorizer denies
+    let conn = Connection::open_with_flags(from, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+        .map_err(db_error)?;
+    conn.busy_timeout(BUSY_TIMEOUT).map_err(db_error)?;
+    conn.execute("VACUUM INTO ?1", [to.to_string_lossy()]).map_err(db_error)?;
+    Ok(())
+}
+
+/// Run blocking database work off the async runtime
+pub(crate) async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| Error::Database(format!("database task failed: {}", e)))?
+}
+
+fn db_error(e: rusqlite::Error) -> Error {
+    Error::Database(e.to_string())
+}
+
+fn to_sql(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        other => Value::Text(other.to_string()),
+    }
+}
+
+fn from_sql(value: ValueRef<'_>) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => serde_json::Number::from_f64(f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into_owned().into(),
+        ValueRef::Blob(b) => crate::base64_encode(b).into(),
+    }
+}
+
+struct OpenTransaction {
+    /// Database path within the kosha
+    database: String,
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// Open transactions of a kosha, each holding its own connection
+#[derive(Default)]
+pub(crate) struct Transactions {
+    open: Mutex<HashMap<String, OpenTransaction>>,
+    counter: AtomicU64,
+    /// Keyed hasher so transaction IDs can't be guessed
+    hasher: RandomState,
+}
+
+impl Transactions {
+    /// Register a connection that has already run `BEGIN` and schedule its
+    /// rollback after `timeout`
+    pub(crate) fn insert(self: &Arc<Self>, database: &str, conn: Connection, timeout: Duration) -> String {
+        let tx_id = format!("tx-{:016x}", self.hasher.hash_one(self.counter.fetch_add(1, Ordering::Relaxed)));
+        self.lock().insert(
+            tx_id.clone(),
+            OpenTransaction {
+                database: database.to_string(),
+                conn: Arc::new(Mutex::new(conn)),
+            },
+        );
+
+        let transactions: Weak<Self> = Arc::downgrade(self);
+        let expired = tx_id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if let Some(transactions) = transactions.upgrade() {
+                // Already committed or rolled back if it's gone
+                let _ = transactions.finish(&expired, "ROLLBACK").await;
+            }
+        });
+        tx_id
+    }
+
+    /// Database a transaction was opened on
+    pub(crate) fn database(&self, tx_id: &str) -> Option<String> {
+        self.lock().get(tx_id).map(|tx| tx.database.clone())
+    }
+
+    /// Connection of an open transaction
+    pub(crate) fn connection(&self, tx_id: &str) -> Result<Arc<Mutex<Connection>>> {
+        self.lock()
+            .get(tx_id)
+            .map(|tx| tx.conn.clone())
+            .ok_or_else(|| Error::TransactionNotFound(tx_id.to_string()))
+    }
+
+    /// Close a transaction with `COMMIT` or `ROLLBACK`
+    ///
+    /// The transaction is gone afterwards even if the statement fails; its
+    /// connection is dropped, which rolls back anything uncommitted.
+    pub(crate) async fn finish(&self, tx_id: &str, statement: &'static str) -> Result<()> {
+        let tx = self
+            .lock()
+            .remove(tx_id)
+            .ok_or_else(|| Error::TransactionNotFound(tx_id.to_string()))?;
+        blocking(move || {
+            let conn = tx.conn.lock().unwrap_or_else(|e| e.into_inner());
+            conn.execute_batch(statement).map_err(db_error)
+        })
+        .await
+    }
+
+    /// Roll back every open transaction, returning how many there were
+    pub(crate) async fn rollback_all(&self) -> usize {
+        let tx_ids: Vec<String> = self.lock().keys().cloned().collect();
+        let mut rolled_back = 0;
+        for tx_id in tx_ids {
+            // Gone already if it finished (or timed out) meanwhile
+            if self.finish(&tx_id, "ROLLBACK").await.is_ok() {
+                rolled_back += 1;
+            }
+        }
+        rolled_back
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, OpenTransaction>> {
+        self.open.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,174 @@
+//! Atomic, durable writes
+//!
+//! Stored files are never written in place. New content goes to a temp file
+//! in tmp/, on the kosha's own file system, and is then renamed over the
+//! destination: readers open either the old or the new content, never part
+//! of one, and a write that fails or is interrupted leaves the old content.
+//! Before a file is replaced, its current content is hard-linked into
+//! history/, so the file exists throughout and the history entry shares the
+//! old content instead of copying it.
+//!
+//! `Durability` sets how much is flushed to disk before a write returns.
+
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Temp files this old were left by a crashed process; `Kosha::open`
+/// removes them
+pub const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// How much of a write reaches the disk before it returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Leave flushing to the OS. Readers still never see partial content,
+    /// but power loss soon after a write may leave the file empty.
+    Buffered,
+    /// Flush new content before it replaces the old. Power loss may undo
+    /// recent writes, but every file and history entry has complete
+    /// content.
+    #[default]
+    Data,
+    /// Also flush directories after each rename, so a write that returned
+    /// survives power loss, and the replaced content is in history/ before
+    /// the new content is live.
+    Full,
+}
+
+impl Durability {
+    fn syncs_data(self) -> bool {
+        self != Durability::Buffered
+    }
+}
+
+/// Numbers temp files within the process
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// A fresh temp file path; the process ID keeps processes sharing a kosha
+/// apart
+fn temp_path(tmp_dir: &Path) -> PathBuf {
+    let n = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+    tmp_dir.join(format!("{}-{}.tmp", std::process::id(), n))
+}
+
+/// Write `content` to a new temp file in `tmp_dir`, returning its path
+pub(crate) async fn write_temp(tmp_dir: &Path, content: &[u8], durability: Durability) -> Result<PathBuf> {
+    let tmp = temp_path(tmp_dir);
+    let written = async {
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(content).await?;
+        if durability.syncs_data() {
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    Ok(tmp)
+}
+
+/// Rename `from` over `to`
+pub(crate) async fn replace(from: &Path, to: &Path, durability: Durability) -> Result<()> {
+    tokio::fs::rename(from, to).await?;
+    sync_parent(to, durability).await
+}
+
+/// Replace `dest` with `content` (temp file, then rename)
+pub(crate) async fn write_atomic(tmp_dir: &Path, dest: &Path, content: &[u8], durability: Durability) -> Result<()> {
+    let tmp = write_temp(tmp_dir, content, durability).await?;
+    if let Err(e) = replace(&tmp, dest, durability).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Put the content of `source` at `dest`, leaving `source` as it is: a hard
+/// link where the file system has them, a copy otherwise
+pub(crate) async fn snapshot(tmp_dir: &Path, source: &Path, dest: &Path, durability: Durability) -> Result<()> {
+    let tmp = temp_path(tmp_dir);
+    let staged = async {
+        if tokio::fs::hard_link(source, &tmp).await.is_err() {
+            tokio::fs::copy(source, &tmp).await?;
+            sync_file(&tmp, durability).await?;
+        }
+        replace(&tmp, dest, durability).await
+    }
+    .await;
+    if let Err(e) = staged {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Put a copy of `source` at `dest`, as a file of its own with a fresh
+/// modification time
+pub(crate) async fn copy_file(tmp_dir: &Path, source: &Path, dest: &Path, durability: Durability) -> Result<()> {
+    let tmp = temp_path(tmp_dir);
+    let copied = async {
+        tokio::fs::copy(source, &tmp).await?;
+        sync_file(&tmp, durability).await?;
+        replace(&tmp, dest, durability).await
+    }
+    .await;
+    if let Err(e) = copied {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Flush a file written without `write_temp`
+pub(crate) async fn sync_file(path: &Path, durability: Durability) -> Result<()> {
+    if durability.syncs_data() {
+        tokio::fs::File::open(path).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+/// Flush the directory holding `path`, making a rename or removal in it
+/// durable
+pub(crate) async fn sync_parent(path: &Path, durability: Durability) -> Result<()> {
+    if durability != Durability::Full {
+        return Ok(());
+    }
+    // Only Unix can open a directory to flush it
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        tokio::fs::File::open(parent).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+/// Remove other processes' temp files older than `STALE_TEMP_AGE`
+///
+/// A hard-linked temp file has the age of its source, so this process's
+/// own, which may belong to a write in progress, are never removed.
+pub(crate) async fn remove_stale(tmp_dir: &Path) -> Result<()> {
+    let own = format!("{}-", std::process::id());
+    let mut dir = tokio::fs::read_dir(tmp_dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        if entry.file_name().to_string_lossy().starts_with(&own) {
+            continue;
+        }
+        let stale = entry
+            .metadata()
+            .await?
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age >= STALE_TEMP_AGE);
+        if stale {
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Copy-on-write forks
+//!
+//! A kosha never changes a stored file in place: a write renames a new file
+//! over the old one after linking the old one into history/, the KV store
+//! and derived artifacts are replaced the same way (see `durable`), and
+//! blobs are never changed at all (see `blob`). So a fork can hard-link
+//! every file instead of copying it; the two koshas then diverge file by file
+//! as either side writes. Databases are the exception (SQLite updates them in
+//! place), so they are copied.
+
+use crate::{db, Result};
+use std::path::{Path, PathBuf};
+
+/// Directories a fork shares with its source. uploads/ is left out:
+/// unfinished uploads stay with the source.
+pub(crate) const SHARED_DIRS: [&str; 5] = ["files", "history", "blobs", "kv", "derived"];
+
+/// Recreate the directory tree `from` at `to`, hard-linking files and
+/// copying databases
+pub(crate) async fn link_tree(from: &Path, to: &Path) -> Result<()> {
+    let mut pending: Vec<(PathBuf, PathBuf)> = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = pending.pop() {
+        tokio::fs::create_dir_all(&to).await?;
+        let mut dir = tokio::fs::read_dir(&from).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name();
+            let (source, target) = (entry.path(), to.join(&name));
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push((source, target));
+            } else if !file_type.is_file() || db::is_database_sidecar(&name) {
+                continue;
+            } else if db::is_database(&name) {
+                db::blocking(move || db::copy(&source, &target)).await?;
+            } else {
+                link_file(&source, &target).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Hard-link a file, copying it where the file system can't link (e.g.
+/// the fork is on another device)
+async fn link_file(source: &Path, target: &Path) -> Result<()> {
+    if tokio::fs::hard_link(source, target).await.is_err() {
+        tokio::fs::copy(source, target).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,263 @@
+//! WASM runtime for get/post handlers and ACL modules
+//!
+//! Handler modules (`foo.wasm`, `foo.json.wasm`, `foo/index.wasm`) have no
+//! imports. The kosha passes the `RequestContext` as JSON and the module
+//! returns a JSON-encoded `Response`:
+//!
+//! ```text
+//! (export "memory" (memory ...))
+//! (export "alloc"  (func (param $len i32) (result i32)))
+//! (export "handle" (func (param $ptr i32) (param $len i32) (result i64)))  ;; (resp_ptr << 32) | resp_len
+//! ```
+//!
+//! The hub's ACL modules use the same shape with an `allowed` export instead
+//! of `handle`, and run on the same `WasmRuntime`.
+//!
+//! One engine is shared by every kosha and hub in the process. Compiled
+//! modules are cached by their location on disk and recompiled when the
+//! content changes. Every call runs in a fresh store with a fuel budget, a
+//! wall-clock deadline and a memory cap. Calls run on a `WasmPool` worker,
+//! with the pool's runtime, and stop at the next epoch tick once their
+//! cancellation token is cancelled.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use wasmtime::{
+    Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap, UpdateDeadline,
+    WasmResults,
+};
+
+/// Granularity of the wall-clock deadline and of cancellation
+const EPOCH_TICK: Duration = Duration::from_millis(5);
+
+/// Error a cancelled call stops with
+const CANCELLED: &str = "cancelled";
+
+/// Resource limits for a single handler call
+#[derive(Debug, Clone, Copy)]
+pub struct HandlerLimits {
+    /// Fuel units (roughly one per WASM instruction)
+    pub fuel: u64,
+    /// Wall-clock limit for instantiation plus the `handle` call
+    pub timeout: Duration,
+    /// Maximum linear memory a module may grow to
+    pub max_memory_bytes: usize,
+    /// Largest response a module may return
+    pub max_response_bytes: usize,
+}
+
+impl Default for HandlerLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 100_000_000,
+            timeout: Duration::from_secs(1),
+            max_memory_bytes: 64 * 1024 * 1024,
+            max_response_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Limits of one store, whatever the module is for
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    /// Fuel units (roughly one per WASM instruction)
+    pub fuel: u64,
+    /// Wall-clock limit for instantiation plus the call
+    pub timeout: Duration,
+    /// Maximum linear memory a module may grow to
+    pub max_memory_bytes: usize,
+}
+
+struct CachedModule {
+    fingerprint: u64,
+    module: Module,
+}
+
+/// Compiles, caches and runs WASM modules
+pub struct WasmRuntime {
+    engine: Engine,
+    /// Compiled modules keyed by their location on disk
+    cache: Mutex<HashMap<String, CachedModule>>,
+    /// Keyed hasher so module bytes can't be crafted to match a cached module
+    hasher: RandomState,
+}
+
+impl WasmRuntime {
+    /// The process-wide runtime, created (with its epoch ticker) on first use
+    pub(crate) fn shared() -> &'static WasmRuntime {
+        static RUNTIME: OnceLock<WasmRuntime> = OnceLock::new();
+        RUNTIME.get_or_init(WasmRuntime::new)
+    }
+
+    fn new() -> Self {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).expect("failed to create WASM engine");
+
+        let ticker = engine.clone();
+        std::thread::Builder::new()
+            .name("fastn-wasm-epoch".to_string())
+            .spawn(move || {
+                loop {
+                    std::thread::sleep(EPOCH_TICK);
+                    ticker.increment_epoch();
+                }
+            })
+            .expect("failed to spawn WASM epoch ticker");
+
+        Self {
+            engine,
+            cache: Mutex::new(HashMap::new()),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Instantiate the module stored at `key`, its location on disk
+    /// (compiling it if the cached copy is missing or stale), and copy
+    /// `ctx_json` into a buffer from its `alloc` export
+    ///
+    /// Blocking: call from a blocking-capable thread.
+    pub fn prepare(
+        &self,
+        key: &str,
+        wasm_bytes: &[u8],
+        ctx_json: &[u8],
+        limits: WasmLimits,
+        cancel: &CancellationToken,
+    ) -> Result<WasmCall, String> {
+        let module = self.module(key, wasm_bytes)?;
+
+        let store_limits = StoreLimitsBuilder::new()
+            .memory_size(limits.max_memory_bytes)
+            .instances(1)
+            .build();
+        let mut store: Store<StoreLimits> = Store::new(&self.engine, store_limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(limits.fuel).map_err(|e| e.to_string())?;
+        let ticks = limits.timeout.as_millis().div_ceil(EPOCH_TICK.as_millis()).max(1) as u64;
+        // Check in on every tick, so cancellation stops the module promptly
+        let (cancel, mut elapsed) = (cancel.clone(), 0);
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(move |_| {
+            elapsed += 1;
+            if cancel.is_cancelled() {
+                return Err(wasmtime::Error::msg(CANCELLED));
+            }
+            if elapsed >= ticks {
+                return Err(Trap::Interrupt.into());
+            }
+            Ok(UpdateDeadline::Continue(1))
+        });
+
+        // No imports are provided: modules are pure functions of the context
+        let instance = Instance::new(&mut store, &module, &[]).map_err(describe_error)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("module does not export `memory`")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(|e| format!("missing `alloc` export: {}", e))?;
+
+        let len = i32::try_from(ctx_json.len()).map_err(|_| "context too large")?;
+        let ptr = alloc.call(&mut store, len).map_err(describe_error)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, ctx_json)
+            .map_err(|_| "`alloc` returned an out-of-bounds pointer")?;
+
+        Ok(WasmCall {
+            store,
+            instance,
+            memory,
+            ctx: (ptr, len),
+        })
+    }
+
+    fn module(&self, key: &str, wasm_bytes: &[u8]) -> Result<Module, String> {
+        let fingerprint = self.hasher.hash_one(wasm_bytes);
+        if let Some(cached) = self.cache.lock().unwrap_or_else(|e| e.into_inner()).get(key)
+            && cached.fingerprint == fingerprint
+        {
+            return Ok(cached.module.clone());
+        }
+
+        // Compile outside the lock so other modules aren't blocked
+        let module = Module::new(&self.engine, wasm_bytes).map_err(|e| format!("invalid module: {}", e))?;
+        self.cache.lock().unwrap_or_else(|e| e.into_inner()).insert(
+            key.to_string(),
+            CachedModule {
+                fingerprint,
+                module: module.clone(),
+            },
+        );
+        Ok(module)
+    }
+}
+
+/// An instantiated module holding the context, ready to be called
+pub struct WasmCall {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+    /// Pointer and length of the context in the module's memory
+    ctx: (i32, i32),
+}
+
+impl WasmCall {
+    /// Call `export(ctx_ptr, ctx_len)`
+    pub fn call<R: WasmResults>(&mut self, export: &str) -> Result<R, String> {
+        let func = self
+            .instance
+            .get_typed_func::<(i32, i32), R>(&mut self.store, export)
+            .map_err(|e| format!("missing `{}` export: {}", export, e))?;
+        func.call(&mut self.store, self.ctx).map_err(describe_error)
+    }
+
+    /// Copy `len` bytes at `ptr` out of the module's memory
+    pub fn read(&self, ptr: usize, len: usize) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![0; len];
+        self.memory
+            .read(&self.store, ptr, &mut bytes)
+            .map_err(|_| "module returned an out-of-bounds buffer")?;
+        Ok(bytes)
+    }
+}
+
+/// Run the handler stored at `location` and return its response JSON
+///
+/// Blocking: call from a blocking-capable thread.
+pub(crate) fn handle(
+    runtime: &WasmRuntime,
+    location: &Path,
+    wasm_bytes: &[u8],
+    ctx_json: &[u8],
+    limits: HandlerLimits,
+    cancel: &CancellationToken,
+) -> Result<Vec<u8>, String> {
+    let store_limits = WasmLimits {
+        fuel: limits.fuel,
+        timeout: limits.timeout,
+        max_memory_bytes: limits.max_memory_bytes,
+    };
+    let key = location.to_string_lossy();
+    let mut call = runtime.prepare(&key, wasm_bytes, ctx_json, store_limits, cancel)?;
+
+    let packed = call.call::<i64>("handle")? as u64;
+    let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+    if out_len > limits.max_response_bytes {
+        return Err(format!("response of {} bytes exceeds the limit", out_len));
+    }
+    call.read(out_ptr, out_len)
+}
+
+fn describe_error(error: wasmtime::Error) -> String {
+    match error.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => "fuel limit exceeded".to_string(),
+        Some(Trap::Interrupt) => "time limit exceeded".to_string(),
+        _ => error.to_string(),
+    }
+}
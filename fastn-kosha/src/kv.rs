@@ -0,0 +1,244 @@
+//! CRDT key-value store, backed by dson
+//!
+//! The store is a dson observed-remove map whose entries are multi-value
+//! registers. A write replaces every value its writer had seen; a delete
+//! removes the key as that writer saw it. Merging two stores is a dson join,
+//! which is commutative, associative and idempotent: two hubs can exchange
+//! their states in any order, any number of times, and end up identical. A
+//! delete is not undone by merging an older copy of the key, while a write
+//! the deleting hub hadn't seen survives.
+//!
+//! Writes nobody saw the other of leave several values in a register. Each
+//! value carries the stamp of its write, taken from a hybrid logical clock
+//! and ordered by `(time_ms, counter, actor)`, and reads return the value
+//! with the largest stamp, so every hub resolves the conflict the same way.
+
+use dson::crdts::mvreg::MvRegValue;
+use dson::{CausalDotStore, Identifier, OrMap};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+
+/// How far ahead of the local wall clock a merged stamp may pull the clock
+///
+/// A peer with a clock set far in the future would otherwise drag every
+/// later local stamp along with it.
+const MAX_CLOCK_DRIFT_MS: i64 = 60_000;
+
+/// Stamp of a KV write
+///
+/// Field order matters: the derived `Ord` compares time, then counter, then
+/// actor as the final tie-break between concurrent writers.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct KvStamp {
+    /// Wall-clock milliseconds, never behind any stamp seen so far
+    pub time_ms: i64,
+    /// Disambiguates writes within the same millisecond
+    pub counter: u32,
+    /// Identity of the writer (the hub's ID52)
+    pub actor: String,
+}
+
+/// A value as kept in a register, with the stamp of its write
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Stamped {
+    value: serde_json::Value,
+    stamp: KvStamp,
+}
+
+/// Full replicated state of a kosha's KV store
+///
+/// This is what gets persisted to `kv/store.dson` and exchanged between hubs
+/// for syncing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KvState {
+    doc: CausalDotStore<OrMap<String>>,
+    /// Largest stamp written or merged, the base for the next local stamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clock: Option<KvStamp>,
+}
+
+impl KvState {
+    /// Current value of a key
+    pub fn get(&self, key: &str) -> Option<serde_json::Value> {
+        self.winner(key).map(|stamped| stamped.value)
+    }
+
+    /// Keys that hold a value
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.doc
+            .store
+            .inner()
+            .keys()
+            .filter(|key| self.winner(key).is_some())
+            .map(String::as_str)
+    }
+
+    /// Record a local write (`None` deletes) and return its stamp
+    pub fn put(&mut self, key: &str, value: Option<serde_json::Value>, actor: &str, now_ms: i64) -> KvStamp {
+        let stamp = self.next_stamp(actor, now_ms);
+        let mut tx = self.doc.transact(actor_identifier(actor));
+        match value {
+            Some(value) => {
+                let stamped = Stamped {
+                    value,
+                    stamp: stamp.clone(),
+                };
+                let encoded = serde_json::to_string(&stamped).expect("JSON values always serialize");
+                tx.write_register(key, MvRegValue::String(encoded));
+            }
+            None => tx.remove(key),
+        }
+        // Hubs exchange whole states, so the delta isn't needed
+        let _ = tx.commit();
+        stamp
+    }
+
+    /// Merge a remote state into this one, returning the keys whose value
+    /// changed
+    pub fn merge(&mut self, other: &KvState, now_ms: i64) -> Vec<String> {
+        let keys: BTreeSet<String> = self
+            .doc
+            .store
+            .inner()
+            .keys()
+            .chain(other.doc.store.inner().keys())
+            .cloned()
+            .collect();
+        let before: Vec<_> = keys.iter().map(|key| self.get(key)).collect();
+
+        self.doc.join_or_replace_with(other.doc.store.clone(), &other.doc.context);
+        for stamped in keys.iter().flat_map(|key| other.values(key)) {
+            self.observe(&stamped.stamp, now_ms);
+        }
+        if let Some(clock) = &other.clock {
+            self.observe(clock, now_ms);
+        }
+
+        keys.into_iter()
+            .zip(before)
+            .filter(|(key, before)| self.get(key) != *before)
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// All concurrent values of a key
+    fn values(&self, key: &str) -> Vec<Stamped> {
+        let Some(entry) = self.doc.store.get(key) else {
+            return Vec::new();
+        };
+        dson::api::register::values(&entry.reg)
+            .filter_map(|value| match value {
+                MvRegValue::String(encoded) => serde_json::from_str(encoded).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The value with the largest stamp
+    fn winner(&self, key: &str) -> Option<Stamped> {
+        self.values(key).into_iter().max_by(|a, b| a.stamp.cmp(&b.stamp))
+    }
+
+    /// Move the clock up to a stamp seen from elsewhere, but no further than
+    /// [`MAX_CLOCK_DRIFT_MS`] ahead of `now_ms`
+    fn observe(&mut self, stamp: &KvStamp, now_ms: i64) {
+        let limit = now_ms.saturating_add(MAX_CLOCK_DRIFT_MS);
+        let stamp = match stamp.time_ms > limit {
+            true => KvStamp {
+                time_ms: limit,
+                counter: 0,
+                actor: stamp.actor.clone(),
+            },
+            false => stamp.clone(),
+        };
+        if self.clock.as_ref().is_none_or(|clock| stamp > *clock) {
+            self.clock = Some(stamp);
+        }
+    }
+
+    /// Hybrid logical clock tick: follow the wall clock, but never go
+    /// backwards relative to anything already seen
+    fn next_stamp(&mut self, actor: &str, now_ms: i64) -> KvStamp {
+        let (time_ms, counter) = match &self.clock {
+            Some(last) if last.time_ms >= now_ms => match last.counter.checked_add(1) {
+                Some(counter) => (last.time_ms, counter),
+                // The millisecond is used up, borrow the next one
+                None => (last.time_ms + 1, 0),
+            },
+            _ => (now_ms, 0),
+        };
+        let stamp = KvStamp {
+            time_ms,
+            counter,
+            actor: actor.to_string(),
+        };
+        self.clock = Some(stamp.clone());
+        stamp
+    }
+}
+
+/// dson replica identifier of a writer
+///
+/// dson identifiers have 20 bits (an 8 bit node and a 12 bit application),
+/// so the actor's ID52 is hashed down to them. Two hubs whose ID52s hash
+/// alike would be taken for one replica; with the handful of hubs a kosha
+/// syncs between that is unlikely, but not impossible.
+fn actor_identifier(actor: &str) -> Identifier {
+    let hash = Sha256::digest(actor.as_bytes());
+    let app = u16::from_be_bytes([hash[1], hash[2]]) & 0x0FFF;
+    Identifier::new(hash[0], app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_counter_overflow_moves_to_next_millisecond() {
+        let mut state = KvState {
+            clock: Some(KvStamp {
+                time_ms: 1_000,
+                counter: u32::MAX,
+                actor: "hub-a".to_string(),
+            }),
+            ..Default::default()
+        };
+        let stamp = state.put("key", Some(json!(1)), "hub-a", 1_000);
+        assert_eq!((stamp.time_ms, stamp.counter), (1_001, 0));
+        let stamp = state.put("key", Some(json!(2)), "hub-a", 1_000);
+        assert_eq!((stamp.time_ms, stamp.counter), (1_001, 1));
+        assert_eq!(state.get("key"), Some(json!(2)));
+    }
+
+    #[test]
+    fn test_remote_clock_is_bounded() {
+        let mut remote = KvState::default();
+        remote.put("key", Some(json!("future")), "hub-b", 10 * MAX_CLOCK_DRIFT_MS);
+
+        let mut local = KvState::default();
+        assert_eq!(local.merge(&remote, 0), vec!["key".to_string()]);
+        let stamp = local.put("other", Some(json!(1)), "hub-a", 0);
+        assert_eq!(stamp.time_ms, MAX_CLOCK_DRIFT_MS);
+
+        // Overwriting a value seen replaces it, whatever its stamp
+        local.put("key", Some(json!("now")), "hub-a", 0);
+        assert_eq!(local.get("key"), Some(json!("now")));
+    }
+
+    #[test]
+    fn test_concurrent_writes_resolve_by_stamp() {
+        let mut a = KvState::default();
+        let mut b = KvState::default();
+        a.put("key", Some(json!("a")), "hub-a", 1_000);
+        b.put("key", Some(json!("b")), "hub-b", 2_000);
+
+        let snapshot = a.clone();
+        a.merge(&b, 2_000);
+        b.merge(&snapshot, 2_000);
+        assert_eq!(a.get("key"), Some(json!("b")));
+        assert_eq!(b.get("key"), Some(json!("b")));
+        assert_eq!(a.values("key").len(), 2);
+    }
+}
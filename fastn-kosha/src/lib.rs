@@ -2,13 +2,54 @@
 //!
 //! A Kosha provides:
 //! - Versioned file storage with automatic history tracking
+//! - Content-addressed blobs, shared by identical versions and copies
+//! - Atomic writes with configurable durability
 //! - CRDT-based key-value store using dson
+//! - SQLite databases (`*.sqlite3` files) with transactions
+//! - Chunked, resumable transfer of large files
+//! - Copy-on-write forks
+//! - Versioned on-disk format with migrations
+//! - Portable snapshots for backups
+//! - Full-text search over file names, content and metadata
+//! - Mount points showing other koshas' folders (resolved by the hub)
 //!
 //! See README.md for full documentation.
 
+mod blob;
+mod db;
+mod durable;
+mod fork;
+mod handler;
+mod kv;
+mod locks;
+mod migrate;
+mod mounts;
+mod pool;
+mod search;
+mod snapshot;
+mod transfer;
+mod watch;
+
+pub use blob::GcReport;
+pub use db::{DATABASE_EXTENSION, DEFAULT_TRANSACTION_TIMEOUT};
+pub use durable::{Durability, STALE_TEMP_AGE};
+pub use handler::{HandlerLimits, WasmCall, WasmLimits, WasmRuntime};
+pub use kv::{KvStamp, KvState};
+pub use migrate::{
+    AppliedMigration, Change, FORMAT_FILE, FORMAT_VERSION, FormatRecord, PlannedMigration, backup, migrate,
+    plan_migrations, read_format,
+};
+pub use mounts::{MOUNTS_FILE, Mount, MountTable};
+pub use pool::{with_cancellation, PoolError, WasmPool, WasmPoolConfig};
+pub use search::{DEFAULT_SEARCH_LIMIT, MAX_INDEXED_SIZE, MAX_SEARCH_LIMIT, SearchHit};
+pub use snapshot::{SNAPSHOT_MANIFEST, SNAPSHOT_VERSION, SnapshotManifest, export_snapshot, import_snapshot};
+pub use transfer::{FileDigest, MAX_CHUNK_SIZE, UPLOAD_EXPIRY, UploadStatus};
+pub use watch::{ChangeEvent, ChangeKind, WATCH_BUFFER, Watcher};
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::Arc;
 use thiserror::Error;
 
 /// Error types for kosha operations
@@ -29,16 +70,142 @@ pub enum Error {
     #[error("Conflict: {0}")]
     Conflict(String),
 
+    /// The file changed since the version the writer based its edit on
+    #[error("Version conflict: {path} was modified")]
+    VersionConflict {
+        path: String,
+        /// Latest version, `None` if the file no longer exists
+        current: Option<FileVersion>,
+    },
+
     #[error("WASM execution error: {0}")]
     WasmExecution(String),
+
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(String),
+
+    #[error("Database error: {0}")]
+    Database(String),
+
+    /// Unknown transaction ID, or the transaction was rolled back on timeout
+    #[error("Transaction not found: {0}")]
+    TransactionNotFound(String),
+
+    /// Unknown upload ID, or the upload was committed, aborted or expired
+    #[error("Upload not found: {0}")]
+    UploadNotFound(String),
+
+    #[error("Invalid range: {0}")]
+    InvalidRange(String),
+
+    /// The uploaded bytes don't hash to what `begin_upload` announced
+    #[error("Integrity check failed for {path}: expected sha256 {expected}, got {actual}")]
+    IntegrityMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// A watcher fell behind and this many changes were dropped
+    #[error("Watcher lagged: {0} changes missed")]
+    WatchLagged(u64),
+
+    /// The write would take the kosha past its storage quota
+    #[error("Storage quota exceeded: {used} of {quota} bytes used, {requested} more requested")]
+    QuotaExceeded {
+        used: u64,
+        quota: u64,
+        requested: u64,
+    },
+
+    /// The kosha was written by a newer build, in a format this one can't
+    /// read
+    #[error("Kosha format version {version} is newer than this build supports ({supported})")]
+    UnsupportedFormat { version: u32, supported: u32 },
+
+    /// The file isn't a kosha snapshot this build can read
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    /// The kosha's `MOUNTS_FILE` can't be parsed
+    #[error("Invalid mount: {0}")]
+    InvalidMount(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Error returned by `Kosha::handle_command`
+///
+/// Conflicts are kept structured so the hub can hand the latest version back
+/// to the spoke for merging.
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("Version conflict: {path} was modified")]
+    Conflict {
+        path: String,
+        current: Option<FileVersion>,
+    },
+
+    /// The kosha is (or would be) over its storage quota
+    #[error("{0}")]
+    QuotaExceeded(String),
+
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl From<Error> for CommandError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::VersionConflict { path, current } => CommandError::Conflict { path, current },
+            e @ Error::QuotaExceeded { .. } => CommandError::QuotaExceeded(e.to_string()),
+            e => CommandError::Failed(e.to_string()),
+        }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Failed(message)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::Failed(message.to_string())
+    }
+}
+
 // ============================================================================
 // Response types for get/post operations
 // ============================================================================
 
+/// Request context passed to get/post WASM handlers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestContext {
+    /// Hub ID of the requesting spoke
+    pub requester_hub_id: String,
+    /// This hub's ID
+    pub current_hub_id: String,
+    /// The spoke requesting access
+    pub spoke_id52: String,
+    /// HTTP method: "GET" or "POST"
+    pub method: String,
+    /// Request path
+    pub path: String,
+    /// Query string (if any)
+    pub query: Option<String>,
+    /// POST payload (JSON)
+    pub payload: Option<serde_json::Value>,
+}
+
+impl RequestContext {
+    /// Check if the requester is the hub owner (same user)
+    pub fn is_owner(&self) -> bool {
+        self.requester_hub_id == self.current_hub_id
+    }
+}
+
 /// Response from get/post operations
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Response {
@@ -152,12 +319,23 @@ pub fn content_type_for_extension(path: &str) -> &'static str {
 }
 
 /// A file version in history
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FileVersion {
     pub timestamp: DateTime<Utc>,
     pub size: u64,
 }
 
+/// Retention rules for history entries, applied after each new entry
+///
+/// The default keeps every version forever.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryPolicy {
+    /// Keep at most this many history entries per file (newest are kept)
+    pub max_versions: Option<usize>,
+    /// Drop history entries older than this
+    pub max_age: Option<chrono::Duration>,
+}
+
 /// A directory entry
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirEntry {
@@ -167,6 +345,35 @@ pub struct DirEntry {
     pub modified: DateTime<Utc>,
 }
 
+/// Storage usage of a kosha
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KoshaStats {
+    /// Current files (excluding history), and the size of their content
+    pub file_count: u64,
+    pub file_bytes: u64,
+    /// Archived versions in history/, and the size of their content
+    pub history_count: u64,
+    pub history_bytes: u64,
+    /// Distinct contents in blobs/, which files and history share
+    #[serde(default)]
+    pub blob_count: u64,
+    #[serde(default)]
+    pub blob_bytes: u64,
+    /// KV keys holding a value
+    pub kv_keys: u64,
+}
+
+/// Outcome of get/post path resolution
+enum Resolved {
+    /// WASM handler to execute
+    Handler(String),
+    /// File to serve as-is
+    Static(String),
+    /// Directory requested without a trailing `/`
+    Directory(String),
+    NotFound,
+}
+
 /// A Kosha - versioned file system with key-value store
 #[derive(Clone)]
 pub struct Kosha {
@@ -174,17 +381,147 @@ pub struct Kosha {
     path: PathBuf,
     /// Unique alias for this kosha within a hub
     alias: String,
+    /// Retention rules for history/
+    history_policy: HistoryPolicy,
+    /// Actor ID stamped on KV writes (the owning hub's ID52)
+    actor_id: String,
+    /// Serializes read-modify-write of kv/store.dson
+    kv_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Resource limits for get/post WASM handlers
+    handler_limits: HandlerLimits,
+    /// Threads the handlers run on
+    worker_pool: Arc<WasmPool>,
+    /// Open database transactions, shared by all clones
+    transactions: Arc<db::Transactions>,
+    /// Open transactions are rolled back after this long
+    transaction_timeout: std::time::Duration,
+    /// Serializes changes to uploads/
+    upload_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Whole-file and per-chunk hashes for downloads, shared by all clones
+    digests: Arc<transfer::DigestCache>,
+    /// Bytes the kosha may hold, `None` for no limit
+    storage_quota: Option<u64>,
+    /// File changes for watchers, shared by all clones
+    changes: tokio::sync::broadcast::Sender<ChangeEvent>,
+    /// On-disk format version at open (see `migrate`)
+    format_version: u32,
+    /// What is flushed to disk before a write returns
+    durability: Durability,
+    /// Held by writes from storing a blob until their manifest is in
+    /// place, and by `gc` throughout
+    blob_lock: Arc<tokio::sync::RwLock<()>>,
+    /// Held by each write to a file from checking its version until the
+    /// new content is in place, shared by all clones
+    file_locks: Arc<locks::FileLocks>,
+    /// Files to bring up to date in the search index, shared by all clones
+    search_queue: Arc<search::SearchQueue>,
 }
 
 impl Kosha {
     /// Create or open a kosha at the given path
+    ///
+    /// Fails with `Error::UnsupportedFormat` if the kosha was written by a
+    /// newer build. Koshas in an older format are opened as they are; see
+    /// `plan_migrations` for bringing them up to date.
     pub async fn open(path: PathBuf, alias: String) -> Result<Self> {
+        let format_version = migrate::open_format(&path).await?;
+
         // Ensure directories exist
         tokio::fs::create_dir_all(path.join("files")).await?;
         tokio::fs::create_dir_all(path.join("history")).await?;
+        tokio::fs::create_dir_all(path.join("blobs")).await?;
         tokio::fs::create_dir_all(path.join("kv")).await?;
+        tokio::fs::create_dir_all(path.join("derived")).await?;
+        tokio::fs::create_dir_all(path.join("uploads")).await?;
+        tokio::fs::create_dir_all(path.join("tmp")).await?;
+        durable::remove_stale(&path.join("tmp")).await?;
+
+        Ok(Self {
+            path,
+            actor_id: alias.clone(),
+            alias,
+            history_policy: HistoryPolicy::default(),
+            kv_lock: Arc::new(tokio::sync::Mutex::new(())),
+            handler_limits: HandlerLimits::default(),
+            worker_pool: WasmPool::shared(),
+            transactions: Arc::new(db::Transactions::default()),
+            transaction_timeout: DEFAULT_TRANSACTION_TIMEOUT,
+            upload_lock: Arc::new(tokio::sync::Mutex::new(())),
+            digests: Arc::new(transfer::DigestCache::default()),
+            storage_quota: None,
+            changes: tokio::sync::broadcast::channel(WATCH_BUFFER).0,
+            format_version,
+            durability: Durability::default(),
+            blob_lock: Arc::new(tokio::sync::RwLock::new(())),
+            file_locks: Arc::new(locks::FileLocks::default()),
+            search_queue: Arc::new(search::SearchQueue::default()),
+        })
+    }
+
+    /// Set the actor ID used to stamp KV writes (builder style)
+    ///
+    /// Defaults to the kosha alias. Hubs set it to their ID52 so concurrent
+    /// writes from different hubs are told apart when merging.
+    pub fn with_actor_id(mut self, actor_id: impl Into<String>) -> Self {
+        self.actor_id = actor_id.into();
+        self
+    }
+
+    /// Actor ID stamped on KV writes
+    pub fn actor_id(&self) -> &str {
+        &self.actor_id
+    }
+
+    /// Set the history retention policy (builder style)
+    pub fn with_history_policy(mut self, policy: HistoryPolicy) -> Self {
+        self.history_policy = policy;
+        self
+    }
+
+    /// Set the resource limits for get/post WASM handlers (builder style)
+    pub fn with_handler_limits(mut self, limits: HandlerLimits) -> Self {
+        self.handler_limits = limits;
+        self
+    }
+
+    /// Run get/post handlers on this pool instead of the process-wide one
+    /// (builder style)
+    pub fn with_worker_pool(mut self, pool: Arc<WasmPool>) -> Self {
+        self.worker_pool = pool;
+        self
+    }
 
-        Ok(Self { path, alias })
+    /// Set how long a database transaction may stay open before it is
+    /// rolled back automatically (builder style)
+    pub fn with_transaction_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.transaction_timeout = timeout;
+        self
+    }
+
+    /// Limit the bytes the kosha may hold, `None` for no limit (builder
+    /// style)
+    ///
+    /// See `storage_used` for what counts. Writes that would go over the
+    /// quota fail with `Error::QuotaExceeded`.
+    pub fn with_storage_quota(mut self, quota: Option<u64>) -> Self {
+        self.storage_quota = quota;
+        self
+    }
+
+    /// Bytes the kosha may hold, `None` for no limit
+    pub fn storage_quota(&self) -> Option<u64> {
+        self.storage_quota
+    }
+
+    /// Set what is flushed to disk before a write returns (builder style)
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// What is flushed to disk before a write returns
+    pub fn durability(&self) -> Durability {
+        self.durability
     }
 
     /// Get the alias of this kosha
@@ -197,23 +534,41 @@ impl Kosha {
         &self.path
     }
 
+    /// On-disk format version, below `FORMAT_VERSION` if the kosha has
+    /// pending migrations
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
     /// Get the files directory path
     fn files_path(&self) -> PathBuf {
         self.path.join("files")
     }
 
-    /// Validate and sanitize a file path to prevent directory traversal
-    fn validate_path(&self, path: &str) -> Result<PathBuf> {
-        // Remove leading slashes
-        let clean_path = path.trim_start_matches('/');
+    /// Get the history directory path
+    fn history_path(&self) -> PathBuf {
+        self.path.join("history")
+    }
 
-        // Check for directory traversal attempts
-        if clean_path.contains("..") {
-            return Err(Error::InvalidPath("Path cannot contain '..'".to_string()));
-        }
+    /// Get the blobs directory path
+    fn blobs_path(&self) -> PathBuf {
+        self.path.join("blobs")
+    }
+
+    /// Get the uploads directory path
+    fn uploads_path(&self) -> PathBuf {
+        self.path.join("uploads")
+    }
+
+    /// Get the directory for temp files of writes in progress
+    fn tmp_path(&self) -> PathBuf {
+        self.path.join("tmp")
+    }
 
+    /// Validate and sanitize a file path to prevent directory traversal
+    fn validate_path(&self, path: &str) -> Result<PathBuf> {
         // Build full path
-        let full_path = self.files_path().join(clean_path);
+        let full_path = self.files_path().join(clean_path(path)?);
 
         // Verify the path is within files directory
         if !full_path.starts_with(&self.files_path()) {
@@ -223,6 +578,56 @@ impl Kosha {
         Ok(full_path)
     }
 
+    // Stored content (see `blob`)
+
+    /// Whether content is stored in blobs/, which koshas in formats from
+    /// before blobs don't do until migrated
+    fn uses_blobs(&self) -> bool {
+        self.format_version >= blob::BLOB_FORMAT_VERSION
+    }
+
+    /// Whether writes to `path` store the content in a blob
+    fn stores_in_blobs(&self, path: &str) -> bool {
+        self.uses_blobs() && !blob::stored_as_is(path)
+    }
+
+    /// The manifest `stored`, an entry of `path` in files/ or history/,
+    /// holds; `None` if it holds the content itself
+    async fn manifest(&self, path: &str, stored: &std::path::Path) -> Result<Option<blob::Manifest>> {
+        match self.stores_in_blobs(path) {
+            true => blob::manifest_at(stored).await,
+            false => Ok(None),
+        }
+    }
+
+    /// Where the content of `stored`, an entry of `path` in files/ or
+    /// history/, is kept
+    async fn content_location(&self, path: &str, stored: &std::path::Path) -> Result<PathBuf> {
+        Ok(match self.manifest(path, stored).await? {
+            Some(manifest) => blob::blob_path(&self.blobs_path(), &manifest.sha256),
+            None => stored.to_path_buf(),
+        })
+    }
+
+    /// Size of the content of `stored`, an entry of `path` in files/ or
+    /// history/
+    async fn content_size(&self, path: &str, stored: &std::path::Path, metadata: &std::fs::Metadata) -> Result<u64> {
+        Ok(match self.manifest(path, stored).await? {
+            Some(manifest) => manifest.size,
+            None => metadata.len(),
+        })
+    }
+
+    /// Write what goes into files/ for `content` to a temp file: the
+    /// content itself, or a manifest once the content is in a blob
+    async fn stage(&self, path: &str, content: &[u8]) -> Result<PathBuf> {
+        if !self.stores_in_blobs(path) {
+            return durable::write_temp(&self.tmp_path(), content, self.durability).await;
+        }
+        let manifest = blob::store(&self.blobs_path(), &self.tmp_path(), content, self.durability).await?;
+        blob::write_manifest(&self.tmp_path(), &manifest, self.durability).await
+    }
+
     // File operations
 
     /// Read a file from files/
@@ -233,30 +638,205 @@ impl Kosha {
             return Err(Error::NotFound(path.to_string()));
         }
 
-        tokio::fs::read(&full_path)
+        tokio::fs::read(self.content_location(path, &full_path).await?)
             .await
             .map_err(|e| Error::Io(e))
     }
 
+    /// Read part of a file
+    ///
+    /// The range is clamped to the end of the file; a range starting past
+    /// the end is an error.
+    pub async fn read_file_range(&self, path: &str, range: impl std::ops::RangeBounds<u64>) -> Result<Vec<u8>> {
+        use std::ops::Bound;
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let full_path = self.validate_path(path)?;
+        if !full_path.is_file() {
+            return Err(Error::NotFound(path.to_string()));
+        }
+
+        let mut file = tokio::fs::File::open(self.content_location(path, &full_path).await?).await?;
+        let size = file.metadata().await?.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => size,
+        }
+        .min(size);
+        if start > size {
+            return Err(Error::InvalidRange(format!("{} is {} bytes, cannot read from {}", path, size, start)));
+        }
+
+        let mut content = Vec::with_capacity(end.saturating_sub(start) as usize);
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+        file.take(end.saturating_sub(start)).read_to_end(&mut content).await?;
+        Ok(content)
+    }
+
+    /// SHA-256 of a file's current content, as lowercase hex
+    pub async fn file_hash(&self, path: &str) -> Result<String> {
+        let full_path = self.validate_path(path)?;
+        if full_path.is_file()
+            && let Some(manifest) = self.manifest(path, &full_path).await?
+        {
+            // The manifest names its blob by it
+            return Ok(manifest.sha256);
+        }
+        Ok(self.file_digest(path).await?.sha256)
+    }
+
+    /// SHA-256 of a file's current content and of each `MAX_CHUNK_SIZE`
+    /// chunk of it
+    ///
+    /// Cached until the file's size or modification time changes, so asking
+    /// again during a download doesn't rehash the file.
+    pub async fn file_digest(&self, path: &str) -> Result<FileDigest> {
+        let full_path = self.validate_path(path)?;
+        if !full_path.is_file() {
+            return Err(Error::NotFound(path.to_string()));
+        }
+        self.digests.digest(&self.content_location(path, &full_path).await?).await
+    }
+
     /// Write a file to files/, creating history entry
-    /// For now, history is not implemented - just writes the file
+    ///
+    /// The content being replaced is kept in history/ under the timestamp
+    /// it was written at. The write is atomic: readers get the old content
+    /// until the new content is complete (see `Durability`).
     pub async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        self.write_file_versioned(path, content, None).await?;
+        Ok(())
+    }
+
+    /// Write a file only if it is still at `base_version`
+    ///
+    /// `base_version` is the version timestamp the writer last read (from
+    /// `read_file`, `get_versions` or a previous write). `None` writes
+    /// unconditionally. On mismatch nothing is written and
+    /// `Error::VersionConflict` carries the latest version. Returns the
+    /// version created by this write.
+    ///
+    /// Writes to the same file are serialized, and each gets a later
+    /// version than the one it replaces (see `next_version`).
+    pub async fn write_file_versioned(
+        &self,
+        path: &str,
+        content: &[u8],
+        base_version: Option<DateTime<Utc>>,
+    ) -> Result<FileVersion> {
+        self.check_quota(content.len() as u64).await?;
+        let _file = self.file_locks.lock(&self.validate_path(path)?).await;
+        let (full_path, kind, version) = self.prepare_write(path, base_version).await?;
+        let blobs = self.blob_lock.read().await;
+        let tmp = self.stage(path, content).await?;
+        let replaced = async {
+            set_version(&tmp, version).await?;
+            self.replace_current(path, &tmp, &full_path).await
+        };
+        if let Err(e) = replaced.await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+        drop(blobs);
+        let version = self.written_version(path, &full_path).await?;
+        self.notify(path, kind, &version);
+        Ok(version)
+    }
+
+    /// Checks shared by every write, made with the file's lock held.
+    /// Returns the location to write the new content to, whether the write
+    /// creates or modifies the file, and the version to give the content.
+    async fn prepare_write(
+        &self,
+        path: &str,
+        base_version: Option<DateTime<Utc>>,
+    ) -> Result<(PathBuf, ChangeKind, DateTime<Utc>)> {
         let full_path = self.validate_path(path)?;
+        self.check_write_conflict(path).await?;
+        self.check_base_version(path, base_version).await?;
 
         // Create parent directories if needed
         if let Some(parent) = full_path.parent() {
             tokio::fs::create_dir_all(parent).await?;
         }
 
-        // TODO: Create history entry before overwriting
+        let kind = match full_path.is_file() {
+            true => ChangeKind::Modified,
+            false => ChangeKind::Created,
+        };
+        let version = self.next_version(path, &full_path).await?;
+        Ok((full_path, kind, version))
+    }
+
+    /// Version for new content of a file: the current second, or if the
+    /// file's current version is that late, the second after it
+    ///
+    /// Versions have second precision, so this keeps each write's version
+    /// distinct and increasing: a `base_version` names exactly one content,
+    /// and history entries never replace one another. A burst of writes
+    /// runs a few seconds ahead of the clock until writes pause.
+    async fn next_version(&self, path: &str, full_path: &std::path::Path) -> Result<DateTime<Utc>> {
+        let now = truncate_timestamp(Utc::now());
+        if full_path.is_file() {
+            let current = version_timestamp(&tokio::fs::metadata(full_path).await?);
+            return Ok(now.max(current + chrono::Duration::seconds(1)));
+        }
+        // Written again after a delete: skip the seconds its history holds
+        let history = self.history_path();
+        let mut version = now;
+        while history.join(history_filename(path.trim_start_matches('/'), version)).exists() {
+            version += chrono::Duration::seconds(1);
+        }
+        Ok(version)
+    }
+
+    /// Make the complete file `new` the current content of `path`, keeping
+    /// the content it replaces in history/
+    async fn replace_current(&self, path: &str, new: &std::path::Path, full_path: &std::path::Path) -> Result<()> {
+        self.archive_current(path, full_path).await?;
+        durable::replace(new, full_path, self.durability).await
+    }
 
-        tokio::fs::write(&full_path, content).await?;
+    /// Fail with `Error::VersionConflict` unless the file is at `base_version`
+    async fn check_base_version(&self, path: &str, base_version: Option<DateTime<Utc>>) -> Result<()> {
+        if let Some(base) = base_version {
+            let current = self.current_version(path).await?;
+            if current.as_ref().map(|v| v.timestamp) != Some(truncate_timestamp(base)) {
+                return Err(Error::VersionConflict {
+                    path: path.to_string(),
+                    current,
+                });
+            }
+        }
         Ok(())
     }
 
+    async fn written_version(&self, path: &str, full_path: &std::path::Path) -> Result<FileVersion> {
+        let metadata = tokio::fs::metadata(full_path).await?;
+        Ok(FileVersion {
+            timestamp: version_timestamp(&metadata),
+            size: self.content_size(path, full_path, &metadata).await?,
+        })
+    }
+
+    /// Version of the current content of a file, `None` if it doesn't exist
+    pub async fn current_version(&self, path: &str) -> Result<Option<FileVersion>> {
+        let full_path = self.validate_path(path)?;
+        if !full_path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(self.written_version(path, &full_path).await?))
+    }
+
     /// List directory contents
     pub async fn list_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
-        let full_path = self.path.join("files").join(path);
+        let full_path = self.validate_path(path)?;
 
         // If path doesn't exist or isn't a directory, return empty list
         if !full_path.exists() {
@@ -277,11 +857,15 @@ impl Kosha {
             let modified = metadata.modified()
                 .map(|t| DateTime::<Utc>::from(t))
                 .unwrap_or_else(|_| Utc::now());
+            let size = match metadata.is_dir() {
+                true => metadata.len(),
+                false => self.content_size(&name, &entry.path(), &metadata).await?,
+            };
 
             entries.push(DirEntry {
                 name,
                 is_dir: metadata.is_dir(),
-                size: metadata.len(),
+                size,
                 modified,
             });
         }
@@ -292,155 +876,1323 @@ impl Kosha {
         Ok(entries)
     }
 
-    /// Get all versions of a file
-    pub async fn get_versions(&self, _path: &str) -> Result<Vec<FileVersion>> {
-        todo!("get_versions")
+    /// Get all versions of a file, newest first
+    ///
+    /// The current content (if the file exists) is the first entry.
+    pub async fn get_versions(&self, path: &str) -> Result<Vec<FileVersion>> {
+        let mut versions: Vec<FileVersion> = self.current_version(path).await?.into_iter().collect();
+        versions.extend(self.history_versions(path).await?);
+
+        if versions.is_empty() {
+            return Err(Error::NotFound(path.to_string()));
+        }
+
+        // Stable sort keeps the current content ahead of a history entry
+        // from the same second
+        versions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        versions.dedup_by_key(|v| v.timestamp);
+        Ok(versions)
     }
 
     /// Read a specific version from history
-    pub async fn read_version(&self, _path: &str, _timestamp: DateTime<Utc>) -> Result<Vec<u8>> {
-        todo!("read_version")
+    ///
+    /// The timestamp must be one returned by `get_versions`.
+    pub async fn read_version(&self, path: &str, timestamp: DateTime<Utc>) -> Result<Vec<u8>> {
+        let full_path = self.validate_path(path)?;
+        let clean_path = path.trim_start_matches('/');
+
+        // The current content is also a version
+        if full_path.is_file() {
+            let metadata = tokio::fs::metadata(&full_path).await?;
+            if version_timestamp(&metadata) == truncate_timestamp(timestamp) {
+                return tokio::fs::read(self.content_location(path, &full_path).await?).await.map_err(Error::Io);
+            }
+        }
+
+        let history_file = self.history_path().join(history_filename(clean_path, timestamp));
+        if history_file.is_file() {
+            return tokio::fs::read(self.content_location(path, &history_file).await?).await.map_err(Error::Io);
+        }
+
+        Err(Error::NotFound(format!("{} @ {}", path, timestamp.to_rfc3339())))
     }
 
     /// Rename a file
-    pub async fn rename(&self, _from: &str, _to: &str) -> Result<()> {
-        todo!("rename")
+    ///
+    /// History entries and derived artifacts move along with the file.
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let from_path = self.validate_path(from)?;
+        let to_path = self.validate_path(to)?;
+        let _files = self.file_locks.lock_pair(&from_path, &to_path).await;
+
+        if !from_path.is_file() {
+            return Err(Error::NotFound(from.to_string()));
+        }
+        if to_path.exists() {
+            return Err(Error::Conflict(format!("{} already exists", to)));
+        }
+        self.check_write_conflict(to).await?;
+
+        if let Some(parent) = to_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        durable::replace(&from_path, &to_path, self.durability).await?;
+        self.move_history(from, to).await?;
+        self.move_derived(from, to).await?;
+
+        let version = self.written_version(to, &to_path).await?;
+        let from = from.trim_start_matches('/').to_string();
+        self.notify(to, ChangeKind::Renamed { from }, &version);
+        Ok(())
     }
 
     /// Delete a file (creates final history entry)
-    pub async fn delete(&self, _path: &str) -> Result<()> {
-        todo!("delete")
-    }
+    ///
+    /// Its derived artifacts are removed: they describe content that is
+    /// gone, and a new file at the path gets its own.
+    pub async fn delete(&self, path: &str) -> Result<()> {
+        let full_path = self.validate_path(path)?;
+        let _file = self.file_locks.lock(&full_path).await;
 
-    // Key-value operations - to be implemented
+        if !full_path.is_file() {
+            return Err(Error::NotFound(path.to_string()));
+        }
 
-    /// Get a value from the KV store
-    pub async fn kv_get(&self, _key: &str) -> Result<Option<serde_json::Value>> {
-        todo!("kv_get")
+        let version = self.written_version(path, &full_path).await?;
+        self.archive_current(path, &full_path).await?;
+        tokio::fs::remove_file(&full_path).await?;
+        durable::sync_parent(&full_path, self.durability).await?;
+        self.remove_derived(path).await?;
+        self.notify(path, ChangeKind::Deleted, &version);
+        Ok(())
     }
 
-    /// Set a value in the KV store
-    pub async fn kv_set(&self, _key: &str, _value: serde_json::Value) -> Result<()> {
-        todo!("kv_set")
-    }
+    // Directory operations
 
-    /// Delete a key from the KV store
-    pub async fn kv_delete(&self, _key: &str) -> Result<()> {
-        todo!("kv_delete")
-    }
+    /// Every file under `path`, as kosha paths in name order; just `path`
+    /// if it is a file
+    pub async fn list_files(&self, path: &str) -> Result<Vec<String>> {
+        let full_path = self.validate_path(path)?;
+        if full_path.is_file() {
+            return Ok(vec![clean_path(path)?.to_string()]);
+        }
+        if !full_path.is_dir() {
+            return Err(Error::NotFound(path.to_string()));
+        }
 
-    // ========================================================================
-    // Get/Post operations - HTTP-like semantics with WASM execution
-    // ========================================================================
+        let files_path = self.files_path();
+        let mut files = Vec::new();
+        let mut pending = vec![full_path];
+        while let Some(dir) = pending.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_dir() {
+                    pending.push(entry.path());
+                } else if let Ok(relative) = entry.path().strip_prefix(&files_path) {
+                    let components: Vec<_> = relative.iter().map(|c| c.to_string_lossy()).collect();
+                    files.push(components.join("/"));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
 
-    /// Handle a GET request with HTTP-like semantics
+    /// Delete a directory and everything in it
     ///
-    /// Resolution order:
-    /// 1. If path ends with `/`, try `{path}.wasm`, then `{path}index.wasm`
-    /// 2. If `{path}.wasm` exists, execute it
-    /// 3. Otherwise, serve static file with appropriate content-type
-    pub async fn get(&self, _path: &str) -> Result<Response> {
-        todo!("get")
+    /// Each file is deleted as by `delete`, so it gets a final history
+    /// entry and a change event. Returns the deleted files.
+    pub async fn delete_dir(&self, path: &str) -> Result<Vec<String>> {
+        let full_path = self.validate_path(path)?;
+        if clean_path(path)?.trim_end_matches('/').is_empty() {
+            return Err(Error::InvalidPath("Cannot delete the kosha root".to_string()));
+        }
+        if full_path.is_file() {
+            return Err(Error::InvalidPath(format!("{} is not a directory", path)));
+        }
+
+        let files = self.list_files(path).await?;
+        for file in &files {
+            self.delete(file).await?;
+        }
+        tokio::fs::remove_dir_all(&full_path).await?;
+        durable::sync_parent(&full_path, self.durability).await?;
+        Ok(files)
     }
 
-    /// Handle a POST request with HTTP-like semantics
+    /// Copy a file or directory to `to`, which must not exist yet
     ///
-    /// Resolution order (same as GET):
-    /// 1. If path ends with `/`, try `{path}.wasm`, then `{path}index.wasm`
-    /// 2. If `{path}.wasm` exists, execute it with payload
-    /// 3. Otherwise, error (can't POST to static files)
-    pub async fn post(&self, _path: &str, _payload: serde_json::Value) -> Result<Response> {
-        todo!("post")
+    /// Copies are new files: each starts its own history, and the
+    /// source's history stays with the source. Derived artifacts are
+    /// copied along. Only manifests are copied, so the copies share the
+    /// source's blobs. Returns the new files.
+    pub async fn copy(&self, from: &str, to: &str) -> Result<Vec<String>> {
+        let (from, to, files) = self.prepare_relocation(from, to).await?;
+        let _blobs = self.blob_lock.read().await;
+
+        let mut size = 0;
+        for file in &files {
+            size += tokio::fs::metadata(self.validate_path(file)?).await?.len();
+        }
+        self.check_quota(size).await?;
+
+        let mut copied = Vec::with_capacity(files.len());
+        for file in &files {
+            let dest = relocated_path(file, &from, &to);
+            let dest_path = self.validate_path(&dest)?;
+            if let Some(parent) = dest_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            durable::copy_file(&self.tmp_path(), &self.validate_path(file)?, &dest_path, self.durability).await?;
+            self.copy_derived(file, &dest).await?;
+            let version = self.written_version(&dest, &dest_path).await?;
+            self.notify(&dest, ChangeKind::Created, &version);
+            copied.push(dest);
+        }
+        Ok(copied)
     }
 
-    /// Check if writing to a path would create a conflict
+    /// Move a file or directory to `to`, which must not exist yet
     ///
-    /// Returns error if:
-    /// - Writing `foo.json` when `foo.json.wasm` exists
-    /// - Writing `foo.json.wasm` when `foo.json` exists
-    /// - Writing `foo.wasm` when `foo/index.wasm` exists
-    /// - Writing `foo/index.wasm` when `foo.wasm` exists
-    pub async fn check_write_conflict(&self, _path: &str) -> Result<()> {
-        todo!("check_write_conflict")
+    /// A directory is renamed in one step, so readers find all of it at
+    /// one path or the other. History entries and derived artifacts move
+    /// along with each file, as for `rename`. Returns the (old, new) path of each file.
+    pub async fn move_path(&self, from: &str, to: &str) -> Result<Vec<(String, String)>> {
+        let (from, to, files) = self.prepare_relocation(from, to).await?;
+
+        let to_path = self.validate_path(&to)?;
+        if let Some(parent) = to_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        durable::replace(&self.validate_path(&from)?, &to_path, self.durability).await?;
+        durable::sync_parent(&self.validate_path(&from)?, self.durability).await?;
+
+        let mut moved = Vec::with_capacity(files.len());
+        for file in files {
+            let dest = relocated_path(&file, &from, &to);
+            self.move_history(&file, &dest).await?;
+            self.move_derived(&file, &dest).await?;
+            let version = self.written_version(&dest, &self.validate_path(&dest)?).await?;
+            self.notify(&dest, ChangeKind::Renamed { from: file.clone() }, &version);
+            moved.push((file, dest));
+        }
+        Ok(moved)
     }
 
-    // ========================================================================
-    // SQLite database operations
-    // ========================================================================
+    /// Checks shared by `copy` and `move_path`: `from` exists, `to` doesn't
+    /// and isn't inside `from`, and no file would land where it conflicts
+    /// with a handler. Returns both paths cleaned, and the files to relocate.
+    async fn prepare_relocation(&self, from: &str, to: &str) -> Result<(String, String, Vec<String>)> {
+        let from = clean_path(from)?.trim_end_matches('/').to_string();
+        let to = clean_path(to)?.trim_end_matches('/').to_string();
+        if from.is_empty() || to.is_empty() {
+            return Err(Error::InvalidPath("Cannot copy or move the kosha root".to_string()));
+        }
+        if to == from || to.starts_with(&format!("{}/", from)) {
+            return Err(Error::InvalidPath(format!("Cannot copy or move {} into itself", from)));
+        }
+        if self.validate_path(&to)?.exists() {
+            return Err(Error::Conflict(format!("{} already exists", to)));
+        }
 
-    /// Execute a read-only query on a database
-    ///
-    /// Returns rows as JSON arrays
-    pub async fn db_query(
-        &self,
-        _database: &str,
-        _sql: &str,
-        _params: Vec<serde_json::Value>,
-    ) -> Result<Vec<serde_json::Value>> {
-        todo!("db_query")
+        let files = self.list_files(&from).await?;
+        for file in &files {
+            self.check_write_conflict(&relocated_path(file, &from, &to)).await?;
+        }
+        Ok((from, to, files))
     }
 
-    /// Execute a write statement on a database
-    ///
-    /// Returns the number of affected rows
-    pub async fn db_execute(
-        &self,
-        _database: &str,
-        _sql: &str,
-        _params: Vec<serde_json::Value>,
-    ) -> Result<usize> {
-        todo!("db_execute")
-    }
+    // Watching
 
-    /// Begin a database transaction
+    /// Follow changes to files under `path_prefix` (a directory or file
+    /// path; empty for the whole kosha)
     ///
-    /// Returns a transaction ID. Transactions have a maximum duration
-    /// (default 30 seconds) after which they are automatically rolled back.
-    pub async fn db_begin(&self, _database: &str) -> Result<String> {
-        todo!("db_begin")
+    /// Only changes made after this call are seen. Writes to databases and
+    /// derived content aren't reported.
+    pub fn watch(&self, path_prefix: &str) -> Result<Watcher> {
+        let prefix = clean_path(path_prefix)?.trim_end_matches('/').to_string();
+        Ok(Watcher::new(prefix, self.changes.subscribe()))
     }
 
-    /// Execute a statement within a transaction
-    pub async fn db_tx_execute(
-        &self,
-        _tx_id: &str,
-        _sql: &str,
-        _params: Vec<serde_json::Value>,
-    ) -> Result<usize> {
-        todo!("db_tx_execute")
+    fn notify(&self, path: &str, kind: ChangeKind, version: &FileVersion) {
+        self.search_queue.changed(path);
+        if let ChangeKind::Renamed { from } = &kind {
+            self.search_queue.changed(from);
+        }
+        // Sending only fails when nobody is watching
+        let _ = self.changes.send(ChangeEvent {
+            path: path.trim_start_matches('/').to_string(),
+            kind,
+            version: version.clone(),
+        });
     }
 
-    /// Query within a transaction
-    pub async fn db_tx_query(
-        &self,
-        _tx_id: &str,
-        _sql: &str,
-        _params: Vec<serde_json::Value>,
-    ) -> Result<Vec<serde_json::Value>> {
-        todo!("db_tx_query")
-    }
+    // History
 
-    /// Commit a transaction
-    pub async fn db_commit(&self, _tx_id: &str) -> Result<()> {
-        todo!("db_commit")
+    /// Copy the current content of a file into history/, if there is any
+    ///
+    /// The file itself stays until the caller replaces or removes it, so
+    /// readers never find it missing. Each write gets its own version (see
+    /// `next_version`), so entries don't replace one another.
+    async fn archive_current(&self, path: &str, full_path: &std::path::Path) -> Result<()> {
+        if !full_path.is_file() {
+            return Ok(());
+        }
+
+        let metadata = tokio::fs::metadata(full_path).await?;
+        let timestamp = version_timestamp(&metadata);
+        let history_file = self
+            .history_path()
+            .join(history_filename(path.trim_start_matches('/'), timestamp));
+
+        durable::snapshot(&self.tmp_path(), full_path, &history_file, self.durability).await?;
+
+        self.prune_history(path).await
     }
 
-    /// Rollback a transaction
-    pub async fn db_rollback(&self, _tx_id: &str) -> Result<()> {
-        todo!("db_rollback")
+    /// Move the history entries of `from` over to `to`
+    async fn move_history(&self, from: &str, to: &str) -> Result<()> {
+        let to = to.trim_start_matches('/');
+        let history = self.history_path();
+        for (name, timestamp) in self.history_entries(from).await? {
+            tokio::fs::rename(history.join(name), history.join(history_filename(to, timestamp))).await?;
+        }
+        Ok(())
     }
-}
 
-/// Convert a file path to a flat history filename
-/// e.g., "foo/bar/baz.txt" -> "foo~bar~baz.txt"
-pub fn flatten_path(path: &str) -> String {
-    path.replace('/', "~")
-}
+    /// History entries for a file as (history filename, timestamp)
+    async fn history_entries(&self, path: &str) -> Result<Vec<(String, DateTime<Utc>)>> {
+        let prefix = format!("{}__", flatten_path(path.trim_start_matches('/')));
+        let mut entries = Vec::new();
+        let mut dir = tokio::fs::read_dir(self.history_path()).await?;
 
-/// Convert a flat history filename back to a path
-/// e.g., "foo~bar~baz.txt" -> "foo/bar/baz.txt"
-pub fn unflatten_path(flat: &str) -> String {
-    flat.replace('~', "/")
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(ts) = name.strip_prefix(&prefix) else {
+                continue;
+            };
+            // Rejects longer paths sharing the prefix, e.g. "a.txt__x__<ts>"
+            if let Some(timestamp) = parse_history_timestamp(ts) {
+                entries.push((name, timestamp));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    async fn history_versions(&self, path: &str) -> Result<Vec<FileVersion>> {
+        let mut versions = Vec::new();
+        for (name, timestamp) in self.history_entries(path).await? {
+            let location = self.history_path().join(name);
+            let metadata = tokio::fs::metadata(&location).await?;
+            versions.push(FileVersion {
+                timestamp,
+                size: self.content_size(path, &location, &metadata).await?,
+            });
+        }
+        Ok(versions)
+    }
+
+    /// Apply the history policy to the entries of one file
+    pub async fn prune_history(&self, path: &str) -> Result<()> {
+        let policy = &self.history_policy;
+        if policy.max_versions.is_none() && policy.max_age.is_none() {
+            return Ok(());
+        }
+
+        let mut entries = self.history_entries(path).await?;
+        entries.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let cutoff = policy.max_age.map(|age| Utc::now() - age);
+        for (i, (name, timestamp)) in entries.iter().enumerate() {
+            let too_many = policy.max_versions.is_some_and(|max| i >= max);
+            let too_old = cutoff.is_some_and(|cutoff| *timestamp < cutoff);
+            if too_many || too_old {
+                tokio::fs::remove_file(self.history_path().join(name)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Derived content (generated from files, e.g. asset metadata)
+
+    /// Path of a derived artifact: derived/<flattened path>__<name>
+    ///
+    /// Names can't hold `__`, so each artifact belongs to exactly one path.
+    fn derived_file_path(&self, path: &str, name: &str) -> Result<PathBuf> {
+        self.validate_path(path)?;
+        if name.contains('/') || name.contains("..") || name.contains("__") {
+            return Err(Error::InvalidPath(format!("Invalid derived name: {}", name)));
+        }
+        let flat = flatten_path(path.trim_start_matches('/'));
+        Ok(self.path.join("derived").join(format!("{}__{}", flat, name)))
+    }
+
+    /// Store a derived artifact for a file (not versioned, not listed in files/)
+    ///
+    /// Written to a temp file, then renamed over, so forks sharing the old
+    /// artifact keep it.
+    pub async fn write_derived(&self, path: &str, name: &str, content: &[u8]) -> Result<()> {
+        let full_path = self.derived_file_path(path, name)?;
+        durable::write_atomic(&self.tmp_path(), &full_path, content, self.durability).await?;
+        self.search_queue.changed(path);
+        Ok(())
+    }
+
+    /// Read a derived artifact for a file
+    pub async fn read_derived(&self, path: &str, name: &str) -> Result<Vec<u8>> {
+        let full_path = self.derived_file_path(path, name)?;
+        if !full_path.exists() {
+            return Err(Error::NotFound(format!("{} ({})", path, name)));
+        }
+        tokio::fs::read(&full_path).await.map_err(Error::Io)
+    }
+
+    /// Names of the derived artifacts stored for a file
+    async fn derived_entries(&self, path: &str) -> Result<Vec<String>> {
+        let prefix = format!("{}__", flatten_path(path.trim_start_matches('/')));
+        Ok(self
+            .derived_names()
+            .await?
+            .into_iter()
+            .filter_map(|name| name.strip_prefix(&prefix).map(str::to_string))
+            // Rejects longer paths sharing the prefix, e.g. "a.glb__x__meta.json"
+            .filter(|name| !name.contains("__"))
+            .collect())
+    }
+
+    /// Move the derived artifacts of `from` over to `to`
+    async fn move_derived(&self, from: &str, to: &str) -> Result<()> {
+        for name in self.derived_entries(from).await? {
+            tokio::fs::rename(self.derived_file_path(from, &name)?, self.derived_file_path(to, &name)?).await?;
+        }
+        Ok(())
+    }
+
+    /// Give `to` a copy of each derived artifact of `from`
+    async fn copy_derived(&self, from: &str, to: &str) -> Result<()> {
+        for name in self.derived_entries(from).await? {
+            let content = tokio::fs::read(self.derived_file_path(from, &name)?).await?;
+            self.write_derived(to, &name, &content).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the derived artifacts of a deleted file
+    async fn remove_derived(&self, path: &str) -> Result<()> {
+        for name in self.derived_entries(path).await? {
+            tokio::fs::remove_file(self.derived_file_path(path, &name)?).await?;
+        }
+        Ok(())
+    }
+
+    // Mounts
+
+    /// Mount points listed in `MOUNTS_FILE`, none if there is no such file
+    pub async fn mounts(&self) -> Result<MountTable> {
+        match self.read_file(MOUNTS_FILE).await {
+            Ok(content) => MountTable::parse(&String::from_utf8_lossy(&content)),
+            Err(Error::NotFound(_)) => Ok(MountTable::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Search
+
+    /// Files under `path_prefix` (a directory or file path; empty for the
+    /// whole kosha) matching `query`, best first, at most `limit` of them
+    /// (capped at `MAX_SEARCH_LIMIT`)
+    ///
+    /// Files changed since the last search are indexed first (see
+    /// `search`).
+    pub async fn search(&self, query: &str, path_prefix: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let prefix = clean_path(path_prefix)?.trim_end_matches('/').to_string();
+        let limit = limit.min(MAX_SEARCH_LIMIT);
+        self.update_search_index().await?;
+        let index = self.path.join(search::INDEX_FILE);
+        let query = query.to_string();
+        db::blocking(move || search::query(&search::open(&index)?, &query, &prefix, limit)).await
+    }
+
+    /// Index the queued files, and on the first search every file whose
+    /// indexed version is out of date
+    async fn update_search_index(&self) -> Result<()> {
+        let _updating = self.search_queue.lock().await;
+        let (paths, compare_all) = self.search_queue.take();
+        let result = self.index_files(paths, compare_all).await;
+        // Queued paths are lost with a failed update: compare everything next time
+        self.search_queue.set_reconciled(result.is_ok());
+        result
+    }
+
+    async fn index_files(&self, mut paths: std::collections::BTreeSet<String>, compare_all: bool) -> Result<()> {
+        let index = self.path.join(search::INDEX_FILE);
+        if compare_all || !index.is_file() {
+            let indexed = {
+                let index = index.clone();
+                db::blocking(move || search::indexed_stamps(&search::open(&index)?)).await?
+            };
+            let files = self.list_files("").await?;
+            for file in &files {
+                let stamp = file_stamp(&tokio::fs::metadata(self.validate_path(file)?).await?);
+                if indexed.get(file) != Some(&stamp) {
+                    paths.insert(file.clone());
+                }
+            }
+            let files: std::collections::HashSet<&String> = files.iter().collect();
+            paths.extend(indexed.into_keys().filter(|path| !files.contains(path)));
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let derived = self.derived_names().await?;
+        let (mut documents, mut removed) = (Vec::new(), Vec::new());
+        for path in paths {
+            match self.search_document(&path, &derived).await? {
+                Some(document) => documents.push(document),
+                None => removed.push(path),
+            }
+        }
+        db::blocking(move || search::update(&mut search::open(&index)?, &documents, &removed)).await
+    }
+
+    /// What the index holds for a file, `None` if it no longer exists
+    async fn search_document(&self, path: &str, derived: &[String]) -> Result<Option<search::Document>> {
+        let full_path = self.validate_path(path)?;
+        if !full_path.is_file() {
+            return Ok(None);
+        }
+        let metadata = tokio::fs::metadata(&full_path).await?;
+        let version = self.written_version(path, &full_path).await?;
+        let is_database = path.ends_with(DATABASE_EXTENSION);
+        let body = match !is_database && version.size <= MAX_INDEXED_SIZE {
+            true => search::indexable_text(self.read_file(path).await?).unwrap_or_default(),
+            false => String::new(),
+        };
+
+        let prefix = format!("{}__", flatten_path(path));
+        let mut meta = Vec::new();
+        for name in derived.iter().filter_map(|name| name.strip_prefix(&prefix)) {
+            if let Ok(content) = self.read_derived(path, name).await
+                && let Some(text) = search::indexable_text(content)
+            {
+                meta.push(text);
+            }
+        }
+        Ok(Some(search::Document {
+            path: path.to_string(),
+            modified: version.timestamp,
+            stamp: file_stamp(&metadata),
+            body,
+            meta: meta.join("\n"),
+        }))
+    }
+
+    /// File names in derived/
+    async fn derived_names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut dir = tokio::fs::read_dir(self.path.join("derived")).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+        Ok(names)
+    }
+
+    // Forks
+
+    /// Create a copy-on-write fork of this kosha at `path`, which must not
+    /// exist yet
+    ///
+    /// Files with their history, derived artifacts and the KV store are
+    /// hard-linked rather than copied, so forking takes time proportional to
+    /// the number of files, not their size, and uses no extra space until
+    /// one side writes. Databases are copied. Unfinished uploads stay with
+    /// this kosha. Writes made while the fork runs may or may not be in it.
+    ///
+    /// The fork is opened like `Kosha::open`, with default settings.
+    pub async fn fork(&self, path: PathBuf, alias: String) -> Result<Kosha> {
+        if tokio::fs::try_exists(&path).await? {
+            return Err(Error::Conflict(format!("{} already exists", path.display())));
+        }
+        let linked = async {
+            for dir in fork::SHARED_DIRS {
+                fork::link_tree(&self.path.join(dir), &path.join(dir)).await?;
+            }
+            migrate::copy_format(&self.path, &path).await
+        }
+        .await;
+        if let Err(e) = linked {
+            // Don't leave a half-made kosha behind
+            let _ = tokio::fs::remove_dir_all(&path).await;
+            return Err(e);
+        }
+        Kosha::open(path, alias).await
+    }
+
+    // Key-value operations
+
+    fn kv_store_path(&self) -> PathBuf {
+        self.path.join("kv").join("store.dson")
+    }
+
+    async fn load_kv(&self) -> Result<KvState> {
+        match tokio::fs::read(self.kv_store_path()).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(KvState::default()),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    /// Persist the KV state (write to a temp file, then rename over)
+    async fn save_kv(&self, state: &KvState) -> Result<()> {
+        let content = serde_json::to_vec(state)?;
+        durable::write_atomic(&self.tmp_path(), &self.kv_store_path(), &content, self.durability).await
+    }
+
+    fn validate_key(key: &str) -> Result<()> {
+        if key.is_empty() || key.contains("..") {
+            return Err(Error::InvalidPath(format!("Invalid key: {}", key)));
+        }
+        Ok(())
+    }
+
+    /// Get a value from the KV store
+    pub async fn kv_get(&self, key: &str) -> Result<Option<serde_json::Value>> {
+        Self::validate_key(key)?;
+        Ok(self.load_kv().await?.get(key))
+    }
+
+    /// Set a value in the KV store
+    pub async fn kv_set(&self, key: &str, value: serde_json::Value) -> Result<()> {
+        self.kv_put(key, Some(value)).await
+    }
+
+    /// Delete a key from the KV store
+    ///
+    /// The delete wins over the copies of the key this hub has seen; a write
+    /// made elsewhere without seeing them survives the merge.
+    pub async fn kv_delete(&self, key: &str) -> Result<()> {
+        self.kv_put(key, None).await
+    }
+
+    async fn kv_put(&self, key: &str, value: Option<serde_json::Value>) -> Result<()> {
+        Self::validate_key(key)?;
+        if let Some(value) = &value {
+            self.check_quota((key.len() + value.to_string().len()) as u64).await?;
+        }
+        let _guard = self.kv_lock.lock().await;
+        let mut state = self.load_kv().await?;
+        state.put(key, value, &self.actor_id, Utc::now().timestamp_millis());
+        self.save_kv(&state).await
+    }
+
+    /// Full replicated KV state, for sending to another hub
+    pub async fn kv_state(&self) -> Result<KvState> {
+        self.load_kv().await
+    }
+
+    /// Merge KV state from another hub, returning the keys whose value changed
+    pub async fn kv_merge(&self, remote: &KvState) -> Result<Vec<String>> {
+        let _guard = self.kv_lock.lock().await;
+        let mut state = self.load_kv().await?;
+        let changed = state.merge(remote, Utc::now().timestamp_millis());
+        self.save_kv(&state).await?;
+        Ok(changed)
+    }
+
+    /// Count files and bytes stored in this kosha
+    pub async fn stats(&self) -> Result<KoshaStats> {
+        let mut stats = KoshaStats::default();
+        for stored in blob::stored_files(&self.path).await? {
+            let manifest = match self.uses_blobs() && !stored.as_is {
+                true => blob::manifest_at(&stored.location).await?,
+                false => None,
+            };
+            let size = match manifest {
+                Some(manifest) => manifest.size,
+                None => tokio::fs::metadata(&stored.location).await?.len(),
+            };
+            if stored.history {
+                stats.history_count += 1;
+                stats.history_bytes += size;
+            } else {
+                stats.file_count += 1;
+                stats.file_bytes += size;
+            }
+        }
+        (stats.blob_count, stats.blob_bytes) = Self::dir_usage(self.blobs_path()).await?;
+        stats.kv_keys = self.load_kv().await?.keys().count() as u64;
+        Ok(stats)
+    }
+
+    /// Bytes counted against the storage quota
+    ///
+    /// Blobs, files stored as they are (databases, and everything in koshas
+    /// from before blobs) with their history, and the KV store, plus the
+    /// announced size of unfinished uploads so parallel uploads can't
+    /// overrun the quota together. Manifests don't count, so content shared
+    /// by several versions or copies counts once. Deleting a file frees no
+    /// space while its history is kept, nor afterwards until `gc` runs.
+    pub async fn storage_used(&self) -> Result<u64> {
+        let mut stored_bytes = 0;
+        for stored in blob::stored_files(&self.path).await? {
+            let manifest = match self.uses_blobs() && !stored.as_is {
+                true => blob::manifest_at(&stored.location).await?,
+                false => None,
+            };
+            if manifest.is_none() {
+                stored_bytes += tokio::fs::metadata(&stored.location).await?.len();
+            }
+        }
+        let (_, blob_bytes) = Self::dir_usage(self.blobs_path()).await?;
+        let (_, kv_bytes) = Self::dir_usage(self.path.join("kv")).await?;
+
+        let mut reserved = 0;
+        let mut dir = tokio::fs::read_dir(self.uploads_path()).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(upload_id) = name.strip_suffix(".json")
+                && let Ok(upload) = self.load_upload(upload_id).await
+            {
+                reserved += upload.size;
+            }
+        }
+        Ok(stored_bytes + blob_bytes + kv_bytes + reserved)
+    }
+
+    /// Fail with `Error::QuotaExceeded` if `requested` more bytes don't fit
+    ///
+    /// Once the kosha is full even writes of unknown size (database
+    /// statements) are refused.
+    async fn check_quota(&self, requested: u64) -> Result<()> {
+        let Some(quota) = self.storage_quota else {
+            return Ok(());
+        };
+        let used = self.storage_used().await?;
+        if used >= quota || used.saturating_add(requested) > quota {
+            return Err(Error::QuotaExceeded { used, quota, requested });
+        }
+        Ok(())
+    }
+
+    /// Remove the blobs that no file or history entry names any more, or
+    /// with `apply` false only count them
+    ///
+    /// Writes in this process wait while it runs; stop the hub server
+    /// before running it from another process. Koshas in a format from
+    /// before blobs have none to remove.
+    pub async fn gc(&self, apply: bool) -> Result<GcReport> {
+        if !self.uses_blobs() {
+            return Ok(GcReport::default());
+        }
+        let _blobs = self.blob_lock.write().await;
+        let referenced = blob::referenced(&self.path).await?;
+        blob::collect(&self.blobs_path(), &referenced, apply).await
+    }
+
+    /// Recursively count regular files and their total size
+    async fn dir_usage(root: PathBuf) -> Result<(u64, u64)> {
+        let (mut count, mut bytes) = (0, 0);
+        let mut pending = vec![root];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else {
+                    count += 1;
+                    bytes += metadata.len();
+                }
+            }
+        }
+        Ok((count, bytes))
+    }
+
+    // ========================================================================
+    // Get/Post operations - HTTP-like semantics with WASM execution
+    // ========================================================================
+
+    /// Handle a GET request with HTTP-like semantics
+    ///
+    /// Resolution order (for `ctx.path`):
+    /// 1. If path ends with `/`, try `{path}.wasm`, then `{path}index.wasm`
+    /// 2. If `{path}.wasm` exists, execute it
+    /// 3. Otherwise, serve static file with appropriate content-type
+    ///
+    /// Directories requested without a trailing `/` redirect to `{path}/`.
+    /// `_`-prefixed WASM files are ACL modules and are never served.
+    pub async fn get(&self, ctx: &RequestContext) -> Result<Response> {
+        match self.resolve_request(&ctx.path).await? {
+            Resolved::Handler(path) => self.run_handler(&path, ctx).await,
+            Resolved::Static(path) => self.serve_static(&path).await,
+            Resolved::Directory(path) => Ok(Response::redirect(&format!("{}/", path))),
+            Resolved::NotFound => Ok(Response::not_found()),
+        }
+    }
+
+    /// Handle a POST request with HTTP-like semantics
+    ///
+    /// Resolution order (same as GET):
+    /// 1. If path ends with `/`, try `{path}.wasm`, then `{path}index.wasm`
+    /// 2. If `{path}.wasm` exists, execute it with payload
+    /// 3. Otherwise, error (can't POST to static files)
+    pub async fn post(&self, ctx: &RequestContext) -> Result<Response> {
+        match self.resolve_request(&ctx.path).await? {
+            Resolved::Handler(path) => self.run_handler(&path, ctx).await,
+            Resolved::Static(path) | Resolved::Directory(path) => Err(Error::MethodNotAllowed(format!(
+                "cannot POST to {}",
+                path
+            ))),
+            Resolved::NotFound => Ok(Response::not_found()),
+        }
+    }
+
+    /// Apply the get/post resolution rules to a request path
+    async fn resolve_request(&self, path: &str) -> Result<Resolved> {
+        let path = path.trim_start_matches('/');
+        self.validate_path(path)?;
+
+        if path.is_empty() || path.ends_with('/') {
+            let dir = path.trim_end_matches('/');
+            if !dir.is_empty() && self.is_handler(&format!("{}.wasm", dir)).await {
+                return Ok(Resolved::Handler(format!("{}.wasm", dir)));
+            }
+            let index = format!("{}index.wasm", path);
+            if self.is_handler(&index).await {
+                return Ok(Resolved::Handler(index));
+            }
+            return Ok(Resolved::NotFound);
+        }
+
+        if path.ends_with(".wasm") {
+            return Ok(match self.is_handler(path).await {
+                true => Resolved::Handler(path.to_string()),
+                false => Resolved::NotFound,
+            });
+        }
+
+        let handler = format!("{}.wasm", path);
+        if self.is_handler(&handler).await {
+            return Ok(Resolved::Handler(handler));
+        }
+
+        Ok(match tokio::fs::metadata(self.validate_path(path)?).await {
+            Ok(metadata) if metadata.is_dir() => Resolved::Directory(path.to_string()),
+            Ok(_) => Resolved::Static(path.to_string()),
+            Err(_) => Resolved::NotFound,
+        })
+    }
+
+    /// Check that a WASM file exists and may handle requests
+    async fn is_handler(&self, path: &str) -> bool {
+        let filename = path.rsplit('/').next().unwrap_or(path);
+        if filename.starts_with('_') {
+            return false;
+        }
+        self.is_file(path).await
+    }
+
+    async fn is_file(&self, path: &str) -> bool {
+        match self.validate_path(path) {
+            Ok(full_path) => tokio::fs::metadata(full_path).await.is_ok_and(|m| m.is_file()),
+            Err(_) => false,
+        }
+    }
+
+    async fn serve_static(&self, path: &str) -> Result<Response> {
+        let content = self.read_file(path).await?;
+        let etag = match self.current_version(path).await? {
+            Some(version) => format!("\"{}-{}\"", version.timestamp.timestamp(), version.size),
+            None => return Err(Error::NotFound(path.to_string())),
+        };
+        Ok(Response::bytes(content_type_for_extension(path), content).with_etag(&etag))
+    }
+
+    /// Execute a handler module with the request context
+    async fn run_handler(&self, path: &str, ctx: &RequestContext) -> Result<Response> {
+        let wasm_bytes = self.read_file(path).await?;
+        let location = self.validate_path(path)?;
+        let ctx_json = serde_json::to_vec(ctx)?;
+        let limits = self.handler_limits;
+
+        // Concurrency is limited per handler file
+        let module = location.to_string_lossy().into_owned();
+        let runtime = self.worker_pool.runtime();
+        let output = self
+            .worker_pool
+            .run(&module, move |cancel| handler::handle(runtime, &location, &wasm_bytes, &ctx_json, limits, cancel))
+            .await
+            .map_err(|e| Error::WasmExecution(format!("{}: {}", path, e)))?
+            .map_err(|e| Error::WasmExecution(format!("{}: {}", path, e)))?;
+
+        serde_json::from_slice(&output)
+            .map_err(|e| Error::WasmExecution(format!("{}: invalid response: {}", path, e)))
+    }
+
+    /// Check if writing to a path would create a conflict
+    ///
+    /// Returns error if:
+    /// - Writing `foo.json` when `foo.json.wasm` exists
+    /// - Writing `foo.json.wasm` when `foo.json` exists
+    /// - Writing `foo.wasm` when `foo/index.wasm` exists
+    /// - Writing `foo/index.wasm` when `foo.wasm` exists
+    pub async fn check_write_conflict(&self, path: &str) -> Result<()> {
+        let path = path.trim_start_matches('/');
+        let filename = path.rsplit('/').next().unwrap_or(path);
+
+        let mut rivals = Vec::new();
+        match path.strip_suffix(".wasm") {
+            // ACL modules never handle requests, so they can't shadow anything
+            Some(_) if filename.starts_with('_') => {}
+            Some(target) => {
+                rivals.push(target.to_string());
+                match target.strip_suffix("/index") {
+                    Some(dir) => rivals.push(format!("{}.wasm", dir)),
+                    None => rivals.push(format!("{}/index.wasm", target)),
+                }
+            }
+            None => rivals.push(format!("{}.wasm", path)),
+        }
+
+        for rival in rivals {
+            if self.is_file(&rival).await {
+                return Err(Error::Conflict(format!("{} conflicts with existing {}", path, rival)));
+            }
+        }
+        Ok(())
+    }
+
+    // ========================================================================
+    // Chunked uploads
+    // ========================================================================
+
+    /// Start (or resume) a chunked upload of `size` bytes hashing to `sha256`
+    ///
+    /// Returns how much has been received already: 0 for a new upload, more
+    /// when an earlier upload with the same arguments was interrupted.
+    /// `base_version` works as for `write_file_versioned` and is checked again
+    /// on commit.
+    pub async fn begin_upload(
+        &self,
+        path: &str,
+        size: u64,
+        sha256: &str,
+        base_version: Option<DateTime<Utc>>,
+    ) -> Result<UploadStatus> {
+        self.validate_path(path)?;
+        self.check_write_conflict(path).await?;
+        transfer::validate_sha256(sha256)?;
+        self.check_base_version(path, base_version).await?;
+
+        let upload = transfer::Upload {
+            path: path.trim_start_matches('/').to_string(),
+            size,
+            sha256: sha256.to_string(),
+            base_version: base_version.map(truncate_timestamp),
+        };
+        let upload_id = upload.id();
+
+        let _guard = self.upload_lock.lock().await;
+        self.prune_uploads().await?;
+        let (meta, part) = self.upload_files(&upload_id);
+        if !meta.is_file() {
+            // The whole size is reserved now; chunks and the commit aren't checked
+            self.check_quota(size).await?;
+            tokio::fs::write(&part, b"").await?;
+            tokio::fs::write(&meta, serde_json::to_vec(&upload)?).await?;
+        }
+        self.upload_status(&upload_id).await
+    }
+
+    /// Append a chunk to an upload
+    ///
+    /// `offset` must be the `received` count of the last status, so a
+    /// retried chunk that already arrived is rejected instead of duplicated.
+    pub async fn upload_chunk(&self, upload_id: &str, offset: u64, content: &[u8]) -> Result<UploadStatus> {
+        use tokio::io::AsyncWriteExt;
+
+        if content.len() > MAX_CHUNK_SIZE {
+            return Err(Error::InvalidRange(format!(
+                "chunk of {} bytes exceeds the {} byte limit",
+                content.len(),
+                MAX_CHUNK_SIZE
+            )));
+        }
+
+        let _guard = self.upload_lock.lock().await;
+        let status = self.upload_status(upload_id).await?;
+        if offset != status.received {
+            return Err(Error::Conflict(format!("upload {} continues at offset {}", upload_id, status.received)));
+        }
+        if status.received + content.len() as u64 > status.size {
+            return Err(Error::InvalidRange(format!("upload {} is only {} bytes", upload_id, status.size)));
+        }
+
+        let (_, part) = self.upload_files(upload_id);
+        let mut file = tokio::fs::OpenOptions::new().append(true).open(&part).await?;
+        file.write_all(content).await?;
+        file.flush().await?;
+        Ok(UploadStatus {
+            received: status.received + content.len() as u64,
+            ..status
+        })
+    }
+
+    /// Verify a complete upload and make it the current version of its file
+    ///
+    /// An upload that doesn't match its announced hash is discarded.
+    pub async fn commit_upload(&self, upload_id: &str) -> Result<FileVersion> {
+        let _guard = self.upload_lock.lock().await;
+        let status = self.upload_status(upload_id).await?;
+        if status.received != status.size {
+            return Err(Error::InvalidRange(format!(
+                "upload {} has {} of {} bytes",
+                upload_id, status.received, status.size
+            )));
+        }
+
+        let upload = self.load_upload(upload_id).await?;
+        let (meta, part) = self.upload_files(upload_id);
+        let actual = transfer::sha256_file(&part).await?;
+        if actual != upload.sha256 {
+            self.remove_upload(upload_id).await?;
+            return Err(Error::IntegrityMismatch {
+                path: upload.path,
+                expected: upload.sha256,
+                actual,
+            });
+        }
+
+        let _file = self.file_locks.lock(&self.validate_path(&upload.path)?).await;
+        let (full_path, kind, version) = self.prepare_write(&upload.path, upload.base_version).await?;
+        durable::sync_file(&part, self.durability).await?;
+        let blobs = self.blob_lock.read().await;
+        let new = match self.stores_in_blobs(&upload.path) {
+            true => {
+                let manifest = blob::store_file(&self.blobs_path(), &part, &upload.sha256, self.durability).await?;
+                blob::write_manifest(&self.tmp_path(), &manifest, self.durability).await?
+            }
+            false => part,
+        };
+        set_version(&new, version).await?;
+        self.replace_current(&upload.path, &new, &full_path).await?;
+        drop(blobs);
+        tokio::fs::remove_file(&meta).await?;
+        let version = self.written_version(&upload.path, &full_path).await?;
+        self.notify(&upload.path, kind, &version);
+        Ok(version)
+    }
+
+    /// Discard an unfinished upload
+    pub async fn abort_upload(&self, upload_id: &str) -> Result<()> {
+        let _guard = self.upload_lock.lock().await;
+        self.load_upload(upload_id).await?;
+        self.remove_upload(upload_id).await
+    }
+
+    /// File an upload will be committed to, `None` if there is no such upload
+    pub async fn upload_target(&self, upload_id: &str) -> Option<String> {
+        self.load_upload(upload_id).await.ok().map(|upload| upload.path)
+    }
+
+    /// Progress of an upload
+    pub async fn upload_status(&self, upload_id: &str) -> Result<UploadStatus> {
+        let upload = self.load_upload(upload_id).await?;
+        let (_, part) = self.upload_files(upload_id);
+        let received = tokio::fs::metadata(&part)
+            .await
+            .map_err(|_| Error::UploadNotFound(upload_id.to_string()))?
+            .len();
+        Ok(UploadStatus {
+            upload_id: upload_id.to_string(),
+            received,
+            size: upload.size,
+        })
+    }
+
+    /// `uploads/<id>.json` and `uploads/<id>.part`
+    fn upload_files(&self, upload_id: &str) -> (PathBuf, PathBuf) {
+        let uploads = self.uploads_path();
+        (uploads.join(format!("{}.json", upload_id)), uploads.join(format!("{}.part", upload_id)))
+    }
+
+    async fn load_upload(&self, upload_id: &str) -> Result<transfer::Upload> {
+        transfer::validate_upload_id(upload_id)?;
+        let (meta, _) = self.upload_files(upload_id);
+        match tokio::fs::read(&meta).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::UploadNotFound(upload_id.to_string())),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    async fn remove_upload(&self, upload_id: &str) -> Result<()> {
+        let (meta, part) = self.upload_files(upload_id);
+        for file in [meta, part] {
+            match tokio::fs::remove_file(&file).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(Error::Io(e)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove uploads that received nothing for `UPLOAD_EXPIRY`
+    async fn prune_uploads(&self) -> Result<()> {
+        let mut dir = tokio::fs::read_dir(self.uploads_path()).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(upload_id) = name.strip_suffix(".part") else {
+                continue;
+            };
+            let idle = entry.metadata().await?.modified()?.elapsed().unwrap_or_default();
+            if idle > UPLOAD_EXPIRY {
+                self.remove_upload(upload_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // ========================================================================
+    // SQLite database operations
+    // ========================================================================
+
+    /// Location of a database file, which must have the `.sqlite3` extension
+    fn database_path(&self, database: &str) -> Result<PathBuf> {
+        if !database.ends_with(DATABASE_EXTENSION) || database.ends_with(&format!("/{}", DATABASE_EXTENSION)) {
+            return Err(Error::InvalidPath(format!("{} is not a {} database", database, DATABASE_EXTENSION)));
+        }
+        self.validate_path(database)
+    }
+
+    /// Execute a read-only query on a database
+    ///
+    /// Returns rows as JSON arrays (see the `db` module for the encoding)
+    pub async fn db_query(
+        &self,
+        database: &str,
+        sql: &str,
+        params: Vec<serde_json::Value>,
+    ) -> Result<Vec<serde_json::Value>> {
+        let path = self.database_path(database)?;
+        if !path.is_file() {
+            return Err(Error::NotFound(database.to_string()));
+        }
+        let sql = sql.to_string();
+        db::blocking(move || db::query(&db::open(&path, false)?, &sql, &params)).await
+    }
+
+    /// Execute a write statement on a database, creating the database if needed
+    ///
+    /// Returns the number of affected rows
+    pub async fn db_execute(
+        &self,
+        database: &str,
+        sql: &str,
+        params: Vec<serde_json::Value>,
+    ) -> Result<usize> {
+        let path = self.database_path(database)?;
+        self.check_quota(0).await?;
+        self.create_database_dir(&path).await?;
+        let sql = sql.to_string();
+        db::blocking(move || db::execute(&db::open(&path, true)?, &sql, &params)).await
+    }
+
+    /// Begin a database transaction
+    ///
+    /// Returns a transaction ID. Transactions have a maximum duration
+    /// (default 30 seconds) after which they are automatically rolled back.
+    /// The write lock is taken immediately, so other writers wait (up to a
+    /// few seconds) until the transaction ends.
+    pub async fn db_begin(&self, database: &str) -> Result<String> {
+        let path = self.database_path(database)?;
+        self.check_quota(0).await?;
+        self.create_database_dir(&path).await?;
+        let conn = db::blocking(move || {
+            let conn = db::open(&path, true)?;
+            conn.execute_batch("BEGIN IMMEDIATE")
+                .map_err(|e| Error::Database(e.to_string()))?;
+            Ok(conn)
+        })
+        .await?;
+        let database = database.trim_start_matches('/');
+        Ok(self.transactions.insert(database, conn, self.transaction_timeout))
+    }
+
+    /// Execute a statement within a transaction
+    pub async fn db_tx_execute(
+        &self,
+        tx_id: &str,
+        sql: &str,
+        params: Vec<serde_json::Value>,
+    ) -> Result<usize> {
+        let conn = self.transactions.connection(tx_id)?;
+        let sql = sql.to_string();
+        db::blocking(move || db::execute(&conn.lock().unwrap_or_else(|e| e.into_inner()), &sql, &params)).await
+    }
+
+    /// Query within a transaction (sees the transaction's own writes)
+    pub async fn db_tx_query(
+        &self,
+        tx_id: &str,
+        sql: &str,
+        params: Vec<serde_json::Value>,
+    ) -> Result<Vec<serde_json::Value>> {
+        let conn = self.transactions.connection(tx_id)?;
+        let sql = sql.to_string();
+        db::blocking(move || db::query(&conn.lock().unwrap_or_else(|e| e.into_inner()), &sql, &params)).await
+    }
+
+    /// Commit a transaction
+    pub async fn db_commit(&self, tx_id: &str) -> Result<()> {
+        self.transactions.finish(tx_id, "COMMIT").await
+    }
+
+    /// Rollback a transaction
+    pub async fn db_rollback(&self, tx_id: &str) -> Result<()> {
+        self.transactions.finish(tx_id, "ROLLBACK").await
+    }
+
+    /// Roll back every open transaction, returning how many there were
+    ///
+    /// Called when the kosha's server shuts down, so no database is left
+    /// locked by a transaction its client can no longer finish.
+    pub async fn db_rollback_all(&self) -> usize {
+        self.transactions.rollback_all().await
+    }
+
+    /// Database a transaction belongs to, `None` if it is no longer open
+    pub fn db_transaction_database(&self, tx_id: &str) -> Option<String> {
+        self.transactions.database(tx_id)
+    }
+
+    async fn create_database_dir(&self, path: &std::path::Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Strip leading slashes from a file path and reject anything that could
+/// leave the kosha: `..` anywhere, NUL bytes, and components other than
+/// plain names (e.g. a Windows drive prefix)
+///
+/// Names are compared byte for byte, so NFC and NFD spellings of the same
+/// name are different files, as they are on the file system.
+pub fn clean_path(path: &str) -> Result<&str> {
+    // Remove leading slashes
+    let clean_path = path.trim_start_matches('/');
+
+    // Check for directory traversal attempts
+    if clean_path.contains("..") {
+        return Err(Error::InvalidPath("Path cannot contain '..'".to_string()));
+    }
+    if clean_path.contains('\0') {
+        return Err(Error::InvalidPath("Path cannot contain NUL".to_string()));
+    }
+    let plain = std::path::Path::new(clean_path)
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir));
+    if !plain {
+        return Err(Error::InvalidPath(format!("Invalid path: {}", path)));
+    }
+    Ok(clean_path)
+}
+
+/// Convert a file path to a flat history filename
+/// e.g., "foo/bar/baz.txt" -> "foo~bar~baz.txt"
+///
+/// `/` becomes `~`; a literal `~` or `%` is percent-escaped (`%7E`, `%25`)
+/// so that "a~b" and "a/b" don't share history.
+pub fn flatten_path(path: &str) -> String {
+    let mut flat = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '/' => flat.push('~'),
+            '~' => flat.push_str("%7E"),
+            '%' => flat.push_str("%25"),
+            c => flat.push(c),
+        }
+    }
+    flat
+}
+
+/// Convert a flat history filename back to a path
+/// e.g., "foo~bar~baz.txt" -> "foo/bar/baz.txt"
+pub fn unflatten_path(flat: &str) -> String {
+    let mut path = String::with_capacity(flat.len());
+    let mut rest = flat;
+    while let Some(c) = rest.chars().next() {
+        let (decoded, len) = match c {
+            '~' => ('/', 1),
+            '%' if rest.starts_with("%7E") => ('~', 3),
+            '%' if rest.starts_with("%25") => ('%', 3),
+            c => (c, c.len_utf8()),
+        };
+        path.push(decoded);
+        rest = &rest[len..];
+    }
+    path
+}
+
+/// Parse the timestamp part of a history filename
+fn parse_history_timestamp(ts: &str) -> Option<DateTime<Utc>> {
+    chrono::NaiveDateTime::parse_from_str(ts, "%Y%m%dT%H%M%SZ")
+        .ok()
+        .map(|naive| naive.and_utc())
+}
+
+/// History filenames have second precision, so versions are identified by
+/// whole seconds
+fn truncate_timestamp(timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    DateTime::from_timestamp(timestamp.timestamp(), 0).unwrap_or(timestamp)
+}
+
+/// Version timestamp of the content currently on disk
+fn version_timestamp(metadata: &std::fs::Metadata) -> DateTime<Utc> {
+    let modified = metadata
+        .modified()
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now());
+    truncate_timestamp(modified)
+}
+
+/// Give staged content its version: versions are modification times
+async fn set_version(staged: &std::path::Path, version: DateTime<Utc>) -> Result<()> {
+    let file = tokio::fs::File::options().write(true).open(staged).await?;
+    file.into_std().await.set_modified(version.into())?;
+    Ok(())
+}
+
+/// Size and modification time of a file in files/, for the search index
+fn file_stamp(metadata: &std::fs::Metadata) -> search::FileStamp {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos() as i64);
+    (metadata.len(), modified)
+}
+
+/// Where `file`, which is `from` or under it, ends up when `from` is copied
+/// or moved to `to` (all three cleaned, without trailing slashes)
+/// e.g., ("docs/a/b.txt", "docs", "archive/docs") -> "archive/docs/a/b.txt"
+pub fn relocated_path(file: &str, from: &str, to: &str) -> String {
+    format!("{}{}", to, file.strip_prefix(from).unwrap_or_default())
+}
+
+/// The file a history filename belongs to
+/// e.g., "foo~bar.txt__20241224T153045Z" -> "foo/bar.txt"
+pub(crate) fn history_source(name: &str) -> Option<String> {
+    let (flat, ts) = name.rsplit_once("__")?;
+    parse_history_timestamp(ts).map(|_| unflatten_path(flat))
 }
 
 /// Generate a history filename for a given path and timestamp
@@ -456,28 +2208,58 @@ impl Kosha {
     /// Commands:
     /// - read_file: { path: string } -> { content: base64, modified: timestamp }
     /// - write_file: { path: string, content: base64, base_version?: timestamp } -> { modified: timestamp }
+    ///   (fails with `CommandError::Conflict` if the file is no longer at base_version)
     /// - list_dir: { path: string } -> { entries: [...] }
     /// - get_versions: { path: string } -> { versions: [...] }
     /// - read_version: { path: string, timestamp: string } -> { content: base64 }
     /// - rename: { from: string, to: string } -> {}
     /// - delete: { path: string } -> {}
+    /// - delete_dir: { path: string } -> { deleted: [path, ...] }
+    /// - copy: { from: string, to: string } -> { copied: [path, ...] }
+    /// - move: { from: string, to: string } -> { moved: [{ from, to }, ...] }
+    ///   (copy and move take a file or directory; `to` must not exist)
+    /// - read_derived: { path: string, name: string } -> { content: base64 }
+    /// - read_range: { path: string, offset: number, length?: number, sha256?: hex } -> { content: base64, offset: number, size: number, modified: timestamp }
+    ///   (length defaults to and is capped at MAX_CHUNK_SIZE; size is the whole file;
+    ///   fails with `CommandError::Conflict` if the content no longer has sha256)
+    /// - file_hash: { path: string } -> { sha256: hex, size: number, modified: timestamp, chunk_size: number, chunks: [hex] }
+    /// - begin_upload: { path: string, size: number, sha256: hex, base_version?: timestamp } -> { upload_id, received, size }
+    /// - upload_chunk: { upload_id: string, path: string, offset: number, content: base64 } -> { upload_id, received, size }
+    /// - commit_upload: { upload_id: string, path: string } -> { modified: timestamp }
+    /// - abort_upload: { upload_id: string, path: string } -> {}
     /// - kv_get: { key: string } -> { value: json | null }
     /// - kv_set: { key: string, value: json } -> {}
     /// - kv_delete: { key: string } -> {}
+    /// - kv_state: {} -> { state: KvState }
+    /// - kv_merge: { state: KvState } -> { changed: [key, ...] }
+    /// - db_query: { database: string, sql: string, params?: [json] } -> { rows: [[json]] }
+    /// - db_execute: { database: string, sql: string, params?: [json] } -> { affected: number }
+    /// - db_begin: { database: string } -> { tx_id: string }
+    /// - db_tx_query: { database: string, tx_id: string, sql: string, params?: [json] } -> { rows: [[json]] }
+    /// - db_tx_execute: { database: string, tx_id: string, sql: string, params?: [json] } -> { affected: number }
+    /// - db_commit: { database: string, tx_id: string } -> {}
+    /// - db_rollback: { database: string, tx_id: string } -> {}
+    /// - search: { query: string, path_prefix?: string, limit?: number } -> { hits: [{ path, score, snippet, modified }] }
+    ///   (limit defaults to DEFAULT_SEARCH_LIMIT and is capped at MAX_SEARCH_LIMIT)
+    ///
+    /// Transaction and upload commands name the database or file too, so the
+    /// hub can check access without looking up the transaction or upload.
     pub async fn handle_command(
         &self,
         command: &str,
         payload: serde_json::Value,
-    ) -> std::result::Result<serde_json::Value, String> {
+    ) -> std::result::Result<serde_json::Value, CommandError> {
         match command {
             "read_file" => {
                 let path = payload.get("path")
                     .and_then(|v| v.as_str())
                     .ok_or("missing 'path' field")?;
-                let content = self.read_file(path).await.map_err(|e| e.to_string())?;
+                let content = self.read_file(path).await?;
+                let modified = self.current_version(path).await?.map(|v| v.timestamp);
                 // Return base64 encoded content
                 Ok(serde_json::json!({
                     "content": base64_encode(&content),
+                    "modified": modified,
                 }))
             }
             "write_file" => {
@@ -489,12 +2271,10 @@ impl Kosha {
                     .ok_or("missing 'content' field")?;
                 let content = base64_decode(content_b64)
                     .map_err(|e| format!("invalid base64: {}", e))?;
-                let _base_version = payload.get("base_version")
-                    .and_then(|v| v.as_str());
-                // TODO: implement optimistic locking with base_version
-                self.write_file(path, &content).await.map_err(|e| e.to_string())?;
+                let base_version = base_version(&payload)?;
+                let version = self.write_file_versioned(path, &content, base_version).await?;
                 Ok(serde_json::json!({
-                    "modified": Utc::now(),
+                    "modified": version.timestamp,
                 }))
             }
             "list_dir" => {
@@ -542,6 +2322,132 @@ impl Kosha {
                 self.delete(path).await.map_err(|e| e.to_string())?;
                 Ok(serde_json::json!({}))
             }
+            "delete_dir" => {
+                let path = payload.get("path")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'path' field")?;
+                let deleted = self.delete_dir(path).await?;
+                Ok(serde_json::json!({ "deleted": deleted }))
+            }
+            "copy" => {
+                let from = payload.get("from")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'from' field")?;
+                let to = payload.get("to")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'to' field")?;
+                let copied = self.copy(from, to).await?;
+                Ok(serde_json::json!({ "copied": copied }))
+            }
+            "move" => {
+                let from = payload.get("from")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'from' field")?;
+                let to = payload.get("to")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'to' field")?;
+                let moved: Vec<_> = self.move_path(from, to).await?
+                    .into_iter()
+                    .map(|(from, to)| serde_json::json!({ "from": from, "to": to }))
+                    .collect();
+                Ok(serde_json::json!({ "moved": moved }))
+            }
+            "read_derived" => {
+                let path = payload.get("path")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'path' field")?;
+                let name = payload.get("name")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'name' field")?;
+                let content = self.read_derived(path, name).await.map_err(|e| e.to_string())?;
+                Ok(serde_json::json!({
+                    "content": base64_encode(&content),
+                }))
+            }
+            "read_range" => {
+                let path = payload.get("path")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'path' field")?;
+                let offset = payload.get("offset")
+                    .and_then(|v| v.as_u64())
+                    .ok_or("missing 'offset' field")?;
+                let length = payload.get("length")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(MAX_CHUNK_SIZE as u64)
+                    .min(MAX_CHUNK_SIZE as u64);
+                let version = self.current_version(path).await?
+                    .ok_or_else(|| Error::NotFound(path.to_string()))?;
+                if let Some(sha256) = payload.get("sha256").and_then(|v| v.as_str()) {
+                    transfer::validate_sha256(sha256)?;
+                    if self.file_hash(path).await? != sha256 {
+                        return Err(Error::VersionConflict {
+                            path: path.to_string(),
+                            current: Some(version),
+                        }
+                        .into());
+                    }
+                }
+                let content = self.read_file_range(path, offset..offset.saturating_add(length)).await?;
+                Ok(serde_json::json!({
+                    "content": base64_encode(&content),
+                    "offset": offset,
+                    "size": version.size,
+                    "modified": version.timestamp,
+                }))
+            }
+            "file_hash" => {
+                let path = payload.get("path")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'path' field")?;
+                let version = self.current_version(path).await?
+                    .ok_or_else(|| Error::NotFound(path.to_string()))?;
+                let digest = self.file_digest(path).await?;
+                Ok(serde_json::json!({
+                    "sha256": digest.sha256,
+                    "size": version.size,
+                    "modified": version.timestamp,
+                    "chunk_size": MAX_CHUNK_SIZE,
+                    "chunks": digest.chunks,
+                }))
+            }
+            "begin_upload" => {
+                let path = payload.get("path")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'path' field")?;
+                let size = payload.get("size")
+                    .and_then(|v| v.as_u64())
+                    .ok_or("missing 'size' field")?;
+                let sha256 = payload.get("sha256")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'sha256' field")?;
+                let status = self.begin_upload(path, size, sha256, base_version(&payload)?).await?;
+                Ok(serde_json::json!(status))
+            }
+            "upload_chunk" => {
+                let upload_id = self.upload_id(&payload).await?;
+                let offset = payload.get("offset")
+                    .and_then(|v| v.as_u64())
+                    .ok_or("missing 'offset' field")?;
+                let content_b64 = payload.get("content")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'content' field")?;
+                let content = base64_decode(content_b64)
+                    .map_err(|e| format!("invalid base64: {}", e))?;
+                let status = self.upload_chunk(upload_id, offset, &content).await?;
+                Ok(serde_json::json!(status))
+            }
+            "commit_upload" => {
+                let upload_id = self.upload_id(&payload).await?;
+                let version = self.commit_upload(upload_id).await?;
+                Ok(serde_json::json!({
+                    "modified": version.timestamp,
+                }))
+            }
+            "abort_upload" => {
+                let upload_id = self.upload_id(&payload).await?;
+                self.abort_upload(upload_id).await?;
+                Ok(serde_json::json!({}))
+            }
             "kv_get" => {
                 let key = payload.get("key")
                     .and_then(|v| v.as_str())
@@ -556,7 +2462,7 @@ impl Kosha {
                 let value = payload.get("value")
                     .cloned()
                     .ok_or("missing 'value' field")?;
-                self.kv_set(key, value).await.map_err(|e| e.to_string())?;
+                self.kv_set(key, value).await?;
                 Ok(serde_json::json!({}))
             }
             "kv_delete" => {
@@ -566,11 +2472,135 @@ impl Kosha {
                 self.kv_delete(key).await.map_err(|e| e.to_string())?;
                 Ok(serde_json::json!({}))
             }
-            _ => Err(format!("unknown command: {}", command)),
+            "kv_state" => {
+                let state = self.kv_state().await?;
+                Ok(serde_json::json!({ "state": state }))
+            }
+            "kv_merge" => {
+                let state = payload.get("state")
+                    .cloned()
+                    .ok_or("missing 'state' field")?;
+                let state: KvState = serde_json::from_value(state)
+                    .map_err(|e| format!("invalid state: {}", e))?;
+                let changed = self.kv_merge(&state).await?;
+                Ok(serde_json::json!({ "changed": changed }))
+            }
+            "db_query" => {
+                let (database, sql, params) = db_statement(&payload)?;
+                let rows = self.db_query(database, sql, params).await?;
+                Ok(serde_json::json!({ "rows": rows }))
+            }
+            "db_execute" => {
+                let (database, sql, params) = db_statement(&payload)?;
+                let affected = self.db_execute(database, sql, params).await?;
+                Ok(serde_json::json!({ "affected": affected }))
+            }
+            "db_begin" => {
+                let database = payload.get("database")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'database' field")?;
+                let tx_id = self.db_begin(database).await?;
+                Ok(serde_json::json!({ "tx_id": tx_id }))
+            }
+            "db_tx_query" => {
+                let tx_id = self.db_transaction(&payload)?;
+                let (_, sql, params) = db_statement(&payload)?;
+                let rows = self.db_tx_query(tx_id, sql, params).await?;
+                Ok(serde_json::json!({ "rows": rows }))
+            }
+            "db_tx_execute" => {
+                let tx_id = self.db_transaction(&payload)?;
+                let (_, sql, params) = db_statement(&payload)?;
+                let affected = self.db_tx_execute(tx_id, sql, params).await?;
+                Ok(serde_json::json!({ "affected": affected }))
+            }
+            "db_commit" => {
+                let tx_id = self.db_transaction(&payload)?;
+                self.db_commit(tx_id).await?;
+                Ok(serde_json::json!({}))
+            }
+            "db_rollback" => {
+                let tx_id = self.db_transaction(&payload)?;
+                self.db_rollback(tx_id).await?;
+                Ok(serde_json::json!({}))
+            }
+            "search" => {
+                let query = payload.get("query")
+                    .and_then(|v| v.as_str())
+                    .ok_or("missing 'query' field")?;
+                let path_prefix = payload.get("path_prefix")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("");
+                let limit = payload.get("limit")
+                    .and_then(|v| v.as_u64())
+                    .map_or(DEFAULT_SEARCH_LIMIT, |limit| limit as usize);
+                let hits = self.search(query, path_prefix, limit).await?;
+                Ok(serde_json::json!({ "hits": hits }))
+            }
+            _ => Err(format!("unknown command: {}", command).into()),
+        }
+    }
+
+    /// Transaction ID of a db_tx_*/db_commit/db_rollback payload, checked
+    /// against the database the payload names
+    fn db_transaction<'a>(&self, payload: &'a serde_json::Value) -> std::result::Result<&'a str, CommandError> {
+        let database = payload.get("database")
+            .and_then(|v| v.as_str())
+            .ok_or("missing 'database' field")?;
+        let tx_id = payload.get("tx_id")
+            .and_then(|v| v.as_str())
+            .ok_or("missing 'tx_id' field")?;
+        match self.db_transaction_database(tx_id) {
+            Some(open) if open == database.trim_start_matches('/') => Ok(tx_id),
+            Some(_) => Err(format!("transaction {} belongs to another database", tx_id).into()),
+            None => Err(Error::TransactionNotFound(tx_id.to_string()).into()),
+        }
+    }
+}
+
+impl Kosha {
+    /// Upload ID of an upload_chunk/commit_upload/abort_upload payload,
+    /// checked against the path the payload names
+    async fn upload_id<'a>(&self, payload: &'a serde_json::Value) -> std::result::Result<&'a str, CommandError> {
+        let path = payload.get("path")
+            .and_then(|v| v.as_str())
+            .ok_or("missing 'path' field")?;
+        let upload_id = payload.get("upload_id")
+            .and_then(|v| v.as_str())
+            .ok_or("missing 'upload_id' field")?;
+        match self.upload_target(upload_id).await {
+            Some(target) if target == path.trim_start_matches('/') => Ok(upload_id),
+            Some(_) => Err(format!("upload {} belongs to another file", upload_id).into()),
+            None => Err(Error::UploadNotFound(upload_id.to_string()).into()),
         }
     }
 }
 
+/// Optional `base_version` timestamp of a write payload
+fn base_version(payload: &serde_json::Value) -> std::result::Result<Option<DateTime<Utc>>, CommandError> {
+    payload.get("base_version")
+        .and_then(|v| v.as_str())
+        .map(|s| s.parse::<DateTime<Utc>>())
+        .transpose()
+        .map_err(|e| format!("invalid base_version: {}", e).into())
+}
+
+/// `database`, `sql` and optional `params` of a db_* payload
+fn db_statement(payload: &serde_json::Value) -> std::result::Result<(&str, &str, Vec<serde_json::Value>), CommandError> {
+    let database = payload.get("database")
+        .and_then(|v| v.as_str())
+        .ok_or("missing 'database' field")?;
+    let sql = payload.get("sql")
+        .and_then(|v| v.as_str())
+        .ok_or("missing 'sql' field")?;
+    let params = match payload.get("params") {
+        None | Some(serde_json::Value::Null) => vec![],
+        Some(serde_json::Value::Array(params)) => params.clone(),
+        Some(_) => return Err("'params' must be an array".into()),
+    };
+    Ok((database, sql, params))
+}
+
 // Base64 encoding/decoding helpers
 fn base64_encode(data: &[u8]) -> String {
     use base64::Engine;
@@ -596,4 +2626,22 @@ mod tests {
     fn test_unflatten_path() {
         assert_eq!(unflatten_path("foo~bar~baz.txt"), "foo/bar/baz.txt");
     }
+
+    #[test]
+    fn test_flatten_escapes_tilde() {
+        assert_eq!(flatten_path("a~b/c%d.txt"), "a%7Eb~c%25d.txt");
+        assert_ne!(flatten_path("a~b.txt"), flatten_path("a/b.txt"));
+        assert_eq!(unflatten_path("a%7Eb~c%25d.txt"), "a~b/c%d.txt");
+        assert_eq!(unflatten_path(&flatten_path("%7E")), "%7E");
+    }
+
+    #[test]
+    fn test_history_timestamp_roundtrip() {
+        let timestamp: DateTime<Utc> = "2024-12-24T15:30:45Z".parse().unwrap();
+        let name = history_filename("foo/bar.txt", timestamp);
+        assert_eq!(name, "foo~bar.txt__20241224T153045Z");
+        let ts = name.strip_prefix("foo~bar.txt__").unwrap();
+        assert_eq!(parse_history_timestamp(ts), Some(timestamp));
+        assert_eq!(parse_history_timestamp("x__20241224T153045Z"), None);
+    }
 }
@@ -0,0 +1,50 @@
+//! Per-file write locks
+//!
+//! A write checks the file's version, then replaces its content. Holding the
+//! file's lock across both keeps a second write to the same file from
+//! slipping in between, so `base_version` checks can't both pass. Writes to
+//! different files don't wait for each other.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Held while a file is being written
+pub(crate) type FileGuard = tokio::sync::OwnedMutexGuard<()>;
+
+/// Locks of the files being written, shared by all clones of a kosha
+#[derive(Default)]
+pub(crate) struct FileLocks {
+    locks: Mutex<HashMap<PathBuf, Weak<tokio::sync::Mutex<()>>>>,
+}
+
+impl FileLocks {
+    /// Wait until no other write holds the file at `path`
+    pub(crate) async fn lock(&self, path: &Path) -> FileGuard {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+            // Locks nobody holds or waits for any more go away
+            locks.retain(|_, lock| lock.strong_count() > 0);
+            match locks.get(path).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::new(tokio::sync::Mutex::new(()));
+                    locks.insert(path.to_path_buf(), Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+
+    /// Lock two files, always in the same order so two writers locking
+    /// the same pair can't wait on each other
+    pub(crate) async fn lock_pair(&self, a: &Path, b: &Path) -> (FileGuard, Option<FileGuard>) {
+        if a == b {
+            return (self.lock(a).await, None);
+        }
+        let (first, second) = if a < b { (a, b) } else { (b, a) };
+        let first = self.lock(first).await;
+        (first, Some(self.lock(second).await))
+    }
+}
@@ -0,0 +1,343 @@
+//! On-disk format versions and migrations between them
+//!
+//! Every kosha records the version of its on-disk layout in `format.json`,
+//! along with the migrations that brought it there. Koshas made before
+//! format records existed have no such file and count as version 0.
+//!
+//! Migration N takes a kosha from version N-1 to N. A migration is a list of
+//! `Change`s computed from what is on disk, so a dry run shows exactly what
+//! applying it would do; the record is updated after each migration, so an
+//! interrupted run can be picked up again. `Kosha::open` refuses koshas
+//! whose version is newer than `FORMAT_VERSION`, and opens older ones as
+//! they are.
+
+use crate::{Durability, Error, Result, blob, fork, transfer};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Version of the on-disk layout this build reads and writes
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Name of the format record in the kosha's root directory
+pub const FORMAT_FILE: &str = "format.json";
+
+/// What `format.json` holds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FormatRecord {
+    pub version: u32,
+    /// Migrations applied to this kosha, oldest first (a kosha created at
+    /// the current version has none)
+    #[serde(default)]
+    pub migrations: Vec<AppliedMigration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub name: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+/// A change a migration makes, with paths relative to the kosha root
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    Rename { from: PathBuf, to: PathBuf },
+    /// Keep the file's content as the blob with this hash, and leave a
+    /// manifest in its place
+    MoveToBlob { path: PathBuf, sha256: String },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Rename { from, to } => write!(f, "rename {} -> {}", from.display(), to.display()),
+            Change::MoveToBlob { path, sha256 } => write!(f, "move {} -> blob {}", path.display(), sha256),
+        }
+    }
+}
+
+/// A pending migration and the changes it makes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedMigration {
+    /// Version the kosha is at after this migration
+    pub version: u32,
+    pub name: String,
+    pub description: String,
+    /// Computed from the kosha as it is on disk: for all but the first
+    /// pending migration that is before the earlier ones ran
+    pub changes: Vec<Change>,
+}
+
+struct Migration {
+    version: u32,
+    name: &'static str,
+    description: &'static str,
+}
+
+/// Every migration, in version order
+const MIGRATIONS: [Migration; 2] = [
+    Migration {
+        version: 1,
+        name: "escape-history-names",
+        description: "Escape '%' in history and derived file names the way flatten_path does",
+    },
+    Migration {
+        version: 2,
+        name: "content-addressed-blobs",
+        description: "Store file and history content in blobs/ by SHA-256, leaving manifests in place",
+    },
+];
+
+impl Migration {
+    async fn changes(&self, path: &Path) -> Result<Vec<Change>> {
+        match self.version {
+            1 => escape_flattened_names(path).await,
+            2 => move_to_blobs(path).await,
+            version => unreachable!("migration {} has no changes", version),
+        }
+    }
+}
+
+/// The kosha's format record; version 0 if it has none
+pub async fn read_format(path: &Path) -> Result<FormatRecord> {
+    match tokio::fs::read(path.join(FORMAT_FILE)).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FormatRecord::default()),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+/// The migrations that would bring the kosha to `FORMAT_VERSION`, without
+/// changing anything
+pub async fn plan_migrations(path: &Path) -> Result<Vec<PlannedMigration>> {
+    let record = supported_format(path).await?;
+    let mut planned = vec![];
+    for migration in MIGRATIONS.iter().filter(|m| m.version > record.version) {
+        planned.push(PlannedMigration {
+            version: migration.version,
+            name: migration.name.to_string(),
+            description: migration.description.to_string(),
+            changes: migration.changes(path).await?,
+        });
+    }
+    Ok(planned)
+}
+
+/// Bring the kosha to `FORMAT_VERSION` and return the migrations applied
+///
+/// The kosha must not be open while it runs: stop the hub first, and make
+/// a `backup` to go back to if something fails halfway.
+pub async fn migrate(path: &Path) -> Result<Vec<PlannedMigration>> {
+    let mut record = supported_format(path).await?;
+    let from = record.version;
+    let mut applied = vec![];
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from) {
+        let changes = migration.changes(path).await?;
+        for change in &changes {
+            apply(path, change).await?;
+        }
+        record.version = migration.version;
+        record.migrations.push(AppliedMigration {
+            version: migration.version,
+            name: migration.name.to_string(),
+            applied_at: Utc::now(),
+        });
+        write_format(path, &record).await?;
+        applied.push(PlannedMigration {
+            version: migration.version,
+            name: migration.name.to_string(),
+            description: migration.description.to_string(),
+            changes,
+        });
+    }
+    Ok(applied)
+}
+
+/// Copy the kosha at `path` to `to`, which must not exist
+///
+/// Like a fork, files are hard-linked (a kosha never changes a stored file
+/// in place) and databases copied. Unfinished uploads are copied, as their
+/// parts grow in place.
+pub async fn backup(path: &Path, to: &Path) -> Result<()> {
+    if tokio::fs::try_exists(to).await? {
+        return Err(Error::Conflict(format!("{} already exists", to.display())));
+    }
+    let copied = async {
+        for dir in fork::SHARED_DIRS {
+            let source = path.join(dir);
+            if tokio::fs::try_exists(&source).await? {
+                fork::link_tree(&source, &to.join(dir)).await?;
+            }
+        }
+        let uploads = path.join("uploads");
+        if tokio::fs::try_exists(&uploads).await? {
+            tokio::fs::create_dir_all(to.join("uploads")).await?;
+            let mut dir = tokio::fs::read_dir(&uploads).await?;
+            while let Some(entry) = dir.next_entry().await? {
+                if entry.file_type().await?.is_file() {
+                    tokio::fs::copy(entry.path(), to.join("uploads").join(entry.file_name())).await?;
+                }
+            }
+        }
+        copy_format(path, to).await
+    }
+    .await;
+    if let Err(e) = copied {
+        // Don't leave a half-made backup behind
+        let _ = tokio::fs::remove_dir_all(to).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Check the format of the kosha being opened at `path`, and return its
+/// version
+///
+/// A new kosha (no files/ yet) is recorded at `FORMAT_VERSION`.
+pub(crate) async fn open_format(path: &Path) -> Result<u32> {
+    if !tokio::fs::try_exists(path.join(FORMAT_FILE)).await? && !tokio::fs::try_exists(path.join("files")).await? {
+        tokio::fs::create_dir_all(path).await?;
+        let record = FormatRecord {
+            version: FORMAT_VERSION,
+            migrations: vec![],
+        };
+        write_format(path, &record).await?;
+        return Ok(FORMAT_VERSION);
+    }
+    Ok(supported_format(path).await?.version)
+}
+
+/// Give a fork or backup the format record of its source
+pub(crate) async fn copy_format(from: &Path, to: &Path) -> Result<()> {
+    match tokio::fs::copy(from.join(FORMAT_FILE), to.join(FORMAT_FILE)).await {
+        Ok(_) => Ok(()),
+        // The source predates format records, and so does the copy
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+/// The format record, or `Error::UnsupportedFormat` if the kosha was
+/// written by a newer build
+async fn supported_format(path: &Path) -> Result<FormatRecord> {
+    let record = read_format(path).await?;
+    if record.version > FORMAT_VERSION {
+        return Err(Error::UnsupportedFormat {
+            version: record.version,
+            supported: FORMAT_VERSION,
+        });
+    }
+    Ok(record)
+}
+
+/// Write the record to a temp file, then rename it over
+async fn write_format(path: &Path, record: &FormatRecord) -> Result<()> {
+    let file = path.join(FORMAT_FILE);
+    let tmp = file.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(record)?).await?;
+    tokio::fs::rename(&tmp, &file).await?;
+    Ok(())
+}
+
+async fn apply(path: &Path, change: &Change) -> Result<()> {
+    match change {
+        Change::Rename { from, to } => {
+            let target = path.join(to);
+            // Never clobber: two old names can't map to one new name, so a
+            // taken target means something else wrote there
+            if tokio::fs::try_exists(&target).await? {
+                return Err(Error::Conflict(format!("{} already exists", to.display())));
+            }
+            tokio::fs::rename(path.join(from), target).await?;
+            Ok(())
+        }
+        Change::MoveToBlob { path: file, sha256 } => {
+            let source = path.join(file);
+            let metadata = tokio::fs::metadata(&source).await?;
+            let blob = blob::blob_path(&path.join("blobs"), sha256);
+            if !tokio::fs::try_exists(&blob).await? {
+                if let Some(parent) = blob.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                // Stored files never change in place, so the blob can share
+                // the content instead of copying it
+                if tokio::fs::hard_link(&source, &blob).await.is_err() {
+                    tokio::fs::copy(&source, &blob).await?;
+                }
+            }
+
+            let manifest = blob::Manifest {
+                sha256: sha256.clone(),
+                size: metadata.len(),
+            };
+            let tmp_dir = path.join("tmp");
+            tokio::fs::create_dir_all(&tmp_dir).await?;
+            let tmp = blob::write_manifest(&tmp_dir, &manifest, Durability::default()).await?;
+            // The modification time is the version timestamp
+            std::fs::File::options().write(true).open(&tmp)?.set_modified(metadata.modified()?)?;
+            tokio::fs::rename(&tmp, &source).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Migration 1: flattened names (`history/<flat>__<timestamp>`,
+/// `derived/<flat>__<name>`) used to keep `%` as is. Names with a `%` that
+/// doesn't start `%7E` or `%25` are from then, and get every `%` escaped;
+/// other names are already in the current format. (`~` stood for `/`
+/// before and after, so it needs no change.)
+async fn escape_flattened_names(path: &Path) -> Result<Vec<Change>> {
+    let mut changes = vec![];
+    for dir in ["history", "derived"] {
+        let mut entries = match tokio::fs::read_dir(path.join(dir)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let mut names = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        for name in names {
+            if has_unescaped_percent(&name) {
+                changes.push(Change::Rename {
+                    from: Path::new(dir).join(&name),
+                    to: Path::new(dir).join(name.replace('%', "%25")),
+                });
+            }
+        }
+    }
+    Ok(changes)
+}
+
+fn has_unescaped_percent(name: &str) -> bool {
+    name.match_indices('%')
+        .any(|(i, _)| !name[i..].starts_with("%7E") && !name[i..].starts_with("%25"))
+}
+
+/// Migration 2: content moves to blobs/ (see `blob`). Every entry in files/
+/// and history/ but databases gets its content stored by hash, and a
+/// manifest in its place with the same modification time. Entries that are
+/// manifests already, from an interrupted run, are left alone.
+async fn move_to_blobs(path: &Path) -> Result<Vec<Change>> {
+    let mut stored = blob::stored_files(path).await?;
+    stored.sort_by(|a, b| a.location.cmp(&b.location));
+    let mut changes = vec![];
+    for stored in stored.into_iter().filter(|s| !s.as_is) {
+        if let Some(manifest) = blob::manifest_at(&stored.location).await?
+            && tokio::fs::try_exists(blob::blob_path(&path.join("blobs"), &manifest.sha256)).await?
+        {
+            continue;
+        }
+        changes.push(Change::MoveToBlob {
+            path: stored.location.strip_prefix(path).unwrap_or(&stored.location).to_path_buf(),
+            sha256: transfer::sha256_file(&stored.location).await?,
+        });
+    }
+    Ok(changes)
+}
@@ -0,0 +1,131 @@
+//! Mount points: folders that show another kosha's content
+//!
+//! A kosha lists its mount points in `_mounts.txt` at its root, one per
+//! line:
+//!
+//! ```text
+//! # Mount point: kosha[/folder]
+//! photos: shared/photos
+//! recipes: cookbook
+//! ```
+//!
+//! The kosha itself stores the file like any other; the hub reads it and
+//! serves reads under `photos/` from `photos/` of the `shared` kosha. When
+//! mount points nest, the longest one wins.
+
+use crate::{clean_path, Error, Result};
+use std::collections::{BTreeSet, HashSet};
+
+/// File listing a kosha's mount points, relative to its root
+pub const MOUNTS_FILE: &str = "_mounts.txt";
+
+/// A folder showing a folder of another kosha
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    /// Folder in this kosha, without leading or trailing slashes
+    pub at: String,
+    /// Alias of the kosha mounted
+    pub kosha: String,
+    /// Folder of that kosha mounted, empty for its root
+    pub path: String,
+}
+
+/// Mount points of a kosha, as listed in `MOUNTS_FILE`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountTable {
+    pub mounts: Vec<Mount>,
+}
+
+impl MountTable {
+    /// Parse the contents of a `MOUNTS_FILE`
+    ///
+    /// Blank lines and lines starting with `#` are ignored. A mount point
+    /// listed twice is an error.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut mounts = Vec::new();
+        let mut seen = HashSet::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| Error::InvalidMount(format!("line {}: {}", number + 1, reason));
+            let (at, target) = line.split_once(':').ok_or_else(|| invalid("expected '<mount point>: <kosha>[/<folder>]'"))?;
+            let at = clean_path(at.trim()).map_err(|e| invalid(&e.to_string()))?.trim_end_matches('/');
+            let target = clean_path(target.trim()).map_err(|e| invalid(&e.to_string()))?.trim_end_matches('/');
+            let (kosha, path) = target.split_once('/').unwrap_or((target, ""));
+            if at.is_empty() {
+                return Err(invalid("the root can't be a mount point"));
+            }
+            if kosha.is_empty() {
+                return Err(invalid("missing kosha"));
+            }
+            if !seen.insert(at.to_string()) {
+                return Err(invalid(&format!("{} is mounted twice", at)));
+            }
+            mounts.push(Mount {
+                at: at.to_string(),
+                kosha: kosha.to_string(),
+                path: path.to_string(),
+            });
+        }
+        Ok(Self { mounts })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mounts.is_empty()
+    }
+
+    /// The mount holding `path` (cleaned) and the path in the mounted
+    /// kosha, if `path` is a mount point or under one
+    pub fn resolve(&self, path: &str) -> Option<(&Mount, String)> {
+        let path = path.trim_end_matches('/');
+        self.mounts
+            .iter()
+            .filter_map(|mount| {
+                let rest = path.strip_prefix(mount.at.as_str())?;
+                let rest = match rest.strip_prefix('/') {
+                    Some(rest) => rest,
+                    None if rest.is_empty() => rest,
+                    None => return None,
+                };
+                Some((mount, rest))
+            })
+            .max_by_key(|(mount, _)| mount.at.len())
+            .map(|(mount, rest)| {
+                let target = match (mount.path.is_empty(), rest.is_empty()) {
+                    (true, _) => rest.to_string(),
+                    (false, true) => mount.path.clone(),
+                    (false, false) => format!("{}/{}", mount.path, rest),
+                };
+                (mount, target)
+            })
+    }
+
+    /// Names of the folders directly in `dir` (cleaned, empty for the root)
+    /// that are mount points or lead to one
+    pub fn children(&self, dir: &str) -> BTreeSet<String> {
+        let dir = dir.trim_end_matches('/');
+        self.mounts
+            .iter()
+            .filter_map(|mount| match dir.is_empty() {
+                true => Some(mount.at.as_str()),
+                false => mount.at.strip_prefix(dir)?.strip_prefix('/'),
+            })
+            .filter_map(|rest| rest.split('/').next())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl std::fmt::Display for MountTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for mount in &self.mounts {
+            match mount.path.is_empty() {
+                true => writeln!(f, "{}: {}", mount.at, mount.kosha)?,
+                false => writeln!(f, "{}: {}/{}", mount.at, mount.kosha, mount.path)?,
+            }
+        }
+        Ok(())
+    }
+}
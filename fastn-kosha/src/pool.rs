@@ -0,0 +1,194 @@
+//! Worker pool for WASM execution
+//!
+//! get/post handlers (and the hub's ACL modules) are CPU-bound. On the async
+//! runtime, or on tokio's unbounded blocking pool, a burst of slow modules
+//! starves every other request. The pool runs them on a fixed set of threads
+//! instead:
+//! - The queue is bounded: calls beyond `queue_capacity` fail right away with
+//!   `PoolError::QueueFull` instead of piling up.
+//! - At most `per_module_concurrency` calls of the same module run at once;
+//!   further calls wait for a slot.
+//! - A call that hasn't finished after `timeout` (waiting included) fails with
+//!   `PoolError::Timeout`.
+//! - Every job gets a `CancellationToken`. It is cancelled when the caller
+//!   gives up (timeout, dropped request future) or when the request's own
+//!   token is (see `with_cancellation`). Queued jobs are then skipped, and
+//!   running modules stop at the next epoch tick.
+//!
+//! Every pool runs its modules on the one process-wide `WasmRuntime`, so
+//! handlers and ACL modules share an engine, its epoch ticker and the
+//! compiled module cache.
+
+use crate::WasmRuntime;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{oneshot, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+tokio::task_local! {
+    /// Cancellation token of the request being handled
+    static REQUEST_CANCELLATION: CancellationToken;
+}
+
+/// Run `future` as a request with its own cancellation token: cancelling
+/// `token` cancels the WASM calls `future` makes on any pool.
+pub async fn with_cancellation<F: Future>(token: CancellationToken, future: F) -> F::Output {
+    REQUEST_CANCELLATION.scope(token, future).await
+}
+
+/// Sizing of a `WasmPool`
+#[derive(Debug, Clone, Copy)]
+pub struct WasmPoolConfig {
+    /// Worker threads
+    pub workers: usize,
+    /// Calls waiting for a worker
+    pub queue_capacity: usize,
+    /// Calls of one module running (or queued) at once
+    pub per_module_concurrency: usize,
+    /// Longest a call may take, waiting for a slot and a worker included
+    pub timeout: Duration,
+}
+
+impl Default for WasmPoolConfig {
+    fn default() -> Self {
+        Self {
+            workers: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            queue_capacity: 256,
+            per_module_concurrency: 4,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Why a pool call didn't produce a result
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PoolError {
+    #[error("WASM worker queue is full")]
+    QueueFull,
+    #[error("WASM call timed out")]
+    Timeout,
+    #[error("WASM call cancelled")]
+    Cancelled,
+    #[error("WASM worker failed")]
+    WorkerFailed,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of threads running WASM calls
+pub struct WasmPool {
+    config: WasmPoolConfig,
+    queue: mpsc::SyncSender<Job>,
+    /// Engine and module cache the jobs run modules with
+    runtime: &'static WasmRuntime,
+    /// Concurrency slots per module key
+    modules: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl WasmPool {
+    /// Start a pool; its threads exit once it is dropped
+    pub fn new(config: WasmPoolConfig) -> Self {
+        let (queue, jobs) = mpsc::sync_channel::<Job>(config.queue_capacity.max(1));
+        let jobs = Arc::new(Mutex::new(jobs));
+        for index in 0..config.workers.max(1) {
+            let jobs = jobs.clone();
+            std::thread::Builder::new()
+                .name(format!("fastn-wasm-{}", index))
+                .spawn(move || {
+                    loop {
+                        let job = jobs.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                        match job {
+                            // A panicking job only loses its own result
+                            Ok(job) => drop(std::panic::catch_unwind(std::panic::AssertUnwindSafe(job))),
+                            Err(_) => break,
+                        }
+                    }
+                })
+                .expect("failed to spawn WASM worker");
+        }
+        Self {
+            config,
+            queue,
+            runtime: WasmRuntime::shared(),
+            modules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The process-wide pool with the default configuration, started on
+    /// first use
+    pub fn shared() -> Arc<WasmPool> {
+        static POOL: OnceLock<Arc<WasmPool>> = OnceLock::new();
+        POOL.get_or_init(|| Arc::new(WasmPool::new(WasmPoolConfig::default())))
+            .clone()
+    }
+
+    pub fn config(&self) -> &WasmPoolConfig {
+        &self.config
+    }
+
+    /// The runtime jobs on this pool should run their modules with
+    pub fn runtime(&self) -> &'static WasmRuntime {
+        self.runtime
+    }
+
+    /// Run `job` on a worker, counting it against `module`'s concurrency
+    ///
+    /// The job gets the call's cancellation token and should stop early
+    /// once it is cancelled.
+    pub async fn run<R, F>(&self, module: &str, job: F) -> Result<R, PoolError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&CancellationToken) -> R + Send + 'static,
+    {
+        let cancel = REQUEST_CANCELLATION
+            .try_with(CancellationToken::child_token)
+            .unwrap_or_default();
+        // Returning early, or the caller dropping this future, cancels the job
+        let _cancel_on_exit = cancel.clone().drop_guard();
+        let deadline = tokio::time::sleep(self.config.timeout);
+        tokio::pin!(deadline);
+
+        let slot = self.module_slot(module);
+        let permit = tokio::select! {
+            permit = slot.acquire_owned() => permit.map_err(|_| PoolError::WorkerFailed)?,
+            _ = &mut deadline => return Err(PoolError::Timeout),
+            _ = cancel.cancelled() => return Err(PoolError::Cancelled),
+        };
+
+        let (result_tx, result_rx) = oneshot::channel();
+        let token = cancel.clone();
+        let task: Job = Box::new(move || {
+            let _permit = permit;
+            // Skip jobs whose caller gave up while they were queued
+            if !token.is_cancelled() {
+                let _ = result_tx.send(job(&token));
+            }
+        });
+        self.queue.try_send(task).map_err(|e| match e {
+            TrySendError::Full(_) => PoolError::QueueFull,
+            TrySendError::Disconnected(_) => PoolError::WorkerFailed,
+        })?;
+
+        tokio::select! {
+            result = result_rx => result.map_err(|_| match cancel.is_cancelled() {
+                true => PoolError::Cancelled,
+                false => PoolError::WorkerFailed,
+            }),
+            _ = &mut deadline => Err(PoolError::Timeout),
+            _ = cancel.cancelled() => Err(PoolError::Cancelled),
+        }
+    }
+
+    fn module_slot(&self, module: &str) -> Arc<Semaphore> {
+        let mut modules = self.modules.lock().unwrap_or_else(|e| e.into_inner());
+        // Forget modules nobody is running or waiting for
+        modules.retain(|_, slot| Arc::strong_count(slot) > 1);
+        modules
+            .entry(module.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.config.per_module_concurrency.max(1))))
+            .clone()
+    }
+}
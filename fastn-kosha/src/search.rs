@@ -0,0 +1,220 @@
+//! Full-text search over files
+//!
+//! The index is an SQLite FTS5 database at `index/search.sqlite3`, outside
+//! `files/`, holding for each file its path, name, text content and derived
+//! artifacts (e.g. the hub's model metadata). Content is indexed when it is
+//! UTF-8 without NUL bytes and at most `MAX_INDEXED_SIZE`; databases and
+//! other files are found by name only.
+//!
+//! Every change a watcher would see, and every derived artifact written,
+//! queues its path; `Kosha::search` brings the queued paths up to date
+//! before it runs. The first search after the kosha is opened also compares
+//! every file with the version indexed, which picks up forks, imported
+//! snapshots and changes made while no index was kept. The index can be
+//! deleted at any time and is rebuilt by the next search.
+//!
+//! Queries are words, each matching words that start with it, in the
+//! path, name, content or artifacts; all must match. Hits come best first,
+//! weighted towards names.
+
+use crate::{db, Error, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Largest content (file or derived artifact) indexed as text
+pub const MAX_INDEXED_SIZE: u64 = 1024 * 1024;
+
+/// Hits returned when the search doesn't set a limit
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+/// Most hits a search returns
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+/// Index location, relative to the kosha
+pub(crate) const INDEX_FILE: &str = "index/search.sqlite3";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS versions (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        modified TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        stamp INTEGER NOT NULL
+    );
+    CREATE VIRTUAL TABLE IF NOT EXISTS documents USING fts5(
+        path, name, body, meta, tokenize = 'unicode61 remove_diacritics 2'
+    );
+";
+
+/// A file matching a search
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub path: String,
+    /// Relevance, higher is better; only comparable within one search
+    pub score: f64,
+    /// Text around the matches, which are wrapped in `**`
+    pub snippet: String,
+    /// Version indexed (the current one)
+    pub modified: DateTime<Utc>,
+}
+
+/// Size and modification time (nanoseconds since 1970) of what is stored
+/// in files/ for a file, which tell whether it changed since it was indexed
+pub(crate) type FileStamp = (u64, i64);
+
+/// What is indexed for a file
+pub(crate) struct Document {
+    pub path: String,
+    pub modified: DateTime<Utc>,
+    pub stamp: FileStamp,
+    pub body: String,
+    /// Derived artifacts, one per line
+    pub meta: String,
+}
+
+/// Paths waiting to be indexed, shared by all clones of a kosha
+#[derive(Default)]
+pub(crate) struct SearchQueue {
+    pending: Mutex<Pending>,
+    /// Held while the index is updated
+    update: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Whether every file was compared with the index since opening
+    reconciled: bool,
+    paths: BTreeSet<String>,
+}
+
+impl SearchQueue {
+    /// Queue a changed file
+    pub(crate) fn changed(&self, path: &str) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.paths.insert(path.trim_start_matches('/').to_string());
+    }
+
+    /// Hold off other updates
+    pub(crate) async fn lock(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.update.lock().await
+    }
+
+    /// Queued paths, and whether every file needs comparing too
+    pub(crate) fn take(&self) -> (BTreeSet<String>, bool) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        (std::mem::take(&mut pending.paths), !pending.reconciled)
+    }
+
+    /// Record that the index caught up with every file, or (after an
+    /// update failed) that it needs comparing again
+    pub(crate) fn set_reconciled(&self, reconciled: bool) {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).reconciled = reconciled;
+    }
+}
+
+/// Open the index, creating it if needed
+pub(crate) fn open(path: &Path) -> Result<Connection> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let conn = db::open(path, true)?;
+    conn.execute_batch(SCHEMA).map_err(db_error)?;
+    Ok(conn)
+}
+
+/// Stamp of every indexed file
+pub(crate) fn indexed_stamps(conn: &Connection) -> Result<HashMap<String, FileStamp>> {
+    let mut stmt = conn.prepare("SELECT path, size, stamp FROM versions").map_err(db_error)?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, (row.get::<_, u64>(1)?, row.get::<_, i64>(2)?))))
+        .map_err(db_error)?;
+    rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+}
+
+/// Replace the entries of `documents` and drop those of `removed`
+pub(crate) fn update(conn: &mut Connection, documents: &[Document], removed: &[String]) -> Result<()> {
+    let tx = conn.transaction().map_err(db_error)?;
+    for path in removed.iter().chain(documents.iter().map(|doc| &doc.path)) {
+        let id: Option<i64> = tx
+            .query_row("SELECT id FROM versions WHERE path = ?1", [path], |row| row.get(0))
+            .optional()
+            .map_err(db_error)?;
+        if let Some(id) = id {
+            tx.execute("DELETE FROM documents WHERE rowid = ?1", [id]).map_err(db_error)?;
+            tx.execute("DELETE FROM versions WHERE id = ?1", [id]).map_err(db_error)?;
+        }
+    }
+    for doc in documents {
+        tx.execute(
+            "INSERT INTO versions (path, modified, size, stamp) VALUES (?1, ?2, ?3, ?4)",
+            params![doc.path, doc.modified.to_rfc3339(), doc.stamp.0, doc.stamp.1],
+        )
+        .map_err(db_error)?;
+        let name = doc.path.rsplit('/').next().unwrap_or_default();
+        tx.execute(
+            "INSERT INTO documents (rowid, path, name, body, meta) VALUES (last_insert_rowid(), ?1, ?2, ?3, ?4)",
+            params![doc.path, name, doc.body, doc.meta],
+        )
+        .map_err(db_error)?;
+    }
+    tx.commit().map_err(db_error)
+}
+
+/// Files under `prefix` (cleaned, empty for all) matching `query`, best
+/// first
+pub(crate) fn query(conn: &Connection, query: &str, prefix: &str, limit: usize) -> Result<Vec<SearchHit>> {
+    let Some(query) = match_expression(query) else {
+        return Ok(vec![]);
+    };
+    let under = format!("{}/%", prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+    let mut stmt = conn
+        .prepare(
+            "SELECT versions.path, -bm25(documents, 2.0, 10.0, 1.0, 3.0),
+                    snippet(documents, -1, '**', '**', '…', 12), versions.modified
+             FROM documents JOIN versions ON versions.id = documents.rowid
+             WHERE documents MATCH ?1 AND (?2 = '' OR versions.path = ?2 OR versions.path LIKE ?3 ESCAPE '\\')
+             ORDER BY bm25(documents, 2.0, 10.0, 1.0, 3.0) LIMIT ?4",
+        )
+        .map_err(db_error)?;
+    let rows = stmt
+        .query_map(params![query, prefix, under, limit as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+        })
+        .map_err(db_error)?;
+    let mut hits = Vec::new();
+    for row in rows {
+        let (path, score, snippet, modified) = row.map_err(db_error)?;
+        let modified = DateTime::parse_from_rfc3339(&modified)
+            .map_err(|e| Error::Database(format!("invalid indexed version of {}: {}", path, e)))?
+            .with_timezone(&Utc);
+        hits.push(SearchHit { path, score, snippet, modified });
+    }
+    Ok(hits)
+}
+
+/// Text of content worth indexing, if it is any
+pub(crate) fn indexable_text(content: Vec<u8>) -> Option<String> {
+    if content.len() as u64 > MAX_INDEXED_SIZE {
+        return None;
+    }
+    String::from_utf8(content).ok().filter(|text| !text.contains('\0'))
+}
+
+/// FTS5 expression for a query: every word, quoted, as a prefix. `None`
+/// if the query has no words.
+fn match_expression(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+fn db_error(e: rusqlite::Error) -> Error {
+    Error::Database(e.to_string())
+}
@@ -0,0 +1,218 @@
+//! Snapshots: a whole kosha in one portable file, for backups and moving
+//! koshas between hubs
+//!
+//! A snapshot is a zstd-compressed tar. `manifest.json` comes first, then
+//! `format.json` and the trees a fork shares: files/ and history/ with the
+//! blobs they refer to, kv/ and derived/. It is archived from a
+//! hard-linked copy like a fork (see `fork`), so it is consistent while
+//! the kosha is being written, and databases are copied by SQLite rather
+//! than read mid-write. Unfinished uploads are left out. Modification times
+//! are kept, as they are the version timestamps.
+
+use crate::{Error, FORMAT_VERSION, Result, fork, migrate};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+/// Layout of snapshots this build writes, and the newest it reads
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Name of the manifest, the first entry of a snapshot
+pub const SNAPSHOT_MANIFEST: &str = "manifest.json";
+
+/// What a snapshot holds, from its `manifest.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Layout of the snapshot (`SNAPSHOT_VERSION` when it was written)
+    pub snapshot_version: u32,
+    /// On-disk format of the kosha in it (see `FORMAT_VERSION`)
+    pub format_version: u32,
+    /// Alias of the exported kosha
+    pub alias: String,
+    pub created_at: DateTime<Utc>,
+    /// Files in the snapshot and their size before compression
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Write the kosha at `path` to a snapshot at `to`, replacing it
+///
+/// The snapshot is written next to `to` and renamed over it once
+/// complete, so a failed export leaves nothing behind.
+pub async fn export_snapshot(path: &Path, alias: &str, to: &Path) -> Result<SnapshotManifest> {
+    let format_version = migrate::read_format(path).await?.version;
+    let staging = sibling(to, "staging");
+    let _ = tokio::fs::remove_dir_all(&staging).await;
+    let written = async {
+        tokio::fs::create_dir_all(&staging).await?;
+        for dir in fork::SHARED_DIRS {
+            let source = path.join(dir);
+            if tokio::fs::try_exists(&source).await? {
+                fork::link_tree(&source, &staging.join(dir)).await?;
+            }
+        }
+        migrate::copy_format(path, &staging).await?;
+
+        let manifest = SnapshotManifest {
+            snapshot_version: SNAPSHOT_VERSION,
+            format_version,
+            alias: alias.to_string(),
+            created_at: Utc::now(),
+            files: 0,
+            bytes: 0,
+        };
+        let (from, to) = (staging.clone(), to.to_path_buf());
+        tokio::task::spawn_blocking(move || write_snapshot(&from, manifest, &to))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?
+    }
+    .await;
+    let _ = tokio::fs::remove_dir_all(&staging).await;
+    written
+}
+
+/// Unpack a snapshot into a new kosha at `to`, which must not exist
+///
+/// Snapshots of a newer layout or kosha format than this build reads are
+/// refused. The kosha is unpacked next to `to` and renamed into place once
+/// complete; open it with `Kosha::open` afterwards.
+pub async fn import_snapshot(from: &Path, to: &Path) -> Result<SnapshotManifest> {
+    if tokio::fs::try_exists(to).await? {
+        return Err(Error::Conflict(format!("{} already exists", to.display())));
+    }
+    let staging = sibling(to, "importing");
+    let _ = tokio::fs::remove_dir_all(&staging).await;
+    tokio::fs::create_dir_all(&staging).await?;
+
+    let (source, target) = (from.to_path_buf(), staging.clone());
+    let unpacked = async {
+        let manifest = tokio::task::spawn_blocking(move || read_snapshot(&source, &target))
+            .await
+            .map_err(|e| Error::Io(std::io::Error::other(e)))??;
+        tokio::fs::rename(&staging, to).await?;
+        Ok(manifest)
+    }
+    .await;
+    if unpacked.is_err() {
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+    }
+    unpacked
+}
+
+/// `<to>.<suffix>` in the same directory
+fn sibling(to: &Path, suffix: &str) -> PathBuf {
+    let mut name = to.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", suffix));
+    to.with_file_name(name)
+}
+
+/// Archive the copy at `from`, counting its files into the manifest
+fn write_snapshot(from: &Path, mut manifest: SnapshotManifest, to: &Path) -> Result<SnapshotManifest> {
+    for dir in fork::SHARED_DIRS {
+        count_tree(&from.join(dir), &mut manifest.files, &mut manifest.bytes)?;
+    }
+
+    let tmp = sibling(to, "tmp");
+    let written = (|| -> Result<()> {
+        let file = std::fs::File::create(&tmp)?;
+        let mut archive = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+        archive.follow_symlinks(false);
+
+        let json = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(manifest.created_at.timestamp().max(0) as u64);
+        header.set_cksum();
+        archive.append_data(&mut header, SNAPSHOT_MANIFEST, json.as_slice())?;
+
+        if from.join(migrate::FORMAT_FILE).is_file() {
+            archive.append_path_with_name(from.join(migrate::FORMAT_FILE), migrate::FORMAT_FILE)?;
+        }
+        for dir in fork::SHARED_DIRS {
+            if from.join(dir).is_dir() {
+                archive.append_dir_all(dir, from.join(dir))?;
+            }
+        }
+        let file = archive.into_inner()?.finish()?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, to)?;
+        Ok(())
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    written.map(|()| manifest)
+}
+
+fn read_snapshot(from: &Path, to: &Path) -> Result<SnapshotManifest> {
+    let file = std::fs::File::open(from)?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+    let mut manifest: Option<SnapshotManifest> = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        if manifest.is_none() {
+            if path != Path::new(SNAPSHOT_MANIFEST) {
+                return Err(invalid(from, "it doesn't start with a manifest"));
+            }
+            let read: SnapshotManifest = serde_json::from_reader(&mut entry)?;
+            if read.snapshot_version > SNAPSHOT_VERSION {
+                return Err(invalid(
+                    from,
+                    &format!("its layout version {} is newer than {}", read.snapshot_version, SNAPSHOT_VERSION),
+                ));
+            }
+            if read.format_version > FORMAT_VERSION {
+                return Err(Error::UnsupportedFormat {
+                    version: read.format_version,
+                    supported: FORMAT_VERSION,
+                });
+            }
+            manifest = Some(read);
+            continue;
+        };
+
+        // Only the kosha's own trees, and nothing that could land outside
+        let allowed = match path.components().next() {
+            Some(Component::Normal(first)) => {
+                first == migrate::FORMAT_FILE || fork::SHARED_DIRS.iter().any(|dir| first == *dir)
+            }
+            _ => false,
+        };
+        let kind = entry.header().entry_type();
+        if !allowed || !(kind.is_file() || kind.is_dir()) || path.components().any(|c| !matches!(c, Component::Normal(_))) {
+            return Err(invalid(from, &format!("unexpected entry {}", path.display())));
+        }
+        entry.set_preserve_mtime(true);
+        if !entry.unpack_in(to)? {
+            return Err(invalid(from, &format!("unexpected entry {}", path.display())));
+        }
+    }
+    manifest.ok_or_else(|| invalid(from, "it is empty"))
+}
+
+fn invalid(snapshot: &Path, reason: &str) -> Error {
+    Error::InvalidSnapshot(format!("{}: {}", snapshot.display(), reason))
+}
+
+/// Add up the regular files under `dir`, if it exists
+fn count_tree(dir: &Path, files: &mut u64, bytes: &mut u64) -> Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::Io(e)),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            count_tree(&entry.path(), files, bytes)?;
+        } else if file_type.is_file() {
+            *files += 1;
+            *bytes += entry.metadata()?.len();
+        }
+    }
+    Ok(())
+}
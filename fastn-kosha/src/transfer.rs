@@ -0,0 +1,183 @@
+//! Chunked file transfer
+//!
+//! Large files don't fit in a single base64 payload, so they are uploaded in
+//! chunks: `begin_upload` announces the final size and SHA-256, chunks are
+//! appended in order, and `commit_upload` checks the hash before the file
+//! replaces the current version (with history, like `write_file`).
+//!
+//! Uploads live in `uploads/` next to `files/` and survive restarts. The
+//! upload ID is derived from the target path, size, hash and base version,
+//! so calling `begin_upload` again with the same arguments resumes where the
+//! previous attempt stopped. Uploads nobody touched for `UPLOAD_EXPIRY` are
+//! removed.
+//!
+//! Downloads go the other way with `read_range`. `file_hash` hands out the
+//! SHA-256 of the whole file and of each `MAX_CHUNK_SIZE` chunk, so clients
+//! can check every chunk as it arrives and resume after the last good one.
+//! A `read_range` naming that SHA-256 fails once the content has changed.
+//! Digests are cached per file until its size or modification time changes.
+
+use crate::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncReadExt;
+
+/// Largest chunk accepted by `upload_chunk` and returned by `read_range`
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Unfinished uploads are removed after this long without a chunk
+pub const UPLOAD_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Files whose digests are kept; the cache starts over when it is full
+const DIGEST_CACHE_SIZE: usize = 1024;
+
+/// SHA-256 of a file and of each of its `MAX_CHUNK_SIZE` chunks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileDigest {
+    /// Whole content, lowercase hex
+    pub sha256: String,
+    /// One per chunk, in order; empty for an empty file
+    pub chunks: Vec<String>,
+}
+
+/// Progress of an upload
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadStatus {
+    pub upload_id: String,
+    /// Bytes received so far; the next chunk must start here
+    pub received: u64,
+    /// Final size announced by `begin_upload`
+    pub size: u64,
+}
+
+/// What `begin_upload` announced, stored as `uploads/<id>.json`
+///
+/// The data itself is appended to `uploads/<id>.part`, whose length is the
+/// number of bytes received.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Upload {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    pub base_version: Option<DateTime<Utc>>,
+}
+
+impl Upload {
+    /// Same arguments give the same ID, which is what makes uploads resumable
+    pub(crate) fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.path.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.size.to_be_bytes());
+        hasher.update(self.sha256.as_bytes());
+        if let Some(base) = self.base_version {
+            hasher.update(base.timestamp().to_be_bytes());
+        }
+        format!("up-{}", &hex(&hasher.finalize())[..32])
+    }
+}
+
+/// Check an upload ID before using it as a filename
+pub(crate) fn validate_upload_id(upload_id: &str) -> Result<()> {
+    let valid = upload_id
+        .strip_prefix("up-")
+        .is_some_and(|h| h.len() == 32 && h.bytes().all(|b| b.is_ascii_hexdigit()));
+    match valid {
+        true => Ok(()),
+        false => Err(Error::UploadNotFound(upload_id.to_string())),
+    }
+}
+
+/// Check a client-supplied hash: 64 lowercase hex digits
+pub(crate) fn validate_sha256(sha256: &str) -> Result<()> {
+    let valid = sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    match valid {
+        true => Ok(()),
+        false => Err(Error::InvalidRange(format!("invalid sha256: {}", sha256))),
+    }
+}
+
+/// SHA-256 of a file as lowercase hex, read in chunks
+pub(crate) async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Whole-file and per-chunk SHA-256 of a file, in one pass
+pub(crate) async fn digest_file(path: &Path) -> Result<FileDigest> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut whole = Sha256::new();
+    let mut chunks = Vec::new();
+    let mut buffer = vec![0; MAX_CHUNK_SIZE];
+    loop {
+        // Fill a whole chunk, short reads notwithstanding
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let n = file.read(&mut buffer[filled..]).await?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        if filled == 0 {
+            break;
+        }
+        whole.update(&buffer[..filled]);
+        chunks.push(hex(&Sha256::digest(&buffer[..filled])));
+        if filled < buffer.len() {
+            break;
+        }
+    }
+    Ok(FileDigest {
+        sha256: hex(&whole.finalize()),
+        chunks,
+    })
+}
+
+/// Digests of files, reused while their size and modification time stay put
+#[derive(Default)]
+pub(crate) struct DigestCache {
+    entries: std::sync::Mutex<HashMap<PathBuf, (SystemTime, u64, FileDigest)>>,
+}
+
+impl DigestCache {
+    pub(crate) async fn digest(&self, path: &Path) -> Result<FileDigest> {
+        let before = tokio::fs::metadata(path).await?;
+        let key = (before.modified()?, before.len());
+        if let Some((modified, size, digest)) = self.entries.lock().unwrap().get(path)
+            && (*modified, *size) == key
+        {
+            return Ok(digest.clone());
+        }
+
+        let digest = digest_file(path).await?;
+
+        // Don't remember a digest of content that changed while hashing
+        let after = tokio::fs::metadata(path).await?;
+        if (after.modified()?, after.len()) == key {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= DIGEST_CACHE_SIZE {
+                entries.clear();
+            }
+            entries.insert(path.to_path_buf(), (key.0, key.1, digest.clone()));
+        }
+        Ok(digest)
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,83 @@
+//! Change notifications for files
+//!
+//! Every write, upload commit, rename and delete sends a `ChangeEvent` to the
+//! kosha's watchers (all clones of a kosha share them). A `Watcher` only gets
+//! the events under its path prefix. Watchers that fall more than
+//! `WATCH_BUFFER` events behind get `Error::WatchLagged` and should re-read
+//! what they keep in sync.
+
+use crate::{Error, FileVersion, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Events kept for watchers that are slow to read
+pub const WATCH_BUFFER: usize = 256;
+
+/// How a file changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    /// Moved here from `from`, history included
+    Renamed { from: String },
+}
+
+/// A change to one file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// Path of the file (the new path for renames)
+    pub path: String,
+    #[serde(flatten)]
+    pub kind: ChangeKind,
+    /// Version written, or for deletes the version that was removed (now
+    /// in history)
+    pub version: FileVersion,
+}
+
+impl ChangeEvent {
+    /// Whether the change touches anything under `prefix`, either side of a
+    /// rename included
+    pub fn is_under(&self, prefix: &str) -> bool {
+        let under = |path: &str| {
+            prefix.is_empty()
+                || path
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        under(&self.path) || matches!(&self.kind, ChangeKind::Renamed { from } if under(from))
+    }
+}
+
+/// Changes under a path prefix, from `Kosha::watch`
+pub struct Watcher {
+    prefix: String,
+    events: broadcast::Receiver<ChangeEvent>,
+}
+
+impl Watcher {
+    pub(crate) fn new(prefix: String, events: broadcast::Receiver<ChangeEvent>) -> Self {
+        Self { prefix, events }
+    }
+
+    /// The prefix this watcher follows (empty for the whole kosha)
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Wait for the next change under the prefix; `None` once the kosha is
+    /// gone
+    ///
+    /// Cancel-safe: no change is lost when the returned future is dropped.
+    pub async fn next(&mut self) -> Result<Option<ChangeEvent>> {
+        loop {
+            match self.events.recv().await {
+                Ok(event) if event.is_under(&self.prefix) => return Ok(Some(event)),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => return Err(Error::WatchLagged(missed)),
+                Err(broadcast::error::RecvError::Closed) => return Ok(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Tests for the content-addressed blob store and its garbage collection
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{GcReport, HistoryPolicy};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn blob(dir: &Path, content: &[u8]) -> PathBuf {
+    let hash = sha256_hex(content);
+    dir.join("blobs").join(&hash[..2]).join(hash)
+}
+
+#[tokio::test]
+async fn test_identical_content_is_stored_once() {
+    let (kosha, dir) = create_test_kosha("dedup").await;
+    let content = vec![7u8; 10_000];
+
+    kosha.write_file("a.bin", &content).await.unwrap();
+    kosha.write_file("b.bin", &content).await.unwrap();
+    kosha.write_file("a.bin", b"changed").await.unwrap();
+    kosha.write_file("a.bin", &content).await.unwrap();
+
+    assert_eq!(std::fs::read(blob(&dir, &content)).unwrap(), content);
+    assert_eq!(kosha.read_file("b.bin").await.unwrap(), content);
+    assert_eq!(kosha.file_hash("b.bin").await.unwrap(), sha256_hex(&content));
+    assert_eq!(kosha.read_file_range("a.bin", 9_998..).await.unwrap(), vec![7, 7]);
+
+    // Sizes are of the content, not of the manifests
+    let versions = kosha.get_versions("a.bin").await.unwrap();
+    assert_eq!(versions[0].size, 10_000);
+    assert_eq!(kosha.list_dir("").await.unwrap()[1].size, 10_000);
+
+    let stats = kosha.stats().await.unwrap();
+    assert_eq!((stats.file_count, stats.file_bytes), (2, 20_000));
+    assert_eq!(stats.blob_count, 2);
+    assert_eq!(stats.blob_bytes, 10_000 + 7);
+    assert_eq!(kosha.storage_used().await.unwrap(), 10_000 + 7);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_copies_share_blobs() {
+    let (kosha, dir) = create_test_kosha("copy").await;
+    kosha.write_file("docs/a.txt", b"alpha").await.unwrap();
+    kosha.write_file("docs/b.txt", b"beta").await.unwrap();
+    let used = kosha.storage_used().await.unwrap();
+
+    kosha.copy("docs", "backup").await.unwrap();
+    assert_eq!(kosha.read_file("backup/a.txt").await.unwrap(), b"alpha");
+    assert_eq!(kosha.storage_used().await.unwrap(), used);
+    assert_eq!(kosha.stats().await.unwrap().blob_count, 2);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_uploads_are_stored_as_blobs() {
+    let (kosha, dir) = create_test_kosha("upload").await;
+    let content = b"uploaded in one chunk";
+    let hash = sha256_hex(content);
+
+    for path in ["a.txt", "b.txt"] {
+        let status = kosha.begin_upload(path, content.len() as u64, &hash, None).await.unwrap();
+        kosha.upload_chunk(&status.upload_id, 0, content).await.unwrap();
+        let version = kosha.commit_upload(&status.upload_id).await.unwrap();
+        assert_eq!(version.size, content.len() as u64);
+    }
+
+    assert_eq!(std::fs::read(blob(&dir, content)).unwrap(), content);
+    assert_eq!(kosha.read_file("b.txt").await.unwrap(), content);
+    assert_eq!(kosha.stats().await.unwrap().blob_count, 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_gc_removes_unreferenced_blobs() {
+    let (kosha, dir) = create_test_kosha("gc").await;
+    let kosha = kosha.with_history_policy(HistoryPolicy {
+        max_versions: Some(0),
+        max_age: None,
+    });
+    kosha.write_file("a.txt", b"first").await.unwrap();
+    kosha.write_file("a.txt", b"second").await.unwrap();
+    kosha.write_file("b.txt", b"second").await.unwrap();
+    kosha.write_file("c.txt", b"deleted").await.unwrap();
+    kosha.delete("c.txt").await.unwrap();
+
+    // A dry run only counts
+    let expected = GcReport {
+        kept: 1,
+        removed: 2,
+        freed_bytes: 5 + 7,
+    };
+    assert_eq!(kosha.gc(false).await.unwrap(), expected);
+    assert!(blob(&dir, b"first").exists());
+
+    assert_eq!(kosha.gc(true).await.unwrap(), expected);
+    assert!(!blob(&dir, b"first").exists());
+    assert!(!blob(&dir, b"deleted").exists());
+    assert_eq!(kosha.read_file("a.txt").await.unwrap(), b"second");
+    assert_eq!(kosha.storage_used().await.unwrap(), 6);
+
+    let again = kosha.gc(true).await.unwrap();
+    assert_eq!((again.kept, again.removed), (1, 0));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_history_keeps_blobs_alive() {
+    let (kosha, dir) = create_test_kosha("history").await;
+    kosha.write_file("a.txt", b"one").await.unwrap();
+    // Versions have second precision: make the first one older
+    std::fs::File::options()
+        .write(true)
+        .open(dir.join("files/a.txt"))
+        .unwrap()
+        .set_modified(UNIX_EPOCH + Duration::from_secs(1_000))
+        .unwrap();
+    kosha.write_file("a.txt", b"two").await.unwrap();
+    kosha.delete("a.txt").await.unwrap();
+
+    assert_eq!(kosha.gc(true).await.unwrap().removed, 0);
+    let versions = kosha.get_versions("a.txt").await.unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(kosha.read_version("a.txt", versions[1].timestamp).await.unwrap(), b"one");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_databases_are_stored_as_they_are() {
+    let (kosha, dir) = create_test_kosha("database").await;
+    kosha.db_execute("app.sqlite3", "CREATE TABLE t (x)", vec![]).await.unwrap();
+    kosha.db_execute("app.sqlite3", "INSERT INTO t VALUES (1)", vec![]).await.unwrap();
+
+    let file = std::fs::read(dir.join("files/app.sqlite3")).unwrap();
+    assert!(file.starts_with(b"SQLite format 3"));
+    assert_eq!(kosha.read_file("app.sqlite3").await.unwrap(), file);
+
+    assert_eq!(kosha.gc(true).await.unwrap(), GcReport::default());
+    let rows = kosha.db_query("app.sqlite3", "SELECT x FROM t", vec![]).await.unwrap();
+    assert_eq!(rows.len(), 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_files_put_in_place_by_hand_are_read_as_they_are() {
+    let (kosha, dir) = create_test_kosha("by-hand").await;
+    std::fs::write(dir.join("files/spokes.txt"), b"laptop: abc\n").unwrap();
+
+    assert_eq!(kosha.read_file("spokes.txt").await.unwrap(), b"laptop: abc\n");
+    assert_eq!(kosha.file_hash("spokes.txt").await.unwrap(), sha256_hex(b"laptop: abc\n"));
+    assert_eq!(kosha.gc(true).await.unwrap(), GcReport::default());
+    assert_eq!(kosha.storage_used().await.unwrap(), 12);
+
+    // The next write moves the content into a blob
+    kosha.write_file("spokes.txt", b"laptop: def\n").await.unwrap();
+    assert!(blob(&dir, b"laptop: def\n").exists());
+    assert_eq!(kosha.read_file("spokes.txt").await.unwrap(), b"laptop: def\n");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
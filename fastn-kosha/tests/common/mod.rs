@@ -0,0 +1,28 @@
+//! Helpers shared by the kosha's integration tests
+
+// Each test file uses only some of them
+#![allow(dead_code)]
+
+use fastn_kosha::Kosha;
+use std::path::PathBuf;
+
+/// An empty temp directory for a test, named after the test file and `name`
+pub fn test_dir(name: &str) -> PathBuf {
+    let temp_dir = std::env::temp_dir().join(format!(
+        "fastn-kosha-{}-{}-{}",
+        env!("CARGO_CRATE_NAME"),
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    temp_dir
+}
+
+/// Helper to create a kosha in its own temp directory
+pub async fn create_test_kosha(name: &str) -> (Kosha, PathBuf) {
+    let temp_dir = test_dir(name);
+    let kosha = Kosha::open(temp_dir.clone(), "test".to_string())
+        .await
+        .expect("Failed to open kosha");
+    (kosha, temp_dir)
+}
@@ -0,0 +1,199 @@
+//! Tests for the SQLite database subsystem
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{Error, Kosha};
+use serde_json::json;
+use std::time::Duration;
+
+async fn create_users_table(kosha: &Kosha, database: &str) {
+    kosha
+        .db_execute(database, "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, score REAL, avatar BLOB)", vec![])
+        .await
+        .expect("Failed to create table");
+}
+
+#[tokio::test]
+async fn test_execute_and_query_json_rows() {
+    let (kosha, dir) = create_test_kosha("roundtrip").await;
+    create_users_table(&kosha, "api/users.sqlite3").await;
+    assert!(dir.join("files/api/users.sqlite3").is_file());
+
+    let affected = kosha
+        .db_execute(
+            "api/users.sqlite3",
+            "INSERT INTO users (name, score, avatar) VALUES (?, ?, X'0102')",
+            vec![json!("Alice"), json!(1.5)],
+        )
+        .await
+        .unwrap();
+    assert_eq!(affected, 1);
+    kosha
+        .db_execute("api/users.sqlite3", "INSERT INTO users (name) VALUES (?)", vec![json!(null)])
+        .await
+        .unwrap();
+
+    let rows = kosha
+        .db_query("api/users.sqlite3", "SELECT id, name, score, avatar FROM users ORDER BY id", vec![])
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![json!([1, "Alice", 1.5, "AQI="]), json!([2, null, null, null])]);
+
+    let rows = kosha
+        .db_query("api/users.sqlite3", "SELECT name FROM users WHERE id = ?", vec![json!(1)])
+        .await
+        .unwrap();
+    assert_eq!(rows, vec![json!(["Alice"])]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_query_is_read_only() {
+    let (kosha, dir) = create_test_kosha("readonly").await;
+    create_users_table(&kosha, "users.sqlite3").await;
+
+    let write = kosha.db_query("users.sqlite3", "DELETE FROM users", vec![]).await;
+    assert!(matches!(write, Err(Error::Database(_))), "got {:?}", write);
+
+    let missing = kosha.db_query("missing.sqlite3", "SELECT 1", vec![]).await;
+    assert!(matches!(missing, Err(Error::NotFound(_))), "got {:?}", missing);
+
+    let not_a_database = kosha.db_execute("notes.txt", "SELECT 1", vec![]).await;
+    assert!(matches!(not_a_database, Err(Error::InvalidPath(_))), "got {:?}", not_a_database);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_other_files_are_unreachable() {
+    let (kosha, dir) = create_test_kosha("attach").await;
+    create_users_table(&kosha, "users.sqlite3").await;
+
+    let outside = dir.join("outside.sqlite3");
+    let attach = kosha
+        .db_execute("users.sqlite3", "ATTACH DATABASE ? AS other", vec![json!(outside.to_string_lossy())])
+        .await;
+    assert!(matches!(attach, Err(Error::Database(_))), "got {:?}", attach);
+    assert!(!outside.exists());
+
+    let vacuum = kosha
+        .db_execute("users.sqlite3", "VACUUM INTO ?", vec![json!(outside.to_string_lossy())])
+        .await;
+    assert!(matches!(vacuum, Err(Error::Database(_))), "got {:?}", vacuum);
+    assert!(!outside.exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_transaction_commit_and_rollback() {
+    let (kosha, dir) = create_test_kosha("transactions").await;
+    create_users_table(&kosha, "users.sqlite3").await;
+
+    let tx = kosha.db_begin("users.sqlite3").await.unwrap();
+    kosha
+        .db_tx_execute(&tx, "INSERT INTO users (name) VALUES (?)", vec![json!("Bob")])
+        .await
+        .unwrap();
+    let inside = kosha.db_tx_query(&tx, "SELECT count(*) FROM users", vec![]).await.unwrap();
+    assert_eq!(inside, vec![json!([1])]);
+    kosha.db_commit(&tx).await.unwrap();
+
+    let tx = kosha.db_begin("users.sqlite3").await.unwrap();
+    kosha
+        .db_tx_execute(&tx, "INSERT INTO users (name) VALUES (?)", vec![json!("Carol")])
+        .await
+        .unwrap();
+    kosha.db_rollback(&tx).await.unwrap();
+
+    let rows = kosha.db_query("users.sqlite3", "SELECT name FROM users", vec![]).await.unwrap();
+    assert_eq!(rows, vec![json!(["Bob"])]);
+
+    // Finished transactions are gone
+    let again = kosha.db_commit(&tx).await;
+    assert!(matches!(again, Err(Error::TransactionNotFound(_))), "got {:?}", again);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_rollback_all_transactions() {
+    let (kosha, dir) = create_test_kosha("rollback-all").await;
+    create_users_table(&kosha, "users.sqlite3").await;
+
+    let tx = kosha.db_begin("users.sqlite3").await.unwrap();
+    kosha
+        .db_tx_execute(&tx, "INSERT INTO users (name) VALUES ('Erin')", vec![])
+        .await
+        .unwrap();
+    assert_eq!(kosha.db_rollback_all().await, 1);
+    assert_eq!(kosha.db_rollback_all().await, 0);
+
+    let rows = kosha.db_query("users.sqlite3", "SELECT name FROM users", vec![]).await.unwrap();
+    assert!(rows.is_empty(), "got {:?}", rows);
+    assert!(kosha.db_transaction_database(&tx).is_none());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_transaction_times_out() {
+    let (kosha, dir) = create_test_kosha("timeout").await;
+    let kosha = kosha.with_transaction_timeout(Duration::from_millis(50));
+    create_users_table(&kosha, "users.sqlite3").await;
+
+    let tx = kosha.db_begin("users.sqlite3").await.unwrap();
+    kosha
+        .db_tx_execute(&tx, "INSERT INTO users (name) VALUES ('Dave')", vec![])
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let late = kosha.db_commit(&tx).await;
+    assert!(matches!(late, Err(Error::TransactionNotFound(_))), "got {:?}", late);
+    let rows = kosha.db_query("users.sqlite3", "SELECT count(*) FROM users", vec![]).await.unwrap();
+    assert_eq!(rows, vec![json!([0])]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_db_commands() {
+    let (kosha, dir) = create_test_kosha("commands").await;
+    create_users_table(&kosha, "users.sqlite3").await;
+
+    let begun = kosha
+        .handle_command("db_begin", json!({ "database": "users.sqlite3" }))
+        .await
+        .unwrap();
+    let tx_id = begun["tx_id"].as_str().unwrap();
+
+    let inserted = kosha
+        .handle_command(
+            "db_tx_execute",
+            json!({ "database": "users.sqlite3", "tx_id": tx_id, "sql": "INSERT INTO users (name) VALUES (?)", "params": ["Eve"] }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(inserted["affected"], 1);
+
+    // A transaction can only be used with the database it was opened on
+    let wrong = kosha
+        .handle_command("db_commit", json!({ "database": "other.sqlite3", "tx_id": tx_id }))
+        .await;
+    assert!(wrong.is_err());
+
+    kosha
+        .handle_command("db_commit", json!({ "database": "users.sqlite3", "tx_id": tx_id }))
+        .await
+        .unwrap();
+    let queried = kosha
+        .handle_command("db_query", json!({ "database": "users.sqlite3", "sql": "SELECT name FROM users" }))
+        .await
+        .unwrap();
+    assert_eq!(queried["rows"], json!([["Eve"]]));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
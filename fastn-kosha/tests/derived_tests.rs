@@ -0,0 +1,87 @@
+//! Tests for derived artifacts following their files
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{Error, Kosha};
+
+/// Write a file with a `meta.json` artifact
+async fn write_with_meta(kosha: &Kosha, path: &str, meta: &str) {
+    kosha.write_file(path, b"model").await.unwrap();
+    kosha.write_derived(path, "meta.json", meta.as_bytes()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rename_moves_derived() {
+    let (kosha, dir) = create_test_kosha("rename").await;
+    write_with_meta(&kosha, "models/robot.glb", "robot").await;
+
+    kosha.rename("models/robot.glb", "models/bot.glb").await.unwrap();
+
+    assert_eq!(kosha.read_derived("models/bot.glb", "meta.json").await.unwrap(), b"robot");
+    assert!(matches!(
+        kosha.read_derived("models/robot.glb", "meta.json").await,
+        Err(Error::NotFound(_))
+    ));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_delete_removes_derived() {
+    let (kosha, dir) = create_test_kosha("delete").await;
+    write_with_meta(&kosha, "robot.glb", "robot").await;
+
+    kosha.delete("robot.glb").await.unwrap();
+    assert!(std::fs::read_dir(dir.join("derived")).unwrap().next().is_none());
+
+    // A new file at the path doesn't inherit the old one's artifacts
+    kosha.write_file("robot.glb", b"another model").await.unwrap();
+    assert!(matches!(
+        kosha.read_derived("robot.glb", "meta.json").await,
+        Err(Error::NotFound(_))
+    ));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_directory_operations_carry_derived() {
+    let (kosha, dir) = create_test_kosha("dir-ops").await;
+    write_with_meta(&kosha, "models/a.glb", "a").await;
+    write_with_meta(&kosha, "models/sub/b.glb", "b").await;
+
+    kosha.copy("models", "backup").await.unwrap();
+    assert_eq!(kosha.read_derived("backup/a.glb", "meta.json").await.unwrap(), b"a");
+    assert_eq!(kosha.read_derived("backup/sub/b.glb", "meta.json").await.unwrap(), b"b");
+    assert_eq!(kosha.read_derived("models/a.glb", "meta.json").await.unwrap(), b"a");
+
+    kosha.move_path("models", "assets").await.unwrap();
+    assert_eq!(kosha.read_derived("assets/sub/b.glb", "meta.json").await.unwrap(), b"b");
+    assert!(kosha.read_derived("models/sub/b.glb", "meta.json").await.is_err());
+
+    kosha.delete_dir("assets").await.unwrap();
+    assert!(kosha.read_derived("assets/a.glb", "meta.json").await.is_err());
+    assert_eq!(kosha.read_derived("backup/a.glb", "meta.json").await.unwrap(), b"a");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_derived_stay_with_their_own_path() {
+    let (kosha, dir) = create_test_kosha("own-path").await;
+    write_with_meta(&kosha, "a.glb", "a").await;
+    // Flattens to a name starting with a.glb's prefix
+    write_with_meta(&kosha, "a.glb__x", "x").await;
+
+    kosha.delete("a.glb").await.unwrap();
+    assert_eq!(kosha.read_derived("a.glb__x", "meta.json").await.unwrap(), b"x");
+
+    // Names holding the separator would be ambiguous
+    assert!(matches!(
+        kosha.write_derived("a.glb__x", "x__meta.json", b"").await,
+        Err(Error::InvalidPath(_))
+    ));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,168 @@
+//! Tests for recursive directory operations: delete_dir, copy and move
+
+mod common;
+
+use common::create_test_kosha;
+use chrono::{DateTime, Utc};
+use fastn_kosha::{ChangeKind, Error, Kosha};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Write a file and pretend it was written at `secs` since the epoch
+async fn write_at(kosha: &Kosha, dir: &Path, path: &str, content: &str, secs: u64) {
+    kosha.write_file(path, content.as_bytes()).await.unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(dir.join("files").join(path))
+        .unwrap()
+        .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap();
+}
+
+fn at(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap()
+}
+
+/// A small tree: docs/a.txt (two versions), docs/sub/b.txt
+async fn write_tree(kosha: &Kosha, dir: &Path) {
+    write_at(kosha, dir, "docs/a.txt", "a1", 1_000).await;
+    write_at(kosha, dir, "docs/a.txt", "a2", 2_000).await;
+    write_at(kosha, dir, "docs/sub/b.txt", "b", 3_000).await;
+}
+
+#[tokio::test]
+async fn test_list_files_is_recursive() {
+    let (kosha, dir) = create_test_kosha("list").await;
+    write_tree(&kosha, &dir).await;
+
+    assert_eq!(kosha.list_files("/docs/").await.unwrap(), vec!["docs/a.txt", "docs/sub/b.txt"]);
+    assert_eq!(kosha.list_files("docs/a.txt").await.unwrap(), vec!["docs/a.txt"]);
+    assert!(matches!(kosha.list_files("missing").await, Err(Error::NotFound(_))));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_delete_dir_keeps_history_of_each_file() {
+    let (kosha, dir) = create_test_kosha("delete-dir").await;
+    write_tree(&kosha, &dir).await;
+    let mut watcher = kosha.watch("docs").unwrap();
+
+    let deleted = kosha.delete_dir("docs").await.unwrap();
+    assert_eq!(deleted, vec!["docs/a.txt", "docs/sub/b.txt"]);
+    assert!(!dir.join("files").join("docs").exists());
+
+    assert_eq!(kosha.read_version("docs/a.txt", at(1_000)).await.unwrap(), b"a1");
+    assert_eq!(kosha.read_version("docs/a.txt", at(2_000)).await.unwrap(), b"a2");
+    assert_eq!(kosha.read_version("docs/sub/b.txt", at(3_000)).await.unwrap(), b"b");
+
+    for path in deleted {
+        let event = watcher.next().await.unwrap().unwrap();
+        assert_eq!((event.path, event.kind), (path, ChangeKind::Deleted));
+    }
+
+    assert!(matches!(kosha.delete_dir("").await, Err(Error::InvalidPath(_))));
+    assert!(matches!(kosha.delete_dir("docs").await, Err(Error::NotFound(_))));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_copy_leaves_source_and_its_history() {
+    let (kosha, dir) = create_test_kosha("copy").await;
+    write_tree(&kosha, &dir).await;
+    let mut watcher = kosha.watch("").unwrap();
+
+    let copied = kosha.copy("docs", "backup/docs").await.unwrap();
+    assert_eq!(copied, vec!["backup/docs/a.txt", "backup/docs/sub/b.txt"]);
+    assert_eq!(kosha.read_file("backup/docs/a.txt").await.unwrap(), b"a2");
+    assert_eq!(kosha.read_file("backup/docs/sub/b.txt").await.unwrap(), b"b");
+    for path in &copied {
+        let event = watcher.next().await.unwrap().unwrap();
+        assert_eq!((&event.path, event.kind), (path, ChangeKind::Created));
+    }
+
+    // The source is untouched; the copy starts a history of its own
+    assert_eq!(kosha.get_versions("docs/a.txt").await.unwrap().len(), 2);
+    assert_eq!(kosha.get_versions("backup/docs/a.txt").await.unwrap().len(), 1);
+
+    // A single file copies too
+    assert_eq!(kosha.copy("docs/a.txt", "a.txt").await.unwrap(), vec!["a.txt"]);
+    assert_eq!(kosha.read_file("a.txt").await.unwrap(), b"a2");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_move_takes_history_along() {
+    let (kosha, dir) = create_test_kosha("move").await;
+    write_tree(&kosha, &dir).await;
+    let mut watcher = kosha.watch("").unwrap();
+
+    let moved = kosha.move_path("docs", "archive/2024").await.unwrap();
+    assert_eq!(
+        moved,
+        vec![
+            ("docs/a.txt".to_string(), "archive/2024/a.txt".to_string()),
+            ("docs/sub/b.txt".to_string(), "archive/2024/sub/b.txt".to_string()),
+        ]
+    );
+    assert!(!dir.join("files").join("docs").exists());
+    assert!(kosha.get_versions("docs/a.txt").await.is_err());
+    assert_eq!(kosha.read_version("archive/2024/a.txt", at(1_000)).await.unwrap(), b"a1");
+    assert_eq!(kosha.read_file("archive/2024/sub/b.txt").await.unwrap(), b"b");
+
+    for (from, to) in moved {
+        let event = watcher.next().await.unwrap().unwrap();
+        assert_eq!((event.path, event.kind), (to, ChangeKind::Renamed { from }));
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_copy_and_move_refuse_bad_destinations() {
+    let (kosha, dir) = create_test_kosha("refuse").await;
+    write_tree(&kosha, &dir).await;
+    kosha.write_file("other/c.txt", b"c").await.unwrap();
+
+    assert!(matches!(kosha.copy("docs", "other").await, Err(Error::Conflict(_))));
+    assert!(matches!(kosha.move_path("docs", "docs/sub/docs").await, Err(Error::InvalidPath(_))));
+    assert!(matches!(kosha.move_path("docs", "docs").await, Err(Error::InvalidPath(_))));
+    assert!(matches!(kosha.copy("", "all").await, Err(Error::InvalidPath(_))));
+    assert!(matches!(kosha.move_path("missing", "found").await, Err(Error::NotFound(_))));
+
+    // A copied file would be shadowed by an existing handler
+    kosha.write_file("site/a.txt.wasm", b"\0asm").await.unwrap();
+    assert!(matches!(kosha.copy("docs", "site").await, Err(Error::Conflict(_))));
+    assert!(!dir.join("files").join("site").join("sub").exists());
+
+    assert_eq!(kosha.list_files("docs").await.unwrap().len(), 2);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_dir_commands() {
+    let (kosha, dir) = create_test_kosha("commands").await;
+    write_tree(&kosha, &dir).await;
+
+    let copied = kosha
+        .handle_command("copy", serde_json::json!({ "from": "docs", "to": "copy" }))
+        .await
+        .unwrap();
+    assert_eq!(copied, serde_json::json!({ "copied": ["copy/a.txt", "copy/sub/b.txt"] }));
+
+    let moved = kosha
+        .handle_command("move", serde_json::json!({ "from": "copy/sub", "to": "moved" }))
+        .await
+        .unwrap();
+    assert_eq!(moved, serde_json::json!({ "moved": [{ "from": "copy/sub/b.txt", "to": "moved/b.txt" }] }));
+
+    let deleted = kosha
+        .handle_command("delete_dir", serde_json::json!({ "path": "copy" }))
+        .await
+        .unwrap();
+    assert_eq!(deleted, serde_json::json!({ "deleted": ["copy/a.txt"] }));
+
+    assert!(kosha.handle_command("move", serde_json::json!({ "from": "docs" })).await.is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,122 @@
+//! Tests for atomic writes and durability settings
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{Durability, Kosha, STALE_TEMP_AGE};
+use std::time::SystemTime;
+
+fn tmp_entries(dir: &std::path::Path) -> usize {
+    std::fs::read_dir(dir.join("tmp")).unwrap().count()
+}
+
+#[tokio::test]
+async fn test_readers_never_see_partial_writes() {
+    let (kosha, dir) = create_test_kosha("atomic-read").await;
+    const SIZE: usize = 1 << 20;
+    kosha.write_file("big.bin", &vec![0u8; SIZE]).await.unwrap();
+
+    let writer = {
+        let kosha = kosha.clone();
+        tokio::spawn(async move {
+            for round in 1..=20u8 {
+                kosha.write_file("big.bin", &vec![round; SIZE]).await.unwrap();
+            }
+        })
+    };
+    while !writer.is_finished() {
+        // The file never goes missing, and is always one whole version
+        let content = kosha.read_file("big.bin").await.unwrap();
+        assert_eq!(content.len(), SIZE);
+        assert!(content.iter().all(|b| *b == content[0]));
+        tokio::task::yield_now().await;
+    }
+    writer.await.unwrap();
+
+    assert_eq!(kosha.read_file("big.bin").await.unwrap(), vec![20u8; SIZE]);
+    assert_eq!(tmp_entries(&dir), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_history_keeps_replaced_content() {
+    let (kosha, dir) = create_test_kosha("atomic-history").await;
+
+    kosha.write_file("a.txt", b"one").await.unwrap();
+    let first = kosha.current_version("a.txt").await.unwrap().unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    kosha.write_file("a.txt", b"two").await.unwrap();
+
+    assert_eq!(kosha.read_file("a.txt").await.unwrap(), b"two");
+    assert_eq!(kosha.read_version("a.txt", first.timestamp).await.unwrap(), b"one");
+
+    kosha.delete("a.txt").await.unwrap();
+    assert!(kosha.read_file("a.txt").await.is_err());
+    assert_eq!(kosha.get_versions("a.txt").await.unwrap().len(), 2);
+    assert_eq!(tmp_entries(&dir), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_temp_files_are_not_listed() {
+    let (kosha, dir) = create_test_kosha("atomic-list").await;
+
+    kosha.write_file("docs/a.txt", b"a").await.unwrap();
+    kosha.write_file("docs/a.txt", b"b").await.unwrap();
+    kosha.kv_set("key", serde_json::json!(1)).await.unwrap();
+    kosha.write_derived("docs/a.txt", "meta.json", b"{}").await.unwrap();
+
+    let names: Vec<_> = kosha.list_dir("docs").await.unwrap().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, vec!["a.txt"]);
+    let derived: Vec<_> = std::fs::read_dir(dir.join("derived"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(derived, vec!["docs~a.txt__meta.json"]);
+    assert!(!dir.join("kv").join("store.dson.tmp").exists());
+    assert_eq!(tmp_entries(&dir), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_durability_settings() {
+    let (kosha, dir) = create_test_kosha("durability").await;
+    assert_eq!(kosha.durability(), Durability::Data);
+
+    for durability in [Durability::Buffered, Durability::Data, Durability::Full] {
+        let kosha = kosha.clone().with_durability(durability);
+        assert_eq!(kosha.durability(), durability);
+        kosha.write_file("a.txt", format!("{:?}", durability).as_bytes()).await.unwrap();
+        assert_eq!(kosha.read_file("a.txt").await.unwrap(), format!("{:?}", durability).as_bytes());
+        kosha.kv_set("k", serde_json::json!(format!("{:?}", durability))).await.unwrap();
+    }
+    let kosha = kosha.with_durability(Durability::Full);
+    kosha.rename("a.txt", "b.txt").await.unwrap();
+    kosha.delete("b.txt").await.unwrap();
+
+    assert_eq!(serde_json::to_string(&Durability::Full).unwrap(), "\"full\"");
+    assert_eq!(tmp_entries(&dir), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_open_removes_stale_temp_files() {
+    let (_kosha, dir) = create_test_kosha("stale-temp").await;
+
+    // Left by a crashed process, and by one still writing
+    let crashed = dir.join("tmp").join("1-0.tmp");
+    std::fs::write(&crashed, b"partial").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&crashed)
+        .unwrap()
+        .set_modified(SystemTime::now() - STALE_TEMP_AGE * 2)
+        .unwrap();
+    let recent = dir.join("tmp").join("2-0.tmp");
+    std::fs::write(&recent, b"in progress").unwrap();
+
+    Kosha::open(dir.clone(), "stale-temp".to_string()).await.unwrap();
+    assert!(!crashed.exists());
+    assert!(recent.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
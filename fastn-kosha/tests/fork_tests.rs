@@ -0,0 +1,103 @@
+//! Tests for copy-on-write forks
+
+mod common;
+
+use fastn_kosha::{Error, Kosha};
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Helper to create a kosha in `source/` of its own temp directory, leaving
+/// room next to it
+async fn create_test_kosha(name: &str) -> (Kosha, PathBuf) {
+    let temp_dir = common::test_dir(name);
+    let kosha = Kosha::open(temp_dir.join("source"), "source".to_string())
+        .await
+        .expect("Failed to open kosha");
+    (kosha, temp_dir)
+}
+
+#[cfg(unix)]
+fn inode(path: PathBuf) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).unwrap().ino()
+}
+
+#[tokio::test]
+async fn test_fork_shares_files_until_written() {
+    let (source, dir) = create_test_kosha("shares").await;
+    source.write_file("notes/a.txt", b"one").await.unwrap();
+    source.write_file("notes/a.txt", b"two").await.unwrap();
+    source.write_derived("notes/a.txt", "summary", b"2 words").await.unwrap();
+    source.kv_set("theme", json!("dark")).await.unwrap();
+
+    let fork = source.fork(dir.join("fork"), "fork".to_string()).await.unwrap();
+    assert_eq!(fork.alias(), "fork");
+    assert_eq!(fork.read_file("notes/a.txt").await.unwrap(), b"two");
+    assert_eq!(fork.read_derived("notes/a.txt", "summary").await.unwrap(), b"2 words");
+    assert_eq!(fork.kv_get("theme").await.unwrap(), Some(json!("dark")));
+
+    // Versions keep their timestamps
+    let versions = source.get_versions("notes/a.txt").await.unwrap();
+    let fork_versions = fork.get_versions("notes/a.txt").await.unwrap();
+    assert_eq!(
+        versions.iter().map(|v| v.timestamp).collect::<Vec<_>>(),
+        fork_versions.iter().map(|v| v.timestamp).collect::<Vec<_>>()
+    );
+
+    #[cfg(unix)]
+    assert_eq!(
+        inode(dir.join("source/files/notes/a.txt")),
+        inode(dir.join("fork/files/notes/a.txt"))
+    );
+
+    // Writes on either side stay on that side
+    fork.write_file("notes/a.txt", b"three").await.unwrap();
+    fork.write_derived("notes/a.txt", "summary", b"3 words").await.unwrap();
+    fork.kv_set("theme", json!("light")).await.unwrap();
+    source.write_file("notes/b.txt", b"new").await.unwrap();
+
+    assert_eq!(source.read_file("notes/a.txt").await.unwrap(), b"two");
+    assert_eq!(source.read_derived("notes/a.txt", "summary").await.unwrap(), b"2 words");
+    assert_eq!(source.kv_get("theme").await.unwrap(), Some(json!("dark")));
+    assert_eq!(fork.read_file("notes/a.txt").await.unwrap(), b"three");
+    assert!(matches!(fork.read_file("notes/b.txt").await, Err(Error::NotFound(_))));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_fork_copies_databases() {
+    let (source, dir) = create_test_kosha("databases").await;
+    source
+        .db_execute("app.sqlite3", "CREATE TABLE items (name TEXT)", vec![])
+        .await
+        .unwrap();
+    source
+        .db_execute("app.sqlite3", "INSERT INTO items VALUES (?)", vec![json!("first")])
+        .await
+        .unwrap();
+
+    let fork = source.fork(dir.join("fork"), "fork".to_string()).await.unwrap();
+    fork.db_execute("app.sqlite3", "INSERT INTO items VALUES (?)", vec![json!("second")])
+        .await
+        .unwrap();
+
+    let count = "SELECT COUNT(*) FROM items";
+    assert_eq!(source.db_query("app.sqlite3", count, vec![]).await.unwrap(), vec![json!([1])]);
+    assert_eq!(fork.db_query("app.sqlite3", count, vec![]).await.unwrap(), vec![json!([2])]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_fork_into_existing_directory_fails() {
+    let (source, dir) = create_test_kosha("existing").await;
+    source.write_file("a.txt", b"one").await.unwrap();
+    std::fs::create_dir_all(dir.join("taken")).unwrap();
+
+    let result = source.fork(dir.join("taken"), "taken".to_string()).await;
+    assert!(matches!(result, Err(Error::Conflict(_))), "got {:?}", result.err());
+    assert!(!dir.join("taken/files").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,205 @@
+//! Tests for get/post resolution and WASM handler execution
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{Error, HandlerLimits, RequestContext, ResponseBody};
+use tokio_util::sync::CancellationToken;
+
+fn request(method: &str, path: &str) -> RequestContext {
+    RequestContext {
+        requester_hub_id: "hub".to_string(),
+        current_hub_id: "hub".to_string(),
+        spoke_id52: "spoke".to_string(),
+        method: method.to_string(),
+        path: path.to_string(),
+        query: Some("page=2".to_string()),
+        payload: None,
+    }
+}
+
+/// Handler that responds with the request context as its JSON body
+///
+/// The response prefix sits at the start of memory and `alloc` places the
+/// context right after it, so the module only has to append `}}`.
+fn echo_handler() -> Vec<u8> {
+    let prefix = r#"{"content_type":"application/json","body":{"type":"Json","data":"#;
+    let wat = format!(
+        r#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{escaped}")
+            (func (export "alloc") (param i32) (result i32) i32.const {len})
+            (func (export "handle") (param $ptr i32) (param $len i32) (result i64)
+                (local $end i32)
+                (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+                (i32.store8 (local.get $end) (i32.const 125))
+                (i32.store8 (i32.add (local.get $end) (i32.const 1)) (i32.const 125))
+                (i64.extend_i32_u (i32.add (local.get $end) (i32.const 2)))))"#,
+        escaped = prefix.replace('"', "\\\""),
+        len = prefix.len(),
+    );
+    wat::parse_str(&wat).expect("Invalid WAT")
+}
+
+/// Handler that never returns
+fn looping_handler() -> Vec<u8> {
+    wat::parse_str(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 0)
+            (func (export "handle") (param i32 i32) (result i64)
+                (loop $forever (br $forever))
+                (i64.const 0)))"#,
+    )
+    .expect("Invalid WAT")
+}
+
+#[tokio::test]
+async fn test_get_serves_static_file() {
+    let (kosha, dir) = create_test_kosha("static").await;
+    kosha.write_file("data/info.json", br#"{"a":1}"#).await.unwrap();
+
+    let response = kosha.get(&request("GET", "data/info.json")).await.unwrap();
+    assert_eq!(response.content_type, "application/json");
+    assert!(response.etag.is_some());
+    match response.body {
+        ResponseBody::Bytes(bytes) => assert_eq!(bytes, br#"{"a":1}"#),
+        other => panic!("Expected bytes, got: {:?}", other),
+    }
+
+    let missing = kosha.get(&request("GET", "data/missing.json")).await.unwrap();
+    assert!(matches!(missing.body, ResponseBody::NotFound));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_get_runs_file_handler_with_context() {
+    let (kosha, dir) = create_test_kosha("file-handler").await;
+    kosha.write_file("api/echo.json.wasm", &echo_handler()).await.unwrap();
+
+    let response = kosha.get(&request("GET", "/api/echo.json")).await.unwrap();
+    assert_eq!(response.content_type, "application/json");
+    match response.body {
+        ResponseBody::Json(ctx) => {
+            assert_eq!(ctx["method"], "GET");
+            assert_eq!(ctx["path"], "/api/echo.json");
+            assert_eq!(ctx["query"], "page=2");
+            assert_eq!(ctx["spoke_id52"], "spoke");
+        }
+        other => panic!("Expected JSON, got: {:?}", other),
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_directory_resolution() {
+    let (kosha, dir) = create_test_kosha("directory").await;
+    kosha.write_file("api/users/index.wasm", &echo_handler()).await.unwrap();
+
+    let response = kosha.get(&request("GET", "api/users/")).await.unwrap();
+    assert!(matches!(response.body, ResponseBody::Json(_)));
+
+    let response = kosha.get(&request("GET", "api/users")).await.unwrap();
+    match response.body {
+        ResponseBody::Redirect(target) => assert_eq!(target, "api/users/"),
+        other => panic!("Expected redirect, got: {:?}", other),
+    }
+
+    // foo.wasm and foo/index.wasm cannot both exist
+    let conflict = kosha.write_file("api/users.wasm", &echo_handler()).await;
+    assert!(matches!(conflict, Err(Error::Conflict(_))), "got {:?}", conflict);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_post_runs_handler_with_payload() {
+    let (kosha, dir) = create_test_kosha("post").await;
+    kosha.write_file("api/submit.wasm", &echo_handler()).await.unwrap();
+    kosha.write_file("notes.txt", b"hello").await.unwrap();
+
+    let mut ctx = request("POST", "api/submit");
+    ctx.payload = Some(serde_json::json!({ "name": "robot" }));
+    match kosha.post(&ctx).await.unwrap().body {
+        ResponseBody::Json(echoed) => assert_eq!(echoed["payload"]["name"], "robot"),
+        other => panic!("Expected JSON, got: {:?}", other),
+    }
+
+    let static_post = kosha.post(&request("POST", "notes.txt")).await;
+    assert!(matches!(static_post, Err(Error::MethodNotAllowed(_))), "got {:?}", static_post);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_special_files_are_not_served() {
+    let (kosha, dir) = create_test_kosha("special").await;
+    kosha.write_file("_access.wasm", &echo_handler()).await.unwrap();
+
+    let response = kosha.get(&request("GET", "_access.wasm")).await.unwrap();
+    assert!(matches!(response.body, ResponseBody::NotFound));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_handler_and_static_file_conflict() {
+    let (kosha, dir) = create_test_kosha("conflict").await;
+    kosha.write_file("foo.json", b"{}").await.unwrap();
+
+    let conflict = kosha.write_file("foo.json.wasm", &echo_handler()).await;
+    assert!(matches!(conflict, Err(Error::Conflict(_))), "got {:?}", conflict);
+
+    kosha.write_file("bar.json.wasm", &echo_handler()).await.unwrap();
+    let conflict = kosha.rename("foo.json", "bar.json").await;
+    assert!(matches!(conflict, Err(Error::Conflict(_))), "got {:?}", conflict);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_handler_fuel_limit() {
+    let (kosha, dir) = create_test_kosha("fuel").await;
+    let kosha = kosha.with_handler_limits(HandlerLimits {
+        fuel: 100_000,
+        ..HandlerLimits::default()
+    });
+    kosha.write_file("spin.wasm", &looping_handler()).await.unwrap();
+
+    match kosha.get(&request("GET", "spin")).await {
+        Err(Error::WasmExecution(message)) => assert!(message.contains("fuel limit exceeded"), "{}", message),
+        other => panic!("Expected WasmExecution error, got: {:?}", other),
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_handler_stops_when_request_is_cancelled() {
+    let (kosha, dir) = create_test_kosha("cancel").await;
+    let kosha = kosha.with_handler_limits(HandlerLimits {
+        fuel: u64::MAX,
+        timeout: std::time::Duration::from_secs(30),
+        ..HandlerLimits::default()
+    });
+    kosha.write_file("spin.wasm", &looping_handler()).await.unwrap();
+
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            cancel.cancel();
+        }
+    });
+    let started = std::time::Instant::now();
+    match fastn_kosha::with_cancellation(cancel, kosha.get(&request("GET", "spin"))).await {
+        Err(Error::WasmExecution(message)) => assert!(message.contains("cancelled"), "{}", message),
+        other => panic!("Expected WasmExecution error, got: {:?}", other),
+    }
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
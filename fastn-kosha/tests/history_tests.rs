@@ -0,0 +1,170 @@
+//! Tests for versioned file history
+
+mod common;
+
+use common::create_test_kosha;
+use chrono::{DateTime, Utc};
+use fastn_kosha::{HistoryPolicy, Kosha};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Write a file and pretend it was written at `secs` since the epoch
+async fn write_at(kosha: &Kosha, dir: &Path, path: &str, content: &str, secs: u64) {
+    kosha.write_file(path, content.as_bytes()).await.unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(dir.join("files").join(path))
+        .unwrap()
+        .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap();
+}
+
+fn at(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap()
+}
+
+#[tokio::test]
+async fn test_write_creates_history() {
+    let (kosha, dir) = create_test_kosha("write-history").await;
+
+    write_at(&kosha, &dir, "notes/a.txt", "one", 1_000).await;
+    write_at(&kosha, &dir, "notes/a.txt", "two", 2_000).await;
+    write_at(&kosha, &dir, "notes/a.txt", "three", 3_000).await;
+
+    assert!(dir.join("history").join("notes~a.txt__19700101T001640Z").is_file());
+
+    let versions = kosha.get_versions("notes/a.txt").await.unwrap();
+    let timestamps: Vec<_> = versions.iter().map(|v| v.timestamp).collect();
+    assert_eq!(timestamps, vec![at(3_000), at(2_000), at(1_000)]);
+
+    assert_eq!(kosha.read_version("notes/a.txt", at(1_000)).await.unwrap(), b"one");
+    assert_eq!(kosha.read_version("notes/a.txt", at(2_000)).await.unwrap(), b"two");
+    assert_eq!(kosha.read_version("notes/a.txt", at(3_000)).await.unwrap(), b"three");
+    assert!(kosha.read_version("notes/a.txt", at(1_500)).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_delete_and_rename_keep_history() {
+    let (kosha, dir) = create_test_kosha("delete-rename").await;
+
+    write_at(&kosha, &dir, "a.txt", "one", 1_000).await;
+    write_at(&kosha, &dir, "a.txt", "two", 2_000).await;
+    kosha.rename("a.txt", "b/c.txt").await.unwrap();
+
+    assert!(kosha.get_versions("a.txt").await.is_err());
+    assert_eq!(kosha.get_versions("b/c.txt").await.unwrap().len(), 2);
+
+    kosha.delete("b/c.txt").await.unwrap();
+    assert!(kosha.read_file("b/c.txt").await.is_err());
+    assert_eq!(kosha.read_version("b/c.txt", at(2_000)).await.unwrap(), b"two");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_history_policy_prunes_old_versions() {
+    let (kosha, dir) = create_test_kosha("prune").await;
+    let kosha = kosha.with_history_policy(HistoryPolicy {
+        max_versions: Some(2),
+        max_age: None,
+    });
+
+    for (i, content) in ["one", "two", "three", "four"].iter().enumerate() {
+        write_at(&kosha, &dir, "a.txt", content, 1_000 * (i as u64 + 1)).await;
+    }
+
+    // Current content plus the two newest history entries
+    let timestamps: Vec<_> = kosha
+        .get_versions("a.txt")
+        .await
+        .unwrap()
+        .iter()
+        .map(|v| v.timestamp)
+        .collect();
+    assert_eq!(timestamps, vec![at(4_000), at(3_000), at(2_000)]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_write_with_stale_base_version_conflicts() {
+    let (kosha, dir) = create_test_kosha("base-version").await;
+
+    write_at(&kosha, &dir, "a.txt", "one", 1_000).await;
+
+    // Writer based on the current version succeeds
+    let version = kosha.write_file_versioned("a.txt", b"two", Some(at(1_000))).await.unwrap();
+    assert_eq!(version.size, 3);
+
+    // Writer still based on the old version is rejected, nothing written
+    match kosha.write_file_versioned("a.txt", b"stale", Some(at(1_000))).await {
+        Err(fastn_kosha::Error::VersionConflict { current: Some(current), .. }) => {
+            assert_eq!(current.timestamp, version.timestamp);
+        }
+        other => panic!("Expected VersionConflict, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(kosha.read_file("a.txt").await.unwrap(), b"two");
+
+    // Base version for a file that doesn't exist
+    assert!(matches!(
+        kosha.write_file_versioned("missing.txt", b"x", Some(at(1_000))).await,
+        Err(fastn_kosha::Error::VersionConflict { current: None, .. })
+    ));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_writes_within_a_second_get_their_own_versions() {
+    let (kosha, dir) = create_test_kosha("same-second").await;
+
+    let mut versions = Vec::new();
+    for content in ["one", "two", "three"] {
+        versions.push(kosha.write_file_versioned("a.txt", content.as_bytes(), None).await.unwrap().timestamp);
+    }
+    assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // Every content written is still there, at its own version
+    assert_eq!(kosha.get_versions("a.txt").await.unwrap().len(), 3);
+    assert_eq!(kosha.read_version("a.txt", versions[0]).await.unwrap(), b"one");
+    assert_eq!(kosha.read_version("a.txt", versions[1]).await.unwrap(), b"two");
+
+    // Written again right after a delete: the deleted content stays in history
+    kosha.delete("a.txt").await.unwrap();
+    let again = kosha.write_file_versioned("a.txt", b"four", None).await.unwrap().timestamp;
+    assert!(!versions.contains(&again));
+    assert_eq!(kosha.read_version("a.txt", versions[2]).await.unwrap(), b"three");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_concurrent_writes_from_one_base_version() {
+    let (kosha, dir) = create_test_kosha("concurrent").await;
+    let base = kosha.write_file_versioned("a.txt", b"base", None).await.unwrap().timestamp;
+
+    let writers: Vec<_> = (0..8)
+        .map(|i| {
+            let kosha = kosha.clone();
+            tokio::spawn(async move {
+                kosha.write_file_versioned("a.txt", format!("writer {}", i).as_bytes(), Some(base)).await
+            })
+        })
+        .collect();
+    let mut written = 0;
+    for writer in writers {
+        match writer.await.unwrap() {
+            Ok(_) => written += 1,
+            Err(fastn_kosha::Error::VersionConflict { .. }) => {}
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    // Only one writer saw the base version; the rest conflicted
+    assert_eq!(written, 1);
+    assert_eq!(kosha.get_versions("a.txt").await.unwrap().len(), 2);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
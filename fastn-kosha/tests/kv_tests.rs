@@ -0,0 +1,79 @@
+//! Tests for the CRDT key-value store
+
+mod common;
+
+use fastn_kosha::Kosha;
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Helper to create a kosha with the given actor in its own temp directory
+async fn create_test_kosha(name: &str, actor: &str) -> (Kosha, PathBuf) {
+    let (kosha, temp_dir) = common::create_test_kosha(name).await;
+    (kosha.with_actor_id(actor), temp_dir)
+}
+
+#[tokio::test]
+async fn test_kv_set_get_delete_persists() {
+    let (kosha, dir) = create_test_kosha("persist", "hub-a").await;
+
+    kosha.kv_set("settings/theme", json!("dark")).await.unwrap();
+    kosha.kv_set("counter", json!(1)).await.unwrap();
+    kosha.kv_delete("counter").await.unwrap();
+
+    // Reopen from disk
+    let reopened = Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    assert_eq!(reopened.kv_get("settings/theme").await.unwrap(), Some(json!("dark")));
+    assert_eq!(reopened.kv_get("counter").await.unwrap(), None);
+    assert_eq!(reopened.kv_get("missing").await.unwrap(), None);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_kv_merge_converges() {
+    let (a, dir_a) = create_test_kosha("merge-a", "hub-a").await;
+    let (b, dir_b) = create_test_kosha("merge-b", "hub-b").await;
+
+    a.kv_set("shared", json!("from a")).await.unwrap();
+    a.kv_set("only-a", json!(1)).await.unwrap();
+    b.kv_set("only-b", json!(2)).await.unwrap();
+    b.kv_set("shared", json!("from b")).await.unwrap();
+
+    let state_a = a.kv_state().await.unwrap();
+    let state_b = b.kv_state().await.unwrap();
+    a.kv_merge(&state_b).await.unwrap();
+    b.kv_merge(&state_a).await.unwrap();
+
+    // Neither side loses a key, and both agree on the concurrent write
+    assert_eq!(a.kv_state().await.unwrap(), b.kv_state().await.unwrap());
+    assert_eq!(a.kv_get("only-a").await.unwrap(), Some(json!(1)));
+    assert_eq!(a.kv_get("only-b").await.unwrap(), Some(json!(2)));
+    assert_eq!(a.kv_get("shared").await.unwrap(), b.kv_get("shared").await.unwrap());
+
+    // Merging again changes nothing
+    assert!(a.kv_merge(&b.kv_state().await.unwrap()).await.unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(&dir_a);
+    let _ = std::fs::remove_dir_all(&dir_b);
+}
+
+#[tokio::test]
+async fn test_kv_delete_survives_merge_of_older_value() {
+    let (a, dir_a) = create_test_kosha("delete-a", "hub-a").await;
+    let (b, dir_b) = create_test_kosha("delete-b", "hub-b").await;
+
+    a.kv_set("key", json!("v1")).await.unwrap();
+    b.kv_merge(&a.kv_state().await.unwrap()).await.unwrap();
+
+    // b deletes after having seen v1; a's stale copy must not resurrect it
+    b.kv_delete("key").await.unwrap();
+    b.kv_merge(&a.kv_state().await.unwrap()).await.unwrap();
+    assert_eq!(b.kv_get("key").await.unwrap(), None);
+
+    let changed = a.kv_merge(&b.kv_state().await.unwrap()).await.unwrap();
+    assert_eq!(changed, vec!["key".to_string()]);
+    assert_eq!(a.kv_get("key").await.unwrap(), None);
+
+    let _ = std::fs::remove_dir_all(&dir_a);
+    let _ = std::fs::remove_dir_all(&dir_b);
+}
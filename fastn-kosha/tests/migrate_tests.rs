@@ -0,0 +1,203 @@
+//! Tests for format records and migrations
+
+use fastn_kosha::{Change, Error, FORMAT_FILE, FORMAT_VERSION, Kosha};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Helper to get a fresh temp directory
+fn test_dir(name: &str) -> PathBuf {
+    let temp_dir = std::env::temp_dir().join(format!("fastn-kosha-migrate-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    temp_dir
+}
+
+fn sha256(content: &[u8]) -> String {
+    Sha256::digest(content).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Lay out a kosha the way builds before format records did: no format.json,
+/// and history names with '%' unescaped
+fn create_version_0_kosha(path: &Path) {
+    for dir in ["files", "history", "kv", "derived", "uploads"] {
+        std::fs::create_dir_all(path.join(dir)).unwrap();
+    }
+    std::fs::write(path.join("files/100%.txt"), b"new").unwrap();
+    std::fs::write(path.join("history/100%.txt__20240101T000000Z"), b"old").unwrap();
+    std::fs::write(path.join("history/notes~a.txt__20240101T000000Z"), b"kept").unwrap();
+    std::fs::write(path.join("derived/100%.txt__summary"), b"derived").unwrap();
+}
+
+#[tokio::test]
+async fn test_new_kosha_is_recorded_at_current_version() {
+    let dir = test_dir("new");
+    let kosha = Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    assert_eq!(kosha.format_version(), FORMAT_VERSION);
+
+    let record = fastn_kosha::read_format(&dir).await.unwrap();
+    assert_eq!(record.version, FORMAT_VERSION);
+    assert!(record.migrations.is_empty());
+    assert!(fastn_kosha::plan_migrations(&dir).await.unwrap().is_empty());
+
+    let fork = kosha.fork(dir.join("fork"), "fork".to_string()).await.unwrap();
+    assert_eq!(fork.format_version(), FORMAT_VERSION);
+}
+
+#[tokio::test]
+async fn test_kosha_without_record_is_version_0() {
+    let dir = test_dir("v0");
+    create_version_0_kosha(&dir);
+
+    // Opened as it is, without writing a record
+    let kosha = Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    assert_eq!(kosha.format_version(), 0);
+    assert!(!dir.join(FORMAT_FILE).exists());
+
+    // Forks keep the format of their source
+    let fork = kosha.fork(dir.join("fork"), "fork".to_string()).await.unwrap();
+    assert_eq!(fork.format_version(), 0);
+}
+
+#[tokio::test]
+async fn test_dry_run_changes_nothing() {
+    let dir = test_dir("dry-run");
+    create_version_0_kosha(&dir);
+
+    let planned = fastn_kosha::plan_migrations(&dir).await.unwrap();
+    assert_eq!(planned.len(), 2);
+    assert_eq!(planned[0].version, 1);
+    assert_eq!(planned[0].name, "escape-history-names");
+    assert_eq!(
+        planned[0].changes,
+        vec![
+            Change::Rename {
+                from: "history/100%.txt__20240101T000000Z".into(),
+                to: "history/100%25.txt__20240101T000000Z".into(),
+            },
+            Change::Rename {
+                from: "derived/100%.txt__summary".into(),
+                to: "derived/100%25.txt__summary".into(),
+            },
+        ]
+    );
+
+    // Computed before the renames, which a dry run doesn't make
+    assert_eq!(planned[1].version, 2);
+    assert_eq!(planned[1].name, "content-addressed-blobs");
+    assert_eq!(
+        planned[1].changes,
+        vec![
+            Change::MoveToBlob {
+                path: "files/100%.txt".into(),
+                sha256: sha256(b"new"),
+            },
+            Change::MoveToBlob {
+                path: "history/100%.txt__20240101T000000Z".into(),
+                sha256: sha256(b"old"),
+            },
+            Change::MoveToBlob {
+                path: "history/notes~a.txt__20240101T000000Z".into(),
+                sha256: sha256(b"kept"),
+            },
+        ]
+    );
+
+    assert!(dir.join("history/100%.txt__20240101T000000Z").exists());
+    assert!(!dir.join(FORMAT_FILE).exists());
+}
+
+#[tokio::test]
+async fn test_migrate_applies_and_records() {
+    let dir = test_dir("apply");
+    create_version_0_kosha(&dir);
+
+    // Before migrating, the old history entry isn't found
+    let kosha = Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    assert_eq!(kosha.get_versions("100%.txt").await.unwrap().len(), 1);
+
+    let applied = fastn_kosha::migrate(&dir).await.unwrap();
+    assert_eq!(applied.len(), 2);
+    assert_eq!(applied[0].changes.len(), 2);
+    assert_eq!(applied[1].changes.len(), 3);
+
+    let record = fastn_kosha::read_format(&dir).await.unwrap();
+    assert_eq!(record.version, FORMAT_VERSION);
+    assert_eq!(record.migrations.len(), 2);
+    assert_eq!(record.migrations[0].name, "escape-history-names");
+    assert_eq!(record.migrations[1].name, "content-addressed-blobs");
+
+    // Content moved to blobs, keeping the version timestamps
+    let hash = sha256(b"old");
+    assert_eq!(std::fs::read(dir.join("blobs").join(&hash[..2]).join(&hash)).unwrap(), b"old");
+    assert_ne!(std::fs::read(dir.join("files/100%.txt")).unwrap(), b"new");
+
+    let kosha = Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    assert_eq!(kosha.format_version(), FORMAT_VERSION);
+    let versions = kosha.get_versions("100%.txt").await.unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(kosha.read_version("100%.txt", versions[1].timestamp).await.unwrap(), b"old");
+    assert_eq!(kosha.read_derived("100%.txt", "summary").await.unwrap(), b"derived");
+    assert!(dir.join("history/notes~a.txt__20240101T000000Z").exists());
+
+    // Nothing left to do
+    assert!(fastn_kosha::plan_migrations(&dir).await.unwrap().is_empty());
+    assert!(fastn_kosha::migrate(&dir).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_current_names_are_not_renamed() {
+    let dir = test_dir("current-names");
+    let kosha = Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    kosha.write_file("a~b%.txt", b"one").await.unwrap();
+    kosha.write_file("a~b%.txt", b"two").await.unwrap();
+    std::fs::remove_file(dir.join(FORMAT_FILE)).unwrap();
+
+    let planned = fastn_kosha::plan_migrations(&dir).await.unwrap();
+    assert_eq!(planned.len(), 2);
+    assert!(planned.iter().all(|migration| migration.changes.is_empty()));
+}
+
+#[tokio::test]
+async fn test_newer_format_is_refused() {
+    let dir = test_dir("newer");
+    Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    let newer = FORMAT_VERSION + 1;
+    std::fs::write(dir.join(FORMAT_FILE), format!(r#"{{"version": {}}}"#, newer)).unwrap();
+
+    let result = Kosha::open(dir.clone(), "test".to_string()).await;
+    assert!(matches!(
+        result,
+        Err(Error::UnsupportedFormat { version, supported }) if version == newer && supported == FORMAT_VERSION
+    ));
+    assert!(matches!(
+        fastn_kosha::plan_migrations(&dir).await,
+        Err(Error::UnsupportedFormat { .. })
+    ));
+    assert!(matches!(fastn_kosha::migrate(&dir).await, Err(Error::UnsupportedFormat { .. })));
+}
+
+#[tokio::test]
+async fn test_backup_keeps_the_old_layout() {
+    let dir = test_dir("backup");
+    let kosha_path = dir.join("kosha");
+    create_version_0_kosha(&kosha_path);
+    std::fs::write(kosha_path.join("uploads/up-1.part"), b"partial").unwrap();
+
+    let backup_path = dir.join("backup");
+    fastn_kosha::backup(&kosha_path, &backup_path).await.unwrap();
+    fastn_kosha::migrate(&kosha_path).await.unwrap();
+
+    assert!(backup_path.join("history/100%.txt__20240101T000000Z").exists());
+    assert_eq!(std::fs::read(backup_path.join("files/100%.txt")).unwrap(), b"new");
+    assert_eq!(std::fs::read(backup_path.join("uploads/up-1.part")).unwrap(), b"partial");
+    assert!(!backup_path.join(FORMAT_FILE).exists());
+
+    // The backup opens as the kosha was before migrating
+    let restored = Kosha::open(backup_path.clone(), "test".to_string()).await.unwrap();
+    assert_eq!(restored.format_version(), 0);
+
+    // Never over an existing directory
+    assert!(matches!(
+        fastn_kosha::backup(&kosha_path, &backup_path).await,
+        Err(Error::Conflict(_))
+    ));
+}
@@ -0,0 +1,71 @@
+//! Tests for mount point tables (`_mounts.txt`)
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{Error, MOUNTS_FILE, MountTable};
+
+#[test]
+fn test_parse_and_display() {
+    let table = MountTable::parse(
+        "# Shared with the family\n\
+         \n\
+         photos: shared/photos\n\
+         /media/music/ : jukebox\n",
+    )
+    .unwrap();
+    assert_eq!(table.mounts.len(), 2);
+    assert_eq!(table.mounts[0].at, "photos");
+    assert_eq!(table.mounts[0].kosha, "shared");
+    assert_eq!(table.mounts[0].path, "photos");
+    assert_eq!(table.mounts[1].at, "media/music");
+    assert_eq!(table.mounts[1].kosha, "jukebox");
+    assert_eq!(table.mounts[1].path, "");
+
+    let written = table.to_string();
+    assert_eq!(written, "photos: shared/photos\nmedia/music: jukebox\n");
+    assert_eq!(MountTable::parse(&written).unwrap(), table);
+
+    for invalid in ["photos shared", "photos: ", ": shared", "/: shared", "a/../b: shared", "a: b\na: c"] {
+        assert!(matches!(MountTable::parse(invalid), Err(Error::InvalidMount(_))), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_resolve_and_children() {
+    let table = MountTable::parse("photos: shared/photos\nphotos/2024: archive\nmedia/music: jukebox\n").unwrap();
+
+    let resolve = |path: &str| table.resolve(path).map(|(mount, target)| (mount.kosha.clone(), target));
+    assert_eq!(resolve("photos"), Some(("shared".to_string(), "photos".to_string())));
+    assert_eq!(resolve("photos/"), Some(("shared".to_string(), "photos".to_string())));
+    assert_eq!(resolve("photos/a.jpg"), Some(("shared".to_string(), "photos/a.jpg".to_string())));
+    // The longest mount point wins
+    assert_eq!(resolve("photos/2024/b.jpg"), Some(("archive".to_string(), "b.jpg".to_string())));
+    assert_eq!(resolve("photos/2024"), Some(("archive".to_string(), "".to_string())));
+    // Whole segments only
+    assert_eq!(resolve("photos_old/a.jpg"), None);
+    assert_eq!(resolve("media"), None);
+    assert_eq!(resolve(""), None);
+
+    assert_eq!(table.children("").into_iter().collect::<Vec<_>>(), ["media", "photos"]);
+    assert_eq!(table.children("media").into_iter().collect::<Vec<_>>(), ["music"]);
+    assert_eq!(table.children("photos").into_iter().collect::<Vec<_>>(), ["2024"]);
+    assert!(table.children("media/music").is_empty());
+    assert!(table.children("med").is_empty());
+}
+
+#[tokio::test]
+async fn test_kosha_mounts() {
+    let (kosha, dir) = create_test_kosha("file").await;
+    assert!(kosha.mounts().await.unwrap().is_empty());
+
+    kosha.write_file(MOUNTS_FILE, b"photos: shared/photos\n").await.unwrap();
+    let table = kosha.mounts().await.unwrap();
+    assert_eq!(table.mounts.len(), 1);
+    assert_eq!(table.mounts[0].kosha, "shared");
+
+    kosha.write_file(MOUNTS_FILE, b"photos\n").await.unwrap();
+    assert!(matches!(kosha.mounts().await, Err(Error::InvalidMount(_))));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
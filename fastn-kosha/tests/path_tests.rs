@@ -0,0 +1,155 @@
+//! Property tests for path validation and the history flattening scheme
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{clean_path, flatten_path, history_filename, unflatten_path, Error, Kosha};
+use proptest::prelude::*;
+use std::path::{Component, Path};
+
+/// Path segments that tend to break path handling: traversal, the
+/// flattening characters, history separators and unicode look-alikes
+fn segment() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just("..".to_string()),
+        Just(".".to_string()),
+        Just("".to_string()),
+        Just("~".to_string()),
+        Just("a~b".to_string()),
+        Just("%7E".to_string()),
+        Just("%25".to_string()),
+        Just("x__20241224T153045Z".to_string()),
+        Just("caf\u{e9}".to_string()),
+        Just("cafe\u{301}".to_string()),
+        Just("\u{ff0e}\u{ff0e}".to_string()),
+        Just("C:".to_string()),
+        Just("a\\..\\b".to_string()),
+        "[a-z~%_.]{1,6}",
+        "\\PC{1,4}",
+    ]
+}
+
+fn path() -> impl Strategy<Value = String> {
+    (any::<bool>(), prop::collection::vec(segment(), 1..5))
+        .prop_map(|(absolute, segments)| format!("{}{}", if absolute { "/" } else { "" }, segments.join("/")))
+}
+
+proptest! {
+    #[test]
+    fn flatten_roundtrips(path in "\\PC*") {
+        prop_assert_eq!(unflatten_path(&flatten_path(&path)), path);
+    }
+
+    #[test]
+    fn flatten_is_injective(a in path(), b in path()) {
+        prop_assume!(a != b);
+        prop_assert_ne!(flatten_path(&a), flatten_path(&b));
+    }
+
+    #[test]
+    fn flattened_paths_have_no_separators(path in path()) {
+        prop_assert!(!flatten_path(&path).contains('/'));
+    }
+
+    #[test]
+    fn history_names_are_unique_per_path(a in path(), b in path()) {
+        prop_assume!(a != b);
+        let timestamp = "2024-12-24T15:30:45Z".parse().unwrap();
+        prop_assert_ne!(history_filename(&a, timestamp), history_filename(&b, timestamp));
+    }
+
+    #[test]
+    fn clean_paths_stay_inside(path in path()) {
+        if let Ok(clean) = clean_path(&path) {
+            for component in Path::new(clean).components() {
+                prop_assert!(
+                    matches!(component, Component::Normal(_) | Component::CurDir),
+                    "{:?} has component {:?}", path, component
+                );
+            }
+            let root = Path::new("/kosha/files");
+            prop_assert!(root.join(clean).starts_with(root));
+        }
+    }
+
+    #[test]
+    fn parent_components_are_rejected(prefix in path(), suffix in path()) {
+        let path = format!("{}/../{}", prefix, suffix);
+        prop_assert!(matches!(clean_path(&path), Err(Error::InvalidPath(_))));
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn writes_never_leave_the_kosha(paths in prop::collection::vec(path(), 1..6)) {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let outer = std::env::temp_dir().join(format!("fastn-kosha-path-test-escape-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&outer);
+            let kosha = Kosha::open(outer.join("kosha"), "test".to_string()).await.unwrap();
+
+            for path in &paths {
+                if kosha.write_file(path, b"x").await.is_ok() {
+                    assert_eq!(kosha.read_file(path).await.unwrap(), b"x");
+                }
+                let _ = kosha.rename(path, "moved.txt").await;
+                let _ = kosha.delete(path).await;
+                let _ = kosha.list_dir(path).await;
+            }
+
+            let entries: Vec<_> = std::fs::read_dir(&outer).unwrap().map(|e| e.unwrap().file_name()).collect();
+            assert_eq!(entries, vec!["kosha"], "{:?} escaped the kosha", paths);
+            let _ = std::fs::remove_dir_all(&outer);
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_tilde_names_keep_separate_history() {
+    let (kosha, dir) = create_test_kosha("tilde").await;
+    kosha.write_file("a~b.txt", b"tilde v1").await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    kosha.write_file("a~b.txt", b"tilde v2").await.unwrap();
+    kosha.write_file("a/b.txt", b"nested").await.unwrap();
+
+    assert_eq!(kosha.get_versions("a~b.txt").await.unwrap().len(), 2);
+    assert_eq!(kosha.get_versions("a/b.txt").await.unwrap().len(), 1);
+
+    // Renaming one doesn't take the other's history along
+    kosha.rename("a/b.txt", "c.txt").await.unwrap();
+    assert_eq!(kosha.get_versions("a~b.txt").await.unwrap().len(), 2);
+    assert_eq!(kosha.get_versions("c.txt").await.unwrap().len(), 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_rename_and_delete_edge_cases() {
+    let (kosha, dir) = create_test_kosha("edges").await;
+    kosha.write_file("docs/note.txt", b"hello").await.unwrap();
+
+    let onto_itself = kosha.rename("docs/note.txt", "docs/note.txt").await;
+    assert!(matches!(onto_itself, Err(Error::Conflict(_))), "got {:?}", onto_itself);
+
+    let out = kosha.rename("docs/note.txt", "../note.txt").await;
+    assert!(matches!(out, Err(Error::InvalidPath(_))), "got {:?}", out);
+
+    let directory = kosha.rename("docs", "documents").await;
+    assert!(matches!(directory, Err(Error::NotFound(_))), "got {:?}", directory);
+    let directory = kosha.delete("docs").await;
+    assert!(matches!(directory, Err(Error::NotFound(_))), "got {:?}", directory);
+
+    let listing = kosha.list_dir("../..").await;
+    assert!(matches!(listing, Err(Error::InvalidPath(_))), "got {:?}", listing);
+
+    // Leading slashes name the same file
+    kosha.rename("/docs/note.txt", "docs/renamed.txt").await.unwrap();
+    kosha.delete("//docs/renamed.txt").await.unwrap();
+    let gone = kosha.read_file("docs/renamed.txt").await;
+    assert!(matches!(gone, Err(Error::NotFound(_))), "got {:?}", gone);
+    assert_eq!(kosha.get_versions("docs/renamed.txt").await.unwrap().len(), 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,141 @@
+//! Tests for the WASM worker pool
+
+use fastn_kosha::{PoolError, WasmPool, WasmPoolConfig};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+fn pool(workers: usize, queue_capacity: usize, per_module_concurrency: usize, timeout: Duration) -> Arc<WasmPool> {
+    Arc::new(WasmPool::new(WasmPoolConfig {
+        workers,
+        queue_capacity,
+        per_module_concurrency,
+        timeout,
+    }))
+}
+
+/// Wait (without blocking the runtime) until a job reports it started
+async fn started(signal: &mpsc::Receiver<()>) {
+    while signal.try_recv().is_err() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+/// Run a job on `module` that blocks its worker until `release` is dropped
+async fn occupy(pool: &Arc<WasmPool>, module: &str) -> (tokio::task::JoinHandle<Result<u32, PoolError>>, mpsc::Sender<()>) {
+    let (started_tx, started_rx) = mpsc::channel();
+    let (release, release_rx) = mpsc::channel::<()>();
+    let job = tokio::spawn({
+        let pool = pool.clone();
+        let module = module.to_string();
+        async move {
+            pool.run(&module, move |_| {
+                started_tx.send(()).unwrap();
+                let _ = release_rx.recv();
+                1
+            })
+            .await
+        }
+    });
+    started(&started_rx).await;
+    (job, release)
+}
+
+#[tokio::test]
+async fn test_pool_runs_jobs() {
+    let pool = pool(2, 4, 2, Duration::from_secs(5));
+    assert_eq!(pool.run("a", |_| 40 + 2).await, Ok(42));
+    let name = pool.run("a", |_| std::thread::current().name().map(str::to_string)).await.unwrap();
+    assert!(name.unwrap().starts_with("fastn-wasm-"));
+}
+
+#[tokio::test]
+async fn test_pool_rejects_when_queue_is_full() {
+    let pool = pool(1, 1, 8, Duration::from_secs(5));
+    let (busy, release) = occupy(&pool, "a").await;
+
+    // Takes the only queue slot
+    let queued = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.run("b", |_| 2).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(pool.run("c", |_| 3).await, Err(PoolError::QueueFull));
+
+    drop(release);
+    assert_eq!(busy.await.unwrap(), Ok(1));
+    assert_eq!(queued.await.unwrap(), Ok(2));
+}
+
+#[tokio::test]
+async fn test_pool_limits_concurrency_per_module() {
+    let pool = pool(4, 8, 1, Duration::from_secs(5));
+    let (busy, release) = occupy(&pool, "a").await;
+
+    // The same module waits for its slot, another module gets a worker
+    let waiting = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.run("a", |_| 3).await }
+    });
+    assert_eq!(pool.run("b", |_| 2).await, Ok(2));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+
+    drop(release);
+    assert_eq!(busy.await.unwrap(), Ok(1));
+    assert_eq!(waiting.await.unwrap(), Ok(3));
+}
+
+#[tokio::test]
+async fn test_pool_times_out_waiting_for_module() {
+    let pool = pool(4, 8, 1, Duration::from_millis(200));
+    let (busy, release) = occupy(&pool, "a").await;
+    assert_eq!(pool.run("a", |_| 3).await, Err(PoolError::Timeout));
+    drop(release);
+    let _ = busy.await;
+}
+
+#[tokio::test]
+async fn test_pool_timeout_cancels_job() {
+    let pool = pool(1, 4, 1, Duration::from_millis(50));
+    let (stopped_tx, stopped_rx) = mpsc::channel();
+    let result = pool
+        .run("a", move |cancel| {
+            while !cancel.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            stopped_tx.send(()).unwrap();
+        })
+        .await;
+    assert_eq!(result, Err(PoolError::Timeout));
+    assert!(stopped_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+}
+
+#[tokio::test]
+async fn test_pool_follows_request_cancellation() {
+    let pool = pool(1, 4, 1, Duration::from_secs(30));
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+        }
+    });
+
+    let result = fastn_kosha::with_cancellation(
+        cancel.clone(),
+        pool.run("a", |cancel| {
+            while !cancel.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }),
+    )
+    .await;
+    assert_eq!(result, Err(PoolError::Cancelled));
+
+    // Calls made after cancellation don't run at all
+    let ran = fastn_kosha::with_cancellation(cancel, pool.run("a", |_| ())).await;
+    assert_eq!(ran, Err(PoolError::Cancelled));
+}
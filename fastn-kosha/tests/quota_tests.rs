@@ -0,0 +1,86 @@
+//! Tests for kosha storage quotas
+
+mod common;
+
+use fastn_kosha::{CommandError, Error, Kosha};
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Helper to create a kosha with a quota in its own temp directory
+async fn create_test_kosha(name: &str, quota: Option<u64>) -> (Kosha, PathBuf) {
+    let (kosha, temp_dir) = common::create_test_kosha(name).await;
+    (kosha.with_storage_quota(quota), temp_dir)
+}
+
+#[tokio::test]
+async fn test_writes_stop_at_quota() {
+    let (kosha, dir) = create_test_kosha("writes", Some(100)).await;
+
+    kosha.write_file("a.txt", &[b'a'; 60]).await.unwrap();
+    assert_eq!(kosha.storage_used().await.unwrap(), 60);
+
+    let over = kosha.write_file("b.txt", &[b'b'; 50]).await;
+    assert!(
+        matches!(over, Err(Error::QuotaExceeded { used: 60, quota: 100, requested: 50 })),
+        "got {:?}",
+        over
+    );
+    assert!(!dir.join("files/b.txt").exists());
+
+    // Replaced content stays in history and keeps counting
+    kosha.write_file("a.txt", &[b'c'; 30]).await.unwrap();
+    assert_eq!(kosha.storage_used().await.unwrap(), 90);
+    assert!(kosha.write_file("c.txt", &[b'c'; 20]).await.is_err());
+    kosha.write_file("c.txt", &[b'c'; 10]).await.unwrap();
+
+    // Full: not even an empty file or a database statement fits
+    assert!(kosha.write_file("d.txt", b"").await.is_err());
+    let db = kosha.db_execute("app.sqlite3", "CREATE TABLE t (x)", vec![]).await;
+    assert!(matches!(db, Err(Error::QuotaExceeded { .. })), "got {:?}", db);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_uploads_reserve_their_size() {
+    let (kosha, dir) = create_test_kosha("uploads", Some(1000)).await;
+    let sha256 = "ab".repeat(32);
+
+    let status = kosha.begin_upload("big.bin", 800, &sha256, None).await.unwrap();
+    assert_eq!(kosha.storage_used().await.unwrap(), 800);
+
+    // Resuming the same upload doesn't reserve twice
+    assert_eq!(kosha.begin_upload("big.bin", 800, &sha256, None).await.unwrap(), status);
+
+    let second = kosha.begin_upload("other.bin", 300, &sha256, None).await;
+    assert!(matches!(second, Err(Error::QuotaExceeded { .. })), "got {:?}", second);
+
+    kosha.abort_upload(&status.upload_id).await.unwrap();
+    kosha.begin_upload("other.bin", 300, &sha256, None).await.unwrap();
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_quota_commands() {
+    let (kosha, dir) = create_test_kosha("commands", Some(64)).await;
+
+    let set = kosha.handle_command("kv_set", json!({ "key": "big", "value": "x".repeat(100) })).await;
+    assert!(matches!(set, Err(CommandError::QuotaExceeded(_))), "got {:?}", set);
+    kosha.handle_command("kv_set", json!({ "key": "small", "value": 1 })).await.unwrap();
+
+    // Deleting is always allowed
+    kosha.handle_command("kv_delete", json!({ "key": "small" })).await.unwrap();
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_no_quota_by_default() {
+    let (kosha, dir) = create_test_kosha("unlimited", None).await;
+    assert_eq!(kosha.storage_quota(), None);
+    kosha.write_file("a.bin", &vec![0; 1 << 20]).await.unwrap();
+    assert_eq!(kosha.storage_used().await.unwrap(), 1 << 20);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
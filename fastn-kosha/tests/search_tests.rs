@@ -0,0 +1,126 @@
+//! Tests for full-text search
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{DEFAULT_SEARCH_LIMIT, Kosha};
+
+/// Paths of the hits, best first
+async fn search(kosha: &Kosha, query: &str, prefix: &str) -> Vec<String> {
+    let hits = kosha.search(query, prefix, DEFAULT_SEARCH_LIMIT).await.unwrap();
+    hits.into_iter().map(|hit| hit.path).collect()
+}
+
+#[tokio::test]
+async fn test_search_finds_content_and_names() {
+    let (kosha, dir) = create_test_kosha("content").await;
+    kosha.write_file("notes/garden.md", b"Plant the tomatoes in May").await.unwrap();
+    kosha.write_file("notes/shopping.txt", b"milk, eggs, tomato sauce").await.unwrap();
+    kosha.write_file("tomatoes.png", &[0x89, b'P', b'N', b'G', 0, 0]).await.unwrap();
+
+    let mut found = search(&kosha, "tomato", "").await;
+    found.sort();
+    assert_eq!(found, ["notes/garden.md", "notes/shopping.txt", "tomatoes.png"]);
+
+    // Every word must match, as a prefix
+    assert_eq!(search(&kosha, "tomato may", "").await, ["notes/garden.md"]);
+    assert!(search(&kosha, "tomato cucumber", "").await.is_empty());
+
+    let hits = kosha.search("plant", "", 10).await.unwrap();
+    assert_eq!(hits[0].snippet, "**Plant** the tomatoes in May");
+    assert_eq!(hits[0].modified, kosha.current_version("notes/garden.md").await.unwrap().unwrap().timestamp);
+
+    // Query syntax is taken literally
+    assert!(search(&kosha, "\"", "").await.is_empty());
+    assert_eq!(search(&kosha, "\"eggs\" milk*", "").await, ["notes/shopping.txt"]);
+    assert!(search(&kosha, "eggs OR cucumber", "").await.is_empty());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_search_follows_changes() {
+    let (kosha, dir) = create_test_kosha("changes").await;
+    kosha.write_file("a.txt", b"first draft").await.unwrap();
+    assert_eq!(search(&kosha, "draft", "").await, ["a.txt"]);
+
+    kosha.write_file("a.txt", b"final version").await.unwrap();
+    assert!(search(&kosha, "draft", "").await.is_empty());
+    assert_eq!(search(&kosha, "final", "").await, ["a.txt"]);
+
+    kosha.rename("a.txt", "b.txt").await.unwrap();
+    assert_eq!(search(&kosha, "final", "").await, ["b.txt"]);
+
+    kosha.delete("b.txt").await.unwrap();
+    assert!(search(&kosha, "final", "").await.is_empty());
+
+    // Derived artifacts are searched too
+    kosha.write_file("models/robot.glb", b"glTF").await.unwrap();
+    kosha.write_derived("models/robot.glb", "meta.json", br#"{"skeleton": "humanoid"}"#).await.unwrap();
+    assert_eq!(search(&kosha, "humanoid", "").await, ["models/robot.glb"]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_search_path_prefix() {
+    let (kosha, dir) = create_test_kosha("prefix").await;
+    kosha.write_file("notes/a.txt", b"apple").await.unwrap();
+    kosha.write_file("notes_old/b.txt", b"apple").await.unwrap();
+    kosha.write_file("c.txt", b"apple").await.unwrap();
+
+    assert_eq!(search(&kosha, "apple", "notes").await, ["notes/a.txt"]);
+    assert_eq!(search(&kosha, "apple", "/notes/").await, ["notes/a.txt"]);
+    assert_eq!(search(&kosha, "apple", "c.txt").await, ["c.txt"]);
+    assert_eq!(kosha.search("apple", "", 2).await.unwrap().len(), 2);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_search_indexes_existing_files_on_open() {
+    let (kosha, dir) = create_test_kosha("reopen").await;
+    kosha.write_file("kept.txt", b"banana").await.unwrap();
+    assert_eq!(search(&kosha, "banana", "").await, ["kept.txt"]);
+
+    // A fork has no index of its own yet
+    let fork = kosha.fork(dir.with_extension("fork"), "fork".to_string()).await.unwrap();
+    assert_eq!(search(&fork, "banana", "").await, ["kept.txt"]);
+    let _ = std::fs::remove_dir_all(dir.with_extension("fork"));
+
+    // Changes the index didn't see (made by another process) are picked up
+    // by the first search
+    let other = Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    other.write_file("kept.txt", b"cherry").await.unwrap();
+    let kosha = Kosha::open(dir.clone(), "test".to_string()).await.unwrap();
+    assert!(search(&kosha, "banana", "").await.is_empty());
+    assert_eq!(search(&kosha, "cherry", "").await, ["kept.txt"]);
+
+    // The index can be thrown away
+    std::fs::remove_dir_all(dir.join("index")).unwrap();
+    kosha.write_file("new.txt", b"cherry pie").await.unwrap();
+    let mut found = search(&kosha, "cherry", "").await;
+    found.sort();
+    assert_eq!(found, ["kept.txt", "new.txt"]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_search_command() {
+    let (kosha, dir) = create_test_kosha("command").await;
+    kosha.write_file("notes/todo.md", b"call the plumber").await.unwrap();
+
+    let response = kosha
+        .handle_command("search", serde_json::json!({ "query": "plumber", "path_prefix": "notes" }))
+        .await
+        .unwrap();
+    let hits = response["hits"].as_array().unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0]["path"], "notes/todo.md");
+    assert!(hits[0]["score"].as_f64().unwrap() > 0.0);
+
+    assert!(kosha.handle_command("search", serde_json::json!({})).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
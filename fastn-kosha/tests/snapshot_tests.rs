@@ -0,0 +1,153 @@
+//! Tests for snapshot export and import
+
+mod common;
+
+use fastn_kosha::{Error, FORMAT_VERSION, Kosha, SNAPSHOT_VERSION, export_snapshot, import_snapshot};
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Helper to create a kosha in `source/` of its own temp directory, leaving
+/// room next to it
+async fn create_test_kosha(name: &str) -> (Kosha, PathBuf) {
+    let temp_dir = common::test_dir(name);
+    let kosha = Kosha::open(temp_dir.join("source"), "source".to_string())
+        .await
+        .expect("Failed to open kosha");
+    (kosha, temp_dir)
+}
+
+/// Write a snapshot by hand, with the given manifest and extra entries
+fn write_raw_snapshot(to: &PathBuf, manifest: serde_json::Value, entries: &[(&str, &[u8])]) {
+    let file = std::fs::File::create(to).unwrap();
+    let mut archive = tar::Builder::new(zstd::Encoder::new(file, 0).unwrap());
+    let manifest = serde_json::to_vec(&manifest).unwrap();
+    let all = std::iter::once(("manifest.json", manifest.as_slice())).chain(entries.iter().copied());
+    for (path, data) in all {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive.append_data(&mut header, path, data).unwrap();
+    }
+    archive.into_inner().unwrap().finish().unwrap();
+}
+
+#[tokio::test]
+async fn test_snapshot_round_trip() {
+    let (source, dir) = create_test_kosha("round-trip").await;
+    source.write_file("notes/a.txt", b"one").await.unwrap();
+    // Versions are per second: date the first one back so both are kept
+    std::fs::File::options()
+        .write(true)
+        .open(dir.join("source/files/notes/a.txt"))
+        .unwrap()
+        .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000))
+        .unwrap();
+    source.write_file("notes/a.txt", b"two").await.unwrap();
+    source.write_derived("notes/a.txt", "summary", b"2 words").await.unwrap();
+    source.kv_set("theme", json!("dark")).await.unwrap();
+    source
+        .db_execute("app.sqlite3", "CREATE TABLE items (name TEXT)", vec![])
+        .await
+        .unwrap();
+    source
+        .db_execute("app.sqlite3", "INSERT INTO items VALUES (?)", vec![json!("first")])
+        .await
+        .unwrap();
+
+    let file = dir.join("backup.tar.zst");
+    let exported = export_snapshot(source.path(), source.alias(), &file).await.unwrap();
+    assert_eq!(exported.snapshot_version, SNAPSHOT_VERSION);
+    assert_eq!(exported.format_version, FORMAT_VERSION);
+    assert_eq!(exported.alias, "source");
+    assert!(exported.files > 0);
+    assert!(!dir.join("backup.tar.zst.staging").exists());
+
+    let imported = import_snapshot(&file, &dir.join("restored")).await.unwrap();
+    assert_eq!(imported, exported);
+
+    let restored = Kosha::open(dir.join("restored"), "restored".to_string()).await.unwrap();
+    assert_eq!(restored.read_file("notes/a.txt").await.unwrap(), b"two");
+    assert_eq!(restored.read_derived("notes/a.txt", "summary").await.unwrap(), b"2 words");
+    assert_eq!(restored.kv_get("theme").await.unwrap(), Some(json!("dark")));
+    let rows = restored
+        .db_query("app.sqlite3", "SELECT name FROM items", vec![])
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+
+    // Versions keep their timestamps
+    let versions = source.get_versions("notes/a.txt").await.unwrap();
+    let restored_versions = restored.get_versions("notes/a.txt").await.unwrap();
+    assert_eq!(
+        versions.iter().map(|v| v.timestamp).collect::<Vec<_>>(),
+        restored_versions.iter().map(|v| v.timestamp).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        restored.read_version("notes/a.txt", versions[1].timestamp).await.unwrap(),
+        source.read_version("notes/a.txt", versions[1].timestamp).await.unwrap()
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_import_refuses_existing_target() {
+    let (source, dir) = create_test_kosha("existing").await;
+    let file = dir.join("backup.tar.zst");
+    export_snapshot(source.path(), source.alias(), &file).await.unwrap();
+
+    let result = import_snapshot(&file, source.path()).await;
+    assert!(matches!(result, Err(Error::Conflict(_))));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_import_refuses_newer_format() {
+    let (_, dir) = create_test_kosha("newer").await;
+    let file = dir.join("newer.tar.zst");
+    let manifest = json!({
+        "snapshot_version": SNAPSHOT_VERSION,
+        "format_version": FORMAT_VERSION + 1,
+        "alias": "future",
+        "created_at": "2030-01-01T00:00:00Z",
+        "files": 0,
+        "bytes": 0,
+    });
+    write_raw_snapshot(&file, manifest, &[]);
+
+    let result = import_snapshot(&file, &dir.join("restored")).await;
+    assert!(matches!(result, Err(Error::UnsupportedFormat { .. })));
+    assert!(!dir.join("restored").exists());
+    assert!(!dir.join("restored.importing").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_import_refuses_foreign_entries() {
+    let (_, dir) = create_test_kosha("foreign").await;
+    let manifest = json!({
+        "snapshot_version": SNAPSHOT_VERSION,
+        "format_version": FORMAT_VERSION,
+        "alias": "other",
+        "created_at": "2030-01-01T00:00:00Z",
+        "files": 1,
+        "bytes": 4,
+    });
+
+    let file = dir.join("outside.tar.zst");
+    write_raw_snapshot(&file, manifest, &[("uploads/part", b"data")]);
+    let result = import_snapshot(&file, &dir.join("restored")).await;
+    assert!(matches!(result, Err(Error::InvalidSnapshot(_))));
+    assert!(!dir.join("restored").exists());
+
+    // Not a snapshot at all
+    let file = dir.join("plain.tar.zst");
+    std::fs::write(&file, b"not a snapshot").unwrap();
+    assert!(import_snapshot(&file, &dir.join("restored")).await.is_err());
+    assert!(!dir.join("restored").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
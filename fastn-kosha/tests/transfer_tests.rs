@@ -0,0 +1,228 @@
+//! Tests for chunked uploads and range reads
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{Error, MAX_CHUNK_SIZE};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Deterministic content a bit over two chunks long
+fn large_content() -> Vec<u8> {
+    (0..2 * MAX_CHUNK_SIZE + 1000).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn test_chunked_upload_and_resume() {
+    let (kosha, dir) = create_test_kosha("resume").await;
+    let content = large_content();
+    let hash = sha256_hex(&content);
+    let size = content.len() as u64;
+
+    let status = kosha.begin_upload("models/big.glb", size, &hash, None).await.unwrap();
+    assert_eq!(status.received, 0);
+    let status = kosha.upload_chunk(&status.upload_id, 0, &content[..MAX_CHUNK_SIZE]).await.unwrap();
+    assert_eq!(status.received, MAX_CHUNK_SIZE as u64);
+
+    // The connection drops; beginning again picks up where it stopped
+    let resumed = kosha.begin_upload("models/big.glb", size, &hash, None).await.unwrap();
+    assert_eq!(resumed, status);
+
+    // A chunk that already arrived is not appended twice
+    let duplicate = kosha.upload_chunk(&resumed.upload_id, 0, &content[..MAX_CHUNK_SIZE]).await;
+    assert!(matches!(duplicate, Err(Error::Conflict(_))), "got {:?}", duplicate);
+
+    let mut status = resumed;
+    while status.received < size {
+        let start = status.received as usize;
+        let end = (start + MAX_CHUNK_SIZE).min(content.len());
+        status = kosha.upload_chunk(&status.upload_id, status.received, &content[start..end]).await.unwrap();
+    }
+    let version = kosha.commit_upload(&status.upload_id).await.unwrap();
+    assert_eq!(version.size, size);
+    assert_eq!(kosha.read_file("models/big.glb").await.unwrap(), content);
+
+    // Committed uploads are gone
+    let again = kosha.commit_upload(&status.upload_id).await;
+    assert!(matches!(again, Err(Error::UploadNotFound(_))), "got {:?}", again);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_upload_integrity_and_limits() {
+    let (kosha, dir) = create_test_kosha("integrity").await;
+    kosha.write_file("notes.txt", b"original").await.unwrap();
+
+    let status = kosha.begin_upload("notes.txt", 7, &sha256_hex(b"updated"), None).await.unwrap();
+    let oversized = kosha.upload_chunk(&status.upload_id, 0, b"updated!").await;
+    assert!(matches!(oversized, Err(Error::InvalidRange(_))), "got {:?}", oversized);
+
+    kosha.upload_chunk(&status.upload_id, 0, b"UPDATED").await.unwrap();
+    let mismatch = kosha.commit_upload(&status.upload_id).await;
+    assert!(matches!(mismatch, Err(Error::IntegrityMismatch { .. })), "got {:?}", mismatch);
+    assert_eq!(kosha.read_file("notes.txt").await.unwrap(), b"original");
+
+    // The corrupt upload was discarded
+    let discarded = kosha.upload_status(&status.upload_id).await;
+    assert!(matches!(discarded, Err(Error::UploadNotFound(_))), "got {:?}", discarded);
+
+    let bad_hash = kosha.begin_upload("notes.txt", 7, "not-a-hash", None).await;
+    assert!(matches!(bad_hash, Err(Error::InvalidRange(_))), "got {:?}", bad_hash);
+
+    let traversal = kosha.upload_status("../files/notes.txt").await;
+    assert!(matches!(traversal, Err(Error::UploadNotFound(_))), "got {:?}", traversal);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_committed_upload_keeps_history() {
+    let (kosha, dir) = create_test_kosha("history").await;
+    kosha.write_file("scene.json", b"{\"v\":1}").await.unwrap();
+    let base = kosha.current_version("scene.json").await.unwrap().map(|v| v.timestamp);
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    let content = b"{\"v\":2}";
+    let status = kosha.begin_upload("scene.json", 7, &sha256_hex(content), base).await.unwrap();
+    kosha.upload_chunk(&status.upload_id, 0, content).await.unwrap();
+    kosha.commit_upload(&status.upload_id).await.unwrap();
+
+    let versions = kosha.get_versions("scene.json").await.unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(kosha.read_version("scene.json", versions[1].timestamp).await.unwrap(), b"{\"v\":1}");
+
+    // The base version is stale now
+    let stale = kosha.begin_upload("scene.json", 7, &sha256_hex(content), base).await;
+    assert!(matches!(stale, Err(Error::VersionConflict { .. })), "got {:?}", stale);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_read_file_range() {
+    let (kosha, dir) = create_test_kosha("range").await;
+    kosha.write_file("data.bin", b"0123456789").await.unwrap();
+
+    assert_eq!(kosha.read_file_range("data.bin", 2..5).await.unwrap(), b"234");
+    assert_eq!(kosha.read_file_range("data.bin", 7..).await.unwrap(), b"789");
+    assert_eq!(kosha.read_file_range("data.bin", ..3).await.unwrap(), b"012");
+    assert_eq!(kosha.read_file_range("data.bin", 8..100).await.unwrap(), b"89");
+    assert_eq!(kosha.read_file_range("data.bin", 10..).await.unwrap(), b"");
+
+    let past_end = kosha.read_file_range("data.bin", 11..).await;
+    assert!(matches!(past_end, Err(Error::InvalidRange(_))), "got {:?}", past_end);
+
+    assert_eq!(kosha.file_hash("data.bin").await.unwrap(), sha256_hex(b"0123456789"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_transfer_commands() {
+    let (kosha, dir) = create_test_kosha("commands").await;
+    let content = b"hello, chunks";
+    let hash = sha256_hex(content);
+
+    let begun = kosha
+        .handle_command("begin_upload", json!({ "path": "greeting.txt", "size": content.len(), "sha256": hash }))
+        .await
+        .unwrap();
+    let upload_id = begun["upload_id"].as_str().unwrap();
+
+    // An upload can only be used with the file it was started for
+    let wrong = kosha
+        .handle_command("commit_upload", json!({ "upload_id": upload_id, "path": "other.txt" }))
+        .await;
+    assert!(wrong.is_err());
+
+    for (offset, chunk) in [(0, &content[..5]), (5, &content[5..])] {
+        kosha
+            .handle_command(
+                "upload_chunk",
+                json!({ "upload_id": upload_id, "path": "greeting.txt", "offset": offset, "content": base64(chunk) }),
+            )
+            .await
+            .unwrap();
+    }
+    kosha
+        .handle_command("commit_upload", json!({ "upload_id": upload_id, "path": "greeting.txt" }))
+        .await
+        .unwrap();
+
+    let range = kosha
+        .handle_command("read_range", json!({ "path": "greeting.txt", "offset": 7, "length": 6 }))
+        .await
+        .unwrap();
+    assert_eq!(range["content"], base64(b"chunks"));
+    assert_eq!(range["size"], content.len());
+
+    let hashed = kosha.handle_command("file_hash", json!({ "path": "greeting.txt" })).await.unwrap();
+    assert_eq!(hashed["sha256"], hash);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_file_digest_chunks() {
+    let (kosha, dir) = create_test_kosha("digest").await;
+    let content = large_content();
+    kosha.write_file("models/big.glb", &content).await.unwrap();
+
+    let digest = kosha.file_digest("models/big.glb").await.unwrap();
+    assert_eq!(digest.sha256, sha256_hex(&content));
+    let expected: Vec<String> = content.chunks(MAX_CHUNK_SIZE).map(sha256_hex).collect();
+    assert_eq!(digest.chunks, expected);
+    assert_eq!(digest.chunks.len(), 3);
+
+    // A new version gets new digests, not the cached ones
+    kosha.write_file("models/big.glb", b"small now").await.unwrap();
+    let digest = kosha.file_digest("models/big.glb").await.unwrap();
+    assert_eq!(digest.chunks, vec![sha256_hex(b"small now")]);
+
+    kosha.write_file("empty.txt", b"").await.unwrap();
+    assert!(kosha.file_digest("empty.txt").await.unwrap().chunks.is_empty());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_read_range_by_digest() {
+    let (kosha, dir) = create_test_kosha("range-digest").await;
+    let content = large_content();
+    kosha.write_file("models/big.glb", &content).await.unwrap();
+
+    let hashed = kosha.handle_command("file_hash", json!({ "path": "models/big.glb" })).await.unwrap();
+    let sha256 = hashed["sha256"].as_str().unwrap().to_string();
+    assert_eq!(hashed["chunk_size"], MAX_CHUNK_SIZE);
+    assert_eq!(hashed["chunks"][1], sha256_hex(&content[MAX_CHUNK_SIZE..2 * MAX_CHUNK_SIZE]));
+
+    let range = kosha
+        .handle_command("read_range", json!({ "path": "models/big.glb", "offset": MAX_CHUNK_SIZE, "sha256": sha256 }))
+        .await
+        .unwrap();
+    assert_eq!(range["content"], base64(&content[MAX_CHUNK_SIZE..2 * MAX_CHUNK_SIZE]));
+
+    // Once the content changes, reads keyed by the old digest are refused
+    kosha.write_file("models/big.glb", b"replaced").await.unwrap();
+    let stale = kosha
+        .handle_command("read_range", json!({ "path": "models/big.glb", "offset": 0, "sha256": sha256 }))
+        .await;
+    assert!(matches!(stale, Err(fastn_kosha::CommandError::Conflict { current: Some(_), .. })));
+
+    let invalid = kosha
+        .handle_command("read_range", json!({ "path": "models/big.glb", "offset": 0, "sha256": "nope" }))
+        .await;
+    assert!(matches!(invalid, Err(fastn_kosha::CommandError::Failed(_))));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn base64(data: &[u8]) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
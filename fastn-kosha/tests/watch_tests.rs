@@ -0,0 +1,117 @@
+//! Tests for watching file changes
+
+mod common;
+
+use common::create_test_kosha;
+use fastn_kosha::{ChangeEvent, ChangeKind, Error, WATCH_BUFFER, Watcher};
+use sha2::{Digest, Sha256};
+
+/// The next change, failing the test if there is none waiting
+async fn next(watcher: &mut Watcher) -> ChangeEvent {
+    tokio::time::timeout(std::time::Duration::from_secs(5), watcher.next())
+        .await
+        .expect("No change arrived")
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_watch_reports_changes_with_versions() {
+    let (kosha, dir) = create_test_kosha("changes").await;
+    let mut watcher = kosha.watch("").unwrap();
+
+    let created = kosha.write_file_versioned("/notes/a.txt", b"one", None).await.unwrap();
+    let event = next(&mut watcher).await;
+    assert_eq!((event.path.as_str(), &event.kind), ("notes/a.txt", &ChangeKind::Created));
+    assert_eq!(event.version, created);
+
+    let modified = kosha.write_file_versioned("notes/a.txt", b"two!", None).await.unwrap();
+    let event = next(&mut watcher).await;
+    assert_eq!(event.kind, ChangeKind::Modified);
+    assert_eq!(event.version, modified);
+
+    kosha.rename("notes/a.txt", "notes/b.txt").await.unwrap();
+    let event = next(&mut watcher).await;
+    assert_eq!(event.path, "notes/b.txt");
+    assert_eq!(
+        event.kind,
+        ChangeKind::Renamed {
+            from: "notes/a.txt".to_string()
+        }
+    );
+    assert_eq!(event.version, modified);
+
+    kosha.delete("notes/b.txt").await.unwrap();
+    let event = next(&mut watcher).await;
+    assert_eq!((event.path.as_str(), &event.kind), ("notes/b.txt", &ChangeKind::Deleted));
+    assert_eq!(event.version, modified);
+
+    // Failed operations report nothing
+    assert!(kosha.delete("notes/b.txt").await.is_err());
+    kosha.write_file("done.txt", b"").await.unwrap();
+    assert_eq!(next(&mut watcher).await.path, "done.txt");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_watch_filters_by_prefix() {
+    let (kosha, dir) = create_test_kosha("prefix").await;
+    let mut docs = kosha.watch("/docs/").unwrap();
+    assert_eq!(docs.prefix(), "docs");
+
+    kosha.write_file("docs.txt", b"not under docs/").await.unwrap();
+    kosha.write_file("other/x.txt", b"x").await.unwrap();
+    kosha.write_file("docs/guide.md", b"guide").await.unwrap();
+    assert_eq!(next(&mut docs).await.path, "docs/guide.md");
+
+    // Moving a file out of the prefix is reported too
+    kosha.rename("docs/guide.md", "archive/guide.md").await.unwrap();
+    let event = next(&mut docs).await;
+    assert_eq!(event.path, "archive/guide.md");
+    assert!(event.is_under("docs"));
+    assert!(event.is_under("archive"));
+    assert!(!event.is_under("arch"));
+
+    assert!(matches!(kosha.watch("../outside"), Err(Error::InvalidPath(_))));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_watch_sees_uploads_and_clones() {
+    let (kosha, dir) = create_test_kosha("uploads").await;
+    let mut watcher = kosha.watch("models").unwrap();
+
+    let content = b"glb bytes".to_vec();
+    let hash: String = Sha256::digest(&content).iter().map(|b| format!("{:02x}", b)).collect();
+    let clone = kosha.clone();
+    let status = clone.begin_upload("models/a.glb", content.len() as u64, &hash, None).await.unwrap();
+    clone.upload_chunk(&status.upload_id, 0, &content).await.unwrap();
+    let version = clone.commit_upload(&status.upload_id).await.unwrap();
+
+    let event = next(&mut watcher).await;
+    assert_eq!((event.path.as_str(), &event.kind), ("models/a.glb", &ChangeKind::Created));
+    assert_eq!(event.version, version);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_slow_watcher_lags() {
+    let (kosha, dir) = create_test_kosha("lag").await;
+    let mut watcher = kosha.watch("").unwrap();
+
+    for i in 0..WATCH_BUFFER + 3 {
+        kosha.write_file(&format!("f{}.txt", i), b"x").await.unwrap();
+    }
+    assert!(matches!(watcher.next().await, Err(Error::WatchLagged(3))));
+    // Later changes arrive after the lag is reported
+    assert_eq!(next(&mut watcher).await.path, "f3.txt");
+
+    drop(kosha);
+    while let Ok(Some(_)) = watcher.next().await {}
+    assert!(watcher.next().await.unwrap().is_none());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
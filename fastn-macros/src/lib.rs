@@ -2,12 +2,15 @@
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, ItemFn};
+use syn::{parse_macro_input, Item};
 
-/// Marks a function as the fastn app entry point.
+/// Marks a function, or an `impl fastn::App` block, as the fastn app entry
+/// point.
 ///
 /// This attribute generates the necessary FFI exports for WASM.
 /// The function receives a `&mut RealityViewContent` to populate with entities.
+/// Apps that keep state across events implement `fastn::App` for a type
+/// with a `Default` instead; the app is created with `Default::default()`.
 ///
 /// ## WASM API
 ///
@@ -33,21 +36,62 @@ use syn::{parse_macro_input, ItemFn};
 ///     content.add(cube);
 /// }
 /// ```
+///
+/// With state:
+///
+/// ```rust,ignore
+/// use fastn::{App, Command, Event, RealityViewContent};
+///
+/// #[derive(Default)]
+/// struct Counter {
+///     frames: u64,
+/// }
+///
+/// #[fastn::app]
+/// impl App for Counter {
+///     fn init(&mut self, content: &mut RealityViewContent) {
+///         // add entities as above
+///     }
+///
+///     fn update(&mut self, event: &Event) -> Vec<Command> {
+///         self.frames += 1;
+///         vec![]
+///     }
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn app(_attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input_fn = parse_macro_input!(item as ItemFn);
-    let fn_name = &input_fn.sig.ident;
+    let item = parse_macro_input!(item as Item);
+    let create = match &item {
+        Item::Fn(input_fn) => {
+            let fn_name = &input_fn.sig.ident;
+            quote! {
+                let mut content = fastn::RealityViewContent::new();
+                #fn_name(&mut content);
+                fastn::wasm_bridge::create_app(&content) as i32
+            }
+        }
+        Item::Impl(input_impl) if input_impl.trait_.is_some() => {
+            let self_ty = &input_impl.self_ty;
+            quote! {
+                fastn::wasm_bridge::create_stateful_app(<#self_ty as ::core::default::Default>::default()) as i32
+            }
+        }
+        _ => {
+            return syn::Error::new_spanned(&item, "#[fastn::app] expects a fn or an `impl fastn::App for ...` block")
+                .to_compile_error()
+                .into();
+        }
+    };
 
     let expanded = quote! {
-        #input_fn
+        #item
 
         /// Create the app and return its pointer.
         /// Call get_result_ptr/get_result_len to read initial commands.
         #[unsafe(no_mangle)]
         pub extern "C" fn init_core() -> i32 {
-            let mut content = fastn::RealityViewContent::new();
-            #fn_name(&mut content);
-            fastn::wasm_bridge::create_app(&content) as i32
+            #create
         }
 
         /// Get pointer to the result buffer (initial commands or last on_event result)
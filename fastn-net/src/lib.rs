@@ -15,18 +15,124 @@
 //! Requests are POST to `/_fastn` with JSON body:
 //! ```json
 //! {
+//!   "version": 3,
 //!   "sender": "<id52>",
+//!   "timestamp": 1735053045,
+//!   "nonce": "<32 hex chars>",
+//!   "audience": { "hub": "<hub id52>", "endpoint": "/_fastn" },
 //!   "payload": { ... },
 //!   "signature": "<base64 signature>"
 //! }
 //! ```
 //!
-//! The signature covers `sender + "|" + canonical_json(payload)`.
+//! The signature covers
+//! `fastn-request-v3|sender|timestamp|nonce|hub|endpoint|canonical_json(payload)`.
+//!
+//! # Canonical JSON
+//!
+//! Signatures are over bytes, so both sides have to serialize the payload
+//! the same way. Version 3 requests use the JSON Canonicalization Scheme
+//! (RFC 8785, see `canonical_json`), which implementations in other
+//! languages can reproduce. Version 2 requests sign whatever serde_json
+//! prints, which differs from JCS in key order (UTF-8 rather than UTF-16)
+//! and number formatting; they are still accepted, so clients talking to
+//! hubs that aren't upgraded yet can keep signing them
+//! (`Client::with_canonicalization`). Hubs answer in the canonicalization of
+//! the request (`SignedResponse::canonicalization`).
+//!
+//! `test-vectors.json` in this crate has canonicalization, request and
+//! response vectors to check other implementations against.
+//!
+//! # Binary Envelopes and Compression
+//!
+//! Clients can also send version 4 requests (`Canonicalization::Cbor`):
+//! the same envelope as CBOR (`Content-Type: application/cbor`), with the
+//! signature over `fastn-request-v4|...|` followed by the payload's
+//! canonical CBOR bytes (see `cbor`). Base64 strings, like file contents,
+//! travel as raw bytes. Hubs answer in the request's format. Hubs that
+//! predate CBOR refuse it with 415, and the client falls back to JCS.
+//!
+//! Bodies are compressed with gzip or zstd when both sides read it:
+//! responses per the request's Accept-Encoding, requests once the hub's
+//! Accept-Encoding said so (see `wire`).
+//!
+//! # Audience Binding
+//!
+//! Every hub can verify every sender's signature, so without more a request
+//! signed for hub A could be submitted to hub B, where only the ACLs would
+//! stand in the way. Version 2 requests name their `Audience` (the target
+//! hub's ID52 and the endpoint) inside the signed message, and hubs reject
+//! requests addressed to anyone else (`SignedRequest::check_audience`).
+//!
+//! Version 1 requests (no `version` field, signature over
+//! `sender|timestamp|nonce|canonical_json(payload)`) are still accepted until
+//! `UNBOUND_REQUESTS_ACCEPTED_UNTIL`, so spokes can be upgraded after their
+//! hubs. Hubs have to be upgraded first: older hubs can't verify version 2
+//! signatures.
+//!
+//! # Replay Protection
+//!
+//! A signature alone doesn't stop anyone who captured a request from sending
+//! it again. Servers pass verified requests through a `ReplayGuard`, which
+//! rejects requests whose timestamp is more than `MAX_CLOCK_SKEW_SECS` away
+//! from the server's clock and requests whose nonce the sender already used
+//! within that window. Stale rejections carry the server's time
+//! (`RejectedRequest`), and clients retry once with their clock corrected.
+//!
+//! # Push Notifications
+//!
+//! Hubs can also push `PushEvent`s to spokes over a WebSocket at
+//! `WS_ENDPOINT`. The spoke's first message is a `SignedRequest` with a
+//! `Subscribe` payload, addressed to `Audience::new(hub_id52, WS_ENDPOINT)`;
+//! every message from the hub is a `SignedResponse`
+//! (`client::Client::subscribe`).
+//!
+//! # Transports
+//!
+//! Clients don't talk HTTP directly: they hand each signed request to a
+//! `Transport`, which delivers it to the hub named by a `HubAddress` (a URL
+//! and/or an iroh endpoint) and brings back the signed reply. `HttpTransport`
+//! is the only one built in. Other transports are added with
+//! `Client::with_transport` and tried first; when one can't reach the hub
+//! the same signed request goes over the next, so at most one of them gets
+//! it handled (hubs reject the replayed nonce). The transport that worked
+//! last is tried first next time. Push subscriptions are HTTP only.
+//!
+//! # Timeouts, Retries and Cancellation
+//!
+//! Each attempt at a call gets `DEFAULT_REQUEST_TIMEOUT` to bring back a
+//! reply (`Client::with_timeout`), so a hung hub fails the call with
+//! `Error::Timeout` instead of hanging the app. Only `Client::call_idempotent`
+//! retries: after a timeout or a transport error it waits, doubling the wait
+//! each time (`RetryPolicy`), and signs the request again. Plain `call`s
+//! aren't retried, as the hub may have handled the first attempt.
+//! `Client::call_cancellable` also returns a `CancelHandle` that stops the
+//! call, with `Error::Cancelled`. In the browser an abandoned fetch is
+//! aborted through its `AbortController`; natively the connection is dropped.
+//!
+//! # Derived Identities
+//!
+//! A node can derive sub-identities for specific purposes, e.g. a per-app
+//! service identity `app:inbox`. The derived key is computed with HKDF-SHA256
+//! from the parent's secret key and the derivation path, so the same parent
+//! and path always give the same ID52. The parent signs a
+//! `DerivationCertificate` binding `parent/path` to the derived ID52, and
+//! `DerivedSignature`s carry that certificate so a verifier who only knows the
+//! parent's ID52 can check both the signature and which identity made it.
+//!
+//! # Key Files and Rotation
+//!
+//! Nodes keep their secret key in a file (`hub.key`, `spoke.key`), as the
+//! raw 32 bytes or, once protected, encrypted with a passphrase or moved to
+//! the OS keychain (see `key_file`). When a hub rotates its key it signs a
+//! `KeyRotation` with the old one; requests still addressed to the old ID52
+//! are rejected with it (`RejectedRequest::key_rotation`), and clients
+//! return `Error::HubKeyRotated` for the spoke to follow.
 //!
 //! # Example
 //!
 //! ```rust,ignore
-//! use fastn_net::{SecretKey, SignedRequest, verify_request};
+//! use fastn_net::{Audience, SecretKey, SignedRequest, ENDPOINT};
 //! use serde::{Serialize, Deserialize};
 //!
 //! #[derive(Serialize, Deserialize)]
@@ -35,19 +141,52 @@
 //! // Create and sign a request
 //! let key = SecretKey::generate();
 //! let request = MyRequest { message: "hello".into() };
-//! let signed = SignedRequest::new(&key, &request)?;
+//! let audience = Audience::new(hub_id52, ENDPOINT);
+//! let signed = SignedRequest::new(&key, &audience, &request)?;
 //!
-//! // Verify and extract
+//! // Verify, check it's for us, and extract
 //! let (sender_id52, payload): (String, MyRequest) = signed.verify()?;
+//! signed.check_audience(&audience)?;
 //! ```
 
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use thiserror::Error;
 
 /// HTTP endpoint path for fastn protocol
 pub const ENDPOINT: &str = "/_fastn";
 
+/// WebSocket endpoint path for push notifications (see `PushEvent`)
+pub const WS_ENDPOINT: &str = "/_fastn/ws";
+
+/// How far (in seconds, either way) a request's timestamp may be from the
+/// server's clock
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Longest nonce a server accepts
+pub const MAX_NONCE_LEN: usize = 64;
+
+/// Version of the request signature scheme: version 3 signs payloads as
+/// RFC 8785 canonical JSON
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Version of requests whose payloads are signed as canonical CBOR, and
+/// sent as CBOR (see `cbor`)
+pub const CBOR_PROTOCOL_VERSION: u32 = 4;
+
+/// Version of requests bound to their audience, with payloads signed as
+/// serde_json prints them
+pub const SERDE_JSON_PROTOCOL_VERSION: u32 = 2;
+
+/// Version of requests signed before audience binding
+pub const UNBOUND_PROTOCOL_VERSION: u32 = 1;
+
+/// Unix time (2027-04-01 UTC) after which version 1 requests are rejected
+pub const UNBOUND_REQUESTS_ACCEPTED_UNTIL: i64 = 1_806_537_600;
+
 /// Error types for fastn-net operations
 #[derive(Error, Debug)]
 pub enum Error {
@@ -66,13 +205,96 @@ pub enum Error {
     #[error("Base64 decode error: {0}")]
     Base64Decode(String),
 
+    #[error("Request timestamp {timestamp} is too far from server time {now}")]
+    StaleRequest { timestamp: i64, now: i64 },
+
+    #[error("Replayed request: nonce {0} was already used")]
+    ReplayedRequest(String),
+
+    #[error("Invalid nonce")]
+    InvalidNonce,
+
+    #[error("Unsupported request version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Request has no audience")]
+    MissingAudience,
+
+    #[error("Request is addressed to {actual}, not {expected}")]
+    WrongAudience { expected: String, actual: String },
+
+    #[error("Requests without an audience are no longer accepted, upgrade the client")]
+    UnboundRequest,
+
+    #[error("Cannot write canonical JSON: {0}")]
+    CanonicalJson(String),
+
+    #[error("Invalid derivation path: {0}")]
+    InvalidDerivationPath(String),
+
+    #[error("Signed by {actual}, expected {expected}")]
+    WrongIdentity { expected: String, actual: String },
+
+    #[error("Invalid CBOR: {0}")]
+    Cbor(String),
+
+    #[error("Invalid invite: {0}")]
+    InvalidInvite(String),
+
+    #[error("Hub {} now uses the key {}", .0.old_id52, .0.new_id52)]
+    HubKeyRotated(Box<KeyRotation>),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid key file: {0}")]
+    InvalidKeyFile(String),
+
+    #[error("{0} is encrypted: set FASTN_KEY_PASSPHRASE to its passphrase")]
+    PassphraseRequired(String),
+
+    #[error("Wrong passphrase for {0}")]
+    WrongPassphrase(String),
+
+    #[error("Keychain error: {0}")]
+    Keychain(String),
+
+    #[error("Unsupported content type {0}")]
+    UnsupportedContentType(String),
+
+    #[error("Unsupported content encoding {0}")]
+    UnsupportedEncoding(String),
+
+    #[error("Compression error: {0}")]
+    Compression(String),
+
     #[cfg(any(feature = "client", target_arch = "wasm32"))]
     #[error("HTTP request failed: {0}")]
     HttpRequest(String),
 
+    #[cfg(any(feature = "client", target_arch = "wasm32"))]
+    #[error("Request rejected with status {status}: {body}")]
+    Rejected { status: u16, body: String },
+
+    #[cfg(any(feature = "client", target_arch = "wasm32"))]
+    #[error("No transport can reach {0}")]
+    NoTransport(String),
+
+    #[cfg(any(feature = "client", target_arch = "wasm32"))]
+    #[error("No reply from the hub within {0:?}")]
+    Timeout(std::time::Duration),
+
+    #[cfg(any(feature = "client", target_arch = "wasm32"))]
+    #[error("Request cancelled")]
+    Cancelled,
+
     #[cfg(feature = "server")]
     #[error("Server error: {0}")]
     Server(String),
+
+    #[cfg(any(feature = "client", feature = "server"))]
+    #[error("WebSocket error: {0}")]
+    WebSocket(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -164,11 +386,372 @@ pub fn from_id52(id52: &str) -> Result<PublicKey> {
     PublicKey::from_bytes(&bytes)
 }
 
+/// A hub's invitation for a spoke to join it (see `fastn-hub invite`)
+///
+/// The token is URL-safe base64 of the hub's public key, the invite ID and
+/// its expiry, signed by the hub, so the spoke learns which hub to trust
+/// from the token alone. The hub accepts each invite once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InviteToken {
+    pub hub_id52: String,
+    /// Names the invite on the hub (16 hex chars)
+    pub invite_id: String,
+    /// Unix time (seconds) after which the hub refuses it
+    pub expires_at: i64,
+}
+
+impl InviteToken {
+    /// Prefix of the signed message, so invite signatures can't pass for
+    /// anything else the hub signs
+    const DOMAIN: &'static [u8] = b"fastn-invite-v1|";
+
+    /// Sign an invite as the hub whose key is `secret_key`
+    pub fn sign(secret_key: &SecretKey, invite_id: [u8; 8], expires_at: i64) -> String {
+        let mut token = secret_key.public().to_bytes().to_vec();
+        token.extend(invite_id);
+        token.extend(expires_at.to_be_bytes());
+        let signature = secret_key.sign(&[Self::DOMAIN, &token].concat());
+        token.extend(signature);
+        data_encoding::BASE64URL_NOPAD.encode(&token)
+    }
+
+    /// Decode a token and check the hub's signature (not its expiry)
+    pub fn verify(token: &str) -> Result<Self> {
+        let bytes = data_encoding::BASE64URL_NOPAD
+            .decode(token.trim().as_bytes())
+            .map_err(|e| Error::InvalidInvite(e.to_string()))?;
+        if bytes.len() != 48 + 64 {
+            return Err(Error::InvalidInvite(format!("expected 112 bytes, got {}", bytes.len())));
+        }
+        let (signed, signature) = bytes.split_at(48);
+        let public_key = PublicKey::from_bytes(signed[..32].try_into().expect("32 bytes"))?;
+        public_key.verify(&[Self::DOMAIN, signed].concat(), signature)?;
+        Ok(Self {
+            hub_id52: public_key.id52(),
+            invite_id: data_encoding::HEXLOWER.encode(&signed[32..40]),
+            expires_at: i64::from_be_bytes(signed[40..48].try_into().expect("8 bytes")),
+        })
+    }
+}
+
+/// A hub's statement that it has moved to a new key (see
+/// `fastn-hub rotate-key`)
+///
+/// Signed by the old key, so a spoke that trusts the old ID52 can move to
+/// the new one on its own. A hub rotated more than once hands out one
+/// rotation per step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub old_id52: String,
+    pub new_id52: String,
+    /// Unix time (seconds) of the rotation
+    pub rotated_at: i64,
+    /// Base64-encoded signature of the old key
+    pub signature: String,
+}
+
+impl KeyRotation {
+    /// Prefix of the signed message, so rotation signatures can't pass for
+    /// anything else the hub signs
+    const DOMAIN: &'static str = "fastn-key-rotation-v1|";
+
+    fn message(old_id52: &str, new_id52: &str, rotated_at: i64) -> Vec<u8> {
+        format!("{}{}|{}|{}", Self::DOMAIN, old_id52, new_id52, rotated_at).into_bytes()
+    }
+
+    /// Vouch for `new_id52` with the key it replaces
+    pub fn sign(old_key: &SecretKey, new_id52: &str, rotated_at: i64) -> Self {
+        let old_id52 = old_key.id52();
+        let signature = old_key.sign(&Self::message(&old_id52, new_id52, rotated_at));
+        Self {
+            old_id52,
+            new_id52: new_id52.to_string(),
+            rotated_at,
+            signature: data_encoding::BASE64.encode(&signature),
+        }
+    }
+
+    /// Check the old key's signature
+    pub fn verify(&self) -> Result<()> {
+        from_id52(&self.new_id52)?;
+        let signature = data_encoding::BASE64
+            .decode(self.signature.as_bytes())
+            .map_err(|e| Error::Base64Decode(e.to_string()))?;
+        from_id52(&self.old_id52)?.verify(&Self::message(&self.old_id52, &self.new_id52, self.rotated_at), &signature)
+    }
+}
+
+/// Current Unix time in seconds
+pub fn unix_time() -> i64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        (js_sys::Date::now() / 1000.0) as i64
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default()
+    }
+}
+
+/// Maximum length of a derivation path
+pub const MAX_DERIVATION_PATH_LEN: usize = 128;
+
+/// HKDF salt for derived identities, versioned so the scheme can change
+const DERIVATION_SALT: &[u8] = b"fastn-net derived identity v1";
+
+/// Check that a derivation path is 1-128 printable ASCII characters without
+/// whitespace or `|` (used as separator in signed messages)
+pub fn validate_derivation_path(path: &str) -> Result<()> {
+    if path.is_empty() || path.len() > MAX_DERIVATION_PATH_LEN {
+        return Err(Error::InvalidDerivationPath(format!(
+            "must be 1-{} characters",
+            MAX_DERIVATION_PATH_LEN
+        )));
+    }
+    if !path.bytes().all(|b| b.is_ascii_graphic() && b != b'|') {
+        return Err(Error::InvalidDerivationPath(format!(
+            "{:?} may only contain printable ASCII characters other than '|'",
+            path
+        )));
+    }
+    Ok(())
+}
+
+impl SecretKey {
+    /// Derive the sub-identity `path` of this key (e.g. `app:inbox`).
+    ///
+    /// Deterministic: the same key and path always give the same identity.
+    pub fn derive(&self, path: &str) -> Result<DerivedIdentity> {
+        validate_derivation_path(path)?;
+
+        let hkdf = hkdf::Hkdf::<sha2::Sha256>::new(Some(DERIVATION_SALT), &self.to_bytes());
+        let mut seed = [0u8; 32];
+        hkdf.expand(path.as_bytes(), &mut seed)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        let secret_key = SecretKey::from_bytes(&seed);
+
+        let parent = self.id52();
+        let derived = secret_key.id52();
+        let message = DerivationCertificate::message(&parent, path, &derived);
+        let signature = data_encoding::BASE64.encode(&self.sign(message.as_bytes()));
+
+        Ok(DerivedIdentity {
+            secret_key,
+            certificate: DerivationCertificate {
+                parent,
+                path: path.to_string(),
+                derived,
+                signature,
+            },
+        })
+    }
+}
+
+/// The parent's statement that `derived` is its sub-identity `path`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DerivationCertificate {
+    /// Parent's ID52
+    pub parent: String,
+    /// Derivation path, e.g. `app:inbox`
+    pub path: String,
+    /// Derived identity's ID52
+    pub derived: String,
+    /// Base64-encoded signature of the parent
+    pub signature: String,
+}
+
+impl DerivationCertificate {
+    fn message(parent: &str, path: &str, derived: &str) -> String {
+        format!("fastn-derive|{}|{}|{}", parent, path, derived)
+    }
+
+    /// Display name of the derived identity: `<parent-id52>/<path>`
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.parent, self.path)
+    }
+
+    /// Verify the parent's signature
+    pub fn verify(&self) -> Result<()> {
+        validate_derivation_path(&self.path)?;
+        let public_key = from_id52(&self.parent)?;
+        from_id52(&self.derived)?;
+        let message = Self::message(&self.parent, &self.path, &self.derived);
+
+        let signature = data_encoding::BASE64
+            .decode(self.signature.as_bytes())
+            .map_err(|e| Error::Base64Decode(e.to_string()))?;
+
+        public_key.verify(message.as_bytes(), &signature)
+    }
+
+    /// Verify that this certifies the sub-identity `path` of a specific parent
+    pub fn verify_from(&self, parent_id52: &str, path: &str) -> Result<()> {
+        if self.parent != parent_id52 || self.path != path {
+            return Err(Error::WrongIdentity {
+                expected: format!("{}/{}", parent_id52, path),
+                actual: self.name(),
+            });
+        }
+        self.verify()
+    }
+}
+
+/// A derived sub-identity: its key and the parent's certificate for it
+#[derive(Clone)]
+pub struct DerivedIdentity {
+    secret_key: SecretKey,
+    certificate: DerivationCertificate,
+}
+
+impl DerivedIdentity {
+    /// The derived key
+    pub fn secret_key(&self) -> &SecretKey {
+        &self.secret_key
+    }
+
+    /// The parent's certificate for this identity
+    pub fn certificate(&self) -> &DerivationCertificate {
+        &self.certificate
+    }
+
+    /// ID52 of the derived key
+    pub fn id52(&self) -> &str {
+        &self.certificate.derived
+    }
+
+    /// Display name: `<parent-id52>/<path>`
+    pub fn name(&self) -> String {
+        self.certificate.name()
+    }
+
+    /// Sign a message, attaching the certificate
+    pub fn sign(&self, message: &[u8]) -> DerivedSignature {
+        DerivedSignature {
+            certificate: self.certificate.clone(),
+            signature: data_encoding::BASE64.encode(&self.secret_key.sign(message)),
+        }
+    }
+}
+
+/// A signature made by a derived identity, verifiable from the parent's ID52
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DerivedSignature {
+    pub certificate: DerivationCertificate,
+    /// Base64-encoded signature of the derived key
+    pub signature: String,
+}
+
+impl DerivedSignature {
+    /// Verify the certificate and the signature over `message`, returning the
+    /// certificate so the caller can check who signed
+    pub fn verify(&self, message: &[u8]) -> Result<&DerivationCertificate> {
+        self.certificate.verify()?;
+        let public_key = from_id52(&self.certificate.derived)?;
+
+        let signature = data_encoding::BASE64
+            .decode(self.signature.as_bytes())
+            .map_err(|e| Error::Base64Decode(e.to_string()))?;
+
+        public_key.verify(message, &signature)?;
+        Ok(&self.certificate)
+    }
+
+    /// Verify that `message` was signed by the sub-identity `path` of a
+    /// specific parent
+    pub fn verify_from(&self, message: &[u8], parent_id52: &str, path: &str) -> Result<()> {
+        self.certificate.verify_from(parent_id52, path)?;
+        self.verify(message)?;
+        Ok(())
+    }
+}
+
+/// Who a request is meant for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Audience {
+    /// Target hub's ID52
+    pub hub: String,
+    /// Endpoint path, e.g. `ENDPOINT`
+    pub endpoint: String,
+}
+
+impl Audience {
+    pub fn new(hub_id52: impl Into<String>, endpoint: impl Into<String>) -> Self {
+        Self {
+            hub: hub_id52.into(),
+            endpoint: endpoint.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Audience {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.hub, self.endpoint)
+    }
+}
+
+fn unbound_protocol_version() -> u32 {
+    UNBOUND_PROTOCOL_VERSION
+}
+
+/// How payloads are serialized for signing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Canonicalization {
+    /// RFC 8785 canonical JSON (`canonical_json`), signed by version 3
+    #[default]
+    Jcs,
+    /// serde_json's output, signed by versions 1 and 2
+    SerdeJson,
+    /// Canonical CBOR (`cbor`), signed by version 4 and sent as CBOR
+    Cbor,
+}
+
+impl Canonicalization {
+    /// Serialize a payload for signing
+    pub fn to_bytes(self, value: &serde_json::Value) -> Result<Vec<u8>> {
+        match self {
+            Canonicalization::Jcs => Ok(canonical_json::to_string(value)?.into_bytes()),
+            Canonicalization::SerdeJson => Ok(serde_json::to_vec(value)?),
+            Canonicalization::Cbor => Ok(cbor::to_vec(value)),
+        }
+    }
+
+    /// Version of the requests signed this way
+    pub fn request_version(self) -> u32 {
+        match self {
+            Canonicalization::Jcs => PROTOCOL_VERSION,
+            Canonicalization::SerdeJson => SERDE_JSON_PROTOCOL_VERSION,
+            Canonicalization::Cbor => CBOR_PROTOCOL_VERSION,
+        }
+    }
+
+    fn is_serde_json(&self) -> bool {
+        *self == Canonicalization::SerdeJson
+    }
+}
+
+fn serde_json_canonicalization() -> Canonicalization {
+    Canonicalization::SerdeJson
+}
+
 /// A signed request envelope
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SignedRequest {
+    /// Signature scheme, `UNBOUND_PROTOCOL_VERSION` if absent
+    #[serde(default = "unbound_protocol_version")]
+    pub version: u32,
     /// Sender's ID52
     pub sender: String,
+    /// Unix time (seconds) the request was signed at
+    pub timestamp: i64,
+    /// Random value, never reused by the sender
+    pub nonce: String,
+    /// Hub and endpoint the request is for (version 2 and later)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<Audience>,
     /// The payload (as JSON value for flexibility)
     pub payload: serde_json::Value,
     /// Base64-encoded signature
@@ -176,37 +759,109 @@ pub struct SignedRequest {
 }
 
 impl SignedRequest {
-    /// Create a new signed request
-    pub fn new<T: Serialize>(secret_key: &SecretKey, payload: &T) -> Result<Self> {
-        let sender = secret_key.id52();
-        let payload_json = serde_json::to_value(payload)?;
+    /// Create a new signed request for `audience`, timestamped now
+    pub fn new<T: Serialize>(secret_key: &SecretKey, audience: &Audience, payload: &T) -> Result<Self> {
+        Self::new_at(secret_key, audience, payload, unix_time())
+    }
 
-        // Create message to sign: sender|payload_json
-        let message = format!("{}|{}", sender, serde_json::to_string(&payload_json)?);
-        let signature = secret_key.sign(message.as_bytes());
-        let signature_b64 = data_encoding::BASE64.encode(&signature);
+    /// Create a new signed request with the given timestamp (e.g. corrected
+    /// for the server's clock)
+    pub fn new_at<T: Serialize>(
+        secret_key: &SecretKey,
+        audience: &Audience,
+        payload: &T,
+        timestamp: i64,
+    ) -> Result<Self> {
+        Self::new_with(secret_key, audience, payload, timestamp, Canonicalization::Jcs)
+    }
 
-        Ok(Self {
-            sender,
-            payload: payload_json,
-            signature: signature_b64,
-        })
+    /// `new_at`, signing the payload with `canonicalization`
+    /// (`Canonicalization::SerdeJson` makes a version 2 request, for hubs
+    /// that don't accept version 3 yet; `Cbor` a version 4 one)
+    pub fn new_with<T: Serialize>(
+        secret_key: &SecretKey,
+        audience: &Audience,
+        payload: &T,
+        timestamp: i64,
+        canonicalization: Canonicalization,
+    ) -> Result<Self> {
+        Self::sign(secret_key, canonicalization.request_version(), Some(audience), payload, timestamp)
+    }
+
+    /// Sign a request of the given version (version 1 has no audience)
+    fn sign<T: Serialize>(
+        secret_key: &SecretKey,
+        version: u32,
+        audience: Option<&Audience>,
+        payload: &T,
+        timestamp: i64,
+    ) -> Result<Self> {
+        use rand::RngCore;
+
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut request = Self {
+            version,
+            sender: secret_key.id52(),
+            timestamp,
+            nonce: data_encoding::HEXLOWER.encode(&nonce),
+            audience: audience.cloned(),
+            payload: serde_json::to_value(payload)?,
+            signature: String::new(),
+        };
+        let message = request.message()?;
+        request.signature = data_encoding::BASE64.encode(&secret_key.sign(&message));
+        Ok(request)
+    }
+
+    /// The signed message:
+    /// fastn-request-v3|sender|timestamp|nonce|hub|endpoint|payload_json
+    /// (the same with v2 for version 2, and with v4 and the payload's CBOR
+    /// bytes for version 4), or sender|timestamp|nonce|payload_json for
+    /// version 1
+    fn message(&self) -> Result<Vec<u8>> {
+        let mut message = match self.version {
+            UNBOUND_PROTOCOL_VERSION => format!("{}|{}|{}|", self.sender, self.timestamp, self.nonce),
+            SERDE_JSON_PROTOCOL_VERSION | PROTOCOL_VERSION | CBOR_PROTOCOL_VERSION => {
+                let audience = self.audience.as_ref().ok_or(Error::MissingAudience)?;
+                format!(
+                    "fastn-request-v{}|{}|{}|{}|{}|{}|",
+                    self.version, self.sender, self.timestamp, self.nonce, audience.hub, audience.endpoint
+                )
+            }
+            version => return Err(Error::UnsupportedVersion(version)),
+        }
+        .into_bytes();
+        message.extend(self.canonicalization().to_bytes(&self.payload)?);
+        Ok(message)
+    }
+
+    /// How the payload is signed, and so how to sign the answer
+    pub fn canonicalization(&self) -> Canonicalization {
+        match self.version {
+            CBOR_PROTOCOL_VERSION.. => Canonicalization::Cbor,
+            PROTOCOL_VERSION => Canonicalization::Jcs,
+            _ => Canonicalization::SerdeJson,
+        }
     }
 
     /// Verify the signature and extract the payload
+    ///
+    /// This doesn't check the audience or freshness; servers also call
+    /// `check_audience` and run verified requests through a `ReplayGuard`.
     pub fn verify<T: DeserializeOwned>(&self) -> Result<(String, T)> {
         // Decode sender's public key
         let public_key = from_id52(&self.sender)?;
 
         // Reconstruct the signed message
-        let message = format!("{}|{}", self.sender, serde_json::to_string(&self.payload)?);
+        let message = self.message()?;
 
         // Decode and verify signature
         let signature = data_encoding::BASE64
             .decode(self.signature.as_bytes())
             .map_err(|e| Error::Base64Decode(e.to_string()))?;
 
-        public_key.verify(message.as_bytes(), &signature)?;
+        public_key.verify(&message, &signature)?;
 
         // Deserialize payload
         let payload: T = serde_json::from_value(self.payload.clone())?;
@@ -218,6 +873,188 @@ impl SignedRequest {
     pub fn sender_id52(&self) -> &str {
         &self.sender
     }
+
+    /// Check that a verified request is addressed to `expected`. Version 1
+    /// requests, which name no audience, pass until
+    /// `UNBOUND_REQUESTS_ACCEPTED_UNTIL`.
+    pub fn check_audience(&self, expected: &Audience) -> Result<()> {
+        self.check_audience_at(expected, unix_time())
+    }
+
+    /// `check_audience` at a given server time
+    pub fn check_audience_at(&self, expected: &Audience, now: i64) -> Result<()> {
+        match &self.audience {
+            Some(audience) if audience == expected => Ok(()),
+            Some(audience) => Err(Error::WrongAudience {
+                expected: expected.to_string(),
+                actual: audience.to_string(),
+            }),
+            None if self.version == UNBOUND_PROTOCOL_VERSION && now < UNBOUND_REQUESTS_ACCEPTED_UNTIL => Ok(()),
+            None if self.version == UNBOUND_PROTOCOL_VERSION => Err(Error::UnboundRequest),
+            None => Err(Error::MissingAudience),
+        }
+    }
+}
+
+/// Rejects stale and replayed requests
+///
+/// Keeps, per sender, the nonces seen within the last `MAX_CLOCK_SKEW_SECS`.
+/// Anything older is rejected by its timestamp alone, so the window never
+/// needs more than that.
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    /// Sender ID52 -> (timestamp, nonce) of recent requests
+    seen: Mutex<HashMap<String, BTreeSet<(i64, String)>>>,
+    checks: AtomicU64,
+}
+
+/// Every this many checks, windows of senders that went quiet are dropped
+const REPLAY_SWEEP_INTERVAL: u64 = 1024;
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check a request whose signature has been verified (checking first
+    /// would let anyone fill other senders' windows)
+    pub fn check(&self, request: &SignedRequest) -> Result<()> {
+        self.check_at(request, unix_time())
+    }
+
+    /// `check` against a given server time
+    pub fn check_at(&self, request: &SignedRequest, now: i64) -> Result<()> {
+        if (request.timestamp - now).abs() > MAX_CLOCK_SKEW_SECS {
+            return Err(Error::StaleRequest {
+                timestamp: request.timestamp,
+                now,
+            });
+        }
+        if request.nonce.is_empty() || request.nonce.len() > MAX_NONCE_LEN {
+            return Err(Error::InvalidNonce);
+        }
+
+        let cutoff = now - MAX_CLOCK_SKEW_SECS;
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if self.checks.fetch_add(1, Ordering::Relaxed).is_multiple_of(REPLAY_SWEEP_INTERVAL) {
+            seen.retain(|_, window| {
+                prune_window(window, cutoff);
+                !window.is_empty()
+            });
+        }
+
+        let window = seen.entry(request.sender.clone()).or_default();
+        prune_window(window, cutoff);
+        if !window.insert((request.timestamp, request.nonce.clone())) {
+            return Err(Error::ReplayedRequest(request.nonce.clone()));
+        }
+        Ok(())
+    }
+}
+
+fn prune_window(window: &mut BTreeSet<(i64, String)>, cutoff: i64) {
+    *window = window.split_off(&(cutoff, String::new()));
+}
+
+/// Body of an HTTP 400 for a request that was not accepted
+///
+/// `server_time` is set when the request was stale, so the client can
+/// correct its clock and retry. `key_rotation` is set when the request was
+/// addressed to a key the hub has rotated away from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejectedRequest {
+    pub error: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_time: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_rotation: Option<KeyRotation>,
+}
+
+impl From<&Error> for RejectedRequest {
+    fn from(e: &Error) -> Self {
+        Self {
+            error: e.to_string(),
+            server_time: match e {
+                Error::StaleRequest { now, .. } => Some(*now),
+                _ => None,
+            },
+            key_rotation: None,
+        }
+    }
+}
+
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+impl RejectedRequest {
+    /// The error for a rejection `body` of a request to `hub_id52`:
+    /// `Error::HubKeyRotated` if it carries a valid rotation away from
+    /// `hub_id52`, `Error::Rejected` otherwise
+    pub fn into_error(status: u16, body: String, hub_id52: &str) -> Error {
+        match serde_json::from_str::<RejectedRequest>(&body) {
+            Ok(RejectedRequest {
+                key_rotation: Some(rotation),
+                ..
+            }) if rotation.old_id52 == hub_id52 && rotation.verify().is_ok() => Error::HubKeyRotated(Box::new(rotation)),
+            _ => Error::Rejected { status, body },
+        }
+    }
+}
+
+/// Signs requests for a client, keeping track of how far the local clock is
+/// from the server's
+#[derive(Debug, Default)]
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+struct RequestSigner {
+    /// Server time minus local time, learned from stale rejections
+    clock_offset: std::sync::atomic::AtomicI64,
+    canonicalization: Canonicalization,
+    /// Set once the hub refused a CBOR request, which then signs JCS
+    cbor_refused: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+impl RequestSigner {
+    /// Sign a request; `binary` is false for requests that can't travel as
+    /// CBOR (the push channel's handshake), which are signed JCS instead
+    fn sign<T: Serialize>(
+        &self,
+        secret_key: &SecretKey,
+        audience: &Audience,
+        payload: &T,
+        binary: bool,
+    ) -> Result<SignedRequest> {
+        let offset = self.clock_offset.load(Ordering::Relaxed);
+        let canonicalization = match self.canonicalization {
+            Canonicalization::Cbor if !binary || self.cbor_refused.load(Ordering::Relaxed) => Canonicalization::Jcs,
+            canonicalization => canonicalization,
+        };
+        SignedRequest::new_with(secret_key, audience, payload, unix_time() + offset, canonicalization)
+    }
+
+    /// Learn from a rejection that the hub doesn't read CBOR (hubs that
+    /// predate it answer 415 Unsupported Media Type). Returns true if the
+    /// request was CBOR and is worth retrying as JSON.
+    fn fall_back_from_cbor(&self, request: &SignedRequest, status: u16) -> bool {
+        if status != 415 || request.canonicalization() != Canonicalization::Cbor {
+            return false;
+        }
+        self.cbor_refused.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Learn the server's clock from a rejection body. Returns true if the
+    /// request was stale and is worth retrying.
+    fn correct_clock(&self, body: &str) -> bool {
+        match serde_json::from_str::<RejectedRequest>(body) {
+            Ok(RejectedRequest {
+                server_time: Some(server_time),
+                ..
+            }) => {
+                self.clock_offset.store(server_time - unix_time(), Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 /// A signed response envelope
@@ -227,37 +1064,62 @@ pub struct SignedResponse {
     pub responder: String,
     /// The payload (Ok or Err)
     pub payload: serde_json::Value,
+    /// How the payload is signed, `SerdeJson` if absent
+    #[serde(default = "serde_json_canonicalization", skip_serializing_if = "Canonicalization::is_serde_json")]
+    pub canonicalization: Canonicalization,
     /// Base64-encoded signature
     pub signature: String,
 }
 
 impl SignedResponse {
-    /// Create a new signed response
+    /// Create a new signed response, signed as serde_json prints it so any
+    /// client can verify it
     pub fn new<T: Serialize>(secret_key: &SecretKey, payload: &T) -> Result<Self> {
-        let responder = secret_key.id52();
-        let payload_json = serde_json::to_value(payload)?;
+        Self::new_with(secret_key, payload, Canonicalization::SerdeJson)
+    }
 
-        let message = format!("{}|{}", responder, serde_json::to_string(&payload_json)?);
-        let signature = secret_key.sign(message.as_bytes());
-        let signature_b64 = data_encoding::BASE64.encode(&signature);
+    /// Create a new signed response with the canonicalization of the request
+    /// it answers (`SignedRequest::canonicalization`)
+    pub fn new_with<T: Serialize>(
+        secret_key: &SecretKey,
+        payload: &T,
+        canonicalization: Canonicalization,
+    ) -> Result<Self> {
+        let mut response = Self {
+            responder: secret_key.id52(),
+            payload: serde_json::to_value(payload)?,
+            canonicalization,
+            signature: String::new(),
+        };
+        let message = response.message()?;
+        response.signature = data_encoding::BASE64.encode(&secret_key.sign(&message));
+        Ok(response)
+    }
 
-        Ok(Self {
-            responder,
-            payload: payload_json,
-            signature: signature_b64,
-        })
+    /// The signed message: fastn-response-jcs|responder|payload_json,
+    /// fastn-response-cbor|responder|payload_cbor, or responder|payload_json
+    /// for `SerdeJson`
+    fn message(&self) -> Result<Vec<u8>> {
+        let mut message = match self.canonicalization {
+            Canonicalization::Jcs => format!("fastn-response-jcs|{}|", self.responder),
+            Canonicalization::Cbor => format!("fastn-response-cbor|{}|", self.responder),
+            Canonicalization::SerdeJson => format!("{}|", self.responder),
+        }
+        .into_bytes();
+        message.extend(self.canonicalization.to_bytes(&self.payload)?);
+        Ok(message)
     }
 
     /// Verify the signature and extract the payload
     pub fn verify<T: DeserializeOwned>(&self) -> Result<(String, T)> {
         let public_key = from_id52(&self.responder)?;
-        let message = format!("{}|{}", self.responder, serde_json::to_string(&self.payload)?);
+        let message = self.message()?;
 
         let signature = data_encoding::BASE64
             .decode(self.signature.as_bytes())
             .map_err(|e| Error::Base64Decode(e.to_string()))?;
 
-        public_key.verify(message.as_bytes(), &signature)?;
+        public_key.verify(&message, &signature)?;
 
         let payload: T = serde_json::from_value(self.payload.clone())?;
         Ok((self.responder.clone(), payload))
@@ -291,17 +1153,903 @@ impl<T, E> ResponseEnvelope<T, E> {
 }
 
 // ============================================================================
-// Hub Protocol Types (used by both hub and spoke)
+// Key Files
 // ============================================================================
 
-/// Request envelope from spokes to hub
-/// Hub routes based on (app, instance) and does ACL check before forwarding
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HubRequest {
-    /// Target hub alias: "self" for local hub, or alias of a remote hub
-    /// If not specified, defaults to "self"
-    #[serde(default = "default_target_hub")]
-    pub target_hub: String,
+/// Secret key files, plain or protected
+///
+/// A key file is either the key's raw 32 bytes, as every key file was
+/// before protection, or JSON naming the key's ID52 and where the key is:
+/// sealed in the file with a passphrase (Argon2id, then ChaCha20-Poly1305
+/// with the ID52 as associated data), or in the OS keychain (feature
+/// `keychain`) under `KEYCHAIN_SERVICE` with the ID52 as account. Raw files
+/// keep working; `write` over them with another `Protection` to migrate.
+///
+/// Files are written next to their path and renamed over it, readable by
+/// their owner only.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod key_file {
+    use super::*;
+    use std::path::Path;
+
+    /// Environment variable holding the passphrase of encrypted key files
+    pub const PASSPHRASE_ENV: &str = "FASTN_KEY_PASSPHRASE";
+
+    /// Keychain service keys are stored under
+    pub const KEYCHAIN_SERVICE: &str = "fastn";
+
+    /// Version of the key files this build writes, and the newest it reads
+    pub const KEY_FILE_VERSION: u32 = 1;
+
+    /// How a key is stored
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Protection {
+        /// The raw bytes, in the file
+        Plain,
+        /// Encrypted with this passphrase, in the file
+        Passphrase(String),
+        /// In the OS keychain, the file only naming it
+        Keychain,
+    }
+
+    impl Protection {
+        /// `plain`, `passphrase` or `keychain`
+        pub fn name(&self) -> &'static str {
+            match self {
+                Protection::Plain => "plain",
+                Protection::Passphrase(_) => "passphrase",
+                Protection::Keychain => "keychain",
+            }
+        }
+    }
+
+    /// A protected key file
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Record {
+        version: u32,
+        id52: String,
+        #[serde(flatten)]
+        storage: Storage,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "storage", rename_all = "snake_case")]
+    enum Storage {
+        Passphrase {
+            kdf: Kdf,
+            /// Base64
+            nonce: String,
+            /// Base64 of the sealed key and its tag
+            ciphertext: String,
+        },
+        Keychain { service: String },
+    }
+
+    /// Argon2id parameters, kept with the key so they can change
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Kdf {
+        /// Base64
+        salt: String,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    }
+
+    /// The passphrase in `PASSPHRASE_ENV`, if set
+    pub fn env_passphrase() -> Option<String> {
+        std::env::var(PASSPHRASE_ENV).ok().filter(|passphrase| !passphrase.is_empty())
+    }
+
+    /// Read the key at `path`, and how it is stored; `passphrase` is only
+    /// needed if it is encrypted
+    pub fn read(path: &Path, passphrase: Option<&str>) -> Result<(SecretKey, Protection)> {
+        let bytes = std::fs::read(path)?;
+        if let Ok(raw) = <[u8; 32]>::try_from(bytes.as_slice()) {
+            return Ok((SecretKey::from_bytes(&raw), Protection::Plain));
+        }
+        let invalid = |reason: String| Error::InvalidKeyFile(format!("{}: {}", path.display(), reason));
+        let record: Record = serde_json::from_slice(&bytes)
+            .map_err(|_| invalid("expected 32 bytes or a protected key".to_string()))?;
+        if record.version > KEY_FILE_VERSION {
+            return Err(invalid(format!("version {} is newer than {}", record.version, KEY_FILE_VERSION)));
+        }
+
+        let (raw, protection) = match record.storage {
+            Storage::Passphrase { kdf, nonce, ciphertext } => {
+                let passphrase = passphrase.ok_or_else(|| Error::PassphraseRequired(path.display().to_string()))?;
+                let decode = |field: &str| {
+                    data_encoding::BASE64
+                        .decode(field.as_bytes())
+                        .map_err(|e| Error::Base64Decode(e.to_string()))
+                };
+                let cipher = cipher(passphrase, &decode(&kdf.salt)?, &kdf)?;
+                let nonce = decode(&nonce)?;
+                if nonce.len() != 12 {
+                    return Err(invalid(format!("expected a 12 byte nonce, got {}", nonce.len())));
+                }
+                let payload = chacha20poly1305::aead::Payload {
+                    msg: &decode(&ciphertext)?,
+                    aad: record.id52.as_bytes(),
+                };
+                let raw = chacha20poly1305::aead::Aead::decrypt(&cipher, nonce.as_slice().into(), payload)
+                    .map_err(|_| Error::WrongPassphrase(path.display().to_string()))?;
+                (raw, Protection::Passphrase(passphrase.to_string()))
+            }
+            Storage::Keychain { service } => (keychain::get(&service, &record.id52)?, Protection::Keychain),
+        };
+        let raw: [u8; 32] = raw
+            .try_into()
+            .map_err(|_| invalid("the stored key is not 32 bytes".to_string()))?;
+        let key = SecretKey::from_bytes(&raw);
+        if key.id52() != record.id52 {
+            return Err(invalid(format!("the stored key is not {}", record.id52)));
+        }
+        Ok((key, protection))
+    }
+
+    /// How the key at `path` is stored, without unlocking it
+    ///
+    /// A passphrase-protected key comes back with an empty passphrase.
+    pub fn protection(path: &Path) -> Result<Protection> {
+        let bytes = std::fs::read(path)?;
+        if bytes.len() == 32 {
+            return Ok(Protection::Plain);
+        }
+        let record: Record = serde_json::from_slice(&bytes)
+            .map_err(|_| Error::InvalidKeyFile(format!("{}: expected 32 bytes or a protected key", path.display())))?;
+        Ok(match record.storage {
+            Storage::Passphrase { .. } => Protection::Passphrase(String::new()),
+            Storage::Keychain { .. } => Protection::Keychain,
+        })
+    }
+
+    /// Write `key` to `path`, replacing whatever is there
+    ///
+    /// Protected with `Keychain`, the key is added to the keychain first. A
+    /// key the file held in the keychain before stays there; `forget` it
+    /// once nothing needs it.
+    pub fn write(path: &Path, key: &SecretKey, protection: &Protection) -> Result<()> {
+        let id52 = key.id52();
+        let storage = match protection {
+            Protection::Plain => None,
+            Protection::Passphrase(passphrase) => {
+                if passphrase.is_empty() {
+                    return Err(Error::InvalidKeyFile("the passphrase is empty".to_string()));
+                }
+                let params = argon2::Params::DEFAULT;
+                let salt = rand::random::<[u8; 16]>();
+                let kdf = Kdf {
+                    salt: data_encoding::BASE64.encode(&salt),
+                    m_cost: params.m_cost(),
+                    t_cost: params.t_cost(),
+                    p_cost: params.p_cost(),
+                };
+                let cipher = cipher(passphrase, &salt, &kdf)?;
+                let nonce = rand::random::<[u8; 12]>();
+                let payload = chacha20poly1305::aead::Payload {
+                    msg: &key.to_bytes(),
+                    aad: id52.as_bytes(),
+                };
+                let ciphertext = chacha20poly1305::aead::Aead::encrypt(&cipher, (&nonce).into(), payload)
+                    .map_err(|e| Error::InvalidKeyFile(e.to_string()))?;
+                Some(Storage::Passphrase {
+                    kdf,
+                    nonce: data_encoding::BASE64.encode(&nonce),
+                    ciphertext: data_encoding::BASE64.encode(&ciphertext),
+                })
+            }
+            Protection::Keychain => {
+                keychain::set(KEYCHAIN_SERVICE, &id52, &key.to_bytes())?;
+                Some(Storage::Keychain {
+                    service: KEYCHAIN_SERVICE.to_string(),
+                })
+            }
+        };
+        let contents = match storage {
+            None => key.to_bytes().to_vec(),
+            Some(storage) => serde_json::to_vec_pretty(&Record {
+                version: KEY_FILE_VERSION,
+                id52,
+                storage,
+            })?,
+        };
+
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        let tmp = std::path::PathBuf::from(tmp);
+        let written = (|| -> std::io::Result<()> {
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(&tmp)?;
+            std::io::Write::write_all(&mut file, &contents)?;
+            file.sync_all()?;
+            std::fs::rename(&tmp, path)
+        })();
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        Ok(written?)
+    }
+
+    /// Remove `id52`'s key from the keychain, if it is there
+    pub fn forget(id52: &str) -> Result<()> {
+        keychain::delete(KEYCHAIN_SERVICE, id52)
+    }
+
+    fn cipher(passphrase: &str, salt: &[u8], kdf: &Kdf) -> Result<chacha20poly1305::ChaCha20Poly1305> {
+        let params = argon2::Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(32))
+            .map_err(|e| Error::InvalidKeyFile(e.to_string()))?;
+        let mut key = [0u8; 32];
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| Error::InvalidKeyFile(e.to_string()))?;
+        Ok(<chacha20poly1305::ChaCha20Poly1305 as chacha20poly1305::KeyInit>::new(&key.into()))
+    }
+
+    #[cfg(feature = "keychain")]
+    mod keychain {
+        use crate::{Error, Result};
+
+        fn entry(service: &str, account: &str) -> Result<keyring::Entry> {
+            keyring::Entry::new(service, account).map_err(|e| Error::Keychain(e.to_string()))
+        }
+
+        pub fn get(service: &str, account: &str) -> Result<Vec<u8>> {
+            entry(service, account)?
+                .get_secret()
+                .map_err(|e| Error::Keychain(format!("{} ({}): {}", account, service, e)))
+        }
+
+        pub fn set(service: &str, account: &str, secret: &[u8]) -> Result<()> {
+            entry(service, account)?
+                .set_secret(secret)
+                .map_err(|e| Error::Keychain(e.to_string()))
+        }
+
+        pub fn delete(service: &str, account: &str) -> Result<()> {
+            match entry(service, account)?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                Err(e) => Err(Error::Keychain(e.to_string())),
+            }
+        }
+    }
+
+    /// Keychain-protected keys need the `keychain` feature
+    #[cfg(not(feature = "keychain"))]
+    mod keychain {
+        use crate::{Error, Result};
+
+        const UNSUPPORTED: &str = "this build has no keychain support (feature \"keychain\")";
+
+        pub fn get(_service: &str, _account: &str) -> Result<Vec<u8>> {
+            Err(Error::Keychain(UNSUPPORTED.to_string()))
+        }
+
+        pub fn set(_service: &str, _account: &str, _secret: &[u8]) -> Result<()> {
+            Err(Error::Keychain(UNSUPPORTED.to_string()))
+        }
+
+        pub fn delete(_service: &str, _account: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+}
+
+// ============================================================================
+// Canonical JSON (RFC 8785)
+// ============================================================================
+
+/// The JSON Canonicalization Scheme (RFC 8785), used to sign payloads
+///
+/// No whitespace, object keys sorted by their UTF-16 code units, strings
+/// escaping only `"`, `\` and control characters, and numbers printed as
+/// ECMAScript's `Number.prototype.toString` prints IEEE doubles. Integers a
+/// double can't hold exactly (beyond 2^53, mostly) are rejected rather than
+/// rounded: two payloads must not share a signature. Send them as strings.
+pub mod canonical_json {
+    use super::*;
+    use serde_json::Value;
+    use std::fmt::Write;
+
+    /// The canonical form of `value`
+    pub fn to_string(value: &Value) -> Result<String> {
+        let mut out = String::new();
+        write_value(&mut out, value)?;
+        Ok(out)
+    }
+
+    fn write_value(out: &mut String, value: &Value) -> Result<()> {
+        match value {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Number(n) => write_number(out, n)?,
+            Value::String(s) => write_string(out, s),
+            Value::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_value(out, item)?;
+                }
+                out.push(']');
+            }
+            Value::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+                out.push('{');
+                for (i, (key, value)) in entries.into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(out, key);
+                    out.push(':');
+                    write_value(out, value)?;
+                }
+                out.push('}');
+            }
+        }
+        Ok(())
+    }
+
+    fn write_number(out: &mut String, n: &serde_json::Number) -> Result<()> {
+        let exact = match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => (i as f64) as i128 == i128::from(i),
+            (_, Some(u)) => (u as f64) as i128 == i128::from(u),
+            _ => true,
+        };
+        match n.as_f64() {
+            Some(f) if exact && f.is_finite() => {
+                write_double(out, f);
+                Ok(())
+            }
+            _ => Err(Error::CanonicalJson(format!("{} is not exactly an IEEE double", n))),
+        }
+    }
+
+    /// ECMAScript's `Number.prototype.toString` of a finite double
+    fn write_double(out: &mut String, f: f64) {
+        if f == 0.0 {
+            // Negative zero too
+            out.push('0');
+            return;
+        }
+        if f < 0.0 {
+            out.push('-');
+        }
+        // Rust prints the shortest digits that read back as `f`, closest to
+        // it on ties, as ECMAScript does; only the layout differs
+        let (mut digits, exponent) = scientific_digits(&format!("{:e}", f.abs()));
+        // f = 0.digits * 10^n
+        let n = exponent + 1;
+        // When two shortest digit strings are as close to `f`, ECMAScript
+        // takes the even one where Rust rounds up (to an odd one)
+        if digits.as_bytes()[digits.len() - 1] % 2 == 1 {
+            let (exact, exact_exponent) = scientific_digits(&format!("{:.800e}", f.abs()));
+            let exact = exact.trim_end_matches('0');
+            if exact_exponent == exponent && exact.len() == digits.len() + 1 && exact.ends_with('5') {
+                let down = &exact[..digits.len()];
+                if format!("0.{}e{}", down, n).parse::<f64>() == Ok(f.abs()) {
+                    digits = down.to_string();
+                }
+            }
+        }
+        let k = digits.len() as i32;
+        if k <= n && n <= 21 {
+            out.push_str(&digits);
+            out.extend(std::iter::repeat_n('0', (n - k) as usize));
+        } else if 0 < n && n <= 21 {
+            out.push_str(&digits[..n as usize]);
+            out.push('.');
+            out.push_str(&digits[n as usize..]);
+        } else if -6 < n && n <= 0 {
+            out.push_str("0.");
+            out.extend(std::iter::repeat_n('0', -n as usize));
+            out.push_str(&digits);
+        } else {
+            out.push_str(&digits[..1]);
+            if k > 1 {
+                out.push('.');
+                out.push_str(&digits[1..]);
+            }
+            let _ = write!(out, "e{}{}", if n > 0 { '+' } else { '-' }, (n - 1).abs());
+        }
+    }
+
+    /// Digits and exponent of Rust's `{:e}` output
+    fn scientific_digits(scientific: &str) -> (String, i32) {
+        let (mantissa, exponent) = scientific.split_once('e').unwrap();
+        (mantissa.replace('.', ""), exponent.parse().unwrap())
+    }
+
+    fn write_string(out: &mut String, s: &str) {
+        out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\u{08}' => out.push_str("\\b"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                '\u{0c}' => out.push_str("\\f"),
+                '\r' => out.push_str("\\r"),
+                c if c < ' ' => {
+                    let _ = write!(out, "\\u{:04x}", c as u32);
+                }
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+}
+
+// ============================================================================
+// Canonical CBOR
+// ============================================================================
+
+/// Canonical CBOR (RFC 8949), the binary alternative to JSON payloads
+///
+/// Encodes the JSON data model deterministically, so it can be signed:
+/// definite lengths, the shortest argument for every length and integer,
+/// map keys sorted by their encoded bytes, floats always as doubles. Strings
+/// of at least `MIN_BASE64_LEN` characters that are canonical padded base64
+/// (file contents, mostly) are sent as the bytes they encode, under tag 22
+/// ("expected base64"), and decode back to the same string. Decoding only
+/// accepts what the JSON data model can hold, so a payload decodes to the
+/// value it was signed as.
+pub mod cbor {
+    use super::*;
+    use serde_json::{Map, Number, Value};
+
+    /// Tag for byte strings that stand for their base64 text
+    const BASE64_TAG: u64 = 22;
+
+    /// Shorter strings stay text, even if they happen to be base64
+    pub const MIN_BASE64_LEN: usize = 16;
+
+    /// Deepest nesting `from_slice` accepts
+    const MAX_DEPTH: usize = 128;
+
+    /// Canonical CBOR of `value`
+    pub fn to_vec(value: &Value) -> Vec<u8> {
+        let mut out = vec![];
+        write_value(&mut out, value);
+        out
+    }
+
+    /// Canonical CBOR of anything serializable
+    pub fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+        Ok(to_vec(&serde_json::to_value(value)?))
+    }
+
+    /// Decode one CBOR item spanning all of `bytes`
+    pub fn from_slice(bytes: &[u8]) -> Result<Value> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let value = reader.value(0)?;
+        if reader.pos != bytes.len() {
+            return Err(Error::Cbor("trailing bytes".to_string()));
+        }
+        Ok(value)
+    }
+
+    /// Decode CBOR into anything deserializable
+    pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        Ok(serde_json::from_value(from_slice(bytes)?)?)
+    }
+
+    fn write_head(out: &mut Vec<u8>, major: u8, n: u64) {
+        let major = major << 5;
+        match n {
+            0..24 => out.push(major | n as u8),
+            24..=0xff => out.extend([major | 24, n as u8]),
+            0x100..=0xffff => {
+                out.push(major | 25);
+                out.extend((n as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                out.push(major | 26);
+                out.extend((n as u32).to_be_bytes());
+            }
+            _ => {
+                out.push(major | 27);
+                out.extend(n.to_be_bytes());
+            }
+        }
+    }
+
+    fn write_value(out: &mut Vec<u8>, value: &Value) {
+        match value {
+            Value::Null => out.push(0xf6),
+            Value::Bool(false) => out.push(0xf4),
+            Value::Bool(true) => out.push(0xf5),
+            Value::Number(n) => write_number(out, n),
+            Value::String(s) => write_string(out, s),
+            Value::Array(items) => {
+                write_head(out, 4, items.len() as u64);
+                for item in items {
+                    write_value(out, item);
+                }
+            }
+            Value::Object(map) => {
+                let mut entries: Vec<(Vec<u8>, &Value)> = map
+                    .iter()
+                    .map(|(key, value)| {
+                        let mut encoded = vec![];
+                        write_text(&mut encoded, key);
+                        (encoded, value)
+                    })
+                    .collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                write_head(out, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    out.extend(key);
+                    write_value(out, value);
+                }
+            }
+        }
+    }
+
+    fn write_number(out: &mut Vec<u8>, n: &Number) {
+        if let Some(n) = n.as_u64() {
+            write_head(out, 0, n);
+        } else if let Some(n) = n.as_i64() {
+            // Negative: major type 1 holds -1 - n
+            write_head(out, 1, !n as u64);
+        } else {
+            out.push(0xfb);
+            out.extend(n.as_f64().unwrap_or_default().to_be_bytes());
+        }
+    }
+
+    fn write_text(out: &mut Vec<u8>, s: &str) {
+        write_head(out, 3, s.len() as u64);
+        out.extend(s.as_bytes());
+    }
+
+    fn write_string(out: &mut Vec<u8>, s: &str) {
+        match base64_bytes(s) {
+            Some(bytes) => {
+                write_head(out, 6, BASE64_TAG);
+                write_head(out, 2, bytes.len() as u64);
+                out.extend(bytes);
+            }
+            None => write_text(out, s),
+        }
+    }
+
+    /// The bytes `s` encodes, if it is long enough and exactly what
+    /// encoding them gives back
+    fn base64_bytes(s: &str) -> Option<Vec<u8>> {
+        if s.len() < MIN_BASE64_LEN || !s.len().is_multiple_of(4) {
+            return None;
+        }
+        let bytes = data_encoding::BASE64.decode(s.as_bytes()).ok()?;
+        (data_encoding::BASE64.encode(&bytes) == s).then_some(bytes)
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+            let end = self.pos.checked_add(n).filter(|end| *end <= self.bytes.len());
+            let end = end.ok_or_else(|| Error::Cbor("unexpected end of input".to_string()))?;
+            let taken = &self.bytes[self.pos..end];
+            self.pos = end;
+            Ok(taken)
+        }
+
+        fn remaining(&self) -> usize {
+            self.bytes.len() - self.pos
+        }
+
+        /// Major type, additional information and argument of the next item
+        fn head(&mut self) -> Result<(u8, u8, u64)> {
+            let initial = self.take(1)?[0];
+            let (major, info) = (initial >> 5, initial & 0x1f);
+            let n = match info {
+                0..24 => info as u64,
+                24 => self.take(1)?[0] as u64,
+                25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap_or_default()) as u64,
+                26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap_or_default()) as u64,
+                27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap_or_default()),
+                _ => return Err(Error::Cbor(format!("unsupported initial byte {:#04x}", initial))),
+            };
+            Ok((major, info, n))
+        }
+
+        fn length(&self, n: u64) -> Result<usize> {
+            usize::try_from(n)
+                .ok()
+                .filter(|n| *n <= self.remaining())
+                .ok_or_else(|| Error::Cbor("length exceeds input".to_string()))
+        }
+
+        fn value(&mut self, depth: usize) -> Result<Value> {
+            if depth > MAX_DEPTH {
+                return Err(Error::Cbor("nested too deeply".to_string()));
+            }
+            let (major, info, n) = self.head()?;
+            match major {
+                0 => Ok(Value::from(n)),
+                1 => i64::try_from(n)
+                    .map(|n| Value::from(!n))
+                    .map_err(|_| Error::Cbor("negative integer out of range".to_string())),
+                2 => {
+                    let len = self.length(n)?;
+                    Ok(Value::String(data_encoding::BASE64.encode(self.take(len)?)))
+                }
+                3 => Ok(Value::String(self.text(n)?)),
+                4 => {
+                    // Every item takes at least a byte
+                    let len = self.length(n)?;
+                    let mut items = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        items.push(self.value(depth + 1)?);
+                    }
+                    Ok(Value::Array(items))
+                }
+                5 => {
+                    let len = self.length(n)?;
+                    let mut map = Map::new();
+                    for _ in 0..len {
+                        let key = match self.head()? {
+                            (3, _, n) => self.text(n)?,
+                            _ => return Err(Error::Cbor("map keys must be text".to_string())),
+                        };
+                        let value = self.value(depth + 1)?;
+                        if map.insert(key.clone(), value).is_some() {
+                            return Err(Error::Cbor(format!("duplicate key {:?}", key)));
+                        }
+                    }
+                    Ok(Value::Object(map))
+                }
+                6 if n == BASE64_TAG => match self.head()? {
+                    (2, _, n) => {
+                        let len = self.length(n)?;
+                        Ok(Value::String(data_encoding::BASE64.encode(self.take(len)?)))
+                    }
+                    _ => Err(Error::Cbor("tag 22 must hold bytes".to_string())),
+                },
+                6 => Err(Error::Cbor(format!("unsupported tag {}", n))),
+                _ => match info {
+                    20 => Ok(Value::Bool(false)),
+                    21 => Ok(Value::Bool(true)),
+                    22 => Ok(Value::Null),
+                    26 => float(f32::from_bits(n as u32) as f64),
+                    27 => float(f64::from_bits(n)),
+                    _ => Err(Error::Cbor(format!("unsupported simple value {}", info))),
+                },
+            }
+        }
+
+        fn text(&mut self, n: u64) -> Result<String> {
+            let len = self.length(n)?;
+            String::from_utf8(self.take(len)?.to_vec()).map_err(|e| Error::Cbor(e.to_string()))
+        }
+    }
+
+    /// JSON has no NaN or infinities
+    fn float(f: f64) -> Result<Value> {
+        Number::from_f64(f)
+            .map(Value::Number)
+            .ok_or_else(|| Error::Cbor("non-finite float".to_string()))
+    }
+}
+
+// ============================================================================
+// Wire Format and Compression
+// ============================================================================
+
+/// How envelopes travel over HTTP: JSON or CBOR bodies, optionally
+/// compressed
+///
+/// The request's Content-Type says its format, and the hub answers in the
+/// same one. Clients send CBOR only with requests signed as CBOR (version
+/// 4), so the format always matches the signature scheme.
+///
+/// Clients list the encodings they read in Accept-Encoding, and hubs
+/// compress responses of `COMPRESSION_THRESHOLD` bytes or more with the
+/// best of them. Hubs list the encodings they read in the Accept-Encoding of
+/// their responses (RFC 7694); clients compress requests only once they've
+/// seen it, so hubs that don't read compressed bodies never get one.
+/// Decompressed bodies are capped at `MAX_DECOMPRESSED_SIZE`.
+pub mod wire {
+    use super::*;
+    use std::io::{Read, Write};
+
+    pub const JSON: &str = "application/json";
+    pub const CBOR: &str = "application/cbor";
+
+    /// Bodies smaller than this are sent as they are
+    pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+    /// Largest body a compressed one may expand to; a request is at most a
+    /// `MAX_CHUNK_SIZE` chunk and its envelope
+    pub const MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+    /// Encodings this side reads, best first, for Accept-Encoding
+    #[cfg(not(target_arch = "wasm32"))]
+    pub const ACCEPTED_ENCODINGS: &str = "zstd, gzip";
+
+    /// Encodings this side reads, best first, for Accept-Encoding
+    #[cfg(target_arch = "wasm32")]
+    pub const ACCEPTED_ENCODINGS: &str = "gzip";
+
+    /// Serialization of an envelope
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum Format {
+        #[default]
+        Json,
+        Cbor,
+    }
+
+    impl Format {
+        /// The format to send requests signed with `canonicalization` in
+        pub fn of(canonicalization: Canonicalization) -> Self {
+            match canonicalization {
+                Canonicalization::Cbor => Format::Cbor,
+                Canonicalization::Jcs | Canonicalization::SerdeJson => Format::Json,
+            }
+        }
+
+        pub fn content_type(self) -> &'static str {
+            match self {
+                Format::Json => JSON,
+                Format::Cbor => CBOR,
+            }
+        }
+
+        /// The format a Content-Type names; none at all is JSON
+        pub fn from_content_type(content_type: Option<&str>) -> Result<Self> {
+            let Some(content_type) = content_type else {
+                return Ok(Format::Json);
+            };
+            let essence = content_type.split(';').next().unwrap_or_default().trim();
+            if essence.eq_ignore_ascii_case(JSON) {
+                Ok(Format::Json)
+            } else if essence.eq_ignore_ascii_case(CBOR) {
+                Ok(Format::Cbor)
+            } else {
+                Err(Error::UnsupportedContentType(content_type.to_string()))
+            }
+        }
+
+        pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+            match self {
+                Format::Json => Ok(serde_json::to_vec(value)?),
+                Format::Cbor => cbor::encode(value),
+            }
+        }
+
+        pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T> {
+            match self {
+                Format::Json => Ok(serde_json::from_slice(bytes)?),
+                Format::Cbor => cbor::decode(bytes),
+            }
+        }
+    }
+
+    /// A body's Content-Encoding
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum Encoding {
+        #[default]
+        Identity,
+        Gzip,
+        /// Not on wasm, where browsers decompress responses themselves
+        Zstd,
+    }
+
+    impl Encoding {
+        pub fn name(self) -> &'static str {
+            match self {
+                Encoding::Identity => "identity",
+                Encoding::Gzip => "gzip",
+                Encoding::Zstd => "zstd",
+            }
+        }
+
+        /// The encoding a Content-Encoding names; none at all is identity
+        pub fn from_header(content_encoding: Option<&str>) -> Result<Self> {
+            match content_encoding.map(|e| e.trim().to_ascii_lowercase()).as_deref() {
+                None | Some("identity") => Ok(Encoding::Identity),
+                Some("gzip" | "x-gzip") => Ok(Encoding::Gzip),
+                #[cfg(not(target_arch = "wasm32"))]
+                Some("zstd") => Ok(Encoding::Zstd),
+                Some(other) => Err(Error::UnsupportedEncoding(other.to_string())),
+            }
+        }
+
+        /// The best encoding in an Accept-Encoding that this side writes,
+        /// identity if none
+        pub fn negotiate(accept_encoding: Option<&str>) -> Self {
+            let accepted: Vec<&str> = accept_encoding
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|item| {
+                    let mut parts = item.split(';').map(str::trim);
+                    let name = parts.next()?;
+                    let refused = parts.any(|param| matches!(param, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+                    (!name.is_empty() && !refused).then_some(name)
+                })
+                .collect();
+            let offers = |name: &str| accepted.iter().any(|a| a.eq_ignore_ascii_case(name));
+            if cfg!(not(target_arch = "wasm32")) && offers("zstd") {
+                Encoding::Zstd
+            } else if offers("gzip") {
+                Encoding::Gzip
+            } else {
+                Encoding::Identity
+            }
+        }
+
+        /// This encoding for a body of `len` bytes, identity if it is too
+        /// small to be worth compressing
+        pub fn for_body(self, len: usize) -> Self {
+            if len < COMPRESSION_THRESHOLD {
+                Encoding::Identity
+            } else {
+                self
+            }
+        }
+
+        pub fn compress(self, body: Vec<u8>) -> Result<Vec<u8>> {
+            let compressed = match self {
+                Encoding::Identity => return Ok(body),
+                Encoding::Gzip => {
+                    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                    encoder.write_all(&body).and_then(|()| encoder.finish())
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                Encoding::Zstd => zstd::encode_all(body.as_slice(), 0),
+                #[cfg(target_arch = "wasm32")]
+                Encoding::Zstd => return Err(Error::UnsupportedEncoding("zstd".to_string())),
+            };
+            compressed.map_err(|e| Error::Compression(e.to_string()))
+        }
+
+        /// Decompress a body, failing if it expands beyond
+        /// `MAX_DECOMPRESSED_SIZE`
+        pub fn decompress(self, body: Vec<u8>) -> Result<Vec<u8>> {
+            let reader: Box<dyn Read + '_> = match self {
+                Encoding::Identity => return Ok(body),
+                Encoding::Gzip => Box::new(flate2::read::GzDecoder::new(body.as_slice())),
+                #[cfg(not(target_arch = "wasm32"))]
+                Encoding::Zstd => {
+                    Box::new(zstd::Decoder::new(body.as_slice()).map_err(|e| Error::Compression(e.to_string()))?)
+                }
+                #[cfg(target_arch = "wasm32")]
+                Encoding::Zstd => return Err(Error::UnsupportedEncoding("zstd".to_string())),
+            };
+            let mut out = vec![];
+            reader
+                .take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+                .read_to_end(&mut out)
+                .map_err(|e| Error::Compression(e.to_string()))?;
+            if out.len() > MAX_DECOMPRESSED_SIZE {
+                return Err(Error::Compression(format!("body expands beyond {} bytes", MAX_DECOMPRESSED_SIZE)));
+            }
+            Ok(out)
+        }
+    }
+}
+
+// ============================================================================
+// Hub Protocol Types (used by both hub and spoke)
+// ============================================================================
+
+/// Request envelope from spokes to hub
+/// Hub routes based on (app, instance) and does ACL check before forwarding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HubRequest {
+    /// Target hub alias: "self" for local hub, or alias of a remote hub
+    /// If not specified, defaults to "self"
+    #[serde(default = "default_target_hub")]
+    pub target_hub: String,
     /// Application type (e.g., "kosha", "chat", "sync")
     pub app: String,
     /// Application instance (e.g., "my-kosha", "work-chat")
@@ -310,32 +2058,604 @@ pub struct HubRequest {
     pub command: String,
     /// Application-specific payload (JSON)
     pub payload: serde_json::Value,
+    /// Ask the hub to explain ACL decisions (owner-only, ignored for others)
+    ///
+    /// The hub evaluates the ACL as it would for a non-owner and, if access
+    /// would be denied, returns `AccessDenied` with the evaluation trace.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub explain: bool,
+}
+
+fn default_target_hub() -> String {
+    "self".to_string()
+}
+
+/// Response envelope from hub to spokes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HubResponse {
+    /// Application-specific response payload (JSON)
+    pub payload: serde_json::Value,
+}
+
+/// Outcome of consulting a single ACL module
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AclDecision {
+    Allowed,
+    Denied,
+    /// Module file not present - level skipped
+    NoModule,
+}
+
+/// One ACL file consulted during access evaluation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AclTraceStep {
+    /// Alias of the kosha the module was looked up in
+    pub kosha: String,
+    /// Path of the module within the kosha (e.g., "kosha/_read.wasm")
+    pub file: String,
+    pub decision: AclDecision,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Full ACL evaluation trace, in the order modules were consulted
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AclTrace {
+    pub steps: Vec<AclTraceStep>,
+    /// Final decision (None while evaluation is in progress)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decision: Option<AclDecision>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Hub-level errors (before reaching application)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HubError {
+    /// Spoke not authorized for this hub
+    Unauthorized,
+    /// Spoke not authorized for this (app, instance)
+    ///
+    /// `trace` is only present when the owner asked for an explanation.
+    AccessDenied {
+        app: String,
+        instance: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trace: Option<AclTrace>,
+    },
+    /// Application type not registered
+    AppNotFound { app: String },
+    /// Application instance not found
+    InstanceNotFound { app: String, instance: String },
+    /// Application returned an error
+    AppError { message: String },
+    /// Write rejected because the target changed since the caller's base version
+    ///
+    /// `current` is app-specific metadata about the latest version (for kosha
+    /// files: `{ timestamp, size }`, or absent if the file was deleted).
+    Conflict {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        current: Option<serde_json::Value>,
+    },
+    /// The sender made too many requests, or a write would take a kosha
+    /// past its storage quota
+    ///
+    /// `retry_after_ms` is set for rate limits: how long until the next
+    /// request is accepted.
+    QuotaExceeded {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry_after_ms: Option<u64>,
+    },
+}
+
+// ============================================================================
+// Push Notifications (hub to spoke, over WebSocket)
+// ============================================================================
+
+/// What a subscriber wants to hear about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "topic", rename_all = "snake_case")]
+pub enum Topic {
+    /// Files and key-value entries changing in a kosha
+    Kosha { instance: String },
+    /// Files changing under a path in a kosha, with their versions
+    /// (`PushEvent::FileChange`); an empty prefix follows the whole kosha
+    Files {
+        instance: String,
+        #[serde(default)]
+        path_prefix: String,
+    },
+    /// Spokes being authorized on the hub (owner only)
+    Spokes,
+}
+
+/// Subscription handshake payload
+///
+/// Sent as the first WebSocket message on `WS_ENDPOINT`, as a `SignedRequest`
+/// addressed to `Audience::new(hub_id52, WS_ENDPOINT)`. The hub answers with
+/// a `SignedResponse` of `ResponseEnvelope<(), HubError>`, then sends each
+/// matching `PushEvent` as a `SignedResponse`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscribe {
+    pub topics: Vec<Topic>,
+}
+
+/// Event pushed by the hub to subscribed spokes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PushEvent {
+    /// A file was written, renamed (both paths) or deleted
+    FileChanged { kosha: String, path: String },
+    /// A key-value entry was set, deleted or merged
+    KvUpdated { kosha: String, key: String },
+    /// A spoke was added to the hub
+    SpokeAuthorized { spoke_id52: String, alias: String },
+    /// A file changed under a followed prefix (`Topic::Files`)
+    FileChange { kosha: String, change: FileChange },
+    /// The subscriber fell behind and `count` events were dropped; re-read
+    /// whatever it keeps in sync
+    Missed { count: u64 },
+}
+
+/// How a file changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FileChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    /// Moved here from `from`, history included
+    Renamed { from: String },
+}
+
+/// A change to one file, as reported by the kosha
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChange {
+    /// Path of the file (the new path for renames)
+    pub path: String,
+    #[serde(flatten)]
+    pub kind: FileChangeKind,
+    /// Version timestamp (RFC 3339) written, or for deletes the one removed;
+    /// usable as a `base_version`
+    pub version: String,
+    pub size: u64,
+}
+
+impl FileChange {
+    /// Whether the change touches anything under `prefix`, either side of a
+    /// rename included
+    pub fn is_under(&self, prefix: &str) -> bool {
+        let prefix = prefix.trim_matches('/');
+        let under = |path: &str| {
+            prefix.is_empty()
+                || path
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        under(&self.path) || matches!(&self.kind, FileChangeKind::Renamed { from } if under(from))
+    }
+}
+
+impl PushEvent {
+    /// Whether a subscriber to `topics` gets this event
+    pub fn matches(&self, topics: &[Topic]) -> bool {
+        match self {
+            PushEvent::FileChanged { kosha, .. } | PushEvent::KvUpdated { kosha, .. } => topics
+                .iter()
+                .any(|topic| matches!(topic, Topic::Kosha { instance } if instance == kosha)),
+            PushEvent::FileChange { kosha, change } => topics.iter().any(|topic| {
+                matches!(topic, Topic::Files { instance, path_prefix } if instance == kosha && change.is_under(path_prefix))
+            }),
+            PushEvent::SpokeAuthorized { .. } => topics.contains(&Topic::Spokes),
+            PushEvent::Missed { .. } => true,
+        }
+    }
+}
+
+// ============================================================================
+// Chunked Transfer (kosha commands for large files)
+// ============================================================================
+
+/// Largest chunk (raw bytes) a kosha accepts in `upload_chunk` or returns
+/// from `read_range`; base64 keeps a chunk well inside the request size limit
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// `begin_upload` payload: announce a file of `size` bytes
+///
+/// Beginning again with the same fields resumes an interrupted upload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BeginUpload {
+    pub path: String,
+    pub size: u64,
+    /// SHA-256 of the whole file (lowercase hex), checked on commit
+    pub sha256: String,
+    /// Only commit if the file is still at this version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_version: Option<String>,
+}
+
+/// `upload_chunk` payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadChunk {
+    pub upload_id: String,
+    /// File being uploaded, so the hub can check access
+    pub path: String,
+    /// Must equal `UploadStatus::received`
+    pub offset: u64,
+    /// Base64, at most `MAX_CHUNK_SIZE` bytes once decoded
+    pub content: String,
+}
+
+/// `commit_upload` and `abort_upload` payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinishUpload {
+    pub upload_id: String,
+    pub path: String,
+}
+
+/// Response to `begin_upload` and `upload_chunk`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadStatus {
+    pub upload_id: String,
+    /// Bytes stored so far; the next chunk starts here
+    pub received: u64,
+    pub size: u64,
+}
+
+/// `read_range` payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadRange {
+    pub path: String,
+    pub offset: u64,
+    /// Defaults to (and is capped at) `MAX_CHUNK_SIZE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
+    /// Only read if the file's content still has this SHA-256 (from
+    /// `file_hash`); otherwise the hub answers with a conflict
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Response to `read_range`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRange {
+    /// Base64 bytes starting at `offset`; shorter than asked at end of file
+    pub content: String,
+    pub offset: u64,
+    /// Size of the whole file
+    pub size: u64,
+    /// Version the bytes were read from; a change mid-download means the
+    /// file was replaced
+    pub modified: String,
+}
+
+/// Response to `file_hash`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileHash {
+    /// SHA-256 of the current content (lowercase hex)
+    pub sha256: String,
+    pub size: u64,
+    pub modified: String,
+    /// Size of the chunks in `chunks`; 0 from hubs that don't send them
+    #[serde(default)]
+    pub chunk_size: u64,
+    /// SHA-256 of each `chunk_size` piece of the content, in order, so a
+    /// download can verify (and keep) every chunk as it arrives
+    #[serde(default)]
+    pub chunks: Vec<String>,
+}
+
+// ============================================================================
+// Transports
+// ============================================================================
+
+/// Where a hub can be reached; any combination may be known
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HubAddress {
+    /// Base URL of the hub's HTTP endpoints, e.g. `https://hub.example.com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The hub's iroh endpoint address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iroh: Option<String>,
+}
+
+impl HubAddress {
+    /// An address with only a URL
+    pub fn url(url: impl Into<String>) -> Self {
+        Self {
+            url: Some(url.into()),
+            iroh: None,
+        }
+    }
+
+    /// Nothing is known about how to reach the hub
+    pub fn is_empty(&self) -> bool {
+        self.url.is_none() && self.iroh.is_none()
+    }
 }
 
-fn default_target_hub() -> String {
-    "self".to_string()
+impl std::fmt::Display for HubAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.url, &self.iroh) {
+            (Some(url), Some(iroh)) => write!(f, "{} (iroh {})", url, iroh),
+            (Some(url), None) => f.write_str(url),
+            (None, Some(iroh)) => write!(f, "iroh {}", iroh),
+            (None, None) => f.write_str("(no address)"),
+        }
+    }
 }
 
-/// Response envelope from hub to spokes
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HubResponse {
-    /// Application-specific response payload (JSON)
-    pub payload: serde_json::Value,
+/// What a transport brought back for a signed request
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// The hub handled the request
+    Response(SignedResponse),
+    /// The hub refused the request before handling it. `body` may be a
+    /// `RejectedRequest`.
+    Rejected { status: u16, body: String },
 }
 
-/// Hub-level errors (before reaching application)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum HubError {
-    /// Spoke not authorized for this hub
-    Unauthorized,
-    /// Spoke not authorized for this (app, instance)
-    AccessDenied { app: String, instance: String },
-    /// Application type not registered
-    AppNotFound { app: String },
-    /// Application instance not found
-    InstanceNotFound { app: String, instance: String },
-    /// Application returned an error
-    AppError { message: String },
+/// A boxed future, `Send` except on wasm where the browser's aren't
+#[cfg(not(target_arch = "wasm32"))]
+pub type BoxFuture<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + Send + 'a>>;
+
+/// A boxed future, `Send` except on wasm where the browser's aren't
+#[cfg(target_arch = "wasm32")]
+pub type BoxFuture<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + 'a>>;
+
+/// A way to deliver signed requests to a hub
+///
+/// Errors mean the hub could not be reached (or its reply not read), and
+/// make the client try its next transport. Anything the hub said, including
+/// refusals, is a `Reply`.
+pub trait Transport: Send + Sync {
+    /// Short name for logs, e.g. "http"
+    fn name(&self) -> &'static str;
+
+    /// Whether the address says enough for this transport to reach the hub
+    fn supports(&self, address: &HubAddress) -> bool;
+
+    /// Deliver a signed request to `ENDPOINT` and return the hub's reply
+    fn send_signed<'a>(&'a self, address: &'a HubAddress, request: &'a SignedRequest) -> BoxFuture<'a, Result<Reply>>;
+}
+
+/// A client's transports, in order of preference, with fallback
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+struct Transports {
+    list: Vec<std::sync::Arc<dyn Transport>>,
+    /// Index of the transport that worked last
+    preferred: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+impl Transports {
+    fn new(http: std::sync::Arc<dyn Transport>) -> Self {
+        Self {
+            list: vec![http],
+            preferred: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    /// Prefer `transport` over the ones already there
+    fn add(&mut self, transport: std::sync::Arc<dyn Transport>) {
+        self.list.insert(0, transport);
+        self.preferred.store(0, Ordering::Relaxed);
+    }
+
+    /// Send over the transport that worked last, then the others in order,
+    /// until one reaches the hub
+    async fn send(&self, address: &HubAddress, request: &SignedRequest) -> Result<Reply> {
+        let preferred = self.preferred.load(Ordering::Relaxed);
+        let order = std::iter::once(preferred).chain((0..self.list.len()).filter(|i| *i != preferred));
+        let mut last_error = None;
+        for index in order {
+            let Some(transport) = self.list.get(index) else {
+                continue;
+            };
+            if !transport.supports(address) {
+                continue;
+            }
+            match transport.send_signed(address, request).await {
+                Ok(reply) => {
+                    self.preferred.store(index, Ordering::Relaxed);
+                    return Ok(reply);
+                }
+                Err(e) => {
+                    tracing::debug!("{} transport failed for {}: {}", transport.name(), address, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::NoTransport(address.to_string())))
+    }
+}
+
+/// How long each attempt at a call may take, unless the client says
+/// otherwise
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+pub const DEFAULT_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How `Client::call_idempotent` retries calls that got no reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each one after it
+    pub initial_backoff: std::time::Duration,
+    /// Longest wait between attempts
+    pub max_backoff: std::time::Duration,
+}
+
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: std::time::Duration::from_millis(250),
+            max_backoff: std::time::Duration::from_secs(5),
+        }
+    }
+}
+
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Wait before retry number `retry` (0 for the first)
+    pub fn backoff(&self, retry: u32) -> std::time::Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+/// Stops the calls it was handed to (see `Client::call_cancellable`)
+///
+/// Clones share the cancellation. Cancelling a call that has finished does
+/// nothing.
+#[derive(Debug, Clone, Default)]
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+pub struct CancelHandle(std::sync::Arc<CancelState>);
+
+#[derive(Debug, Default)]
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+struct CancelState {
+    cancelled: std::sync::atomic::AtomicBool,
+    /// Calls waiting on the handle
+    wakers: Mutex<Vec<std::task::Waker>>,
+}
+
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the call; it returns `Error::Cancelled`
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.0.wakers.lock().unwrap_or_else(|e| e.into_inner()));
+        wakers.into_iter().for_each(std::task::Waker::wake);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Ready once cancelled
+    fn poll_cancelled(&self, cx: &mut std::task::Context<'_>) -> std::task::Poll<()> {
+        if self.is_cancelled() {
+            return std::task::Poll::Ready(());
+        }
+        let mut wakers = self.0.wakers.lock().unwrap_or_else(|e| e.into_inner());
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        drop(wakers);
+        // cancel() may have run before the waker was added
+        match self.is_cancelled() {
+            true => std::task::Poll::Ready(()),
+            false => std::task::Poll::Pending,
+        }
+    }
+}
+
+/// Run `future` until it finishes, `cancel` is cancelled or `timeout`
+/// passes; whatever is left of it is dropped
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+async fn race<T>(
+    future: impl std::future::Future<Output = Result<T>>,
+    cancel: &CancelHandle,
+    timeout: Option<std::time::Duration>,
+) -> Result<T> {
+    use std::future::Future;
+
+    let mut future = std::pin::pin!(future);
+    let mut deadline = std::pin::pin!(async {
+        match timeout {
+            Some(timeout) => sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    });
+    std::future::poll_fn(|cx| {
+        if cancel.poll_cancelled(cx).is_ready() {
+            return std::task::Poll::Ready(Err(Error::Cancelled));
+        }
+        if let std::task::Poll::Ready(result) = future.as_mut().poll(cx) {
+            return std::task::Poll::Ready(result);
+        }
+        match deadline.as_mut().poll(cx) {
+            std::task::Poll::Ready(()) => std::task::Poll::Ready(Err(Error::Timeout(timeout.unwrap_or_default()))),
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Make attempts at a call, each within `timeout`; retried after timeouts
+/// and transport errors as `retry` says
+#[cfg(any(feature = "client", target_arch = "wasm32"))]
+async fn with_retries<T, F>(
+    retry: &RetryPolicy,
+    timeout: Option<std::time::Duration>,
+    cancel: &CancelHandle,
+    mut attempt: impl FnMut() -> F,
+) -> Result<T>
+where
+    F: std::future::Future<Output = Result<T>>,
+{
+    let mut retries = 0;
+    loop {
+        match race(attempt(), cancel, timeout).await {
+            Err(e @ (Error::Timeout(_) | Error::HttpRequest(_))) if retries < retry.max_retries => {
+                let wait = retry.backoff(retries);
+                tracing::debug!("retrying in {:?} after: {}", wait, e);
+                race(
+                    async {
+                        sleep(wait).await;
+                        Ok(())
+                    },
+                    cancel,
+                    None,
+                )
+                .await?;
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(all(feature = "client", not(target_arch = "wasm32")))]
+async fn sleep(duration: std::time::Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// Wait with the global `setTimeout`, there in windows and workers alike
+#[cfg(target_arch = "wasm32")]
+async fn sleep(duration: std::time::Duration) {
+    use wasm_bindgen::JsCast;
+
+    let millis = duration.as_millis().min(i32::MAX as u128) as i32;
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let set_timeout = js_sys::Reflect::get(&js_sys::global(), &"setTimeout".into())
+            .ok()
+            .and_then(|f| f.dyn_into::<js_sys::Function>().ok());
+        if let Some(set_timeout) = set_timeout {
+            let _ = set_timeout.call2(&wasm_bindgen::JsValue::NULL, &resolve, &millis.into());
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
 }
 
 // ============================================================================
@@ -345,26 +2665,72 @@ pub enum HubError {
 #[cfg(feature = "client")]
 pub mod client {
     use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
 
-    /// HTTP client for making signed requests to a hub
+    /// Client for making signed requests to a hub
     pub struct Client {
         secret_key: SecretKey,
         hub_id52: String,
-        hub_url: String,
+        address: HubAddress,
         http: reqwest::Client,
+        transports: Transports,
+        signer: RequestSigner,
+        timeout: Option<std::time::Duration>,
+        retry: RetryPolicy,
     }
 
     impl Client {
-        /// Create a new client
+        /// Create a new client for a hub reached over HTTP
         pub fn new(secret_key: SecretKey, hub_id52: String, hub_url: String) -> Self {
+            Self::with_address(secret_key, hub_id52, HubAddress::url(hub_url))
+        }
+
+        /// Create a new client for a hub at `address`, using `HttpTransport`
+        /// until other transports are added
+        pub fn with_address(secret_key: SecretKey, hub_id52: String, mut address: HubAddress) -> Self {
+            address.url = address.url.map(|url| url.trim_end_matches('/').to_string());
+            let http = reqwest::Client::new();
             Self {
                 secret_key,
                 hub_id52,
-                hub_url: hub_url.trim_end_matches('/').to_string(),
-                http: reqwest::Client::new(),
+                address,
+                transports: Transports::new(std::sync::Arc::new(HttpTransport::new(http.clone()))),
+                http,
+                signer: RequestSigner::default(),
+                timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+                retry: RetryPolicy::default(),
             }
         }
 
+        /// Try `transport` before the ones added so far
+        pub fn with_transport(mut self, transport: std::sync::Arc<dyn Transport>) -> Self {
+            self.transports.add(transport);
+            self
+        }
+
+        /// Sign requests with `canonicalization` (`Canonicalization::Jcs`
+        /// by default; `SerdeJson` for hubs that don't accept version 3
+        /// requests yet; `Cbor` to send them as CBOR, falling back to JCS
+        /// if the hub doesn't read it)
+        pub fn with_canonicalization(mut self, canonicalization: Canonicalization) -> Self {
+            self.signer.canonicalization = canonicalization;
+            self
+        }
+
+        /// Give each attempt at a call `timeout` (`DEFAULT_REQUEST_TIMEOUT`
+        /// by default) to bring back a reply; `None` waits forever
+        pub fn with_timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        /// Retry `call_idempotent`s as `retry` says
+        pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+            self.retry = retry;
+            self
+        }
+
         /// Get our ID52
         pub fn id52(&self) -> String {
             self.secret_key.id52()
@@ -375,7 +2741,15 @@ pub mod client {
             &self.hub_id52
         }
 
+        /// Where the hub is reached
+        pub fn address(&self) -> &HubAddress {
+            &self.address
+        }
+
         /// Make a signed request and get a verified response
+        ///
+        /// Not retried: use `call_idempotent` for requests that are safe to
+        /// handle twice.
         pub async fn call<Req, Res, Err>(
             &self,
             request: &Req,
@@ -385,38 +2759,249 @@ pub mod client {
             Res: DeserializeOwned,
             Err: DeserializeOwned,
         {
-            // Sign the request
-            let signed_req = SignedRequest::new(&self.secret_key, request)?;
-
-            // Send HTTP POST
-            let url = format!("{}{}", self.hub_url, ENDPOINT);
-            let response = self
-                .http
-                .post(&url)
-                .json(&signed_req)
-                .send()
-                .await
-                .map_err(|e| Error::HttpRequest(e.to_string()))?;
-
-            if !response.status().is_success() {
-                return Err(Error::HttpRequest(format!(
-                    "HTTP {}: {}",
-                    response.status(),
-                    response.text().await.unwrap_or_default()
-                )));
-            }
-
-            // Parse and verify response
-            let signed_res: SignedResponse = response
-                .json()
-                .await
-                .map_err(|e| Error::HttpRequest(e.to_string()))?;
+            self.call_with(request, false, &CancelHandle::new()).await
+        }
+
+        /// Make a signed request the hub may handle more than once (a
+        /// read), retrying it when no reply comes back
+        pub async fn call_idempotent<Req, Res, Err>(
+            &self,
+            request: &Req,
+        ) -> Result<std::result::Result<Res, Err>>
+        where
+            Req: Serialize,
+            Res: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            self.call_with(request, true, &CancelHandle::new()).await
+        }
+
+        /// Like `call` (`call_idempotent` if `idempotent`), with a handle
+        /// that cancels it
+        pub fn call_cancellable<'a, Req, Res, Err>(
+            &'a self,
+            request: &'a Req,
+            idempotent: bool,
+        ) -> (CancelHandle, impl std::future::Future<Output = Result<std::result::Result<Res, Err>>> + 'a)
+        where
+            Req: Serialize,
+            Res: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            let cancel = CancelHandle::new();
+            let handle = cancel.clone();
+            (handle, async move { self.call_with(request, idempotent, &cancel).await })
+        }
+
+        async fn call_with<Req, Res, Err>(
+            &self,
+            request: &Req,
+            idempotent: bool,
+            cancel: &CancelHandle,
+        ) -> Result<std::result::Result<Res, Err>>
+        where
+            Req: Serialize,
+            Res: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            let retry = if idempotent { self.retry } else { RetryPolicy::none() };
+            with_retries(&retry, self.timeout, cancel, || self.call_once(request)).await
+        }
+
+        /// One attempt at a call
+        async fn call_once<Req, Res, Err>(
+            &self,
+            request: &Req,
+        ) -> Result<std::result::Result<Res, Err>>
+        where
+            Req: Serialize,
+            Res: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            let mut retried = false;
+            let signed_res = loop {
+                // Sign the request (a fresh nonce for every attempt)
+                let audience = Audience::new(&self.hub_id52, ENDPOINT);
+                let signed_req = self.signer.sign(&self.secret_key, &audience, request, true)?;
+
+                match self.transports.send(&self.address, &signed_req).await? {
+                    Reply::Response(signed_res) => break signed_res,
+                    // The hub doesn't read CBOR: send JSON from now on
+                    Reply::Rejected { status, .. } if self.signer.fall_back_from_cbor(&signed_req, status) => {}
+                    // Our clock is off: retry once with the hub's time
+                    Reply::Rejected { body, .. } if !retried && self.signer.correct_clock(&body) => retried = true,
+                    Reply::Rejected { status, body } => {
+                        return Err(RejectedRequest::into_error(status, body, &self.hub_id52));
+                    }
+                }
+            };
 
             // Verify response came from the expected hub
             let envelope: ResponseEnvelope<Res, Err> = signed_res.verify_from(&self.hub_id52)?;
 
             Ok(envelope.into_result())
         }
+
+        /// Open a push channel: connect to the hub's `WS_ENDPOINT` and send
+        /// `request` as the signed subscription handshake
+        ///
+        /// The hub accepts or refuses the subscription like a `call`; once
+        /// accepted, `Subscription::next` yields the events it pushes.
+        pub async fn subscribe<Req, T, Err>(
+            &self,
+            request: &Req,
+        ) -> Result<std::result::Result<Subscription<T>, Err>>
+        where
+            Req: Serialize,
+            T: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            let Some(hub_url) = &self.address.url else {
+                return Err(Error::NoTransport(format!("push channel of {}", self.address)));
+            };
+            let url = format!("{}{}", hub_url, WS_ENDPOINT);
+            let audience = Audience::new(&self.hub_id52, WS_ENDPOINT);
+            let mut retried = false;
+            loop {
+                let mut subscription = Subscription {
+                    hub_id52: self.hub_id52.clone(),
+                    stream: ws::connect(&self.http, &url).await?,
+                    event: std::marker::PhantomData,
+                };
+
+                let signed_req = self.signer.sign(&self.secret_key, &audience, request, false)?;
+                subscription
+                    .stream
+                    .send(Message::Text(serde_json::to_string(&signed_req)?.into()))
+                    .await
+                    .map_err(ws::error)?;
+
+                let text = subscription
+                    .receive_text()
+                    .await?
+                    .ok_or_else(|| Error::WebSocket("Hub closed the connection".to_string()))?;
+                match serde_json::from_str::<SignedResponse>(&text) {
+                    Ok(signed_res) => {
+                        let envelope: ResponseEnvelope<(), Err> = signed_res.verify_from(&self.hub_id52)?;
+                        return Ok(envelope.into_result().map(|()| subscription));
+                    }
+                    // Our clock is off: retry once with the hub's time
+                    Err(_) if !retried && self.signer.correct_clock(&text) => retried = true,
+                    Err(_) => return Err(Error::WebSocket(text)),
+                }
+            }
+        }
+    }
+
+    /// Events pushed by a hub, from `Client::subscribe`
+    pub struct Subscription<T> {
+        hub_id52: String,
+        stream: ws::ClientStream,
+        event: std::marker::PhantomData<fn() -> T>,
+    }
+
+    impl<T: DeserializeOwned> Subscription<T> {
+        /// Wait for the next event, verified as signed by the hub; None once
+        /// the hub closed the connection
+        pub async fn next(&mut self) -> Result<Option<T>> {
+            let Some(text) = self.receive_text().await? else {
+                return Ok(None);
+            };
+            let signed_res: SignedResponse = serde_json::from_str(&text)?;
+            signed_res.verify_from(&self.hub_id52).map(Some)
+        }
+
+        /// Next text message; the hub's pings are answered while reading
+        async fn receive_text(&mut self) -> Result<Option<String>> {
+            while let Some(message) = self.stream.next().await {
+                match message.map_err(ws::error)? {
+                    Message::Text(text) => return Ok(Some(text.to_string())),
+                    Message::Close(_) => return Ok(None),
+                    _ => {}
+                }
+            }
+            Ok(None)
+        }
+
+        /// Close the connection
+        pub async fn close(mut self) -> Result<()> {
+            self.stream.close(None).await.map_err(ws::error)
+        }
+    }
+
+    /// Delivers signed requests as HTTP POSTs to the hub's URL, in the
+    /// request's `wire::Format` and compressed once the hub takes it
+    #[derive(Debug, Clone, Default)]
+    pub struct HttpTransport {
+        http: reqwest::Client,
+        /// What the hub reads, from the Accept-Encoding of its last response
+        request_encoding: std::sync::Arc<Mutex<wire::Encoding>>,
+    }
+
+    impl HttpTransport {
+        pub fn new(http: reqwest::Client) -> Self {
+            Self {
+                http,
+                request_encoding: Default::default(),
+            }
+        }
+    }
+
+    impl Transport for HttpTransport {
+        fn name(&self) -> &'static str {
+            "http"
+        }
+
+        fn supports(&self, address: &HubAddress) -> bool {
+            address.url.is_some()
+        }
+
+        fn send_signed<'a>(&'a self, address: &'a HubAddress, request: &'a SignedRequest) -> BoxFuture<'a, Result<Reply>> {
+            Box::pin(async move {
+                use reqwest::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+
+                let url = address.url.as_deref().unwrap_or_default().trim_end_matches('/');
+                let format = wire::Format::of(request.canonicalization());
+                let body = format.encode(request)?;
+                let encoding = self.request_encoding.lock().unwrap_or_else(|e| e.into_inner()).for_body(body.len());
+                let mut builder = self
+                    .http
+                    .post(format!("{}{}", url, ENDPOINT))
+                    .header(CONTENT_TYPE, format.content_type())
+                    .header(ACCEPT_ENCODING, wire::ACCEPTED_ENCODINGS);
+                if encoding != wire::Encoding::Identity {
+                    builder = builder.header(CONTENT_ENCODING, encoding.name());
+                }
+                let response = builder
+                    .body(encoding.compress(body)?)
+                    .send()
+                    .await
+                    .map_err(|e| Error::HttpRequest(e.to_string()))?;
+
+                let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok());
+                *self.request_encoding.lock().unwrap_or_else(|e| e.into_inner()) =
+                    wire::Encoding::negotiate(header(ACCEPT_ENCODING));
+                let format = wire::Format::from_content_type(header(CONTENT_TYPE));
+                let encoding = wire::Encoding::from_header(header(CONTENT_ENCODING));
+                let status = response.status();
+                let body = response.bytes().await.map_err(|e| Error::HttpRequest(e.to_string()))?;
+                let body = encoding.and_then(|encoding| encoding.decompress(body.to_vec()));
+                let body = body.map_err(|e| Error::HttpRequest(e.to_string()))?;
+
+                if !status.is_success() {
+                    let body = String::from_utf8_lossy(&body).into_owned();
+                    // A proxy in front of the hub answered, the hub itself wasn't reached
+                    if matches!(status.as_u16(), 502..=504) {
+                        return Err(Error::HttpRequest(format!("HTTP {}: {}", status, body)));
+                    }
+                    return Ok(Reply::Rejected { status: status.as_u16(), body });
+                }
+                let signed_res: SignedResponse = format
+                    .and_then(|format| format.decode(&body))
+                    .map_err(|e| Error::HttpRequest(e.to_string()))?;
+                Ok(Reply::Response(signed_res))
+            })
+        }
     }
 }
 
@@ -428,23 +3013,66 @@ pub mod client {
 pub mod web_client {
     use super::*;
 
-    /// HTTP client for making signed requests to a hub (WASM version using gloo-net)
+    /// Client for making signed requests to a hub (WASM version)
     pub struct Client {
         secret_key: SecretKey,
         hub_id52: String,
-        hub_url: String,
+        address: HubAddress,
+        transports: Transports,
+        signer: RequestSigner,
+        timeout: Option<std::time::Duration>,
+        retry: RetryPolicy,
     }
 
     impl Client {
-        /// Create a new client
+        /// Create a new client for a hub reached over HTTP
         pub fn new(secret_key: SecretKey, hub_id52: String, hub_url: String) -> Self {
+            Self::with_address(secret_key, hub_id52, HubAddress::url(hub_url))
+        }
+
+        /// Create a new client for a hub at `address`, using `HttpTransport`
+        /// until other transports are added
+        pub fn with_address(secret_key: SecretKey, hub_id52: String, mut address: HubAddress) -> Self {
+            address.url = address.url.map(|url| url.trim_end_matches('/').to_string());
             Self {
                 secret_key,
                 hub_id52,
-                hub_url: hub_url.trim_end_matches('/').to_string(),
+                address,
+                transports: Transports::new(std::sync::Arc::new(HttpTransport::default())),
+                signer: RequestSigner::default(),
+                timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+                retry: RetryPolicy::default(),
             }
         }
 
+        /// Try `transport` before the ones added so far
+        pub fn with_transport(mut self, transport: std::sync::Arc<dyn Transport>) -> Self {
+            self.transports.add(transport);
+            self
+        }
+
+        /// Sign requests with `canonicalization` (`Canonicalization::Jcs`
+        /// by default; `SerdeJson` for hubs that don't accept version 3
+        /// requests yet; `Cbor` to send them as CBOR, falling back to JCS
+        /// if the hub doesn't read it)
+        pub fn with_canonicalization(mut self, canonicalization: Canonicalization) -> Self {
+            self.signer.canonicalization = canonicalization;
+            self
+        }
+
+        /// Give each attempt at a call `timeout` (`DEFAULT_REQUEST_TIMEOUT`
+        /// by default) to bring back a reply; `None` waits forever
+        pub fn with_timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        /// Retry `call_idempotent`s as `retry` says
+        pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+            self.retry = retry;
+            self
+        }
+
         /// Get our ID52
         pub fn id52(&self) -> String {
             self.secret_key.id52()
@@ -455,7 +3083,15 @@ pub mod web_client {
             &self.hub_id52
         }
 
+        /// Where the hub is reached
+        pub fn address(&self) -> &HubAddress {
+            &self.address
+        }
+
         /// Make a signed request and get a verified response
+        ///
+        /// Not retried: use `call_idempotent` for requests that are safe to
+        /// handle twice.
         pub async fn call<Req, Res, Err>(
             &self,
             request: &Req,
@@ -465,33 +3101,82 @@ pub mod web_client {
             Res: DeserializeOwned,
             Err: DeserializeOwned,
         {
-            use gloo_net::http::Request;
-
-            // Sign the request
-            let signed_req = SignedRequest::new(&self.secret_key, request)?;
-
-            // Send HTTP POST
-            let url = format!("{}{}", self.hub_url, ENDPOINT);
-            let response = Request::post(&url)
-                .header("Content-Type", "application/json")
-                .body(serde_json::to_string(&signed_req)?)
-                .map_err(|e| Error::HttpRequest(e.to_string()))?
-                .send()
-                .await
-                .map_err(|e| Error::HttpRequest(e.to_string()))?;
-
-            if !response.ok() {
-                let status = response.status();
-                let text = response.text().await.unwrap_or_default();
-                return Err(Error::HttpRequest(format!("HTTP {}: {}", status, text)));
-            }
+            self.call_with(request, false, &CancelHandle::new()).await
+        }
 
-            // Parse and verify response
-            let text = response
-                .text()
-                .await
-                .map_err(|e| Error::HttpRequest(e.to_string()))?;
-            let signed_res: SignedResponse = serde_json::from_str(&text)?;
+        /// Make a signed request the hub may handle more than once (a
+        /// read), retrying it when no reply comes back
+        pub async fn call_idempotent<Req, Res, Err>(
+            &self,
+            request: &Req,
+        ) -> Result<std::result::Result<Res, Err>>
+        where
+            Req: Serialize,
+            Res: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            self.call_with(request, true, &CancelHandle::new()).await
+        }
+
+        /// Like `call` (`call_idempotent` if `idempotent`), with a handle
+        /// that cancels it
+        pub fn call_cancellable<'a, Req, Res, Err>(
+            &'a self,
+            request: &'a Req,
+            idempotent: bool,
+        ) -> (CancelHandle, impl std::future::Future<Output = Result<std::result::Result<Res, Err>>> + 'a)
+        where
+            Req: Serialize,
+            Res: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            let cancel = CancelHandle::new();
+            let handle = cancel.clone();
+            (handle, async move { self.call_with(request, idempotent, &cancel).await })
+        }
+
+        async fn call_with<Req, Res, Err>(
+            &self,
+            request: &Req,
+            idempotent: bool,
+            cancel: &CancelHandle,
+        ) -> Result<std::result::Result<Res, Err>>
+        where
+            Req: Serialize,
+            Res: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            let retry = if idempotent { self.retry } else { RetryPolicy::none() };
+            with_retries(&retry, self.timeout, cancel, || self.call_once(request)).await
+        }
+
+        /// One attempt at a call
+        async fn call_once<Req, Res, Err>(
+            &self,
+            request: &Req,
+        ) -> Result<std::result::Result<Res, Err>>
+        where
+            Req: Serialize,
+            Res: DeserializeOwned,
+            Err: DeserializeOwned,
+        {
+            let mut retried = false;
+            let signed_res = loop {
+                // Sign the request (a fresh nonce for every attempt)
+                let audience = Audience::new(&self.hub_id52, ENDPOINT);
+                let signed_req = self.signer.sign(&self.secret_key, &audience, request, true)?;
+
+                match self.transports.send(&self.address, &signed_req).await? {
+                    Reply::Response(signed_res) => break signed_res,
+                    // The hub doesn't read CBOR: send JSON from now on
+                    Reply::Rejected { status, .. } if self.signer.fall_back_from_cbor(&signed_req, status) => {}
+                    // Our clock is off: retry once with the hub's time
+                    Reply::Rejected { body, .. } if !retried && self.signer.correct_clock(&body) => retried = true,
+                    Reply::Rejected { status, body } => {
+                        return Err(RejectedRequest::into_error(status, body, &self.hub_id52));
+                    }
+                }
+            };
 
             // Verify response came from the expected hub
             let envelope: ResponseEnvelope<Res, Err> = signed_res.verify_from(&self.hub_id52)?;
@@ -499,6 +3184,80 @@ pub mod web_client {
             Ok(envelope.into_result())
         }
     }
+
+    /// Delivers signed requests as HTTP POSTs to the hub's URL (gloo-net),
+    /// in the request's `wire::Format` and gzipped once the hub takes it
+    #[derive(Debug, Clone, Default)]
+    pub struct HttpTransport {
+        /// What the hub reads, from the Accept-Encoding of its last response
+        request_encoding: std::sync::Arc<Mutex<wire::Encoding>>,
+    }
+
+    impl Transport for HttpTransport {
+        fn name(&self) -> &'static str {
+            "http"
+        }
+
+        fn supports(&self, address: &HubAddress) -> bool {
+            address.url.is_some()
+        }
+
+        fn send_signed<'a>(&'a self, address: &'a HubAddress, request: &'a SignedRequest) -> BoxFuture<'a, Result<Reply>> {
+            Box::pin(async move {
+                use gloo_net::http::Request;
+
+                let url = address.url.as_deref().unwrap_or_default().trim_end_matches('/');
+                let format = wire::Format::of(request.canonicalization());
+                let body = format.encode(request)?;
+                let encoding = self.request_encoding.lock().unwrap_or_else(|e| e.into_inner()).for_body(body.len());
+                let mut builder = Request::post(&format!("{}{}", url, ENDPOINT)).header("Content-Type", format.content_type());
+                if encoding != wire::Encoding::Identity {
+                    builder = builder.header("Content-Encoding", encoding.name());
+                }
+                // Abort the fetch if this future is dropped (the call was
+                // cancelled or timed out)
+                let controller = web_sys::AbortController::new()
+                    .map_err(|e| Error::HttpRequest(format!("AbortController: {:?}", e)))?;
+                let _abort = AbortOnDrop(controller.clone());
+                let response = builder
+                    .abort_signal(Some(&controller.signal()))
+                    .body(js_sys::Uint8Array::from(encoding.compress(body)?.as_slice()))
+                    .map_err(|e| Error::HttpRequest(e.to_string()))?
+                    .send()
+                    .await
+                    .map_err(|e| Error::HttpRequest(e.to_string()))?;
+
+                let headers = response.headers();
+                *self.request_encoding.lock().unwrap_or_else(|e| e.into_inner()) =
+                    wire::Encoding::negotiate(headers.get("Accept-Encoding").as_deref());
+                let status = response.status();
+                // The browser has undone the response's Content-Encoding
+                let body = response
+                    .binary()
+                    .await
+                    .map_err(|e| Error::HttpRequest(e.to_string()))?;
+                if !response.ok() {
+                    let text = String::from_utf8_lossy(&body).into_owned();
+                    // A proxy in front of the hub answered, the hub itself wasn't reached
+                    if matches!(status, 502..=504) {
+                        return Err(Error::HttpRequest(format!("HTTP {}: {}", status, text)));
+                    }
+                    return Ok(Reply::Rejected { status, body: text });
+                }
+                let format = wire::Format::from_content_type(headers.get("Content-Type").as_deref())?;
+                Ok(Reply::Response(format.decode(&body)?))
+            })
+        }
+    }
+
+    /// Aborts a fetch when dropped; a no-op once the fetch has finished
+    struct AbortOnDrop(web_sys::AbortController);
+
+    impl Drop for AbortOnDrop {
+        fn drop(&mut self) {
+            self.0.abort();
+        }
+    }
 }
 
 // ============================================================================
@@ -509,9 +3268,10 @@ pub mod web_client {
 pub mod server {
     use super::*;
     use axum::{
+        body::Bytes,
         extract::State,
-        http::StatusCode,
-        response::IntoResponse,
+        http::{header, HeaderMap, HeaderValue, StatusCode},
+        response::{IntoResponse, Response},
         routing::post,
         Json, Router,
     };
@@ -529,6 +3289,7 @@ pub mod server {
     pub struct ServerState<Req, Res, Err> {
         pub secret_key: SecretKey,
         pub handler: HandlerFn<Req, Res, Err>,
+        pub replay_guard: ReplayGuard,
     }
 
     /// Create an axum router for the fastn endpoint
@@ -547,31 +3308,123 @@ pub mod server {
         let state = Arc::new(ServerState {
             secret_key,
             handler: Arc::new(handler),
+            replay_guard: ReplayGuard::new(),
         });
 
-        Router::new()
-            .route(ENDPOINT, post(handle_request::<Req, Res, Err>))
-            .with_state(state)
+        Router::new()
+            .route(ENDPOINT, post(handle_request::<Req, Res, Err>))
+            .with_state(state)
+    }
+
+    /// A signed request read from a POST to `ENDPOINT`, and how to answer it
+    pub struct Incoming {
+        pub request: SignedRequest,
+        format: wire::Format,
+        /// For the response, from the client's Accept-Encoding
+        encoding: wire::Encoding,
+    }
+
+    /// Read a signed request from a POST's headers and body: JSON or CBOR by
+    /// its Content-Type, decompressed by its Content-Encoding. Errors are
+    /// answered with `reject`.
+    pub fn read_request(headers: &HeaderMap, body: Bytes) -> Result<Incoming> {
+        let header = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok());
+        let format = wire::Format::from_content_type(header(header::CONTENT_TYPE))?;
+        let body = wire::Encoding::from_header(header(header::CONTENT_ENCODING))?.decompress(body.to_vec())?;
+        Ok(Incoming {
+            request: format.decode(&body)?,
+            format,
+            encoding: wire::Encoding::negotiate(header(header::ACCEPT_ENCODING)),
+        })
+    }
+
+    /// Refuse a request with a `RejectedRequest`: 415 Unsupported Media
+    /// Type for bodies in a format or encoding this server doesn't read
+    /// (clients then fall back to JSON), 400 otherwise
+    pub fn reject(e: &Error) -> Response {
+        let status = match e {
+            Error::UnsupportedContentType(_) | Error::UnsupportedEncoding(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        with_accepted_encodings((status, Json(RejectedRequest::from(e))).into_response())
+    }
+
+    /// Reject a request addressed to a key the server rotated away from,
+    /// telling the client where it went
+    pub fn reject_rotated(e: &Error, rotation: KeyRotation) -> Response {
+        let body = RejectedRequest {
+            key_rotation: Some(rotation),
+            ..RejectedRequest::from(e)
+        };
+        with_accepted_encodings((StatusCode::BAD_REQUEST, Json(body)).into_response())
+    }
+
+    impl Incoming {
+        /// Send `signed` in the request's format, compressed if the client
+        /// reads compressed bodies and it's big enough to be worth it
+        pub fn respond(&self, signed: Result<SignedResponse>) -> Response {
+            let body = signed.and_then(|signed| {
+                let body = self.format.encode(&signed)?;
+                let encoding = self.encoding.for_body(body.len());
+                Ok((encoding, encoding.compress(body)?))
+            });
+            let (encoding, body) = match body {
+                Ok(body) => body,
+                Err(e) => {
+                    tracing::error!("Failed to sign response: {}", e);
+                    let error = Json(serde_json::json!({"error": "Failed to sign response"}));
+                    return with_accepted_encodings((StatusCode::INTERNAL_SERVER_ERROR, error).into_response());
+                }
+            };
+            let mut response = (StatusCode::OK, [(header::CONTENT_TYPE, self.format.content_type())], body).into_response();
+            if encoding != wire::Encoding::Identity {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+            }
+            with_accepted_encodings(response)
+        }
+    }
+
+    /// Tell the client which request encodings we read (RFC 7694)
+    fn with_accepted_encodings(mut response: Response) -> Response {
+        response
+            .headers_mut()
+            .insert(header::ACCEPT_ENCODING, HeaderValue::from_static(wire::ACCEPTED_ENCODINGS));
+        response
     }
 
     async fn handle_request<Req, Res, Err>(
         State(state): State<Arc<ServerState<Req, Res, Err>>>,
-        Json(signed_req): Json<SignedRequest>,
-    ) -> impl IntoResponse
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response
     where
         Req: DeserializeOwned + Send,
         Res: Serialize + Send,
         Err: Serialize + Send,
     {
-        // Verify and extract the request
-        let (sender_id52, request): (String, Req) = match signed_req.verify() {
+        let incoming = match read_request(&headers, body) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                tracing::warn!("Unreadable request: {}", e);
+                return reject(&e);
+            }
+        };
+        let signed_req = &incoming.request;
+
+        // Verify and extract the request, then make sure it's for us and not
+        // a replay
+        let audience = Audience::new(state.secret_key.id52(), ENDPOINT);
+        let verified = signed_req
+            .verify()
+            .and_then(|r| signed_req.check_audience(&audience).map(|()| r))
+            .and_then(|r| state.replay_guard.check(signed_req).map(|()| r));
+        let (sender_id52, request): (String, Req) = match verified {
             Ok(r) => r,
             Err(e) => {
                 tracing::warn!("Request verification failed: {}", e);
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(serde_json::json!({"error": e.to_string()})),
-                );
+                return reject(&e);
             }
         };
 
@@ -584,18 +3437,79 @@ pub mod server {
             Err(err) => ResponseEnvelope::Err(err),
         };
 
-        let signed_res = match SignedResponse::new(&state.secret_key, &envelope) {
-            Ok(r) => r,
-            Err(e) => {
-                tracing::error!("Failed to sign response: {}", e);
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(serde_json::json!({"error": "Failed to sign response"})),
-                );
-            }
-        };
+        incoming.respond(SignedResponse::new_with(&state.secret_key, &envelope, signed_req.canonicalization()))
+    }
+}
+
+// ============================================================================
+// WebSocket (push channel)
+// ============================================================================
+
+/// WebSocket support for the push channel at `WS_ENDPOINT`
+///
+/// Hubs answer upgrades with axum's `WebSocketUpgrade`; spokes upgrade a
+/// request from their reqwest client and run tungstenite on the connection.
+/// Both answer pings themselves.
+#[cfg(any(feature = "client", feature = "server"))]
+pub mod ws {
+    use super::*;
+
+    /// Largest message either side accepts
+    pub const MAX_MESSAGE_LEN: usize = 1024 * 1024;
 
-        (StatusCode::OK, Json(serde_json::to_value(signed_res).unwrap()))
+    /// The client side of a connection
+    #[cfg(feature = "client")]
+    pub type ClientStream = tokio_tungstenite::WebSocketStream<reqwest::Upgraded>;
+
+    #[cfg(feature = "client")]
+    pub(crate) fn error(e: tokio_tungstenite::tungstenite::Error) -> Error {
+        Error::WebSocket(e.to_string())
+    }
+
+    /// Open a WebSocket connection to `url` (http or https)
+    #[cfg(feature = "client")]
+    pub async fn connect(http: &reqwest::Client, url: &str) -> Result<ClientStream> {
+        use reqwest::header;
+        use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+        use tokio_tungstenite::tungstenite::protocol::{Role, WebSocketConfig};
+
+        let key = data_encoding::BASE64.encode(&rand::random::<[u8; 16]>());
+        let response = http
+            .get(url)
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .header(header::SEC_WEBSOCKET_KEY, &key)
+            .send()
+            .await
+            .map_err(|e| Error::WebSocket(e.to_string()))?;
+
+        if response.status() != reqwest::StatusCode::SWITCHING_PROTOCOLS {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(Error::WebSocket(format!("HTTP {}: {}", status, text)));
+        }
+        let accept = response.headers().get(header::SEC_WEBSOCKET_ACCEPT).and_then(|v| v.to_str().ok());
+        if accept != Some(derive_accept_key(key.as_bytes()).as_str()) {
+            return Err(Error::WebSocket("Invalid Sec-WebSocket-Accept".to_string()));
+        }
+        let upgraded = response.upgrade().await.map_err(|e| Error::WebSocket(e.to_string()))?;
+        let config = WebSocketConfig::default().max_message_size(Some(MAX_MESSAGE_LEN));
+        Ok(tokio_tungstenite::WebSocketStream::from_raw_socket(upgraded, Role::Client, Some(config)).await)
+    }
+
+    /// Answer a WebSocket upgrade, then run `session` on the connection.
+    /// Anything but an upgrade request is refused by the extractor.
+    #[cfg(feature = "server")]
+    pub fn accept<F, Fut>(upgrade: axum::extract::ws::WebSocketUpgrade, session: F) -> axum::response::Response
+    where
+        F: FnOnce(axum::extract::ws::WebSocket) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        upgrade
+            .max_message_size(MAX_MESSAGE_LEN)
+            .on_failed_upgrade(|e| tracing::warn!("WebSocket upgrade failed: {}", e))
+            .on_upgrade(session)
     }
 }
 
@@ -603,6 +3517,10 @@ pub mod server {
 mod tests {
     use super::*;
 
+    fn test_audience() -> Audience {
+        Audience::new(SecretKey::from_bytes(&[7; 32]).id52(), ENDPOINT)
+    }
+
     #[test]
     fn test_id52_roundtrip() {
         let key = SecretKey::generate();
@@ -615,6 +3533,101 @@ mod tests {
         assert_eq!(public, parsed);
     }
 
+    #[test]
+    fn test_invite_token_roundtrip() {
+        let hub = SecretKey::generate();
+        let token = InviteToken::sign(&hub, [1, 2, 3, 4, 5, 6, 7, 8], 1_800_000_000);
+        let invite = InviteToken::verify(&token).unwrap();
+        assert_eq!(invite.hub_id52, hub.id52());
+        assert_eq!(invite.invite_id, "0102030405060708");
+        assert_eq!(invite.expires_at, 1_800_000_000);
+
+        // Any change breaks the signature
+        let mut bytes = data_encoding::BASE64URL_NOPAD.decode(token.as_bytes()).unwrap();
+        bytes[40] ^= 1;
+        let tampered = data_encoding::BASE64URL_NOPAD.encode(&bytes);
+        assert!(matches!(InviteToken::verify(&tampered), Err(Error::VerificationFailed)));
+        assert!(matches!(InviteToken::verify("short"), Err(Error::InvalidInvite(_))));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = SecretKey::generate();
+        let new = SecretKey::generate();
+        let rotation = KeyRotation::sign(&old, &new.id52(), 1_800_000_000);
+        assert_eq!(rotation.old_id52, old.id52());
+        rotation.verify().unwrap();
+
+        // Only the old key can vouch for the new one
+        let forged = KeyRotation { old_id52: new.id52(), ..rotation.clone() };
+        assert!(matches!(forged.verify(), Err(Error::VerificationFailed)));
+        let redirected = KeyRotation { new_id52: old.id52(), ..rotation.clone() };
+        assert!(redirected.verify().is_err());
+
+        // Clients only follow rotations away from the hub they called
+        let body = serde_json::to_string(&RejectedRequest {
+            key_rotation: Some(rotation),
+            ..RejectedRequest::from(&Error::MissingAudience)
+        })
+        .unwrap();
+        assert!(matches!(RejectedRequest::into_error(400, body.clone(), &old.id52()), Error::HubKeyRotated(_)));
+        assert!(matches!(RejectedRequest::into_error(400, body, &new.id52()), Error::Rejected { .. }));
+    }
+
+    #[test]
+    fn test_key_file_protection() {
+        use key_file::Protection;
+        let dir = std::env::temp_dir().join(format!("fastn-net-key-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hub.key");
+        let key = SecretKey::generate();
+
+        // Raw files, as written before protection
+        std::fs::write(&path, key.to_bytes()).unwrap();
+        let (read, protection) = key_file::read(&path, None).unwrap();
+        assert_eq!((read.id52(), protection), (key.id52(), Protection::Plain));
+
+        // Migrated to a passphrase in place
+        let passphrase = Protection::Passphrase("correct horse".to_string());
+        key_file::write(&path, &key, &passphrase).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(&data_encoding::BASE64.encode(&key.to_bytes())));
+        assert_eq!(key_file::protection(&path).unwrap().name(), "passphrase");
+        let (read, protection) = key_file::read(&path, Some("correct horse")).unwrap();
+        assert_eq!((read.id52(), protection), (key.id52(), passphrase));
+        assert!(matches!(key_file::read(&path, None), Err(Error::PassphraseRequired(_))));
+        assert!(matches!(key_file::read(&path, Some("wrong")), Err(Error::WrongPassphrase(_))));
+
+        // And back
+        key_file::write(&path, &key, &Protection::Plain).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), key.to_bytes());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        std::fs::write(&path, b"not a key").unwrap();
+        assert!(matches!(key_file::read(&path, None), Err(Error::InvalidKeyFile(_))));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_file_hash_without_chunks() {
+        // Hubs without per-chunk digests still produce a usable FileHash
+        let hash: FileHash = serde_json::from_value(serde_json::json!({
+            "sha256": "ab".repeat(32),
+            "size": 3,
+            "modified": "2024-12-24T15:30:45Z",
+        }))
+        .unwrap();
+        assert_eq!(hash.chunk_size, 0);
+        assert!(hash.chunks.is_empty());
+
+        // A plain read_range payload carries no digest
+        let read = ReadRange { path: "a.bin".to_string(), offset: 0, length: None, sha256: None };
+        assert_eq!(serde_json::to_value(&read).unwrap(), serde_json::json!({ "path": "a.bin", "offset": 0 }));
+    }
+
     #[test]
     fn test_signed_request_roundtrip() {
         #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -629,7 +3642,7 @@ mod tests {
             count: 42,
         };
 
-        let signed = SignedRequest::new(&key, &payload).unwrap();
+        let signed = SignedRequest::new(&key, &test_audience(), &payload).unwrap();
         let (sender, extracted): (String, TestPayload) = signed.verify().unwrap();
 
         assert_eq!(sender, key.id52());
@@ -648,7 +3661,7 @@ mod tests {
             message: "Hello".to_string(),
         };
 
-        let mut signed = SignedRequest::new(&key, &payload).unwrap();
+        let mut signed = SignedRequest::new(&key, &test_audience(), &payload).unwrap();
 
         // Tamper with the payload
         signed.payload = serde_json::json!({"message": "Tampered"});
@@ -670,7 +3683,7 @@ mod tests {
             message: "Hello".to_string(),
         };
 
-        let mut signed = SignedRequest::new(&key1, &payload).unwrap();
+        let mut signed = SignedRequest::new(&key1, &test_audience(), &payload).unwrap();
 
         // Claim to be someone else
         signed.sender = key2.id52();
@@ -699,4 +3712,794 @@ mod tests {
             ResponseEnvelope::Err(_) => panic!("Expected Ok"),
         }
     }
+
+    #[test]
+    fn test_timestamp_and_nonce_are_signed() {
+        let key = SecretKey::generate();
+        let signed = SignedRequest::new(&key, &test_audience(), &serde_json::json!({"op": "read"})).unwrap();
+
+        let mut later = signed.clone();
+        later.timestamp += 60;
+        assert!(later.verify::<serde_json::Value>().is_err());
+
+        let mut renonced = signed.clone();
+        renonced.nonce = "0".repeat(32);
+        assert!(renonced.verify::<serde_json::Value>().is_err());
+
+        assert_ne!(signed.nonce, SignedRequest::new(&key, &test_audience(), &serde_json::json!({"op": "read"})).unwrap().nonce);
+    }
+
+    #[test]
+    fn test_audience_is_signed_and_checked() {
+        let key = SecretKey::generate();
+        let hub_a = test_audience();
+        let hub_b = Audience::new(SecretKey::generate().id52(), ENDPOINT);
+        let signed = SignedRequest::new(&key, &hub_a, &serde_json::json!({"op": "read"})).unwrap();
+        assert_eq!(signed.version, PROTOCOL_VERSION);
+
+        signed.verify::<serde_json::Value>().unwrap();
+        signed.check_audience(&hub_a).unwrap();
+
+        // Hub B rejects a request signed for hub A
+        let result = signed.check_audience(&hub_b);
+        assert!(matches!(result, Err(Error::WrongAudience { .. })), "got {:?}", result);
+        let other_endpoint = Audience::new(hub_a.hub.clone(), "/_other");
+        assert!(matches!(signed.check_audience(&other_endpoint), Err(Error::WrongAudience { .. })));
+
+        // Readdressing, or dropping the audience to pass as version 1, breaks
+        // the signature
+        let mut readdressed = signed.clone();
+        readdressed.audience = Some(hub_b.clone());
+        assert!(readdressed.verify::<serde_json::Value>().is_err());
+        let mut downgraded = signed.clone();
+        downgraded.version = UNBOUND_PROTOCOL_VERSION;
+        downgraded.audience = None;
+        assert!(downgraded.verify::<serde_json::Value>().is_err());
+
+        let mut stripped = signed.clone();
+        stripped.audience = None;
+        assert!(matches!(stripped.verify::<serde_json::Value>(), Err(Error::MissingAudience)));
+        let mut future = signed.clone();
+        future.version = CBOR_PROTOCOL_VERSION + 1;
+        assert!(matches!(future.verify::<serde_json::Value>(), Err(Error::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn test_unbound_requests_during_compatibility_window() {
+        let key = SecretKey::generate();
+        let now = 1_735_053_045;
+        let legacy = SignedRequest::sign(&key, UNBOUND_PROTOCOL_VERSION, None, &serde_json::json!({"op": "read"}), now).unwrap();
+
+        // Old clients send no version or audience
+        let json = serde_json::to_value(&legacy).unwrap();
+        assert!(json.get("audience").is_none());
+        let mut json = json;
+        json.as_object_mut().unwrap().remove("version");
+        let legacy: SignedRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(legacy.version, UNBOUND_PROTOCOL_VERSION);
+        legacy.verify::<serde_json::Value>().unwrap();
+
+        legacy.check_audience_at(&test_audience(), now).unwrap();
+        let result = legacy.check_audience_at(&test_audience(), UNBOUND_REQUESTS_ACCEPTED_UNTIL);
+        assert!(matches!(result, Err(Error::UnboundRequest)), "got {:?}", result);
+    }
+
+    #[test]
+    fn test_serde_json_requests_still_accepted() {
+        let key = SecretKey::generate();
+        let payload = serde_json::json!({"ratio": 1e21, "€": 1, "😀": 2});
+        let jcs = SignedRequest::new(&key, &test_audience(), &payload).unwrap();
+        let serde = SignedRequest::new_with(&key, &test_audience(), &payload, unix_time(), Canonicalization::SerdeJson).unwrap();
+        assert_eq!((jcs.version, jcs.canonicalization()), (PROTOCOL_VERSION, Canonicalization::Jcs));
+        assert_eq!(serde.version, SERDE_JSON_PROTOCOL_VERSION);
+        assert_eq!(serde.canonicalization(), Canonicalization::SerdeJson);
+        jcs.verify::<serde_json::Value>().unwrap();
+        serde.verify::<serde_json::Value>().unwrap();
+        assert!(jcs.message().unwrap().ends_with(r#"{"ratio":1e+21,"€":1,"😀":2}"#.as_bytes()));
+        assert!(serde.message().unwrap().ends_with(r#"{"ratio":1e21,"€":1,"😀":2}"#.as_bytes()));
+
+        // The version is signed
+        let mut relabeled = jcs.clone();
+        relabeled.version = SERDE_JSON_PROTOCOL_VERSION;
+        assert!(relabeled.verify::<serde_json::Value>().is_err());
+    }
+
+    #[test]
+    fn test_response_canonicalization() {
+        let key = SecretKey::generate();
+        let payload = ResponseEnvelope::<f64, HubError>::Ok(0.000001);
+        let legacy = serde_json::to_value(SignedResponse::new(&key, &payload).unwrap()).unwrap();
+        assert!(legacy.get("canonicalization").is_none());
+        let legacy: SignedResponse = serde_json::from_value(legacy).unwrap();
+        assert_eq!(legacy.verify_from::<ResponseEnvelope<f64, HubError>>(&key.id52()).unwrap().into_result().unwrap(), 0.000001);
+
+        let jcs = SignedResponse::new_with(&key, &payload, Canonicalization::Jcs).unwrap();
+        assert_eq!(serde_json::to_value(&jcs).unwrap()["canonicalization"], "jcs");
+        jcs.verify::<ResponseEnvelope<f64, HubError>>().unwrap();
+        let mut relabeled = jcs.clone();
+        relabeled.canonicalization = Canonicalization::SerdeJson;
+        assert!(relabeled.verify::<ResponseEnvelope<f64, HubError>>().is_err());
+    }
+
+    #[test]
+    fn test_cbor_is_canonical() {
+        let value = serde_json::json!({ "b": [1, -1, 1.5, null, true], "a": "x", "aa": 500 });
+        let reordered = serde_json::json!({ "aa": 500, "a": "x", "b": [1, -1, 1.5, null, true] });
+        let encoded = cbor::to_vec(&value);
+        assert_eq!(encoded, cbor::to_vec(&reordered));
+        // Keys by their encoded bytes: shorter first
+        let expected = "a3616161786162850120fb3ff8000000000000f6f562616119 01f4".replace(' ', "");
+        assert_eq!(data_encoding::HEXLOWER.encode(&encoded), expected);
+        assert_eq!(cbor::from_slice(&encoded).unwrap(), value);
+
+        // Base64 strings travel as bytes and come back as they were
+        let content = data_encoding::BASE64.encode(&[0xab; 300]);
+        let value = serde_json::json!({ "content": content, "short": "AAAA", "not": "AAAAAAAAAAAAAAA=" });
+        let encoded = cbor::to_vec(&value);
+        assert!(encoded.len() < content.len(), "{}", encoded.len());
+        assert_eq!(cbor::from_slice(&encoded).unwrap(), value);
+        assert_eq!(cbor::to_vec(&cbor::from_slice(&encoded).unwrap()), encoded);
+
+        let big = serde_json::json!([u64::MAX, i64::MIN, 9007199254740993u64]);
+        assert_eq!(cbor::from_slice(&cbor::to_vec(&big)).unwrap(), big);
+    }
+
+    #[test]
+    fn test_cbor_rejects_what_json_cannot_hold() {
+        let hex = |s: &str| data_encoding::HEXLOWER.decode(s.as_bytes()).unwrap();
+        for invalid in [
+            "a2616101616102",     // duplicate key
+            "a10101",             // integer key
+            "0101",               // trailing bytes
+            "c16161",             // unsupported tag
+            "9f01ff",             // indefinite length
+            "fb7ff8000000000000", // NaN
+            "3bffffffffffffffff", // below i64::MIN
+            "9b00ffffffffffffff", // length beyond the input
+            "f7",                 // undefined
+        ] {
+            assert!(cbor::from_slice(&hex(invalid)).is_err(), "{}", invalid);
+        }
+        let deep = [vec![0x81; 200], vec![0x01]].concat();
+        assert!(cbor::from_slice(&deep).is_err());
+    }
+
+    #[test]
+    fn test_cbor_requests() {
+        let sender = SecretKey::generate();
+        let payload = serde_json::json!({ "content": data_encoding::BASE64.encode(&[1; 64]), "n": 1 });
+        let request = SignedRequest::new_with(&sender, &test_audience(), &payload, unix_time(), Canonicalization::Cbor)
+            .unwrap();
+        assert_eq!(request.version, CBOR_PROTOCOL_VERSION);
+        assert_eq!(request.canonicalization(), Canonicalization::Cbor);
+        assert!(request.message().unwrap().starts_with(b"fastn-request-v4|"));
+
+        // Through the wire and back, the signature still holds
+        let received: SignedRequest = wire::Format::Cbor.decode(&wire::Format::Cbor.encode(&request).unwrap()).unwrap();
+        let (_, received): (String, serde_json::Value) = received.verify().unwrap();
+        assert_eq!(received, payload);
+
+        // It covers the payload as CBOR, so relabeling the version breaks it
+        let mut relabeled = request.clone();
+        relabeled.version = PROTOCOL_VERSION;
+        assert!(relabeled.verify::<serde_json::Value>().is_err());
+        let mut tampered = request.clone();
+        tampered.payload["n"] = serde_json::json!(2);
+        assert!(tampered.verify::<serde_json::Value>().is_err());
+
+        let response = SignedResponse::new_with(&sender, &payload, request.canonicalization()).unwrap();
+        let encoded = wire::Format::Cbor.encode(&response).unwrap();
+        let response: SignedResponse = wire::Format::Cbor.decode(&encoded).unwrap();
+        assert_eq!(response.canonicalization, Canonicalization::Cbor);
+        assert_eq!(response.verify_from::<serde_json::Value>(&sender.id52()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_compression() {
+        let body = br#"{"content":"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"}"#.repeat(100);
+        for encoding in [wire::Encoding::Identity, wire::Encoding::Gzip, wire::Encoding::Zstd] {
+            let compressed = encoding.compress(body.clone()).unwrap();
+            assert!(encoding == wire::Encoding::Identity || compressed.len() < body.len() / 10);
+            assert_eq!(encoding.decompress(compressed).unwrap(), body);
+        }
+
+        // Nothing expands beyond the cap
+        let bomb = vec![0; wire::MAX_DECOMPRESSED_SIZE + 1];
+        for encoding in [wire::Encoding::Gzip, wire::Encoding::Zstd] {
+            let compressed = encoding.compress(bomb.clone()).unwrap();
+            assert!(matches!(encoding.decompress(compressed), Err(Error::Compression(_))));
+        }
+
+        assert_eq!(wire::Encoding::negotiate(Some("gzip, deflate, br, zstd")), wire::Encoding::Zstd);
+        assert_eq!(wire::Encoding::negotiate(Some("zstd;q=0, gzip;q=0.5")), wire::Encoding::Gzip);
+        assert_eq!(wire::Encoding::negotiate(Some("br")), wire::Encoding::Identity);
+        assert_eq!(wire::Encoding::negotiate(None), wire::Encoding::Identity);
+        assert_eq!(wire::Encoding::Gzip.for_body(100), wire::Encoding::Identity);
+        assert!(wire::Encoding::from_header(Some("br")).is_err());
+        assert_eq!(wire::Format::from_content_type(Some("application/json; charset=utf-8")).unwrap(), wire::Format::Json);
+        assert!(matches!(wire::Format::from_content_type(Some("text/plain")), Err(Error::UnsupportedContentType(_))));
+    }
+
+    #[cfg(all(feature = "client", feature = "server"))]
+    async fn serve(app: axum::Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    #[cfg(all(feature = "client", feature = "server"))]
+    #[tokio::test]
+    async fn test_cbor_and_compression_over_http() {
+        let hub_key = SecretKey::generate();
+        let app = server::router(hub_key.clone(), |_sender: String, content: String| {
+            Box::pin(async move { Ok::<_, HubError>(content.repeat(2)) })
+        });
+        let url = serve(app).await;
+
+        // Large enough for both the response and, once the hub has said it
+        // reads them, the request to be compressed
+        let content = data_encoding::BASE64.encode(&[5; 4096]);
+        for canonicalization in [Canonicalization::Jcs, Canonicalization::Cbor] {
+            let client = client::Client::new(SecretKey::generate(), hub_key.id52(), url.clone())
+                .with_canonicalization(canonicalization);
+            for _ in 0..2 {
+                let reply: std::result::Result<String, HubError> = client.call(&content).await.unwrap();
+                assert_eq!(reply.unwrap(), content.repeat(2));
+            }
+        }
+
+        // What the hub sends back
+        let request = SignedRequest::new_with(
+            &SecretKey::generate(),
+            &Audience::new(hub_key.id52(), ENDPOINT),
+            &content,
+            unix_time(),
+            Canonicalization::Cbor,
+        )
+        .unwrap();
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", url, ENDPOINT))
+            .header("Content-Type", wire::CBOR)
+            .header("Accept-Encoding", "gzip")
+            .body(wire::Format::Cbor.encode(&request).unwrap())
+            .send()
+            .await
+            .unwrap();
+        let header = |name| response.headers()[name].to_str().unwrap().to_string();
+        assert_eq!(header("content-type"), wire::CBOR);
+        assert_eq!(header("content-encoding"), "gzip");
+        assert_eq!(header("accept-encoding"), wire::ACCEPTED_ENCODINGS);
+
+        // Formats and encodings the hub doesn't read are refused as such
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", url, ENDPOINT))
+            .header("Content-Type", "application/xml")
+            .body("<request/>")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 415);
+    }
+
+    #[cfg(all(feature = "client", feature = "server"))]
+    #[tokio::test]
+    async fn test_cbor_falls_back_for_json_only_hubs() {
+        use axum::extract::Json;
+
+        // A hub from before CBOR: JSON bodies only, no compression
+        let hub_key = SecretKey::generate();
+        let key = hub_key.clone();
+        let versions = std::sync::Arc::new(Mutex::new(vec![]));
+        let seen = versions.clone();
+        let app = axum::Router::new().route(
+            ENDPOINT,
+            axum::routing::post(move |Json(request): Json<SignedRequest>| {
+                let key = key.clone();
+                let seen = seen.clone();
+                async move {
+                    seen.lock().unwrap().push(request.version);
+                    let (_, message): (String, String) = request.verify().unwrap();
+                    let envelope = ResponseEnvelope::<String, HubError>::Ok(format!("echo {}", message));
+                    Json(SignedResponse::new_with(&key, &envelope, request.canonicalization()).unwrap())
+                }
+            }),
+        );
+        let url = serve(app).await;
+
+        let client = client::Client::new(SecretKey::generate(), hub_key.id52(), url)
+            .with_canonicalization(Canonicalization::Cbor);
+        let content = "a".repeat(2000);
+        for _ in 0..2 {
+            let reply: std::result::Result<String, HubError> = client.call(&content).await.unwrap();
+            assert_eq!(reply.unwrap(), format!("echo {}", content));
+        }
+        // Refused once as CBOR, JSON from then on
+        assert_eq!(*versions.lock().unwrap(), vec![PROTOCOL_VERSION, PROTOCOL_VERSION]);
+    }
+
+    fn test_vectors() -> serde_json::Value {
+        serde_json::from_str(include_str!("../test-vectors.json")).unwrap()
+    }
+
+    #[test]
+    fn test_canonical_json_vectors() {
+        let vectors = test_vectors();
+        for vector in vectors["canonical_json"].as_array().unwrap() {
+            let value: serde_json::Value = serde_json::from_str(vector["input"].as_str().unwrap()).unwrap();
+            assert_eq!(canonical_json::to_string(&value).unwrap(), vector["canonical"], "{}", vector["name"]);
+        }
+        for vector in vectors["not_canonicalizable"].as_array().unwrap() {
+            let value: serde_json::Value = serde_json::from_str(vector["input"].as_str().unwrap()).unwrap();
+            let result = canonical_json::to_string(&value);
+            assert!(matches!(result, Err(Error::CanonicalJson(_))), "{}: {:?}", vector["name"], result);
+        }
+        for vector in vectors["numbers"].as_array().unwrap() {
+            let bits = u64::from_str_radix(vector["bits"].as_str().unwrap(), 16).unwrap();
+            let value = serde_json::Value::from(f64::from_bits(bits));
+            assert_eq!(canonical_json::to_string(&value).unwrap(), vector["canonical"], "{}", vector["bits"]);
+        }
+    }
+
+    #[test]
+    fn test_signature_vectors() {
+        let vectors = test_vectors();
+        let vector = &vectors["signed_request"];
+        let request: SignedRequest = serde_json::from_value(vector["request"].clone()).unwrap();
+        assert_eq!(request.message().unwrap(), vector["message"].as_str().unwrap().as_bytes());
+        request.verify::<serde_json::Value>().unwrap();
+        let sender = SecretKey::from_bytes(&[1; 32]);
+        assert_eq!(request.sender, sender.id52());
+        // Ed25519 signatures are deterministic
+        assert_eq!(data_encoding::BASE64.encode(&sender.sign(&request.message().unwrap())), request.signature);
+
+        let vector = &vectors["signed_response"];
+        let response: SignedResponse = serde_json::from_value(vector["response"].clone()).unwrap();
+        assert_eq!(response.message().unwrap(), vector["message"].as_str().unwrap().as_bytes());
+        response.verify_from::<serde_json::Value>(&SecretKey::from_bytes(&[7; 32]).id52()).unwrap();
+        let resigned = SignedResponse::new_with(&SecretKey::from_bytes(&[7; 32]), &response.payload, response.canonicalization).unwrap();
+        assert_eq!(resigned.signature, response.signature);
+    }
+
+    #[test]
+    fn test_replay_guard_rejects_duplicates() {
+        let key = SecretKey::generate();
+        let guard = ReplayGuard::new();
+        let now = 1_735_053_045;
+        let signed = SignedRequest::new_at(&key, &test_audience(), &serde_json::json!({"op": "write"}), now).unwrap();
+
+        guard.check_at(&signed, now).unwrap();
+        let replayed = guard.check_at(&signed, now + 10);
+        assert!(matches!(replayed, Err(Error::ReplayedRequest(_))), "got {:?}", replayed);
+
+        // Same payload, new nonce: a different request
+        let again = SignedRequest::new_at(&key, &test_audience(), &serde_json::json!({"op": "write"}), now).unwrap();
+        guard.check_at(&again, now + 10).unwrap();
+
+        // Another sender's nonces are tracked separately
+        let mut other = SignedRequest::new_at(&SecretKey::generate(), &test_audience(), &serde_json::json!({}), now).unwrap();
+        other.nonce = signed.nonce.clone();
+        guard.check_at(&other, now).unwrap();
+    }
+
+    #[test]
+    fn test_replay_guard_rejects_stale_requests() {
+        let key = SecretKey::generate();
+        let guard = ReplayGuard::new();
+        let now = 1_735_053_045;
+        let signed = SignedRequest::new_at(&key, &test_audience(), &serde_json::json!({}), now).unwrap();
+
+        // Once the window has passed the request is rejected by its timestamp
+        let late = guard.check_at(&signed, now + MAX_CLOCK_SKEW_SECS + 1);
+        assert!(matches!(late, Err(Error::StaleRequest { .. })), "got {:?}", late);
+        let early = guard.check_at(&signed, now - MAX_CLOCK_SKEW_SECS - 1);
+        assert!(matches!(early, Err(Error::StaleRequest { .. })), "got {:?}", early);
+
+        // Stale rejections tell the client the server's time
+        let rejected = RejectedRequest::from(&late.unwrap_err());
+        assert_eq!(rejected.server_time, Some(now + MAX_CLOCK_SKEW_SECS + 1));
+        let rejected = RejectedRequest::from(&Error::VerificationFailed);
+        assert_eq!(serde_json::to_value(&rejected).unwrap(), serde_json::json!({"error": "Signature verification failed"}));
+
+        let mut long_nonce = signed.clone();
+        long_nonce.nonce = "a".repeat(MAX_NONCE_LEN + 1);
+        assert!(matches!(guard.check_at(&long_nonce, now), Err(Error::InvalidNonce)));
+    }
+
+    #[test]
+    fn test_replay_window_is_pruned() {
+        let key = SecretKey::generate();
+        let guard = ReplayGuard::new();
+        let start = 1_735_053_045;
+        for i in 0..10 {
+            let signed = SignedRequest::new_at(&key, &test_audience(), &serde_json::json!({}), start + i * 100).unwrap();
+            guard.check_at(&signed, start + i * 100).unwrap();
+        }
+        let seen = guard.seen.lock().unwrap();
+        let window = &seen[&key.id52()];
+        assert!(window.len() <= (MAX_CLOCK_SKEW_SECS / 100 + 1) as usize, "{} nonces kept", window.len());
+    }
+
+    #[test]
+    fn test_derived_identity() {
+        let key = SecretKey::generate();
+        let inbox = key.derive("app:inbox").unwrap();
+
+        // Deterministic, distinct per path and from the parent
+        assert_eq!(inbox.id52(), key.derive("app:inbox").unwrap().id52());
+        assert_ne!(inbox.id52(), key.derive("app:outbox").unwrap().id52());
+        assert_ne!(inbox.id52(), key.id52());
+        assert_eq!(inbox.id52(), inbox.secret_key().id52());
+        assert_eq!(inbox.name(), format!("{}/app:inbox", key.id52()));
+
+        let signed = inbox.sign(b"hello");
+        assert_eq!(signed.verify(b"hello").unwrap().path, "app:inbox");
+        signed.verify_from(b"hello", &key.id52(), "app:inbox").unwrap();
+        assert!(matches!(signed.verify(b"bye"), Err(Error::VerificationFailed)));
+
+        // Scoped verification rejects other identities
+        let other = SecretKey::generate();
+        assert!(matches!(
+            signed.verify_from(b"hello", &other.id52(), "app:inbox"),
+            Err(Error::WrongIdentity { .. })
+        ));
+        assert!(matches!(
+            key.derive("app:outbox").unwrap().sign(b"hello").verify_from(b"hello", &key.id52(), "app:inbox"),
+            Err(Error::WrongIdentity { .. })
+        ));
+
+        // A certificate can't be forged for another key or rebound to another path
+        let mut forged = other.derive("app:inbox").unwrap().sign(b"hello");
+        forged.certificate.parent = key.id52();
+        assert!(matches!(forged.verify(b"hello"), Err(Error::VerificationFailed)));
+        let mut rebound = signed.clone();
+        rebound.certificate.path = "app:admin".to_string();
+        assert!(matches!(rebound.verify(b"hello"), Err(Error::VerificationFailed)));
+
+        assert!(matches!(key.derive(""), Err(Error::InvalidDerivationPath(_))));
+        assert!(matches!(key.derive("a|b"), Err(Error::InvalidDerivationPath(_))));
+        assert!(matches!(key.derive("app inbox"), Err(Error::InvalidDerivationPath(_))));
+        assert!(key.derive(&"a".repeat(MAX_DERIVATION_PATH_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_push_event_topics() {
+        let kosha = |instance: &str| Topic::Kosha {
+            instance: instance.to_string(),
+        };
+        let changed = PushEvent::FileChanged {
+            kosha: "notes".to_string(),
+            path: "a.txt".to_string(),
+        };
+        assert!(changed.matches(&[kosha("work"), kosha("notes")]));
+        assert!(!changed.matches(&[kosha("work"), Topic::Spokes]));
+
+        let authorized = PushEvent::SpokeAuthorized {
+            spoke_id52: "x".to_string(),
+            alias: "phone".to_string(),
+        };
+        assert!(authorized.matches(&[Topic::Spokes]));
+        assert!(!authorized.matches(&[kosha("notes")]));
+        assert!(PushEvent::Missed { count: 3 }.matches(&[]));
+
+        let json = serde_json::to_value(&changed).unwrap();
+        assert_eq!(json["event"], "file_changed");
+        let json = serde_json::to_value(Subscribe { topics: vec![kosha("notes"), Topic::Spokes] }).unwrap();
+        assert_eq!(json["topics"][0]["topic"], "kosha");
+        assert_eq!(json["topics"][1]["topic"], "spokes");
+    }
+
+    #[test]
+    fn test_file_change_topics() {
+        let files = |instance: &str, path_prefix: &str| Topic::Files {
+            instance: instance.to_string(),
+            path_prefix: path_prefix.to_string(),
+        };
+        let renamed = PushEvent::FileChange {
+            kosha: "notes".to_string(),
+            change: FileChange {
+                path: "archive/a.txt".to_string(),
+                kind: FileChangeKind::Renamed {
+                    from: "docs/a.txt".to_string(),
+                },
+                version: "2026-01-01T00:00:00Z".to_string(),
+                size: 3,
+            },
+        };
+        assert!(renamed.matches(&[files("notes", "")]));
+        assert!(renamed.matches(&[files("notes", "/docs/")]));
+        assert!(renamed.matches(&[files("notes", "archive/a.txt")]));
+        assert!(!renamed.matches(&[files("notes", "doc"), files("work", "docs")]));
+        // Kosha topics keep getting the coarser `file_changed` only
+        assert!(!renamed.matches(&[Topic::Kosha {
+            instance: "notes".to_string()
+        }]));
+
+        let json = serde_json::to_value(&renamed).unwrap();
+        assert_eq!(json["event"], "file_change");
+        assert_eq!(json["change"]["kind"], "renamed");
+        assert_eq!(json["change"]["from"], "docs/a.txt");
+        let topic: Topic = serde_json::from_value(serde_json::json!({ "topic": "files", "instance": "notes" })).unwrap();
+        assert_eq!(topic, files("notes", ""));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_over_websocket() {
+        let hub_key = SecretKey::from_bytes(&[7; 32]);
+        let session_key = hub_key.clone();
+        let (pong_tx, pong_rx) = std::sync::mpsc::channel();
+        let app = axum::Router::new().route(
+            WS_ENDPOINT,
+            axum::routing::get(move |upgrade: axum::extract::ws::WebSocketUpgrade| {
+                let key = session_key.clone();
+                let pong_tx = pong_tx.clone();
+                async move {
+                    ws::accept(upgrade, move |mut socket| async move {
+                        use axum::extract::ws::Message;
+
+                        let Some(Ok(Message::Text(text))) = socket.recv().await else {
+                            panic!("Expected the handshake");
+                        };
+                        let signed: SignedRequest = serde_json::from_str(&text).unwrap();
+                        let (_, subscribe): (String, Subscribe) = signed.verify().unwrap();
+                        signed.check_audience(&Audience::new(key.id52(), WS_ENDPOINT)).unwrap();
+                        assert_eq!(subscribe.topics, vec![Topic::Spokes]);
+
+                        let ack = SignedResponse::new(&key, &ResponseEnvelope::<(), HubError>::Ok(())).unwrap();
+                        socket.send(Message::Text(serde_json::to_string(&ack).unwrap().into())).await.unwrap();
+                        socket.send(Message::Ping(b"alive".to_vec().into())).await.unwrap();
+                        let event = PushEvent::SpokeAuthorized {
+                            spoke_id52: "spoke".to_string(),
+                            alias: "phone".to_string(),
+                        };
+                        let event = SignedResponse::new(&key, &event).unwrap();
+                        socket.send(Message::Text(serde_json::to_string(&event).unwrap().into())).await.unwrap();
+
+                        // The client answers pings while waiting for events
+                        pong_tx.send(socket.recv().await.unwrap().unwrap()).unwrap();
+                        socket.send(Message::Close(None)).await.unwrap();
+                    })
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = client::Client::new(SecretKey::generate(), hub_key.id52(), url);
+        let subscribe = Subscribe { topics: vec![Topic::Spokes] };
+        let mut subscription = client
+            .subscribe::<_, PushEvent, HubError>(&subscribe)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            subscription.next().await.unwrap(),
+            Some(PushEvent::SpokeAuthorized {
+                spoke_id52: "spoke".to_string(),
+                alias: "phone".to_string(),
+            })
+        );
+        assert_eq!(subscription.next().await.unwrap(), None);
+        assert_eq!(pong_rx.recv().unwrap(), axum::extract::ws::Message::Pong(b"alive".to_vec().into()));
+    }
+
+    /// Answers every request in-process, as the hub with `key`
+    #[cfg(feature = "client")]
+    struct LoopbackTransport {
+        key: SecretKey,
+        calls: AtomicU64,
+    }
+
+    #[cfg(feature = "client")]
+    impl Transport for LoopbackTransport {
+        fn name(&self) -> &'static str {
+            "loopback"
+        }
+
+        fn supports(&self, address: &HubAddress) -> bool {
+            address.iroh.is_some()
+        }
+
+        fn send_signed<'a>(&'a self, _address: &'a HubAddress, request: &'a SignedRequest) -> BoxFuture<'a, Result<Reply>> {
+            Box::pin(async move {
+                self.calls.fetch_add(1, Ordering::Relaxed);
+                let (_, message): (String, String) = request.verify()?;
+                let envelope = ResponseEnvelope::<String, HubError>::Ok(format!("echo {}", message));
+                Ok(Reply::Response(SignedResponse::new(&self.key, &envelope)?))
+            })
+        }
+    }
+
+    /// Never gets through
+    #[cfg(feature = "client")]
+    struct DownTransport {
+        calls: AtomicU64,
+    }
+
+    #[cfg(feature = "client")]
+    impl Transport for DownTransport {
+        fn name(&self) -> &'static str {
+            "down"
+        }
+
+        fn supports(&self, _address: &HubAddress) -> bool {
+            true
+        }
+
+        fn send_signed<'a>(&'a self, _address: &'a HubAddress, _request: &'a SignedRequest) -> BoxFuture<'a, Result<Reply>> {
+            Box::pin(async move {
+                self.calls.fetch_add(1, Ordering::Relaxed);
+                Err(Error::HttpRequest("connection refused".to_string()))
+            })
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_transport_fallback() {
+        use std::sync::Arc;
+
+        let hub_key = SecretKey::generate();
+        let loopback = Arc::new(LoopbackTransport {
+            key: hub_key.clone(),
+            calls: AtomicU64::new(0),
+        });
+        let down = Arc::new(DownTransport { calls: AtomicU64::new(0) });
+        let address = HubAddress {
+            url: None,
+            iroh: Some("endpoint".to_string()),
+        };
+        let client = client::Client::with_address(SecretKey::generate(), hub_key.id52(), address)
+            .with_transport(loopback.clone())
+            .with_transport(down.clone());
+
+        // The first choice is down, the next one answers
+        let reply: std::result::Result<String, HubError> = client.call(&"hi".to_string()).await.unwrap();
+        assert_eq!(reply.unwrap(), "echo hi");
+        assert_eq!(down.calls.load(Ordering::Relaxed), 1);
+
+        // ... and is tried first from then on
+        let reply: std::result::Result<String, HubError> = client.call(&"again".to_string()).await.unwrap();
+        assert_eq!(reply.unwrap(), "echo again");
+        assert_eq!(down.calls.load(Ordering::Relaxed), 1);
+        assert_eq!(loopback.calls.load(Ordering::Relaxed), 2);
+
+        // HTTP needs a URL, and nothing else can reach a URL-only hub
+        let client = client::Client::new(SecretKey::generate(), hub_key.id52(), "http://127.0.0.1:9".to_string())
+            .with_transport(loopback.clone());
+        let result: Result<std::result::Result<String, HubError>> = client.call(&"hi".to_string()).await;
+        assert!(matches!(result, Err(Error::HttpRequest(_))), "{:?}", result);
+        let client = client::Client::with_address(SecretKey::generate(), hub_key.id52(), HubAddress::default());
+        let result: Result<std::result::Result<String, HubError>> = client.call(&"hi".to_string()).await;
+        assert!(matches!(result, Err(Error::NoTransport(_))), "{:?}", result);
+    }
+
+    /// Never answers
+    #[cfg(feature = "client")]
+    struct HungTransport {
+        calls: AtomicU64,
+    }
+
+    #[cfg(feature = "client")]
+    impl Transport for HungTransport {
+        fn name(&self) -> &'static str {
+            "hung"
+        }
+
+        fn supports(&self, _address: &HubAddress) -> bool {
+            true
+        }
+
+        fn send_signed<'a>(&'a self, _address: &'a HubAddress, _request: &'a SignedRequest) -> BoxFuture<'a, Result<Reply>> {
+            Box::pin(async move {
+                self.calls.fetch_add(1, Ordering::Relaxed);
+                std::future::pending().await
+            })
+        }
+    }
+
+    #[cfg(feature = "client")]
+    fn quick_retries() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            initial_backoff: std::time::Duration::from_millis(1),
+            max_backoff: std::time::Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "client")]
+    fn test_retry_backoff() {
+        use std::time::Duration;
+
+        let policy = RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        let waits: Vec<_> = (0..5).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(waits, [100, 200, 400, 800, 1000].map(Duration::from_millis));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_timeouts_and_retries() {
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let hub_key = SecretKey::generate();
+        let address = HubAddress {
+            url: None,
+            iroh: Some("endpoint".to_string()),
+        };
+        let hung = Arc::new(HungTransport { calls: AtomicU64::new(0) });
+        let client = client::Client::with_address(SecretKey::generate(), hub_key.id52(), address.clone())
+            .with_transport(hung.clone())
+            .with_timeout(Some(Duration::from_millis(20)))
+            .with_retry_policy(quick_retries());
+
+        // Plain calls get one attempt, idempotent ones are retried
+        let result: Result<std::result::Result<String, HubError>> = client.call(&"hi".to_string()).await;
+        assert!(matches!(result, Err(Error::Timeout(_))), "{:?}", result);
+        assert_eq!(hung.calls.load(Ordering::Relaxed), 1);
+        let result: Result<std::result::Result<String, HubError>> = client.call_idempotent(&"hi".to_string()).await;
+        assert!(matches!(result, Err(Error::Timeout(_))), "{:?}", result);
+        assert_eq!(hung.calls.load(Ordering::Relaxed), 4);
+
+        // Transport errors are retried too, and a later attempt can succeed
+        let down = Arc::new(DownTransport { calls: AtomicU64::new(0) });
+        let client = client::Client::with_address(SecretKey::generate(), hub_key.id52(), address.clone())
+            .with_transport(down.clone())
+            .with_retry_policy(quick_retries());
+        let result: Result<std::result::Result<String, HubError>> = client.call_idempotent(&"hi".to_string()).await;
+        assert!(matches!(result, Err(Error::HttpRequest(_))), "{:?}", result);
+        assert_eq!(down.calls.load(Ordering::Relaxed), 3);
+
+        let loopback = Arc::new(LoopbackTransport {
+            key: hub_key.clone(),
+            calls: AtomicU64::new(0),
+        });
+        let client = client::Client::with_address(SecretKey::generate(), hub_key.id52(), address)
+            .with_transport(loopback.clone())
+            .with_retry_policy(quick_retries());
+        let reply: std::result::Result<String, HubError> = client.call_idempotent(&"hi".to_string()).await.unwrap();
+        assert_eq!(reply.unwrap(), "echo hi");
+        assert_eq!(loopback.calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_cancel_call() {
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let hub_key = SecretKey::generate();
+        let address = HubAddress {
+            url: None,
+            iroh: Some("endpoint".to_string()),
+        };
+        let hung = Arc::new(HungTransport { calls: AtomicU64::new(0) });
+        let client = client::Client::with_address(SecretKey::generate(), hub_key.id52(), address)
+            .with_transport(hung.clone())
+            .with_timeout(None);
+
+        let message = "hi".to_string();
+        let (cancel, call) = client.call_cancellable::<_, String, HubError>(&message, true);
+        let cancel_soon = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            cancel.cancel();
+        };
+        let (result, ()) = tokio::join!(call, cancel_soon);
+        assert!(matches!(result, Err(Error::Cancelled)), "{:?}", result);
+        assert!(cancel.is_cancelled());
+        assert_eq!(hung.calls.load(Ordering::Relaxed), 1);
+
+        // Cancelled before it starts, the call never goes out
+        let (cancel, call) = client.call_cancellable::<_, String, HubError>(&message, false);
+        cancel.cancel();
+        assert!(matches!(call.await, Err(Error::Cancelled)));
+        assert_eq!(hung.calls.load(Ordering::Relaxed), 1);
+    }
 }
@@ -50,6 +50,27 @@ pub type TextureId = String;
 /// Unique identifier for timers
 pub type TimerId = String;
 
+/// Unique identifier for portals
+pub type PortalId = String;
+
+/// Unique identifier for panels
+pub type PanelId = String;
+
+/// Unique identifier for deferred commands, echoed back in their acks
+pub type CommandId = String;
+
+/// Unique identifier for photo and video captures
+pub type CaptureId = String;
+
+/// Unique identifier for sound sources
+pub type AudioSourceId = String;
+
+/// Unique identifier for loaded sound files
+pub type SoundId = String;
+
+/// Unique identifier for dynamic lights
+pub type LightId = String;
+
 // ============================================================================
 // EVENTS (Shell -> Core)
 // ============================================================================
@@ -72,8 +93,20 @@ pub enum Event {
     Network(NetworkEvent),
     /// Media streaming events
     Media(MediaEvent),
+    /// Sound loading and playback events
+    Audio(AudioEvent),
     /// Timer events
     Timer(TimerEvent),
+    /// Persistent key-value storage events
+    Storage(StorageEvent),
+    /// Files read from koshas through the shell's spoke
+    Kosha(KoshaEvent),
+    /// Acks for deferred commands
+    Schedule(ScheduleEvent),
+    /// Assistive technology interaction with the accessibility tree
+    Accessibility(AccessibilityEvent),
+    /// Pointer input on panels
+    Panel(PanelEvent),
 }
 
 // ----------------------------------------------------------------------------
@@ -89,6 +122,8 @@ pub enum LifecycleEvent {
     Frame(FrameEvent),
     /// Viewport/window resized
     Resize(ResizeEvent),
+    /// The user changed their accessibility settings
+    AccessibilityChanged(AccessibilityPreferences),
     /// Application going to background
     Pause,
     /// Application resuming from background
@@ -109,8 +144,154 @@ pub struct InitEvent {
     pub webrtc_supported: bool,
     pub websocket_supported: bool,
     pub features: Vec<String>,
+    #[serde(default)]
+    pub accessibility: AccessibilityPreferences,
+    /// The shell's coordinate system; shells that don't say use the core's
+    #[serde(default)]
+    pub conventions: Conventions,
+    /// Camera to start from instead of the app's default, for shells that
+    /// reload the app in place (e.g. after a rebuild during development)
+    #[serde(default)]
+    pub camera: Option<CameraData>,
+}
+
+/// Platform accessibility settings. The framework's default behaviors
+/// respect them, and apps are expected to as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessibilityPreferences {
+    /// Prefer strong colors and opaque surfaces
+    pub high_contrast: bool,
+    /// Avoid animations, fades and other non-essential motion
+    pub reduced_motion: bool,
+    /// A screen reader is known to be running
+    pub screen_reader: bool,
+}
+
+/// Coordinate system conventions: which way the axes point and how big a
+/// unit is.
+///
+/// The core works right-handed, +Y up, in meters (glTF's conventions; the
+/// default). A shell declares its own in `InitEvent::conventions` and the
+/// core converts every position, rotation, scale, direction and length it
+/// sends, and those it receives in events, between the two. In every
+/// convention +X points right; the third axis points toward the viewer in
+/// right-handed Y-up and left-handed Z-up systems and away from them in the
+/// other two.
+///
+/// Geometry follows the axes: a `Primitive::Box`'s height and a `Cylinder`'s
+/// axis run along the up axis, and shells load assets into their own
+/// conventions (as their glTF loaders do). Sizes of primitives and assets
+/// are not converted; the unit change is part of the volume's scale. Bone
+/// transforms are in the asset's own space and pass through unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Conventions {
+    pub handedness: Handedness,
+    pub up_axis: UpAxis,
+    /// How many meters one unit is, e.g. 0.01 for centimeters
+    pub meters_per_unit: f32,
+}
+
+impl Conventions {
+    /// Right-handed, Y-up, meters: the core's own conventions, and glTF's
+    pub const CORE: Conventions = Conventions {
+        handedness: Handedness::Right,
+        up_axis: UpAxis::Y,
+        meters_per_unit: 1.0,
+    };
+}
+
+impl Default for Conventions {
+    fn default() -> Self {
+        Self::CORE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Handedness {
+    Right,
+    Left,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+/// `InitEvent::features` entry: the shell can show HTML UI during AR sessions
+pub const FEATURE_XR_DOM_OVERLAY: &str = "xr-dom-overlay";
+
+/// `InitEvent::features` entry: the shell draws `PanelCommand` panels with
+/// SVG and widget content, and sends `PanelEvent`s for them
+pub const FEATURE_PANELS: &str = "panels";
+
+/// `InitEvent::features` entry: the shell also draws `PanelContent::Html`
+pub const FEATURE_HTML_PANELS: &str = "html-panels";
+
+/// `InitEvent::features` entry: the shell renders the view through portals
+pub const FEATURE_PORTALS: &str = "portals";
+
+/// `InitEvent::features` entry: the shell understands `ScheduleCommand`
+pub const FEATURE_COMMAND_SCHEDULING: &str = "command-scheduling";
+
+/// `InitEvent::features` entry: the shell exposes `AccessibilityCommand`s to
+/// assistive technology
+pub const FEATURE_ACCESSIBILITY_TREE: &str = "accessibility-tree";
+
+/// `InitEvent::features` entry: the shell shows the debug HUD
+/// (`DebugCommand::SetHudVisible`)
+pub const FEATURE_DEBUG_HUD: &str = "debug-hud";
+
+/// `InitEvent::features` entry: the shell answers `SceneCommand::RequestHitTest`
+pub const FEATURE_HIT_TEST: &str = "hit-test";
+
+/// `InitEvent::features` entry: the shell saves photos and clips for
+/// `MediaCommand::Capture`
+pub const FEATURE_CAPTURE: &str = "capture";
+
+/// `InitEvent::features` entry: the shell streams the composited view for
+/// `MediaSource::Viewfinder`, so it can be shown on a texture
+pub const FEATURE_VIEWFINDER: &str = "viewfinder";
+
+/// `InitEvent::features` entry: the shell animates `SceneCommand::SetTransform`,
+/// sends `SceneEvent::VolumeReady` for every volume it creates and
+/// `SceneEvent::VolumeAnimationComplete` when a named transform animation ends
+pub const FEATURE_TRANSFORM_ANIMATION: &str = "transform-animation";
+
+/// `InitEvent::features` entry: the shell plays `AudioCommand` sources with
+/// the gain, filter and direction the core mixes for them
+pub const FEATURE_SPATIAL_AUDIO: &str = "spatial-audio";
+
+/// `InitEvent::features` entry: the shell draws `Primitive::Text`
+pub const FEATURE_TEXT: &str = "text";
+
+/// `InitEvent::features` entry: the shell has a spoke connected to a hub and
+/// answers `KoshaCommand`s
+pub const FEATURE_KOSHA: &str = "kosha";
+
+/// `InitEvent::features` entry: the shell lights each volume with the point
+/// lights the core assigns it (`EnvironmentCommand::AssignLights`) and
+/// renders shadows for the one `SetShadowCaster` names
+pub const FEATURE_DYNAMIC_LIGHTS: &str = "dynamic-lights";
+
+/// `InitEvent::features` entry: the shell places volumes relative to their
+/// parent (`CreateVolumeData::parent_id`, `SceneCommand::SetParent`)
+pub const FEATURE_SCENE_HIERARCHY: &str = "scene-hierarchy";
+
+/// `InitEvent::features` entry: `BackgroundData::Transparent` shows what's
+/// behind the app (the desktop behind the window, the headset's passthrough
+/// cameras) instead of black
+pub const FEATURE_TRANSPARENT_BACKGROUND: &str = "transparent-background";
+
+/// `InitEvent::features` entry: the shell recognizes gestures itself and
+/// sends `XrEvent::Gesture`s, so the core doesn't make its own from raw input
+pub const FEATURE_GESTURES: &str = "gestures";
+
+/// `InitEvent::features` entry: the shell keeps network assets across
+/// sessions (see `CachePolicy`)
+pub const FEATURE_ASSET_CACHE: &str = "asset-cache";
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Platform {
     WebGL,
@@ -299,7 +480,10 @@ pub struct GamepadInputData {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum XrEvent {
-    SessionChanged(XrSessionState),
+    SessionChanged(XrSessionData),
+    /// The tracking origin jumped (e.g. user recentered); poses before and
+    /// after are not continuous
+    ReferenceSpaceReset,
     HeadPose(PoseData),
     ControllerPose(XrControllerData),
     HandPose(XrHandData),
@@ -307,6 +491,39 @@ pub enum XrEvent {
     Gesture(XrGestureData),
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XrSessionData {
+    pub state: XrSessionState,
+    /// Mode of the requested/running session, `None` once it has ended
+    #[serde(default)]
+    pub mode: Option<XrMode>,
+    /// Whether 2D HTML UI is composited over the session (WebXR DOM overlay)
+    #[serde(default)]
+    pub dom_overlay: bool,
+    /// The area the user can walk in, if the runtime knows it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub play_area: Option<PlayArea>,
+}
+
+/// A rectangle on the floor centered on the tracking origin: `width` along
+/// X, `depth` along the other floor axis, in the shell's units.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlayArea {
+    pub width: f32,
+    pub depth: f32,
+}
+
+impl PlayArea {
+    /// Whether a point given relative to the tracking origin (X and the
+    /// other floor axis) is inside the area
+    pub fn contains(&self, x: f32, z: f32) -> bool {
+        x.abs() <= self.width / 2.0 && z.abs() <= self.depth / 2.0
+    }
+}
+
+/// Session transitions: `None -> Starting -> Active <-> Paused -> Ending -> None`.
+/// A request the user or browser refuses goes straight from `Starting` back
+/// to `None`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum XrSessionState {
     None,
@@ -337,9 +554,14 @@ pub enum Hand {
     Right,
 }
 
+/// Index of the index finger's tip in `XrHandData::joints`
+pub const HAND_JOINT_INDEX_TIP: usize = 10;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct XrHandData {
     pub hand: Hand,
+    /// In the OpenXR order (`XR_HAND_JOINT_*_EXT`): palm, wrist, then each
+    /// finger from the thumb, base to tip; 26 in all
     pub joints: Vec<PoseData>,
     pub pinch_strength: f32,
 }
@@ -350,11 +572,43 @@ pub struct GazeData {
     pub direction: [f32; 3],
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct XrGestureData {
     pub gesture: XrGesture,
     pub hand: Option<Hand>,
+    /// World-space position, for hand gestures
     pub position: Option<[f32; 3]>,
+    /// Continuous gestures (drags, pinches) begin, change and end;
+    /// discrete ones (taps, long presses) only end
+    #[serde(default)]
+    pub phase: GesturePhase,
+    /// The volume under where the gesture started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume_id: Option<VolumeId>,
+    /// Position on screen, for mouse and touch gestures (logical pixels
+    /// from the top-left corner)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen: Option<[f32; 2]>,
+    /// Drags: world-space movement since the gesture began, for hands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<[f32; 3]>,
+    /// Drags: movement on screen since the gesture began, for mouse and
+    /// touch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen_translation: Option<[f32; 2]>,
+    /// Pinches: distance between the two touches (or hands) over what it
+    /// was when the gesture began
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GesturePhase {
+    Began,
+    Changed,
+    #[default]
+    Ended,
+    Cancelled,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -389,6 +643,11 @@ pub struct AssetLoadedData {
     pub meshes: Vec<MeshInfo>,
     pub animations: Vec<AnimationInfo>,
     pub skeletons: Vec<SkeletonInfo>,
+    /// Conventions the file declares (glTF is always `Conventions::CORE`,
+    /// USD stages carry `upAxis` and `metersPerUnit`), if the shell knows
+    /// them. The core warns when they differ from the shell's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conventions: Option<Conventions>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -440,8 +699,22 @@ pub struct BoneInfo {
 pub enum SceneEvent {
     VolumeReady { volume_id: VolumeId },
     VolumeAnimationComplete { volume_id: VolumeId, animation_id: String },
+    /// Answer to `SceneCommand::RequestHitTest`: the nearest volume the ray
+    /// hit, if any
+    HitTestResult { request_id: String, hit: Option<Hit> },
     TextureReady { texture_id: TextureId },
     TextureError { texture_id: TextureId, error: String },
+    /// Two entities' colliders started touching. Physics runs in the core,
+    /// which raises this for its own callbacks; shells don't send it.
+    CollisionStarted(CollisionData),
+    /// Two entities' colliders stopped touching (raised by the core)
+    CollisionEnded(CollisionData),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollisionData {
+    pub entity_a: VolumeId,
+    pub entity_b: VolumeId,
 }
 
 // ----------------------------------------------------------------------------
@@ -516,6 +789,47 @@ pub enum MediaEvent {
     StreamReady { media_id: MediaId, tracks: Vec<MediaTrackInfo> },
     StreamEnded { media_id: MediaId },
     FrameAvailable { media_id: MediaId },
+    /// A `MediaCommand::Capture` was saved to device storage
+    CaptureSaved(CaptureSavedData),
+    /// A `MediaCommand::Capture` could not be taken or saved
+    CaptureFailed(CaptureFailedData),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureSavedData {
+    pub capture_id: CaptureId,
+    pub kind: CaptureKind,
+    /// Where the shell saved it: a file path, or on web the name of the
+    /// download
+    pub path: String,
+    /// e.g. `image/png`, `video/webm`
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    /// Length of a video
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u32>,
+    /// Whether the camera's view is in it, or only the virtual content
+    pub passthrough: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureFailedData {
+    pub capture_id: CaptureId,
+    pub reason: CaptureFailure,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptureFailure {
+    /// The user or the platform refused camera or storage access
+    PermissionDenied,
+    /// The shell can't capture this kind of media
+    Unsupported,
+    /// Another video capture is running
+    Busy,
+    /// Capturing or saving went wrong
+    Failed,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -526,6 +840,21 @@ pub struct MediaTrackInfo {
     pub height: Option<u32>,
 }
 
+// ----------------------------------------------------------------------------
+// Audio Events
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AudioEvent {
+    /// Response to `AudioCommand::Load`
+    Loaded { sound_id: SoundId, duration_ms: u32 },
+    /// A source that doesn't loop played to the end
+    Ended { source_id: AudioSourceId },
+    /// A sound could not be loaded or played
+    Error { sound_id: SoundId, error: String },
+}
+
 // ----------------------------------------------------------------------------
 // Timer Events
 // ----------------------------------------------------------------------------
@@ -536,6 +865,91 @@ pub enum TimerEvent {
     Fired { timer_id: TimerId },
 }
 
+// ----------------------------------------------------------------------------
+// Storage Events
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum StorageEvent {
+    /// Response to `StorageCommand::Get`; `value` is None if the key is unset
+    Loaded { key: String, value: Option<String> },
+    Error { key: String, error: String },
+}
+
+// ----------------------------------------------------------------------------
+// Kosha Events
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum KoshaEvent {
+    /// Content of a file watched with `KoshaCommand::Watch`, sent when the
+    /// watch starts and after every change; `content` is None if the file
+    /// doesn't exist (or was deleted)
+    FileLoaded { uri: String, content: Option<String> },
+    Error { uri: String, error: String },
+}
+
+// ----------------------------------------------------------------------------
+// Schedule Events
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ScheduleEvent {
+    /// A deferred command finished executing (including any asset load it
+    /// started)
+    Completed { command_id: CommandId },
+    Failed { command_id: CommandId, error: String },
+}
+
+// ----------------------------------------------------------------------------
+// Accessibility Events
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AccessibilityEvent {
+    /// The user activated a node (e.g. a screen reader "click" on a button)
+    Activate { volume_id: VolumeId },
+}
+
+// ----------------------------------------------------------------------------
+// Panel Events
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PanelEvent {
+    /// A pointer (mouse, touch, controller) pressed, moved or released over
+    /// a panel. After a press on a panel, moves and the release go to that
+    /// panel wherever the pointer is.
+    Pointer(PanelPointerData),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PanelPointerData {
+    pub panel_id: PanelId,
+    pub phase: PointerPhase,
+    /// Position in panel pixels (see `CreatePanelData::pixels_per_meter`)
+    /// from the panel's top-left corner; outside the panel for drags and
+    /// releases away from it
+    pub x: f32,
+    pub y: f32,
+    /// ID of the content under the pointer: the `Widget::Button`, or the
+    /// innermost element with an `id` in HTML and SVG content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PointerPhase {
+    Down,
+    Move,
+    Up,
+}
+
 // ============================================================================
 // COMMANDS (Core -> Shell)
 // ============================================================================
@@ -562,6 +976,18 @@ pub enum Command {
     Network(NetworkCommand),
     /// Media commands
     Media(MediaCommand),
+    /// Spatial audio sources and their per-frame mix
+    Audio(AudioCommand),
+    /// Persistent key-value storage commands
+    Storage(StorageCommand),
+    /// Reading koshas through the shell's spoke
+    Kosha(KoshaCommand),
+    /// Deferred execution across frames
+    Schedule(ScheduleCommand),
+    /// Accessibility tree and screen-reader output
+    Accessibility(AccessibilityCommand),
+    /// 2D panels placed in the scene
+    Panel(PanelCommand),
     /// Debug/logging commands
     Debug(DebugCommand),
 }
@@ -573,11 +999,90 @@ pub enum Command {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "action")]
 pub enum AssetCommand {
-    Load { asset_id: AssetId, path: String },
+    /// `path` is an asset URI (see `AssetScheme`), e.g. `bundle://robot.glb`.
+    /// Shells load `High` and `Normal` assets in order as they come, and
+    /// `Low` ones (prefetches) after them, when they have time.
+    Load {
+        asset_id: AssetId,
+        path: String,
+        #[serde(default)]
+        priority: Priority,
+        #[serde(default)]
+        cache: CachePolicy,
+    },
     Cancel { asset_id: AssetId },
     Unload { asset_id: AssetId },
 }
 
+/// How a shell with `FEATURE_ASSET_CACHE` uses its cache (on disk, or in
+/// IndexedDB on web) for an asset it fetches over the network. Copies are
+/// kept by URL and ETag, and the least recently used are evicted once the
+/// cache outgrows its size limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CachePolicy {
+    /// Follow the server's `Cache-Control`: reuse a copy while `max-age`
+    /// lasts, then revalidate it (`If-None-Match`/`If-Modified-Since`).
+    /// Nothing is kept of `no-store` responses.
+    #[default]
+    Default,
+    /// Revalidate a cached copy every time before using it
+    Revalidate,
+    /// Neither use nor keep a cached copy
+    NoStore,
+}
+
+/// URI schemes for asset references, so the same reference works on every
+/// shell:
+///
+/// - `bundle://models/robot.glb` - shipped with the app (the `assets/` dir)
+/// - `file:///abs/path/robot.glb` - local filesystem (native shells)
+/// - `http://...`, `https://...` - fetched over the network
+/// - `kosha://<hub>/<kosha>/<path>` - a file in a kosha, via the spoke's hub
+///
+/// Shells list the schemes they can load in `InitEvent::features` as
+/// `asset-scheme:<scheme>` (see `asset_scheme_feature`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetScheme {
+    Bundle,
+    File,
+    Http,
+    Https,
+    Kosha,
+}
+
+impl AssetScheme {
+    pub const ALL: [AssetScheme; 5] = [
+        AssetScheme::Bundle,
+        AssetScheme::File,
+        AssetScheme::Http,
+        AssetScheme::Https,
+        AssetScheme::Kosha,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssetScheme::Bundle => "bundle",
+            AssetScheme::File => "file",
+            AssetScheme::Http => "http",
+            AssetScheme::Https => "https",
+            AssetScheme::Kosha => "kosha",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AssetScheme> {
+        AssetScheme::ALL.into_iter().find(|s| s.as_str() == name)
+    }
+}
+
+/// `InitEvent::features` prefix for supported asset schemes
+pub const ASSET_SCHEME_FEATURE_PREFIX: &str = "asset-scheme:";
+
+/// `InitEvent::features` entry advertising support for a scheme
+pub fn asset_scheme_feature(scheme: AssetScheme) -> String {
+    format!("{}{}", ASSET_SCHEME_FEATURE_PREFIX, scheme.as_str())
+}
+
 // ----------------------------------------------------------------------------
 // Scene Commands
 // ----------------------------------------------------------------------------
@@ -586,17 +1091,37 @@ pub enum AssetCommand {
 #[serde(tag = "action")]
 pub enum SceneCommand {
     CreateVolume(CreateVolumeData),
+    /// Destroys the volume and its descendants
     DestroyVolume { volume_id: VolumeId },
     SetTransform(SetTransformData),
+    /// Hiding a volume hides its descendants
     SetVisible { volume_id: VolumeId, visible: bool },
+    /// Move a volume under `parent_id`, or to the top level when `None`. Its
+    /// transform is kept, so it now applies relative to the new parent. A
+    /// parent that is the volume itself or one of its descendants is ignored.
+    SetParent {
+        volume_id: VolumeId,
+        #[serde(default)]
+        parent_id: Option<VolumeId>,
+    },
+    CreatePortal(CreatePortalData),
+    DestroyPortal { portal_id: PortalId },
+    /// Cast a ray against the volumes; answered with `SceneEvent::HitTestResult`
+    RequestHitTest(HitTestRequest),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateVolumeData {
     pub volume_id: VolumeId,
     pub source: VolumeSource,
+    /// Relative to the parent volume when there is one
     pub transform: Transform,
     pub material: Option<MaterialOverride>,
+    /// The volume this one is attached to: it moves, hides and is destroyed
+    /// with it. The parent is created first; an unknown parent places the
+    /// volume at the top level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<VolumeId>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -605,14 +1130,52 @@ pub enum VolumeSource {
     Asset { asset_id: AssetId, mesh_index: Option<u32> },
 }
 
+/// Generated geometry, centered on the volume's origin.
+///
+/// `segments` is how many sides round shapes have around their axis (Y);
+/// spheres have half as many rings from pole to pole, and a torus's tube
+/// half as many sides. Shells clamp it to at least 3.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Primitive {
     Cube { size: f32 },
     Box { width: f32, height: f32, depth: f32 },
     Sphere { radius: f32, segments: u32 },
     Cylinder { radius: f32, height: f32, segments: u32 },
+    /// Flat on the local XZ plane, facing +Y; `height` runs along Z
     Plane { width: f32, height: f32 },
+    /// Flat on the local XY plane, facing +Z
     Quad { width: f32, height: f32 },
+    /// Base circle at the bottom, apex `height` above it
+    Cone { radius: f32, height: f32, segments: u32 },
+    /// Ring around Y: `radius` from the center to the middle of the tube,
+    /// whose own radius is `tube_radius`
+    Torus { radius: f32, tube_radius: f32, segments: u32 },
+    /// Text in the local XY plane, read from +Z, in the material's color
+    /// (unlit). `font_size` is the em size in meters; lines break at `\n`
+    /// and wrap at `max_width` meters. The block is centered on the origin,
+    /// its lines aligned by `alignment`. Billboard text ignores the volume's
+    /// rotation and turns to face the viewer, staying upright.
+    Text {
+        text: String,
+        font_size: f32,
+        #[serde(default)]
+        alignment: TextAlignment,
+        #[serde(default)]
+        max_width: Option<f32>,
+        #[serde(default)]
+        billboard: bool,
+    },
+}
+
+/// How the lines of a `Primitive::Text` line up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextAlignment {
+    /// Left edges aligned
+    Leading,
+    #[default]
+    Center,
+    /// Right edges aligned
+    Trailing,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -632,6 +1195,23 @@ impl Default for Transform {
     }
 }
 
+/// A window from one place in the scene into another.
+///
+/// The surface is a `width` x `height` rectangle in the local XY plane of
+/// `entrance`. Looking through it shows the scene as seen from the same
+/// relative pose around `exit`, and crossing it moves the viewer there.
+/// Portals are one-way; a doorway is two portals with swapped ends.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatePortalData {
+    pub portal_id: PortalId,
+    pub width: f32,
+    pub height: f32,
+    /// Pose of the portal surface (scale is ignored)
+    pub entrance: Transform,
+    /// Pose the entrance maps onto (scale is ignored)
+    pub exit: Transform,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SetTransformData {
     pub volume_id: VolumeId,
@@ -643,6 +1223,11 @@ pub struct SetTransformData {
 pub struct AnimateTransform {
     pub duration_ms: u32,
     pub easing: Easing,
+    /// When set, the shell reports the end of the animation with
+    /// `SceneEvent::VolumeAnimationComplete`. Not reported if another
+    /// `SetTransform` for the volume interrupts it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -654,6 +1239,36 @@ pub enum Easing {
     CubicBezier(u32),
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HitTestRequest {
+    /// Echoed in the result, to match it with the request
+    pub request_id: String,
+    pub source: HitTestSource,
+}
+
+/// Where a hit-test ray starts and which way it goes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HitTestSource {
+    /// A world-space ray, e.g. a controller's aim or the head's gaze.
+    /// `direction` doesn't need to be normalized.
+    Ray { origin: [f32; 3], direction: [f32; 3] },
+    /// From the camera through a point on screen, in the coordinates of
+    /// mouse and touch events (logical pixels from the top-left corner)
+    Screen { x: f32, y: f32 },
+}
+
+/// Where a hit-test ray hit a volume
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hit {
+    pub volume_id: VolumeId,
+    /// World-space hit point
+    pub point: [f32; 3],
+    /// World-space surface normal, facing the ray
+    pub normal: [f32; 3],
+    /// Distance from the ray origin to `point`
+    pub distance: f32,
+}
+
 // ----------------------------------------------------------------------------
 // Animation Commands
 // ----------------------------------------------------------------------------
@@ -735,6 +1350,11 @@ pub struct MaterialOverride {
     pub metallic: Option<f32>,
     pub roughness: Option<f32>,
     pub emissive: Option<[f32; 3]>,
+    /// Part of the texture the surface shows, as `[u, v, width, height]` in
+    /// 0-1 texture coordinates (e.g. an atlas region): shells map a UV to
+    /// `offset + uv * size`. The whole texture if None.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uv_rect: Option<[f32; 4]>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -788,6 +1408,19 @@ pub enum EnvironmentCommand {
     SetCamera(CameraData),
     SetBackground(BackgroundData),
     SetLighting(LightingData),
+    /// Full-screen fade overlay (alpha 0.0 = hidden, 1.0 = opaque)
+    SetFade(FadeData),
+    /// Add a point light. Lights light nothing until assigned to volumes;
+    /// this and the commands below are only sent to shells that list
+    /// `FEATURE_DYNAMIC_LIGHTS`.
+    CreateLight(PointLightData),
+    /// Move a light that follows an entity
+    MoveLight { light_id: LightId, position: [f32; 3] },
+    /// The lights of the volumes whose lights changed, most significant
+    /// first; the other volumes keep theirs
+    AssignLights { volumes: Vec<VolumeLights> },
+    /// The one light shells render shadows for, None for no shadows
+    SetShadowCaster { light_id: Option<LightId> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -804,6 +1437,9 @@ pub struct CameraData {
 pub enum BackgroundData {
     Color([f32; 4]),
     Skybox { asset_id: AssetId },
+    /// Nothing is drawn behind the volumes: shells advertising
+    /// `FEATURE_TRANSPARENT_BACKGROUND` show the real world or the desktop
+    /// there (AR), the others black
     Transparent,
 }
 
@@ -813,6 +1449,11 @@ pub struct LightingData {
     pub directional: Option<DirectionalLight>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FadeData {
+    pub color: [f32; 4],
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirectionalLight {
     pub direction: [f32; 3],
@@ -820,6 +1461,22 @@ pub struct DirectionalLight {
     pub intensity: f32,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PointLightData {
+    pub light_id: LightId,
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which the light fades out completely
+    pub range: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VolumeLights {
+    pub volume_id: VolumeId,
+    pub lights: Vec<LightId>,
+}
+
 // ----------------------------------------------------------------------------
 // Timer Commands
 // ----------------------------------------------------------------------------
@@ -838,8 +1495,19 @@ pub enum TimerCommand {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "action")]
 pub enum XrCommand {
-    Enter { mode: XrMode },
+    /// Request a session. Browsers only grant this while handling a user
+    /// gesture, so send it in response to an input event.
+    Enter {
+        mode: XrMode,
+        /// Ask for a DOM overlay (only granted if the shell advertises
+        /// `FEATURE_XR_DOM_OVERLAY`)
+        #[serde(default)]
+        dom_overlay: bool,
+    },
     Exit,
+    /// Place the tracking origin (the rig) in the scene. Poses reported by
+    /// the shell are in scene coordinates, i.e. already include this offset.
+    SetRigTransform(Transform),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -930,6 +1598,29 @@ impl Default for DataChannelConfig {
 pub enum MediaCommand {
     CreateStream { media_id: MediaId, source: MediaSource },
     DestroyStream { media_id: MediaId },
+    /// Save a photo or video of what the user sees to device storage, asking
+    /// for the permissions that needs. Answered with `MediaEvent::CaptureSaved`
+    /// or `CaptureFailed`. Only for shells with `FEATURE_CAPTURE`.
+    Capture(CaptureRequest),
+    /// End a video capture early; it is saved as usual
+    StopCapture { capture_id: CaptureId },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRequest {
+    pub capture_id: CaptureId,
+    pub kind: CaptureKind,
+    /// Composite the camera's view (AR passthrough) behind the virtual
+    /// content. Shells that can't read the camera save the virtual content
+    /// alone and say so in `CaptureSavedData::passthrough`.
+    pub passthrough: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaptureKind {
+    Photo,
+    /// Recorded until `StopCapture`, or at most `max_duration_ms`
+    Video { max_duration_ms: Option<u32> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -940,6 +1631,9 @@ pub enum MediaSource {
     AudioFile { asset_id: AssetId },
     ScreenCapture,
     Canvas { width: u32, height: u32 },
+    /// What the user sees (passthrough and virtual content composited), for
+    /// showing on a texture. Only for shells with `FEATURE_VIEWFINDER`.
+    Viewfinder { width: u32, height: u32 },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -949,6 +1643,305 @@ pub enum CameraFacing {
     Environment,
 }
 
+// ----------------------------------------------------------------------------
+// Audio Commands
+// ----------------------------------------------------------------------------
+
+/// Sounds and the sources playing them in the scene.
+///
+/// Sounds are files loaded once; sources play them. The core does the
+/// acoustics (distance rolloff, occlusion by scene geometry) and sends the
+/// result as a `Mix` every frame something changed. Shells play each source
+/// at its volume times the mixed gain, through its low-pass filter, panned
+/// by its direction (HRTF or equal-power), without attenuating it
+/// themselves. Changes should be smoothed over a few tens of milliseconds to
+/// avoid clicks.
+///
+/// Only sent to shells that list `FEATURE_SPATIAL_AUDIO`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action")]
+pub enum AudioCommand {
+    /// `path` is an asset URI (see `AssetScheme`). Answered with
+    /// `AudioEvent::Loaded` or `Error`.
+    Load { sound_id: SoundId, path: String },
+    /// Forget a sound, stopping the sources playing it
+    Unload { sound_id: SoundId },
+    /// Create a stopped source; it is silent until its first `Mix` entry
+    CreateSource(CreateAudioSourceData),
+    DestroySource { source_id: AudioSourceId },
+    /// Play from the start, once the sound is loaded
+    Play { source_id: AudioSourceId },
+    Stop { source_id: AudioSourceId },
+    SetVolume { source_id: AudioSourceId, volume: f32 },
+    SetLooping { source_id: AudioSourceId, looping: bool },
+    /// Mix parameters of the sources that changed since the last `Mix`;
+    /// the others keep theirs
+    Mix { sources: Vec<AudioMix> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreateAudioSourceData {
+    pub source_id: AudioSourceId,
+    pub sound_id: SoundId,
+    /// The source's own level, 0 to 1, multiplied with the mixed gain
+    pub volume: f32,
+    /// Start over when the end is reached
+    pub looping: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioMix {
+    pub source_id: AudioSourceId,
+    /// Linear gain from distance and occlusion, 0 (silent) to 1
+    pub gain: f32,
+    /// Cutoff of a low-pass filter in Hz, None for no filtering
+    pub low_pass_hz: Option<f32>,
+    /// Unit vector from the listener's head to the source, in the listener's
+    /// frame: +X right, +Y up, -Z ahead
+    pub direction: [f32; 3],
+    /// Meters from the listener, for shells that add reverb or delay
+    pub distance: f32,
+}
+
+// ----------------------------------------------------------------------------
+// Storage Commands
+// ----------------------------------------------------------------------------
+
+/// Small per-app key-value storage (localStorage on web, a file on native).
+///
+/// Values are opaque strings; cores typically store JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action")]
+pub enum StorageCommand {
+    Get { key: String },
+    Set { key: String, value: String },
+    Remove { key: String },
+}
+
+// ----------------------------------------------------------------------------
+// Kosha Commands
+// ----------------------------------------------------------------------------
+
+/// Files in koshas, read through the spoke the shell is connected with.
+///
+/// `uri` is a `kosha://<hub>/<kosha>/<path>` URI. Files are text (UTF-8).
+///
+/// Only sent to shells that list `FEATURE_KOSHA`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action")]
+pub enum KoshaCommand {
+    /// Read a file and follow it (the hub's push channel): answered with
+    /// `KoshaEvent::FileLoaded` now and whenever the file changes
+    Watch { uri: String },
+    Unwatch { uri: String },
+}
+
+// ----------------------------------------------------------------------------
+// Schedule Commands
+// ----------------------------------------------------------------------------
+
+/// Spreading expensive work across frames.
+///
+/// Shells execute plain commands as soon as they arrive. A `Defer`red command
+/// is queued instead and the queue is drained a little every frame, highest
+/// priority first and in arrival order within a priority, until the frame
+/// budget is used up (at least one command runs per frame). Each deferred
+/// command is acked with `ScheduleEvent::Completed` or `Failed`.
+///
+/// Only sent to shells that list `FEATURE_COMMAND_SCHEDULING`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action")]
+pub enum ScheduleCommand {
+    Defer(DeferredCommand),
+    /// Drop a queued command; it is not acked
+    Cancel { command_id: CommandId },
+    /// Milliseconds per frame spent on deferred commands
+    SetFrameBudget { budget_ms: f32 },
+}
+
+/// Default for `ScheduleCommand::SetFrameBudget`
+pub const DEFAULT_FRAME_BUDGET_MS: f32 = 4.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeferredCommand {
+    pub command_id: CommandId,
+    #[serde(default)]
+    pub priority: Priority,
+    pub command: Box<Command>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+// ----------------------------------------------------------------------------
+// Accessibility Commands
+// ----------------------------------------------------------------------------
+
+/// What assistive technology sees of the scene.
+///
+/// The tree is a flat list of nodes in reading order; each node names the
+/// volume it describes and its parent node. Shells mirror it into the
+/// platform accessibility API (hidden ARIA elements on web).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action")]
+pub enum AccessibilityCommand {
+    /// Replace the whole tree
+    SetTree { nodes: Vec<AccessibilityNode> },
+    /// Have the screen reader speak a message
+    Announce { message: String, politeness: Politeness },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccessibilityNode {
+    pub volume_id: VolumeId,
+    /// Nearest labelled ancestor, None for top-level nodes
+    pub parent: Option<VolumeId>,
+    pub role: AccessibilityRole,
+    pub label: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessibilityRole {
+    /// A container for other nodes
+    Group,
+    /// Something to look at
+    Image,
+    /// Something that does something when activated
+    Button,
+    /// Readable text
+    Text,
+    /// A place in the scene worth navigating to
+    Landmark,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Politeness {
+    /// Spoken once the screen reader is idle
+    #[default]
+    Polite,
+    /// Interrupts whatever is being spoken
+    Assertive,
+}
+
+// ----------------------------------------------------------------------------
+// Panel Commands
+// ----------------------------------------------------------------------------
+
+/// Flat 2D UI in the scene, like visionOS windows next to volumes.
+///
+/// A panel is a `width` x `height` rectangle in the local XY plane of its
+/// transform, facing +Z, showing its content rasterized at
+/// `pixels_per_meter`. Shells draw it as a quad volume with the panel's ID,
+/// so panel IDs must not be used by other volumes. Pointer input on it is
+/// sent back as `PanelEvent`s.
+///
+/// Only sent to shells that list `FEATURE_PANELS`; `PanelContent::Html`
+/// only to those that also list `FEATURE_HTML_PANELS`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action")]
+pub enum PanelCommand {
+    Create(CreatePanelData),
+    /// Replace what the panel shows; its size stays
+    SetContent { panel_id: PanelId, content: PanelContent },
+    SetTransform { panel_id: PanelId, transform: Transform },
+    Destroy { panel_id: PanelId },
+}
+
+/// Default for `CreatePanelData::pixels_per_meter`: a millimeter per pixel
+pub const DEFAULT_PANEL_PIXELS_PER_METER: f32 = 1000.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatePanelData {
+    pub panel_id: PanelId,
+    /// Size in meters
+    pub width: f32,
+    pub height: f32,
+    /// Pose of the panel's center (scale is ignored)
+    pub transform: Transform,
+    /// Resolution of the content: a panel is `width * pixels_per_meter`
+    /// pixels across, the unit of its widget sizes and pointer positions
+    #[serde(default = "default_panel_pixels_per_meter")]
+    pub pixels_per_meter: f32,
+    pub content: PanelContent,
+}
+
+fn default_panel_pixels_per_meter() -> f32 {
+    DEFAULT_PANEL_PIXELS_PER_METER
+}
+
+/// What a panel shows, filling it from the top-left corner
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PanelContent {
+    /// An HTML fragment with inline styles, laid out at the panel's pixel
+    /// size (`FEATURE_HTML_PANELS` only)
+    Html { html: String },
+    /// An SVG document, stretched to the panel
+    Svg { svg: String },
+    /// Simple controls, laid out and styled by the shell
+    Widgets { root: Widget },
+}
+
+/// A control of `PanelContent::Widgets`. Sizes are in panel pixels.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Widget {
+    /// Children top to bottom, left-aligned
+    Column { children: Vec<Widget> },
+    /// Children left to right, top-aligned
+    Row { children: Vec<Widget> },
+    /// A line of text; `size` is the em size
+    Text {
+        text: String,
+        #[serde(default = "default_widget_text_size")]
+        size: f32,
+    },
+    /// A labelled button, the `target` of pointer events over it
+    Button { id: String, label: String },
+}
+
+impl Widget {
+    pub fn column(children: impl IntoIterator<Item = Widget>) -> Self {
+        Widget::Column {
+            children: children.into_iter().collect(),
+        }
+    }
+
+    pub fn row(children: impl IntoIterator<Item = Widget>) -> Self {
+        Widget::Row {
+            children: children.into_iter().collect(),
+        }
+    }
+
+    /// Text at the default size
+    pub fn text(text: impl Into<String>) -> Self {
+        Widget::Text {
+            text: text.into(),
+            size: DEFAULT_WIDGET_TEXT_SIZE,
+        }
+    }
+
+    pub fn button(id: impl Into<String>, label: impl Into<String>) -> Self {
+        Widget::Button {
+            id: id.into(),
+            label: label.into(),
+        }
+    }
+}
+
+/// Default for `Widget::Text::size`
+pub const DEFAULT_WIDGET_TEXT_SIZE: f32 = 16.0;
+
+fn default_widget_text_size() -> f32 {
+    DEFAULT_WIDGET_TEXT_SIZE
+}
+
 // ----------------------------------------------------------------------------
 // Debug Commands
 // ----------------------------------------------------------------------------
@@ -957,6 +1950,17 @@ pub enum CameraFacing {
 #[serde(tag = "action")]
 pub enum DebugCommand {
     Log { level: LogLevel, message: String },
+    /// Show or hide the debug HUD. Shells measure FPS and draw calls
+    /// themselves and show the last `Log` messages.
+    SetHudVisible { visible: bool },
+    /// What the core knows for the HUD
+    SetStats(DebugStats),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DebugStats {
+    /// Entities in the scene, including children
+    pub entity_count: u32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -967,6 +1971,227 @@ pub enum LogLevel {
     Error,
 }
 
+// ============================================================================
+// INPUT SCRIPTS (automation and demo playback)
+// ============================================================================
+
+/// Port shells listen on for input scripts, on the loopback interface
+pub const DEFAULT_AUTOMATION_PORT: u16 = 7878;
+
+/// Time between the moves of a `PointerPath` (one frame at 60 Hz)
+pub const POINTER_PATH_INTERVAL_MS: u64 = 16;
+
+/// Device ID of the keyboard script steps type on
+pub const SCRIPT_KEYBOARD_ID: &str = "script-keyboard";
+
+/// Device ID of the mouse script steps move
+pub const SCRIPT_MOUSE_ID: &str = "script-mouse";
+
+/// One line of an input script (JSON Lines): synthetic input `at_ms`
+/// milliseconds after the script starts. Shells record sessions as `Event`
+/// and `Command` steps, so recordings play back like written scripts: their
+/// input and XR events are replayed, the rest is only for replaying the
+/// whole session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptStep {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: ScriptAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ScriptAction {
+    /// Send an input or XR event as is. Recordings also hold the other
+    /// events the core was sent, which scripts skip.
+    Event { event: Event },
+    /// A command the core sent, in recordings; scripts skip it
+    Command { command: Box<Command> },
+    /// Press and release keys in turn, `interval_ms` apart. Codes are
+    /// `KeyEventData::code`s ("KeyW", "Space", "ArrowUp", ...).
+    Keys {
+        codes: Vec<String>,
+        #[serde(default = "default_key_interval_ms")]
+        interval_ms: u64,
+    },
+    /// Move the mouse through `points` (logical pixels from the top-left
+    /// corner) at a steady speed over `duration_ms`, holding `button` down
+    /// from the first point to the last if set
+    PointerPath {
+        points: Vec<[f32; 2]>,
+        duration_ms: u64,
+        #[serde(default)]
+        button: Option<MouseButton>,
+    },
+    /// Put an XR controller at a pose
+    ControllerPose(XrControllerData),
+}
+
+fn default_key_interval_ms() -> u64 {
+    100
+}
+
+/// An event of an expanded input script and when to send it
+#[derive(Debug, Clone)]
+pub struct TimedEvent {
+    pub at_ms: u64,
+    pub event: Event,
+}
+
+impl Event {
+    /// Whether the event is user input, which input scripts replay
+    pub fn is_user_input(&self) -> bool {
+        matches!(self, Event::Input(_) | Event::Xr(_))
+    }
+}
+
+impl ScriptStep {
+    /// Record an event `at_ms` after the recording started
+    pub fn recorded(at_ms: u64, event: Event) -> Self {
+        Self {
+            at_ms,
+            action: ScriptAction::Event { event },
+        }
+    }
+
+    /// Record a command `at_ms` after the recording started
+    pub fn recorded_command(at_ms: u64, command: Command) -> Self {
+        Self {
+            at_ms,
+            action: ScriptAction::Command {
+                command: Box::new(command),
+            },
+        }
+    }
+
+    /// Parse one line of a script; None for blank lines and `#` comments
+    pub fn parse_line(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        serde_json::from_str(line).map(Some).map_err(|e| e.to_string())
+    }
+
+    /// The input events this step sends, in time order
+    pub fn events(&self) -> Vec<TimedEvent> {
+        let at = |offset_ms: u64, event: Event| TimedEvent {
+            at_ms: self.at_ms + offset_ms,
+            event,
+        };
+        match &self.action {
+            ScriptAction::Event { event } if event.is_user_input() => vec![at(0, event.clone())],
+            ScriptAction::Event { .. } | ScriptAction::Command { .. } => vec![],
+            ScriptAction::Keys { codes, interval_ms } => codes
+                .iter()
+                .enumerate()
+                .flat_map(|(index, code)| {
+                    let down = index as u64 * interval_ms;
+                    [
+                        at(down, key_event(code, true)),
+                        at(down + interval_ms / 2, key_event(code, false)),
+                    ]
+                })
+                .collect(),
+            ScriptAction::PointerPath { points, duration_ms, button } => {
+                pointer_path(points, *duration_ms, *button).into_iter().map(|(offset, event)| at(offset, event)).collect()
+            }
+            ScriptAction::ControllerPose(data) => vec![at(0, Event::Xr(XrEvent::ControllerPose(data.clone())))],
+        }
+    }
+}
+
+/// Parse a whole input script into its events, in time order. Errors name
+/// the line.
+pub fn parse_input_script(script: &str) -> Result<Vec<TimedEvent>, String> {
+    let mut events = vec![];
+    for (index, line) in script.lines().enumerate() {
+        if let Some(step) = ScriptStep::parse_line(line).map_err(|e| format!("line {}: {}", index + 1, e))? {
+            events.extend(step.events());
+        }
+    }
+    events.sort_by_key(|event| event.at_ms);
+    Ok(events)
+}
+
+fn key_event(code: &str, down: bool) -> Event {
+    let data = KeyEventData {
+        device_id: SCRIPT_KEYBOARD_ID.to_string(),
+        key: code.to_string(),
+        code: code.to_string(),
+        shift: false,
+        ctrl: false,
+        alt: false,
+        meta: false,
+        repeat: false,
+    };
+    Event::Input(InputEvent::Keyboard(match down {
+        true => KeyboardEvent::KeyDown(data),
+        false => KeyboardEvent::KeyUp(data),
+    }))
+}
+
+/// Mouse moves along a path every `POINTER_PATH_INTERVAL_MS`, with the
+/// button pressed at the start and released at the end
+fn pointer_path(points: &[[f32; 2]], duration_ms: u64, button: Option<MouseButton>) -> Vec<(u64, Event)> {
+    let Some(&first) = points.first() else {
+        return vec![];
+    };
+    let lengths: Vec<f32> = points.windows(2).map(|w| (w[1][0] - w[0][0]).hypot(w[1][1] - w[0][1])).collect();
+    let total: f32 = lengths.iter().sum();
+
+    // The point `t` (0..1) of the way along the path
+    let point_at = |t: f32| {
+        let mut remaining = t * total;
+        for (segment, length) in points.windows(2).zip(&lengths) {
+            if remaining <= *length && *length > 0.0 {
+                let f = remaining / length;
+                return [
+                    segment[0][0] + (segment[1][0] - segment[0][0]) * f,
+                    segment[0][1] + (segment[1][1] - segment[0][1]) * f,
+                ];
+            }
+            remaining -= length;
+        }
+        points[points.len() - 1]
+    };
+    let mouse = |event: MouseEvent| Event::Input(InputEvent::Mouse(event));
+    let button_data = |[x, y]: [f32; 2], button| MouseButtonData {
+        device_id: SCRIPT_MOUSE_ID.to_string(),
+        x,
+        y,
+        button,
+    };
+
+    let mut events = vec![];
+    let mut last = first;
+    let steps = (duration_ms / POINTER_PATH_INTERVAL_MS).max(1);
+    for step in 0..=steps {
+        let offset = duration_ms * step / steps;
+        let [x, y] = point_at(step as f32 / steps as f32);
+        events.push((
+            offset,
+            mouse(MouseEvent::Move(MouseMoveData {
+                device_id: SCRIPT_MOUSE_ID.to_string(),
+                x,
+                y,
+                dx: x - last[0],
+                dy: y - last[1],
+            })),
+        ));
+        last = [x, y];
+        if step == 0
+            && let Some(button) = button
+        {
+            events.push((0, mouse(MouseEvent::Down(button_data(first, button)))));
+        }
+    }
+    if let Some(button) = button {
+        events.push((duration_ms, mouse(MouseEvent::Up(button_data(last, button)))));
+    }
+    events
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -991,8 +2216,576 @@ mod tests {
         match event {
             Event::Lifecycle(LifecycleEvent::Init(data)) => {
                 assert_eq!(data.viewport_width, 1280);
+                // Shells that predate accessibility preferences get the defaults
+                assert_eq!(data.accessibility, AccessibilityPreferences::default());
+                assert_eq!(data.conventions, Conventions::CORE);
+                assert!(data.camera.is_none());
             }
             _ => panic!("Expected Lifecycle::Init event"),
         }
     }
+
+    #[test]
+    fn test_capture_json() {
+        let json = r#"{"category":"Media","command":{"action":"Capture","capture_id":"moment","kind":{"Video":{"max_duration_ms":10000}},"passthrough":true}}"#;
+        let command: Command = serde_json::from_str(json).unwrap();
+        match &command {
+            Command::Media(MediaCommand::Capture(request)) => {
+                assert_eq!(request.kind, CaptureKind::Video { max_duration_ms: Some(10000) });
+            }
+            _ => panic!("Expected Media::Capture command"),
+        }
+        assert_eq!(serde_json::to_string(&command).unwrap(), json);
+
+        let json = r#"{"category":"Media","event":{"type":"CaptureSaved","capture_id":"moment","kind":"Photo","path":"fastn-moment.png","mime_type":"image/png","width":1832,"height":1920,"passthrough":false}}"#;
+        match serde_json::from_str::<Event>(json).unwrap() {
+            Event::Media(MediaEvent::CaptureSaved(data)) => {
+                assert_eq!(data.kind, CaptureKind::Photo);
+                assert_eq!(data.duration_ms, None);
+                assert!(!data.passthrough);
+            }
+            _ => panic!("Expected Media::CaptureSaved event"),
+        }
+
+        let json = r#"{"category":"Media","event":{"type":"CaptureFailed","capture_id":"clip","reason":"Busy","message":"already recording"}}"#;
+        match serde_json::from_str::<Event>(json).unwrap() {
+            Event::Media(MediaEvent::CaptureFailed(data)) => assert_eq!(data.reason, CaptureFailure::Busy),
+            _ => panic!("Expected Media::CaptureFailed event"),
+        }
+    }
+
+    #[test]
+    fn test_scene_hierarchy_json() {
+        // Top-level volumes serialize as before
+        let json = r#"{"category":"Scene","command":{"action":"CreateVolume","volume_id":"arm","source":{"Primitive":{"Cube":{"size":1.0}}},"transform":{"position":[0.0,1.0,0.0],"rotation":[0.0,0.0,0.0,1.0],"scale":[1.0,1.0,1.0]},"material":null}}"#;
+        let command: Command = serde_json::from_str(json).unwrap();
+        match &command {
+            Command::Scene(SceneCommand::CreateVolume(data)) => assert!(data.parent_id.is_none()),
+            _ => panic!("Expected Scene::CreateVolume command"),
+        }
+        assert_eq!(serde_json::to_string(&command).unwrap(), json);
+
+        let json = json.replace(r#""material":null"#, r#""material":null,"parent_id":"body""#);
+        match serde_json::from_str::<Command>(&json).unwrap() {
+            Command::Scene(SceneCommand::CreateVolume(data)) => assert_eq!(data.parent_id.as_deref(), Some("body")),
+            _ => panic!("Expected Scene::CreateVolume command"),
+        }
+
+        let json = r#"{"category":"Scene","command":{"action":"SetParent","volume_id":"arm","parent_id":null}}"#;
+        let command: Command = serde_json::from_str(json).unwrap();
+        match &command {
+            Command::Scene(SceneCommand::SetParent { volume_id, parent_id }) => {
+                assert_eq!(volume_id, "arm");
+                assert!(parent_id.is_none());
+            }
+            _ => panic!("Expected Scene::SetParent command"),
+        }
+        assert_eq!(serde_json::to_string(&command).unwrap(), json);
+    }
+
+    #[test]
+    fn test_collision_events_json() {
+        let json = r#"{"category":"Scene","event":{"type":"CollisionStarted","entity_a":"ball","entity_b":"floor"}}"#;
+        let event: Event = serde_json::from_str(json).unwrap();
+        match &event {
+            Event::Scene(SceneEvent::CollisionStarted(data)) => {
+                assert_eq!(data.entity_a, "ball");
+                assert_eq!(data.entity_b, "floor");
+            }
+            _ => panic!("Expected Scene::CollisionStarted event"),
+        }
+        assert_eq!(serde_json::to_string(&event).unwrap(), json);
+
+        let json = json.replace("CollisionStarted", "CollisionEnded");
+        assert!(matches!(
+            serde_json::from_str::<Event>(&json).unwrap(),
+            Event::Scene(SceneEvent::CollisionEnded(_))
+        ));
+    }
+
+    #[test]
+    fn test_conventions_json() {
+        let json = r#"{"handedness":"Left","up_axis":"Z","meters_per_unit":0.01}"#;
+        let conventions: Conventions = serde_json::from_str(json).unwrap();
+        assert_eq!(conventions.handedness, Handedness::Left);
+        assert_eq!(conventions.up_axis, UpAxis::Z);
+        assert_eq!(conventions.meters_per_unit, 0.01);
+        assert_eq!(serde_json::to_string(&conventions).unwrap(), json);
+
+        let json = r#"{"type":"Loaded","asset_id":"a","path":"city.usdz","asset_type":"Usdz","meshes":[],"animations":[],"skeletons":[],"conventions":{"handedness":"Right","up_axis":"Z","meters_per_unit":1.0}}"#;
+        match serde_json::from_str::<AssetEvent>(json).unwrap() {
+            AssetEvent::Loaded(data) => assert_eq!(data.conventions.unwrap().up_axis, UpAxis::Z),
+            _ => panic!("Expected AssetEvent::Loaded"),
+        }
+    }
+
+    #[test]
+    fn test_storage_loaded_json() {
+        let json = r#"{"category":"Storage","event":{"type":"Loaded","key":"fastn.bookmarks","value":null}}"#;
+        let event: Event = serde_json::from_str(json).unwrap();
+        match event {
+            Event::Storage(StorageEvent::Loaded { key, value }) => {
+                assert_eq!(key, "fastn.bookmarks");
+                assert!(value.is_none());
+            }
+            _ => panic!("Expected Storage::Loaded event"),
+        }
+    }
+
+    #[test]
+    fn test_kosha_file_loaded_json() {
+        let json = r#"{"category":"Kosha","event":{"type":"FileLoaded","uri":"kosha://home/app/flags.json","content":"{}"}}"#;
+        let event: Event = serde_json::from_str(json).unwrap();
+        match event {
+            Event::Kosha(KoshaEvent::FileLoaded { uri, content }) => {
+                assert_eq!(uri, "kosha://home/app/flags.json");
+                assert_eq!(content.as_deref(), Some("{}"));
+            }
+            _ => panic!("Expected Kosha::FileLoaded event"),
+        }
+    }
+
+    #[test]
+    fn test_xr_session_changed_json() {
+        let json = r#"{"category":"Xr","event":{"type":"SessionChanged","state":"Active","mode":"ImmersiveAr","dom_overlay":true}}"#;
+        let event: Event = serde_json::from_str(json).unwrap();
+        match event {
+            Event::Xr(XrEvent::SessionChanged(data)) => {
+                assert_eq!(data.state, XrSessionState::Active);
+                assert_eq!(data.mode, Some(XrMode::ImmersiveAr));
+                assert!(data.dom_overlay);
+            }
+            _ => panic!("Expected Xr::SessionChanged event"),
+        }
+    }
+
+    #[test]
+    fn test_create_portal_json() {
+        let command = Command::Scene(SceneCommand::CreatePortal(CreatePortalData {
+            portal_id: "door".to_string(),
+            width: 1.0,
+            height: 2.0,
+            entrance: Transform::default(),
+            exit: Transform {
+                position: [10.0, 0.0, 0.0],
+                ..Default::default()
+            },
+        }));
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["category"], "Scene");
+        assert_eq!(json["command"]["action"], "CreatePortal");
+        assert_eq!(json["command"]["portal_id"], "door");
+        assert_eq!(json["command"]["exit"]["position"][0], 10.0);
+
+        let rig = Command::Xr(XrCommand::SetRigTransform(Transform::default()));
+        let json = serde_json::to_value(&rig).unwrap();
+        assert_eq!(json["command"]["action"], "SetRigTransform");
+        assert_eq!(json["command"]["rotation"][3], 1.0);
+    }
+
+    #[test]
+    fn test_panel_json() {
+        let json = r#"{"category":"Panel","command":{"action":"Create","panel_id":"menu","width":0.4,"height":0.3,
+            "transform":{"position":[0.0,1.5,-1.0],"rotation":[0.0,0.0,0.0,1.0],"scale":[1.0,1.0,1.0]},
+            "content":{"type":"Widgets","root":{"type":"Column","children":[
+                {"type":"Text","text":"Settings"},{"type":"Button","id":"close","label":"Close"}]}}}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Command::Panel(PanelCommand::Create(data)) => {
+                assert_eq!(data.pixels_per_meter, DEFAULT_PANEL_PIXELS_PER_METER);
+                let PanelContent::Widgets { root: Widget::Column { children } } = data.content else {
+                    panic!("Expected a widget column");
+                };
+                assert_eq!(children[0], Widget::Text { text: "Settings".to_string(), size: DEFAULT_WIDGET_TEXT_SIZE });
+            }
+            _ => panic!("Expected Panel::Create command"),
+        }
+
+        let event = Event::Panel(PanelEvent::Pointer(PanelPointerData {
+            panel_id: "menu".to_string(),
+            phase: PointerPhase::Up,
+            x: 120.0,
+            y: 64.0,
+            target: Some("close".to_string()),
+        }));
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["category"], "Panel");
+        assert_eq!(json["event"]["type"], "Pointer");
+        assert_eq!(json["event"]["phase"], "Up");
+        assert_eq!(json["event"]["target"], "close");
+    }
+
+    #[test]
+    fn test_play_area_json() {
+        let json = r#"{"category":"Xr","event":{"type":"SessionChanged","state":"Active","mode":"ImmersiveVr","play_area":{"width":3.0,"depth":2.0}}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Event::Xr(XrEvent::SessionChanged(data)) => {
+                let area = data.play_area.unwrap();
+                assert!(area.contains(1.5, -0.9));
+                assert!(!area.contains(0.0, 1.1));
+            }
+            _ => panic!("Expected Xr::SessionChanged event"),
+        }
+
+        // Shells that don't know the play area leave it out
+        let json = r#"{"category":"Xr","event":{"type":"SessionChanged","state":"Active","mode":"ImmersiveAr"}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Event::Xr(XrEvent::SessionChanged(data)) => assert!(data.play_area.is_none()),
+            _ => panic!("Expected Xr::SessionChanged event"),
+        }
+    }
+
+    #[test]
+    fn test_accessibility_json() {
+        let json = r#"{"category":"Lifecycle","event":{"type":"AccessibilityChanged","reduced_motion":true}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Event::Lifecycle(LifecycleEvent::AccessibilityChanged(prefs)) => {
+                assert!(prefs.reduced_motion);
+                assert!(!prefs.high_contrast);
+            }
+            _ => panic!("Expected Lifecycle::AccessibilityChanged event"),
+        }
+
+        let command = Command::Accessibility(AccessibilityCommand::SetTree {
+            nodes: vec![AccessibilityNode {
+                volume_id: "door".to_string(),
+                parent: Some("house".to_string()),
+                role: AccessibilityRole::Button,
+                label: "Front door".to_string(),
+                description: None,
+            }],
+        });
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["category"], "Accessibility");
+        assert_eq!(json["command"]["action"], "SetTree");
+        assert_eq!(json["command"]["nodes"][0]["role"], "Button");
+        assert_eq!(json["command"]["nodes"][0]["parent"], "house");
+    }
+
+    #[test]
+    fn test_asset_load_json() {
+        let command = Command::Asset(AssetCommand::Load {
+            asset_id: "asset:robot.glb".to_string(),
+            path: "https://example.com/robot.glb".to_string(),
+            priority: Priority::Low,
+            cache: CachePolicy::Revalidate,
+        });
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["command"]["action"], "Load");
+        assert_eq!(json["command"]["priority"], "Low");
+        assert_eq!(json["command"]["cache"], "Revalidate");
+
+        // Older cores send neither priority nor cache policy
+        let json = r#"{"category":"Asset","command":{"action":"Load","asset_id":"robot","path":"bundle://robot.glb"}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Command::Asset(AssetCommand::Load { priority, cache, .. }) => {
+                assert_eq!(priority, Priority::Normal);
+                assert_eq!(cache, CachePolicy::Default);
+            }
+            _ => panic!("Expected Asset::Load command"),
+        }
+    }
+
+    #[test]
+    fn test_deferred_command_json() {
+        let command = Command::Schedule(ScheduleCommand::Defer(DeferredCommand {
+            command_id: "cmd-1".to_string(),
+            priority: Priority::Low,
+            command: Box::new(Command::Asset(AssetCommand::Load {
+                asset_id: "robot".to_string(),
+                path: "bundle://robot.glb".to_string(),
+                priority: Priority::Normal,
+                cache: CachePolicy::Default,
+            })),
+        }));
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["category"], "Schedule");
+        assert_eq!(json["command"]["action"], "Defer");
+        assert_eq!(json["command"]["priority"], "Low");
+        assert_eq!(json["command"]["command"]["category"], "Asset");
+        assert_eq!(json["command"]["command"]["command"]["action"], "Load");
+
+        // Priority is optional
+        let json = r#"{"category":"Schedule","command":{"action":"Defer","command_id":"cmd-2","command":{"category":"Scene","command":{"action":"DestroyVolume","volume_id":"v"}}}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Command::Schedule(ScheduleCommand::Defer(deferred)) => assert_eq!(deferred.priority, Priority::Normal),
+            _ => panic!("Expected Schedule::Defer command"),
+        }
+
+        let json = r#"{"category":"Schedule","event":{"type":"Failed","command_id":"cmd-1","error":"404"}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Event::Schedule(ScheduleEvent::Failed { command_id, error }) => {
+                assert_eq!(command_id, "cmd-1");
+                assert_eq!(error, "404");
+            }
+            _ => panic!("Expected Schedule::Failed event"),
+        }
+    }
+
+    #[test]
+    fn test_text_json() {
+        let json = r#"{"Primitive":{"Text":{"text":"Hello","font_size":0.1}}}"#;
+        let source: VolumeSource = serde_json::from_str(json).unwrap();
+        match source {
+            VolumeSource::Primitive(Primitive::Text { text, font_size, alignment, max_width, billboard }) => {
+                assert_eq!(text, "Hello");
+                assert_eq!(font_size, 0.1);
+                assert_eq!(alignment, TextAlignment::Center);
+                assert_eq!(max_width, None);
+                assert!(!billboard);
+            }
+            _ => panic!("Expected Text primitive"),
+        }
+
+        let text = Primitive::Text {
+            text: "Exit".to_string(),
+            font_size: 0.2,
+            alignment: TextAlignment::Leading,
+            max_width: Some(1.5),
+            billboard: true,
+        };
+        let json = serde_json::to_string(&text).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Text":{"text":"Exit","font_size":0.2,"alignment":"Leading","max_width":1.5,"billboard":true}}"#
+        );
+    }
+
+    #[test]
+    fn test_hit_test_json() {
+        let command = Command::Scene(SceneCommand::RequestHitTest(HitTestRequest {
+            request_id: "click-1".to_string(),
+            source: HitTestSource::Screen { x: 10.0, y: 20.0 },
+        }));
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["command"]["action"], "RequestHitTest");
+        assert_eq!(json["command"]["request_id"], "click-1");
+        assert_eq!(json["command"]["source"]["Screen"]["x"], 10.0);
+
+        let json = r#"{"category":"Scene","event":{"type":"HitTestResult","request_id":"click-1","hit":{"volume_id":"cube","point":[0,0,0.5],"normal":[0,0,1],"distance":2.5}}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Event::Scene(SceneEvent::HitTestResult { request_id, hit: Some(hit) }) => {
+                assert_eq!(request_id, "click-1");
+                assert_eq!(hit.volume_id, "cube");
+                assert_eq!(hit.distance, 2.5);
+            }
+            _ => panic!("Expected Scene::HitTestResult event with a hit"),
+        }
+
+        let json = r#"{"category":"Scene","event":{"type":"HitTestResult","request_id":"gaze","hit":null}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Event::Scene(SceneEvent::HitTestResult { hit, .. }) => assert!(hit.is_none()),
+            _ => panic!("Expected Scene::HitTestResult event"),
+        }
+    }
+
+    #[test]
+    fn test_material_uv_rect_json() {
+        let material = MaterialOverride {
+            color: None,
+            texture_id: Some("ui".to_string()),
+            metallic: None,
+            roughness: None,
+            emissive: None,
+            uv_rect: Some([0.5, 0.0, 0.25, 0.125]),
+        };
+        let json = serde_json::to_value(&material).unwrap();
+        assert_eq!(json["uv_rect"], serde_json::json!([0.5, 0.0, 0.25, 0.125]));
+
+        // Materials from older cores map the whole texture
+        let json = r#"{"color":null,"texture_id":"ui","metallic":null,"roughness":null,"emissive":null}"#;
+        let material: MaterialOverride = serde_json::from_str(json).unwrap();
+        assert!(material.uv_rect.is_none());
+        assert!(serde_json::to_value(&material).unwrap().get("uv_rect").is_none());
+    }
+
+    #[test]
+    fn test_primitive_json() {
+        let json = r#"{"Primitive":{"Torus":{"radius":0.5,"tube_radius":0.1,"segments":24}}}"#;
+        match serde_json::from_str(json).unwrap() {
+            VolumeSource::Primitive(Primitive::Torus { radius, tube_radius, segments }) => {
+                assert_eq!((radius, tube_radius, segments), (0.5, 0.1, 24));
+            }
+            _ => panic!("Expected a Torus primitive"),
+        }
+        let cone = Primitive::Cone { radius: 0.2, height: 0.5, segments: 16 };
+        assert_eq!(serde_json::to_value(&cone).unwrap()["Cone"]["height"], 0.5);
+    }
+
+    #[test]
+    fn test_animate_transform_json() {
+        let command = Command::Scene(SceneCommand::SetTransform(SetTransformData {
+            volume_id: "cube".to_string(),
+            transform: Transform::default(),
+            animate: Some(AnimateTransform {
+                duration_ms: 500,
+                easing: Easing::EaseInOut,
+                animation_id: Some("anim-1".to_string()),
+            }),
+        }));
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["command"]["action"], "SetTransform");
+        assert_eq!(json["command"]["animate"]["easing"], "EaseInOut");
+        assert_eq!(json["command"]["animate"]["animation_id"], "anim-1");
+
+        // Older cores don't name their animations
+        let json = r#"{"volume_id":"cube","transform":{"position":[0,1,0],"rotation":[0,0,0,1],"scale":[1,1,1]},"animate":{"duration_ms":250,"easing":"Linear"}}"#;
+        let data: SetTransformData = serde_json::from_str(json).unwrap();
+        assert!(data.animate.unwrap().animation_id.is_none());
+
+        let json = r#"{"category":"Scene","event":{"type":"VolumeAnimationComplete","volume_id":"cube","animation_id":"anim-1"}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Event::Scene(SceneEvent::VolumeAnimationComplete { volume_id, animation_id }) => {
+                assert_eq!(volume_id, "cube");
+                assert_eq!(animation_id, "anim-1");
+            }
+            _ => panic!("Expected Scene::VolumeAnimationComplete event"),
+        }
+    }
+
+    #[test]
+    fn test_audio_json() {
+        let command = Command::Audio(AudioCommand::Mix {
+            sources: vec![AudioMix {
+                source_id: "fountain".to_string(),
+                gain: 0.5,
+                low_pass_hz: None,
+                direction: [0.0, 0.0, -1.0],
+                distance: 2.0,
+            }],
+        });
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["category"], "Audio");
+        assert_eq!(json["command"]["action"], "Mix");
+        assert_eq!(json["command"]["sources"][0]["gain"], 0.5);
+        assert!(json["command"]["sources"][0]["low_pass_hz"].is_null());
+
+        let json = r#"{"category":"Audio","command":{"action":"CreateSource","source_id":"fountain","sound_id":"water","volume":0.8,"looping":true}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Command::Audio(AudioCommand::CreateSource(data)) => {
+                assert_eq!(data.sound_id, "water");
+                assert!(data.looping);
+            }
+            _ => panic!("Expected Audio::CreateSource command"),
+        }
+
+        let json = r#"{"category":"Audio","event":{"type":"Loaded","sound_id":"water","duration_ms":4200}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Event::Audio(AudioEvent::Loaded { sound_id, duration_ms }) => {
+                assert_eq!(sound_id, "water");
+                assert_eq!(duration_ms, 4200);
+            }
+            _ => panic!("Expected Audio::Loaded event"),
+        }
+    }
+
+    #[test]
+    fn test_dynamic_lights_json() {
+        let command = Command::Environment(EnvironmentCommand::AssignLights {
+            volumes: vec![VolumeLights {
+                volume_id: "table".to_string(),
+                lights: vec!["lamp".to_string(), "candle".to_string()],
+            }],
+        });
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["category"], "Environment");
+        assert_eq!(json["command"]["action"], "AssignLights");
+        assert_eq!(json["command"]["volumes"][0]["lights"][1], "candle");
+
+        let json = r#"{"category":"Environment","command":{"action":"SetShadowCaster","light_id":null}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Command::Environment(EnvironmentCommand::SetShadowCaster { light_id }) => assert!(light_id.is_none()),
+            _ => panic!("Expected Environment::SetShadowCaster command"),
+        }
+    }
+
+    #[test]
+    fn test_debug_hud_json() {
+        let command = Command::Debug(DebugCommand::SetStats(DebugStats { entity_count: 3 }));
+        let json = serde_json::to_value(&command).unwrap();
+        assert_eq!(json["category"], "Debug");
+        assert_eq!(json["command"]["action"], "SetStats");
+        assert_eq!(json["command"]["entity_count"], 3);
+
+        let json = r#"{"category":"Debug","command":{"action":"SetHudVisible","visible":true}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Command::Debug(DebugCommand::SetHudVisible { visible }) => assert!(visible),
+            _ => panic!("Expected Debug::SetHudVisible command"),
+        }
+    }
+
+    #[test]
+    fn test_input_script() {
+        let script = r#"
+# Walk forward, then drag the cube
+{"at_ms":0,"type":"Keys","codes":["KeyW","KeyW"],"interval_ms":200}
+{"at_ms":500,"type":"PointerPath","points":[[100,100],[200,100],[200,200]],"duration_ms":160,"button":"Left"}
+{"at_ms":1000,"type":"ControllerPose","hand":"Right","pose":{"position":[0.2,1.2,-0.3],"orientation":[0,0,0,1]},"grip_pose":null,"buttons":[[1.0,true]],"axes":[]}
+{"at_ms":1100,"type":"Event","event":{"category":"Input","event":{"type":"Keyboard","action":"KeyUp","device_id":"keyboard-0","key":"Space","code":"Space","shift":false,"ctrl":false,"alt":false,"meta":false,"repeat":false}}}
+"#;
+        let events = parse_input_script(script).unwrap();
+        let times: Vec<u64> = events.iter().map(|e| e.at_ms).collect();
+        assert!(times.windows(2).all(|w| w[0] <= w[1]));
+
+        // Key presses and releases, interval_ms apart
+        let keys: Vec<(u64, bool)> = events
+            .iter()
+            .filter_map(|e| match &e.event {
+                Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyDown(data))) if data.code == "KeyW" => {
+                    Some((e.at_ms, true))
+                }
+                Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyUp(data))) if data.code == "KeyW" => {
+                    Some((e.at_ms, false))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keys, vec![(0, true), (100, false), (200, true), (300, false)]);
+
+        // The pointer moves at a steady speed: halfway at the corner
+        let moves: Vec<(u64, f32, f32)> = events
+            .iter()
+            .filter_map(|e| match &e.event {
+                Event::Input(InputEvent::Mouse(MouseEvent::Move(data))) => Some((e.at_ms, data.x, data.y)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(moves.len(), 11);
+        assert_eq!(moves[0], (500, 100.0, 100.0));
+        assert_eq!(moves[5], (580, 200.0, 100.0));
+        assert_eq!(moves[10], (660, 200.0, 200.0));
+        let buttons: Vec<(u64, bool)> = events
+            .iter()
+            .filter_map(|e| match &e.event {
+                Event::Input(InputEvent::Mouse(MouseEvent::Down(_))) => Some((e.at_ms, true)),
+                Event::Input(InputEvent::Mouse(MouseEvent::Up(data))) => {
+                    assert_eq!((data.x, data.y), (200.0, 200.0));
+                    Some((e.at_ms, false))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(buttons, vec![(500, true), (660, false)]);
+
+        assert!(matches!(
+            events[events.len() - 2].event,
+            Event::Xr(XrEvent::ControllerPose(XrControllerData { hand: Hand::Right, .. }))
+        ));
+        assert!(events.iter().all(|e| e.event.is_user_input()));
+
+        // Recordings are scripts
+        let recorded = ScriptStep::recorded(1100, events[events.len() - 1].event.clone());
+        let line = serde_json::to_string(&recorded).unwrap();
+        assert_eq!(ScriptStep::parse_line(&line).unwrap().unwrap().events().len(), 1);
+
+        // Frames and commands in them are only for replaying the session
+        let frame = Event::Lifecycle(LifecycleEvent::Frame(FrameEvent { time: 1.1, dt: 0.016, frame: 66 }));
+        let command = Command::Environment(EnvironmentCommand::SetFade(FadeData { color: [0.0, 0.0, 0.0, 0.5] }));
+        for step in [ScriptStep::recorded(1100, frame), ScriptStep::recorded_command(1100, command)] {
+            let line = serde_json::to_string(&step).unwrap();
+            assert!(ScriptStep::parse_line(&line).unwrap().unwrap().events().is_empty(), "{}", line);
+        }
+
+        let error = parse_input_script("{\"at_ms\":0,\"type\":\"Keys\"}").unwrap_err();
+        assert!(error.starts_with("line 1:"), "{}", error);
+    }
 }
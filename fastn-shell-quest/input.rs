@@ -0,0 +1,167 @@
+//! Touch controllers, read through OpenXR actions
+//!
+//! Buttons and axes follow WebXR's `xr-standard` gamepad layout, as the
+//! native shell's OpenXR mode reports them: trigger, squeeze, touchpad
+//! (none), thumbstick press, A/X and B/Y; touchpad X/Y (zero), thumbstick
+//! X/Y with Y down.
+
+use fastn_protocol::{Hand, PoseData, XrControllerData};
+use glam::{Mat4, Quat, Vec3};
+use openxr as xr;
+
+/// How far an analog trigger or grip goes before it counts as pressed
+const PRESSED: f32 = 0.5;
+
+/// Both controllers' actions and the spaces following their poses
+pub struct Controllers {
+    set: xr::ActionSet,
+    aim: xr::Action<xr::Posef>,
+    grip: xr::Action<xr::Posef>,
+    trigger: xr::Action<f32>,
+    squeeze: xr::Action<f32>,
+    thumbstick: xr::Action<xr::Vector2f>,
+    thumbstick_click: xr::Action<bool>,
+    primary: xr::Action<bool>,
+    secondary: xr::Action<bool>,
+    hands: Vec<Controller>,
+}
+
+/// A hand's controller: the spaces following its poses
+struct Controller {
+    hand: Hand,
+    path: xr::Path,
+    aim: xr::Space,
+    grip: xr::Space,
+}
+
+impl Controllers {
+    /// Bind the actions for Touch controllers and attach them to the session
+    pub fn new(instance: &xr::Instance, session: &xr::Session<xr::Vulkan>) -> xr::Result<Self> {
+        let set = instance.create_action_set("controllers", "Controllers", 0)?;
+        let paths = [instance.string_to_path(hand_path(Hand::Left))?, instance.string_to_path(hand_path(Hand::Right))?];
+        let aim = set.create_action("aim", "Aim", &paths)?;
+        let grip = set.create_action("grip", "Grip", &paths)?;
+        let trigger = set.create_action("trigger", "Trigger", &paths)?;
+        let squeeze = set.create_action("squeeze", "Squeeze", &paths)?;
+        let thumbstick = set.create_action("thumbstick", "Thumbstick", &paths)?;
+        let thumbstick_click = set.create_action("thumbstick_click", "Thumbstick press", &paths)?;
+        let primary = set.create_action("primary", "A/X", &paths)?;
+        let secondary = set.create_action("secondary", "B/Y", &paths)?;
+
+        let mut bindings = vec![];
+        for hand in [Hand::Left, Hand::Right] {
+            let input = |name: &str| instance.string_to_path(&format!("{}/input/{}", hand_path(hand), name));
+            let (primary_input, secondary_input) = match hand {
+                Hand::Left => ("x/click", "y/click"),
+                Hand::Right => ("a/click", "b/click"),
+            };
+            bindings.extend([
+                xr::Binding::new(&aim, input("aim/pose")?),
+                xr::Binding::new(&grip, input("grip/pose")?),
+                xr::Binding::new(&trigger, input("trigger/value")?),
+                xr::Binding::new(&squeeze, input("squeeze/value")?),
+                xr::Binding::new(&thumbstick, input("thumbstick")?),
+                xr::Binding::new(&thumbstick_click, input("thumbstick/click")?),
+                xr::Binding::new(&primary, input(primary_input)?),
+                xr::Binding::new(&secondary, input(secondary_input)?),
+            ]);
+        }
+        instance.suggest_interaction_profile_bindings(
+            instance.string_to_path("/interaction_profiles/oculus/touch_controller")?,
+            &bindings,
+        )?;
+        session.attach_action_sets(&[&set])?;
+
+        let mut controllers = Self {
+            set,
+            aim,
+            grip,
+            trigger,
+            squeeze,
+            thumbstick,
+            thumbstick_click,
+            primary,
+            secondary,
+            hands: vec![],
+        };
+        for (hand, path) in [Hand::Left, Hand::Right].into_iter().zip(paths) {
+            let aim = controllers.aim.create_space(session.clone(), path, xr::Posef::IDENTITY)?;
+            let grip = controllers.grip.create_space(session.clone(), path, xr::Posef::IDENTITY)?;
+            controllers.hands.push(Controller { hand, path, aim, grip });
+        }
+        Ok(controllers)
+    }
+
+    /// The tracked controllers' poses (in scene coordinates, the tracking
+    /// origin placed at `rig`) and inputs at `time`
+    pub fn read(
+        &self,
+        session: &xr::Session<xr::Vulkan>,
+        stage: &xr::Space,
+        rig: Mat4,
+        time: xr::Time,
+    ) -> xr::Result<Vec<XrControllerData>> {
+        session.sync_actions(&[(&self.set).into()])?;
+        let mut controllers = vec![];
+        for controller in &self.hands {
+            let aim = controller.aim.locate(stage, time)?;
+            if !aim.location_flags.contains(xr::SpaceLocationFlags::POSITION_VALID) {
+                continue;
+            }
+            let grip = controller.grip.locate(stage, time)?;
+            let grip_pose = grip
+                .location_flags
+                .contains(xr::SpaceLocationFlags::POSITION_VALID)
+                .then(|| scene_pose(rig, &grip.pose));
+
+            let path = controller.path;
+            let analog = |action: &xr::Action<f32>| -> xr::Result<(f32, bool)> {
+                let value = action.state(session, path)?.current_state;
+                Ok((value, value >= PRESSED))
+            };
+            let button = |action: &xr::Action<bool>| -> xr::Result<(f32, bool)> {
+                let pressed = action.state(session, path)?.current_state;
+                Ok((if pressed { 1.0 } else { 0.0 }, pressed))
+            };
+            let thumbstick = self.thumbstick.state(session, path)?.current_state;
+
+            controllers.push(XrControllerData {
+                hand: controller.hand,
+                pose: scene_pose(rig, &aim.pose),
+                grip_pose,
+                buttons: vec![
+                    analog(&self.trigger)?,
+                    analog(&self.squeeze)?,
+                    (0.0, false),
+                    button(&self.thumbstick_click)?,
+                    button(&self.primary)?,
+                    button(&self.secondary)?,
+                ],
+                axes: vec![0.0, 0.0, thumbstick.x, -thumbstick.y],
+            });
+        }
+        Ok(controllers)
+    }
+}
+
+/// The OpenXR path of a hand's controller
+fn hand_path(hand: Hand) -> &'static str {
+    match hand {
+        Hand::Left => "/user/hand/left",
+        Hand::Right => "/user/hand/right",
+    }
+}
+
+/// A pose tracked in the stage space, in scene coordinates
+pub fn scene_pose(rig: Mat4, pose: &xr::Posef) -> PoseData {
+    let (_, rotation, position) = (rig * pose_matrix(pose)).to_scale_rotation_translation();
+    PoseData {
+        position: position.to_array(),
+        orientation: rotation.to_array(),
+    }
+}
+
+pub fn pose_matrix(pose: &xr::Posef) -> Mat4 {
+    let (p, o) = (pose.position, pose.orientation);
+    Mat4::from_rotation_translation(Quat::from_xyzw(o.x, o.y, o.z, o.w), Vec3::new(p.x, p.y, p.z))
+}
@@ -0,0 +1,474 @@
+//! fastn Quest shell - runs a fastn app on Meta Quest headsets
+//!
+//! `fastn build --target quest` generates a cargo-apk package from this
+//! directory's sources and Cargo.toml.tmpl, with the app's assets in
+//! `assets/` and its core next to them as `assets/app.wasm`.
+//!
+//! The shell starts an OpenXR session (Vulkan), runs the core with wasmtime
+//! and sends it lifecycle events, session changes, the head pose and the
+//! Touch controllers (see input.rs), in scene coordinates. The core's scene
+//! is drawn into each eye (see renderer.rs): volumes made of primitives are
+//! boxes of the primitive's size, over the app's background color; text and
+//! glTF volumes are not drawn yet. A transparent background shows the
+//! headset's passthrough cameras (see passthrough.rs). Logs go to logcat,
+//! tagged with the app's name:
+//!
+//! ```bash
+//! adb logcat -s <app>:*
+//! ```
+
+#![cfg(target_os = "android")]
+
+mod input;
+mod passthrough;
+mod renderer;
+mod wasm_core;
+
+use android_activity::{AndroidApp, MainEvent, PollEvent};
+use ash::vk::Handle;
+use fastn_protocol::*;
+use glam::{Mat4, Quat, Vec3, Vec4};
+use input::Controllers;
+use openxr as xr;
+use passthrough::Passthrough;
+use renderer::{Draw, Renderer};
+use std::io::Read;
+use wasm_core::{Core, ShellState};
+
+const APP_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// The app's core, in the APK's assets
+const APP_WASM: &std::ffi::CStr = c"app.wasm";
+
+/// Clip planes of the eyes' projections, as the native shell's
+const NEAR: f32 = 0.1;
+const FAR: f32 = 100.0;
+
+/// Run the app until the session ends or the activity is destroyed
+pub fn run_xr_app(app: &AndroidApp) -> Result<(), Box<dyn std::error::Error>> {
+    let mut wasm = vec![];
+    app.asset_manager()
+        .open(APP_WASM)
+        .ok_or("app.wasm not found in the APK's assets")?
+        .read_to_end(&mut wasm)?;
+    let mut core = Core::new(&wasm)?;
+
+    // Initialize Meta OpenXR loader
+    let native_activity = app.activity_as_ptr();
+    let vm = ndk_context::android_context().vm();
+    unsafe { initialize_meta_loader(vm, native_activity)?; }
+
+    let entry = unsafe { xr::Entry::load().map_err(|e| format!("Failed to load OpenXR: {:?}", e))? };
+    let available_extensions = entry.enumerate_extensions()?;
+    if !available_extensions.khr_vulkan_enable2 {
+        return Err("Vulkan not supported".into());
+    }
+
+    let mut extensions = xr::ExtensionSet::default();
+    extensions.khr_vulkan_enable2 = true;
+    extensions.fb_passthrough = available_extensions.fb_passthrough;
+    let xr_instance = entry.create_instance(
+        &xr::ApplicationInfo {
+            application_name: APP_NAME,
+            application_version: 1,
+            engine_name: "fastn",
+            engine_version: 1,
+            api_version: xr::Version::new(1, 0, 0),
+        },
+        &extensions,
+        &[],
+    )?;
+
+    let system = xr_instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+    let system_props = xr_instance.system_properties(system)?;
+    log::info!("System: {}", system_props.system_name);
+
+    let views = xr_instance.enumerate_view_configuration_views(system, xr::ViewConfigurationType::PRIMARY_STEREO)?;
+    let _vk_requirements = xr_instance.graphics_requirements::<xr::Vulkan>(system)?;
+
+    // Vulkan instance, device and queue, created through OpenXR
+    let vk_entry = unsafe { ash::Entry::load()? };
+    let vk_app_info = ash::vk::ApplicationInfo::default()
+        .application_version(1)
+        .engine_name(c"fastn")
+        .engine_version(1)
+        .api_version(ash::vk::make_api_version(0, 1, 1, 0));
+    let vk_instance_create_info = ash::vk::InstanceCreateInfo::default().application_info(&vk_app_info);
+    let vk_instance_raw = unsafe {
+        xr_instance.create_vulkan_instance(
+            system,
+            std::mem::transmute(vk_entry.static_fn().get_instance_proc_addr),
+            &vk_instance_create_info as *const _ as *const _,
+        )?.map_err(|e| format!("Vulkan instance creation failed: {:?}", e))?
+    };
+    let vk_instance = unsafe {
+        ash::Instance::load(vk_entry.static_fn(), ash::vk::Instance::from_raw(vk_instance_raw as _))
+    };
+
+    let vk_physical_device = unsafe {
+        let pd = xr_instance.vulkan_graphics_device(system, vk_instance.handle().as_raw() as _)?;
+        ash::vk::PhysicalDevice::from_raw(pd as _)
+    };
+    let queue_family_props = unsafe { vk_instance.get_physical_device_queue_family_properties(vk_physical_device) };
+    let queue_family_index = queue_family_props
+        .iter()
+        .position(|props| props.queue_flags.contains(ash::vk::QueueFlags::GRAPHICS))
+        .ok_or("No graphics queue family")? as u32;
+
+    let queue_priorities = [1.0f32];
+    let queue_create_info = ash::vk::DeviceQueueCreateInfo::default()
+        .queue_family_index(queue_family_index)
+        .queue_priorities(&queue_priorities);
+    let device_create_info =
+        ash::vk::DeviceCreateInfo::default().queue_create_infos(std::slice::from_ref(&queue_create_info));
+    let vk_device_raw = unsafe {
+        xr_instance.create_vulkan_device(
+            system,
+            std::mem::transmute(vk_entry.static_fn().get_instance_proc_addr),
+            vk_physical_device.as_raw() as _,
+            &device_create_info as *const _ as *const _,
+        )?.map_err(|e| format!("Vulkan device creation failed: {:?}", e))?
+    };
+    let vk_device = unsafe { ash::Device::load(vk_instance.fp_v1_0(), ash::vk::Device::from_raw(vk_device_raw as _)) };
+
+    let (session, mut frame_waiter, mut frame_stream) = unsafe {
+        xr_instance.create_session::<xr::Vulkan>(
+            system,
+            &xr::vulkan::SessionCreateInfo {
+                instance: vk_instance.handle().as_raw() as _,
+                physical_device: vk_physical_device.as_raw() as _,
+                device: vk_device.handle().as_raw() as _,
+                queue_family_index,
+                queue_index: 0,
+            },
+        )?
+    };
+    let stage = session.create_reference_space(xr::ReferenceSpaceType::STAGE, xr::Posef::IDENTITY)?;
+    let head = session.create_reference_space(xr::ReferenceSpaceType::VIEW, xr::Posef::IDENTITY)?;
+    let controllers = Controllers::new(&xr_instance, &session)?;
+    let mut passthrough = if available_extensions.fb_passthrough {
+        Passthrough::new(&session)
+            .inspect_err(|e| log::warn!("Passthrough unavailable: {}", e))
+            .ok()
+    } else {
+        None
+    };
+
+    let swapchain_format = ash::vk::Format::R8G8B8A8_SRGB;
+    let mut swapchain_data: Vec<_> = views
+        .iter()
+        .map(|view| {
+            let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+                create_flags: xr::SwapchainCreateFlags::EMPTY,
+                usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT,
+                format: swapchain_format.as_raw() as _,
+                sample_count: 1,
+                width: view.recommended_image_rect_width,
+                height: view.recommended_image_rect_height,
+                face_count: 1,
+                array_size: 1,
+                mip_count: 1,
+            })?;
+            let images: Vec<ash::vk::Image> = swapchain
+                .enumerate_images()?
+                .into_iter()
+                .map(|img| ash::vk::Image::from_raw(img as _))
+                .collect();
+            Ok((swapchain, images, view.recommended_image_rect_width, view.recommended_image_rect_height))
+        })
+        .collect::<Result<_, xr::sys::Result>>()?;
+    let mut renderer = Renderer::new(
+        &vk_instance,
+        vk_physical_device,
+        vk_device.clone(),
+        queue_family_index,
+        swapchain_format,
+        &swapchain_data
+            .iter()
+            .map(|(_, images, width, height)| renderer::Swapchain {
+                images,
+                width: *width,
+                height: *height,
+            })
+            .collect::<Vec<_>>(),
+    )?;
+
+    core.send(&Event::Lifecycle(LifecycleEvent::Init(InitEvent {
+        platform: Platform::Quest,
+        viewport_width: views[0].recommended_image_rect_width,
+        viewport_height: views[0].recommended_image_rect_height,
+        dpr: 1.0,
+        xr_supported: true,
+        xr_immersive_vr: true,
+        xr_immersive_ar: false,
+        webrtc_supported: false,
+        websocket_supported: false,
+        features: std::iter::once(FEATURE_SCENE_HIERARCHY)
+            .chain(passthrough.is_some().then_some(FEATURE_TRANSPARENT_BACKGROUND))
+            .map(str::to_string)
+            .collect(),
+        accessibility: AccessibilityPreferences::default(),
+        conventions: Conventions::CORE,
+        camera: None,
+    })))?;
+
+    let mut session_running = false;
+    let mut should_quit = false;
+    let mut xr_state = XrSessionState::None;
+    let mut exit_requested = false;
+    let mut last_time: Option<xr::Time> = None;
+    let mut frame = 0u64;
+
+    while !should_quit {
+        let mut lifecycle = vec![];
+        app.poll_events(Some(std::time::Duration::from_millis(0)), |event| match event {
+            PollEvent::Main(MainEvent::Pause) => lifecycle.push(LifecycleEvent::Pause),
+            PollEvent::Main(MainEvent::Resume { .. }) => lifecycle.push(LifecycleEvent::Resume),
+            PollEvent::Main(MainEvent::Destroy) => should_quit = true,
+            _ => {}
+        });
+        for event in lifecycle {
+            core.send(&Event::Lifecycle(event))?;
+        }
+
+        let mut event_buffer = xr::EventDataBuffer::new();
+        while let Some(event) = xr_instance.poll_event(&mut event_buffer)? {
+            match event {
+                xr::Event::SessionStateChanged(e) => {
+                    log::info!("Session state: {:?}", e.state());
+                    let state = match e.state() {
+                        xr::SessionState::READY => {
+                            session.begin(xr::ViewConfigurationType::PRIMARY_STEREO)?;
+                            session_running = true;
+                            Some(XrSessionState::Starting)
+                        }
+                        xr::SessionState::FOCUSED => Some(XrSessionState::Active),
+                        xr::SessionState::VISIBLE if xr_state == XrSessionState::Active => {
+                            Some(XrSessionState::Paused)
+                        }
+                        xr::SessionState::STOPPING => {
+                            session.end()?;
+                            session_running = false;
+                            Some(XrSessionState::Ending)
+                        }
+                        xr::SessionState::IDLE if xr_state == XrSessionState::Ending => Some(XrSessionState::None),
+                        xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => {
+                            should_quit = true;
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(state) = state {
+                        xr_state = state;
+                        // The guardian boundary, centered on the stage origin
+                        let play_area = session
+                            .reference_space_bounds_rect(xr::ReferenceSpaceType::STAGE)
+                            .ok()
+                            .flatten()
+                            .map(|extent| PlayArea {
+                                width: extent.width,
+                                depth: extent.height,
+                            });
+                        core.send(&Event::Xr(XrEvent::SessionChanged(XrSessionData {
+                            state,
+                            mode: (state != XrSessionState::None).then_some(XrMode::ImmersiveVr),
+                            dom_overlay: false,
+                            play_area,
+                        })))?;
+                    }
+                }
+                xr::Event::ReferenceSpaceChangePending(_) => core.send(&Event::Xr(XrEvent::ReferenceSpaceReset))?,
+                xr::Event::InstanceLossPending(_) => should_quit = true,
+                _ => {}
+            }
+        }
+
+        if core.state().exit_requested && !exit_requested {
+            session.request_exit()?;
+            exit_requested = true;
+        }
+        if !session_running {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            continue;
+        }
+
+        let frame_state = frame_waiter.wait()?;
+        frame_stream.begin()?;
+
+        let time = frame_state.predicted_display_time;
+        let dt = last_time.map_or(0.0, |last| (time.as_nanos() - last.as_nanos()) as f32 / 1e9);
+        last_time = Some(time);
+        core.send(&Event::Lifecycle(LifecycleEvent::Frame(FrameEvent {
+            time: time.as_nanos() as f64 / 1e9,
+            dt,
+            frame,
+        })))?;
+        frame += 1;
+
+        let rig = transform_matrix(&core.state().rig);
+        let head_pose = head.locate(&stage, time)?;
+        if head_pose.location_flags.contains(xr::SpaceLocationFlags::POSITION_VALID) {
+            core.send(&Event::Xr(XrEvent::HeadPose(input::scene_pose(rig, &head_pose.pose))))?;
+        }
+        for data in controllers.read(&session, &stage, rig, time)? {
+            core.send(&Event::Xr(XrEvent::ControllerPose(data)))?;
+        }
+
+        if !frame_state.should_render {
+            frame_stream.end(time, xr::EnvironmentBlendMode::OPAQUE, &[])?;
+            continue;
+        }
+
+        let (_, xr_views) = session.locate_views(xr::ViewConfigurationType::PRIMARY_STEREO, time, &stage)?;
+        // The core may have moved the rig while handling this frame's events
+        let state = core.state();
+        let rig = transform_matrix(&state.rig);
+        let draws = scene_draws(state);
+        if let Some(passthrough) = &mut passthrough {
+            passthrough.set_shown(state.transparent)?;
+        }
+        let passthrough_layer = passthrough.as_ref().and_then(Passthrough::composition_layer);
+
+        let mut projection_views = Vec::new();
+        let eyes = swapchain_data.iter_mut().zip(xr_views.iter()).enumerate();
+        for (eye, ((swapchain, _, width, height), xr_view)) in eyes {
+            let image_index = swapchain.acquire_image()?;
+            swapchain.wait_image(xr::Duration::INFINITE)?;
+            let pose = rig * input::pose_matrix(&xr_view.pose);
+            renderer.render(
+                eye,
+                image_index as usize,
+                projection(&xr_view.fov) * pose.inverse(),
+                state.background,
+                &draws,
+            )?;
+            swapchain.release_image()?;
+
+            projection_views.push(
+                xr::CompositionLayerProjectionView::new().pose(xr_view.pose).fov(xr_view.fov).sub_image(
+                    xr::SwapchainSubImage::new()
+                        .swapchain(swapchain)
+                        .image_rect(xr::Rect2Di {
+                            offset: xr::Offset2Di { x: 0, y: 0 },
+                            extent: xr::Extent2Di { width: *width as i32, height: *height as i32 },
+                        })
+                        .image_array_index(0),
+                ),
+            );
+        }
+
+        let mut projection_layer = xr::CompositionLayerProjection::new().space(&stage).views(&projection_views);
+        match &passthrough_layer {
+            // The eyes' images are blended over the cameras by their alpha
+            Some(passthrough_layer) => {
+                projection_layer = projection_layer.layer_flags(xr::CompositionLayerFlags::BLEND_TEXTURE_SOURCE_ALPHA);
+                frame_stream.end(time, xr::EnvironmentBlendMode::OPAQUE, &[passthrough_layer, &projection_layer])?;
+            }
+            None => frame_stream.end(time, xr::EnvironmentBlendMode::OPAQUE, &[&projection_layer])?,
+        }
+    }
+
+    core.send(&Event::Lifecycle(LifecycleEvent::Shutdown))?;
+    Ok(())
+}
+
+/// The visible volumes, placed in the scene through their ancestors
+fn scene_draws(state: &ShellState) -> Vec<Draw> {
+    state
+        .volumes
+        .iter()
+        .filter_map(|volume| {
+            let mut model = Mat4::from_scale(Vec3::from_array(volume.extent));
+            for ancestor in state.ancestors(volume) {
+                if !ancestor.visible {
+                    return None;
+                }
+                model = transform_matrix(&ancestor.transform) * model;
+            }
+            Some(Draw {
+                model,
+                color: volume.color,
+            })
+        })
+        .collect()
+}
+
+fn transform_matrix(transform: &Transform) -> Mat4 {
+    Mat4::from_scale_rotation_translation(
+        Vec3::from_array(transform.scale),
+        Quat::from_array(transform.rotation),
+        Vec3::from_array(transform.position),
+    )
+}
+
+/// Projection for an eye's (asymmetric) field of view, with Vulkan's 0..1
+/// depth range; the shader's SPIR-V flips Y for Vulkan
+fn projection(fov: &xr::Fovf) -> Mat4 {
+    let (left, right) = (fov.angle_left.tan(), fov.angle_right.tan());
+    let (up, down) = (fov.angle_up.tan(), fov.angle_down.tan());
+    let (width, height) = (right - left, up - down);
+    Mat4::from_cols(
+        Vec4::new(2.0 / width, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 2.0 / height, 0.0, 0.0),
+        Vec4::new((right + left) / width, (up + down) / height, FAR / (NEAR - FAR), -1.0),
+        Vec4::new(0.0, 0.0, NEAR * FAR / (NEAR - FAR), 0.0),
+    )
+}
+
+/// Initialize Meta's OpenXR loader (`libopenxr_loader.so`, bundled from the
+/// app's libs/) with the Android VM and activity
+unsafe fn initialize_meta_loader(
+    vm: *mut std::ffi::c_void,
+    activity: *mut std::ffi::c_void,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::c_void;
+
+    let lib = libloading::Library::new("libopenxr_loader.so")
+        .map_err(|e| format!("Failed to load loader: {:?}", e))?;
+
+    #[repr(C)]
+    struct XrLoaderInitInfoAndroidKHR {
+        ty: xr::sys::StructureType,
+        next: *const c_void,
+        application_vm: *mut c_void,
+        application_context: *mut c_void,
+    }
+
+    type XrInitializeLoaderKHR = unsafe extern "C" fn(*const c_void) -> xr::sys::Result;
+
+    let init_loader: Option<libloading::Symbol<XrInitializeLoaderKHR>> =
+        lib.get(b"xrInitializeLoaderKHR").ok().or_else(|| {
+            lib.get(b"_Z21xrInitializeLoaderKHRPK29XrLoaderInitInfoBaseHeaderKHR").ok()
+        });
+
+    if let Some(init_fn) = init_loader {
+        let init_info = XrLoaderInitInfoAndroidKHR {
+            ty: xr::sys::StructureType::LOADER_INIT_INFO_ANDROID_KHR,
+            next: std::ptr::null(),
+            application_vm: vm,
+            application_context: activity,
+        };
+        let result = init_fn(&init_info as *const _ as *const c_void);
+        if result != xr::sys::Result::SUCCESS {
+            log::warn!("xrInitializeLoaderKHR failed: {:?}", result);
+        }
+    }
+
+    std::mem::forget(lib);
+    Ok(())
+}
+
+#[no_mangle]
+fn android_main(app: AndroidApp) {
+    android_logger::init_once(
+        android_logger::Config::default()
+            .with_max_level(log::LevelFilter::Info)
+            .with_tag(APP_NAME),
+    );
+
+    log::info!("=== {} started ===", APP_NAME);
+    match run_xr_app(&app) {
+        Ok(()) => log::info!("App exited normally"),
+        Err(e) => log::error!("App error: {}", e),
+    }
+}
@@ -0,0 +1,72 @@
+//! The headset's cameras behind a transparent background (XR_FB_passthrough)
+//!
+//! The passthrough layer is submitted under the eyes' projection layer,
+//! which is then blended over it by alpha: the renderer clears transparent
+//! backgrounds to transparent black. The layer is paused while the
+//! background is opaque, so the cameras only run when they're seen.
+
+use openxr as xr;
+
+pub struct Passthrough {
+    // The layer is dropped before the feature it was made from
+    layer: xr::PassthroughLayer,
+    _passthrough: xr::Passthrough,
+    /// Whether the layer is running
+    shown: bool,
+}
+
+/// `XrCompositionLayerPassthroughFB`, which openxr has no builder for
+#[repr(transparent)]
+pub struct PassthroughLayer(xr::sys::CompositionLayerPassthroughFB);
+
+impl Passthrough {
+    /// Start passthrough, with its layer paused
+    pub fn new(session: &xr::Session<xr::Vulkan>) -> xr::Result<Self> {
+        let passthrough = session.create_passthrough(xr::PassthroughFlagsFB::IS_RUNNING_AT_CREATION)?;
+        let layer = session.create_passthrough_layer(
+            &passthrough,
+            xr::PassthroughFlagsFB::EMPTY,
+            xr::PassthroughLayerPurposeFB::RECONSTRUCTION,
+        )?;
+        Ok(Self {
+            layer,
+            _passthrough: passthrough,
+            shown: false,
+        })
+    }
+
+    /// Resume or pause the layer
+    pub fn set_shown(&mut self, shown: bool) -> xr::Result<()> {
+        if shown != self.shown {
+            if shown {
+                self.layer.resume()?;
+            } else {
+                self.layer.pause()?;
+            }
+            self.shown = shown;
+        }
+        Ok(())
+    }
+
+    /// The layer to submit under the projection layer, None while paused
+    pub fn composition_layer(&self) -> Option<PassthroughLayer> {
+        self.shown.then(|| {
+            PassthroughLayer(xr::sys::CompositionLayerPassthroughFB {
+                ty: xr::sys::CompositionLayerPassthroughFB::TYPE,
+                next: std::ptr::null(),
+                flags: xr::CompositionLayerFlags::EMPTY,
+                space: xr::sys::Space::NULL,
+                layer_handle: self.layer.as_raw(),
+            })
+        })
+    }
+}
+
+impl std::ops::Deref for PassthroughLayer {
+    type Target = xr::CompositionLayerBase<'static, xr::Vulkan>;
+
+    fn deref(&self) -> &Self::Target {
+        // Layers start with the base header that CompositionLayerBase wraps
+        unsafe { &*(&self.0 as *const xr::sys::CompositionLayerPassthroughFB as *const Self::Target) }
+    }
+}
@@ -0,0 +1,428 @@
+//! Vulkan renderer for the eyes' swapchains
+//!
+//! Volumes are drawn as lit boxes, one draw of 36 generated vertices each,
+//! with the box's matrix and color as push constants; there are no vertex
+//! buffers or descriptor sets. The shader (shader.wgsl) is translated to
+//! SPIR-V with naga when the renderer is created.
+
+use ash::vk;
+use glam::Mat4;
+
+const SHADER: &str = include_str!("shader.wgsl");
+
+/// Depth formats the renderer can use, best first; D16 is always supported
+const DEPTH_FORMATS: [vk::Format; 3] = [vk::Format::D32_SFLOAT, vk::Format::D24_UNORM_S8_UINT, vk::Format::D16_UNORM];
+
+/// A volume to draw: the unit box's model matrix, in scene coordinates
+pub struct Draw {
+    pub model: Mat4,
+    pub color: [f32; 4],
+}
+
+/// The shader's push constants
+#[repr(C)]
+struct PushConstants {
+    mvp: [f32; 16],
+    color: [f32; 4],
+}
+
+pub struct Renderer {
+    device: ash::Device,
+    queue: vk::Queue,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    command_pool: vk::CommandPool,
+    cmd: vk::CommandBuffer,
+    fence: vk::Fence,
+    targets: Vec<Target>,
+}
+
+/// What an eye is drawn into: its swapchain images and a depth buffer
+struct Target {
+    width: u32,
+    height: u32,
+    views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+    depth: vk::Image,
+    depth_memory: vk::DeviceMemory,
+    depth_view: vk::ImageView,
+}
+
+/// An eye's swapchain images, all `width` x `height`
+pub struct Swapchain<'a> {
+    pub images: &'a [vk::Image],
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Renderer {
+    /// A renderer drawing into the swapchains, whose images are in `format`
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+        queue_family_index: u32,
+        format: vk::Format,
+        swapchains: &[Swapchain],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let depth_format = DEPTH_FORMATS
+            .into_iter()
+            .find(|&format| {
+                let properties = unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+                properties
+                    .optimal_tiling_features
+                    .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            })
+            .ok_or("No depth format")?;
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+        let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+        let render_pass = create_render_pass(&device, format, depth_format)?;
+        let (pipeline_layout, pipeline) = create_pipeline(&device, render_pass)?;
+
+        let command_pool_info = vk::CommandPoolCreateInfo::default()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = unsafe { device.create_command_pool(&command_pool_info, None)? };
+        let cmd_alloc_info = vk::CommandBufferAllocateInfo::default()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let cmd = unsafe { device.allocate_command_buffers(&cmd_alloc_info)? }[0];
+        let fence_info = vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
+        let fence = unsafe { device.create_fence(&fence_info, None)? };
+
+        let mut renderer = Self {
+            device,
+            queue,
+            render_pass,
+            pipeline_layout,
+            pipeline,
+            command_pool,
+            cmd,
+            fence,
+            targets: Vec::new(),
+        };
+        for swapchain in swapchains {
+            let target = renderer.create_target(swapchain, format, depth_format, &memory_properties)?;
+            renderer.targets.push(target);
+        }
+        Ok(renderer)
+    }
+
+    /// Draw into an eye's swapchain image, cleared to `background`, and wait
+    /// until it's written: the image goes back to OpenXR next. The image is
+    /// left in COLOR_ATTACHMENT_OPTIMAL, as OpenXR expects.
+    pub fn render(
+        &mut self,
+        eye: usize,
+        image_index: usize,
+        view_projection: Mat4,
+        background: [f32; 4],
+        draws: &[Draw],
+    ) -> Result<(), vk::Result> {
+        let target = &self.targets[eye];
+        let device = &self.device;
+        let cmd = self.cmd;
+        let extent = vk::Extent2D {
+            width: target.width,
+            height: target.height,
+        };
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue { float32: background },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+            },
+        ];
+        unsafe {
+            device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+            device.reset_fences(&[self.fence])?;
+            device.reset_command_buffer(cmd, vk::CommandBufferResetFlags::empty())?;
+            let begin_info = vk::CommandBufferBeginInfo::default().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device.begin_command_buffer(cmd, &begin_info)?;
+
+            let render_pass_info = vk::RenderPassBeginInfo::default()
+                .render_pass(self.render_pass)
+                .framebuffer(target.framebuffers[image_index])
+                .render_area(extent.into())
+                .clear_values(&clear_values);
+            device.cmd_begin_render_pass(cmd, &render_pass_info, vk::SubpassContents::INLINE);
+            device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            let viewport = vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: target.width as f32,
+                height: target.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            };
+            device.cmd_set_viewport(cmd, 0, &[viewport]);
+            device.cmd_set_scissor(cmd, 0, &[extent.into()]);
+
+            for draw in draws {
+                let constants = PushConstants {
+                    mvp: (view_projection * draw.model).to_cols_array(),
+                    color: draw.color,
+                };
+                let bytes = std::slice::from_raw_parts(
+                    &constants as *const PushConstants as *const u8,
+                    std::mem::size_of::<PushConstants>(),
+                );
+                device.cmd_push_constants(
+                    cmd,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    bytes,
+                );
+                device.cmd_draw(cmd, 36, 1, 0, 0);
+            }
+
+            device.cmd_end_render_pass(cmd);
+            device.end_command_buffer(cmd)?;
+            let cmd_buffers = [cmd];
+            let submit_info = vk::SubmitInfo::default().command_buffers(&cmd_buffers);
+            device.queue_submit(self.queue, &[submit_info], self.fence)?;
+            device.wait_for_fences(&[self.fence], true, u64::MAX)
+        }
+    }
+
+    /// Views and framebuffers for a swapchain's images, sharing a depth
+    /// buffer of their size
+    fn create_target(
+        &self,
+        swapchain: &Swapchain,
+        format: vk::Format,
+        depth_format: vk::Format,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Result<Target, Box<dyn std::error::Error>> {
+        let device = &self.device;
+        let extent = vk::Extent3D {
+            width: swapchain.width,
+            height: swapchain.height,
+            depth: 1,
+        };
+        let depth_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(depth_format)
+            .extent(extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let depth = unsafe { device.create_image(&depth_info, None)? };
+        let requirements = unsafe { device.get_image_memory_requirements(depth) };
+        let memory_type_index = (0..memory_properties.memory_type_count)
+            .find(|&i| {
+                requirements.memory_type_bits & (1 << i) != 0
+                    && memory_properties.memory_types[i as usize]
+                        .property_flags
+                        .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+            })
+            .ok_or("No memory for the depth buffer")?;
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        let depth_memory = unsafe { device.allocate_memory(&allocate_info, None)? };
+        unsafe { device.bind_image_memory(depth, depth_memory, 0)? };
+        let depth_view = create_view(device, depth, depth_format, vk::ImageAspectFlags::DEPTH)?;
+
+        let mut views = Vec::new();
+        let mut framebuffers = Vec::new();
+        for &image in swapchain.images {
+            let view = create_view(device, image, format, vk::ImageAspectFlags::COLOR)?;
+            let attachments = [view, depth_view];
+            let framebuffer_info = vk::FramebufferCreateInfo::default()
+                .render_pass(self.render_pass)
+                .attachments(&attachments)
+                .width(swapchain.width)
+                .height(swapchain.height)
+                .layers(1);
+            views.push(view);
+            framebuffers.push(unsafe { device.create_framebuffer(&framebuffer_info, None)? });
+        }
+
+        Ok(Target {
+            width: swapchain.width,
+            height: swapchain.height,
+            views,
+            framebuffers,
+            depth,
+            depth_memory,
+            depth_view,
+        })
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            for target in &self.targets {
+                for &framebuffer in &target.framebuffers {
+                    self.device.destroy_framebuffer(framebuffer, None);
+                }
+                for &view in &target.views {
+                    self.device.destroy_image_view(view, None);
+                }
+                self.device.destroy_image_view(target.depth_view, None);
+                self.device.destroy_image(target.depth, None);
+                self.device.free_memory(target.depth_memory, None);
+            }
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_render_pass(self.render_pass, None);
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
+
+/// One subpass drawing into a color and a depth attachment, both cleared
+fn create_render_pass(
+    device: &ash::Device,
+    format: vk::Format,
+    depth_format: vk::Format,
+) -> Result<vk::RenderPass, vk::Result> {
+    let attachments = [
+        vk::AttachmentDescription::default()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+        vk::AttachmentDescription::default()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+    ];
+    let color_ref = vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    };
+    let depth_ref = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+    let subpass = vk::SubpassDescription::default()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(std::slice::from_ref(&color_ref))
+        .depth_stencil_attachment(&depth_ref);
+    let info = vk::RenderPassCreateInfo::default()
+        .attachments(&attachments)
+        .subpasses(std::slice::from_ref(&subpass));
+    unsafe { device.create_render_pass(&info, None) }
+}
+
+/// The volume pipeline: depth tested, alpha blended, both sides of each face
+/// drawn (planes and quads are flat boxes)
+fn create_pipeline(
+    device: &ash::Device,
+    render_pass: vk::RenderPass,
+) -> Result<(vk::PipelineLayout, vk::Pipeline), Box<dyn std::error::Error>> {
+    let module = naga::front::wgsl::parse_str(SHADER).map_err(|e| e.emit_to_string(SHADER))?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::PUSH_CONSTANT)
+        .validate(&module)
+        .map_err(|e| format!("Invalid shader: {:?}", e))?;
+    let spirv = naga::back::spv::write_vec(&module, &info, &naga::back::spv::Options::default(), None)?;
+    let shader_info = vk::ShaderModuleCreateInfo::default().code(&spirv);
+    let shader = unsafe { device.create_shader_module(&shader_info, None)? };
+
+    let push_constant_range = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: std::mem::size_of::<PushConstants>() as u32,
+    };
+    let layout_info =
+        vk::PipelineLayoutCreateInfo::default().push_constant_ranges(std::slice::from_ref(&push_constant_range));
+    let layout = unsafe { device.create_pipeline_layout(&layout_info, None)? };
+
+    let stages = [
+        vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(shader)
+            .name(c"vs_main"),
+        vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(shader)
+            .name(c"fs_main"),
+    ];
+    let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+    let input_assembly =
+        vk::PipelineInputAssemblyStateCreateInfo::default().topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+    let viewport = vk::PipelineViewportStateCreateInfo::default()
+        .viewport_count(1)
+        .scissor_count(1);
+    let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .line_width(1.0);
+    let multisample =
+        vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS);
+    let blend_attachment = vk::PipelineColorBlendAttachmentState::default()
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .color_write_mask(vk::ColorComponentFlags::RGBA);
+    let color_blend =
+        vk::PipelineColorBlendStateCreateInfo::default().attachments(std::slice::from_ref(&blend_attachment));
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+    let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input)
+        .input_assembly_state(&input_assembly)
+        .viewport_state(&viewport)
+        .rasterization_state(&rasterization)
+        .multisample_state(&multisample)
+        .depth_stencil_state(&depth_stencil)
+        .color_blend_state(&color_blend)
+        .dynamic_state(&dynamic)
+        .layout(layout)
+        .render_pass(render_pass)
+        .subpass(0);
+    let pipelines =
+        unsafe { device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None) };
+    // The module is only needed while the pipeline is created
+    unsafe { device.destroy_shader_module(shader, None) };
+    let pipeline = pipelines.map_err(|(_, e)| e)?[0];
+    Ok((layout, pipeline))
+}
+
+fn create_view(
+    device: &ash::Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+) -> Result<vk::ImageView, vk::Result> {
+    let info = vk::ImageViewCreateInfo::default()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        });
+    unsafe { device.create_image_view(&info, None) }
+}
@@ -0,0 +1,52 @@
+// Volume shader for the Quest shell
+
+// One per volume, a box spanning -0.5..0.5 scaled to its extent by `mvp`
+struct Draw {
+    mvp: mat4x4<f32>,
+    color: vec4<f32>,
+};
+
+var<push_constant> draw: Draw;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+};
+
+// 36 vertices, two triangles for each face: +X, -X, +Y, -Y, +Z, -Z
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let face = index / 6u;
+    let axis = face / 2u;
+    let corner = corners[index % 6u];
+
+    var normal = vec3<f32>(0.0);
+    normal[axis] = select(0.5, -0.5, face % 2u == 1u);
+    var position = normal;
+    position[(axis + 1u) % 3u] = corner.x;
+    position[(axis + 2u) % 3u] = corner.y;
+
+    var out: VertexOutput;
+    out.clip_position = draw.mvp * vec4<f32>(position, 1.0);
+    out.normal = normal;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Simple directional lighting, as the native shell's
+    let normal = normalize(in.normal);
+    let light_dir = normalize(vec3<f32>(0.5, 1.0, 0.3));
+    let ambient = 0.3;
+    let diffuse = max(dot(normal, light_dir), 0.0);
+    let brightness = ambient + diffuse * 0.7;
+    return vec4<f32>(draw.color.rgb * brightness, draw.color.a);
+}
@@ -0,0 +1,284 @@
+//! The app's core, run with wasmtime
+//!
+//! Same exports as for the native shell (see fastn-shell's wasm_runtime.rs);
+//! the module is read from the APK's assets. The scene the core builds is
+//! kept in `ShellState` for the renderer.
+
+use fastn_protocol::*;
+use wasmtime::*;
+
+/// Most ancestors followed up from a volume, a guard against cycles
+const MAX_DEPTH: usize = 64;
+
+pub struct Core {
+    store: Store<()>,
+    memory: Memory,
+    app_ptr: i32,
+    alloc: TypedFunc<i32, i32>,
+    on_event: TypedFunc<(i32, i32, i32), i32>,
+    get_result_ptr: TypedFunc<i32, i32>,
+    get_result_len: TypedFunc<i32, i32>,
+    /// What the core asked for that the shell keeps
+    state: ShellState,
+    /// Events produced while executing commands, sent once they are done
+    pending: Vec<Event>,
+}
+
+/// What the shell keeps from the core's commands
+pub struct ShellState {
+    /// Clear color of both eyes
+    pub background: [f32; 4],
+    /// Whether passthrough shows behind the volumes
+    pub transparent: bool,
+    /// Volumes in creation order, parents before their children
+    pub volumes: Vec<Volume>,
+    /// Where the tracking origin is in the scene
+    pub rig: Transform,
+    /// Whether the core asked to leave the session
+    pub exit_requested: bool,
+    /// Kinds of commands already reported as unsupported
+    unsupported: Vec<String>,
+}
+
+/// A volume as the shell draws it: a box spanning its primitive's extent
+pub struct Volume {
+    pub id: VolumeId,
+    pub parent: Option<VolumeId>,
+    /// Relative to the parent when there is one
+    pub transform: Transform,
+    /// Size of the box along X, Y and Z
+    pub extent: [f32; 3],
+    pub color: [f32; 4],
+    pub visible: bool,
+}
+
+impl ShellState {
+    /// The volume and its ancestors, nearest first
+    pub fn ancestors<'a>(&'a self, volume: &'a Volume) -> impl Iterator<Item = &'a Volume> + 'a {
+        std::iter::successors(Some(volume), |v| {
+            let parent = v.parent.as_ref()?;
+            self.volumes.iter().find(|p| &p.id == parent)
+        })
+        .take(MAX_DEPTH)
+    }
+
+    fn volume_mut(&mut self, volume_id: &str) -> Option<&mut Volume> {
+        let volume = self.volumes.iter_mut().find(|v| v.id == volume_id);
+        if volume.is_none() {
+            log::warn!("Unknown volume {}", volume_id);
+        }
+        volume
+    }
+}
+
+impl Core {
+    /// Instantiate the core from its module and run its initial commands
+    pub fn new(wasm: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[])?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("WASM module must export 'memory'")?;
+        let init_core = instance.get_typed_func::<(), i32>(&mut store, "init_core")?;
+        let app_ptr = init_core.call(&mut store, ())?;
+
+        let mut core = Self {
+            alloc: instance.get_typed_func(&mut store, "alloc")?,
+            on_event: instance.get_typed_func(&mut store, "on_event")?,
+            get_result_ptr: instance.get_typed_func(&mut store, "get_result_ptr")?,
+            get_result_len: instance.get_typed_func(&mut store, "get_result_len")?,
+            store,
+            memory,
+            app_ptr,
+            state: ShellState {
+                background: [0.1, 0.1, 0.18, 1.0],
+                transparent: false,
+                volumes: Vec::new(),
+                rig: Transform::default(),
+                exit_requested: false,
+                unsupported: Vec::new(),
+            },
+            pending: Vec::new(),
+        };
+        let commands = core.read_result()?;
+        log::info!("WASM core initialized with {} commands", commands.len());
+        core.execute(commands);
+        Ok(core)
+    }
+
+    pub fn state(&self) -> &ShellState {
+        &self.state
+    }
+
+    /// Send an event to the core and run the commands it returns
+    pub fn send(&mut self, event: &Event) -> Result<(), Box<dyn std::error::Error>> {
+        let event_json = serde_json::to_string(event)?;
+        let event_bytes = event_json.as_bytes();
+        let event_len = event_bytes.len() as i32;
+
+        let event_ptr = self.alloc.call(&mut self.store, event_len)?;
+        self.memory.data_mut(&mut self.store)[event_ptr as usize..event_ptr as usize + event_bytes.len()]
+            .copy_from_slice(event_bytes);
+        self.on_event.call(&mut self.store, (self.app_ptr, event_ptr, event_len))?;
+
+        let commands = self.read_result()?;
+        self.execute(commands);
+        for event in std::mem::take(&mut self.pending) {
+            self.send(&event)?;
+        }
+        Ok(())
+    }
+
+    fn read_result(&mut self) -> Result<Vec<Command>, Box<dyn std::error::Error>> {
+        let result_len = self.get_result_len.call(&mut self.store, self.app_ptr)? as usize;
+        if result_len == 0 {
+            return Ok(vec![]);
+        }
+        let result_ptr = self.get_result_ptr.call(&mut self.store, self.app_ptr)? as usize;
+        let result_bytes = &self.memory.data(&self.store)[result_ptr..result_ptr + result_len];
+        Ok(serde_json::from_slice(result_bytes)?)
+    }
+
+    fn execute(&mut self, commands: Vec<Command>) {
+        for command in commands {
+            match command {
+                Command::Debug(DebugCommand::Log { level, message }) => match level {
+                    LogLevel::Debug => log::debug!("[core] {}", message),
+                    LogLevel::Info => log::info!("[core] {}", message),
+                    LogLevel::Warn => log::warn!("[core] {}", message),
+                    LogLevel::Error => log::error!("[core] {}", message),
+                },
+                Command::Environment(EnvironmentCommand::SetBackground(BackgroundData::Color(color))) => {
+                    self.state.background = color;
+                    self.state.transparent = false;
+                }
+                Command::Environment(EnvironmentCommand::SetBackground(BackgroundData::Transparent)) => {
+                    self.state.background = [0.0, 0.0, 0.0, 0.0];
+                    self.state.transparent = true;
+                }
+                Command::Scene(command) => self.execute_scene(command),
+                Command::Material(MaterialCommand::SetMaterial(data)) => {
+                    if let (Some(volume), Some(color)) = (self.state.volume_mut(&data.volume_id), data.material.color) {
+                        volume.color = color;
+                    }
+                }
+                Command::Xr(XrCommand::SetRigTransform(transform)) => self.state.rig = transform,
+                Command::Xr(XrCommand::Exit) => self.state.exit_requested = true,
+                command => self.unsupported(&command),
+            }
+        }
+    }
+
+    fn execute_scene(&mut self, command: SceneCommand) {
+        match command {
+            SceneCommand::CreateVolume(data) => {
+                let VolumeSource::Primitive(primitive) = &data.source else {
+                    return self.report_unsupported("glTF asset volumes".to_string());
+                };
+                let Some(extent) = extent(primitive) else {
+                    return self.report_unsupported("text volumes".to_string());
+                };
+                let parent = data.parent_id.filter(|parent_id| {
+                    let known = self.state.volumes.iter().any(|v| &v.id == parent_id);
+                    if !known {
+                        log::warn!("Volume {} has unknown parent {}, placing it at the top level", data.volume_id, parent_id);
+                    }
+                    known
+                });
+                self.state.volumes.retain(|v| v.id != data.volume_id);
+                self.state.volumes.push(Volume {
+                    id: data.volume_id,
+                    parent,
+                    transform: data.transform,
+                    extent,
+                    color: data.material.and_then(|m| m.color).unwrap_or([1.0, 1.0, 1.0, 1.0]),
+                    visible: true,
+                });
+            }
+            SceneCommand::DestroyVolume { volume_id } => {
+                let state = &self.state;
+                let doomed: Vec<VolumeId> = state
+                    .volumes
+                    .iter()
+                    .filter(|v| state.ancestors(v).any(|ancestor| ancestor.id == volume_id))
+                    .map(|v| v.id.clone())
+                    .collect();
+                self.state.volumes.retain(|v| !doomed.contains(&v.id));
+            }
+            // Animations jump to their end
+            SceneCommand::SetTransform(data) => {
+                if let Some(volume) = self.state.volume_mut(&data.volume_id) {
+                    volume.transform = data.transform;
+                }
+                if let Some(animation_id) = data.animate.and_then(|animate| animate.animation_id) {
+                    self.pending.push(Event::Scene(SceneEvent::VolumeAnimationComplete {
+                        volume_id: data.volume_id,
+                        animation_id,
+                    }));
+                }
+            }
+            SceneCommand::SetVisible { volume_id, visible } => {
+                if let Some(volume) = self.state.volume_mut(&volume_id) {
+                    volume.visible = visible;
+                }
+            }
+            SceneCommand::SetParent { volume_id, parent_id } => {
+                let state = &self.state;
+                // A parent under the volume would make a cycle
+                let valid = parent_id.as_ref().is_none_or(|parent_id| {
+                    state.volumes.iter().find(|v| &v.id == parent_id).is_some_and(|parent| {
+                        state.ancestors(parent).all(|ancestor| ancestor.id != volume_id)
+                    })
+                });
+                if !valid {
+                    log::warn!("Ignoring parent {:?} of volume {}", parent_id, volume_id);
+                } else if let Some(volume) = self.state.volume_mut(&volume_id) {
+                    volume.parent = parent_id;
+                }
+            }
+            command => self.unsupported(&Command::Scene(command)),
+        }
+    }
+
+    /// Log the first command of each kind the shell can't run yet
+    fn unsupported(&mut self, command: &Command) {
+        let value = serde_json::to_value(command).unwrap_or_default();
+        let category = value["category"].as_str().unwrap_or_default();
+        let kind = match value["command"]["action"].as_str() {
+            Some(action) => format!("{}::{} commands", category, action),
+            None => format!("{} commands", category),
+        };
+        self.report_unsupported(kind);
+    }
+
+    fn report_unsupported(&mut self, kind: String) {
+        if !self.state.unsupported.contains(&kind) {
+            log::warn!("The Quest shell does not support {} yet", kind);
+            self.state.unsupported.push(kind);
+        }
+    }
+}
+
+/// Size of the box standing in for a primitive; None for text, which the
+/// shell can't draw yet
+fn extent(primitive: &Primitive) -> Option<[f32; 3]> {
+    Some(match *primitive {
+        Primitive::Cube { size } => [size; 3],
+        Primitive::Box { width, height, depth } => [width, height, depth],
+        Primitive::Sphere { radius, .. } => [radius * 2.0; 3],
+        Primitive::Cylinder { radius, height, .. } | Primitive::Cone { radius, height, .. } => {
+            [radius * 2.0, height, radius * 2.0]
+        }
+        Primitive::Torus { radius, tube_radius, .. } => {
+            let outer = (radius + tube_radius) * 2.0;
+            [outer, tube_radius * 2.0, outer]
+        }
+        // Planes lie on the floor, quads face +Z
+        Primitive::Plane { width, height } => [width, 0.0, height],
+        Primitive::Quad { width, height } => [width, height, 0.0],
+        Primitive::Text { .. } => return None,
+    })
+}
@@ -7,6 +7,8 @@ class WebGLXRShell {
         this.canvas = canvas;
         this.gl = null;
         this.program = null;
+        // Unlit, textured quads for panels
+        this.panelProgram = null;
         this.positionBuffer = null;
         this.normalBuffer = null;
         this.indexBuffer = null;
@@ -20,6 +22,9 @@ class WebGLXRShell {
         this.sceneState = new SceneState();
         this.inputHandler = new InputHandler(this.core);
         this.inputHandler.setCommandHandler((commands) => this.sceneState.processCommands(commands));
+        this.inputHandler.setPointerHandler((phase, x, y) => this.sceneState.panelPointer(phase, x, y));
+        // Input scripts and recordings, set up once the app is loaded
+        this.automation = null;
 
         // Set up callback for creating custom mesh buffers
         this.sceneState.onVolumeCreated = (volume, assetManager) => {
@@ -27,12 +32,34 @@ class WebGLXRShell {
         };
 
         this.lastFrameTime = performance.now();
+        // Draw calls of the current frame, for the debug HUD
+        this.drawCalls = 0;
 
         // WebXR state
         this.xrSession = null;
+        // Reference space poses are tracked in, and the same space offset by
+        // the rig transform (scene coordinates)
+        this.xrBaseRefSpace = null;
         this.xrRefSpace = null;
+        this.rigTransform = null;
         this.xrGLLayer = null;
+        this.xrMode = null;
+        this.xrState = 'None';
+        this.xrDomOverlay = false;
+        this.xrCapabilities = { features: [] };
         this.inVR = false;
+        // Whether the canvas / XR framebuffer has a stencil buffer (needed
+        // for portals); the XR one is checked on the first XR frame
+        this.canvasStencil = false;
+        this.xrStencil = null;
+        this.portalQuadBuffer = null;
+        this.sceneState.onXrCommand = (command) => this.handleXrCommand(command);
+        this.sceneState.onShellEvent = (event) => {
+            this.sceneState.processCommands(this.core.sendEvent(event));
+        };
+        AccessibilityLayer.watchPreferences(() => {
+            this.sceneState.processCommands(this.core.sendAccessibilityChangedEvent());
+        });
     }
 
     // Create GL buffers for custom mesh from loaded asset
@@ -78,9 +105,9 @@ class WebGLXRShell {
 
     async init() {
         // Initialize WebGL
-        this.gl = this.canvas.getContext('webgl2', { xrCompatible: true });
+        this.gl = this.canvas.getContext('webgl2', { xrCompatible: true, stencil: true });
         if (!this.gl) {
-            this.gl = this.canvas.getContext('webgl', { xrCompatible: true });
+            this.gl = this.canvas.getContext('webgl', { xrCompatible: true, stencil: true });
         }
         if (!this.gl) {
             throw new Error('WebGL not supported');
@@ -91,11 +118,14 @@ class WebGLXRShell {
         // Set initial canvas size
         this.resizeCanvas();
 
-        // Create shader program
+        // Create shader programs
         this.createShaderProgram();
+        this.createPanelProgram();
 
         // Create geometry buffers
         this.createCubeGeometry();
+        this.createPortalGeometry();
+        this.canvasStencil = !!gl.getContextAttributes().stencil;
 
         // Setup input handlers
         this.inputHandler.setup(this.canvas);
@@ -110,14 +140,38 @@ class WebGLXRShell {
         await this.setupXRButton();
     }
 
+    // WebXR mode names used by the protocol vs the WebXR API
+    static XR_MODES = {
+        ImmersiveVr: 'immersive-vr',
+        ImmersiveAr: 'immersive-ar',
+    };
+
+    async detectXR() {
+        this.xrCapabilities = {
+            xrSupported: !!navigator.xr,
+            xrImmersiveVr: false,
+            xrImmersiveAr: false,
+            features: [],
+        };
+        if (!navigator.xr) return;
+
+        this.xrCapabilities.xrImmersiveVr = await navigator.xr.isSessionSupported('immersive-vr');
+        this.xrCapabilities.xrImmersiveAr = await navigator.xr.isSessionSupported('immersive-ar');
+        // DOM overlay is only offered for handheld/headset AR sessions
+        if (this.xrCapabilities.xrImmersiveAr) {
+            this.xrCapabilities.features.push('xr-dom-overlay');
+        }
+    }
+
     async setupXRButton() {
+        await this.detectXR();
+
         if (!navigator.xr) {
             console.log('WebXR not available');
             return;
         }
 
-        const supported = await navigator.xr.isSessionSupported('immersive-vr');
-        if (!supported) {
+        if (!this.xrCapabilities.xrImmersiveVr) {
             console.log('Immersive VR not supported');
             return;
         }
@@ -148,55 +202,180 @@ class WebGLXRShell {
 
     async toggleVR() {
         if (this.xrSession) {
-            await this.xrSession.end();
+            await this.endXRSession();
+            return;
+        }
+        await this.requestXRSession('ImmersiveVr', false);
+    }
+
+    // Handle Xr commands from the core
+    handleXrCommand(command) {
+        if (command.action === 'Enter') {
+            this.requestXRSession(command.mode, command.dom_overlay || false);
+        } else if (command.action === 'Exit') {
+            this.endXRSession();
+        } else if (command.action === 'SetRigTransform') {
+            this.rigTransform = command;
+            this.applyRigTransform();
+        }
+    }
+
+    // Poses are reported in scene coordinates: offset the tracking space by
+    // the inverse of the rig pose
+    applyRigTransform() {
+        if (!this.xrBaseRefSpace) return;
+        if (!this.rigTransform) {
+            this.xrRefSpace = this.xrBaseRefSpace;
+            return;
+        }
+        const [x, y, z] = this.rigTransform.position;
+        const [qx, qy, qz, qw] = this.rigTransform.rotation;
+        const rig = new XRRigidTransform({ x, y, z }, { x: qx, y: qy, z: qz, w: qw });
+        this.xrRefSpace = this.xrBaseRefSpace.getOffsetReferenceSpace(rig.inverse);
+    }
+
+    notifyXrSession(state, mode = null, domOverlay = false) {
+        this.xrState = state;
+        const commands = this.core.sendXrSessionEvent(state, mode, domOverlay);
+        this.sceneState.processCommands(commands);
+    }
+
+    // Root element for HTML UI shown during AR sessions. Pages can provide
+    // their own <div id="xr-overlay">, otherwise an empty one is created.
+    getDomOverlayRoot() {
+        let root = document.getElementById('xr-overlay');
+        if (!root) {
+            root = document.createElement('div');
+            root.id = 'xr-overlay';
+            root.style.display = 'none';
+            document.body.appendChild(root);
+        }
+        return root;
+    }
+
+    async requestXRSession(mode, wantDomOverlay) {
+        if (this.xrSession || this.xrState === 'Starting') {
+            console.warn('XR session already active or starting');
             return;
         }
+        const xrMode = WebGLXRShell.XR_MODES[mode];
+        if (!navigator.xr || !xrMode) {
+            console.warn(`XR mode ${mode} not available`);
+            return;
+        }
+
+        const options = {
+            requiredFeatures: ['local-floor'],
+            optionalFeatures: [],
+        };
+        let overlayRoot = null;
+        if (wantDomOverlay && mode === 'ImmersiveAr') {
+            overlayRoot = this.getDomOverlayRoot();
+            options.optionalFeatures.push('dom-overlay');
+            options.domOverlay = { root: overlayRoot };
+        }
+
+        this.notifyXrSession('Starting', mode);
 
+        let session;
         try {
-            const session = await navigator.xr.requestSession('immersive-vr', {
-                requiredFeatures: ['local-floor'],
-            });
+            session = await navigator.xr.requestSession(xrMode, options);
+        } catch (e) {
+            // Denied by the user/browser or requested without a user gesture
+            console.error('Failed to start XR session:', e);
+            this.notifyXrSession('None');
+            return;
+        }
 
+        try {
             this.xrSession = session;
+            this.xrMode = mode;
             this.inVR = true;
-            this.vrButton.textContent = 'Exit VR';
+            if (this.vrButton) this.vrButton.textContent = 'Exit VR';
 
             // Create XR WebGL layer
-            this.xrGLLayer = new XRWebGLLayer(session, this.gl);
+            this.xrGLLayer = new XRWebGLLayer(session, this.gl, { stencil: true });
             session.updateRenderState({ baseLayer: this.xrGLLayer });
 
             // Get reference space
-            this.xrRefSpace = await session.requestReferenceSpace('local-floor');
-
-            // Notify core that we entered VR
-            const commands = this.core.sendXrSessionEvent('Entered');
-            this.sceneState.processCommands(commands);
-
-            // Handle session end
-            session.addEventListener('end', () => {
-                this.xrSession = null;
-                this.xrRefSpace = null;
-                this.xrGLLayer = null;
-                this.inVR = false;
-                this.vrButton.textContent = 'Enter VR';
-
-                // Notify core that we exited VR
-                const commands = this.core.sendXrSessionEvent('Exited');
+            this.xrBaseRefSpace = await session.requestReferenceSpace('local-floor');
+            this.xrBaseRefSpace.addEventListener('reset', () => {
+                const commands = this.core.sendXrReferenceSpaceResetEvent();
                 this.sceneState.processCommands(commands);
-
-                // Resume non-VR rendering
-                this.lastFrameTime = performance.now();
-                requestAnimationFrame(() => this.render());
             });
+            this.applyRigTransform();
+        } catch (e) {
+            console.error('Failed to set up XR session:', e);
+            session.end().catch(() => {});
+            this.onXRSessionEnded();
+            return;
+        }
 
-            // Start XR render loop
-            session.requestAnimationFrame((time, frame) => this.renderXR(time, frame));
+        // The browser decides whether the overlay was granted
+        this.xrDomOverlay = !!session.domOverlayState;
+        if (overlayRoot) {
+            overlayRoot.style.display = this.xrDomOverlay ? '' : 'none';
+        }
 
+        // Browser UI (system menu, headset removed) can blur or hide the session
+        session.addEventListener('visibilitychange', () => {
+            const state = session.visibilityState === 'visible' ? 'Active' : 'Paused';
+            if (state !== this.xrState) {
+                this.notifyXrSession(state, this.xrMode, this.xrDomOverlay);
+            }
+        });
+
+        // Fires both for endXRSession() and for exits via browser UI
+        session.addEventListener('end', () => this.onXRSessionEnded());
+
+        this.notifyXrSession('Active', mode, this.xrDomOverlay);
+
+        // Start XR render loop
+        session.requestAnimationFrame((time, frame) => this.renderXR(time, frame));
+    }
+
+    async endXRSession() {
+        if (!this.xrSession) return;
+        this.notifyXrSession('Ending', this.xrMode, this.xrDomOverlay);
+        try {
+            await this.xrSession.end();
         } catch (e) {
-            console.error('Failed to start VR session:', e);
+            // Session already ended by the browser; the 'end' handler cleans up
+            console.warn('XR session end failed:', e);
         }
     }
 
+    onXRSessionEnded() {
+        if (!this.inVR && !this.xrSession) return;
+
+        if (this.xrState !== 'Ending') {
+            this.notifyXrSession('Ending', this.xrMode, this.xrDomOverlay);
+        }
+
+        this.xrSession = null;
+        this.xrBaseRefSpace = null;
+        this.xrRefSpace = null;
+        this.xrGLLayer = null;
+        this.xrStencil = null;
+        this.xrMode = null;
+        this.xrDomOverlay = false;
+        this.inVR = false;
+        if (this.vrButton) this.vrButton.textContent = 'Enter VR';
+
+        const overlayRoot = document.getElementById('xr-overlay');
+        if (overlayRoot) overlayRoot.style.display = 'none';
+
+        // Restore the canvas framebuffer and size for non-XR rendering
+        this.gl.bindFramebuffer(this.gl.FRAMEBUFFER, null);
+        this.resizeCanvas();
+
+        this.notifyXrSession('None');
+
+        // Resume non-VR rendering
+        this.lastFrameTime = performance.now();
+        requestAnimationFrame(() => this.render());
+    }
+
     resizeCanvas() {
         const width = window.innerWidth;
         const height = window.innerHeight;
@@ -275,6 +454,52 @@ class WebGLXRShell {
         };
     }
 
+    createPanelProgram() {
+        const gl = this.gl;
+
+        // Drawn on the unit quad of portals, scaled to the panel
+        const vsSource = `
+            attribute vec3 aPosition;
+
+            uniform mat4 uMVP;
+
+            varying vec2 vUV;
+
+            void main() {
+                gl_Position = uMVP * vec4(aPosition, 1.0);
+                vUV = vec2(aPosition.x + 0.5, 0.5 - aPosition.y);
+            }
+        `;
+
+        const fsSource = `
+            precision mediump float;
+
+            uniform sampler2D uTexture;
+
+            varying vec2 vUV;
+
+            void main() {
+                gl_FragColor = vec4(texture2D(uTexture, vUV).rgb, 1.0);
+            }
+        `;
+
+        const program = gl.createProgram();
+        gl.attachShader(program, this.compileShader(gl.VERTEX_SHADER, vsSource));
+        gl.attachShader(program, this.compileShader(gl.FRAGMENT_SHADER, fsSource));
+        gl.linkProgram(program);
+
+        if (!gl.getProgramParameter(program, gl.LINK_STATUS)) {
+            throw new Error('Panel shader link failed: ' + gl.getProgramInfoLog(program));
+        }
+
+        this.panelProgram = {
+            program,
+            position: gl.getAttribLocation(program, 'aPosition'),
+            mvp: gl.getUniformLocation(program, 'uMVP'),
+            texture: gl.getUniformLocation(program, 'uTexture'),
+        };
+    }
+
     compileShader(type, source) {
         const gl = this.gl;
         const shader = gl.createShader(type);
@@ -316,9 +541,48 @@ class WebGLXRShell {
         this.indexCount = indices.length;
     }
 
+    // Unit quad in the XY plane, drawn as a triangle strip
+    createPortalGeometry() {
+        const gl = this.gl;
+        this.portalQuadBuffer = gl.createBuffer();
+        gl.bindBuffer(gl.ARRAY_BUFFER, this.portalQuadBuffer);
+        gl.bufferData(gl.ARRAY_BUFFER, new Float32Array([
+            -0.5, -0.5, 0,
+             0.5, -0.5, 0,
+            -0.5,  0.5, 0,
+             0.5,  0.5, 0,
+        ]), gl.STATIC_DRAW);
+    }
+
     async loadWasm(wasmPath) {
+        await this.sceneState.assetManager.loadAssetMap();
         const commands = await this.core.loadWasm(wasmPath);
         this.sceneState.processCommands(commands);
+
+        // Tell the core what this shell supports (XR modes, DOM overlay,
+        // portals, deferred commands, screen readers, hit tests, animation,
+        // the scene hierarchy, capture, panels, audio, the asset cache)
+        const capabilities = {
+            ...this.xrCapabilities,
+            features: this.xrCapabilities.features.concat(
+                this.canvasStencil ? ['portals'] : [],
+                [
+                    CommandScheduler.FEATURE,
+                    AccessibilityLayer.FEATURE,
+                    DebugHud.FEATURE,
+                    Picking.FEATURE,
+                    TransformAnimations.FEATURE,
+                    SceneState.FEATURE,
+                    MediaCapture.FEATURE,
+                    PanelLayer.FEATURE,
+                    PanelLayer.HTML_FEATURE,
+                ],
+                this.sceneState.audio ? [SpatialAudio.FEATURE] : [],
+                AssetCache.supported() ? [AssetCache.FEATURE] : []
+            ),
+        };
+        const initCommands = this.core.sendInitEvent('WebGL', capabilities);
+        this.sceneState.processCommands(initCommands);
     }
 
     render() {
@@ -331,14 +595,19 @@ class WebGLXRShell {
         const dt = (now - this.lastFrameTime) / 1000.0;
         this.lastFrameTime = now;
 
-        // Send frame event to core
+        // Send scripted input that is due, then the frame event
+        if (this.automation) {
+            this.sceneState.processCommands(this.automation.sendDue());
+        }
         const commands = this.core.sendFrameEvent(dt);
         this.sceneState.processCommands(commands);
+        this.sceneState.runScheduled();
+        this.sceneState.updateAnimations();
 
         // Clear
         gl.viewport(0, 0, this.canvas.width, this.canvas.height);
         gl.clearColor(0.1, 0.1, 0.15, 1.0);
-        gl.clear(gl.COLOR_BUFFER_BIT | gl.DEPTH_BUFFER_BIT);
+        gl.clear(gl.COLOR_BUFFER_BIT | gl.DEPTH_BUFFER_BIT | gl.STENCIL_BUFFER_BIT);
 
         // Render scene
         const camera = this.sceneState.camera;
@@ -346,7 +615,10 @@ class WebGLXRShell {
         const projection = MathUtils.perspectiveRH(camera.fov, aspect, camera.near, camera.far);
         const view = MathUtils.lookAtRH(camera.position, camera.target, camera.up);
 
+        this.drawCalls = 0;
         this.renderScene(projection, view);
+        this.sceneState.endFrame(this.drawCalls);
+        this.sceneState.captureFrame(gl, { x: 0, y: 0, width: this.canvas.width, height: this.canvas.height });
 
         requestAnimationFrame(() => this.render());
     }
@@ -379,9 +651,14 @@ class WebGLXRShell {
         );
         this.sceneState.processCommands(headCommands);
 
-        // Send frame event
+        // Send scripted input that is due, then the frame event
+        if (this.automation) {
+            this.sceneState.processCommands(this.automation.sendDue());
+        }
         const frameCommands = this.core.sendFrameEvent(dt);
         this.sceneState.processCommands(frameCommands);
+        this.sceneState.runScheduled();
+        this.sceneState.updateAnimations();
 
         // Get input sources (controllers)
         for (const inputSource of session.inputSources) {
@@ -421,10 +698,14 @@ class WebGLXRShell {
 
         // Bind XR framebuffer
         gl.bindFramebuffer(gl.FRAMEBUFFER, glLayer.framebuffer);
+        if (this.xrStencil === null) {
+            this.xrStencil = gl.getParameter(gl.STENCIL_BITS) > 0;
+        }
         gl.clearColor(0.1, 0.1, 0.15, 1.0);
-        gl.clear(gl.COLOR_BUFFER_BIT | gl.DEPTH_BUFFER_BIT);
+        gl.clear(gl.COLOR_BUFFER_BIT | gl.DEPTH_BUFFER_BIT | gl.STENCIL_BUFFER_BIT);
 
         // Render for each eye
+        this.drawCalls = 0;
         for (const view of pose.views) {
             const viewport = glLayer.getViewport(view);
             gl.viewport(viewport.x, viewport.y, viewport.width, viewport.height);
@@ -435,18 +716,103 @@ class WebGLXRShell {
 
             this.renderScene(projection, viewMatrix);
         }
+        this.sceneState.endFrame(this.drawCalls);
+        // Captures keep the first eye's view
+        this.sceneState.captureFrame(gl, glLayer.getViewport(pose.views[0]));
     }
 
     renderScene(projection, view) {
+        const stencil = this.inVR ? this.xrStencil : this.canvasStencil;
+        if (stencil && this.sceneState.portals.size > 0) {
+            this.renderPortals(projection, view);
+        }
+        this.renderVolumes(projection, view);
+    }
+
+    // For each portal: mark its surface in the stencil buffer, then render
+    // the scene as seen from the exit where the stencil matches. Afterwards
+    // the surfaces go into the depth buffer so local geometry in front of a
+    // portal still covers it and geometry behind it doesn't.
+    renderPortals(projection, view) {
+        const gl = this.gl;
+        gl.useProgram(this.program);
+
+        // Far to near, so nearer portals win where they overlap on screen
+        const depth = (portal) => MathUtils.transformPoint(view, portal.position)[2];
+        const portals = [...this.sceneState.portals.values()].sort((a, b) => depth(a) - depth(b));
+
+        gl.enable(gl.STENCIL_TEST);
+        portals.forEach((portal, i) => {
+            const ref = (i % 255) + 1;
+
+            // Portals can be looked (and walked) through from both sides
+            gl.disable(gl.CULL_FACE);
+            gl.stencilFunc(gl.ALWAYS, ref, 0xff);
+            gl.stencilOp(gl.KEEP, gl.KEEP, gl.REPLACE);
+            gl.colorMask(false, false, false, false);
+            gl.depthMask(false);
+            this.drawPortalSurface(projection, view, portal);
+            gl.colorMask(true, true, true, true);
+            gl.depthMask(true);
+            gl.enable(gl.CULL_FACE);
+
+            gl.stencilFunc(gl.EQUAL, ref, 0xff);
+            gl.stencilOp(gl.KEEP, gl.KEEP, gl.KEEP);
+            const portalView = MathUtils.multiplyMatrices(view, portal.remoteToLocal);
+            this.renderVolumes(this.portalProjection(projection, portalView, portal), portalView);
+            gl.clear(gl.DEPTH_BUFFER_BIT);
+        });
+        gl.disable(gl.STENCIL_TEST);
+
+        gl.disable(gl.CULL_FACE);
+        gl.colorMask(false, false, false, false);
+        for (const portal of portals) {
+            this.drawPortalSurface(projection, view, portal);
+        }
+        gl.colorMask(true, true, true, true);
+        gl.enable(gl.CULL_FACE);
+    }
+
+    // Projection for the view through a portal, with the near plane moved
+    // onto the exit so nothing between the virtual eye and the exit shows
+    portalProjection(projection, portalView, portal) {
+        const n = MathUtils.transformDirection(portalView, portal.exitNormal);
+        const p = MathUtils.transformPoint(portalView, portal.exitPosition);
+        let plane = [n[0], n[1], n[2], -MathUtils.dot(n, p)];
+        // Eye (the view-space origin) must be on the clipped side
+        if (plane[3] > 0) plane = plane.map((v) => -v);
+        if (Math.abs(plane[3]) < 1e-4) return projection;
+        return MathUtils.obliqueProjection(projection, plane);
+    }
+
+    drawPortalSurface(projection, view, portal) {
+        const gl = this.gl;
+        const mvp = MathUtils.multiplyMatrices(MathUtils.multiplyMatrices(projection, view), portal.surface);
+        gl.uniformMatrix4fv(this.uniforms.mvp, false, mvp);
+        gl.uniformMatrix4fv(this.uniforms.model, false, portal.surface);
+
+        gl.bindBuffer(gl.ARRAY_BUFFER, this.portalQuadBuffer);
+        gl.enableVertexAttribArray(this.attribs.position);
+        gl.vertexAttribPointer(this.attribs.position, 3, gl.FLOAT, false, 0, 0);
+        gl.disableVertexAttribArray(this.attribs.normal);
+        gl.vertexAttrib3f(this.attribs.normal, 0, 0, 1);
+        gl.drawArrays(gl.TRIANGLE_STRIP, 0, 4);
+        this.drawCalls++;
+    }
+
+    renderVolumes(projection, view) {
         const gl = this.gl;
 
         gl.useProgram(this.program);
 
-        // Render each volume
+        // Render each volume; panels afterwards, with their own program
+        const panels = [];
         for (const volume of this.sceneState.volumes.values()) {
-            // For custom meshes, use the scale from transform; for primitives, use size
-            const scale = volume.meshType === 'asset' ? volume.scale[0] : volume.size;
-            const model = MathUtils.modelMatrix(volume.position, scale);
+            if (volume.panel) {
+                panels.push(volume);
+                continue;
+            }
+            const model = MathUtils.volumeMatrix(volume);
 
             // MVP = projection * view * model
             const vp = MathUtils.multiplyMatrices(projection, view);
@@ -468,6 +834,7 @@ class WebGLXRShell {
 
                 gl.bindBuffer(gl.ELEMENT_ARRAY_BUFFER, volume.customBuffers.indexBuffer);
                 gl.drawElements(gl.TRIANGLES, volume.customBuffers.indexCount, volume.customBuffers.indexType, 0);
+                this.drawCalls++;
             } else {
                 gl.bindBuffer(gl.ARRAY_BUFFER, this.positionBuffer);
                 gl.enableVertexAttribArray(this.attribs.position);
@@ -479,8 +846,63 @@ class WebGLXRShell {
 
                 gl.bindBuffer(gl.ELEMENT_ARRAY_BUFFER, this.indexBuffer);
                 gl.drawElements(gl.TRIANGLES, this.indexCount, gl.UNSIGNED_SHORT, 0);
+                this.drawCalls++;
+            }
+        }
+
+        if (panels.length > 0) {
+            this.renderPanels(projection, view, panels);
+        }
+    }
+
+    renderPanels(projection, view, volumes) {
+        const gl = this.gl;
+        const panelProgram = this.panelProgram;
+        gl.useProgram(panelProgram.program);
+        gl.activeTexture(gl.TEXTURE0);
+        gl.uniform1i(panelProgram.texture, 0);
+        gl.bindBuffer(gl.ARRAY_BUFFER, this.portalQuadBuffer);
+        gl.enableVertexAttribArray(panelProgram.position);
+        gl.vertexAttribPointer(panelProgram.position, 3, gl.FLOAT, false, 0, 0);
+
+        const vp = MathUtils.multiplyMatrices(projection, view);
+        for (const volume of volumes) {
+            const panel = volume.panel;
+            const size = MathUtils.poseMatrix([0, 0, 0], [0, 0, 0, 1], [panel.size[0], panel.size[1], 1]);
+            const mvp = MathUtils.multiplyMatrices(vp, MathUtils.multiplyMatrices(MathUtils.volumeMatrix(volume), size));
+            gl.uniformMatrix4fv(panelProgram.mvp, false, mvp);
+            gl.bindTexture(gl.TEXTURE_2D, this.panelTexture(volume));
+            gl.drawArrays(gl.TRIANGLE_STRIP, 0, 4);
+            this.drawCalls++;
+        }
+        // Portal surfaces are drawn after this with the volume program
+        gl.useProgram(this.program);
+    }
+
+    // The panel's texture, uploaded again when its content was redrawn
+    panelTexture(volume) {
+        const gl = this.gl;
+        const panel = volume.panel;
+        if (!volume.panelTexture) {
+            volume.panelTexture = { texture: gl.createTexture(), version: -1 };
+            gl.bindTexture(gl.TEXTURE_2D, volume.panelTexture.texture);
+            // Panel sizes needn't be powers of two
+            gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_WRAP_S, gl.CLAMP_TO_EDGE);
+            gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_WRAP_T, gl.CLAMP_TO_EDGE);
+            gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MIN_FILTER, gl.LINEAR);
+            gl.texParameteri(gl.TEXTURE_2D, gl.TEXTURE_MAG_FILTER, gl.LINEAR);
+        }
+        gl.bindTexture(gl.TEXTURE_2D, volume.panelTexture.texture);
+        if (volume.panelTexture.version !== panel.version) {
+            volume.panelTexture.version = panel.version;
+            try {
+                gl.texImage2D(gl.TEXTURE_2D, 0, gl.RGBA, gl.RGBA, gl.UNSIGNED_BYTE, panel.canvas);
+            } catch (e) {
+                // Browsers that treat <foreignObject> images as cross-origin
+                console.warn(`Panel ${panel.id} not drawn:`, e);
             }
         }
+        return volume.panelTexture.texture;
     }
 }
 
@@ -499,6 +921,7 @@ async function initWebGLXR(wasmPathArg) {
         const wasmPath = wasmPathArg || params.get('app') || canvas.dataset.wasm || WASM_PATH;
 
         await shell.loadWasm(wasmPath);
+        shell.automation = await InputAutomation.fromUrl(shell.core);
         shell.render();
 
         console.log('fastn-shell-web (WebGL+XR) running');
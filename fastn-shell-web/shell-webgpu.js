@@ -23,11 +23,18 @@ class WebGPUShell {
         this.sceneState = new SceneState();
         this.inputHandler = new InputHandler(this.core);
         this.inputHandler.setCommandHandler((commands) => this.sceneState.processCommands(commands));
+        this.inputHandler.setPointerHandler((phase, x, y) => this.sceneState.panelPointer(phase, x, y));
+        // Input scripts and recordings, set up once the app is loaded
+        this.automation = null;
 
         // Set up callback for creating custom mesh buffers
         this.sceneState.onVolumeCreated = (volume, assetManager) => {
             this.createCustomMeshBuffers(volume, assetManager);
         };
+        // Screen-reader activations go back to the core
+        this.sceneState.onShellEvent = (event) => {
+            this.sceneState.processCommands(this.core.sendEvent(event));
+        };
 
         this.lastFrameTime = performance.now();
     }
@@ -253,6 +260,7 @@ class WebGPUShell {
     }
 
     async loadWasm(wasmPath) {
+        await this.sceneState.assetManager.loadAssetMap();
         const commands = await this.core.loadWasm(wasmPath);
         this.sceneState.processCommands(commands);
     }
@@ -293,12 +301,16 @@ class WebGPUShell {
         const dt = (now - this.lastFrameTime) / 1000.0;
         this.lastFrameTime = now;
 
-        // Poll gamepad state
+        // Poll gamepad state, then send scripted input that is due
         this.pollGamepads();
+        if (this.automation) {
+            this.sceneState.processCommands(this.automation.sendDue());
+        }
 
         // Send frame event to core (handles camera movement)
         const commands = this.core.sendFrameEvent(dt);
         this.sceneState.processCommands(commands);
+        this.sceneState.updateAnimations();
 
         const commandEncoder = this.device.createCommandEncoder();
         const textureView = this.context.getCurrentTexture().createView();
@@ -345,6 +357,8 @@ class WebGPUShell {
 
         renderPass.end();
         this.device.queue.submit([commandEncoder.finish()]);
+        // One draw call per volume
+        this.sceneState.endFrame(this.sceneState.volumes.size);
 
         requestAnimationFrame(() => this.render());
     }
@@ -356,9 +370,7 @@ class WebGPUShell {
         const projection = MathUtils.perspectiveRH(camera.fov, aspect, camera.near, camera.far);
         const view = MathUtils.lookAtRH(camera.position, camera.target, camera.up);
 
-        // For custom meshes, use the scale from transform; for primitives, use size
-        const scale = volume.meshType === 'asset' ? volume.scale[0] : volume.size;
-        const model = MathUtils.modelMatrix(volume.position, scale);
+        const model = MathUtils.volumeMatrix(volume);
 
         // MVP = projection * view * model
         return MathUtils.multiplyMatrices(projection, MathUtils.multiplyMatrices(view, model));
@@ -380,6 +392,7 @@ async function initWebGPU(wasmPathArg) {
         const wasmPath = wasmPathArg || params.get('app') || canvas.dataset.wasm || WASM_PATH;
 
         await shell.loadWasm(wasmPath);
+        shell.automation = await InputAutomation.fromUrl(shell.core);
         shell.render();
 
         console.log('fastn-shell-web (WebGPU) running');
@@ -0,0 +1,319 @@
+//! Disk cache for assets fetched over HTTP(S)
+//!
+//! Downloads are kept in `FASTN_ASSET_CACHE_DIR` (by default `fastn/assets`
+//! in the user's cache directory), one file per URL and ETag, with an
+//! `index.json` saying which file holds which URL and how long it is fresh.
+//!
+//! A copy is used without asking the server while its `Cache-Control:
+//! max-age` lasts (`CachePolicy::Default`), and revalidated with
+//! `If-None-Match`/`If-Modified-Since` after that; a `304 Not Modified`
+//! keeps it. When the server can't be reached, a stale copy is used as is.
+//! Nothing is kept of `no-store` responses or `CachePolicy::NoStore` loads.
+//!
+//! The cache holds at most `FASTN_ASSET_CACHE_MAX_MB` megabytes (default
+//! `DEFAULT_MAX_BYTES`); the least recently used files are evicted first.
+
+use fastn_protocol::CachePolicy;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderMap, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size limit unless `FASTN_ASSET_CACHE_MAX_MB` sets one
+pub const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Downloads are read in chunks of this size, with a progress report after each
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+
+const INDEX_FILE: &str = "index.json";
+
+/// A cached download
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// File in the cache directory holding the body
+    file: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Until when (seconds since 1970) the copy may be used without
+    /// revalidating it
+    fresh_until: u64,
+    size: u64,
+    /// Last use (milliseconds since 1970), for eviction
+    last_used: u128,
+}
+
+/// Fetches network assets, keeping them on disk across sessions
+pub struct AssetCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Cached downloads by URL
+    entries: HashMap<String, Entry>,
+    client: Client,
+}
+
+impl AssetCache {
+    /// Open the cache where the environment says, or in the user's cache
+    /// directory
+    pub fn open_default() -> Self {
+        let dir = std::env::var_os("FASTN_ASSET_CACHE_DIR").map_or_else(
+            || {
+                dirs::cache_dir()
+                    .unwrap_or_else(std::env::temp_dir)
+                    .join("fastn")
+                    .join("assets")
+            },
+            PathBuf::from,
+        );
+        let max_bytes = std::env::var("FASTN_ASSET_CACHE_MAX_MB")
+            .ok()
+            .and_then(|mb| mb.parse::<u64>().ok())
+            .map_or(DEFAULT_MAX_BYTES, |mb| mb * 1024 * 1024);
+        Self::open(dir, max_bytes)
+    }
+
+    /// Open the cache in `dir`, holding at most `max_bytes`. A missing or
+    /// unreadable index starts an empty cache.
+    pub fn open(dir: PathBuf, max_bytes: u64) -> Self {
+        let entries = std::fs::read(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default();
+        Self {
+            dir,
+            max_bytes,
+            entries,
+            client: Client::new(),
+        }
+    }
+
+    /// Fetch a URL, from the cache when `policy` and the copy's freshness
+    /// allow. `on_progress` is called with the bytes downloaded so far and
+    /// the size, if the server says.
+    pub fn fetch(
+        &mut self,
+        url: &str,
+        policy: CachePolicy,
+        on_progress: impl FnMut(u64, Option<u64>),
+    ) -> Result<Vec<u8>, String> {
+        if policy == CachePolicy::NoStore {
+            let response = self.get(url, &HeaderMap::new())?;
+            return download(url, response, on_progress);
+        }
+
+        let cached = self.entries.get(url).cloned();
+        if let Some(entry) = &cached
+            && policy == CachePolicy::Default
+            && now_secs() < entry.fresh_until
+            && let Some(bytes) = self.read_entry(url, entry)
+        {
+            log::debug!("Using cached {}", url);
+            return Ok(bytes);
+        }
+
+        let mut validators = HeaderMap::new();
+        if let Some(entry) = &cached {
+            if let Some(etag) = entry.etag.as_ref().and_then(|v| v.parse().ok()) {
+                validators.insert(IF_NONE_MATCH, etag);
+            }
+            if let Some(modified) = entry.last_modified.as_ref().and_then(|v| v.parse().ok()) {
+                validators.insert(IF_MODIFIED_SINCE, modified);
+            }
+        }
+        let response = match self.get(url, &validators) {
+            Ok(response) => response,
+            Err(e) => {
+                // Offline: a stale copy beats nothing
+                if let Some(bytes) = cached.as_ref().and_then(|entry| self.read_entry(url, entry)) {
+                    log::warn!("{}, using the cached copy", e);
+                    return Ok(bytes);
+                }
+                return Err(e);
+            }
+        };
+
+        if response.status() == StatusCode::NOT_MODIFIED
+            && let Some(mut entry) = cached
+        {
+            if let Some(bytes) = self.read_entry(url, &entry) {
+                log::debug!("Cached {} is still current", url);
+                entry.fresh_until = now_secs() + max_age(response.headers()).unwrap_or_default();
+                entry.last_used = now_millis();
+                self.entries.insert(url.to_string(), entry);
+                self.save_index();
+                return Ok(bytes);
+            }
+            // The copy is gone; ask again without validators
+            let response = self.get(url, &HeaderMap::new())?;
+            return self.store(url, response, on_progress);
+        }
+        self.store(url, response, on_progress)
+    }
+
+    /// GET a URL, failing on error statuses
+    fn get(&self, url: &str, headers: &HeaderMap) -> Result<Response, String> {
+        let response = self
+            .client
+            .get(url)
+            .headers(headers.clone())
+            .send()
+            .map_err(|e| format!("Failed to fetch {}: {}", url, e))?;
+        match response.status() {
+            status if status.is_success() || status == StatusCode::NOT_MODIFIED => Ok(response),
+            status => Err(format!("Failed to fetch {}: HTTP {}", url, status)),
+        }
+    }
+
+    /// Download a response and keep it, unless the server says not to
+    fn store(&mut self, url: &str, response: Response, on_progress: impl FnMut(u64, Option<u64>)) -> Result<Vec<u8>, String> {
+        let headers = response.headers().clone();
+        let bytes = download(url, response, on_progress)?;
+
+        self.remove(url);
+        let Some(fresh_for) = max_age(&headers) else {
+            self.save_index();
+            return Ok(bytes);
+        };
+        if bytes.len() as u64 > self.max_bytes {
+            log::debug!("Not caching {}: larger than the whole cache", url);
+            self.save_index();
+            return Ok(bytes);
+        }
+
+        let etag = header(&headers, ETAG);
+        let entry = Entry {
+            file: file_name(url, etag.as_deref()),
+            etag,
+            last_modified: header(&headers, LAST_MODIFIED),
+            fresh_until: now_secs() + fresh_for,
+            size: bytes.len() as u64,
+            last_used: now_millis(),
+        };
+        let written = std::fs::create_dir_all(&self.dir).and_then(|()| std::fs::write(self.dir.join(&entry.file), &bytes));
+        match written {
+            Ok(()) => {
+                self.entries.insert(url.to_string(), entry);
+                self.evict();
+            }
+            Err(e) => log::warn!("Failed to cache {}: {}", url, e),
+        }
+        self.save_index();
+        Ok(bytes)
+    }
+
+    /// Read a cached copy, marking it used. Copies whose file is gone are
+    /// forgotten.
+    fn read_entry(&mut self, url: &str, entry: &Entry) -> Option<Vec<u8>> {
+        match std::fs::read(self.dir.join(&entry.file)) {
+            Ok(bytes) => {
+                if let Some(entry) = self.entries.get_mut(url) {
+                    entry.last_used = now_millis();
+                }
+                self.save_index();
+                Some(bytes)
+            }
+            Err(e) => {
+                log::warn!("Dropping cached {}: {}", url, e);
+                self.remove(url);
+                self.save_index();
+                None
+            }
+        }
+    }
+
+    /// Forget a URL and delete its file
+    fn remove(&mut self, url: &str) {
+        if let Some(entry) = self.entries.remove(url) {
+            let _ = std::fs::remove_file(self.dir.join(entry.file));
+        }
+    }
+
+    /// Remove the least recently used copies until the cache fits
+    fn evict(&mut self) {
+        let mut total: u64 = self.entries.values().map(|entry| entry.size).sum();
+        while total > self.max_bytes {
+            let Some(url) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(url, _)| url.clone())
+            else {
+                return;
+            };
+            log::debug!("Evicting cached {}", url);
+            total -= self.entries[&url].size;
+            self.remove(&url);
+        }
+    }
+
+    fn save_index(&self) {
+        let saved = serde_json::to_vec(&self.entries)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                std::fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+                let temp = self.dir.join(format!("{}.tmp", INDEX_FILE));
+                std::fs::write(&temp, json).map_err(|e| e.to_string())?;
+                std::fs::rename(&temp, self.dir.join(INDEX_FILE)).map_err(|e| e.to_string())
+            });
+        if let Err(e) = saved {
+            log::warn!("Failed to save the asset cache index: {}", e);
+        }
+    }
+}
+
+/// Read a response body in chunks, reporting progress
+fn download(url: &str, mut response: Response, mut on_progress: impl FnMut(u64, Option<u64>)) -> Result<Vec<u8>, String> {
+    let total = response.content_length();
+    let mut bytes = Vec::with_capacity(total.unwrap_or_default() as usize);
+    let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        let n = response.read(&mut chunk).map_err(|e| format!("Failed to download {}: {}", url, e))?;
+        if n == 0 {
+            return Ok(bytes);
+        }
+        bytes.extend_from_slice(&chunk[..n]);
+        on_progress(bytes.len() as u64, total);
+    }
+}
+
+/// Seconds a response may be used without revalidating it, None if it
+/// must not be stored (`no-store`). Responses without a `max-age` are
+/// revalidated every time.
+fn max_age(headers: &HeaderMap) -> Option<u64> {
+    let mut max_age = 0;
+    let mut no_cache = false;
+    for directive in headers.get_all(CACHE_CONTROL).iter().filter_map(|v| v.to_str().ok()).flat_map(|v| v.split(',')) {
+        let directive = directive.trim().to_ascii_lowercase();
+        match directive.split_once('=') {
+            None if directive == "no-store" => return None,
+            None if directive == "no-cache" => no_cache = true,
+            Some(("max-age", secs)) => max_age = secs.trim_matches('"').parse().unwrap_or_default(),
+            _ => {}
+        }
+    }
+    Some(if no_cache { 0 } else { max_age })
+}
+
+fn header(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+/// Cache file of a URL's version
+fn file_name(url: &str, etag: Option<&str>) -> String {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    etag.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
@@ -1,32 +1,101 @@
-//! Asset loader for GLB/glTF files
+//! Asset loader for GLB/glTF files and images
 //!
-//! Uses the gltf crate to load 3D model files and extract mesh data.
+//! Uses the gltf crate to load 3D model files: the triangle primitives of
+//! the default scene are flattened into one mesh (with node transforms
+//! applied) for the renderer, and the meshes, skeletons and animations are
+//! described to the core in `AssetEvent::Loaded`. Models with skins, morph
+//! targets or animations also get a `Rig`, so the renderer can pose them.
+//!
+//! PNG and JPEG files load as images, for `TextureSource::Asset`. A model's
+//! base color texture is kept the same way.
+//!
+//! `http(s)://` assets are fetched through the `AssetCache`, which keeps
+//! them on disk across sessions. External buffers and images of `.gltf`
+//! files are only found next to local files; fetch GLBs instead.
 
+use crate::asset_cache::AssetCache;
+use crate::skinning::Rig;
+use crate::texture::TextureImage;
+use fastn_protocol::{
+    AnimationInfo, AssetLoadedData, AssetType, BoneInfo, CachePolicy, Conventions, MeshInfo, SkeletonInfo,
+};
+use glam::{Mat3, Mat4, Vec3};
 use std::collections::HashMap;
+use std::io::Read;
 use std::path::Path;
+use std::sync::Arc;
+
+/// Files are read in chunks of this size, with a progress report after each
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
 
 /// Loaded mesh data ready for GPU upload
 #[derive(Debug)]
 pub struct LoadedMesh {
     pub vertices: Vec<[f32; 3]>,
     pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
     pub indices: Vec<u32>,
     pub color: [f32; 4],  // Base color from material (if available)
+    /// Skeleton, morph targets and animations; the vertices above are the
+    /// rest pose
+    pub rig: Option<Arc<Rig>>,
+}
+
+/// Position and normal displacements of a morph target's vertices (empty
+/// if the target doesn't displace them)
+pub type MorphDisplacements = (Vec<[f32; 3]>, Vec<[f32; 3]>);
+
+/// A triangle primitive of the scene, in the space of the node showing it
+pub struct ScenePrimitive {
+    pub node: usize,
+    /// The node's transform in model space
+    pub transform: Mat4,
+    /// Skin of the node, if any
+    pub skin: Option<usize>,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// First texture coordinates, 0,0 if the primitive has none
+    pub uvs: Vec<[f32; 2]>,
+    /// Joints of the skin moving each vertex, and their weights
+    pub joints: Option<Vec<[u16; 4]>>,
+    pub weights: Option<Vec<[f32; 4]>>,
+    pub targets: Vec<MorphDisplacements>,
+    pub indices: Vec<u32>,
+    pub color: [f32; 4],
+    /// Index of the base color texture's image
+    pub image: Option<usize>,
+}
+
+/// A loaded asset: geometry or an image for the renderer, and the
+/// description sent to the core
+struct LoadedAsset {
+    mesh: Option<LoadedMesh>,
+    image: Option<TextureImage>,
+    data: AssetLoadedData,
 }
 
 /// Asset manager that loads and caches assets
 pub struct AssetManager {
-    /// Cache of loaded meshes by asset_id
-    meshes: HashMap<String, LoadedMesh>,
+    /// Cache of loaded assets by asset_id
+    assets: HashMap<String, LoadedAsset>,
     /// Base path for resolving relative asset paths
     base_path: Option<std::path::PathBuf>,
+    /// Downloads of network assets, opened by the first one
+    cache: Option<AssetCache>,
+}
+
+/// Where an asset's bytes come from
+enum Source {
+    Local(std::path::PathBuf),
+    Remote(String),
 }
 
 impl AssetManager {
     pub fn new() -> Self {
         Self {
-            meshes: HashMap::new(),
+            assets: HashMap::new(),
             base_path: None,
+            cache: None,
         }
     }
 
@@ -35,81 +104,155 @@ impl AssetManager {
         self.base_path = Some(path.as_ref().to_path_buf());
     }
 
-    /// Load a GLB/glTF file and cache it
-    pub fn load(&mut self, asset_id: &str, path: &str) -> Result<(), String> {
-        // Check if already loaded
-        if self.meshes.contains_key(asset_id) {
-            log::debug!("Asset {} already loaded, skipping", asset_id);
-            return Ok(());
+    /// Map an asset URI to a local file or a URL. Bare paths (older cores)
+    /// are treated as bundle paths.
+    fn resolve(&self, uri: &str) -> Result<Source, String> {
+        let (scheme, location) = uri.split_once("://").unwrap_or(("bundle", uri));
+        match fastn_protocol::AssetScheme::from_name(scheme) {
+            Some(fastn_protocol::AssetScheme::Bundle) => Ok(Source::Local(match self.base_path {
+                Some(ref base) => base.join(location),
+                None => std::path::PathBuf::from(location),
+            })),
+            Some(fastn_protocol::AssetScheme::File) => Ok(Source::Local(std::path::PathBuf::from(location))),
+            Some(fastn_protocol::AssetScheme::Http | fastn_protocol::AssetScheme::Https) => {
+                Ok(Source::Remote(uri.to_string()))
+            }
+            _ => Err(format!("Unsupported asset scheme: {}://", scheme)),
         }
+    }
 
-        // Resolve the path
-        let full_path = if let Some(ref base) = self.base_path {
-            base.join(path)
-        } else {
-            std::path::PathBuf::from(path)
-        };
-
-        log::info!("Loading asset {} from {:?}", asset_id, full_path);
-
-        // Load the glTF file
-        let (document, buffers, _images) = gltf::import(&full_path)
-            .map_err(|e| format!("Failed to load GLB: {}", e))?;
-
-        // Get the first mesh from the file
-        let mesh = document.meshes().next()
-            .ok_or_else(|| "No meshes found in GLB file".to_string())?;
-
-        // Get the first primitive from the mesh
-        let primitive = mesh.primitives().next()
-            .ok_or_else(|| "No primitives found in mesh".to_string())?;
+    /// Read a file the core refers to by asset URI, without keeping it
+    /// loaded (network files still go through the disk cache)
+    pub fn read(&mut self, uri: &str) -> Result<Vec<u8>, String> {
+        match self.resolve(uri)? {
+            Source::Local(path) => std::fs::read(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e)),
+            Source::Remote(url) => self.fetch(&url, CachePolicy::Default, |_, _| {}),
+        }
+    }
 
-        // Extract positions
-        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    fn fetch(&mut self, url: &str, cache: CachePolicy, on_progress: impl FnMut(u64, Option<u64>)) -> Result<Vec<u8>, String> {
+        self.cache.get_or_insert_with(AssetCache::open_default).fetch(url, cache, on_progress)
+    }
 
-        let positions: Vec<[f32; 3]> = reader.read_positions()
-            .ok_or_else(|| "No positions found".to_string())?
-            .collect();
+    /// Load a GLB/glTF or image file and keep it loaded. `cache` says how
+    /// network files use the disk cache.
+    ///
+    /// `on_progress` is called with the bytes read so far and the file size
+    /// while the file is read.
+    pub fn load(
+        &mut self,
+        asset_id: &str,
+        path: &str,
+        cache: CachePolicy,
+        on_progress: impl FnMut(u64, Option<u64>),
+    ) -> Result<AssetLoadedData, String> {
+        // Check if already loaded
+        if let Some(asset) = self.assets.get(asset_id) {
+            log::debug!("Asset {} already loaded, skipping", asset_id);
+            return Ok(asset.data.clone());
+        }
 
-        // Extract normals (or generate defaults)
-        let normals: Vec<[f32; 3]> = reader.read_normals()
-            .map(|n| n.collect())
-            .unwrap_or_else(|| {
-                // Default normals pointing up
-                vec![[0.0, 1.0, 0.0]; positions.len()]
-            });
+        let source = self.resolve(path)?;
+        let (bytes, full_path) = match source {
+            Source::Local(full_path) => {
+                log::info!("Loading asset {} from {:?}", asset_id, full_path);
+                (read_with_progress(&full_path, on_progress)?, Some(full_path))
+            }
+            Source::Remote(url) => {
+                log::info!("Loading asset {} from {}", asset_id, url);
+                (self.fetch(&url, cache, on_progress)?, None)
+            }
+        };
+        let base = full_path.as_deref().and_then(Path::parent);
+        if image::guess_format(&bytes).is_ok() {
+            let image = TextureImage::decode(&bytes)?;
+            log::info!("Loaded image {}: {}x{}", asset_id, image.width, image.height);
+            let data = AssetLoadedData {
+                asset_id: asset_id.to_string(),
+                path: path.to_string(),
+                asset_type: AssetType::Image,
+                meshes: vec![],
+                animations: vec![],
+                skeletons: vec![],
+                conventions: None,
+            };
+            let asset = LoadedAsset {
+                mesh: None,
+                image: Some(image),
+                data: data.clone(),
+            };
+            self.assets.insert(asset_id.to_string(), asset);
+            return Ok(data);
+        }
+        let asset_type = match bytes.starts_with(b"glTF") {
+            true => AssetType::Glb,
+            false => AssetType::Gltf,
+        };
 
-        // Extract indices
-        let indices: Vec<u32> = reader.read_indices()
-            .ok_or_else(|| "No indices found".to_string())?
-            .into_u32()
-            .collect();
+        // Parse the document; external buffers and images of .gltf files
+        // are resolved next to the file
+        let gltf = gltf::Gltf::from_slice(&bytes).map_err(|e| format!("Failed to parse glTF: {}", e))?;
+        let buffers = gltf::import_buffers(&gltf.document, base, gltf.blob.clone())
+            .map_err(|e| format!("Failed to load glTF buffers: {}", e))?;
+        let document = &gltf.document;
 
-        // Try to extract base color from material
-        let color = primitive.material().pbr_metallic_roughness().base_color_factor();
+        let primitives = read_primitives(document, &buffers)?;
+        let mut mesh = flatten_meshes(&primitives)?;
+        mesh.rig = Rig::new(document, &buffers, &primitives).map(Arc::new);
+        let image = primitives.first().and_then(|p| p.image).and_then(|index| {
+            let source = document.images().nth(index)?.source();
+            gltf::image::Data::from_source(source, base, &buffers)
+                .map_err(|e| e.to_string())
+                .and_then(|data| TextureImage::from_gltf(&data))
+                .inspect_err(|e| log::warn!("Skipping base color texture of {}: {}", asset_id, e))
+                .ok()
+        });
+        let skeletons = read_skeletons(document);
+        let data = AssetLoadedData {
+            asset_id: asset_id.to_string(),
+            path: path.to_string(),
+            asset_type,
+            meshes: read_mesh_info(document),
+            animations: read_animations(document, &buffers, &skeletons),
+            skeletons: skeletons.into_iter().map(|(_, skeleton)| skeleton).collect(),
+            // glTF is always right-handed, Y-up, in meters
+            conventions: Some(Conventions::CORE),
+        };
 
         log::info!(
-            "Loaded mesh: {} vertices, {} normals, {} indices, color: {:?}",
-            positions.len(),
-            normals.len(),
-            indices.len(),
-            color
+            "Loaded asset {}: {} vertices, {} indices, color {:?}, {} meshes, {} skeletons, {} animations",
+            asset_id,
+            mesh.vertices.len(),
+            mesh.indices.len(),
+            mesh.color,
+            data.meshes.len(),
+            data.skeletons.len(),
+            data.animations.len()
         );
 
-        let loaded_mesh = LoadedMesh {
-            vertices: positions,
-            normals,
-            indices,
-            color,
+        let asset = LoadedAsset {
+            mesh: Some(mesh),
+            image,
+            data: data.clone(),
         };
+        self.assets.insert(asset_id.to_string(), asset);
+        Ok(data)
+    }
 
-        self.meshes.insert(asset_id.to_string(), loaded_mesh);
-        Ok(())
+    /// Drop a loaded asset, returning whether it was loaded
+    pub fn unload(&mut self, asset_id: &str) -> bool {
+        self.assets.remove(asset_id).is_some()
     }
 
     /// Get a loaded mesh by asset_id
     pub fn get_mesh(&self, asset_id: &str) -> Option<&LoadedMesh> {
-        self.meshes.get(asset_id)
+        self.assets.get(asset_id).and_then(|asset| asset.mesh.as_ref())
+    }
+
+    /// Get a loaded image by asset_id: the image file, or the base color
+    /// texture of a model
+    pub fn get_image(&self, asset_id: &str) -> Option<&TextureImage> {
+        self.assets.get(asset_id).and_then(|asset| asset.image.as_ref())
     }
 }
 
@@ -118,3 +261,217 @@ impl Default for AssetManager {
         Self::new()
     }
 }
+
+fn read_with_progress(path: &Path, mut on_progress: impl FnMut(u64, Option<u64>)) -> Result<Vec<u8>, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+    let total = file.metadata().ok().map(|m| m.len());
+
+    let mut bytes = Vec::with_capacity(total.unwrap_or_default() as usize);
+    let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut chunk).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        if n == 0 {
+            return Ok(bytes);
+        }
+        bytes.extend_from_slice(&chunk[..n]);
+        on_progress(bytes.len() as u64, total);
+    }
+}
+
+/// The triangle primitives of the default scene (or the first scene). The
+/// color and image are the base color of the primitive's material.
+fn read_primitives(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Result<Vec<ScenePrimitive>, String> {
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| "No scenes found in glTF file".to_string())?;
+
+    let mut primitives = vec![];
+    // (node, transform of its parent)
+    let mut stack: Vec<(gltf::Node, Mat4)> = scene.nodes().map(|node| (node, Mat4::IDENTITY)).collect();
+    while let Some((node, parent_transform)) = stack.pop() {
+        let transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+        stack.extend(node.children().map(|child| (child, transform)));
+
+        let Some(node_mesh) = node.mesh() else {
+            continue;
+        };
+
+        for primitive in node_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::debug!("Skipping {:?} primitive of mesh {}", primitive.mode(), node_mesh.index());
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions: Vec<[f32; 3]> = positions.collect();
+
+            // Normals default to pointing up
+            let normals: Vec<[f32; 3]> = reader
+                .read_normals()
+                .map(|n| n.collect())
+                .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; positions.len()]);
+            let uvs: Vec<[f32; 2]> = reader
+                .read_tex_coords(0)
+                .map(|uvs| uvs.into_f32().collect())
+                .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
+
+            // Non-indexed primitives use their vertices in order
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            let targets = reader
+                .read_morph_targets()
+                .map(|(positions, normals, _)| {
+                    (
+                        positions.map(|p| p.collect()).unwrap_or_default(),
+                        normals.map(|n| n.collect()).unwrap_or_default(),
+                    )
+                })
+                .collect();
+
+            let base_color = primitive.material().pbr_metallic_roughness();
+            primitives.push(ScenePrimitive {
+                node: node.index(),
+                transform,
+                skin: node.skin().map(|skin| skin.index()),
+                joints: reader.read_joints(0).map(|joints| joints.into_u16().collect()),
+                weights: reader.read_weights(0).map(|weights| weights.into_f32().collect()),
+                targets,
+                positions,
+                normals,
+                uvs,
+                indices,
+                color: base_color.base_color_factor(),
+                image: base_color.base_color_texture().map(|info| info.texture().source().index()),
+            });
+        }
+    }
+    Ok(primitives)
+}
+
+/// Merge the primitives into one mesh in model space. The base color comes
+/// from the first primitive's material.
+fn flatten_meshes(primitives: &[ScenePrimitive]) -> Result<LoadedMesh, String> {
+    let mut mesh = LoadedMesh {
+        vertices: vec![],
+        normals: vec![],
+        uvs: vec![],
+        indices: vec![],
+        color: primitives.first().map_or([1.0, 1.0, 1.0, 1.0], |p| p.color),
+        rig: None,
+    };
+
+    for primitive in primitives {
+        let transform = primitive.transform;
+        let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+        let offset = mesh.vertices.len() as u32;
+        mesh.vertices
+            .extend(primitive.positions.iter().map(|p| transform.transform_point3(Vec3::from_array(*p)).to_array()));
+        mesh.normals.extend(
+            primitive
+                .normals
+                .iter()
+                .map(|n| (normal_matrix * Vec3::from_array(*n)).normalize_or_zero().to_array()),
+        );
+        mesh.uvs.extend(&primitive.uvs);
+        mesh.indices.extend(primitive.indices.iter().map(|i| i + offset));
+    }
+
+    if mesh.indices.is_empty() {
+        return Err("No triangle meshes found in glTF file".to_string());
+    }
+    Ok(mesh)
+}
+
+fn read_mesh_info(document: &gltf::Document) -> Vec<MeshInfo> {
+    document
+        .meshes()
+        .map(|mesh| MeshInfo {
+            index: mesh.index() as u32,
+            name: mesh.name().map(str::to_string),
+            vertex_count: mesh
+                .primitives()
+                .filter_map(|p| p.get(&gltf::Semantic::Positions))
+                .map(|accessor| accessor.count() as u32)
+                .sum(),
+            has_skeleton: document
+                .nodes()
+                .any(|node| node.skin().is_some() && node.mesh().is_some_and(|m| m.index() == mesh.index())),
+        })
+        .collect()
+}
+
+/// One skeleton per skin, with the node indices of its joints
+fn read_skeletons(document: &gltf::Document) -> Vec<(Vec<usize>, SkeletonInfo)> {
+    // Parent of every node, to link the bones
+    let mut parents = HashMap::new();
+    for node in document.nodes() {
+        for child in node.children() {
+            parents.insert(child.index(), node.index());
+        }
+    }
+
+    document
+        .skins()
+        .map(|skin| {
+            let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+            let bones = skin
+                .joints()
+                .enumerate()
+                .map(|(index, joint)| BoneInfo {
+                    index: index as u32,
+                    name: joint.name().map(str::to_string).unwrap_or_else(|| format!("bone-{}", index)),
+                    parent_index: parents
+                        .get(&joint.index())
+                        .and_then(|parent| joints.iter().position(|j| j == parent))
+                        .map(|i| i as u32),
+                })
+                .collect();
+            let name = skin.name().map(str::to_string).unwrap_or_else(|| format!("skeleton-{}", skin.index()));
+            (joints, SkeletonInfo { name, bones })
+        })
+        .collect()
+}
+
+/// Animations with their duration (the last keyframe time) and the skeleton
+/// whose bones they move, if any
+fn read_animations(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+    skeletons: &[(Vec<usize>, SkeletonInfo)],
+) -> Vec<AnimationInfo> {
+    document
+        .animations()
+        .map(|animation| {
+            let mut duration_secs = 0.0f32;
+            let mut target_skeleton = None;
+            for channel in animation.channels() {
+                let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+                if let Some(inputs) = reader.read_inputs() {
+                    duration_secs = inputs.fold(duration_secs, f32::max);
+                }
+                let node = channel.target().node().index();
+                if target_skeleton.is_none() {
+                    target_skeleton = skeletons
+                        .iter()
+                        .find(|(joints, _)| joints.contains(&node))
+                        .map(|(_, skeleton)| skeleton.name.clone());
+                }
+            }
+            AnimationInfo {
+                name: animation
+                    .name()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("animation-{}", animation.index())),
+                duration_secs,
+                target_skeleton,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,252 @@
+//! Sound playback using SDL2
+//!
+//! Plays the core's audio sources (`AudioCommand`). The core works out each
+//! source's gain, low-pass cutoff and direction and sends them as `Mix`;
+//! the mixer applies them, panning in stereo by the direction. Sounds must
+//! be WAV files, the one format SDL decodes; they are converted to mono at
+//! the device's rate when loaded.
+
+use fastn_protocol::{AudioCommand, AudioEvent, AudioMix, AudioSourceId, CreateAudioSourceData, SoundId};
+use sdl2::audio::{AudioCVT, AudioCallback, AudioDevice, AudioFormat, AudioSpecDesired, AudioSpecWAV};
+use sdl2::rwops::RWops;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Seconds for mix changes to settle, to avoid clicks
+const SMOOTHING_SECS: f32 = 0.03;
+
+/// Plays sounds on the default output device
+pub struct AudioPlayer {
+    device: AudioDevice<Mixer>,
+    /// Decoded sounds, mono at the device's rate
+    sounds: HashMap<SoundId, Arc<[f32]>>,
+}
+
+impl AudioPlayer {
+    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Self, String> {
+        let audio = sdl_context.audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(48000),
+            channels: Some(2),
+            samples: Some(1024),
+        };
+        let device = audio.open_playback(None, &desired, |spec| {
+            log::info!("Audio output: {} Hz, {} channels", spec.freq, spec.channels);
+            Mixer::new(spec.freq as f32, spec.channels as usize)
+        })?;
+        device.resume();
+        Ok(Self {
+            device,
+            sounds: HashMap::new(),
+        })
+    }
+
+    /// Stop and forget everything, for the next app
+    pub fn reset(&mut self) {
+        self.sounds.clear();
+        let mut mixer = self.device.lock();
+        mixer.voices.clear();
+        mixer.ended.clear();
+    }
+
+    /// Decode a WAV file, returning its duration in milliseconds
+    pub fn load(&mut self, sound_id: &str, bytes: &[u8]) -> Result<u32, String> {
+        let wav = AudioSpecWAV::load_wav_rw(&mut RWops::from_bytes(bytes)?)?;
+        let rate = self.device.spec().freq;
+        let cvt = AudioCVT::new(wav.format, wav.channels, wav.freq, AudioFormat::F32LSB, 1, rate)?;
+        let samples: Arc<[f32]> = cvt
+            .convert(wav.buffer().to_vec())
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let duration_ms = (samples.len() as u64 * 1000 / rate as u64) as u32;
+        self.sounds.insert(sound_id.to_string(), samples);
+        Ok(duration_ms)
+    }
+
+    /// Run any command but `Load`; errors are reported as `AudioEvent::Error`
+    pub fn execute(&mut self, cmd: AudioCommand) -> Result<(), AudioEvent> {
+        let mut mixer = self.device.lock();
+        match cmd {
+            AudioCommand::Load { .. } => {}
+            AudioCommand::Unload { sound_id } => {
+                if let Some(sound) = self.sounds.remove(&sound_id) {
+                    mixer.voices.retain(|_, voice| !voice.plays(&sound));
+                }
+            }
+            AudioCommand::CreateSource(data) => {
+                let sound = self.sounds.get(&data.sound_id).cloned();
+                mixer.voices.insert(data.source_id.clone(), Voice::new(data, sound));
+            }
+            AudioCommand::DestroySource { source_id } => {
+                mixer.voices.remove(&source_id);
+            }
+            AudioCommand::Play { source_id } => {
+                let Some(voice) = mixer.voices.get_mut(&source_id) else {
+                    return Ok(());
+                };
+                if voice.sound.is_none() {
+                    return Err(AudioEvent::Error {
+                        sound_id: voice.sound_id.clone(),
+                        error: "Sound is not loaded".to_string(),
+                    });
+                }
+                voice.position = 0;
+                voice.playing = true;
+            }
+            AudioCommand::Stop { source_id } => {
+                if let Some(voice) = mixer.voices.get_mut(&source_id) {
+                    voice.playing = false;
+                }
+            }
+            AudioCommand::SetVolume { source_id, volume } => {
+                if let Some(voice) = mixer.voices.get_mut(&source_id) {
+                    voice.volume = volume.clamp(0.0, 1.0);
+                }
+            }
+            AudioCommand::SetLooping { source_id, looping } => {
+                if let Some(voice) = mixer.voices.get_mut(&source_id) {
+                    voice.looping = looping;
+                }
+            }
+            AudioCommand::Mix { sources } => {
+                let rate = mixer.rate;
+                for mix in &sources {
+                    if let Some(voice) = mixer.voices.get_mut(&mix.source_id) {
+                        voice.mix(mix, rate);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sources that played to the end since the last call
+    pub fn take_ended(&mut self) -> Vec<AudioSourceId> {
+        std::mem::take(&mut self.device.lock().ended)
+    }
+}
+
+/// A source, as the audio thread sees it
+struct Voice {
+    sound_id: SoundId,
+    /// None if the sound failed to load
+    sound: Option<Arc<[f32]>>,
+    /// Next sample to play
+    position: usize,
+    playing: bool,
+    looping: bool,
+    volume: f32,
+    /// Current and target values of the mixed parameters
+    gain: f32,
+    target_gain: f32,
+    /// Left and right channel gains
+    pan: [f32; 2],
+    target_pan: [f32; 2],
+    /// One-pole low-pass coefficient (1 lets everything through) and state
+    filter: f32,
+    target_filter: f32,
+    filtered: f32,
+}
+
+impl Voice {
+    fn new(data: CreateAudioSourceData, sound: Option<Arc<[f32]>>) -> Self {
+        let center = std::f32::consts::FRAC_1_SQRT_2;
+        Self {
+            sound_id: data.sound_id,
+            sound,
+            position: 0,
+            playing: false,
+            looping: data.looping,
+            volume: data.volume.clamp(0.0, 1.0),
+            // Silent until the first mix
+            gain: 0.0,
+            target_gain: 0.0,
+            pan: [center, center],
+            target_pan: [center, center],
+            filter: 1.0,
+            target_filter: 1.0,
+            filtered: 0.0,
+        }
+    }
+
+    fn plays(&self, sound: &Arc<[f32]>) -> bool {
+        self.sound.as_ref().is_some_and(|own| Arc::ptr_eq(own, sound))
+    }
+
+    fn mix(&mut self, mix: &AudioMix, rate: f32) {
+        self.target_gain = mix.gain.clamp(0.0, 1.0);
+        // Equal-power panning by how far right the source is
+        let angle = (mix.direction[0].clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+        self.target_pan = [angle.cos(), angle.sin()];
+        self.target_filter = match mix.low_pass_hz {
+            Some(cutoff) => 1.0 - (-2.0 * std::f32::consts::PI * cutoff.max(0.0) / rate).exp(),
+            None => 1.0,
+        };
+    }
+}
+
+/// Mixes the voices on the audio thread
+struct Mixer {
+    rate: f32,
+    channels: usize,
+    /// How far parameters move toward their targets per sample
+    smoothing: f32,
+    voices: HashMap<AudioSourceId, Voice>,
+    ended: Vec<AudioSourceId>,
+}
+
+impl Mixer {
+    fn new(rate: f32, channels: usize) -> Self {
+        Self {
+            rate,
+            channels: channels.max(1),
+            smoothing: 1.0 - (-1.0 / (rate * SMOOTHING_SECS)).exp(),
+            voices: HashMap::new(),
+            ended: Vec::new(),
+        }
+    }
+}
+
+impl AudioCallback for Mixer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        let (channels, k) = (self.channels, self.smoothing);
+        for (source_id, voice) in &mut self.voices {
+            let Some(sound) = voice.sound.as_ref().filter(|_| voice.playing) else {
+                continue;
+            };
+            for frame in out.chunks_exact_mut(channels) {
+                if voice.position >= sound.len() {
+                    if !voice.looping || sound.is_empty() {
+                        voice.playing = false;
+                        self.ended.push(source_id.clone());
+                        break;
+                    }
+                    voice.position = 0;
+                }
+                voice.gain += (voice.target_gain - voice.gain) * k;
+                voice.filter += (voice.target_filter - voice.filter) * k;
+                for i in 0..2 {
+                    voice.pan[i] += (voice.target_pan[i] - voice.pan[i]) * k;
+                }
+                voice.filtered += (sound[voice.position] - voice.filtered) * voice.filter;
+                voice.position += 1;
+
+                let sample = voice.filtered * voice.gain * voice.volume;
+                match channels {
+                    1 => frame[0] += sample,
+                    _ => {
+                        frame[0] += sample * voice.pan[0];
+                        frame[1] += sample * voice.pan[1];
+                    }
+                }
+            }
+        }
+        for sample in out.iter_mut() {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Input automation: synthetic input for QA and demos
+//!
+//! Input scripts (`fastn_protocol::ScriptStep`, one JSON step per line) come
+//! from a file given at startup or from connections to a loopback TCP port,
+//! one script per connection (`fastn input script.jsonl` sends one). Each
+//! script starts when it arrives; its events reach the core at their
+//! `at_ms`, as if the user had produced them. The shell can also record a
+//! session: every event the core was sent and every command it sent back,
+//! as `Event` and `Command` steps. Played as a script, a recording sends its
+//! input again; `--replay` sends all of it (see `replay`).
+
+use fastn_protocol::{Command, Event, ScriptStep, TimedEvent};
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// What the shell automates, from its command line
+#[derive(Debug, Clone, Default)]
+pub struct AutomationOptions {
+    /// Port to accept input scripts on
+    pub listen: Option<u16>,
+    /// Script to play once the app is loaded
+    pub script: Option<PathBuf>,
+    /// File to record the session's events and commands to
+    pub record: Option<PathBuf>,
+    /// Recording to replay instead of the live session
+    pub replay: Option<PathBuf>,
+}
+
+/// A connection sending a script
+struct Connection {
+    stream: TcpStream,
+    /// Bytes of the line being received
+    buffer: Vec<u8>,
+    start: Instant,
+    lines: usize,
+    events: usize,
+}
+
+struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+pub struct Automation {
+    listener: Option<TcpListener>,
+    connections: Vec<Connection>,
+    /// The startup script, played from the first frame
+    script: Option<Vec<TimedEvent>>,
+    /// Events waiting for their time, in time order
+    queue: Vec<(Instant, Event)>,
+    recorder: Option<Recorder>,
+}
+
+impl Automation {
+    pub fn new(options: &AutomationOptions) -> Result<Self, String> {
+        let listener = match options.listen {
+            Some(port) => {
+                let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+                    .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+                listener
+                    .set_nonblocking(true)
+                    .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+                log::info!("Accepting input scripts on 127.0.0.1:{}", port);
+                Some(listener)
+            }
+            None => None,
+        };
+        let script = match &options.script {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read input script {}: {}", path.display(), e))?;
+                let events = fastn_protocol::parse_input_script(&text)
+                    .map_err(|e| format!("Invalid input script {}: {}", path.display(), e))?;
+                Some(events)
+            }
+            None => None,
+        };
+        let recorder = match &options.record {
+            Some(path) => {
+                let file = File::create(path)
+                    .map_err(|e| format!("Failed to create recording {}: {}", path.display(), e))?;
+                log::info!("Recording the session to {}", path.display());
+                Some(Recorder {
+                    writer: BufWriter::new(file),
+                    start: Instant::now(),
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            listener,
+            connections: vec![],
+            script,
+            queue: vec![],
+            recorder,
+        })
+    }
+
+    /// Receive scripts and return the events that are due, in order
+    pub fn poll(&mut self) -> Vec<Event> {
+        let now = Instant::now();
+        if let Some(script) = self.script.take() {
+            log::info!("Playing input script: {} events", script.len());
+            self.schedule(now, script);
+        }
+        self.accept(now);
+        self.receive();
+
+        if let Some(recorder) = &mut self.recorder
+            && let Err(e) = recorder.writer.flush()
+        {
+            log::error!("Failed to write recording: {}", e);
+            self.recorder = None;
+        }
+
+        let due = self.queue.partition_point(|(at, _)| *at <= now);
+        self.queue.drain(..due).map(|(_, event)| event).collect()
+    }
+
+    /// Write an event the core was sent to the recording
+    pub fn record(&mut self, event: &Event) {
+        if let Some(recorder) = &self.recorder {
+            let at_ms = recorder.start.elapsed().as_millis() as u64;
+            self.write_step(ScriptStep::recorded(at_ms, event.clone()));
+        }
+    }
+
+    /// Write the commands the core sent to the recording
+    pub fn record_commands(&mut self, commands: &[Command]) {
+        if let Some(recorder) = &self.recorder {
+            let at_ms = recorder.start.elapsed().as_millis() as u64;
+            for command in commands {
+                self.write_step(ScriptStep::recorded_command(at_ms, command.clone()));
+            }
+        }
+    }
+
+    fn write_step(&mut self, step: ScriptStep) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        let line = serde_json::to_string(&step).map_err(|e| e.to_string());
+        if let Err(e) = line.and_then(|line| writeln!(recorder.writer, "{}", line).map_err(|e| e.to_string())) {
+            log::error!("Failed to write recording: {}", e);
+            self.recorder = None;
+        }
+    }
+
+    fn schedule(&mut self, start: Instant, events: Vec<TimedEvent>) {
+        self.queue.extend(
+            events
+                .into_iter()
+                .map(|e| (start + Duration::from_millis(e.at_ms), e.event)),
+        );
+        // Stable, so events due at once keep their order
+        self.queue.sort_by_key(|(at, _)| *at);
+    }
+
+    fn accept(&mut self, now: Instant) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+        loop {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        log::warn!("Dropped input script connection from {}: {}", address, e);
+                        continue;
+                    }
+                    log::info!("Input script connection from {}", address);
+                    self.connections.push(Connection {
+                        stream,
+                        buffer: vec![],
+                        start: now,
+                        lines: 0,
+                        events: 0,
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Failed to accept input script connection: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Read what the connections sent and schedule the complete lines.
+    /// A connection is answered and closed when its script ends (the
+    /// sender shuts down writing) or has an invalid line.
+    fn receive(&mut self) {
+        let mut scheduled = vec![];
+        self.connections.retain_mut(|connection| {
+            let mut chunk = [0; 4096];
+            let finished = loop {
+                match connection.stream.read(&mut chunk) {
+                    Ok(0) => break true,
+                    Ok(n) => connection.buffer.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break false,
+                    Err(e) => {
+                        log::warn!("Input script connection failed: {}", e);
+                        return false;
+                    }
+                }
+            };
+            if finished && !connection.buffer.is_empty() {
+                connection.buffer.push(b'\n');
+            }
+
+            while let Some(end) = connection.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = connection.buffer.drain(..=end).collect();
+                connection.lines += 1;
+                let step = std::str::from_utf8(&line)
+                    .map_err(|e| e.to_string())
+                    .and_then(ScriptStep::parse_line);
+                match step {
+                    Ok(Some(step)) => {
+                        let events = step.events();
+                        connection.events += events.len();
+                        scheduled.push((connection.start, events));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        let error = format!("line {}: {}", connection.lines, e);
+                        log::warn!("Invalid input script: {}", error);
+                        let _ = writeln!(connection.stream, "error: {}", error);
+                        return false;
+                    }
+                }
+            }
+
+            if finished {
+                log::info!("Playing input script: {} events", connection.events);
+                let _ = writeln!(connection.stream, "ok: {} events", connection.events);
+            }
+            !finished
+        });
+        for (start, events) in scheduled {
+            self.schedule(start, events);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Draw batching
+//!
+//! Volumes showing the same geometry with the same texture and material
+//! parameters are drawn together, in one instanced draw call: their
+//! transforms and colors sit side by side in the instance buffer and they
+//! share one material slot. Batches are sorted by pipeline, then texture, then
+//! material, then mesh, so each of these is switched as rarely as possible.
+//! Within a batch volumes are drawn front to back, so nearer ones hide the
+//! pixels of those behind before they're shaded. Text is alpha blended and
+//! drawn back to front, one volume at a time.
+//!
+//! `BatchReport` counts what a frame cost and names the volumes that were
+//! drawn on their own although another volume shows the same geometry,
+//! with the reason.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Which pipeline draws a volume, in drawing order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pass {
+    Opaque,
+    /// Rigged assets, each with the joint matrices of its own pose
+    Skinned,
+    /// Alpha blended, after everything else
+    Text,
+}
+
+/// Geometry a volume is drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MeshKey {
+    /// The shared unit cube
+    Cube,
+    /// A loaded asset's buffers, by address
+    Asset(usize),
+    /// Buffers of the volume's own (glyph quads)
+    Own(usize),
+}
+
+/// What a volume must share with the others in its batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BatchKey {
+    pub pass: Pass,
+    /// Texture bind group, by address
+    pub texture: usize,
+    /// Bits of the material parameters other than color: emissive, texture region,
+    /// metallic and roughness
+    pub material: [u32; 9],
+    pub mesh: MeshKey,
+    /// Set for skinned volumes, whose pose is theirs alone
+    pub skeleton: Option<usize>,
+}
+
+impl BatchKey {
+    /// The material slot volumes with this key can share
+    pub fn material_key(&self) -> [u32; 9] {
+        self.material
+    }
+}
+
+/// Volumes drawn with one call, by index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub key: BatchKey,
+    pub volumes: Vec<usize>,
+}
+
+/// Group volumes by their keys, in drawing order; `depths` are the
+/// volumes' distances from the camera
+pub fn batch(keys: &[BatchKey], depths: &[f32]) -> Vec<Batch> {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|&a, &b| match (keys[a].pass, keys[b].pass) {
+        // Blending needs text drawn farthest first
+        (Pass::Text, Pass::Text) => depths[b].total_cmp(&depths[a]).then(a.cmp(&b)),
+        _ => keys[a].cmp(&keys[b]).then(depths[a].total_cmp(&depths[b])).then(a.cmp(&b)),
+    });
+
+    let mut batches: Vec<Batch> = vec![];
+    for index in order {
+        let key = keys[index];
+        match batches.last_mut() {
+            Some(last) if last.key == key && key.pass != Pass::Text => last.volumes.push(index),
+            _ => batches.push(Batch {
+                key,
+                volumes: vec![index],
+            }),
+        }
+    }
+    batches
+}
+
+/// Why a volume wasn't batched with others showing the same geometry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BreakReason {
+    /// Different texture
+    Texture,
+    /// Different emissive, texture region, metallic or roughness
+    Material,
+    /// Rigged: every pose needs its own joint matrices
+    Rigged,
+    /// Blended text, drawn one at a time, in order
+    Transparency,
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BreakReason::Texture => "unique texture",
+            BreakReason::Material => "unique material",
+            BreakReason::Rigged => "rigged",
+            BreakReason::Transparency => "transparency",
+        })
+    }
+}
+
+/// What drawing a frame took, and what kept it from taking less
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    /// Volumes drawn
+    pub volumes: usize,
+    /// Volumes outside the view, not drawn
+    pub culled: usize,
+    pub draw_calls: usize,
+    pub pipeline_switches: usize,
+    /// Texture, material and skeleton bind group changes
+    pub bind_group_switches: usize,
+    pub buffer_switches: usize,
+    /// Volumes outside the largest batch of their geometry, by ID
+    pub unbatched: Vec<(String, BreakReason)>,
+}
+
+impl BatchReport {
+    /// Explain `batches` (from `batch`) of the volumes with `ids`; the
+    /// culled volumes and switch counts are left for the renderer to fill
+    /// in
+    pub fn new(batches: &[Batch], ids: &[&str]) -> Self {
+        let mut by_mesh: HashMap<(Pass, MeshKey), Vec<&Batch>> = HashMap::new();
+        for batch in batches {
+            by_mesh.entry((batch.key.pass, batch.key.mesh)).or_default().push(batch);
+        }
+
+        let mut unbatched = vec![];
+        for batch in batches {
+            let reason = match batch.key.pass {
+                Pass::Text => Some(BreakReason::Transparency),
+                Pass::Skinned => (by_mesh[&(batch.key.pass, batch.key.mesh)].len() > 1).then_some(BreakReason::Rigged),
+                Pass::Opaque => {
+                    // The largest batch showing this geometry (the first of
+                    // them) is the one the others missed
+                    let largest = by_mesh[&(batch.key.pass, batch.key.mesh)]
+                        .iter()
+                        .reduce(|a, b| match b.volumes.len().cmp(&a.volumes.len()) {
+                            Ordering::Greater => b,
+                            _ => a,
+                        })
+                        .unwrap();
+                    match largest.key {
+                        key if key == batch.key => None,
+                        key if key.texture != batch.key.texture => Some(BreakReason::Texture),
+                        _ => Some(BreakReason::Material),
+                    }
+                }
+            };
+            if let Some(reason) = reason {
+                unbatched.extend(batch.volumes.iter().map(|&index| (ids[index].to_string(), reason)));
+            }
+        }
+        unbatched.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+        Self {
+            volumes: batches.iter().map(|batch| batch.volumes.len()).sum(),
+            draw_calls: batches.len(),
+            unbatched,
+            ..Self::default()
+        }
+    }
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} volumes drawn, {} culled, in {} draw calls ({} pipeline, {} bind group, {} buffer switches)",
+            self.volumes,
+            self.culled,
+            self.draw_calls,
+            self.pipeline_switches,
+            self.bind_group_switches,
+            self.buffer_switches
+        )?;
+        for chunk in self.unbatched.chunk_by(|a, b| a.1 == b.1) {
+            let ids: Vec<&str> = chunk.iter().map(|(id, _)| id.as_str()).collect();
+            write!(f, "\n  not batched, {}: {}", chunk[0].1, ids.join(", "))?;
+        }
+        Ok(())
+    }
+}
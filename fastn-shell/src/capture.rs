@@ -0,0 +1,245 @@
+//! Photos and videos of the window (`MediaCommand::Capture`)
+//!
+//! Frames are read back from the renderer right after they are drawn. Photos
+//! are saved as `fastn-<id>.png`, videos as animated PNGs `fastn-<id>.apng`
+//! of at most `VIDEO_FPS` frames a second, in `FASTN_CAPTURE_DIR` or else
+//! `captures/` in the working directory. The native shell has no camera, so
+//! captures hold the virtual content only (passthrough: false).
+
+use crate::golden::Image;
+use fastn_protocol::{
+    CaptureFailedData, CaptureFailure, CaptureId, CaptureKind, CaptureSavedData, MediaCommand, MediaEvent,
+};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Frames a second kept in videos
+const VIDEO_FPS: u32 = 15;
+
+pub struct Captures {
+    dir: PathBuf,
+    /// Photo capture IDs waiting for the next frame
+    photos: Vec<CaptureId>,
+    /// The running video capture
+    recording: Option<Recording>,
+}
+
+/// A video being recorded, its frames saved one PNG each until it stops
+struct Recording {
+    capture_id: CaptureId,
+    kind: CaptureKind,
+    max_duration: Option<Duration>,
+    frames_dir: PathBuf,
+    /// Set by the first frame, which also fixes the size
+    start: Option<Instant>,
+    size: (u32, u32),
+    /// When each saved frame was drawn, since `start`
+    frame_times: Vec<Duration>,
+}
+
+impl Captures {
+    pub fn new() -> Self {
+        let dir = std::env::var_os("FASTN_CAPTURE_DIR").map_or_else(|| PathBuf::from("captures"), PathBuf::from);
+        Self {
+            dir,
+            photos: vec![],
+            recording: None,
+        }
+    }
+
+    /// Start or stop a capture. Captures that can't start are answered
+    /// right away.
+    pub fn execute(&mut self, cmd: MediaCommand) -> Option<MediaEvent> {
+        match cmd {
+            MediaCommand::Capture(request) => match request.kind {
+                CaptureKind::Photo => {
+                    self.photos.push(request.capture_id);
+                    None
+                }
+                CaptureKind::Video { max_duration_ms } => self.start_video(request.capture_id, request.kind, max_duration_ms),
+            },
+            MediaCommand::StopCapture { capture_id } => {
+                if self.recording.as_ref().is_some_and(|r| r.capture_id == capture_id) {
+                    return self.recording.take().map(|recording| self.finish_video(recording));
+                }
+                None
+            }
+            cmd => {
+                log::debug!("Unhandled media command: {:?}", cmd);
+                None
+            }
+        }
+    }
+
+    /// Whether the frame just drawn is wanted, by a photo or the video
+    pub fn wants_frame(&self) -> bool {
+        let frame_interval = Duration::from_secs(1) / VIDEO_FPS;
+        !self.photos.is_empty()
+            || self.recording.as_ref().is_some_and(|recording| match recording.start {
+                Some(start) => recording.frame_times.last().is_none_or(|&last| start.elapsed() >= last + frame_interval),
+                None => true,
+            })
+    }
+
+    /// Save the frame just drawn (tightly packed RGBA rows, None if it
+    /// couldn't be read back) to the photos and the video waiting for it,
+    /// and end a video that reached its maximum length
+    pub fn frame(&mut self, pixels: Option<Vec<u8>>, width: u32, height: u32) -> Vec<MediaEvent> {
+        let mut events = vec![];
+        let Some(pixels) = pixels else {
+            for capture_id in std::mem::take(&mut self.photos) {
+                events.push(failed(capture_id, CaptureFailure::Failed, "Could not read back the frame".to_string()));
+            }
+            return events;
+        };
+        let image = Image { width, height, pixels };
+
+        for capture_id in std::mem::take(&mut self.photos) {
+            let path = self.dir.join(format!("fastn-{}.png", capture_id));
+            let event = match std::fs::create_dir_all(&self.dir).map_err(|e| e.to_string()).and_then(|()| image.write_png(&path)) {
+                Ok(()) => saved(capture_id, CaptureKind::Photo, path, "image/png", (width, height), None),
+                Err(e) => failed(capture_id, CaptureFailure::Failed, e),
+            };
+            events.push(event);
+        }
+
+        if let Some(recording) = &mut self.recording
+            && let Err(e) = recording.add_frame(&image)
+        {
+            let recording = self.recording.take().expect("recording");
+            let _ = std::fs::remove_dir_all(&recording.frames_dir);
+            events.push(failed(recording.capture_id, CaptureFailure::Failed, e));
+        }
+        if let Some(recording) = self.recording.take_if(|recording| recording.is_over()) {
+            events.push(self.finish_video(recording));
+        }
+        events
+    }
+
+    fn start_video(&mut self, capture_id: CaptureId, kind: CaptureKind, max_duration_ms: Option<u32>) -> Option<MediaEvent> {
+        if let Some(recording) = &self.recording {
+            return Some(failed(capture_id, CaptureFailure::Busy, format!("Already recording {}", recording.capture_id)));
+        }
+        let frames_dir = self.dir.join(format!(".fastn-{}.frames", capture_id));
+        let _ = std::fs::remove_dir_all(&frames_dir);
+        if let Err(e) = std::fs::create_dir_all(&frames_dir) {
+            return Some(failed(capture_id, CaptureFailure::Failed, format!("{}: {}", frames_dir.display(), e)));
+        }
+        self.recording = Some(Recording {
+            capture_id,
+            kind,
+            max_duration: max_duration_ms.map(|ms| Duration::from_millis(ms as u64)),
+            frames_dir,
+            start: None,
+            size: (0, 0),
+            frame_times: vec![],
+        });
+        None
+    }
+
+    /// Put the recorded frames together into the video and remove them
+    fn finish_video(&self, recording: Recording) -> MediaEvent {
+        let path = self.dir.join(format!("fastn-{}.apng", recording.capture_id));
+        let result = match recording.start.map(|start| start.elapsed()) {
+            Some(duration) => recording.write_apng(&path, duration).map(|()| duration),
+            None => Err("No frames were recorded".to_string()),
+        };
+        let _ = std::fs::remove_dir_all(&recording.frames_dir);
+        match result {
+            Ok(duration) => {
+                let duration_ms = Some(duration.as_millis().min(u32::MAX as u128) as u32);
+                saved(recording.capture_id, recording.kind, path, "image/apng", recording.size, duration_ms)
+            }
+            Err(e) => failed(recording.capture_id, CaptureFailure::Failed, e),
+        }
+    }
+}
+
+impl Drop for Captures {
+    /// A video still recording when the shell closes is saved
+    fn drop(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            self.finish_video(recording);
+        }
+    }
+}
+
+impl Recording {
+    fn add_frame(&mut self, image: &Image) -> Result<(), String> {
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                self.size = (image.width, image.height);
+                *self.start.insert(Instant::now())
+            }
+        };
+        // Frames after the window was resized don't fit the video
+        if (image.width, image.height) != self.size {
+            return Ok(());
+        }
+        image.write_png(&self.frame_path(self.frame_times.len()))?;
+        self.frame_times.push(start.elapsed());
+        Ok(())
+    }
+
+    fn is_over(&self) -> bool {
+        match (self.start, self.max_duration) {
+            (Some(start), Some(max_duration)) => start.elapsed() >= max_duration,
+            _ => false,
+        }
+    }
+
+    fn frame_path(&self, index: usize) -> PathBuf {
+        self.frames_dir.join(format!("{:06}.png", index))
+    }
+
+    /// Each frame is shown until the next one was drawn, the last until
+    /// `end`
+    fn write_apng(&self, path: &std::path::Path, end: Duration) -> Result<(), String> {
+        let error = |e: png::EncodingError| format!("{}: {}", path.display(), e);
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), self.size.0, self.size.1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frame_times.len() as u32, 0).map_err(error)?;
+        let mut writer = encoder.write_header().map_err(error)?;
+        let ends = self.frame_times.iter().skip(1).copied().chain(std::iter::once(end));
+        for (index, (time, next)) in self.frame_times.iter().zip(ends).enumerate() {
+            let delay_ms = (next - *time).as_millis().clamp(1, u16::MAX as u128) as u16;
+            writer.set_frame_delay(delay_ms, 1000).map_err(error)?;
+            let frame = Image::read_png(&self.frame_path(index))?;
+            writer.write_image_data(&frame.pixels).map_err(error)?;
+        }
+        writer.finish().map_err(error)
+    }
+}
+
+fn saved(
+    capture_id: CaptureId,
+    kind: CaptureKind,
+    path: PathBuf,
+    mime_type: &str,
+    (width, height): (u32, u32),
+    duration_ms: Option<u32>,
+) -> MediaEvent {
+    log::info!("Saved capture {} to {}", capture_id, path.display());
+    MediaEvent::CaptureSaved(CaptureSavedData {
+        capture_id,
+        kind,
+        path: path.display().to_string(),
+        mime_type: mime_type.to_string(),
+        width,
+        height,
+        duration_ms,
+        passthrough: false,
+    })
+}
+
+fn failed(capture_id: CaptureId, reason: CaptureFailure, message: String) -> MediaEvent {
+    log::warn!("Capture {} failed: {}", capture_id, message);
+    MediaEvent::CaptureFailed(CaptureFailedData {
+        capture_id,
+        reason,
+        message,
+    })
+}
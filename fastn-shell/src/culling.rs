@@ -0,0 +1,81 @@
+//! Frustum culling
+//!
+//! Every mesh has a bounding box in model space. Each frame, a volume's box
+//! is moved into the scene by its model matrix and tested against the
+//! planes of the camera's view frustum; volumes entirely outside one of
+//! them aren't drawn. Rigged volumes can move beyond their rest pose's box
+//! and are always drawn.
+
+use glam::{Mat3, Mat4, Vec3, Vec4};
+
+/// Axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The shared cube's box
+    pub const UNIT_CUBE: Aabb = Aabb {
+        min: Vec3::splat(-0.5),
+        max: Vec3::splat(0.5),
+    };
+
+    /// Empty (min above max) when there are no points, so it is outside
+    /// every frustum
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(
+            Aabb {
+                min: Vec3::splat(f32::INFINITY),
+                max: Vec3::splat(f32::NEG_INFINITY),
+            },
+            |aabb, point| Aabb {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            },
+        )
+    }
+}
+
+/// Planes of a view frustum, facing inward: points inside have a
+/// non-negative distance from all six
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// The frustum `view_projection` maps into wgpu's clip volume (depth
+    /// from 0 to 1)
+    pub fn new(view_projection: Mat4) -> Self {
+        let row = |i| view_projection.row(i);
+        Self {
+            planes: [
+                row(3) + row(0),
+                row(3) - row(0),
+                row(3) + row(1),
+                row(3) - row(1),
+                row(2),
+                row(3) - row(2),
+            ],
+        }
+    }
+
+    /// Whether a box in model space, placed by `model`, is at least partly
+    /// inside
+    pub fn intersects(&self, model: Mat4, bounds: &Aabb) -> bool {
+        if bounds.min.cmpgt(bounds.max).any() {
+            return false;
+        }
+        // The box around the placed box: its center moved, its half
+        // extents spread over the axes the model turns them into
+        let center = model.transform_point3((bounds.min + bounds.max) / 2.0);
+        let axes = Mat3::from_mat4(model);
+        let half = (bounds.max - bounds.min) / 2.0;
+        let extent = axes.x_axis.abs() * half.x + axes.y_axis.abs() * half.y + axes.z_axis.abs() * half.z;
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + plane.w >= -normal.abs().dot(extent)
+        })
+    }
+}
@@ -0,0 +1,18 @@
+// Full-screen fade overlay (EnvironmentCommand::SetFade), blended over the
+// scene after everything else
+
+@group(0) @binding(0)
+var<uniform> fade_color: vec4<f32>;
+
+// One triangle covering the whole target
+@vertex
+fn vs_fade(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32((index << 1u) & 2u) * 2.0 - 1.0;
+    let y = f32(index & 2u) * 2.0 - 1.0;
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@fragment
+fn fs_fade() -> @location(0) vec4<f32> {
+    return fade_color;
+}
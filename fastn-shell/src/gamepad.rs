@@ -1,190 +1,226 @@
 //! Gamepad input handling using SDL2
 //!
-//! Provides gamepad state that can be polled each frame.
-//! Supports hot-plug detection for connecting/disconnecting controllers.
+//! Controllers are polled each frame and their state is turned into
+//! `GamepadEvent`s for the core: `Connected` when one is plugged in,
+//! `Input` whenever its state changes, `Disconnected` when it goes away.
+//! Each controller gets the lowest free `gamepad-N` device ID, which it
+//! keeps until it disconnects, like the browser's gamepad index.
 
-use sdl2::controller::{GameController, Axis, Button};
+use fastn_protocol::{DeviceId, GamepadEvent, GamepadInfo, GamepadInputData};
 use sdl2::GameControllerSubsystem;
-
-/// Normalized gamepad state (values in -1.0 to 1.0 range for axes, bool for buttons)
-#[derive(Debug, Clone, Default)]
-pub struct GamepadState {
-    pub connected: bool,
-
-    // Sticks (normalized -1.0 to 1.0)
-    pub left_stick_x: f32,
-    pub left_stick_y: f32,
-    pub right_stick_x: f32,
-    pub right_stick_y: f32,
-
-    // Triggers (normalized 0.0 to 1.0)
-    pub left_trigger: f32,
-    pub right_trigger: f32,
-
-    // Face buttons
-    pub button_a: bool,
-    pub button_b: bool,
-    pub button_x: bool,
-    pub button_y: bool,
-
-    // Shoulder buttons
-    pub left_shoulder: bool,
-    pub right_shoulder: bool,
-
-    // Stick buttons
-    pub left_stick_button: bool,
-    pub right_stick_button: bool,
-
-    // D-pad
-    pub dpad_up: bool,
-    pub dpad_down: bool,
-    pub dpad_left: bool,
-    pub dpad_right: bool,
-
-    // Menu buttons
-    pub start: bool,
-    pub back: bool,
-    pub guide: bool,
+use sdl2::controller::{Axis, Button, GameController};
+use std::collections::HashMap;
+
+/// Axis layout: left stick X/Y, right stick X/Y, then the triggers
+const AXES: [Axis; 6] = [
+    Axis::LeftX,
+    Axis::LeftY,
+    Axis::RightX,
+    Axis::RightY,
+    Axis::TriggerLeft,
+    Axis::TriggerRight,
+];
+
+/// Button layout, as the core's camera expects it
+const BUTTONS: [Button; 15] = [
+    Button::A,
+    Button::B,
+    Button::X,
+    Button::Y,
+    Button::LeftShoulder,
+    Button::RightShoulder,
+    Button::Back,
+    Button::Start,
+    Button::Guide,
+    Button::LeftStick,
+    Button::RightStick,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
+
+/// Sticks closer to center than this read as centered, so a stick at rest
+/// doesn't send noise. Below the core camera's own dead zone, which
+/// rescales what is past it.
+const STICK_DEADZONE: f32 = 0.1;
+
+/// Triggers pulled less than this read as released
+const TRIGGER_DEADZONE: f32 = 0.05;
+
+/// Axis changes smaller than this don't send an `Input` event
+const AXIS_EPSILON: f32 = 0.01;
+
+struct Gamepad {
+    controller: GameController,
+    device_id: DeviceId,
+    /// The state last sent to the core, None before the first `Input`
+    sent: Option<GamepadInputData>,
 }
 
 pub struct GamepadManager {
     controller_subsystem: GameControllerSubsystem,
-    controller: Option<GameController>,
-    state: GamepadState,
+    /// Open controllers by SDL joystick instance ID
+    gamepads: HashMap<u32, Gamepad>,
+    /// Joysticks seen at the last scan, to look for new ones only when
+    /// it changes
+    joystick_count: u32,
 }
 
 impl GamepadManager {
     pub fn new(sdl_context: &sdl2::Sdl) -> Result<Self, String> {
         let controller_subsystem = sdl_context.game_controller()?;
-
-        // Try to open the first available controller
-        let controller = Self::find_controller(&controller_subsystem);
-
-        if let Some(ref c) = controller {
-            log::info!("Gamepad connected: {}", c.name());
-        } else {
-            log::info!("No gamepad connected");
-        }
-
         Ok(Self {
             controller_subsystem,
-            controller,
-            state: GamepadState::default(),
+            gamepads: HashMap::new(),
+            joystick_count: 0,
         })
     }
 
-    fn find_controller(subsystem: &GameControllerSubsystem) -> Option<GameController> {
-        let num_joysticks = subsystem.num_joysticks().ok()?;
-
-        for i in 0..num_joysticks {
-            if subsystem.is_game_controller(i) {
-                if let Ok(controller) = subsystem.open(i) {
-                    return Some(controller);
-                }
+    /// Poll the controllers and return the events for what changed since
+    /// the last call - call this each frame after pumping SDL events
+    pub fn update(&mut self) -> Vec<GamepadEvent> {
+        let mut events = vec![];
+
+        let disconnected: Vec<u32> = self
+            .gamepads
+            .iter()
+            .filter(|(_, gamepad)| !gamepad.controller.attached())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in disconnected {
+            if let Some(gamepad) = self.gamepads.remove(&id) {
+                log::info!("Gamepad disconnected: {} ({})", gamepad.controller.name(), gamepad.device_id);
+                events.push(GamepadEvent::Disconnected {
+                    device_id: gamepad.device_id,
+                });
             }
         }
-        None
-    }
 
-    /// Try to connect a controller if none is currently connected
-    /// Call this periodically (e.g., every frame) for hot-plug support
-    pub fn try_connect(&mut self) {
-        if self.controller.is_none() {
-            if let Some(controller) = Self::find_controller(&self.controller_subsystem) {
-                log::info!("Gamepad connected: {}", controller.name());
-                self.controller = Some(controller);
+        events.extend(self.connect());
+
+        let mut gamepads: Vec<&mut Gamepad> = self.gamepads.values_mut().collect();
+        gamepads.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        for gamepad in gamepads {
+            let input = read_input(&gamepad.controller, &gamepad.device_id);
+            if gamepad.sent.as_ref().is_none_or(|sent| changed(sent, &input)) {
+                gamepad.sent = Some(input.clone());
+                events.push(GamepadEvent::Input(input));
             }
         }
+        events
+    }
+
+    /// Announce the connected controllers again on the next `update`, for a
+    /// core that hasn't heard of them
+    pub fn reset(&mut self) {
+        self.gamepads.clear();
+        self.joystick_count = 0;
     }
 
-    /// Update gamepad state - call this each frame after pumping SDL events
-    pub fn update(&mut self) {
-        // Hot-plug: try to find a controller if we don't have one
-        self.try_connect();
-
-        if let Some(ref controller) = self.controller {
-            if !controller.attached() {
-                log::info!("Gamepad disconnected");
-                self.controller = None;
-                self.state = GamepadState::default();
-                return;
+    /// Open the controllers plugged in since the last scan
+    fn connect(&mut self) -> Vec<GamepadEvent> {
+        let count = self.controller_subsystem.num_joysticks().unwrap_or(0);
+        if count == self.joystick_count {
+            return vec![];
+        }
+        self.joystick_count = count;
+
+        let mut events = vec![];
+        for index in 0..count {
+            if !self.controller_subsystem.is_game_controller(index) {
+                continue;
+            }
+            let controller = match self.controller_subsystem.open(index) {
+                Ok(controller) => controller,
+                Err(e) => {
+                    log::warn!("Failed to open gamepad {}: {}", index, e);
+                    continue;
+                }
+            };
+            let instance_id = controller.instance_id();
+            if self.gamepads.contains_key(&instance_id) {
+                continue;
             }
 
-            self.state.connected = true;
-
-            // Axes (SDL returns i16, normalize to -1.0..1.0)
-            self.state.left_stick_x = normalize_axis(controller.axis(Axis::LeftX));
-            self.state.left_stick_y = normalize_axis(controller.axis(Axis::LeftY));
-            self.state.right_stick_x = normalize_axis(controller.axis(Axis::RightX));
-            self.state.right_stick_y = normalize_axis(controller.axis(Axis::RightY));
-
-            // Triggers (SDL returns 0..32767, normalize to 0.0..1.0)
-            self.state.left_trigger = normalize_trigger(controller.axis(Axis::TriggerLeft));
-            self.state.right_trigger = normalize_trigger(controller.axis(Axis::TriggerRight));
-
-            // Face buttons
-            self.state.button_a = controller.button(Button::A);
-            self.state.button_b = controller.button(Button::B);
-            self.state.button_x = controller.button(Button::X);
-            self.state.button_y = controller.button(Button::Y);
-
-            // Shoulder buttons
-            self.state.left_shoulder = controller.button(Button::LeftShoulder);
-            self.state.right_shoulder = controller.button(Button::RightShoulder);
-
-            // Stick buttons
-            self.state.left_stick_button = controller.button(Button::LeftStick);
-            self.state.right_stick_button = controller.button(Button::RightStick);
-
-            // D-pad
-            self.state.dpad_up = controller.button(Button::DPadUp);
-            self.state.dpad_down = controller.button(Button::DPadDown);
-            self.state.dpad_left = controller.button(Button::DPadLeft);
-            self.state.dpad_right = controller.button(Button::DPadRight);
-
-            // Menu buttons
-            self.state.start = controller.button(Button::Start);
-            self.state.back = controller.button(Button::Back);
-            self.state.guide = controller.button(Button::Guide);
-        } else {
-            self.state.connected = false;
+            let device_id = self.free_device_id();
+            let name = controller.name();
+            log::info!("Gamepad connected: {} ({})", name, device_id);
+            events.push(GamepadEvent::Connected(GamepadInfo {
+                device_id: device_id.clone(),
+                name,
+                axes_count: AXES.len() as u32,
+                buttons_count: BUTTONS.len() as u32,
+            }));
+            self.gamepads.insert(
+                instance_id,
+                Gamepad {
+                    controller,
+                    device_id,
+                    sent: None,
+                },
+            );
         }
+        events
     }
 
-    /// Get current gamepad state
-    pub fn state(&self) -> &GamepadState {
-        &self.state
+    /// The lowest `gamepad-N` no connected controller has
+    fn free_device_id(&self) -> DeviceId {
+        (0..)
+            .map(|n| DeviceId::from(format!("gamepad-{}", n)))
+            .find(|id| self.gamepads.values().all(|gamepad| gamepad.device_id != *id))
+            .expect("there is a free gamepad number")
     }
+}
 
-    /// Check if any input is active (for logging/debugging)
-    pub fn has_input(&self) -> bool {
-        if !self.state.connected {
-            return false;
+/// A controller's axes and buttons, in the `AXES` and `BUTTONS` layout, with
+/// the dead zones applied
+fn read_input(controller: &GameController, device_id: &DeviceId) -> GamepadInputData {
+    let mut axes: Vec<f32> = AXES
+        .iter()
+        .map(|axis| match axis {
+            Axis::TriggerLeft | Axis::TriggerRight => normalize_trigger(controller.axis(*axis)),
+            _ => normalize_axis(controller.axis(*axis)),
+        })
+        .collect();
+    // Radial dead zone, so a stick pushed along one axis keeps the other
+    // axis' small values
+    for stick in axes[..4].chunks_mut(2) {
+        if stick[0].hypot(stick[1]) < STICK_DEADZONE {
+            stick.fill(0.0);
         }
-
-        // Check if any stick is moved significantly
-        let deadzone = 0.15;
-        if self.state.left_stick_x.abs() > deadzone
-            || self.state.left_stick_y.abs() > deadzone
-            || self.state.right_stick_x.abs() > deadzone
-            || self.state.right_stick_y.abs() > deadzone
-        {
-            return true;
+    }
+    for trigger in &mut axes[4..] {
+        if *trigger < TRIGGER_DEADZONE {
+            *trigger = 0.0;
         }
+    }
 
-        // Check triggers
-        if self.state.left_trigger > 0.1 || self.state.right_trigger > 0.1 {
-            return true;
-        }
+    let buttons = BUTTONS
+        .iter()
+        .map(|button| {
+            let pressed = controller.button(*button);
+            (if pressed { 1.0 } else { 0.0 }, pressed)
+        })
+        .collect();
 
-        // Check any button
-        self.state.button_a || self.state.button_b || self.state.button_x || self.state.button_y
-            || self.state.left_shoulder || self.state.right_shoulder
-            || self.state.dpad_up || self.state.dpad_down || self.state.dpad_left || self.state.dpad_right
-            || self.state.start || self.state.back
+    GamepadInputData {
+        device_id: device_id.clone(),
+        axes,
+        buttons,
     }
 }
 
+/// Whether `input` differs enough from what was sent to send it
+fn changed(sent: &GamepadInputData, input: &GamepadInputData) -> bool {
+    sent.buttons != input.buttons
+        || sent.axes.iter().zip(&input.axes).any(|(a, b)| {
+            // Always send an axis coming to rest, so the core doesn't keep
+            // a small value
+            (a - b).abs() >= AXIS_EPSILON || (*b == 0.0 && *a != 0.0)
+        })
+}
+
 /// Normalize axis value from i16 (-32768..32767) to f32 (-1.0..1.0)
 fn normalize_axis(value: i16) -> f32 {
     if value >= 0 {
@@ -0,0 +1,281 @@
+//! Golden image tests for the renderer
+//!
+//! Canonical scenes live in `golden/scenes/` as JSON arrays of protocol
+//! commands, so they describe what any shell should draw rather than how
+//! this one draws it. `fastn-shell golden` renders each scene headlessly and
+//! compares it with `golden/images/<scene>.png`; `--bless` stores the new
+//! renders as the goldens instead. Run it with `cargo xtask golden`.
+//!
+//! Renders differ slightly between GPUs and drivers, so images are compared
+//! perceptually: a pixel only counts as different when its color moved more
+//! than `PIXEL_THRESHOLD` in YIQ space (the metric of pixelmatch), and a
+//! scene fails when more than `MAX_DIFF_RATIO` of its pixels did.
+
+use crate::asset_loader::AssetManager;
+use crate::renderer::Renderer;
+use crate::texture::TextureImage;
+use fastn_protocol::{AssetCommand, Command, EnvironmentCommand, MaterialCommand, SceneCommand};
+use std::path::{Path, PathBuf};
+
+/// Size of the rendered images
+pub const WIDTH: u32 = 320;
+pub const HEIGHT: u32 = 240;
+
+/// Per-pixel color distance that counts as a difference, 0 to 1
+pub const PIXEL_THRESHOLD: f32 = 0.1;
+
+/// Share of different pixels a scene may have and still match
+pub const MAX_DIFF_RATIO: f32 = 0.001;
+
+/// Largest possible YIQ distance (black against white)
+const MAX_YIQ_DELTA: f32 = 35215.0;
+
+/// What `run` renders and where it looks
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Directory of the `<scene>.json` command lists
+    pub scenes: PathBuf,
+    /// Directory of the `<scene>.png` goldens
+    pub goldens: PathBuf,
+    /// Where renders and diff images of failing scenes are written
+    pub out: PathBuf,
+    /// Store the renders as the new goldens
+    pub bless: bool,
+    /// Scenes to run, all if empty
+    pub only: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        Self {
+            scenes: root.join("golden/scenes"),
+            goldens: root.join("golden/images"),
+            out: root.join("../target/golden"),
+            bless: false,
+            only: vec![],
+        }
+    }
+}
+
+/// An RGBA8 image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn read_png(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::ALPHA);
+        let mut reader = decoder.read_info().map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut pixels = vec![0; reader.output_buffer_size().ok_or("PNG too large")?];
+        let info = reader.next_frame(&mut pixels).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+            return Err(format!("{}: not an 8-bit RGBA image", path.display()));
+        }
+        pixels.truncate(info.buffer_size());
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| format!("{}: {}", path.display(), e))?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn pixel(&self, index: usize) -> [u8; 4] {
+        let p = &self.pixels[index * 4..index * 4 + 4];
+        [p[0], p[1], p[2], p[3]]
+    }
+}
+
+/// Result of comparing a render with its golden
+#[derive(Debug, Clone)]
+pub struct Diff {
+    /// Pixels past `PIXEL_THRESHOLD`, or all of them if the sizes differ
+    pub different: usize,
+    pub total: usize,
+    /// The golden faded to gray with the different pixels in red
+    pub image: Image,
+}
+
+impl Diff {
+    pub fn matches(&self) -> bool {
+        self.different as f32 <= self.total as f32 * MAX_DIFF_RATIO
+    }
+}
+
+/// Compare two images pixel by pixel
+pub fn diff(expected: &Image, actual: &Image, threshold: f32) -> Diff {
+    let total = (expected.width * expected.height) as usize;
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        return Diff {
+            different: total,
+            total,
+            image: actual.clone(),
+        };
+    }
+
+    let max_delta = MAX_YIQ_DELTA * threshold * threshold;
+    let mut different = 0;
+    let mut pixels = Vec::with_capacity(expected.pixels.len());
+    for index in 0..total {
+        let (a, b) = (expected.pixel(index), actual.pixel(index));
+        if yiq_delta(a, b) > max_delta {
+            different += 1;
+            pixels.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let gray = (255.0 - (255.0 - luma(blend(a))) * 0.1) as u8;
+            pixels.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+    Diff {
+        different,
+        total,
+        image: Image {
+            width: expected.width,
+            height: expected.height,
+            pixels,
+        },
+    }
+}
+
+/// Blend a pixel onto white
+fn blend(pixel: [u8; 4]) -> [f32; 3] {
+    let alpha = pixel[3] as f32 / 255.0;
+    [0, 1, 2].map(|i| 255.0 + (pixel[i] as f32 - 255.0) * alpha)
+}
+
+fn luma([r, g, b]: [f32; 3]) -> f32 {
+    r * 0.298_895 + g * 0.586_622 + b * 0.114_482
+}
+
+/// Squared perceptual color distance (Kotsarenko and Ramos)
+fn yiq_delta(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let ([r1, g1, b1], [r2, g2, b2]) = (blend(a), blend(b));
+    let y = luma([r1, g1, b1]) - luma([r2, g2, b2]);
+    let i = (r1 - r2) * 0.595_978 - (g1 - g2) * 0.274_176 - (b1 - b2) * 0.321_802;
+    let q = (r1 - r2) * 0.211_470 - (g1 - g2) * 0.522_617 + (b1 - b2) * 0.311_147;
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+/// Render a scene's commands headlessly. Assets are loaded relative to
+/// `base`.
+pub fn render_scene(commands: &[Command], base: &Path) -> Result<Image, String> {
+    let mut renderer = pollster::block_on(Renderer::headless(WIDTH, HEIGHT)).ok_or("No GPU adapter for headless rendering")?;
+    let mut assets = AssetManager::new();
+    assets.set_base_path(base);
+
+    for command in commands {
+        match command {
+            Command::Environment(EnvironmentCommand::SetBackground(background)) => renderer.set_background(background),
+            Command::Environment(EnvironmentCommand::SetCamera(camera)) => renderer.set_camera(camera),
+            Command::Environment(EnvironmentCommand::SetFade(fade)) => renderer.set_fade(fade),
+            Command::Asset(AssetCommand::Load { asset_id, path, cache, .. }) => {
+                assets.load(asset_id, path, *cache, |_, _| {})?;
+                if let Some(mesh) = assets.get_mesh(asset_id) {
+                    renderer.upload_mesh(asset_id, mesh, assets.get_image(asset_id));
+                }
+            }
+            Command::Scene(SceneCommand::CreateVolume(data)) => renderer.create_volume(data),
+            Command::Scene(SceneCommand::SetTransform(data)) => {
+                // Goldens show where animations end
+                renderer.set_transform(data);
+                renderer.update_animations(f32::INFINITY);
+            }
+            Command::Scene(SceneCommand::SetParent { volume_id, parent_id }) => {
+                renderer.set_parent(volume_id, parent_id.as_deref())
+            }
+            Command::Scene(SceneCommand::DestroyVolume { volume_id }) => renderer.destroy_volume(volume_id),
+            Command::Material(MaterialCommand::CreateTexture(data)) => {
+                renderer.upload_texture(&data.texture_id, &TextureImage::from_source(&data.source, &assets)?)?;
+            }
+            Command::Material(MaterialCommand::UpdateTexture(data)) => {
+                renderer.upload_texture(&data.texture_id, &TextureImage::from_data(&data.data)?)?;
+            }
+            Command::Material(MaterialCommand::SetMaterial(data)) => renderer.set_material(data),
+            _ => log::debug!("Golden scenes ignore {:?}", command),
+        }
+    }
+
+    let pixels = renderer.capture().ok_or("Failed to read back the frame")?;
+    Ok(Image {
+        width: WIDTH,
+        height: HEIGHT,
+        pixels,
+    })
+}
+
+/// Render the scenes and compare (or bless) them. Returns whether every
+/// scene matched its golden.
+pub fn run(options: &Options) -> Result<bool, String> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let mut scenes: Vec<PathBuf> = std::fs::read_dir(&options.scenes)
+        .map_err(|e| format!("{}: {}", options.scenes.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter(|path| options.only.is_empty() || options.only.iter().any(|name| path.file_stem().is_some_and(|s| s == name.as_str())))
+        .collect();
+    scenes.sort();
+    if scenes.is_empty() {
+        return Err(format!("No scenes found in {}", options.scenes.display()));
+    }
+
+    std::fs::create_dir_all(&options.out).map_err(|e| format!("{}: {}", options.out.display(), e))?;
+    let mut passed = true;
+    for path in scenes {
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+        let json = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let commands: Vec<Command> = serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let actual = render_scene(&commands, &options.scenes)?;
+        let golden = options.goldens.join(format!("{}.png", name));
+
+        if options.bless {
+            std::fs::create_dir_all(&options.goldens).map_err(|e| format!("{}: {}", options.goldens.display(), e))?;
+            actual.write_png(&golden)?;
+            println!("blessed  {}", name);
+            continue;
+        }
+
+        let actual_path = options.out.join(format!("{}.actual.png", name));
+        if !golden.exists() {
+            actual.write_png(&actual_path)?;
+            println!("MISSING  {} (render in {}, run with --bless to store it)", name, actual_path.display());
+            passed = false;
+            continue;
+        }
+
+        let result = diff(&Image::read_png(&golden)?, &actual, PIXEL_THRESHOLD);
+        if result.matches() {
+            println!("ok       {} ({} of {} pixels differ)", name, result.different, result.total);
+        } else {
+            let diff_path = options.out.join(format!("{}.diff.png", name));
+            actual.write_png(&actual_path)?;
+            result.image.write_png(&diff_path)?;
+            println!(
+                "FAILED   {} ({} of {} pixels differ, see {} and {})",
+                name,
+                result.different,
+                result.total,
+                actual_path.display(),
+                diff_path.display()
+            );
+            passed = false;
+        }
+    }
+    Ok(passed)
+}
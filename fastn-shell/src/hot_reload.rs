@@ -0,0 +1,64 @@
+//! Hot reload: notice when the running app's WASM is rebuilt
+//!
+//! The shell polls the module's modification time and size. A change is
+//! acted on once it has stayed the same for one check, so a module still
+//! being written isn't loaded half-done.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the module is checked
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Modification time and size of a file
+type Stamp = Option<(SystemTime, u64)>;
+
+pub struct WasmWatcher {
+    path: PathBuf,
+    /// Stamp of the loaded module
+    loaded: Stamp,
+    /// Stamp seen at the last check, if it differs from the loaded one
+    pending: Stamp,
+    last_check: Instant,
+}
+
+impl WasmWatcher {
+    /// Watch the module at `path`, taking its current state as loaded
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            loaded: stamp(&path),
+            path,
+            pending: None,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Whether the module was rebuilt and has settled since the last time
+    /// this returned true. Checks the file at most every `CHECK_INTERVAL`.
+    pub fn poll(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let current = stamp(&self.path);
+        if current == self.loaded || current.is_none() {
+            // Unchanged, or removed mid-rebuild
+            self.pending = None;
+            return false;
+        }
+        if current != self.pending {
+            self.pending = current;
+            return false;
+        }
+        self.loaded = current;
+        self.pending = None;
+        true
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -5,13 +5,54 @@
 //! 2. Creates a window with wgpu rendering
 //! 3. Sends input events to the WASM core
 //! 4. Executes Commands returned by the WASM core
-//! 5. Handles gamepad input via SDL2
+//! 5. Forwards gamepad connections and input via SDL2
+//! 6. Forwards mouse and touch input
+//! 7. Plays sounds via SDL2
+//! 8. Plays input scripts and records sessions (see `automation`), and
+//!    replays recorded sessions (see `replay`)
+//! 9. Reloads the app when its WASM is rebuilt, keeping the camera (see
+//!    `hot_reload`)
+//! 10. Batches volumes into instanced draws (see `batching`) and, while the
+//!     core shows the debug HUD, logs the draw calls and what broke
+//!     batching whenever that changes
+//! 11. Draws panels, laying widgets out into SVG textures, and sends
+//!     pointer input on them (see `panel`)
+//! 12. With `--xr` (and the `xr` feature), draws to a desktop VR headset
+//!     through OpenXR and sends its head and controller poses (see `xr`)
+//! 13. Saves photos and videos of the window to disk (see `capture`)
+//! 14. Fetches `http(s)://` assets through a disk cache kept across
+//!     sessions (see `asset_cache`), loading prefetches one per frame
+//! 15. Keeps each app's stored values in a file across sessions (see
+//!     `storage`)
+//!
+//! It also renders the golden scenes headlessly for the renderer's
+//! regression tests (see `golden`).
 
+mod asset_cache;
 mod asset_loader;
+mod audio;
+mod automation;
+mod batching;
+mod capture;
+mod culling;
 mod gamepad;
+pub mod golden;
+mod hot_reload;
+mod panel;
+mod picking;
+mod pointer;
+mod primitives;
 mod renderer;
+mod replay;
+mod skinning;
+mod storage;
+mod text;
+mod texture;
 pub mod wasm_runtime;
+#[cfg(feature = "xr")]
+mod xr;
 
+use std::collections::VecDeque;
 use std::path::Path;
 use std::sync::Arc;
 use winit::{
@@ -23,37 +64,79 @@ use winit::{
 };
 
 use fastn_protocol::{
-    Command, DeviceId, Event, FrameEvent, GamepadEvent, GamepadInputData, InputEvent,
-    KeyEventData, KeyboardEvent, LifecycleEvent, LogLevel,
+    AssetEvent, AssetId, AudioCommand, AudioEvent, CachePolicy, CameraData, Command, DeviceId, Event, FrameEvent, InitEvent, InputEvent, KeyEventData,
+    KeyboardEvent, LifecycleEvent, LogLevel, Priority, SceneEvent,
 };
 
 use asset_loader::AssetManager;
+use audio::AudioPlayer;
+use automation::Automation;
+pub use automation::AutomationOptions;
+use capture::Captures;
 use gamepad::GamepadManager;
+use hot_reload::WasmWatcher;
+use panel::Panels;
+use pointer::PointerTracker;
+use batching::BatchReport;
 use renderer::Renderer;
+use replay::Replay;
+use storage::AppStorage;
+use texture::TextureImage;
 use wasm_runtime::WasmCore;
 
 struct App {
     window: Option<Arc<Window>>,
+    // Whether to draw to a headset (`--xr`), and the OpenXR session doing
+    // it, dropped before the renderer whose device it shares
+    #[cfg_attr(not(feature = "xr"), allow(dead_code))]
+    xr_requested: bool,
+    #[cfg(feature = "xr")]
+    xr: Option<xr::OpenXr>,
     renderer: Option<Renderer>,
     wasm_core: Option<WasmCore>,
     last_frame_time: std::time::Instant,
-    wasm_path: String,
+    // Apps to run; Tab switches to the next one when there are several
+    wasm_paths: Vec<String>,
+    current_app: usize,
     // Queue for commands that need to be executed
     pending_commands: Vec<Command>,
+    // Events produced while executing commands (e.g. asset load results),
+    // sent to the core once the commands are done
+    pending_events: Vec<Event>,
     // SDL2 context and gamepad manager
     sdl_context: sdl2::Sdl,
     gamepad: Option<GamepadManager>,
-    // Track last gamepad log time to avoid spam
-    #[allow(dead_code)]
-    last_gamepad_log: std::time::Instant,
+    // Sound output, None if there is no audio device
+    audio: Option<AudioPlayer>,
+    // Mouse and touch devices, positions for deltas
+    pointer: PointerTracker,
+    // Panels, drawn as textured volumes, and the pointer presses on them
+    panels: Panels,
+    // Scripted input and the session recording
+    automation: Automation,
+    // The recorded session being replayed, in place of live events
+    replay: Option<Replay>,
+    // Photos and videos waiting for frames
+    captures: Captures,
+    // The running app's stored values, opened when it starts
+    storage: Option<AppStorage>,
     // Frame counter
     frame_count: u64,
     // Asset manager for loading GLB/glTF files
     asset_manager: AssetManager,
+    // Low priority loads (prefetches), run one per frame
+    prefetches: VecDeque<(AssetId, String, CachePolicy)>,
+    // Notices rebuilds of the running app's WASM
+    watcher: Option<WasmWatcher>,
+    // Camera the core last set, restored when the app is reloaded
+    camera: Option<CameraData>,
+    // Whether the core shows the debug HUD, which this shell has as log
+    // lines: the batching report last logged
+    stats: Option<BatchReport>,
 }
 
 impl App {
-    fn new(wasm_path: String) -> Self {
+    fn new(wasm_paths: Vec<String>, automation: Automation, replay: Option<Replay>, xr_requested: bool) -> Self {
         // Initialize SDL2 for gamepad support
         let sdl_context = sdl2::init().expect("Failed to initialize SDL2");
 
@@ -66,42 +149,267 @@ impl App {
             }
         };
 
-        // Initialize asset manager with base path from WASM file directory
-        let mut asset_manager = AssetManager::new();
-        if let Some(parent) = Path::new(&wasm_path).parent() {
-            asset_manager.set_base_path(parent);
-            log::info!("Asset base path: {:?}", parent);
-        }
+        let audio = match AudioPlayer::new(&sdl_context) {
+            Ok(player) => Some(player),
+            Err(e) => {
+                log::warn!("Failed to initialize audio: {}", e);
+                None
+            }
+        };
 
         Self {
             window: None,
+            xr_requested,
+            #[cfg(feature = "xr")]
+            xr: None,
             renderer: None,
             wasm_core: None,
             last_frame_time: std::time::Instant::now(),
-            wasm_path,
+            wasm_paths,
+            current_app: 0,
             pending_commands: Vec::new(),
+            pending_events: Vec::new(),
             sdl_context,
             gamepad,
-            last_gamepad_log: std::time::Instant::now(),
+            audio,
+            pointer: PointerTracker::new(),
+            panels: Panels::new(),
+            automation,
+            replay,
+            captures: Captures::new(),
+            storage: None,
             frame_count: 0,
-            asset_manager,
+            asset_manager: AssetManager::new(),
+            prefetches: VecDeque::new(),
+            watcher: None,
+            camera: None,
+            stats: None,
         }
     }
 
-    /// Send an event to the WASM core and execute any resulting commands
-    fn send_event(&mut self, event: Event) {
-        if let Some(ref mut wasm_core) = self.wasm_core {
-            match wasm_core.send_event(&event) {
-                Ok(commands) => {
-                    self.execute_commands(commands);
-                }
-                Err(e) => {
-                    log::error!("Failed to send event to core: {}", e);
+    /// Load the current app into the window, replacing the running one
+    fn load_app(&mut self) {
+        let wasm_path = self.wasm_paths[self.current_app].clone();
+        log::info!("Loading WASM module: {}", wasm_path);
+        let (wasm_core, init_commands) = WasmCore::new(&wasm_path).expect("Failed to load WASM module");
+        self.start_app(wasm_core, init_commands, None);
+    }
+
+    /// Restart the current app from its rebuilt WASM, from the same camera.
+    /// A module that fails to load leaves the running app as it is.
+    fn reload_app(&mut self) {
+        let wasm_path = self.wasm_paths[self.current_app].clone();
+        log::info!("Reloading rebuilt WASM module: {}", wasm_path);
+        match WasmCore::new(&wasm_path) {
+            Ok((wasm_core, init_commands)) => {
+                let camera = self.camera.take();
+                self.start_app(wasm_core, init_commands, camera);
+            }
+            Err(e) => log::error!("Failed to reload {}, keeping the running app: {}", wasm_path, e),
+        }
+    }
+
+    /// Replace the running app with a freshly initialized core, rebuilding
+    /// the scene from its commands. `camera` is handed to the core in
+    /// `Init` to start from.
+    fn start_app(&mut self, wasm_core: WasmCore, init_commands: Vec<Command>, camera: Option<CameraData>) {
+        let Some(window) = self.window.clone() else {
+            return;
+        };
+        let wasm_path = self.wasm_paths[self.current_app].clone();
+
+        // Start from an empty scene and asset cache
+        self.wasm_core = None;
+        self.renderer = None;
+        self.pending_commands.clear();
+        self.pending_events.clear();
+        self.pointer = PointerTracker::new();
+        self.panels = Panels::new();
+        if let Some(gamepad) = &mut self.gamepad {
+            gamepad.reset();
+        }
+        self.frame_count = 0;
+        if let Some(audio) = &mut self.audio {
+            audio.reset();
+        }
+        self.watcher = Some(WasmWatcher::new(&wasm_path));
+        self.camera = None;
+
+        // Initialize asset manager with base path from WASM file directory
+        self.asset_manager = AssetManager::new();
+        self.prefetches.clear();
+        if let Some(parent) = Path::new(&wasm_path).parent() {
+            self.asset_manager.set_base_path(parent);
+            log::info!("Asset base path: {:?}", parent);
+        }
+
+        let app_name = Path::new(&wasm_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("app");
+        window.set_title(&format!("fastn-shell - {}", app_name));
+        self.storage = Some(AppStorage::open_default(app_name));
+
+        // Create renderer
+        let renderer = self.create_renderer(Arc::clone(&window));
+
+        self.renderer = Some(renderer);
+        self.wasm_core = Some(wasm_core);
+
+        // Execute initial commands
+        self.automation.record_commands(&init_commands);
+        self.execute_commands(init_commands);
+
+        // Tell the core what this shell can do
+        let size = window.inner_size();
+        self.send_event(Event::Lifecycle(LifecycleEvent::Init(InitEvent {
+            platform: fastn_protocol::Platform::Desktop,
+            viewport_width: size.width,
+            viewport_height: size.height,
+            dpr: window.scale_factor() as f32,
+            xr_supported: self.has_xr(),
+            xr_immersive_vr: self.has_xr(),
+            xr_immersive_ar: false,
+            webrtc_supported: false,
+            websocket_supported: false,
+            features: self.features(),
+            accessibility: Default::default(),
+            conventions: Default::default(),
+            camera,
+        })));
+    }
+
+    /// A renderer for the window, on the OpenXR session's device if there
+    /// is one
+    fn create_renderer(&self, window: Arc<Window>) -> Renderer {
+        #[cfg(feature = "xr")]
+        if let Some(xr) = &self.xr {
+            return xr.renderer(window);
+        }
+        pollster::block_on(Renderer::new(window))
+    }
+
+    /// Whether the shell draws to a headset
+    fn has_xr(&self) -> bool {
+        #[cfg(feature = "xr")]
+        return self.xr.is_some();
+        #[cfg(not(feature = "xr"))]
+        false
+    }
+
+    /// Connect to the headset, falling back to the window alone if that fails
+    #[cfg(feature = "xr")]
+    fn start_xr(&mut self, window: &Arc<Window>) {
+        match xr::OpenXr::new(window) {
+            Ok(xr) => self.xr = Some(xr),
+            Err(e) => log::error!("Failed to start OpenXR, running in the window only: {}", e),
+        }
+    }
+
+    /// Wait for the headset's next frame, sending the core its session
+    /// changes and poses
+    #[cfg(feature = "xr")]
+    fn begin_xr_frame(&mut self) -> Option<xr::XrFrame> {
+        match self.xr.as_mut()?.begin_frame() {
+            Ok((frame, events)) => {
+                for event in events {
+                    self.send_event(event);
                 }
+                frame
+            }
+            Err(e) => {
+                log::error!("OpenXR frame failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Draw the headset's frame from the scene the window just showed
+    #[cfg(feature = "xr")]
+    fn end_xr_frame(&mut self, frame: Option<xr::XrFrame>) {
+        let (Some(xr), Some(frame)) = (&mut self.xr, frame) else {
+            return;
+        };
+        if let Err(e) = xr.end_frame(frame, self.renderer.as_mut()) {
+            log::error!("OpenXR frame failed: {}", e);
+        }
+    }
+
+    /// The protocol features this shell supports, for `InitEvent`
+    fn features(&self) -> Vec<String> {
+        use fastn_protocol::{
+            AssetScheme, FEATURE_ASSET_CACHE, FEATURE_CAPTURE, FEATURE_HIT_TEST, FEATURE_PANELS, FEATURE_SCENE_HIERARCHY,
+            FEATURE_SPATIAL_AUDIO, FEATURE_TEXT, FEATURE_TRANSFORM_ANIMATION, FEATURE_TRANSPARENT_BACKGROUND,
+        };
+        let mut features = vec![
+            FEATURE_HIT_TEST.to_string(),
+            FEATURE_TRANSFORM_ANIMATION.to_string(),
+            FEATURE_TEXT.to_string(),
+            FEATURE_SCENE_HIERARCHY.to_string(),
+            FEATURE_PANELS.to_string(),
+            FEATURE_CAPTURE.to_string(),
+            FEATURE_ASSET_CACHE.to_string(),
+            fastn_protocol::asset_scheme_feature(AssetScheme::Bundle),
+            fastn_protocol::asset_scheme_feature(AssetScheme::File),
+            fastn_protocol::asset_scheme_feature(AssetScheme::Http),
+            fastn_protocol::asset_scheme_feature(AssetScheme::Https),
+        ];
+        if self.audio.is_some() {
+            features.push(FEATURE_SPATIAL_AUDIO.to_string());
+        }
+        if self.renderer.as_ref().is_some_and(Renderer::supports_transparency) {
+            features.push(FEATURE_TRANSPARENT_BACKGROUND.to_string());
+        }
+        features
+    }
+
+    /// Send an event to the WASM core and execute any resulting commands.
+    /// While a session is replayed, the live events are dropped.
+    fn send_event(&mut self, event: Event) {
+        if self.replay.is_some() {
+            return;
+        }
+        if let Some(commands) = self.send_to_core(&event) {
+            self.execute_commands(commands);
+        }
+    }
+
+    /// Send an event to the WASM core, recording it and the commands it
+    /// answers with
+    fn send_to_core(&mut self, event: &Event) -> Option<Vec<Command>> {
+        self.automation.record(event);
+        let wasm_core = self.wasm_core.as_mut()?;
+        match wasm_core.send_event(event) {
+            Ok(commands) => {
+                self.automation.record_commands(&commands);
+                Some(commands)
+            }
+            Err(e) => {
+                log::error!("Failed to send event to core: {}", e);
+                None
             }
         }
     }
 
+    /// Send the next recorded frame of the replayed session and execute the
+    /// commands, handing over to live input after the last one
+    fn replay_frame(&mut self) {
+        let Some(replay) = &mut self.replay else {
+            return;
+        };
+        for (event, recorded) in replay.next_frame() {
+            let commands = self.send_to_core(&event).unwrap_or_default();
+            if let Some(replay) = &mut self.replay {
+                replay.check(&event, &recorded, &commands);
+            }
+            self.execute_commands(commands);
+        }
+        if self.replay.as_ref().is_some_and(Replay::is_finished) {
+            log::info!("Replay finished, back to live input");
+            self.replay = None;
+        }
+    }
+
     fn execute_commands(&mut self, commands: Vec<Command>) {
         for cmd in commands {
             self.execute_command(cmd);
@@ -114,6 +422,10 @@ impl App {
                 self.execute_command(cmd);
             }
         }
+
+        for event in std::mem::take(&mut self.pending_events) {
+            self.send_event(event);
+        }
     }
 
     fn execute_command(&mut self, cmd: Command) {
@@ -127,19 +439,36 @@ impl App {
                         LogLevel::Warn => log::warn!("[Core] {}", message),
                         LogLevel::Error => log::error!("[Core] {}", message),
                     },
+                    DebugCommand::SetHudVisible { visible } => {
+                        self.stats = visible.then(BatchReport::default);
+                    }
+                    _ => {
+                        log::debug!("Unhandled debug command: {:?}", debug_cmd);
+                    }
                 }
             }
             Command::Asset(asset_cmd) => {
                 use fastn_protocol::AssetCommand;
                 match asset_cmd {
-                    AssetCommand::Load { asset_id, path } => {
+                    AssetCommand::Load { asset_id, path, priority: Priority::Low, cache } => {
+                        log::info!("Prefetching asset: {} from {}", asset_id, path);
+                        self.prefetches.push_back((asset_id, path, cache));
+                    }
+                    AssetCommand::Load { asset_id, path, cache, .. } => {
                         log::info!("Loading asset: {} from {}", asset_id, path);
-                        if let Err(e) = self.asset_manager.load(&asset_id, &path) {
-                            log::error!("Failed to load asset {}: {}", asset_id, e);
+                        self.load_asset(asset_id, path, cache);
+                    }
+                    AssetCommand::Unload { asset_id } => {
+                        self.prefetches.retain(|(id, ..)| *id != asset_id);
+                        self.asset_manager.unload(&asset_id);
+                        if let Some(renderer) = &mut self.renderer {
+                            renderer.unload_mesh(&asset_id);
                         }
                     }
-                    _ => {
-                        log::debug!("Unhandled asset command: {:?}", asset_cmd);
+                    AssetCommand::Cancel { asset_id } => {
+                        // Loads finish within the command; only prefetches
+                        // can still be waiting
+                        self.prefetches.retain(|(id, ..)| *id != asset_id);
                     }
                 }
             }
@@ -153,8 +482,23 @@ impl App {
                             data.transform.position
                         );
                         if let Some(renderer) = &mut self.renderer {
-                            renderer.create_volume(&data, &self.asset_manager);
+                            renderer.create_volume(&data);
                         }
+                        self.pending_events.push(Event::Scene(SceneEvent::VolumeReady {
+                            volume_id: data.volume_id,
+                        }));
+                    }
+                    SceneCommand::RequestHitTest(request) => {
+                        let scale_factor = self.window.as_ref().map_or(1.0, |w| w.scale_factor()) as f32;
+                        let hit = self
+                            .renderer
+                            .as_ref()
+                            .and_then(|renderer| renderer.hit_test(&request.source, scale_factor));
+                        log::debug!("Hit test {}: {:?}", request.request_id, hit);
+                        self.pending_events.push(Event::Scene(SceneEvent::HitTestResult {
+                            request_id: request.request_id,
+                            hit,
+                        }));
                     }
                     SceneCommand::SetTransform(data) => {
                         log::debug!(
@@ -162,6 +506,20 @@ impl App {
                             data.volume_id,
                             data.transform.position
                         );
+                        if let Some(renderer) = &mut self.renderer {
+                            renderer.set_transform(&data);
+                        }
+                    }
+                    SceneCommand::SetParent { volume_id, parent_id } => {
+                        log::debug!("SetParent: {} -> {:?}", volume_id, parent_id);
+                        if let Some(renderer) = &mut self.renderer {
+                            renderer.set_parent(&volume_id, parent_id.as_deref());
+                        }
+                    }
+                    SceneCommand::DestroyVolume { volume_id } => {
+                        if let Some(renderer) = &mut self.renderer {
+                            renderer.destroy_volume(&volume_id);
+                        }
                     }
                     _ => {
                         log::debug!("Unhandled scene command: {:?}", scene_cmd);
@@ -180,16 +538,164 @@ impl App {
                         if let Some(renderer) = &mut self.renderer {
                             renderer.set_camera(&camera_data);
                         }
+                        self.camera = Some(camera_data);
+                    }
+                    EnvironmentCommand::SetFade(fade) => {
+                        if let Some(renderer) = &mut self.renderer {
+                            renderer.set_fade(&fade);
+                        }
                     }
                     _ => {}
                 }
             }
+            Command::Animation(animation_cmd) => {
+                use fastn_protocol::AnimationCommand;
+                let Some(renderer) = &mut self.renderer else {
+                    return;
+                };
+                match animation_cmd {
+                    AnimationCommand::Play(data) => renderer.play_animation(&data),
+                    AnimationCommand::Stop { volume_id, animation_id } => {
+                        renderer.stop_animation(&volume_id, animation_id.as_deref());
+                    }
+                    AnimationCommand::SetBoneTransform(data) => {
+                        renderer.set_bone_transform(&data.volume_id, &data.bone_name, &data.transform, data.weight);
+                    }
+                    AnimationCommand::SetBoneTransforms(data) => {
+                        for (bone_name, transform, weight) in &data.bones {
+                            renderer.set_bone_transform(&data.volume_id, bone_name, transform, *weight);
+                        }
+                    }
+                    AnimationCommand::SetBlendShape(data) => renderer.set_blend_shape(&data),
+                }
+            }
+            Command::Material(material_cmd) => self.execute_material_command(material_cmd),
+            Command::Panel(panel_cmd) => {
+                if let Some(renderer) = &mut self.renderer {
+                    self.panels.execute(panel_cmd, renderer);
+                }
+            }
+            Command::Audio(audio_cmd) => self.execute_audio_command(audio_cmd),
+            Command::Media(media_cmd) => {
+                if let Some(event) = self.captures.execute(media_cmd) {
+                    self.pending_events.push(Event::Media(event));
+                }
+            }
+            Command::Storage(storage_cmd) => {
+                if let Some(event) = self.storage.as_mut().and_then(|storage| storage.execute(storage_cmd)) {
+                    self.pending_events.push(Event::Storage(event));
+                }
+            }
+            #[cfg(feature = "xr")]
+            Command::Xr(xr_cmd) => match &mut self.xr {
+                Some(xr) => xr.execute_command(xr_cmd),
+                None => log::debug!("No headset for XR command: {:?}", xr_cmd),
+            },
             _ => {
                 log::debug!("Unhandled command: {:?}", cmd);
             }
         }
     }
 
+    /// Override volume materials, and create, fill and destroy textures.
+    /// Created and filled textures are reported to the core with
+    /// `TextureReady` or `TextureError`.
+    fn execute_material_command(&mut self, cmd: fastn_protocol::MaterialCommand) {
+        use fastn_protocol::MaterialCommand;
+        let Some(renderer) = &mut self.renderer else {
+            return;
+        };
+        let (texture_id, image) = match cmd {
+            MaterialCommand::SetMaterial(data) => {
+                renderer.set_material(&data);
+                return;
+            }
+            MaterialCommand::DestroyTexture { texture_id } => {
+                renderer.destroy_texture(&texture_id);
+                return;
+            }
+            MaterialCommand::BindMediaToTexture { texture_id, media_id } => {
+                log::warn!("Cannot show media {} on texture {}: the native shell has no media streams", media_id, texture_id);
+                return;
+            }
+            MaterialCommand::CreateTexture(data) => {
+                (data.texture_id, TextureImage::from_source(&data.source, &self.asset_manager))
+            }
+            MaterialCommand::UpdateTexture(data) => {
+                let image = match renderer.has_texture(&data.texture_id) {
+                    true => TextureImage::from_data(&data.data),
+                    false => Err(format!("No texture {}", data.texture_id)),
+                };
+                (data.texture_id, image)
+            }
+        };
+
+        let event = match image.and_then(|image| renderer.upload_texture(&texture_id, &image)) {
+            Ok(()) => SceneEvent::TextureReady { texture_id },
+            Err(error) => {
+                log::warn!("Texture {} failed: {}", texture_id, error);
+                SceneEvent::TextureError { texture_id, error }
+            }
+        };
+        self.pending_events.push(Event::Scene(event));
+    }
+
+    /// Load sounds and control their playback. Load results and sources
+    /// that can't play are reported to the core as `AudioEvent`s.
+    fn execute_audio_command(&mut self, cmd: AudioCommand) {
+        let Some(audio) = &mut self.audio else {
+            return;
+        };
+        let event = match cmd {
+            AudioCommand::Load { sound_id, path } => {
+                match self.asset_manager.read(&path).and_then(|bytes| audio.load(&sound_id, &bytes)) {
+                    Ok(duration_ms) => AudioEvent::Loaded { sound_id, duration_ms },
+                    Err(error) => {
+                        log::error!("Failed to load sound {}: {}", path, error);
+                        AudioEvent::Error { sound_id, error }
+                    }
+                }
+            }
+            cmd => match audio.execute(cmd) {
+                Ok(()) => return,
+                Err(event) => event,
+            },
+        };
+        self.pending_events.push(Event::Audio(event));
+    }
+
+    /// Load an asset, upload its mesh to the GPU and report the result to
+    /// the core
+    fn load_asset(&mut self, asset_id: String, path: String, cache: CachePolicy) {
+        self.pending_events.push(Event::Asset(AssetEvent::LoadStarted {
+            asset_id: asset_id.clone(),
+            path: path.clone(),
+        }));
+
+        let pending_events = &mut self.pending_events;
+        let result = self.asset_manager.load(&asset_id, &path, cache, |loaded, total| {
+            pending_events.push(Event::Asset(AssetEvent::LoadProgress {
+                asset_id: asset_id.clone(),
+                loaded,
+                total,
+            }));
+        });
+
+        let event = match result {
+            Ok(data) => {
+                if let (Some(renderer), Some(mesh)) = (&mut self.renderer, self.asset_manager.get_mesh(&asset_id)) {
+                    renderer.upload_mesh(&asset_id, mesh, self.asset_manager.get_image(&asset_id));
+                }
+                AssetEvent::Loaded(data)
+            }
+            Err(error) => {
+                log::error!("Failed to load asset {}: {}", asset_id, error);
+                AssetEvent::LoadFailed { asset_id, error }
+            }
+        };
+        self.pending_events.push(Event::Asset(event));
+    }
+
     /// Convert winit KeyCode to key code string (matching web standard)
     fn keycode_to_string(key_code: KeyCode) -> String {
         match key_code {
@@ -252,24 +758,17 @@ impl ApplicationHandler for App {
 
         let window_attrs = Window::default_attributes()
             .with_title("fastn-shell")
-            .with_inner_size(winit::dpi::LogicalSize::new(1280, 720));
+            .with_inner_size(winit::dpi::LogicalSize::new(1280, 720))
+            // For transparent backgrounds; opaque until the core sets one
+            .with_transparent(true);
 
         let window = Arc::new(event_loop.create_window(window_attrs).unwrap());
-
-        // Create renderer
-        let renderer = pollster::block_on(Renderer::new(Arc::clone(&window)));
-
-        // Load WASM core and get initial commands
-        log::info!("Loading WASM module: {}", self.wasm_path);
-        let (wasm_core, init_commands) =
-            WasmCore::new(&self.wasm_path).expect("Failed to load WASM module");
-
+        #[cfg(feature = "xr")]
+        if self.xr_requested {
+            self.start_xr(&window);
+        }
         self.window = Some(window);
-        self.renderer = Some(renderer);
-        self.wasm_core = Some(wasm_core);
-
-        // Execute initial commands
-        self.execute_commands(init_commands);
+        self.load_app();
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
@@ -298,6 +797,14 @@ impl ApplicationHandler for App {
                     return;
                 }
 
+                // Tab switches to the next app in gallery mode (shell-level too)
+                if key_code == KeyCode::Tab && state == ElementState::Pressed && self.wasm_paths.len() > 1 {
+                    self.send_event(Event::Lifecycle(LifecycleEvent::Shutdown));
+                    self.current_app = (self.current_app + 1) % self.wasm_paths.len();
+                    self.load_app();
+                    return;
+                }
+
                 // Send keyboard event to core
                 let code = Self::keycode_to_string(key_code);
                 let key_event_data = KeyEventData {
@@ -318,6 +825,23 @@ impl ApplicationHandler for App {
 
                 self.send_event(Event::Input(InputEvent::Keyboard(kb_event)));
             }
+            WindowEvent::CursorMoved { .. }
+            | WindowEvent::CursorLeft { .. }
+            | WindowEvent::MouseInput { .. }
+            | WindowEvent::MouseWheel { .. }
+            | WindowEvent::Touch(_) => {
+                let scale_factor = self.window.as_ref().map_or(1.0, |w| w.scale_factor());
+                for input in self.pointer.handle(&event, scale_factor) {
+                    let panel_event = self
+                        .renderer
+                        .as_ref()
+                        .and_then(|renderer| self.panels.pointer(&input, renderer, scale_factor as f32));
+                    self.send_event(Event::Input(input));
+                    if let Some(panel_event) = panel_event {
+                        self.send_event(Event::Panel(panel_event));
+                    }
+                }
+            }
             WindowEvent::RedrawRequested => {
                 let now = std::time::Instant::now();
                 let dt = now.duration_since(self.last_frame_time).as_secs_f32();
@@ -325,70 +849,47 @@ impl ApplicationHandler for App {
                 self.last_frame_time = now;
                 self.frame_count += 1;
 
+                // Pick up a rebuilt app before this frame's events
+                if self.watcher.as_mut().is_some_and(WasmWatcher::poll) {
+                    self.reload_app();
+                }
+
                 // Pump SDL events (required for gamepad state updates)
                 let mut event_pump = self.sdl_context.event_pump().unwrap();
                 event_pump.pump_events();
 
-                // Update gamepad state and send event to core
-                if let Some(ref mut gamepad) = self.gamepad {
-                    gamepad.update();
-
-                    let state = gamepad.state();
-                    if state.connected {
-                        // Build axes array: [left_x, left_y, right_x, right_y, left_trigger, right_trigger]
-                        let axes = vec![
-                            state.left_stick_x,
-                            state.left_stick_y,
-                            state.right_stick_x,
-                            state.right_stick_y,
-                            state.left_trigger,
-                            state.right_trigger,
-                        ];
-
-                        // Build buttons array: [(pressure, pressed), ...]
-                        // Order: A, B, X, Y, LB, RB, Back, Start, Guide, LS, RS, DPadUp, DPadDown, DPadLeft, DPadRight
-                        let buttons = vec![
-                            (if state.button_a { 1.0 } else { 0.0 }, state.button_a),
-                            (if state.button_b { 1.0 } else { 0.0 }, state.button_b),
-                            (if state.button_x { 1.0 } else { 0.0 }, state.button_x),
-                            (if state.button_y { 1.0 } else { 0.0 }, state.button_y),
-                            (
-                                if state.left_shoulder { 1.0 } else { 0.0 },
-                                state.left_shoulder,
-                            ),
-                            (
-                                if state.right_shoulder { 1.0 } else { 0.0 },
-                                state.right_shoulder,
-                            ),
-                            (if state.back { 1.0 } else { 0.0 }, state.back),
-                            (if state.start { 1.0 } else { 0.0 }, state.start),
-                            (if state.guide { 1.0 } else { 0.0 }, state.guide),
-                            (
-                                if state.left_stick_button { 1.0 } else { 0.0 },
-                                state.left_stick_button,
-                            ),
-                            (
-                                if state.right_stick_button { 1.0 } else { 0.0 },
-                                state.right_stick_button,
-                            ),
-                            (if state.dpad_up { 1.0 } else { 0.0 }, state.dpad_up),
-                            (if state.dpad_down { 1.0 } else { 0.0 }, state.dpad_down),
-                            (if state.dpad_left { 1.0 } else { 0.0 }, state.dpad_left),
-                            (if state.dpad_right { 1.0 } else { 0.0 }, state.dpad_right),
-                        ];
-
-                        let gamepad_input = GamepadInputData {
-                            device_id: DeviceId::from("gamepad-0"),
-                            axes,
-                            buttons,
-                        };
-
-                        self.send_event(Event::Input(InputEvent::Gamepad(GamepadEvent::Input(
-                            gamepad_input,
-                        ))));
-                    }
+                // One prefetch per frame, after everything the scene asked
+                // for
+                if let Some((asset_id, path, cache)) = self.prefetches.pop_front() {
+                    self.load_asset(asset_id, path, cache);
+                }
+
+                // Scripted input that is due
+                for event in self.automation.poll() {
+                    self.send_event(event);
+                }
+
+                // Gamepad connections and state changes
+                let gamepad_events = self.gamepad.as_mut().map(GamepadManager::update).unwrap_or_default();
+                for event in gamepad_events {
+                    self.send_event(Event::Input(InputEvent::Gamepad(event)));
                 }
 
+                // Report sounds that played to the end
+                let ended = self.audio.as_mut().map(AudioPlayer::take_ended).unwrap_or_default();
+                for source_id in ended {
+                    self.send_event(Event::Audio(AudioEvent::Ended { source_id }));
+                }
+
+                // The headset's session changes and poses for the frame it
+                // waits for
+                #[cfg(feature = "xr")]
+                let xr_frame = self.begin_xr_frame();
+
+                // A replayed session's next frame stands in for this one's
+                // events
+                self.replay_frame();
+
                 // Send Frame event to core (this triggers camera updates based on held keys)
                 self.send_event(Event::Lifecycle(LifecycleEvent::Frame(FrameEvent {
                     time,
@@ -396,9 +897,30 @@ impl ApplicationHandler for App {
                     frame: self.frame_count,
                 })));
 
-                // Render
+                // Animate, then render
                 if let Some(renderer) = &mut self.renderer {
+                    for (volume_id, animation_id) in renderer.update_animations(dt) {
+                        self.pending_events.push(Event::Scene(SceneEvent::VolumeAnimationComplete {
+                            volume_id,
+                            animation_id,
+                        }));
+                    }
                     renderer.render();
+                    if self.captures.wants_frame() {
+                        let (width, height) = renderer.size();
+                        for event in self.captures.frame(renderer.capture(), width, height) {
+                            self.pending_events.push(Event::Media(event));
+                        }
+                    }
+                    if let Some(stats) = self.stats.as_mut().filter(|stats| *stats != renderer.batch_report()) {
+                        *stats = renderer.batch_report().clone();
+                        log::info!("[Stats] {}", stats);
+                    }
+                }
+                #[cfg(feature = "xr")]
+                self.end_xr_frame(xr_frame);
+                for event in std::mem::take(&mut self.pending_events) {
+                    self.send_event(event);
                 }
 
                 // Request next frame
@@ -409,6 +931,11 @@ impl ApplicationHandler for App {
             _ => {}
         }
     }
+
+    /// Let the app save its state (e.g. for resuming) before the window goes
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        self.send_event(Event::Lifecycle(LifecycleEvent::Shutdown));
+    }
 }
 
 /// Run the native shell with the given WASM path
@@ -416,12 +943,43 @@ impl ApplicationHandler for App {
 /// This is the main entry point for the fastn-shell library.
 /// It creates a window, loads the WASM module, and runs the event loop.
 pub fn run(wasm_path: &str) -> Result<(), String> {
+    run_with(wasm_path, AutomationOptions::default())
+}
+
+/// Run the native shell, playing or recording input scripts
+pub fn run_with(wasm_path: &str, automation: AutomationOptions) -> Result<(), String> {
+    run_apps(vec![wasm_path.to_string()], &automation, false)
+}
+
+/// Run the native shell drawing to a desktop VR headset through OpenXR,
+/// with the window as a mirror (see `xr`)
+pub fn run_xr(wasm_path: &str, automation: AutomationOptions) -> Result<(), String> {
+    if !cfg!(feature = "xr") {
+        return Err("fastn-shell was built without OpenXR support (the xr feature)".to_string());
+    }
+    run_apps(vec![wasm_path.to_string()], &automation, true)
+}
+
+/// Run the native shell with several apps, switching to the next one on Tab
+///
+/// Used by `fastn examples --native` to go through all examples in one window.
+pub fn run_gallery(wasm_paths: Vec<String>) -> Result<(), String> {
+    run_apps(wasm_paths, &AutomationOptions::default(), false)
+}
+
+fn run_apps(wasm_paths: Vec<String>, automation: &AutomationOptions, xr: bool) -> Result<(), String> {
+    if wasm_paths.is_empty() {
+        return Err("No apps to run".to_string());
+    }
+
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let replay = automation.replay.as_deref().map(Replay::load).transpose()?;
+    let automation = Automation::new(automation)?;
 
     let event_loop = EventLoop::new().map_err(|e| format!("Failed to create event loop: {}", e))?;
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = App::new(wasm_path.to_string());
+    let mut app = App::new(wasm_paths, automation, replay, xr);
     event_loop
         .run_app(&mut app)
         .map_err(|e| format!("Event loop error: {}", e))?;
@@ -1,17 +1,82 @@
 //! fastn-shell CLI binary
 //!
-//! Usage: fastn-shell <path-to-wasm>
+//! Usage: fastn-shell <path-to-wasm> [--listen [PORT]] [--script FILE] [--record FILE] [--replay FILE] [--xr]
+//!        fastn-shell golden [--bless] [SCENE...]
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let wasm_path = args.get(1).cloned().unwrap_or_else(|| {
-        eprintln!("Usage: fastn-shell <path-to-wasm>");
+    if args.get(1).is_some_and(|a| a == "golden") {
+        golden(&args[2..]);
+    }
+
+    let (wasm_path, automation, xr) = parse_args(&args[1..]).unwrap_or_else(|e| {
+        if let Some(e) = e {
+            eprintln!("Error: {}", e);
+        }
+        eprintln!("Usage: fastn-shell <path-to-wasm> [--listen [PORT]] [--script FILE] [--record FILE] [--replay FILE] [--xr]");
+        eprintln!("       fastn-shell golden [--bless] [SCENE...]");
         eprintln!("Example: fastn-shell ./app.wasm");
         std::process::exit(1);
     });
 
-    if let Err(e) = fastn_shell::run(&wasm_path) {
+    let run = if xr { fastn_shell::run_xr } else { fastn_shell::run_with };
+    if let Err(e) = run(&wasm_path, automation) {
         eprintln!("Error: {}", e);
         std::process::exit(1);
     }
 }
+
+/// The app and input automation options. `--listen` accepts input scripts
+/// on a loopback port, `--script` plays one from a file, `--record` records
+/// the session's events and commands to a file in the same format and
+/// `--replay` sends a recorded session to the app again. `--xr` draws to a
+/// desktop VR headset through OpenXR.
+fn parse_args(args: &[String]) -> Result<(String, fastn_shell::AutomationOptions, bool), Option<String>> {
+    let mut wasm_path = None;
+    let mut options = fastn_shell::AutomationOptions::default();
+    let mut xr = false;
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => {
+                let port = match args.next_if(|a| !a.starts_with("--")) {
+                    Some(port) => port.parse().map_err(|_| Some(format!("Invalid port {}", port)))?,
+                    None => fastn_protocol::DEFAULT_AUTOMATION_PORT,
+                };
+                options.listen = Some(port);
+            }
+            "--script" => options.script = Some(file_arg(arg, args.next())?),
+            "--record" => options.record = Some(file_arg(arg, args.next())?),
+            "--replay" => options.replay = Some(file_arg(arg, args.next())?),
+            "--xr" => xr = true,
+            flag if flag.starts_with("--") => return Err(Some(format!("Unknown option {}", flag))),
+            path if wasm_path.is_none() => wasm_path = Some(path.to_string()),
+            extra => return Err(Some(format!("Unexpected argument {}", extra))),
+        }
+    }
+    Ok((wasm_path.ok_or(None)?, options, xr))
+}
+
+fn file_arg(flag: &str, value: Option<&String>) -> Result<std::path::PathBuf, Option<String>> {
+    value.map(Into::into).ok_or_else(|| Some(format!("{} needs a file", flag)))
+}
+
+/// Render the golden scenes and compare them with the stored images
+fn golden(args: &[String]) -> ! {
+    let mut options = fastn_shell::golden::Options::default();
+    for arg in args {
+        match arg.as_str() {
+            "--bless" => options.bless = true,
+            name => options.only.push(name.to_string()),
+        }
+    }
+
+    match fastn_shell::golden::run(&options) {
+        Ok(true) => std::process::exit(0),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Panels (`PanelCommand`)
+//!
+//! A panel is a `Primitive::Quad` volume with the panel's ID, textured with
+//! its content rasterized as SVG (`TextureImage::from_svg`). Widget trees
+//! are laid out here into SVG, their text as glyph outlines since resvg
+//! loads no fonts. HTML can't be drawn natively: those panels show only
+//! their background.
+//!
+//! Mouse and touch input over a panel's volume is also sent as
+//! `PanelEvent`s, with the position in panel pixels and the button (or SVG
+//! element with an `id`) under it.
+
+use crate::renderer::Renderer;
+use crate::texture::TextureImage;
+use ab_glyph::{Font, FontRef, OutlineCurve, PxScale, ScaleFont};
+use fastn_protocol::{
+    CreatePanelData, CreateVolumeData, HitTestSource, InputEvent, MaterialOverride, MouseButton, MouseEvent,
+    PanelCommand, PanelContent, PanelEvent, PanelId, PanelPointerData, PointerPhase, Primitive, SetTransformData,
+    TouchEvent, Transform, VolumeSource, Widget, DEFAULT_WIDGET_TEXT_SIZE,
+};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Room around the widgets and between them, in panel pixels
+const MARGIN: f32 = 12.0;
+const SPACING: f32 = 8.0;
+/// Room around a button's label, horizontally and vertically
+const BUTTON_PADDING: [f32; 2] = [16.0, 8.0];
+const BUTTON_RADIUS: f32 = 6.0;
+
+const BACKGROUND: &str = "#f2f2f7";
+const TEXT_COLOR: &str = "#1c1c1e";
+const BUTTON_COLOR: &str = "#0a84ff";
+const BUTTON_TEXT_COLOR: &str = "#ffffff";
+
+/// Something pointer events can name as their `target`, in panel pixels
+struct Target {
+    id: String,
+    /// Left, top, width and height
+    rect: [f32; 4],
+}
+
+struct Panel {
+    /// Size in shell units
+    size: [f32; 2],
+    /// Size in pixels
+    pixels: [u32; 2],
+    /// In paint order: later ones are on top
+    targets: Vec<Target>,
+}
+
+impl Panel {
+    /// The topmost target containing a point
+    fn target_at(&self, x: f32, y: f32) -> Option<String> {
+        self.targets
+            .iter()
+            .rev()
+            .find(|target| {
+                let [left, top, width, height] = target.rect;
+                (left..=left + width).contains(&x) && (top..=top + height).contains(&y)
+            })
+            .map(|target| target.id.clone())
+    }
+}
+
+pub struct Panels {
+    panels: HashMap<PanelId, Panel>,
+    /// The panel a pointer went down on, which gets its moves and release
+    pressed: Option<PanelId>,
+    font: FontRef<'static>,
+}
+
+impl Panels {
+    pub fn new() -> Self {
+        Self {
+            panels: HashMap::new(),
+            pressed: None,
+            font: FontRef::try_from_slice(notosans::REGULAR_TTF).expect("bundled font is valid"),
+        }
+    }
+
+    pub fn execute(&mut self, cmd: PanelCommand, renderer: &mut Renderer) {
+        match cmd {
+            PanelCommand::Create(data) => self.create(&data, renderer),
+            PanelCommand::SetContent { panel_id, content } => {
+                let Some(pixels) = self.panels.get(&panel_id).map(|panel| panel.pixels) else {
+                    log::warn!("SetContent for unknown panel {}", panel_id);
+                    return;
+                };
+                let targets = self.draw(&panel_id, &content, pixels, renderer);
+                if let Some(panel) = self.panels.get_mut(&panel_id) {
+                    panel.targets = targets;
+                }
+            }
+            PanelCommand::SetTransform { panel_id, transform } => {
+                if !self.panels.contains_key(&panel_id) {
+                    log::warn!("SetTransform for unknown panel {}", panel_id);
+                    return;
+                }
+                renderer.set_transform(&SetTransformData {
+                    volume_id: panel_id,
+                    transform: unscaled(transform),
+                    animate: None,
+                });
+            }
+            PanelCommand::Destroy { panel_id } => {
+                if self.panels.remove(&panel_id).is_none() {
+                    log::warn!("Destroy for unknown panel {}", panel_id);
+                    return;
+                }
+                renderer.destroy_volume(&panel_id);
+                renderer.destroy_texture(&panel_id);
+            }
+        }
+    }
+
+    fn create(&mut self, data: &CreatePanelData, renderer: &mut Renderer) {
+        if self.panels.contains_key(&data.panel_id) {
+            log::warn!("Panel {} exists already", data.panel_id);
+            return;
+        }
+        let pixels = [data.width, data.height].map(|size| (size * data.pixels_per_meter).round().max(1.0) as u32);
+        let targets = self.draw(&data.panel_id, &data.content, pixels, renderer);
+        renderer.create_volume(&CreateVolumeData {
+            volume_id: data.panel_id.clone(),
+            source: VolumeSource::Primitive(Primitive::Quad {
+                width: data.width,
+                height: data.height,
+            }),
+            transform: unscaled(data.transform.clone()),
+            material: Some(MaterialOverride {
+                color: Some([1.0, 1.0, 1.0, 1.0]),
+                texture_id: Some(data.panel_id.clone()),
+                metallic: None,
+                roughness: None,
+                emissive: None,
+                uv_rect: None,
+            }),
+            parent_id: None,
+        });
+        self.panels.insert(
+            data.panel_id.clone(),
+            Panel {
+                size: [data.width, data.height],
+                pixels,
+                targets,
+            },
+        );
+    }
+
+    /// Rasterize content into the panel's texture, returning its targets
+    fn draw(&self, panel_id: &str, content: &PanelContent, [width, height]: [u32; 2], renderer: &mut Renderer) -> Vec<Target> {
+        let (svg, targets) = match content {
+            PanelContent::Svg { svg } => (svg.clone(), svg_targets(svg, width, height)),
+            PanelContent::Widgets { root } => {
+                let mut layout = WidgetSvg {
+                    font: &self.font,
+                    body: String::new(),
+                    targets: vec![],
+                };
+                layout.draw(root, MARGIN, MARGIN);
+                (document(width, height, &layout.body), layout.targets)
+            }
+            PanelContent::Html { .. } => {
+                log::warn!("Panel {} is HTML, which the native shell can't draw", panel_id);
+                (document(width, height, ""), vec![])
+            }
+        };
+        if let Err(e) = TextureImage::from_svg(&svg, width, height).and_then(|image| renderer.upload_texture(panel_id, &image)) {
+            log::warn!("Panel {} not drawn: {}", panel_id, e);
+        }
+        targets
+    }
+
+    /// The panel event for mouse or touch input over a panel, or after a
+    /// press on one
+    pub fn pointer(&mut self, input: &InputEvent, renderer: &Renderer, scale_factor: f32) -> Option<PanelEvent> {
+        let (phase, x, y) = match input {
+            InputEvent::Mouse(MouseEvent::Move(data)) => (PointerPhase::Move, data.x, data.y),
+            InputEvent::Mouse(MouseEvent::Down(data)) if data.button == MouseButton::Left => {
+                (PointerPhase::Down, data.x, data.y)
+            }
+            InputEvent::Mouse(MouseEvent::Up(data)) if data.button == MouseButton::Left => {
+                (PointerPhase::Up, data.x, data.y)
+            }
+            InputEvent::Touch(TouchEvent::Start(data)) => (PointerPhase::Down, data.touches.first()?.x, data.touches.first()?.y),
+            InputEvent::Touch(TouchEvent::Move(data)) => (PointerPhase::Move, data.touches.first()?.x, data.touches.first()?.y),
+            InputEvent::Touch(TouchEvent::End(data)) => (PointerPhase::Up, data.touches.first()?.x, data.touches.first()?.y),
+            _ => return None,
+        };
+
+        // Other volumes in front of a panel keep the pointer from it
+        let hovered = renderer
+            .hit_test(&HitTestSource::Screen { x, y }, scale_factor)
+            .map(|hit| hit.volume_id)
+            .filter(|id| self.panels.contains_key(id));
+        let panel_id = match phase {
+            PointerPhase::Down => {
+                self.pressed = hovered.clone();
+                hovered
+            }
+            PointerPhase::Move => self.pressed.clone().or(hovered),
+            PointerPhase::Up => self.pressed.take().or(hovered),
+        }?;
+
+        let panel = self.panels.get(&panel_id)?;
+        let local = renderer.screen_to_volume_plane(&panel_id, x, y, scale_factor)?;
+        let x = (local.x / panel.size[0] + 0.5) * panel.pixels[0] as f32;
+        let y = (0.5 - local.y / panel.size[1]) * panel.pixels[1] as f32;
+        let inside = (0.0..=panel.pixels[0] as f32).contains(&x) && (0.0..=panel.pixels[1] as f32).contains(&y);
+        Some(PanelEvent::Pointer(PanelPointerData {
+            target: inside.then(|| panel.target_at(x, y)).flatten(),
+            panel_id,
+            phase,
+            x,
+            y,
+        }))
+    }
+}
+
+/// Panels ignore their transform's scale
+fn unscaled(transform: Transform) -> Transform {
+    Transform {
+        scale: [1.0, 1.0, 1.0],
+        ..transform
+    }
+}
+
+/// An SVG document of the panel's pixel size, on the panel background
+fn document(width: u32, height: u32, body: &str) -> String {
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}"><rect width="{width}" height="{height}" fill="{BACKGROUND}"/>{body}</svg>"#
+    )
+}
+
+/// The elements of an SVG document that have an `id`, with their bounds
+/// scaled from the document's size to the panel's
+fn svg_targets(svg: &str, width: u32, height: u32) -> Vec<Target> {
+    use resvg::usvg;
+
+    fn collect(group: &usvg::Group, scale: [f32; 2], targets: &mut Vec<Target>) {
+        for node in group.children() {
+            if !node.id().is_empty() {
+                let bounds = node.abs_bounding_box();
+                targets.push(Target {
+                    id: node.id().to_string(),
+                    rect: [
+                        bounds.x() * scale[0],
+                        bounds.y() * scale[1],
+                        bounds.width() * scale[0],
+                        bounds.height() * scale[1],
+                    ],
+                });
+            }
+            if let usvg::Node::Group(group) = node {
+                collect(group, scale, targets);
+            }
+        }
+    }
+
+    let Ok(tree) = usvg::Tree::from_str(svg, &usvg::Options::default()) else {
+        return vec![];
+    };
+    let size = tree.size();
+    let mut targets = vec![];
+    collect(
+        tree.root(),
+        [width as f32 / size.width(), height as f32 / size.height()],
+        &mut targets,
+    );
+    targets
+}
+
+/// Lays widgets out into SVG elements, from the top-left corner
+struct WidgetSvg<'a> {
+    font: &'a FontRef<'static>,
+    body: String,
+    targets: Vec<Target>,
+}
+
+impl WidgetSvg<'_> {
+    /// Width and height of a widget
+    fn measure(&self, widget: &Widget) -> [f32; 2] {
+        match widget {
+            Widget::Column { children } => {
+                let sizes = children.iter().map(|child| self.measure(child));
+                let (width, height) = sizes.fold((0.0f32, 0.0), |(width, height), [w, h]| (width.max(w), height + h));
+                [width, height + gaps(children.len())]
+            }
+            Widget::Row { children } => {
+                let sizes = children.iter().map(|child| self.measure(child));
+                let (width, height) = sizes.fold((0.0, 0.0f32), |(width, height), [w, h]| (width + w, height.max(h)));
+                [width + gaps(children.len()), height]
+            }
+            Widget::Text { text, size } => self.text_size(text, *size),
+            Widget::Button { label, .. } => {
+                let [width, height] = self.text_size(label, DEFAULT_WIDGET_TEXT_SIZE);
+                [width + 2.0 * BUTTON_PADDING[0], height + 2.0 * BUTTON_PADDING[1]]
+            }
+        }
+    }
+
+    /// Add a widget with its top-left corner at `x`, `y`
+    fn draw(&mut self, widget: &Widget, x: f32, y: f32) {
+        match widget {
+            Widget::Column { children } => {
+                let mut y = y;
+                for child in children {
+                    self.draw(child, x, y);
+                    y += self.measure(child)[1] + SPACING;
+                }
+            }
+            Widget::Row { children } => {
+                let mut x = x;
+                for child in children {
+                    self.draw(child, x, y);
+                    x += self.measure(child)[0] + SPACING;
+                }
+            }
+            Widget::Text { text, size } => self.text(text, *size, x, y, TEXT_COLOR),
+            Widget::Button { id, label } => {
+                let [width, height] = self.measure(widget);
+                let _ = write!(
+                    self.body,
+                    r#"<rect x="{x}" y="{y}" width="{width}" height="{height}" rx="{BUTTON_RADIUS}" fill="{BUTTON_COLOR}"/>"#
+                );
+                self.text(label, DEFAULT_WIDGET_TEXT_SIZE, x + BUTTON_PADDING[0], y + BUTTON_PADDING[1], BUTTON_TEXT_COLOR);
+                self.targets.push(Target {
+                    id: id.clone(),
+                    rect: [x, y, width, height],
+                });
+            }
+        }
+    }
+
+    /// The font scaled to an em of `size` pixels (PxScale is the height
+    /// from descent to ascent, not the em)
+    fn scale(&self, size: f32) -> PxScale {
+        let units_per_em = self.font.units_per_em().unwrap_or(1000.0);
+        PxScale::from(size * self.font.height_unscaled() / units_per_em)
+    }
+
+    /// Advance width and line height of a line of text
+    fn text_size(&self, text: &str, size: f32) -> [f32; 2] {
+        let font = self.font.as_scaled(self.scale(size));
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                width += font.kern(previous, id);
+            }
+            width += font.h_advance(id);
+            previous = Some(id);
+        }
+        [width, font.height()]
+    }
+
+    /// A line of text as a path of glyph outlines, its top-left corner at
+    /// `x`, `top`
+    fn text(&mut self, text: &str, size: f32, x: f32, top: f32, fill: &str) {
+        let outlines = self.font;
+        let font = outlines.as_scaled(self.scale(size));
+        let units = size / outlines.units_per_em().unwrap_or(1000.0);
+        let baseline = top + font.ascent();
+        let mut d = String::new();
+        let mut pen = x;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                pen += font.kern(previous, id);
+            }
+            // Outlines are in font units, y up
+            let point = |p: ab_glyph::Point| (pen + p.x * units, baseline - p.y * units);
+            let mut last = None;
+            for curve in outlines.outline(id).map(|outline| outline.curves).unwrap_or_default() {
+                let (start, end) = match curve {
+                    OutlineCurve::Line(start, end) | OutlineCurve::Quad(start, _, end) | OutlineCurve::Cubic(start, _, _, end) => {
+                        (start, end)
+                    }
+                };
+                if last != Some(start) {
+                    let (x, y) = point(start);
+                    let _ = write!(d, "M{x:.2} {y:.2}");
+                }
+                let _ = match curve {
+                    OutlineCurve::Line(_, end) => {
+                        let (x, y) = point(end);
+                        write!(d, "L{x:.2} {y:.2}")
+                    }
+                    OutlineCurve::Quad(_, control, end) => {
+                        let ((cx, cy), (x, y)) = (point(control), point(end));
+                        write!(d, "Q{cx:.2} {cy:.2} {x:.2} {y:.2}")
+                    }
+                    OutlineCurve::Cubic(_, control1, control2, end) => {
+                        let ((c1x, c1y), (c2x, c2y), (x, y)) = (point(control1), point(control2), point(end));
+                        write!(d, "C{c1x:.2} {c1y:.2} {c2x:.2} {c2y:.2} {x:.2} {y:.2}")
+                    }
+                };
+                last = Some(end);
+            }
+            pen += font.h_advance(id);
+            previous = Some(id);
+        }
+        if !d.is_empty() {
+            let _ = write!(self.body, r#"<path d="{d}" fill="{fill}"/>"#);
+        }
+    }
+}
+
+/// Total spacing between `count` widgets
+fn gaps(count: usize) -> f32 {
+    count.saturating_sub(1) as f32 * SPACING
+}
@@ -0,0 +1,172 @@
+//! Ray casting for hit tests (`SceneCommand::RequestHitTest`)
+//!
+//! Rays are cast in each volume's model space, so the ray parameter stays the
+//! world-space distance as long as the world direction is normalized.
+
+use crate::culling::Aabb;
+use glam::{Mat3, Mat4, Vec3};
+
+/// Rays closer than this to a triangle's plane miss it
+const EPSILON: f32 = 1e-6;
+
+/// A world-space ray with a normalized direction
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    origin: Vec3,
+    direction: Vec3,
+}
+
+/// Where a ray hit, in world space
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub point: Vec3,
+    /// Surface normal, facing the ray
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+/// Geometry of a loaded mesh, kept on the CPU for ray casting
+pub struct Triangles {
+    positions: Vec<Vec3>,
+    indices: Vec<u32>,
+    /// Checked before the triangles
+    bounds: Aabb,
+}
+
+impl Triangles {
+    pub fn new(positions: &[[f32; 3]], indices: &[u32]) -> Self {
+        let positions: Vec<Vec3> = positions.iter().map(|p| Vec3::from_array(*p)).collect();
+        Self {
+            bounds: Aabb::from_points(positions.iter().copied()),
+            positions,
+            indices: indices.to_vec(),
+        }
+    }
+
+    /// The bounding box, for frustum culling
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+impl Ray {
+    /// None if `direction` is zero
+    pub fn new(origin: Vec3, direction: Vec3) -> Option<Self> {
+        let direction = direction.try_normalize()?;
+        Some(Self { origin, direction })
+    }
+
+    /// The ray through a point in normalized device coordinates, starting on
+    /// the near plane
+    pub fn unproject(view_projection: Mat4, ndc_x: f32, ndc_y: f32) -> Option<Self> {
+        let inverse = view_projection.inverse();
+        let near = inverse.project_point3(Vec3::new(ndc_x, ndc_y, 0.0));
+        let far = inverse.project_point3(Vec3::new(ndc_x, ndc_y, 1.0));
+        Self::new(near, far - near)
+    }
+
+    /// Cast against the cube from -0.5 to 0.5 in model space
+    pub fn cast_unit_cube(&self, model: Mat4) -> Option<RayHit> {
+        let inverse = model.inverse();
+        let (origin, direction) = self.local(inverse);
+        let (distance, axis) = cast_box(origin, direction, Vec3::splat(-0.5), Vec3::splat(0.5))?;
+        let mut normal = Vec3::ZERO;
+        normal[axis] = 1.0;
+        Some(self.hit(inverse, distance, normal))
+    }
+
+    /// Cast against a mesh, returning the nearest triangle hit. Triangles are
+    /// hit from both sides.
+    pub fn cast_triangles(&self, model: Mat4, triangles: &Triangles) -> Option<RayHit> {
+        let inverse = model.inverse();
+        let (origin, direction) = self.local(inverse);
+        cast_box(origin, direction, triangles.bounds.min, triangles.bounds.max)?;
+
+        let mut nearest: Option<(f32, Vec3)> = None;
+        for triangle in triangles.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangles.positions.get(triangle[i] as usize).copied());
+            let (Some(a), Some(b), Some(c)) = (a, b, c) else {
+                continue;
+            };
+            if let Some(distance) = cast_triangle(origin, direction, a, b, c)
+                && nearest.is_none_or(|(nearest, _)| distance < nearest)
+            {
+                nearest = Some((distance, (b - a).cross(c - a)));
+            }
+        }
+        let (distance, normal) = nearest?;
+        Some(self.hit(inverse, distance, normal))
+    }
+
+    /// Where the ray crosses the model's local XY plane (z = 0), in model
+    /// space; None if it runs along the plane or away from it
+    pub fn cast_xy_plane(&self, model: Mat4) -> Option<Vec3> {
+        let (origin, direction) = self.local(model.inverse());
+        if direction.z.abs() < EPSILON {
+            return None;
+        }
+        let distance = -origin.z / direction.z;
+        (distance >= 0.0).then(|| origin + direction * distance)
+    }
+
+    fn local(&self, inverse: Mat4) -> (Vec3, Vec3) {
+        (inverse.transform_point3(self.origin), inverse.transform_vector3(self.direction))
+    }
+
+    /// World-space hit from a distance and a model-space normal
+    fn hit(&self, inverse: Mat4, distance: f32, local_normal: Vec3) -> RayHit {
+        let normal = (Mat3::from_mat4(inverse).transpose() * local_normal).normalize_or_zero();
+        RayHit {
+            point: self.origin + self.direction * distance,
+            normal: if normal.dot(self.direction) > 0.0 { -normal } else { normal },
+            distance,
+        }
+    }
+}
+
+/// Slab test against an axis-aligned box: the distance to where the ray
+/// enters it (or leaves it, for rays starting inside) and the axis of that
+/// face
+fn cast_box(origin: Vec3, direction: Vec3, min: Vec3, max: Vec3) -> Option<(f32, usize)> {
+    let mut enter = (f32::NEG_INFINITY, 0);
+    let mut exit = (f32::INFINITY, 0);
+    for axis in 0..3 {
+        let t1 = (min[axis] - origin[axis]) / direction[axis];
+        let t2 = (max[axis] - origin[axis]) / direction[axis];
+        let (near, far) = (t1.min(t2), t1.max(t2));
+        if near > enter.0 {
+            enter = (near, axis);
+        }
+        if far < exit.0 {
+            exit = (far, axis);
+        }
+    }
+    if exit.0 < enter.0.max(0.0) {
+        return None;
+    }
+    Some(if enter.0 >= 0.0 { enter } else { exit })
+}
+
+/// Möller-Trumbore ray/triangle intersection, two-sided
+fn cast_triangle(origin: Vec3, direction: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let s = origin - a;
+    let u = s.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(q) * inverse_determinant;
+    (distance > EPSILON).then_some(distance)
+}
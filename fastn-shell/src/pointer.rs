@@ -0,0 +1,180 @@
+//! Mouse and touch input handling
+//!
+//! Turns winit cursor, button, wheel and touch events into protocol input
+//! events. Each winit device gets a stable ID ("mouse-0", "touch-0", ...)
+//! and a `Connected` event the first time it is seen. Positions are in
+//! logical pixels from the top-left corner of the window, like the web's
+//! `clientX`/`clientY`.
+
+use fastn_protocol::{
+    DeviceId, InputEvent, MouseButton, MouseButtonData, MouseEvent, MouseInfo, MouseMoveData,
+    MouseWheelData, TouchData, TouchEvent, TouchInfo, TouchPoint,
+};
+use std::collections::HashMap;
+use winit::event::{ElementState, MouseScrollDelta, TouchPhase, WindowEvent};
+
+/// Pixels per wheel line, to report line-based scrolling in pixels
+const LINE_HEIGHT: f32 = 16.0;
+
+struct MouseState {
+    device_id: DeviceId,
+    /// Last cursor position, None while the cursor is outside the window
+    position: Option<(f32, f32)>,
+}
+
+/// Tracks mice and touch screens to fill in device IDs, positions and deltas
+#[derive(Default)]
+pub struct PointerTracker {
+    mice: HashMap<winit::event::DeviceId, MouseState>,
+    touch_screens: HashMap<winit::event::DeviceId, DeviceId>,
+}
+
+impl PointerTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert a window event into input events for the core (empty for
+    /// events that aren't mouse or touch input)
+    pub fn handle(&mut self, event: &WindowEvent, scale_factor: f64) -> Vec<InputEvent> {
+        let mut events = vec![];
+        match event {
+            WindowEvent::CursorMoved { device_id, position } => {
+                let position = position.to_logical::<f32>(scale_factor);
+                let (x, y) = (position.x, position.y);
+                let mouse = self.mouse(*device_id, &mut events);
+                // No delta for the first position after entering the window
+                let (dx, dy) = match mouse.position {
+                    Some((last_x, last_y)) => (x - last_x, y - last_y),
+                    None => (0.0, 0.0),
+                };
+                mouse.position = Some((x, y));
+                events.push(InputEvent::Mouse(MouseEvent::Move(MouseMoveData {
+                    device_id: mouse.device_id.clone(),
+                    x,
+                    y,
+                    dx,
+                    dy,
+                })));
+            }
+            WindowEvent::CursorLeft { device_id } => {
+                if let Some(mouse) = self.mice.get_mut(device_id) {
+                    mouse.position = None;
+                }
+            }
+            WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+            } => {
+                let Some(button) = convert_button(*button) else {
+                    return events;
+                };
+                let mouse = self.mouse(*device_id, &mut events);
+                let (x, y) = mouse.position.unwrap_or_default();
+                let data = MouseButtonData {
+                    device_id: mouse.device_id.clone(),
+                    x,
+                    y,
+                    button,
+                };
+                events.push(InputEvent::Mouse(match state {
+                    ElementState::Pressed => MouseEvent::Down(data),
+                    ElementState::Released => MouseEvent::Up(data),
+                }));
+            }
+            WindowEvent::MouseWheel { device_id, delta, .. } => {
+                // winit reports scrolling up as positive; like the web, the
+                // core gets positive deltas for scrolling down/right
+                let (dx, dy) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (-x * LINE_HEIGHT, -y * LINE_HEIGHT),
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        let delta = delta.to_logical::<f32>(scale_factor);
+                        (-delta.x, -delta.y)
+                    }
+                };
+                let mouse = self.mouse(*device_id, &mut events);
+                let (x, y) = mouse.position.unwrap_or_default();
+                events.push(InputEvent::Mouse(MouseEvent::Wheel(MouseWheelData {
+                    device_id: mouse.device_id.clone(),
+                    x,
+                    y,
+                    dx,
+                    dy,
+                })));
+            }
+            WindowEvent::Touch(touch) => {
+                let device_id = self.touch_screen(touch.device_id, &mut events);
+                let position = touch.location.to_logical::<f32>(scale_factor);
+                // winit reports one touch point per event; the core tracks
+                // multi-touch gestures by point ID
+                let data = TouchData {
+                    device_id,
+                    touches: vec![TouchPoint {
+                        id: touch.id as u32,
+                        x: position.x,
+                        y: position.y,
+                        force: touch.force.map(|force| force.normalized() as f32),
+                    }],
+                };
+                events.push(InputEvent::Touch(match touch.phase {
+                    TouchPhase::Started => TouchEvent::Start(data),
+                    TouchPhase::Moved => TouchEvent::Move(data),
+                    TouchPhase::Ended => TouchEvent::End(data),
+                    TouchPhase::Cancelled => TouchEvent::Cancel(data),
+                }));
+            }
+            _ => {}
+        }
+        events
+    }
+
+    /// The state of a mouse, announcing it to the core when it is new
+    fn mouse(&mut self, id: winit::event::DeviceId, events: &mut Vec<InputEvent>) -> &mut MouseState {
+        let count = self.mice.len();
+        self.mice.entry(id).or_insert_with(|| {
+            let device_id = format!("mouse-{}", count);
+            events.push(InputEvent::Mouse(MouseEvent::Connected(MouseInfo {
+                device_id: device_id.clone(),
+                name: "Mouse".to_string(),
+                is_virtual: false,
+                has_wheel: true,
+                button_count: 5,
+            })));
+            MouseState {
+                device_id,
+                position: None,
+            }
+        })
+    }
+
+    /// The ID of a touch screen, announcing it to the core when it is new
+    fn touch_screen(&mut self, id: winit::event::DeviceId, events: &mut Vec<InputEvent>) -> DeviceId {
+        let count = self.touch_screens.len();
+        self.touch_screens
+            .entry(id)
+            .or_insert_with(|| {
+                let device_id = format!("touch-{}", count);
+                events.push(InputEvent::Touch(TouchEvent::Connected(TouchInfo {
+                    device_id: device_id.clone(),
+                    name: "Touch screen".to_string(),
+                    is_virtual: false,
+                    // winit doesn't report the limit
+                    max_touch_points: 10,
+                })));
+                device_id
+            })
+            .clone()
+    }
+}
+
+fn convert_button(button: winit::event::MouseButton) -> Option<MouseButton> {
+    match button {
+        winit::event::MouseButton::Left => Some(MouseButton::Left),
+        winit::event::MouseButton::Middle => Some(MouseButton::Middle),
+        winit::event::MouseButton::Right => Some(MouseButton::Right),
+        winit::event::MouseButton::Back => Some(MouseButton::Back),
+        winit::event::MouseButton::Forward => Some(MouseButton::Forward),
+        winit::event::MouseButton::Other(_) => None,
+    }
+}
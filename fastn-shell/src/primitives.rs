@@ -0,0 +1,149 @@
+//! Geometry of `Primitive` volumes other than cubes
+//!
+//! Meshes are centered on the origin, wound counter-clockwise seen from
+//! outside. Round shapes are grids wrapped around the Y axis, their seam
+//! column repeated so UVs run 0 to 1 around it; `segments` is the number
+//! of columns.
+
+use crate::asset_loader::LoadedMesh;
+use fastn_protocol::Primitive;
+use glam::Vec3;
+use std::f32::consts::{PI, TAU};
+
+/// Fewest and most sides around a round primitive's axis
+const MIN_SEGMENTS: u32 = 3;
+const MAX_SEGMENTS: u32 = 256;
+
+/// The primitive's mesh, white; None for text, which isn't a mesh
+pub fn generate(primitive: &Primitive) -> Option<LoadedMesh> {
+    let mut mesh = LoadedMesh {
+        vertices: vec![],
+        normals: vec![],
+        uvs: vec![],
+        indices: vec![],
+        color: [1.0, 1.0, 1.0, 1.0],
+        rig: None,
+    };
+    let sides = |segments: u32| segments.clamp(MIN_SEGMENTS, MAX_SEGMENTS);
+    match *primitive {
+        Primitive::Cube { size } => add_box(&mut mesh, Vec3::splat(size / 2.0)),
+        Primitive::Box { width, height, depth } => add_box(&mut mesh, Vec3::new(width, height, depth) / 2.0),
+        Primitive::Sphere { radius, segments } => {
+            let columns = sides(segments);
+            add_grid(&mut mesh, columns, (columns / 2).max(2), |direction, v| {
+                let latitude = (v - 0.5) * PI;
+                let normal = direction * latitude.cos() + Vec3::Y * latitude.sin();
+                (normal * radius, normal)
+            });
+        }
+        Primitive::Cylinder { radius, height, segments } => {
+            let columns = sides(segments);
+            add_grid(&mut mesh, columns, 1, |direction, v| {
+                (direction * radius + Vec3::Y * (v - 0.5) * height, direction)
+            });
+            add_disc(&mut mesh, columns, radius, height / 2.0, Vec3::Y);
+            add_disc(&mut mesh, columns, radius, -height / 2.0, Vec3::NEG_Y);
+        }
+        Primitive::Cone { radius, height, segments } => {
+            let columns = sides(segments);
+            add_grid(&mut mesh, columns, 1, |direction, v| {
+                let position = direction * radius * (1.0 - v) + Vec3::Y * (v - 0.5) * height;
+                (position, (direction * height + Vec3::Y * radius).normalize_or_zero())
+            });
+            add_disc(&mut mesh, columns, radius, -height / 2.0, Vec3::NEG_Y);
+        }
+        Primitive::Torus { radius, tube_radius, segments } => {
+            let columns = sides(segments);
+            add_grid(&mut mesh, columns, (columns / 2).max(MIN_SEGMENTS), |direction, v| {
+                let angle = v * TAU;
+                let normal = direction * angle.cos() + Vec3::Y * angle.sin();
+                (direction * radius + normal * tube_radius, normal)
+            });
+        }
+        Primitive::Plane { width, height } => {
+            add_face(&mut mesh, Vec3::ZERO, Vec3::X * width / 2.0, Vec3::NEG_Z * height / 2.0);
+        }
+        Primitive::Quad { width, height } => {
+            add_face(&mut mesh, Vec3::ZERO, Vec3::X * width / 2.0, Vec3::Y * height / 2.0);
+        }
+        Primitive::Text { .. } => return None,
+    }
+    Some(mesh)
+}
+
+/// Six faces around the origin, `half` its half extents
+fn add_box(mesh: &mut LoadedMesh, half: Vec3) {
+    let (x, y, z) = (Vec3::X * half.x, Vec3::Y * half.y, Vec3::Z * half.z);
+    add_face(mesh, z, x, y);
+    add_face(mesh, -z, -x, y);
+    add_face(mesh, y, x, -z);
+    add_face(mesh, -y, x, z);
+    add_face(mesh, x, -z, y);
+    add_face(mesh, -x, z, y);
+}
+
+/// A rectangle at `center` spanning `right` and `up` either way, facing
+/// `right × up`
+fn add_face(mesh: &mut LoadedMesh, center: Vec3, right: Vec3, up: Vec3) {
+    let normal = right.cross(up).normalize_or_zero();
+    let first = mesh.vertices.len() as u32;
+    for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+        mesh.vertices.push((center + right * x + up * y).to_array());
+        mesh.normals.push(normal.to_array());
+        mesh.uvs.push([(x + 1.0) / 2.0, (1.0 - y) / 2.0]);
+    }
+    mesh.indices.extend([0, 1, 2, 2, 3, 0].map(|i| first + i));
+}
+
+/// A surface swept around the Y axis: `columns` around and `rows` from
+/// v = 0 (bottom) to 1 (top). `point` gives the position and normal at
+/// each grid vertex from the outward horizontal direction and v.
+fn add_grid(mesh: &mut LoadedMesh, columns: u32, rows: u32, point: impl Fn(Vec3, f32) -> (Vec3, Vec3)) {
+    let first = mesh.vertices.len() as u32;
+    for row in 0..=rows {
+        let v = row as f32 / rows as f32;
+        for column in 0..=columns {
+            let u = column as f32 / columns as f32;
+            let (position, normal) = point(direction(u * TAU), v);
+            mesh.vertices.push(position.to_array());
+            mesh.normals.push(normal.to_array());
+            mesh.uvs.push([u, 1.0 - v]);
+        }
+    }
+    let stride = columns + 1;
+    for row in 0..rows {
+        for column in 0..columns {
+            let bottom = first + row * stride + column;
+            let top = bottom + stride;
+            mesh.indices.extend([bottom, bottom + 1, top + 1, top + 1, top, bottom]);
+        }
+    }
+}
+
+/// A flat cap of a cylinder or cone at height `y`, facing `normal` (up or
+/// down)
+fn add_disc(mesh: &mut LoadedMesh, columns: u32, radius: f32, y: f32, normal: Vec3) {
+    let center = mesh.vertices.len() as u32;
+    mesh.vertices.push([0.0, y, 0.0]);
+    mesh.normals.push(normal.to_array());
+    mesh.uvs.push([0.5, 0.5]);
+    for column in 0..columns {
+        let direction = direction(column as f32 / columns as f32 * TAU);
+        mesh.vertices.push((direction * radius + Vec3::Y * y).to_array());
+        mesh.normals.push(normal.to_array());
+        mesh.uvs.push([0.5 + direction.x / 2.0, 0.5 + direction.z * normal.y / 2.0]);
+    }
+    for column in 0..columns {
+        let (a, b) = (center + 1 + column, center + 1 + (column + 1) % columns);
+        // The rim runs counter-clockwise seen from above
+        match normal.y > 0.0 {
+            true => mesh.indices.extend([center, a, b]),
+            false => mesh.indices.extend([center, b, a]),
+        }
+    }
+}
+
+/// Horizontal unit vector `angle` radians from +Z toward +X
+fn direction(angle: f32) -> Vec3 {
+    Vec3::new(angle.sin(), 0.0, angle.cos())
+}
@@ -1,46 +1,277 @@
 //! Basic wgpu renderer for fastn-shell
 
+use std::collections::HashMap;
 use std::sync::Arc;
+use winit::dpi::PhysicalSize;
 use winit::window::Window;
 use wgpu::util::DeviceExt;
-use fastn_protocol::{CreateVolumeData, BackgroundData, CameraData};
-use glam::{Mat4, Vec3};
+use fastn_protocol::{
+    BoneTransform, CreateVolumeData, BackgroundData, CameraData, Easing, FadeData, Hit, HitTestSource, MaterialOverride,
+    PlayAnimationData, SetBlendShapeData, SetMaterialData, SetTransformData, Transform,
+};
+use glam::{Mat4, Quat, Vec3};
 use bytemuck::{Pod, Zeroable};
-use crate::asset_loader::AssetManager;
+use crate::asset_loader::LoadedMesh;
+use crate::batching::{self, BatchKey, BatchReport, MeshKey, Pass};
+use crate::culling::{Aabb, Frustum};
+use crate::picking::{Ray, Triangles};
+use crate::primitives;
+use crate::skinning::{Rig, Skeleton};
+use crate::text::{ATLAS_SIZE, GlyphAtlas, TextMesh};
+use crate::texture::TextureImage;
 
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 struct Vertex {
     position: [f32; 3],
     normal: [f32; 3],
+    uv: [f32; 2],
 }
 
+/// Vertex of a rigged asset, moved by up to four joint matrices
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
-struct Uniforms {
+struct SkinnedVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    uv: [f32; 2],
+    joints: [u32; 4],
+    weights: [f32; 4],
+}
+
+/// Per-volume data, read by instance index
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct Instance {
     mvp: [[f32; 4]; 4],
+    /// Camera position in model space, w unused
+    camera: [f32; 4],
+    /// Base color, so volumes differing only in color share a batch
     color: [f32; 4],
 }
 
+/// Material parameters, shared by the volumes of a batch
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct MaterialUniforms {
+    /// Emitted light, w unused
+    emissive: [f32; 4],
+    /// Offset and size of the texture region shown
+    uv_rect: [f32; 4],
+    /// Metallic and roughness, zw unused
+    params: [f32; 4],
+}
+
+/// A texture and the bind group sampling it
+pub struct GpuTexture {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+/// GPU buffers of a loaded asset or generated primitive, shared by all
+/// volumes showing it
+pub struct GpuMesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    /// Base color from the asset's material
+    color: [f32; 4],
+    /// Base color texture from the asset's material
+    texture: Option<GpuTexture>,
+    /// CPU copy of the geometry for hit tests (rest pose for rigged assets)
+    triangles: Triangles,
+    /// Set for rigged assets, whose vertex buffer holds `SkinnedVertex`es
+    skin: Option<GpuSkin>,
+}
+
+/// What volumes showing a rigged asset share
+pub struct GpuSkin {
+    rig: Arc<Rig>,
+    /// Position and normal displacement of every vertex for each morph
+    /// target, as pairs of vec4s
+    morph_buffer: wgpu::Buffer,
+}
+
+/// Pose of a volume showing a rigged asset, and the buffers the vertex
+/// shader reads it from
+pub struct VolumeSkeleton {
+    skeleton: Skeleton,
+    joint_buffer: wgpu::Buffer,
+    weight_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// GPU buffers of a text volume's glyph quads, textured from the glyph
+/// atlas
+pub struct GpuText {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    /// The text block's rectangle, for hit tests
+    triangles: Triangles,
+    /// Turn to face the camera
+    billboard: bool,
+}
+
 /// Mesh buffers for a volume (either shared or custom)
 pub enum VolumeMesh {
     /// Use the shared primitive cube mesh
     Primitive { size: f32 },
-    /// Use a custom loaded mesh
-    Custom {
-        vertex_buffer: wgpu::Buffer,
-        index_buffer: wgpu::Buffer,
-        num_indices: u32,
-    },
+    /// Use a loaded asset's mesh, or a generated primitive's
+    Custom(Arc<GpuMesh>),
+    /// Glyph quads, drawn unlit and blended after the other volumes
+    Text(GpuText),
 }
 
 pub struct Volume {
     pub id: String,
+    /// The volume this one is placed relative to
+    pub parent: Option<String>,
+    /// Relative to `parent`
     pub position: [f32; 3],
     pub rotation: [f32; 4],
     pub scale: [f32; 3],
     pub color: [f32; 4],
+    pub material: VolumeMaterial,
     pub mesh: VolumeMesh,
+    /// Transform animation in progress
+    pub animation: Option<TransformAnimation>,
+    /// Set for volumes showing a rigged asset
+    pub skeleton: Option<VolumeSkeleton>,
+}
+
+/// A volume's material besides its color, as set by `MaterialOverride`s
+pub struct VolumeMaterial {
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    /// Texture from `CreateTexture`; None shows the asset's own texture
+    pub texture_id: Option<String>,
+    pub uv_rect: [f32; 4],
+}
+
+impl Default for VolumeMaterial {
+    /// Dielectric and fully rough: plain diffuse lighting
+    fn default() -> Self {
+        Self {
+            metallic: 0.0,
+            roughness: 1.0,
+            emissive: [0.0; 3],
+            texture_id: None,
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+        }
+    }
+}
+
+impl VolumeMaterial {
+    /// Apply the fields an override sets
+    fn apply(&mut self, material: &MaterialOverride) {
+        if let Some(metallic) = material.metallic {
+            self.metallic = metallic.clamp(0.0, 1.0);
+        }
+        if let Some(roughness) = material.roughness {
+            self.roughness = roughness.clamp(0.0, 1.0);
+        }
+        if let Some(emissive) = material.emissive {
+            self.emissive = emissive;
+        }
+        if let Some(texture_id) = &material.texture_id {
+            self.texture_id = Some(texture_id.clone());
+        }
+        if let Some(uv_rect) = material.uv_rect {
+            self.uv_rect = uv_rect;
+        }
+    }
+}
+
+/// Interpolation of a volume's transform requested by `SetTransform`
+pub struct TransformAnimation {
+    from: Transform,
+    to: Transform,
+    duration: f32,
+    elapsed: f32,
+    easing: Easing,
+    /// Reported in `VolumeAnimationComplete` when the animation ends
+    animation_id: Option<String>,
+}
+
+impl TransformAnimation {
+    /// Transform after `dt` more seconds, and whether the animation is done
+    fn advance(&mut self, dt: f32) -> (Transform, bool) {
+        self.elapsed += dt;
+        let t = match self.duration > 0.0 {
+            true => (self.elapsed / self.duration).min(1.0),
+            false => 1.0,
+        };
+        if t >= 1.0 {
+            return (self.to.clone(), true);
+        }
+        let t = ease(self.easing, t);
+        let rotation = Quat::from_array(self.from.rotation).slerp(Quat::from_array(self.to.rotation), t);
+        let transform = Transform {
+            position: Vec3::from_array(self.from.position).lerp(Vec3::from_array(self.to.position), t).to_array(),
+            rotation: rotation.to_array(),
+            scale: Vec3::from_array(self.from.scale).lerp(Vec3::from_array(self.to.scale), t).to_array(),
+        };
+        (transform, false)
+    }
+}
+
+/// Apply a timing curve to linear progress `t` in 0..1. Cubic bezier curves
+/// are shown as ease-in-out.
+fn ease(easing: Easing, t: f32) -> f32 {
+    match easing {
+        Easing::Linear => t,
+        Easing::EaseIn => t * t * t,
+        Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+        Easing::EaseInOut | Easing::CubicBezier(_) => match t < 0.5 {
+            true => 4.0 * t * t * t,
+            false => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+        },
+    }
+}
+
+impl Volume {
+    /// The volume's transform relative to its parent, without its mesh
+    fn local_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(
+            Vec3::from_array(self.scale),
+            Quat::from_array(self.rotation),
+            Vec3::from_array(self.position),
+        )
+    }
+
+    /// Model matrix under a parent at `parent`; primitive cubes are unit
+    /// cubes scaled by their size, and billboard text turns about Y to
+    /// face `camera`
+    fn model_matrix(&self, parent: Mat4, camera: Vec3) -> Mat4 {
+        match &self.mesh {
+            VolumeMesh::Primitive { size } => parent * self.local_matrix() * Mat4::from_scale(Vec3::splat(*size)),
+            VolumeMesh::Text(text) if text.billboard => {
+                // Only the parents' scale applies: the text faces the
+                // viewer whichever way they turn
+                let position = parent.transform_point3(Vec3::from_array(self.position));
+                let (parent_scale, _, _) = parent.to_scale_rotation_translation();
+                let to_camera = camera - position;
+                Mat4::from_scale_rotation_translation(
+                    Vec3::from_array(self.scale) * parent_scale,
+                    Quat::from_rotation_y(to_camera.x.atan2(to_camera.z)),
+                    position,
+                )
+            }
+            VolumeMesh::Custom(_) | VolumeMesh::Text(_) => parent * self.local_matrix(),
+        }
+    }
+
+    /// Material parameters as the shader reads them
+    fn material_uniforms(&self) -> MaterialUniforms {
+        let material = &self.material;
+        MaterialUniforms {
+            emissive: [material.emissive[0], material.emissive[1], material.emissive[2], 0.0],
+            uv_rect: material.uv_rect,
+            params: [material.metallic, material.roughness, 0.0, 0.0],
+        }
+    }
 }
 
 // Default camera settings
@@ -48,20 +279,74 @@ const DEFAULT_CAMERA_POSITION: Vec3 = Vec3::new(0.0, 1.6, 3.0);
 const DEFAULT_CAMERA_YAW: f32 = -std::f32::consts::FRAC_PI_2; // Facing -Z (towards origin)
 const DEFAULT_CAMERA_PITCH: f32 = -0.5; // Looking slightly down at origin
 
+/// Color format of headless renderers (sRGB, like window surfaces)
+const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Volumes the instance and material buffers have room for before they
+/// grow
+const INITIAL_UNIFORM_CAPACITY: usize = 64;
+
 pub struct Renderer {
-    surface: wgpu::Surface<'static>,
+    /// None for headless renderers, which draw into `offscreen`
+    surface: Option<wgpu::Surface<'static>>,
+    /// What `capture` draws into: the headless target, or for a window a
+    /// texture like its surface, made by the first capture
+    offscreen: Option<wgpu::Texture>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     render_pipeline: wgpu::RenderPipeline,
+    /// Pipeline of rigged assets: joint matrices and morph targets in
+    /// bind group 2
+    skinned_pipeline: wgpu::RenderPipeline,
+    /// Pipeline of text volumes: unlit, alpha blended, seen from both sides
+    text_pipeline: wgpu::RenderPipeline,
+    /// Full-screen overlay drawn last while the fade color isn't clear, and
+    /// the uniform holding that color
+    fade_pipeline: wgpu::RenderPipeline,
+    fade_buffer: wgpu::Buffer,
+    fade_bind_group: wgpu::BindGroup,
+    fade_color: [f32; 4],
+    skin_bind_group_layout: wgpu::BindGroupLayout,
+    /// A texture and its sampler, bind group 1
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    /// 1x1 white, for volumes without a texture
+    default_texture: GpuTexture,
+    /// Textures by texture_id
+    textures: HashMap<String, GpuTexture>,
+    /// Glyphs of all text volumes, and their texture once text was created
+    glyph_atlas: GlyphAtlas,
+    atlas_texture: Option<GpuTexture>,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
-    uniform_buffer: wgpu::Buffer,
+    /// One `Instance` per volume, in drawing order
+    instance_buffer: wgpu::Buffer,
+    /// One `MaterialUniforms` per distinct material, `uniform_stride` bytes
+    /// apart
+    material_buffer: wgpu::Buffer,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
     uniform_bind_group: wgpu::BindGroup,
+    uniform_stride: u64,
+    uniform_capacity: usize,
+    /// What the last frame took, see `batching`
+    batch_report: BatchReport,
     depth_texture: wgpu::TextureView,
+    /// Depth buffer of the views drawn with `render_view`, and its size
+    #[cfg(feature = "xr")]
+    view_depth: Option<(wgpu::Extent3d, wgpu::TextureView)>,
     num_indices: u32,
     background_color: [f32; 4],
+    /// Whether the background is `BackgroundData::Transparent`
+    transparent_background: bool,
+    /// How the window's surface composites a transparent background over
+    /// what's behind it, None if it can't
+    transparent_alpha_mode: Option<wgpu::CompositeAlphaMode>,
     volumes: Vec<Volume>,
+    /// Uploaded asset meshes by asset_id
+    meshes: HashMap<String, Arc<GpuMesh>>,
+    /// Generated primitive meshes, shared by volumes with the same shape
+    primitive_meshes: HashMap<String, Arc<GpuMesh>>,
     camera_position: Vec3,
     camera_yaw: f32,   // Rotation around Y axis (left/right)
     camera_pitch: f32, // Rotation around X axis (up/down)
@@ -87,33 +372,79 @@ impl Renderer {
             .await
             .unwrap();
 
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
-                label: None,
-                memory_hints: wgpu::MemoryHints::default(),
-                trace: wgpu::Trace::Off,
-                experimental_features: Default::default(),
-            })
-            .await
-            .unwrap();
+        let (device, queue) = request_device(&adapter).await.unwrap();
 
-        let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps.formats[0];
+        let capabilities = surface.get_capabilities(&adapter);
+        Self::on_surface(
+            surface,
+            capabilities.formats[0],
+            &capabilities.alpha_modes,
+            wgpu::PresentMode::Fifo,
+            device,
+            queue,
+            size,
+        )
+    }
 
+    /// Renderer drawing into a window's surface with a device created
+    /// elsewhere, e.g. one shared with an OpenXR session. `alpha_modes` are
+    /// the ones the surface supports.
+    pub(crate) fn on_surface(
+        surface: wgpu::Surface<'static>,
+        format: wgpu::TextureFormat,
+        alpha_modes: &[wgpu::CompositeAlphaMode],
+        present_mode: wgpu::PresentMode,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        size: PhysicalSize<u32>,
+    ) -> Self {
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode: wgpu::CompositeAlphaMode::Auto,
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
+            present_mode,
+            ..surface_config(format, size.width, size.height)
         };
         surface.configure(&device, &config);
 
+        let mut renderer = Self::with_target(device, queue, config, Some(surface), None);
+        // Premultiplied first, as the pipelines blend
+        renderer.transparent_alpha_mode =
+            [wgpu::CompositeAlphaMode::PreMultiplied, wgpu::CompositeAlphaMode::PostMultiplied]
+                .into_iter()
+                .find(|mode| alpha_modes.contains(mode));
+        renderer
+    }
+
+    /// Renderer without a window, drawing into a texture that `capture`
+    /// reads back. None if there is no GPU adapter.
+    pub async fn headless(width: u32, height: u32) -> Option<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await
+            .ok()?;
+        log::info!("Headless renderer on {:?}", adapter.get_info());
+
+        let (device, queue) = request_device(&adapter).await.ok()?;
+        let config = surface_config(HEADLESS_FORMAT, width, height);
+        let offscreen = create_offscreen_texture(&device, &config);
+
+        Some(Self::with_target(device, queue, config, None, Some(offscreen)))
+    }
+
+    fn with_target(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        surface: Option<wgpu::Surface<'static>>,
+        offscreen: Option<wgpu::Texture>,
+    ) -> Self {
         // Create depth texture
         let depth_texture = create_depth_texture(&device, &config);
 
@@ -123,103 +454,149 @@ impl Renderer {
             source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
         });
 
-        // Create uniform buffer and bind group
-        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Uniform Buffer"),
-            size: std::mem::size_of::<Uniforms>() as u64,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        // Create the instance and material buffers and their bind group.
+        // Volumes' transforms are instances, read by instance index, so a
+        // batch is one instanced draw; each batch's material sits at its
+        // own dynamic offset.
+        let uniform_stride = (std::mem::size_of::<MaterialUniforms>() as u64)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
 
         let uniform_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Uniform Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<MaterialUniforms>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let (instance_buffer, material_buffer, uniform_bind_group) =
+            create_uniforms(&device, &uniform_bind_group_layout, INITIAL_UNIFORM_CAPACITY, uniform_stride);
+
+        // The texture a volume shows, and how it is sampled
+        let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Texture Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let default_texture = create_texture(&device, &texture_bind_group_layout, &sampler, "Default Texture", 1, 1);
+        write_texture(&queue, &default_texture.texture, &[255; 4]);
+
+        // Joint matrices and morph weights of a skinned volume, and the morph
+        // displacements of its asset
+        let skin_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skin Bind Group Layout"),
+            entries: &[0, 1, 2].map(|binding| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::VERTEX,
                 ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
                     min_binding_size: None,
                 },
                 count: None,
-            }],
-        });
-
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Uniform Bind Group"),
-            layout: &uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
-            }],
+            }),
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&uniform_bind_group_layout],
+            bind_group_layouts: &[&uniform_bind_group_layout, &texture_bind_group_layout],
             push_constant_ranges: &[],
         });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &[
-                        wgpu::VertexAttribute {
-                            offset: 0,
-                            shader_location: 0,
-                            format: wgpu::VertexFormat::Float32x3,
-                        },
-                        wgpu::VertexAttribute {
-                            offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                            shader_location: 1,
-                            format: wgpu::VertexFormat::Float32x3,
-                        },
-                    ],
-                }],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+        let vertex_layout = wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x2],
+        };
+        let render_pipeline = create_pipeline(
+            &device,
+            "Render Pipeline",
+            &pipeline_layout,
+            &shader,
+            PipelineStages::opaque("vs_main"),
+            vertex_layout.clone(),
+            config.format,
+        );
+        let text_pipeline = create_pipeline(
+            &device,
+            "Text Pipeline",
+            &pipeline_layout,
+            &shader,
+            PipelineStages {
+                vertex: "vs_main",
+                fragment: "fs_text",
+                blend: wgpu::BlendState::ALPHA_BLENDING,
+                cull_mode: None,
             },
-            multiview: None,
-            cache: None,
+            vertex_layout,
+            config.format,
+        );
+
+        let skinned_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinned Pipeline Layout"),
+            bind_group_layouts: &[&uniform_bind_group_layout, &texture_bind_group_layout, &skin_bind_group_layout],
+            push_constant_ranges: &[],
         });
+        let skinned_pipeline = create_pipeline(
+            &device,
+            "Skinned Pipeline",
+            &skinned_pipeline_layout,
+            &shader,
+            PipelineStages::opaque("vs_skinned"),
+            wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![
+                    0 => Float32x3, 1 => Float32x3, 2 => Float32x2, 3 => Uint32x4, 4 => Float32x4
+                ],
+            },
+            config.format,
+        );
 
-        // Create cube vertices with normals
+        let (fade_pipeline, fade_buffer, fade_bind_group) = create_fade_pipeline(&device, config.format);
+
+        // Create cube vertices with normals and texture coordinates
         let vertices = create_cube_vertices();
         let indices = create_cube_indices();
 
@@ -237,18 +614,43 @@ impl Renderer {
 
         Self {
             surface,
+            offscreen,
             device,
             queue,
             config,
             render_pipeline,
+            skinned_pipeline,
+            text_pipeline,
+            fade_pipeline,
+            fade_buffer,
+            fade_bind_group,
+            fade_color: [0.0; 4],
+            skin_bind_group_layout,
+            texture_bind_group_layout,
+            sampler,
+            default_texture,
+            textures: HashMap::new(),
+            glyph_atlas: GlyphAtlas::new(),
+            atlas_texture: None,
             vertex_buffer,
             index_buffer,
-            uniform_buffer,
+            instance_buffer,
+            material_buffer,
+            uniform_bind_group_layout,
             uniform_bind_group,
+            uniform_stride,
+            uniform_capacity: INITIAL_UNIFORM_CAPACITY,
+            batch_report: BatchReport::default(),
             depth_texture,
+            #[cfg(feature = "xr")]
+            view_depth: None,
             num_indices: indices.len() as u32,
             background_color: [0.1, 0.1, 0.2, 1.0],
+            transparent_background: false,
+            transparent_alpha_mode: None,
             volumes: Vec::new(),
+            meshes: HashMap::new(),
+            primitive_meshes: HashMap::new(),
             camera_position: DEFAULT_CAMERA_POSITION,
             camera_yaw: DEFAULT_CAMERA_YAW,
             camera_pitch: DEFAULT_CAMERA_PITCH,
@@ -259,72 +661,273 @@ impl Renderer {
         if width > 0 && height > 0 {
             self.config.width = width;
             self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
+            if self.offscreen.is_some() {
+                self.offscreen = Some(create_offscreen_texture(&self.device, &self.config));
+            }
             self.depth_texture = create_depth_texture(&self.device, &self.config);
         }
     }
 
+    /// A transparent background is cleared to transparent black, which the
+    /// window composites over the desktop when its surface can
     pub fn set_background(&mut self, bg: &BackgroundData) {
-        match bg {
+        let alpha_mode = match bg {
             BackgroundData::Color(color) => {
                 self.background_color = *color;
+                self.transparent_background = false;
+                wgpu::CompositeAlphaMode::Auto
+            }
+            BackgroundData::Transparent => {
+                self.background_color = [0.0; 4];
+                self.transparent_background = true;
+                self.transparent_alpha_mode.unwrap_or_else(|| {
+                    if self.surface.is_some() {
+                        log::warn!("The window can't be transparent, showing a black background");
+                    }
+                    wgpu::CompositeAlphaMode::Auto
+                })
+            }
+            BackgroundData::Skybox { .. } => return,
+        };
+        if alpha_mode != self.config.alpha_mode {
+            self.config.alpha_mode = alpha_mode;
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
+        }
+    }
+
+    /// Cover the scene with a color, e.g. black to hide a teleport; an
+    /// alpha of 0 hides the overlay
+    pub fn set_fade(&mut self, fade: &FadeData) {
+        self.fade_color = fade.color;
+        self.queue.write_buffer(&self.fade_buffer, 0, bytemuck::cast_slice(&fade.color));
+    }
+
+    /// Whether the window can show what's behind it through a transparent
+    /// background
+    pub fn supports_transparency(&self) -> bool {
+        self.transparent_alpha_mode.is_some()
+    }
+
+    /// Whether the background is transparent
+    #[cfg(feature = "xr")]
+    pub fn transparent_background(&self) -> bool {
+        self.transparent_background
+    }
+
+    /// Upload a loaded asset's mesh and base color texture to the GPU, for
+    /// volumes created from it. Rigged assets are uploaded in bind space,
+    /// for the skinned pipeline.
+    pub fn upload_mesh(&mut self, asset_id: &str, mesh: &LoadedMesh, image: Option<&TextureImage>) {
+        let gpu_mesh = self.create_gpu_mesh(asset_id, mesh, image);
+        self.meshes.insert(asset_id.to_string(), Arc::new(gpu_mesh));
+    }
+
+    /// A primitive's generated mesh, uploaded the first time a volume has
+    /// its shape. Text is not a mesh; it's drawn from `upload_text`.
+    fn primitive_mesh(&mut self, primitive: &fastn_protocol::Primitive) -> Arc<GpuMesh> {
+        let key = format!("{:?}", primitive);
+        if let Some(gpu_mesh) = self.primitive_meshes.get(&key) {
+            return Arc::clone(gpu_mesh);
+        }
+        let mesh = primitives::generate(primitive).expect("text volumes are laid out, not generated");
+        let gpu_mesh = Arc::new(self.create_gpu_mesh(&key, &mesh, None));
+        self.primitive_meshes.insert(key, Arc::clone(&gpu_mesh));
+        gpu_mesh
+    }
+
+    fn create_gpu_mesh(&self, asset_id: &str, mesh: &LoadedMesh, image: Option<&TextureImage>) -> GpuMesh {
+        let (vertex_buffer, skin) = match &mesh.rig {
+            Some(rig) => {
+                let vertices: Vec<SkinnedVertex> = rig.vertices.iter()
+                    .map(|v| SkinnedVertex {
+                        position: v.position,
+                        normal: v.normal,
+                        uv: v.uv,
+                        joints: v.joints,
+                        weights: v.weights,
+                    })
+                    .collect();
+                let vertex_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Skinned Vertex Buffer {}", asset_id)),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+
+                // Storage buffers can't be empty
+                let mut displacements: Vec<[f32; 4]> = rig.morph_displacements.iter()
+                    .flat_map(|(position, normal)| {
+                        [[position[0], position[1], position[2], 0.0], [normal[0], normal[1], normal[2], 0.0]]
+                    })
+                    .collect();
+                if displacements.is_empty() {
+                    displacements = vec![[0.0; 4]; 2];
+                }
+                let morph_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Morph Target Buffer {}", asset_id)),
+                    contents: bytemuck::cast_slice(&displacements),
+                    usage: wgpu::BufferUsages::STORAGE,
+                });
+                (vertex_buffer, Some(GpuSkin { rig: Arc::clone(rig), morph_buffer }))
+            }
+            None => {
+                let vertices: Vec<Vertex> = mesh.vertices.iter()
+                    .zip(mesh.normals.iter())
+                    .zip(mesh.uvs.iter())
+                    .map(|((pos, norm), uv)| Vertex {
+                        position: *pos,
+                        normal: *norm,
+                        uv: *uv,
+                    })
+                    .collect();
+                let vertex_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Vertex Buffer {}", asset_id)),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+                (vertex_buffer, None)
+            }
+        };
+
+        let index_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("Index Buffer {}", asset_id)),
+            contents: bytemuck::cast_slice(&mesh.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let texture = image.map(|image| {
+            let texture = create_texture(
+                &self.device,
+                &self.texture_bind_group_layout,
+                &self.sampler,
+                &format!("Texture {}", asset_id),
+                image.width,
+                image.height,
+            );
+            write_texture(&self.queue, &texture.texture, &image.rgba);
+            texture
+        });
+
+        log::info!("Uploaded mesh buffers for {} ({} vertices, {} indices{}{})",
+            asset_id, mesh.vertices.len(), mesh.indices.len(),
+            if skin.is_some() { ", rigged" } else { "" },
+            if texture.is_some() { ", textured" } else { "" });
+
+        GpuMesh {
+            vertex_buffer,
+            index_buffer,
+            num_indices: mesh.indices.len() as u32,
+            color: mesh.color,
+            texture,
+            triangles: Triangles::new(&mesh.vertices, &mesh.indices),
+            skin,
+        }
+    }
+
+    /// Drop an asset's GPU buffers; volumes already showing it keep them
+    pub fn unload_mesh(&mut self, asset_id: &str) {
+        self.meshes.remove(asset_id);
+    }
+
+    /// Create a texture, or replace its pixels. The GPU texture is reused
+    /// when the size is unchanged.
+    pub fn upload_texture(&mut self, texture_id: &str, image: &TextureImage) -> Result<(), String> {
+        let max = self.device.limits().max_texture_dimension_2d;
+        if image.width > max || image.height > max {
+            return Err(format!("{}x{} is larger than the GPU allows ({})", image.width, image.height, max));
+        }
+        let reusable = self.textures.get(texture_id).filter(|existing| {
+            let size = existing.texture.size();
+            (size.width, size.height) == (image.width, image.height)
+        });
+        match reusable {
+            Some(existing) => write_texture(&self.queue, &existing.texture, &image.rgba),
+            None => {
+                let texture = create_texture(
+                    &self.device,
+                    &self.texture_bind_group_layout,
+                    &self.sampler,
+                    &format!("Texture {}", texture_id),
+                    image.width,
+                    image.height,
+                );
+                write_texture(&self.queue, &texture.texture, &image.rgba);
+                self.textures.insert(texture_id.to_string(), texture);
             }
-            _ => {}
+        }
+        log::debug!("Texture {} uploaded ({}x{})", texture_id, image.width, image.height);
+        Ok(())
+    }
+
+    /// Whether a texture was created
+    pub fn has_texture(&self, texture_id: &str) -> bool {
+        self.textures.contains_key(texture_id)
+    }
+
+    /// Drop a texture; volumes showing it fall back to their asset's texture
+    /// or none
+    pub fn destroy_texture(&mut self, texture_id: &str) {
+        if self.textures.remove(texture_id).is_none() {
+            log::warn!("DestroyTexture for unknown texture {}", texture_id);
         }
     }
 
-    pub fn create_volume(&mut self, data: &CreateVolumeData, asset_manager: &AssetManager) {
+    /// Override parts of a volume's material. Volumes are drawn with a
+    /// single material, so `slot` is not used.
+    pub fn set_material(&mut self, data: &SetMaterialData) {
+        let Some(volume) = self.volumes.iter_mut().find(|v| v.id == data.volume_id) else {
+            log::warn!("SetMaterial for unknown volume {}", data.volume_id);
+            return;
+        };
+        if let Some(slot) = data.slot {
+            log::debug!("SetMaterial slot {} of {} applies to the whole volume", slot, data.volume_id);
+        }
+        if let Some(color) = data.material.color {
+            volume.color = color;
+        }
+        volume.material.apply(&data.material);
+    }
+
+    pub fn create_volume(&mut self, data: &CreateVolumeData) {
         // Determine mesh type and create appropriate volume
         let (mesh, color) = match &data.source {
+            fastn_protocol::VolumeSource::Primitive(fastn_protocol::Primitive::Text {
+                text,
+                font_size,
+                alignment,
+                max_width,
+                billboard,
+            }) => {
+                let layout = self.glyph_atlas.layout(text, *font_size, *alignment, *max_width);
+                self.upload_atlas();
+                let color = data.material
+                    .as_ref()
+                    .and_then(|m| m.color)
+                    .unwrap_or([1.0, 1.0, 1.0, 1.0]);
+                (VolumeMesh::Text(self.upload_text(&data.volume_id, &layout, *billboard)), color)
+            }
             fastn_protocol::VolumeSource::Primitive(p) => {
-                let size = match p {
-                    fastn_protocol::Primitive::Cube { size } => *size,
-                    fastn_protocol::Primitive::Box { width, .. } => *width,
-                    _ => 1.0,
-                };
                 let color = data.material
                     .as_ref()
                     .and_then(|m| m.color)
                     .unwrap_or([1.0, 1.0, 1.0, 1.0]);
-                (VolumeMesh::Primitive { size }, color)
+                match p {
+                    fastn_protocol::Primitive::Cube { size } => (VolumeMesh::Primitive { size: *size }, color),
+                    _ => (VolumeMesh::Custom(self.primitive_mesh(p)), color),
+                }
             }
             fastn_protocol::VolumeSource::Asset { asset_id, .. } => {
-                if let Some(loaded_mesh) = asset_manager.get_mesh(asset_id) {
-                    // Create GPU buffers from loaded mesh
-                    let vertices: Vec<Vertex> = loaded_mesh.vertices.iter()
-                        .zip(loaded_mesh.normals.iter())
-                        .map(|(pos, norm)| Vertex {
-                            position: *pos,
-                            normal: *norm,
-                        })
-                        .collect();
-
-                    let vertex_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some(&format!("Vertex Buffer {}", data.volume_id)),
-                        contents: bytemuck::cast_slice(&vertices),
-                        usage: wgpu::BufferUsages::VERTEX,
-                    });
-
-                    let index_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some(&format!("Index Buffer {}", data.volume_id)),
-                        contents: bytemuck::cast_slice(&loaded_mesh.indices),
-                        usage: wgpu::BufferUsages::INDEX,
-                    });
-
+                if let Some(gpu_mesh) = self.meshes.get(asset_id) {
                     // Use color from GLB material, or override from command
                     let color = data.material
                         .as_ref()
                         .and_then(|m| m.color)
-                        .unwrap_or(loaded_mesh.color);
-
-                    log::info!("Created custom mesh buffers for {} ({} vertices, {} indices)",
-                        data.volume_id, vertices.len(), loaded_mesh.indices.len());
-
-                    (VolumeMesh::Custom {
-                        vertex_buffer,
-                        index_buffer,
-                        num_indices: loaded_mesh.indices.len() as u32,
-                    }, color)
+                        .unwrap_or(gpu_mesh.color);
+                    (VolumeMesh::Custom(Arc::clone(gpu_mesh)), color)
                 } else {
                     log::warn!("Asset {} not found, using placeholder cube", asset_id);
                     let color = data.material
@@ -336,18 +939,305 @@ impl Renderer {
             }
         };
 
+        let skeleton = match &mesh {
+            VolumeMesh::Custom(gpu_mesh) => gpu_mesh.skin.as_ref().map(|skin| self.create_skeleton(&data.volume_id, skin)),
+            VolumeMesh::Primitive { .. } | VolumeMesh::Text(_) => None,
+        };
+
+        let mut material = VolumeMaterial::default();
+        if let Some(m) = &data.material {
+            material.apply(m);
+        }
+
+        let parent = data.parent_id.clone().filter(|parent_id| {
+            let known = self.volumes.iter().any(|v| &v.id == parent_id);
+            if !known {
+                log::warn!("Volume {} has unknown parent {}, placing it at the top level", data.volume_id, parent_id);
+            }
+            known
+        });
         self.volumes.push(Volume {
             id: data.volume_id.clone(),
+            parent,
             position: data.transform.position,
             rotation: data.transform.rotation,
             scale: data.transform.scale,
             color,
+            material,
             mesh,
+            animation: None,
+            skeleton,
         });
         log::info!("Volume created: {} with color {:?} (total: {})",
             data.volume_id, color, self.volumes.len());
     }
 
+    /// Vertex and index buffers of laid out text
+    fn upload_text(&self, volume_id: &str, layout: &TextMesh, billboard: bool) -> GpuText {
+        let vertices: Vec<Vertex> = layout.positions.iter()
+            .zip(layout.uvs.iter())
+            .map(|(position, uv)| Vertex {
+                position: *position,
+                normal: [0.0, 0.0, 1.0],
+                uv: *uv,
+            })
+            .collect();
+        // Buffers can't be empty; a degenerate triangle draws nothing
+        let (vertices, indices) = match vertices.is_empty() {
+            true => (vec![Vertex::zeroed()], vec![0; 3]),
+            false => (vertices, layout.indices.clone()),
+        };
+        let vertex_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("Text Vertex Buffer {}", volume_id)),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("Text Index Buffer {}", volume_id)),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let [width, height] = layout.size.map(|s| s / 2.0);
+        let corners = [[-width, -height, 0.0], [width, -height, 0.0], [width, height, 0.0], [-width, height, 0.0]];
+        GpuText {
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+            triangles: Triangles::new(&corners, &[0, 1, 2, 2, 3, 0]),
+            billboard,
+        }
+    }
+
+    /// Upload the glyph atlas if glyphs were added to it
+    fn upload_atlas(&mut self) {
+        if !self.glyph_atlas.take_changed() {
+            return;
+        }
+        let texture = self.atlas_texture.get_or_insert_with(|| {
+            create_texture(
+                &self.device,
+                &self.texture_bind_group_layout,
+                &self.sampler,
+                "Glyph Atlas",
+                ATLAS_SIZE,
+                ATLAS_SIZE,
+            )
+        });
+        write_texture(&self.queue, &texture.texture, &self.glyph_atlas.image().rgba);
+    }
+
+    /// A rest pose skeleton for a volume showing a rigged asset
+    fn create_skeleton(&self, volume_id: &str, skin: &GpuSkin) -> VolumeSkeleton {
+        let skeleton = Skeleton::new(Arc::clone(&skin.rig));
+        let joint_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("Joint Buffer {}", volume_id)),
+            contents: bytemuck::cast_slice(&skeleton.joint_matrices()),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        // Storage buffers can't be empty; a zero weight is never applied
+        let mut weights = skeleton.morph_weights();
+        if weights.is_empty() {
+            weights.push(0.0);
+        }
+        let weight_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("Morph Weight Buffer {}", volume_id)),
+            contents: bytemuck::cast_slice(&weights),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("Skin Bind Group {}", volume_id)),
+            layout: &self.skin_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: joint_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: weight_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: skin.morph_buffer.as_entire_binding() },
+            ],
+        });
+        VolumeSkeleton {
+            skeleton,
+            joint_buffer,
+            weight_buffer,
+            bind_group,
+        }
+    }
+
+    /// Move a volume, at once or animated. A new transform interrupts the
+    /// volume's animation in progress, which then never completes.
+    pub fn set_transform(&mut self, data: &SetTransformData) {
+        let Some(volume) = self.volumes.iter_mut().find(|v| v.id == data.volume_id) else {
+            log::warn!("SetTransform for unknown volume {}", data.volume_id);
+            return;
+        };
+        volume.animation = data.animate.as_ref().map(|animate| TransformAnimation {
+            from: Transform {
+                position: volume.position,
+                rotation: volume.rotation,
+                scale: volume.scale,
+            },
+            to: data.transform.clone(),
+            duration: animate.duration_ms as f32 / 1000.0,
+            elapsed: 0.0,
+            easing: animate.easing,
+            animation_id: animate.animation_id.clone(),
+        });
+        if volume.animation.is_none() {
+            volume.position = data.transform.position;
+            volume.rotation = data.transform.rotation;
+            volume.scale = data.transform.scale;
+        }
+    }
+
+    /// Move a volume under another, keeping its transform (which is now
+    /// relative to the new parent). Parents that would form a cycle are
+    /// refused.
+    pub fn set_parent(&mut self, volume_id: &str, parent_id: Option<&str>) {
+        if !self.volumes.iter().any(|v| v.id == volume_id) {
+            log::warn!("SetParent for unknown volume {}", volume_id);
+            return;
+        }
+        if let Some(parent_id) = parent_id {
+            if !self.volumes.iter().any(|v| v.id == parent_id) {
+                log::warn!("SetParent of {} to unknown volume {}", volume_id, parent_id);
+                return;
+            }
+            if self.ancestors(parent_id).any(|ancestor| ancestor == volume_id) {
+                log::warn!("SetParent of {} to {} would make it its own ancestor", volume_id, parent_id);
+                return;
+            }
+        }
+        if let Some(volume) = self.volumes.iter_mut().find(|v| v.id == volume_id) {
+            volume.parent = parent_id.map(str::to_string);
+        }
+    }
+
+    /// Remove a volume and its descendants
+    pub fn destroy_volume(&mut self, volume_id: &str) {
+        let before = self.volumes.len();
+        let doomed: Vec<String> = self.volumes.iter()
+            .filter(|v| self.ancestors(&v.id).any(|ancestor| ancestor == volume_id))
+            .map(|v| v.id.clone())
+            .collect();
+        self.volumes.retain(|v| !doomed.contains(&v.id));
+        match self.volumes.len() < before {
+            true => log::info!("Volume destroyed: {} (total: {})", volume_id, self.volumes.len()),
+            false => log::warn!("DestroyVolume for unknown volume {}", volume_id),
+        }
+    }
+
+    /// The volume and its ancestors, nearest first. Unknown parents end the
+    /// chain, and the length bound guards against cycles.
+    fn ancestors<'a>(&'a self, volume_id: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        std::iter::successors(Some(volume_id), |id| {
+            self.volumes.iter().find(|v| v.id == *id)?.parent.as_deref()
+        })
+        .take(self.volumes.len() + 1)
+    }
+
+    /// Where a volume's parent is in the scene: the transforms of its
+    /// ancestors composed, identity at the top level
+    fn parent_matrix(&self, volume: &Volume) -> Mat4 {
+        self.ancestors(&volume.id)
+            .skip(1)
+            .filter_map(|id| self.volumes.iter().find(|v| v.id == id))
+            .fold(Mat4::IDENTITY, |matrix, ancestor| ancestor.local_matrix() * matrix)
+    }
+
+    /// `parent_matrix` of every volume by index, each ancestor's transform
+    /// composed once rather than once per descendant
+    fn parent_matrices(&self) -> Vec<Mat4> {
+        let indices: HashMap<&str, usize> = self.volumes.iter().enumerate().map(|(i, v)| (v.id.as_str(), i)).collect();
+        let parent = |index: usize| self.volumes[index].parent.as_deref().and_then(|id| indices.get(id).copied());
+
+        // Each volume's own frame: its transform under its parents'
+        let mut frames: Vec<Option<Mat4>> = vec![None; self.volumes.len()];
+        for index in 0..self.volumes.len() {
+            // Walk up to the first ancestor placed already; the length
+            // bound guards against cycles
+            let mut chain = vec![];
+            let mut next = Some(index);
+            while let Some(i) = next
+                && frames[i].is_none()
+                && chain.len() <= self.volumes.len()
+            {
+                chain.push(i);
+                next = parent(i);
+            }
+            let mut frame = next.and_then(|i| frames[i]).unwrap_or(Mat4::IDENTITY);
+            for &i in chain.iter().rev() {
+                frame *= self.volumes[i].local_matrix();
+                frames[i] = Some(frame);
+            }
+        }
+        (0..self.volumes.len())
+            .map(|index| parent(index).and_then(|p| frames[p]).unwrap_or(Mat4::IDENTITY))
+            .collect()
+    }
+
+    /// Advance transform and model animations by `dt` seconds. Returns the
+    /// volume and animation IDs of the named animations that ended.
+    pub fn update_animations(&mut self, dt: f32) -> Vec<(String, String)> {
+        let mut completed = vec![];
+        for volume in &mut self.volumes {
+            if let Some(skeleton) = &mut volume.skeleton {
+                completed.extend(skeleton.skeleton.advance(dt).into_iter().map(|id| (volume.id.clone(), id)));
+            }
+            let Some(animation) = &mut volume.animation else {
+                continue;
+            };
+            let (transform, done) = animation.advance(dt);
+            volume.position = transform.position;
+            volume.rotation = transform.rotation;
+            volume.scale = transform.scale;
+            if done && let Some(animation_id) = volume.animation.take().and_then(|a| a.animation_id) {
+                completed.push((volume.id.clone(), animation_id));
+            }
+        }
+        completed
+    }
+
+    /// The skeleton of a volume showing a rigged asset
+    fn skeleton_mut(&mut self, volume_id: &str) -> Result<&mut Skeleton, String> {
+        let volume = self.volumes.iter_mut()
+            .find(|v| v.id == volume_id)
+            .ok_or_else(|| format!("unknown volume {}", volume_id))?;
+        volume.skeleton.as_mut()
+            .map(|s| &mut s.skeleton)
+            .ok_or_else(|| format!("volume {} has no skeleton, morph targets or animations", volume_id))
+    }
+
+    /// Play one of the model's animations on a volume; `update_animations`
+    /// reports it when a `LoopMode::Once` animation ends
+    pub fn play_animation(&mut self, data: &PlayAnimationData) {
+        if let Err(e) = self.skeleton_mut(&data.volume_id).and_then(|s| s.play(data)) {
+            log::warn!("Cannot play {} on {}: {}", data.animation_name, data.volume_id, e);
+        }
+    }
+
+    /// Stop one of a volume's animations, or all of them if `animation_id`
+    /// is None
+    pub fn stop_animation(&mut self, volume_id: &str, animation_id: Option<&str>) {
+        if let Err(e) = self.skeleton_mut(volume_id).map(|s| s.stop(animation_id)) {
+            log::warn!("Cannot stop animations: {}", e);
+        }
+    }
+
+    /// Pose a bone of a volume's skeleton over its animations
+    pub fn set_bone_transform(&mut self, volume_id: &str, bone_name: &str, transform: &BoneTransform, weight: f32) {
+        if let Err(e) = self.skeleton_mut(volume_id).and_then(|s| s.set_bone(bone_name, transform, weight)) {
+            log::warn!("Cannot move bone {} of {}: {}", bone_name, volume_id, e);
+        }
+    }
+
+    /// Set a blend shape (morph target) weight of a volume
+    pub fn set_blend_shape(&mut self, data: &SetBlendShapeData) {
+        let result = self.skeleton_mut(&data.volume_id)
+            .and_then(|s| s.set_blend_shape(&data.blend_shape_name, data.weight));
+        if let Err(e) = result {
+            log::warn!("Cannot set blend shape {} of {}: {}", data.blend_shape_name, data.volume_id, e);
+        }
+    }
+
     /// Set camera from CameraData (position + target)
     /// Computes yaw and pitch from the direction vector
     pub fn set_camera(&mut self, camera: &CameraData) {
@@ -362,14 +1252,8 @@ impl Renderer {
         self.camera_pitch = direction.y.asin();
     }
 
-    pub fn render(&mut self) {
-        let output = match self.surface.get_current_texture() {
-            Ok(t) => t,
-            Err(_) => return,
-        };
-
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
-
+    /// Projection * view matrix of the camera
+    fn view_projection(&self) -> Mat4 {
         let aspect = self.config.width as f32 / self.config.height as f32;
         let proj = Mat4::perspective_rh(std::f32::consts::FRAC_PI_4, aspect, 0.1, 100.0);
 
@@ -386,6 +1270,279 @@ impl Renderer {
             target,
             Vec3::Y,
         );
+        proj * view_mat
+    }
+
+    /// Cast a ray against the volumes and return the nearest hit.
+    /// `scale_factor` converts screen points from logical to physical pixels.
+    pub fn hit_test(&self, source: &HitTestSource, scale_factor: f32) -> Option<Hit> {
+        let ray = match source {
+            HitTestSource::Ray { origin, direction } => Ray::new(Vec3::from_array(*origin), Vec3::from_array(*direction))?,
+            HitTestSource::Screen { x, y } => self.screen_ray(*x, *y, scale_factor)?,
+        };
+
+        self.volumes
+            .iter()
+            .filter_map(|volume| {
+                let model = volume.model_matrix(self.parent_matrix(volume), self.camera_position);
+                let hit = match &volume.mesh {
+                    VolumeMesh::Primitive { .. } => ray.cast_unit_cube(model),
+                    VolumeMesh::Custom(gpu_mesh) => ray.cast_triangles(model, &gpu_mesh.triangles),
+                    VolumeMesh::Text(text) => ray.cast_triangles(model, &text.triangles),
+                }?;
+                Some(Hit {
+                    volume_id: volume.id.clone(),
+                    point: hit.point.to_array(),
+                    normal: hit.normal.to_array(),
+                    distance: hit.distance,
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    /// Where the ray from the camera through a point on screen (logical
+    /// pixels) crosses a volume's local XY plane, in the volume's model
+    /// space
+    pub fn screen_to_volume_plane(&self, volume_id: &str, x: f32, y: f32, scale_factor: f32) -> Option<Vec3> {
+        let volume = self.volumes.iter().find(|v| v.id == volume_id)?;
+        let model = volume.model_matrix(self.parent_matrix(volume), self.camera_position);
+        self.screen_ray(x, y, scale_factor)?.cast_xy_plane(model)
+    }
+
+    /// The ray from the camera through a point on screen, in logical pixels
+    fn screen_ray(&self, x: f32, y: f32, scale_factor: f32) -> Option<Ray> {
+        let ndc_x = 2.0 * x * scale_factor / self.config.width as f32 - 1.0;
+        let ndc_y = 1.0 - 2.0 * y * scale_factor / self.config.height as f32;
+        Ray::unproject(self.view_projection(), ndc_x, ndc_y)
+    }
+
+    pub fn render(&mut self) {
+        let Some(surface) = &self.surface else {
+            return;
+        };
+        let output = match surface.get_current_texture() {
+            Ok(t) => t,
+            Err(_) => return,
+        };
+
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth = self.depth_texture.clone();
+        let encoder = self.draw(&view, &depth, self.view_projection(), self.camera_position);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+    }
+
+    /// Render the scene as seen from `eye` through `view_projection` into
+    /// `target` (an XR swapchain image), which must have the renderer's
+    /// format. Each call is submitted on its own, as the transforms written
+    /// for it replace the previous view's.
+    #[cfg(feature = "xr")]
+    pub(crate) fn render_view(&mut self, target: &wgpu::Texture, view_projection: Mat4, eye: Vec3) {
+        let size = target.size();
+        let depth = match &self.view_depth {
+            Some((depth_size, depth)) if *depth_size == size => depth.clone(),
+            _ => {
+                let config = surface_config(self.config.format, size.width, size.height);
+                let depth = create_depth_texture(&self.device, &config);
+                self.view_depth = Some((size, depth.clone()));
+                depth
+            }
+        };
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let encoder = self.draw(&view, &depth, view_projection, eye);
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Size of what is drawn, in physical pixels
+    pub fn size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    /// Render a frame and read it back as tightly packed RGBA rows, `size`
+    /// big. A window's frame is drawn again, into a texture of its own.
+    pub fn capture(&mut self) -> Option<Vec<u8>> {
+        let texture = self
+            .offscreen
+            .get_or_insert_with(|| create_offscreen_texture(&self.device, &self.config))
+            .clone();
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth = self.depth_texture.clone();
+        let mut encoder = self.draw(&view, &depth, self.view_projection(), self.camera_position);
+
+        // Buffer rows must be aligned, the padding is dropped below
+        let (width, height) = (self.config.width, self.config.height);
+        let row_bytes = width * 4;
+        let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: (padded_row_bytes * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        if let Err(e) = self.device.poll(wgpu::PollType::wait_indefinitely()) {
+            log::error!("Failed to read back frame: {}", e);
+            return None;
+        }
+        let data = slice.get_mapped_range();
+        let mut pixels: Vec<u8> = data
+            .chunks(padded_row_bytes as usize)
+            .flat_map(|row| &row[..row_bytes as usize])
+            .copied()
+            .collect();
+        drop(data);
+        buffer.unmap();
+
+        // Window surfaces are often BGRA
+        if matches!(self.config.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Some(pixels)
+    }
+
+    /// What the last frame took, and which volumes weren't batched
+    pub fn batch_report(&self) -> &BatchReport {
+        &self.batch_report
+    }
+
+    /// Make room in the instance and material buffers for `count` volumes
+    fn reserve_uniforms(&mut self, count: usize) {
+        if count <= self.uniform_capacity {
+            return;
+        }
+        self.uniform_capacity = count.next_power_of_two();
+        (self.instance_buffer, self.material_buffer, self.uniform_bind_group) = create_uniforms(
+            &self.device,
+            &self.uniform_bind_group_layout,
+            self.uniform_capacity,
+            self.uniform_stride,
+        );
+    }
+
+    /// What a volume must share with others to be drawn with them
+    fn batch_key(&self, index: usize, volume: &Volume) -> BatchKey {
+        let pass = match (&volume.skeleton, &volume.mesh) {
+            (Some(_), _) => Pass::Skinned,
+            (None, VolumeMesh::Text(_)) => Pass::Text,
+            (None, _) => Pass::Opaque,
+        };
+        let mesh = match &volume.mesh {
+            VolumeMesh::Primitive { .. } => MeshKey::Cube,
+            VolumeMesh::Custom(gpu_mesh) => MeshKey::Asset(Arc::as_ptr(gpu_mesh) as usize),
+            VolumeMesh::Text(_) => MeshKey::Own(index),
+        };
+        let material = volume.material_uniforms();
+        let bits = |values: &[f32]| values.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+        let mut material_bits = [0; 9];
+        material_bits.copy_from_slice(
+            &[bits(&material.emissive[..3]), bits(&material.uv_rect), bits(&material.params[..2])].concat(),
+        );
+        BatchKey {
+            pass,
+            texture: self.volume_texture(volume) as *const GpuTexture as usize,
+            material: material_bits,
+            mesh,
+            skeleton: volume.skeleton.is_some().then_some(index),
+        }
+    }
+
+    /// Whether a volume placed by `model` is at least partly inside
+    /// `frustum`; rigged volumes count as always inside
+    fn in_view(&self, volume: &Volume, model: Mat4, frustum: &Frustum) -> bool {
+        let bounds = match &volume.mesh {
+            _ if volume.skeleton.is_some() => return true,
+            VolumeMesh::Primitive { .. } => Aabb::UNIT_CUBE,
+            VolumeMesh::Custom(gpu_mesh) => gpu_mesh.triangles.bounds(),
+            VolumeMesh::Text(text) => text.triangles.bounds(),
+        };
+        frustum.intersects(model, &bounds)
+    }
+
+    /// Record the frame's render pass into `view`, seen from `eye`
+    fn draw(
+        &mut self,
+        view: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        view_proj: Mat4,
+        eye: Vec3,
+    ) -> wgpu::CommandEncoder {
+        // Only volumes in view are drawn
+        let parents = self.parent_matrices();
+        let models: Vec<Mat4> = self.volumes.iter()
+            .zip(parents)
+            .map(|(volume, parent)| volume.model_matrix(parent, eye))
+            .collect();
+        let frustum = Frustum::new(view_proj);
+        let drawn: Vec<usize> = (0..self.volumes.len())
+            .filter(|&index| self.in_view(&self.volumes[index], models[index], &frustum))
+            .collect();
+        let keys: Vec<BatchKey> = drawn.iter().map(|&index| self.batch_key(index, &self.volumes[index])).collect();
+        let depths: Vec<f32> = drawn.iter().map(|&index| models[index].w_axis.truncate().distance(eye)).collect();
+        let mut batches = batching::batch(&keys, &depths);
+        for batch in &mut batches {
+            for index in &mut batch.volumes {
+                *index = drawn[*index];
+            }
+        }
+
+        // Upload every drawn volume's transform and color, in drawing
+        // order, and each distinct material once
+        self.reserve_uniforms(drawn.len());
+        let stride = self.uniform_stride as usize;
+        let mut instances = Vec::with_capacity(drawn.len());
+        let mut material_data = vec![];
+        let mut material_slots = HashMap::new();
+        let mut batch_materials = Vec::with_capacity(batches.len());
+        for batch in &batches {
+            let slot = *material_slots.entry(batch.key.material_key()).or_insert_with(|| {
+                let uniforms = self.volumes[batch.volumes[0]].material_uniforms();
+                let mut slot = vec![0u8; stride];
+                slot[..std::mem::size_of::<MaterialUniforms>()].copy_from_slice(bytemuck::bytes_of(&uniforms));
+                material_data.extend(slot);
+                material_data.len() / stride - 1
+            });
+            batch_materials.push((slot * stride) as wgpu::DynamicOffset);
+            for &index in &batch.volumes {
+                let model = models[index];
+                let camera = model.inverse().transform_point3(eye);
+                instances.push(Instance {
+                    mvp: (view_proj * model).to_cols_array_2d(),
+                    camera: [camera.x, camera.y, camera.z, 1.0],
+                    color: self.volumes[index].color,
+                });
+            }
+        }
+        if !instances.is_empty() {
+            self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+            self.queue.write_buffer(&self.material_buffer, 0, &material_data);
+        }
+        // And every skinned volume's pose
+        for skeleton in self.volumes.iter().filter_map(|v| v.skeleton.as_ref()) {
+            let joints = skeleton.skeleton.joint_matrices();
+            self.queue.write_buffer(&skeleton.joint_buffer, 0, bytemuck::cast_slice(&joints));
+            let weights = skeleton.skeleton.morph_weights();
+            if !weights.is_empty() {
+                self.queue.write_buffer(&skeleton.weight_buffer, 0, bytemuck::cast_slice(&weights));
+            }
+        }
 
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
@@ -395,7 +1552,7 @@ impl Renderer {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -409,7 +1566,7 @@ impl Renderer {
                     depth_slice: None,
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture,
+                    view: depth,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Store,
@@ -420,54 +1577,373 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-
-            // Render each volume
-            for volume in &self.volumes {
-                // Compute scale based on mesh type
-                let scale = match &volume.mesh {
-                    VolumeMesh::Primitive { size } => Vec3::from_array(volume.scale) * *size,
-                    VolumeMesh::Custom { .. } => Vec3::from_array(volume.scale),
-                };
-
-                let model = Mat4::from_scale_rotation_translation(
-                    scale,
-                    glam::Quat::from_array(volume.rotation),
-                    Vec3::from_array(volume.position),
-                );
-                let mvp = proj * view_mat * model;
-
-                let uniforms = Uniforms {
-                    mvp: mvp.to_cols_array_2d(),
-                    color: volume.color,
-                };
-
-                self.queue.write_buffer(
-                    &self.uniform_buffer,
-                    0,
-                    bytemuck::cast_slice(&[uniforms]),
-                );
+            // One instanced draw per batch, switching only what changes
+            let ids: Vec<&str> = self.volumes.iter().map(|v| v.id.as_str()).collect();
+            let mut report = BatchReport::new(&batches, &ids);
+            report.culled = self.volumes.len() - drawn.len();
+            let mut first_instance = 0;
+            let (mut pass, mut texture, mut material, mut mesh) = (None, None, None, None);
+            for (batch, offset) in batches.iter().zip(batch_materials) {
+                let volume = &self.volumes[batch.volumes[0]];
+                if pass != Some(batch.key.pass) {
+                    pass = Some(batch.key.pass);
+                    report.pipeline_switches += 1;
+                    render_pass.set_pipeline(match batch.key.pass {
+                        Pass::Opaque => &self.render_pipeline,
+                        Pass::Skinned => &self.skinned_pipeline,
+                        Pass::Text => &self.text_pipeline,
+                    });
+                }
+                if let Some(skeleton) = &volume.skeleton {
+                    render_pass.set_bind_group(2, &skeleton.bind_group, &[]);
+                    report.bind_group_switches += 1;
+                }
+                if material != Some(offset) {
+                    material = Some(offset);
+                    render_pass.set_bind_group(0, &self.uniform_bind_group, &[offset]);
+                    report.bind_group_switches += 1;
+                }
+                if texture != Some(batch.key.texture) {
+                    texture = Some(batch.key.texture);
+                    render_pass.set_bind_group(1, &self.volume_texture(volume).bind_group, &[]);
+                    report.bind_group_switches += 1;
+                }
 
-                // Set buffers and draw based on mesh type
-                match &volume.mesh {
+                let (vertex_buffer, index_buffer, format, num_indices) = match &volume.mesh {
                     VolumeMesh::Primitive { .. } => {
-                        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-                        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+                        (&self.vertex_buffer, &self.index_buffer, wgpu::IndexFormat::Uint16, self.num_indices)
                     }
-                    VolumeMesh::Custom { vertex_buffer, index_buffer, num_indices } => {
-                        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-                        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                        render_pass.draw_indexed(0..*num_indices, 0, 0..1);
+                    VolumeMesh::Custom(gpu_mesh) => (
+                        &gpu_mesh.vertex_buffer,
+                        &gpu_mesh.index_buffer,
+                        wgpu::IndexFormat::Uint32,
+                        gpu_mesh.num_indices,
+                    ),
+                    VolumeMesh::Text(text) => {
+                        (&text.vertex_buffer, &text.index_buffer, wgpu::IndexFormat::Uint32, text.num_indices)
                     }
+                };
+                if mesh != Some(batch.key.mesh) {
+                    mesh = Some(batch.key.mesh);
+                    render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(index_buffer.slice(..), format);
+                    report.buffer_switches += 1;
                 }
+                let instances = first_instance..first_instance + batch.volumes.len() as u32;
+                render_pass.draw_indexed(0..num_indices, 0, instances.clone());
+                first_instance = instances.end;
+            }
+            self.batch_report = report;
+
+            if self.fade_color[3] > 0.0 {
+                render_pass.set_pipeline(&self.fade_pipeline);
+                render_pass.set_bind_group(0, &self.fade_bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
             }
         }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        encoder
     }
+
+    /// The texture a volume shows: its material's, its asset's, or white.
+    /// Textures not created (yet) show as white. Text always shows the
+    /// glyph atlas.
+    fn volume_texture<'a>(&'a self, volume: &'a Volume) -> &'a GpuTexture {
+        let asset_texture = match &volume.mesh {
+            VolumeMesh::Custom(gpu_mesh) => gpu_mesh.texture.as_ref(),
+            VolumeMesh::Text(_) => return self.atlas_texture.as_ref().unwrap_or(&self.default_texture),
+            VolumeMesh::Primitive { .. } => None,
+        };
+        match &volume.material.texture_id {
+            Some(texture_id) => self.textures.get(texture_id),
+            None => asset_texture,
+        }
+        .unwrap_or(&self.default_texture)
+    }
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            required_features: wgpu::Features::empty(),
+            required_limits: wgpu::Limits::default(),
+            label: None,
+            memory_hints: wgpu::MemoryHints::default(),
+            trace: wgpu::Trace::Off,
+            experimental_features: Default::default(),
+        })
+        .await
+}
+
+/// Shader entry points and the state that differs between pipelines
+struct PipelineStages<'a> {
+    vertex: &'a str,
+    fragment: &'a str,
+    blend: wgpu::BlendState,
+    cull_mode: Option<wgpu::Face>,
+}
+
+impl<'a> PipelineStages<'a> {
+    /// Lit, opaque and back face culled
+    fn opaque(vertex: &'a str) -> Self {
+        Self {
+            vertex,
+            fragment: "fs_main",
+            blend: wgpu::BlendState::REPLACE,
+            cull_mode: Some(wgpu::Face::Back),
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    stages: PipelineStages,
+    vertex_layout: wgpu::VertexBufferLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some(stages.vertex),
+            buffers: &[vertex_layout],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(stages.fragment),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(stages.blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: stages.cull_mode,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Pipeline of the fade overlay, with the uniform buffer of its color and
+/// their bind group. It draws over everything and leaves depth alone.
+fn create_fade_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+) -> (wgpu::RenderPipeline, wgpu::Buffer, wgpu::BindGroup) {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Fade Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("fade.wgsl").into()),
+    });
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Fade Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    });
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Fade Buffer"),
+        contents: bytemuck::cast_slice(&[0.0f32; 4]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Fade Bind Group"),
+        layout: &bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Fade Pipeline Layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Fade Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_fade"),
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_fade"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+    (pipeline, buffer, bind_group)
+}
+
+fn surface_config(format: wgpu::TextureFormat, width: u32, height: u32) -> wgpu::SurfaceConfiguration {
+    wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width,
+        height,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+        desired_maximum_frame_latency: 2,
+    }
+}
+
+fn create_offscreen_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Instance and material buffers with room for `capacity` volumes, and
+/// their bind group
+fn create_uniforms(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    capacity: usize,
+    material_stride: u64,
+) -> (wgpu::Buffer, wgpu::Buffer, wgpu::BindGroup) {
+    let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Instance Buffer"),
+        size: (std::mem::size_of::<Instance>() * capacity) as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let material_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Material Buffer"),
+        size: material_stride * capacity as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Uniform Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: instance_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &material_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<MaterialUniforms>() as u64),
+                }),
+            },
+        ],
+    });
+    (instance_buffer, material_buffer, bind_group)
+}
+
+/// An sRGB texture and a bind group sampling it; the pixels are written
+/// separately
+fn create_texture(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    label: &str,
+    width: u32,
+    height: u32,
+) -> GpuTexture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&view) },
+            wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(sampler) },
+        ],
+    });
+    GpuTexture { texture, bind_group }
+}
+
+/// Replace all of a texture's pixels with tightly packed RGBA rows
+fn write_texture(queue: &wgpu::Queue, texture: &wgpu::Texture, rgba: &[u8]) {
+    let size = texture.size();
+    queue.write_texture(
+        texture.as_image_copy(),
+        rgba,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(size.width * 4),
+            rows_per_image: Some(size.height),
+        },
+        size,
+    );
 }
 
 fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> wgpu::TextureView {
@@ -488,38 +1964,56 @@ fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfigurati
     texture.create_view(&wgpu::TextureViewDescriptor::default())
 }
 
+/// A vertex of the unit cube. Each face shows the whole texture, upright
+/// when seen from outside (top and bottom as if tipped towards the viewer).
+fn cube_vertex(position: [f32; 3], normal: [f32; 3]) -> Vertex {
+    let n = Vec3::from_array(normal);
+    let up = match normal[1] {
+        y if y > 0.0 => Vec3::NEG_Z,
+        y if y < 0.0 => Vec3::Z,
+        _ => Vec3::Y,
+    };
+    let right = up.cross(n);
+    let p = Vec3::from_array(position);
+    Vertex {
+        position,
+        normal,
+        uv: [p.dot(right) + 0.5, 0.5 - p.dot(up)],
+    }
+}
+
 fn create_cube_vertices() -> Vec<Vertex> {
     vec![
         // Front face (+Z)
-        Vertex { position: [-0.5, -0.5,  0.5], normal: [0.0, 0.0, 1.0] },
-        Vertex { position: [ 0.5, -0.5,  0.5], normal: [0.0, 0.0, 1.0] },
-        Vertex { position: [ 0.5,  0.5,  0.5], normal: [0.0, 0.0, 1.0] },
-        Vertex { position: [-0.5,  0.5,  0.5], normal: [0.0, 0.0, 1.0] },
+        cube_vertex([-0.5, -0.5,  0.5], [0.0, 0.0, 1.0]),
+        cube_vertex([ 0.5, -0.5,  0.5], [0.0, 0.0, 1.0]),
+        cube_vertex([ 0.5,  0.5,  0.5], [0.0, 0.0, 1.0]),
+        cube_vertex([-0.5,  0.5,  0.5], [0.0, 0.0, 1.0]),
         // Back face (-Z)
-        Vertex { position: [-0.5, -0.5, -0.5], normal: [0.0, 0.0, -1.0] },
-        Vertex { position: [-0.5,  0.5, -0.5], normal: [0.0, 0.0, -1.0] },
-        Vertex { position: [ 0.5,  0.5, -0.5], normal: [0.0, 0.0, -1.0] },
-        Vertex { position: [ 0.5, -0.5, -0.5], normal: [0.0, 0.0, -1.0] },
+        cube_vertex([-0.5, -0.5, -0.5], [0.0, 0.0, -1.0]),
+        cube_vertex([-0.5,  0.5, -0.5], [0.0, 0.0, -1.0]),
+        cube_vertex([ 0.5,  0.5, -0.5], [0.0, 0.0, -1.0]),
+        cube_vertex([ 0.5, -0.5, -0.5], [0.0, 0.0, -1.0]),
         // Top face (+Y)
-        Vertex { position: [-0.5,  0.5, -0.5], normal: [0.0, 1.0, 0.0] },
-        Vertex { position: [-0.5,  0.5,  0.5], normal: [0.0, 1.0, 0.0] },
-        Vertex { position: [ 0.5,  0.5,  0.5], normal: [0.0, 1.0, 0.0] },
-        Vertex { position: [ 0.5,  0.5, -0.5], normal: [0.0, 1.0, 0.0] },
+        cube_vertex([-0.5,  0.5, -0.5], [0.0, 1.0, 0.0]),
+        cube_vertex([-0.5,  0.5,  0.5], [0.0, 1.0, 0.0]),
+        cube_vertex([ 0.5,  0.5,  0.5], [0.0, 1.0, 0.0]),
+        cube_vertex([ 0.5,  0.5, -0.5], [0.0, 1.0, 0.0]),
         // Bottom face (-Y)
-        Vertex { position: [-0.5, -0.5, -0.5], normal: [0.0, -1.0, 0.0] },
-        Vertex { position: [ 0.5, -0.5, -0.5], normal: [0.0, -1.0, 0.0] },
-        Vertex { position: [ 0.5, -0.5,  0.5], normal: [0.0, -1.0, 0.0] },
-        Vertex { position: [-0.5, -0.5,  0.5], normal: [0.0, -1.0, 0.0] },
+        cube_vertex([-0.5, -0.5, -0.5], [0.0, -1.0, 0.0]),
+        cube_vertex([ 0.5, -0.5, -0.5], [0.0, -1.0, 0.0]),
+        cube_vertex([ 0.5, -0.5,  0.5], [0.0, -1.0, 0.0]),
+        cube_vertex([-0.5, -0.5,  0.5], [0.0, -1.0, 0.0]),
         // Right face (+X)
-        Vertex { position: [ 0.5, -0.5, -0.5], normal: [1.0, 0.0, 0.0] },
-        Vertex { position: [ 0.5,  0.5, -0.5], normal: [1.0, 0.0, 0.0] },
-        Vertex { position: [ 0.5,  0.5,  0.5], normal: [1.0, 0.0, 0.0] },
-        Vertex { position: [ 0.5, -0.5,  0.5], normal: [1.0, 0.0, 0.0] },
+        cube_vertex([ 0.5, -0.5, -0.5], [1.0, 0.0, 0.0]),
+        cube_vertex([ 0.5,  0.5, -0.5], [1.0, 0.0, 0.0]),
+        cube_vertex([ 0.5,  0.5,  0.5], [1.0, 0.0, 0.0]),
+        cube_vertex([ 0.5, -0.5,  0.5], [1.0, 0.0, 0.0]),
         // Left face (-X)
-        Vertex { position: [-0.5, -0.5, -0.5], normal: [-1.0, 0.0, 0.0] },
-        Vertex { position: [-0.5, -0.5,  0.5], normal: [-1.0, 0.0, 0.0] },
-        Vertex { position: [-0.5,  0.5,  0.5], normal: [-1.0, 0.0, 0.0] },
-        Vertex { position: [-0.5,  0.5, -0.5], normal: [-1.0, 0.0, 0.0] },
+        cube_vertex([-0.5, -0.5, -0.5], [-1.0, 0.0, 0.0]),
+        cube_vertex([-0.5, -0.5,  0.5], [-1.0, 0.0, 0.0]),
+        cube_vertex([-0.5,  0.5,  0.5], [-1.0, 0.0, 0.0]),
+        cube_vertex([-0.5,  0.5, -0.5], [-1.0, 0.0, 0.0]),
     ]
 }
 
@@ -0,0 +1,103 @@
+//! Replaying recorded sessions (`--replay`)
+//!
+//! A recording (`--record`) holds every event the core was sent and the
+//! commands it answered each one with. Replaying starts the app afresh and
+//! sends it the recorded events instead of the live ones, a recorded frame
+//! per drawn frame, so the core sees exactly what it saw then, whatever the
+//! input devices and timing are now. The shell still executes and draws the
+//! commands, which reproduces what was on screen.
+//!
+//! Each replayed event's commands are compared with the recorded ones, and
+//! the first difference is logged: from there on the app isn't doing what
+//! it did, e.g. because it reads the clock or a random number. Once the
+//! recording ends, live input takes over.
+
+use fastn_protocol::{Command, Event, LifecycleEvent, ScriptAction, ScriptStep};
+use std::collections::VecDeque;
+use std::path::Path;
+
+pub struct Replay {
+    /// Recorded events and the commands the core answered them with
+    steps: VecDeque<(Event, Vec<Command>)>,
+    /// Events replayed so far
+    replayed: usize,
+    /// Whether a difference from the recording was logged
+    diverged: bool,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read recording {}: {}", path.display(), e))?;
+        let mut steps: VecDeque<(Event, Vec<Command>)> = VecDeque::new();
+        for (index, line) in text.lines().enumerate() {
+            let step = ScriptStep::parse_line(line)
+                .map_err(|e| format!("Invalid recording {}: line {}: {}", path.display(), index + 1, e))?;
+            match step.map(|step| step.action) {
+                Some(ScriptAction::Event { event }) => steps.push_back((event, vec![])),
+                // Commands before the first event are the app's initial ones
+                Some(ScriptAction::Command { command }) => {
+                    if let Some((_, commands)) = steps.back_mut() {
+                        commands.push(*command);
+                    }
+                }
+                Some(_) => {
+                    return Err(format!(
+                        "{} line {} is a scripted step; replay takes recordings made with --record",
+                        path.display(),
+                        index + 1
+                    ));
+                }
+                None => {}
+            }
+        }
+        log::info!("Replaying {} recorded events from {}", steps.len(), path.display());
+        Ok(Self {
+            steps,
+            replayed: 0,
+            diverged: false,
+        })
+    }
+
+    /// The recorded events up to and including the next frame, with their
+    /// commands
+    pub fn next_frame(&mut self) -> Vec<(Event, Vec<Command>)> {
+        let end = self
+            .steps
+            .iter()
+            .position(|(event, _)| matches!(event, Event::Lifecycle(LifecycleEvent::Frame(_))))
+            .map_or(self.steps.len(), |index| index + 1);
+        self.steps.drain(..end).collect()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Compare the commands the core answered a replayed event with to the
+    /// recorded ones, logging the first difference of the replay
+    pub fn check(&mut self, event: &Event, recorded: &[Command], commands: &[Command]) {
+        self.replayed += 1;
+        if self.diverged {
+            return;
+        }
+        let json = |commands: &[Command]| -> Vec<String> {
+            commands.iter().map(|c| serde_json::to_string(c).unwrap_or_default()).collect()
+        };
+        let (recorded, commands) = (json(recorded), json(commands));
+        let difference = match recorded.iter().zip(&commands).position(|(a, b)| a != b) {
+            Some(index) => format!("command {} was {}, now {}", index + 1, recorded[index], commands[index]),
+            None if recorded.len() != commands.len() => {
+                format!("it answered with {} commands, now {}", recorded.len(), commands.len())
+            }
+            None => return,
+        };
+        self.diverged = true;
+        log::warn!(
+            "Replay diverged from the recording at event {} ({:?}): {}",
+            self.replayed,
+            event,
+            difference
+        );
+    }
+}
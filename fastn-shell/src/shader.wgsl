@@ -1,38 +1,153 @@
 // Basic 3D shader for fastn-shell
 
-struct Uniforms {
+// One per volume; a batch's volumes are consecutive instances
+struct Instance {
     mvp: mat4x4<f32>,
+    // Camera position in model space
+    camera: vec4<f32>,
     color: vec4<f32>,
 };
 
+// Shared by the volumes of a batch
+struct Material {
+    emissive: vec4<f32>,
+    // Offset (xy) and size (zw) of the texture region shown
+    uv_rect: vec4<f32>,
+    // Metallic (x) and roughness (y)
+    params: vec4<f32>,
+};
+
 @group(0) @binding(0)
-var<uniform> uniforms: Uniforms;
+var<storage, read> instances: array<Instance>;
+@group(0) @binding(1)
+var<uniform> material: Material;
+
+// The volume's texture, white if it has none
+@group(1) @binding(0)
+var base_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var base_sampler: sampler;
 
 struct VertexInput {
+    @builtin(instance_index) instance: u32,
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) model_position: vec3<f32>,
+    @location(3) camera: vec3<f32>,
+    @location(4) @interpolate(flat) color: vec4<f32>,
 };
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
+    let instance = instances[in.instance];
     var out: VertexOutput;
-    out.clip_position = uniforms.mvp * vec4<f32>(in.position, 1.0);
+    out.clip_position = instance.mvp * vec4<f32>(in.position, 1.0);
     out.normal = in.normal;
+    out.uv = in.uv;
+    out.model_position = in.position;
+    out.camera = instance.camera.xyz;
+    out.color = instance.color;
+    return out;
+}
+
+// Rigged assets: the volume's joint matrices and morph target weights, and
+// the asset's morph displacements (position and normal for each target and
+// vertex). Skinned normals stay in model space like the others.
+@group(2) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+@group(2) @binding(1)
+var<storage, read> morph_weights: array<f32>;
+@group(2) @binding(2)
+var<storage, read> morph_displacements: array<vec4<f32>>;
+
+struct SkinnedVertexInput {
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+};
+
+@vertex
+fn vs_skinned(in: SkinnedVertexInput) -> VertexOutput {
+    var position = in.position;
+    var normal = in.normal;
+    let target_count = arrayLength(&morph_weights);
+    let vertex_count = arrayLength(&morph_displacements) / (2u * target_count);
+    for (var t = 0u; t < target_count; t++) {
+        let weight = morph_weights[t];
+        if (weight != 0.0) {
+            let i = 2u * (t * vertex_count + in.vertex_index);
+            position += weight * morph_displacements[i].xyz;
+            normal += weight * morph_displacements[i + 1u].xyz;
+        }
+    }
+
+    let skin = in.weights.x * joint_matrices[in.joints.x]
+        + in.weights.y * joint_matrices[in.joints.y]
+        + in.weights.z * joint_matrices[in.joints.z]
+        + in.weights.w * joint_matrices[in.joints.w];
+
+    let skinned = skin * vec4<f32>(position, 1.0);
+    let instance = instances[in.instance];
+    var out: VertexOutput;
+    out.clip_position = instance.mvp * skinned;
+    out.normal = (skin * vec4<f32>(normal, 0.0)).xyz;
+    out.uv = in.uv;
+    out.model_position = skinned.xyz;
+    out.camera = instance.camera.xyz;
+    out.color = instance.color;
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = material.uv_rect.xy + in.uv * material.uv_rect.zw;
+    let base = in.color * textureSample(base_texture, base_sampler, uv);
+    let metallic = material.params.x;
+    let roughness = material.params.y;
+
     // Simple directional lighting
+    let normal = normalize(in.normal);
     let light_dir = normalize(vec3<f32>(0.5, 1.0, 0.3));
     let ambient = 0.3;
-    let diffuse = max(dot(normalize(in.normal), light_dir), 0.0);
+    let diffuse = max(dot(normal, light_dir), 0.0);
     let brightness = ambient + diffuse * 0.7;
 
-    return vec4<f32>(uniforms.color.rgb * brightness, uniforms.color.a);
+    // Blinn-Phong highlight, sharper and brighter as roughness drops. Fully
+    // rough, non-metallic materials (the default) are lit as above.
+    var specular = 0.0;
+    if (roughness < 1.0 && diffuse > 0.0) {
+        let view_dir = normalize(in.camera - in.model_position);
+        let half_dir = normalize(light_dir + view_dir);
+        let shininess = exp2(10.0 * (1.0 - roughness) + 1.0);
+        specular = (1.0 - roughness) * pow(max(dot(normal, half_dir), 0.0), shininess);
+    }
+    // Metals have no diffuse color; they reflect theirs
+    let specular_color = mix(vec3<f32>(0.04), base.rgb, metallic);
+    let rgb = base.rgb * (1.0 - metallic) * brightness
+        + specular_color * (metallic * brightness + specular)
+        + material.emissive.rgb;
+
+    return vec4<f32>(rgb, base.a);
+}
+
+// Text: the glyph atlas's coverage in the text color, unlit. Pixels outside
+// the glyphs are dropped so they don't hide what is drawn after.
+@fragment
+fn fs_text(in: VertexOutput) -> @location(0) vec4<f32> {
+    let alpha = in.color.a * textureSample(base_texture, base_sampler, in.uv).a;
+    if (alpha < 0.01) {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, alpha);
 }
@@ -0,0 +1,623 @@
+//! Skeletal and morph target animation of loaded GLB models
+//!
+//! Models with skins, morph targets or animations keep their node hierarchy
+//! in a `Rig`. Each volume showing one has its own `Skeleton`: every frame
+//! the animations it plays (`AnimationCommand::Play`) are sampled and blended
+//! over the rest pose, bone and blend-shape overrides go on top, and the
+//! renderer skins the mesh on the GPU with the resulting joint matrices and
+//! morph weights.
+//!
+//! Vertices of meshes without a skin are bound to the node showing them, so
+//! animations that move nodes rigidly play too.
+
+use crate::asset_loader::ScenePrimitive;
+use fastn_protocol::{BoneTransform, LoopMode, PlayAnimationData};
+use glam::{Mat4, Quat, Vec3};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Translation, rotation and scale of a node relative to its parent
+#[derive(Debug, Clone, Copy)]
+pub struct NodePose {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+}
+
+impl NodePose {
+    fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+#[derive(Debug)]
+struct RigNode {
+    parent: Option<usize>,
+    rest: NodePose,
+    /// Morph target weights of the node's mesh at rest
+    rest_weights: Vec<f32>,
+}
+
+/// A blend shape: one morph target of the mesh shown by a node
+#[derive(Debug)]
+struct MorphTarget {
+    node: usize,
+    index: usize,
+    name: String,
+}
+
+/// A vertex in bind space, with the joint matrices that move it
+#[derive(Debug, Clone, Copy)]
+pub struct SkinVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Property {
+    Translation,
+    Rotation,
+    Scale,
+    Weights,
+}
+
+/// Keyframes of one property of one node
+#[derive(Debug)]
+struct Channel {
+    node: usize,
+    property: Property,
+    interpolation: gltf::animation::Interpolation,
+    times: Vec<f32>,
+    /// `width` values per keyframe, or in-tangent, value and out-tangent for
+    /// cubic splines
+    values: Vec<f32>,
+    width: usize,
+}
+
+#[derive(Debug)]
+struct Clip {
+    name: String,
+    duration: f32,
+    channels: Vec<Channel>,
+}
+
+/// Node hierarchy, skin and animations of a loaded model
+#[derive(Debug)]
+pub struct Rig {
+    nodes: Vec<RigNode>,
+    /// Node indices, parents before children
+    order: Vec<usize>,
+    /// The joint matrices vertices refer to: a node and its inverse bind
+    /// matrix
+    joints: Vec<(usize, Mat4)>,
+    /// Nodes by bone name, as in `SkeletonInfo`
+    bones: HashMap<String, usize>,
+    morph_targets: Vec<MorphTarget>,
+    clips: Vec<Clip>,
+    /// The mesh's vertices, in the order of `LoadedMesh::vertices`
+    pub vertices: Vec<SkinVertex>,
+    /// Position and normal displacement of every vertex for each morph
+    /// target, `[target][vertex]`
+    pub morph_displacements: Vec<([f32; 3], [f32; 3])>,
+}
+
+impl Rig {
+    /// The rig of a model, None if it has no skins, morph targets or
+    /// animations
+    pub fn new(document: &gltf::Document, buffers: &[gltf::buffer::Data], primitives: &[ScenePrimitive]) -> Option<Self> {
+        let has_targets = primitives.iter().any(|p| !p.targets.is_empty());
+        if document.skins().next().is_none() && document.animations().next().is_none() && !has_targets {
+            return None;
+        }
+
+        let mut parents = HashMap::new();
+        let mut children = vec![vec![]; document.nodes().len()];
+        for node in document.nodes() {
+            for child in node.children() {
+                parents.insert(child.index(), node.index());
+                children[node.index()].push(child.index());
+            }
+        }
+        let nodes: Vec<RigNode> = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                let target_count = node
+                    .mesh()
+                    .and_then(|mesh| mesh.primitives().next())
+                    .map_or(0, |primitive| primitive.morph_targets().count());
+                let mut rest_weights = node
+                    .weights()
+                    .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                    .map(<[f32]>::to_vec)
+                    .unwrap_or_default();
+                rest_weights.resize(target_count, 0.0);
+                RigNode {
+                    parent: parents.get(&node.index()).copied(),
+                    rest: NodePose {
+                        translation: Vec3::from_array(translation),
+                        rotation: Quat::from_array(rotation).normalize(),
+                        scale: Vec3::from_array(scale),
+                    },
+                    rest_weights,
+                }
+            })
+            .collect();
+
+        let mut order = Vec::with_capacity(nodes.len());
+        let mut stack: Vec<usize> = (0..nodes.len()).filter(|i| nodes[*i].parent.is_none()).rev().collect();
+        while let Some(index) = stack.pop() {
+            order.push(index);
+            stack.extend(children[index].iter().rev());
+        }
+
+        let mut bones = HashMap::new();
+        for skin in document.skins() {
+            for (index, joint) in skin.joints().enumerate() {
+                let name = joint.name().map(str::to_string).unwrap_or_else(|| format!("bone-{}", index));
+                bones.entry(name).or_insert(joint.index());
+            }
+        }
+
+        let mut rig = Self {
+            nodes,
+            order,
+            joints: vec![],
+            bones,
+            morph_targets: vec![],
+            clips: read_clips(document, buffers),
+            vertices: vec![],
+            morph_displacements: vec![],
+        };
+        rig.bind_vertices(document, buffers, primitives);
+        Some(rig)
+    }
+
+    /// Bind every vertex to its joints, and collect the morph targets
+    fn bind_vertices(&mut self, document: &gltf::Document, buffers: &[gltf::buffer::Data], primitives: &[ScenePrimitive]) {
+        // Joint matrix indices of each skin's joints, and of nodes moving
+        // unskinned vertices
+        let mut skin_joints: HashMap<usize, Vec<u32>> = HashMap::new();
+        let mut node_joints: HashMap<usize, u32> = HashMap::new();
+        // Morph target indices by node and target
+        let mut targets: HashMap<(usize, usize), usize> = HashMap::new();
+        // Displacements of each primitive's vertices, added up below
+        let mut displaced = vec![];
+
+        for primitive in primitives {
+            let skinned = match (primitive.skin, &primitive.joints, &primitive.weights) {
+                (Some(skin), Some(joints), Some(weights)) => Some((skin, joints, weights)),
+                _ => None,
+            };
+            let first = self.vertices.len();
+            match skinned {
+                Some((skin, joints, weights)) => {
+                    let palette = skin_joints.entry(skin).or_insert_with(|| {
+                        let Some(skin) = document.skins().nth(skin) else {
+                            return vec![];
+                        };
+                        let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+                        let mut inverse_binds = reader.read_inverse_bind_matrices().into_iter().flatten();
+                        skin.joints()
+                            .map(|joint| {
+                                let inverse_bind = inverse_binds
+                                    .next()
+                                    .map_or(Mat4::IDENTITY, |m| Mat4::from_cols_array_2d(&m));
+                                self.joints.push((joint.index(), inverse_bind));
+                                (self.joints.len() - 1) as u32
+                            })
+                            .collect()
+                    });
+                    let vertices = primitive.positions.iter().zip(&primitive.normals).zip(&primitive.uvs);
+                    for (i, ((position, normal), uv)) in vertices.enumerate() {
+                        let joint = joints.get(i).copied().unwrap_or_default();
+                        let mut weight = weights.get(i).copied().unwrap_or([1.0, 0.0, 0.0, 0.0]);
+                        let sum: f32 = weight.iter().sum();
+                        if sum > 0.0 {
+                            weight = weight.map(|w| w / sum);
+                        }
+                        self.vertices.push(SkinVertex {
+                            position: *position,
+                            normal: *normal,
+                            uv: *uv,
+                            joints: joint.map(|j| palette.get(j as usize).copied().unwrap_or_default()),
+                            weights: weight,
+                        });
+                    }
+                }
+                None => {
+                    let joint = *node_joints.entry(primitive.node).or_insert_with(|| {
+                        self.joints.push((primitive.node, Mat4::IDENTITY));
+                        (self.joints.len() - 1) as u32
+                    });
+                    let vertices = primitive.positions.iter().zip(&primitive.normals).zip(&primitive.uvs);
+                    self.vertices.extend(vertices.map(|((position, normal), uv)| {
+                        SkinVertex {
+                            position: *position,
+                            normal: *normal,
+                            uv: *uv,
+                            joints: [joint, 0, 0, 0],
+                            weights: [1.0, 0.0, 0.0, 0.0],
+                        }
+                    }));
+                }
+            }
+
+            let names = document
+                .nodes()
+                .nth(primitive.node)
+                .and_then(|node| node.mesh())
+                .map(|mesh| target_names(&mesh))
+                .unwrap_or_default();
+            for (index, displacements) in primitive.targets.iter().enumerate() {
+                let target = *targets.entry((primitive.node, index)).or_insert_with(|| {
+                    let name = names.get(index).filter(|name| !name.is_empty()).cloned();
+                    self.morph_targets.push(MorphTarget {
+                        node: primitive.node,
+                        index,
+                        name: name.unwrap_or_else(|| format!("target-{}", index)),
+                    });
+                    self.morph_targets.len() - 1
+                });
+                displaced.push((target, first, primitive.positions.len(), displacements));
+            }
+        }
+
+        let vertex_count = self.vertices.len();
+        self.morph_displacements = vec![([0.0; 3], [0.0; 3]); self.morph_targets.len() * vertex_count];
+        for (target, first, count, (positions, normals)) in displaced {
+            let start = target * vertex_count + first;
+            for (i, slot) in self.morph_displacements[start..start + count].iter_mut().enumerate() {
+                *slot = (
+                    positions.get(i).copied().unwrap_or_default(),
+                    normals.get(i).copied().unwrap_or_default(),
+                );
+            }
+        }
+    }
+}
+
+/// Names of a mesh's morph targets, from the `targetNames` extra most
+/// exporters write
+fn target_names(mesh: &gltf::Mesh) -> Vec<String> {
+    mesh.extras()
+        .as_ref()
+        .and_then(|extras| serde_json::from_str::<serde_json::Value>(extras.get()).ok())
+        .and_then(|extras| {
+            let names = extras.get("targetNames")?.as_array()?;
+            Some(names.iter().map(|name| name.as_str().unwrap_or_default().to_string()).collect())
+        })
+        .unwrap_or_default()
+}
+
+/// Animations, named like `AnimationInfo`
+fn read_clips(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Vec<Clip> {
+    use gltf::animation::util::ReadOutputs;
+
+    document
+        .animations()
+        .map(|animation| {
+            let channels: Vec<Channel> = animation
+                .channels()
+                .filter_map(|channel| {
+                    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+                    let times: Vec<f32> = reader.read_inputs()?.collect();
+                    let (property, values): (Property, Vec<f32>) = match reader.read_outputs()? {
+                        ReadOutputs::Translations(values) => (Property::Translation, values.flatten().collect()),
+                        ReadOutputs::Rotations(values) => (Property::Rotation, values.into_f32().flatten().collect()),
+                        ReadOutputs::Scales(values) => (Property::Scale, values.flatten().collect()),
+                        ReadOutputs::MorphTargetWeights(values) => (Property::Weights, values.into_f32().collect()),
+                    };
+                    let interpolation = channel.sampler().interpolation();
+                    let per_key = match interpolation {
+                        gltf::animation::Interpolation::CubicSpline => 3,
+                        _ => 1,
+                    };
+                    let width = match property {
+                        Property::Translation | Property::Scale => 3,
+                        Property::Rotation => 4,
+                        Property::Weights => values.len() / (times.len() * per_key).max(1),
+                    };
+                    if times.is_empty() || width == 0 || values.len() != times.len() * per_key * width {
+                        log::warn!("Skipping malformed channel of animation {}", animation.index());
+                        return None;
+                    }
+                    Some(Channel {
+                        node: channel.target().node().index(),
+                        property,
+                        interpolation,
+                        times,
+                        values,
+                        width,
+                    })
+                })
+                .collect();
+            Clip {
+                name: animation
+                    .name()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("animation-{}", animation.index())),
+                duration: channels.iter().filter_map(|c| c.times.last().copied()).fold(0.0, f32::max),
+                channels,
+            }
+        })
+        .collect()
+}
+
+impl Channel {
+    /// The property's value at `time`, clamped to the keyframes
+    fn sample(&self, time: f32) -> Vec<f32> {
+        use gltf::animation::Interpolation;
+
+        let last = self.times.len() - 1;
+        let next = self.times.partition_point(|t| *t <= time);
+        if next == 0 {
+            return self.key(0).to_vec();
+        }
+        if next > last {
+            return self.key(last).to_vec();
+        }
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let t = if span > 0.0 { (time - self.times[previous]) / span } else { 0.0 };
+        let (a, b) = (self.key(previous), self.key(next));
+        match self.interpolation {
+            Interpolation::Step => a.to_vec(),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let (a, b) = (Quat::from_slice(a), Quat::from_slice(b));
+                a.slerp(b, t).to_array().to_vec()
+            }
+            Interpolation::Linear => a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect(),
+            Interpolation::CubicSpline => {
+                // Hermite spline, tangents are scaled by the keyframe span
+                let (t2, t3) = (t * t, t * t * t);
+                let out_tangent = &self.values[(previous * 3 + 2) * self.width..][..self.width];
+                let in_tangent = &self.values[next * 3 * self.width..][..self.width];
+                (0..self.width)
+                    .map(|i| {
+                        (2.0 * t3 - 3.0 * t2 + 1.0) * a[i]
+                            + (t3 - 2.0 * t2 + t) * span * out_tangent[i]
+                            + (-2.0 * t3 + 3.0 * t2) * b[i]
+                            + (t3 - t2) * span * in_tangent[i]
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// The value of a keyframe (the middle of the triplet for cubic splines)
+    fn key(&self, index: usize) -> &[f32] {
+        match self.interpolation {
+            gltf::animation::Interpolation::CubicSpline => &self.values[(index * 3 + 1) * self.width..][..self.width],
+            _ => &self.values[index * self.width..][..self.width],
+        }
+    }
+}
+
+/// An animation a volume plays
+struct Playback {
+    animation_id: String,
+    clip: usize,
+    /// Seconds into the clip, unwrapped for ping-pong
+    time: f32,
+    speed: f32,
+    loop_mode: LoopMode,
+    weight: f32,
+    /// Ended `Once` playbacks hold their last pose until stopped
+    finished: bool,
+}
+
+impl Playback {
+    /// Advance by `dt` seconds, returning whether the playback just ended
+    fn advance(&mut self, dt: f32, duration: f32) -> bool {
+        if self.finished {
+            return false;
+        }
+        let step = dt * self.speed;
+        match self.loop_mode {
+            LoopMode::Once => {
+                self.time += step;
+                let end = if self.speed < 0.0 { 0.0 } else { duration };
+                if !self.time.is_finite() || (self.speed >= 0.0 && self.time >= end) || (self.speed < 0.0 && self.time <= end) {
+                    self.time = end;
+                    self.finished = true;
+                }
+                self.finished
+            }
+            LoopMode::Loop | LoopMode::PingPong => {
+                // A ping-pong cycle plays the clip forwards, then backwards
+                let cycle = if self.loop_mode == LoopMode::Loop { duration } else { 2.0 * duration };
+                if cycle > 0.0 && step.is_finite() {
+                    self.time = (self.time + step).rem_euclid(cycle);
+                }
+                false
+            }
+        }
+    }
+
+    /// Where in the clip the playback is
+    fn clip_time(&self, duration: f32) -> f32 {
+        match self.loop_mode {
+            LoopMode::PingPong if self.time > duration => 2.0 * duration - self.time,
+            _ => self.time,
+        }
+    }
+}
+
+/// The pose of a volume showing a rigged model, and what animates it
+pub struct Skeleton {
+    rig: Arc<Rig>,
+    playing: Vec<Playback>,
+    /// `SetBoneTransform` overrides by node, with their weight
+    bones: HashMap<usize, (BoneTransform, f32)>,
+    /// `SetBlendShape` overrides by name
+    blend_shapes: HashMap<String, f32>,
+    poses: Vec<NodePose>,
+    weights: Vec<Vec<f32>>,
+}
+
+impl Skeleton {
+    /// The model in its rest pose
+    pub fn new(rig: Arc<Rig>) -> Self {
+        let poses = rig.nodes.iter().map(|node| node.rest).collect();
+        let weights = rig.nodes.iter().map(|node| node.rest_weights.clone()).collect();
+        Self {
+            rig,
+            playing: vec![],
+            bones: HashMap::new(),
+            blend_shapes: HashMap::new(),
+            poses,
+            weights,
+        }
+    }
+
+    /// Start an animation by name, replacing the one playing under the same
+    /// `animation_id`. Animations playing together are blended in the order
+    /// they were started, each by its weight.
+    pub fn play(&mut self, data: &PlayAnimationData) -> Result<(), String> {
+        let clip = self
+            .rig
+            .clips
+            .iter()
+            .position(|clip| clip.name == data.animation_name)
+            .ok_or_else(|| format!("no animation named {}", data.animation_name))?;
+        let duration = self.rig.clips[clip].duration;
+        self.playing.retain(|playback| playback.animation_id != data.animation_id);
+        self.playing.push(Playback {
+            animation_id: data.animation_id.clone(),
+            clip,
+            time: data.start_time.clamp(0.0, duration),
+            speed: data.speed,
+            loop_mode: data.loop_mode,
+            weight: data.weight.clamp(0.0, 1.0),
+            finished: false,
+        });
+        self.update_pose();
+        Ok(())
+    }
+
+    /// Stop one animation, or all of them. Stopped animations don't complete.
+    pub fn stop(&mut self, animation_id: Option<&str>) {
+        self.playing.retain(|playback| animation_id.is_some_and(|id| playback.animation_id != id));
+        self.update_pose();
+    }
+
+    /// Override a bone's transform, blended over the animated pose by
+    /// `weight`. A weight of 0 removes the override.
+    pub fn set_bone(&mut self, bone_name: &str, transform: &BoneTransform, weight: f32) -> Result<(), String> {
+        let node = *self.rig.bones.get(bone_name).ok_or_else(|| format!("no bone named {}", bone_name))?;
+        match weight > 0.0 {
+            true => self.bones.insert(node, (transform.clone(), weight.min(1.0))),
+            false => self.bones.remove(&node),
+        };
+        self.update_pose();
+        Ok(())
+    }
+
+    /// Set a blend shape's weight, overriding animations of it
+    pub fn set_blend_shape(&mut self, name: &str, weight: f32) -> Result<(), String> {
+        if !self.rig.morph_targets.iter().any(|target| target.name == name) {
+            return Err(format!("no blend shape named {}", name));
+        }
+        self.blend_shapes.insert(name.to_string(), weight);
+        Ok(())
+    }
+
+    /// Advance the animations by `dt` seconds, returning the IDs of those
+    /// that ended
+    pub fn advance(&mut self, dt: f32) -> Vec<String> {
+        if self.playing.is_empty() {
+            return vec![];
+        }
+        let rig = &self.rig;
+        let completed = self
+            .playing
+            .iter_mut()
+            .filter_map(|playback| {
+                let duration = rig.clips[playback.clip].duration;
+                playback.advance(dt, duration).then(|| playback.animation_id.clone())
+            })
+            .collect();
+        self.update_pose();
+        completed
+    }
+
+    /// Rest pose, then the animations, then the bone overrides
+    fn update_pose(&mut self) {
+        let rig = &self.rig;
+        for ((pose, weights), node) in self.poses.iter_mut().zip(&mut self.weights).zip(&rig.nodes) {
+            *pose = node.rest;
+            weights.clone_from(&node.rest_weights);
+        }
+
+        for playback in &self.playing {
+            let clip = &rig.clips[playback.clip];
+            let time = playback.clip_time(clip.duration);
+            let w = playback.weight;
+            for channel in &clip.channels {
+                let value = channel.sample(time);
+                let Some(pose) = self.poses.get_mut(channel.node) else {
+                    continue;
+                };
+                match channel.property {
+                    Property::Translation => pose.translation = pose.translation.lerp(Vec3::from_slice(&value), w),
+                    Property::Rotation => {
+                        pose.rotation = pose.rotation.slerp(Quat::from_slice(&value).normalize(), w);
+                    }
+                    Property::Scale => pose.scale = pose.scale.lerp(Vec3::from_slice(&value), w),
+                    Property::Weights => {
+                        for (current, target) in self.weights[channel.node].iter_mut().zip(&value) {
+                            *current += (target - *current) * w;
+                        }
+                    }
+                }
+            }
+        }
+
+        for (node, (transform, w)) in &self.bones {
+            let pose = &mut self.poses[*node];
+            if let Some(position) = transform.position {
+                pose.translation = pose.translation.lerp(Vec3::from_array(position), *w);
+            }
+            if let Some(rotation) = transform.rotation {
+                pose.rotation = pose.rotation.slerp(Quat::from_array(rotation).normalize(), *w);
+            }
+            if let Some(scale) = transform.scale {
+                pose.scale = pose.scale.lerp(Vec3::from_array(scale), *w);
+            }
+        }
+    }
+
+    /// Model-space transform of every joint matrix, times its inverse bind
+    /// matrix, for the vertex shader
+    pub fn joint_matrices(&self) -> Vec<[[f32; 4]; 4]> {
+        let mut globals = vec![Mat4::IDENTITY; self.poses.len()];
+        for &index in &self.rig.order {
+            let local = self.poses[index].matrix();
+            globals[index] = match self.rig.nodes[index].parent {
+                Some(parent) => globals[parent] * local,
+                None => local,
+            };
+        }
+        self.rig
+            .joints
+            .iter()
+            .map(|(node, inverse_bind)| (globals[*node] * *inverse_bind).to_cols_array_2d())
+            .collect()
+    }
+
+    /// Weight of every blend shape, for the vertex shader
+    pub fn morph_weights(&self) -> Vec<f32> {
+        self.rig
+            .morph_targets
+            .iter()
+            .map(|target| match self.blend_shapes.get(&target.name) {
+                Some(weight) => *weight,
+                None => self.weights[target.node].get(target.index).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,82 @@
+//! Per-app key-value storage (`StorageCommand`)
+//!
+//! Each app's values are kept in one JSON file, `<app>.json` in
+//! `FASTN_STORAGE_DIR` (by default `fastn/storage` in the user's data
+//! directory), written through on every change. A missing file is an app
+//! that stored nothing yet; one that can't be read starts empty, with a
+//! warning.
+
+use fastn_protocol::{StorageCommand, StorageEvent};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The stored values of one app
+pub struct AppStorage {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl AppStorage {
+    /// Open the storage of the app named `app` where the environment says,
+    /// or in the user's data directory
+    pub fn open_default(app: &str) -> Self {
+        let dir = std::env::var_os("FASTN_STORAGE_DIR").map_or_else(
+            || {
+                dirs::data_dir()
+                    .unwrap_or_else(std::env::temp_dir)
+                    .join("fastn")
+                    .join("storage")
+            },
+            PathBuf::from,
+        );
+        Self::open(dir.join(format!("{}.json", app)))
+    }
+
+    /// Open the storage kept in the file at `path`
+    pub fn open(path: PathBuf) -> Self {
+        let values = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable storage {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self { path, values }
+    }
+
+    /// Run a storage command; `Get`s and failed writes answer with an event
+    pub fn execute(&mut self, cmd: StorageCommand) -> Option<StorageEvent> {
+        let key = match cmd {
+            StorageCommand::Get { key } => {
+                let value = self.values.get(&key).cloned();
+                return Some(StorageEvent::Loaded { key, value });
+            }
+            StorageCommand::Set { key, value } => {
+                self.values.insert(key.clone(), value);
+                key
+            }
+            StorageCommand::Remove { key } => {
+                self.values.remove(&key)?;
+                key
+            }
+        };
+        match self.save() {
+            Ok(()) => None,
+            Err(error) => {
+                log::error!("Failed to save storage {}: {}", self.path.display(), error);
+                Some(StorageEvent::Error { key, error })
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_vec_pretty(&self.values).map_err(|e| e.to_string())?;
+        // Written aside and renamed, so a crash never leaves half a file
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, json).map_err(|e| e.to_string())?;
+        std::fs::rename(&temp, &self.path).map_err(|e| e.to_string())
+    }
+}
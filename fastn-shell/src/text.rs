@@ -0,0 +1,229 @@
+//! Text layout for `Primitive::Text`
+//!
+//! Glyphs are rasterized once, at `PX_PER_EM`, into an atlas shared by all
+//! text volumes: white RGBA with the glyph's coverage in alpha, so the
+//! volume's color tints it. Laying text out gives one quad per glyph in
+//! meters, in the volume's XY plane, facing +Z.
+
+use crate::texture::TextureImage;
+use ab_glyph::{Font, FontRef, GlyphId, PxScale, ScaleFont, point};
+use fastn_protocol::TextAlignment;
+use std::collections::HashMap;
+
+/// Side of the square atlas texture
+pub const ATLAS_SIZE: u32 = 1024;
+
+/// Pixels per em glyphs are rasterized at
+const PX_PER_EM: f32 = 48.0;
+
+/// Transparent pixels around every glyph, so filtering doesn't pick up its
+/// neighbors
+const PADDING: u32 = 1;
+
+/// Where a glyph is in the atlas and how it sits on the baseline
+#[derive(Debug, Clone, Copy)]
+struct AtlasGlyph {
+    /// Pixel bounds relative to the pen position, y down
+    min: [f32; 2],
+    max: [f32; 2],
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+}
+
+/// Text laid out as glyph quads, centered on the origin
+pub struct TextMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    /// Width and height of the text block in meters
+    pub size: [f32; 2],
+}
+
+pub struct GlyphAtlas {
+    font: FontRef<'static>,
+    scale: PxScale,
+    /// Rasterized glyphs; None for glyphs without an outline (spaces) or
+    /// that didn't fit
+    glyphs: HashMap<GlyphId, Option<AtlasGlyph>>,
+    image: TextureImage,
+    /// Shelf packing: where the next glyph goes, and the height of the
+    /// current row
+    cursor: [u32; 2],
+    row_height: u32,
+    /// Whether glyphs were added since `take_changed`
+    changed: bool,
+}
+
+impl GlyphAtlas {
+    pub fn new() -> Self {
+        let font = FontRef::try_from_slice(notosans::REGULAR_TTF).expect("bundled font is valid");
+        // PxScale is the height from descent to ascent, not the em
+        let units_per_em = font.units_per_em().unwrap_or(1000.0);
+        let scale = PxScale::from(PX_PER_EM * font.height_unscaled() / units_per_em);
+        Self {
+            font,
+            scale,
+            glyphs: HashMap::new(),
+            image: TextureImage::empty(ATLAS_SIZE, ATLAS_SIZE).expect("atlas size is valid"),
+            cursor: [PADDING, PADDING],
+            row_height: 0,
+            changed: false,
+        }
+    }
+
+    /// The atlas pixels
+    pub fn image(&self) -> &TextureImage {
+        &self.image
+    }
+
+    /// Whether glyphs were added since the last call, and the atlas needs
+    /// uploading again
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// Lay `text` out with an em of `font_size` meters, breaking lines at
+    /// `\n` and between words wider than `max_width`
+    pub fn layout(
+        &mut self,
+        text: &str,
+        font_size: f32,
+        alignment: TextAlignment,
+        max_width: Option<f32>,
+    ) -> TextMesh {
+        let meters_per_px = font_size / PX_PER_EM;
+        let max_width = max_width.map(|width| width / meters_per_px);
+        let lines: Vec<String> = text.split('\n').flat_map(|line| self.wrap(line, max_width)).collect();
+
+        let font = self.font.as_scaled(self.scale);
+        let (ascent, line_height) = (font.ascent(), font.height() + font.line_gap());
+        let widths: Vec<f32> = lines.iter().map(|line| self.line_width(line)).collect();
+        let width = widths.iter().copied().fold(0.0, f32::max);
+        let height = font.height() + line_height * lines.len().saturating_sub(1) as f32;
+
+        let mut mesh = TextMesh {
+            positions: vec![],
+            uvs: vec![],
+            indices: vec![],
+            size: [width * meters_per_px, height * meters_per_px],
+        };
+        for (index, (line, line_width)) in lines.iter().zip(widths).enumerate() {
+            let mut x = match alignment {
+                TextAlignment::Leading => -width / 2.0,
+                TextAlignment::Center => -line_width / 2.0,
+                TextAlignment::Trailing => width / 2.0 - line_width,
+            };
+            let baseline = height / 2.0 - ascent - line_height * index as f32;
+            let mut previous = None;
+            for c in line.chars() {
+                let id = self.font.glyph_id(c);
+                let font = self.font.as_scaled(self.scale);
+                if let Some(previous) = previous {
+                    x += font.kern(previous, id);
+                }
+                let advance = font.h_advance(id);
+                if let Some(glyph) = self.glyph(id) {
+                    let left = (x + glyph.min[0]) * meters_per_px;
+                    let right = (x + glyph.max[0]) * meters_per_px;
+                    let top = (baseline - glyph.min[1]) * meters_per_px;
+                    let bottom = (baseline - glyph.max[1]) * meters_per_px;
+                    let first = mesh.positions.len() as u32;
+                    mesh.positions.extend([[left, bottom, 0.0], [right, bottom, 0.0], [right, top, 0.0], [left, top, 0.0]]);
+                    mesh.uvs.extend([
+                        [glyph.uv_min[0], glyph.uv_max[1]],
+                        glyph.uv_max,
+                        [glyph.uv_max[0], glyph.uv_min[1]],
+                        glyph.uv_min,
+                    ]);
+                    mesh.indices.extend([0, 1, 2, 2, 3, 0].map(|i| first + i));
+                }
+                x += advance;
+                previous = Some(id);
+            }
+        }
+        mesh
+    }
+
+    /// Split a line between words so each part fits in `max_width` pixels;
+    /// a word wider than that gets a line of its own
+    fn wrap(&self, line: &str, max_width: Option<f32>) -> Vec<String> {
+        let Some(max_width) = max_width else {
+            return vec![line.to_string()];
+        };
+        let mut lines = vec![];
+        let mut current = String::new();
+        for word in line.split(' ') {
+            let candidate = match current.is_empty() {
+                true => word.to_string(),
+                false => format!("{} {}", current, word),
+            };
+            if current.is_empty() || self.line_width(&candidate) <= max_width {
+                current = candidate;
+            } else {
+                lines.push(std::mem::replace(&mut current, word.to_string()));
+            }
+        }
+        lines.push(current);
+        lines
+    }
+
+    /// Advance width of a line in pixels
+    fn line_width(&self, line: &str) -> f32 {
+        let font = self.font.as_scaled(self.scale);
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                width += font.kern(previous, id);
+            }
+            width += font.h_advance(id);
+            previous = Some(id);
+        }
+        width
+    }
+
+    /// A glyph's place in the atlas, rasterizing it on first use
+    fn glyph(&mut self, id: GlyphId) -> Option<AtlasGlyph> {
+        if let Some(glyph) = self.glyphs.get(&id) {
+            return *glyph;
+        }
+        let glyph = self.rasterize(id);
+        self.glyphs.insert(id, glyph);
+        glyph
+    }
+
+    fn rasterize(&mut self, id: GlyphId) -> Option<AtlasGlyph> {
+        let outlined = self.font.outline_glyph(id.with_scale_and_position(self.scale, point(0.0, 0.0)))?;
+        let bounds = outlined.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+
+        if self.cursor[0] + width + PADDING > ATLAS_SIZE {
+            self.cursor = [PADDING, self.cursor[1] + self.row_height + PADDING];
+            self.row_height = 0;
+        }
+        if self.cursor[1] + height + PADDING > ATLAS_SIZE {
+            log::warn!("Glyph atlas is full, glyph {} is not drawn", id.0);
+            return None;
+        }
+        let [x, y] = self.cursor;
+        self.cursor[0] += width + PADDING;
+        self.row_height = self.row_height.max(height);
+
+        outlined.draw(|px, py, coverage| {
+            let offset = (((y + py) * ATLAS_SIZE + x + px) * 4) as usize;
+            if let Some(pixel) = self.image.rgba.get_mut(offset..offset + 4) {
+                pixel.copy_from_slice(&[255, 255, 255, (coverage.clamp(0.0, 1.0) * 255.0) as u8]);
+            }
+        });
+        self.changed = true;
+
+        let size = ATLAS_SIZE as f32;
+        Some(AtlasGlyph {
+            min: [bounds.min.x, bounds.min.y],
+            max: [bounds.min.x + width as f32, bounds.min.y + height as f32],
+            uv_min: [x as f32 / size, y as f32 / size],
+            uv_max: [(x + width) as f32 / size, (y + height) as f32 / size],
+        })
+    }
+}
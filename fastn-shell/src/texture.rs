@@ -0,0 +1,186 @@
+//! Texture images for the renderer
+//!
+//! Whatever a texture comes from (an image or model asset, pixels sent by
+//! the core, an SVG document), it reaches the GPU as sRGB RGBA8 with
+//! straight alpha: `TextureImage`.
+
+use crate::asset_loader::AssetManager;
+use fastn_protocol::{TextureData, TextureFormat, TextureSource};
+
+/// Largest texture side accepted, the smallest limit wgpu guarantees
+pub const MAX_TEXTURE_SIZE: u32 = 8192;
+
+/// An RGBA8 image, rows top to bottom
+#[derive(Debug, Clone)]
+pub struct TextureImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl TextureImage {
+    /// Transparent black, for textures filled later
+    pub fn empty(width: u32, height: u32) -> Result<Self, String> {
+        check_size(width, height)?;
+        Ok(Self {
+            width,
+            height,
+            rgba: vec![0; width as usize * height as usize * 4],
+        })
+    }
+
+    /// The image a `MaterialCommand::CreateTexture` starts with. The native
+    /// shell has no media streams, so their textures stay blank.
+    pub fn from_source(source: &TextureSource, assets: &AssetManager) -> Result<Self, String> {
+        match source {
+            TextureSource::Asset { asset_id } => assets
+                .get_image(asset_id)
+                .cloned()
+                .ok_or_else(|| format!("Asset {} is not a loaded image or textured model", asset_id)),
+            TextureSource::Empty { width, height, .. } => Self::empty(*width, *height),
+            TextureSource::Media { media_id } => {
+                log::warn!("Texture of media {} stays blank: the native shell has no media streams", media_id);
+                Self::empty(1, 1)
+            }
+        }
+    }
+
+    /// The contents of a `MaterialCommand::UpdateTexture`
+    pub fn from_data(data: &TextureData) -> Result<Self, String> {
+        match data {
+            TextureData::Pixels { width, height, format, data } => Self::from_pixels(*width, *height, *format, data),
+            TextureData::Svg { svg, width, height } => Self::from_svg(svg, *width, *height),
+            TextureData::Html { .. } => Err("HTML textures are not supported by the native shell".to_string()),
+        }
+    }
+
+    /// Pixels sent by the core. `R8` is shown as gray and `Rgba16Float`
+    /// (linear, little-endian halves) is clamped to 0-1 and encoded as sRGB.
+    pub fn from_pixels(width: u32, height: u32, format: TextureFormat, data: &[u8]) -> Result<Self, String> {
+        check_size(width, height)?;
+        let pixels = width as usize * height as usize;
+        let bytes_per_pixel = match format {
+            TextureFormat::Rgba8 => 4,
+            TextureFormat::Rgb8 => 3,
+            TextureFormat::R8 => 1,
+            TextureFormat::Rgba16Float => 8,
+        };
+        if data.len() != pixels * bytes_per_pixel {
+            return Err(format!(
+                "{}x{} {:?} pixels take {} bytes, got {}",
+                width,
+                height,
+                format,
+                pixels * bytes_per_pixel,
+                data.len()
+            ));
+        }
+
+        let rgba = match format {
+            TextureFormat::Rgba8 => data.to_vec(),
+            TextureFormat::Rgb8 => data.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            TextureFormat::R8 => data.iter().flat_map(|&r| [r, r, r, 255]).collect(),
+            TextureFormat::Rgba16Float => data
+                .chunks_exact(8)
+                .flat_map(|p| {
+                    let channel = |i: usize| f16_to_f32(u16::from_le_bytes([p[2 * i], p[2 * i + 1]]));
+                    [
+                        linear_to_srgb(channel(0)),
+                        linear_to_srgb(channel(1)),
+                        linear_to_srgb(channel(2)),
+                        (channel(3).clamp(0.0, 1.0) * 255.0).round() as u8,
+                    ]
+                })
+                .collect(),
+        };
+        Ok(Self { width, height, rgba })
+    }
+
+    /// Rasterize an SVG document, stretched to `width` x `height`. Text is
+    /// not drawn: the shell loads no fonts.
+    pub fn from_svg(svg: &str, width: u32, height: u32) -> Result<Self, String> {
+        use resvg::{tiny_skia, usvg};
+
+        check_size(width, height)?;
+        let tree = usvg::Tree::from_str(svg, &usvg::Options::default()).map_err(|e| format!("Invalid SVG: {}", e))?;
+        let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("Invalid SVG texture size")?;
+        let size = tree.size();
+        let transform = tiny_skia::Transform::from_scale(width as f32 / size.width(), height as f32 / size.height());
+        resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+        // tiny-skia premultiplies alpha
+        let rgba = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let color = pixel.demultiply();
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            })
+            .collect();
+        Ok(Self { width, height, rgba })
+    }
+
+    /// Decode a PNG or JPEG file
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let image = image::load_from_memory(bytes).map_err(|e| format!("Failed to decode image: {}", e))?;
+        let image = image.to_rgba8();
+        check_size(image.width(), image.height())?;
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            rgba: image.into_raw(),
+        })
+    }
+
+    /// An image embedded in or referenced by a glTF file
+    pub fn from_gltf(data: &gltf::image::Data) -> Result<Self, String> {
+        use gltf::image::Format;
+
+        check_size(data.width, data.height)?;
+        let rgba = match data.format {
+            Format::R8G8B8A8 => data.pixels.clone(),
+            Format::R8G8B8 => data.pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            Format::R8G8 => data.pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+            Format::R8 => data.pixels.iter().flat_map(|&r| [r, r, r, 255]).collect(),
+            format => return Err(format!("Unsupported glTF image format {:?}", format)),
+        };
+        Ok(Self {
+            width: data.width,
+            height: data.height,
+            rgba,
+        })
+    }
+}
+
+fn check_size(width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 || width > MAX_TEXTURE_SIZE || height > MAX_TEXTURE_SIZE {
+        return Err(format!(
+            "Texture size {}x{} is not between 1 and {}",
+            width, height, MAX_TEXTURE_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// IEEE 754 half to single precision
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+/// Encode a linear channel as sRGB, clamped to 0-1 (NaN is black)
+fn linear_to_srgb(linear: f32) -> u8 {
+    let linear = if linear.is_nan() { 0.0 } else { linear.clamp(0.0, 1.0) };
+    let srgb = match linear <= 0.0031308 {
+        true => linear * 12.92,
+        false => 1.055 * linear.powf(1.0 / 2.4) - 0.055,
+    };
+    (srgb * 255.0).round() as u8
+}
@@ -0,0 +1,772 @@
+//! Desktop VR through OpenXR (`fastn-shell --xr`)
+//!
+//! The Vulkan instance and device are created through the OpenXR runtime
+//! (`XR_KHR_vulkan_enable2`) and handed to wgpu, so the window and both eyes
+//! are drawn by the same renderer from the same volume list; the window keeps
+//! showing the flat camera as a mirror.
+//!
+//! Each frame the runtime paces, the core gets `XrEvent::HeadPose` and an
+//! `XrEvent::ControllerPose` per tracked controller, in scene coordinates
+//! (the tracking origin is placed with `XrCommand::SetRigTransform`), then
+//! each eye is rendered into its swapchain from the predicted view. Session
+//! changes are reported as the Quest shell reports them. A transparent
+//! background is blended over the real world on runtimes with an alpha
+//! blend mode (video see-through headsets), and is black on the others.
+//!
+//! Controllers are bound through the Oculus Touch and simple controller
+//! profiles, which runtimes remap for other controllers. Their buttons and
+//! axes follow WebXR's `xr-standard` gamepad layout: trigger, squeeze,
+//! touchpad (none), thumbstick press, A/X and B/Y; touchpad X/Y (zero),
+//! thumbstick X/Y with Y down.
+
+use crate::renderer::Renderer;
+use ash::vk::{self, Handle};
+use fastn_protocol::{
+    Event, Hand, PlayArea, PoseData, Transform, XrCommand, XrControllerData, XrEvent, XrMode, XrSessionData,
+    XrSessionState,
+};
+use glam::{Mat4, Quat, Vec3, Vec4};
+use openxr as xr;
+use std::sync::Arc;
+use wgpu::hal::{self, api::Vulkan};
+use winit::window::Window;
+
+/// Vulkan version requested from the runtime, the oldest wgpu runs on
+const VK_VERSION: u32 = vk::make_api_version(0, 1, 1, 0);
+
+/// Clip planes of the eyes' projections, as the window camera's
+const NEAR: f32 = 0.1;
+const FAR: f32 = 100.0;
+
+/// How far an analog trigger or grip goes before it counts as pressed
+const PRESSED: f32 = 0.5;
+
+/// Color formats the shell can draw into, best first
+const FORMATS: [(wgpu::TextureFormat, vk::Format); 4] = [
+    (wgpu::TextureFormat::Bgra8UnormSrgb, vk::Format::B8G8R8A8_SRGB),
+    (wgpu::TextureFormat::Rgba8UnormSrgb, vk::Format::R8G8B8A8_SRGB),
+    (wgpu::TextureFormat::Bgra8Unorm, vk::Format::B8G8R8A8_UNORM),
+    (wgpu::TextureFormat::Rgba8Unorm, vk::Format::R8G8B8A8_UNORM),
+];
+
+/// An OpenXR session and the GPU it shares with the window
+pub struct OpenXr {
+    // The session's objects come first, so they are dropped before the
+    // device they were made with
+    eyes: Vec<Eye>,
+    controllers: [Controller; 2],
+    actions: Actions,
+    head: xr::Space,
+    stage: xr::Space,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    frame_waiter: xr::FrameWaiter,
+    session: xr::Session<xr::Vulkan>,
+    instance: xr::Instance,
+    /// Whether the session has begun, i.e. frames are expected
+    running: bool,
+    /// What the core was last told about the session
+    state: XrSessionState,
+    /// Where the tracking origin is in the scene
+    rig: Mat4,
+    /// Whether the runtime can blend the eyes' images over the real world
+    alpha_blend: bool,
+    format: wgpu::TextureFormat,
+    gpu: Gpu,
+}
+
+/// A frame the runtime is waiting for, from `begin_frame` to `end_frame`
+pub struct XrFrame {
+    state: xr::FrameState,
+}
+
+/// One eye's swapchain, with its images as wgpu textures
+struct Eye {
+    images: Vec<wgpu::Texture>,
+    swapchain: xr::Swapchain<xr::Vulkan>,
+}
+
+/// A hand's controller: the spaces following its poses
+struct Controller {
+    hand: Hand,
+    path: xr::Path,
+    aim: xr::Space,
+    grip: xr::Space,
+}
+
+/// Controller actions, for both hands (told apart by their paths)
+struct Actions {
+    set: xr::ActionSet,
+    aim: xr::Action<xr::Posef>,
+    grip: xr::Action<xr::Posef>,
+    trigger: xr::Action<f32>,
+    squeeze: xr::Action<f32>,
+    thumbstick: xr::Action<xr::Vector2f>,
+    thumbstick_click: xr::Action<bool>,
+    primary: xr::Action<bool>,
+    secondary: xr::Action<bool>,
+}
+
+/// wgpu on the Vulkan instance and device the runtime asked for
+struct Gpu {
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// The raw handles, for creating the session
+    session_info: xr::vulkan::SessionCreateInfo,
+}
+
+impl OpenXr {
+    /// Connect to the headset and create a session drawing in a format the
+    /// window can show too. The session starts once the runtime is ready.
+    pub fn new(window: &Arc<Window>) -> Result<Self, String> {
+        let entry = unsafe { xr::Entry::load() }.map_err(|e| format!("Failed to load OpenXR: {:?}", e))?;
+        let available = entry.enumerate_extensions().map_err(xr_error("extension query"))?;
+        if !available.khr_vulkan_enable2 {
+            return Err("The OpenXR runtime doesn't support Vulkan".to_string());
+        }
+        let mut extensions = xr::ExtensionSet::default();
+        extensions.khr_vulkan_enable2 = true;
+        let instance = entry
+            .create_instance(
+                &xr::ApplicationInfo {
+                    application_name: "fastn-shell",
+                    application_version: 1,
+                    engine_name: "fastn",
+                    engine_version: 1,
+                    api_version: xr::Version::new(1, 0, 0),
+                },
+                &extensions,
+                &[],
+            )
+            .map_err(xr_error("instance creation"))?;
+        let system = instance
+            .system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)
+            .map_err(|e| format!("No headset found: {}", e))?;
+        let properties = instance.system_properties(system).map_err(xr_error("system query"))?;
+        log::info!("OpenXR headset: {}", properties.system_name);
+
+        let gpu = Gpu::new(&instance, system)?;
+        let (session, frame_waiter, frame_stream) = unsafe {
+            instance.create_session::<xr::Vulkan>(system, &gpu.session_info)
+        }
+        .map_err(xr_error("session creation"))?;
+
+        // A format both the headset's swapchains and the window take, as
+        // the renderer's pipelines draw into one
+        let swapchain_formats = session.enumerate_swapchain_formats().map_err(xr_error("format query"))?;
+        let surface = gpu.instance.create_surface(Arc::clone(window)).map_err(|e| e.to_string())?;
+        let window_formats = surface.get_capabilities(&gpu.adapter).formats;
+        let (format, vk_format) = FORMATS
+            .into_iter()
+            .find(|(format, vk_format)| {
+                window_formats.contains(format) && swapchain_formats.contains(&(vk_format.as_raw() as _))
+            })
+            .ok_or("No color format both the window and the headset can show")?;
+
+        let views = instance
+            .enumerate_view_configuration_views(system, xr::ViewConfigurationType::PRIMARY_STEREO)
+            .map_err(xr_error("view query"))?;
+        let eyes = views
+            .iter()
+            .map(|view| Eye::new(&session, &gpu.device, view, format, vk_format))
+            .collect::<Result<_, _>>()?;
+
+        let alpha_blend = instance
+            .enumerate_environment_blend_modes(system, xr::ViewConfigurationType::PRIMARY_STEREO)
+            .map_err(xr_error("blend mode query"))?
+            .contains(&xr::EnvironmentBlendMode::ALPHA_BLEND);
+
+        let actions = Actions::new(&instance).map_err(xr_error("action setup"))?;
+        session.attach_action_sets(&[&actions.set]).map_err(xr_error("action setup"))?;
+        let controllers = [
+            Controller::new(&instance, &session, &actions, Hand::Left).map_err(xr_error("controller setup"))?,
+            Controller::new(&instance, &session, &actions, Hand::Right).map_err(xr_error("controller setup"))?,
+        ];
+
+        let stage = session
+            .create_reference_space(xr::ReferenceSpaceType::STAGE, xr::Posef::IDENTITY)
+            .map_err(xr_error("space creation"))?;
+        let head = session
+            .create_reference_space(xr::ReferenceSpaceType::VIEW, xr::Posef::IDENTITY)
+            .map_err(xr_error("space creation"))?;
+
+        Ok(Self {
+            eyes,
+            controllers,
+            actions,
+            head,
+            stage,
+            frame_stream,
+            frame_waiter,
+            session,
+            instance,
+            running: false,
+            state: XrSessionState::None,
+            rig: Mat4::IDENTITY,
+            alpha_blend,
+            format,
+            gpu,
+        })
+    }
+
+    /// A renderer for the window on the session's device, so scenes it
+    /// loads can be drawn to the headset too. The window doesn't wait for
+    /// its vsync, the headset paces the frames.
+    pub fn renderer(&self, window: Arc<Window>) -> Renderer {
+        let size = window.inner_size();
+        let surface = self.gpu.instance.create_surface(window).unwrap();
+        let alpha_modes = surface.get_capabilities(&self.gpu.adapter).alpha_modes;
+        Renderer::on_surface(
+            surface,
+            self.format,
+            &alpha_modes,
+            wgpu::PresentMode::AutoNoVsync,
+            self.gpu.device.clone(),
+            self.gpu.queue.clone(),
+            size,
+        )
+    }
+
+    /// Execute an XR command from the core
+    pub fn execute_command(&mut self, cmd: XrCommand) {
+        match cmd {
+            XrCommand::Enter { mode, .. } => match mode {
+                XrMode::ImmersiveVr if self.running => log::debug!("OpenXR session already running"),
+                XrMode::ImmersiveVr => log::info!("The OpenXR session starts when the runtime is ready"),
+                XrMode::ImmersiveAr => log::warn!("The native shell only supports immersive VR"),
+            },
+            XrCommand::Exit => {
+                if let Err(e) = self.session.request_exit() {
+                    log::warn!("Failed to end the OpenXR session: {}", e);
+                }
+            }
+            XrCommand::SetRigTransform(transform) => self.rig = transform_matrix(&transform),
+        }
+    }
+
+    /// Wait for the runtime's next frame, and report the session's changes
+    /// and where the head and controllers will be when it's shown. No frame
+    /// while the session isn't running.
+    pub fn begin_frame(&mut self) -> Result<(Option<XrFrame>, Vec<Event>), String> {
+        let mut events = vec![];
+        self.poll_events(&mut events)?;
+        if !self.running {
+            return Ok((None, events));
+        }
+
+        let state = self.frame_waiter.wait().map_err(xr_error("frame wait"))?;
+        self.frame_stream.begin().map_err(xr_error("frame begin"))?;
+        let time = state.predicted_display_time;
+
+        let head = self.head.locate(&self.stage, time).map_err(xr_error("head tracking"))?;
+        if head.location_flags.contains(xr::SpaceLocationFlags::POSITION_VALID) {
+            events.push(Event::Xr(XrEvent::HeadPose(self.scene_pose(&head.pose))));
+        }
+
+        self.session
+            .sync_actions(&[(&self.actions.set).into()])
+            .map_err(xr_error("controller sync"))?;
+        for controller in &self.controllers {
+            if let Some(data) = self.controller_data(controller, time).map_err(xr_error("controller tracking"))? {
+                events.push(Event::Xr(XrEvent::ControllerPose(data)));
+            }
+        }
+
+        Ok((Some(XrFrame { state }), events))
+    }
+
+    /// Draw both eyes and hand the frame to the runtime. Without a renderer
+    /// (or when the runtime doesn't show the frame), it is handed back empty.
+    pub fn end_frame(&mut self, frame: XrFrame, renderer: Option<&mut Renderer>) -> Result<(), String> {
+        let time = frame.state.predicted_display_time;
+        let renderer = match renderer {
+            Some(renderer) if frame.state.should_render => renderer,
+            _ => {
+                return self
+                    .frame_stream
+                    .end(time, xr::EnvironmentBlendMode::OPAQUE, &[])
+                    .map_err(xr_error("frame end"));
+            }
+        };
+
+        let (blend_mode, layer_flags) = if self.alpha_blend && renderer.transparent_background() {
+            (xr::EnvironmentBlendMode::ALPHA_BLEND, xr::CompositionLayerFlags::BLEND_TEXTURE_SOURCE_ALPHA)
+        } else {
+            (xr::EnvironmentBlendMode::OPAQUE, xr::CompositionLayerFlags::EMPTY)
+        };
+        let (_, views) = self
+            .session
+            .locate_views(xr::ViewConfigurationType::PRIMARY_STEREO, time, &self.stage)
+            .map_err(xr_error("view tracking"))?;
+        for (eye, view) in self.eyes.iter_mut().zip(&views) {
+            let index = eye.swapchain.acquire_image().map_err(xr_error("swapchain acquire"))?;
+            eye.swapchain.wait_image(xr::Duration::INFINITE).map_err(xr_error("swapchain wait"))?;
+            let pose = self.rig * pose_matrix(&view.pose);
+            renderer.render_view(
+                &eye.images[index as usize],
+                projection(&view.fov) * pose.inverse(),
+                pose.w_axis.truncate(),
+            );
+            eye.swapchain.release_image().map_err(xr_error("swapchain release"))?;
+        }
+
+        let projection_views: Vec<_> = self
+            .eyes
+            .iter()
+            .zip(&views)
+            .map(|(eye, view)| {
+                let size = eye.images[0].size();
+                xr::CompositionLayerProjectionView::new().pose(view.pose).fov(view.fov).sub_image(
+                    xr::SwapchainSubImage::new()
+                        .swapchain(&eye.swapchain)
+                        .image_rect(xr::Rect2Di {
+                            offset: xr::Offset2Di { x: 0, y: 0 },
+                            extent: xr::Extent2Di { width: size.width as i32, height: size.height as i32 },
+                        })
+                        .image_array_index(0),
+                )
+            })
+            .collect();
+        let layer = xr::CompositionLayerProjection::new()
+            .layer_flags(layer_flags)
+            .space(&self.stage)
+            .views(&projection_views);
+        self.frame_stream
+            .end(time, blend_mode, &[&layer])
+            .map_err(xr_error("frame end"))
+    }
+
+    /// Follow the runtime's session changes, starting and stopping the
+    /// session when it asks
+    fn poll_events(&mut self, events: &mut Vec<Event>) -> Result<(), String> {
+        let mut buffer = xr::EventDataBuffer::new();
+        while let Some(event) = self.instance.poll_event(&mut buffer).map_err(xr_error("event poll"))? {
+            match event {
+                xr::Event::SessionStateChanged(e) => {
+                    log::info!("OpenXR session state: {:?}", e.state());
+                    let state = match e.state() {
+                        xr::SessionState::READY => {
+                            self.session
+                                .begin(xr::ViewConfigurationType::PRIMARY_STEREO)
+                                .map_err(xr_error("session begin"))?;
+                            self.running = true;
+                            Some(XrSessionState::Starting)
+                        }
+                        xr::SessionState::FOCUSED => Some(XrSessionState::Active),
+                        xr::SessionState::VISIBLE if self.state == XrSessionState::Active => {
+                            Some(XrSessionState::Paused)
+                        }
+                        xr::SessionState::STOPPING => {
+                            self.session.end().map_err(xr_error("session end"))?;
+                            self.running = false;
+                            Some(XrSessionState::Ending)
+                        }
+                        xr::SessionState::IDLE if self.state == XrSessionState::Ending => Some(XrSessionState::None),
+                        // The window keeps running on its own
+                        xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => {
+                            self.running = false;
+                            (self.state != XrSessionState::None).then_some(XrSessionState::None)
+                        }
+                        _ => None,
+                    };
+                    if let Some(state) = state {
+                        self.state = state;
+                        events.push(Event::Xr(XrEvent::SessionChanged(XrSessionData {
+                            state,
+                            mode: (state != XrSessionState::None).then_some(XrMode::ImmersiveVr),
+                            dom_overlay: false,
+                            play_area: self.play_area(),
+                        })));
+                    }
+                }
+                xr::Event::ReferenceSpaceChangePending(_) => events.push(Event::Xr(XrEvent::ReferenceSpaceReset)),
+                xr::Event::InstanceLossPending(_) => self.running = false,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The guardian boundary, centered on the stage origin
+    fn play_area(&self) -> Option<PlayArea> {
+        let extent = self
+            .session
+            .reference_space_bounds_rect(xr::ReferenceSpaceType::STAGE)
+            .ok()
+            .flatten()?;
+        Some(PlayArea {
+            width: extent.width,
+            depth: extent.height,
+        })
+    }
+
+    /// A controller's poses and inputs, None while it isn't tracked
+    fn controller_data(&self, controller: &Controller, time: xr::Time) -> xr::Result<Option<XrControllerData>> {
+        let aim = controller.aim.locate(&self.stage, time)?;
+        if !aim.location_flags.contains(xr::SpaceLocationFlags::POSITION_VALID) {
+            return Ok(None);
+        }
+        let grip = controller.grip.locate(&self.stage, time)?;
+        let grip_pose = grip
+            .location_flags
+            .contains(xr::SpaceLocationFlags::POSITION_VALID)
+            .then(|| self.scene_pose(&grip.pose));
+
+        let path = controller.path;
+        let analog = |action: &xr::Action<f32>| -> xr::Result<(f32, bool)> {
+            let value = action.state(&self.session, path)?.current_state;
+            Ok((value, value >= PRESSED))
+        };
+        let button = |action: &xr::Action<bool>| -> xr::Result<(f32, bool)> {
+            let pressed = action.state(&self.session, path)?.current_state;
+            Ok((if pressed { 1.0 } else { 0.0 }, pressed))
+        };
+        let thumbstick = self.actions.thumbstick.state(&self.session, path)?.current_state;
+
+        Ok(Some(XrControllerData {
+            hand: controller.hand,
+            pose: self.scene_pose(&aim.pose),
+            grip_pose,
+            buttons: vec![
+                analog(&self.actions.trigger)?,
+                analog(&self.actions.squeeze)?,
+                (0.0, false),
+                button(&self.actions.thumbstick_click)?,
+                button(&self.actions.primary)?,
+                button(&self.actions.secondary)?,
+            ],
+            axes: vec![0.0, 0.0, thumbstick.x, -thumbstick.y],
+        }))
+    }
+
+    /// A pose tracked in the stage space, in scene coordinates
+    fn scene_pose(&self, pose: &xr::Posef) -> PoseData {
+        let (_, rotation, position) = (self.rig * pose_matrix(pose)).to_scale_rotation_translation();
+        PoseData {
+            position: position.to_array(),
+            orientation: rotation.to_array(),
+        }
+    }
+}
+
+impl Eye {
+    /// A swapchain at the view's recommended size, its images wrapped for
+    /// the renderer
+    fn new(
+        session: &xr::Session<xr::Vulkan>,
+        device: &wgpu::Device,
+        view: &xr::ViewConfigurationView,
+        format: wgpu::TextureFormat,
+        vk_format: vk::Format,
+    ) -> Result<Self, String> {
+        let (width, height) = (view.recommended_image_rect_width, view.recommended_image_rect_height);
+        let swapchain = session
+            .create_swapchain(&xr::SwapchainCreateInfo {
+                create_flags: xr::SwapchainCreateFlags::EMPTY,
+                usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT,
+                format: vk_format.as_raw() as _,
+                sample_count: 1,
+                width,
+                height,
+                face_count: 1,
+                array_size: 1,
+                mip_count: 1,
+            })
+            .map_err(xr_error("swapchain creation"))?;
+        let raw_images = swapchain.enumerate_images().map_err(xr_error("swapchain creation"))?;
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let hal_textures: Vec<_> = {
+            let hal_device = unsafe { device.as_hal::<Vulkan>() }.ok_or("The renderer isn't on Vulkan")?;
+            raw_images
+                .into_iter()
+                .map(|image| unsafe {
+                    // The runtime owns the images, wgpu must not destroy them
+                    hal_device.texture_from_raw(
+                        vk::Image::from_raw(image as _),
+                        &hal::TextureDescriptor {
+                            label: Some("XR Swapchain"),
+                            size,
+                            mip_level_count: 1,
+                            sample_count: 1,
+                            dimension: wgpu::TextureDimension::D2,
+                            format,
+                            usage: wgpu::TextureUses::COLOR_TARGET,
+                            memory_flags: hal::MemoryFlags::empty(),
+                            view_formats: vec![],
+                        },
+                        Some(Box::new(|| {})),
+                    )
+                })
+                .collect()
+        };
+        let images = hal_textures
+            .into_iter()
+            .map(|texture| unsafe {
+                device.create_texture_from_hal::<Vulkan>(
+                    texture,
+                    &wgpu::TextureDescriptor {
+                        label: Some("XR Swapchain"),
+                        size,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format,
+                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                        view_formats: &[],
+                    },
+                )
+            })
+            .collect();
+        Ok(Self { images, swapchain })
+    }
+}
+
+impl Controller {
+    /// Spaces following a hand's aim and grip poses
+    fn new(
+        instance: &xr::Instance,
+        session: &xr::Session<xr::Vulkan>,
+        actions: &Actions,
+        hand: Hand,
+    ) -> xr::Result<Self> {
+        let path = instance.string_to_path(hand_path(hand))?;
+        Ok(Self {
+            hand,
+            path,
+            aim: actions.aim.create_space(session.clone(), path, xr::Posef::IDENTITY)?,
+            grip: actions.grip.create_space(session.clone(), path, xr::Posef::IDENTITY)?,
+        })
+    }
+}
+
+impl Actions {
+    /// The actions, bound for Touch and simple controllers
+    fn new(instance: &xr::Instance) -> xr::Result<Self> {
+        let set = instance.create_action_set("controllers", "Controllers", 0)?;
+        let hands = [instance.string_to_path(hand_path(Hand::Left))?, instance.string_to_path(hand_path(Hand::Right))?];
+        let actions = Self {
+            aim: set.create_action("aim", "Aim", &hands)?,
+            grip: set.create_action("grip", "Grip", &hands)?,
+            trigger: set.create_action("trigger", "Trigger", &hands)?,
+            squeeze: set.create_action("squeeze", "Squeeze", &hands)?,
+            thumbstick: set.create_action("thumbstick", "Thumbstick", &hands)?,
+            thumbstick_click: set.create_action("thumbstick_click", "Thumbstick press", &hands)?,
+            primary: set.create_action("primary", "A/X", &hands)?,
+            secondary: set.create_action("secondary", "B/Y", &hands)?,
+            set,
+        };
+
+        let mut touch = vec![];
+        let mut simple = vec![];
+        for hand in [Hand::Left, Hand::Right] {
+            let input = |name: &str| instance.string_to_path(&format!("{}/input/{}", hand_path(hand), name));
+            let (primary, secondary) = match hand {
+                Hand::Left => ("x/click", "y/click"),
+                Hand::Right => ("a/click", "b/click"),
+            };
+            for bindings in [&mut touch, &mut simple] {
+                bindings.push(xr::Binding::new(&actions.aim, input("aim/pose")?));
+                bindings.push(xr::Binding::new(&actions.grip, input("grip/pose")?));
+            }
+            touch.extend([
+                xr::Binding::new(&actions.trigger, input("trigger/value")?),
+                xr::Binding::new(&actions.squeeze, input("squeeze/value")?),
+                xr::Binding::new(&actions.thumbstick, input("thumbstick")?),
+                xr::Binding::new(&actions.thumbstick_click, input("thumbstick/click")?),
+                xr::Binding::new(&actions.primary, input(primary)?),
+                xr::Binding::new(&actions.secondary, input(secondary)?),
+            ]);
+            // The runtime reads the select button as a trigger of 0 or 1
+            simple.push(xr::Binding::new(&actions.trigger, input("select/click")?));
+        }
+        instance.suggest_interaction_profile_bindings(
+            instance.string_to_path("/interaction_profiles/oculus/touch_controller")?,
+            &touch,
+        )?;
+        instance.suggest_interaction_profile_bindings(
+            instance.string_to_path("/interaction_profiles/khr/simple_controller")?,
+            &simple,
+        )?;
+        Ok(actions)
+    }
+}
+
+impl Gpu {
+    /// Create the Vulkan instance and device through the runtime, with the
+    /// extensions and features wgpu needs, and wrap them for wgpu
+    fn new(xr_instance: &xr::Instance, system: xr::SystemId) -> Result<Self, String> {
+        // Required before the runtime creates Vulkan objects
+        xr_instance
+            .graphics_requirements::<xr::Vulkan>(system)
+            .map_err(xr_error("graphics requirements query"))?;
+
+        let entry = unsafe { ash::Entry::load() }.map_err(|e| format!("Failed to load Vulkan: {}", e))?;
+        let get_instance_proc_addr = entry.static_fn().get_instance_proc_addr;
+        let flags = wgpu::InstanceFlags::from_build_config();
+        let extensions =
+            hal::vulkan::Instance::desired_extensions(&entry, VK_VERSION, flags).map_err(|e| e.to_string())?;
+        let extension_names: Vec<_> = extensions.iter().map(|name| name.as_ptr()).collect();
+        let app_info = vk::ApplicationInfo::default()
+            .application_name(c"fastn-shell")
+            .engine_name(c"fastn")
+            .api_version(VK_VERSION);
+        let create_info = vk::InstanceCreateInfo::default()
+            .application_info(&app_info)
+            .enabled_extension_names(&extension_names);
+        let raw_instance = unsafe {
+            xr_instance.create_vulkan_instance(
+                system,
+                std::mem::transmute::<vk::PFN_vkGetInstanceProcAddr, xr::sys::platform::VkGetInstanceProcAddr>(
+                    get_instance_proc_addr,
+                ),
+                &create_info as *const _ as *const _,
+            )
+        }
+        .map_err(xr_error("Vulkan instance creation"))?
+        .map_err(|e| format!("Failed to create the Vulkan instance: {}", vk::Result::from_raw(e)))?;
+        let ash_instance = unsafe { ash::Instance::load(entry.static_fn(), vk::Instance::from_raw(raw_instance as _)) };
+
+        let raw_physical_device = unsafe { xr_instance.vulkan_graphics_device(system, raw_instance) }
+            .map_err(xr_error("GPU query"))?;
+        let physical_device = vk::PhysicalDevice::from_raw(raw_physical_device as _);
+        let hal_instance = unsafe {
+            hal::vulkan::Instance::from_raw(
+                entry,
+                ash_instance.clone(),
+                VK_VERSION,
+                0,
+                None,
+                extensions,
+                flags,
+                wgpu::MemoryBudgetThresholds::default(),
+                false,
+                None,
+            )
+        }
+        .map_err(|e| e.to_string())?;
+        let exposed = hal_instance
+            .expose_adapter(physical_device)
+            .ok_or("The headset's GPU can't run the renderer")?;
+
+        // The device wgpu would create, with a graphics queue
+        let features = wgpu::Features::empty();
+        let device_extensions = exposed.adapter.required_device_extensions(features);
+        let mut device_features = exposed.adapter.physical_device_features(&device_extensions, features);
+        let queue_family_index = unsafe { ash_instance.get_physical_device_queue_family_properties(physical_device) }
+            .iter()
+            .position(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .ok_or("The headset's GPU has no graphics queue")? as u32;
+        let queue_priorities = [1.0];
+        let queue_info = vk::DeviceQueueCreateInfo::default()
+            .queue_family_index(queue_family_index)
+            .queue_priorities(&queue_priorities);
+        let device_extension_names: Vec<_> = device_extensions.iter().map(|name| name.as_ptr()).collect();
+        let device_info = device_features.add_to_device_create(
+            vk::DeviceCreateInfo::default()
+                .queue_create_infos(std::slice::from_ref(&queue_info))
+                .enabled_extension_names(&device_extension_names),
+        );
+        let raw_device = unsafe {
+            xr_instance.create_vulkan_device(
+                system,
+                std::mem::transmute::<vk::PFN_vkGetInstanceProcAddr, xr::sys::platform::VkGetInstanceProcAddr>(
+                    get_instance_proc_addr,
+                ),
+                raw_physical_device,
+                &device_info as *const _ as *const _,
+            )
+        }
+        .map_err(xr_error("Vulkan device creation"))?
+        .map_err(|e| format!("Failed to create the Vulkan device: {}", vk::Result::from_raw(e)))?;
+        let ash_device = unsafe { ash::Device::load(ash_instance.fp_v1_0(), vk::Device::from_raw(raw_device as _)) };
+
+        let open_device = unsafe {
+            exposed.adapter.device_from_raw(
+                ash_device,
+                None,
+                &device_extensions,
+                features,
+                &wgpu::MemoryHints::default(),
+                queue_family_index,
+                0,
+            )
+        }
+        .map_err(|e| e.to_string())?;
+        let instance = unsafe { wgpu::Instance::from_hal::<Vulkan>(hal_instance) };
+        let adapter = unsafe { instance.create_adapter_from_hal(exposed) };
+        let (device, queue) = unsafe {
+            adapter.create_device_from_hal(
+                open_device,
+                &wgpu::DeviceDescriptor {
+                    label: Some("XR Device"),
+                    required_features: features,
+                    required_limits: wgpu::Limits::default(),
+                    memory_hints: wgpu::MemoryHints::default(),
+                    trace: wgpu::Trace::Off,
+                    experimental_features: Default::default(),
+                },
+            )
+        }
+        .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            session_info: xr::vulkan::SessionCreateInfo {
+                instance: raw_instance,
+                physical_device: raw_physical_device,
+                device: raw_device,
+                queue_family_index,
+                queue_index: 0,
+            },
+        })
+    }
+}
+
+/// The OpenXR path of a hand's controller
+fn hand_path(hand: Hand) -> &'static str {
+    match hand {
+        Hand::Left => "/user/hand/left",
+        Hand::Right => "/user/hand/right",
+    }
+}
+
+/// Describes a failed OpenXR call
+fn xr_error(what: &'static str) -> impl Fn(xr::sys::Result) -> String {
+    move |e| format!("OpenXR {} failed: {}", what, e)
+}
+
+fn pose_matrix(pose: &xr::Posef) -> Mat4 {
+    let (p, o) = (pose.position, pose.orientation);
+    Mat4::from_rotation_translation(Quat::from_xyzw(o.x, o.y, o.z, o.w), Vec3::new(p.x, p.y, p.z))
+}
+
+fn transform_matrix(transform: &Transform) -> Mat4 {
+    Mat4::from_scale_rotation_translation(
+        Vec3::from_array(transform.scale),
+        Quat::from_array(transform.rotation),
+        Vec3::from_array(transform.position),
+    )
+}
+
+/// Projection for an eye's (asymmetric) field of view, with wgpu's 0..1
+/// depth range as `Mat4::perspective_rh`
+fn projection(fov: &xr::Fovf) -> Mat4 {
+    let (left, right) = (fov.angle_left.tan(), fov.angle_right.tan());
+    let (up, down) = (fov.angle_up.tan(), fov.angle_down.tan());
+    let (width, height) = (right - left, up - down);
+    Mat4::from_cols(
+        Vec4::new(2.0 / width, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 2.0 / height, 0.0, 0.0),
+        Vec4::new((right + left) / width, (up + down) / height, FAR / (NEAR - FAR), -1.0),
+        Vec4::new(0.0, 0.0, NEAR * FAR / (NEAR - FAR), 0.0),
+    )
+}
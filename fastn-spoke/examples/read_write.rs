@@ -0,0 +1,39 @@
+//! Write a file, read it back, and update it only if nobody else did
+//!
+//! Usage: cargo run -p fastn-spoke --example read_write -- <hub> <kosha> <path>
+//!
+//! Uses the spoke in SPOKE_HOME (or the default home), like the CLI.
+
+use fastn_spoke::{Error, Spoke};
+
+#[tokio::main]
+async fn main() -> fastn_spoke::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [hub, kosha, path] = args.as_slice() else {
+        eprintln!("Usage: read_write <hub> <kosha> <path>");
+        std::process::exit(1);
+    };
+
+    let spoke = Spoke::builder().open().await?;
+    let client = spoke.kosha(hub, kosha);
+
+    let version = client.write(path, b"hello from a program\n").await?;
+    println!("wrote {} at {}", path, version);
+
+    let file = client.read(path).await?;
+    println!("read back: {}", String::from_utf8_lossy(&file.content));
+
+    // Append a line, unless the file changed since it was read
+    let mut content = file.content;
+    content.extend_from_slice(b"and one more line\n");
+    match client.write_versioned(path, &content, file.version).await {
+        Ok(version) => println!("updated {} at {}", path, version),
+        Err(Error::Conflict { current, .. }) => println!("someone else wrote {} first: {:?}", path, current),
+        Err(e) => return Err(e),
+    }
+
+    for version in client.versions(path).await? {
+        println!("version {} ({} bytes)", version.timestamp, version.size);
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Keep a local directory in sync with a kosha, like `fastn-spoke sync --watch`
+//!
+//! Usage: cargo run -p fastn-spoke --example sync -- <local-dir> <hub> <kosha> <remote-path>
+//!
+//! Syncs once, then again whenever the hub pushes a change or a local file
+//! changes (checked every two seconds).
+
+use fastn_spoke::{Spoke, WatchEvent};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> fastn_spoke::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [local_dir, hub, kosha, remote_path] = args.as_slice() else {
+        eprintln!("Usage: sync <local-dir> <hub> <kosha> <remote-path>");
+        std::process::exit(1);
+    };
+
+    let spoke = Spoke::builder().open().await?;
+    let mut syncer = spoke.syncer(local_dir, hub, kosha, remote_path)?;
+    report(&syncer.sync().await?);
+
+    // `Watch::next` isn't cancel-safe, so it gets a task of its own
+    let mut watch = syncer.watch().await?;
+    let (tx, mut changes) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok(Some(event)) = watch.next().await {
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let remote_changed = tokio::select! {
+            Some(event) = changes.recv() => match event {
+                WatchEvent::File(change) => !syncer.is_own_change(&change),
+                WatchEvent::Missed(_) => true,
+                WatchEvent::Kv(_) => false,
+            },
+            _ = tokio::time::sleep(Duration::from_secs(2)) => false,
+        };
+        if remote_changed || syncer.local_changed() {
+            report(&syncer.sync().await?);
+        }
+    }
+}
+
+fn report(summary: &fastn_spoke::SyncSummary) {
+    if summary.is_empty() {
+        return;
+    }
+    println!(
+        "uploaded {:?}, downloaded {:?}, deleted {:?} remotely and {:?} locally, conflicts {:?}",
+        summary.uploaded, summary.downloaded, summary.deleted_remote, summary.deleted_local, summary.conflicts
+    );
+}
@@ -0,0 +1,30 @@
+//! Print a kosha's changes as they happen
+//!
+//! Usage: cargo run -p fastn-spoke --example watch -- <hub> <kosha> [path-prefix]
+//!
+//! `<hub>` must be 'self' or a known hub: hubs don't forward changes.
+
+use fastn_spoke::{Spoke, WatchEvent};
+
+#[tokio::main]
+async fn main() -> fastn_spoke::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("Usage: watch <hub> <kosha> [path-prefix]");
+        std::process::exit(1);
+    }
+    let prefix = args.get(2).map(String::as_str).unwrap_or_default();
+
+    let spoke = Spoke::builder().open().await?;
+    let mut watch = spoke.kosha(&args[0], &args[1]).watch(prefix).await?;
+
+    while let Some(event) = watch.next().await? {
+        match event {
+            WatchEvent::File(change) => println!("{:?} {} @ {}", change.kind, change.path, change.version),
+            WatchEvent::Kv(key) => println!("kv {}", key),
+            WatchEvent::Missed(count) => println!("missed {} changes", count),
+        }
+    }
+    println!("hub closed the connection");
+    Ok(())
+}
@@ -12,6 +12,8 @@ class FastnCore {
         this.wasm = null;
         this.appPtr = null;
         this.frameNumber = 0;
+        // Called with every input and XR event sent, for recordings
+        this.onUserInput = null;
     }
 
     async loadWasm(wasmPath) {
@@ -48,6 +50,9 @@ class FastnCore {
 
     sendEvent(event) {
         if (!this.wasm || this.appPtr === null) return [];
+        if (this.onUserInput && (event.category === "Input" || event.category === "Xr")) {
+            this.onUserInput(event);
+        }
 
         const eventJson = JSON.stringify(event);
         const eventBytes = new TextEncoder().encode(eventJson);
@@ -98,6 +103,52 @@ class FastnCore {
         });
     }
 
+    // action: Down | Up (with button) or Move (with dx, dy); x and y in CSS
+    // pixels from the top-left corner of the window
+    sendMouseEvent(action, x, y, extra = {}) {
+        return this.sendEvent({
+            category: "Input",
+            event: {
+                type: "Mouse",
+                action: action,
+                device_id: "mouse-0",
+                x: x,
+                y: y,
+                ...extra
+            }
+        });
+    }
+
+    // action: Start | Move | End | Cancel, for the touches that changed
+    sendTouchEvent(action, touches) {
+        return this.sendEvent({
+            category: "Input",
+            event: {
+                type: "Touch",
+                action: action,
+                device_id: "touch-0",
+                touches: Array.from(touches, (t) => ({
+                    id: t.identifier,
+                    x: t.clientX,
+                    y: t.clientY,
+                    force: t.force || null
+                }))
+            }
+        });
+    }
+
+    sendResizeEvent() {
+        return this.sendEvent({
+            category: "Lifecycle",
+            event: {
+                type: "Resize",
+                width: window.innerWidth,
+                height: window.innerHeight,
+                dpr: window.devicePixelRatio || 1.0
+            }
+        });
+    }
+
     sendFrameEvent(dt) {
         this.frameNumber++;
         return this.sendEvent({
@@ -111,12 +162,67 @@ class FastnCore {
         });
     }
 
-    sendXrSessionEvent(state) {
+    sendInitEvent(platform, capabilities) {
+        return this.sendEvent({
+            category: "Lifecycle",
+            event: {
+                type: "Init",
+                platform: platform,
+                viewport_width: window.innerWidth,
+                viewport_height: window.innerHeight,
+                dpr: window.devicePixelRatio || 1.0,
+                xr_supported: capabilities.xrSupported || false,
+                xr_immersive_vr: capabilities.xrImmersiveVr || false,
+                xr_immersive_ar: capabilities.xrImmersiveAr || false,
+                webrtc_supported: typeof RTCPeerConnection !== 'undefined',
+                websocket_supported: typeof WebSocket !== 'undefined',
+                features: (capabilities.features || []).concat(
+                    AssetManager.SUPPORTED_SCHEMES.map((scheme) => 'asset-scheme:' + scheme)
+                ),
+                accessibility: AccessibilityLayer.preferences(),
+                // three.js: right-handed, +Y up, meters
+                conventions: { handedness: "Right", up_axis: "Y", meters_per_unit: 1.0 }
+            }
+        });
+    }
+
+    // type: Pause | Resume | Shutdown
+    sendLifecycleEvent(type) {
+        return this.sendEvent({
+            category: "Lifecycle",
+            event: { type: type }
+        });
+    }
+
+    sendAccessibilityChangedEvent() {
+        return this.sendEvent({
+            category: "Lifecycle",
+            event: {
+                type: "AccessibilityChanged",
+                ...AccessibilityLayer.preferences()
+            }
+        });
+    }
+
+    // state: None | Starting | Active | Paused | Ending
+    // mode: ImmersiveVr | ImmersiveAr | null
+    sendXrSessionEvent(state, mode = null, domOverlay = false) {
         return this.sendEvent({
             category: "Xr",
             event: {
                 type: "SessionChanged",
-                state: state
+                state: state,
+                mode: mode,
+                dom_overlay: domOverlay
+            }
+        });
+    }
+
+    sendXrReferenceSpaceResetEvent() {
+        return this.sendEvent({
+            category: "Xr",
+            event: {
+                type: "ReferenceSpaceReset"
             }
         });
     }
@@ -161,7 +267,179 @@ class FastnCore {
 }
 
 // ============================================================================
-// Input Handler - Keyboard input tracking
+// Input Automation - Input scripts and session recordings
+// ============================================================================
+
+// Scripts are JSON Lines of steps (fastn_protocol::ScriptStep): raw events,
+// key sequences, pointer paths and controller poses, each `at_ms` after the
+// script starts. Recordings are scripts of raw events. `?script=<url>` plays
+// a script once the app is loaded and `?record` records from the start; on a
+// headset, drive `window.fastnAutomation` from remote devtools.
+class InputAutomation {
+    static KEY_INTERVAL_MS = 100;
+    static POINTER_PATH_INTERVAL_MS = 16;
+
+    constructor(core) {
+        this.core = core;
+        this.queue = []; // { due, event } in time order
+        this.recording = null; // { start, lines }
+        core.onUserInput = (event) => this.record(event);
+    }
+
+    // Play the script in `?script=` and record if `?record` is set
+    static async fromUrl(core) {
+        const automation = new InputAutomation(core);
+        window.fastnAutomation = automation;
+        const params = new URLSearchParams(window.location.search);
+        if (params.has('record')) {
+            automation.startRecording();
+        }
+        const script = params.get('script');
+        if (script) {
+            const response = await fetch(script);
+            if (!response.ok) {
+                throw new Error(`Failed to load input script ${script}: ${response.status}`);
+            }
+            automation.play(await response.text());
+        }
+        return automation;
+    }
+
+    // Schedule a script's events from now. Throws on invalid lines.
+    play(script) {
+        const now = performance.now();
+        const events = InputAutomation.parse(script).map((e) => ({ due: now + e.at_ms, event: e.event }));
+        this.queue = this.queue.concat(events).sort((a, b) => a.due - b.due);
+        console.log(`Playing input script: ${events.length} events`);
+        return events.length;
+    }
+
+    // Send the events whose time has come, returning the core's commands
+    sendDue() {
+        const now = performance.now();
+        let commands = [];
+        while (this.queue.length > 0 && this.queue[0].due <= now) {
+            commands = commands.concat(this.core.sendEvent(this.queue.shift().event));
+        }
+        return commands;
+    }
+
+    startRecording() {
+        this.recording = { start: performance.now(), lines: [] };
+        console.log('Recording input; call fastnAutomation.saveRecording() to download it');
+    }
+
+    record(event) {
+        if (!this.recording) return;
+        const atMs = Math.round(performance.now() - this.recording.start);
+        this.recording.lines.push(JSON.stringify({ at_ms: atMs, type: "Event", event }));
+    }
+
+    // Stop recording and download it as fastn-input.jsonl
+    saveRecording() {
+        const recording = this.recording;
+        if (!recording) return 0;
+        this.recording = null;
+        const blob = new Blob([recording.lines.join('\n') + '\n'], { type: 'application/jsonl' });
+        MediaCapture.download(blob, 'fastn-input.jsonl');
+        return recording.lines.length;
+    }
+
+    // A script's events, in time order: [{ at_ms, event }]
+    static parse(script) {
+        const events = [];
+        script.split('\n').forEach((line, index) => {
+            line = line.trim();
+            if (line === '' || line.startsWith('#')) return;
+            try {
+                events.push(...InputAutomation.expand(JSON.parse(line)));
+            } catch (e) {
+                throw new Error(`line ${index + 1}: ${e.message}`);
+            }
+        });
+        return events.sort((a, b) => a.at_ms - b.at_ms);
+    }
+
+    static expand(step) {
+        const at = (offset, event) => ({ at_ms: step.at_ms + offset, event });
+        switch (step.type) {
+            case 'Event':
+                // Recordings of whole sessions also hold frames and the like
+                return ['Input', 'Xr'].includes(step.event.category) ? [at(0, step.event)] : [];
+            case 'Command':
+                return [];
+            case 'Keys': {
+                const interval = step.interval_ms ?? InputAutomation.KEY_INTERVAL_MS;
+                return step.codes.flatMap((code, index) => [
+                    at(index * interval, InputAutomation.keyEvent('KeyDown', code)),
+                    at(index * interval + Math.floor(interval / 2), InputAutomation.keyEvent('KeyUp', code)),
+                ]);
+            }
+            case 'PointerPath':
+                return InputAutomation.pointerPath(step.points, step.duration_ms, step.button)
+                    .map(([offset, event]) => at(offset, event));
+            case 'ControllerPose': {
+                const { at_ms, type, ...controller } = step;
+                return [at(0, { category: "Xr", event: { type: "ControllerPose", ...controller } })];
+            }
+            default:
+                throw new Error(`unknown step type ${step.type}`);
+        }
+    }
+
+    static keyEvent(action, code) {
+        return {
+            category: "Input",
+            event: {
+                type: "Keyboard", action, device_id: "script-keyboard", key: code, code,
+                shift: false, ctrl: false, alt: false, meta: false, repeat: false,
+            },
+        };
+    }
+
+    // Mouse moves along the path at a steady speed, with the button (if
+    // any) held from the first point to the last
+    static pointerPath(points, durationMs, button) {
+        if (points.length === 0) return [];
+        const mouse = (action, [x, y], extra) => ({
+            category: "Input",
+            event: { type: "Mouse", action, device_id: "script-mouse", x, y, ...extra },
+        });
+        const lengths = points.slice(1).map((p, i) => Math.hypot(p[0] - points[i][0], p[1] - points[i][1]));
+        const total = lengths.reduce((a, b) => a + b, 0);
+        const pointAt = (t) => {
+            let remaining = t * total;
+            for (let i = 0; i < lengths.length; i++) {
+                if (remaining <= lengths[i] && lengths[i] > 0) {
+                    const f = remaining / lengths[i];
+                    return [0, 1].map((axis) => points[i][axis] + (points[i + 1][axis] - points[i][axis]) * f);
+                }
+                remaining -= lengths[i];
+            }
+            return points[points.length - 1];
+        };
+
+        const events = [];
+        const steps = Math.max(1, Math.floor(durationMs / InputAutomation.POINTER_PATH_INTERVAL_MS));
+        let last = points[0];
+        for (let step = 0; step <= steps; step++) {
+            const point = pointAt(step / steps);
+            const offset = Math.floor(durationMs * step / steps);
+            events.push([offset, mouse('Move', point, { dx: point[0] - last[0], dy: point[1] - last[1] })]);
+            last = point;
+            if (step === 0 && button) {
+                events.push([0, mouse('Down', points[0], { button })]);
+            }
+        }
+        if (button) {
+            events.push([durationMs, mouse('Up', last, { button })]);
+        }
+        return events;
+    }
+}
+
+// ============================================================================
+// Input Handler - Keyboard, mouse and touch input
 // ============================================================================
 
 class InputHandler {
@@ -169,12 +447,19 @@ class InputHandler {
         this.core = core;
         this.pressedKeys = new Set();
         this.commandHandler = null;
+        // Called with the phase (Down, Move, Up) and screen point of the
+        // left button or first touch, for panels
+        this.pointerHandler = null;
     }
 
     setCommandHandler(handler) {
         this.commandHandler = handler;
     }
 
+    setPointerHandler(handler) {
+        this.pointerHandler = handler;
+    }
+
     setup(canvas) {
         window.addEventListener('keydown', (e) => {
             if (!this.pressedKeys.has(e.code)) {
@@ -205,6 +490,53 @@ class InputHandler {
             }
         });
 
+        // Mouse and touch, for taps and manipulation handles
+        const MOUSE_BUTTONS = ['Left', 'Middle', 'Right', 'Back', 'Forward'];
+        const send = (commands) => {
+            if (this.commandHandler) {
+                this.commandHandler(commands);
+            }
+        };
+        const pointer = (phase, x, y) => {
+            if (this.pointerHandler) {
+                this.pointerHandler(phase, x, y);
+            }
+        };
+        canvas.addEventListener('mousedown', (e) => {
+            if (MOUSE_BUTTONS[e.button]) {
+                send(this.core.sendMouseEvent('Down', e.clientX, e.clientY, { button: MOUSE_BUTTONS[e.button] }));
+            }
+            if (e.button === 0) pointer('Down', e.clientX, e.clientY);
+        });
+        window.addEventListener('mouseup', (e) => {
+            if (MOUSE_BUTTONS[e.button]) {
+                send(this.core.sendMouseEvent('Up', e.clientX, e.clientY, { button: MOUSE_BUTTONS[e.button] }));
+            }
+            if (e.button === 0) pointer('Up', e.clientX, e.clientY);
+        });
+        window.addEventListener('mousemove', (e) => {
+            send(this.core.sendMouseEvent('Move', e.clientX, e.clientY, { dx: e.movementX, dy: e.movementY }));
+            pointer('Move', e.clientX, e.clientY);
+        });
+        const TOUCH_PHASES = { Start: 'Down', Move: 'Move', End: 'Up' };
+        for (const [name, action] of [['touchstart', 'Start'], ['touchmove', 'Move'], ['touchend', 'End'], ['touchcancel', 'Cancel']]) {
+            canvas.addEventListener(name, (e) => {
+                // Keep the browser from scrolling or sending emulated mouse events
+                e.preventDefault();
+                send(this.core.sendTouchEvent(action, e.changedTouches));
+                if (TOUCH_PHASES[action] && e.changedTouches.length > 0) {
+                    pointer(TOUCH_PHASES[action], e.changedTouches[0].clientX, e.changedTouches[0].clientY);
+                }
+            }, { passive: false });
+        }
+        window.addEventListener('resize', () => send(this.core.sendResizeEvent()));
+
+        // Backgrounded and closed tabs, so the app can save its state in time
+        document.addEventListener('visibilitychange', () => {
+            send(this.core.sendLifecycleEvent(document.visibilityState === 'hidden' ? 'Pause' : 'Resume'));
+        });
+        window.addEventListener('pagehide', () => send(this.core.sendLifecycleEvent('Shutdown')));
+
         // Focus canvas for keyboard events
         canvas.tabIndex = 0;
         canvas.focus();
@@ -216,8 +548,12 @@ class InputHandler {
 // ============================================================================
 
 class SceneState {
+    // Volumes are placed relative to their parent (parent_id, SetParent)
+    static FEATURE = 'scene-hierarchy';
+
     constructor() {
         this.volumes = new Map();
+        this.portals = new Map();
         this.camera = {
             position: [0, 1.6, 3],
             target: [0, 0, 0],
@@ -229,6 +565,81 @@ class SceneState {
         this.assetManager = new AssetManager();
         this.pendingAssets = []; // Assets to be loaded
         this.onVolumeCreated = null; // Callback for custom mesh creation
+        this.onXrCommand = null; // Callback for XR session commands (Enter/Exit)
+        // Callback for events the shell raises itself (deferred command acks,
+        // accessibility activations), to be sent to the core
+        this.onShellEvent = null;
+        // Deferred commands run a few per frame
+        this.scheduler = new CommandScheduler(async (command) => {
+            const failed = await this.processCommands([command]);
+            if (failed.length > 0) {
+                throw new Error(`Failed to load asset ${failed.join(', ')}`);
+            }
+        });
+        this.scheduler.onAck = (event) => this.raiseEvent(event);
+        // SetTransform interpolation
+        this.animations = new TransformAnimations();
+        // FPS, draw calls, entity count and log tail overlay (browsers only)
+        this.hud = typeof document !== 'undefined' ? new DebugHud() : null;
+        // Screen-reader mirror of the scene (browsers only)
+        this.accessibility = typeof document !== 'undefined' ? new AccessibilityLayer() : null;
+        if (this.accessibility) {
+            this.accessibility.onActivate = (volumeId) => this.raiseEvent({
+                category: "Accessibility",
+                event: { type: "Activate", volume_id: volumeId }
+            });
+        }
+        // Photos and clips of the rendered view (browsers only)
+        this.capture = typeof document !== 'undefined' ? new MediaCapture() : null;
+        if (this.capture) {
+            this.capture.onEvent = (event) => this.raiseEvent(event);
+        }
+        // 2D panels in the scene (browsers only)
+        this.panels = typeof document !== 'undefined' ? new PanelLayer() : null;
+        // Sound sources (browsers with WebAudio only)
+        this.audio = typeof AudioContext !== 'undefined' ? new SpatialAudio(this.assetManager) : null;
+        if (this.audio) {
+            this.audio.onEvent = (event) => this.raiseEvent(event);
+        }
+        // Values kept across sessions; without localStorage every command
+        // answers with an error
+        this.storage = new AppStorage();
+        this.storage.onEvent = (event) => this.raiseEvent(event);
+        // Full-screen fade, e.g. over a teleport (browsers only)
+        this.fade = typeof document !== 'undefined' ? new FadeOverlay() : null;
+    }
+
+    raiseEvent(event) {
+        if (this.onShellEvent) {
+            this.onShellEvent(event);
+        }
+    }
+
+    // Call once per frame to spend the frame budget on deferred commands
+    runScheduled() {
+        this.scheduler.runFrame();
+    }
+
+    // Call once per frame, before drawing, to move animated volumes
+    updateAnimations() {
+        for (const event of this.animations.update(this.volumes, performance.now())) {
+            this.raiseEvent(event);
+        }
+    }
+
+    // Renderers report the draw calls of each frame for the debug HUD
+    endFrame(drawCalls) {
+        if (this.hud) {
+            this.hud.endFrame(drawCalls);
+        }
+    }
+
+    // Renderers call this after drawing, with the framebuffer still bound,
+    // for pending photos and the running recording
+    captureFrame(gl, viewport) {
+        if (this.capture) {
+            this.capture.captureFrame(gl, viewport);
+        }
     }
 
     async processCommands(commands) {
@@ -239,6 +650,8 @@ class SceneState {
                     this.pendingAssets.push({
                         asset_id: cmd.command.asset_id,
                         path: cmd.command.path,
+                        priority: cmd.command.priority || 'Normal',
+                        cache: cmd.command.cache || 'Default',
                     });
                 }
                 continue;
@@ -252,6 +665,65 @@ class SceneState {
                     this.camera.fov = cmd.command.fov_degrees * Math.PI / 180;
                     this.camera.near = cmd.command.near;
                     this.camera.far = cmd.command.far;
+                } else if (cmd.command.action === "SetFade") {
+                    if (this.fade) {
+                        this.fade.set(cmd.command.color);
+                    }
+                }
+                continue;
+            }
+
+            if (cmd.category === "Storage" && cmd.command) {
+                this.storage.handle(cmd.command);
+                continue;
+            }
+
+            if (cmd.category === "Xr" && cmd.command) {
+                if (this.onXrCommand) {
+                    this.onXrCommand(cmd.command);
+                }
+                continue;
+            }
+
+            if (cmd.category === "Schedule" && cmd.command) {
+                this.scheduler.handle(cmd.command);
+                continue;
+            }
+
+            if (cmd.category === "Debug" && cmd.command) {
+                if (cmd.command.action === "Log") {
+                    DebugHud.console(cmd.command.level, cmd.command.message);
+                }
+                if (this.hud) {
+                    this.hud.handle(cmd.command);
+                }
+                continue;
+            }
+
+            if (cmd.category === "Media" && cmd.command) {
+                if (this.capture) {
+                    this.capture.handle(cmd.command);
+                }
+                continue;
+            }
+
+            if (cmd.category === "Audio" && cmd.command) {
+                if (this.audio) {
+                    this.audio.handle(cmd.command);
+                }
+                continue;
+            }
+
+            if (cmd.category === "Accessibility" && cmd.command) {
+                if (this.accessibility) {
+                    this.accessibility.handle(cmd.command);
+                }
+                continue;
+            }
+
+            if (cmd.category === "Panel" && cmd.command) {
+                if (this.panels) {
+                    this.handlePanel(cmd.command);
                 }
                 continue;
             }
@@ -260,26 +732,65 @@ class SceneState {
                 if (cmd.command.action === "CreateVolume") {
                     this.handleCreateVolume(cmd.command);
                 } else if (cmd.command.action === "DestroyVolume") {
-                    this.volumes.delete(cmd.command.volume_id);
+                    this.destroyVolume(cmd.command.volume_id);
+                } else if (cmd.command.action === "SetParent") {
+                    this.setParent(cmd.command.volume_id, cmd.command.parent_id);
+                } else if (cmd.command.action === "SetTransform") {
+                    const volume = this.volumes.get(cmd.command.volume_id);
+                    if (volume) {
+                        this.animations.set(volume, cmd.command, performance.now());
+                    }
+                } else if (cmd.command.action === "CreatePortal") {
+                    this.handleCreatePortal(cmd.command);
+                } else if (cmd.command.action === "DestroyPortal") {
+                    this.portals.delete(cmd.command.portal_id);
+                } else if (cmd.command.action === "RequestHitTest") {
+                    this.raiseEvent({
+                        category: "Scene",
+                        event: {
+                            type: "HitTestResult",
+                            request_id: cmd.command.request_id,
+                            hit: Picking.hitTest(this, cmd.command.source),
+                        }
+                    });
                 }
                 continue;
             }
         }
 
-        // Load any pending assets
-        await this.loadPendingAssets();
+        // Load any pending assets, returns the IDs of those that failed
+        return this.loadPendingAssets();
     }
 
+    // High priority assets load first and Low ones (prefetches) last; the
+    // sort keeps the order of the rest
     async loadPendingAssets() {
-        for (const asset of this.pendingAssets) {
-            await this.assetManager.load(asset.asset_id, asset.path);
-        }
+        const failed = [];
+        const rank = (asset) => CommandScheduler.PRIORITIES.indexOf(asset.priority);
+        const assets = this.pendingAssets.sort((a, b) => rank(a) - rank(b));
         this.pendingAssets = [];
+        for (const asset of assets) {
+            if (!await this.assetManager.load(asset.asset_id, asset.path, asset.cache)) {
+                failed.push(asset.asset_id);
+            }
+        }
+        return failed;
     }
 
     handleCreateVolume(cmd) {
         console.log('CreateVolume:', cmd);
 
+        // Text needs a glyph atlas the web renderers don't have; the core
+        // knows from the missing "text" feature
+        if (cmd.source && cmd.source.Primitive && cmd.source.Primitive.Text) {
+            console.warn('Text is not drawn by this shell:', cmd.volume_id);
+            this.raiseEvent({
+                category: "Scene",
+                event: { type: "VolumeReady", volume_id: cmd.volume_id }
+            });
+            return;
+        }
+
         let color = [1.0, 1.0, 1.0, 1.0];
         if (cmd.material && cmd.material.color) {
             color = cmd.material.color;
@@ -291,14 +802,18 @@ class SceneState {
 
         if (cmd.source) {
             if (cmd.source.Primitive) {
-                if (cmd.source.Primitive.Cube) {
-                    size = cmd.source.Primitive.Cube.size;
-                } else if (cmd.source.Primitive.Sphere) {
-                    size = cmd.source.Primitive.Sphere.radius * 2;
-                } else if (cmd.source.Primitive.Box) {
-                    size = Math.max(cmd.source.Primitive.Box.width,
-                                   cmd.source.Primitive.Box.height,
-                                   cmd.source.Primitive.Box.depth);
+                const [shape, params] = Object.entries(cmd.source.Primitive)[0];
+                if (shape === 'Cube') {
+                    size = params.size;
+                } else {
+                    // Other shapes are generated at their size and drawn
+                    // like assets; unknown ones stay cubes
+                    assetId = this.assetManager.primitiveMesh(shape, params);
+                    if (assetId) {
+                        meshType = 'asset';
+                    } else {
+                        console.warn('Unknown primitive', shape, 'drawn as a cube:', cmd.volume_id);
+                    }
                 }
             } else if (cmd.source.Asset) {
                 meshType = 'asset';
@@ -313,11 +828,20 @@ class SceneState {
 
         const transform = cmd.transform || {};
         const position = transform.position || [0, 0, 0];
+        const rotation = transform.rotation || [0, 0, 0, 1];
         const scale = transform.scale || [1, 1, 1];
 
+        // Children hold their parent's volume, so they follow it as it moves
+        const parent = cmd.parent_id ? this.volumes.get(cmd.parent_id) || null : null;
+        if (cmd.parent_id && !parent) {
+            console.warn('Unknown parent', cmd.parent_id, 'of', cmd.volume_id, '- placing it at the top level');
+        }
+
         const volume = {
             id: cmd.volume_id,
+            parent: parent,
             position: position,
+            rotation: rotation,
             scale: scale,
             size: size,
             color: color,
@@ -335,69 +859,854 @@ class SceneState {
         }
 
         console.log('Added volume:', volume);
+        this.raiseEvent({
+            category: "Scene",
+            event: { type: "VolumeReady", volume_id: cmd.volume_id }
+        });
+    }
+
+    // Remove a volume and its descendants
+    destroyVolume(volumeId) {
+        for (const volume of [...this.volumes.values()]) {
+            if (SceneState.ancestors(volume).some((ancestor) => ancestor.id === volumeId)) {
+                this.volumes.delete(volume.id);
+                this.animations.stop(volume.id);
+            }
+        }
+    }
+
+    // Move a volume under another (or to the top level), keeping its
+    // transform, which is now relative to the new parent
+    setParent(volumeId, parentId) {
+        const volume = this.volumes.get(volumeId);
+        const parent = parentId ? this.volumes.get(parentId) : null;
+        if (!volume || parent === undefined) {
+            console.warn('SetParent of unknown volume:', volumeId, parentId);
+            return;
+        }
+        if (parent && SceneState.ancestors(parent).includes(volume)) {
+            console.warn('SetParent would make', volumeId, 'its own ancestor');
+            return;
+        }
+        volume.parent = parent;
+    }
+
+    // The volume and its ancestors, nearest first
+    static ancestors(volume) {
+        const chain = [];
+        for (let v = volume; v; v = v.parent) {
+            chain.push(v);
+        }
+        return chain;
+    }
+
+    // A panel is drawn as a quad volume with the panel's ID; it ignores its
+    // transform's scale
+    handlePanel(cmd) {
+        const panel = this.panels.panels.get(cmd.panel_id);
+        if (cmd.action === "Create") {
+            if (panel) {
+                console.warn(`Panel ${cmd.panel_id} exists already`);
+                return;
+            }
+            const transform = cmd.transform || {};
+            this.volumes.set(cmd.panel_id, {
+                id: cmd.panel_id,
+                parent: null,
+                position: transform.position || [0, 0, 0],
+                rotation: transform.rotation || [0, 0, 0, 1],
+                scale: [1, 1, 1],
+                size: 1,
+                color: PanelLayer.BACKGROUND_COLOR,
+                meshType: 'asset',
+                assetId: this.assetManager.primitiveMesh('Quad', { width: cmd.width, height: cmd.height }),
+                customBuffers: null,
+                panel: this.panels.create(cmd),
+            });
+            if (this.onVolumeCreated) {
+                this.onVolumeCreated(this.volumes.get(cmd.panel_id), this.assetManager);
+            }
+        } else if (!panel) {
+            console.warn(`${cmd.action} for unknown panel ${cmd.panel_id}`);
+        } else if (cmd.action === "SetContent") {
+            this.panels.setContent(panel, cmd.content);
+        } else if (cmd.action === "SetTransform") {
+            this.animations.set(this.volumes.get(cmd.panel_id), {
+                volume_id: cmd.panel_id,
+                transform: { ...cmd.transform, scale: [1, 1, 1] },
+            }, performance.now());
+        } else if (cmd.action === "Destroy") {
+            this.panels.destroy(cmd.panel_id);
+            this.destroyVolume(cmd.panel_id);
+        }
+    }
+
+    // Mouse and touch input, also sent as panel events when over a panel
+    // (or after a press on one)
+    panelPointer(phase, x, y) {
+        const event = this.panels ? this.panels.pointer(this, phase, x, y) : null;
+        if (event) {
+            this.raiseEvent(event);
+        }
+    }
+
+    handleCreatePortal(cmd) {
+        const entrance = MathUtils.poseMatrix(cmd.entrance.position, cmd.entrance.rotation);
+        const exit = MathUtils.poseMatrix(cmd.exit.position, cmd.exit.rotation);
+
+        this.portals.set(cmd.portal_id, {
+            id: cmd.portal_id,
+            position: cmd.entrance.position,
+            // Unit quad in the XY plane -> portal surface
+            surface: MathUtils.poseMatrix(cmd.entrance.position, cmd.entrance.rotation, [cmd.width, cmd.height, 1]),
+            // Moves the exit onto the entrance: view * remoteToLocal is the
+            // view through the portal
+            remoteToLocal: MathUtils.multiplyMatrices(entrance, MathUtils.invertRigid(exit)),
+            // Plane of the exit surface, geometry in front of it is clipped
+            exitPosition: cmd.exit.position,
+            exitNormal: [exit[8], exit[9], exit[10]],
+        });
     }
 }
 
 // ============================================================================
-// Math Utilities - Shared between renderers
+// Command Scheduler - Spreads deferred commands across frames
 // ============================================================================
 
-const MathUtils = {
-    normalize(v) {
-        const len = Math.sqrt(v[0]*v[0] + v[1]*v[1] + v[2]*v[2]);
-        if (len === 0) return [0, 0, 0];
-        return [v[0]/len, v[1]/len, v[2]/len];
-    },
+class CommandScheduler {
+    static FEATURE = 'command-scheduling';
+    static DEFAULT_BUDGET_MS = 4.0;
+    static PRIORITIES = ['High', 'Normal', 'Low'];
+
+    constructor(execute) {
+        this.execute = execute; // async (command) => void
+        this.onAck = null; // (event) => void
+        this.queue = []; // Sorted by priority, then arrival
+        this.budgetMs = CommandScheduler.DEFAULT_BUDGET_MS;
+        this.running = false;
+    }
 
-    subtract(a, b) {
-        return [a[0]-b[0], a[1]-b[1], a[2]-b[2]];
-    },
+    handle(cmd) {
+        if (cmd.action === "Defer") {
+            const rank = CommandScheduler.PRIORITIES.indexOf(cmd.priority || 'Normal');
+            const entry = { id: cmd.command_id, rank: rank < 0 ? 1 : rank, command: cmd.command };
+            // Insert after everything of the same or higher priority
+            const index = this.queue.findIndex((queued) => queued.rank > entry.rank);
+            this.queue.splice(index < 0 ? this.queue.length : index, 0, entry);
+        } else if (cmd.action === "Cancel") {
+            this.queue = this.queue.filter((queued) => queued.id !== cmd.command_id);
+        } else if (cmd.action === "SetFrameBudget") {
+            this.budgetMs = cmd.budget_ms;
+        }
+    }
 
-    cross(a, b) {
-        return [
-            a[1]*b[2] - a[2]*b[1],
-            a[2]*b[0] - a[0]*b[2],
-            a[0]*b[1] - a[1]*b[0],
-        ];
-    },
+    // Run queued commands until this frame's budget is used up. At least one
+    // command runs per frame so the queue always drains; a command that is
+    // still loading (e.g. an asset fetch) holds the queue until it finishes.
+    async runFrame() {
+        if (this.running || this.queue.length === 0) return;
+        this.running = true;
+        const start = performance.now();
+        try {
+            do {
+                const entry = this.queue.shift();
+                try {
+                    await this.execute(entry.command);
+                    this.ack({ type: "Completed", command_id: entry.id });
+                } catch (e) {
+                    this.ack({ type: "Failed", command_id: entry.id, error: String(e) });
+                }
+            } while (this.queue.length > 0 && performance.now() - start < this.budgetMs);
+        } finally {
+            this.running = false;
+        }
+    }
 
-    dot(a, b) {
-        return a[0]*b[0] + a[1]*b[1] + a[2]*b[2];
-    },
+    ack(event) {
+        if (this.onAck) {
+            this.onAck({ category: "Schedule", event: event });
+        }
+    }
+}
 
-    // Right-handed look-at matrix (matches glam's Mat4::look_at_rh)
-    lookAtRH(eye, target, up) {
-        const zAxis = this.normalize(this.subtract(eye, target));
-        const xAxis = this.normalize(this.cross(up, zAxis));
-        const yAxis = this.cross(zAxis, xAxis);
+// ============================================================================
+// Accessibility Layer - Mirrors the accessibility tree into hidden ARIA DOM
+// ============================================================================
 
-        return new Float32Array([
-            xAxis[0], yAxis[0], zAxis[0], 0,
-            xAxis[1], yAxis[1], zAxis[1], 0,
-            xAxis[2], yAxis[2], zAxis[2], 0,
-            -this.dot(xAxis, eye), -this.dot(yAxis, eye), -this.dot(zAxis, eye), 1,
-        ]);
-    },
+class AccessibilityLayer {
+    static FEATURE = 'accessibility-tree';
 
-    // Perspective projection matrix
-    perspectiveRH(fov, aspect, near, far) {
-        const f = 1.0 / Math.tan(fov / 2);
-        return new Float32Array([
-            f / aspect, 0, 0, 0,
-            0, f, 0, 0,
-            0, 0, (far + near) / (near - far), -1,
-            0, 0, (2 * far * near) / (near - far), 0,
-        ]);
+    // Platform settings, sent with Init and AccessibilityChanged. Browsers
+    // don't reveal whether a screen reader is running.
+    static preferences() {
+        const matches = (query) => typeof window !== 'undefined' && window.matchMedia
+            && window.matchMedia(query).matches;
+        return {
+            high_contrast: !!(matches('(prefers-contrast: more)') || matches('(forced-colors: active)')),
+            reduced_motion: !!matches('(prefers-reduced-motion: reduce)'),
+            screen_reader: false,
+        };
+    }
+
+    // Call `callback` whenever the preferences change
+    static watchPreferences(callback) {
+        if (typeof window === 'undefined' || !window.matchMedia) return;
+        for (const query of ['(prefers-contrast: more)', '(forced-colors: active)', '(prefers-reduced-motion: reduce)']) {
+            window.matchMedia(query).addEventListener('change', callback);
+        }
+    }
+
+    constructor() {
+        this.onActivate = null; // (volumeId) => void
+        this.root = AccessibilityLayer.hidden(document.createElement('div'));
+        this.root.setAttribute('role', 'application');
+        this.root.setAttribute('aria-label', document.title || 'Scene');
+        this.tree = document.createElement('div');
+        this.politeRegion = this.liveRegion('polite');
+        this.assertiveRegion = this.liveRegion('assertive');
+        this.root.append(this.tree, this.politeRegion, this.assertiveRegion);
+        document.body.appendChild(this.root);
+    }
+
+    // Visible to screen readers only
+    static hidden(element) {
+        Object.assign(element.style, {
+            position: 'absolute', width: '1px', height: '1px', overflow: 'hidden',
+            clip: 'rect(0 0 0 0)', clipPath: 'inset(50%)', whiteSpace: 'nowrap',
+        });
+        return element;
+    }
+
+    liveRegion(politeness) {
+        const region = document.createElement('div');
+        region.setAttribute('aria-live', politeness);
+        region.setAttribute('aria-atomic', 'true');
+        return region;
+    }
+
+    handle(cmd) {
+        if (cmd.action === "SetTree") {
+            this.setTree(cmd.nodes);
+        } else if (cmd.action === "Announce") {
+            const region = cmd.politeness === 'Assertive' ? this.assertiveRegion : this.politeRegion;
+            // Clear first so repeating the same message is announced again
+            region.textContent = '';
+            setTimeout(() => { region.textContent = cmd.message; }, 50);
+        }
+    }
+
+    setTree(nodes) {
+        // Element each node's children are appended to
+        const containers = new Map();
+        this.tree.replaceChildren();
+        for (const node of nodes) {
+            const element = this.createElement(node);
+            const container = (node.parent && containers.get(node.parent)) || this.tree;
+            container.appendChild(element);
+            // Only groups and landmarks can contain other nodes; children of
+            // images, buttons and text follow them in their parent instead
+            const isContainer = node.role === 'Group' || node.role === 'Landmark';
+            containers.set(node.volume_id, isContainer ? element : container);
+        }
+    }
+
+    createElement(node) {
+        let element;
+        if (node.role === 'Button') {
+            element = document.createElement('button');
+            element.textContent = node.label;
+            element.addEventListener('click', () => {
+                if (this.onActivate) {
+                    this.onActivate(node.volume_id);
+                }
+            });
+        } else if (node.role === 'Text') {
+            element = document.createElement('p');
+            element.textContent = node.label;
+        } else {
+            element = document.createElement(node.role === 'Landmark' ? 'section' : 'div');
+            element.setAttribute('role', { Group: 'group', Image: 'img', Landmark: 'region' }[node.role]);
+            element.setAttribute('aria-label', node.label);
+        }
+        if (node.description) {
+            element.setAttribute('aria-description', node.description);
+        }
+        return element;
+    }
+}
+
+// ============================================================================
+// App Storage - Per-app values kept in localStorage (StorageCommand)
+// ============================================================================
+
+class AppStorage {
+    // Keys are kept under the page's path, so apps served from one origin
+    // don't see each other's values
+    constructor(namespace = typeof location !== 'undefined' ? location.pathname : '') {
+        this.prefix = `fastn:${namespace}:`;
+        this.onEvent = null; // (event) => void
+    }
+
+    handle(cmd) {
+        try {
+            if (cmd.action === "Get") {
+                // null (unset) is the core's None
+                const value = localStorage.getItem(this.prefix + cmd.key);
+                this.raise({ type: "Loaded", key: cmd.key, value });
+            } else if (cmd.action === "Set") {
+                localStorage.setItem(this.prefix + cmd.key, cmd.value);
+            } else if (cmd.action === "Remove") {
+                localStorage.removeItem(this.prefix + cmd.key);
+            }
+        } catch (e) {
+            // Storage disabled for the page, or over its quota
+            this.raise({ type: "Error", key: cmd.key, error: String(e) });
+        }
+    }
+
+    raise(event) {
+        if (this.onEvent) {
+            this.onEvent({ category: "Storage", event });
+        }
+    }
+}
+
+// ============================================================================
+// Fade Overlay - Full-screen color over the page (SetFade)
+// ============================================================================
+
+class FadeOverlay {
+    constructor() {
+        this.element = null; // Created by the first fade
+    }
+
+    // `color` is RGBA from 0 to 1; an alpha of 0 hides the overlay. Being
+    // DOM, it isn't seen inside immersive sessions.
+    set(color) {
+        if (!this.element) {
+            this.element = document.createElement('div');
+            this.element.setAttribute('aria-hidden', 'true');
+            Object.assign(this.element.style, {
+                position: 'fixed', inset: '0', zIndex: 999, pointerEvents: 'none',
+            });
+            document.body.appendChild(this.element);
+        }
+        const [r, g, b, a] = color.map((c) => Math.min(Math.max(c, 0), 1));
+        this.element.style.backgroundColor = `rgba(${r * 255}, ${g * 255}, ${b * 255}, ${a})`;
+        this.element.style.display = a > 0 ? 'block' : 'none';
+    }
+}
+
+// ============================================================================
+// Debug HUD - FPS, draw calls, entity count and the last log messages
+// ============================================================================
+
+class DebugHud {
+    static FEATURE = 'debug-hud';
+    static LOG_LINES = 8;
+    static LEVEL_COLORS = { Debug: '#888', Info: '#ddd', Warn: '#ffcc66', Error: '#ff6b6b' };
+
+    // Core log messages go to the console too, at their level
+    static console(level, message) {
+        const log = { Debug: console.debug, Info: console.info, Warn: console.warn, Error: console.error }[level];
+        (log || console.log)(`[Core] ${message}`);
+    }
+
+    constructor() {
+        this.visible = false;
+        this.entityCount = 0;
+        this.logs = []; // Last LOG_LINES { level, message }
+        this.frameTimes = []; // Frame end times over the last second
+        this.drawCalls = 0;
+        this.element = null; // Created when first shown
+        this.lastUpdate = 0;
+    }
+
+    handle(cmd) {
+        if (cmd.action === "Log") {
+            this.logs.push({ level: cmd.level, message: cmd.message });
+            this.logs = this.logs.slice(-DebugHud.LOG_LINES);
+        } else if (cmd.action === "SetHudVisible") {
+            this.visible = cmd.visible;
+            this.show();
+        } else if (cmd.action === "SetStats") {
+            this.entityCount = cmd.entity_count;
+        }
+        this.update();
+    }
+
+    show() {
+        if (!this.element) {
+            this.element = document.createElement('div');
+            this.element.setAttribute('aria-hidden', 'true');
+            Object.assign(this.element.style, {
+                position: 'fixed', top: '8px', left: '8px', zIndex: 1000,
+                padding: '6px 8px', maxWidth: '480px', pointerEvents: 'none',
+                background: 'rgba(0, 0, 0, 0.6)', color: '#ddd',
+                font: '12px monospace', whiteSpace: 'pre-wrap',
+            });
+            document.body.appendChild(this.element);
+        }
+        this.element.style.display = this.visible ? 'block' : 'none';
+    }
+
+    endFrame(drawCalls) {
+        const now = performance.now();
+        this.drawCalls = drawCalls;
+        this.frameTimes.push(now);
+        while (this.frameTimes.length > 0 && now - this.frameTimes[0] > 1000) {
+            this.frameTimes.shift();
+        }
+        // Refresh the text a few times per second, not every frame
+        if (now - this.lastUpdate > 250) {
+            this.update();
+        }
+    }
+
+    update() {
+        if (!this.visible || !this.element) return;
+        this.lastUpdate = performance.now();
+        const stats = document.createElement('div');
+        stats.textContent = `FPS ${this.frameTimes.length}  draw calls ${this.drawCalls}  entities ${this.entityCount}`;
+        const lines = this.logs.map((log) => {
+            const line = document.createElement('div');
+            line.style.color = DebugHud.LEVEL_COLORS[log.level] || '#ddd';
+            line.textContent = log.message;
+            return line;
+        });
+        this.element.replaceChildren(stats, ...lines);
+    }
+}
+
+// ============================================================================
+// Transform Animations - Interpolates SetTransform over time
+// ============================================================================
+
+class TransformAnimations {
+    static FEATURE = 'transform-animation';
+
+    constructor() {
+        this.running = new Map(); // volume ID -> animation
+    }
+
+    // Move a volume at once, or start animating it. A new transform
+    // interrupts the volume's animation in progress, which never completes.
+    set(volume, cmd, now) {
+        this.running.delete(volume.id);
+        if (!cmd.animate) {
+            Object.assign(volume, TransformAnimations.copy(cmd.transform));
+            return;
+        }
+        this.running.set(volume.id, {
+            from: TransformAnimations.copy(volume),
+            to: TransformAnimations.copy(cmd.transform),
+            start: now,
+            duration: cmd.animate.duration_ms,
+            easing: cmd.animate.easing,
+            animationId: cmd.animate.animation_id || null,
+        });
+    }
+
+    stop(volumeId) {
+        this.running.delete(volumeId);
+    }
+
+    // Move the animated volumes to where they are at `now`, returns the
+    // VolumeAnimationComplete events of the named animations that ended
+    update(volumes, now) {
+        const events = [];
+        for (const [volumeId, animation] of this.running) {
+            const volume = volumes.get(volumeId);
+            const progress = animation.duration > 0 ? (now - animation.start) / animation.duration : 1;
+            if (!volume || progress >= 1) {
+                this.running.delete(volumeId);
+                if (volume) Object.assign(volume, animation.to);
+                if (volume && animation.animationId) {
+                    events.push({
+                        category: "Scene",
+                        event: { type: "VolumeAnimationComplete", volume_id: volumeId, animation_id: animation.animationId }
+                    });
+                }
+                continue;
+            }
+            const t = TransformAnimations.ease(animation.easing, Math.max(progress, 0));
+            volume.position = MathUtils.lerp(animation.from.position, animation.to.position, t);
+            volume.rotation = MathUtils.slerp(animation.from.rotation, animation.to.rotation, t);
+            volume.scale = MathUtils.lerp(animation.from.scale, animation.to.scale, t);
+        }
+        return events;
+    }
+
+    static copy(transform) {
+        return {
+            position: [...transform.position],
+            rotation: [...transform.rotation],
+            scale: [...transform.scale],
+        };
+    }
+
+    // Timing curves for "Linear", "EaseIn", "EaseOut" and "EaseInOut";
+    // cubic beziers ({ CubicBezier: n }) are shown as ease-in-out
+    static ease(easing, t) {
+        switch (easing) {
+            case 'Linear': return t;
+            case 'EaseIn': return t * t * t;
+            case 'EaseOut': return 1 - Math.pow(1 - t, 3);
+            default: return t < 0.5 ? 4 * t * t * t : 1 - Math.pow(-2 * t + 2, 3) / 2;
+        }
+    }
+}
+
+// ============================================================================
+// Picking - Ray casting for SceneCommand::RequestHitTest
+// ============================================================================
+
+class Picking {
+    static FEATURE = 'hit-test';
+    static EPSILON = 1e-6;
+
+    // The nearest volume hit by a request's ray, or null. Screen points are
+    // in CSS pixels, like mouse events; the canvas fills the window.
+    static hitTest(scene, source) {
+        const ray = source.Screen
+            ? Picking.screenRay(scene.camera, source.Screen.x, source.Screen.y)
+            : { origin: source.Ray.origin, direction: MathUtils.normalize(source.Ray.direction) };
+        if (MathUtils.dot(ray.direction, ray.direction) === 0) return null;
+
+        let nearest = null;
+        for (const volume of scene.volumes.values()) {
+            const hit = Picking.castVolume(ray, volume, scene.assetManager);
+            if (hit && (!nearest || hit.distance < nearest.distance)) {
+                nearest = { volume_id: volume.id, ...hit };
+            }
+        }
+        return nearest;
+    }
+
+    // From the camera through a point on screen
+    static screenRay(camera, x, y) {
+        const forward = MathUtils.normalize(MathUtils.subtract(camera.target, camera.position));
+        const right = MathUtils.normalize(MathUtils.cross(forward, camera.up));
+        const up = MathUtils.cross(right, forward);
+        const tanHalfFov = Math.tan(camera.fov / 2);
+        const ndcX = (2 * x / window.innerWidth - 1) * tanHalfFov * (window.innerWidth / window.innerHeight);
+        const ndcY = (1 - 2 * y / window.innerHeight) * tanHalfFov;
+        const direction = [0, 1, 2].map((i) => forward[i] + right[i] * ndcX + up[i] * ndcY);
+        return { origin: camera.position, direction: MathUtils.normalize(direction) };
+    }
+
+    // The ray is cast in model space (see MathUtils.volumeMatrix); the
+    // mapping is affine, so distances along it stay the same
+    static castVolume(ray, volume, assetManager) {
+        const inverse = MathUtils.invertAffine(MathUtils.volumeMatrix(volume));
+        if (!inverse) return null;
+        const origin = MathUtils.transformPoint(inverse, ray.origin);
+        const direction = MathUtils.transformDirection(inverse, ray.direction);
+
+        let local = null;
+        if (volume.meshType === 'asset') {
+            const mesh = assetManager.getMesh(volume.assetId);
+            local = mesh ? Picking.castMesh(origin, direction, mesh) : null;
+        } else {
+            local = Picking.castBox(origin, direction, [-0.5, -0.5, -0.5], [0.5, 0.5, 0.5]);
+        }
+        if (!local) return null;
+
+        // Normals transform by the inverse transpose, and face the ray
+        const n = local.normal;
+        let normal = MathUtils.normalize([0, 1, 2].map((r) =>
+            inverse[r * 4] * n[0] + inverse[r * 4 + 1] * n[1] + inverse[r * 4 + 2] * n[2]));
+        if (MathUtils.dot(normal, ray.direction) > 0) {
+            normal = normal.map((n) => -n);
+        }
+        return {
+            point: [0, 1, 2].map((i) => ray.origin[i] + ray.direction[i] * local.distance),
+            normal: normal,
+            distance: local.distance,
+        };
+    }
+
+    // Slab test: where the ray enters the box (or leaves it, for rays
+    // starting inside)
+    static castBox(origin, direction, min, max) {
+        let enter = { distance: -Infinity, axis: 0 };
+        let exit = { distance: Infinity, axis: 0 };
+        for (let axis = 0; axis < 3; axis++) {
+            const t1 = (min[axis] - origin[axis]) / direction[axis];
+            const t2 = (max[axis] - origin[axis]) / direction[axis];
+            const near = Math.min(t1, t2), far = Math.max(t1, t2);
+            if (near > enter.distance) enter = { distance: near, axis: axis };
+            if (far < exit.distance) exit = { distance: far, axis: axis };
+        }
+        if (exit.distance < Math.max(enter.distance, 0)) return null;
+        const face = enter.distance >= 0 ? enter : exit;
+        const normal = [0, 0, 0];
+        normal[face.axis] = 1;
+        return { distance: face.distance, normal: normal };
+    }
+
+    // Nearest triangle hit, from either side
+    static castMesh(origin, direction, mesh) {
+        const vertex = (index) => [0, 1, 2].map((i) => mesh.vertices[index * 3 + i]);
+        let nearest = null;
+        for (let i = 0; i + 2 < mesh.indices.length; i += 3) {
+            const a = vertex(mesh.indices[i]);
+            const b = vertex(mesh.indices[i + 1]);
+            const c = vertex(mesh.indices[i + 2]);
+            const distance = Picking.castTriangle(origin, direction, a, b, c);
+            if (distance !== null && (!nearest || distance < nearest.distance)) {
+                nearest = { distance: distance, normal: MathUtils.cross(MathUtils.subtract(b, a), MathUtils.subtract(c, a)) };
+            }
+        }
+        return nearest;
+    }
+
+    // Möller-Trumbore
+    static castTriangle(origin, direction, a, b, c) {
+        const edge1 = MathUtils.subtract(b, a);
+        const edge2 = MathUtils.subtract(c, a);
+        const p = MathUtils.cross(direction, edge2);
+        const determinant = MathUtils.dot(edge1, p);
+        if (Math.abs(determinant) < Picking.EPSILON) return null;
+        const s = MathUtils.subtract(origin, a);
+        const u = MathUtils.dot(s, p) / determinant;
+        if (u < 0 || u > 1) return null;
+        const q = MathUtils.cross(s, edge1);
+        const v = MathUtils.dot(direction, q) / determinant;
+        if (v < 0 || u + v > 1) return null;
+        const distance = MathUtils.dot(edge2, q) / determinant;
+        return distance > Picking.EPSILON ? distance : null;
+    }
+}
+
+// ============================================================================
+// Panel Layer - 2D widgets, SVG and HTML placed in the scene
+// ============================================================================
+
+// Each panel's content is laid out as DOM in an offscreen element of the
+// panel's pixel size, where pointer targets are looked up, and rasterized
+// onto a canvas through an SVG image (HTML and widgets in a
+// <foreignObject>). Renderers upload the canvas when its `version` changes.
+// Widgets are styled like the native shell's.
+class PanelLayer {
+    static FEATURE = 'panels';
+    static HTML_FEATURE = 'html-panels';
+    static BACKGROUND = '#f2f2f7';
+    // Panel volumes have the background color, for renderers that don't
+    // draw panel textures
+    static BACKGROUND_COLOR = [0.949, 0.949, 0.969, 1.0];
+    static STYLES = {
+        root: 'box-sizing: border-box; padding: 12px; font-family: sans-serif; color: #1c1c1e;',
+        Column: 'display: flex; flex-direction: column; align-items: flex-start; gap: 8px;',
+        Row: 'display: flex; flex-direction: row; align-items: flex-start; gap: 8px;',
+        Button: 'padding: 8px 16px; border-radius: 6px; background: #0a84ff; color: #ffffff; font-size: 16px; line-height: 1.25;',
+    };
+
+    constructor() {
+        this.panels = new Map();
+        // The panel a pointer went down on, which gets its moves and release
+        this.pressed = null;
+    }
+
+    create(cmd) {
+        const pixels = [cmd.width, cmd.height].map((size) => Math.max(Math.round(size * cmd.pixels_per_meter), 1));
+        const element = document.createElement('div');
+        Object.assign(element.style, {
+            position: 'fixed', left: '-100000px', top: '0', overflow: 'hidden',
+            width: `${pixels[0]}px`, height: `${pixels[1]}px`, pointerEvents: 'none',
+        });
+        element.setAttribute('aria-hidden', 'true');
+        document.body.appendChild(element);
+
+        const canvas = document.createElement('canvas');
+        [canvas.width, canvas.height] = pixels;
+        const panel = { id: cmd.panel_id, size: [cmd.width, cmd.height], pixels, element, canvas, version: 0, drawing: 0 };
+        this.fill(panel);
+        this.panels.set(cmd.panel_id, panel);
+        this.setContent(panel, cmd.content);
+        return panel;
+    }
+
+    destroy(panelId) {
+        const panel = this.panels.get(panelId);
+        if (!panel) return false;
+        panel.element.remove();
+        this.panels.delete(panelId);
+        if (this.pressed === panelId) this.pressed = null;
+        return true;
+    }
+
+    setContent(panel, content) {
+        panel.element.replaceChildren();
+        let markup;
+        if (content.type === 'Svg') {
+            const svg = new DOMParser().parseFromString(content.svg, 'image/svg+xml').documentElement;
+            if (svg.localName !== 'svg') {
+                console.warn(`Panel ${panel.id} has invalid SVG`);
+                return;
+            }
+            // Stretched to the panel, like the native shell does
+            if (!svg.hasAttribute('viewBox') && svg.hasAttribute('width') && svg.hasAttribute('height')) {
+                svg.setAttribute('viewBox', `0 0 ${parseFloat(svg.getAttribute('width'))} ${parseFloat(svg.getAttribute('height'))}`);
+            }
+            svg.setAttribute('width', panel.pixels[0]);
+            svg.setAttribute('height', panel.pixels[1]);
+            svg.setAttribute('preserveAspectRatio', 'none');
+            panel.element.appendChild(document.importNode(svg, true));
+            markup = new XMLSerializer().serializeToString(svg);
+        } else {
+            const root = document.createElement('div');
+            root.setAttribute('style', `${PanelLayer.STYLES.root} width: ${panel.pixels[0]}px; height: ${panel.pixels[1]}px; background: ${PanelLayer.BACKGROUND};`);
+            if (content.type === 'Widgets') {
+                root.appendChild(this.widgetElement(content.root));
+            } else {
+                root.innerHTML = content.html;
+            }
+            panel.element.appendChild(root);
+            const [width, height] = panel.pixels;
+            markup = `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">`
+                + `<foreignObject width="100%" height="100%">${new XMLSerializer().serializeToString(root)}</foreignObject></svg>`;
+        }
+        this.rasterize(panel, markup);
+    }
+
+    // Styles are inline: the rasterized copy doesn't see the page's CSS
+    widgetElement(widget) {
+        const element = document.createElement('div');
+        if (widget.type === 'Column' || widget.type === 'Row') {
+            element.setAttribute('style', PanelLayer.STYLES[widget.type]);
+            element.append(...widget.children.map((child) => this.widgetElement(child)));
+        } else if (widget.type === 'Text') {
+            element.setAttribute('style', `font-size: ${widget.size}px; line-height: 1.25;`);
+            element.textContent = widget.text;
+        } else if (widget.type === 'Button') {
+            element.setAttribute('style', PanelLayer.STYLES.Button);
+            element.id = widget.id;
+            element.textContent = widget.label;
+        } else {
+            console.warn('Unknown widget', widget.type);
+        }
+        return element;
+    }
+
+    fill(panel) {
+        const context = panel.canvas.getContext('2d');
+        context.fillStyle = PanelLayer.BACKGROUND;
+        context.fillRect(0, 0, panel.canvas.width, panel.canvas.height);
+        return context;
+    }
+
+    // Images load asynchronously; only the latest content is kept
+    rasterize(panel, markup) {
+        const drawing = ++panel.drawing;
+        const image = new Image();
+        image.onload = () => {
+            if (drawing !== panel.drawing || !this.panels.has(panel.id)) return;
+            this.fill(panel).drawImage(image, 0, 0, panel.canvas.width, panel.canvas.height);
+            panel.version++;
+        };
+        image.onerror = () => console.warn(`Panel ${panel.id} not drawn: its content failed to load as an image`);
+        image.src = 'data:image/svg+xml;charset=utf-8,' + encodeURIComponent(markup);
+    }
+
+    // The topmost element with an id at a point in panel pixels
+    targetAt(panel, x, y) {
+        const bounds = panel.element.getBoundingClientRect();
+        const elements = [...panel.element.querySelectorAll('[id]')].reverse();
+        const target = elements.find((element) => {
+            const rect = element.getBoundingClientRect();
+            const left = rect.left - bounds.left, top = rect.top - bounds.top;
+            return x >= left && x <= left + rect.width && y >= top && y <= top + rect.height;
+        });
+        return target ? target.id : null;
+    }
+
+    // The panel event for a pointer at a screen point (CSS pixels), or null
+    // when it isn't over a panel and no panel was pressed
+    pointer(scene, phase, x, y) {
+        // Other volumes in front of a panel keep the pointer from it
+        const hit = Picking.hitTest(scene, { Screen: { x, y } });
+        const hovered = hit && this.panels.has(hit.volume_id) ? hit.volume_id : null;
+        let panelId;
+        if (phase === 'Down') {
+            panelId = this.pressed = hovered;
+        } else if (phase === 'Move') {
+            panelId = this.pressed || hovered;
+        } else {
+            panelId = this.pressed || hovered;
+            this.pressed = null;
+        }
+        const panel = panelId && this.panels.get(panelId);
+        const volume = panel && scene.volumes.get(panelId);
+        if (!volume) return null;
+
+        // Where the ray crosses the panel's plane, in its model space
+        const inverse = MathUtils.invertAffine(MathUtils.volumeMatrix(volume));
+        if (!inverse) return null;
+        const ray = Picking.screenRay(scene.camera, x, y);
+        const origin = MathUtils.transformPoint(inverse, ray.origin);
+        const direction = MathUtils.transformDirection(inverse, ray.direction);
+        if (Math.abs(direction[2]) < Picking.EPSILON) return null;
+        const t = -origin[2] / direction[2];
+        const px = ((origin[0] + direction[0] * t) / panel.size[0] + 0.5) * panel.pixels[0];
+        const py = (0.5 - (origin[1] + direction[1] * t) / panel.size[1]) * panel.pixels[1];
+        const inside = px >= 0 && px <= panel.pixels[0] && py >= 0 && py <= panel.pixels[1];
+        const pointer = { type: "Pointer", panel_id: panelId, phase, x: px, y: py };
+        const target = inside ? this.targetAt(panel, px, py) : null;
+        if (target) pointer.target = target;
+        return { category: "Panel", event: pointer };
+    }
+}
+
+// ============================================================================
+// Math Utilities - Shared between renderers
+// ============================================================================
+
+const MathUtils = {
+    normalize(v) {
+        const len = Math.sqrt(v[0]*v[0] + v[1]*v[1] + v[2]*v[2]);
+        if (len === 0) return [0, 0, 0];
+        return [v[0]/len, v[1]/len, v[2]/len];
+    },
+
+    subtract(a, b) {
+        return [a[0]-b[0], a[1]-b[1], a[2]-b[2]];
+    },
+
+    cross(a, b) {
+        return [
+            a[1]*b[2] - a[2]*b[1],
+            a[2]*b[0] - a[0]*b[2],
+            a[0]*b[1] - a[1]*b[0],
+        ];
     },
 
-    // Model matrix from position and scale
-    modelMatrix(position, scale) {
-        const s = typeof scale === 'number' ? scale : scale[0];
+    dot(a, b) {
+        return a[0]*b[0] + a[1]*b[1] + a[2]*b[2];
+    },
+
+    // Right-handed look-at matrix (matches glam's Mat4::look_at_rh)
+    lookAtRH(eye, target, up) {
+        const zAxis = this.normalize(this.subtract(eye, target));
+        const xAxis = this.normalize(this.cross(up, zAxis));
+        const yAxis = this.cross(zAxis, xAxis);
+
         return new Float32Array([
-            s, 0, 0, 0,
-            0, s, 0, 0,
-            0, 0, s, 0,
-            position[0], position[1], position[2], 1,
+            xAxis[0], yAxis[0], zAxis[0], 0,
+            xAxis[1], yAxis[1], zAxis[1], 0,
+            xAxis[2], yAxis[2], zAxis[2], 0,
+            -this.dot(xAxis, eye), -this.dot(yAxis, eye), -this.dot(zAxis, eye), 1,
+        ]);
+    },
+
+    // Perspective projection matrix
+    perspectiveRH(fov, aspect, near, far) {
+        const f = 1.0 / Math.tan(fov / 2);
+        return new Float32Array([
+            f / aspect, 0, 0, 0,
+            0, f, 0, 0,
+            0, 0, (far + near) / (near - far), -1,
+            0, 0, (2 * far * near) / (near - far), 0,
         ]);
     },
 
@@ -415,6 +1724,122 @@ const MathUtils = {
         return result;
     },
 
+    // Model matrix from position, quaternion rotation and per-axis scale
+    poseMatrix(position, rotation, scale = [1, 1, 1]) {
+        const [x, y, z, w] = rotation;
+        const [sx, sy, sz] = scale;
+        return new Float32Array([
+            (1 - 2 * (y * y + z * z)) * sx, 2 * (x * y + w * z) * sx, 2 * (x * z - w * y) * sx, 0,
+            2 * (x * y - w * z) * sy, (1 - 2 * (x * x + z * z)) * sy, 2 * (y * z + w * x) * sy, 0,
+            2 * (x * z + w * y) * sz, 2 * (y * z - w * x) * sz, (1 - 2 * (x * x + y * y)) * sz, 0,
+            position[0], position[1], position[2], 1,
+        ]);
+    },
+
+    // Model matrix of a volume; primitive cubes are unit cubes scaled by
+    // their size (matches the native renderer). Children are placed in
+    // their parent's frame.
+    volumeMatrix(volume) {
+        const model = this.poseMatrix(volume.position, volume.rotation, this.volumeScale(volume));
+        return volume.parent ? this.multiplyMatrices(this.frameMatrix(volume.parent), model) : model;
+    },
+
+    // The frame a volume's children are placed in: its transform without
+    // its size, through its own parents
+    frameMatrix(volume) {
+        const pose = this.poseMatrix(volume.position, volume.rotation, volume.scale);
+        return volume.parent ? this.multiplyMatrices(this.frameMatrix(volume.parent), pose) : pose;
+    },
+
+    volumeScale(volume) {
+        return volume.meshType === 'asset' ? volume.scale : volume.scale.map((s) => s * volume.size);
+    },
+
+    lerp(a, b, t) {
+        return a.map((value, i) => value + (b[i] - value) * t);
+    },
+
+    // Spherical interpolation of unit quaternions, the short way around
+    slerp(a, b, t) {
+        let cos = a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3];
+        if (cos < 0) {
+            b = b.map((c) => -c);
+            cos = -cos;
+        }
+        if (cos > 0.9995) {
+            const q = this.lerp(a, b, t);
+            const length = Math.hypot(...q);
+            return q.map((c) => c / length);
+        }
+        const angle = Math.acos(cos);
+        const wa = Math.sin((1 - t) * angle) / Math.sin(angle);
+        const wb = Math.sin(t * angle) / Math.sin(angle);
+        return a.map((c, i) => c * wa + b[i] * wb);
+    },
+
+    // Inverse of a rotation + translation matrix
+    invertRigid(m) {
+        const t = [m[12], m[13], m[14]];
+        return new Float32Array([
+            m[0], m[4], m[8], 0,
+            m[1], m[5], m[9], 0,
+            m[2], m[6], m[10], 0,
+            -(m[0] * t[0] + m[1] * t[1] + m[2] * t[2]),
+            -(m[4] * t[0] + m[5] * t[1] + m[6] * t[2]),
+            -(m[8] * t[0] + m[9] * t[1] + m[10] * t[2]),
+            1,
+        ]);
+    },
+
+    // Inverse of a matrix without projection, or null if it flattens space:
+    // the columns of the inverse 3x3 are cross products of its rows
+    invertAffine(m) {
+        const rows = [0, 1, 2].map((r) => [m[r], m[r + 4], m[r + 8]]);
+        const columns = [0, 1, 2].map((j) => this.cross(rows[(j + 1) % 3], rows[(j + 2) % 3]));
+        const det = this.dot(rows[0], columns[0]);
+        if (Math.abs(det) < 1e-12) return null;
+        const result = new Float32Array(16);
+        columns.forEach((column, j) => column.forEach((c, r) => { result[j * 4 + r] = c / det; }));
+        const t = this.transformDirection(result, [m[12], m[13], m[14]]);
+        result.set([-t[0], -t[1], -t[2], 1], 12);
+        return result;
+    },
+
+    transformPoint(m, p) {
+        return [
+            m[0] * p[0] + m[4] * p[1] + m[8] * p[2] + m[12],
+            m[1] * p[0] + m[5] * p[1] + m[9] * p[2] + m[13],
+            m[2] * p[0] + m[6] * p[1] + m[10] * p[2] + m[14],
+        ];
+    },
+
+    transformDirection(m, v) {
+        return [
+            m[0] * v[0] + m[4] * v[1] + m[8] * v[2],
+            m[1] * v[0] + m[5] * v[1] + m[9] * v[2],
+            m[2] * v[0] + m[6] * v[1] + m[10] * v[2],
+        ];
+    },
+
+    // Replace the near plane of a perspective projection with a view-space
+    // clip plane [a, b, c, d] (Lengyel's oblique frustum). Points with
+    // a*x + b*y + c*z + d < 0 are clipped; the camera must be on that side.
+    obliqueProjection(projection, plane) {
+        const p = new Float32Array(projection);
+        const q = [
+            (Math.sign(plane[0]) + p[8]) / p[0],
+            (Math.sign(plane[1]) + p[9]) / p[5],
+            -1,
+            (1 + p[10]) / p[14],
+        ];
+        const scale = 2 / (plane[0] * q[0] + plane[1] * q[1] + plane[2] * q[2] + plane[3] * q[3]);
+        p[2] = plane[0] * scale - p[3];
+        p[6] = plane[1] * scale - p[7];
+        p[10] = plane[2] * scale - p[11];
+        p[14] = plane[3] * scale - p[15];
+        return p;
+    },
+
     // Identity matrix
     identity() {
         return new Float32Array([
@@ -526,36 +1951,730 @@ const CubeGeometry = {
     }
 };
 
+// ============================================================================
+// Primitive Geometry - Meshes of the protocol's primitives, as the native
+// shell generates them
+// ============================================================================
+
+// Centered on the origin, wound counter-clockwise seen from outside. Round
+// shapes are grids wrapped around the Y axis with `segments` columns;
+// spheres have half as many rings, a torus's tube half as many sides.
+const PrimitiveGeometry = {
+    MIN_SEGMENTS: 3,
+    MAX_SEGMENTS: 256,
+
+    // A mesh in AssetManager's format, or null for unknown shapes
+    generate(shape, params) {
+        const mesh = { vertices: [], normals: [], indices: [] };
+        const sides = (segments) => Math.min(Math.max(segments | 0, this.MIN_SEGMENTS), this.MAX_SEGMENTS);
+        switch (shape) {
+            case 'Box':
+                this.addBox(mesh, [params.width / 2, params.height / 2, params.depth / 2]);
+                break;
+            case 'Sphere': {
+                const columns = sides(params.segments);
+                this.addGrid(mesh, columns, Math.max(columns >> 1, 2), (d, v) => {
+                    const latitude = (v - 0.5) * Math.PI;
+                    const n = [d[0] * Math.cos(latitude), Math.sin(latitude), d[2] * Math.cos(latitude)];
+                    return [n.map((c) => c * params.radius), n];
+                });
+                break;
+            }
+            case 'Cylinder': {
+                const columns = sides(params.segments);
+                const { radius, height } = params;
+                this.addGrid(mesh, columns, 1, (d, v) => [[d[0] * radius, (v - 0.5) * height, d[2] * radius], d]);
+                this.addDisc(mesh, columns, radius, height / 2, 1);
+                this.addDisc(mesh, columns, radius, -height / 2, -1);
+                break;
+            }
+            case 'Cone': {
+                const columns = sides(params.segments);
+                const { radius, height } = params;
+                this.addGrid(mesh, columns, 1, (d, v) => [
+                    [d[0] * radius * (1 - v), (v - 0.5) * height, d[2] * radius * (1 - v)],
+                    MathUtils.normalize([d[0] * height, radius, d[2] * height]),
+                ]);
+                this.addDisc(mesh, columns, radius, -height / 2, -1);
+                break;
+            }
+            case 'Torus': {
+                const columns = sides(params.segments);
+                const { radius, tube_radius } = params;
+                this.addGrid(mesh, columns, Math.max(columns >> 1, this.MIN_SEGMENTS), (d, v) => {
+                    const angle = v * 2 * Math.PI;
+                    const n = [d[0] * Math.cos(angle), Math.sin(angle), d[2] * Math.cos(angle)];
+                    return [[0, 1, 2].map((i) => d[i] * radius + n[i] * tube_radius), n];
+                });
+                break;
+            }
+            case 'Plane':
+                this.addFace(mesh, [0, 0, 0], [params.width / 2, 0, 0], [0, 0, -params.height / 2]);
+                break;
+            case 'Quad':
+                this.addFace(mesh, [0, 0, 0], [params.width / 2, 0, 0], [0, params.height / 2, 0]);
+                break;
+            default:
+                return null;
+        }
+        return {
+            vertices: new Float32Array(mesh.vertices),
+            normals: new Float32Array(mesh.normals),
+            indices: new Uint32Array(mesh.indices),
+            indexType: 'uint32',
+            color: [1.0, 1.0, 1.0, 1.0],
+        };
+    },
+
+    addBox(mesh, [x, y, z]) {
+        this.addFace(mesh, [0, 0, z], [x, 0, 0], [0, y, 0]);
+        this.addFace(mesh, [0, 0, -z], [-x, 0, 0], [0, y, 0]);
+        this.addFace(mesh, [0, y, 0], [x, 0, 0], [0, 0, -z]);
+        this.addFace(mesh, [0, -y, 0], [x, 0, 0], [0, 0, z]);
+        this.addFace(mesh, [x, 0, 0], [0, 0, -z], [0, y, 0]);
+        this.addFace(mesh, [-x, 0, 0], [0, 0, z], [0, y, 0]);
+    },
+
+    // A rectangle spanning `right` and `up` either way, facing right × up
+    addFace(mesh, center, right, up) {
+        const normal = MathUtils.normalize(MathUtils.cross(right, up));
+        const first = mesh.vertices.length / 3;
+        for (const [x, y] of [[-1, -1], [1, -1], [1, 1], [-1, 1]]) {
+            mesh.vertices.push(...[0, 1, 2].map((i) => center[i] + right[i] * x + up[i] * y));
+            mesh.normals.push(...normal);
+        }
+        mesh.indices.push(...[0, 1, 2, 2, 3, 0].map((i) => first + i));
+    },
+
+    // `point(direction, v)` gives the position and normal at each vertex,
+    // from the outward horizontal direction and v (0 at the bottom row, 1
+    // at the top)
+    addGrid(mesh, columns, rows, point) {
+        const first = mesh.vertices.length / 3;
+        for (let row = 0; row <= rows; row++) {
+            for (let column = 0; column <= columns; column++) {
+                const [position, normal] = point(this.direction(column / columns * 2 * Math.PI), row / rows);
+                mesh.vertices.push(...position);
+                mesh.normals.push(...normal);
+            }
+        }
+        const stride = columns + 1;
+        for (let row = 0; row < rows; row++) {
+            for (let column = 0; column < columns; column++) {
+                const bottom = first + row * stride + column;
+                const top = bottom + stride;
+                mesh.indices.push(bottom, bottom + 1, top + 1, top + 1, top, bottom);
+            }
+        }
+    },
+
+    // A flat cap at height y, facing up (facing = 1) or down (-1)
+    addDisc(mesh, columns, radius, y, facing) {
+        const center = mesh.vertices.length / 3;
+        mesh.vertices.push(0, y, 0);
+        mesh.normals.push(0, facing, 0);
+        for (let column = 0; column < columns; column++) {
+            const d = this.direction(column / columns * 2 * Math.PI);
+            mesh.vertices.push(d[0] * radius, y, d[2] * radius);
+            mesh.normals.push(0, facing, 0);
+        }
+        for (let column = 0; column < columns; column++) {
+            const a = center + 1 + column;
+            const b = center + 1 + (column + 1) % columns;
+            // The rim runs counter-clockwise seen from above
+            mesh.indices.push(...(facing > 0 ? [center, a, b] : [center, b, a]));
+        }
+    },
+
+    // Horizontal unit vector `angle` radians from +Z toward +X
+    direction(angle) {
+        return [Math.sin(angle), 0, Math.cos(angle)];
+    },
+};
+
+// ============================================================================
+// Media Capture - Photos and clips of the rendered view
+// ============================================================================
+
+// Browsers don't give pages the camera image behind WebXR passthrough, so
+// captures hold the virtual content only (passthrough: false). Files are
+// saved as downloads.
+class MediaCapture {
+    static FEATURE = 'capture';
+    static VIDEO_TYPES = ['video/webm;codecs=vp9', 'video/webm', 'video/mp4'];
+
+    constructor() {
+        this.onEvent = null; // (event) => void
+        this.photos = []; // Photo capture IDs waiting for the next frame
+        this.recording = null; // The running video capture
+        this.frame = document.createElement('canvas');
+    }
+
+    handle(cmd) {
+        if (cmd.action === "Capture") {
+            if (cmd.kind === "Photo") {
+                this.photos.push(cmd.capture_id);
+            } else if (cmd.kind && cmd.kind.Video) {
+                this.startVideo(cmd.capture_id, cmd.kind);
+            }
+        } else if (cmd.action === "StopCapture") {
+            if (this.recording && this.recording.id === cmd.capture_id) {
+                this.stopVideo(this.recording);
+            }
+        }
+    }
+
+    // Renderers call this right after drawing a frame, with its framebuffer
+    // still bound. viewport is the part to keep (the first eye in XR).
+    captureFrame(gl, viewport) {
+        if (this.photos.length === 0 && !this.recording) return;
+        this.readFrame(gl, viewport);
+
+        for (const id of this.photos) {
+            this.savePhoto(id);
+        }
+        this.photos = [];
+
+        const recording = this.recording;
+        if (recording) {
+            if (recording.recorder.state === 'inactive') {
+                this.startRecorder(recording);
+            }
+            const canvas = recording.canvas;
+            canvas.getContext('2d').drawImage(this.frame, 0, 0, canvas.width, canvas.height);
+        }
+    }
+
+    // Copy the viewport of the bound framebuffer onto the frame canvas,
+    // flipping it upright
+    readFrame(gl, viewport) {
+        const { x, y, width, height } = viewport;
+        const pixels = new Uint8Array(width * height * 4);
+        gl.readPixels(x, y, width, height, gl.RGBA, gl.UNSIGNED_BYTE, pixels);
+        const image = new ImageData(width, height);
+        const row = width * 4;
+        for (let r = 0; r < height; r++) {
+            image.data.set(pixels.subarray(r * row, (r + 1) * row), (height - 1 - r) * row);
+        }
+        if (this.frame.width !== width || this.frame.height !== height) {
+            this.frame.width = width;
+            this.frame.height = height;
+        }
+        this.frame.getContext('2d').putImageData(image, 0, 0);
+    }
+
+    savePhoto(id) {
+        const { width, height } = this.frame;
+        const name = `fastn-${id}.png`;
+        // toBlob copies the canvas right away, the frame can be reused
+        this.frame.toBlob((blob) => {
+            if (!blob) {
+                this.fail(id, 'Failed', 'Could not encode the photo');
+                return;
+            }
+            MediaCapture.download(blob, name);
+            this.saved({ capture_id: id, kind: 'Photo', path: name, mime_type: 'image/png', width, height });
+        }, 'image/png');
+    }
+
+    // The recorder starts with the first frame, once the size is known
+    startVideo(id, kind) {
+        if (this.recording) {
+            this.fail(id, 'Busy', `Already recording ${this.recording.id}`);
+            return;
+        }
+        const mimeType = typeof MediaRecorder === 'undefined' ? null
+            : MediaCapture.VIDEO_TYPES.find((type) => MediaRecorder.isTypeSupported(type));
+        const canvas = document.createElement('canvas');
+        if (!mimeType || !canvas.captureStream) {
+            this.fail(id, 'Unsupported', 'This browser cannot record video');
+            return;
+        }
+        const recorder = new MediaRecorder(canvas.captureStream(), { mimeType });
+        const recording = { id, kind, canvas, recorder, mimeType, chunks: [], start: 0, timer: null };
+        recorder.ondataavailable = (e) => {
+            if (e.data.size > 0) recording.chunks.push(e.data);
+        };
+        recorder.onstop = () => this.finishVideo(recording);
+        recorder.onerror = (e) => {
+            this.recording = null;
+            clearTimeout(recording.timer);
+            this.fail(id, 'Failed', String(e.error || 'Recording failed'));
+        };
+        this.recording = recording;
+    }
+
+    startRecorder(recording) {
+        recording.canvas.width = this.frame.width;
+        recording.canvas.height = this.frame.height;
+        recording.recorder.start(1000);
+        recording.start = performance.now();
+        const maxMs = recording.kind.Video.max_duration_ms;
+        if (maxMs != null) {
+            recording.timer = setTimeout(() => this.stopVideo(recording), maxMs);
+        }
+    }
+
+    stopVideo(recording) {
+        clearTimeout(recording.timer);
+        if (recording.recorder.state === 'inactive') {
+            // Stopped before a frame was drawn
+            this.recording = null;
+            this.fail(recording.id, 'Failed', 'No frames were recorded');
+        } else {
+            recording.recorder.stop();
+        }
+    }
+
+    finishVideo(recording) {
+        if (this.recording === recording) {
+            this.recording = null;
+        }
+        const type = recording.mimeType.split(';')[0];
+        const name = `fastn-${recording.id}.${type === 'video/mp4' ? 'mp4' : 'webm'}`;
+        const blob = new Blob(recording.chunks, { type });
+        if (blob.size === 0) {
+            this.fail(recording.id, 'Failed', 'No frames were recorded');
+            return;
+        }
+        MediaCapture.download(blob, name);
+        this.saved({
+            capture_id: recording.id,
+            kind: recording.kind,
+            path: name,
+            mime_type: type,
+            width: recording.canvas.width,
+            height: recording.canvas.height,
+            duration_ms: Math.round(performance.now() - recording.start),
+        });
+    }
+
+    static download(blob, name) {
+        const url = URL.createObjectURL(blob);
+        const link = document.createElement('a');
+        link.href = url;
+        link.download = name;
+        link.click();
+        setTimeout(() => URL.revokeObjectURL(url), 10000);
+    }
+
+    saved(data) {
+        this.raise({ type: "CaptureSaved", ...data, passthrough: false });
+    }
+
+    fail(id, reason, message) {
+        this.raise({ type: "CaptureFailed", capture_id: id, reason, message });
+    }
+
+    raise(event) {
+        if (this.onEvent) {
+            this.onEvent({ category: "Media", event });
+        }
+    }
+}
+
+// ============================================================================
+// Spatial Audio - Plays sources with the mix the core computes
+// ============================================================================
+
+// The core does distance rolloff and occlusion; each source here is just
+// buffer -> low-pass -> gain -> HRTF panner, with the panner placed at the
+// source's direction in the listener's frame (WebAudio's default listener
+// faces -Z with +Y up, the same frame) and its own attenuation turned off.
+class SpatialAudio {
+    static FEATURE = 'spatial-audio';
+    // Seconds for mix changes to settle, to avoid clicks
+    static SMOOTHING = 0.03;
+
+    constructor(assetManager) {
+        this.assetManager = assetManager;
+        this.context = new AudioContext();
+        this.onEvent = null; // (event) => void
+        this.sounds = new Map(); // sound_id -> Promise<AudioBuffer>
+        this.sources = new Map(); // source_id -> source
+        // Browsers keep audio suspended until the user interacts with the page
+        const resume = () => {
+            this.context.resume();
+            document.removeEventListener('pointerdown', resume);
+            document.removeEventListener('keydown', resume);
+        };
+        document.addEventListener('pointerdown', resume);
+        document.addEventListener('keydown', resume);
+    }
+
+    handle(cmd) {
+        const source = this.sources.get(cmd.source_id);
+        if (cmd.action === "Load") {
+            this.load(cmd.sound_id, cmd.path);
+        } else if (cmd.action === "Unload") {
+            for (const [id, other] of this.sources) {
+                if (other.soundId === cmd.sound_id) this.destroy(id);
+            }
+            this.sounds.delete(cmd.sound_id);
+        } else if (cmd.action === "CreateSource") {
+            this.create(cmd);
+        } else if (cmd.action === "DestroySource") {
+            this.destroy(cmd.source_id);
+        } else if (cmd.action === "Mix") {
+            for (const mix of cmd.sources) {
+                this.mix(mix);
+            }
+        } else if (!source) {
+            return;
+        } else if (cmd.action === "Play") {
+            this.play(cmd.source_id, source);
+        } else if (cmd.action === "Stop") {
+            this.stop(source);
+        } else if (cmd.action === "SetVolume") {
+            source.volume.gain.setTargetAtTime(cmd.volume, this.context.currentTime, SpatialAudio.SMOOTHING);
+        } else if (cmd.action === "SetLooping") {
+            source.looping = cmd.looping;
+            if (source.player) source.player.loop = cmd.looping;
+        }
+    }
+
+    load(soundId, path) {
+        const sound = (async () => {
+            const response = await fetch(this.assetManager.resolveUri(path));
+            if (!response.ok) {
+                throw new Error(`HTTP ${response.status}: ${response.statusText}`);
+            }
+            return this.context.decodeAudioData(await response.arrayBuffer());
+        })();
+        this.sounds.set(soundId, sound);
+        sound.then((buffer) => {
+            this.raise({ type: "Loaded", sound_id: soundId, duration_ms: Math.round(buffer.duration * 1000) });
+        }, (e) => {
+            this.sounds.delete(soundId);
+            this.raise({ type: "Error", sound_id: soundId, error: e.message });
+        });
+    }
+
+    create(cmd) {
+        this.destroy(cmd.source_id);
+        const context = this.context;
+        const filter = new BiquadFilterNode(context, { type: 'lowpass', frequency: context.sampleRate / 2 });
+        const volume = new GainNode(context, { gain: cmd.volume });
+        const gain = new GainNode(context, { gain: 0 });
+        const panner = new PannerNode(context, {
+            panningModel: 'HRTF',
+            distanceModel: 'linear',
+            rolloffFactor: 0,
+            positionZ: -1,
+        });
+        filter.connect(volume).connect(gain).connect(panner).connect(context.destination);
+        this.sources.set(cmd.source_id, {
+            soundId: cmd.sound_id,
+            looping: cmd.looping,
+            filter, volume, gain, panner,
+            player: null,
+        });
+    }
+
+    destroy(sourceId) {
+        const source = this.sources.get(sourceId);
+        if (!source) return;
+        this.stop(source);
+        this.sources.delete(sourceId);
+        source.panner.disconnect();
+    }
+
+    play(sourceId, source) {
+        const sound = this.sounds.get(source.soundId);
+        if (!sound) {
+            this.raise({ type: "Error", sound_id: source.soundId, error: 'Sound is not loaded' });
+            return;
+        }
+        this.stop(source);
+        const request = {};
+        source.request = request;
+        sound.then((buffer) => {
+            // Stopped or restarted meanwhile
+            if (source.request !== request || this.sources.get(sourceId) !== source) return;
+            const player = new AudioBufferSourceNode(this.context, { buffer, loop: source.looping });
+            player.connect(source.filter);
+            player.onended = () => {
+                if (source.player !== player) return;
+                source.player = null;
+                this.raise({ type: "Ended", source_id: sourceId });
+            };
+            player.start();
+            source.player = player;
+        }, () => {}); // The load failure was reported
+    }
+
+    stop(source) {
+        source.request = null;
+        const player = source.player;
+        if (player) {
+            source.player = null;
+            player.stop();
+        }
+    }
+
+    mix(mix) {
+        const source = this.sources.get(mix.source_id);
+        if (!source) return;
+        const now = this.context.currentTime;
+        const smoothing = SpatialAudio.SMOOTHING;
+        const cutoff = mix.low_pass_hz ?? this.context.sampleRate / 2;
+        source.gain.gain.setTargetAtTime(mix.gain, now, smoothing);
+        source.filter.frequency.setTargetAtTime(cutoff, now, smoothing);
+        source.panner.positionX.setTargetAtTime(mix.direction[0], now, smoothing);
+        source.panner.positionY.setTargetAtTime(mix.direction[1], now, smoothing);
+        source.panner.positionZ.setTargetAtTime(mix.direction[2], now, smoothing);
+    }
+
+    raise(event) {
+        if (this.onEvent) {
+            this.onEvent({ category: "Audio", event });
+        }
+    }
+}
+
+// ============================================================================
+// Asset Cache - Keeps fetched assets in IndexedDB across sessions
+// ============================================================================
+
+// One record per URL with the ETag and Last-Modified it was fetched with;
+// bodies are in a store of their own so eviction only reads the metadata.
+// A copy is used without asking the server while its Cache-Control max-age
+// lasts (cache policy "Default"), then revalidated with If-None-Match /
+// If-Modified-Since; a 304 keeps it. Offline, a stale copy is used as is.
+// Nothing is kept of no-store responses or "NoStore" loads. Once the
+// bodies outgrow maxBytes, the least recently used are evicted.
+class AssetCache {
+    static FEATURE = 'asset-cache';
+    static DB_NAME = 'fastn-asset-cache';
+    static DEFAULT_MAX_BYTES = 256 * 1024 * 1024;
+
+    static supported() {
+        return typeof indexedDB !== 'undefined';
+    }
+
+    constructor(maxBytes = AssetCache.DEFAULT_MAX_BYTES) {
+        this.maxBytes = maxBytes;
+        this.db = null; // Promise<IDBDatabase | null>, opened by the first fetch
+    }
+
+    open() {
+        if (!this.db) {
+            this.db = new Promise((resolve) => {
+                if (!AssetCache.supported()) return resolve(null);
+                const request = indexedDB.open(AssetCache.DB_NAME, 1);
+                request.onupgradeneeded = () => {
+                    request.result.createObjectStore('entries', { keyPath: 'url' });
+                    request.result.createObjectStore('bodies');
+                };
+                request.onsuccess = () => resolve(request.result);
+                request.onerror = () => {
+                    console.warn(`Asset cache unavailable: ${request.error}`);
+                    resolve(null);
+                };
+            });
+        }
+        return this.db;
+    }
+
+    // Run `action(stores)` in a transaction, resolving with the request it
+    // returns' result once the transaction completes
+    async transact(mode, action) {
+        const db = await this.open();
+        if (!db) return undefined;
+        return new Promise((resolve, reject) => {
+            const tx = db.transaction(['entries', 'bodies'], mode);
+            const request = action({ entries: tx.objectStore('entries'), bodies: tx.objectStore('bodies') });
+            tx.oncomplete = () => resolve(request ? request.result : undefined);
+            tx.onerror = () => reject(tx.error);
+            tx.onabort = () => reject(tx.error);
+        });
+    }
+
+    // Fetch a URL's bytes, from the cache when the policy and the copy's
+    // freshness allow
+    async fetch(url, policy = 'Default') {
+        // Relative URLs of different pages must not share copies
+        if (typeof location !== 'undefined') url = new URL(url, location.href).href;
+        if (policy === 'NoStore' || !await this.open()) {
+            return AssetCache.download(await fetch(url, { cache: 'no-store' }));
+        }
+
+        const entry = await this.transact('readonly', (s) => s.entries.get(url)).catch(() => undefined);
+        if (entry && policy === 'Default' && Date.now() < entry.freshUntil) {
+            const body = await this.read(entry);
+            if (body) return body;
+        }
+
+        const headers = {};
+        if (entry?.etag) headers['If-None-Match'] = entry.etag;
+        if (entry?.lastModified) headers['If-Modified-Since'] = entry.lastModified;
+        let response;
+        try {
+            // Validators are ours, the browser's HTTP cache stays out of it
+            response = await fetch(url, { headers, cache: 'no-store' });
+        } catch (e) {
+            const body = entry && await this.read(entry);
+            if (body) {
+                console.warn(`${e.message}, using the cached copy of ${url}`);
+                return body;
+            }
+            throw e;
+        }
+
+        if (response.status === 304 && entry) {
+            const body = await this.read(entry);
+            if (body) {
+                entry.freshUntil = Date.now() + AssetCache.maxAge(response.headers) * 1000;
+                await this.transact('readwrite', (s) => s.entries.put(entry)).catch(() => {});
+                return body;
+            }
+            response = await fetch(url, { cache: 'no-store' });
+        }
+
+        const body = await AssetCache.download(response);
+        await this.store(url, response.headers, body).catch((e) => console.warn(`Failed to cache ${url}: ${e}`));
+        return body;
+    }
+
+    // The body of a cached copy, marking it used; copies whose body is gone
+    // are forgotten
+    async read(entry) {
+        const body = await this.transact('readwrite', (s) => {
+            s.entries.put({ ...entry, lastUsed: Date.now() });
+            return s.bodies.get(entry.url);
+        }).catch(() => undefined);
+        if (!body) {
+            await this.transact('readwrite', (s) => s.entries.delete(entry.url)).catch(() => {});
+        }
+        return body;
+    }
+
+    async store(url, headers, body) {
+        const maxAge = AssetCache.maxAge(headers);
+        if (maxAge === null || body.byteLength > this.maxBytes) {
+            await this.transact('readwrite', (s) => {
+                s.entries.delete(url);
+                s.bodies.delete(url);
+            });
+            return;
+        }
+        await this.transact('readwrite', (s) => {
+            s.entries.put({
+                url,
+                etag: headers.get('ETag'),
+                lastModified: headers.get('Last-Modified'),
+                freshUntil: Date.now() + maxAge * 1000,
+                size: body.byteLength,
+                lastUsed: Date.now(),
+            });
+            s.bodies.put(body, url);
+        });
+        await this.evict();
+    }
+
+    // Drop the least recently used copies until the cache fits
+    async evict() {
+        const entries = await this.transact('readonly', (s) => s.entries.getAll());
+        let total = entries.reduce((sum, entry) => sum + entry.size, 0);
+        if (total <= this.maxBytes) return;
+        entries.sort((a, b) => a.lastUsed - b.lastUsed);
+        await this.transact('readwrite', (s) => {
+            for (const entry of entries) {
+                if (total <= this.maxBytes) break;
+                s.entries.delete(entry.url);
+                s.bodies.delete(entry.url);
+                total -= entry.size;
+            }
+        });
+    }
+
+    static async download(response) {
+        if (!response.ok) {
+            throw new Error(`HTTP ${response.status}: ${response.statusText}`);
+        }
+        return response.arrayBuffer();
+    }
+
+    // Seconds a response may be used without revalidating it, null if it
+    // must not be kept (no-store). Without max-age it is revalidated every
+    // time.
+    static maxAge(headers) {
+        const directives = (headers.get('Cache-Control') || '').toLowerCase().split(',').map((d) => d.trim());
+        if (directives.includes('no-store')) return null;
+        if (directives.includes('no-cache')) return 0;
+        const maxAge = directives.find((d) => d.startsWith('max-age='));
+        return maxAge ? parseInt(maxAge.slice('max-age='.length).replace(/"/g, ''), 10) || 0 : 0;
+    }
+}
+
 // ============================================================================
 // Asset Manager - Loads and caches GLB/glTF files
 // ============================================================================
 
 class AssetManager {
+    // Asset URI schemes this shell can load (reported to the core on Init)
+    static SUPPORTED_SCHEMES = ['bundle', 'http', 'https'];
+
     constructor() {
         this.meshes = new Map(); // asset_id -> LoadedMesh
+        this.cache = new AssetCache(); // Fetched files, kept across sessions
         this.basePath = './';
+        // Bundle paths of assets stored once for several apps -> their URL
+        // relative to basePath (written by `fastn build --all`)
+        this.assetMap = {};
     }
 
     setBasePath(path) {
         this.basePath = path.endsWith('/') ? path : path + '/';
     }
 
-    async load(assetId, path) {
+    // Load the app's asset-map.json. Builds without one (hand-made
+    // deployments) load every asset from the app's own folder.
+    async loadAssetMap() {
+        try {
+            const response = await fetch(this.basePath + 'asset-map.json');
+            if (response.ok) {
+                this.assetMap = await response.json();
+            }
+        } catch (e) {
+            console.warn(`No asset map: ${e.message}`);
+        }
+    }
+
+    bundleUrl(path) {
+        return this.basePath + (this.assetMap[path] ?? path);
+    }
+
+    // Map an asset URI to a fetchable URL. Bare paths (older cores) are
+    // treated as bundle paths.
+    resolveUri(uri) {
+        const match = /^([a-zA-Z][a-zA-Z0-9+.-]*):\/\/(.*)$/.exec(uri);
+        if (!match) return this.bundleUrl(uri);
+
+        const scheme = match[1].toLowerCase();
+        switch (scheme) {
+            case 'bundle':
+                return this.bundleUrl(match[2]);
+            case 'http':
+            case 'https':
+                return uri;
+            default:
+                throw new Error(`Unsupported asset scheme: ${scheme}://`);
+        }
+    }
+
+    // `cache` is the load's cache policy: Default, Revalidate or NoStore
+    async load(assetId, path, cache = 'Default') {
         if (this.meshes.has(assetId)) {
             console.log(`Asset ${assetId} already loaded, skipping`);
             return true;
         }
 
-        const fullPath = this.basePath + path;
-        console.log(`Loading asset ${assetId} from ${fullPath}`);
-
         try {
-            const response = await fetch(fullPath);
-            if (!response.ok) {
-                throw new Error(`HTTP ${response.status}: ${response.statusText}`);
-            }
+            const fullPath = this.resolveUri(path);
+            console.log(`Loading asset ${assetId} from ${fullPath}`);
 
-            const arrayBuffer = await response.arrayBuffer();
+            const arrayBuffer = await this.cache.fetch(fullPath, cache);
             const mesh = this.parseGLB(arrayBuffer);
 
             this.meshes.set(assetId, mesh);
@@ -571,6 +2690,19 @@ class AssetManager {
         return this.meshes.get(assetId);
     }
 
+    // Generate a primitive's mesh and register it like a loaded asset,
+    // shared by volumes of the same shape. Returns its asset_id, or null
+    // for shapes PrimitiveGeometry doesn't know.
+    primitiveMesh(shape, params) {
+        const assetId = `primitive:${shape}:${JSON.stringify(params)}`;
+        if (!this.meshes.has(assetId)) {
+            const mesh = PrimitiveGeometry.generate(shape, params);
+            if (!mesh) return null;
+            this.meshes.set(assetId, mesh);
+        }
+        return assetId;
+    }
+
     parseGLB(arrayBuffer) {
         const dataView = new DataView(arrayBuffer);
 
@@ -714,8 +2846,13 @@ if (typeof window !== 'undefined') {
     window.FastnCore = FastnCore;
     window.InputHandler = InputHandler;
     window.SceneState = SceneState;
+    window.CommandScheduler = CommandScheduler;
+    window.AccessibilityLayer = AccessibilityLayer;
+    window.Picking = Picking;
+    window.TransformAnimations = TransformAnimations;
     window.MathUtils = MathUtils;
     window.CubeGeometry = CubeGeometry;
+    window.PrimitiveGeometry = PrimitiveGeometry;
     window.AssetManager = AssetManager;
     window.detectPlatform = detectPlatform;
     window.WASM_PATH = WASM_PATH;
@@ -0,0 +1,61 @@
+//! Opening a spoke from a program (`SpokeBuilder`)
+
+use crate::{Result, Spoke};
+use std::path::PathBuf;
+
+/// How to open a spoke, from `Spoke::builder`
+///
+/// ```no_run
+/// # async fn run() -> fastn_spoke::Result<()> {
+/// let spoke = fastn_spoke::Spoke::builder()
+///     .home("/var/lib/my-daemon/spoke")
+///     .or_init("<hub-id52>", "http://localhost:3000", "my-daemon")
+///     .open()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SpokeBuilder {
+    home: Option<PathBuf>,
+    /// (hub ID52, hub URL, alias) to initialize with
+    init: Option<(String, String, String)>,
+}
+
+impl SpokeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// SPOKE_HOME to use; without one, the `SPOKE_HOME` environment
+    /// variable or else `Spoke::default_home`, like the CLI
+    pub fn home(mut self, home: impl Into<PathBuf>) -> Self {
+        self.home = Some(home.into());
+        self
+    }
+
+    /// Initialize the spoke with this hub and alias if the home has none
+    /// yet; without it, `open` fails with `Error::NotInitialized`
+    pub fn or_init(mut self, hub_id52: &str, hub_url: &str, alias: &str) -> Self {
+        self.init = Some((hub_id52.to_string(), hub_url.to_string(), alias.to_string()));
+        self
+    }
+
+    /// The home `open` uses
+    pub fn resolved_home(&self) -> PathBuf {
+        match (&self.home, std::env::var_os("SPOKE_HOME")) {
+            (Some(home), _) => home.clone(),
+            (None, Some(home)) => PathBuf::from(home),
+            (None, None) => Spoke::default_home(),
+        }
+    }
+
+    /// Load the spoke, initializing it first if asked to
+    pub async fn open(self) -> Result<Spoke> {
+        let home = self.resolved_home();
+        match self.init {
+            Some((hub_id52, hub_url, alias)) => Spoke::load_or_init(home, &hub_id52, &hub_url, &alias).await,
+            None => Spoke::load(&home).await,
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Typed access to one kosha (`KoshaClient`)
+//!
+//! `HubConnection` speaks the hub protocol command by command, with JSON in
+//! and out. A `KoshaClient` is bound to one kosha on one hub and takes and
+//! returns plain types: bytes instead of base64, parsed versions instead of
+//! timestamp strings. Large files go up and down in chunks without the
+//! caller asking for it.
+
+use crate::{Error, HubConnection, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+/// Entry of a kosha directory listing
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
+
+/// One version of a file, as listed by `KoshaClient::versions`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FileVersion {
+    pub timestamp: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// A file matching `KoshaClient::search`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SearchHit {
+    pub path: String,
+    /// Relevance, higher is better; only comparable within one search
+    pub score: f64,
+    /// Text around the matches, which are wrapped in `**`
+    pub snippet: String,
+    pub modified: DateTime<Utc>,
+}
+
+/// A file's content and the version it was read at
+#[derive(Debug, Clone)]
+pub struct FileContent {
+    pub content: Vec<u8>,
+    /// Pass as `base_version` to only overwrite this version
+    pub version: Option<DateTime<Utc>>,
+}
+
+/// Something that happened in a watched kosha
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// A file under the watched prefix changed
+    File(fastn_net::FileChange),
+    /// A key-value entry changed (only when watching the whole kosha)
+    Kv(String),
+    /// The hub dropped this many events; re-read whatever you keep in sync
+    Missed(u64),
+}
+
+/// Changes in a kosha as the hub pushes them, from `KoshaClient::watch`
+pub struct Watch {
+    subscription: fastn_net::client::Subscription<fastn_net::PushEvent>,
+}
+
+impl Watch {
+    /// Wait for the next change; `None` once the hub closed the connection
+    ///
+    /// Not cancel-safe: don't race it in a `select!` that may drop it
+    /// midway, give it a task of its own instead.
+    pub async fn next(&mut self) -> Result<Option<WatchEvent>> {
+        loop {
+            let event = match self.subscription.next().await? {
+                Some(fastn_net::PushEvent::FileChange { change, .. }) => WatchEvent::File(change),
+                Some(fastn_net::PushEvent::KvUpdated { key, .. }) => WatchEvent::Kv(key),
+                Some(fastn_net::PushEvent::Missed { count }) => WatchEvent::Missed(count),
+                // `file_changed` repeats `file_change` without versions
+                Some(_) => continue,
+                None => return Ok(None),
+            };
+            return Ok(Some(event));
+        }
+    }
+}
+
+/// One kosha on one hub, from `Spoke::kosha`
+pub struct KoshaClient {
+    conn: HubConnection,
+    /// The `<hub>` the client was made for: "self", a known hub, or a hub
+    /// the configured one forwards to
+    hub: String,
+    /// Target hub of requests on `conn`
+    target_hub: String,
+    kosha: String,
+}
+
+impl KoshaClient {
+    pub(crate) fn new(conn: HubConnection, hub: &str, target_hub: &str, kosha: &str) -> Self {
+        Self {
+            conn,
+            hub: hub.to_string(),
+            target_hub: target_hub.to_string(),
+            kosha: kosha.to_string(),
+        }
+    }
+
+    /// The hub, as passed to `Spoke::kosha`
+    pub fn hub(&self) -> &str {
+        &self.hub
+    }
+
+    /// The kosha's name
+    pub fn name(&self) -> &str {
+        &self.kosha
+    }
+
+    /// Whether requests go straight to the hub holding the kosha, rather
+    /// than being forwarded by the configured hub. Only then can it be
+    /// watched.
+    pub fn is_direct(&self) -> bool {
+        self.target_hub == "self"
+    }
+
+    /// The underlying connection, for commands without a typed method
+    pub fn connection(&self) -> &HubConnection {
+        &self.conn
+    }
+
+    // Files
+
+    /// Read a file (up to `fastn_net::MAX_CHUNK_SIZE`; use `download` for
+    /// larger ones)
+    pub async fn read(&self, path: &str) -> Result<FileContent> {
+        let response = self.conn.read_file(&self.target_hub, &self.kosha, path).await?;
+        let content = response.get("content").and_then(|v| v.as_str()).unwrap_or_default();
+        Ok(FileContent {
+            content: decode(content)?,
+            version: response.get("modified").and_then(|v| v.as_str()).and_then(|v| v.parse().ok()),
+        })
+    }
+
+    /// Write a file of any size, returning the version written
+    pub async fn write(&self, path: &str, content: &[u8]) -> Result<DateTime<Utc>> {
+        self.write_versioned(path, content, None).await
+    }
+
+    /// Write a file only if it is still at `base_version`; fails with
+    /// `Error::Conflict` carrying the current version otherwise
+    pub async fn write_versioned(
+        &self,
+        path: &str,
+        content: &[u8],
+        base_version: Option<DateTime<Utc>>,
+    ) -> Result<DateTime<Utc>> {
+        let base_version = base_version.map(|v| v.to_rfc3339());
+        let response = if content.len() > fastn_net::MAX_CHUNK_SIZE {
+            self.conn
+                .upload_file(&self.target_hub, &self.kosha, path, content, base_version.as_deref())
+                .await?
+        } else {
+            let content_base64 = base64::Engine::encode(&base64::prelude::BASE64_STANDARD, content);
+            self.conn
+                .write_file(&self.target_hub, &self.kosha, path, &content_base64, base_version.as_deref())
+                .await?
+        };
+        response
+            .get("modified")
+            .and_then(|v| v.as_str())
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| Error::Hub(format!("no version in write response: {}", response)))
+    }
+
+    /// Download a file of any size, verifying its SHA-256
+    pub async fn download(&self, path: &str) -> Result<Vec<u8>> {
+        self.conn.download_file(&self.target_hub, &self.kosha, path).await
+    }
+
+    /// Download a file of any size to `dest`, resuming an interrupted
+    /// download of the same content
+    pub async fn download_to(&self, path: &str, dest: &std::path::Path) -> Result<fastn_net::FileHash> {
+        self.conn.download_file_to(&self.target_hub, &self.kosha, path, dest).await
+    }
+
+    /// SHA-256, size and version of a file's current content
+    pub async fn file_hash(&self, path: &str) -> Result<fastn_net::FileHash> {
+        self.conn.file_hash(&self.target_hub, &self.kosha, path).await
+    }
+
+    /// Entries of a directory, by name
+    pub async fn list_dir(&self, path: &str) -> Result<Vec<DirEntry>> {
+        let response = self.conn.list_dir(&self.target_hub, &self.kosha, path).await?;
+        Ok(serde_json::from_value(response.get("entries").cloned().unwrap_or_default())?)
+    }
+
+    /// Files under `path_prefix` (empty for the whole kosha) whose name,
+    /// text or metadata has every word of `query`, best first; files the
+    /// caller can't read are left out
+    pub async fn search(&self, query: &str, path_prefix: &str, limit: Option<usize>) -> Result<Vec<SearchHit>> {
+        let response = self.conn.search(&self.target_hub, &self.kosha, query, path_prefix, limit).await?;
+        Ok(serde_json::from_value(response.get("hits").cloned().unwrap_or_default())?)
+    }
+
+    /// Versions of a file, newest (the current content) first
+    pub async fn versions(&self, path: &str) -> Result<Vec<FileVersion>> {
+        let response = self.conn.get_versions(&self.target_hub, &self.kosha, path).await?;
+        Ok(serde_json::from_value(response.get("versions").cloned().unwrap_or_default())?)
+    }
+
+    /// Content of a file at one of its `versions`
+    pub async fn read_version(&self, path: &str, timestamp: DateTime<Utc>) -> Result<Vec<u8>> {
+        let timestamp = timestamp.to_rfc3339();
+        let response = self.conn.read_version(&self.target_hub, &self.kosha, path, &timestamp).await?;
+        decode(response.get("content").and_then(|v| v.as_str()).unwrap_or_default())
+    }
+
+    /// Rename a file, history included
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.conn.rename(&self.target_hub, &self.kosha, from, to).await?;
+        Ok(())
+    }
+
+    /// Delete a file; its history stays on the hub
+    pub async fn delete(&self, path: &str) -> Result<()> {
+        self.conn.delete(&self.target_hub, &self.kosha, path).await?;
+        Ok(())
+    }
+
+    /// Delete a directory and everything in it, returning the deleted files
+    pub async fn delete_dir(&self, path: &str) -> Result<Vec<String>> {
+        let response = self.conn.delete_dir(&self.target_hub, &self.kosha, path).await?;
+        Ok(serde_json::from_value(response.get("deleted").cloned().unwrap_or_default())?)
+    }
+
+    /// Copy a file or directory to a path that doesn't exist yet, returning
+    /// the new files
+    pub async fn copy(&self, from: &str, to: &str) -> Result<Vec<String>> {
+        let response = self.conn.copy(&self.target_hub, &self.kosha, from, to).await?;
+        Ok(serde_json::from_value(response.get("copied").cloned().unwrap_or_default())?)
+    }
+
+    /// Move a file or directory, history included, to a path that doesn't
+    /// exist yet; returns the (old, new) path of each file
+    pub async fn move_path(&self, from: &str, to: &str) -> Result<Vec<(String, String)>> {
+        #[derive(Deserialize)]
+        struct Moved {
+            from: String,
+            to: String,
+        }
+        let response = self.conn.move_path(&self.target_hub, &self.kosha, from, to).await?;
+        let moved: Vec<Moved> = serde_json::from_value(response.get("moved").cloned().unwrap_or_default())?;
+        Ok(moved.into_iter().map(|m| (m.from, m.to)).collect())
+    }
+
+    // Key-value store
+
+    /// Value of a key, `None` if it isn't set
+    pub async fn kv_get(&self, key: &str) -> Result<Option<serde_json::Value>> {
+        let response = self.conn.kv_get(&self.target_hub, &self.kosha, key).await?;
+        Ok(response.get("value").cloned().filter(|v| !v.is_null()))
+    }
+
+    pub async fn kv_set(&self, key: &str, value: serde_json::Value) -> Result<()> {
+        self.conn.kv_set(&self.target_hub, &self.kosha, key, value).await?;
+        Ok(())
+    }
+
+    pub async fn kv_delete(&self, key: &str) -> Result<()> {
+        self.conn.kv_delete(&self.target_hub, &self.kosha, key).await?;
+        Ok(())
+    }
+
+    // Watching
+
+    /// Follow changes under `path_prefix` (empty for the whole kosha, which
+    /// includes key-value changes)
+    ///
+    /// Only for kosha on the hub the client talks to directly (see
+    /// `is_direct`): hubs don't forward change notifications.
+    pub async fn watch(&self, path_prefix: &str) -> Result<Watch> {
+        if !self.is_direct() {
+            return Err(Error::Unsupported(format!(
+                "{} is reached through another hub, which doesn't forward changes",
+                self.hub
+            )));
+        }
+        let mut topics = vec![fastn_net::Topic::Files {
+            instance: self.kosha.clone(),
+            path_prefix: path_prefix.trim_matches('/').to_string(),
+        }];
+        if path_prefix.trim_matches('/').is_empty() {
+            topics.push(fastn_net::Topic::Kosha {
+                instance: self.kosha.clone(),
+            });
+        }
+        Ok(Watch {
+            subscription: self.conn.subscribe(topics).await?,
+        })
+    }
+}
+
+fn decode(content: &str) -> Result<Vec<u8>> {
+    base64::Engine::decode(&base64::prelude::BASE64_STANDARD, content)
+        .map_err(|e| Error::Hub(format!("invalid base64 from hub: {}", e)))
+}
@@ -0,0 +1,179 @@
+//! Resumable downloads (native)
+//!
+//! `HubConnection::download_file_to` fetches a file chunk by chunk into
+//! `downloads/` in SPOKE_HOME. Each chunk is checked against the per-chunk
+//! SHA-256 from `file_hash` before it is kept, and `<sha256>.json` next to
+//! the data records how many bytes passed. Files are named after the
+//! content's SHA-256, so when the connection drops (or the process is
+//! killed) fetching the same content again, from any path, continues after
+//! the last good chunk. Partial downloads nobody resumed for
+//! `DOWNLOAD_EXPIRY` are removed.
+
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// Partial downloads untouched for this long are removed
+pub const DOWNLOAD_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Contents of `<sha256>.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Progress {
+    sha256: String,
+    size: u64,
+    chunk_size: u64,
+    /// Bytes at the start of `<sha256>.part` that passed verification
+    verified: u64,
+}
+
+/// A download in progress, kept on disk
+pub(crate) struct PartialDownload {
+    part: PathBuf,
+    progress_path: PathBuf,
+    file: tokio::fs::File,
+    progress: Progress,
+}
+
+impl PartialDownload {
+    /// Pick up the partial download of `hash`'s content, or start one
+    pub(crate) async fn open(dir: &Path, hash: &fastn_net::FileHash) -> Result<Self> {
+        // The digest names files, so it must be nothing but hex
+        let valid = hash.sha256.len() == 64 && hash.sha256.bytes().all(|b| b.is_ascii_hexdigit());
+        if !valid {
+            return Err(Error::Integrity(format!("invalid sha256 from hub: {}", hash.sha256)));
+        }
+
+        tokio::fs::create_dir_all(dir).await?;
+        remove_expired(dir).await;
+
+        let part = dir.join(format!("{}.part", hash.sha256));
+        let progress_path = dir.join(format!("{}.json", hash.sha256));
+        let fresh = Progress {
+            sha256: hash.sha256.clone(),
+            size: hash.size,
+            chunk_size: hash.chunk_size,
+            verified: 0,
+        };
+        let mut progress = match tokio::fs::read(&progress_path).await {
+            Ok(bytes) => match serde_json::from_slice::<Progress>(&bytes) {
+                Ok(saved) if Progress { verified: 0, ..saved.clone() } == fresh => saved,
+                _ => fresh,
+            },
+            Err(_) => fresh,
+        };
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&part)
+            .await?;
+        // Bytes written after the last recorded chunk were never verified
+        progress.verified = progress.verified.min(file.metadata().await?.len());
+        file.set_len(progress.verified).await?;
+        file.seek(std::io::SeekFrom::End(0)).await?;
+
+        Ok(Self {
+            part,
+            progress_path,
+            file,
+            progress,
+        })
+    }
+
+    /// Bytes kept so far; the next chunk starts here
+    pub(crate) fn verified(&self) -> u64 {
+        self.progress.verified
+    }
+
+    /// Keep a verified chunk
+    pub(crate) async fn append(&mut self, bytes: &[u8]) -> Result<()> {
+        self.file.write_all(bytes).await?;
+        self.file.sync_data().await?;
+        self.progress.verified += bytes.len() as u64;
+        tokio::fs::write(&self.progress_path, serde_json::to_vec(&self.progress)?).await?;
+        Ok(())
+    }
+
+    /// Check the whole file and move it to `dest`
+    pub(crate) async fn finish_to(self, dest: &Path) -> Result<()> {
+        let part = self.finish().await?;
+        if let Some(parent) = dest.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Rename fails across filesystems; copy then
+        if tokio::fs::rename(&part, dest).await.is_err() {
+            tokio::fs::copy(&part, dest).await?;
+            tokio::fs::remove_file(&part).await?;
+        }
+        Ok(())
+    }
+
+    /// Check the whole file and read it into memory
+    pub(crate) async fn finish_to_memory(self) -> Result<Vec<u8>> {
+        let part = self.finish().await?;
+        let content = tokio::fs::read(&part).await?;
+        tokio::fs::remove_file(&part).await?;
+        Ok(content)
+    }
+
+    /// Forget the download, e.g. because the content changed on the hub
+    pub(crate) async fn discard(self) {
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.part).await;
+        let _ = tokio::fs::remove_file(&self.progress_path).await;
+    }
+
+    /// Verify the SHA-256 of the whole file, returning where the data is
+    async fn finish(mut self) -> Result<PathBuf> {
+        self.file.flush().await?;
+        let actual = sha256_file(&self.part).await?;
+        if actual != self.progress.sha256 {
+            let sha256 = self.progress.sha256.clone();
+            self.discard().await;
+            return Err(Error::Integrity(format!("download does not match sha256 {}", sha256)));
+        }
+        drop(self.file);
+        tokio::fs::remove_file(&self.progress_path).await?;
+        Ok(self.part)
+    }
+}
+
+/// SHA-256 of a file as lowercase hex, read in pieces
+async fn sha256_file(path: &Path) -> Result<String> {
+    use sha2::Digest;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = sha2::Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Remove partial downloads older than `DOWNLOAD_EXPIRY`
+async fn remove_expired(dir: &Path) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let expired = entry
+            .metadata()
+            .await
+            .and_then(|m| m.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > DOWNLOAD_EXPIRY));
+        if expired {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Hub subcommand handlers
+//!
+//! Usage: fastn-spoke hub <operation> [args...]
+//!
+//! Operations:
+//!   add <hub-id52> <hub-url> [alias] [--own-key]  - Add a hub to talk to directly
+//!   remove <hub>                                  - Remove a known hub
+//!   list                                          - List the configured and known hubs
+//!   set-url <hub> <hub-url>                       - Change a hub's URL
+
+use fastn_spoke::Spoke;
+use std::path::Path;
+
+/// Run the hub subcommand
+pub async fn run(args: &[String], home: &Path) {
+    let op = args.first().map(|s| s.as_str());
+
+    match op {
+        Some("add") => add(&args[1..], home).await,
+        Some("remove") => remove(&args[1..], home).await,
+        Some("list") => list(home).await,
+        Some("set-url") => set_url(&args[1..], home).await,
+        Some("help") | Some("-h") | Some("--help") => print_help(),
+        Some(cmd) => {
+            eprintln!("Unknown hub operation: {}", cmd);
+            print_help();
+            std::process::exit(1);
+        }
+        None => {
+            eprintln!("Missing hub operation");
+            print_help();
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_help() {
+    println!("fastn-spoke hub - Manage the hubs this spoke talks to");
+    println!();
+    println!("Usage: fastn-spoke hub <operation> [args...]");
+    println!();
+    println!("Operations:");
+    println!("  add <hub-id52> <hub-url> [alias] [--own-key]  Add a hub to talk to directly");
+    println!("                                                (--own-key: use a separate spoke ID52 for it)");
+    println!("  remove <hub>                                  Remove a known hub");
+    println!("  list                                          List the configured and known hubs");
+    println!("  set-url <hub> <hub-url>                       Change a hub's URL ('self' for the configured hub)");
+    println!();
+    println!("Known hubs can be used as <hub> in kosha operations; requests then go");
+    println!("to that hub directly instead of being forwarded by the configured hub.");
+    println!();
+    println!("Examples:");
+    println!("  fastn-spoke hub add <hub-id52> https://hub.example.com work --own-key");
+    println!("  fastn-spoke kosha read-file work root spokes.txt");
+}
+
+/// Load the spoke or exit
+async fn load(home: &Path) -> Spoke {
+    match Spoke::load(home).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to load spoke: {}", e);
+            eprintln!("Run 'fastn-spoke init <hub-id52> <hub-url> <alias>' first.");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Add a known hub
+/// Usage: add <hub-id52> <hub-url> [alias] [--own-key]
+async fn add(args: &[String], home: &Path) {
+    let own_key = args.iter().any(|a| a == "--own-key");
+    let args: Vec<&String> = args.iter().filter(|a| *a != "--own-key").collect();
+
+    if args.len() < 2 {
+        eprintln!("Usage: fastn-spoke hub add <hub-id52> <hub-url> [alias] [--own-key]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub-id52   The 52-character ID of the hub (output of 'fastn-hub id')");
+        eprintln!("  hub-url    The HTTP URL of the hub (e.g., 'https://hub.example.com')");
+        eprintln!("  alias      A short name to use for the hub in kosha operations");
+        eprintln!("  --own-key  Use a separate spoke ID52 for this hub");
+        std::process::exit(1);
+    }
+
+    let mut spoke = load(home).await;
+    let (id52, url, alias) = (args[0], args[1], args.get(2).map(|a| a.as_str()));
+
+    let hub = match spoke.add_hub(id52, alias, url, own_key).await {
+        Ok(hub) => hub.clone(),
+        Err(e) => {
+            eprintln!("Failed to add hub: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let spoke_id52 = spoke.id52_for(&hub.id52).expect("hub was just added");
+
+    println!("Hub added successfully!");
+    println!("Hub ID52:   {}", hub.id52);
+    println!("Hub URL:    {}", hub.url);
+    if let Some(alias) = &hub.alias {
+        println!("Alias:      {}", alias);
+    }
+    println!("Spoke ID52: {}", spoke_id52);
+    println!();
+    println!("Ask the hub admin to run: fastn-hub add-spoke {}", spoke_id52);
+}
+
+/// Remove a known hub
+/// Usage: remove <hub>
+async fn remove(args: &[String], home: &Path) {
+    let Some(hub) = args.first() else {
+        eprintln!("Usage: fastn-spoke hub remove <hub>");
+        eprintln!();
+        eprintln!("  hub  ID52 or alias of a known hub");
+        std::process::exit(1);
+    };
+
+    let mut spoke = load(home).await;
+    match spoke.remove_hub(hub).await {
+        Ok(()) => println!("Hub removed: {}", hub),
+        Err(e) => {
+            eprintln!("Failed to remove hub: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// List the configured hub and the known hubs
+async fn list(home: &Path) {
+    let spoke = load(home).await;
+
+    println!("Configured hub (self):");
+    println!("  {}  {}", spoke.hub_id52(), spoke.hub_url());
+
+    let hubs = spoke.list_hubs();
+    if hubs.is_empty() {
+        println!("No other hubs.");
+        return;
+    }
+    println!("Known hubs:");
+    for hub in hubs {
+        println!(
+            "  {}: {}  {}{}",
+            hub.id52,
+            hub.alias.as_deref().unwrap_or("-"),
+            hub.url,
+            if hub.own_key { "  (own key)" } else { "" }
+        );
+    }
+}
+
+/// Change the URL of a hub
+/// Usage: set-url <hub> <hub-url>
+async fn set_url(args: &[String], home: &Path) {
+    if args.len() < 2 {
+        eprintln!("Usage: fastn-spoke hub set-url <hub> <hub-url>");
+        eprintln!();
+        eprintln!("  hub      ID52 or alias of a known hub, or 'self' for the configured hub");
+        eprintln!("  hub-url  The new HTTP URL");
+        std::process::exit(1);
+    }
+
+    let mut spoke = load(home).await;
+    match spoke.set_hub_url(&args[0], &args[1]).await {
+        Ok(()) => println!("Hub URL updated: {} -> {}", args[0], args[1]),
+        Err(e) => {
+            eprintln!("Failed to update hub URL: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
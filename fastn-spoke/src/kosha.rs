@@ -5,26 +5,54 @@
 //! Operations:
 //!   read-file <hub> <kosha> <path>                  - Read a file
 //!   write-file <hub> <kosha> <path> <local-file>    - Write a file
+//!   download <hub> <kosha> <path> <local-file>      - Download a file of any size
 //!   list-dir <hub> <kosha> <path>                   - List directory contents
+//!   get-versions <hub> <kosha> <path>               - List a file's versions
+//!   read-version <hub> <kosha> <path> <timestamp>   - Read a specific version
+//!   search <hub> <kosha> <query> [path-prefix]      - Find files by name, text or metadata
+//!   watch <hub> <kosha> [path-prefix]               - Print changes as the hub pushes them
+//!   delete-dir <hub> <kosha> <path>                 - Delete a directory, keeping history
+//!   copy <hub> <kosha> <from> <to>                  - Copy a file or directory
+//!   move <hub> <kosha> <from> <to>                  - Move a file or directory with its history
 //!   ... more to be implemented
 //!
+//! Reads and listings take `--output raw|json|table` (and reads `--out
+//! <file>`), and failed requests exit with a code saying why; see `output`.
+//!
+//! Writes whose hub can't be reached are queued in the outbox (see
+//! 'fastn-spoke outbox') instead of failing.
+//!
+//! `--explain`, on any operation but `watch`, asks your own hub why it
+//! would deny the request to others: a denial then prints the ACL modules
+//! consulted and what each decided.
+//!
 //! Hub aliases:
 //!   self     - Access your own hub directly (no ACL checks)
+//!   <known>  - Access a hub added with 'fastn-spoke hub add' directly
 //!   <alias>  - Access a remote hub via hub-to-hub forwarding (ACL applies)
 
-use fastn_spoke::Spoke;
-use std::io::Write;
+use crate::output::{self, Format, Output};
+use fastn_spoke::{Delivery, Spoke, WatchEvent};
 use std::path::Path;
 
 /// Run the kosha subcommand
 pub async fn run(args: &[String], home: &Path) {
+    let explain = args.iter().any(|arg| arg == "--explain");
+    let args: Vec<String> = args.iter().filter(|arg| *arg != "--explain").cloned().collect();
     let op = args.first().map(|s| s.as_str());
 
     match op {
-        Some("read-file") => read_file(&args[1..], home).await,
-        Some("write-file") => write_file(&args[1..], home).await,
-        Some("list-dir") | Some("get-versions") | Some("read-version")
-        | Some("rename") | Some("delete") | Some("kv-get") | Some("kv-set") | Some("kv-delete") => {
+        Some("read-file") => read_file(&args[1..], home, explain).await,
+        Some("read-version") => read_version(&args[1..], home, explain).await,
+        Some("list-dir") => list_dir(&args[1..], home, explain).await,
+        Some("get-versions") => get_versions(&args[1..], home, explain).await,
+        Some("search") => search(&args[1..], home, explain).await,
+        Some("write-file") => write_file(&args[1..], home, explain).await,
+        Some("download") => download(&args[1..], home, explain).await,
+        Some("watch") => watch(&args[1..], home).await,
+        Some("delete-dir") => delete_dir(&args[1..], home, explain).await,
+        Some("copy") | Some("move") => copy_or_move(op.unwrap(), &args[1..], home, explain).await,
+        Some("rename") | Some("delete") | Some("kv-get") | Some("kv-set") | Some("kv-delete") => {
             eprintln!("Not implemented yet: {}", op.unwrap());
             std::process::exit(1);
         }
@@ -48,120 +76,375 @@ fn print_help() {
     println!("Usage: fastn-spoke kosha <operation> <hub> <kosha> [args...]");
     println!();
     println!("Operations:");
-    println!("  read-file <hub> <kosha> <path>                Read a file");
+    println!("  read-file <hub> <kosha> <path>                Read a file to stdout (or --out <file>)");
     println!("  write-file <hub> <kosha> <path> <local-file>  Write a file from local path");
+    println!("             [--base-version <timestamp>]       (fail if changed since that version)");
+    println!("  download <hub> <kosha> <path> <local-file>    Download a file to local path");
     println!("  list-dir <hub> <kosha> <path>                 List directory contents");
     println!("  get-versions <hub> <kosha> <path>             Get file version history");
     println!("  read-version <hub> <kosha> <path> <timestamp> Read a specific version");
+    println!("  search <hub> <kosha> <query> [path-prefix]    Find files by name, text or metadata");
     println!("  rename <hub> <kosha> <from> <to>              Rename a file");
     println!("  delete <hub> <kosha> <path>                   Delete a file");
+    println!("  delete-dir <hub> <kosha> <path>               Delete a directory and everything in it");
+    println!("  copy <hub> <kosha> <from> <to>                Copy a file or directory");
+    println!("  move <hub> <kosha> <from> <to>                Move a file or directory");
     println!("  kv-get <hub> <kosha> <key>                    Get a key-value");
     println!("  kv-set <hub> <kosha> <key> <value>            Set a key-value");
     println!("  kv-delete <hub> <kosha> <key>                 Delete a key-value");
+    println!("  watch <hub> <kosha> [path-prefix]             Print changes as they happen");
+    println!();
+    println!("Output (read-file, read-version, list-dir, get-versions, search):");
+    println!("  --output raw    File contents as they are, listings one per line");
+    println!("                  (default for reads)");
+    println!("  --output json   One JSON document; file contents in base64");
+    println!("  --output table  Listings in columns (default for listings)");
+    println!("  --out <file>    Save file contents to <file> instead of stdout");
+    println!();
+    println!("ACL debugging (your own hub, any operation but watch):");
+    println!("  --explain       If the hub would deny the request to others, fail with");
+    println!("                  the ACL modules it consulted and what each decided");
+    println!();
+    println!("Exit codes:");
+    println!("  0 ok, 1 local or usage error, 2 hub unreachable, 3 not authorized or");
+    println!("  access denied, 4 no such app or kosha, 5 conflict, 6 rate limit or");
+    println!("  quota, 7 other hub error (e.g. no such file)");
     println!();
     println!("Hub aliases:");
     println!("  self      Access your own hub directly (no ACL checks)");
+    println!("  <known>   Access a hub added with 'fastn-spoke hub add' directly");
     println!("  <alias>   Access a remote hub via hub-to-hub forwarding");
     println!();
     println!("Examples:");
     println!("  fastn-spoke kosha read-file self root spokes.txt");
     println!("  fastn-spoke kosha write-file self my-kosha docs/note.txt ./local.txt");
-    println!("  fastn-spoke kosha list-dir self root /");
+    println!("  fastn-spoke kosha read-file self my-kosha models/city.glb --out city.glb");
+    println!("  fastn-spoke kosha list-dir self root / --output json");
+    println!("  fastn-spoke kosha search self notes \"garden plan\" journal/");
+    println!("  fastn-spoke kosha read-file self photos family/beach.jpg --explain");
 }
 
 /// Read a file from a kosha
-/// Usage: read-file <hub> <kosha> <path>
-async fn read_file(args: &[String], home: &Path) {
+/// Usage: read-file <hub> <kosha> <path> [--output raw|json] [--out <file>]
+async fn read_file(args: &[String], home: &Path, explain: bool) {
+    let (args, output) = Output::take(args);
     if args.len() < 3 {
-        eprintln!("Usage: fastn-spoke kosha read-file <hub> <kosha> <path>");
+        eprintln!("Usage: fastn-spoke kosha read-file <hub> <kosha> <path> [--output raw|json] [--out <file>]");
         eprintln!();
         eprintln!("Arguments:");
-        eprintln!("  hub     Hub alias ('self' for local hub, or remote hub alias)");
-        eprintln!("  kosha   Kosha name (e.g., 'root', 'my-data')");
-        eprintln!("  path    File path within the kosha");
+        eprintln!("  hub       Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha     Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  path      File path within the kosha");
+        eprintln!("  --output  raw (default): the file as it is; json: path, version,");
+        eprintln!("            size and base64 content");
+        eprintln!("  --out     Save the file here instead of printing it");
         eprintln!();
         eprintln!("Example:");
         eprintln!("  fastn-spoke kosha read-file self root spokes.txt");
         std::process::exit(1);
     }
+    let format = output.format(Format::Raw, &[Format::Raw, Format::Json]);
 
     let hub = &args[0];
     let kosha = &args[1];
     let path = &args[2];
 
-    // Load the spoke
-    let spoke = match Spoke::load(home).await {
-        Ok(s) => s,
+    let spoke = load_spoke(home, explain).await;
+    let client = spoke.kosha(hub, kosha);
+
+    eprintln!("Reading file: {}/{}/{}", hub, kosha, path);
+
+    let file = match client.read(path).await {
+        Ok(file) => file,
+        Err(e) => output::fail("Failed to read file", &e),
+    };
+    let version = file.version.map(|v| v.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true));
+    if let Some(version) = &version {
+        eprintln!("Version: {}", version);
+    }
+    print_file(format, &output, path, version, &file.content);
+}
+
+/// Read an earlier version of a file from a kosha
+/// Usage: read-version <hub> <kosha> <path> <timestamp> [--output raw|json] [--out <file>]
+async fn read_version(args: &[String], home: &Path, explain: bool) {
+    let (args, output) = Output::take(args);
+    if args.len() < 4 {
+        eprintln!("Usage: fastn-spoke kosha read-version <hub> <kosha> <path> <timestamp> [--output raw|json] [--out <file>]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub        Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha      Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  path       File path within the kosha");
+        eprintln!("  timestamp  A version, as listed by get-versions");
+        eprintln!("  --output   raw (default) or json, as for read-file");
+        eprintln!("  --out      Save the version here instead of printing it");
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke kosha read-version self my-kosha docs/note.txt 2025-01-01T10:00:00Z");
+        std::process::exit(1);
+    }
+    let format = output.format(Format::Raw, &[Format::Raw, Format::Json]);
+
+    let hub = &args[0];
+    let kosha = &args[1];
+    let path = &args[2];
+    let timestamp = match args[3].parse::<chrono::DateTime<chrono::Utc>>() {
+        Ok(timestamp) => timestamp,
         Err(e) => {
-            eprintln!("Failed to load spoke: {}", e);
-            eprintln!("Run 'fastn-spoke init <hub-id52> <alias>' first.");
+            eprintln!("Invalid timestamp '{}': {}", args[3], e);
             std::process::exit(1);
         }
     };
 
-    // Create connection (HTTP client)
-    let conn = spoke.connect();
+    let spoke = load_spoke(home, explain).await;
+    let client = spoke.kosha(hub, kosha);
 
-    eprintln!("Reading file: {}/{}/{}", hub, kosha, path);
+    eprintln!("Reading version: {}/{}/{} @ {}", hub, kosha, path, args[3]);
 
-    // Read the file
-    match conn.read_file(hub, kosha, path).await {
-        Ok(response) => {
-            // Response should be { "content": "<base64>" }
-            if let Some(content) = response.get("content").and_then(|v| v.as_str()) {
-                // Decode base64 and print
-                match base64::Engine::decode(&base64::prelude::BASE64_STANDARD, content) {
-                    Ok(bytes) => {
-                        // Try to print as UTF-8, otherwise print as hex
-                        match String::from_utf8(bytes.clone()) {
-                            Ok(text) => {
-                                // Handle broken pipe gracefully (e.g., when piped to head)
-                                if let Err(e) = std::io::stdout().write_all(text.as_bytes()) {
-                                    if e.kind() != std::io::ErrorKind::BrokenPipe {
-                                        eprintln!("Failed to write output: {}", e);
-                                        std::process::exit(1);
-                                    }
-                                } else {
-                                    let _ = std::io::stdout().write_all(b"\n");
-                                }
-                            }
-                            Err(_) => {
-                                eprintln!("(binary file, {} bytes)", bytes.len());
-                                for byte in &bytes {
-                                    print!("{:02x}", byte);
-                                }
-                                println!();
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to decode base64 content: {}", e);
-                        std::process::exit(1);
-                    }
-                }
-            } else {
-                eprintln!("Unexpected response format: {:?}", response);
-                std::process::exit(1);
-            }
+    let content = match client.read_version(path, timestamp).await {
+        Ok(content) => content,
+        Err(e) => output::fail("Failed to read version", &e),
+    };
+    let version = timestamp.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true);
+    print_file(format, &output, path, Some(version), &content);
+}
+
+/// Print file contents read from a kosha, as raw bytes or JSON
+fn print_file(format: Format, output: &Output, path: &str, version: Option<String>, content: &[u8]) {
+    match format {
+        Format::Json => output::print_json(&serde_json::json!({
+            "path": path,
+            "version": version,
+            "size": content.len(),
+            "content": base64::Engine::encode(&base64::prelude::BASE64_STANDARD, content),
+        })),
+        _ => output.write_raw(content),
+    }
+}
+
+/// List a directory in a kosha
+/// Usage: list-dir <hub> <kosha> <path> [--output table|json|raw]
+async fn list_dir(args: &[String], home: &Path, explain: bool) {
+    let (args, output) = Output::take(args);
+    if args.len() < 3 {
+        eprintln!("Usage: fastn-spoke kosha list-dir <hub> <kosha> <path> [--output table|json|raw]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub       Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha     Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  path      Directory within the kosha ('/' for the top)");
+        eprintln!("  --output  table (default), json, or raw: one name per line,");
+        eprintln!("            directories ending in '/'");
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke kosha list-dir self root /");
+        std::process::exit(1);
+    }
+    let format = output.format(Format::Table, &[Format::Table, Format::Json, Format::Raw]);
+
+    let hub = &args[0];
+    let kosha = &args[1];
+    let path = &args[2];
+
+    let spoke = load_spoke(home, explain).await;
+    let entries = match spoke.kosha(hub, kosha).list_dir(path).await {
+        Ok(entries) => entries,
+        Err(e) => output::fail("Failed to list directory", &e),
+    };
+
+    match format {
+        Format::Json => {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    serde_json::json!({
+                        "name": entry.name,
+                        "is_dir": entry.is_dir,
+                        "size": entry.size,
+                        "modified": entry.modified.to_rfc3339(),
+                    })
+                })
+                .collect();
+            output::print_json(&serde_json::Value::Array(entries));
+        }
+        Format::Raw => {
+            let names: String = entries
+                .iter()
+                .map(|entry| format!("{}{}\n", entry.name, if entry.is_dir { "/" } else { "" }))
+                .collect();
+            output::write_stdout(names.as_bytes());
+        }
+        Format::Table => {
+            let rows: Vec<Vec<String>> = entries
+                .iter()
+                .map(|entry| {
+                    vec![
+                        entry.name.clone(),
+                        if entry.is_dir { "dir" } else { "file" }.to_string(),
+                        if entry.is_dir { "-".to_string() } else { entry.size.to_string() },
+                        entry.modified.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    ]
+                })
+                .collect();
+            output::print_table(&["NAME", "TYPE", "SIZE", "MODIFIED"], &[false, false, true], &rows);
+        }
+    }
+}
+
+/// List the versions of a file in a kosha, newest first
+/// Usage: get-versions <hub> <kosha> <path> [--output table|json|raw]
+async fn get_versions(args: &[String], home: &Path, explain: bool) {
+    let (args, output) = Output::take(args);
+    if args.len() < 3 {
+        eprintln!("Usage: fastn-spoke kosha get-versions <hub> <kosha> <path> [--output table|json|raw]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub       Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha     Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  path      File path within the kosha");
+        eprintln!("  --output  table (default), json, or raw: one timestamp per line,");
+        eprintln!("            as read-version takes it");
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke kosha get-versions self my-kosha docs/note.txt");
+        std::process::exit(1);
+    }
+    let format = output.format(Format::Table, &[Format::Table, Format::Json, Format::Raw]);
+
+    let hub = &args[0];
+    let kosha = &args[1];
+    let path = &args[2];
+
+    let spoke = load_spoke(home, explain).await;
+    let versions = match spoke.kosha(hub, kosha).versions(path).await {
+        Ok(versions) => versions,
+        Err(e) => output::fail("Failed to get versions", &e),
+    };
+    let timestamp = |version: &fastn_spoke::FileVersion| {
+        version.timestamp.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+    };
+
+    match format {
+        Format::Json => {
+            let versions: Vec<_> = versions
+                .iter()
+                .map(|version| serde_json::json!({ "timestamp": timestamp(version), "size": version.size }))
+                .collect();
+            output::print_json(&serde_json::Value::Array(versions));
+        }
+        Format::Raw => {
+            let lines: String = versions.iter().map(|version| format!("{}\n", timestamp(version))).collect();
+            output::write_stdout(lines.as_bytes());
+        }
+        Format::Table => {
+            let rows: Vec<Vec<String>> = versions
+                .iter()
+                .map(|version| vec![timestamp(version), version.size.to_string()])
+                .collect();
+            output::print_table(&["VERSION", "SIZE"], &[false, true], &rows);
+        }
+    }
+}
+
+/// Search a kosha, best matches first
+/// Usage: search <hub> <kosha> <query> [path-prefix] [--output table|json|raw]
+async fn search(args: &[String], home: &Path, explain: bool) {
+    let (args, output) = Output::take(args);
+    if args.len() < 3 {
+        eprintln!("Usage: fastn-spoke kosha search <hub> <kosha> <query> [path-prefix] [--output table|json|raw]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub          Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha        Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  query        Words to find (all of them, each as a word prefix)");
+        eprintln!("  path-prefix  Only files under this path");
+        eprintln!("  --output     table (default), json, or raw: one path per line");
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke kosha search self my-kosha \"tomato seeds\" garden/");
+        std::process::exit(1);
+    }
+    let format = output.format(Format::Table, &[Format::Table, Format::Json, Format::Raw]);
+
+    let hub = &args[0];
+    let kosha = &args[1];
+    let query = &args[2];
+    let path_prefix = args.get(3).map(String::as_str).unwrap_or_default();
+
+    let spoke = load_spoke(home, explain).await;
+    let hits = match spoke.kosha(hub, kosha).search(query, path_prefix, None).await {
+        Ok(hits) => hits,
+        Err(e) => output::fail("Failed to search", &e),
+    };
+
+    match format {
+        Format::Json => {
+            let hits: Vec<_> = hits
+                .iter()
+                .map(|hit| {
+                    serde_json::json!({
+                        "path": hit.path,
+                        "score": hit.score,
+                        "snippet": hit.snippet,
+                        "modified": hit.modified.to_rfc3339(),
+                    })
+                })
+                .collect();
+            output::print_json(&serde_json::Value::Array(hits));
+        }
+        Format::Raw => {
+            let lines: String = hits.iter().map(|hit| format!("{}\n", hit.path)).collect();
+            output::write_stdout(lines.as_bytes());
+        }
+        Format::Table => {
+            let rows: Vec<Vec<String>> = hits
+                .iter()
+                .map(|hit| vec![hit.path.clone(), hit.snippet.replace('\n', " ")])
+                .collect();
+            output::print_table(&["PATH", "MATCH"], &[false, false], &rows);
+        }
+    }
+}
+
+/// Load the spoke, or exit telling the user to set it up
+async fn load_spoke(home: &Path, explain: bool) -> Spoke {
+    match Spoke::load(home).await {
+        Ok(mut spoke) => {
+            spoke.set_explain(explain);
+            spoke
         }
         Err(e) => {
-            eprintln!("Failed to read file: {}", e);
+            eprintln!("Failed to load spoke: {}", e);
+            eprintln!("Run 'fastn-spoke init <hub-id52> <alias>' first.");
             std::process::exit(1);
         }
     }
 }
 
 /// Write a file to a kosha
-/// Usage: write-file <hub> <kosha> <path> <local-file>
-async fn write_file(args: &[String], home: &Path) {
+/// Usage: write-file <hub> <kosha> <path> <local-file> [--base-version <timestamp>]
+async fn write_file(args: &[String], home: &Path, explain: bool) {
+    let (args, base_version) = match args.iter().position(|a| a == "--base-version") {
+        Some(i) if i + 1 < args.len() => {
+            let mut rest = args.to_vec();
+            let value = rest.remove(i + 1);
+            rest.remove(i);
+            (rest, Some(value))
+        }
+        _ => (args.to_vec(), None),
+    };
+
     if args.len() < 4 {
-        eprintln!("Usage: fastn-spoke kosha write-file <hub> <kosha> <path> <local-file>");
+        eprintln!("Usage: fastn-spoke kosha write-file <hub> <kosha> <path> <local-file> [--base-version <timestamp>]");
         eprintln!();
         eprintln!("Arguments:");
-        eprintln!("  hub         Hub alias ('self' for local hub, or remote hub alias)");
-        eprintln!("  kosha       Kosha name (e.g., 'root', 'my-data')");
-        eprintln!("  path        Destination file path within the kosha");
-        eprintln!("  local-file  Path to local file to upload");
+        eprintln!("  hub           Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha         Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  path          Destination file path within the kosha");
+        eprintln!("  local-file    Path to local file to upload");
+        eprintln!("  --base-version  Only write if the file is still at this version");
+        eprintln!("                  (the 'modified' timestamp from read-file)");
         eprintln!();
         eprintln!("Example:");
         eprintln!("  fastn-spoke kosha write-file self my-kosha docs/note.txt ./local.txt");
@@ -172,6 +455,13 @@ async fn write_file(args: &[String], home: &Path) {
     let kosha = &args[1];
     let path = &args[2];
     let local_file = &args[3];
+    let base_version = match base_version.map(|v| v.parse::<chrono::DateTime<chrono::Utc>>()).transpose() {
+        Ok(base_version) => base_version,
+        Err(e) => {
+            eprintln!("Invalid --base-version: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Read local file
     let content = match std::fs::read(local_file) {
@@ -182,32 +472,253 @@ async fn write_file(args: &[String], home: &Path) {
         }
     };
 
-    // Base64 encode the content
-    let content_base64 = base64::Engine::encode(&base64::prelude::BASE64_STANDARD, &content);
+    let spoke = load_spoke(home, explain).await;
 
-    // Load the spoke
-    let spoke = match Spoke::load(home).await {
-        Ok(s) => s,
+    eprintln!("Writing file: {}/{}/{} ({} bytes)", hub, kosha, path, content.len());
+
+    // Large files go up in chunks
+    let mut payload = serde_json::json!({
+        "path": path,
+        "content": base64::Engine::encode(&base64::prelude::BASE64_STANDARD, &content),
+    });
+    if let Some(base_version) = base_version {
+        payload["base_version"] = base_version.to_rfc3339().into();
+    }
+    match send_or_queue(&spoke, hub, kosha, "write_file", payload).await {
+        Ok(Some(response)) => {
+            eprintln!("File written successfully");
+            if let Some(version) = response.get("modified").and_then(|v| v.as_str()) {
+                eprintln!("Version: {}", version);
+            }
+        }
+        Ok(None) => {}
+        Err(fastn_spoke::Error::Conflict { current, .. }) => {
+            match current.as_ref().and_then(|v| v.get("timestamp")) {
+                Some(timestamp) => eprintln!("Conflict: file was modified (current version: {})", timestamp),
+                None => eprintln!("Conflict: file no longer exists"),
+            }
+            std::process::exit(output::EXIT_CONFLICT);
+        }
+        Err(e) => output::fail("Failed to write file", &e),
+    }
+}
+
+/// Download a file from a kosha in chunks
+/// Usage: download <hub> <kosha> <path> <local-file>
+async fn download(args: &[String], home: &Path, explain: bool) {
+    if args.len() < 4 {
+        eprintln!("Usage: fastn-spoke kosha download <hub> <kosha> <path> <local-file>");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub         Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha       Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  path        File path within the kosha");
+        eprintln!("  local-file  Where to save the file");
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke kosha download self my-kosha models/city.glb ./city.glb");
+        std::process::exit(1);
+    }
+
+    let hub = &args[0];
+    let kosha = &args[1];
+    let path = &args[2];
+    let local_file = &args[3];
+
+    let spoke = load_spoke(home, explain).await;
+
+    let client = spoke.kosha(hub, kosha);
+
+    eprintln!("Downloading file: {}/{}/{}", hub, kosha, path);
+
+    let hash = match client.download_to(path, Path::new(local_file)).await {
+        Ok(hash) => hash,
         Err(e) => {
-            eprintln!("Failed to load spoke: {}", e);
-            eprintln!("Run 'fastn-spoke init <hub-id52> <alias>' first.");
-            std::process::exit(1);
+            eprintln!("Run the same command again to resume.");
+            output::fail("Failed to download file", &e)
         }
     };
+    eprintln!("Saved {} bytes to {}", hash.size, local_file);
+}
 
-    // Create connection (HTTP client)
-    let conn = spoke.connect();
+/// Delete a directory from a kosha
+/// Usage: delete-dir <hub> <kosha> <path>
+async fn delete_dir(args: &[String], home: &Path, explain: bool) {
+    if args.len() < 3 {
+        eprintln!("Usage: fastn-spoke kosha delete-dir <hub> <kosha> <path>");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub     Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha   Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  path    Directory within the kosha; each file's history is kept");
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke kosha delete-dir self my-kosha drafts");
+        std::process::exit(1);
+    }
 
-    eprintln!("Writing file: {}/{}/{} ({} bytes)", hub, kosha, path, content.len());
+    let hub = &args[0];
+    let kosha = &args[1];
+    let path = &args[2];
 
-    // Write the file (no base_version for new files)
-    match conn.write_file(hub, kosha, path, &content_base64, None).await {
-        Ok(_) => {
-            eprintln!("File written successfully");
+    let spoke = load_spoke(home, explain).await;
+    eprintln!("Deleting directory: {}/{}/{}", hub, kosha, path);
+
+    match send_or_queue(&spoke, hub, kosha, "delete_dir", serde_json::json!({ "path": path })).await {
+        Ok(Some(response)) => {
+            let deleted = strings(&response, "deleted", |v| v.as_str().map(str::to_string));
+            for path in &deleted {
+                println!("deleted {}", path);
+            }
+            eprintln!("Deleted {} files", deleted.len());
         }
+        Ok(None) => {}
+        Err(e) => output::fail("Failed to delete directory", &e),
+    }
+}
+
+/// Copy or move a file or directory within a kosha
+/// Usage: copy|move <hub> <kosha> <from> <to>
+async fn copy_or_move(op: &str, args: &[String], home: &Path, explain: bool) {
+    if args.len() < 4 {
+        eprintln!("Usage: fastn-spoke kosha {} <hub> <kosha> <from> <to>", op);
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub     Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha   Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  from    File or directory within the kosha");
+        eprintln!("  to      New path; must not exist yet");
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke kosha {} self my-kosha docs archive/docs", op);
+        std::process::exit(1);
+    }
+
+    let hub = &args[0];
+    let kosha = &args[1];
+    let from = &args[2];
+    let to = &args[3];
+
+    let spoke = load_spoke(home, explain).await;
+    let command = if op == "copy" {
+        eprintln!("Copying: {}/{}/{} -> {}", hub, kosha, from, to);
+        "copy"
+    } else {
+        eprintln!("Moving: {}/{}/{} -> {}", hub, kosha, from, to);
+        "move"
+    };
+
+    let result = send_or_queue(&spoke, hub, kosha, command, serde_json::json!({ "from": from, "to": to })).await;
+    match result {
+        Ok(Some(response)) if op == "copy" => {
+            let lines = strings(&response, "copied", |v| Some(format!("copied {}", v.as_str()?)));
+            for line in &lines {
+                println!("{}", line);
+            }
+            eprintln!("{} files", lines.len());
+        }
+        Ok(Some(response)) => {
+            let lines = strings(&response, "moved", |v| {
+                Some(format!("moved {} -> {}", v.get("from")?.as_str()?, v.get("to")?.as_str()?))
+            });
+            for line in &lines {
+                println!("{}", line);
+            }
+            eprintln!("{} files", lines.len());
+        }
+        Ok(None) => {}
+        Err(e) => output::fail(&format!("Failed to {}", op), &e),
+    }
+}
+
+/// Send a kosha write, or queue it in the outbox; None if it was queued
+async fn send_or_queue(
+    spoke: &Spoke,
+    hub: &str,
+    kosha: &str,
+    command: &str,
+    payload: serde_json::Value,
+) -> fastn_spoke::Result<Option<serde_json::Value>> {
+    match spoke.send_or_queue(hub, kosha, command, payload).await? {
+        Delivery::Sent(response) => Ok(Some(response)),
+        Delivery::Queued(entry) => {
+            eprintln!("Queued as outbox entry #{}: the hub can't be reached, or earlier", entry.id);
+            eprintln!("writes to the same paths are still queued.");
+            eprintln!("Run 'fastn-spoke outbox flush' to send it.");
+            Ok(None)
+        }
+    }
+}
+
+/// The items of an array in a response, as `item` maps them
+fn strings(
+    response: &serde_json::Value,
+    field: &str,
+    item: impl Fn(&serde_json::Value) -> Option<String>,
+) -> Vec<String> {
+    let items = response.get(field).and_then(|v| v.as_array());
+    items.into_iter().flatten().filter_map(item).collect()
+}
+
+/// Print a kosha's changes as the hub pushes them
+/// Usage: watch <hub> <kosha> [path-prefix]
+async fn watch(args: &[String], home: &Path) {
+    if args.len() < 2 {
+        eprintln!("Usage: fastn-spoke kosha watch <hub> <kosha> [path-prefix]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  hub           'self' or a known hub (changes aren't forwarded between hubs)");
+        eprintln!("  kosha         Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  path-prefix   Only files under this path (key-value changes are skipped)");
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke kosha watch self my-kosha");
+        eprintln!("  fastn-spoke kosha watch self my-kosha docs/");
+        std::process::exit(1);
+    }
+
+    let hub = &args[0];
+    let kosha = &args[1];
+    let path_prefix = args.get(2).map(String::as_str).unwrap_or_default();
+
+    let spoke = match Spoke::load(home).await {
+        Ok(s) => s,
         Err(e) => {
-            eprintln!("Failed to write file: {}", e);
+            eprintln!("Failed to load spoke: {}", e);
             std::process::exit(1);
         }
+    };
+    // Key-value changes come along only when following the whole kosha
+    let mut watch = match spoke.kosha(hub, kosha).watch(path_prefix).await {
+        Ok(watch) => watch,
+        Err(e) => output::fail("Failed to subscribe", &e),
+    };
+    eprintln!("Watching {}/{} (Ctrl+C to stop)", hub, kosha);
+
+    loop {
+        match watch.next().await {
+            Ok(Some(WatchEvent::File(change))) => match change.kind {
+                fastn_net::FileChangeKind::Renamed { from } => {
+                    println!("renamed {} -> {} @ {}", from, change.path, change.version)
+                }
+                kind => println!("{} {} @ {}", kind_name(&kind), change.path, change.version),
+            },
+            Ok(Some(WatchEvent::Kv(key))) => println!("kv {}", key),
+            Ok(Some(WatchEvent::Missed(count))) => eprintln!("Missed {} changes", count),
+            Ok(None) => {
+                eprintln!("Hub closed the connection");
+                break;
+            }
+            Err(e) => output::fail("Watch failed", &e),
+        }
+    }
+}
+
+fn kind_name(kind: &fastn_net::FileChangeKind) -> &'static str {
+    match kind {
+        fastn_net::FileChangeKind::Created => "created",
+        fastn_net::FileChangeKind::Modified => "modified",
+        fastn_net::FileChangeKind::Deleted => "deleted",
+        fastn_net::FileChangeKind::Renamed { .. } => "renamed",
     }
 }
@@ -3,6 +3,22 @@
 //! A Spoke connects to hubs and accesses koshas.
 //! See README.md for full documentation.
 //!
+//! # Library API
+//!
+//! Everything the `fastn-spoke` CLI does is available to programs (native
+//! only):
+//!
+//! - `SpokeBuilder` opens (or initializes) a spoke's home
+//! - `Spoke::kosha` gives a typed `KoshaClient` for one kosha: files of any
+//!   size, versions, directory operations, key-value entries
+//! - `KoshaClient::watch` streams the changes the hub pushes
+//! - `Syncer` keeps a local directory in two-way sync with a kosha
+//! - `Spoke::send_or_queue` keeps writes in an outbox while their hub can't
+//!   be reached, and `Spoke::flush_outbox` delivers them later
+//!
+//! `HubConnection` stays available for raw hub commands. See examples/ for
+//! complete programs.
+//!
 //! # Platform Support
 //!
 //! - Native (desktop): Uses file system storage via tokio::fs
@@ -15,6 +31,29 @@ use thiserror::Error;
 #[cfg(not(target_arch = "wasm32"))]
 use std::path::PathBuf;
 
+#[cfg(not(target_arch = "wasm32"))]
+mod builder;
+#[cfg(not(target_arch = "wasm32"))]
+mod client;
+#[cfg(not(target_arch = "wasm32"))]
+mod download;
+mod offline;
+#[cfg(not(target_arch = "wasm32"))]
+mod syncer;
+#[cfg(not(target_arch = "wasm32"))]
+pub use builder::SpokeBuilder;
+#[cfg(not(target_arch = "wasm32"))]
+pub use client::{DirEntry, FileContent, FileVersion, KoshaClient, SearchHit, Watch, WatchEvent};
+#[cfg(not(target_arch = "wasm32"))]
+pub use download::DOWNLOAD_EXPIRY;
+#[cfg(not(target_arch = "wasm32"))]
+pub use syncer::{IGNORE_FILE, STATE_FILE, SyncSummary, Syncer};
+
+pub use offline::{
+    Delivery, Outbox, OutboxEntry, OutboxFlush, OutboxState, Resolution, FIRST_RETRY_DELAY, MAX_RETRY_DELAY,
+    OUTBOX_FILE, is_queueable, is_unreachable, retry_delay,
+};
+
 pub use fastn_net::{PublicKey, SecretKey};
 
 /// Error types for spoke operations
@@ -36,6 +75,12 @@ pub enum Error {
     #[error("Hub not found: {0}")]
     HubNotFound(String),
 
+    #[error("Hub already known: {0}")]
+    HubExists(String),
+
+    #[error("Invalid hub alias: {0:?}")]
+    InvalidHubAlias(String),
+
     #[error("Connection failed: {0}")]
     ConnectionFailed(String),
 
@@ -45,9 +90,49 @@ pub enum Error {
     #[error("Hub error: {0}")]
     Hub(String),
 
+    /// The hub refused the request; see `HubError` for why
+    #[error("Hub error: {0:?}")]
+    HubRefused(fastn_net::HubError),
+
+    #[error("The invite is for hub {invite}, but this spoke belongs to hub {configured}")]
+    InviteForOtherHub { invite: String, configured: String },
+
+    /// The hub rotated its key; the spoke has followed it, so the same
+    /// request to the hub's new ID52 will go through
+    #[error("Hub {old} moved to the key {new}; the spoke now uses it, try again")]
+    HubKeyRotated { old: String, new: String },
+
+    /// A write was rejected because the file changed since `base_version`
+    ///
+    /// `current` is the latest version (`{ timestamp, size }`), absent if the
+    /// file was deleted. Re-read, merge and retry with its timestamp.
+    #[error("Conflict: {message}")]
+    Conflict {
+        message: String,
+        current: Option<serde_json::Value>,
+    },
+
     #[error("Invalid ID52: {0}")]
     InvalidId52(String),
 
+    /// A chunked transfer didn't produce the content the hub announced
+    #[error("Integrity check failed: {0}")]
+    Integrity(String),
+
+    /// The hub can't do this for the connection, e.g. watch a kosha on a
+    /// hub it forwards to
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    /// An outbox entry can't be queued or delivered
+    #[error("Outbox: {0}")]
+    Outbox(String),
+
+    /// A sync state file is invalid, or belongs to another kosha or path
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("Sync state: {0}")]
+    SyncState(String),
+
     #[cfg(not(target_arch = "wasm32"))]
     #[error("Spoke already initialized at {0:?}")]
     AlreadyInitialized(PathBuf),
@@ -63,6 +148,36 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// SHA-256 as lowercase hex, the form chunked transfers exchange
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode a `read_range` response and check it against `file_hash`
+///
+/// Hubs that send per-chunk digests get each chunk checked; with older hubs
+/// only the version is compared and the whole-file SHA-256 has to catch the
+/// rest.
+fn verified_chunk(path: &str, hash: &fastn_net::FileHash, range: &fastn_net::FileRange) -> Result<Vec<u8>> {
+    if hash.chunks.is_empty() && range.modified != hash.modified {
+        return Err(Error::Integrity(format!("{} changed during download", path)));
+    }
+    let bytes = base64::Engine::decode(&base64::prelude::BASE64_STANDARD, &range.content)
+        .map_err(|e| Error::Integrity(format!("invalid base64 from hub: {}", e)))?;
+    if bytes.is_empty() {
+        return Err(Error::Integrity(format!("{} is shorter than announced", path)));
+    }
+    if hash.chunk_size > 0 {
+        let index = (range.offset / hash.chunk_size) as usize;
+        let expected = hash.chunks.get(index).map(String::as_str);
+        if !range.offset.is_multiple_of(hash.chunk_size) || expected != Some(sha256_hex(&bytes).as_str()) {
+            return Err(Error::Integrity(format!("chunk {} of {} does not match its sha256", index, path)));
+        }
+    }
+    Ok(bytes)
+}
+
 /// Spoke configuration stored in config.json
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpokeConfig {
@@ -83,21 +198,63 @@ pub struct SpokeConfig {
 pub struct KnownHub {
     pub id52: String,
     pub alias: Option<String>,
+    /// The hub's HTTP URL
+    pub url: String,
+    /// Whether the spoke uses a key of its own for this hub (stored in
+    /// hub-keys/<id52>.key) instead of spoke.key
+    #[serde(default)]
+    pub own_key: bool,
     pub added_at: DateTime<Utc>,
 }
 
 /// Hubs configuration stored in hubs.json
+///
+/// These are hubs the spoke talks to directly, in addition to the hub in
+/// config.json. Remote hubs reached through hub-to-hub forwarding don't need
+/// an entry.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct HubsConfig {
     pub hubs: Vec<KnownHub>,
 }
 
+impl HubsConfig {
+    /// Find a hub by ID52 or alias
+    pub fn find(&self, id52_or_alias: &str) -> Option<&KnownHub> {
+        self.hubs.iter().find(|h| h.id52 == id52_or_alias || h.alias.as_deref() == Some(id52_or_alias))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn find_mut(&mut self, id52_or_alias: &str) -> Option<&mut KnownHub> {
+        self.hubs.iter_mut().find(|h| h.id52 == id52_or_alias || h.alias.as_deref() == Some(id52_or_alias))
+    }
+
+    /// Check that a new hub doesn't clash with a known one or with the
+    /// configured hub (`primary_id52`)
+    #[cfg(not(target_arch = "wasm32"))]
+    fn check_new(&self, primary_id52: &str, id52: &str, alias: Option<&str>) -> Result<()> {
+        if id52 == primary_id52 || self.find(id52).is_some() {
+            return Err(Error::HubExists(id52.to_string()));
+        }
+        if let Some(alias) = alias {
+            // "self" means the configured hub in requests
+            if alias.is_empty() || alias == "self" || fastn_net::from_id52(alias).is_ok() {
+                return Err(Error::InvalidHubAlias(alias.to_string()));
+            }
+            if self.find(alias).is_some() {
+                return Err(Error::HubExists(alias.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
 // ============================================================================
 // Native implementation (desktop)
 // ============================================================================
 #[cfg(not(target_arch = "wasm32"))]
 mod native {
     use super::*;
+    use fastn_net::key_file::{self, Protection};
 
     /// The Spoke client (native)
     pub struct Spoke {
@@ -105,11 +262,16 @@ mod native {
         home: PathBuf,
         /// Spoke's secret key
         secret_key: SecretKey,
+        /// How spoke.key stores it, and so the keys in hub-keys/
+        key_protection: Protection,
         /// Configuration
         config: SpokeConfig,
-        /// Known hubs (for future multi-hub support)
-        #[allow(dead_code)]
+        /// Known hubs
         hubs: HubsConfig,
+        /// Keys for known hubs with `own_key`, by hub ID52
+        hub_keys: std::collections::HashMap<String, SecretKey>,
+        /// Ask hubs to explain ACL denials (see `set_explain`)
+        explain: bool,
     }
 
     impl Spoke {
@@ -124,6 +286,11 @@ mod native {
                 })
         }
 
+        /// Open a spoke from a program; see `SpokeBuilder`
+        pub fn builder() -> crate::SpokeBuilder {
+            crate::SpokeBuilder::new()
+        }
+
         /// Check if spoke is initialized at a specific path
         pub fn is_initialized(home: &std::path::Path) -> bool {
             home.join("spoke.key").exists()
@@ -154,8 +321,55 @@ mod native {
             &self.secret_key
         }
 
+        /// Ask hubs to explain why they deny requests made from now on
+        ///
+        /// Sets `explain` on every request, so a hub that would deny a
+        /// non-owner answers its owner with the ACL trace in `AccessDenied`;
+        /// other hubs ignore it.
+        pub fn set_explain(&mut self, explain: bool) {
+            self.explain = explain;
+        }
+
         /// Initialize a new spoke at the specified path
         pub async fn init(home: PathBuf, hub_id52: &str, hub_url: &str, alias: &str) -> Result<Self> {
+            Self::init_with_key(home, SecretKey::generate(), hub_id52, hub_url, alias).await
+        }
+
+        /// Join a hub with an invite token from `fastn-hub invite`
+        ///
+        /// Sets up a spoke for the invite's hub at `home`, its alias
+        /// defaulting to the first 8 characters of its new ID52, or uses the
+        /// spoke already there if it belongs to that hub, then asks the hub
+        /// to authorize it. The hub's answer is signed, so a `hub_url` that
+        /// leads elsewhere fails instead of joining another hub.
+        pub async fn join(home: PathBuf, hub_url: &str, token: &str, alias: Option<&str>) -> Result<Self> {
+            let invite = fastn_net::InviteToken::verify(token)?;
+            let spoke = if Self::is_initialized(&home) {
+                let spoke = Self::load(&home).await?;
+                if spoke.hub_id52() != invite.hub_id52 {
+                    return Err(Error::InviteForOtherHub {
+                        invite: invite.hub_id52,
+                        configured: spoke.hub_id52().to_string(),
+                    });
+                }
+                spoke
+            } else {
+                let secret_key = SecretKey::generate();
+                let alias = alias.map(str::to_string).unwrap_or_else(|| secret_key.id52()[..8].to_string());
+                Self::init_with_key(home, secret_key, &invite.hub_id52, hub_url, &alias).await?
+            };
+            let payload = serde_json::json!({ "token": token, "alias": alias.unwrap_or(spoke.alias()) });
+            spoke.connect().send_request("self", "hub", "self", "join", payload).await?;
+            Ok(spoke)
+        }
+
+        async fn init_with_key(
+            home: PathBuf,
+            secret_key: SecretKey,
+            hub_id52: &str,
+            hub_url: &str,
+            alias: &str,
+        ) -> Result<Self> {
             if Self::is_initialized(&home) {
                 return Err(Error::AlreadyInitialized(home));
             }
@@ -165,13 +379,10 @@ mod native {
 
             tokio::fs::create_dir_all(&home).await?;
 
-            let secret_key = SecretKey::generate();
             let public_key = secret_key.public();
             let spoke_id52 = public_key.id52();
 
-            let key_path = home.join("spoke.key");
-            let key_bytes = secret_key.to_bytes();
-            tokio::fs::write(&key_path, key_bytes).await?;
+            key_file::write(&home.join("spoke.key"), &secret_key, &Protection::Plain)?;
 
             let config = SpokeConfig {
                 spoke_id52,
@@ -192,11 +403,25 @@ mod native {
             Ok(Self {
                 home,
                 secret_key,
+                key_protection: Protection::Plain,
                 config,
                 hubs,
+                hub_keys: Default::default(),
+                explain: false,
             })
         }
 
+        /// Read a secret key file, with FASTN_KEY_PASSPHRASE if it is
+        /// encrypted
+        fn read_key(path: &std::path::Path) -> Result<(SecretKey, Protection)> {
+            Ok(key_file::read(path, key_file::env_passphrase().as_deref())?)
+        }
+
+        /// Path of the key used for a known hub with `own_key`
+        fn hub_key_path(&self, hub_id52: &str) -> PathBuf {
+            self.home.join("hub-keys").join(format!("{}.key", hub_id52))
+        }
+
         /// Load an existing spoke from the specified path
         pub async fn load(home: &std::path::Path) -> Result<Self> {
             if !Self::is_initialized(home) {
@@ -205,34 +430,34 @@ mod native {
 
             let home = home.to_path_buf();
 
-            let key_path = home.join("spoke.key");
-            let key_bytes = tokio::fs::read(&key_path).await?;
-            let key_array: [u8; 32] = key_bytes
-                .try_into()
-                .map_err(|_| Error::Io(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Invalid key file: expected 32 bytes",
-                )))?;
-            let secret_key = SecretKey::from_bytes(&key_array);
+            let (secret_key, key_protection) = Self::read_key(&home.join("spoke.key"))?;
 
             let config_path = home.join("config.json");
             let config_json = tokio::fs::read_to_string(&config_path).await?;
             let config: SpokeConfig = serde_json::from_str(&config_json)?;
 
             let hubs_path = home.join("hubs.json");
-            let hubs = if hubs_path.exists() {
+            let hubs: HubsConfig = if hubs_path.exists() {
                 let hubs_json = tokio::fs::read_to_string(&hubs_path).await?;
                 serde_json::from_str(&hubs_json)?
             } else {
                 HubsConfig::default()
             };
 
-            Ok(Self {
+            let mut spoke = Self {
                 home,
                 secret_key,
+                key_protection,
                 config,
                 hubs,
-            })
+                hub_keys: Default::default(),
+                explain: false,
+            };
+            for hub in spoke.hubs.hubs.iter().filter(|h| h.own_key) {
+                let (key, _) = Self::read_key(&spoke.hub_key_path(&hub.id52))?;
+                spoke.hub_keys.insert(hub.id52.clone(), key);
+            }
+            Ok(spoke)
         }
 
         /// Load or initialize spoke at the specified path
@@ -249,14 +474,129 @@ mod native {
             &self.config.hub_url
         }
 
+        /// Save hubs.json
+        async fn save_hubs(&self) -> Result<()> {
+            let hubs_json = serde_json::to_string_pretty(&self.hubs)?;
+            tokio::fs::write(self.home.join("hubs.json"), hubs_json).await?;
+            Ok(())
+        }
+
+        /// Save config.json
+        async fn save_config(&self) -> Result<()> {
+            let config_json = serde_json::to_string_pretty(&self.config)?;
+            tokio::fs::write(self.home.join("config.json"), config_json).await?;
+            Ok(())
+        }
+
         /// Add a hub to known hubs
-        pub async fn add_hub(&mut self, _id52: &str, _alias: Option<&str>) -> Result<()> {
-            todo!("Spoke::add_hub")
+        ///
+        /// With `own_key` the spoke gets a new key (and so a different ID52)
+        /// for this hub, so hubs can't tell they serve the same spoke. Give
+        /// `id52_for(hub)` to that hub's admin.
+        pub async fn add_hub(&mut self, id52: &str, alias: Option<&str>, url: &str, own_key: bool) -> Result<&KnownHub> {
+            fastn_net::from_id52(id52).map_err(|_| Error::InvalidId52(id52.to_string()))?;
+            self.hubs.check_new(&self.config.hub_id52, id52, alias)?;
+
+            if own_key {
+                let key = SecretKey::generate();
+                let key_path = self.hub_key_path(id52);
+                tokio::fs::create_dir_all(self.home.join("hub-keys")).await?;
+                key_file::write(&key_path, &key, &self.key_protection)?;
+                self.hub_keys.insert(id52.to_string(), key);
+            }
+
+            self.hubs.hubs.push(KnownHub {
+                id52: id52.to_string(),
+                alias: alias.map(|a| a.to_string()),
+                url: url.to_string(),
+                own_key,
+                added_at: Utc::now(),
+            });
+            self.save_hubs().await?;
+            Ok(self.hubs.hubs.last().expect("hub was just added"))
+        }
+
+        /// Remove a hub from known hubs (and its own key, if it has one)
+        pub async fn remove_hub(&mut self, id52_or_alias: &str) -> Result<()> {
+            let hub = self
+                .find_hub(id52_or_alias)
+                .ok_or_else(|| Error::HubNotFound(id52_or_alias.to_string()))?
+                .clone();
+            self.hubs.hubs.retain(|h| h.id52 != hub.id52);
+            self.save_hubs().await?;
+
+            if let Some(key) = self.hub_keys.remove(&hub.id52) {
+                tokio::fs::remove_file(self.hub_key_path(&hub.id52)).await?;
+                if self.key_protection == Protection::Keychain {
+                    key_file::forget(&key.id52())?;
+                }
+            }
+            Ok(())
         }
 
-        /// Remove a hub from known hubs
-        pub async fn remove_hub(&mut self, _id52_or_alias: &str) -> Result<()> {
-            todo!("Spoke::remove_hub")
+        /// How spoke.key at `home` stores the key, without unlocking it
+        pub fn key_protection(home: &std::path::Path) -> Result<Protection> {
+            if !Self::is_initialized(home) {
+                return Err(Error::NotInitialized);
+            }
+            Ok(key_file::protection(&home.join("spoke.key"))?)
+        }
+
+        /// Rewrite spoke.key, and the keys of hubs with `own_key`, with
+        /// `protection`
+        ///
+        /// Keys moved out of the keychain are removed from it.
+        pub fn protect_keys(&mut self, protection: Protection) -> Result<()> {
+            let keys = std::iter::once((self.home.join("spoke.key"), &self.secret_key))
+                .chain(self.hub_keys.iter().map(|(hub_id52, key)| (self.hub_key_path(hub_id52), key)));
+            for (path, key) in keys {
+                key_file::write(&path, key, &protection)?;
+                if self.key_protection == Protection::Keychain && protection != Protection::Keychain {
+                    key_file::forget(&key.id52())?;
+                }
+            }
+            self.key_protection = protection;
+            Ok(())
+        }
+
+        /// Follow a hub that rotated its key: wherever the spoke knows the
+        /// old ID52, as its hub or a known hub, use the new one
+        ///
+        /// The rotation has to be signed by the old key. Returns whether
+        /// the spoke knew the old ID52.
+        pub async fn follow_key_rotation(&mut self, rotation: &fastn_net::KeyRotation) -> Result<bool> {
+            rotation.verify()?;
+            let (old, new) = (&rotation.old_id52, &rotation.new_id52);
+            let mut followed = false;
+            if &self.config.hub_id52 == old {
+                self.config.hub_id52 = new.clone();
+                self.save_config().await?;
+                followed = true;
+            }
+            if let Some(hub) = self.hubs.hubs.iter_mut().find(|hub| &hub.id52 == old) {
+                hub.id52 = new.clone();
+                if let Some(key) = self.hub_keys.remove(old) {
+                    tokio::fs::rename(self.hub_key_path(old), self.hub_key_path(new)).await?;
+                    self.hub_keys.insert(new.clone(), key);
+                }
+                self.save_hubs().await?;
+                followed = true;
+            }
+            Ok(followed)
+        }
+
+        /// Change the URL of a known hub or of the configured hub
+        pub async fn set_hub_url(&mut self, id52_or_alias: &str, url: &str) -> Result<()> {
+            if id52_or_alias == self.config.hub_id52 || id52_or_alias == "self" {
+                self.config.hub_url = url.to_string();
+                return self.save_config().await;
+            }
+            let hub = self
+                .hubs
+                .find_mut(id52_or_alias)
+                .ok_or_else(|| Error::HubNotFound(id52_or_alias.to_string()))?;
+            hub.url = url.to_string();
+            self.save_hubs().await
         }
 
         /// List known hubs
@@ -266,8 +606,21 @@ mod native {
 
         /// Find a hub by ID52 or alias
         pub fn find_hub(&self, id52_or_alias: &str) -> Option<&KnownHub> {
-            self.hubs.hubs.iter().find(|h| {
-                h.id52 == id52_or_alias || h.alias.as_deref() == Some(id52_or_alias)
+            self.hubs.find(id52_or_alias)
+        }
+
+        /// The spoke's ID52 as seen by a known hub (differs from `id52()` for
+        /// hubs with `own_key`)
+        pub fn id52_for(&self, id52_or_alias: &str) -> Result<String> {
+            if id52_or_alias == self.config.hub_id52 || id52_or_alias == "self" {
+                return Ok(self.id52().to_string());
+            }
+            let hub = self
+                .find_hub(id52_or_alias)
+                .ok_or_else(|| Error::HubNotFound(id52_or_alias.to_string()))?;
+            Ok(match self.hub_keys.get(&hub.id52) {
+                Some(key) => key.public().id52(),
+                None => self.id52().to_string(),
             })
         }
 
@@ -281,7 +634,200 @@ mod native {
             HubConnection {
                 hub_id52: self.config.hub_id52.clone(),
                 client,
+                home: self.home.clone(),
+                downloads: self.home.join("downloads"),
+                explain: self.explain,
+            }
+        }
+
+        /// Connect to a known hub by ID52 or alias
+        ///
+        /// `"self"` or the configured hub's ID52 connect to the configured
+        /// hub. Requests on the connection use `target_hub: "self"` for the
+        /// hub itself.
+        pub fn connect_to(&self, id52_or_alias: &str) -> Result<HubConnection> {
+            if id52_or_alias == self.config.hub_id52 || id52_or_alias == "self" {
+                return Ok(self.connect());
             }
+            let hub = self
+                .find_hub(id52_or_alias)
+                .ok_or_else(|| Error::HubNotFound(id52_or_alias.to_string()))?;
+            let key = self.hub_keys.get(&hub.id52).unwrap_or(&self.secret_key);
+            let client = fastn_net::client::Client::new(key.clone(), hub.id52.clone(), hub.url.clone());
+            Ok(HubConnection {
+                hub_id52: hub.id52.clone(),
+                client,
+                home: self.home.clone(),
+                downloads: self.home.join("downloads"),
+                explain: self.explain,
+            })
+        }
+
+        /// Typed client for a kosha on `hub`
+        ///
+        /// `hub` is as for the CLI: `"self"`, a known hub's ID52 or alias
+        /// (connected to directly), or else a hub the configured hub
+        /// forwards requests to.
+        pub fn kosha(&self, hub: &str, kosha: &str) -> crate::KoshaClient {
+            match self.connect_to(hub) {
+                Ok(conn) => crate::KoshaClient::new(conn, hub, "self", kosha),
+                Err(_) => crate::KoshaClient::new(self.connect(), hub, hub, kosha),
+            }
+        }
+
+        /// Sync `local_dir` with `remote_path` in a kosha; see `Syncer::new`
+        pub fn syncer(
+            &self,
+            local_dir: impl Into<PathBuf>,
+            hub: &str,
+            kosha: &str,
+            remote_path: &str,
+        ) -> Result<crate::Syncer> {
+            crate::Syncer::new(self.kosha(hub, kosha), local_dir, remote_path, self.alias())
+        }
+
+        // Outbox
+
+        /// The writes waiting for their hubs (see `Outbox`)
+        pub async fn outbox(&self) -> Result<crate::Outbox> {
+            match tokio::fs::read(self.home.join(crate::OUTBOX_FILE)).await {
+                Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(crate::Outbox::default()),
+                Err(e) => Err(e.into()),
+            }
+        }
+
+        async fn save_outbox(&self, outbox: &crate::Outbox) -> Result<()> {
+            let path = self.home.join(crate::OUTBOX_FILE);
+            let temp = path.with_extension("json.tmp");
+            tokio::fs::write(&temp, serde_json::to_vec_pretty(outbox)?).await?;
+            tokio::fs::rename(&temp, &path).await?;
+            Ok(())
+        }
+
+        /// Connection and request for a kosha command, as `kosha` would
+        /// send it
+        fn kosha_request(
+            &self,
+            hub: &str,
+            kosha: &str,
+            command: &str,
+            payload: serde_json::Value,
+        ) -> (HubConnection, fastn_net::HubRequest) {
+            let (conn, target_hub) = match self.connect_to(hub) {
+                Ok(conn) => (conn, "self"),
+                Err(_) => (self.connect(), hub),
+            };
+            let request = fastn_net::HubRequest {
+                target_hub: target_hub.to_string(),
+                app: "kosha".to_string(),
+                instance: kosha.to_string(),
+                command: command.to_string(),
+                payload,
+                explain: self.explain,
+            };
+            (conn, request)
+        }
+
+        /// Queue a kosha write to send later; `hub` is as for `kosha`
+        pub async fn queue(
+            &self,
+            hub: &str,
+            kosha: &str,
+            command: &str,
+            payload: serde_json::Value,
+        ) -> Result<crate::OutboxEntry> {
+            let (conn, request) = self.kosha_request(hub, kosha, command, payload);
+            let mut outbox = self.outbox().await?;
+            let entry = outbox.push(&self.secret_key, conn.hub_id52(), hub, &request)?;
+            self.save_outbox(&outbox).await?;
+            Ok(entry)
+        }
+
+        /// Send a kosha command, or queue it if it is a write (see
+        /// `is_queueable`) and its hub can't be reached
+        ///
+        /// A write to paths or keys that queued writes are still waiting
+        /// for is queued behind them, to keep the order.
+        pub async fn send_or_queue(
+            &self,
+            hub: &str,
+            kosha: &str,
+            command: &str,
+            payload: serde_json::Value,
+        ) -> Result<crate::Delivery> {
+            let (conn, request) = self.kosha_request(hub, kosha, command, payload.clone());
+            let queueable = crate::is_queueable(command);
+            if queueable && self.outbox().await?.holds(hub, &request)? {
+                return Ok(crate::Delivery::Queued(Box::new(self.queue(hub, kosha, command, payload).await?)));
+            }
+            match conn.deliver(&request).await {
+                Err(e) if queueable && crate::is_unreachable(&e) => {
+                    Ok(crate::Delivery::Queued(Box::new(self.queue(hub, kosha, command, payload).await?)))
+                }
+                result => result.map(crate::Delivery::Sent),
+            }
+        }
+
+        /// Deliver the outbox's entries in order
+        ///
+        /// Hubs that still can't be reached are retried after
+        /// `retry_delay`; `force` retries them now. `resolve` decides what
+        /// happens to a write that conflicts.
+        pub async fn flush_outbox(
+            &self,
+            force: bool,
+            mut resolve: impl FnMut(&crate::OutboxEntry) -> crate::Resolution,
+        ) -> Result<crate::OutboxFlush> {
+            let mut outbox = self.outbox().await?;
+            let mut flush = crate::OutboxFlush::default();
+            while let Some(entry) = outbox.next_due(force, &flush) {
+                let conn = self.connect_to(&entry.hub).unwrap_or_else(|_| self.connect());
+                let result = match entry.request(self.id52()) {
+                    Ok(request) => conn.deliver(&request).await,
+                    Err(e) => Err(e),
+                };
+                outbox.record(entry.id, result, &mut flush, &mut resolve, &self.secret_key, self.id52())?;
+                self.save_outbox(&outbox).await?;
+            }
+            flush.remaining = outbox.entries().len();
+            Ok(flush)
+        }
+
+        /// Flush the outbox until no entry is left waiting for its hub,
+        /// sleeping out the backoff in between
+        ///
+        /// Returns once every entry is delivered, discarded or held;
+        /// `delivered` and `discarded` cover all the passes.
+        pub async fn drain_outbox(
+            &self,
+            mut resolve: impl FnMut(&crate::OutboxEntry) -> crate::Resolution,
+        ) -> Result<crate::OutboxFlush> {
+            let (mut delivered, mut discarded) = (vec![], vec![]);
+            loop {
+                let mut flush = self.flush_outbox(false, &mut resolve).await?;
+                delivered.append(&mut flush.delivered);
+                discarded.append(&mut flush.discarded);
+                let Some(next) = self.outbox().await?.next_attempt() else {
+                    return Ok(crate::OutboxFlush {
+                        delivered,
+                        discarded,
+                        ..flush
+                    });
+                };
+                let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+            }
+        }
+
+        /// Settle an entry that conflicted or was refused; false if there
+        /// is no entry `id`, or it can't be overwritten (it conflicted
+        /// without a `base_version`)
+        pub async fn resolve_outbox(&self, id: u64, resolution: crate::Resolution) -> Result<bool> {
+            let mut outbox = self.outbox().await?;
+            let resolved = outbox.resolve(id, resolution, &self.secret_key, self.id52())?;
+            self.save_outbox(&outbox).await?;
+            Ok(resolved)
         }
 
         /// Connect to the hub (with HTTP, connection is made on each request)
@@ -294,6 +840,12 @@ mod native {
     pub struct HubConnection {
         hub_id52: String,
         client: fastn_net::client::Client,
+        /// SPOKE_HOME, to follow the hub when it rotates its key
+        home: PathBuf,
+        /// Partial downloads, `downloads/` in SPOKE_HOME
+        downloads: PathBuf,
+        /// Ask the hub to explain ACL denials (see `Spoke::set_explain`)
+        explain: bool,
     }
 
     impl HubConnection {
@@ -315,14 +867,22 @@ mod native {
                 instance: instance.to_string(),
                 command: command.to_string(),
                 payload,
+                explain: self.explain,
             };
 
             let result: std::result::Result<fastn_net::HubResponse, fastn_net::HubError> =
-                self.client.call(&request).await?;
+                match self.client.call(&request).await {
+                    Ok(result) => result,
+                    Err(fastn_net::Error::HubKeyRotated(rotation)) => return Err(self.follow(&rotation).await),
+                    Err(e) => return Err(e.into()),
+                };
 
             match result {
                 Ok(response) => Ok(response.payload),
-                Err(hub_error) => Err(Error::Hub(format!("{:?}", hub_error))),
+                Err(fastn_net::HubError::Conflict { message, current }) => {
+                    Err(Error::Conflict { message, current })
+                }
+                Err(hub_error) => Err(Error::HubRefused(hub_error)),
             }
         }
 
@@ -330,6 +890,56 @@ mod native {
             Ok(())
         }
 
+        /// Record that the hub rotated its key, for the next connection
+        async fn follow(&self, rotation: &fastn_net::KeyRotation) -> Error {
+            let followed = async {
+                let mut spoke = Spoke::load(&self.home).await?;
+                spoke.follow_key_rotation(rotation).await
+            };
+            match followed.await {
+                Ok(_) => Error::HubKeyRotated {
+                    old: rotation.old_id52.clone(),
+                    new: rotation.new_id52.clone(),
+                },
+                Err(e) => e,
+            }
+        }
+
+        /// Send a request as is, but a `write_file` too large for one
+        /// request goes up in chunks
+        async fn deliver(&self, request: &fastn_net::HubRequest) -> Result<serde_json::Value> {
+            let field = |name: &str| request.payload.get(name).and_then(|v| v.as_str());
+            if let (Some(content), Some(path)) = (offline::chunked_content(request)?, field("path")) {
+                return self
+                    .upload_file(&request.target_hub, &request.instance, path, &content, field("base_version"))
+                    .await;
+            }
+            self.send_request(
+                &request.target_hub,
+                &request.app,
+                &request.instance,
+                &request.command,
+                request.payload.clone(),
+            )
+            .await
+        }
+
+        /// Subscribe to events the hub pushes over its WebSocket endpoint
+        ///
+        /// Only the connected hub's own events: subscriptions aren't
+        /// forwarded to other hubs.
+        pub async fn subscribe(
+            &self,
+            topics: Vec<fastn_net::Topic>,
+        ) -> Result<fastn_net::client::Subscription<fastn_net::PushEvent>> {
+            let subscribe = fastn_net::Subscribe { topics };
+            let result: std::result::Result<_, fastn_net::HubError> = self.client.subscribe(&subscribe).await?;
+            match result {
+                Ok(subscription) => Ok(subscription),
+                Err(hub_error) => Err(Error::HubRefused(hub_error)),
+            }
+        }
+
         pub async fn read_file(
             &self,
             target_hub: &str,
@@ -346,6 +956,9 @@ mod native {
             .await
         }
 
+        /// Write a file; with `base_version` (the `modified` timestamp from a
+        /// previous read/write) the write fails with `Error::Conflict` if the
+        /// file changed in the meantime
         pub async fn write_file(
             &self,
             target_hub: &str,
@@ -365,6 +978,132 @@ mod native {
                 .await
         }
 
+        /// Upload a file of any size in `fastn_net::MAX_CHUNK_SIZE` chunks
+        ///
+        /// An earlier interrupted upload of the same content to the same path
+        /// is resumed. The hub checks the SHA-256 before committing; the
+        /// response is `{ modified }` like `write_file`.
+        pub async fn upload_file(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            path: &str,
+            content: &[u8],
+            base_version: Option<&str>,
+        ) -> Result<serde_json::Value> {
+            let begin = fastn_net::BeginUpload {
+                path: path.to_string(),
+                size: content.len() as u64,
+                sha256: sha256_hex(content),
+                base_version: base_version.map(|bv| bv.to_string()),
+            };
+            let response = self
+                .send_request(target_hub, "kosha", kosha, "begin_upload", serde_json::to_value(&begin)?)
+                .await?;
+            let mut status: fastn_net::UploadStatus = serde_json::from_value(response)?;
+
+            while status.received < status.size {
+                let start = status.received as usize;
+                let end = (start + fastn_net::MAX_CHUNK_SIZE).min(content.len());
+                let chunk = fastn_net::UploadChunk {
+                    upload_id: status.upload_id.clone(),
+                    path: path.to_string(),
+                    offset: status.received,
+                    content: base64::Engine::encode(&base64::prelude::BASE64_STANDARD, &content[start..end]),
+                };
+                let response = self
+                    .send_request(target_hub, "kosha", kosha, "upload_chunk", serde_json::to_value(&chunk)?)
+                    .await?;
+                status = serde_json::from_value(response)?;
+            }
+
+            let commit = fastn_net::FinishUpload {
+                upload_id: status.upload_id,
+                path: path.to_string(),
+            };
+            self.send_request(target_hub, "kosha", kosha, "commit_upload", serde_json::to_value(&commit)?)
+                .await
+        }
+
+        /// SHA-256, size and version of a file's current content
+        pub async fn file_hash(&self, target_hub: &str, kosha: &str, path: &str) -> Result<fastn_net::FileHash> {
+            let response = self
+                .send_request(target_hub, "kosha", kosha, "file_hash", serde_json::json!({ "path": path }))
+                .await?;
+            Ok(serde_json::from_value(response)?)
+        }
+
+        /// Download a file of any size with `read_range`, verifying its SHA-256
+        pub async fn download_file(&self, target_hub: &str, kosha: &str, path: &str) -> Result<Vec<u8>> {
+            Ok(self.download_file_versioned(target_hub, kosha, path).await?.0)
+        }
+
+        /// Like `download_file`, also returning the version downloaded
+        pub async fn download_file_versioned(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            path: &str,
+        ) -> Result<(Vec<u8>, fastn_net::FileHash)> {
+            let (partial, hash) = self.fetch(target_hub, kosha, path).await?;
+            Ok((partial.finish_to_memory().await?, hash))
+        }
+
+        /// Download a file of any size straight to `dest`, without holding
+        /// it in memory
+        ///
+        /// Chunks are verified as they arrive and kept in SPOKE_HOME, so an
+        /// interrupted download of the same content resumes from the last
+        /// good chunk instead of from zero.
+        pub async fn download_file_to(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            path: &str,
+            dest: &std::path::Path,
+        ) -> Result<fastn_net::FileHash> {
+            let (partial, hash) = self.fetch(target_hub, kosha, path).await?;
+            partial.finish_to(dest).await?;
+            Ok(hash)
+        }
+
+        /// Read the chunks of `path` the partial download doesn't have yet
+        async fn fetch(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            path: &str,
+        ) -> Result<(download::PartialDownload, fastn_net::FileHash)> {
+            let hash = self.file_hash(target_hub, kosha, path).await?;
+            let mut partial = download::PartialDownload::open(&self.downloads, &hash).await?;
+
+            while partial.verified() < hash.size {
+                let offset = partial.verified();
+                let read = fastn_net::ReadRange {
+                    path: path.to_string(),
+                    offset,
+                    length: (hash.chunk_size > 0).then_some(hash.chunk_size),
+                    sha256: Some(hash.sha256.clone()),
+                };
+                let response = match self
+                    .send_request(target_hub, "kosha", kosha, "read_range", serde_json::to_value(&read)?)
+                    .await
+                {
+                    Ok(response) => response,
+                    // The content changed on the hub; what we have is useless
+                    Err(e @ Error::Conflict { .. }) => {
+                        partial.discard().await;
+                        return Err(e);
+                    }
+                    Err(e) => return Err(e),
+                };
+                let range: fastn_net::FileRange = serde_json::from_value(response)?;
+                let bytes = verified_chunk(path, &hash, &range)?;
+                partial.append(&bytes).await?;
+            }
+            Ok((partial, hash))
+        }
+
         pub async fn list_dir(
             &self,
             target_hub: &str,
@@ -381,6 +1120,26 @@ mod native {
             .await
         }
 
+        /// Files under `path_prefix` matching `query`, best first; the hub
+        /// leaves out files the caller can't read
+        pub async fn search(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            query: &str,
+            path_prefix: &str,
+            limit: Option<usize>,
+        ) -> Result<serde_json::Value> {
+            self.send_request(
+                target_hub,
+                "kosha",
+                kosha,
+                "search",
+                serde_json::json!({ "query": query, "path_prefix": path_prefix, "limit": limit }),
+            )
+            .await
+        }
+
         pub async fn get_versions(
             &self,
             target_hub: &str,
@@ -447,6 +1206,56 @@ mod native {
             .await
         }
 
+        pub async fn delete_dir(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            path: &str,
+        ) -> Result<serde_json::Value> {
+            self.send_request(
+                target_hub,
+                "kosha",
+                kosha,
+                "delete_dir",
+                serde_json::json!({ "path": path }),
+            )
+            .await
+        }
+
+        pub async fn copy(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            from: &str,
+            to: &str,
+        ) -> Result<serde_json::Value> {
+            self.send_request(
+                target_hub,
+                "kosha",
+                kosha,
+                "copy",
+                serde_json::json!({ "from": from, "to": to }),
+            )
+            .await
+        }
+
+        pub async fn move_path(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            from: &str,
+            to: &str,
+        ) -> Result<serde_json::Value> {
+            self.send_request(
+                target_hub,
+                "kosha",
+                kosha,
+                "move",
+                serde_json::json!({ "from": from, "to": to }),
+            )
+            .await
+        }
+
         pub async fn kv_get(
             &self,
             target_hub: &str,
@@ -515,6 +1324,7 @@ mod wasm {
     };
 
     /// The Spoke client (WASM/web)
+    #[derive(Clone)]
     pub struct Spoke {
         /// Spoke's secret key
         secret_key: SecretKey,
@@ -759,9 +1569,7 @@ mod wasm {
 
         /// Find a hub by ID52 or alias
         pub fn find_hub(&self, id52_or_alias: &str) -> Option<&KnownHub> {
-            self.hubs.hubs.iter().find(|h| {
-                h.id52 == id52_or_alias || h.alias.as_deref() == Some(id52_or_alias)
-            })
+            self.hubs.find(id52_or_alias)
         }
 
         /// Connect to the configured hub
@@ -776,6 +1584,111 @@ mod wasm {
                 client,
             }
         }
+
+        // Outbox
+
+        /// The writes waiting for their hubs (see `Outbox`)
+        pub async fn outbox(&self) -> Result<crate::Outbox> {
+            match Self::get_file(&self.opfs_root, crate::OUTBOX_FILE, false).await {
+                Ok(file) => Ok(serde_json::from_slice(&Self::read_file_bytes(&file).await?)?),
+                Err(_) => Ok(crate::Outbox::default()),
+            }
+        }
+
+        async fn save_outbox(&self, outbox: &crate::Outbox) -> Result<()> {
+            let file = Self::get_file(&self.opfs_root, crate::OUTBOX_FILE, true).await?;
+            Self::write_file_bytes(&file, &serde_json::to_vec_pretty(outbox)?).await
+        }
+
+        /// Request for a kosha command; the web spoke only connects to the
+        /// configured hub, which forwards requests for other hubs
+        fn kosha_request(
+            &self,
+            hub: &str,
+            kosha: &str,
+            command: &str,
+            payload: serde_json::Value,
+        ) -> fastn_net::HubRequest {
+            let target_hub = match hub == self.config.hub_id52 {
+                true => "self",
+                false => hub,
+            };
+            fastn_net::HubRequest {
+                target_hub: target_hub.to_string(),
+                app: "kosha".to_string(),
+                instance: kosha.to_string(),
+                command: command.to_string(),
+                payload,
+                explain: false,
+            }
+        }
+
+        /// Queue a kosha write to send later
+        pub async fn queue(
+            &self,
+            hub: &str,
+            kosha: &str,
+            command: &str,
+            payload: serde_json::Value,
+        ) -> Result<crate::OutboxEntry> {
+            let request = self.kosha_request(hub, kosha, command, payload);
+            let mut outbox = self.outbox().await?;
+            let entry = outbox.push(&self.secret_key, &self.config.hub_id52, hub, &request)?;
+            self.save_outbox(&outbox).await?;
+            Ok(entry)
+        }
+
+        /// Send a kosha command, or queue it if it is a write and the hub
+        /// can't be reached (or writes to the same paths are queued)
+        pub async fn send_or_queue(
+            &self,
+            hub: &str,
+            kosha: &str,
+            command: &str,
+            payload: serde_json::Value,
+        ) -> Result<crate::Delivery> {
+            let request = self.kosha_request(hub, kosha, command, payload.clone());
+            let queueable = crate::is_queueable(command);
+            if queueable && self.outbox().await?.holds(hub, &request)? {
+                return Ok(crate::Delivery::Queued(Box::new(self.queue(hub, kosha, command, payload).await?)));
+            }
+            match self.connect().deliver(&request).await {
+                Err(e) if queueable && crate::is_unreachable(&e) => {
+                    Ok(crate::Delivery::Queued(Box::new(self.queue(hub, kosha, command, payload).await?)))
+                }
+                result => result.map(crate::Delivery::Sent),
+            }
+        }
+
+        /// Deliver the outbox's entries in order; see the native
+        /// `Spoke::flush_outbox`
+        pub async fn flush_outbox(
+            &self,
+            force: bool,
+            mut resolve: impl FnMut(&crate::OutboxEntry) -> crate::Resolution,
+        ) -> Result<crate::OutboxFlush> {
+            let mut outbox = self.outbox().await?;
+            let mut flush = crate::OutboxFlush::default();
+            let conn = self.connect();
+            while let Some(entry) = outbox.next_due(force, &flush) {
+                let result = match entry.request(self.id52()) {
+                    Ok(request) => conn.deliver(&request).await,
+                    Err(e) => Err(e),
+                };
+                outbox.record(entry.id, result, &mut flush, &mut resolve, &self.secret_key, self.id52())?;
+                self.save_outbox(&outbox).await?;
+            }
+            flush.remaining = outbox.entries().len();
+            Ok(flush)
+        }
+
+        /// Settle an entry that conflicted or was refused
+        pub async fn resolve_outbox(&self, id: u64, resolution: crate::Resolution) -> Result<bool> {
+            let mut outbox = self.outbox().await?;
+            let resolved = outbox.resolve(id, resolution, &self.secret_key, self.id52())?;
+            self.save_outbox(&outbox).await?;
+            Ok(resolved)
+        }
     }
 
     /// An active connection to a hub (WASM)
@@ -803,6 +1716,7 @@ mod wasm {
                 instance: instance.to_string(),
                 command: command.to_string(),
                 payload,
+                explain: false,
             };
 
             let result: std::result::Result<fastn_net::HubResponse, fastn_net::HubError> =
@@ -810,7 +1724,10 @@ mod wasm {
 
             match result {
                 Ok(response) => Ok(response.payload),
-                Err(hub_error) => Err(Error::Hub(format!("{:?}", hub_error))),
+                Err(fastn_net::HubError::Conflict { message, current }) => {
+                    Err(Error::Conflict { message, current })
+                }
+                Err(hub_error) => Err(Error::HubRefused(hub_error)),
             }
         }
 
@@ -818,6 +1735,25 @@ mod wasm {
             Ok(())
         }
 
+        /// Send a request as is, but a `write_file` too large for one
+        /// request goes up in chunks
+        async fn deliver(&self, request: &fastn_net::HubRequest) -> Result<serde_json::Value> {
+            let field = |name: &str| request.payload.get(name).and_then(|v| v.as_str());
+            if let (Some(content), Some(path)) = (offline::chunked_content(request)?, field("path")) {
+                return self
+                    .upload_file(&request.target_hub, &request.instance, path, &content, field("base_version"))
+                    .await;
+            }
+            self.send_request(
+                &request.target_hub,
+                &request.app,
+                &request.instance,
+                &request.command,
+                request.payload.clone(),
+            )
+            .await
+        }
+
         pub async fn read_file(
             &self,
             target_hub: &str,
@@ -834,6 +1770,9 @@ mod wasm {
             .await
         }
 
+        /// Write a file; with `base_version` (the `modified` timestamp from a
+        /// previous read/write) the write fails with `Error::Conflict` if the
+        /// file changed in the meantime
         pub async fn write_file(
             &self,
             target_hub: &str,
@@ -853,6 +1792,81 @@ mod wasm {
                 .await
         }
 
+        /// Upload a file of any size in `fastn_net::MAX_CHUNK_SIZE` chunks
+        ///
+        /// An earlier interrupted upload of the same content to the same path
+        /// is resumed. The hub checks the SHA-256 before committing; the
+        /// response is `{ modified }` like `write_file`.
+        pub async fn upload_file(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            path: &str,
+            content: &[u8],
+            base_version: Option<&str>,
+        ) -> Result<serde_json::Value> {
+            let begin = fastn_net::BeginUpload {
+                path: path.to_string(),
+                size: content.len() as u64,
+                sha256: sha256_hex(content),
+                base_version: base_version.map(|bv| bv.to_string()),
+            };
+            let response = self
+                .send_request(target_hub, "kosha", kosha, "begin_upload", serde_json::to_value(&begin)?)
+                .await?;
+            let mut status: fastn_net::UploadStatus = serde_json::from_value(response)?;
+
+            while status.received < status.size {
+                let start = status.received as usize;
+                let end = (start + fastn_net::MAX_CHUNK_SIZE).min(content.len());
+                let chunk = fastn_net::UploadChunk {
+                    upload_id: status.upload_id.clone(),
+                    path: path.to_string(),
+                    offset: status.received,
+                    content: base64::Engine::encode(&base64::prelude::BASE64_STANDARD, &content[start..end]),
+                };
+                let response = self
+                    .send_request(target_hub, "kosha", kosha, "upload_chunk", serde_json::to_value(&chunk)?)
+                    .await?;
+                status = serde_json::from_value(response)?;
+            }
+
+            let commit = fastn_net::FinishUpload {
+                upload_id: status.upload_id,
+                path: path.to_string(),
+            };
+            self.send_request(target_hub, "kosha", kosha, "commit_upload", serde_json::to_value(&commit)?)
+                .await
+        }
+
+        /// Download a file of any size with `read_range`, verifying its SHA-256
+        pub async fn download_file(&self, target_hub: &str, kosha: &str, path: &str) -> Result<Vec<u8>> {
+            let response = self
+                .send_request(target_hub, "kosha", kosha, "file_hash", serde_json::json!({ "path": path }))
+                .await?;
+            let hash: fastn_net::FileHash = serde_json::from_value(response)?;
+
+            let mut content = Vec::with_capacity(hash.size as usize);
+            while (content.len() as u64) < hash.size {
+                let read = fastn_net::ReadRange {
+                    path: path.to_string(),
+                    offset: content.len() as u64,
+                    length: (hash.chunk_size > 0).then_some(hash.chunk_size),
+                    sha256: Some(hash.sha256.clone()),
+                };
+                let response = self
+                    .send_request(target_hub, "kosha", kosha, "read_range", serde_json::to_value(&read)?)
+                    .await?;
+                let range: fastn_net::FileRange = serde_json::from_value(response)?;
+                content.extend(verified_chunk(path, &hash, &range)?);
+            }
+
+            if sha256_hex(&content) != hash.sha256 {
+                return Err(Error::Integrity(format!("{} does not match its sha256", path)));
+            }
+            Ok(content)
+        }
+
         pub async fn list_dir(
             &self,
             target_hub: &str,
@@ -869,6 +1883,26 @@ mod wasm {
             .await
         }
 
+        /// Files under `path_prefix` matching `query`, best first; the hub
+        /// leaves out files the caller can't read
+        pub async fn search(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            query: &str,
+            path_prefix: &str,
+            limit: Option<usize>,
+        ) -> Result<serde_json::Value> {
+            self.send_request(
+                target_hub,
+                "kosha",
+                kosha,
+                "search",
+                serde_json::json!({ "query": query, "path_prefix": path_prefix, "limit": limit }),
+            )
+            .await
+        }
+
         pub async fn get_versions(
             &self,
             target_hub: &str,
@@ -935,6 +1969,56 @@ mod wasm {
             .await
         }
 
+        pub async fn delete_dir(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            path: &str,
+        ) -> Result<serde_json::Value> {
+            self.send_request(
+                target_hub,
+                "kosha",
+                kosha,
+                "delete_dir",
+                serde_json::json!({ "path": path }),
+            )
+            .await
+        }
+
+        pub async fn copy(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            from: &str,
+            to: &str,
+        ) -> Result<serde_json::Value> {
+            self.send_request(
+                target_hub,
+                "kosha",
+                kosha,
+                "copy",
+                serde_json::json!({ "from": from, "to": to }),
+            )
+            .await
+        }
+
+        pub async fn move_path(
+            &self,
+            target_hub: &str,
+            kosha: &str,
+            from: &str,
+            to: &str,
+        ) -> Result<serde_json::Value> {
+            self.send_request(
+                target_hub,
+                "kosha",
+                kosha,
+                "move",
+                serde_json::json!({ "from": from, "to": to }),
+            )
+            .await
+        }
+
         pub async fn kv_get(
             &self,
             target_hub: &str,
@@ -1127,6 +2211,7 @@ mod wasm {
             instance: instance.to_string(),
             command: command.to_string(),
             payload,
+            explain: false,
         };
 
         let result: std::result::Result<fastn_net::HubResponse, fastn_net::HubError> =
@@ -1140,6 +2225,73 @@ mod wasm {
         }
     }
 
+    /// The loaded spoke, for calls that outlive a borrow
+    fn loaded_spoke() -> std::result::Result<Spoke, JsValue> {
+        SPOKE_INSTANCE.with(|s| s.borrow().clone().ok_or_else(|| JsValue::from_str("Spoke not loaded")))
+    }
+
+    /// Send a kosha command, or queue it in the outbox if it is a write
+    /// and the hub can't be reached
+    /// Returns `{"sent": payload}` or `{"queued": entry}` as a JSON string
+    #[wasm_bindgen]
+    pub async fn spoke_send_or_queue(
+        hub: &str,
+        kosha: &str,
+        command: &str,
+        payload_json: &str,
+    ) -> std::result::Result<String, JsValue> {
+        let payload: serde_json::Value = serde_json::from_str(payload_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid JSON payload: {}", e)))?;
+        let delivery = loaded_spoke()?
+            .send_or_queue(hub, kosha, command, payload)
+            .await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let response = match delivery {
+            crate::Delivery::Sent(payload) => serde_json::json!({ "sent": payload }),
+            crate::Delivery::Queued(entry) => serde_json::json!({ "queued": entry }),
+        };
+        Ok(response.to_string())
+    }
+
+    /// The outbox's entries as a JSON array
+    #[wasm_bindgen]
+    pub async fn spoke_outbox() -> std::result::Result<String, JsValue> {
+        let outbox = loaded_spoke()?
+            .outbox()
+            .await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        serde_json::to_string(outbox.entries())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize outbox: {}", e)))
+    }
+
+    /// Deliver queued writes, keeping conflicting ones for
+    /// `spoke_resolve_outbox`
+    /// Returns the `OutboxFlush` as a JSON string
+    #[wasm_bindgen]
+    pub async fn spoke_flush_outbox(force: bool) -> std::result::Result<String, JsValue> {
+        let flush = loaded_spoke()?
+            .flush_outbox(force, |_| crate::Resolution::Keep)
+            .await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        serde_json::to_string(&flush)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize flush: {}", e)))
+    }
+
+    /// Settle a queued write that conflicted or was refused: "overwrite"
+    /// or "discard"
+    #[wasm_bindgen]
+    pub async fn spoke_resolve_outbox(id: u64, resolution: &str) -> std::result::Result<bool, JsValue> {
+        let resolution = match resolution {
+            "overwrite" => crate::Resolution::Overwrite,
+            "discard" => crate::Resolution::Discard,
+            other => return Err(JsValue::from_str(&format!("Unknown resolution: {}", other))),
+        };
+        loaded_spoke()?
+            .resolve_outbox(id, resolution)
+            .await
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     /// Get spoke info as JSON
     #[wasm_bindgen]
     pub fn spoke_info() -> std::result::Result<String, JsValue> {
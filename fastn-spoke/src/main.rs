@@ -2,15 +2,24 @@
 //!
 //! Usage:
 //!   fastn-spoke init <hub-id52>  - Initialize spoke with a hub to connect to
+//!   fastn-spoke join <hub-url> <token> [alias] - Join a hub with an invite from 'fastn-hub invite'
 //!   fastn-spoke                  - Run the spoke (launches GUI if enabled, otherwise shows info)
 //!   fastn-spoke id               - Show the spoke's ID52
 //!   fastn-spoke kosha <op>       - Kosha operations (read-file, write-file, list-dir, etc.)
+//!   fastn-spoke hub <op>         - Manage known hubs (add, remove, list, set-url)
+//!   fastn-spoke sync <dir> ...   - Two-way sync of a local directory with a kosha
+//!   fastn-spoke outbox <op>      - Writes queued while the hub was unreachable (list, flush, resolve)
+//!   fastn-spoke key [protect|unprotect] - Show or change how the spoke's keys are stored
 
 use fastn_spoke::Spoke;
 use std::env;
 use std::path::PathBuf;
 
+mod hub;
 mod kosha;
+mod outbox;
+mod output;
+mod sync;
 
 #[cfg(feature = "gui")]
 mod gui;
@@ -92,6 +101,36 @@ async fn main() {
                 }
             }
         }
+        Some("join") => {
+            let (hub_url, token) = match (args.get(2), args.get(3)) {
+                (Some(hub_url), Some(token)) => (hub_url, token),
+                _ => {
+                    eprintln!("Usage: fastn-spoke join <hub-url> <token> [alias]");
+                    eprintln!();
+                    eprintln!("Joins the hub with an invite token from its admin ('fastn-hub invite'),");
+                    eprintln!("setting the spoke up first if needed. The alias defaults to the first");
+                    eprintln!("8 characters of the spoke's ID52.");
+                    std::process::exit(1);
+                }
+            };
+
+            match Spoke::join(home, hub_url, token, args.get(4).map(|s| s.as_str())).await {
+                Ok(spoke) => {
+                    println!("Joined hub successfully!");
+                    println!();
+                    println!("Spoke ID52: {}", spoke.id52());
+                    println!("Alias:      {}", spoke.alias());
+                    println!("Hub ID52:   {}", spoke.hub_id52());
+                    println!("Hub URL:    {}", spoke.hub_url());
+                    println!();
+                    println!("Try: fastn-spoke kosha read-file self root spokes.txt");
+                }
+                Err(e) => {
+                    eprintln!("Failed to join hub: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
         Some("id") => {
             match Spoke::load(&home).await {
                 Ok(spoke) => {
@@ -121,6 +160,21 @@ async fn main() {
         Some("kosha") => {
             kosha::run(&args[2..], &home).await;
         }
+        Some("hub") => {
+            hub::run(&args[2..], &home).await;
+        }
+        Some("sync") => {
+            sync::run(&args[2..], &home).await;
+        }
+        Some("outbox") => {
+            outbox::run(&args[2..], &home).await;
+        }
+        Some("key") => {
+            if let Err(e) = key_command(&home, &args[2..]).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
         Some("help") | Some("-h") | Some("--help") => {
             print_help();
         }
@@ -170,10 +224,16 @@ fn print_help() {
     println!();
     println!("Usage:");
     println!("  fastn-spoke init <hub-id52> <hub-url> <alias>  Initialize spoke with a hub");
+    println!("  fastn-spoke join <hub-url> <token> [alias]     Join a hub with an invite from 'fastn-hub invite'");
     println!("  fastn-spoke                                    Show spoke info");
     println!("  fastn-spoke id                                 Show the spoke's ID52");
     println!("  fastn-spoke info                               Show spoke configuration");
     println!("  fastn-spoke kosha <operation> ...              Kosha operations (see below)");
+    println!("  fastn-spoke hub <operation> ...                Manage known hubs (see below)");
+    println!("  fastn-spoke sync <local-dir> <hub> <kosha> <remote-path> [--watch]");
+    println!("                                                 Two-way sync of a directory with a kosha");
+    println!("  fastn-spoke outbox <operation> ...             Writes queued while the hub was unreachable");
+    println!("  fastn-spoke key [protect|unprotect]            Protect the spoke's keys (see 'fastn-spoke key --help')");
     println!("  fastn-spoke help                               Show this help message");
     println!();
     println!("Kosha Operations:");
@@ -184,12 +244,27 @@ fn print_help() {
     println!("  fastn-spoke kosha read-version <hub> <kosha> <path> <timestamp>");
     println!("  fastn-spoke kosha rename <hub> <kosha> <from> <to>");
     println!("  fastn-spoke kosha delete <hub> <kosha> <path>");
+    println!("  fastn-spoke kosha delete-dir <hub> <kosha> <path>");
+    println!("  fastn-spoke kosha copy <hub> <kosha> <from> <to>");
+    println!("  fastn-spoke kosha move <hub> <kosha> <from> <to>");
     println!("  fastn-spoke kosha kv-get <hub> <kosha> <key>");
     println!("  fastn-spoke kosha kv-set <hub> <kosha> <key> <value>");
     println!("  fastn-spoke kosha kv-delete <hub> <kosha> <key>");
     println!();
+    println!("Hub Operations:");
+    println!("  fastn-spoke hub add <hub-id52> <hub-url> [alias] [--own-key]");
+    println!("  fastn-spoke hub remove <hub>");
+    println!("  fastn-spoke hub list");
+    println!("  fastn-spoke hub set-url <hub> <hub-url>");
+    println!();
+    println!("Outbox Operations:");
+    println!("  fastn-spoke outbox list");
+    println!("  fastn-spoke outbox flush [--force] [--wait] [--on-conflict <keep|overwrite|discard>]");
+    println!("  fastn-spoke outbox resolve <id> <overwrite|discard>");
+    println!();
     println!("Hub Aliases:");
     println!("  self      Access your own hub directly (no ACL checks)");
+    println!("  <known>   Access a hub from 'fastn-spoke hub list' directly");
     println!("  <alias>   Access a remote hub via hub-to-hub forwarding (ACL applies)");
     println!();
     println!("Arguments:");
@@ -200,6 +275,7 @@ fn print_help() {
     println!("Environment:");
     println!("  SPOKE_HOME  Override the default home directory");
     println!("              Default: ~/.fastn-spoke (or platform-specific)");
+    println!("  FASTN_KEY_PASSPHRASE  Passphrase of encrypted key files");
     println!();
     println!("Workflow:");
     println!("  1. Get the hub's ID52 and HTTP URL from the hub admin");
@@ -208,3 +284,48 @@ fn print_help() {
     println!("  4. Hub admin runs: fastn-hub add-spoke <your-spoke-id52>");
     println!("  5. Run: fastn-spoke kosha read-file self root spokes.txt");
 }
+
+fn print_key_usage() {
+    println!("Usage: fastn-spoke key");
+    println!("       fastn-spoke key protect --passphrase");
+    println!("       fastn-spoke key protect --keychain");
+    println!("       fastn-spoke key unprotect");
+    println!();
+    println!("spoke.key, and the keys in hub-keys/, are plain bytes unless protected:");
+    println!("  --passphrase  Encrypt them with the passphrase in FASTN_KEY_PASSPHRASE,");
+    println!("                which the spoke then needs set");
+    println!("  --keychain    Move them to the OS keychain (builds with the \"keychain\"");
+    println!("                feature)");
+}
+
+/// Run `fastn-spoke key [command]`
+async fn key_command(home: &std::path::Path, args: &[String]) -> Result<(), String> {
+    use fastn_net::key_file::{self, Protection};
+    let protection = match args.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        [] => {
+            let protection = Spoke::key_protection(home).map_err(|e| format!("Failed to read spoke.key: {}", e))?;
+            println!("spoke.key: {}", protection.name());
+            return Ok(());
+        }
+        ["protect", "--passphrase"] => match key_file::env_passphrase() {
+            Some(passphrase) => Protection::Passphrase(passphrase),
+            None => return Err(format!("Set {} to the passphrase first", key_file::PASSPHRASE_ENV)),
+        },
+        ["protect", "--keychain"] => Protection::Keychain,
+        ["unprotect"] => Protection::Plain,
+        ["-h"] | ["--help"] => {
+            print_key_usage();
+            return Ok(());
+        }
+        _ => {
+            print_key_usage();
+            std::process::exit(1);
+        }
+    };
+    let mut spoke = Spoke::load(home).await.map_err(|e| format!("Failed to load spoke: {}", e))?;
+    spoke
+        .protect_keys(protection.clone())
+        .map_err(|e| format!("Failed to write keys: {}", e))?;
+    println!("Keys are now {}.", protection.name());
+    Ok(())
+}
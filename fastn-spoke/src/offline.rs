@@ -0,0 +1,374 @@
+//! Offline outbox: writes kept while their hub can't be reached
+//!
+//! A write (files, directories, key-value entries) whose hub is unreachable
+//! can be queued instead of failing: `Spoke::send_or_queue` does that, and
+//! `fastn-spoke kosha` queues on its own. The outbox is `outbox.json` in
+//! SPOKE_HOME, or in OPFS on the web. `Spoke::flush_outbox` delivers the
+//! entries in the order they were queued, and backs off exponentially
+//! (`retry_delay`) from hubs it still can't reach.
+//!
+//! Each entry is signed by the spoke when it is queued, so an outbox that
+//! was tampered with, or copied from another spoke, is refused instead of
+//! delivered. Hubs only accept signatures a few minutes old with a fresh
+//! nonce, so delivery signs the request again.
+//!
+//! A write whose `base_version` no longer matches on delivery conflicts,
+//! and the flush asks its resolver what to do: send it again without the
+//! version (`Resolution::Overwrite`), drop it (`Discard`) or leave it in the
+//! outbox (`Keep`). A kept entry, like one the hub refused outright, holds
+//! back later entries for the same paths and keys until
+//! `Spoke::resolve_outbox` settles it.
+
+use crate::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The outbox, in SPOKE_HOME (or OPFS)
+pub const OUTBOX_FILE: &str = "outbox.json";
+
+/// Wait before the first retry of an unreachable hub
+pub const FIRST_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest wait between retries
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(15 * 60);
+
+/// Wait before retrying after `attempts` failed deliveries
+pub fn retry_delay(attempts: u32) -> Duration {
+    FIRST_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Commands that can wait in the outbox
+pub fn is_queueable(command: &str) -> bool {
+    matches!(
+        command,
+        "write_file" | "delete" | "delete_dir" | "rename" | "copy" | "move" | "kv_set" | "kv_delete" | "kv_merge"
+    )
+}
+
+/// Whether an error means the hub couldn't be reached, rather than that it
+/// refused the request
+pub fn is_unreachable(error: &Error) -> bool {
+    match error {
+        Error::ConnectionFailed(_) => true,
+        Error::Net(fastn_net::Error::HttpRequest(_) | fastn_net::Error::NoTransport(_)) => true,
+        Error::Net(fastn_net::Error::Rejected { status, .. }) => matches!(status, 502..=504),
+        _ => false,
+    }
+}
+
+/// The content of a `write_file` request too large to send in one piece,
+/// which has to go up in chunks
+pub(crate) fn chunked_content(request: &fastn_net::HubRequest) -> Result<Option<Vec<u8>>> {
+    let content = match request.payload.get("content").and_then(|v| v.as_str()) {
+        Some(content) if request.command == "write_file" => content,
+        _ => return Ok(None),
+    };
+    if content.len() <= fastn_net::MAX_CHUNK_SIZE.div_ceil(3) * 4 {
+        return Ok(None);
+    }
+    base64::Engine::decode(&base64::prelude::BASE64_STANDARD, content)
+        .map(Some)
+        .map_err(|e| Error::Outbox(format!("invalid base64 content: {}", e)))
+}
+
+/// Where a queued write stands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum OutboxState {
+    /// Waiting for its hub
+    Pending,
+    /// Delivery conflicted and the resolver kept it; `current` is the
+    /// file's version on the hub, as in `Error::Conflict`
+    Conflict {
+        message: String,
+        current: Option<serde_json::Value>,
+    },
+    /// The hub refused it (see `last_error`)
+    Rejected,
+}
+
+/// A write waiting in the outbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Increasing in queue order
+    pub id: u64,
+    /// The hub as given when queued: "self", a known hub, or a hub the
+    /// configured one forwards to
+    pub hub: String,
+    /// The request, signed by the spoke when it was queued
+    pub signed: fastn_net::SignedRequest,
+    pub queued_at: DateTime<Utc>,
+    /// Failed deliveries so far
+    pub attempts: u32,
+    /// Not retried before this, unless flushed with `force`
+    pub next_attempt: DateTime<Utc>,
+    #[serde(flatten)]
+    pub state: OutboxState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl OutboxEntry {
+    /// The queued request, checked to be signed by `spoke_id52`
+    pub fn request(&self, spoke_id52: &str) -> Result<fastn_net::HubRequest> {
+        let (sender, request) = self.signed.verify::<fastn_net::HubRequest>()?;
+        if sender != spoke_id52 {
+            return Err(Error::Outbox(format!("entry {} was queued by {}, not this spoke", self.id, sender)));
+        }
+        Ok(request)
+    }
+
+    /// Command, kosha and path of the queued request, for listings
+    pub fn describe(&self) -> String {
+        let payload = &self.signed.payload;
+        let field = |name: &str| payload.get("payload")?.get(name)?.as_str().map(str::to_string);
+        let command = payload.get("command").and_then(|v| v.as_str()).unwrap_or("?");
+        let instance = payload.get("instance").and_then(|v| v.as_str()).unwrap_or("?");
+        let target = match (field("path"), field("from"), field("to"), field("key")) {
+            (_, Some(from), Some(to), _) => format!("{} -> {}", from, to),
+            (Some(path), ..) => path,
+            (_, _, _, Some(key)) => format!("key {}", key),
+            _ => String::new(),
+        };
+        format!("{} {}/{}/{}", command, self.hub, instance, target)
+    }
+
+    fn targets(&self) -> Vec<String> {
+        targets(&self.hub, &self.signed.payload)
+    }
+
+    fn is_pending(&self) -> bool {
+        self.state == OutboxState::Pending
+    }
+}
+
+/// What a request (as JSON) to `hub` changes, as
+/// `hub|target_hub|instance|path-or-key`
+fn targets(hub: &str, request: &serde_json::Value) -> Vec<String> {
+    let text = |name: &str| request.get(name).and_then(|v| v.as_str()).unwrap_or_default();
+    let prefix = format!("{}|{}|{}|", hub, text("target_hub"), text("instance"));
+    let payload = request.get("payload").cloned().unwrap_or_default();
+    ["path", "from", "to", "key"]
+        .iter()
+        .filter_map(|name| payload.get(*name)?.as_str())
+        .map(|target| format!("{}{}", prefix, target.trim_matches('/')))
+        .collect()
+}
+
+/// Whether two sets of targets share a path, or one holds the other's
+/// directory
+fn overlap(a: &[String], b: &[String]) -> bool {
+    let within = |path: &str, dir: &str| path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'));
+    a.iter().any(|a| b.iter().any(|b| a == b || within(a, b) || within(b, a)))
+}
+
+/// How to settle a queued write that conflicted or was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Send it again: without its `base_version` if it conflicted, as it
+    /// is if it was refused
+    Overwrite,
+    /// Drop it from the outbox
+    Discard,
+    /// Leave it in the outbox for later
+    Keep,
+}
+
+/// Outcome of `Spoke::flush_outbox`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OutboxFlush {
+    /// Entries delivered (and removed), by ID
+    pub delivered: Vec<u64>,
+    /// Conflicting entries the resolver discarded
+    pub discarded: Vec<u64>,
+    /// Entries kept after a conflict or refusal
+    pub held: Vec<u64>,
+    /// Hubs that couldn't be reached
+    pub unreachable: Vec<String>,
+    /// Entries left in the outbox
+    pub remaining: usize,
+}
+
+/// What `Spoke::send_or_queue` did with a request
+#[derive(Debug, Clone)]
+pub enum Delivery {
+    /// The hub answered with this payload
+    Sent(serde_json::Value),
+    /// The hub couldn't be reached, or earlier writes to the same paths are
+    /// still queued
+    Queued(Box<OutboxEntry>),
+}
+
+/// The queued writes, as saved in `OUTBOX_FILE`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Outbox {
+    next_id: u64,
+    entries: Vec<OutboxEntry>,
+}
+
+impl Outbox {
+    /// Entries in queue order
+    pub fn entries(&self) -> &[OutboxEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// When the next pending entry is due, if any
+    pub fn next_attempt(&self) -> Option<DateTime<Utc>> {
+        self.entries.iter().filter(|e| e.is_pending()).map(|e| e.next_attempt).min()
+    }
+
+    /// Whether a request to `hub` has to wait behind queued entries for
+    /// the same paths or keys, to keep writes in order
+    pub(crate) fn holds(&self, hub: &str, request: &fastn_net::HubRequest) -> Result<bool> {
+        let targets = targets(hub, &serde_json::to_value(request)?);
+        Ok(self.entries.iter().any(|e| overlap(&e.targets(), &targets)))
+    }
+
+    /// Sign `request` for the hub with ID `hub_id52` and queue it
+    pub(crate) fn push(
+        &mut self,
+        key: &fastn_net::SecretKey,
+        hub_id52: &str,
+        hub: &str,
+        request: &fastn_net::HubRequest,
+    ) -> Result<OutboxEntry> {
+        if !is_queueable(&request.command) {
+            return Err(Error::Outbox(format!("{} can't be queued", request.command)));
+        }
+        let audience = fastn_net::Audience::new(hub_id52, fastn_net::ENDPOINT);
+        let now = Utc::now();
+        self.next_id += 1;
+        let entry = OutboxEntry {
+            id: self.next_id,
+            hub: hub.to_string(),
+            signed: fastn_net::SignedRequest::new(key, &audience, request)?,
+            queued_at: now,
+            attempts: 0,
+            next_attempt: now,
+            state: OutboxState::Pending,
+            last_error: None,
+        };
+        self.entries.push(entry.clone());
+        Ok(entry)
+    }
+
+    /// Settle an entry; false if there is none with that ID, or it can't be
+    /// sent again (a conflicting request without `base_version`)
+    pub(crate) fn resolve(
+        &mut self,
+        id: u64,
+        resolution: Resolution,
+        key: &fastn_net::SecretKey,
+        spoke_id52: &str,
+    ) -> Result<bool> {
+        let Some(index) = self.entries.iter().position(|e| e.id == id) else {
+            return Ok(false);
+        };
+        match resolution {
+            Resolution::Keep => Ok(true),
+            Resolution::Discard => {
+                self.entries.remove(index);
+                Ok(true)
+            }
+            Resolution::Overwrite => {
+                let entry = &mut self.entries[index];
+                if let OutboxState::Conflict { .. } = entry.state {
+                    let mut request = entry.request(spoke_id52)?;
+                    let Some(payload) = request.payload.as_object_mut() else {
+                        return Ok(false);
+                    };
+                    if payload.remove("base_version").is_none() {
+                        return Ok(false);
+                    }
+                    let audience = entry.signed.audience.clone().ok_or(fastn_net::Error::MissingAudience)?;
+                    entry.signed = fastn_net::SignedRequest::new(key, &audience, &request)?;
+                }
+                entry.state = OutboxState::Pending;
+                entry.next_attempt = Utc::now();
+                Ok(true)
+            }
+        }
+    }
+
+    /// The next entry to deliver: the first pending one that is due (any,
+    /// with `force`), whose hub wasn't found unreachable in this flush, and
+    /// that no earlier entry for the same paths or keys holds back
+    pub(crate) fn next_due(&self, force: bool, flush: &OutboxFlush) -> Option<OutboxEntry> {
+        let now = Utc::now();
+        let mut blocked: Vec<String> = vec![];
+        for entry in &self.entries {
+            let targets = entry.targets();
+            if !overlap(&targets, &blocked)
+                && entry.is_pending()
+                && (force || entry.next_attempt <= now)
+                && !flush.unreachable.contains(&entry.hub)
+            {
+                return Some(entry.clone());
+            }
+            blocked.extend(targets);
+        }
+        None
+    }
+
+    /// Record how delivering entry `id` went, asking `resolve` about a
+    /// conflict
+    pub(crate) fn record(
+        &mut self,
+        id: u64,
+        result: Result<serde_json::Value>,
+        flush: &mut OutboxFlush,
+        resolve: &mut dyn FnMut(&OutboxEntry) -> Resolution,
+        key: &fastn_net::SecretKey,
+        spoke_id52: &str,
+    ) -> Result<()> {
+        let Some(index) = self.entries.iter().position(|e| e.id == id) else {
+            return Ok(());
+        };
+        match result {
+            Ok(_) => {
+                self.entries.remove(index);
+                flush.delivered.push(id);
+            }
+            Err(e) if is_unreachable(&e) => {
+                // Every entry for the hub waits as long
+                let hub = self.entries[index].hub.clone();
+                for entry in self.entries.iter_mut().filter(|e| e.hub == hub && e.is_pending()) {
+                    entry.attempts += 1;
+                    entry.next_attempt = Utc::now() + retry_delay(entry.attempts);
+                    entry.last_error = Some(e.to_string());
+                }
+                flush.unreachable.push(hub);
+            }
+            Err(Error::Conflict { message, current }) => {
+                let entry = &mut self.entries[index];
+                entry.last_error = Some(message.clone());
+                entry.state = OutboxState::Conflict { message, current };
+                let resolution = resolve(entry);
+                let settled = match resolution {
+                    Resolution::Keep => false,
+                    _ => self.resolve(id, resolution, key, spoke_id52)?,
+                };
+                match (resolution, settled) {
+                    (Resolution::Discard, true) => flush.discarded.push(id),
+                    (Resolution::Overwrite, true) => {}
+                    _ => flush.held.push(id),
+                }
+            }
+            Err(e) => {
+                let entry = &mut self.entries[index];
+                entry.attempts += 1;
+                entry.last_error = Some(e.to_string());
+                entry.state = OutboxState::Rejected;
+                flush.held.push(id);
+            }
+        }
+        Ok(())
+    }
+}
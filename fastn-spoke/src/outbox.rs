@@ -0,0 +1,172 @@
+//! Outbox subcommand: writes queued while their hub was unreachable
+//!
+//! Usage: fastn-spoke outbox <list|flush|resolve> [args...]
+//!
+//! `fastn-spoke kosha` queues writes it can't deliver; this lists them,
+//! delivers them and settles the ones that conflicted. How entries are
+//! kept, retried and held back is documented on `fastn_spoke::Outbox`.
+
+use fastn_spoke::{OutboxEntry, OutboxState, Resolution, Spoke};
+use std::path::Path;
+
+/// Run the outbox subcommand
+pub async fn run(args: &[String], home: &Path) {
+    let op = args.first().map(|s| s.as_str());
+    let spoke = match op {
+        Some("list") | Some("flush") | Some("resolve") => match Spoke::load(home).await {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Failed to load spoke: {}", e);
+                eprintln!("Run 'fastn-spoke init <hub-id52> <alias>' first.");
+                std::process::exit(1);
+            }
+        },
+        Some("help") | Some("-h") | Some("--help") => return print_help(),
+        _ => {
+            print_help();
+            std::process::exit(1);
+        }
+    };
+
+    let result = match op {
+        Some("list") => list(&spoke).await,
+        Some("flush") => flush(&spoke, &args[1..]).await,
+        _ => resolve(&spoke, &args[1..]).await,
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn print_help() {
+    println!("fastn-spoke outbox - Writes waiting for their hub");
+    println!();
+    println!("Usage: fastn-spoke outbox <operation> [args...]");
+    println!();
+    println!("Operations:");
+    println!("  list                                   Show queued writes");
+    println!("  flush [--force] [--wait] [--on-conflict <keep|overwrite|discard>]");
+    println!("                                         Deliver queued writes in order");
+    println!("  resolve <id> <overwrite|discard>       Settle a write that conflicted or was refused");
+    println!();
+    println!("'fastn-spoke kosha' queues a write when its hub can't be reached. A flush");
+    println!("retries unreachable hubs after a growing delay (5s up to 15 minutes);");
+    println!("--force retries them now and --wait keeps retrying until nothing is left");
+    println!("waiting. A write that no longer matches its --base-version conflicts and");
+    println!("is kept unless --on-conflict says otherwise; kept and refused writes hold");
+    println!("back later writes to the same paths until resolved.");
+}
+
+/// Print the queued writes
+async fn list(spoke: &Spoke) -> Result<(), String> {
+    let outbox = spoke.outbox().await.map_err(|e| format!("Failed to read outbox: {}", e))?;
+    if outbox.is_empty() {
+        println!("Outbox is empty.");
+    }
+    for entry in outbox.entries() {
+        println!("#{} {} ({})", entry.id, entry.describe(), state(entry));
+        if let Some(error) = &entry.last_error {
+            println!("    {}", error);
+        }
+    }
+    Ok(())
+}
+
+fn state(entry: &OutboxEntry) -> String {
+    let queued = entry.queued_at.format("%Y-%m-%d %H:%M:%S");
+    match &entry.state {
+        OutboxState::Pending if entry.attempts == 0 => format!("queued {}", queued),
+        OutboxState::Pending => format!(
+            "queued {}, {} attempts, next {}",
+            queued,
+            entry.attempts,
+            entry.next_attempt.format("%H:%M:%S")
+        ),
+        OutboxState::Conflict { .. } => format!("conflict, queued {}", queued),
+        OutboxState::Rejected => format!("refused, queued {}", queued),
+    }
+}
+
+/// Deliver the outbox
+/// Usage: flush [--force] [--wait] [--on-conflict <keep|overwrite|discard>]
+async fn flush(spoke: &Spoke, args: &[String]) -> Result<(), String> {
+    let (mut force, mut wait, mut on_conflict) = (false, false, Resolution::Keep);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--wait" => wait = true,
+            "--on-conflict" => on_conflict = parse_resolution(args.next().map(|s| s.as_str()))?,
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    let resolve = |entry: &OutboxEntry| {
+        eprintln!("Conflict: #{} {}", entry.id, entry.describe());
+        on_conflict
+    };
+    let flush = match wait {
+        // A forced pass first, then as the backoff allows
+        true if force => match spoke.flush_outbox(true, resolve).await {
+            Ok(mut first) => spoke.drain_outbox(resolve).await.map(|mut rest| {
+                first.delivered.append(&mut rest.delivered);
+                first.discarded.append(&mut rest.discarded);
+                fastn_spoke::OutboxFlush {
+                    delivered: first.delivered,
+                    discarded: first.discarded,
+                    ..rest
+                }
+            }),
+            Err(e) => Err(e),
+        },
+        true => spoke.drain_outbox(resolve).await,
+        false => spoke.flush_outbox(force, resolve).await,
+    }
+    .map_err(|e| format!("Failed to flush outbox: {}", e))?;
+
+    println!(
+        "{} delivered, {} discarded, {} held, {} left",
+        flush.delivered.len(),
+        flush.discarded.len(),
+        flush.held.len(),
+        flush.remaining
+    );
+    for hub in &flush.unreachable {
+        println!("Unreachable: {}", hub);
+    }
+    if !flush.held.is_empty() {
+        println!("See 'fastn-spoke outbox list', then 'fastn-spoke outbox resolve <id> <overwrite|discard>'.");
+    }
+    Ok(())
+}
+
+/// Settle an entry
+/// Usage: resolve <id> <overwrite|discard>
+async fn resolve(spoke: &Spoke, args: &[String]) -> Result<(), String> {
+    let (Some(id), Some(resolution)) = (args.first(), args.get(1)) else {
+        return Err("Usage: fastn-spoke outbox resolve <id> <overwrite|discard>".to_string());
+    };
+    let id: u64 = id.trim_start_matches('#').parse().map_err(|_| format!("Invalid entry ID: {}", id))?;
+    let resolution = match parse_resolution(Some(resolution))? {
+        Resolution::Keep => return Ok(()),
+        resolution => resolution,
+    };
+    match spoke.resolve_outbox(id, resolution).await {
+        Ok(true) if resolution == Resolution::Discard => println!("Discarded #{}", id),
+        Ok(true) => println!("#{} will be sent again on the next flush", id),
+        Ok(false) => return Err(format!("No entry #{} that can be sent again", id)),
+        Err(e) => return Err(format!("Failed to resolve #{}: {}", id, e)),
+    }
+    Ok(())
+}
+
+fn parse_resolution(value: Option<&str>) -> Result<Resolution, String> {
+    match value {
+        Some("keep") => Ok(Resolution::Keep),
+        Some("overwrite") => Ok(Resolution::Overwrite),
+        Some("discard") => Ok(Resolution::Discard),
+        Some(other) => Err(format!("Unknown resolution: {}", other)),
+        None => Err("Expected keep, overwrite or discard".to_string()),
+    }
+}
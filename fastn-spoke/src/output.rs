@@ -0,0 +1,206 @@
+//! How kosha commands print what they read, and how they exit
+//!
+//! `--output raw` writes file contents as they are (to stdout, or to the
+//! file given with `--out`) and listings one name per line; `--output json`
+//! prints one JSON document, with file contents in base64; `--output table`
+//! prints listings as aligned columns. Reads default to raw, listings to
+//! table.
+//!
+//! Exit codes tell scripts what went wrong without parsing messages:
+//!   0  success
+//!   1  failed on this side (usage, spoke not set up, local files)
+//!   2  the hub could not be reached
+//!   3  not authorized, or access denied
+//!   4  no such app or kosha on the hub
+//!   5  conflict: the file changed since the given version
+//!   6  rate limited, or over a storage quota
+//!   7  the hub refused the request for another reason (e.g. no such file)
+
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_UNREACHABLE: i32 = 2;
+pub const EXIT_DENIED: i32 = 3;
+pub const EXIT_NOT_FOUND: i32 = 4;
+pub const EXIT_CONFLICT: i32 = 5;
+pub const EXIT_QUOTA: i32 = 6;
+pub const EXIT_HUB_ERROR: i32 = 7;
+
+/// Value of `--output`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Raw,
+    Json,
+    Table,
+}
+
+/// `--output` and `--out`, taken out of a command's arguments
+#[derive(Debug, Default)]
+pub struct Output {
+    pub format: Option<Format>,
+    /// Where raw file contents go instead of stdout
+    pub out: Option<PathBuf>,
+}
+
+impl Output {
+    /// Split `--output <format>` (or `-o`) and `--out <file>` from the
+    /// other arguments; exits on a bad value
+    pub fn take(args: &[String]) -> (Vec<String>, Output) {
+        let mut output = Output::default();
+        let mut rest = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--output" | "-o" => {
+                    output.format = Some(match args.next().map(String::as_str) {
+                        Some("raw") => Format::Raw,
+                        Some("json") => Format::Json,
+                        Some("table") => Format::Table,
+                        other => usage_error(&format!("--output takes raw, json or table, not {:?}", other.unwrap_or(""))),
+                    })
+                }
+                "--out" => match args.next() {
+                    Some(file) => output.out = Some(PathBuf::from(file)),
+                    None => usage_error("--out needs a file"),
+                },
+                _ => rest.push(arg.clone()),
+            }
+        }
+        (rest, output)
+    }
+
+    /// The format asked for, or `default`; exits if the command can't
+    /// print it
+    pub fn format(&self, default: Format, supported: &[Format]) -> Format {
+        let format = self.format.unwrap_or(default);
+        if !supported.contains(&format) {
+            usage_error(&format!("this command can't print --output {}", format.name()));
+        }
+        if self.out.is_some() && format != Format::Raw {
+            usage_error("--out only takes raw output");
+        }
+        format
+    }
+
+    /// Write file contents to `--out`, or stdout
+    pub fn write_raw(&self, bytes: &[u8]) {
+        match &self.out {
+            Some(file) => {
+                if let Err(e) = std::fs::write(file, bytes) {
+                    eprintln!("Failed to write {}: {}", file.display(), e);
+                    std::process::exit(EXIT_FAILURE);
+                }
+                eprintln!("Saved {} bytes to {}", bytes.len(), file.display());
+            }
+            None => write_stdout(bytes),
+        }
+    }
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Raw => "raw",
+            Format::Json => "json",
+            Format::Table => "table",
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_FAILURE);
+}
+
+/// Write to stdout, stopping quietly when the reader went away (e.g. when
+/// piped to head)
+pub fn write_stdout(bytes: &[u8]) {
+    let mut stdout = std::io::stdout().lock();
+    if let Err(e) = stdout.write_all(bytes).and_then(|()| stdout.flush())
+        && e.kind() != std::io::ErrorKind::BrokenPipe
+    {
+        eprintln!("Failed to write output: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Print a JSON document on a line of its own
+pub fn print_json(value: &serde_json::Value) {
+    let mut json = serde_json::to_string_pretty(value).expect("JSON values serialize");
+    json.push('\n');
+    write_stdout(json.as_bytes());
+}
+
+/// Print rows under `headers`, each column as wide as its widest cell;
+/// `right` columns (e.g. sizes) are aligned to the right
+pub fn print_table(headers: &[&str], right: &[bool], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    let mut table = String::new();
+    for row in std::iter::once(&headers).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .zip(right.iter().chain(std::iter::repeat(&false)))
+            .map(|((cell, &width), &right)| match right {
+                true => format!("{:>width$}", cell),
+                false => format!("{:<width$}", cell),
+            })
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    write_stdout(table.as_bytes());
+}
+
+/// The exit code for a failed request (see the module docs)
+pub fn exit_code(error: &fastn_spoke::Error) -> i32 {
+    use fastn_net::HubError;
+    use fastn_spoke::Error;
+    match error {
+        e if fastn_spoke::is_unreachable(e) => EXIT_UNREACHABLE,
+        Error::NotAuthorized(_) => EXIT_DENIED,
+        Error::Conflict { .. } => EXIT_CONFLICT,
+        Error::HubRefused(refused) => match refused {
+            HubError::Unauthorized | HubError::AccessDenied { .. } => EXIT_DENIED,
+            HubError::AppNotFound { .. } | HubError::InstanceNotFound { .. } => EXIT_NOT_FOUND,
+            HubError::Conflict { .. } => EXIT_CONFLICT,
+            HubError::QuotaExceeded { .. } => EXIT_QUOTA,
+            HubError::AppError { .. } => EXIT_HUB_ERROR,
+        },
+        Error::Hub(_) | Error::Net(fastn_net::Error::Rejected { .. }) => EXIT_HUB_ERROR,
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Report a failed request and exit with its code
+pub fn fail(context: &str, error: &fastn_spoke::Error) -> ! {
+    eprintln!("{}: {}", context, error);
+    if let fastn_spoke::Error::HubRefused(fastn_net::HubError::AccessDenied { trace: Some(trace), .. }) = error {
+        print_trace(trace);
+    }
+    std::process::exit(exit_code(error));
+}
+
+/// Print the ACL modules a hub consulted to deny a `--explain` request
+fn print_trace(trace: &fastn_net::AclTrace) {
+    eprintln!("ACL trace:");
+    for step in &trace.steps {
+        match &step.reason {
+            Some(reason) => eprintln!("  {}/{}: {:?} ({})", step.kosha, step.file, step.decision, reason),
+            None => eprintln!("  {}/{}: {:?}", step.kosha, step.file, step.decision),
+        }
+    }
+    if let Some(decision) = trace.decision {
+        match &trace.reason {
+            Some(reason) => eprintln!("  => {:?} ({})", decision, reason),
+            None => eprintln!("  => {:?}", decision),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Sync subcommand: two-way sync of a local directory with a kosha
+//!
+//! Usage: fastn-spoke sync <local-dir> <hub> <kosha> <remote-path> [--watch]
+//!
+//! The syncing itself is `fastn_spoke::Syncer`, which documents how
+//! changes and conflicts are handled; this prints what each pass did.
+//!
+//! With `--watch` the command keeps running: local changes are picked up by
+//! polling, remote ones as the hub pushes them (for 'self' and known hubs)
+//! and otherwise by a periodic full pass.
+
+use fastn_spoke::{IGNORE_FILE, Spoke, SyncSummary, Syncer, WatchEvent};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often --watch looks for local changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often --watch does a full pass anyway, for remote changes that
+/// weren't pushed
+const FULL_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Run the sync subcommand
+pub async fn run(args: &[String], home: &Path) {
+    let watch = args.iter().any(|a| a == "--watch");
+    let args: Vec<&String> = args.iter().filter(|a| *a != "--watch").collect();
+    if args.len() < 4 {
+        eprintln!("Usage: fastn-spoke sync <local-dir> <hub> <kosha> <remote-path> [--watch]");
+        eprintln!();
+        eprintln!("Arguments:");
+        eprintln!("  local-dir     Directory to sync (created if missing)");
+        eprintln!("  hub           Hub alias ('self' for local hub, or remote hub alias)");
+        eprintln!("  kosha         Kosha name (e.g., 'root', 'my-data')");
+        eprintln!("  remote-path   Directory within the kosha ('/' for all of it)");
+        eprintln!("  --watch       Keep running and sync changes as they happen");
+        eprintln!();
+        eprintln!("Conflicting edits keep both versions: the local one is saved as");
+        eprintln!("'<name> (conflict <spoke> <time>).<ext>'. List paths to skip in");
+        eprintln!("<local-dir>/{}.", IGNORE_FILE);
+        eprintln!();
+        eprintln!("Example:");
+        eprintln!("  fastn-spoke sync ./notes self my-kosha notes --watch");
+        std::process::exit(1);
+    }
+
+    let local_dir = PathBuf::from(args[0]);
+    let hub = args[1].as_str();
+    let kosha = args[2].as_str();
+    let remote_path = args[3].trim_matches('/').to_string();
+
+    let spoke = match Spoke::load(home).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to load spoke: {}", e);
+            eprintln!("Run 'fastn-spoke init <hub-id52> <alias>' first.");
+            std::process::exit(1);
+        }
+    };
+
+    let mut syncer = match spoke.syncer(&local_dir, hub, kosha, &remote_path) {
+        Ok(syncer) => syncer,
+        Err(e) => {
+            eprintln!("Failed to sync '{}': {}", local_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    eprintln!("Syncing {} with {}/{}/{}", local_dir.display(), hub, kosha, syncer.remote_path());
+    match syncer.sync().await {
+        Ok(summary) => report(&summary),
+        Err(e) => {
+            eprintln!("Sync failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if !watch {
+        return;
+    }
+
+    let mut changes = subscribe(&syncer).await;
+    eprintln!("Watching for changes (Ctrl+C to stop)");
+    let mut last_full_sync = tokio::time::Instant::now();
+    loop {
+        let remote_changed = tokio::select! {
+            event = recv(&mut changes) => match event {
+                Some(WatchEvent::File(change)) => !syncer.is_own_change(&change),
+                Some(WatchEvent::Missed(_)) => true,
+                Some(WatchEvent::Kv(_)) => false,
+                None => {
+                    eprintln!("Lost the hub's change notifications, syncing every {}s", FULL_SYNC_INTERVAL.as_secs());
+                    changes = None;
+                    false
+                }
+            },
+            _ = tokio::time::sleep(POLL_INTERVAL) => false,
+        };
+        let due = last_full_sync.elapsed() >= FULL_SYNC_INTERVAL;
+        if !(remote_changed || due || syncer.local_changed()) {
+            continue;
+        }
+        // Let a burst of changes settle before syncing them together
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        match syncer.sync().await {
+            Ok(summary) => report(&summary),
+            Err(e) => eprintln!("Sync failed (retrying): {}", e),
+        }
+        last_full_sync = tokio::time::Instant::now();
+    }
+}
+
+/// Print what a pass did
+fn report(summary: &SyncSummary) {
+    for warning in &summary.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    for (path, copy) in &summary.conflicts {
+        eprintln!("Conflict: {} changed on both sides, kept the local version as {}", path, copy);
+    }
+    for path in &summary.uploaded {
+        eprintln!("  uploaded {}", path);
+    }
+    for path in &summary.downloaded {
+        eprintln!("  downloaded {}", path);
+    }
+    for path in summary.deleted_remote.iter().chain(&summary.deleted_local) {
+        eprintln!("  deleted {}", path);
+    }
+    if !summary.is_empty() {
+        eprintln!(
+            "Synced: {} uploaded, {} downloaded, {} deleted remotely, {} deleted locally, {} conflicts",
+            summary.uploaded.len(),
+            summary.downloaded.len(),
+            summary.deleted_remote.len(),
+            summary.deleted_local.len(),
+            summary.conflicts.len()
+        );
+    }
+}
+
+/// Follow the hub's file changes under the remote path, if it pushes them
+/// to us
+async fn subscribe(syncer: &Syncer) -> Option<tokio::sync::mpsc::UnboundedReceiver<WatchEvent>> {
+    let mut watch = match syncer.watch().await {
+        Ok(watch) => watch,
+        Err(fastn_spoke::Error::Unsupported(_)) => {
+            eprintln!("Hubs don't forward change notifications, syncing every {}s", FULL_SYNC_INTERVAL.as_secs());
+            return None;
+        }
+        Err(e) => {
+            eprintln!("No change notifications ({}), syncing every {}s", e, FULL_SYNC_INTERVAL.as_secs());
+            return None;
+        }
+    };
+    // Reading the WebSocket isn't cancel-safe, so it gets its own task
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok(Some(event)) = watch.next().await {
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    Some(rx)
+}
+
+/// Next pushed change; never resolves without a subscription
+async fn recv(changes: &mut Option<tokio::sync::mpsc::UnboundedReceiver<WatchEvent>>) -> Option<WatchEvent> {
+    match changes {
+        Some(changes) => changes.recv().await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,604 @@
+//! Two-way sync of a local directory with a kosha (`Syncer`)
+//!
+//! Every pass compares each file's local and remote content with what they
+//! were after the last sync (kept in `.fastn-sync.json` in the local
+//! directory):
+//! - Changed on one side only: the change (edit, new file or delete) is
+//!   copied to the other side. Uploads pass the last synced version as
+//!   `base_version`, so an edit that raced a remote one is caught.
+//! - Changed on both sides to different content: the remote content wins the
+//!   path and the local one is kept (and uploaded) next to it as
+//!   `<name> (conflict <spoke> <time>).<ext>` (numbered if that is taken).
+//!   An edit always wins over a delete.
+//!
+//! Local files are hashed (SHA-256) only when their size or modification
+//! time changed; remote ones only when their version did. Paths matching a
+//! pattern in `.fastnignore` are skipped on both sides: one glob per line
+//! (`*.tmp`, `build/`, `/notes/draft.md`), `#` for comments. Patterns
+//! without a `/` match a name at any depth, a trailing `/` matches
+//! directories only.
+//!
+//! A `Syncer` syncs when asked. To keep a directory in sync, call `sync`
+//! again whenever `Syncer::watch` reports a remote change or
+//! `Syncer::local_changed` a local one (see examples/sync.rs).
+
+use crate::{Error, KoshaClient, Result, Watch};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Sync state, in the synced directory
+pub const STATE_FILE: &str = ".fastn-sync.json";
+
+/// Ignore patterns, in the synced directory (synced like any other file)
+pub const IGNORE_FILE: &str = ".fastnignore";
+
+/// Downloads are written here first, then renamed into place
+const TEMP_SUFFIX: &str = ".fastn-sync-tmp";
+
+/// A file as of the last sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Synced {
+    sha256: String,
+    size: u64,
+    /// Remote version timestamp, the `base_version` of the next upload
+    version: DateTime<Utc>,
+    /// Local modification time, to skip hashing unchanged files
+    local_modified: DateTime<Utc>,
+}
+
+/// Contents of `.fastn-sync.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    hub: String,
+    kosha: String,
+    remote_path: String,
+    /// Relative path -> state after the last sync
+    files: BTreeMap<String, Synced>,
+}
+
+impl SyncState {
+    /// Load the directory's state, or start fresh; a directory syncs with
+    /// one place only
+    fn load(local_dir: &Path, hub: &str, kosha: &str, remote_path: &str) -> Result<Self> {
+        let path = local_dir.join(STATE_FILE);
+        let state: SyncState = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| Error::SyncState(format!("invalid {}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(SyncState {
+                    hub: hub.to_string(),
+                    kosha: kosha.to_string(),
+                    remote_path: remote_path.to_string(),
+                    files: BTreeMap::new(),
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if (state.hub.as_str(), state.kosha.as_str(), state.remote_path.as_str()) != (hub, kosha, remote_path) {
+            return Err(Error::SyncState(format!(
+                "{} is synced with {}/{}/{}; delete {} to sync it with something else",
+                local_dir.display(),
+                state.hub,
+                state.kosha,
+                state.remote_path,
+                STATE_FILE
+            )));
+        }
+        Ok(state)
+    }
+
+    fn save(&self, local_dir: &Path) -> std::io::Result<()> {
+        let temp = local_dir.join(format!("{}{}", STATE_FILE, TEMP_SUFFIX));
+        std::fs::write(&temp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(temp, local_dir.join(STATE_FILE))
+    }
+}
+
+/// Patterns from `.fastnignore`
+struct Ignore {
+    /// (pattern, directories only, matched against the whole path)
+    patterns: Vec<(glob::Pattern, bool, bool)>,
+}
+
+impl Ignore {
+    /// Load the directory's patterns, noting invalid ones in `warnings`
+    fn load(local_dir: &Path, warnings: &mut Vec<String>) -> Self {
+        let text = std::fs::read_to_string(local_dir.join(IGNORE_FILE)).unwrap_or_default();
+        let patterns = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let dir_only = line.ends_with('/');
+                let line = line.trim_end_matches('/');
+                let anchored = line.contains('/');
+                match glob::Pattern::new(line.trim_start_matches('/')) {
+                    Ok(pattern) => Some((pattern, dir_only, anchored)),
+                    Err(e) => {
+                        warnings.push(format!("skipped invalid pattern '{}' in {}: {}", line, IGNORE_FILE, e));
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    /// Whether `path` (relative, '/'-separated) or a directory it is in is
+    /// ignored
+    fn is_ignored(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        if name == STATE_FILE || name.ends_with(TEMP_SUFFIX) {
+            return true;
+        }
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let components: Vec<&str> = path.split('/').collect();
+        (1..=components.len()).any(|depth| {
+            let is_dir = depth < components.len();
+            let prefix = components[..depth].join("/");
+            self.patterns.iter().any(|(pattern, dir_only, anchored)| {
+                (is_dir || !dir_only)
+                    && match anchored {
+                        true => pattern.matches_with(&prefix, options),
+                        false => pattern.matches_with(components[depth - 1], options),
+                    }
+            })
+        })
+    }
+}
+
+/// A local file's size and modification time
+#[derive(Debug, Clone, Copy)]
+struct LocalFile {
+    size: u64,
+    modified: DateTime<Utc>,
+}
+
+/// A remote file's size and version
+#[derive(Debug, Clone, Copy)]
+struct RemoteFile {
+    size: u64,
+    version: DateTime<Utc>,
+}
+
+/// What a pass changed, by synced path (relative to the local directory)
+#[derive(Debug, Clone, Default)]
+pub struct SyncSummary {
+    pub uploaded: Vec<String>,
+    pub downloaded: Vec<String>,
+    pub deleted_remote: Vec<String>,
+    pub deleted_local: Vec<String>,
+    /// Paths changed on both sides, with the conflict copy the local
+    /// content was kept (and uploaded) as
+    pub conflicts: Vec<(String, String)>,
+    /// Invalid ignore patterns and local names that can't be synced
+    pub warnings: Vec<String>,
+}
+
+impl SyncSummary {
+    /// Whether the pass changed nothing on either side
+    pub fn is_empty(&self) -> bool {
+        self.uploaded.is_empty()
+            && self.downloaded.is_empty()
+            && self.deleted_remote.is_empty()
+            && self.deleted_local.is_empty()
+            && self.conflicts.is_empty()
+    }
+}
+
+/// Syncs a local directory with a directory in a kosha, from `Spoke::syncer`
+pub struct Syncer {
+    client: KoshaClient,
+    remote_path: String,
+    local_dir: PathBuf,
+    spoke_alias: String,
+    state: SyncState,
+}
+
+impl Syncer {
+    /// Sync `local_dir` (created if missing) with `remote_path` in the
+    /// client's kosha, marking conflict copies with `spoke_alias`
+    ///
+    /// A directory syncs with one place only: fails with `Error::SyncState`
+    /// if it was synced with another before.
+    pub fn new(
+        client: KoshaClient,
+        local_dir: impl Into<PathBuf>,
+        remote_path: &str,
+        spoke_alias: &str,
+    ) -> Result<Self> {
+        let local_dir = local_dir.into();
+        let remote_path = remote_path.trim_matches('/').to_string();
+        std::fs::create_dir_all(&local_dir)?;
+        let state = SyncState::load(&local_dir, client.hub(), client.name(), &remote_path)?;
+        Ok(Self {
+            client,
+            remote_path,
+            local_dir,
+            spoke_alias: spoke_alias.to_string(),
+            state,
+        })
+    }
+
+    pub fn client(&self) -> &KoshaClient {
+        &self.client
+    }
+
+    pub fn local_dir(&self) -> &Path {
+        &self.local_dir
+    }
+
+    /// Directory within the kosha, without leading or trailing '/'
+    pub fn remote_path(&self) -> &str {
+        &self.remote_path
+    }
+
+    /// Follow remote changes under the remote path; see `KoshaClient::watch`
+    pub async fn watch(&self) -> Result<Watch> {
+        self.client.watch(&self.remote_path).await
+    }
+
+    /// One reconciliation pass over every file on either side
+    ///
+    /// The state is saved after each file, so a pass that fails midway
+    /// keeps what it did and the next pass continues from there.
+    pub async fn sync(&mut self) -> Result<SyncSummary> {
+        let mut summary = SyncSummary::default();
+        let ignore = Ignore::load(&self.local_dir, &mut summary.warnings);
+        let local = self.scan_local(&ignore, &mut summary.warnings)?;
+        let remote = self.list_remote(&ignore).await?;
+        let paths: BTreeSet<String> = local
+            .keys()
+            .chain(remote.keys())
+            .chain(self.state.files.keys())
+            .filter(|path| !ignore.is_ignored(path))
+            .cloned()
+            .collect();
+
+        for path in paths {
+            self.sync_file(&path, local.get(&path).copied(), remote.get(&path).copied(), &mut summary)
+                .await?;
+        }
+        // Files that became ignored are no longer tracked
+        self.state.files.retain(|path, _| !ignore.is_ignored(path));
+        self.state.save(&self.local_dir)?;
+        Ok(summary)
+    }
+
+    async fn sync_file(
+        &mut self,
+        path: &str,
+        local: Option<LocalFile>,
+        remote: Option<RemoteFile>,
+        summary: &mut SyncSummary,
+    ) -> Result<()> {
+        let base = self.state.files.get(path).cloned();
+        let local_sha = match local {
+            Some(file) => match &base {
+                Some(base) if base.size == file.size && base.local_modified == file.modified => Some(base.sha256.clone()),
+                _ => Some(hash_file(&self.local_path(path))?),
+            },
+            None => None,
+        };
+        let remote_sha = match remote {
+            Some(file) => match &base {
+                Some(base) if base.size == file.size && base.version == file.version => Some(base.sha256.clone()),
+                _ => Some(self.client.file_hash(&self.remote(path)).await?.sha256),
+            },
+            None => None,
+        };
+        let base_sha = base.as_ref().map(|base| base.sha256.clone());
+        let (local_changed, remote_changed) = (local_sha != base_sha, remote_sha != base_sha);
+
+        match (local_sha, remote_sha) {
+            // In sync (or gone on both sides)
+            (local_sha, remote_sha) if local_sha == remote_sha => match (local, remote, local_sha) {
+                (Some(local), Some(remote), Some(sha256)) => {
+                    self.state.files.insert(path.to_string(), Synced {
+                        sha256,
+                        size: remote.size,
+                        version: remote.version,
+                        local_modified: local.modified,
+                    });
+                }
+                _ => {
+                    self.state.files.remove(path);
+                }
+            },
+            // Edited or created here: upload
+            (Some(_), _) if !remote_changed => {
+                let base_version = remote.and(base).map(|base| base.version);
+                self.upload_or_resolve(path, base_version, summary).await?;
+            }
+            // Deleted here: delete there
+            (None, Some(_)) if !remote_changed => {
+                self.client.delete(&self.remote(path)).await?;
+                self.state.files.remove(path);
+                summary.deleted_remote.push(path.to_string());
+            }
+            // Edited or created there: download
+            (_, Some(_)) if !local_changed => {
+                self.download(path).await?;
+                summary.downloaded.push(path.to_string());
+            }
+            // Deleted there: delete here
+            (Some(_), None) if !local_changed => {
+                self.delete_local(path)?;
+                summary.deleted_local.push(path.to_string());
+            }
+            // Changed on both sides, where an edit beats a delete
+            (Some(_), None) => self.upload_or_resolve(path, None, summary).await?,
+            (None, Some(_)) => {
+                self.download(path).await?;
+                summary.downloaded.push(path.to_string());
+            }
+            (Some(_), Some(_)) => {
+                let copy = self.resolve_conflict(path).await?;
+                summary.conflicts.push((path.to_string(), copy));
+            }
+            (None, None) => {
+                self.state.files.remove(path);
+            }
+        }
+        self.state.save(&self.local_dir)?;
+        Ok(())
+    }
+
+    /// Upload, or resolve the conflict if the remote file changed since
+    /// `base_version` after all
+    async fn upload_or_resolve(
+        &mut self,
+        path: &str,
+        base_version: Option<DateTime<Utc>>,
+        summary: &mut SyncSummary,
+    ) -> Result<()> {
+        match self.upload(path, base_version).await {
+            Ok(()) => summary.uploaded.push(path.to_string()),
+            Err(Error::Conflict { .. }) => {
+                let copy = self.resolve_conflict(path).await?;
+                summary.conflicts.push((path.to_string(), copy));
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Keep the local content as a conflict copy (uploaded as a new file)
+    /// and take the remote content, returning the copy's path
+    async fn resolve_conflict(&mut self, path: &str) -> Result<String> {
+        let copy = self.conflict_copy_name(path);
+        std::fs::rename(self.local_path(path), self.local_path(&copy))?;
+        self.upload(&copy, None).await?;
+        match self.download(path).await {
+            Ok(()) => {}
+            // Deleted there meanwhile: the copy is all that's left
+            Err(Error::Hub(_) | Error::HubRefused(_)) => {
+                self.state.files.remove(path);
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(copy)
+    }
+
+    fn conflict_copy_name(&self, path: &str) -> String {
+        let (dir, name) = match path.rsplit_once('/') {
+            Some((dir, name)) => (format!("{}/", dir), name),
+            None => (String::new(), path),
+        };
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+            _ => (name, String::new()),
+        };
+        let time = Utc::now().format("%Y%m%d-%H%M%S");
+        let copy = format!("{}{} (conflict {} {}", dir, stem, self.spoke_alias, time);
+        // Another conflict of the path within the same second gets a number
+        (1..)
+            .map(|n| match n {
+                1 => format!("{}){}", copy, ext),
+                n => format!("{} {}){}", copy, n, ext),
+            })
+            .find(|copy| !self.local_path(copy).exists() && !self.state.files.contains_key(copy))
+            .expect("unbounded range")
+    }
+
+    /// Upload a local file; with `base_version` only if the remote file is
+    /// still at it
+    async fn upload(&mut self, path: &str, base_version: Option<DateTime<Utc>>) -> Result<()> {
+        let local_path = self.local_path(path);
+        let content = std::fs::read(&local_path)?;
+        let local = local_file(&local_path)?;
+        let version = self.client.write_versioned(&self.remote(path), &content, base_version).await?;
+        self.state.files.insert(path.to_string(), Synced {
+            sha256: crate::sha256_hex(&content),
+            size: content.len() as u64,
+            version,
+            local_modified: local.modified,
+        });
+        Ok(())
+    }
+
+    /// Replace the local file with the remote one
+    async fn download(&mut self, path: &str) -> Result<()> {
+        let local_path = self.local_path(path);
+        let temp = PathBuf::from(format!("{}{}", local_path.display(), TEMP_SUFFIX));
+        let hash = self.client.download_to(&self.remote(path), &temp).await?;
+        let version = hash
+            .modified
+            .parse()
+            .map_err(|e| Error::Hub(format!("invalid version {}: {}", hash.modified, e)))?;
+        std::fs::rename(&temp, &local_path)?;
+        self.state.files.insert(path.to_string(), Synced {
+            sha256: hash.sha256,
+            size: hash.size,
+            version,
+            local_modified: local_file(&local_path)?.modified,
+        });
+        Ok(())
+    }
+
+    /// Delete a local file, and the directories that leaves empty
+    fn delete_local(&mut self, path: &str) -> std::io::Result<()> {
+        let local_path = self.local_path(path);
+        std::fs::remove_file(&local_path)?;
+        let mut dir = local_path.parent();
+        while let Some(parent) = dir.filter(|d| *d != self.local_dir) {
+            if std::fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+        self.state.files.remove(path);
+        Ok(())
+    }
+
+    /// Whether a local file differs from the last sync (by size or
+    /// modification time); cheap enough to poll
+    pub fn local_changed(&self) -> bool {
+        let mut warnings = vec![];
+        let ignore = Ignore::load(&self.local_dir, &mut warnings);
+        let Ok(local) = self.scan_local(&ignore, &mut warnings) else {
+            return true;
+        };
+        let tracked = self.state.files.keys().filter(|path| !ignore.is_ignored(path)).count();
+        let unchanged = local
+            .iter()
+            .filter(|(path, file)| {
+                self.state
+                    .files
+                    .get(*path)
+                    .is_some_and(|synced| synced.size == file.size && synced.local_modified == file.modified)
+            })
+            .count();
+        local.len() != unchanged || tracked != unchanged
+    }
+
+    /// Whether a watched change is one of this syncer's own uploads or
+    /// deletes (or outside the remote path), so needs no pass
+    ///
+    /// Versions are whole seconds, so two writes in the same second can't
+    /// be told apart by their time alone. The kosha gives every write of a
+    /// file a later version than the one before, so a write is this
+    /// syncer's only if it carries exactly the version (and size) last
+    /// synced; anything else, another writer's edit in the same second
+    /// included, needs a pass.
+    pub fn is_own_change(&self, change: &fastn_net::FileChange) -> bool {
+        let Some(path) = self.relative(&change.path) else {
+            return true;
+        };
+        let synced = self.state.files.get(path);
+        match &change.kind {
+            fastn_net::FileChangeKind::Renamed { .. } => false,
+            fastn_net::FileChangeKind::Deleted => synced.is_none(),
+            _ => synced.is_some_and(|synced| {
+                let version = change.version.parse::<DateTime<Utc>>().ok();
+                version == Some(synced.version) && change.size == synced.size
+            }),
+        }
+    }
+
+    /// Every file under the local directory
+    fn scan_local(&self, ignore: &Ignore, warnings: &mut Vec<String>) -> std::io::Result<BTreeMap<String, LocalFile>> {
+        let mut files = BTreeMap::new();
+        let mut dirs = vec![(self.local_dir.clone(), String::new())];
+        while let Some((dir, prefix)) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    warnings.push(format!("skipped non-UTF-8 name in {}", dir.display()));
+                    continue;
+                };
+                let path = format!("{}{}", prefix, name);
+                if ignore.is_ignored(&path) {
+                    continue;
+                }
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    dirs.push((entry.path(), format!("{}/", path)));
+                } else if file_type.is_file() {
+                    files.insert(path, local_file(&entry.path())?);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Every file under the remote path
+    async fn list_remote(&self, ignore: &Ignore) -> Result<BTreeMap<String, RemoteFile>> {
+        let mut files = BTreeMap::new();
+        let mut dirs = vec![String::new()];
+        while let Some(prefix) = dirs.pop() {
+            let dir = self.remote(prefix.trim_end_matches('/'));
+            for entry in self.client.list_dir(&dir).await? {
+                let path = format!("{}{}", prefix, entry.name);
+                if ignore.is_ignored(&path) {
+                    continue;
+                }
+                match entry.is_dir {
+                    true => dirs.push(format!("{}/", path)),
+                    false => {
+                        // Versions are whole seconds
+                        let version = DateTime::from_timestamp(entry.modified.timestamp(), 0).unwrap_or(entry.modified);
+                        files.insert(path, RemoteFile { size: entry.size, version });
+                    }
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn local_path(&self, path: &str) -> PathBuf {
+        path.split('/').fold(self.local_dir.clone(), |dir, part| dir.join(part))
+    }
+
+    /// Kosha path of a synced file
+    fn remote(&self, path: &str) -> String {
+        match (self.remote_path.as_str(), path) {
+            ("", path) => path.to_string(),
+            (root, "") => root.to_string(),
+            (root, path) => format!("{}/{}", root, path),
+        }
+    }
+
+    /// Synced path of a kosha path, if it is under the remote path
+    fn relative<'a>(&self, kosha_path: &'a str) -> Option<&'a str> {
+        let kosha_path = kosha_path.trim_start_matches('/');
+        if self.remote_path.is_empty() {
+            return Some(kosha_path);
+        }
+        kosha_path.strip_prefix(self.remote_path.as_str())?.strip_prefix('/')
+    }
+}
+
+fn local_file(path: &Path) -> std::io::Result<LocalFile> {
+    let metadata = std::fs::metadata(path)?;
+    Ok(LocalFile {
+        size: metadata.len(),
+        modified: metadata.modified().map(DateTime::<Utc>::from).unwrap_or_default(),
+    })
+}
+
+/// SHA-256 of a file, read in blocks
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = sha2::Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,287 @@
+//! Tests for two-way sync against an in-process hub
+
+use fastn_hub::Hub;
+use fastn_spoke::{KoshaClient, Spoke, Syncer};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A hub with a "notes" kosha, served on a free port, and a spoke it knows
+async fn create_test_spoke(name: &str) -> (Spoke, PathBuf) {
+    let temp_dir = std::env::temp_dir().join(format!("fastn-syncer-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    std::fs::create_dir_all(&temp_dir).expect("Failed to create test directory");
+    let mut hub = Hub::init(temp_dir.join("hub")).await.expect("Failed to init hub");
+    hub.create_kosha("notes").await.expect("Failed to create kosha");
+
+    let port = free_port();
+    let url = format!("http://127.0.0.1:{}", port);
+    let spoke = Spoke::init(temp_dir.join("spoke"), hub.id52(), &url, "laptop")
+        .await
+        .expect("Failed to init spoke");
+    hub.add_spoke(spoke.id52()).await.expect("Failed to add spoke");
+    serve(hub, port).await;
+    (spoke, temp_dir)
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Serve the hub on `port`, waiting for it to come up
+async fn serve(hub: Hub, port: u16) {
+    tokio::spawn(hub.serve(port));
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Hub did not come up");
+}
+
+fn syncer(spoke: &Spoke, dir: &Path) -> Syncer {
+    spoke.syncer(dir.join("local"), "self", "notes", "docs").unwrap()
+}
+
+fn write_local(dir: &Path, path: &str, content: &str) {
+    let path = dir.join("local").join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+}
+
+fn read_local(dir: &Path, path: &str) -> Option<String> {
+    std::fs::read_to_string(dir.join("local").join(path)).ok()
+}
+
+async fn read_remote(kosha: &KoshaClient, path: &str) -> Option<String> {
+    let file = kosha.read(&format!("docs/{}", path)).await.ok()?;
+    Some(String::from_utf8(file.content).unwrap())
+}
+
+/// Names of the files in the local directory, sync state aside
+fn local_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir.join("local"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name != fastn_spoke::STATE_FILE)
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn test_edit_edit_keeps_both() {
+    let (spoke, dir) = create_test_spoke("edit-edit").await;
+    let kosha = spoke.kosha("self", "notes");
+    let mut syncer = syncer(&spoke, &dir);
+    write_local(&dir, "a.md", "base");
+    syncer.sync().await.unwrap();
+
+    write_local(&dir, "a.md", "local edit");
+    kosha.write("docs/a.md", b"remote edit!").await.unwrap();
+    let summary = syncer.sync().await.unwrap();
+
+    assert_eq!(summary.conflicts.len(), 1);
+    let (path, copy) = &summary.conflicts[0];
+    assert_eq!(path, "a.md");
+    assert_eq!(read_local(&dir, "a.md").as_deref(), Some("remote edit!"));
+    assert_eq!(read_local(&dir, copy).as_deref(), Some("local edit"));
+    assert_eq!(read_remote(&kosha, copy).await.as_deref(), Some("local edit"));
+    assert_eq!(read_remote(&kosha, "a.md").await.as_deref(), Some("remote edit!"));
+
+    // Settled: the next pass has nothing to do
+    assert!(syncer.sync().await.unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_edit_beats_delete() {
+    let (spoke, dir) = create_test_spoke("edit-delete").await;
+    let kosha = spoke.kosha("self", "notes");
+    let mut syncer = syncer(&spoke, &dir);
+    write_local(&dir, "local.md", "base");
+    write_local(&dir, "remote.md", "base");
+    syncer.sync().await.unwrap();
+
+    // Edited here, deleted there
+    write_local(&dir, "local.md", "local edit");
+    kosha.delete("docs/local.md").await.unwrap();
+    // Deleted here, edited there
+    std::fs::remove_file(dir.join("local").join("remote.md")).unwrap();
+    kosha.write("docs/remote.md", b"remote edit").await.unwrap();
+
+    let summary = syncer.sync().await.unwrap();
+    assert_eq!(summary.uploaded, vec!["local.md".to_string()]);
+    assert_eq!(summary.downloaded, vec!["remote.md".to_string()]);
+    assert!(summary.conflicts.is_empty());
+    assert_eq!(read_remote(&kosha, "local.md").await.as_deref(), Some("local edit"));
+    assert_eq!(read_local(&dir, "remote.md").as_deref(), Some("remote edit"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_delete_delete_stays_deleted() {
+    let (spoke, dir) = create_test_spoke("delete-delete").await;
+    let kosha = spoke.kosha("self", "notes");
+    let mut syncer = syncer(&spoke, &dir);
+    write_local(&dir, "a.md", "base");
+    syncer.sync().await.unwrap();
+
+    std::fs::remove_file(dir.join("local").join("a.md")).unwrap();
+    kosha.delete("docs/a.md").await.unwrap();
+    assert!(syncer.sync().await.unwrap().is_empty());
+    assert!(syncer.sync().await.unwrap().is_empty());
+
+    assert_eq!(read_local(&dir, "a.md"), None);
+    assert_eq!(read_remote(&kosha, "a.md").await, None);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_remote_delete_removes_emptied_dirs() {
+    let (spoke, dir) = create_test_spoke("delete-local").await;
+    let kosha = spoke.kosha("self", "notes");
+    let mut syncer = syncer(&spoke, &dir);
+    write_local(&dir, "a/b/c.md", "c");
+    write_local(&dir, "a/d.md", "d");
+    syncer.sync().await.unwrap();
+
+    kosha.delete("docs/a/b/c.md").await.unwrap();
+    let summary = syncer.sync().await.unwrap();
+    assert_eq!(summary.deleted_local, vec!["a/b/c.md".to_string()]);
+    assert!(!dir.join("local/a/b").exists());
+    // Directories still holding files stay
+    assert_eq!(read_local(&dir, "a/d.md").as_deref(), Some("d"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_ignored_paths_are_not_synced() {
+    let (spoke, dir) = create_test_spoke("ignored").await;
+    let kosha = spoke.kosha("self", "notes");
+    let mut syncer = syncer(&spoke, &dir);
+    write_local(&dir, fastn_spoke::IGNORE_FILE, "# scratch\n*.tmp\nbuild/\n");
+    write_local(&dir, "scratch.tmp", "local");
+    write_local(&dir, "build/out.md", "local");
+    write_local(&dir, "a.md", "a");
+    kosha.write("docs/sub/remote.tmp", b"remote").await.unwrap();
+    kosha.write("docs/build/remote.md", b"remote").await.unwrap();
+
+    let summary = syncer.sync().await.unwrap();
+    let mut uploaded = summary.uploaded.clone();
+    uploaded.sort();
+    assert_eq!(uploaded, vec![fastn_spoke::IGNORE_FILE.to_string(), "a.md".to_string()]);
+    assert!(summary.downloaded.is_empty());
+    assert_eq!(read_remote(&kosha, "scratch.tmp").await, None);
+    assert_eq!(read_remote(&kosha, "build/out.md").await, None);
+    assert_eq!(read_local(&dir, "sub/remote.tmp"), None);
+    assert_eq!(read_local(&dir, "build/remote.md"), None);
+
+    // Deleting an ignored file on one side doesn't touch the other
+    std::fs::remove_file(dir.join("local").join("scratch.tmp")).unwrap();
+    kosha.delete("docs/build/remote.md").await.unwrap();
+    assert!(syncer.sync().await.unwrap().is_empty());
+    assert_eq!(read_local(&dir, "build/out.md").as_deref(), Some("local"));
+    assert_eq!(read_remote(&kosha, "sub/remote.tmp").await.as_deref(), Some("remote"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_conflict_copy_names() {
+    let (spoke, dir) = create_test_spoke("copy-names").await;
+    let kosha = spoke.kosha("self", "notes");
+    let mut syncer = syncer(&spoke, &dir);
+    for path in ["report.final.md", "sub/README", ".env"] {
+        write_local(&dir, path, "base");
+    }
+    syncer.sync().await.unwrap();
+
+    for path in ["report.final.md", "sub/README", ".env"] {
+        write_local(&dir, path, "local edit");
+        kosha.write(&format!("docs/{}", path), b"remote edit!").await.unwrap();
+    }
+    let summary = syncer.sync().await.unwrap();
+    let copies: Vec<&str> = summary.conflicts.iter().map(|(_, copy)| copy.as_str()).collect();
+    assert_eq!(copies.len(), 3);
+
+    // Paths are synced in order: ".env", "report.final.md", "sub/README"
+    let time = |copy: &str, prefix: &str, suffix: &str| {
+        let time = copy.strip_prefix(prefix).and_then(|rest| rest.strip_suffix(suffix));
+        let time = time.unwrap_or_else(|| panic!("unexpected conflict copy {:?}", copy));
+        assert!(chrono::NaiveDateTime::parse_from_str(time, "%Y%m%d-%H%M%S").is_ok(), "{:?}", copy);
+    };
+    // A dotfile has no extension to keep
+    time(copies[0], ".env (conflict laptop ", ")");
+    // Only the last extension is kept after the marker
+    time(copies[1], "report.final (conflict laptop ", ").md");
+    time(copies[2], "sub/README (conflict laptop ", ")");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_repeated_conflicts_keep_every_copy() {
+    let (spoke, dir) = create_test_spoke("repeated").await;
+    let kosha = spoke.kosha("self", "notes");
+    let mut syncer = syncer(&spoke, &dir);
+    write_local(&dir, "a.md", "base");
+    syncer.sync().await.unwrap();
+
+    // Two conflicts of the same file, likely within the same second
+    for (local, remote) in [("local 1", "remote 1!"), ("local 2", "remote 2!")] {
+        write_local(&dir, "a.md", local);
+        kosha.write("docs/a.md", remote.as_bytes()).await.unwrap();
+        syncer.sync().await.unwrap();
+    }
+
+    let names = local_names(&dir);
+    assert_eq!(names.len(), 3, "{:?}", names);
+    let copies: Vec<String> = names
+        .iter()
+        .filter(|name| *name != "a.md")
+        .map(|name| read_local(&dir, name).unwrap())
+        .collect();
+    assert!(copies.contains(&"local 1".to_string()));
+    assert!(copies.contains(&"local 2".to_string()));
+    assert_eq!(read_local(&dir, "a.md").as_deref(), Some("remote 2!"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_own_change_matches_version_and_size() {
+    let (spoke, dir) = create_test_spoke("own-change").await;
+    let kosha = spoke.kosha("self", "notes");
+    let mut syncer = syncer(&spoke, &dir);
+    write_local(&dir, "a.md", "mine");
+    syncer.sync().await.unwrap();
+
+    let hash = kosha.file_hash("docs/a.md").await.unwrap();
+    let change = |version: &str, size: u64| fastn_net::FileChange {
+        path: "docs/a.md".to_string(),
+        kind: fastn_net::FileChangeKind::Modified,
+        version: version.to_string(),
+        size,
+    };
+    assert!(syncer.is_own_change(&change(&hash.modified, hash.size)));
+    // Another write in the same second
+    assert!(!syncer.is_own_change(&change(&hash.modified, hash.size + 1)));
+
+    // The next write of the file gets a later version, even within the second
+    kosha.write("docs/a.md", b"ours").await.unwrap();
+    let hash = kosha.file_hash("docs/a.md").await.unwrap();
+    assert_eq!(hash.size, 4);
+    assert!(!syncer.is_own_change(&change(&hash.modified, hash.size)));
+
+    // Outside the remote path
+    let mut other = change(&hash.modified, 0);
+    other.path = "elsewhere/a.md".to_string();
+    assert!(syncer.is_own_change(&other));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
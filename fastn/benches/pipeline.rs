@@ -0,0 +1,378 @@
+//! Benchmarks of the event -> command pipeline
+//!
+//! Run with `cargo xtask bench`, which keeps a history of the results and
+//! shows how each benchmark moved since the last saved run. Directly:
+//!
+//! ```bash
+//! cargo bench -p fastn --no-default-features --bench pipeline -- [FILTER...] [--output results.json]
+//! ```
+//!
+//! Scenes are Quest-sized: up to 10k entities in groups of 100, a tenth of
+//! them spinning and the groups labelled for screen readers.
+
+use fastn::wasm_bridge::{self, CoreApp};
+use fastn::*;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Entity counts the scene benchmarks run with
+const SCENE_SIZES: [usize; 2] = [1_000, 10_000];
+
+/// How long a sample should take; fast routines run many times per sample
+const SAMPLE_TIME: Duration = Duration::from_millis(20);
+
+/// Samples per benchmark, of which the median is reported
+const SAMPLES: usize = 15;
+
+fn main() {
+    let mut bencher = Bencher::from_args();
+
+    event_deserialization(&mut bencher);
+    command_serialization(&mut bencher);
+    animation(&mut bencher);
+    registry_sync(&mut bencher);
+    bridge(&mut bencher);
+
+    if let Err(e) = bencher.save() {
+        eprintln!("Failed to save the results: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// ============================================================================
+// Benchmarks
+// ============================================================================
+
+/// Parsing the events a shell sends every frame
+fn event_deserialization(bencher: &mut Bencher) {
+    let pose = PoseData {
+        position: [0.1, 1.6, -0.3],
+        orientation: [0.0, 0.38, 0.0, 0.92],
+    };
+    let events = [
+        ("frame", frame(1)),
+        ("head_pose", Event::Xr(XrEvent::HeadPose(pose.clone()))),
+        (
+            "controller_pose",
+            Event::Xr(XrEvent::ControllerPose(XrControllerData {
+                hand: Hand::Right,
+                pose: pose.clone(),
+                grip_pose: Some(pose.clone()),
+                buttons: vec![(0.0, false); 6],
+                axes: vec![0.0; 4],
+            })),
+        ),
+        (
+            "hand_pose",
+            Event::Xr(XrEvent::HandPose(XrHandData {
+                hand: Hand::Left,
+                joints: vec![pose; 26],
+                pinch_strength: 0.4,
+            })),
+        ),
+    ];
+    for (name, event) in events {
+        let json = serde_json::to_string(&event).unwrap();
+        bencher.run(&format!("event/deserialize/{}", name), || {
+            black_box(serde_json::from_str::<Event>(black_box(&json)).unwrap());
+        });
+    }
+}
+
+/// Serializing what the core sends: the scene at startup and a frame's
+/// worth of transform updates
+fn command_serialization(bencher: &mut Bencher) {
+    for size in SCENE_SIZES {
+        let commands = initial_commands(&scene(size));
+        bencher.run(&format!("command/serialize/init/{}", size), || {
+            black_box(serde_json::to_string(black_box(&commands)).unwrap());
+        });
+
+        let transforms: Vec<Command> = (0..size).map(|i| set_transform(&format!("e{}", i), i as f32)).collect();
+        bencher.run(&format!("command/serialize/transforms/{}", size), || {
+            black_box(serde_json::to_string(black_box(&transforms)).unwrap());
+        });
+    }
+}
+
+/// Advancing every running animation by a step, as the shell reports them
+/// complete
+fn animation(bencher: &mut Bencher) {
+    for size in SCENE_SIZES {
+        let entities: Vec<EntityKind> = (0..size)
+            .map(|i| model(&format!("e{}", i), i).spin([0.0, 1.0, 0.0], 2.0).into())
+            .collect();
+        let mut animations = Animations::new(&entities);
+        animations.handle_event(&init());
+        let mut running: Vec<String> = (0..size)
+            .flat_map(|i| animations.handle_event(&volume_ready(&format!("e{}", i))))
+            .filter_map(|command| animation_id(&command))
+            .collect();
+        assert_eq!(running.len(), size);
+
+        bencher.run(&format!("animation/step/{}", size), || {
+            running = running
+                .iter()
+                .flat_map(|animation_id| {
+                    animations.handle_event(&Event::Scene(SceneEvent::VolumeAnimationComplete {
+                        volume_id: String::new(),
+                        animation_id: animation_id.clone(),
+                    }))
+                })
+                .filter_map(|command| animation_id(&command))
+                .collect();
+        });
+    }
+}
+
+/// A frame in a running app: every framework part checks what changed in
+/// the state it keeps for the scene's entities (ray cast shapes, audio mix,
+/// accessibility nodes, schedules) and sends only the differences
+fn registry_sync(bencher: &mut Bencher) {
+    for size in SCENE_SIZES {
+        let mut app = started_app(size);
+        let mut frame_number = 1;
+        bencher.run(&format!("registry/frame/{}", size), || {
+            frame_number += 1;
+            black_box(app.on_event(&frame(frame_number)));
+        });
+    }
+}
+
+/// What a shell pays per call into the core: the event as JSON in, the
+/// commands as JSON out. Plus creating the app, which sends the whole scene.
+fn bridge(bencher: &mut Bencher) {
+    for size in SCENE_SIZES {
+        let content = scene(size);
+        bencher.run(&format!("bridge/create/{}", size), || {
+            let app = wasm_bridge::create_app(&content);
+            // SAFETY: `app` was just created and is destroyed right after
+            unsafe {
+                black_box(wasm_bridge::get_result_len(app));
+                wasm_bridge::destroy_app(app);
+            }
+        });
+
+        let app = Box::into_raw(started_app(size));
+        let mut frame_number = 1;
+        bencher.run(&format!("bridge/round_trip/{}", size), || {
+            frame_number += 1;
+            let json = serde_json::to_vec(&frame(frame_number)).unwrap();
+            // SAFETY: `app` lives until destroyed below, `json` through the call
+            unsafe {
+                wasm_bridge::app_on_event(app, json.as_ptr(), json.len());
+                black_box(std::slice::from_raw_parts(
+                    wasm_bridge::get_result_ptr(app),
+                    wasm_bridge::get_result_len(app),
+                ));
+            }
+        });
+        // SAFETY: created above and not used after this
+        unsafe { wasm_bridge::destroy_app(app) };
+    }
+}
+
+// ============================================================================
+// Scenes and events
+// ============================================================================
+
+fn model(id: &str, i: usize) -> ModelEntity {
+    let mesh = match i % 3 {
+        0 => MeshResource::generate_box(0.1),
+        1 => MeshResource::generate_sphere(0.05),
+        _ => MeshResource::generate_cylinder(0.05, 0.1),
+    };
+    let shade = (i % 10) as f32 / 10.0;
+    ModelEntity::with_id(id, mesh, SimpleMaterial::new().color(shade, 0.5, 1.0 - shade))
+        .position((i % 100) as f32 * 0.2, (i / 100 % 10) as f32 * 0.2, -((i / 1000) as f32) - 1.0)
+}
+
+/// `size` entities in labelled groups of 100, every tenth one spinning
+fn scene(size: usize) -> RealityViewContent {
+    let mut content = fastn::content();
+    for group in 0..size.div_ceil(100) {
+        let mut parent = Entity::with_id(format!("group{}", group)).accessibility_label(format!("Shelf {}", group));
+        // The group is the 100th entity
+        for i in group * 100 + 1..((group + 1) * 100).min(size) {
+            let entity = model(&format!("e{}", i), i);
+            match i % 10 {
+                0 => parent.add_child(entity.spin([0.0, 1.0, 0.0], 4.0)),
+                _ => parent.add_child(entity),
+            }
+        }
+        content.add(parent);
+    }
+    content
+}
+
+/// The commands a new app sends for `content`
+fn initial_commands(content: &RealityViewContent) -> Vec<Command> {
+    let app = wasm_bridge::create_app(content);
+    // SAFETY: `app` was just created and is destroyed right after
+    unsafe {
+        let json = std::slice::from_raw_parts(wasm_bridge::get_result_ptr(app), wasm_bridge::get_result_len(app));
+        let commands = serde_json::from_slice(json).unwrap();
+        wasm_bridge::destroy_app(app);
+        commands
+    }
+}
+
+/// An app for a scene of `size` entities, initialized by a Quest-like shell
+/// that has created every volume
+fn started_app(size: usize) -> Box<CoreApp> {
+    let content = scene(size);
+    let mut app = CoreApp::new(&content);
+    app.on_event(&init());
+    for command in initial_commands(&content) {
+        if let Command::Scene(SceneCommand::CreateVolume(volume)) = command {
+            app.on_event(&volume_ready(&volume.volume_id));
+        }
+    }
+    app.on_event(&frame(1));
+    app
+}
+
+fn init() -> Event {
+    Event::Lifecycle(LifecycleEvent::Init(InitEvent {
+        platform: Platform::Quest,
+        viewport_width: 1832,
+        viewport_height: 1920,
+        dpr: 1.0,
+        xr_supported: true,
+        xr_immersive_vr: true,
+        xr_immersive_ar: true,
+        webrtc_supported: false,
+        websocket_supported: true,
+        features: [
+            FEATURE_TRANSFORM_ANIMATION,
+            FEATURE_ACCESSIBILITY_TREE,
+            FEATURE_COMMAND_SCHEDULING,
+            FEATURE_SPATIAL_AUDIO,
+        ]
+        .map(String::from)
+        .to_vec(),
+        accessibility: AccessibilityPreferences::default(),
+        conventions: Conventions::default(),
+        camera: None,
+    }))
+}
+
+fn frame(frame: u64) -> Event {
+    Event::Lifecycle(LifecycleEvent::Frame(FrameEvent {
+        time: frame as f64 / 72.0,
+        dt: 1.0 / 72.0,
+        frame,
+    }))
+}
+
+fn volume_ready(volume_id: &str) -> Event {
+    Event::Scene(SceneEvent::VolumeReady {
+        volume_id: volume_id.to_string(),
+    })
+}
+
+fn set_transform(volume_id: &str, angle: f32) -> Command {
+    Command::Scene(SceneCommand::SetTransform(SetTransformData {
+        volume_id: volume_id.to_string(),
+        transform: Transform {
+            position: [angle.cos(), 1.0, angle.sin()],
+            rotation: [0.0, (angle / 2.0).sin(), 0.0, (angle / 2.0).cos()],
+            scale: [1.0; 3],
+        },
+        animate: None,
+    }))
+}
+
+fn animation_id(command: &Command) -> Option<String> {
+    match command {
+        Command::Scene(SceneCommand::SetTransform(SetTransformData {
+            animate: Some(AnimateTransform {
+                animation_id: Some(id), ..
+            }),
+            ..
+        })) => Some(id.clone()),
+        _ => None,
+    }
+}
+
+// ============================================================================
+// Measuring
+// ============================================================================
+
+/// Times routines and collects the results
+struct Bencher {
+    /// Substrings of the benchmarks to run; all if empty
+    filters: Vec<String>,
+    /// Where to write the results as JSON
+    output: Option<PathBuf>,
+    /// Benchmark name -> median and fastest time per iteration, in ns
+    results: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Bencher {
+    /// `[FILTER...] [--output PATH]`; other flags (like the `--bench` cargo
+    /// passes) are ignored
+    fn from_args() -> Self {
+        let mut bencher = Bencher {
+            filters: vec![],
+            output: None,
+            results: serde_json::Map::new(),
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--output" => bencher.output = args.next().map(PathBuf::from),
+                flag if flag.starts_with("--") => {}
+                filter => bencher.filters.push(filter.to_string()),
+            }
+        }
+        bencher
+    }
+
+    /// Time `routine`: batches of iterations lasting about `SAMPLE_TIME`,
+    /// `SAMPLES` times
+    fn run(&mut self, name: &str, mut routine: impl FnMut()) {
+        if !self.filters.is_empty() && !self.filters.iter().any(|f| name.contains(f.as_str())) {
+            return;
+        }
+        let mut batch = |iterations: u64| {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                routine();
+            }
+            start.elapsed()
+        };
+
+        // Warm up while finding how many iterations fill a sample
+        let mut iterations = 1;
+        while batch(iterations) < SAMPLE_TIME && iterations < 1 << 30 {
+            iterations *= 2;
+        }
+        let mut times: Vec<f64> = (0..SAMPLES)
+            .map(|_| batch(iterations).as_nanos() as f64 / iterations as f64)
+            .collect();
+        times.sort_by(f64::total_cmp);
+        let (median, min) = (times[SAMPLES / 2], times[0]);
+
+        println!("{:<36} {:>12}  (fastest {})", name, format_ns(median), format_ns(min));
+        self.results
+            .insert(name.to_string(), serde_json::json!({ "median_ns": median, "min_ns": min }));
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let Some(output) = &self.output else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(&self.results).map_err(std::io::Error::other)?;
+        std::fs::write(output, json)
+    }
+}
+
+fn format_ns(ns: f64) -> String {
+    match ns {
+        ns if ns >= 1e6 => format!("{:.2} ms", ns / 1e6),
+        ns if ns >= 1e3 => format!("{:.2} µs", ns / 1e3),
+        ns => format!("{:.1} ns", ns),
+    }
+}
@@ -0,0 +1,126 @@
+//! Accessibility
+//!
+//! Entities can carry a semantic label, role and description (like
+//! RealityKit's `AccessibilityComponent`). The core turns the labelled
+//! entities into an accessibility tree and sends it to the shell, which
+//! exposes it to screen readers. Unlabelled entities are skipped; their
+//! labelled descendants attach to the nearest labelled ancestor.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{AccessibilityRole, Entity, ModelEntity, MeshResource, SimpleMaterial};
+//!
+//! let mut table = Entity::new().accessibility_label("Workbench");
+//! table.add_child(
+//!     ModelEntity::new(MeshResource::generate_box(0.1), SimpleMaterial::new())
+//!         .accessibility_label("Power switch")
+//!         .accessibility_role(AccessibilityRole::Button)
+//!         .accessibility_description("Turns the lamp on and off"),
+//! );
+//! content.add(table);
+//! ```
+//!
+//! The shell's `AccessibilityPreferences` arrive with its `Init` event (and
+//! `AccessibilityChanged` later). The framework's own behaviors respect them:
+//! bookmark teleports cut instead of fading with `reduced_motion`, and
+//! bookmark markers turn opaque with `high_contrast`.
+
+use crate::EntityKind;
+use fastn_protocol::*;
+
+/// Accessibility information of an entity.
+///
+/// Equivalent to RealityKit's `AccessibilityComponent`. An entity is
+/// exposed to assistive technology only if it has a label.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessibilityComponent {
+    pub label: Option<String>,
+    /// Defaults to `Group` for plain entities and `Image` for models
+    pub role: Option<AccessibilityRole>,
+    pub description: Option<String>,
+}
+
+/// The accessibility tree of the scene.
+#[derive(Debug, Default)]
+pub struct AccessibilityTree {
+    /// Nodes for the app's entities
+    content: Vec<AccessibilityNode>,
+    /// Nodes for things the framework adds (bookmark markers)
+    framework: Vec<AccessibilityNode>,
+}
+
+impl AccessibilityTree {
+    /// Build the tree from the scene's entities.
+    pub fn new(entities: &[EntityKind]) -> Self {
+        let mut content = vec![];
+        for entity in entities {
+            collect_nodes(entity, None, &mut content);
+        }
+        Self {
+            content,
+            framework: vec![],
+        }
+    }
+
+    /// All nodes in reading order.
+    pub fn nodes(&self) -> impl Iterator<Item = &AccessibilityNode> {
+        self.content.iter().chain(&self.framework)
+    }
+
+    /// Commands to run at startup: the initial tree, if there is one.
+    pub fn init_commands(&self) -> Vec<Command> {
+        match self.nodes().next() {
+            Some(_) => vec![self.set_tree_command()],
+            None => vec![],
+        }
+    }
+
+    /// Replace the framework's nodes, returning the updated tree if they
+    /// changed.
+    pub fn set_framework_nodes(&mut self, nodes: Vec<AccessibilityNode>) -> Option<Command> {
+        if nodes == self.framework {
+            return None;
+        }
+        self.framework = nodes;
+        Some(self.set_tree_command())
+    }
+
+    fn set_tree_command(&self) -> Command {
+        Command::Accessibility(AccessibilityCommand::SetTree {
+            nodes: self.nodes().cloned().collect(),
+        })
+    }
+}
+
+/// Have the screen reader speak a message once it is idle.
+pub fn announce(message: impl Into<String>) -> Command {
+    Command::Accessibility(AccessibilityCommand::Announce {
+        message: message.into(),
+        politeness: Politeness::Polite,
+    })
+}
+
+fn collect_nodes(entity: &EntityKind, parent: Option<&str>, nodes: &mut Vec<AccessibilityNode>) {
+    let accessibility = entity.accessibility();
+    let parent = match &accessibility.label {
+        Some(label) => {
+            let default_role = match entity {
+                EntityKind::Entity(_) => AccessibilityRole::Group,
+                _ => AccessibilityRole::Image,
+            };
+            nodes.push(AccessibilityNode {
+                volume_id: entity.id().to_string(),
+                parent: parent.map(str::to_string),
+                role: accessibility.role.unwrap_or(default_role),
+                label: label.clone(),
+                description: accessibility.description.clone(),
+            });
+            Some(entity.id())
+        }
+        None => parent,
+    };
+    for child in entity.children() {
+        collect_nodes(child, parent, nodes);
+    }
+}
@@ -0,0 +1,477 @@
+//! Declarative transform animations
+//!
+//! Entities animate with a fluent API, like RealityKit's
+//! `entity.move(to:relativeTo:duration:timingFunction:)`:
+//!
+//! ```rust,ignore
+//! use fastn::{Animation, Easing, ModelEntity, MeshResource, SimpleMaterial};
+//!
+//! let cube = ModelEntity::new(MeshResource::generate_box(0.5), SimpleMaterial::new())
+//!     .position(0.0, 0.0, -2.0)
+//!     .animate_position([0.0, 1.0, -2.0], 1.0)
+//!     .animate(
+//!         Animation::position([0.0, 0.0, -2.0])
+//!             .duration(0.5)
+//!             .easing(Easing::EaseIn)
+//!             .on_complete(|animator, id| animator.animate(id, Animation::spin([0.0, 1.0, 0.0]).turns(1))),
+//!     );
+//! let fan = ModelEntity::new(MeshResource::generate_box(0.2), SimpleMaterial::new()).spin([0.0, 0.0, 1.0], 0.5);
+//! ```
+//!
+//! An entity's animations play one after another, starting once the shell
+//! reports its volume ready. Each one compiles to a `SceneCommand::SetTransform`
+//! with an `AnimateTransform`; the shell interpolates the transform and
+//! reports the end with `SceneEvent::VolumeAnimationComplete`, which runs the
+//! animation's `on_complete` callback and starts the next one. Spins are
+//! chains of third turns, since a full turn would interpolate to where it
+//! started.
+//!
+//! Shells without `FEATURE_TRANSFORM_ANIMATION` never report completions, so
+//! only the first animation of each entity plays there.
+
+use crate::EntityKind;
+use crate::entity::Placement;
+use fastn_protocol::*;
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::TAU;
+use std::rc::Rc;
+
+/// Duration of animations that don't set one (for spins: of one turn)
+pub const DEFAULT_ANIMATION_SECS: f32 = 1.0;
+
+/// Spins advance a third of a turn per `SetTransform`
+const SPIN_STEPS_PER_TURN: u32 = 3;
+
+/// What an animation changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Position([f32; 3]),
+    Rotation([f32; 4]),
+    Scale([f32; 3]),
+    /// Turns around a world axis, forever if `turns` is None
+    Spin { axis: [f32; 3], turns: Option<u32> },
+}
+
+/// Called with the animator and the entity's ID when an animation finishes
+type Callback = Rc<dyn Fn(&mut Animator, &str)>;
+
+/// A transform animation of one entity.
+///
+/// Built with `Animation::position`, `rotation`, `scale` or `spin` and added
+/// with the entities' `animate` methods.
+#[derive(Clone)]
+pub struct Animation {
+    target: Target,
+    duration_secs: f32,
+    easing: Easing,
+    on_complete: Option<Callback>,
+}
+
+impl std::fmt::Debug for Animation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Animation")
+            .field("target", &self.target)
+            .field("duration_secs", &self.duration_secs)
+            .field("easing", &self.easing)
+            .field("on_complete", &self.on_complete.is_some())
+            .finish()
+    }
+}
+
+impl Animation {
+    fn new(target: Target) -> Self {
+        Self {
+            target,
+            duration_secs: DEFAULT_ANIMATION_SECS,
+            easing: Easing::EaseInOut,
+            on_complete: None,
+        }
+    }
+
+    /// Move to a position in the parent's coordinate space.
+    pub fn position(to: [f32; 3]) -> Self {
+        Self::new(Target::Position(to))
+    }
+
+    /// Turn to an orientation (quaternion).
+    pub fn rotation(to: [f32; 4]) -> Self {
+        Self::new(Target::Rotation(to))
+    }
+
+    /// Grow or shrink to a scale.
+    pub fn scale(to: [f32; 3]) -> Self {
+        Self::new(Target::Scale(to))
+    }
+
+    /// Turn around an axis at a steady speed, one turn per `duration`, until
+    /// stopped (or for the number of `turns`). Spins are always linear.
+    pub fn spin(axis: [f32; 3]) -> Self {
+        Self::new(Target::Spin { axis, turns: None }).easing(Easing::Linear)
+    }
+
+    /// Seconds the animation takes (for spins: seconds per turn).
+    pub fn duration(mut self, secs: f32) -> Self {
+        self.duration_secs = secs.max(0.0);
+        self
+    }
+
+    /// Timing curve (default `EaseInOut`). Ignored by spins.
+    pub fn easing(mut self, easing: Easing) -> Self {
+        if !matches!(self.target, Target::Spin { .. }) {
+            self.easing = easing;
+        }
+        self
+    }
+
+    /// Stop a spin after this many turns (at least one). No effect on other
+    /// animations.
+    pub fn turns(mut self, count: u32) -> Self {
+        if let Target::Spin { ref mut turns, .. } = self.target {
+            *turns = Some(count.max(1));
+        }
+        self
+    }
+
+    /// Run `callback` when the animation finishes, e.g. to start another one.
+    pub fn on_complete(mut self, callback: impl Fn(&mut Animator, &str) + 'static) -> Self {
+        self.on_complete = Some(Rc::new(callback));
+        self
+    }
+
+    /// `SetTransform` steps the animation takes, None for endless spins
+    fn steps(&self) -> Option<u32> {
+        match self.target {
+            Target::Spin { turns, .. } => turns.map(|turns| turns * SPIN_STEPS_PER_TURN),
+            _ => Some(1),
+        }
+    }
+
+    /// Transform after one step from `from`, and that step's duration
+    fn step(&self, from: &Transform) -> (Transform, f32) {
+        let mut to = from.clone();
+        match self.target {
+            Target::Position(position) => to.position = position,
+            Target::Rotation(rotation) => to.rotation = rotation,
+            Target::Scale(scale) => to.scale = scale,
+            Target::Spin { axis, .. } => {
+                let step = axis_angle(axis, TAU / SPIN_STEPS_PER_TURN as f32);
+                to.rotation = normalize(multiply(step, from.rotation));
+                return (to, self.duration_secs / SPIN_STEPS_PER_TURN as f32);
+            }
+        }
+        (to, self.duration_secs)
+    }
+}
+
+/// Starts and stops animations from `on_complete` callbacks.
+#[derive(Debug, Default)]
+pub struct Animator {
+    started: Vec<(String, Animation)>,
+    stopped: Vec<String>,
+}
+
+impl Animator {
+    /// Queue an animation for an entity, after the ones it already has.
+    pub fn animate(&mut self, entity_id: &str, animation: Animation) {
+        self.started.push((entity_id.to_string(), animation));
+    }
+
+    /// Drop an entity's queued animations and end a spin. The animation in
+    /// progress (for spins: the current third of a turn) still finishes.
+    pub fn stop(&mut self, entity_id: &str) {
+        self.stopped.push(entity_id.to_string());
+    }
+}
+
+/// An animation in progress
+#[derive(Debug)]
+struct Running {
+    animation: Animation,
+    /// Steps after the current one, None for endless spins
+    steps_left: Option<u32>,
+}
+
+#[derive(Debug)]
+struct EntityAnimations {
+    /// Transform the entity will have when the running animation ends,
+    /// relative to `parent`
+    transform: Transform,
+    /// The entity whose volume this one's is attached to
+    parent: Option<String>,
+    queue: VecDeque<Animation>,
+    running: Option<Running>,
+    /// Whether the shell has the entity's volume
+    ready: bool,
+}
+
+/// Plays the entities' animations and runs their callbacks.
+#[derive(Debug, Default)]
+pub struct Animations {
+    entities: HashMap<String, EntityAnimations>,
+    /// Animation ID -> entity ID, for animations in progress
+    running: HashMap<String, String>,
+    /// Whether the shell reports volumes and completions, None until its
+    /// first event
+    supported: Option<bool>,
+    next_id: u64,
+}
+
+impl Animations {
+    /// Collect the animations of the scene's entities.
+    pub fn new(entities: &[EntityKind]) -> Self {
+        let mut animations = Self::default();
+        for entity in entities {
+            entity.visit_volumes(&Placement::default(), &mut |entity, at| {
+                animations.entities.insert(
+                    entity.id().to_string(),
+                    EntityAnimations {
+                        transform: at.local.clone(),
+                        parent: at.parent.map(str::to_string),
+                        queue: entity.animations().iter().cloned().collect(),
+                        running: None,
+                        ready: false,
+                    },
+                );
+            });
+        }
+        animations
+    }
+
+    /// Process an event: start animations once volumes are ready and
+    /// advance them as they complete.
+    pub fn handle_event(&mut self, event: &Event) -> Vec<Command> {
+        let mut commands = vec![];
+        if self.supported.is_none() {
+            let supported = match event {
+                Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                    init.features.iter().any(|f| f == FEATURE_TRANSFORM_ANIMATION)
+                }
+                // Shells that skip Init (like the native one) may still
+                // report their volumes
+                Event::Scene(SceneEvent::VolumeReady { .. }) => true,
+                _ => false,
+            };
+            self.supported = Some(supported);
+            if !supported {
+                let animated = self.entities.values().filter(|e| !e.queue.is_empty()).count();
+                if animated > 0 && matches!(event, Event::Lifecycle(LifecycleEvent::Init(_))) {
+                    commands.push(Command::Debug(DebugCommand::Log {
+                        level: LogLevel::Warn,
+                        message: format!(
+                            "Shell has no transform animation, {} entities only play their first animation",
+                            animated
+                        ),
+                    }));
+                }
+                // No VolumeReady events will come: assume the volumes exist
+                let ids: Vec<String> = self.entities.keys().cloned().collect();
+                for id in ids {
+                    commands.extend(self.set_ready(&id));
+                }
+            }
+        }
+
+        match event {
+            Event::Scene(SceneEvent::VolumeReady { volume_id }) => commands.extend(self.set_ready(volume_id)),
+            Event::Scene(SceneEvent::VolumeAnimationComplete { animation_id, .. }) => {
+                commands.extend(self.complete(animation_id));
+            }
+            _ => {}
+        }
+        commands
+    }
+
+    fn set_ready(&mut self, entity_id: &str) -> Vec<Command> {
+        let Some(entity) = self.entities.get_mut(entity_id) else {
+            return vec![];
+        };
+        entity.ready = true;
+        self.start_next(entity_id).into_iter().collect()
+    }
+
+    /// Start the entity's next queued animation if it is idle
+    fn start_next(&mut self, entity_id: &str) -> Option<Command> {
+        let entity = self.entities.get_mut(entity_id)?;
+        if !entity.ready || entity.running.is_some() {
+            return None;
+        }
+        let animation = entity.queue.pop_front()?;
+        let steps_left = animation.steps().map(|steps| steps.saturating_sub(1));
+        self.step(entity_id, animation, steps_left)
+    }
+
+    /// Send the next step of an animation
+    fn step(&mut self, entity_id: &str, animation: Animation, steps_left: Option<u32>) -> Option<Command> {
+        self.next_id += 1;
+        let animation_id = format!("anim-{}", self.next_id);
+        let entity = self.entities.get_mut(entity_id)?;
+        let (transform, duration_secs) = animation.step(&entity.transform);
+        entity.transform = transform.clone();
+        let easing = animation.easing;
+        entity.running = Some(Running { animation, steps_left });
+        self.running.insert(animation_id.clone(), entity_id.to_string());
+
+        Some(Command::Scene(SceneCommand::SetTransform(SetTransformData {
+            volume_id: entity_id.to_string(),
+            transform,
+            animate: Some(AnimateTransform {
+                duration_ms: (duration_secs * 1000.0).round() as u32,
+                easing,
+                animation_id: Some(animation_id),
+            }),
+        })))
+    }
+
+    /// Continue or finish the animation that just completed a step
+    fn complete(&mut self, animation_id: &str) -> Vec<Command> {
+        let Some(entity_id) = self.running.remove(animation_id) else {
+            return vec![];
+        };
+        let Some(running) = self.entities.get_mut(&entity_id).and_then(|e| e.running.take()) else {
+            return vec![];
+        };
+
+        if running.steps_left != Some(0) {
+            let steps_left = running.steps_left.map(|steps| steps - 1);
+            return self.step(&entity_id, running.animation, steps_left).into_iter().collect();
+        }
+
+        let mut commands = vec![];
+        if let Some(callback) = &running.animation.on_complete {
+            let mut animator = Animator::default();
+            callback(&mut animator, &entity_id);
+            commands.extend(self.apply(animator));
+        }
+        commands.extend(self.start_next(&entity_id));
+        commands
+    }
+
+    /// The entity's transform once its running animation (if any) ends,
+    /// relative to its parent
+    pub(crate) fn transform(&self, entity_id: &str) -> Option<&Transform> {
+        self.entities.get(entity_id).map(|entity| &entity.transform)
+    }
+
+    /// The entity's transform in the scene, through its parents
+    pub(crate) fn world_transform(&self, entity_id: &str) -> Option<Transform> {
+        let entity = self.entities.get(entity_id)?;
+        match self.parent_transform(entity_id) {
+            Some(parent) => Some(compose(&parent, &entity.transform)),
+            None => Some(entity.transform.clone()),
+        }
+    }
+
+    /// The scene transform of the entity's parent, None at the top level
+    pub(crate) fn parent_transform(&self, entity_id: &str) -> Option<Transform> {
+        let parent = self.entities.get(entity_id)?.parent.as_deref()?;
+        self.world_transform(parent)
+    }
+
+    /// Record a transform set without an animation (like a drag), so later
+    /// animations start from it
+    pub(crate) fn set_transform(&mut self, entity_id: &str, transform: Transform) {
+        if let Some(entity) = self.entities.get_mut(entity_id) {
+            entity.transform = transform;
+        }
+    }
+
+    /// Apply what a callback started and stopped
+    pub(crate) fn apply(&mut self, animator: Animator) -> Vec<Command> {
+        let mut commands = vec![];
+        for entity_id in animator.stopped {
+            if let Some(entity) = self.entities.get_mut(&entity_id) {
+                entity.queue.clear();
+                if let Some(running) = &mut entity.running {
+                    running.steps_left = Some(0);
+                }
+            }
+        }
+        for (entity_id, animation) in animator.started {
+            match self.entities.get_mut(&entity_id) {
+                Some(entity) => {
+                    entity.queue.push_back(animation);
+                    commands.extend(self.start_next(&entity_id));
+                }
+                None => commands.push(Command::Debug(DebugCommand::Log {
+                    level: LogLevel::Warn,
+                    message: format!("Cannot animate {}: no such volume", entity_id),
+                })),
+            }
+        }
+        commands
+    }
+}
+
+/// Quaternion for a rotation by `angle` radians around `axis`
+pub(crate) fn axis_angle(axis: [f32; 3], angle: f32) -> [f32; 4] {
+    let length = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+    if length == 0.0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    let (sin, cos) = (angle / 2.0).sin_cos();
+    let s = sin / length;
+    [axis[0] * s, axis[1] * s, axis[2] * s, cos]
+}
+
+/// Quaternion product `a * b` (rotate by `b`, then by `a`)
+pub(crate) fn multiply(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    let [ax, ay, az, aw] = a;
+    let [bx, by, bz, bw] = b;
+    [
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+        aw * bw - ax * bx - ay * by - az * bz,
+    ]
+}
+
+/// `local` placed in the space of `parent`. Positions are exact; with a
+/// non-uniform parent scale a rotated child would shear, which a transform
+/// can't hold, so its scale is only multiplied through.
+pub(crate) fn compose(parent: &Transform, local: &Transform) -> Transform {
+    let scaled = [0, 1, 2].map(|i| parent.scale[i] * local.position[i]);
+    let offset = rotate(parent.rotation, scaled);
+    Transform {
+        position: [0, 1, 2].map(|i| parent.position[i] + offset[i]),
+        rotation: normalize(multiply(parent.rotation, local.rotation)),
+        scale: [0, 1, 2].map(|i| parent.scale[i] * local.scale[i]),
+    }
+}
+
+/// A scene point in the space of `frame`: the inverse of `compose` for
+/// positions
+pub(crate) fn point_to_local(frame: &Transform, point: [f32; 3]) -> [f32; 3] {
+    let offset = [0, 1, 2].map(|i| point[i] - frame.position[i]);
+    direction_to_local(frame, offset)
+}
+
+/// A scene direction in the space of `frame`, not normalized
+pub(crate) fn direction_to_local(frame: &Transform, direction: [f32; 3]) -> [f32; 3] {
+    let [x, y, z, w] = frame.rotation;
+    let unrotated = rotate([-x, -y, -z, w], direction);
+    [0, 1, 2].map(|i| match frame.scale[i].abs() > f32::EPSILON {
+        true => unrotated[i] / frame.scale[i],
+        false => 0.0,
+    })
+}
+
+/// Rotate `v` by the unit quaternion `q`
+fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let u = [q[0], q[1], q[2]];
+    let t = cross(u, v).map(|c| 2.0 * c);
+    let ut = cross(u, t);
+    [0, 1, 2].map(|i| v[i] + q[3] * t[i] + ut[i])
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+/// Keeps chained spin steps from drifting off unit length
+pub(crate) fn normalize(q: [f32; 4]) -> [f32; 4] {
+    let length = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    match length > 0.0 {
+        true => q.map(|c| c / length),
+        false => [0.0, 0.0, 0.0, 1.0],
+    }
+}
@@ -0,0 +1,50 @@
+//! Stateful apps
+//!
+//! A plain `#[fastn::app]` function only builds the initial scene. Apps that
+//! keep state across events implement `App` instead and put the attribute on
+//! the impl block:
+//!
+//! ```rust,ignore
+//! use fastn::{App, Command, Event, KeyboardEvent, InputEvent, RealityViewContent, announce};
+//!
+//! #[derive(Default)]
+//! struct Score {
+//!     points: u32,
+//! }
+//!
+//! #[fastn::app]
+//! impl App for Score {
+//!     fn init(&mut self, content: &mut RealityViewContent) {
+//!         // add entities, register callbacks
+//!     }
+//!
+//!     fn update(&mut self, event: &Event) -> Vec<Command> {
+//!         match event {
+//!             Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyDown(key))) if key.code == "Space" => {
+//!                 self.points += 1;
+//!                 vec![announce(format!("{} points", self.points))]
+//!             }
+//!             _ => vec![],
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! The macro creates the app with `Default::default()` and generates the
+//! same WASM exports as for a function.
+
+use crate::{Command, Event, RealityViewContent};
+
+/// An app that keeps state across events.
+pub trait App: 'static {
+    /// Build the initial scene, like a `#[fastn::app]` function.
+    fn init(&mut self, content: &mut RealityViewContent);
+
+    /// React to an event from the shell. Runs after the framework's own
+    /// handling and the content's callbacks; the returned commands are sent
+    /// after theirs.
+    fn update(&mut self, event: &Event) -> Vec<Command> {
+        let _ = event;
+        vec![]
+    }
+}
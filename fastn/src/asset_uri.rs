@@ -0,0 +1,243 @@
+//! Asset URIs
+//!
+//! Asset references are URIs so they mean the same thing on every shell (see
+//! `AssetScheme` for the built-in schemes). Bare paths like `"robot.glb"` are
+//! shorthand for `bundle://robot.glb`.
+//!
+//! Apps can register resolvers for their own schemes, which rewrite a URI
+//! into another one until a built-in scheme is reached:
+//!
+//! ```rust,ignore
+//! content.register_asset_resolver("cdn", |uri: &AssetUri| {
+//!     AssetUri::parse(&format!("https://cdn.example.com/models/{}", uri.location()))
+//! });
+//! content.add(Entity::load("cdn://robot.glb"));
+//! ```
+//!
+//! URIs are validated when commands are emitted; invalid or unresolvable
+//! references are reported with a debug log instead of reaching the shell.
+
+use fastn_protocol::*;
+use std::collections::HashMap;
+
+/// Resolution stops after this many rewrites (guards against cycles)
+const MAX_RESOLVE_DEPTH: usize = 8;
+
+/// Why an asset reference was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetUriError {
+    Empty,
+    /// `scheme://` with nothing usable after it
+    Invalid { uri: String, reason: String },
+    /// No built-in scheme or registered resolver matches
+    UnknownScheme(String),
+    /// A resolver failed, or resolution didn't reach a built-in scheme
+    Unresolved { uri: String, reason: String },
+}
+
+impl std::fmt::Display for AssetUriError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetUriError::Empty => write!(f, "empty asset reference"),
+            AssetUriError::Invalid { uri, reason } => write!(f, "invalid asset URI {}: {}", uri, reason),
+            AssetUriError::UnknownScheme(scheme) => write!(f, "unknown asset scheme: {}", scheme),
+            AssetUriError::Unresolved { uri, reason } => write!(f, "cannot resolve {}: {}", uri, reason),
+        }
+    }
+}
+
+impl std::error::Error for AssetUriError {}
+
+/// A parsed asset reference: `<scheme>://<location>`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AssetUri {
+    scheme: String,
+    location: String,
+}
+
+impl AssetUri {
+    /// Parse an asset reference. Bare paths become `bundle://` URIs.
+    ///
+    /// Built-in schemes are validated; custom schemes are accepted as-is and
+    /// checked when resolved.
+    pub fn parse(reference: &str) -> Result<AssetUri, AssetUriError> {
+        if reference.is_empty() {
+            return Err(AssetUriError::Empty);
+        }
+
+        let (scheme, location) = match reference.split_once("://") {
+            Some((scheme, location)) if is_scheme_name(scheme) => (scheme.to_ascii_lowercase(), location),
+            _ => ("bundle".to_string(), reference.trim_start_matches("./")),
+        };
+
+        let uri = AssetUri {
+            scheme,
+            location: location.to_string(),
+        };
+        if let Some(scheme) = uri.builtin_scheme() {
+            uri.validate(scheme)?;
+        }
+        Ok(uri)
+    }
+
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Everything after `://`
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    /// The built-in scheme, `None` for app-registered schemes
+    pub fn builtin_scheme(&self) -> Option<AssetScheme> {
+        AssetScheme::from_name(&self.scheme)
+    }
+
+    fn invalid(&self, reason: &str) -> AssetUriError {
+        AssetUriError::Invalid {
+            uri: self.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn validate(&self, scheme: AssetScheme) -> Result<(), AssetUriError> {
+        let location = &self.location;
+        match scheme {
+            AssetScheme::Bundle => {
+                if location.is_empty() || location.starts_with('/') {
+                    return Err(self.invalid("expected a path relative to the app bundle"));
+                }
+                if location.split('/').any(|part| part == "..") {
+                    return Err(self.invalid("path cannot leave the app bundle"));
+                }
+            }
+            AssetScheme::File => {
+                if !location.starts_with('/') {
+                    return Err(self.invalid("expected an absolute path (file:///...)"));
+                }
+            }
+            AssetScheme::Http | AssetScheme::Https => {
+                let host = location.split(['/', '?', '#']).next().unwrap_or_default();
+                if host.is_empty() {
+                    return Err(self.invalid("missing host"));
+                }
+            }
+            AssetScheme::Kosha => {
+                let mut parts = location.splitn(3, '/');
+                let hub = parts.next().unwrap_or_default();
+                let kosha = parts.next().unwrap_or_default();
+                let path = parts.next().unwrap_or_default();
+                if hub.is_empty() || kosha.is_empty() || path.is_empty() {
+                    return Err(self.invalid("expected kosha://<hub>/<kosha>/<path>"));
+                }
+                if path.split('/').any(|part| part == "..") {
+                    return Err(self.invalid("path cannot contain '..'"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for AssetUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}", self.scheme, self.location)
+    }
+}
+
+/// RFC 3986 scheme: a letter followed by letters, digits, `+`, `-` or `.`
+fn is_scheme_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Rewrites URIs of an app-defined scheme into other URIs.
+pub trait AssetResolver {
+    fn resolve(&self, uri: &AssetUri) -> Result<AssetUri, AssetUriError>;
+}
+
+impl<F> AssetResolver for F
+where
+    F: Fn(&AssetUri) -> Result<AssetUri, AssetUriError>,
+{
+    fn resolve(&self, uri: &AssetUri) -> Result<AssetUri, AssetUriError> {
+        self(uri)
+    }
+}
+
+/// Registry of app-defined asset schemes.
+#[derive(Default)]
+pub struct AssetResolvers {
+    resolvers: HashMap<String, Box<dyn AssetResolver>>,
+}
+
+impl std::fmt::Debug for AssetResolvers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.resolvers.keys()).finish()
+    }
+}
+
+impl AssetResolvers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a resolver for `scheme`. Built-in schemes can't be overridden.
+    pub fn register(&mut self, scheme: &str, resolver: impl AssetResolver + 'static) -> Result<(), AssetUriError> {
+        let scheme = scheme.to_ascii_lowercase();
+        if !is_scheme_name(&scheme) {
+            return Err(AssetUriError::Invalid {
+                uri: format!("{}://", scheme),
+                reason: "invalid scheme name".to_string(),
+            });
+        }
+        if AssetScheme::from_name(&scheme).is_some() {
+            return Err(AssetUriError::Invalid {
+                uri: format!("{}://", scheme),
+                reason: "built-in schemes cannot be overridden".to_string(),
+            });
+        }
+        self.resolvers.insert(scheme, Box::new(resolver));
+        Ok(())
+    }
+
+    /// Parse a reference and resolve it down to a built-in scheme.
+    pub fn resolve(&self, reference: &str) -> Result<AssetUri, AssetUriError> {
+        let mut uri = AssetUri::parse(reference)?;
+        for _ in 0..MAX_RESOLVE_DEPTH {
+            if uri.builtin_scheme().is_some() {
+                return Ok(uri);
+            }
+            let resolver = self
+                .resolvers
+                .get(uri.scheme())
+                .ok_or_else(|| AssetUriError::UnknownScheme(uri.scheme().to_string()))?;
+            let next = resolver.resolve(&uri)?;
+            // Resolvers may build URIs by hand; validate what they return
+            uri = AssetUri::parse(&next.to_string())?;
+        }
+        Err(AssetUriError::Unresolved {
+            uri: reference.to_string(),
+            reason: format!("more than {} resolver steps", MAX_RESOLVE_DEPTH),
+        })
+    }
+}
+
+/// Asset schemes a shell reported in its `InitEvent` features.
+///
+/// Shells that don't report any schemes are assumed to support only
+/// `bundle://`, which every shell has always handled.
+pub fn supported_schemes(features: &[String]) -> Vec<AssetScheme> {
+    let schemes: Vec<AssetScheme> = features
+        .iter()
+        .filter_map(|f| f.strip_prefix(ASSET_SCHEME_FEATURE_PREFIX))
+        .filter_map(AssetScheme::from_name)
+        .collect();
+    if schemes.is_empty() {
+        vec![AssetScheme::Bundle]
+    } else {
+        schemes
+    }
+}
@@ -0,0 +1,219 @@
+//! Texture atlases and sprite sheets
+//!
+//! An atlas is one texture holding many small images (UI icons, particle
+//! frames, sprites) as named regions. Materials reference a region by name
+//! and the core turns it into a `MaterialOverride::uv_rect`, so everything
+//! drawn from an atlas shares a single texture:
+//!
+//! ```rust,ignore
+//! use fastn::{MeshResource, ModelEntity, SimpleMaterial, TextureAtlas};
+//!
+//! let ui = TextureAtlas::new("ui", "ui.png", 256, 256)
+//!     .region("play", 0, 0, 64, 64)
+//!     .sprite_sheet("coin", [0, 64], [32, 32], 8, 8); // coin/0 .. coin/7
+//! content.add_atlas(ui);
+//!
+//! let button = ModelEntity::new(MeshResource::generate_plane(0.2, 0.2), SimpleMaterial::new().atlas_region("ui", "play"));
+//! content.add(button);
+//! ```
+//!
+//! Atlases can also be described in JSON, e.g. the `<name>.atlas.json` the
+//! CLI writes when it packs the images in `atlases/<name>/` at build time:
+//!
+//! ```json
+//! { "image": "ui.atlas.png", "width": 256, "height": 256, "regions": { "play": [0, 0, 64, 64] } }
+//! ```
+//!
+//! Regions are `[x, y, width, height]` in pixels from the top left corner.
+
+use crate::{AssetCommand, AssetUri, CachePolicy, Command, CreateTextureData, MaterialCommand, Priority, TextureSource};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Why an atlas or a reference to one was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtlasError {
+    /// The JSON description doesn't parse
+    Invalid(String),
+    /// A region reaches past the atlas image
+    RegionOutOfBounds { atlas: String, region: String },
+    UnknownAtlas(String),
+    UnknownRegion { atlas: String, region: String },
+}
+
+impl std::fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasError::Invalid(reason) => write!(f, "invalid atlas description: {}", reason),
+            AtlasError::RegionOutOfBounds { atlas, region } => {
+                write!(f, "region {} is outside atlas {}", region, atlas)
+            }
+            AtlasError::UnknownAtlas(atlas) => write!(f, "unknown texture atlas: {}", atlas),
+            AtlasError::UnknownRegion { atlas, region } => write!(f, "atlas {} has no region {}", atlas, region),
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+/// JSON description of an atlas
+#[derive(Deserialize)]
+struct AtlasFile {
+    image: String,
+    width: u32,
+    height: u32,
+    regions: BTreeMap<String, [u32; 4]>,
+}
+
+/// A texture with named regions.
+#[derive(Debug, Clone)]
+pub struct TextureAtlas {
+    id: String,
+    image: String,
+    width: u32,
+    height: u32,
+    regions: BTreeMap<String, [u32; 4]>,
+}
+
+impl TextureAtlas {
+    /// Create an atlas from an image asset reference and its size in pixels.
+    pub fn new(id: impl Into<String>, image: impl Into<String>, width: u32, height: u32) -> Self {
+        Self {
+            id: id.into(),
+            image: image.into(),
+            width,
+            height,
+            regions: BTreeMap::new(),
+        }
+    }
+
+    /// Create an atlas from its JSON description, e.g.
+    /// `include_str!("../assets/ui.atlas.json")`.
+    pub fn from_json(id: impl Into<String>, json: &str) -> Result<Self, AtlasError> {
+        let file: AtlasFile = serde_json::from_str(json).map_err(|e| AtlasError::Invalid(e.to_string()))?;
+        let atlas = Self {
+            id: id.into(),
+            image: file.image,
+            width: file.width,
+            height: file.height,
+            regions: file.regions,
+        };
+        for (name, rect) in &atlas.regions {
+            atlas.check_bounds(name, *rect)?;
+        }
+        Ok(atlas)
+    }
+
+    /// Name a region, in pixels from the top left corner.
+    pub fn region(mut self, name: impl Into<String>, x: u32, y: u32, width: u32, height: u32) -> Self {
+        self.regions.insert(name.into(), [x, y, width, height]);
+        self
+    }
+
+    /// Name the frames of a sprite sheet whose first frame is at `origin`:
+    /// `count` frames of `frame` (width, height) pixels, `columns` to a row,
+    /// named `<name>/0`, `<name>/1`, ...
+    pub fn sprite_sheet(mut self, name: &str, origin: [u32; 2], frame: [u32; 2], columns: u32, count: u32) -> Self {
+        let columns = columns.max(1);
+        for index in 0..count {
+            let rect = [
+                origin[0] + (index % columns) * frame[0],
+                origin[1] + (index / columns) * frame[1],
+                frame[0],
+                frame[1],
+            ];
+            self.regions.insert(format!("{}/{}", name, index), rect);
+        }
+        self
+    }
+
+    /// Get the atlas ID, which is also its texture ID.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Get the image asset reference.
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    /// Region names, sorted
+    pub fn regions(&self) -> impl Iterator<Item = &str> {
+        self.regions.keys().map(String::as_str)
+    }
+
+    /// Texture coordinates of a region: `[u, v, width, height]` in 0-1.
+    pub fn uv_rect(&self, region: &str) -> Result<[f32; 4], AtlasError> {
+        let rect = *self.regions.get(region).ok_or_else(|| AtlasError::UnknownRegion {
+            atlas: self.id.clone(),
+            region: region.to_string(),
+        })?;
+        self.check_bounds(region, rect)?;
+        let (width, height) = (self.width as f32, self.height as f32);
+        Ok([
+            rect[0] as f32 / width,
+            rect[1] as f32 / height,
+            rect[2] as f32 / width,
+            rect[3] as f32 / height,
+        ])
+    }
+
+    fn check_bounds(&self, region: &str, [x, y, width, height]: [u32; 4]) -> Result<(), AtlasError> {
+        let inside = x.checked_add(width).is_some_and(|right| right <= self.width)
+            && y.checked_add(height).is_some_and(|bottom| bottom <= self.height)
+            && width > 0
+            && height > 0;
+        match inside {
+            true => Ok(()),
+            false => Err(AtlasError::RegionOutOfBounds {
+                atlas: self.id.clone(),
+                region: region.to_string(),
+            }),
+        }
+    }
+
+    /// Load the image (from its resolved URI) and create the texture
+    pub(crate) fn to_commands(&self, uri: &AssetUri) -> Vec<Command> {
+        let asset_id = format!("atlas:{}", self.id);
+        vec![
+            Command::Asset(AssetCommand::Load {
+                asset_id: asset_id.clone(),
+                path: uri.to_string(),
+                priority: Priority::Normal,
+                cache: CachePolicy::Default,
+            }),
+            Command::Material(MaterialCommand::CreateTexture(CreateTextureData {
+                texture_id: self.id.clone(),
+                source: TextureSource::Asset { asset_id },
+            })),
+        ]
+    }
+}
+
+/// The atlases of a scene, by ID.
+#[derive(Debug, Clone, Default)]
+pub struct TextureAtlases {
+    atlases: BTreeMap<String, TextureAtlas>,
+}
+
+impl TextureAtlases {
+    /// Add an atlas, replacing any with the same ID.
+    pub fn add(&mut self, atlas: TextureAtlas) {
+        self.atlases.insert(atlas.id.clone(), atlas);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TextureAtlas> {
+        self.atlases.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TextureAtlas> {
+        self.atlases.values()
+    }
+
+    /// Texture coordinates of a region in one of the atlases.
+    pub fn uv_rect(&self, atlas: &str, region: &str) -> Result<[f32; 4], AtlasError> {
+        self.get(atlas)
+            .ok_or_else(|| AtlasError::UnknownAtlas(atlas.to_string()))?
+            .uv_rect(region)
+    }
+}
@@ -0,0 +1,560 @@
+//! Spatial audio
+//!
+//! Sounds are placed in the scene as `AudioSource`s, at a fixed point or on
+//! an entity. The core does the acoustics, so every shell sounds the same:
+//! each frame it works out how loud each source is at the listener (a
+//! distance rolloff curve), whether scene geometry is in the way (a ray from
+//! the listener to the source, tested against the entities' shapes; each
+//! occluder turns the source down and muffles it with a low-pass filter) and
+//! where the source is relative to the listener's head. Shells with
+//! `FEATURE_SPATIAL_AUDIO` play the sounds with those parameters.
+//!
+//! Sources are added to the content, or to an entity with `with_audio`, and
+//! start playing once the shell is ready unless they are `paused()`.
+//! Callbacks start and stop them with `EventContext::play_audio` and
+//! `stop_audio`.
+//!
+//! The listener is the camera, or the head during XR sessions. Entity
+//! positions are those the core knows, so a source on an animated entity
+//! moves when each animation step ends. Only generated solids occlude:
+//! loaded models have no shape the core knows, and text is too thin.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{AudioSource, MeshResource, ModelEntity, RealityViewContent, Rolloff, SimpleMaterial};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     content.add(ModelEntity::with_id(
+//!         "wall",
+//!         MeshResource::generate_box_with_dimensions(4.0, 2.5, 0.2),
+//!         SimpleMaterial::new(),
+//!     ).position(0.0, 1.25, -3.0));
+//!     // Muffled until the user walks around the wall
+//!     content.add_audio_source(AudioSource::new("fountain", "bundle://water.ogg").position(0.0, 0.5, -6.0));
+//!     // A radio heard across the room, following its entity around
+//!     content.add(ModelEntity::with_id("radio", MeshResource::generate_box(0.2), SimpleMaterial::new()));
+//!     content.add_audio_source(
+//!         AudioSource::new("music", "bundle://music.ogg")
+//!             .attach_to("radio")
+//!             .rolloff(Rolloff::Linear)
+//!             .distances(0.5, 8.0),
+//!     );
+//! }
+//! ```
+
+use crate::animation::Animations;
+use crate::camera::CameraController;
+use crate::entity::EntityKind;
+use crate::mesh::MeshResource;
+use fastn_protocol::*;
+use std::collections::{HashMap, HashSet};
+
+/// Gain left per occluder between the listener and a source (about -8 dB)
+const OCCLUDED_GAIN: f32 = 0.4;
+
+/// Low-pass cutoff behind one, two, and three or more occluders
+const OCCLUDED_LOW_PASS_HZ: [f32; 3] = [4000.0, 1200.0, 500.0];
+
+/// Smallest gain change worth sending
+const GAIN_EPSILON: f32 = 0.005;
+
+/// Smallest direction change worth sending (about one degree)
+const DIRECTION_EPSILON: f32 = 0.017;
+
+/// How a source fades with distance. The curves are those of WebAudio's
+/// `PannerNode` (and OpenAL's clamped models): between `ref_distance` and
+/// `max_distance` the gain follows the curve, closer it is full and farther
+/// it stays where the curve ends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rolloff {
+    /// `ref / (ref + factor * (d - ref))`, like sound in the open
+    #[default]
+    Inverse,
+    /// `1 - factor * (d - ref) / (max - ref)`, silent at `max_distance` for
+    /// factor 1
+    Linear,
+    /// `(d / ref) ^ -factor`
+    Exponential,
+    /// No fading, for narration or music that should follow the user
+    None,
+}
+
+/// A sound playing in the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioSource {
+    pub id: String,
+    /// Asset path or URI of the sound file
+    pub path: String,
+    /// Position in the scene, or offset from the entity's position
+    pub position: [f32; 3],
+    /// Entity the source follows
+    pub entity_id: Option<String>,
+    /// The source's own level, 0 to 1
+    pub volume: f32,
+    pub looping: bool,
+    /// Whether the source starts playing as soon as the shell is ready
+    pub autoplay: bool,
+    pub rolloff: Rolloff,
+    pub ref_distance: f32,
+    pub max_distance: f32,
+    pub rolloff_factor: f32,
+    /// Whether scene geometry muffles the source
+    pub occlusion: bool,
+}
+
+impl AudioSource {
+    /// Create a looping source at the origin at full volume, fading with
+    /// `Rolloff::Inverse` from 1m to 20m.
+    pub fn new(id: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            path: path.into(),
+            position: [0.0, 0.0, 0.0],
+            entity_id: None,
+            volume: 1.0,
+            looping: true,
+            autoplay: true,
+            rolloff: Rolloff::Inverse,
+            ref_distance: 1.0,
+            max_distance: 20.0,
+            rolloff_factor: 1.0,
+            occlusion: true,
+        }
+    }
+
+    /// Set the position, or the offset from the entity the source is
+    /// attached to (builder style).
+    pub fn position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.position = [x, y, z];
+        self
+    }
+
+    /// Follow an entity; the entity doesn't occlude its own sound (builder
+    /// style).
+    pub fn attach_to(mut self, entity_id: impl Into<String>) -> Self {
+        self.entity_id = Some(entity_id.into());
+        self
+    }
+
+    /// Set the source's own level, 0 to 1 (builder style).
+    pub fn volume(mut self, volume: f32) -> Self {
+        self.volume = volume.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Play once instead of looping (builder style).
+    pub fn once(mut self) -> Self {
+        self.looping = false;
+        self
+    }
+
+    /// Don't start playing until `EventContext::play_audio` (builder style).
+    pub fn paused(mut self) -> Self {
+        self.autoplay = false;
+        self
+    }
+
+    /// Set the distance curve (builder style).
+    pub fn rolloff(mut self, rolloff: Rolloff) -> Self {
+        self.rolloff = rolloff;
+        self
+    }
+
+    /// Set where fading starts and ends, in meters (builder style).
+    pub fn distances(mut self, ref_distance: f32, max_distance: f32) -> Self {
+        self.ref_distance = ref_distance.max(0.01);
+        self.max_distance = max_distance.max(self.ref_distance);
+        self
+    }
+
+    /// Set how fast the curve falls, 1 by default (builder style).
+    pub fn rolloff_factor(mut self, factor: f32) -> Self {
+        self.rolloff_factor = factor.max(0.0);
+        self
+    }
+
+    /// Keep the source clear when geometry is in the way (builder style).
+    pub fn no_occlusion(mut self) -> Self {
+        self.occlusion = false;
+        self
+    }
+
+    /// Gain at `distance` meters from the listener, before occlusion and
+    /// the source's volume.
+    pub fn gain_at(&self, distance: f32) -> f32 {
+        let (reference, max, factor) = (self.ref_distance, self.max_distance, self.rolloff_factor);
+        let d = distance.clamp(reference, max);
+        let gain = match self.rolloff {
+            Rolloff::Inverse => reference / (reference + factor * (d - reference)),
+            Rolloff::Linear if max > reference => 1.0 - factor * (d - reference) / (max - reference),
+            Rolloff::Linear => 1.0,
+            Rolloff::Exponential => (d / reference).powf(-factor),
+            Rolloff::None => 1.0,
+        };
+        gain.clamp(0.0, 1.0)
+    }
+
+    fn to_create_command(&self) -> Command {
+        Command::Audio(AudioCommand::CreateSource(CreateAudioSourceData {
+            source_id: self.id.clone(),
+            sound_id: sound_id(&self.path),
+            volume: self.volume,
+            looping: self.looping,
+        }))
+    }
+}
+
+/// Sound ID for a file, shared by the sources playing it
+fn sound_id(path: &str) -> SoundId {
+    format!("sound:{}", path)
+}
+
+/// Shape of an occluding entity, in its local space
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    /// Half extents of a box centered on the origin
+    Box([f32; 3]),
+    Sphere(f32),
+}
+
+impl Shape {
+    /// None for meshes that don't block sound
+    fn of(mesh: &MeshResource) -> Option<Self> {
+        Some(match *mesh {
+            MeshResource::Box { size } => Shape::Box([size / 2.0; 3]),
+            MeshResource::BoxWithDimensions { width, height, depth } => {
+                Shape::Box([width / 2.0, height / 2.0, depth / 2.0])
+            }
+            MeshResource::Sphere { radius, .. } => Shape::Sphere(radius),
+            MeshResource::Plane { width, depth } => Shape::Box([width / 2.0, 0.0, depth / 2.0]),
+            // Close enough for muffling
+            MeshResource::Cylinder { radius, height, .. } | MeshResource::Cone { radius, height, .. } => {
+                Shape::Box([radius, height / 2.0, radius])
+            }
+            MeshResource::Torus { radius, tube_radius, .. } => {
+                Shape::Box([radius + tube_radius, tube_radius, radius + tube_radius])
+            }
+            MeshResource::Text { .. } => return None,
+        })
+    }
+
+    /// Where the segment `from + t * (to - from)` enters and leaves the
+    /// shape, if it meets it
+    fn crossing(&self, from: [f32; 3], to: [f32; 3]) -> Option<(f32, f32)> {
+        let direction = sub(to, from);
+        match *self {
+            Shape::Box(half) => {
+                let (mut enter, mut exit) = (f32::NEG_INFINITY, f32::INFINITY);
+                for i in 0..3 {
+                    if direction[i].abs() < f32::EPSILON {
+                        if from[i].abs() > half[i] {
+                            return None;
+                        }
+                        continue;
+                    }
+                    let a = (-half[i] - from[i]) / direction[i];
+                    let b = (half[i] - from[i]) / direction[i];
+                    enter = enter.max(a.min(b));
+                    exit = exit.min(a.max(b));
+                }
+                (enter <= exit).then_some((enter, exit))
+            }
+            Shape::Sphere(radius) => {
+                let a = dot(direction, direction);
+                let b = 2.0 * dot(from, direction);
+                let c = dot(from, from) - radius * radius;
+                let discriminant = b * b - 4.0 * a * c;
+                if a < f32::EPSILON || discriminant < 0.0 {
+                    return None;
+                }
+                let root = discriminant.sqrt();
+                Some(((-b - root) / (2.0 * a), (-b + root) / (2.0 * a)))
+            }
+        }
+    }
+}
+
+/// Where the listener is and which way it faces
+#[derive(Debug, Clone, Copy)]
+struct Listener {
+    position: [f32; 3],
+    forward: [f32; 3],
+    up: [f32; 3],
+}
+
+impl Listener {
+    /// `v` in the listener's frame: +X right, +Y up, -Z ahead
+    fn local(&self, v: [f32; 3]) -> [f32; 3] {
+        let right = normalize(cross(self.forward, self.up));
+        let up = cross(right, self.forward);
+        [dot(v, right), dot(v, up), -dot(v, self.forward)]
+    }
+}
+
+/// The sound sources of an app, mixed for the listener every frame.
+#[derive(Debug, Default)]
+pub struct AudioSources {
+    sources: Vec<AudioSource>,
+    /// Entities that block sound, with their shapes
+    occluders: Vec<(String, Shape)>,
+    /// Whether the shell plays spatial audio, known on `Init`
+    supported: bool,
+    /// Sources playing now
+    playing: HashSet<String>,
+    /// Whether an XR session is running (head poses are the listener)
+    xr_active: bool,
+    head: Option<Listener>,
+    /// Last mix sent per source
+    sent: HashMap<String, AudioMix>,
+}
+
+impl AudioSources {
+    /// Collect the sources, and the shapes of the entities that can occlude
+    /// them.
+    pub fn new(sources: Vec<AudioSource>, entities: &[EntityKind]) -> Self {
+        let mut audio = Self {
+            sources,
+            ..Default::default()
+        };
+        if audio.sources.iter().any(|s| s.occlusion) {
+            for entity in entities {
+                audio.collect_occluders(entity);
+            }
+        }
+        audio
+    }
+
+    fn collect_occluders(&mut self, entity: &EntityKind) {
+        if let EntityKind::ModelEntity(model) = entity
+            && let Some(shape) = Shape::of(model.mesh())
+        {
+            self.occluders.push((model.id().to_string(), shape));
+        }
+        for child in entity.children() {
+            self.collect_occluders(child);
+        }
+    }
+
+    pub fn list(&self) -> &[AudioSource] {
+        &self.sources
+    }
+
+    /// Process an event: load and start the sources on `Init` and mix the
+    /// playing ones every frame. Call after the camera and animations have
+    /// handled the event.
+    pub fn handle_event(&mut self, event: &Event, camera: &CameraController, animations: &Animations) -> Vec<Command> {
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => self.start(&init.features),
+            Event::Lifecycle(LifecycleEvent::Frame(_)) if self.supported => self.mix(camera, animations),
+            Event::Audio(AudioEvent::Ended { source_id }) => {
+                self.playing.remove(source_id);
+                vec![]
+            }
+            Event::Audio(AudioEvent::Error { sound_id, error }) => vec![Command::Debug(DebugCommand::Log {
+                level: LogLevel::Warn,
+                message: format!("Cannot play {}: {}", sound_id, error),
+            })],
+            Event::Xr(XrEvent::SessionChanged(data)) => {
+                self.xr_active = data.state == XrSessionState::Active;
+                self.head = None;
+                vec![]
+            }
+            Event::Xr(XrEvent::HeadPose(pose)) if self.xr_active => {
+                self.head = Some(Listener {
+                    position: pose.position,
+                    forward: rotate(pose.orientation, [0.0, 0.0, -1.0]),
+                    up: rotate(pose.orientation, [0.0, 1.0, 0.0]),
+                });
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Track the sources that the app's commands start and stop. Call with
+    /// every command the core sends.
+    pub fn observe(&mut self, commands: &[Command]) {
+        for command in commands {
+            match command {
+                Command::Audio(AudioCommand::Play { source_id }) => {
+                    self.playing.insert(source_id.clone());
+                }
+                Command::Audio(AudioCommand::Stop { source_id } | AudioCommand::DestroySource { source_id }) => {
+                    self.playing.remove(source_id);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn start(&mut self, features: &[String]) -> Vec<Command> {
+        self.supported = features.iter().any(|f| f == FEATURE_SPATIAL_AUDIO);
+        self.sent.clear();
+        self.playing.clear();
+        if self.sources.is_empty() {
+            return vec![];
+        }
+        if !self.supported {
+            return vec![Command::Debug(DebugCommand::Log {
+                level: LogLevel::Warn,
+                message: "Shell cannot play spatial audio; sounds are silent".to_string(),
+            })];
+        }
+
+        let mut paths = HashSet::new();
+        let mut commands: Vec<Command> = self
+            .sources
+            .iter()
+            .filter(|source| paths.insert(source.path.as_str()))
+            .map(|source| {
+                Command::Audio(AudioCommand::Load {
+                    sound_id: sound_id(&source.path),
+                    path: source.path.clone(),
+                })
+            })
+            .collect();
+        commands.extend(self.sources.iter().map(AudioSource::to_create_command));
+        commands.extend(self.sources.iter().filter(|source| source.autoplay).map(|source| {
+            Command::Audio(AudioCommand::Play {
+                source_id: source.id.clone(),
+            })
+        }));
+        self.observe(&commands);
+        commands
+    }
+
+    fn mix(&mut self, camera: &CameraController, animations: &Animations) -> Vec<Command> {
+        let listener = match self.head {
+            Some(head) if self.xr_active => head,
+            _ => Listener {
+                position: camera.position,
+                forward: camera.forward(),
+                up: [0.0, 1.0, 0.0],
+            },
+        };
+        let mut changed = vec![];
+        for source in self.sources.iter().filter(|source| self.playing.contains(&source.id)) {
+            let Some(mix) = self.mix_source(source, &listener, animations) else {
+                continue;
+            };
+            if self.sent.get(&source.id).is_some_and(|last| !differs(last, &mix)) {
+                continue;
+            }
+            self.sent.insert(source.id.clone(), mix.clone());
+            changed.push(mix);
+        }
+        if changed.is_empty() {
+            return vec![];
+        }
+        vec![Command::Audio(AudioCommand::Mix { sources: changed })]
+    }
+
+    /// None while the source's entity is unknown
+    fn mix_source(&self, source: &AudioSource, listener: &Listener, animations: &Animations) -> Option<AudioMix> {
+        let position = match &source.entity_id {
+            Some(entity_id) => {
+                let anchor = animations.world_transform(entity_id)?.position;
+                [0, 1, 2].map(|i| anchor[i] + source.position[i])
+            }
+            None => source.position,
+        };
+        let offset = sub(position, listener.position);
+        let distance = length(offset);
+        let direction = match distance > f32::EPSILON {
+            true => normalize(listener.local(offset)),
+            // On top of the listener: straight ahead
+            false => [0.0, 0.0, -1.0],
+        };
+
+        let occluders = match source.occlusion {
+            true => self.count_occluders(listener.position, position, source.entity_id.as_deref(), animations),
+            false => 0,
+        };
+        let gain = source.gain_at(distance) * OCCLUDED_GAIN.powi(occluders.min(3) as i32);
+        let low_pass_hz = occluders.checked_sub(1).map(|i| OCCLUDED_LOW_PASS_HZ[i.min(2)]);
+        Some(AudioMix {
+            source_id: source.id.clone(),
+            gain,
+            low_pass_hz,
+            direction,
+            distance,
+        })
+    }
+
+    /// Entities the straight path between listener and source passes
+    /// through. Shapes around either end (the room the user stands in)
+    /// don't count.
+    fn count_occluders(&self, from: [f32; 3], to: [f32; 3], own: Option<&str>, animations: &Animations) -> usize {
+        self.occluders
+            .iter()
+            .filter(|(entity_id, _)| Some(entity_id.as_str()) != own)
+            .filter(|(entity_id, shape)| {
+                let Some(transform) = animations.world_transform(entity_id) else {
+                    return false;
+                };
+                // Affine maps keep the segment parameter, so the crossing
+                // is found in the entity's space
+                let (Some(from), Some(to)) = (to_local(&transform, from), to_local(&transform, to)) else {
+                    return false;
+                };
+                matches!(shape.crossing(from, to), Some((enter, exit)) if enter > 0.0 && exit < 1.0)
+            })
+            .count()
+    }
+}
+
+/// Whether a new mix is far enough from the last one sent to be worth sending
+fn differs(last: &AudioMix, mix: &AudioMix) -> bool {
+    (last.gain - mix.gain).abs() > GAIN_EPSILON
+        || last.low_pass_hz != mix.low_pass_hz
+        || length(sub(last.direction, mix.direction)) > DIRECTION_EPSILON
+}
+
+/// A scene point in the space of an entity's volume, None for volumes
+/// scaled flat
+fn to_local(transform: &Transform, point: [f32; 3]) -> Option<[f32; 3]> {
+    if transform.scale.iter().any(|s| s.abs() < f32::EPSILON) {
+        return None;
+    }
+    let [x, y, z, w] = transform.rotation;
+    let unrotated = rotate([-x, -y, -z, w], sub(point, transform.position));
+    Some([0, 1, 2].map(|i| unrotated[i] / transform.scale[i]))
+}
+
+// ----------------------------------------------------------------------------
+// Vector helpers
+// ----------------------------------------------------------------------------
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(v: [f32; 3]) -> f32 {
+    dot(v, v).sqrt()
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = length(v);
+    match length > f32::EPSILON {
+        true => v.map(|c| c / length),
+        false => [0.0, 0.0, -1.0],
+    }
+}
+
+/// Rotate a vector by a unit quaternion [x, y, z, w]
+fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let u = [q[0], q[1], q[2]];
+    let t = cross(u, v).map(|c| 2.0 * c);
+    let ut = cross(u, t);
+    [v[0] + q[3] * t[0] + ut[0], v[1] + q[3] * t[1] + ut[1], v[2] + q[3] * t[2] + ut[2]]
+}
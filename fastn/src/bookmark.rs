@@ -0,0 +1,408 @@
+//! Spatial Bookmarks
+//!
+//! Named camera poses that can be saved, persisted via the storage API and
+//! teleported between with a fade transition.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{Bookmark, RealityViewContent};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     content.add_bookmark(Bookmark::new("Entrance").position(0.0, 1.6, 3.0));
+//!     content.add_bookmark(Bookmark::new("Balcony").position(4.0, 3.0, -2.0).look(3.14, -0.3));
+//! }
+//! ```
+//!
+//! # Default Gestures
+//!
+//! - `1`-`9`: teleport to the bookmark in that slot
+//! - `Ctrl+1`-`Ctrl+9`: save the current camera pose into that slot
+//!
+//! Markers are buttons in the accessibility tree; activating one teleports
+//! there. With `reduced_motion` teleports cut instead of fading, and with
+//! `high_contrast` markers are opaque yellow.
+
+use crate::accessibility::announce;
+use crate::camera::CameraController;
+use crate::{MeshResource, ModelEntity, SimpleMaterial};
+use fastn_protocol::*;
+use serde::{Deserialize, Serialize};
+
+/// Storage key under which bookmarks are persisted
+pub const BOOKMARKS_STORAGE_KEY: &str = "fastn.bookmarks";
+
+/// Total fade out + fade in duration for a teleport (seconds)
+const FADE_DURATION: f32 = 0.4;
+const FADE_COLOR: [f32; 3] = [0.0, 0.0, 0.0];
+
+/// Marker colors (RGBA), normally and with `high_contrast`
+const MARKER_COLOR: [f32; 4] = [0.3, 0.7, 1.0, 0.8];
+const MARKER_COLOR_HIGH_CONTRAST: [f32; 4] = [1.0, 0.85, 0.0, 1.0];
+
+/// Standing eye height, used to place markers on the floor below a bookmark
+const EYE_HEIGHT: f32 = 1.6;
+
+/// A named camera pose.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl Bookmark {
+    /// Create a bookmark at the default camera pose.
+    pub fn new(name: impl Into<String>) -> Self {
+        let camera = CameraController::new();
+        Self {
+            name: name.into(),
+            position: camera.position,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+        }
+    }
+
+    /// Capture the current pose of a camera.
+    pub fn from_camera(name: impl Into<String>, camera: &CameraController) -> Self {
+        Self {
+            name: name.into(),
+            position: camera.position,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+        }
+    }
+
+    /// Set the eye position (builder style).
+    pub fn position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.position = [x, y, z];
+        self
+    }
+
+    /// Set the view direction as yaw/pitch in radians (builder style).
+    pub fn look(mut self, yaw: f32, pitch: f32) -> Self {
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self
+    }
+
+    fn marker_id(&self) -> String {
+        format!("bookmark:{}", self.name)
+    }
+
+    fn marker_position(&self) -> [f32; 3] {
+        [self.position[0], self.position[1] - EYE_HEIGHT, self.position[2]]
+    }
+
+    /// Teleportation marker shown on the floor below the bookmark.
+    pub fn marker(&self) -> ModelEntity {
+        self.styled_marker(false)
+    }
+
+    fn styled_marker(&self, high_contrast: bool) -> ModelEntity {
+        let mut marker = ModelEntity::with_id(
+            self.marker_id(),
+            MeshResource::generate_cylinder(0.25, 0.02),
+            marker_material(high_contrast),
+        )
+        .accessibility_label(self.marker_label())
+        .accessibility_role(AccessibilityRole::Button);
+        marker.set_position(self.marker_position());
+        marker
+    }
+
+    fn marker_label(&self) -> String {
+        format!("Teleport to {}", self.name)
+    }
+
+    fn move_marker_command(&self) -> Command {
+        Command::Scene(SceneCommand::SetTransform(SetTransformData {
+            volume_id: self.marker_id(),
+            transform: Transform {
+                position: self.marker_position(),
+                ..Default::default()
+            },
+            animate: None,
+        }))
+    }
+}
+
+fn marker_material(high_contrast: bool) -> SimpleMaterial {
+    let [r, g, b, a] = match high_contrast {
+        true => MARKER_COLOR_HIGH_CONTRAST,
+        false => MARKER_COLOR,
+    };
+    SimpleMaterial::new().color_with_alpha(r, g, b, a)
+}
+
+struct Transition {
+    target: Bookmark,
+    elapsed: f32,
+    jumped: bool,
+}
+
+/// The set of bookmarks for an app, plus the in-flight teleport transition.
+#[derive(Default)]
+pub struct Bookmarks {
+    bookmarks: Vec<Bookmark>,
+    transition: Option<Transition>,
+    preferences: AccessibilityPreferences,
+}
+
+impl Bookmarks {
+    pub fn new(bookmarks: Vec<Bookmark>) -> Self {
+        Self {
+            bookmarks,
+            transition: None,
+            preferences: AccessibilityPreferences::default(),
+        }
+    }
+
+    /// All bookmarks, in slot order.
+    pub fn list(&self) -> &[Bookmark] {
+        &self.bookmarks
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Bookmark> {
+        self.bookmarks.iter().find(|b| b.name == name)
+    }
+
+    /// Whether a teleport fade is in progress.
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Quick menu: a floating row of markers, one per bookmark, in slot order.
+    pub fn menu_entity(&self) -> crate::Entity {
+        let mut menu = crate::Entity::with_id("bookmark-menu");
+        let width = self.bookmarks.len().saturating_sub(1) as f32 * 0.15;
+        for (i, bookmark) in self.bookmarks.iter().enumerate() {
+            let item = ModelEntity::with_id(
+                format!("bookmark-menu:{}", bookmark.name),
+                MeshResource::generate_box(0.1),
+                SimpleMaterial::new().color(0.3, 0.7, 1.0),
+            )
+            .position(i as f32 * 0.15 - width / 2.0, 0.0, 0.0);
+            menu.add_child(item);
+        }
+        menu
+    }
+
+    /// Accessibility tree nodes for the markers, in slot order.
+    pub fn accessibility_nodes(&self) -> Vec<AccessibilityNode> {
+        self.bookmarks
+            .iter()
+            .map(|b| AccessibilityNode {
+                volume_id: b.marker_id(),
+                parent: None,
+                role: AccessibilityRole::Button,
+                label: b.marker_label(),
+                description: None,
+            })
+            .collect()
+    }
+
+    /// Commands to run at startup: markers for known bookmarks and a request
+    /// for previously saved ones.
+    pub fn init_commands(&self) -> Vec<Command> {
+        let mut commands: Vec<Command> = self.bookmarks.iter().map(|b| self.marker_command(b)).collect();
+        commands.push(Command::Storage(StorageCommand::Get {
+            key: BOOKMARKS_STORAGE_KEY.to_string(),
+        }));
+        commands
+    }
+
+    /// Save (or replace) a bookmark and persist the set.
+    pub fn save(&mut self, bookmark: Bookmark) -> Vec<Command> {
+        let mut commands = vec![];
+        match self.bookmarks.iter_mut().find(|b| b.name == bookmark.name) {
+            Some(existing) => {
+                *existing = bookmark.clone();
+                commands.push(bookmark.move_marker_command());
+            }
+            None => {
+                commands.push(self.marker_command(&bookmark));
+                self.bookmarks.push(bookmark);
+            }
+        }
+        commands.push(self.persist_command());
+        commands
+    }
+
+    /// Remove a bookmark by name and persist the set.
+    pub fn remove(&mut self, name: &str) -> Vec<Command> {
+        let Some(index) = self.bookmarks.iter().position(|b| b.name == name) else {
+            return vec![];
+        };
+        let bookmark = self.bookmarks.remove(index);
+        vec![
+            Command::Scene(SceneCommand::DestroyVolume {
+                volume_id: bookmark.marker_id(),
+            }),
+            self.persist_command(),
+        ]
+    }
+
+    /// Start a fade transition to the named bookmark.
+    pub fn teleport(&mut self, name: &str) -> bool {
+        match self.get(name) {
+            Some(target) => {
+                self.transition = Some(Transition {
+                    target: target.clone(),
+                    elapsed: 0.0,
+                    jumped: false,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn marker_command(&self, bookmark: &Bookmark) -> Command {
+        bookmark.styled_marker(self.preferences.high_contrast).to_command()
+    }
+
+    /// Apply new accessibility preferences, restyling markers if needed.
+    fn set_preferences(&mut self, preferences: AccessibilityPreferences) -> Vec<Command> {
+        let restyle = preferences.high_contrast != self.preferences.high_contrast;
+        self.preferences = preferences;
+        if !restyle {
+            return vec![];
+        }
+        let material = marker_material(preferences.high_contrast).to_override();
+        self.bookmarks
+            .iter()
+            .map(|b| {
+                Command::Material(MaterialCommand::SetMaterial(SetMaterialData {
+                    volume_id: b.marker_id(),
+                    slot: None,
+                    material: material.clone(),
+                }))
+            })
+            .collect()
+    }
+
+    fn persist_command(&self) -> Command {
+        let value = serde_json::to_string(&self.bookmarks).unwrap_or_else(|_| "[]".to_string());
+        Command::Storage(StorageCommand::Set {
+            key: BOOKMARKS_STORAGE_KEY.to_string(),
+            value,
+        })
+    }
+
+    /// Process an event, moving the camera when a teleport reaches full fade.
+    pub fn handle_event(&mut self, event: &Event, camera: &mut CameraController) -> Vec<Command> {
+        match event {
+            Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyDown(data))) if !data.repeat => {
+                self.handle_key(data, camera)
+            }
+            Event::Lifecycle(LifecycleEvent::Frame(frame)) => self.handle_frame(frame.dt, camera),
+            Event::Lifecycle(LifecycleEvent::Init(init)) => self.set_preferences(init.accessibility),
+            Event::Lifecycle(LifecycleEvent::AccessibilityChanged(preferences)) => self.set_preferences(*preferences),
+            Event::Accessibility(AccessibilityEvent::Activate { volume_id }) => {
+                if let Some(name) = self.bookmarks.iter().find(|b| &b.marker_id() == volume_id).map(|b| b.name.clone()) {
+                    self.teleport(&name);
+                }
+                vec![]
+            }
+            Event::Storage(StorageEvent::Loaded {
+                key,
+                value: Some(value),
+            }) if key == BOOKMARKS_STORAGE_KEY => self.handle_loaded(value),
+            _ => vec![],
+        }
+    }
+
+    fn handle_key(&mut self, data: &KeyEventData, camera: &CameraController) -> Vec<Command> {
+        let Some(slot) = data
+            .code
+            .strip_prefix("Digit")
+            .and_then(|d| d.parse::<usize>().ok())
+            .filter(|d| (1..=9).contains(d))
+        else {
+            return vec![];
+        };
+
+        if data.ctrl {
+            let name = self
+                .bookmarks
+                .get(slot - 1)
+                .map(|b| b.name.clone())
+                .unwrap_or_else(|| format!("Bookmark {}", slot));
+            self.save(Bookmark::from_camera(name, camera))
+        } else {
+            if let Some(name) = self.bookmarks.get(slot - 1).map(|b| b.name.clone()) {
+                self.teleport(&name);
+            }
+            vec![]
+        }
+    }
+
+    fn handle_frame(&mut self, dt: f32, camera: &mut CameraController) -> Vec<Command> {
+        let Some(transition) = &mut self.transition else {
+            return vec![];
+        };
+
+        // Cut straight to the target instead of fading
+        if self.preferences.reduced_motion {
+            let target = &transition.target;
+            camera.set_pose(target.position, target.yaw, target.pitch);
+            let arrived = announce(format!("Teleported to {}", target.name));
+            self.transition = None;
+            return vec![arrived];
+        }
+
+        transition.elapsed += dt;
+        let mut commands = vec![];
+        let half = FADE_DURATION / 2.0;
+        let alpha = if transition.elapsed < half {
+            transition.elapsed / half
+        } else {
+            if !transition.jumped {
+                let target = &transition.target;
+                camera.set_pose(target.position, target.yaw, target.pitch);
+                commands.push(announce(format!("Teleported to {}", target.name)));
+                transition.jumped = true;
+            }
+            (1.0 - (transition.elapsed - half) / half).max(0.0)
+        };
+
+        if transition.elapsed >= FADE_DURATION {
+            self.transition = None;
+        }
+
+        commands.push(Command::Environment(EnvironmentCommand::SetFade(FadeData {
+            color: [FADE_COLOR[0], FADE_COLOR[1], FADE_COLOR[2], alpha],
+        })));
+        commands
+    }
+
+    fn handle_loaded(&mut self, value: &str) -> Vec<Command> {
+        let saved: Vec<Bookmark> = match serde_json::from_str(value) {
+            Ok(saved) => saved,
+            Err(e) => {
+                return vec![Command::Debug(DebugCommand::Log {
+                    level: LogLevel::Warn,
+                    message: format!("Ignoring invalid saved bookmarks: {}", e),
+                })];
+            }
+        };
+
+        let mut commands = vec![];
+        for bookmark in saved {
+            match self.bookmarks.iter_mut().find(|b| b.name == bookmark.name) {
+                Some(existing) => {
+                    commands.push(bookmark.move_marker_command());
+                    *existing = bookmark;
+                }
+                None => {
+                    commands.push(self.marker_command(&bookmark));
+                    self.bookmarks.push(bookmark);
+                }
+            }
+        }
+        commands
+    }
+}
@@ -2,20 +2,71 @@
 //!
 //! Default camera controller that handles keyboard, mouse, and gamepad input
 //! to move the camera around in the scene.
-
+//!
+//! In first-person mode (the default) the move keys walk and fly, the turn
+//! keys and right-dragging the mouse look around, and the wheel moves along
+//! the view. In orbit mode the camera circles a point it looks at, like a
+//! turntable: the move and turn keys and right-dragging orbit it, and the
+//! wheel and the forward/back keys zoom. In both, dragging with the middle
+//! button pans.
+//!
+//! Apps pick the mode and remap or drop keys with `CameraControls`, set with
+//! `content.set_camera_controls`; `CameraControls::disabled()` turns the
+//! controller off, for apps that move the camera themselves. Callbacks frame
+//! entities with `ctx.zoom_to_fit`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{CameraAction, CameraControls, CameraMode, RealityViewContent};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     content.set_camera_controls(
+//!         CameraControls::default()
+//!             .mode(CameraMode::Orbit { target: [0.0, 0.5, 0.0] })
+//!             .bind(CameraAction::Reset, &["KeyR"])
+//!             .unbind(CameraAction::Slow),
+//!     );
+//!     content.on_key_down(|ctx, key| {
+//!         if key.code == "KeyF" {
+//!             ctx.zoom_to_fit(&["statue"]);
+//!         }
+//!     });
+//! }
+//! ```
+
+use crate::raycast::Scene;
 use fastn_protocol::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 /// Default camera settings
 const DEFAULT_CAMERA_POSITION: [f32; 3] = [0.0, 1.6, 3.0];
 const DEFAULT_CAMERA_YAW: f32 = -std::f32::consts::FRAC_PI_2; // Facing -Z (towards origin)
 const DEFAULT_CAMERA_PITCH: f32 = -0.5; // Looking slightly down
+const FOV_DEGREES: f32 = 45.0;          // Vertical field of view
 
 /// Camera movement speeds
 const MOVE_SPEED: f32 = 2.0;        // Units per second
 const MOVE_SPEED_SLOW: f32 = 0.2;   // Units per second (with shift)
 const ROTATE_SPEED: f32 = 0.15;     // Radians per second (fine-grained for keyboard)
 
+/// Orbit mode speeds
+const ORBIT_SPEED: f32 = 1.0;       // Radians per second (keys)
+const ZOOM_SPEED: f32 = 1.0;        // Distance grows e-fold per second (keys)
+const MIN_ORBIT_DISTANCE: f32 = 0.1;
+const MAX_ORBIT_DISTANCE: f32 = 90.0; // Inside the far plane
+
+/// Mouse settings
+const DRAG_SPEED: f32 = 0.005;      // Radians per pixel dragged
+const WHEEL_ZOOM: f32 = 0.002;      // Distance change per pixel scrolled, relative
+const WHEEL_MOVE: f32 = 0.01;       // Units per pixel scrolled (first person)
+const PAN_SPEED: f32 = 0.0015;      // Distance to the target per pixel dragged
+const PAN_DISTANCE: f32 = 3.0;      // Stands in for the distance in first person
+
+/// zoom_to_fit leaves this much room around the entities
+const FIT_MARGIN: f32 = 1.1;
+
 /// Gamepad settings
 const GAMEPAD_MOVE_SPEED: f32 = 3.0;      // Units per second (stick fully pushed)
 const GAMEPAD_ROTATE_SPEED: f32 = 2.0;    // Radians per second (stick fully pushed)
@@ -33,6 +84,129 @@ const AXIS_RIGHT_TRIGGER: usize = 5;
 const BTN_A: usize = 0;
 const BTN_LB: usize = 4;
 
+/// How the default controller moves the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    /// Walk and fly through the scene
+    FirstPerson,
+    /// Circle `target`, looking at it
+    Orbit { target: [f32; 3] },
+}
+
+/// Something a key does to the camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CameraAction {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    TurnLeft,
+    TurnRight,
+    LookUp,
+    LookDown,
+    /// Held for slow movement
+    Slow,
+    /// Back to the starting pose
+    Reset,
+}
+
+/// The default controller's mode, key bindings and input devices.
+///
+/// Keys are `KeyboardEvent` codes (`"KeyW"`, `"ArrowUp"`). `"Shift+<code>"`
+/// only counts with Shift held, and takes over from the plain key then: by
+/// default Shift+Up/Down arrow fly up and down instead of moving.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraControls {
+    pub(crate) enabled: bool,
+    pub(crate) mode: CameraMode,
+    keys: HashMap<CameraAction, Vec<String>>,
+    pub(crate) mouse: bool,
+    pub(crate) gamepad: bool,
+}
+
+impl Default for CameraControls {
+    fn default() -> Self {
+        let keys = [
+            (CameraAction::Forward, &["KeyW", "ArrowUp"][..]),
+            (CameraAction::Back, &["KeyS", "ArrowDown"]),
+            (CameraAction::Left, &["KeyA", "ArrowLeft"]),
+            (CameraAction::Right, &["KeyD", "ArrowRight"]),
+            (CameraAction::Up, &["KeyE", "Shift+ArrowUp"]),
+            (CameraAction::Down, &["KeyQ", "Shift+ArrowDown"]),
+            (CameraAction::TurnLeft, &["KeyJ"]),
+            (CameraAction::TurnRight, &["KeyL"]),
+            (CameraAction::LookUp, &["KeyI"]),
+            (CameraAction::LookDown, &["KeyK"]),
+            (CameraAction::Slow, &["ShiftLeft", "ShiftRight"]),
+            (CameraAction::Reset, &["Digit0"]),
+        ];
+        Self {
+            enabled: true,
+            mode: CameraMode::FirstPerson,
+            keys: keys
+                .into_iter()
+                .map(|(action, codes)| (action, codes.iter().map(|code| code.to_string()).collect()))
+                .collect(),
+            mouse: true,
+            gamepad: true,
+        }
+    }
+}
+
+impl CameraControls {
+    /// No default controller: input leaves the camera alone, for apps that
+    /// move it themselves. Bookmarks, portals and `zoom_to_fit` still do.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+
+    /// Move the camera in `mode` (builder style).
+    pub fn mode(mut self, mode: CameraMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Do `action` with these keys instead of its default ones (builder
+    /// style).
+    pub fn bind(mut self, action: CameraAction, keys: &[&str]) -> Self {
+        self.keys.insert(action, keys.iter().map(|key| key.to_string()).collect());
+        self
+    }
+
+    /// Leave `action` without keys (builder style).
+    pub fn unbind(mut self, action: CameraAction) -> Self {
+        self.keys.remove(&action);
+        self
+    }
+
+    /// Whether dragging and the wheel move the camera (builder style).
+    pub fn mouse(mut self, enabled: bool) -> Self {
+        self.mouse = enabled;
+        self
+    }
+
+    /// Whether gamepad sticks and triggers move the camera (builder style).
+    pub fn gamepad(mut self, enabled: bool) -> Self {
+        self.gamepad = enabled;
+        self
+    }
+
+    /// The keys bound to `action`
+    pub fn keys(&self, action: CameraAction) -> &[String] {
+        self.keys.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Whether some action is bound to Shift+`code`
+    fn has_shift_binding(&self, code: &str) -> bool {
+        self.keys.values().flatten().any(|key| key.strip_prefix("Shift+") == Some(code))
+    }
+}
+
 /// Camera controller that processes input events and produces camera commands
 pub struct CameraController {
     /// Camera position in world space
@@ -46,6 +220,14 @@ pub struct CameraController {
     /// Current gamepad state (axes and buttons)
     gamepad_axes: Vec<f32>,
     gamepad_buttons: Vec<(f32, bool)>,
+    /// Mode, key bindings and devices
+    controls: CameraControls,
+    /// How far ahead of the camera the point orbit mode circles is
+    orbit_distance: f32,
+    /// Mouse button being dragged with (right looks or orbits, middle pans)
+    drag: Option<MouseButton>,
+    /// Viewport width over height, for zoom_to_fit
+    aspect: f32,
     /// Whether camera state has changed and needs to emit a command
     dirty: bool,
 }
@@ -58,21 +240,58 @@ impl Default for CameraController {
 
 impl CameraController {
     pub fn new() -> Self {
-        Self {
+        Self::with_controls(CameraControls::default())
+    }
+
+    /// A controller moving the camera as `controls` say
+    pub fn with_controls(controls: CameraControls) -> Self {
+        let mut camera = Self {
             position: DEFAULT_CAMERA_POSITION,
             yaw: DEFAULT_CAMERA_YAW,
             pitch: DEFAULT_CAMERA_PITCH,
             pressed_keys: HashSet::new(),
             gamepad_axes: vec![0.0; 6],
             gamepad_buttons: vec![(0.0, false); 15],
+            controls: CameraControls::default(),
+            orbit_distance: PAN_DISTANCE,
+            drag: None,
+            aspect: 1.0,
             dirty: true, // Emit initial camera state
+        };
+        camera.set_controls(controls);
+        camera
+    }
+
+    pub fn controls(&self) -> &CameraControls {
+        &self.controls
+    }
+
+    /// Change mode, bindings or devices. Entering orbit mode turns the
+    /// camera to its target.
+    pub fn set_controls(&mut self, controls: CameraControls) {
+        if let CameraMode::Orbit { target } = controls.mode {
+            self.orbit_around(target);
         }
+        self.controls = controls;
+        self.pressed_keys.clear();
+        self.drag = None;
     }
 
     /// Process an input event and return any resulting commands
     pub fn handle_event(&mut self, event: &Event) -> Vec<Command> {
         match event {
-            Event::Input(input_event) => self.handle_input(input_event),
+            Event::Input(input_event) if self.controls.enabled => self.handle_input(input_event),
+            Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                self.aspect = init.viewport_width as f32 / init.viewport_height.max(1) as f32;
+                if let Some(camera) = &init.camera {
+                    self.look_at(camera.position, camera.target);
+                }
+                vec![]
+            }
+            Event::Lifecycle(LifecycleEvent::Resize(resize)) => {
+                self.aspect = resize.width as f32 / resize.height.max(1) as f32;
+                vec![]
+            }
             Event::Lifecycle(LifecycleEvent::Frame(frame)) => self.handle_frame(frame.dt),
             _ => vec![],
         }
@@ -92,8 +311,7 @@ impl CameraController {
             KeyboardEvent::KeyDown(data) => {
                 self.pressed_keys.insert(data.code.clone());
 
-                // Handle reset on '0' key
-                if data.code == "Digit0" {
+                if self.controls.keys(CameraAction::Reset).contains(&data.code) {
                     self.reset();
                     self.dirty = true;
                 }
@@ -106,15 +324,62 @@ impl CameraController {
         vec![]
     }
 
-    fn handle_mouse(&mut self, _event: &MouseEvent) -> Vec<Command> {
-        // TODO: Implement mouse look when dragging
+    fn handle_mouse(&mut self, event: &MouseEvent) -> Vec<Command> {
+        if !self.controls.mouse {
+            return vec![];
+        }
+        match event {
+            MouseEvent::Down(data) if matches!(data.button, MouseButton::Right | MouseButton::Middle) => {
+                self.drag = Some(data.button);
+            }
+            MouseEvent::Up(data) if self.drag == Some(data.button) => self.drag = None,
+            MouseEvent::Move(data) => match self.drag {
+                Some(MouseButton::Right) => match self.controls.mode {
+                    // Dragging turns the scene like a turntable
+                    CameraMode::Orbit { .. } => self.orbit(data.dx * DRAG_SPEED, -data.dy * DRAG_SPEED),
+                    CameraMode::FirstPerson => {
+                        let (yaw, pitch) = (self.yaw + data.dx * DRAG_SPEED, self.pitch - data.dy * DRAG_SPEED);
+                        self.set_pose(self.position, yaw, pitch);
+                    }
+                },
+                Some(_) => self.pan(data.dx, data.dy),
+                None => {}
+            },
+            // Positive deltas scroll down: away from the scene
+            MouseEvent::Wheel(data) if data.dy != 0.0 => match self.controls.mode {
+                CameraMode::Orbit { .. } => self.zoom((data.dy * WHEEL_ZOOM).exp()),
+                CameraMode::FirstPerson => {
+                    let forward = self.forward();
+                    let position = std::array::from_fn(|i| self.position[i] - forward[i] * data.dy * WHEEL_MOVE);
+                    self.set_pose(position, self.yaw, self.pitch);
+                }
+            },
+            MouseEvent::Disconnected { .. } => self.drag = None,
+            _ => {}
+        }
         vec![]
     }
 
+    /// Move the camera and its orbit target across the view, following a
+    /// drag of `dx`, `dy` pixels
+    fn pan(&mut self, dx: f32, dy: f32) {
+        let (right, up) = self.right_up();
+        let distance = match self.controls.mode {
+            CameraMode::Orbit { .. } => self.orbit_distance,
+            CameraMode::FirstPerson => PAN_DISTANCE,
+        };
+        let step = distance * PAN_SPEED;
+        let position = std::array::from_fn(|i| self.position[i] + (-right[i] * dx + up[i] * dy) * step);
+        self.set_pose(position, self.yaw, self.pitch);
+    }
+
     fn handle_gamepad(&mut self, event: &GamepadEvent) -> Vec<Command> {
+        if !self.controls.gamepad {
+            return vec![];
+        }
         match event {
+            // Shells send input when the state changes, store it for use in handle_frame
             GamepadEvent::Input(data) => {
-                // Store gamepad state for use in handle_frame
                 self.gamepad_axes = data.axes.clone();
                 self.gamepad_buttons = data.buttons.clone();
 
@@ -124,7 +389,12 @@ impl CameraController {
                     self.dirty = true;
                 }
             }
-            _ => {}
+            // Stop moving if the gamepad goes away with a stick pushed
+            GamepadEvent::Disconnected { .. } => {
+                self.gamepad_axes = vec![0.0; 6];
+                self.gamepad_buttons = vec![(0.0, false); 15];
+            }
+            GamepadEvent::Connected(_) => {}
         }
         vec![]
     }
@@ -151,55 +421,37 @@ impl CameraController {
         self.gamepad_buttons.get(index).map(|(_, pressed)| *pressed).unwrap_or(false)
     }
 
+    /// Whether a key bound to `action` is held
+    fn held(&self, action: CameraAction) -> bool {
+        let shift = self.pressed_keys.contains("ShiftLeft") || self.pressed_keys.contains("ShiftRight");
+        self.controls.keys(action).iter().any(|key| match key.strip_prefix("Shift+") {
+            Some(code) => shift && self.pressed_keys.contains(code),
+            None => self.pressed_keys.contains(key) && !(shift && self.controls.has_shift_binding(key)),
+        })
+    }
+
+    /// Whether a Shift+ binding is held, which moves at normal speed
+    fn shift_binding_held(&self) -> bool {
+        let shift = self.pressed_keys.contains("ShiftLeft") || self.pressed_keys.contains("ShiftRight");
+        shift && self.pressed_keys.iter().any(|code| self.controls.has_shift_binding(code))
+    }
+
+    /// -1, 0 or 1, for keys pulling both ways
+    fn key_axis(&self, negative: CameraAction, positive: CameraAction) -> f32 {
+        self.held(positive) as i32 as f32 - self.held(negative) as i32 as f32
+    }
+
     fn handle_frame(&mut self, dt: f32) -> Vec<Command> {
-        // Process held keys for movement
-        let mut dx = 0.0f32;
-        let mut dz = 0.0f32;
-        let mut dy = 0.0f32;
-        let mut dyaw = 0.0f32;
-        let mut dpitch = 0.0f32;
-
-        // Check for shift modifier (slow movement)
-        let shift_held = self.pressed_keys.contains("ShiftLeft")
-            || self.pressed_keys.contains("ShiftRight");
-
-        // Movement: WASD + QE + Arrow keys
-        // Arrow keys: Shift+Up/Down = fly up/down at normal speed
-        let arrow_up = self.pressed_keys.contains("ArrowUp");
-        let arrow_down = self.pressed_keys.contains("ArrowDown");
-
-        if self.pressed_keys.contains("KeyW") || (arrow_up && !shift_held) {
-            dz -= 1.0; // Forward
-        }
-        if self.pressed_keys.contains("KeyS") || (arrow_down && !shift_held) {
-            dz += 1.0; // Backward
-        }
-        if self.pressed_keys.contains("KeyA") || self.pressed_keys.contains("ArrowLeft") {
-            dx -= 1.0; // Left
-        }
-        if self.pressed_keys.contains("KeyD") || self.pressed_keys.contains("ArrowRight") {
-            dx += 1.0; // Right
-        }
-        if self.pressed_keys.contains("KeyQ") || (arrow_down && shift_held) {
-            dy -= 1.0; // Down
-        }
-        if self.pressed_keys.contains("KeyE") || (arrow_up && shift_held) {
-            dy += 1.0; // Up
-        }
+        // Process held keys for movement: dz is negative forward, and the
+        // turn keys yaw and pitch
+        let dx = self.key_axis(CameraAction::Left, CameraAction::Right);
+        let dz = self.key_axis(CameraAction::Forward, CameraAction::Back);
+        let dy = self.key_axis(CameraAction::Down, CameraAction::Up);
+        let dyaw = self.key_axis(CameraAction::TurnLeft, CameraAction::TurnRight);
+        let dpitch = self.key_axis(CameraAction::LookDown, CameraAction::LookUp);
 
-        // Rotation: IJKL
-        if self.pressed_keys.contains("KeyJ") {
-            dyaw -= 1.0; // Turn left
-        }
-        if self.pressed_keys.contains("KeyL") {
-            dyaw += 1.0; // Turn right
-        }
-        if self.pressed_keys.contains("KeyI") {
-            dpitch += 1.0; // Look up
-        }
-        if self.pressed_keys.contains("KeyK") {
-            dpitch -= 1.0; // Look down
-        }
+        // Shift+ bindings (Shift+Up/Down arrow) move at normal speed
+        let slow = self.held(CameraAction::Slow) && !self.shift_binding_held();
 
         // ======== Gamepad input ========
         // Left stick: movement (X = strafe, Y = forward/back)
@@ -217,6 +469,23 @@ impl CameraController {
         // LB for slow movement
         let gp_slow = self.get_button(BTN_LB);
 
+        if let CameraMode::Orbit { .. } = self.controls.mode {
+            // Sideways and turning keys orbit around, up and down go over and
+            // under, forward and back zoom; the sticks do the same
+            let speed = if slow { ORBIT_SPEED * 0.2 } else { ORBIT_SPEED } * dt;
+            let gp_speed = if gp_slow { ORBIT_SPEED * 0.2 } else { ORBIT_SPEED } * GAMEPAD_ROTATE_SPEED * dt;
+            let around = (-dx - dyaw) * speed + (-gp_left_x - gp_right_x) * gp_speed;
+            let over = (dpitch - dy) * speed + (-gp_right_y + gp_left_trigger - gp_right_trigger) * gp_speed;
+            let zoom = dz * ZOOM_SPEED * dt + gp_left_y * ZOOM_SPEED * GAMEPAD_ROTATE_SPEED * dt;
+            if around != 0.0 || over != 0.0 {
+                self.orbit(around, over);
+            }
+            if zoom != 0.0 {
+                self.zoom(zoom.exp());
+            }
+            return self.take_camera_command();
+        }
+
         // Apply gamepad movement (left stick)
         if gp_left_x != 0.0 || gp_left_y != 0.0 {
             // Forward direction (in XZ plane)
@@ -253,11 +522,7 @@ impl CameraController {
             self.dirty = true;
         }
 
-        // Select move speed: slow only for Shift+WASD, not for arrow keys
-        let using_arrows = arrow_up || arrow_down
-            || self.pressed_keys.contains("ArrowLeft")
-            || self.pressed_keys.contains("ArrowRight");
-        let move_speed = if shift_held && !using_arrows { MOVE_SPEED_SLOW } else { MOVE_SPEED };
+        let move_speed = if slow { MOVE_SPEED_SLOW } else { MOVE_SPEED };
 
         // Apply movement in camera's local space
         if dx != 0.0 || dz != 0.0 {
@@ -289,7 +554,11 @@ impl CameraController {
             self.dirty = true;
         }
 
-        // Emit camera command if changed
+        self.take_camera_command()
+    }
+
+    /// The camera command, if the camera changed since the last one
+    fn take_camera_command(&mut self) -> Vec<Command> {
         if self.dirty {
             self.dirty = false;
             vec![self.make_camera_command()]
@@ -298,20 +567,123 @@ impl CameraController {
         }
     }
 
-    /// Reset camera to default position and orientation
+    /// Reset camera to default position and orientation, or in orbit mode
+    /// to the default position looking at the target
     pub fn reset(&mut self) {
         self.position = DEFAULT_CAMERA_POSITION;
         self.yaw = DEFAULT_CAMERA_YAW;
         self.pitch = DEFAULT_CAMERA_PITCH;
+        if let CameraMode::Orbit { target } = self.controls.mode {
+            self.orbit_around(target);
+        }
     }
 
-    /// Calculate camera target from position, yaw, and pitch
-    fn calculate_target(&self) -> [f32; 3] {
-        let direction = [
+    /// The point orbit mode circles
+    pub fn orbit_target(&self) -> [f32; 3] {
+        let forward = self.forward();
+        std::array::from_fn(|i| self.position[i] + forward[i] * self.orbit_distance)
+    }
+
+    /// Turn the camera to `target` and circle it from where the camera is
+    fn orbit_around(&mut self, target: [f32; 3]) {
+        let offset: [f32; 3] = std::array::from_fn(|i| target[i] - self.position[i]);
+        let distance = (offset[0] * offset[0] + offset[1] * offset[1] + offset[2] * offset[2]).sqrt();
+        if distance > f32::EPSILON {
+            self.orbit_distance = distance.clamp(MIN_ORBIT_DISTANCE, MAX_ORBIT_DISTANCE);
+            self.look_at(self.position, target);
+        }
+    }
+
+    /// Circle the orbit target by `around` radians to the left and `over`
+    /// radians down (negative to go over the top)
+    fn orbit(&mut self, around: f32, over: f32) {
+        let target = self.orbit_target();
+        self.yaw += around;
+        self.pitch = (self.pitch + over).clamp(-1.4, 1.4);
+        self.place_before(target);
+    }
+
+    /// Move toward the orbit target, multiplying the distance by `factor`
+    fn zoom(&mut self, factor: f32) {
+        let target = self.orbit_target();
+        self.orbit_distance = (self.orbit_distance * factor).clamp(MIN_ORBIT_DISTANCE, MAX_ORBIT_DISTANCE);
+        self.place_before(target);
+    }
+
+    /// Put the camera the orbit distance before `target`, facing it
+    fn place_before(&mut self, target: [f32; 3]) {
+        let forward = self.forward();
+        self.position = std::array::from_fn(|i| target[i] - forward[i] * self.orbit_distance);
+        self.dirty = true;
+    }
+
+    /// Frame the entities: turn nowhere, but move back from the sphere
+    /// around them until it fills the view, and orbit its center. False,
+    /// leaving the camera, if the scene knows none of their shapes.
+    pub fn zoom_to_fit(&mut self, scene: &Scene, volume_ids: &[impl AsRef<str>]) -> bool {
+        let Some((center, radius)) = scene.bounding_sphere(volume_ids) else {
+            return false;
+        };
+        // The narrower of the two fields of view
+        let half_fov = (FOV_DEGREES.to_radians() / 2.0).tan();
+        let half_fov = half_fov.min(half_fov * self.aspect).atan();
+        let distance = (radius.max(MIN_ORBIT_DISTANCE) * FIT_MARGIN / half_fov.sin()).min(MAX_ORBIT_DISTANCE);
+        self.orbit_distance = distance;
+        self.place_before(center);
+        true
+    }
+
+    /// Move the camera to a new pose; the camera command is emitted on the next frame
+    pub fn set_pose(&mut self, position: [f32; 3], yaw: f32, pitch: f32) {
+        self.position = position;
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-1.4, 1.4);
+        self.dirty = true;
+    }
+
+    /// Move the camera to `position`, facing `target`
+    pub fn look_at(&mut self, position: [f32; 3], target: [f32; 3]) {
+        let direction = normalize([target[0] - position[0], target[1] - position[1], target[2] - position[2]]);
+        self.set_pose(position, direction[2].atan2(direction[0]), direction[1].clamp(-1.0, 1.0).asin());
+    }
+
+    /// Unit vector the camera looks along
+    pub(crate) fn forward(&self) -> [f32; 3] {
+        [
             self.yaw.cos() * self.pitch.cos(),
             self.pitch.sin(),
             self.yaw.sin() * self.pitch.cos(),
+        ]
+    }
+
+    /// Unit vectors to the right of and up from the view
+    fn right_up(&self) -> ([f32; 3], [f32; 3]) {
+        let forward = self.forward();
+        // right = forward x up (world up is +Y), up = right x forward
+        let right = normalize([-forward[2], 0.0, forward[0]]);
+        let up = [
+            right[1] * forward[2] - right[2] * forward[1],
+            right[2] * forward[0] - right[0] * forward[2],
+            right[0] * forward[1] - right[1] * forward[0],
         ];
+        (right, up)
+    }
+
+    /// Ray (origin, unit direction) from the camera through a point on a
+    /// `width` x `height` screen, in the coordinates of mouse and touch events
+    pub(crate) fn screen_ray(&self, x: f32, y: f32, width: f32, height: f32) -> ([f32; 3], [f32; 3]) {
+        let forward = self.forward();
+        let (right, up) = self.right_up();
+        let tan_half_fov = (FOV_DEGREES.to_radians() / 2.0).tan();
+        let ndc_x = (2.0 * x / width - 1.0) * tan_half_fov * (width / height);
+        let ndc_y = (1.0 - 2.0 * y / height) * tan_half_fov;
+        let direction = std::array::from_fn(|i| forward[i] + right[i] * ndc_x + up[i] * ndc_y);
+        (self.position, normalize(direction))
+    }
+
+    /// Calculate camera target from position, yaw, and pitch
+    fn calculate_target(&self) -> [f32; 3] {
+        let direction = self.forward();
         [
             self.position[0] + direction[0],
             self.position[1] + direction[1],
@@ -319,14 +691,22 @@ impl CameraController {
         ]
     }
 
-    fn make_camera_command(&self) -> Command {
+    pub(crate) fn make_camera_command(&self) -> Command {
         Command::Environment(EnvironmentCommand::SetCamera(CameraData {
             position: self.position,
             target: self.calculate_target(),
             up: [0.0, 1.0, 0.0],
-            fov_degrees: 45.0,
+            fov_degrees: FOV_DEGREES,
             near: 0.1,
             far: 100.0,
         }))
     }
 }
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    match length > 0.0 {
+        true => v.map(|c| c / length),
+        false => v,
+    }
+}
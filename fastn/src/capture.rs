@@ -0,0 +1,173 @@
+//! Photo and video capture
+//!
+//! AR apps let people keep what they see: `EventContext::capture_photo` and
+//! `capture_video` have the shell save the composited view (passthrough plus
+//! virtual content) to device storage, and `on_capture` reports where it
+//! went. A `Viewfinder` shows the same view on a panel in the scene, so
+//! people can frame the shot.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{RealityViewContent, Viewfinder};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     content.add_viewfinder(Viewfinder::new("viewfinder").position(0.3, 1.2, -0.6).yaw(-0.4));
+//!     content.on_tap("viewfinder", |ctx| ctx.capture_photo("moment"));
+//!     content.on_capture(|ctx, result| match result {
+//!         Ok(saved) => ctx.announce(format!("Saved {}", saved.path)),
+//!         Err(failed) => ctx.announce(format!("Could not capture: {}", failed.message)),
+//!     });
+//! }
+//! ```
+
+use fastn_protocol::*;
+
+/// A panel showing what the user sees, live.
+///
+/// The panel is a quad facing +Z at yaw 0, textured with a
+/// `MediaSource::Viewfinder` stream. Its volume, stream and texture are all
+/// named after the viewfinder's ID, so `on_tap` works with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Viewfinder {
+    pub id: String,
+    pub width: f32,
+    pub height: f32,
+    pub position: [f32; 3],
+    pub yaw: f32,
+    /// Pixel size of the stream
+    pub resolution: (u32, u32),
+}
+
+impl Viewfinder {
+    /// Create a 32cm x 18cm viewfinder at eye height in front of the origin,
+    /// streaming at 640x360.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            width: 0.32,
+            height: 0.18,
+            position: [0.0, 1.4, -0.8],
+            yaw: 0.0,
+            resolution: (640, 360),
+        }
+    }
+
+    /// Set the panel size in meters (builder style).
+    pub fn size(mut self, width: f32, height: f32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Set the panel center (builder style).
+    pub fn position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.position = [x, y, z];
+        self
+    }
+
+    /// Turn the panel around +Y, in radians (builder style).
+    pub fn yaw(mut self, yaw: f32) -> Self {
+        self.yaw = yaw;
+        self
+    }
+
+    /// Set the pixel size of the stream (builder style).
+    pub fn resolution(mut self, width: u32, height: u32) -> Self {
+        self.resolution = (width, height);
+        self
+    }
+
+    /// The stream, its texture and the panel showing it
+    pub fn to_commands(&self) -> Vec<Command> {
+        let (sin, cos) = (self.yaw / 2.0).sin_cos();
+        vec![
+            Command::Media(MediaCommand::CreateStream {
+                media_id: self.id.clone(),
+                source: MediaSource::Viewfinder {
+                    width: self.resolution.0,
+                    height: self.resolution.1,
+                },
+            }),
+            Command::Material(MaterialCommand::CreateTexture(CreateTextureData {
+                texture_id: self.id.clone(),
+                source: TextureSource::Media {
+                    media_id: self.id.clone(),
+                },
+            })),
+            Command::Scene(SceneCommand::CreateVolume(CreateVolumeData {
+                volume_id: self.id.clone(),
+                source: VolumeSource::Primitive(Primitive::Quad {
+                    width: self.width,
+                    height: self.height,
+                }),
+                transform: Transform {
+                    position: self.position,
+                    rotation: [0.0, sin, 0.0, cos],
+                    ..Default::default()
+                },
+                material: Some(MaterialOverride {
+                    color: Some([1.0, 1.0, 1.0, 1.0]),
+                    texture_id: Some(self.id.clone()),
+                    metallic: None,
+                    roughness: None,
+                    emissive: None,
+                    uv_rect: None,
+                }),
+                parent_id: None,
+            })),
+        ]
+    }
+}
+
+/// The viewfinders of an app.
+#[derive(Debug, Default)]
+pub struct Viewfinders {
+    viewfinders: Vec<Viewfinder>,
+}
+
+impl Viewfinders {
+    pub fn new(viewfinders: Vec<Viewfinder>) -> Self {
+        Self { viewfinders }
+    }
+
+    /// Commands to run at startup: a stream, texture and panel per viewfinder.
+    pub fn init_commands(&self) -> Vec<Command> {
+        self.viewfinders.iter().flat_map(Viewfinder::to_commands).collect()
+    }
+
+    /// Warn on `Init` when the shell can't fill the viewfinders.
+    pub fn handle_event(&self, event: &Event) -> Vec<Command> {
+        let Event::Lifecycle(LifecycleEvent::Init(init)) = event else {
+            return vec![];
+        };
+        if self.viewfinders.is_empty() || init.features.iter().any(|f| f == FEATURE_VIEWFINDER) {
+            return vec![];
+        }
+        vec![Command::Debug(DebugCommand::Log {
+            level: LogLevel::Warn,
+            message: "Shell cannot stream the view; viewfinders stay blank".to_string(),
+        })]
+    }
+}
+
+/// `MediaCommand::Capture` for a photo, with passthrough
+pub(crate) fn photo(capture_id: &str) -> Command {
+    Command::Media(MediaCommand::Capture(CaptureRequest {
+        capture_id: capture_id.to_string(),
+        kind: CaptureKind::Photo,
+        passthrough: true,
+    }))
+}
+
+/// `MediaCommand::Capture` for a video, with passthrough
+pub(crate) fn video(capture_id: &str, max_secs: Option<f32>) -> Command {
+    Command::Media(MediaCommand::Capture(CaptureRequest {
+        capture_id: capture_id.to_string(),
+        kind: CaptureKind::Video {
+            max_duration_ms: max_secs.map(|secs| (secs.max(0.0) * 1000.0) as u32),
+        },
+        passthrough: true,
+    }))
+}
@@ -0,0 +1,403 @@
+//! Coordinate system conventions
+//!
+//! Apps and the framework work right-handed, +Y up, in meters
+//! (`Conventions::CORE`). Shells built on engines with other conventions
+//! declare theirs in `InitEvent::conventions`, and every command leaving the
+//! core and every event entering it is converted here, so nothing else has
+//! to care.
+//!
+//! The startup commands are sent before `Init` arrives. Those that place
+//! things (volumes, portals, panels, the camera and lighting) are kept, and restated
+//! in the shell's conventions once `Init` says they differ.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::ShellConventions;
+//!
+//! let mut conventions = ShellConventions::new(&startup_commands);
+//! // ... for every event from the shell
+//! let mut commands = conventions.handle_event(&event);
+//! let event = conventions.event_from_shell(&event);
+//! // ... the core's own commands for the event
+//! let commands: Vec<_> = commands.into_iter().map(|c| conventions.command_to_shell(c)).collect();
+//! ```
+
+use fastn_protocol::*;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Converts commands and events between the core's conventions and the
+/// shell's.
+#[derive(Debug, Clone, Default)]
+pub struct ShellConventions {
+    conventions: Conventions,
+    /// Startup commands that place things, restated after `Init`
+    startup: Vec<Command>,
+    /// Volumes placed relative to a parent, whose transforms aren't
+    /// converted to the shell's unit: the parent's scale already is
+    children: HashSet<VolumeId>,
+}
+
+impl ShellConventions {
+    /// Keep the startup commands that need restating if the shell turns out
+    /// to use other conventions
+    pub fn new(startup: &[Command]) -> Self {
+        let startup: Vec<Command> = startup
+            .iter()
+            .filter(|c| {
+                matches!(
+                    c,
+                    Command::Scene(SceneCommand::CreateVolume(_) | SceneCommand::CreatePortal(_))
+                        | Command::Panel(PanelCommand::Create(_))
+                        | Command::Environment(EnvironmentCommand::SetCamera(_) | EnvironmentCommand::SetLighting(_))
+                )
+            })
+            .cloned()
+            .collect();
+        let children = startup
+            .iter()
+            .filter_map(|c| match c {
+                Command::Scene(SceneCommand::CreateVolume(data)) if data.parent_id.is_some() => {
+                    Some(data.volume_id.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        Self {
+            conventions: Conventions::CORE,
+            startup,
+            children,
+        }
+    }
+
+    /// The shell's conventions, `Conventions::CORE` until `Init`
+    pub fn conventions(&self) -> Conventions {
+        self.conventions
+    }
+
+    /// Whether the shell works in the core's conventions
+    pub fn is_core(&self) -> bool {
+        self.conventions == Conventions::CORE
+    }
+
+    /// Learn the shell's conventions from `Init` and warn about assets that
+    /// declare conflicting ones. Returned commands are in core conventions,
+    /// like every other command the core makes.
+    pub fn handle_event(&mut self, event: &Event) -> Vec<Command> {
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => self.set_conventions(init.conventions),
+            Event::Asset(AssetEvent::Loaded(data)) => match data.conventions {
+                Some(declared) if !same(&declared, &self.conventions) => vec![Command::Debug(DebugCommand::Log {
+                    level: LogLevel::Warn,
+                    message: format!(
+                        "Asset {} is {} but the shell is {}; it will look rotated, mirrored or scaled",
+                        data.path,
+                        describe(&declared),
+                        describe(&self.conventions)
+                    ),
+                })],
+                _ => vec![],
+            },
+            _ => vec![],
+        }
+    }
+
+    fn set_conventions(&mut self, conventions: Conventions) -> Vec<Command> {
+        let startup = std::mem::take(&mut self.startup);
+        if !(conventions.meters_per_unit.is_finite() && conventions.meters_per_unit > 0.0) {
+            return vec![Command::Debug(DebugCommand::Log {
+                level: LogLevel::Warn,
+                message: format!(
+                    "Shell declared {} meters per unit, using the core's conventions",
+                    conventions.meters_per_unit
+                ),
+            })];
+        }
+        self.conventions = conventions;
+        if self.is_core() {
+            return vec![];
+        }
+
+        // The shell placed these in the core's conventions; place them again
+        startup
+            .into_iter()
+            .flat_map(|command| match command {
+                Command::Scene(SceneCommand::CreateVolume(data)) => {
+                    vec![Command::Scene(SceneCommand::SetTransform(SetTransformData {
+                        volume_id: data.volume_id,
+                        transform: data.transform,
+                        animate: None,
+                    }))]
+                }
+                Command::Scene(SceneCommand::CreatePortal(data)) => vec![
+                    Command::Scene(SceneCommand::DestroyPortal {
+                        portal_id: data.portal_id.clone(),
+                    }),
+                    Command::Scene(SceneCommand::CreatePortal(data)),
+                ],
+                Command::Panel(PanelCommand::Create(data)) => vec![
+                    Command::Panel(PanelCommand::Destroy {
+                        panel_id: data.panel_id.clone(),
+                    }),
+                    Command::Panel(PanelCommand::Create(data)),
+                ],
+                command => vec![command],
+            })
+            .collect()
+    }
+
+    /// A command from the core, in the shell's conventions
+    pub fn command_to_shell(&self, command: Command) -> Command {
+        if self.is_core() {
+            return command;
+        }
+        let c = &self.conventions;
+        match command {
+            Command::Scene(SceneCommand::CreateVolume(mut data)) => {
+                data.transform = match data.parent_id {
+                    Some(_) => local_transform_to_shell(c, &data.transform),
+                    None => transform_to_shell(c, &data.transform),
+                };
+                Command::Scene(SceneCommand::CreateVolume(data))
+            }
+            Command::Scene(SceneCommand::SetTransform(mut data)) => {
+                data.transform = match self.children.contains(&data.volume_id) {
+                    true => local_transform_to_shell(c, &data.transform),
+                    false => transform_to_shell(c, &data.transform),
+                };
+                Command::Scene(SceneCommand::SetTransform(data))
+            }
+            Command::Scene(SceneCommand::CreatePortal(mut data)) => {
+                data.width /= c.meters_per_unit;
+                data.height /= c.meters_per_unit;
+                data.entrance = transform_to_shell(c, &data.entrance);
+                data.exit = transform_to_shell(c, &data.exit);
+                Command::Scene(SceneCommand::CreatePortal(data))
+            }
+            Command::Panel(PanelCommand::Create(mut data)) => {
+                // The same pixels on a panel measured in shell units
+                data.width /= c.meters_per_unit;
+                data.height /= c.meters_per_unit;
+                data.pixels_per_meter *= c.meters_per_unit;
+                data.transform = transform_to_shell(c, &data.transform);
+                Command::Panel(PanelCommand::Create(data))
+            }
+            Command::Panel(PanelCommand::SetTransform { panel_id, transform }) => {
+                Command::Panel(PanelCommand::SetTransform {
+                    panel_id,
+                    transform: transform_to_shell(c, &transform),
+                })
+            }
+            Command::Scene(SceneCommand::RequestHitTest(mut request)) => {
+                if let HitTestSource::Ray { origin, direction } = &mut request.source {
+                    *origin = point_to_shell(c, *origin);
+                    *direction = direction_to_shell(c, *direction);
+                }
+                Command::Scene(SceneCommand::RequestHitTest(request))
+            }
+            Command::Environment(EnvironmentCommand::SetCamera(mut camera)) => {
+                camera.position = point_to_shell(c, camera.position);
+                camera.target = point_to_shell(c, camera.target);
+                camera.up = direction_to_shell(c, camera.up);
+                camera.near /= c.meters_per_unit;
+                camera.far /= c.meters_per_unit;
+                Command::Environment(EnvironmentCommand::SetCamera(camera))
+            }
+            Command::Environment(EnvironmentCommand::SetLighting(mut lighting)) => {
+                if let Some(light) = &mut lighting.directional {
+                    light.direction = direction_to_shell(c, light.direction);
+                }
+                Command::Environment(EnvironmentCommand::SetLighting(lighting))
+            }
+            Command::Environment(EnvironmentCommand::CreateLight(mut light)) => {
+                light.position = point_to_shell(c, light.position);
+                light.range /= c.meters_per_unit;
+                Command::Environment(EnvironmentCommand::CreateLight(light))
+            }
+            Command::Environment(EnvironmentCommand::MoveLight { light_id, position }) => {
+                Command::Environment(EnvironmentCommand::MoveLight {
+                    light_id,
+                    position: point_to_shell(c, position),
+                })
+            }
+            Command::Audio(AudioCommand::Mix { mut sources }) => {
+                for mix in &mut sources {
+                    mix.direction = direction_to_shell(c, mix.direction);
+                    mix.distance /= c.meters_per_unit;
+                }
+                Command::Audio(AudioCommand::Mix { sources })
+            }
+            Command::Schedule(ScheduleCommand::Defer(mut deferred)) => {
+                deferred.command = Box::new(self.command_to_shell(*deferred.command));
+                Command::Schedule(ScheduleCommand::Defer(deferred))
+            }
+            command => command,
+        }
+    }
+
+    /// An event from the shell, in the core's conventions
+    pub fn event_from_shell<'a>(&self, event: &'a Event) -> Cow<'a, Event> {
+        if self.is_core() {
+            return Cow::Borrowed(event);
+        }
+        let c = &self.conventions;
+        let converted = match event {
+            // A camera restored by a reloading shell
+            Event::Lifecycle(LifecycleEvent::Init(init)) if init.camera.is_some() => {
+                let camera = init.camera.as_ref().map(|camera| CameraData {
+                    position: point_from_shell(c, camera.position),
+                    target: point_from_shell(c, camera.target),
+                    up: direction_from_shell(c, camera.up),
+                    near: camera.near * c.meters_per_unit,
+                    far: camera.far * c.meters_per_unit,
+                    ..camera.clone()
+                });
+                Event::Lifecycle(LifecycleEvent::Init(InitEvent { camera, ..init.clone() }))
+            }
+            Event::Xr(xr) => Event::Xr(match xr {
+                XrEvent::SessionChanged(session) if session.play_area.is_some() => {
+                    XrEvent::SessionChanged(XrSessionData {
+                        play_area: session.play_area.map(|area| PlayArea {
+                            width: area.width * c.meters_per_unit,
+                            depth: area.depth * c.meters_per_unit,
+                        }),
+                        ..session.clone()
+                    })
+                }
+                XrEvent::HeadPose(pose) => XrEvent::HeadPose(pose_from_shell(c, pose)),
+                XrEvent::ControllerPose(controller) => XrEvent::ControllerPose(XrControllerData {
+                    pose: pose_from_shell(c, &controller.pose),
+                    grip_pose: controller.grip_pose.as_ref().map(|pose| pose_from_shell(c, pose)),
+                    ..controller.clone()
+                }),
+                XrEvent::HandPose(hand) => XrEvent::HandPose(XrHandData {
+                    joints: hand.joints.iter().map(|pose| pose_from_shell(c, pose)).collect(),
+                    ..hand.clone()
+                }),
+                XrEvent::Gaze(gaze) => XrEvent::Gaze(GazeData {
+                    origin: point_from_shell(c, gaze.origin),
+                    direction: direction_from_shell(c, gaze.direction),
+                }),
+                XrEvent::Gesture(gesture) => XrEvent::Gesture(XrGestureData {
+                    position: gesture.position.map(|p| point_from_shell(c, p)),
+                    translation: gesture.translation.map(|t| point_from_shell(c, t)),
+                    ..gesture.clone()
+                }),
+                _ => return Cow::Borrowed(event),
+            }),
+            Event::Scene(SceneEvent::HitTestResult { request_id, hit }) => Event::Scene(SceneEvent::HitTestResult {
+                request_id: request_id.clone(),
+                hit: hit.as_ref().map(|hit| Hit {
+                    volume_id: hit.volume_id.clone(),
+                    point: point_from_shell(c, hit.point),
+                    normal: direction_from_shell(c, hit.normal),
+                    distance: hit.distance * c.meters_per_unit,
+                }),
+            }),
+            _ => return Cow::Borrowed(event),
+        };
+        Cow::Owned(converted)
+    }
+}
+
+/// Where each shell axis comes from: `shell[i] = sign[i] * core[axis[i]]`.
+/// +X stays right; the other two follow the up axis and handedness.
+fn basis(c: &Conventions) -> ([usize; 3], [f32; 3]) {
+    match (c.handedness, c.up_axis) {
+        (Handedness::Right, UpAxis::Y) => ([0, 1, 2], [1.0, 1.0, 1.0]),
+        (Handedness::Left, UpAxis::Y) => ([0, 1, 2], [1.0, 1.0, -1.0]),
+        (Handedness::Right, UpAxis::Z) => ([0, 2, 1], [1.0, -1.0, 1.0]),
+        (Handedness::Left, UpAxis::Z) => ([0, 2, 1], [1.0, 1.0, 1.0]),
+    }
+}
+
+fn direction_to_shell(c: &Conventions, v: [f32; 3]) -> [f32; 3] {
+    let (axis, sign) = basis(c);
+    [sign[0] * v[axis[0]], sign[1] * v[axis[1]], sign[2] * v[axis[2]]]
+}
+
+fn direction_from_shell(c: &Conventions, v: [f32; 3]) -> [f32; 3] {
+    let (axis, sign) = basis(c);
+    let mut core = [0.0; 3];
+    for i in 0..3 {
+        core[axis[i]] = sign[i] * v[i];
+    }
+    core
+}
+
+fn point_to_shell(c: &Conventions, p: [f32; 3]) -> [f32; 3] {
+    direction_to_shell(c, p).map(|x| x / c.meters_per_unit)
+}
+
+fn point_from_shell(c: &Conventions, p: [f32; 3]) -> [f32; 3] {
+    direction_from_shell(c, p).map(|x| x * c.meters_per_unit)
+}
+
+/// A rotation's axis is a pseudovector: mirroring flips it once more
+fn rotation_to_shell(c: &Conventions, q: [f32; 4]) -> [f32; 4] {
+    let flip = mirror(c);
+    let [x, y, z] = direction_to_shell(c, [q[0], q[1], q[2]]);
+    [x * flip, y * flip, z * flip, q[3]]
+}
+
+fn rotation_from_shell(c: &Conventions, q: [f32; 4]) -> [f32; 4] {
+    let flip = mirror(c);
+    let [x, y, z] = direction_from_shell(c, [q[0], q[1], q[2]]);
+    [x * flip, y * flip, z * flip, q[3]]
+}
+
+/// -1 if the conversion mirrors the scene
+fn mirror(c: &Conventions) -> f32 {
+    match c.handedness {
+        Handedness::Right => 1.0,
+        Handedness::Left => -1.0,
+    }
+}
+
+fn transform_to_shell(c: &Conventions, t: &Transform) -> Transform {
+    let (axis, _) = basis(c);
+    Transform {
+        position: point_to_shell(c, t.position),
+        rotation: rotation_to_shell(c, t.rotation),
+        // Primitive and asset sizes aren't converted, the scale makes up for
+        // the unit
+        scale: axis.map(|i| t.scale[i] / c.meters_per_unit),
+    }
+}
+
+/// A transform relative to a parent volume: only the axes change
+fn local_transform_to_shell(c: &Conventions, t: &Transform) -> Transform {
+    let (axis, _) = basis(c);
+    Transform {
+        position: direction_to_shell(c, t.position),
+        rotation: rotation_to_shell(c, t.rotation),
+        scale: axis.map(|i| t.scale[i]),
+    }
+}
+
+fn pose_from_shell(c: &Conventions, pose: &PoseData) -> PoseData {
+    PoseData {
+        position: point_from_shell(c, pose.position),
+        orientation: rotation_from_shell(c, pose.orientation),
+    }
+}
+
+/// Equal up to float noise in the unit
+fn same(a: &Conventions, b: &Conventions) -> bool {
+    a.handedness == b.handedness
+        && a.up_axis == b.up_axis
+        && (a.meters_per_unit / b.meters_per_unit - 1.0).abs() < 1e-4
+}
+
+fn describe(c: &Conventions) -> String {
+    let handedness = match c.handedness {
+        Handedness::Right => "right-handed",
+        Handedness::Left => "left-handed",
+    };
+    let up = match c.up_axis {
+        UpAxis::Y => "Y-up",
+        UpAxis::Z => "Z-up",
+    };
+    format!("{} {} with {} m per unit", handedness, up, c.meters_per_unit)
+}
@@ -0,0 +1,77 @@
+//! Debug HUD
+//!
+//! An overlay with FPS, draw calls, entity count and the last log messages,
+//! the same on every shell. The core owns the keybinding (Backquote) and
+//! tells the shell to show or hide the HUD with
+//! `DebugCommand::SetHudVisible`; shells that advertise `FEATURE_DEBUG_HUD`
+//! draw it. FPS and draw calls are measured by the shell, the entity count
+//! comes from the core in `DebugCommand::SetStats`, and the log tail is made
+//! of the `DebugCommand::Log` messages the shell has received.
+
+use crate::EntityKind;
+use fastn_protocol::*;
+
+/// Key code that toggles the HUD
+pub const DEBUG_HUD_KEY: &str = "Backquote";
+
+/// Toggles the shell's debug HUD and feeds it the core's stats.
+#[derive(Debug, Default)]
+pub struct DebugHud {
+    visible: bool,
+    stats: DebugStats,
+    /// Whether the shell can show the HUD, None until its `Init` event
+    supported: Option<bool>,
+}
+
+impl DebugHud {
+    /// Create the HUD (hidden) for the scene's entities.
+    pub fn new(entities: &[EntityKind]) -> Self {
+        Self {
+            stats: DebugStats {
+                entity_count: entities.iter().map(count_entities).sum(),
+            },
+            ..Self::default()
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Show or hide the HUD
+    pub fn set_visible(&mut self, visible: bool) -> Vec<Command> {
+        self.visible = visible;
+        let mut commands = vec![Command::Debug(DebugCommand::SetHudVisible { visible })];
+        if !visible {
+            return commands;
+        }
+        commands.push(Command::Debug(DebugCommand::SetStats(self.stats.clone())));
+        if self.supported == Some(false) {
+            commands.push(Command::Debug(DebugCommand::Log {
+                level: LogLevel::Warn,
+                message: "Shell has no debug HUD".to_string(),
+            }));
+        }
+        commands
+    }
+
+    /// Process an event, toggling the HUD on its key.
+    pub fn handle_event(&mut self, event: &Event) -> Vec<Command> {
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                self.supported = Some(init.features.iter().any(|f| f == FEATURE_DEBUG_HUD));
+                vec![]
+            }
+            Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyDown(data)))
+                if data.code == DEBUG_HUD_KEY && !data.repeat =>
+            {
+                self.set_visible(!self.visible)
+            }
+            _ => vec![],
+        }
+    }
+}
+
+fn count_entities(entity: &EntityKind) -> u32 {
+    1 + entity.children().iter().map(count_entities).sum::<u32>()
+}
@@ -18,6 +18,7 @@
 //!     SimpleMaterial::new().color(1.0, 0.0, 0.0)
 //! );
 //! cube.set_position([0.0, 1.0, -2.0]);
+//! let cube = cube.animate_position([0.0, 1.5, -2.0], 0.5);
 //! parent.add_child(cube);
 //!
 //! // Load entity from file (GLB, USDZ)
@@ -26,8 +27,12 @@
 //!     .scale(0.5);
 //! ```
 
-use crate::{MeshResource, SimpleMaterial};
-use crate::{Command, SceneCommand, CreateVolumeData, AssetCommand, Transform, VolumeSource, Primitive};
+use crate::{AccessibilityComponent, AccessibilityRole, Animation, AssetUri, AudioSource, MeshResource, SimpleMaterial};
+use crate::MeshGeometry;
+use crate::{Command, SceneCommand, CreateVolumeData, AssetCommand, CachePolicy, Priority, Transform, VolumeSource, Primitive};
+use crate::animation::compose;
+use crate::physics::{PhysicsBody, PhysicsComponent, ShapeResource};
+use std::rc::Rc;
 
 /// Base entity - a node in the scene hierarchy.
 ///
@@ -38,6 +43,8 @@ pub struct Entity {
     position: [f32; 3],
     orientation: [f32; 4],  // Quaternion
     scale: [f32; 3],
+    accessibility: AccessibilityComponent,
+    audio: Vec<AudioSource>,
     children: Vec<EntityKind>,
 }
 
@@ -49,6 +56,17 @@ pub enum EntityKind {
     LoadedEntity(LoadedEntity),
 }
 
+/// Where a volume sits in the scene hierarchy
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Placement<'a> {
+    /// The nearest ancestor with a volume
+    pub parent: Option<&'a str>,
+    /// Relative to `parent`, with the transforms of plain entities in
+    /// between folded in
+    pub local: Transform,
+    pub world: Transform,
+}
+
 impl Entity {
     /// Create a new empty entity.
     ///
@@ -85,6 +103,8 @@ impl Entity {
             position: [0.0, 0.0, 0.0],
             orientation: [0.0, 0.0, 0.0, 1.0],
             scale: [1.0, 1.0, 1.0],
+            accessibility: AccessibilityComponent::default(),
+            audio: Vec::new(),
             children: Vec::new(),
         }
     }
@@ -127,7 +147,36 @@ impl Entity {
         self
     }
 
-    /// Add a child entity.
+    /// Label read out by screen readers; unlabelled entities are not
+    /// exposed to assistive technology.
+    ///
+    /// Equivalent to `accessibilityLabel` in RealityKit.
+    pub fn accessibility_label(mut self, label: impl Into<String>) -> Self {
+        self.accessibility.label = Some(label.into());
+        self
+    }
+
+    /// Set what kind of thing this is for assistive technology.
+    pub fn accessibility_role(mut self, role: AccessibilityRole) -> Self {
+        self.accessibility.role = Some(role);
+        self
+    }
+
+    /// Longer description read after the label.
+    pub fn accessibility_description(mut self, description: impl Into<String>) -> Self {
+        self.accessibility.description = Some(description.into());
+        self
+    }
+
+    /// Play a sound from the entity. Entities without a volume don't
+    /// move, so the source stays where the entity is placed; its position
+    /// is an offset from there.
+    pub fn with_audio(mut self, source: AudioSource) -> Self {
+        self.audio.push(source);
+        self
+    }
+
+    /// Add a child entity. Its transform is relative to this entity.
     ///
     /// Equivalent to `entity.addChild(child)` in RealityKit.
     pub fn add_child(&mut self, child: impl Into<EntityKind>) {
@@ -138,6 +187,20 @@ impl Entity {
     pub fn children(&self) -> &[EntityKind] {
         &self.children
     }
+
+    /// Duplicate this entity and its whole subtree, assigning new IDs.
+    ///
+    /// Unlike `clone()`, which keeps IDs (and so refers to the same scene
+    /// nodes), the copy can be added to the scene alongside the original.
+    pub fn clone_deep(&self) -> Self {
+        let id = generate_id();
+        Self {
+            audio: clone_audio(&self.audio, &id),
+            id,
+            children: clone_children(&self.children),
+            ..self.clone()
+        }
+    }
 }
 
 impl Default for Entity {
@@ -173,6 +236,10 @@ pub struct ModelEntity {
     position: [f32; 3],
     orientation: [f32; 4],
     scale: [f32; 3],
+    animations: Vec<Animation>,
+    accessibility: AccessibilityComponent,
+    audio: Vec<AudioSource>,
+    physics: PhysicsComponent,
     children: Vec<EntityKind>,
 }
 
@@ -188,6 +255,10 @@ impl ModelEntity {
             position: [0.0, 0.0, 0.0],
             orientation: [0.0, 0.0, 0.0, 1.0],
             scale: [1.0, 1.0, 1.0],
+            animations: Vec::new(),
+            accessibility: AccessibilityComponent::default(),
+            audio: Vec::new(),
+            physics: PhysicsComponent::default(),
             children: Vec::new(),
         }
     }
@@ -201,6 +272,10 @@ impl ModelEntity {
             position: [0.0, 0.0, 0.0],
             orientation: [0.0, 0.0, 0.0, 1.0],
             scale: [1.0, 1.0, 1.0],
+            animations: Vec::new(),
+            accessibility: AccessibilityComponent::default(),
+            audio: Vec::new(),
+            physics: PhysicsComponent::default(),
             children: Vec::new(),
         }
     }
@@ -237,7 +312,77 @@ impl ModelEntity {
         self
     }
 
-    /// Add a child entity.
+    /// Label read out by screen readers (builder style).
+    pub fn accessibility_label(mut self, label: impl Into<String>) -> Self {
+        self.accessibility.label = Some(label.into());
+        self
+    }
+
+    /// Set what kind of thing this is for assistive technology (builder style).
+    pub fn accessibility_role(mut self, role: AccessibilityRole) -> Self {
+        self.accessibility.role = Some(role);
+        self
+    }
+
+    /// Longer description read after the label (builder style).
+    pub fn accessibility_description(mut self, description: impl Into<String>) -> Self {
+        self.accessibility.description = Some(description.into());
+        self
+    }
+
+    /// Queue an animation; an entity's animations play one after another
+    /// once the scene is shown (builder style).
+    pub fn animate(mut self, animation: Animation) -> Self {
+        self.animations.push(animation);
+        self
+    }
+
+    /// Move to `to` over `secs` seconds, easing in and out (builder style).
+    ///
+    /// Equivalent to `entity.move(to:relativeTo:duration:)` in RealityKit.
+    pub fn animate_position(self, to: [f32; 3], secs: f32) -> Self {
+        self.animate(Animation::position(to).duration(secs))
+    }
+
+    /// Turn to the orientation `to` over `secs` seconds (builder style).
+    pub fn animate_rotation(self, to: [f32; 4], secs: f32) -> Self {
+        self.animate(Animation::rotation(to).duration(secs))
+    }
+
+    /// Spin around `axis` forever, one turn every `secs_per_turn` seconds
+    /// (builder style).
+    pub fn spin(self, axis: [f32; 3], secs_per_turn: f32) -> Self {
+        self.animate(Animation::spin(axis).duration(secs_per_turn))
+    }
+
+    /// Play a sound from the entity; the source follows it, with its
+    /// position as an offset.
+    pub fn with_audio(mut self, source: AudioSource) -> Self {
+        self.audio.push(source);
+        self
+    }
+
+    /// Give the entity a collider, so physics bodies bounce off it. Without
+    /// `with_physics` it stays where its transform puts it.
+    ///
+    /// Equivalent to RealityKit's `CollisionComponent(shapes:)`.
+    pub fn with_collision(mut self, shape: ShapeResource) -> Self {
+        self.physics.collision = Some(shape);
+        self
+    }
+
+    /// Make the entity a physics body that falls and collides, shaped as
+    /// its mesh unless `with_collision` says otherwise; see the `physics`
+    /// module for how it is simulated.
+    ///
+    /// Equivalent to RealityKit's `PhysicsBodyComponent` in dynamic mode.
+    pub fn with_physics(mut self, mass: f32, restitution: f32) -> Self {
+        self.physics.body = Some(PhysicsBody::new(mass, restitution));
+        self
+    }
+
+    /// Add a child entity. Its transform is relative to this entity, so it
+    /// moves, turns and scales with it.
     pub fn add_child(&mut self, child: impl Into<EntityKind>) {
         self.children.push(child.into());
     }
@@ -247,6 +392,28 @@ impl ModelEntity {
         &self.children
     }
 
+    /// Duplicate this entity and its whole subtree, assigning new IDs.
+    ///
+    /// Mesh and material are copied; the shell reuses GPU resources for
+    /// identical primitives.
+    pub fn clone_deep(&self) -> Self {
+        let id = generate_id();
+        Self {
+            audio: clone_audio(&self.audio, &id),
+            id,
+            children: clone_children(&self.children),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn material(&self) -> &SimpleMaterial {
+        &self.material
+    }
+
+    pub(crate) fn mesh(&self) -> &MeshResource {
+        &self.mesh
+    }
+
     /// Convert to a CreateVolumeData command.
     pub(crate) fn to_command(&self) -> Command {
         let primitive = match &self.mesh {
@@ -254,11 +421,24 @@ impl ModelEntity {
             MeshResource::BoxWithDimensions { width, height, depth } => {
                 Primitive::Box { width: *width, height: *height, depth: *depth }
             }
-            MeshResource::Sphere { radius } => Primitive::Sphere { radius: *radius, segments: 32 },
+            MeshResource::Sphere { radius, segments } => Primitive::Sphere { radius: *radius, segments: *segments },
             MeshResource::Plane { width, depth } => Primitive::Plane { width: *width, height: *depth },
-            MeshResource::Cylinder { radius, height } => {
-                Primitive::Cylinder { radius: *radius, height: *height, segments: 32 }
+            MeshResource::Cylinder { radius, height, segments } => {
+                Primitive::Cylinder { radius: *radius, height: *height, segments: *segments }
+            }
+            MeshResource::Cone { radius, height, segments } => {
+                Primitive::Cone { radius: *radius, height: *height, segments: *segments }
+            }
+            MeshResource::Torus { radius, tube_radius, segments } => {
+                Primitive::Torus { radius: *radius, tube_radius: *tube_radius, segments: *segments }
             }
+            MeshResource::Text { text, font_size, alignment, max_width, billboard } => Primitive::Text {
+                text: text.clone(),
+                font_size: *font_size,
+                alignment: *alignment,
+                max_width: *max_width,
+                billboard: *billboard,
+            },
         };
 
         Command::Scene(SceneCommand::CreateVolume(CreateVolumeData {
@@ -270,6 +450,7 @@ impl ModelEntity {
                 scale: self.scale,
             },
             material: Some(self.material.to_override()),
+            parent_id: None,
         }))
     }
 }
@@ -294,29 +475,45 @@ pub struct LoadedEntity {
     asset_id: String,
     path: String,
     mesh_index: Option<u32>,
+    cache: CachePolicy,
     position: [f32; 3],
     orientation: [f32; 4],
     scale: [f32; 3],
     material_override: Option<SimpleMaterial>,
+    animations: Vec<Animation>,
+    accessibility: AccessibilityComponent,
+    audio: Vec<AudioSource>,
+    physics: PhysicsComponent,
+    /// Shape rays are cast against, parsed by the app
+    geometry: Option<Rc<MeshGeometry>>,
     children: Vec<EntityKind>,
 }
 
 impl LoadedEntity {
-    /// Create a new loaded entity from a file path.
+    /// Create a new loaded entity from an asset reference.
+    ///
+    /// Accepts an asset URI (`bundle://`, `file://`, `http(s)://`,
+    /// `kosha://` or an app-registered scheme) or a bare path, which is
+    /// relative to the app bundle.
     pub fn new(path: impl Into<String>) -> Self {
         let path = path.into();
         let id = generate_id();
-        // Asset ID is derived from path for deduplication
-        let asset_id = format!("asset:{}", path);
+        let asset_id = path_asset_id(&path);
         Self {
             id,
             asset_id,
             path,
             mesh_index: None,
+            cache: CachePolicy::Default,
             position: [0.0, 0.0, 0.0],
             orientation: [0.0, 0.0, 0.0, 1.0],
             scale: [1.0, 1.0, 1.0],
             material_override: None,
+            animations: Vec::new(),
+            accessibility: AccessibilityComponent::default(),
+            audio: Vec::new(),
+            physics: PhysicsComponent::default(),
+            geometry: None,
             children: Vec::new(),
         }
     }
@@ -326,6 +523,10 @@ impl LoadedEntity {
         &self.id
     }
 
+    pub(crate) fn material_override(&self) -> Option<&SimpleMaterial> {
+        self.material_override.as_ref()
+    }
+
     /// Get the asset path.
     pub fn path(&self) -> &str {
         &self.path
@@ -342,6 +543,14 @@ impl LoadedEntity {
         self
     }
 
+    /// Set how the shell's asset cache is used for the file, when it is
+    /// fetched over the network (builder style). By default the server's
+    /// cache headers decide.
+    pub fn cache(mut self, policy: CachePolicy) -> Self {
+        self.cache = policy;
+        self
+    }
+
     /// Set the position in parent's coordinate space.
     pub fn set_position(&mut self, position: [f32; 3]) {
         self.position = position;
@@ -375,7 +584,89 @@ impl LoadedEntity {
         self
     }
 
-    /// Add a child entity.
+    /// Give the core the model's geometry, usually parsed from the same
+    /// file with `MeshGeometry::from_glb_bytes`, so rays hit its surface
+    /// (builder style).
+    pub fn geometry(mut self, geometry: MeshGeometry) -> Self {
+        self.geometry = Some(Rc::new(geometry));
+        self
+    }
+
+    pub(crate) fn collision_geometry(&self) -> Option<&Rc<MeshGeometry>> {
+        self.geometry.as_ref()
+    }
+
+    /// Label read out by screen readers (builder style).
+    pub fn accessibility_label(mut self, label: impl Into<String>) -> Self {
+        self.accessibility.label = Some(label.into());
+        self
+    }
+
+    /// Set what kind of thing this is for assistive technology (builder style).
+    pub fn accessibility_role(mut self, role: AccessibilityRole) -> Self {
+        self.accessibility.role = Some(role);
+        self
+    }
+
+    /// Longer description read after the label (builder style).
+    pub fn accessibility_description(mut self, description: impl Into<String>) -> Self {
+        self.accessibility.description = Some(description.into());
+        self
+    }
+
+    /// Queue an animation; an entity's animations play one after another
+    /// once the scene is shown (builder style).
+    pub fn animate(mut self, animation: Animation) -> Self {
+        self.animations.push(animation);
+        self
+    }
+
+    /// Move to `to` over `secs` seconds, easing in and out (builder style).
+    ///
+    /// Equivalent to `entity.move(to:relativeTo:duration:)` in RealityKit.
+    pub fn animate_position(self, to: [f32; 3], secs: f32) -> Self {
+        self.animate(Animation::position(to).duration(secs))
+    }
+
+    /// Turn to the orientation `to` over `secs` seconds (builder style).
+    pub fn animate_rotation(self, to: [f32; 4], secs: f32) -> Self {
+        self.animate(Animation::rotation(to).duration(secs))
+    }
+
+    /// Spin around `axis` forever, one turn every `secs_per_turn` seconds
+    /// (builder style).
+    pub fn spin(self, axis: [f32; 3], secs_per_turn: f32) -> Self {
+        self.animate(Animation::spin(axis).duration(secs_per_turn))
+    }
+
+    /// Play a sound from the entity; the source follows it, with its
+    /// position as an offset.
+    pub fn with_audio(mut self, source: AudioSource) -> Self {
+        self.audio.push(source);
+        self
+    }
+
+    /// Give the entity a collider, so physics bodies bounce off it. Without
+    /// `with_physics` it stays where its transform puts it.
+    ///
+    /// Equivalent to RealityKit's `CollisionComponent(shapes:)`.
+    pub fn with_collision(mut self, shape: ShapeResource) -> Self {
+        self.physics.collision = Some(shape);
+        self
+    }
+
+    /// Make the entity a physics body that falls and collides. It needs a
+    /// `with_collision` shape; see the `physics` module for how it is
+    /// simulated.
+    ///
+    /// Equivalent to RealityKit's `PhysicsBodyComponent` in dynamic mode.
+    pub fn with_physics(mut self, mass: f32, restitution: f32) -> Self {
+        self.physics.body = Some(PhysicsBody::new(mass, restitution));
+        self
+    }
+
+    /// Add a child entity. Its transform is relative to this entity, so it
+    /// moves, turns and scales with it.
     pub fn add_child(&mut self, child: impl Into<EntityKind>) {
         self.children.push(child.into());
     }
@@ -385,11 +676,27 @@ impl LoadedEntity {
         &self.children
     }
 
-    /// Generate the asset load command.
-    pub(crate) fn to_load_command(&self) -> Command {
+    /// Duplicate this entity and its whole subtree, assigning new IDs.
+    ///
+    /// The copy keeps the same asset ID, so the file is loaded only once
+    /// and shared by all copies.
+    pub fn clone_deep(&self) -> Self {
+        let id = generate_id();
+        Self {
+            audio: clone_audio(&self.audio, &id),
+            id,
+            children: clone_children(&self.children),
+            ..self.clone()
+        }
+    }
+
+    /// Generate the asset load command for the resolved asset URI.
+    pub(crate) fn to_load_command(&self, uri: &AssetUri) -> Command {
         Command::Asset(AssetCommand::Load {
             asset_id: self.asset_id.clone(),
-            path: self.path.clone(),
+            path: uri.to_string(),
+            priority: Priority::Normal,
+            cache: self.cache,
         })
     }
 
@@ -407,10 +714,158 @@ impl LoadedEntity {
                 scale: self.scale,
             },
             material: self.material_override.as_ref().map(|m| m.to_override()),
+            parent_id: None,
         }))
     }
 }
 
+impl EntityKind {
+    /// Get the entity's ID.
+    pub fn id(&self) -> &str {
+        match self {
+            EntityKind::Entity(e) => e.id(),
+            EntityKind::ModelEntity(e) => e.id(),
+            EntityKind::LoadedEntity(e) => e.id(),
+        }
+    }
+
+    /// Get children.
+    pub fn children(&self) -> &[EntityKind] {
+        match self {
+            EntityKind::Entity(e) => e.children(),
+            EntityKind::ModelEntity(e) => e.children(),
+            EntityKind::LoadedEntity(e) => e.children(),
+        }
+    }
+
+    /// Get the accessibility information.
+    pub fn accessibility(&self) -> &AccessibilityComponent {
+        match self {
+            EntityKind::Entity(e) => &e.accessibility,
+            EntityKind::ModelEntity(e) => &e.accessibility,
+            EntityKind::LoadedEntity(e) => &e.accessibility,
+        }
+    }
+
+    /// Transform of the entity relative to its parent
+    pub(crate) fn transform(&self) -> Transform {
+        let (position, rotation, scale) = match self {
+            EntityKind::Entity(e) => (e.position, e.orientation, e.scale),
+            EntityKind::ModelEntity(e) => (e.position, e.orientation, e.scale),
+            EntityKind::LoadedEntity(e) => (e.position, e.orientation, e.scale),
+        };
+        Transform { position, rotation, scale }
+    }
+
+    /// Visit the entities with volumes in the subtree, parents first, with
+    /// where each is placed. Plain entities have no volume: their
+    /// transform carries over to their children. `at` is the placement of
+    /// the entity's own parent.
+    pub(crate) fn visit_volumes<'a>(
+        &'a self,
+        at: &Placement<'a>,
+        visit: &mut impl FnMut(&'a EntityKind, &Placement<'a>),
+    ) {
+        let local = compose(&at.local, &self.transform());
+        let world = compose(&at.world, &self.transform());
+        let inner = match self {
+            EntityKind::Entity(_) => Placement { parent: at.parent, local, world },
+            _ => {
+                visit(self, &Placement { parent: at.parent, local, world: world.clone() });
+                Placement { parent: Some(self.id()), local: Transform::default(), world }
+            }
+        };
+        for child in self.children() {
+            child.visit_volumes(&inner, visit);
+        }
+    }
+
+    /// Audio sources playing from the entity.
+    pub fn audio_sources(&self) -> &[AudioSource] {
+        match self {
+            EntityKind::Entity(e) => &e.audio,
+            EntityKind::ModelEntity(e) => &e.audio,
+            EntityKind::LoadedEntity(e) => &e.audio,
+        }
+    }
+
+    /// The audio sources of the entity and its subtree, placed: attached to
+    /// entities with volumes, at the position of those without (offset
+    /// from the nearest volume above them, if any). `at` is the placement
+    /// of the entity's own parent.
+    pub(crate) fn collect_audio(&self, at: &Placement<'_>, sources: &mut Vec<AudioSource>) {
+        let local = compose(&at.local, &self.transform());
+        for source in self.audio_sources() {
+            let source = match self {
+                EntityKind::Entity(_) => {
+                    let source = AudioSource {
+                        position: [0, 1, 2].map(|i| local.position[i] + source.position[i]),
+                        ..source.clone()
+                    };
+                    match at.parent {
+                        Some(parent) => source.attach_to(parent),
+                        None => source,
+                    }
+                }
+                _ => source.clone().attach_to(self.id()),
+            };
+            sources.push(source);
+        }
+        let inner = match self {
+            EntityKind::Entity(_) => Placement { local, ..at.clone() },
+            _ => Placement { parent: Some(self.id()), ..Placement::default() },
+        };
+        for child in self.children() {
+            child.collect_audio(&inner, sources);
+        }
+    }
+
+    /// The entity's collider and physics body, if it has either
+    pub(crate) fn physics(&self) -> Option<&PhysicsComponent> {
+        let physics = match self {
+            EntityKind::Entity(_) => return None,
+            EntityKind::ModelEntity(e) => &e.physics,
+            EntityKind::LoadedEntity(e) => &e.physics,
+        };
+        (physics.collision.is_some() || physics.body.is_some()).then_some(physics)
+    }
+
+    /// Animations queued on the entity.
+    pub fn animations(&self) -> &[Animation] {
+        match self {
+            EntityKind::Entity(_) => &[],
+            EntityKind::ModelEntity(e) => &e.animations,
+            EntityKind::LoadedEntity(e) => &e.animations,
+        }
+    }
+
+    /// Duplicate this entity and its whole subtree, assigning new IDs.
+    /// Audio sources are copied with IDs `<source id>@<copy id>`.
+    pub fn clone_deep(&self) -> Self {
+        match self {
+            EntityKind::Entity(e) => EntityKind::Entity(e.clone_deep()),
+            EntityKind::ModelEntity(e) => EntityKind::ModelEntity(e.clone_deep()),
+            EntityKind::LoadedEntity(e) => EntityKind::LoadedEntity(e.clone_deep()),
+        }
+    }
+}
+
+fn clone_children(children: &[EntityKind]) -> Vec<EntityKind> {
+    children.iter().map(EntityKind::clone_deep).collect()
+}
+
+/// Copies of an entity's audio sources for its copy `entity_id`, which
+/// need IDs of their own
+fn clone_audio(audio: &[AudioSource], entity_id: &str) -> Vec<AudioSource> {
+    audio
+        .iter()
+        .map(|source| AudioSource {
+            id: format!("{}@{}", source.id, entity_id),
+            ..source.clone()
+        })
+        .collect()
+}
+
 // Conversions to EntityKind
 impl From<Entity> for EntityKind {
     fn from(e: Entity) -> Self {
@@ -430,6 +885,12 @@ impl From<LoadedEntity> for EntityKind {
     }
 }
 
+/// Asset ID of a model file, derived from its path so entities (and
+/// prefetches) of the same file share one load
+pub(crate) fn path_asset_id(path: &str) -> String {
+    format!("asset:{}", path)
+}
+
 // Simple ID generation (in real impl, use UUID)
 fn generate_id() -> String {
     use std::sync::atomic::{AtomicU64, Ordering};
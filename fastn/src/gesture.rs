@@ -0,0 +1,638 @@
+//! Gestures recognized from raw input
+//!
+//! Most shells only report raw input: mouse buttons, touches and hand
+//! poses. The core recognizes gestures in it and runs `on_gesture`
+//! callbacks with the same `XrGestureData` that shells with
+//! `FEATURE_GESTURES` send (those recognize gestures themselves, and the
+//! core passes theirs on instead):
+//!
+//! ```rust,ignore
+//! use fastn::{GesturePhase, GestureThresholds, RealityViewContent, XrGesture};
+//!
+//! fn make_content(content: &mut RealityViewContent) {
+//!     // ... add the "map" entity
+//!     content.on_gesture("map", |ctx, gesture| match (gesture.gesture, gesture.phase) {
+//!         (XrGesture::DoubleTap, _) => ctx.zoom_to_fit(&["map"]),
+//!         (XrGesture::Pinch, GesturePhase::Ended) => {
+//!             ctx.announce(format!("{:.1}x", gesture.scale.unwrap_or(1.0)))
+//!         }
+//!         _ => {}
+//!     });
+//!     content.set_gesture_thresholds(GestureThresholds {
+//!         long_press_secs: 0.8,
+//!         ..GestureThresholds::default()
+//!     });
+//! }
+//! ```
+//!
+//! A press is the left mouse button, a touch, or a hand pinching (its
+//! `pinch_strength` reaching `pinch_start`, until it drops below
+//! `pinch_end`). From presses come:
+//!
+//! - `Tap`: released before moving `tap_slop` (`hand_slop` for hands) or
+//!   being held `long_press_secs`
+//! - `DoubleTap`: a tap within `double_tap_secs` and `double_tap_slop`
+//!   (`hand_double_tap_slop`) of the one before, right after its own `Tap`
+//! - `LongPress`: held `long_press_secs` without moving, while still down
+//! - `Drag`: moved past the slop. It begins, changes with every move and
+//!   ends on release, with the movement so far in `screen_translation`
+//!   (`translation` for hands).
+//! - `Pinch`: a second touch (or the other hand) pressing while the first
+//!   is down. It begins once their distance changes by `pinch_slop`, and
+//!   `scale` follows the distance until either lifts. A drag in progress is
+//!   cancelled.
+//!
+//! Every gesture carries the volume under where its first press started,
+//! hit-tested like taps (`SceneCommand::RequestHitTest`, or picked in the
+//! core on shells that don't answer hit tests). Hands aim with the user's
+//! gaze, or from the head through the index fingertip. Gestures wait for
+//! their hit test, so callbacks get them in order and with their volume.
+//! Time comes from frames: a long press is noticed on the first frame past
+//! `long_press_secs`.
+
+use crate::{CameraController, Scene};
+use fastn_protocol::*;
+use std::collections::{HashMap, VecDeque};
+
+/// Prefix of the hit-test requests sent for presses
+const GESTURE_REQUEST_PREFIX: &str = "gesture-";
+
+/// Viewport until the shell reports one (the native shell's window size)
+const DEFAULT_VIEWPORT: (f32, f32) = (1280.0, 720.0);
+
+/// Seconds a hit test may take before its gestures go out without a volume
+const HIT_TEST_TIMEOUT_SECS: f64 = 1.0;
+
+/// When input becomes a gesture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureThresholds {
+    /// Pointer travel (logical pixels) after which a press is a drag
+    pub tap_slop: f32,
+    /// Hand travel (meters) after which a pinch is a drag
+    pub hand_slop: f32,
+    /// Longest time between the taps of a double tap, in seconds
+    pub double_tap_secs: f64,
+    /// Farthest apart the taps of a double tap can be, in logical pixels
+    pub double_tap_slop: f32,
+    /// Farthest apart the taps of a hand's double tap can be, in meters
+    pub hand_double_tap_slop: f32,
+    /// Seconds a press is held before it is a long press
+    pub long_press_secs: f64,
+    /// Pinch strength at which a hand presses
+    pub pinch_start: f32,
+    /// Pinch strength below which a pressing hand lets go
+    pub pinch_end: f32,
+    /// Change in the distance between two presses, as a fraction of where
+    /// it started, after which they pinch
+    pub pinch_slop: f32,
+}
+
+impl Default for GestureThresholds {
+    fn default() -> Self {
+        Self {
+            tap_slop: 8.0,
+            hand_slop: 0.02,
+            double_tap_secs: 0.3,
+            double_tap_slop: 24.0,
+            hand_double_tap_slop: 0.05,
+            long_press_secs: 0.5,
+            pinch_start: 0.8,
+            pinch_end: 0.6,
+            pinch_slop: 0.05,
+        }
+    }
+}
+
+/// What is pressing: the mouse, one touch or a pinching hand
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pointer {
+    Mouse,
+    Touch(u32),
+    Hand(Hand),
+}
+
+impl Pointer {
+    fn hand(self) -> Option<Hand> {
+        match self {
+            Pointer::Hand(hand) => Some(hand),
+            _ => None,
+        }
+    }
+
+    /// Whether a press of `other` while this one is down makes a pinch
+    fn pinches_with(self, other: Pointer) -> bool {
+        match (self, other) {
+            (Pointer::Touch(a), Pointer::Touch(b)) => a != b,
+            (Pointer::Hand(a), Pointer::Hand(b)) => a != b,
+            _ => false,
+        }
+    }
+}
+
+/// A pointer that is down. Positions are on screen (`[x, y, 0]`) for the
+/// mouse and touches, in the world for hands.
+#[derive(Debug, Clone, Copy)]
+struct Contact {
+    pointer: Pointer,
+    start: [f32; 3],
+    current: [f32; 3],
+}
+
+impl Contact {
+    fn new(pointer: Pointer, at: [f32; 3]) -> Self {
+        Self {
+            pointer,
+            start: at,
+            current: at,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    Idle,
+    Press {
+        contact: Contact,
+        stroke: u64,
+        /// When the press started
+        since: f64,
+        dragging: bool,
+        long_pressed: bool,
+    },
+    Pinch {
+        contacts: [Contact; 2],
+        stroke: u64,
+        /// Distance between the contacts when the second pressed
+        distance: f32,
+        pinching: bool,
+    },
+    /// A gesture ended with these pointers still down; nothing starts until
+    /// they lift
+    Spent(Vec<Pointer>),
+}
+
+/// Turns raw input into gestures, for `EventHandlers`.
+#[derive(Debug, Clone)]
+pub(crate) struct GestureRecognizer {
+    thresholds: GestureThresholds,
+    /// Whether the shell recognizes gestures itself
+    shell_gestures: bool,
+    /// Whether presses are picked in the core, for shells that don't
+    /// answer hit tests
+    core_picking: bool,
+    viewport: (f32, f32),
+    state: State,
+    /// Whether each hand (left, right) is pressing
+    pinched: [bool; 2],
+    /// Where the user last looked, and from where
+    gaze: Option<([f32; 3], [f32; 3])>,
+    head: Option<[f32; 3]>,
+    /// The last tap, for double taps: when, where and whether by a hand
+    last_tap: Option<(f64, [f32; 3], bool)>,
+    /// Time of the last frame
+    now: f64,
+    next_stroke: u64,
+    /// Volume under each press (stroke) hit-tested so far
+    targets: HashMap<u64, Option<VolumeId>>,
+    /// Hit tests out, with their stroke and when they were sent
+    requests: HashMap<String, (u64, f64)>,
+    /// Gestures waiting for their stroke's hit test, in order
+    queue: VecDeque<(u64, XrGestureData)>,
+    /// Hit tests to send
+    commands: Vec<Command>,
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        Self {
+            thresholds: GestureThresholds::default(),
+            shell_gestures: false,
+            core_picking: false,
+            viewport: DEFAULT_VIEWPORT,
+            state: State::Idle,
+            pinched: [false; 2],
+            gaze: None,
+            head: None,
+            last_tap: None,
+            now: 0.0,
+            next_stroke: 0,
+            targets: HashMap::new(),
+            requests: HashMap::new(),
+            queue: VecDeque::new(),
+            commands: Vec::new(),
+        }
+    }
+}
+
+impl GestureRecognizer {
+    pub(crate) fn set_thresholds(&mut self, thresholds: GestureThresholds) {
+        self.thresholds = thresholds;
+    }
+
+    /// Follow an event. Returns the hit tests to send and the gestures
+    /// ready for callbacks; input only counts while someone is `listening`.
+    pub(crate) fn handle_event(
+        &mut self,
+        event: &Event,
+        listening: bool,
+        scene: &Scene,
+        camera: &CameraController,
+    ) -> (Vec<Command>, Vec<XrGestureData>) {
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                self.shell_gestures = init.features.iter().any(|f| f == FEATURE_GESTURES);
+                self.core_picking = !init.features.iter().any(|f| f == FEATURE_HIT_TEST);
+                self.viewport = (init.viewport_width as f32, init.viewport_height as f32);
+            }
+            Event::Lifecycle(LifecycleEvent::Resize(resize)) => {
+                self.viewport = (resize.width as f32, resize.height as f32);
+            }
+            Event::Lifecycle(LifecycleEvent::Frame(frame)) => {
+                self.now = frame.time;
+                if listening {
+                    self.tick();
+                }
+            }
+            Event::Xr(XrEvent::Gaze(gaze)) => self.gaze = Some((gaze.origin, gaze.direction)),
+            Event::Xr(XrEvent::HeadPose(pose)) => self.head = Some(pose.position),
+            Event::Xr(XrEvent::Gesture(gesture)) if listening => return (vec![], vec![gesture.clone()]),
+            _ if !listening || self.shell_gestures => {}
+            Event::Input(InputEvent::Mouse(MouseEvent::Down(data))) if data.button == MouseButton::Left => {
+                self.down(Pointer::Mouse, [data.x, data.y, 0.0], scene, camera);
+            }
+            Event::Input(InputEvent::Mouse(MouseEvent::Move(data))) => self.moved(Pointer::Mouse, [data.x, data.y, 0.0]),
+            Event::Input(InputEvent::Mouse(MouseEvent::Up(data))) if data.button == MouseButton::Left => {
+                self.up(Pointer::Mouse, [data.x, data.y, 0.0]);
+            }
+            Event::Input(InputEvent::Touch(TouchEvent::Start(data))) => {
+                for touch in &data.touches {
+                    self.down(Pointer::Touch(touch.id), [touch.x, touch.y, 0.0], scene, camera);
+                }
+            }
+            Event::Input(InputEvent::Touch(TouchEvent::Move(data))) => {
+                for touch in &data.touches {
+                    self.moved(Pointer::Touch(touch.id), [touch.x, touch.y, 0.0]);
+                }
+            }
+            Event::Input(InputEvent::Touch(TouchEvent::End(data))) => {
+                for touch in &data.touches {
+                    self.up(Pointer::Touch(touch.id), [touch.x, touch.y, 0.0]);
+                }
+            }
+            Event::Input(InputEvent::Touch(TouchEvent::Cancel(data))) => {
+                for touch in &data.touches {
+                    self.cancel(Pointer::Touch(touch.id));
+                }
+            }
+            Event::Xr(XrEvent::HandPose(hand)) => self.hand(hand, scene, camera),
+            // Hand poses before and after aren't continuous, or stop coming
+            Event::Xr(XrEvent::ReferenceSpaceReset) => self.release_hands(),
+            Event::Xr(XrEvent::SessionChanged(session)) if session.state != XrSessionState::Active => {
+                self.release_hands()
+            }
+            Event::Scene(SceneEvent::HitTestResult { request_id, hit }) => {
+                if let Some((stroke, _)) = self.requests.remove(request_id) {
+                    self.targets.insert(stroke, hit.as_ref().map(|hit| hit.volume_id.clone()));
+                }
+            }
+            _ => {}
+        }
+        (std::mem::take(&mut self.commands), self.ready())
+    }
+
+    fn down(&mut self, pointer: Pointer, at: [f32; 3], scene: &Scene, camera: &CameraController) {
+        self.state = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle => State::Press {
+                contact: Contact::new(pointer, at),
+                stroke: self.start_stroke(pointer, at, scene, camera),
+                since: self.now,
+                dragging: false,
+                long_pressed: false,
+            },
+            State::Press {
+                contact,
+                stroke,
+                dragging,
+                ..
+            } if contact.pointer.pinches_with(pointer) => {
+                if dragging {
+                    self.emit(stroke, gesture(XrGesture::Drag, GesturePhase::Cancelled, &contact));
+                }
+                State::Pinch {
+                    contacts: [contact, Contact::new(pointer, at)],
+                    stroke,
+                    distance: distance(contact.current, at),
+                    pinching: false,
+                }
+            }
+            state => state,
+        };
+    }
+
+    fn moved(&mut self, pointer: Pointer, at: [f32; 3]) {
+        match &mut self.state {
+            State::Press {
+                contact,
+                stroke,
+                dragging,
+                ..
+            } if contact.pointer == pointer && contact.current != at => {
+                contact.current = at;
+                let phase = match *dragging {
+                    true => GesturePhase::Changed,
+                    false if distance(contact.start, at) > self.thresholds.slop(pointer) => GesturePhase::Began,
+                    false => return,
+                };
+                *dragging = true;
+                let (contact, stroke) = (*contact, *stroke);
+                self.emit(stroke, gesture(XrGesture::Drag, phase, &contact));
+            }
+            State::Pinch {
+                contacts,
+                stroke,
+                distance: start,
+                pinching,
+            } => {
+                let Some(contact) = contacts.iter_mut().find(|contact| contact.pointer == pointer && contact.current != at)
+                else {
+                    return;
+                };
+                contact.current = at;
+                let scale = distance(contacts[0].current, contacts[1].current) / start.max(f32::EPSILON);
+                let phase = match *pinching {
+                    true => GesturePhase::Changed,
+                    false if (scale - 1.0).abs() > self.thresholds.pinch_slop => GesturePhase::Began,
+                    false => return,
+                };
+                *pinching = true;
+                let (contacts, stroke) = (*contacts, *stroke);
+                self.emit(stroke, pinch(phase, &contacts, scale));
+            }
+            _ => {}
+        }
+    }
+
+    fn up(&mut self, pointer: Pointer, at: [f32; 3]) {
+        self.moved(pointer, at);
+        self.state = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Press {
+                contact,
+                stroke,
+                since,
+                dragging,
+                long_pressed,
+            } if contact.pointer == pointer => {
+                if dragging {
+                    self.emit(stroke, gesture(XrGesture::Drag, GesturePhase::Ended, &contact));
+                } else if !long_pressed && self.now - since < self.thresholds.long_press_secs {
+                    self.tap(stroke, &contact);
+                }
+                State::Idle
+            }
+            state => self.end_pinch(state, pointer, GesturePhase::Ended),
+        };
+    }
+
+    fn cancel(&mut self, pointer: Pointer) {
+        self.state = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Press {
+                contact,
+                stroke,
+                dragging,
+                ..
+            } if contact.pointer == pointer => {
+                if dragging {
+                    self.emit(stroke, gesture(XrGesture::Drag, GesturePhase::Cancelled, &contact));
+                }
+                State::Idle
+            }
+            state => self.end_pinch(state, pointer, GesturePhase::Cancelled),
+        };
+    }
+
+    /// The state after `pointer` lifts from a pinch (or from a spent
+    /// gesture)
+    fn end_pinch(&mut self, state: State, pointer: Pointer, phase: GesturePhase) -> State {
+        match state {
+            State::Pinch {
+                contacts,
+                stroke,
+                distance: start,
+                pinching,
+            } if contacts.iter().any(|contact| contact.pointer == pointer) => {
+                if pinching {
+                    let scale = distance(contacts[0].current, contacts[1].current) / start.max(f32::EPSILON);
+                    self.emit(stroke, pinch(phase, &contacts, scale));
+                }
+                let rest = contacts.iter().map(|contact| contact.pointer).filter(|p| *p != pointer).collect();
+                State::Spent(rest)
+            }
+            State::Spent(mut pointers) => {
+                pointers.retain(|p| *p != pointer);
+                match pointers.is_empty() {
+                    true => State::Idle,
+                    false => State::Spent(pointers),
+                }
+            }
+            state => state,
+        }
+    }
+
+    fn tap(&mut self, stroke: u64, contact: &Contact) {
+        self.emit(stroke, gesture(XrGesture::Tap, GesturePhase::Ended, contact));
+        let by_hand = contact.pointer.hand().is_some();
+        let slop = match by_hand {
+            true => self.thresholds.hand_double_tap_slop,
+            false => self.thresholds.double_tap_slop,
+        };
+        match self.last_tap.take() {
+            Some((at, position, hand))
+                if hand == by_hand
+                    && self.now - at <= self.thresholds.double_tap_secs
+                    && distance(position, contact.current) <= slop =>
+            {
+                self.emit(stroke, gesture(XrGesture::DoubleTap, GesturePhase::Ended, contact));
+            }
+            _ => self.last_tap = Some((self.now, contact.current, by_hand)),
+        }
+    }
+
+    /// Press or release a hand as its pinch strength crosses the thresholds
+    fn hand(&mut self, hand: &XrHandData, scene: &Scene, camera: &CameraController) {
+        let Some(tip) = hand.joints.get(HAND_JOINT_INDEX_TIP).map(|pose| pose.position) else {
+            return;
+        };
+        let index = hand_index(hand.hand);
+        let pointer = Pointer::Hand(hand.hand);
+        let was_pinched = self.pinched[index];
+        let threshold = match was_pinched {
+            true => self.thresholds.pinch_end,
+            false => self.thresholds.pinch_start,
+        };
+        let pinched = hand.pinch_strength >= threshold;
+        self.pinched[index] = pinched;
+        match (was_pinched, pinched) {
+            (false, true) => self.down(pointer, tip, scene, camera),
+            (true, true) => self.moved(pointer, tip),
+            (true, false) => self.up(pointer, tip),
+            (false, false) => {}
+        }
+    }
+
+    fn release_hands(&mut self) {
+        for hand in [Hand::Left, Hand::Right] {
+            if std::mem::take(&mut self.pinched[hand_index(hand)]) {
+                self.cancel(Pointer::Hand(hand));
+            }
+        }
+    }
+
+    /// Notice long presses and give up on hit tests the shell didn't answer
+    fn tick(&mut self) {
+        if let State::Press {
+            contact,
+            stroke,
+            since,
+            dragging: false,
+            long_pressed: long_pressed @ false,
+        } = &mut self.state
+            && self.now - *since >= self.thresholds.long_press_secs
+        {
+            *long_pressed = true;
+            let (contact, stroke) = (*contact, *stroke);
+            self.emit(stroke, gesture(XrGesture::LongPress, GesturePhase::Ended, &contact));
+        }
+        let now = self.now;
+        let expired: Vec<u64> = self
+            .requests
+            .values()
+            .filter(|(_, sent)| now - sent > HIT_TEST_TIMEOUT_SECS)
+            .map(|(stroke, _)| *stroke)
+            .collect();
+        if !expired.is_empty() {
+            self.requests.retain(|_, (stroke, _)| !expired.contains(stroke));
+            self.targets.extend(expired.into_iter().map(|stroke| (stroke, None)));
+        }
+    }
+
+    /// Find the volume under a new press: now when picking in the core,
+    /// otherwise once the shell answers
+    fn start_stroke(&mut self, pointer: Pointer, at: [f32; 3], scene: &Scene, camera: &CameraController) -> u64 {
+        self.next_stroke += 1;
+        let stroke = self.next_stroke;
+        let source = match pointer {
+            Pointer::Hand(_) => self.hand_ray(at).map(|(origin, direction)| HitTestSource::Ray { origin, direction }),
+            _ => Some(HitTestSource::Screen { x: at[0], y: at[1] }),
+        };
+        match source {
+            None => {
+                self.targets.insert(stroke, None);
+            }
+            Some(source) if self.core_picking => {
+                let (origin, direction) = match source {
+                    HitTestSource::Ray { origin, direction } => (origin, direction),
+                    HitTestSource::Screen { x, y } => {
+                        camera.screen_ray(x, y, self.viewport.0.max(1.0), self.viewport.1.max(1.0))
+                    }
+                };
+                self.targets.insert(stroke, scene.raycast(origin, direction).map(|hit| hit.entity));
+            }
+            Some(source) => {
+                let request_id = format!("{}{}", GESTURE_REQUEST_PREFIX, stroke);
+                self.requests.insert(request_id.clone(), (stroke, self.now));
+                self.commands.push(Command::Scene(SceneCommand::RequestHitTest(HitTestRequest {
+                    request_id,
+                    source,
+                })));
+            }
+        }
+        stroke
+    }
+
+    /// Where a hand aims: where the user looks, or from the head through
+    /// the fingertip
+    fn hand_ray(&self, tip: [f32; 3]) -> Option<([f32; 3], [f32; 3])> {
+        self.gaze
+            .or_else(|| self.head.map(|head| (head, std::array::from_fn(|i| tip[i] - head[i]))))
+    }
+
+    fn emit(&mut self, stroke: u64, gesture: XrGestureData) {
+        self.queue.push_back((stroke, gesture));
+    }
+
+    /// Gestures whose volume is known, in order, and forget volumes no
+    /// gesture needs anymore
+    fn ready(&mut self) -> Vec<XrGestureData> {
+        let mut ready = Vec::new();
+        while let Some(target) = self.queue.front().and_then(|(stroke, _)| self.targets.get(stroke)) {
+            let volume_id = target.clone();
+            if let Some((_, mut gesture)) = self.queue.pop_front() {
+                gesture.volume_id = volume_id;
+                ready.push(gesture);
+            }
+        }
+        let active = match &self.state {
+            State::Press { stroke, .. } | State::Pinch { stroke, .. } => Some(*stroke),
+            _ => None,
+        };
+        let queue = &self.queue;
+        self.targets
+            .retain(|stroke, _| Some(*stroke) == active || queue.iter().any(|(queued, _)| queued == stroke));
+        ready
+    }
+}
+
+impl GestureThresholds {
+    /// Travel after which a press of `pointer` is a drag
+    fn slop(&self, pointer: Pointer) -> f32 {
+        match pointer {
+            Pointer::Hand(_) => self.hand_slop,
+            _ => self.tap_slop,
+        }
+    }
+}
+
+/// A gesture of one contact
+fn gesture(kind: XrGesture, phase: GesturePhase, contact: &Contact) -> XrGestureData {
+    let moved: [f32; 3] = std::array::from_fn(|i| contact.current[i] - contact.start[i]);
+    let drag = kind == XrGesture::Drag;
+    let hand = contact.pointer.hand();
+    XrGestureData {
+        gesture: kind,
+        hand,
+        position: hand.map(|_| contact.current),
+        phase,
+        volume_id: None,
+        screen: hand.is_none().then_some([contact.current[0], contact.current[1]]),
+        translation: (drag && hand.is_some()).then_some(moved),
+        screen_translation: (drag && hand.is_none()).then_some([moved[0], moved[1]]),
+        scale: None,
+    }
+}
+
+/// A pinch of two contacts, placed between them
+fn pinch(phase: GesturePhase, contacts: &[Contact; 2], scale: f32) -> XrGestureData {
+    let [a, b] = contacts;
+    let middle = Contact {
+        pointer: a.pointer,
+        start: std::array::from_fn(|i| (a.start[i] + b.start[i]) / 2.0),
+        current: std::array::from_fn(|i| (a.current[i] + b.current[i]) / 2.0),
+    };
+    XrGestureData {
+        // Both hands pinch
+        hand: None,
+        scale: Some(scale),
+        ..gesture(XrGesture::Pinch, phase, &middle)
+    }
+}
+
+fn hand_index(hand: Hand) -> usize {
+    match hand {
+        Hand::Left => 0,
+        Hand::Right => 1,
+    }
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f32>().sqrt()
+}
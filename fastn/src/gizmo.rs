@@ -0,0 +1,820 @@
+//! Manipulation handles for apps
+//!
+//! Handles let people drag entities with the mouse or a finger: a knob that
+//! slides along a rail, a dial that turns, a photo frame that grows. Each
+//! handle constrains how its entity moves and reports the new value to the
+//! app:
+//!
+//! ```rust,ignore
+//! use fastn::{RealityViewContent, RotateHandle, ScaleHandle, TranslateHandle};
+//! use std::f32::consts::PI;
+//!
+//! fn make_content(content: &mut RealityViewContent) {
+//!     // ... add the "knob", "dial" and "frame" entities
+//!     content.add_handle(
+//!         TranslateHandle::new("knob")
+//!             .lock_y()
+//!             .lock_z()
+//!             .bounds([-0.5, 0.0, 0.0], [0.5, 0.0, 0.0])
+//!             .snap(0.1)
+//!             .on_change(|ctx, position| ctx.announce(format!("{:.1}", position[0]))),
+//!     );
+//!     content.add_handle(
+//!         RotateHandle::new("dial", [0.0, 0.0, 1.0])
+//!             .range(0.0, PI)
+//!             .snap(PI / 12.0)
+//!             .on_end(|ctx, angle| ctx.announce(format!("{:.0} degrees", angle.to_degrees()))),
+//!     );
+//!     content.add_handle(ScaleHandle::new("frame").lock_z().range(0.5, 2.0));
+//! }
+//! ```
+//!
+//! - `TranslateHandle`: the value is the entity's position. With one free
+//!   axis the entity slides along it, with two it moves in their plane, and
+//!   with none locked in the plane facing the camera. `bounds` clamps and
+//!   `snap` rounds each axis to a grid.
+//! - `RotateHandle`: the value is the angle (radians) turned around the axis
+//!   since the app started; without a `range` a dial can turn past a full
+//!   turn.
+//! - `ScaleHandle`: the value is the factor from the entity's starting size,
+//!   growing as the pointer moves away from its center. Locked axes keep
+//!   their size.
+//!
+//! A press is hit-tested with `SceneCommand::RequestHitTest`, or picked in
+//! the core on shells that don't answer hit tests (where only entities
+//! whose shape the core knows can be grabbed, see `Scene`). When the press
+//! lands on an entity with a handle, the entity follows the pointer (one
+//! `SetTransform` per move) until it is released. Pointer rays come from the
+//! camera controller's pose and the viewport reported in `Init` and
+//! `Resize`. Positions and axes are in the entity's parent space, which is
+//! the world for top-level entities.
+
+use crate::animation::{axis_angle, direction_to_local, multiply, normalize, point_to_local};
+use crate::{Animations, CameraController, EventContext, FeatureFlags, Scene};
+use fastn_protocol::*;
+use std::f32::consts::{PI, TAU};
+use std::rc::Rc;
+
+/// Prefix of the hit-test requests sent for presses
+const GIZMO_REQUEST_PREFIX: &str = "gizmo-";
+
+/// Viewport until the shell reports one (the native shell's window size)
+const DEFAULT_VIEWPORT: (f32, f32) = (1280.0, 720.0);
+
+/// Smallest scale factor a drag can reach, so the entity can still be grabbed
+const MIN_SCALE_FACTOR: f32 = 0.01;
+
+type ValueFn<T> = dyn Fn(&mut EventContext, T);
+
+/// Called with a handle's value
+struct Callback<T>(Rc<ValueFn<T>>);
+
+impl<T> Clone for Callback<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> std::fmt::Debug for Callback<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+/// Moves an entity, along one axis, in a plane or freely.
+#[derive(Debug, Clone)]
+pub struct TranslateHandle {
+    entity_id: String,
+    locked: [bool; 3],
+    bounds: Option<([f32; 3], [f32; 3])>,
+    snap: Option<f32>,
+    on_change: Option<Callback<[f32; 3]>>,
+    on_end: Option<Callback<[f32; 3]>>,
+}
+
+impl TranslateHandle {
+    /// A handle for the entity with this ID.
+    pub fn new(entity_id: impl Into<String>) -> Self {
+        Self {
+            entity_id: entity_id.into(),
+            locked: [false; 3],
+            bounds: None,
+            snap: None,
+            on_change: None,
+            on_end: None,
+        }
+    }
+
+    /// Keep the X coordinate.
+    pub fn lock_x(mut self) -> Self {
+        self.locked[0] = true;
+        self
+    }
+
+    /// Keep the Y coordinate.
+    pub fn lock_y(mut self) -> Self {
+        self.locked[1] = true;
+        self
+    }
+
+    /// Keep the Z coordinate.
+    pub fn lock_z(mut self) -> Self {
+        self.locked[2] = true;
+        self
+    }
+
+    /// Keep the position within a box (per axis, for the free axes).
+    pub fn bounds(mut self, min: [f32; 3], max: [f32; 3]) -> Self {
+        self.bounds = Some((min, max));
+        self
+    }
+
+    /// Round the free coordinates to multiples of `step`.
+    pub fn snap(mut self, step: f32) -> Self {
+        self.snap = (step > 0.0).then_some(step);
+        self
+    }
+
+    /// Run `callback` with the new position whenever the drag moves the
+    /// entity.
+    pub fn on_change(mut self, callback: impl Fn(&mut EventContext, [f32; 3]) + 'static) -> Self {
+        self.on_change = Some(Callback(Rc::new(callback)));
+        self
+    }
+
+    /// Run `callback` with the final position when the drag ends.
+    pub fn on_end(mut self, callback: impl Fn(&mut EventContext, [f32; 3]) + 'static) -> Self {
+        self.on_end = Some(Callback(Rc::new(callback)));
+        self
+    }
+
+    fn constrain(&self, start: [f32; 3], position: [f32; 3]) -> [f32; 3] {
+        std::array::from_fn(|i| {
+            if self.locked[i] {
+                return start[i];
+            }
+            let mut value = snap(position[i], self.snap);
+            if let Some((min, max)) = self.bounds {
+                value = value.clamp(min[i].min(max[i]), max[i].max(min[i]));
+            }
+            value
+        })
+    }
+}
+
+/// Turns an entity around an axis through its center.
+#[derive(Debug, Clone)]
+pub struct RotateHandle {
+    entity_id: String,
+    axis: [f32; 3],
+    range: Option<(f32, f32)>,
+    snap: Option<f32>,
+    /// Radians turned so far
+    angle: f32,
+    on_change: Option<Callback<f32>>,
+    on_end: Option<Callback<f32>>,
+}
+
+impl RotateHandle {
+    /// A handle turning the entity with this ID around `axis`.
+    pub fn new(entity_id: impl Into<String>, axis: [f32; 3]) -> Self {
+        Self {
+            entity_id: entity_id.into(),
+            axis: normalize3(axis),
+            range: None,
+            snap: None,
+            angle: 0.0,
+            on_change: None,
+            on_end: None,
+        }
+    }
+
+    /// Keep the angle (radians from the starting orientation) between `min`
+    /// and `max`.
+    pub fn range(mut self, min: f32, max: f32) -> Self {
+        self.range = Some((min.min(max), max.max(min)));
+        self
+    }
+
+    /// Round the angle to multiples of `step` radians.
+    pub fn snap(mut self, step: f32) -> Self {
+        self.snap = (step > 0.0).then_some(step);
+        self
+    }
+
+    /// Run `callback` with the new angle whenever the drag turns the entity.
+    pub fn on_change(mut self, callback: impl Fn(&mut EventContext, f32) + 'static) -> Self {
+        self.on_change = Some(Callback(Rc::new(callback)));
+        self
+    }
+
+    /// Run `callback` with the final angle when the drag ends.
+    pub fn on_end(mut self, callback: impl Fn(&mut EventContext, f32) + 'static) -> Self {
+        self.on_end = Some(Callback(Rc::new(callback)));
+        self
+    }
+}
+
+/// Grows and shrinks an entity.
+#[derive(Debug, Clone)]
+pub struct ScaleHandle {
+    entity_id: String,
+    locked: [bool; 3],
+    range: Option<(f32, f32)>,
+    snap: Option<f32>,
+    /// Factor from the starting size
+    factor: f32,
+    on_change: Option<Callback<f32>>,
+    on_end: Option<Callback<f32>>,
+}
+
+impl ScaleHandle {
+    /// A handle for the entity with this ID.
+    pub fn new(entity_id: impl Into<String>) -> Self {
+        Self {
+            entity_id: entity_id.into(),
+            locked: [false; 3],
+            range: None,
+            snap: None,
+            factor: 1.0,
+            on_change: None,
+            on_end: None,
+        }
+    }
+
+    /// Keep the X size.
+    pub fn lock_x(mut self) -> Self {
+        self.locked[0] = true;
+        self
+    }
+
+    /// Keep the Y size.
+    pub fn lock_y(mut self) -> Self {
+        self.locked[1] = true;
+        self
+    }
+
+    /// Keep the Z size.
+    pub fn lock_z(mut self) -> Self {
+        self.locked[2] = true;
+        self
+    }
+
+    /// Keep the factor between `min` and `max`.
+    pub fn range(mut self, min: f32, max: f32) -> Self {
+        self.range = Some((min.min(max), max.max(min)));
+        self
+    }
+
+    /// Round the factor to multiples of `step`.
+    pub fn snap(mut self, step: f32) -> Self {
+        self.snap = (step > 0.0).then_some(step);
+        self
+    }
+
+    /// Run `callback` with the new factor whenever the drag resizes the
+    /// entity.
+    pub fn on_change(mut self, callback: impl Fn(&mut EventContext, f32) + 'static) -> Self {
+        self.on_change = Some(Callback(Rc::new(callback)));
+        self
+    }
+
+    /// Run `callback` with the final factor when the drag ends.
+    pub fn on_end(mut self, callback: impl Fn(&mut EventContext, f32) + 'static) -> Self {
+        self.on_end = Some(Callback(Rc::new(callback)));
+        self
+    }
+}
+
+/// Any manipulation handle, for `RealityViewContent::add_handle`.
+#[derive(Debug, Clone)]
+pub enum Handle {
+    Translate(TranslateHandle),
+    Rotate(RotateHandle),
+    Scale(ScaleHandle),
+}
+
+impl Handle {
+    /// ID of the entity the handle moves
+    pub fn entity_id(&self) -> &str {
+        match self {
+            Handle::Translate(handle) => &handle.entity_id,
+            Handle::Rotate(handle) => &handle.entity_id,
+            Handle::Scale(handle) => &handle.entity_id,
+        }
+    }
+}
+
+impl From<TranslateHandle> for Handle {
+    fn from(handle: TranslateHandle) -> Self {
+        Handle::Translate(handle)
+    }
+}
+
+impl From<RotateHandle> for Handle {
+    fn from(handle: RotateHandle) -> Self {
+        Handle::Rotate(handle)
+    }
+}
+
+impl From<ScaleHandle> for Handle {
+    fn from(handle: ScaleHandle) -> Self {
+        Handle::Scale(handle)
+    }
+}
+
+/// What is pressing: the mouse or one touch
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pointer {
+    Mouse,
+    Touch(u32),
+}
+
+/// A press waiting for its hit test
+#[derive(Debug)]
+struct Press {
+    pointer: Pointer,
+    request_id: String,
+    at: (f32, f32),
+    /// Where the pointer is now
+    current: (f32, f32),
+}
+
+/// Where a drag grabbed its handle
+#[derive(Debug)]
+enum Grab {
+    /// Point grabbed on the line or plane the entity moves in
+    Translate { point: [f32; 3] },
+    /// Orientation at angle 0, and the pointer's last angle around the axis
+    Rotate { base: [f32; 4], last: f32, start_angle: f32, turned: f32 },
+    /// Size at factor 1, and the pointer's distance from the center
+    Scale { base: [f32; 3], distance: f32, start_factor: f32 },
+}
+
+#[derive(Debug)]
+struct Drag {
+    pointer: Pointer,
+    handle: usize,
+    /// Transform when the drag started
+    start: Transform,
+    grab: Grab,
+}
+
+/// The app's manipulation handles and the drag in progress.
+#[derive(Debug)]
+pub struct Gizmos {
+    handles: Vec<Handle>,
+    viewport: (f32, f32),
+    press: Option<Press>,
+    drag: Option<Drag>,
+    next_request: u64,
+    /// Whether presses are picked in the core, for shells that don't
+    /// answer hit tests
+    core_picking: bool,
+    /// The scene as of the event being handled, for callbacks
+    scene: Rc<Scene>,
+    /// The flags as of the event being handled, for callbacks
+    flags: Rc<FeatureFlags>,
+    /// Entities a callback asked the camera to frame
+    zoom_to_fit: Option<Vec<String>>,
+}
+
+impl Gizmos {
+    pub fn new(handles: Vec<Handle>) -> Self {
+        Self {
+            handles,
+            viewport: DEFAULT_VIEWPORT,
+            press: None,
+            drag: None,
+            next_request: 0,
+            core_picking: false,
+            scene: Rc::default(),
+            flags: Rc::default(),
+            zoom_to_fit: None,
+        }
+    }
+
+    /// Process an event: start drags on handles, move their entities and run
+    /// the handles' callbacks.
+    pub fn handle_event(
+        &mut self,
+        event: &Event,
+        camera: &mut CameraController,
+        animations: &mut Animations,
+        scene: &Rc<Scene>,
+        flags: &Rc<FeatureFlags>,
+    ) -> Vec<Command> {
+        if self.handles.is_empty() {
+            return vec![];
+        }
+        self.scene = scene.clone();
+        self.flags = flags.clone();
+        let commands = self.handle_pointer(event, camera, animations);
+        if let Some(volume_ids) = self.zoom_to_fit.take() {
+            camera.zoom_to_fit(scene, &volume_ids);
+        }
+        commands
+    }
+
+    fn handle_pointer(&mut self, event: &Event, camera: &CameraController, animations: &mut Animations) -> Vec<Command> {
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                self.viewport = (init.viewport_width as f32, init.viewport_height as f32);
+                self.core_picking = !init.features.iter().any(|f| f == FEATURE_HIT_TEST);
+                vec![]
+            }
+            Event::Lifecycle(LifecycleEvent::Resize(resize)) => {
+                self.viewport = (resize.width as f32, resize.height as f32);
+                vec![]
+            }
+            Event::Input(InputEvent::Mouse(MouseEvent::Down(data))) if data.button == MouseButton::Left => {
+                self.pick(Pointer::Mouse, (data.x, data.y), camera, animations)
+            }
+            Event::Input(InputEvent::Mouse(MouseEvent::Move(data))) => {
+                self.move_to(Pointer::Mouse, (data.x, data.y), camera, animations)
+            }
+            Event::Input(InputEvent::Mouse(MouseEvent::Up(data))) if data.button == MouseButton::Left => {
+                self.release(Pointer::Mouse, animations)
+            }
+            Event::Input(InputEvent::Touch(TouchEvent::Start(data))) => match data.touches.first() {
+                Some(touch) => self.pick(Pointer::Touch(touch.id), (touch.x, touch.y), camera, animations),
+                None => vec![],
+            },
+            Event::Input(InputEvent::Touch(TouchEvent::Move(data))) => data
+                .touches
+                .iter()
+                .flat_map(|touch| self.move_to(Pointer::Touch(touch.id), (touch.x, touch.y), camera, animations))
+                .collect(),
+            Event::Input(InputEvent::Touch(TouchEvent::End(data) | TouchEvent::Cancel(data))) => data
+                .touches
+                .iter()
+                .flat_map(|touch| self.release(Pointer::Touch(touch.id), animations))
+                .collect(),
+            Event::Scene(SceneEvent::HitTestResult { request_id, hit }) => {
+                let Some(press) = self.press.take_if(|press| &press.request_id == request_id) else {
+                    return vec![];
+                };
+                match hit {
+                    Some(hit) => self.start_drag(press, &hit.volume_id, camera, animations),
+                    None => vec![],
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Start a press, picked in the core if the shell doesn't answer hit
+    /// tests
+    fn pick(
+        &mut self,
+        pointer: Pointer,
+        at: (f32, f32),
+        camera: &CameraController,
+        animations: &mut Animations,
+    ) -> Vec<Command> {
+        let Some(command) = self.press(pointer, at) else {
+            return vec![];
+        };
+        if !self.core_picking {
+            return vec![command];
+        }
+        let Some(press) = self.press.take() else {
+            return vec![];
+        };
+        let (origin, direction) = self.ray(camera, at);
+        match self.scene.raycast(origin, direction) {
+            Some(hit) => self.start_drag(press, &hit.entity, camera, animations),
+            None => vec![],
+        }
+    }
+
+    /// Hit-test a press, unless another pointer is already pressing
+    fn press(&mut self, pointer: Pointer, at: (f32, f32)) -> Option<Command> {
+        if self.press.is_some() || self.drag.is_some() {
+            return None;
+        }
+        self.next_request += 1;
+        let request_id = format!("{}{}", GIZMO_REQUEST_PREFIX, self.next_request);
+        self.press = Some(Press {
+            pointer,
+            request_id: request_id.clone(),
+            at,
+            current: at,
+        });
+        Some(Command::Scene(SceneCommand::RequestHitTest(HitTestRequest {
+            request_id,
+            source: HitTestSource::Screen { x: at.0, y: at.1 },
+        })))
+    }
+
+    fn start_drag(
+        &mut self,
+        press: Press,
+        volume_id: &str,
+        camera: &CameraController,
+        animations: &mut Animations,
+    ) -> Vec<Command> {
+        let Some(index) = self.handles.iter().position(|handle| handle.entity_id() == volume_id) else {
+            return vec![];
+        };
+        let Some(start) = animations.transform(volume_id).cloned() else {
+            return vec![];
+        };
+        let (ray, forward) = self.parent_ray(camera, press.at, volume_id, animations);
+        let center = start.position;
+        let grab = match &self.handles[index] {
+            Handle::Translate(handle) => {
+                let Some(point) = translate_target(handle.locked, center, forward, ray) else {
+                    return vec![];
+                };
+                Grab::Translate { point }
+            }
+            Handle::Rotate(handle) => {
+                let Some(last) = angle_around(handle.axis, center, ray) else {
+                    return vec![];
+                };
+                Grab::Rotate {
+                    base: multiply(axis_angle(handle.axis, -handle.angle), start.rotation),
+                    last,
+                    start_angle: handle.angle,
+                    turned: 0.0,
+                }
+            }
+            Handle::Scale(handle) => {
+                let Some(point) = intersect_plane(ray, center, forward) else {
+                    return vec![];
+                };
+                let distance = length(sub(point, center));
+                if distance <= f32::EPSILON {
+                    return vec![];
+                }
+                Grab::Scale {
+                    base: start.scale.map(|s| s / handle.factor),
+                    distance,
+                    start_factor: handle.factor,
+                }
+            }
+        };
+        self.drag = Some(Drag {
+            pointer: press.pointer,
+            handle: index,
+            start,
+            grab,
+        });
+        // The pointer may have moved while the hit test was out
+        self.move_to(press.pointer, press.current, camera, animations)
+    }
+
+    fn move_to(
+        &mut self,
+        pointer: Pointer,
+        at: (f32, f32),
+        camera: &CameraController,
+        animations: &mut Animations,
+    ) -> Vec<Command> {
+        if let Some(press) = self.press.as_mut().filter(|press| press.pointer == pointer) {
+            press.current = at;
+            return vec![];
+        }
+        let Some(entity_id) = self.drag.as_ref().filter(|drag| drag.pointer == pointer).map(|drag| {
+            self.handles[drag.handle].entity_id().to_string()
+        }) else {
+            return vec![];
+        };
+        let (ray, forward) = self.parent_ray(camera, at, &entity_id, animations);
+        let Some(drag) = self.drag.as_mut() else {
+            return vec![];
+        };
+        let center = drag.start.position;
+        let mut transform = drag.start.clone();
+        let notify = match (&mut self.handles[drag.handle], &mut drag.grab) {
+            (Handle::Translate(handle), Grab::Translate { point }) => {
+                let Some(target) = translate_target(handle.locked, center, forward, ray) else {
+                    return vec![];
+                };
+                let moved = std::array::from_fn(|i| center[i] + target[i] - point[i]);
+                let position = handle.constrain(center, moved);
+                let current = animations.transform(&handle.entity_id).map(|t| t.position);
+                if current == Some(position) {
+                    return vec![];
+                }
+                transform.position = position;
+                Notify::Position(handle.on_change.clone(), position)
+            }
+            (Handle::Rotate(handle), Grab::Rotate { base, last, start_angle, turned }) => {
+                let Some(angle) = angle_around(handle.axis, center, ray) else {
+                    return vec![];
+                };
+                // Unwrap across the ±π seam so dials can keep turning
+                let mut step = angle - *last;
+                if step > PI {
+                    step -= TAU;
+                } else if step < -PI {
+                    step += TAU;
+                }
+                *last = angle;
+                *turned += step;
+                let mut value = snap(*start_angle + *turned, handle.snap);
+                if let Some((min, max)) = handle.range {
+                    value = value.clamp(min, max);
+                }
+                if value == handle.angle {
+                    return vec![];
+                }
+                handle.angle = value;
+                transform.rotation = normalize(multiply(axis_angle(handle.axis, value), *base));
+                Notify::Value(handle.on_change.clone(), value)
+            }
+            (Handle::Scale(handle), Grab::Scale { base, distance, start_factor }) => {
+                let Some(point) = intersect_plane(ray, center, forward) else {
+                    return vec![];
+                };
+                let mut value = snap(*start_factor * length(sub(point, center)) / *distance, handle.snap);
+                if let Some((min, max)) = handle.range {
+                    value = value.clamp(min, max);
+                }
+                value = value.max(MIN_SCALE_FACTOR);
+                if value == handle.factor {
+                    return vec![];
+                }
+                handle.factor = value;
+                transform.scale =
+                    std::array::from_fn(|i| if handle.locked[i] { drag.start.scale[i] } else { base[i] * value });
+                Notify::Value(handle.on_change.clone(), value)
+            }
+            _ => return vec![],
+        };
+
+        let mut commands = vec![Command::Scene(SceneCommand::SetTransform(SetTransformData {
+            volume_id: entity_id.clone(),
+            transform: transform.clone(),
+            animate: None,
+        }))];
+        animations.set_transform(&entity_id, transform);
+        commands.extend(notify.run(animations, &self.scene, &self.flags, &mut self.zoom_to_fit));
+        commands
+    }
+
+    /// End the press or drag of `pointer`
+    fn release(&mut self, pointer: Pointer, animations: &mut Animations) -> Vec<Command> {
+        if self.press.as_ref().is_some_and(|press| press.pointer == pointer) {
+            self.press = None;
+        }
+        let Some(drag) = self.drag.take_if(|drag| drag.pointer == pointer) else {
+            return vec![];
+        };
+        let notify = match &self.handles[drag.handle] {
+            Handle::Translate(handle) => {
+                let position = animations.transform(&handle.entity_id).map_or(drag.start.position, |t| t.position);
+                Notify::Position(handle.on_end.clone(), position)
+            }
+            Handle::Rotate(handle) => Notify::Value(handle.on_end.clone(), handle.angle),
+            Handle::Scale(handle) => Notify::Value(handle.on_end.clone(), handle.factor),
+        };
+        notify.run(animations, &self.scene, &self.flags, &mut self.zoom_to_fit)
+    }
+
+    fn ray(&self, camera: &CameraController, at: (f32, f32)) -> ([f32; 3], [f32; 3]) {
+        camera.screen_ray(at.0, at.1, self.viewport.0.max(1.0), self.viewport.1.max(1.0))
+    }
+
+    /// The pointer ray and the camera's forward direction in the space of
+    /// the entity's parent, where its handle works
+    fn parent_ray(
+        &self,
+        camera: &CameraController,
+        at: (f32, f32),
+        entity_id: &str,
+        animations: &Animations,
+    ) -> (([f32; 3], [f32; 3]), [f32; 3]) {
+        let (origin, direction) = self.ray(camera, at);
+        match animations.parent_transform(entity_id) {
+            Some(frame) => (
+                (point_to_local(&frame, origin), direction_to_local(&frame, direction)),
+                direction_to_local(&frame, camera.forward()),
+            ),
+            None => ((origin, direction), camera.forward()),
+        }
+    }
+}
+
+/// A handle callback to run with a new value
+enum Notify {
+    Position(Option<Callback<[f32; 3]>>, [f32; 3]),
+    Value(Option<Callback<f32>>, f32),
+}
+
+impl Notify {
+    /// Run the callback and apply what it did, keeping what it asked the
+    /// camera to frame
+    fn run(
+        self,
+        animations: &mut Animations,
+        scene: &Rc<Scene>,
+        flags: &Rc<FeatureFlags>,
+        zoom_to_fit: &mut Option<Vec<String>>,
+    ) -> Vec<Command> {
+        let mut ctx = EventContext::new(scene, flags);
+        match self {
+            Notify::Position(Some(Callback(callback)), position) => callback(&mut ctx, position),
+            Notify::Value(Some(Callback(callback)), value) => callback(&mut ctx, value),
+            _ => {}
+        }
+        if let Some(volume_ids) = ctx.take_zoom_to_fit() {
+            *zoom_to_fit = Some(volume_ids);
+        }
+        let (mut commands, animator) = ctx.into_parts();
+        commands.extend(animations.apply(animator));
+        commands
+    }
+}
+
+/// Round `value` to a multiple of `step`
+fn snap(value: f32, step: Option<f32>) -> f32 {
+    match step {
+        Some(step) => (value / step).round() * step,
+        None => value,
+    }
+}
+
+/// Where the ray meets the line or plane a translate handle moves in
+fn translate_target(
+    locked: [bool; 3],
+    center: [f32; 3],
+    forward: [f32; 3],
+    ray: ([f32; 3], [f32; 3]),
+) -> Option<[f32; 3]> {
+    let free: Vec<usize> = (0..3).filter(|&i| !locked[i]).collect();
+    match free.as_slice() {
+        [] => Some(center),
+        [axis] => {
+            let mut direction = [0.0; 3];
+            direction[*axis] = 1.0;
+            closest_on_line(ray, center, direction)
+        }
+        [_, _] => {
+            let mut normal = [0.0; 3];
+            normal[(0..3).find(|&i| locked[i])?] = 1.0;
+            intersect_plane(ray, center, normal)
+        }
+        _ => intersect_plane(ray, center, forward),
+    }
+}
+
+/// Angle of the ray's hit on the plane across `axis` through `center`
+fn angle_around(axis: [f32; 3], center: [f32; 3], ray: ([f32; 3], [f32; 3])) -> Option<f32> {
+    let point = intersect_plane(ray, center, axis)?;
+    // Any two directions across the axis, at a right angle to each other
+    let helper = if axis[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+    let u = normalize3(cross(helper, axis));
+    let v = cross(axis, u);
+    let offset = sub(point, center);
+    Some(dot(offset, v).atan2(dot(offset, u)))
+}
+
+fn intersect_plane(ray: ([f32; 3], [f32; 3]), point: [f32; 3], normal: [f32; 3]) -> Option<[f32; 3]> {
+    let (origin, direction) = ray;
+    let facing = dot(direction, normal);
+    if facing.abs() < 1e-4 {
+        return None;
+    }
+    let t = dot(sub(point, origin), normal) / facing;
+    (t > 0.0).then(|| std::array::from_fn(|i| origin[i] + direction[i] * t))
+}
+
+/// Point on the line through `point` along unit `direction` closest to the ray
+fn closest_on_line(ray: ([f32; 3], [f32; 3]), point: [f32; 3], direction: [f32; 3]) -> Option<[f32; 3]> {
+    let (origin, ray_direction) = ray;
+    let alignment = dot(ray_direction, direction);
+    let denominator = 1.0 - alignment * alignment;
+    if denominator < 1e-4 {
+        return None;
+    }
+    let offset = sub(origin, point);
+    let t = (dot(offset, direction) - alignment * dot(offset, ray_direction)) / denominator;
+    Some(std::array::from_fn(|i| point[i] + direction[i] * t))
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn length(v: [f32; 3]) -> f32 {
+    dot(v, v).sqrt()
+}
+
+fn normalize3(v: [f32; 3]) -> [f32; 3] {
+    let length = length(v);
+    match length > 0.0 {
+        true => v.map(|c| c / length),
+        false => [0.0, 1.0, 0.0],
+    }
+}
@@ -0,0 +1,387 @@
+//! Event callbacks for interactive apps
+//!
+//! Apps register closures on their content instead of processing raw events:
+//!
+//! ```rust,ignore
+//! use fastn::{Animation, ModelEntity, MeshResource, RealityViewContent, SimpleMaterial};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     let cube = ModelEntity::with_id("cube", MeshResource::generate_box(0.5), SimpleMaterial::new())
+//!         .position(0.0, 0.0, -2.0);
+//!     content.add(cube);
+//!
+//!     content.on_tap("cube", |ctx| ctx.animate("cube", Animation::spin([0.0, 1.0, 0.0]).turns(1)));
+//!     content.on_key_down(|ctx, key| {
+//!         if key.code == "Space" && !key.repeat {
+//!             ctx.announce("Jump!");
+//!         }
+//!     });
+//! }
+//! ```
+//!
+//! Callbacks get an `EventContext` to start animations and send commands.
+//! They run after the framework's own handling (camera, bookmarks, the debug
+//! HUD), which keeps its keys.
+//!
+//! Taps are `Tap` gestures on the volume, plus screen-reader activations.
+//! `on_gesture` callbacks get every gesture that starts on a volume, and
+//! `on_any_gesture` ones every gesture. Gestures are recognized in the core
+//! from mouse, touch and hand input unless the shell sends its own (see the
+//! `gesture` module). The volume under a press is found with a
+//! `SceneCommand::RequestHitTest`; shells without `FEATURE_HIT_TEST` get
+//! their presses picked in the core, against the entities whose shape it
+//! knows (see `Scene`).
+//!
+//! During XR sessions, `on_gaze` callbacks run whenever the entity the user
+//! looks at changes, found the same way.
+//!
+//! `on_flag_change` callbacks run when the remote config changes a flag (see
+//! `FeatureFlags`).
+//!
+//! `on_collision_start` and `on_collision_end` callbacks run when physics
+//! bodies touch and part (see the `physics` module).
+//!
+//! `on_panel_pointer` callbacks run for pointer input on a panel (see the
+//! `panel` module).
+//!
+//! `ctx.zoom_to_fit` frames entities with the default camera controller
+//! once the callbacks are done (see the `camera` module).
+
+use crate::camera::CameraController;
+use crate::gesture::{GestureRecognizer, GestureThresholds};
+use crate::{announce, capture, Animation, Animator, FeatureFlags, FlagValue, RaycastHit, Scene};
+use fastn_protocol::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type KeyCallback = Rc<dyn Fn(&mut EventContext, &KeyEventData)>;
+type FrameCallback = Rc<dyn Fn(&mut EventContext, &FrameEvent)>;
+type TapCallback = Rc<dyn Fn(&mut EventContext)>;
+type EventCallback = Rc<dyn Fn(&mut EventContext, &Event)>;
+type CaptureCallback = Rc<dyn Fn(&mut EventContext, Result<&CaptureSavedData, &CaptureFailedData>)>;
+type GazeCallback = Rc<dyn Fn(&mut EventContext, Option<&RaycastHit>)>;
+type FlagCallback = Rc<dyn Fn(&mut EventContext, &FlagValue)>;
+type CollisionCallback = Rc<dyn Fn(&mut EventContext, &CollisionData)>;
+type PanelPointerCallback = Rc<dyn Fn(&mut EventContext, &PanelPointerData)>;
+type GestureCallback = Rc<dyn Fn(&mut EventContext, &XrGestureData)>;
+
+/// What callbacks can do in response to an event.
+#[derive(Debug, Default)]
+pub struct EventContext {
+    animator: Animator,
+    commands: Vec<Command>,
+    scene: Rc<Scene>,
+    flags: Rc<FeatureFlags>,
+    /// Entities to frame with the camera afterwards
+    zoom_to_fit: Option<Vec<String>>,
+}
+
+impl EventContext {
+    pub(crate) fn new(scene: &Rc<Scene>, flags: &Rc<FeatureFlags>) -> Self {
+        Self {
+            scene: scene.clone(),
+            flags: flags.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// The scene's shapes, to cast rays against.
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    /// The app's feature flags, as the remote config last set them.
+    pub fn flags(&self) -> &FeatureFlags {
+        &self.flags
+    }
+
+    /// Queue an animation for an entity, after the ones it already has.
+    pub fn animate(&mut self, entity_id: &str, animation: Animation) {
+        self.animator.animate(entity_id, animation);
+    }
+
+    /// Drop an entity's queued animations and end a spin.
+    pub fn stop_animations(&mut self, entity_id: &str) {
+        self.animator.stop(entity_id);
+    }
+
+    /// Have the screen reader announce a message.
+    pub fn announce(&mut self, message: impl Into<String>) {
+        self.commands.push(announce(message));
+    }
+
+    /// Save a photo of what the user sees (passthrough and virtual content)
+    /// to device storage. `on_capture` gets the result.
+    pub fn capture_photo(&mut self, capture_id: &str) {
+        self.commands.push(capture::photo(capture_id));
+    }
+
+    /// Start recording what the user sees, until `stop_capture` or for at
+    /// most `max_secs`. `on_capture` gets the result.
+    pub fn capture_video(&mut self, capture_id: &str, max_secs: Option<f32>) {
+        self.commands.push(capture::video(capture_id, max_secs));
+    }
+
+    /// Stop a video recording started with `capture_video`.
+    pub fn stop_capture(&mut self, capture_id: &str) {
+        self.commands.push(Command::Media(MediaCommand::StopCapture {
+            capture_id: capture_id.to_string(),
+        }));
+    }
+
+    /// Start an audio source from the beginning.
+    pub fn play_audio(&mut self, source_id: &str) {
+        self.commands.push(Command::Audio(AudioCommand::Play {
+            source_id: source_id.to_string(),
+        }));
+    }
+
+    /// Stop an audio source.
+    pub fn stop_audio(&mut self, source_id: &str) {
+        self.commands.push(Command::Audio(AudioCommand::Stop {
+            source_id: source_id.to_string(),
+        }));
+    }
+
+    /// Set an audio source's own level, 0 to 1.
+    pub fn set_audio_volume(&mut self, source_id: &str, volume: f32) {
+        self.commands.push(Command::Audio(AudioCommand::SetVolume {
+            source_id: source_id.to_string(),
+            volume: volume.clamp(0.0, 1.0),
+        }));
+    }
+
+    /// Set whether an audio source starts over at the end.
+    pub fn set_audio_looping(&mut self, source_id: &str, looping: bool) {
+        self.commands.push(Command::Audio(AudioCommand::SetLooping {
+            source_id: source_id.to_string(),
+            looping,
+        }));
+    }
+
+    /// Replace what a panel shows.
+    pub fn set_panel_content(&mut self, panel_id: &str, content: PanelContent) {
+        self.commands.push(Command::Panel(PanelCommand::SetContent {
+            panel_id: panel_id.to_string(),
+            content,
+        }));
+    }
+
+    /// Send a protocol command to the shell.
+    pub fn send(&mut self, command: Command) {
+        self.commands.push(command);
+    }
+
+    /// Move the camera back until the entities fill the view, and orbit
+    /// them in orbit mode. Entities whose shape the core doesn't know (see
+    /// `Scene`) are left out.
+    pub fn zoom_to_fit(&mut self, volume_ids: &[&str]) {
+        self.zoom_to_fit = Some(volume_ids.iter().map(|id| id.to_string()).collect());
+    }
+
+    /// The entities `zoom_to_fit` asked to frame, for the camera
+    pub(crate) fn take_zoom_to_fit(&mut self) -> Option<Vec<String>> {
+        self.zoom_to_fit.take()
+    }
+
+    /// The commands sent and the animations started, for `Animations` to
+    /// apply
+    pub(crate) fn into_parts(self) -> (Vec<Command>, Animator) {
+        (self.commands, self.animator)
+    }
+}
+
+/// The callbacks an app registered, and the gestures in progress.
+#[derive(Clone, Default)]
+pub struct EventHandlers {
+    key_down: Vec<KeyCallback>,
+    key_up: Vec<KeyCallback>,
+    frame: Vec<FrameCallback>,
+    tap: HashMap<String, Vec<TapCallback>>,
+    event: Vec<EventCallback>,
+    capture: Vec<CaptureCallback>,
+    gaze: Vec<GazeCallback>,
+    flag_change: HashMap<String, Vec<FlagCallback>>,
+    collision_start: Vec<CollisionCallback>,
+    collision_end: Vec<CollisionCallback>,
+    panel_pointer: HashMap<String, Vec<PanelPointerCallback>>,
+    gesture: HashMap<String, Vec<GestureCallback>>,
+    any_gesture: Vec<GestureCallback>,
+    /// Entity the user looked at last
+    gazed: Option<String>,
+    gestures: GestureRecognizer,
+}
+
+impl std::fmt::Debug for EventHandlers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventHandlers")
+            .field("key_down", &self.key_down.len())
+            .field("key_up", &self.key_up.len())
+            .field("frame", &self.frame.len())
+            .field("tap", &self.tap.keys().collect::<Vec<_>>())
+            .field("event", &self.event.len())
+            .field("capture", &self.capture.len())
+            .field("gaze", &self.gaze.len())
+            .field("flag_change", &self.flag_change.keys().collect::<Vec<_>>())
+            .field("collision_start", &self.collision_start.len())
+            .field("collision_end", &self.collision_end.len())
+            .field("panel_pointer", &self.panel_pointer.keys().collect::<Vec<_>>())
+            .field("gesture", &self.gesture.keys().collect::<Vec<_>>())
+            .field("any_gesture", &self.any_gesture.len())
+            .field("gestures", &self.gestures)
+            .finish()
+    }
+}
+
+impl EventHandlers {
+    pub(crate) fn on_key_down(&mut self, callback: impl Fn(&mut EventContext, &KeyEventData) + 'static) {
+        self.key_down.push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_key_up(&mut self, callback: impl Fn(&mut EventContext, &KeyEventData) + 'static) {
+        self.key_up.push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_frame(&mut self, callback: impl Fn(&mut EventContext, &FrameEvent) + 'static) {
+        self.frame.push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_tap(&mut self, volume_id: &str, callback: impl Fn(&mut EventContext) + 'static) {
+        self.tap.entry(volume_id.to_string()).or_default().push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_event(&mut self, callback: impl Fn(&mut EventContext, &Event) + 'static) {
+        self.event.push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_capture(
+        &mut self,
+        callback: impl Fn(&mut EventContext, Result<&CaptureSavedData, &CaptureFailedData>) + 'static,
+    ) {
+        self.capture.push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_gaze(&mut self, callback: impl Fn(&mut EventContext, Option<&RaycastHit>) + 'static) {
+        self.gaze.push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_flag_change(&mut self, name: &str, callback: impl Fn(&mut EventContext, &FlagValue) + 'static) {
+        self.flag_change.entry(name.to_string()).or_default().push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_collision_start(&mut self, callback: impl Fn(&mut EventContext, &CollisionData) + 'static) {
+        self.collision_start.push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_collision_end(&mut self, callback: impl Fn(&mut EventContext, &CollisionData) + 'static) {
+        self.collision_end.push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_panel_pointer(
+        &mut self,
+        panel_id: &str,
+        callback: impl Fn(&mut EventContext, &PanelPointerData) + 'static,
+    ) {
+        self.panel_pointer.entry(panel_id.to_string()).or_default().push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_gesture(&mut self, volume_id: &str, callback: impl Fn(&mut EventContext, &XrGestureData) + 'static) {
+        self.gesture.entry(volume_id.to_string()).or_default().push(Rc::new(callback));
+    }
+
+    pub(crate) fn on_any_gesture(&mut self, callback: impl Fn(&mut EventContext, &XrGestureData) + 'static) {
+        self.any_gesture.push(Rc::new(callback));
+    }
+
+    pub(crate) fn set_gesture_thresholds(&mut self, thresholds: GestureThresholds) {
+        self.gestures.set_thresholds(thresholds);
+    }
+
+    /// Run the callbacks for an event, and frame what they asked the camera
+    /// to. Returns the commands they sent and the animations they started,
+    /// for `Animations` to apply.
+    pub fn handle_event(
+        &mut self,
+        event: &Event,
+        scene: &Rc<Scene>,
+        flags: &Rc<FeatureFlags>,
+        camera: &mut CameraController,
+    ) -> (Vec<Command>, Animator) {
+        let mut ctx = EventContext::new(scene, flags);
+        let listening = !self.tap.is_empty() || !self.gesture.is_empty() || !self.any_gesture.is_empty();
+        let (hit_tests, gestures) = self.gestures.handle_event(event, listening, scene, camera);
+        ctx.commands.extend(hit_tests);
+        for gesture in &gestures {
+            self.gesture(&mut ctx, gesture);
+        }
+        match event {
+            Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyDown(key))) => {
+                self.key_down.iter().for_each(|callback| callback(&mut ctx, key));
+            }
+            Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyUp(key))) => {
+                self.key_up.iter().for_each(|callback| callback(&mut ctx, key));
+            }
+            Event::Lifecycle(LifecycleEvent::Frame(frame)) => {
+                self.frame.iter().for_each(|callback| callback(&mut ctx, frame));
+            }
+            Event::Accessibility(AccessibilityEvent::Activate { volume_id }) => self.tap(&mut ctx, volume_id),
+            Event::Xr(XrEvent::Gaze(gaze)) if !self.gaze.is_empty() => {
+                let hit = scene.raycast(gaze.origin, gaze.direction);
+                let entity = hit.as_ref().map(|hit| hit.entity.clone());
+                if entity != self.gazed {
+                    self.gazed = entity;
+                    self.gaze.iter().for_each(|callback| callback(&mut ctx, hit.as_ref()));
+                }
+            }
+            Event::Media(MediaEvent::CaptureSaved(saved)) => {
+                self.capture.iter().for_each(|callback| callback(&mut ctx, Ok(saved)));
+            }
+            Event::Media(MediaEvent::CaptureFailed(failed)) => {
+                self.capture.iter().for_each(|callback| callback(&mut ctx, Err(failed)));
+            }
+            Event::Scene(SceneEvent::CollisionStarted(collision)) => {
+                self.collision_start.iter().for_each(|callback| callback(&mut ctx, collision));
+            }
+            Event::Scene(SceneEvent::CollisionEnded(collision)) => {
+                self.collision_end.iter().for_each(|callback| callback(&mut ctx, collision));
+            }
+            Event::Panel(PanelEvent::Pointer(pointer)) => {
+                for callback in self.panel_pointer.get(&pointer.panel_id).into_iter().flatten() {
+                    callback(&mut ctx, pointer);
+                }
+            }
+            _ => {}
+        }
+        for name in flags.changed() {
+            let Some(value) = flags.get(name) else { continue };
+            for callback in self.flag_change.get(name).into_iter().flatten() {
+                callback(&mut ctx, value);
+            }
+        }
+        self.event.iter().for_each(|callback| callback(&mut ctx, event));
+        if let Some(volume_ids) = ctx.take_zoom_to_fit() {
+            camera.zoom_to_fit(scene, &volume_ids);
+        }
+        (ctx.commands, ctx.animator)
+    }
+
+    fn tap(&self, ctx: &mut EventContext, volume_id: &str) {
+        for callback in self.tap.get(volume_id).into_iter().flatten() {
+            callback(ctx);
+        }
+    }
+
+    /// Run the callbacks for a gesture: its volume's, and taps' for a tap
+    fn gesture(&self, ctx: &mut EventContext, gesture: &XrGestureData) {
+        if let Some(volume_id) = &gesture.volume_id {
+            if gesture.gesture == XrGesture::Tap {
+                self.tap(ctx, volume_id);
+            }
+            for callback in self.gesture.get(volume_id).into_iter().flatten() {
+                callback(ctx, gesture);
+            }
+        }
+        self.any_gesture.iter().for_each(|callback| callback(ctx, gesture));
+    }
+}
@@ -28,21 +28,83 @@
 //! | `Entity` | `Entity` |
 //! | `Entity.load(named:)` | `Entity::load(path)` |
 //! | `MeshResource.generateBox(size:)` | `MeshResource::generate_box(size)` |
+//! | `MeshResource.generateText(_:)` | `MeshResource::generate_text(text, font_size)` |
 //! | `SimpleMaterial` | `SimpleMaterial` |
+//! | `CollisionComponent(shapes:)` | `.with_collision(ShapeResource::generate_box(w, h, d))` |
+//! | `PhysicsBodyComponent` | `.with_physics(mass, restitution)` |
 //! | `RealityViewContent` | `RealityViewContent` |
 //! | `content.add(entity)` | `content.add(entity)` |
 
+mod accessibility;
+mod app;
+mod animation;
+mod asset_uri;
+mod atlas;
+mod audio;
+mod bookmark;
 mod camera;
+mod capture;
+mod conventions;
+mod debug_hud;
 mod entity;
+mod gesture;
+mod gizmo;
+mod handlers;
+mod lighting;
 mod material;
 mod mesh;
+mod panel;
+mod physics;
+mod portal;
+mod raycast;
 mod reality_view;
+mod remote_config;
+mod resume;
+mod schedule;
+mod session;
+mod text;
 
 #[doc(hidden)]
 pub mod wasm_bridge;
 
+// Running apps without a shell, for tests
+pub mod test;
+
+// Accessibility labels and the accessibility tree
+pub use accessibility::{announce, AccessibilityComponent, AccessibilityTree};
+
+// Stateful apps
+pub use app::App;
+
+// Declarative transform animations
+pub use animation::{Animation, Animations, Animator, DEFAULT_ANIMATION_SECS};
+
+// Asset URIs and resolvers for app-defined schemes
+pub use asset_uri::{AssetResolver, AssetResolvers, AssetUri, AssetUriError};
+
+// Texture atlases and sprite sheets
+pub use atlas::{AtlasError, TextureAtlas, TextureAtlases};
+
+// Spatial audio sources, mixed for the listener in the core
+pub use audio::{AudioSource, AudioSources, Rolloff};
+
+// Point lights, picked per volume in the core
+pub use lighting::{Lights, PointLight, LIGHTS_PER_VOLUME};
+
+// Spatial bookmarks and teleportation
+pub use bookmark::{Bookmark, Bookmarks, BOOKMARKS_STORAGE_KEY};
+
 // Camera controller for default input handling
-pub use camera::CameraController;
+pub use camera::{CameraAction, CameraController, CameraControls, CameraMode};
+
+// Photo and video capture, and viewfinders showing what it sees
+pub use capture::{Viewfinder, Viewfinders};
+
+// Conversion to and from the shell's coordinate conventions
+pub use conventions::ShellConventions;
+
+// Debug HUD toggling and stats
+pub use debug_hud::{DebugHud, DEBUG_HUD_KEY};
 
 // Re-export the proc macro
 pub use fastn_macros::app;
@@ -50,15 +112,51 @@ pub use fastn_macros::app;
 // Entity types (like RealityKit)
 pub use entity::{Entity, ModelEntity, EntityKind, LoadedEntity};
 
+// Event callbacks for interactive apps
+pub use handlers::{EventContext, EventHandlers};
+
+// Gestures recognized from raw input
+pub use gesture::GestureThresholds;
+
+// Manipulation handles (translate, rotate and scale entities by dragging)
+pub use gizmo::{Gizmos, Handle, RotateHandle, ScaleHandle, TranslateHandle};
+
 // Mesh generation (like MeshResource)
 pub use mesh::MeshResource;
 
 // Materials (like SimpleMaterial)
 pub use material::SimpleMaterial;
 
+// 2D panels in the scene
+pub use panel::{Panel, Panels};
+
+// Rigid body physics, simulated in the core
+pub use physics::{Physics, PhysicsBody, ShapeResource, DEFAULT_GRAVITY};
+
+// Portals between places in the scene
+pub use portal::{Portal, Portals};
+
+// Ray casts against the scene's geometry
+pub use raycast::{MeshGeometry, RaycastHit, RaycastOptions, Scene};
+
 // RealityView content
 pub use reality_view::RealityViewContent;
 
+// Feature flags, remotely configured through a kosha
+pub use remote_config::{FeatureFlags, FlagValue};
+
+// Resuming where the user left off
+pub use resume::{SavedRig, SavedSession, SessionRestore, RESUME_STORAGE_KEY};
+
+// Spreading expensive commands across frames
+pub use schedule::CommandScheduler;
+
+// Multi-user sessions (pose broadcasting over data channels)
+pub use session::{BroadcastConfig, PeerMetrics, PoseDecoder, SharedSession, POSE_CHANNEL_LABEL};
+
+// Text labels
+pub use text::TextEntity;
+
 // Protocol types for advanced usage
 pub use fastn_protocol::*;
 
@@ -0,0 +1,427 @@
+//! Dynamic lights, and which of them light each volume
+//!
+//! Apps add `PointLight`s to the content, at a fixed point or on an entity.
+//! Shells can't afford every light on every volume (a Quest manages a few
+//! per draw), so each frame the core picks the lights that matter:
+//! - for every volume, the `lights_per_volume` most significant lights, by
+//!   intensity and distance (see `PointLight::significance_at`)
+//! - for the whole scene, the one light shells render shadows for: the one
+//!   lighting the volumes most, among those that cast shadows
+//!
+//! A light that is picked keeps its place until another is `HYSTERESIS`
+//! times as significant, so lights don't pop in and out as things move
+//! between two of them. Only changes are sent. Shells with
+//! `FEATURE_DYNAMIC_LIGHTS` light each volume with the lights assigned to
+//! it; lighting authored with many lights on desktop stays within what a
+//! headset can draw.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{MeshResource, ModelEntity, PointLight, RealityViewContent, SimpleMaterial};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     content.add(ModelEntity::with_id("table", MeshResource::generate_box(1.0), SimpleMaterial::new()));
+//!     content.add_light(PointLight::new("lamp").position(0.0, 2.0, 0.0).intensity(3.0).range(6.0));
+//!     content.add_light(PointLight::new("candle").attach_to("table").position(0.0, 0.6, 0.0).no_shadows());
+//!     // Two lights per volume for a busy scene on a headset
+//!     content.set_lights_per_volume(2);
+//! }
+//! ```
+
+use crate::animation::Animations;
+use crate::entity::EntityKind;
+use fastn_protocol::*;
+use std::collections::HashMap;
+
+/// Lights a volume gets unless the app sets `lights_per_volume`
+pub const LIGHTS_PER_VOLUME: usize = 4;
+
+/// How many times as significant a light must be to take the place of one
+/// already picked
+const HYSTERESIS: f32 = 1.25;
+
+/// Smallest move of a light worth sending, in meters
+const POSITION_EPSILON: f32 = 0.001;
+
+/// A point light in the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointLight {
+    pub id: String,
+    /// Position in the scene, or offset from the entity's position
+    pub position: [f32; 3],
+    /// Entity the light follows
+    pub entity_id: Option<String>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Distance at which the light fades out completely, in meters
+    pub range: f32,
+    /// Whether the light can be the scene's shadow caster
+    pub shadows: bool,
+}
+
+impl PointLight {
+    /// Create a white light at the origin with intensity 1, reaching 10m.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            position: [0.0, 0.0, 0.0],
+            entity_id: None,
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            range: 10.0,
+            shadows: true,
+        }
+    }
+
+    /// Set the position, or the offset from the entity the light is
+    /// attached to (builder style).
+    pub fn position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.position = [x, y, z];
+        self
+    }
+
+    /// Follow an entity (builder style).
+    pub fn attach_to(mut self, entity_id: impl Into<String>) -> Self {
+        self.entity_id = Some(entity_id.into());
+        self
+    }
+
+    /// Set the color, each channel 0 to 1 (builder style).
+    pub fn color(mut self, r: f32, g: f32, b: f32) -> Self {
+        self.color = [r, g, b].map(|c| c.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Set the intensity (builder style).
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity.max(0.0);
+        self
+    }
+
+    /// Set how far the light reaches, in meters (builder style).
+    pub fn range(mut self, range: f32) -> Self {
+        self.range = range.max(0.01);
+        self
+    }
+
+    /// Never render shadows for this light (builder style).
+    pub fn no_shadows(mut self) -> Self {
+        self.shadows = false;
+        self
+    }
+
+    /// How much the light lights something `distance` meters away:
+    /// inverse-square falloff, smoothly windowed to 0 at `range`.
+    pub fn significance_at(&self, distance: f32) -> f32 {
+        let window = (1.0 - (distance / self.range).powi(4)).clamp(0.0, 1.0).powi(2);
+        self.intensity * window / (1.0 + distance * distance)
+    }
+
+    fn to_create_command(&self, position: [f32; 3]) -> Command {
+        Command::Environment(EnvironmentCommand::CreateLight(PointLightData {
+            light_id: self.id.clone(),
+            position,
+            color: self.color,
+            intensity: self.intensity,
+            range: self.range,
+        }))
+    }
+}
+
+/// The lights of an app, assigned to its volumes every frame.
+#[derive(Debug, Default)]
+pub struct Lights {
+    lights: Vec<PointLight>,
+    /// Volumes to light
+    volumes: Vec<String>,
+    limit: usize,
+    /// Whether the shell draws dynamic lights, known on `Init`
+    supported: bool,
+    /// Last position sent per light
+    positions: Vec<Option<[f32; 3]>>,
+    /// Lights last assigned per volume, as indexes into `lights`
+    assigned: HashMap<String, Vec<usize>>,
+    /// Index of the last shadow caster sent, if any was
+    shadow_caster: Option<Option<usize>>,
+}
+
+impl Lights {
+    /// Collect the lights and the volumes they light, giving each volume
+    /// up to `limit` lights.
+    pub fn new(lights: Vec<PointLight>, limit: usize, entities: &[EntityKind]) -> Self {
+        let mut all = Self {
+            positions: vec![None; lights.len()],
+            lights,
+            limit,
+            ..Default::default()
+        };
+        for entity in entities {
+            all.collect_volumes(entity);
+        }
+        all
+    }
+
+    fn collect_volumes(&mut self, entity: &EntityKind) {
+        if !matches!(entity, EntityKind::Entity(_)) {
+            self.volumes.push(entity.id().to_string());
+        }
+        for child in entity.children() {
+            self.collect_volumes(child);
+        }
+    }
+
+    pub fn list(&self) -> &[PointLight] {
+        &self.lights
+    }
+
+    /// Process an event: create the lights on `Init`, then move and assign
+    /// them every frame. Call after animations have handled the event.
+    pub fn handle_event(&mut self, event: &Event, animations: &Animations) -> Vec<Command> {
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => self.start(&init.features, animations),
+            Event::Lifecycle(LifecycleEvent::Frame(_)) if self.supported => self.update(animations),
+            _ => vec![],
+        }
+    }
+
+    fn start(&mut self, features: &[String], animations: &Animations) -> Vec<Command> {
+        self.supported = features.iter().any(|f| f == FEATURE_DYNAMIC_LIGHTS);
+        self.positions = vec![None; self.lights.len()];
+        self.assigned.clear();
+        self.shadow_caster = None;
+        if self.lights.is_empty() {
+            return vec![];
+        }
+        if !self.supported {
+            return vec![Command::Debug(DebugCommand::Log {
+                level: LogLevel::Warn,
+                message: "Shell cannot draw dynamic lights; point lights are ignored".to_string(),
+            })];
+        }
+
+        let mut commands = vec![];
+        for (i, light) in self.lights.iter().enumerate() {
+            // Lights on entities the core doesn't know stay where they're
+            // put, as if at the top level
+            let position = self.light_position(light, animations).unwrap_or(light.position);
+            self.positions[i] = Some(position);
+            commands.push(light.to_create_command(position));
+        }
+        commands.extend(self.update(animations));
+        commands
+    }
+
+    fn update(&mut self, animations: &Animations) -> Vec<Command> {
+        let mut commands = vec![];
+        for (i, light) in self.lights.iter().enumerate() {
+            let Some(position) = self.light_position(light, animations) else {
+                continue;
+            };
+            if self.positions[i].is_some_and(|last| distance(last, position) <= POSITION_EPSILON) {
+                continue;
+            }
+            self.positions[i] = Some(position);
+            commands.push(Command::Environment(EnvironmentCommand::MoveLight {
+                light_id: light.id.clone(),
+                position,
+            }));
+        }
+
+        // Significance of every light for every volume
+        let mut totals = vec![0.0; self.lights.len()];
+        let mut changed = vec![];
+        for volume_id in &self.volumes {
+            let Some(center) = animations.world_transform(volume_id).map(|t| t.position) else {
+                continue;
+            };
+            let scores: Vec<(usize, f32)> = self
+                .lights
+                .iter()
+                .zip(&self.positions)
+                .enumerate()
+                .filter_map(|(i, (light, position))| Some((i, light.significance_at(distance((*position)?, center)))))
+                .collect();
+            for &(i, score) in &scores {
+                totals[i] += score;
+            }
+            let current = self.assigned.get(volume_id).map(Vec::as_slice).unwrap_or_default();
+            let picked = pick(&scores, current, self.limit);
+            if self.assigned.get(volume_id) != Some(&picked) {
+                changed.push(VolumeLights {
+                    volume_id: volume_id.clone(),
+                    lights: picked.iter().map(|&i| self.lights[i].id.clone()).collect(),
+                });
+                self.assigned.insert(volume_id.clone(), picked);
+            }
+        }
+        if !changed.is_empty() {
+            commands.push(Command::Environment(EnvironmentCommand::AssignLights { volumes: changed }));
+        }
+
+        let casters: Vec<(usize, f32)> = totals
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| self.lights[i].shadows)
+            .collect();
+        let current: Vec<usize> = self.shadow_caster.flatten().into_iter().collect();
+        let caster = pick(&casters, &current, 1).first().copied();
+        if self.shadow_caster != Some(caster) {
+            self.shadow_caster = Some(caster);
+            commands.push(Command::Environment(EnvironmentCommand::SetShadowCaster {
+                light_id: caster.map(|i| self.lights[i].id.clone()),
+            }));
+        }
+        commands
+    }
+
+    /// None while the light's entity is unknown
+    fn light_position(&self, light: &PointLight, animations: &Animations) -> Option<[f32; 3]> {
+        match &light.entity_id {
+            Some(entity_id) => {
+                let anchor = animations.world_transform(entity_id)?.position;
+                Some([0, 1, 2].map(|i| anchor[i] + light.position[i]))
+            }
+            None => Some(light.position),
+        }
+    }
+}
+
+/// Up to `limit` of the lights in `scores` (light index and significance)
+/// that light anything, most significant first. Lights in `current` count
+/// `HYSTERESIS` times their significance, so they keep their place against
+/// lights that are only a little more significant.
+fn pick(scores: &[(usize, f32)], current: &[usize], limit: usize) -> Vec<usize> {
+    let mut ranked: Vec<(usize, f32)> = scores
+        .iter()
+        .filter(|(_, score)| *score > 0.0)
+        .map(|&(i, score)| match current.contains(&i) {
+            true => (i, score * HYSTERESIS),
+            false => (i, score),
+        })
+        .collect();
+    // Ties go to the light added first
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    ranked.into_iter().take(limit).map(|(i, _)| i).collect()
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MeshResource, ModelEntity, SimpleMaterial};
+
+    fn assignments(commands: &[Command]) -> Vec<VolumeLights> {
+        commands
+            .iter()
+            .filter_map(|c| match c {
+                Command::Environment(EnvironmentCommand::AssignLights { volumes }) => Some(volumes.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn shadow_casters(commands: &[Command]) -> Vec<Option<LightId>> {
+        commands
+            .iter()
+            .filter_map(|c| match c {
+                Command::Environment(EnvironmentCommand::SetShadowCaster { light_id }) => Some(light_id.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_significance() {
+        let light = PointLight::new("lamp").intensity(2.0).range(5.0);
+        assert_eq!(light.significance_at(0.0), 2.0);
+        assert!(light.significance_at(1.0) > light.significance_at(2.0));
+        assert_eq!(light.significance_at(5.0), 0.0);
+        assert_eq!(light.significance_at(50.0), 0.0);
+    }
+
+    #[test]
+    fn test_pick_with_hysteresis() {
+        let scores = [(0, 1.0), (1, 3.0), (2, 2.0), (3, 0.0)];
+        assert_eq!(pick(&scores, &[], 2), [1, 2]);
+        // Lights that light nothing aren't picked
+        assert_eq!(pick(&scores, &[], 10), [1, 2, 0]);
+
+        // A light picked before stays against one a little more significant
+        let scores = [(0, 1.0), (1, 1.1)];
+        assert_eq!(pick(&scores, &[0], 1), [0]);
+        assert_eq!(pick(&scores, &[], 1), [1]);
+        // but not against one well above it
+        let scores = [(0, 1.0), (1, 1.5)];
+        assert_eq!(pick(&scores, &[0], 1), [1]);
+    }
+
+    fn init(features: &[&str]) -> Event {
+        Event::Lifecycle(LifecycleEvent::Init(InitEvent {
+            platform: Platform::Desktop,
+            viewport_width: 800,
+            viewport_height: 600,
+            dpr: 1.0,
+            xr_supported: false,
+            xr_immersive_vr: false,
+            xr_immersive_ar: false,
+            webrtc_supported: false,
+            websocket_supported: false,
+            features: features.iter().map(|f| f.to_string()).collect(),
+            accessibility: Default::default(),
+            conventions: Default::default(),
+            camera: None,
+        }))
+    }
+
+    fn frame() -> Event {
+        Event::Lifecycle(LifecycleEvent::Frame(FrameEvent { time: 0.0, dt: 0.016, frame: 1 }))
+    }
+
+    #[test]
+    fn test_lights_are_assigned_per_volume() {
+        let entities: Vec<EntityKind> = [("left", -5.0), ("right", 5.0)]
+            .into_iter()
+            .map(|(id, x)| ModelEntity::with_id(id, MeshResource::generate_box(1.0), SimpleMaterial::new()).position(x, 0.0, 0.0).into())
+            .collect();
+        let animations = Animations::new(&entities);
+        let lights = vec![
+            PointLight::new("a").position(-5.0, 1.0, 0.0),
+            PointLight::new("b").position(-4.0, 1.0, 0.0).no_shadows(),
+            PointLight::new("c").position(5.0, 1.0, 0.0).intensity(0.5),
+        ];
+        let mut lights = Lights::new(lights, 1, &entities);
+
+        let commands = lights.handle_event(&init(&[FEATURE_DYNAMIC_LIGHTS]), &animations);
+        let created = commands
+            .iter()
+            .filter(|c| matches!(c, Command::Environment(EnvironmentCommand::CreateLight(_))))
+            .count();
+        assert_eq!(created, 3);
+        let assigned = assignments(&commands);
+        assert_eq!(assigned.len(), 2);
+        assert_eq!(assigned[0].lights, ["a"]);
+        assert_eq!(assigned[1].lights, ["c"]);
+        assert_eq!(shadow_casters(&commands), [Some("a".to_string())]);
+
+        // Nothing moved, nothing sent
+        assert!(lights.handle_event(&frame(), &animations).is_empty());
+    }
+
+    #[test]
+    fn test_lights_ignored_without_the_feature() {
+        let entities: Vec<EntityKind> = vec![ModelEntity::with_id("box", MeshResource::generate_box(1.0), SimpleMaterial::new()).into()];
+        let animations = Animations::new(&entities);
+        let mut lights = Lights::new(vec![PointLight::new("lamp")], LIGHTS_PER_VOLUME, &entities);
+
+        let mut commands = lights.handle_event(&init(&[]), &animations);
+        commands.extend(lights.handle_event(&frame(), &animations));
+        assert!(assignments(&commands).is_empty());
+        assert!(commands.iter().any(|c| matches!(c, Command::Debug(DebugCommand::Log { level: LogLevel::Warn, message }) if message.contains("dynamic lights"))));
+    }
+}
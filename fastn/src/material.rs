@@ -14,6 +14,9 @@
 //! let metal = SimpleMaterial::new()
 //!     .color(0.8, 0.8, 0.9)
 //!     .metallic(true);
+//!
+//! // A region of a texture atlas
+//! let icon = SimpleMaterial::new().atlas_region("ui", "play");
 //! ```
 
 /// Simple material with color and basic properties.
@@ -24,6 +27,8 @@ pub struct SimpleMaterial {
     pub(crate) color: [f32; 4],
     pub(crate) is_metallic: bool,
     pub(crate) roughness: f32,
+    /// Atlas ID and region name of the texture
+    pub(crate) atlas_region: Option<(String, String)>,
 }
 
 impl Default for SimpleMaterial {
@@ -32,6 +37,7 @@ impl Default for SimpleMaterial {
             color: [1.0, 1.0, 1.0, 1.0],  // White
             is_metallic: false,
             roughness: 0.5,
+            atlas_region: None,
         }
     }
 }
@@ -71,6 +77,13 @@ impl SimpleMaterial {
         self.roughness = roughness;
         self
     }
+
+    /// Texture the surface with a region of a texture atlas added to the
+    /// content (see `TextureAtlas`). The color tints it.
+    pub fn atlas_region(mut self, atlas: impl Into<String>, region: impl Into<String>) -> Self {
+        self.atlas_region = Some((atlas.into(), region.into()));
+        self
+    }
 }
 
 /// Convert SimpleMaterial to internal MaterialOverride for protocol.
@@ -82,6 +95,7 @@ impl SimpleMaterial {
             metallic: Some(if self.is_metallic { 1.0 } else { 0.0 }),
             roughness: Some(self.roughness),
             emissive: None,
+            uv_rect: None,
         }
     }
 }
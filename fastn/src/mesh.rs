@@ -11,9 +11,18 @@
 //! let box_mesh = MeshResource::generate_box(0.5);
 //! let sphere_mesh = MeshResource::generate_sphere(0.3);
 //! let plane_mesh = MeshResource::generate_plane(1.0, 1.0);
+//! let ring_mesh = MeshResource::generate_torus(0.3, 0.05).with_segments(64);
+//! let label_mesh = MeshResource::generate_text("Hello", 0.1);
 //! ```
 //!
 //! For loading meshes from files (GLB, USDZ), use `Entity::load()` instead.
+//! For labels, `TextEntity` sets up a text mesh and its material at once.
+
+use fastn_protocol::TextAlignment;
+
+/// Sides around the axis of round meshes unless `with_segments` says
+/// otherwise
+pub const DEFAULT_SEGMENTS: u32 = 32;
 
 /// Mesh geometry resource for procedural primitives.
 ///
@@ -25,9 +34,21 @@
 pub enum MeshResource {
     Box { size: f32 },
     BoxWithDimensions { width: f32, height: f32, depth: f32 },
-    Sphere { radius: f32 },
+    Sphere { radius: f32, segments: u32 },
     Plane { width: f32, depth: f32 },
-    Cylinder { radius: f32, height: f32 },
+    Cylinder { radius: f32, height: f32, segments: u32 },
+    /// Apex up, base circle at the bottom
+    Cone { radius: f32, height: f32, segments: u32 },
+    /// Ring lying flat around the Y axis (see `Primitive::Torus`)
+    Torus { radius: f32, tube_radius: f32, segments: u32 },
+    /// Flat text facing +Z, drawn by the shell (see `Primitive::Text`)
+    Text {
+        text: String,
+        font_size: f32,
+        alignment: TextAlignment,
+        max_width: Option<f32>,
+        billboard: bool,
+    },
 }
 
 impl MeshResource {
@@ -49,7 +70,7 @@ impl MeshResource {
     ///
     /// Equivalent to `MeshResource.generateSphere(radius:)` in RealityKit.
     pub fn generate_sphere(radius: f32) -> Self {
-        MeshResource::Sphere { radius }
+        MeshResource::Sphere { radius, segments: DEFAULT_SEGMENTS }
     }
 
     /// Generate a plane mesh.
@@ -63,6 +84,48 @@ impl MeshResource {
     ///
     /// Equivalent to `MeshResource.generateCylinder(radius:height:)` in RealityKit.
     pub fn generate_cylinder(radius: f32, height: f32) -> Self {
-        MeshResource::Cylinder { radius, height }
+        MeshResource::Cylinder { radius, height, segments: DEFAULT_SEGMENTS }
+    }
+
+    /// Generate a cone mesh.
+    ///
+    /// Equivalent to `MeshResource.generateCone(height:radius:)` in RealityKit.
+    pub fn generate_cone(radius: f32, height: f32) -> Self {
+        MeshResource::Cone { radius, height, segments: DEFAULT_SEGMENTS }
+    }
+
+    /// Generate a torus mesh: `radius` from its center to the middle of the
+    /// tube, which is `tube_radius` thick.
+    pub fn generate_torus(radius: f32, tube_radius: f32) -> Self {
+        MeshResource::Torus { radius, tube_radius, segments: DEFAULT_SEGMENTS }
+    }
+
+    /// Set how many sides a round mesh has around its axis; spheres get
+    /// half as many rings, a torus's tube half as many sides. Other meshes
+    /// are unchanged.
+    pub fn with_segments(mut self, count: u32) -> Self {
+        match &mut self {
+            MeshResource::Sphere { segments, .. }
+            | MeshResource::Cylinder { segments, .. }
+            | MeshResource::Cone { segments, .. }
+            | MeshResource::Torus { segments, .. } => *segments = count.max(3),
+            _ => {}
+        }
+        self
+    }
+
+    /// Generate flat text, `font_size` meters to the em, centered on the
+    /// entity.
+    ///
+    /// Equivalent to `MeshResource.generateText(_:)` in RealityKit, without
+    /// the extrusion.
+    pub fn generate_text(text: impl Into<String>, font_size: f32) -> Self {
+        MeshResource::Text {
+            text: text.into(),
+            font_size,
+            alignment: TextAlignment::Center,
+            max_width: None,
+            billboard: false,
+        }
     }
 }
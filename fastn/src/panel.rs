@@ -0,0 +1,170 @@
+//! Panels
+//!
+//! 2D UI in the scene next to the app's volumes, like windows on visionOS:
+//! a rectangle showing simple widgets, an SVG document or (on web shells)
+//! HTML. Pointer input on a panel comes back to `on_panel_pointer`
+//! callbacks, with where on the panel it happened and the button under it.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{Animation, Panel, PointerPhase, RealityViewContent, Widget};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     let menu = Panel::widgets(
+//!         "menu",
+//!         Widget::column([
+//!             Widget::text("Robot"),
+//!             Widget::row([Widget::button("spin", "Spin"), Widget::button("stop", "Stop")]),
+//!         ]),
+//!     )
+//!     .size(0.3, 0.15)
+//!     .position(0.4, 1.4, -0.8)
+//!     .yaw(-0.3);
+//!     content.add_panel(menu);
+//!
+//!     content.on_panel_pointer("menu", |ctx, pointer| {
+//!         if pointer.phase != PointerPhase::Up {
+//!             return;
+//!         }
+//!         match pointer.target.as_deref() {
+//!             Some("spin") => ctx.animate("robot", Animation::spin([0.0, 1.0, 0.0])),
+//!             Some("stop") => ctx.stop_animations("robot"),
+//!             _ => {}
+//!         }
+//!     });
+//! }
+//! ```
+
+use fastn_protocol::*;
+
+/// A panel, facing +Z at yaw 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Panel {
+    pub id: String,
+    pub width: f32,
+    pub height: f32,
+    pub position: [f32; 3],
+    pub yaw: f32,
+    /// Resolution of the content (see `CreatePanelData::pixels_per_meter`)
+    pub pixels_per_meter: f32,
+    pub content: PanelContent,
+}
+
+impl Panel {
+    /// Create a 40cm x 30cm panel at eye height in front of the origin.
+    pub fn new(id: impl Into<String>, content: PanelContent) -> Self {
+        Self {
+            id: id.into(),
+            width: 0.4,
+            height: 0.3,
+            position: [0.0, 1.4, -0.8],
+            yaw: 0.0,
+            pixels_per_meter: DEFAULT_PANEL_PIXELS_PER_METER,
+            content,
+        }
+    }
+
+    /// A panel showing widgets, laid out by the shell.
+    pub fn widgets(id: impl Into<String>, root: Widget) -> Self {
+        Self::new(id, PanelContent::Widgets { root })
+    }
+
+    /// A panel showing an SVG document, stretched to fit.
+    pub fn svg(id: impl Into<String>, svg: impl Into<String>) -> Self {
+        Self::new(id, PanelContent::Svg { svg: svg.into() })
+    }
+
+    /// A panel showing HTML, on shells with `FEATURE_HTML_PANELS`.
+    pub fn html(id: impl Into<String>, html: impl Into<String>) -> Self {
+        Self::new(id, PanelContent::Html { html: html.into() })
+    }
+
+    /// Set the panel size in meters (builder style).
+    pub fn size(mut self, width: f32, height: f32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Set the panel center (builder style).
+    pub fn position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.position = [x, y, z];
+        self
+    }
+
+    /// Turn the panel around +Y, in radians (builder style).
+    pub fn yaw(mut self, yaw: f32) -> Self {
+        self.yaw = yaw;
+        self
+    }
+
+    /// Set how many content pixels there are per meter (builder style).
+    pub fn pixels_per_meter(mut self, pixels_per_meter: f32) -> Self {
+        self.pixels_per_meter = pixels_per_meter;
+        self
+    }
+
+    pub fn to_command(&self) -> Command {
+        let (sin, cos) = (self.yaw / 2.0).sin_cos();
+        Command::Panel(PanelCommand::Create(CreatePanelData {
+            panel_id: self.id.clone(),
+            width: self.width,
+            height: self.height,
+            transform: Transform {
+                position: self.position,
+                rotation: [0.0, sin, 0.0, cos],
+                ..Default::default()
+            },
+            pixels_per_meter: self.pixels_per_meter,
+            content: self.content.clone(),
+        }))
+    }
+}
+
+/// The panels of an app.
+#[derive(Debug, Default)]
+pub struct Panels {
+    panels: Vec<Panel>,
+}
+
+impl Panels {
+    pub fn new(panels: Vec<Panel>) -> Self {
+        Self { panels }
+    }
+
+    /// Commands to run at startup: one per panel.
+    pub fn init_commands(&self) -> Vec<Command> {
+        self.panels.iter().map(Panel::to_command).collect()
+    }
+
+    /// Warn on `Init` when the shell can't draw some of the panels.
+    pub fn handle_event(&self, event: &Event) -> Vec<Command> {
+        let Event::Lifecycle(LifecycleEvent::Init(init)) = event else {
+            return vec![];
+        };
+        if self.panels.is_empty() {
+            return vec![];
+        }
+        let supports = |feature: &str| init.features.iter().any(|f| f == feature);
+        let message = if !supports(FEATURE_PANELS) {
+            "Shell cannot draw panels; they stay hidden".to_string()
+        } else {
+            let html: Vec<&str> = self
+                .panels
+                .iter()
+                .filter(|panel| matches!(panel.content, PanelContent::Html { .. }))
+                .map(|panel| panel.id.as_str())
+                .collect();
+            if html.is_empty() || supports(FEATURE_HTML_PANELS) {
+                return vec![];
+            }
+            format!("Shell cannot draw HTML; panels {} stay blank", html.join(", "))
+        };
+        vec![Command::Debug(DebugCommand::Log {
+            level: LogLevel::Warn,
+            message,
+        })]
+    }
+}
@@ -0,0 +1,460 @@
+//! Rigid body physics
+//!
+//! Entities with volumes get a collider with `with_collision` and fall,
+//! bounce and collide once they also have `with_physics`:
+//!
+//! ```rust,ignore
+//! use fastn::{MeshResource, ModelEntity, RealityViewContent, ShapeResource, SimpleMaterial};
+//!
+//! fn make_content(content: &mut RealityViewContent) {
+//!     let floor = ModelEntity::new(MeshResource::generate_box_with_dimensions(4.0, 0.1, 4.0), SimpleMaterial::new())
+//!         .with_collision(ShapeResource::generate_box(4.0, 0.1, 4.0));
+//!     let ball = ModelEntity::with_id("ball", MeshResource::generate_sphere(0.1), SimpleMaterial::new())
+//!         .position(0.0, 2.0, -1.0)
+//!         .with_physics(0.5, 0.8);
+//!     content.add(floor);
+//!     content.add(ball);
+//!     content.on_collision_start(|ctx, collision| ctx.announce(format!("{} hit {}", collision.entity_a, collision.entity_b)));
+//! }
+//! ```
+//!
+//! The simulation runs in the core, so every shell gets the same physics:
+//! rapier steps the world at a fixed 60 Hz on `Frame` events and the bodies
+//! that moved are sent as plain `SceneCommand::SetTransform`s. Shells only
+//! draw. Collisions are raised by the core as `SceneEvent::CollisionStarted`
+//! and `CollisionEnded`, for `on_collision_start`, `on_collision_end` and
+//! `on_event` callbacks.
+//!
+//! - Entities with `with_physics` are dynamic bodies: gravity
+//!   (`RealityViewContent::set_gravity`) and contacts move them, and their
+//!   animations are overridden. Without a `with_collision` shape, a model
+//!   entity collides as its mesh; a loaded entity needs one.
+//! - Entities with only `with_collision` are kinematic: they don't fall,
+//!   but bodies bounce off them, and they follow their transform as the
+//!   core knows it (an animation moves them to where it ends).
+//!
+//! Shapes are sized by the entity's scale when the scene starts. The
+//! simulation needs the `physics` feature of the `fastn` crate (it pulls in
+//! rapier); without it bodies stay where they are and the core says so.
+
+use crate::animation::Animations;
+use crate::entity::Placement;
+use crate::{EntityKind, MeshResource};
+use fastn_protocol::*;
+
+/// Earth's gravity, straight down
+pub const DEFAULT_GRAVITY: [f32; 3] = [0.0, -9.81, 0.0];
+
+/// The shape of an entity's collider, centered on the entity.
+///
+/// Equivalent to RealityKit's `ShapeResource`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShapeResource {
+    Box { width: f32, height: f32, depth: f32 },
+    Sphere { radius: f32 },
+    /// Upright along Y; `height` includes the rounded ends
+    Capsule { height: f32, radius: f32 },
+    /// Upright along Y
+    Cylinder { height: f32, radius: f32 },
+}
+
+impl ShapeResource {
+    /// Equivalent to `ShapeResource.generateBox(width:height:depth:)`.
+    pub fn generate_box(width: f32, height: f32, depth: f32) -> Self {
+        ShapeResource::Box { width, height, depth }
+    }
+
+    /// Equivalent to `ShapeResource.generateSphere(radius:)`.
+    pub fn generate_sphere(radius: f32) -> Self {
+        ShapeResource::Sphere { radius }
+    }
+
+    /// Equivalent to `ShapeResource.generateCapsule(height:radius:)`.
+    pub fn generate_capsule(height: f32, radius: f32) -> Self {
+        ShapeResource::Capsule { height, radius }
+    }
+
+    /// A cylinder standing on its base.
+    pub fn generate_cylinder(height: f32, radius: f32) -> Self {
+        ShapeResource::Cylinder { height, radius }
+    }
+
+    /// The shape of a generated mesh; text has none.
+    pub fn of(mesh: &MeshResource) -> Option<Self> {
+        Some(match mesh {
+            MeshResource::Box { size } => Self::generate_box(*size, *size, *size),
+            MeshResource::BoxWithDimensions { width, height, depth } => Self::generate_box(*width, *height, *depth),
+            MeshResource::Sphere { radius, .. } => Self::generate_sphere(*radius),
+            MeshResource::Plane { width, depth } => Self::generate_box(*width, 0.0, *depth),
+            MeshResource::Cylinder { radius, height, .. } => Self::generate_cylinder(*height, *radius),
+            // Rounded shapes are approximated by the cylinders around them
+            MeshResource::Cone { radius, height, .. } => Self::generate_cylinder(*height, *radius),
+            MeshResource::Torus { radius, tube_radius, .. } => {
+                Self::generate_cylinder(tube_radius * 2.0, radius + tube_radius)
+            }
+            MeshResource::Text { .. } => return None,
+        })
+    }
+}
+
+/// How a dynamic body responds to forces and contacts.
+///
+/// Equivalent to RealityKit's `PhysicsBodyComponent` in dynamic mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicsBody {
+    /// Kilograms
+    pub mass: f32,
+    /// Bounciness, from 0 (none) to 1 (keeps all its speed)
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+impl PhysicsBody {
+    pub fn new(mass: f32, restitution: f32) -> Self {
+        Self {
+            mass: mass.max(f32::EPSILON),
+            restitution: restitution.clamp(0.0, 1.0),
+            friction: 0.5,
+        }
+    }
+}
+
+/// An entity's collider and body
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PhysicsComponent {
+    pub collision: Option<ShapeResource>,
+    pub body: Option<PhysicsBody>,
+}
+
+/// A collider to simulate, from an entity
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "physics"), allow(dead_code))]
+struct Collider {
+    entity: String,
+    shape: ShapeResource,
+    body: Option<PhysicsBody>,
+    /// The entity's scene transform when the scene starts
+    world: Transform,
+}
+
+/// Steps the scene's bodies and reports their collisions.
+#[derive(Default)]
+pub struct Physics {
+    /// Entities with `with_physics`
+    bodies: usize,
+    #[cfg(feature = "physics")]
+    world: Option<simulation::World>,
+}
+
+impl std::fmt::Debug for Physics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Physics").field("bodies", &self.bodies).finish()
+    }
+}
+
+impl Physics {
+    /// Collect the colliders of the scene's entities.
+    pub(crate) fn new(entities: &[EntityKind], gravity: [f32; 3]) -> Self {
+        let mut colliders = vec![];
+        for entity in entities {
+            entity.visit_volumes(&Placement::default(), &mut |entity, at| {
+                let Some(physics) = entity.physics() else {
+                    return;
+                };
+                let shape = physics.collision.clone().or_else(|| match entity {
+                    EntityKind::ModelEntity(model) if physics.body.is_some() => ShapeResource::of(model.mesh()),
+                    _ => None,
+                });
+                if let Some(shape) = shape {
+                    colliders.push(Collider {
+                        entity: entity.id().to_string(),
+                        shape,
+                        body: physics.body,
+                        world: at.world.clone(),
+                    });
+                }
+            });
+        }
+        #[cfg(not(feature = "physics"))]
+        let _ = gravity;
+        Self {
+            bodies: colliders.iter().filter(|c| c.body.is_some()).count(),
+            #[cfg(feature = "physics")]
+            world: (!colliders.is_empty()).then(|| simulation::World::new(&colliders, gravity)),
+        }
+    }
+
+    /// Step the bodies on frames. Returns the commands moving them and the
+    /// collisions that started or ended, as events for the callbacks.
+    pub(crate) fn handle_event(&mut self, event: &Event, animations: &mut Animations) -> (Vec<Command>, Vec<Event>) {
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(_)) => (self.check_support().into_iter().collect(), vec![]),
+            #[cfg(feature = "physics")]
+            Event::Lifecycle(LifecycleEvent::Frame(frame)) => match &mut self.world {
+                Some(world) => world.step(frame.dt, animations),
+                None => (vec![], vec![]),
+            },
+            _ => {
+                let _ = animations;
+                (vec![], vec![])
+            }
+        }
+    }
+
+    /// Warn when the core was built without a simulation for its bodies
+    fn check_support(&self) -> Option<Command> {
+        if cfg!(feature = "physics") || self.bodies == 0 {
+            return None;
+        }
+        Some(Command::Debug(DebugCommand::Log {
+            level: LogLevel::Warn,
+            message: format!("Built without the physics feature, {} physics bodies stay in place", self.bodies),
+        }))
+    }
+}
+
+#[cfg(feature = "physics")]
+mod simulation {
+    use super::{Collider, ShapeResource};
+    use crate::animation::{point_to_local, Animations};
+    use fastn_protocol::*;
+    use rapier3d::na::{Isometry3, Quaternion, Translation3, UnitQuaternion};
+    use rapier3d::prelude::*;
+    use std::sync::Mutex;
+
+    /// Seconds per simulation step
+    const STEP_SECS: f32 = 1.0 / 60.0;
+
+    /// Steps run per frame at most, so a long frame (a tab in the
+    /// background) doesn't stall the next one
+    const MAX_STEPS_PER_FRAME: u32 = 4;
+
+    /// Movement below which a body isn't sent again (meters, and quaternion
+    /// components)
+    const MOVE_EPSILON: f32 = 1e-4;
+
+    /// The rapier world and where each body was last sent
+    pub(super) struct World {
+        gravity: Vector<Real>,
+        parameters: IntegrationParameters,
+        pipeline: PhysicsPipeline,
+        islands: IslandManager,
+        broad_phase: DefaultBroadPhase,
+        narrow_phase: NarrowPhase,
+        bodies: RigidBodySet,
+        colliders: ColliderSet,
+        impulse_joints: ImpulseJointSet,
+        multibody_joints: MultibodyJointSet,
+        ccd: CCDSolver,
+        /// Entity ID, body and whether it is dynamic, by collider user data
+        entities: Vec<(String, RigidBodyHandle, bool)>,
+        sent: Vec<Option<Transform>>,
+        /// Time not yet stepped
+        pending: f32,
+    }
+
+    /// Collects rapier's collision events during a step
+    #[derive(Default)]
+    struct Collisions(Mutex<Vec<CollisionEvent>>);
+
+    impl EventHandler for Collisions {
+        fn handle_collision_event(
+            &self,
+            _bodies: &RigidBodySet,
+            _colliders: &ColliderSet,
+            event: CollisionEvent,
+            _contact_pair: Option<&ContactPair>,
+        ) {
+            if let Ok(mut events) = self.0.lock() {
+                events.push(event);
+            }
+        }
+
+        fn handle_contact_force_event(
+            &self,
+            _dt: Real,
+            _bodies: &RigidBodySet,
+            _colliders: &ColliderSet,
+            _contact_pair: &ContactPair,
+            _total_force_magnitude: Real,
+        ) {
+        }
+    }
+
+    impl World {
+        pub(super) fn new(colliders: &[Collider], gravity: [f32; 3]) -> Self {
+            let mut world = Self {
+                gravity: vector![gravity[0], gravity[1], gravity[2]],
+                parameters: IntegrationParameters {
+                    dt: STEP_SECS,
+                    ..IntegrationParameters::default()
+                },
+                pipeline: PhysicsPipeline::new(),
+                islands: IslandManager::new(),
+                broad_phase: DefaultBroadPhase::new(),
+                narrow_phase: NarrowPhase::new(),
+                bodies: RigidBodySet::new(),
+                colliders: ColliderSet::new(),
+                impulse_joints: ImpulseJointSet::new(),
+                multibody_joints: MultibodyJointSet::new(),
+                ccd: CCDSolver::new(),
+                entities: vec![],
+                sent: vec![],
+                pending: 0.0,
+            };
+            for collider in colliders {
+                world.add(collider);
+            }
+            world
+        }
+
+        fn add(&mut self, collider: &Collider) {
+            let body = match collider.body {
+                Some(_) => RigidBodyBuilder::dynamic().ccd_enabled(true),
+                None => RigidBodyBuilder::kinematic_position_based(),
+            };
+            let handle = self.bodies.insert(body.position(isometry(&collider.world)).build());
+
+            let scale = collider.world.scale.map(f32::abs);
+            let shape = match collider.shape {
+                ShapeResource::Box { width, height, depth } => {
+                    ColliderBuilder::cuboid(width * scale[0] / 2.0, height * scale[1] / 2.0, depth * scale[2] / 2.0)
+                }
+                ShapeResource::Sphere { radius } => ColliderBuilder::ball(radius * scale[0].max(scale[1]).max(scale[2])),
+                ShapeResource::Capsule { height, radius } => {
+                    let radius = radius * scale[0].max(scale[2]);
+                    ColliderBuilder::capsule_y((height * scale[1] / 2.0 - radius).max(0.0), radius)
+                }
+                ShapeResource::Cylinder { height, radius } => {
+                    ColliderBuilder::cylinder(height * scale[1] / 2.0, radius * scale[0].max(scale[2]))
+                }
+            };
+            let shape = match collider.body {
+                Some(body) => shape.mass(body.mass).restitution(body.restitution).friction(body.friction),
+                None => shape,
+            };
+            let shape = shape
+                .active_events(ActiveEvents::COLLISION_EVENTS)
+                .user_data(self.entities.len() as u128)
+                .build();
+            self.colliders.insert_with_parent(shape, handle, &mut self.bodies);
+            self.entities.push((collider.entity.clone(), handle, collider.body.is_some()));
+            self.sent.push(Some(collider.world.clone()));
+        }
+
+        /// Advance by `dt` seconds in fixed steps
+        pub(super) fn step(&mut self, dt: f32, animations: &mut Animations) -> (Vec<Command>, Vec<Event>) {
+            self.pending = (self.pending + dt.max(0.0)).min(STEP_SECS * MAX_STEPS_PER_FRAME as f32);
+            let mut events = vec![];
+            if self.pending < STEP_SECS {
+                return (vec![], events);
+            }
+
+            // Kinematic colliders go where their entities are
+            for (entity, handle, dynamic) in &self.entities {
+                if let (false, Some(transform)) = (*dynamic, animations.world_transform(entity))
+                    && let Some(body) = self.bodies.get_mut(*handle)
+                {
+                    body.set_next_kinematic_position(isometry(&transform));
+                }
+            }
+
+            let collisions = Collisions::default();
+            while self.pending >= STEP_SECS {
+                self.pending -= STEP_SECS;
+                self.pipeline.step(
+                    &self.gravity,
+                    &self.parameters,
+                    &mut self.islands,
+                    &mut self.broad_phase,
+                    &mut self.narrow_phase,
+                    &mut self.bodies,
+                    &mut self.colliders,
+                    &mut self.impulse_joints,
+                    &mut self.multibody_joints,
+                    &mut self.ccd,
+                    None,
+                    &(),
+                    &collisions,
+                );
+            }
+            for collision in collisions.0.into_inner().unwrap_or_default() {
+                let (Some(a), Some(b)) = (self.entity(collision.collider1()), self.entity(collision.collider2())) else {
+                    continue;
+                };
+                let data = CollisionData {
+                    entity_a: a.to_string(),
+                    entity_b: b.to_string(),
+                };
+                events.push(Event::Scene(match collision.started() {
+                    true => SceneEvent::CollisionStarted(data),
+                    false => SceneEvent::CollisionEnded(data),
+                }));
+            }
+            (self.moved(animations), events)
+        }
+
+        /// The entity a collider belongs to
+        fn entity(&self, collider: ColliderHandle) -> Option<&str> {
+            let index = self.colliders.get(collider)?.user_data as usize;
+            self.entities.get(index).map(|(entity, _, _)| entity.as_str())
+        }
+
+        /// Transforms for the dynamic bodies that moved since last sent,
+        /// relative to their entities' parents
+        fn moved(&mut self, animations: &mut Animations) -> Vec<Command> {
+            let mut commands = vec![];
+            for (index, (entity, handle, dynamic)) in self.entities.iter().enumerate() {
+                let Some(body) = self.bodies.get(*handle).filter(|body| *dynamic && !body.is_sleeping()) else {
+                    continue;
+                };
+                let position = body.position();
+                let world = Transform {
+                    position: position.translation.vector.into(),
+                    rotation: position.rotation.coords.into(),
+                    scale: animations.world_transform(entity).map_or([1.0; 3], |t| t.scale),
+                };
+                if self.sent[index].as_ref().is_some_and(|sent| !moved(sent, &world)) {
+                    continue;
+                }
+                self.sent[index] = Some(world.clone());
+                let transform = match animations.parent_transform(entity) {
+                    Some(parent) => relative(&parent, &world, animations.transform(entity)),
+                    None => world,
+                };
+                animations.set_transform(entity, transform.clone());
+                commands.push(Command::Scene(SceneCommand::SetTransform(SetTransformData {
+                    volume_id: entity.clone(),
+                    transform,
+                    animate: None,
+                })));
+            }
+            commands
+        }
+    }
+
+    fn isometry(transform: &Transform) -> Isometry3<f32> {
+        let [x, y, z] = transform.position;
+        let [qx, qy, qz, qw] = transform.rotation;
+        Isometry3::from_parts(
+            Translation3::new(x, y, z),
+            UnitQuaternion::from_quaternion(Quaternion::new(qw, qx, qy, qz)),
+        )
+    }
+
+    fn moved(a: &Transform, b: &Transform) -> bool {
+        let position = (0..3).any(|i| (a.position[i] - b.position[i]).abs() > MOVE_EPSILON);
+        let rotation = (0..4).any(|i| (a.rotation[i] - b.rotation[i]).abs() > MOVE_EPSILON);
+        position || rotation
+    }
+
+    /// A scene transform in the space of `parent`, keeping the local scale
+    fn relative(parent: &Transform, world: &Transform, local: Option<&Transform>) -> Transform {
+        let [x, y, z, w] = parent.rotation;
+        Transform {
+            position: point_to_local(parent, world.position),
+            rotation: crate::animation::multiply([-x, -y, -z, w], world.rotation),
+            scale: local.map_or([1.0; 3], |t| t.scale),
+        }
+    }
+}
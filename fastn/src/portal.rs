@@ -0,0 +1,322 @@
+//! Portals
+//!
+//! A portal is a rectangular window from one place in the scene into another.
+//! Shells that advertise `FEATURE_PORTALS` draw the remote view through the
+//! portal surface (per eye in XR); walking through it moves the camera, or
+//! the XR rig, to the matching pose at the exit.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{Portal, RealityViewContent};
+//! use std::f32::consts::FRAC_PI_2;
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     // A doorway between the room at the origin and a garden far away
+//!     let door = Portal::new("garden")
+//!         .size(1.0, 2.0)
+//!         .entrance(0.0, 1.0, -2.0, 0.0)
+//!         .exit(50.0, 1.0, 0.0, FRAC_PI_2);
+//!     content.add_portal(door.reverse());
+//!     content.add_portal(door);
+//! }
+//! ```
+
+use crate::camera::CameraController;
+use fastn_protocol::*;
+
+/// Longest viewer movement between two checks that still counts as walking.
+/// Bigger jumps (bookmark teleports, camera resets) never cross a portal.
+const MAX_STEP: f32 = 1.0;
+
+/// A one-way portal.
+///
+/// Ends are given as a position plus a yaw around +Y; at yaw 0 the surface
+/// faces +Z. Crossing works from either side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Portal {
+    pub id: String,
+    pub width: f32,
+    pub height: f32,
+    pub entrance: [f32; 3],
+    pub entrance_yaw: f32,
+    pub exit: [f32; 3],
+    pub exit_yaw: f32,
+}
+
+impl Portal {
+    /// Create a 1m x 2m portal whose ends are both at the origin.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            width: 1.0,
+            height: 2.0,
+            entrance: [0.0, 0.0, 0.0],
+            entrance_yaw: 0.0,
+            exit: [0.0, 0.0, 0.0],
+            exit_yaw: 0.0,
+        }
+    }
+
+    /// Set the surface size in meters (builder style).
+    pub fn size(mut self, width: f32, height: f32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Set the center and facing of the portal surface (builder style).
+    pub fn entrance(mut self, x: f32, y: f32, z: f32, yaw: f32) -> Self {
+        self.entrance = [x, y, z];
+        self.entrance_yaw = yaw;
+        self
+    }
+
+    /// Set the pose the entrance maps onto (builder style).
+    pub fn exit(mut self, x: f32, y: f32, z: f32, yaw: f32) -> Self {
+        self.exit = [x, y, z];
+        self.exit_yaw = yaw;
+        self
+    }
+
+    /// The portal leading back, with its surface at this portal's exit.
+    pub fn reverse(&self) -> Portal {
+        Portal {
+            id: format!("{}:return", self.id),
+            width: self.width,
+            height: self.height,
+            entrance: self.exit,
+            entrance_yaw: self.exit_yaw,
+            exit: self.entrance,
+            exit_yaw: self.entrance_yaw,
+        }
+    }
+
+    /// Yaw added to anything passing through
+    fn turn(&self) -> f32 {
+        self.exit_yaw - self.entrance_yaw
+    }
+
+    /// Map a point near the entrance to the matching point near the exit.
+    pub fn map_point(&self, point: [f32; 3]) -> [f32; 3] {
+        let offset = rotate_y(sub(point, self.entrance), self.turn());
+        [self.exit[0] + offset[0], self.exit[1] + offset[1], self.exit[2] + offset[2]]
+    }
+
+    /// Whether moving from `from` to `to` passes through the portal surface.
+    pub fn crossed(&self, from: [f32; 3], to: [f32; 3]) -> bool {
+        let a = rotate_y(sub(from, self.entrance), -self.entrance_yaw);
+        let b = rotate_y(sub(to, self.entrance), -self.entrance_yaw);
+        if (a[2] >= 0.0) == (b[2] >= 0.0) {
+            return false;
+        }
+        let t = a[2] / (a[2] - b[2]);
+        let x = a[0] + (b[0] - a[0]) * t;
+        let y = a[1] + (b[1] - a[1]) * t;
+        x.abs() <= self.width / 2.0 && y.abs() <= self.height / 2.0
+    }
+
+    pub fn to_command(&self) -> Command {
+        Command::Scene(SceneCommand::CreatePortal(CreatePortalData {
+            portal_id: self.id.clone(),
+            width: self.width,
+            height: self.height,
+            entrance: yaw_transform(self.entrance, self.entrance_yaw),
+            exit: yaw_transform(self.exit, self.exit_yaw),
+        }))
+    }
+}
+
+/// The portals of an app, plus the viewer tracking needed to detect crossings.
+#[derive(Default)]
+pub struct Portals {
+    portals: Vec<Portal>,
+    /// Viewer position at the previous check, `None` after a discontinuity
+    last_position: Option<[f32; 3]>,
+    /// Whether an XR session is running (head poses drive crossings)
+    xr_active: bool,
+    /// Tracking origin in the scene, as position and yaw
+    rig: ([f32; 3], f32),
+}
+
+impl Portals {
+    pub fn new(portals: Vec<Portal>) -> Self {
+        Self {
+            portals,
+            ..Default::default()
+        }
+    }
+
+    pub fn list(&self) -> &[Portal] {
+        &self.portals
+    }
+
+    /// Commands to run at startup: one `CreatePortal` per portal.
+    pub fn init_commands(&self) -> Vec<Command> {
+        self.portals.iter().map(Portal::to_command).collect()
+    }
+
+    /// Where the tracking origin is in the scene, as position and yaw
+    pub(crate) fn rig(&self) -> ([f32; 3], f32) {
+        self.rig
+    }
+
+    /// Move the tracking origin; the viewer's next position is a jump, not
+    /// a step through a portal
+    pub(crate) fn set_rig(&mut self, position: [f32; 3], yaw: f32) -> Command {
+        self.rig = (position, yaw);
+        self.last_position = None;
+        Command::Xr(XrCommand::SetRigTransform(yaw_transform(position, yaw)))
+    }
+
+    /// Process an event, teleporting the camera or XR rig when the viewer
+    /// walks through a portal. Call after the camera has handled the event.
+    pub fn handle_event(&mut self, event: &Event, camera: &mut CameraController) -> Vec<Command> {
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => self.check_support(&init.features),
+            Event::Lifecycle(LifecycleEvent::Frame(_)) if !self.xr_active => self.handle_camera(camera),
+            Event::Xr(XrEvent::SessionChanged(data)) => {
+                self.xr_active = data.state == XrSessionState::Active;
+                self.last_position = None;
+                vec![]
+            }
+            Event::Xr(XrEvent::ReferenceSpaceReset) => {
+                self.last_position = None;
+                vec![]
+            }
+            Event::Xr(XrEvent::HeadPose(pose)) if self.xr_active => self.handle_head(pose.position),
+            _ => vec![],
+        }
+    }
+
+    fn check_support(&self, features: &[String]) -> Vec<Command> {
+        if self.portals.is_empty() || features.iter().any(|f| f == FEATURE_PORTALS) {
+            return vec![];
+        }
+        vec![Command::Debug(DebugCommand::Log {
+            level: LogLevel::Warn,
+            message: "Shell cannot render portals; they are invisible but still teleport".to_string(),
+        })]
+    }
+
+    /// The portal crossed by moving to `position`, if any
+    fn crossing(&mut self, position: [f32; 3]) -> Option<Portal> {
+        let last = self.last_position.replace(position)?;
+        let step = sub(position, last);
+        if (step[0] * step[0] + step[1] * step[1] + step[2] * step[2]).sqrt() > MAX_STEP {
+            return None;
+        }
+        self.portals.iter().find(|p| p.crossed(last, position)).cloned()
+    }
+
+    fn handle_camera(&mut self, camera: &mut CameraController) -> Vec<Command> {
+        let Some(portal) = self.crossing(camera.position) else {
+            return vec![];
+        };
+        // Camera yaw turns the other way round: its forward is (cos, sin) in XZ
+        let (position, yaw) = (portal.map_point(camera.position), camera.yaw - portal.turn());
+        camera.set_pose(position, yaw, camera.pitch);
+        self.last_position = Some(position);
+        vec![camera.make_camera_command()]
+    }
+
+    fn handle_head(&mut self, head: [f32; 3]) -> Vec<Command> {
+        let Some(portal) = self.crossing(head) else {
+            return vec![];
+        };
+        let (head, rig_position, turn) = (portal.map_point(head), portal.map_point(self.rig.0), portal.turn());
+        self.rig = (rig_position, self.rig.1 + turn);
+        self.last_position = Some(head);
+        vec![Command::Xr(XrCommand::SetRigTransform(yaw_transform(self.rig.0, self.rig.1)))]
+    }
+}
+
+pub(crate) fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+/// Rotate around +Y (right-handed, same as the `Transform` quaternion)
+pub(crate) fn rotate_y(v: [f32; 3], angle: f32) -> [f32; 3] {
+    let (sin, cos) = angle.sin_cos();
+    [v[0] * cos + v[2] * sin, v[1], v[2] * cos - v[0] * sin]
+}
+
+fn yaw_transform(position: [f32; 3], yaw: f32) -> Transform {
+    let (sin, cos) = (yaw / 2.0).sin_cos();
+    Transform {
+        position,
+        rotation: [0.0, sin, 0.0, cos],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    /// A doorway 2m in front of the origin, facing +Z, leading to a turned
+    /// spot far away
+    fn door() -> Portal {
+        Portal::new("garden")
+            .size(1.0, 2.0)
+            .entrance(0.0, 1.0, -2.0, 0.0)
+            .exit(50.0, 1.0, 0.0, FRAC_PI_2)
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_crossing_hit() {
+        let door = door();
+        assert!(door.crossed([0.2, 1.5, -1.8], [0.2, 1.5, -2.2]));
+        // Either way through
+        assert!(door.crossed([0.0, 1.0, -2.2], [0.0, 1.0, -1.8]));
+        // Moving along the surface isn't crossing it
+        assert!(!door.crossed([0.0, 1.0, -1.8], [0.3, 1.0, -1.9]));
+    }
+
+    #[test]
+    fn test_near_miss_outside_surface() {
+        let door = door();
+        // Just past the sides (half width 0.5)
+        assert!(!door.crossed([0.6, 1.0, -1.8], [0.6, 1.0, -2.2]));
+        assert!(!door.crossed([-0.6, 1.0, -1.8], [-0.6, 1.0, -2.2]));
+        // Over the top and under the bottom (half height 1.0 around y = 1)
+        assert!(!door.crossed([0.0, 2.1, -1.8], [0.0, 2.1, -2.2]));
+        assert!(!door.crossed([0.0, -0.1, -1.8], [0.0, -0.1, -2.2]));
+    }
+
+    #[test]
+    fn test_long_jump_does_not_cross() {
+        let mut portals = Portals::new(vec![door()]);
+        let (from, to) = ([0.0, 1.0, -1.0], [0.0, 1.0, -3.5]);
+        assert!(door().crossed(from, to));
+
+        assert!(portals.crossing(from).is_none());
+        assert!(portals.crossing(to).is_none(), "a jump over MAX_STEP crossed");
+
+        // Walking back in steps does cross
+        assert!(portals.crossing([0.0, 1.0, -2.6]).is_none());
+        assert_eq!(portals.crossing([0.0, 1.0, -1.8]).map(|p| p.id), Some("garden".to_string()));
+    }
+
+    #[test]
+    fn test_reverse_maps_exit_back_to_entrance() {
+        let door = door();
+        let back = door.reverse();
+        assert_eq!(back.id, "garden:return");
+        assert_close(back.map_point(door.exit), door.entrance);
+
+        let point = [0.3, 1.2, -1.5];
+        let there = door.map_point(point);
+        assert_close(there, [50.5, 1.2, -0.3]);
+        assert_close(back.map_point(there), point);
+        assert_eq!(door.turn(), -back.turn());
+    }
+}
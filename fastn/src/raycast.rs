@@ -0,0 +1,876 @@
+//! Ray casts against the scene's geometry
+//!
+//! The core keeps the shape of every entity it can: generated solids
+//! (boxes, spheres, planes and cylinders) exactly, and loaded models whose
+//! geometry the app hands over with `LoadedEntity::geometry`, parsed from
+//! the model file with `MeshGeometry::from_glb_bytes`. Meshes get a
+//! bounding volume hierarchy when they are parsed, so a ray only tests the
+//! triangles near it.
+//!
+//! `Scene::raycast` finds the nearest surface a ray hits: the entity, the
+//! point, the surface normal and the distance. Callbacks reach the scene
+//! with `ctx.scene()`. The framework casts rays too: taps are picked in the
+//! core on shells that don't answer hit tests, `on_gaze` callbacks hear
+//! which entity the user looks at, and `Scene::teleport_target` finds floor
+//! to teleport onto.
+//!
+//! On slow devices `RaycastOptions::triangle_budget` caps the triangles one
+//! cast tests. Once it is spent, the rest of a mesh is answered by the
+//! bounding boxes of its hierarchy: coarser, but at a fixed cost. Set the
+//! options for the whole scene with `content.set_raycast_options`.
+//!
+//! Entity transforms are those the core knows, as for audio occlusion: an
+//! animated entity is where its current animation step ends. Text and
+//! loaded models without geometry are never hit.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{Entity, MeshGeometry, RaycastOptions, RealityViewContent};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     let statue = MeshGeometry::from_glb_bytes(include_bytes!("../assets/statue.glb")).unwrap();
+//!     content.add(Entity::load("statue.glb").geometry(statue).position(0.0, 0.0, -3.0));
+//!     content.set_raycast_options(RaycastOptions::default().triangle_budget(2_000));
+//!     content.on_gaze(|ctx, hit| {
+//!         if let Some(hit) = hit {
+//!             ctx.announce(format!("Looking at {}, {:.1} m away", hit.entity, hit.distance));
+//!         }
+//!     });
+//! }
+//! ```
+
+use crate::animation::Animations;
+use crate::entity::Placement;
+use crate::entity::EntityKind;
+use crate::mesh::MeshResource;
+use fastn_protocol::Transform;
+use serde_json::Value;
+use std::rc::Rc;
+
+/// Most triangles in a leaf of a mesh's hierarchy
+const LEAF_TRIANGLES: usize = 4;
+
+/// Rays this close to parallel with a triangle miss it
+const PARALLEL_EPSILON: f32 = 1e-9;
+
+/// Teleports land on surfaces facing at most 45 degrees away from up (the
+/// cosine of that)
+const TELEPORT_MIN_UP: f32 = 0.7;
+
+/// First four bytes of a binary glTF file ("glTF")
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_CHUNK_JSON: u32 = 0x4E4F_534A;
+const GLB_CHUNK_BIN: u32 = 0x004E_4942;
+
+/// glTF accessor component types
+const COMPONENT_U8: u64 = 5121;
+const COMPONENT_U16: u64 = 5123;
+const COMPONENT_U32: u64 = 5125;
+const COMPONENT_F32: u64 = 5126;
+
+/// glTF primitive mode for triangle lists, the default
+const MODE_TRIANGLES: u64 = 4;
+
+/// Where a ray hit the scene
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaycastHit {
+    /// ID of the entity hit
+    pub entity: String,
+    /// Scene-space hit point
+    pub point: [f32; 3],
+    /// Scene-space surface normal, facing the ray
+    pub normal: [f32; 3],
+    /// Distance from the ray origin to `point`
+    pub distance: f32,
+}
+
+/// How far and how precisely rays are cast
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastOptions {
+    /// Surfaces further than this are not hit
+    pub max_distance: f32,
+    /// Triangles one cast tests at most, None for no limit
+    pub triangle_budget: Option<usize>,
+}
+
+impl Default for RaycastOptions {
+    fn default() -> Self {
+        Self {
+            max_distance: f32::INFINITY,
+            triangle_budget: None,
+        }
+    }
+}
+
+impl RaycastOptions {
+    /// Only hit surfaces within `meters` of the ray origin (builder style).
+    pub fn max_distance(mut self, meters: f32) -> Self {
+        self.max_distance = meters;
+        self
+    }
+
+    /// Test at most `triangles` triangles per cast, answering the rest of
+    /// the scene with bounding boxes (builder style).
+    pub fn triangle_budget(mut self, triangles: usize) -> Self {
+        self.triangle_budget = Some(triangles);
+        self
+    }
+}
+
+/// Triangle geometry of a model, with a bounding volume hierarchy for
+/// ray casts.
+#[derive(Clone)]
+pub struct MeshGeometry {
+    positions: Vec<[f32; 3]>,
+    /// Ordered so every hierarchy leaf owns a contiguous run
+    triangles: Vec<[u32; 3]>,
+    /// Depth first, root first
+    nodes: Vec<BvhNode>,
+}
+
+impl std::fmt::Debug for MeshGeometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MeshGeometry")
+            .field("vertices", &self.positions.len())
+            .field("triangles", &self.triangles.len())
+            .field("nodes", &self.nodes.len())
+            .finish()
+    }
+}
+
+impl MeshGeometry {
+    /// Geometry from a triangle list: three `indices` into `positions` per
+    /// triangle. Triangles with indices out of range are dropped.
+    pub fn new(positions: Vec<[f32; 3]>, indices: &[u32]) -> Self {
+        let mut triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .filter(|t| t.iter().all(|&i| (i as usize) < positions.len()))
+            .collect();
+        let mut nodes = vec![];
+        if !triangles.is_empty() {
+            build(&mut nodes, &positions, &mut triangles, 0);
+        }
+        Self {
+            positions,
+            triangles,
+            nodes,
+        }
+    }
+
+    /// Parse the triangles of a binary glTF file's default scene (or its
+    /// first), placed the way shells draw the model: with the node
+    /// transforms applied.
+    ///
+    /// Only positions and indices are read. Buffers outside the file and
+    /// non-triangle primitives aren't supported.
+    pub fn from_glb_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let (json, bin) = glb_chunks(bytes)?;
+        let gltf: Value = serde_json::from_slice(json).map_err(|e| format!("Invalid glTF JSON: {}", e))?;
+        let scene = gltf["scene"].as_u64().unwrap_or(0) as usize;
+        let roots = gltf["scenes"][scene]["nodes"]
+            .as_array()
+            .ok_or_else(|| "No scenes found in glTF file".to_string())?;
+        let node_count = gltf["nodes"].as_array().map_or(0, Vec::len);
+
+        let mut positions = vec![];
+        let mut indices = vec![];
+        // (node, transform of its parent)
+        let mut stack: Vec<(usize, [f32; 16])> = roots.iter().filter_map(as_index).map(|n| (n, IDENTITY)).collect();
+        let mut visited = 0;
+        while let Some((index, parent)) = stack.pop() {
+            let node = &gltf["nodes"][index];
+            if !node.is_object() {
+                return Err(format!("glTF node {} not found", index));
+            }
+            visited += 1;
+            if visited > node_count {
+                return Err("glTF node hierarchy has a cycle".to_string());
+            }
+            let transform = multiply(&parent, &node_matrix(node));
+            stack.extend(node["children"].as_array().into_iter().flatten().filter_map(as_index).map(|c| (c, transform)));
+
+            let Some(mesh) = as_index(&node["mesh"]) else {
+                continue;
+            };
+            for primitive in gltf["meshes"][mesh]["primitives"].as_array().into_iter().flatten() {
+                if primitive["mode"].as_u64().unwrap_or(MODE_TRIANGLES) != MODE_TRIANGLES {
+                    continue;
+                }
+                let Some(accessor) = as_index(&primitive["attributes"]["POSITION"]) else {
+                    continue;
+                };
+                let first = positions.len() as u32;
+                let values = read_accessor(&gltf, bin, accessor, "VEC3", &[COMPONENT_F32])?;
+                positions.extend(
+                    values
+                        .chunks_exact(3)
+                        .map(|p| transform_point(&transform, [p[0] as f32, p[1] as f32, p[2] as f32])),
+                );
+                let count = positions.len() as u32 - first;
+                // Non-indexed primitives use their vertices in order
+                match as_index(&primitive["indices"]) {
+                    Some(accessor) => {
+                        let types = [COMPONENT_U8, COMPONENT_U16, COMPONENT_U32];
+                        let values = read_accessor(&gltf, bin, accessor, "SCALAR", &types)?;
+                        indices.extend(values.into_iter().map(|i| i as u32 + first));
+                    }
+                    None => indices.extend(first..first + count),
+                }
+            }
+        }
+        if indices.len() < 3 {
+            return Err("No triangle meshes in glTF file".to_string());
+        }
+        Ok(Self::new(positions, &indices))
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Corners of the box around the geometry, None if it has no triangles
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        self.nodes.first().map(|root| (root.bounds.min, root.bounds.max))
+    }
+
+    /// Nearest hit of a model-space ray before `limit`, as the ray
+    /// parameter and an unnormalized normal. Each triangle tested spends
+    /// one from `budget`; with none left, boxes stand in for what's in them.
+    fn cast(&self, ray: &Ray, limit: f32, budget: &mut usize) -> Option<(f32, [f32; 3])> {
+        let root = self.nodes.first()?;
+        let mut best: Option<(f32, [f32; 3])> = None;
+        // (node, where the ray enters its box), the nearest on top
+        let mut stack = vec![(0, root.bounds.slab(ray)?.near())];
+        while let Some((index, enter)) = stack.pop() {
+            if enter > best.map_or(limit, |(t, _)| t) {
+                continue;
+            }
+            let node = &self.nodes[index];
+            let leaf = node.count > 0;
+            if *budget == 0 || (leaf && node.count as usize > *budget) {
+                *budget = 0;
+                if let Some(slab) = node.bounds.slab(ray) {
+                    best = nearer(best, Some((slab.near(), slab.near_normal(ray))), limit);
+                }
+                continue;
+            }
+            if leaf {
+                let start = node.start as usize;
+                *budget -= node.count as usize;
+                for triangle in &self.triangles[start..start + node.count as usize] {
+                    let [a, b, c] = triangle.map(|i| self.positions[i as usize]);
+                    best = nearer(best, intersect_triangle(ray, a, b, c), limit);
+                }
+                continue;
+            }
+            let children = [index + 1, node.start as usize]
+                .map(|child| Some((child, self.nodes[child].bounds.slab(ray)?.near())));
+            let [first, second] = match children {
+                [Some(a), Some(b)] if b.1 < a.1 => [Some(b), Some(a)],
+                children => children,
+            };
+            // Farther child first, so the nearer one is popped next
+            stack.extend(second);
+            stack.extend(first);
+        }
+        best
+    }
+}
+
+/// A node of a mesh's bounding volume hierarchy
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    /// Leaves: first triangle. Inner nodes: index of the second child (the
+    /// first follows the node).
+    start: u32,
+    /// Triangles of a leaf, 0 for inner nodes
+    count: u32,
+}
+
+/// Add the hierarchy over `triangles` (which start at `start` in the
+/// mesh's list) to `nodes`, reordering them, and return its root
+fn build(nodes: &mut Vec<BvhNode>, positions: &[[f32; 3]], triangles: &mut [[u32; 3]], start: usize) -> usize {
+    let bounds = triangles
+        .iter()
+        .flatten()
+        .fold(Aabb::EMPTY, |bounds, &i| bounds.grow(positions[i as usize]));
+    let index = nodes.len();
+    nodes.push(BvhNode {
+        bounds,
+        start: start as u32,
+        count: triangles.len() as u32,
+    });
+    if triangles.len() <= LEAF_TRIANGLES {
+        return index;
+    }
+
+    // Split at the median centroid along the longest side
+    let extent = sub(bounds.max, bounds.min);
+    let axis = (0..3).max_by(|&a, &b| extent[a].total_cmp(&extent[b])).unwrap_or(0);
+    let center = |t: &[u32; 3]| t.iter().map(|&i| positions[i as usize][axis]).sum::<f32>();
+    let middle = triangles.len() / 2;
+    triangles.select_nth_unstable_by(middle, |a, b| center(a).total_cmp(&center(b)));
+    let (first, second) = triangles.split_at_mut(middle);
+    build(nodes, positions, first, start);
+    let second = build(nodes, positions, second, start + middle);
+    nodes[index].start = second as u32;
+    nodes[index].count = 0;
+    index
+}
+
+/// Shape of an entity, in its local space
+#[derive(Debug, Clone)]
+enum Shape {
+    /// Half extents of a box centered on the origin
+    Box([f32; 3]),
+    Sphere(f32),
+    /// Around the Y axis, centered on the origin
+    Cylinder { radius: f32, half_height: f32 },
+    Mesh(Rc<MeshGeometry>),
+}
+
+impl Shape {
+    /// None for meshes too thin to hit
+    fn of(mesh: &MeshResource) -> Option<Self> {
+        Some(match *mesh {
+            MeshResource::Box { size } => Shape::Box([size / 2.0; 3]),
+            MeshResource::BoxWithDimensions { width, height, depth } => {
+                Shape::Box([width / 2.0, height / 2.0, depth / 2.0])
+            }
+            MeshResource::Sphere { radius, .. } => Shape::Sphere(radius),
+            MeshResource::Plane { width, depth } => Shape::Box([width / 2.0, 0.0, depth / 2.0]),
+            // Cones and tori are hit within the cylinders around them
+            MeshResource::Cylinder { radius, height, .. } | MeshResource::Cone { radius, height, .. } => {
+                Shape::Cylinder {
+                    radius,
+                    half_height: height / 2.0,
+                }
+            }
+            // Tori too, their hole included
+            MeshResource::Torus { radius, tube_radius, .. } => Shape::Cylinder {
+                radius: radius + tube_radius,
+                half_height: tube_radius,
+            },
+            MeshResource::Text { .. } => return None,
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let half = match self {
+            Shape::Box(half) => *half,
+            Shape::Sphere(radius) => [*radius; 3],
+            Shape::Cylinder { radius, half_height } => [*radius, *half_height, *radius],
+            Shape::Mesh(mesh) => return mesh.nodes.first().map(|root| root.bounds),
+        };
+        Some(Aabb {
+            min: half.map(|h| -h),
+            max: half,
+        })
+    }
+
+    /// Nearest hit before `limit`, as the ray parameter and an
+    /// unnormalized local normal. Rays from inside a solid hit it on the
+    /// way out.
+    fn cast(&self, ray: &Ray, limit: f32, budget: &mut usize) -> Option<(f32, [f32; 3])> {
+        let hit = match self {
+            Shape::Box(_) => {
+                let slab = self.bounds()?.slab(ray)?;
+                match slab.enter >= 0.0 {
+                    true => (slab.enter, slab.near_normal(ray)),
+                    false => (slab.exit, axis_normal(slab.exit_axis, ray.direction[slab.exit_axis])),
+                }
+            }
+            Shape::Sphere(radius) => {
+                let t = smallest_root(
+                    dot(ray.direction, ray.direction),
+                    2.0 * dot(ray.origin, ray.direction),
+                    dot(ray.origin, ray.origin) - radius * radius,
+                )?;
+                (t, ray.at(t))
+            }
+            Shape::Cylinder { radius, half_height } => cast_cylinder(ray, *radius, *half_height)?,
+            Shape::Mesh(mesh) => return mesh.cast(ray, limit, budget),
+        };
+        (hit.0 <= limit).then_some(hit)
+    }
+}
+
+/// Side and caps of a cylinder around the Y axis
+fn cast_cylinder(ray: &Ray, radius: f32, half_height: f32) -> Option<(f32, [f32; 3])> {
+    let ([ox, oy, oz], [dx, dy, dz]) = (ray.origin, ray.direction);
+    let mut hits: Vec<(f32, [f32; 3])> = vec![];
+    let a = dx * dx + dz * dz;
+    let b = 2.0 * (ox * dx + oz * dz);
+    let c = ox * ox + oz * oz - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if a > PARALLEL_EPSILON && discriminant >= 0.0 {
+        let root = discriminant.sqrt();
+        for t in [(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)] {
+            let point = ray.at(t);
+            if t >= 0.0 && point[1].abs() <= half_height {
+                hits.push((t, [point[0], 0.0, point[2]]));
+            }
+        }
+    }
+    if dy.abs() > PARALLEL_EPSILON {
+        for cap in [-half_height, half_height] {
+            let t = (cap - oy) / dy;
+            let point = ray.at(t);
+            if t >= 0.0 && point[0] * point[0] + point[2] * point[2] <= radius * radius {
+                hits.push((t, [0.0, cap.signum(), 0.0]));
+            }
+        }
+    }
+    hits.into_iter().min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// Smallest non-negative root of `a t^2 + b t + c`
+fn smallest_root(a: f32, b: f32, c: f32) -> Option<f32> {
+    let discriminant = b * b - 4.0 * a * c;
+    if a < PARALLEL_EPSILON || discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    [(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)].into_iter().find(|t| *t >= 0.0)
+}
+
+/// Möller–Trumbore: the ray parameter and the (unnormalized) face normal
+fn intersect_triangle(ray: &Ray, a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> Option<(f32, [f32; 3])> {
+    let (ab, ac) = (sub(b, a), sub(c, a));
+    let p = cross(ray.direction, ac);
+    let determinant = dot(ab, p);
+    if determinant.abs() < PARALLEL_EPSILON {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = sub(ray.origin, a);
+    let u = dot(s, p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = cross(s, ab);
+    let v = dot(ray.direction, q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = dot(ac, q) * inverse;
+    (t >= 0.0).then(|| (t, cross(ab, ac)))
+}
+
+/// The nearer of two hits, as long as it is within `limit`
+fn nearer(best: Option<(f32, [f32; 3])>, hit: Option<(f32, [f32; 3])>, limit: f32) -> Option<(f32, [f32; 3])> {
+    match (best, hit) {
+        (best, Some(hit)) if hit.0 <= best.map_or(limit, |b| b.0) => Some(hit),
+        (best, _) => best,
+    }
+}
+
+/// A ray in some entity's space. The direction isn't normalized there, so
+/// the ray parameter stays the scene-space distance.
+#[derive(Debug, Clone, Copy)]
+struct Ray {
+    origin: [f32; 3],
+    direction: [f32; 3],
+}
+
+impl Ray {
+    fn at(&self, t: f32) -> [f32; 3] {
+        std::array::from_fn(|i| self.origin[i] + t * self.direction[i])
+    }
+}
+
+/// An axis-aligned box
+#[derive(Debug, Clone, Copy)]
+struct Aabb {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+/// Where a ray enters and leaves a box, and through which axes' faces
+#[derive(Debug, Clone, Copy)]
+struct Slab {
+    enter: f32,
+    exit: f32,
+    enter_axis: usize,
+    exit_axis: usize,
+}
+
+impl Slab {
+    /// The first point of the box on the ray (the origin, if it's inside)
+    fn near(&self) -> f32 {
+        self.enter.max(0.0)
+    }
+
+    fn near_normal(&self, ray: &Ray) -> [f32; 3] {
+        axis_normal(self.enter_axis, -ray.direction[self.enter_axis])
+    }
+}
+
+impl Aabb {
+    const EMPTY: Aabb = Aabb {
+        min: [f32::INFINITY; 3],
+        max: [f32::NEG_INFINITY; 3],
+    };
+
+    fn grow(self, point: [f32; 3]) -> Self {
+        Self {
+            min: std::array::from_fn(|i| self.min[i].min(point[i])),
+            max: std::array::from_fn(|i| self.max[i].max(point[i])),
+        }
+    }
+
+    /// None if the ray misses the box or it is behind the origin
+    fn slab(&self, ray: &Ray) -> Option<Slab> {
+        let mut slab = Slab {
+            enter: f32::NEG_INFINITY,
+            exit: f32::INFINITY,
+            enter_axis: 0,
+            exit_axis: 0,
+        };
+        for i in 0..3 {
+            if ray.direction[i].abs() < PARALLEL_EPSILON {
+                if ray.origin[i] < self.min[i] || ray.origin[i] > self.max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (self.min[i] - ray.origin[i]) / ray.direction[i];
+            let b = (self.max[i] - ray.origin[i]) / ray.direction[i];
+            if a.min(b) > slab.enter {
+                slab.enter = a.min(b);
+                slab.enter_axis = i;
+            }
+            if a.max(b) < slab.exit {
+                slab.exit = a.max(b);
+                slab.exit_axis = i;
+            }
+        }
+        (slab.enter <= slab.exit && slab.exit >= 0.0).then_some(slab)
+    }
+}
+
+/// Unit vector along `axis`, pointing the way `sign` does
+fn axis_normal(axis: usize, sign: f32) -> [f32; 3] {
+    std::array::from_fn(|i| if i == axis { sign.signum() } else { 0.0 })
+}
+
+/// An entity the scene's rays can hit
+#[derive(Debug, Clone)]
+struct Collider {
+    entity: String,
+    shape: Shape,
+    transform: Transform,
+}
+
+impl Collider {
+    /// The ray in the entity's space, None for entities scaled flat
+    fn local_ray(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<Ray> {
+        let scale = self.transform.scale;
+        if scale.iter().any(|s| s.abs() < f32::EPSILON) {
+            return None;
+        }
+        let [x, y, z, w] = self.transform.rotation;
+        let inverse = [-x, -y, -z, w];
+        let origin = rotate(inverse, sub(origin, self.transform.position));
+        let direction = rotate(inverse, direction);
+        Some(Ray {
+            origin: std::array::from_fn(|i| origin[i] / scale[i]),
+            direction: std::array::from_fn(|i| direction[i] / scale[i]),
+        })
+    }
+
+    /// A local normal in scene space, facing against `direction`
+    fn scene_normal(&self, normal: [f32; 3], direction: [f32; 3]) -> [f32; 3] {
+        let scaled = std::array::from_fn(|i| normal[i] / self.transform.scale[i]);
+        let normal = normalize(rotate(self.transform.rotation, scaled));
+        match dot(normal, direction) > 0.0 {
+            true => normal.map(|c| -c),
+            false => normal,
+        }
+    }
+}
+
+/// What rays can hit: the shapes of the scene's entities, where the core
+/// knows them to be.
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    colliders: Vec<Collider>,
+    options: RaycastOptions,
+}
+
+impl Scene {
+    /// Collect the shapes of the scene's entities.
+    pub(crate) fn new(entities: &[EntityKind], options: RaycastOptions) -> Self {
+        let mut scene = Self {
+            colliders: vec![],
+            options,
+        };
+        for entity in entities {
+            entity.visit_volumes(&Placement::default(), &mut |entity, at| {
+                let shape = match entity {
+                    EntityKind::ModelEntity(model) => Shape::of(model.mesh()),
+                    EntityKind::LoadedEntity(loaded) => loaded.collision_geometry().cloned().map(Shape::Mesh),
+                    EntityKind::Entity(_) => None,
+                };
+                if let Some(shape) = shape {
+                    scene.colliders.push(Collider {
+                        entity: entity.id().to_string(),
+                        shape,
+                        transform: at.world.clone(),
+                    });
+                }
+            });
+        }
+        scene
+    }
+
+    /// Move the shapes to where the entities are now.
+    pub(crate) fn sync(&mut self, animations: &Animations) {
+        for collider in &mut self.colliders {
+            if let Some(transform) = animations.world_transform(&collider.entity) {
+                collider.transform = transform;
+            }
+        }
+    }
+
+    /// The nearest surface the ray from `origin` along `direction` hits,
+    /// with the scene's options. `direction` doesn't need to be normalized.
+    pub fn raycast(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<RaycastHit> {
+        self.raycast_with(origin, direction, &self.options)
+    }
+
+    /// Like `raycast`, with other options than the scene's.
+    pub fn raycast_with(&self, origin: [f32; 3], direction: [f32; 3], options: &RaycastOptions) -> Option<RaycastHit> {
+        let length = dot(direction, direction).sqrt();
+        if length < f32::EPSILON {
+            return None;
+        }
+        let direction = direction.map(|c| c / length);
+
+        // Nearest boxes first, so far entities are skipped once something
+        // nearer is hit
+        let mut candidates: Vec<(f32, &Collider, Ray)> = self
+            .colliders
+            .iter()
+            .filter_map(|collider| {
+                let ray = collider.local_ray(origin, direction)?;
+                let enter = collider.shape.bounds()?.slab(&ray)?.near();
+                Some((enter, collider, ray))
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut budget = options.triangle_budget.unwrap_or(usize::MAX);
+        let mut best: Option<(f32, &Collider, [f32; 3])> = None;
+        for (enter, collider, ray) in candidates {
+            let limit = best.map_or(options.max_distance, |(t, ..)| t);
+            if enter > limit {
+                break;
+            }
+            if let Some((t, normal)) = collider.shape.cast(&ray, limit, &mut budget) {
+                best = Some((t, collider, normal));
+            }
+        }
+        let (distance, collider, normal) = best?;
+        Some(RaycastHit {
+            entity: collider.entity.clone(),
+            point: std::array::from_fn(|i| origin[i] + distance * direction[i]),
+            normal: collider.scene_normal(normal, direction),
+            distance,
+        })
+    }
+
+    /// Where a teleport aimed along the ray lands: the hit point, if the
+    /// surface there is floor (facing within 45 degrees of up).
+    pub fn teleport_target(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<[f32; 3]> {
+        let hit = self.raycast(origin, direction)?;
+        (hit.normal[1] >= TELEPORT_MIN_UP).then_some(hit.point)
+    }
+
+    pub fn options(&self) -> &RaycastOptions {
+        &self.options
+    }
+
+    /// Center and radius of a sphere around the given entities, from the
+    /// corners of their boxes where they are now. Entities whose shape the
+    /// scene doesn't know are left out; None if that is all of them.
+    pub fn bounding_sphere(&self, entity_ids: &[impl AsRef<str>]) -> Option<([f32; 3], f32)> {
+        let corners: Vec<[f32; 3]> = self
+            .colliders
+            .iter()
+            .filter(|collider| entity_ids.iter().any(|id| id.as_ref() == collider.entity))
+            .filter_map(|collider| Some((collider, collider.shape.bounds()?)))
+            .flat_map(|(collider, bounds)| {
+                let Transform { position, rotation, scale } = &collider.transform;
+                (0..8).map(move |corner| {
+                    let local: [f32; 3] = std::array::from_fn(|i| {
+                        let end = if corner & (1 << i) == 0 { bounds.min[i] } else { bounds.max[i] };
+                        end * scale[i]
+                    });
+                    let rotated = rotate(*rotation, local);
+                    std::array::from_fn(|i| rotated[i] + position[i])
+                })
+            })
+            .collect();
+        let first = *corners.first()?;
+        let (min, max) = corners.iter().fold((first, first), |(min, max), p| {
+            (std::array::from_fn(|i| min[i].min(p[i])), std::array::from_fn(|i| max[i].max(p[i])))
+        });
+        let center = std::array::from_fn(|i| (min[i] + max[i]) / 2.0);
+        let radius = corners.iter().map(|&p| dot(sub(p, center), sub(p, center)).sqrt()).fold(0.0, f32::max);
+        Some((center, radius))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Binary glTF
+// ----------------------------------------------------------------------------
+
+/// The JSON and binary chunks of a GLB file
+fn glb_chunks(bytes: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    let word = |at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    if word(0) != Some(GLB_MAGIC) {
+        return Err("Not a binary glTF file".to_string());
+    }
+    if word(4) != Some(2) {
+        return Err(format!("Unsupported glTF version {:?}", word(4)));
+    }
+    let (mut json, mut bin) = (None, &[][..]);
+    let mut at = 12;
+    while let (Some(length), Some(kind)) = (word(at), word(at + 4)) {
+        let data = bytes
+            .get(at + 8..at + 8 + length as usize)
+            .ok_or_else(|| "Truncated GLB chunk".to_string())?;
+        match kind {
+            GLB_CHUNK_JSON => json = json.or(Some(data)),
+            GLB_CHUNK_BIN => bin = data,
+            _ => {}
+        }
+        at += 8 + length as usize;
+    }
+    Ok((json.ok_or_else(|| "GLB file has no JSON chunk".to_string())?, bin))
+}
+
+/// The values of an accessor into the GLB's binary chunk, one after another
+fn read_accessor(gltf: &Value, bin: &[u8], index: usize, kind: &str, types: &[u64]) -> Result<Vec<f64>, String> {
+    let accessor = &gltf["accessors"][index];
+    if accessor["type"].as_str() != Some(kind) {
+        return Err(format!("Accessor {} is {:?}, expected {}", index, accessor["type"].as_str(), kind));
+    }
+    let components = if kind == "VEC3" { 3 } else { 1 };
+    let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+    if !types.contains(&component_type) {
+        return Err(format!("Accessor {} has unsupported component type {}", index, component_type));
+    }
+    let size = if component_type == COMPONENT_U8 { 1 } else if component_type == COMPONENT_U16 { 2 } else { 4 };
+    let count = accessor["count"].as_u64().unwrap_or(0) as usize;
+    let view = as_index(&accessor["bufferView"])
+        .map(|view| &gltf["bufferViews"][view])
+        .ok_or_else(|| format!("Accessor {} has no buffer view", index))?;
+    if view["buffer"].as_u64() != Some(0) || gltf["buffers"][0]["uri"].is_string() {
+        return Err(format!("Accessor {} is not in the GLB's binary chunk", index));
+    }
+
+    let view_start = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+    let view_end = view_start + view["byteLength"].as_u64().unwrap_or(0) as usize;
+    let start = view_start + accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+    let stride = view["byteStride"].as_u64().map_or(size * components, |s| s as usize);
+    let end = match count {
+        0 => start,
+        count => start + stride * (count - 1) + size * components,
+    };
+    if end > view_end.min(bin.len()) {
+        return Err(format!("Accessor {} runs past its buffer", index));
+    }
+
+    let mut values = Vec::with_capacity(count * components);
+    for element in 0..count {
+        for component in 0..components {
+            let at = start + element * stride + component * size;
+            let b = &bin[at..at + size];
+            values.push(match component_type {
+                COMPONENT_U8 => b[0] as f64,
+                COMPONENT_U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                COMPONENT_U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            });
+        }
+    }
+    Ok(values)
+}
+
+fn as_index(value: &Value) -> Option<usize> {
+    value.as_u64().map(|v| v as usize)
+}
+
+/// `N` numbers from a JSON array, or `default` if it isn't one that long
+fn floats<const N: usize>(value: &Value, default: [f32; N]) -> [f32; N] {
+    match value.as_array() {
+        Some(array) if array.len() == N => std::array::from_fn(|i| array[i].as_f64().unwrap_or(0.0) as f32),
+        _ => default,
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Matrix and vector helpers (column-major 4x4 matrices, as in glTF)
+// ----------------------------------------------------------------------------
+
+const IDENTITY: [f32; 16] = [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+
+/// A node's local transform, from its matrix or its TRS properties
+fn node_matrix(node: &Value) -> [f32; 16] {
+    if node["matrix"].is_array() {
+        return floats(&node["matrix"], IDENTITY);
+    }
+    let translation = floats(&node["translation"], [0.0; 3]);
+    let rotation = floats(&node["rotation"], [0.0, 0.0, 0.0, 1.0]);
+    let scale = floats(&node["scale"], [1.0; 3]);
+    let x = rotate(rotation, [scale[0], 0.0, 0.0]);
+    let y = rotate(rotation, [0.0, scale[1], 0.0]);
+    let z = rotate(rotation, [0.0, 0.0, scale[2]]);
+    let [tx, ty, tz] = translation;
+    [x[0], x[1], x[2], 0.0, y[0], y[1], y[2], 0.0, z[0], z[1], z[2], 0.0, tx, ty, tz, 1.0]
+}
+
+fn multiply(a: &[f32; 16], b: &[f32; 16]) -> [f32; 16] {
+    std::array::from_fn(|i| {
+        let (column, row) = (i / 4, i % 4);
+        (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum()
+    })
+}
+
+fn transform_point(m: &[f32; 16], p: [f32; 3]) -> [f32; 3] {
+    std::array::from_fn(|row| m[row] * p[0] + m[4 + row] * p[1] + m[8 + row] * p[2] + m[12 + row])
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt();
+    match length > f32::EPSILON {
+        true => v.map(|c| c / length),
+        false => [0.0, 1.0, 0.0],
+    }
+}
+
+/// Rotate a vector by a unit quaternion [x, y, z, w]
+fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let u = [q[0], q[1], q[2]];
+    let t = cross(u, v).map(|c| 2.0 * c);
+    let ut = cross(u, t);
+    [v[0] + q[3] * t[0] + ut[0], v[1] + q[3] * t[1] + ut[1], v[2] + q[3] * t[2] + ut[2]]
+}
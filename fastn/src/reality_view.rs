@@ -23,7 +23,18 @@
 //! }
 //! ```
 
-use crate::{Command, EntityKind};
+use crate::asset_uri::{AssetResolver, AssetResolvers, AssetUri, AssetUriError};
+use crate::atlas::{TextureAtlas, TextureAtlases};
+use crate::camera::CameraControls;
+use crate::entity::{path_asset_id, Placement};
+use crate::gizmo::Handle;
+use crate::handlers::{EventContext, EventHandlers};
+use crate::{
+    AssetCommand, AssetScheme, AudioSource, Bookmark, CachePolicy, CaptureFailedData, CaptureSavedData, CollisionData, Command, DebugCommand,
+    EntityKind, Event, FlagValue, FrameEvent, GestureThresholds, KeyEventData, LogLevel, Panel, PanelPointerData, PointLight, Portal, Priority, RaycastHit, RaycastOptions, SceneCommand,
+    SimpleMaterial, Viewfinder, XrGestureData,
+};
+use std::collections::{BTreeMap, HashSet};
 
 /// Content container for RealityView.
 ///
@@ -32,6 +43,24 @@ use crate::{Command, EntityKind};
 #[derive(Debug, Default)]
 pub struct RealityViewContent {
     pub(crate) entities: Vec<EntityKind>,
+    pub(crate) bookmarks: Vec<Bookmark>,
+    pub(crate) portals: Vec<Portal>,
+    pub(crate) viewfinders: Vec<Viewfinder>,
+    pub(crate) panels: Vec<Panel>,
+    pub(crate) audio_sources: Vec<AudioSource>,
+    pub(crate) lights: Vec<PointLight>,
+    pub(crate) lights_per_volume: Option<usize>,
+    pub(crate) asset_resolvers: AssetResolvers,
+    pub(crate) atlases: TextureAtlases,
+    pub(crate) prefetches: Vec<String>,
+    pub(crate) handlers: EventHandlers,
+    pub(crate) handles: Vec<Handle>,
+    pub(crate) raycast_options: RaycastOptions,
+    pub(crate) flags: BTreeMap<String, FlagValue>,
+    pub(crate) remote_config: Option<String>,
+    pub(crate) resume_disabled: bool,
+    pub(crate) gravity: Option<[f32; 3]>,
+    pub(crate) camera_controls: CameraControls,
 }
 
 impl RealityViewContent {
@@ -47,37 +76,358 @@ impl RealityViewContent {
         self.entities.push(entity.into());
     }
 
+    /// Add a spatial bookmark with a teleportation marker.
+    ///
+    /// Bookmarks occupy the `1`-`9` teleport slots in the order they are added.
+    pub fn add_bookmark(&mut self, bookmark: Bookmark) {
+        self.bookmarks.push(bookmark);
+    }
+
+    /// Add a portal. Add `portal.reverse()` too for a two-way doorway.
+    pub fn add_portal(&mut self, portal: Portal) {
+        self.portals.push(portal);
+    }
+
+    /// Add a viewfinder, a panel showing what photo and video captures see.
+    pub fn add_viewfinder(&mut self, viewfinder: Viewfinder) {
+        self.viewfinders.push(viewfinder);
+    }
+
+    /// Add a 2D panel, on shells with `FEATURE_PANELS`.
+    pub fn add_panel(&mut self, panel: Panel) {
+        self.panels.push(panel);
+    }
+
+    /// Add a sound playing in the scene, from a point or an entity.
+    pub fn add_audio_source(&mut self, source: AudioSource) {
+        self.audio_sources.push(source);
+    }
+
+    /// Add a point light, at a point or following an entity, on shells with
+    /// `FEATURE_DYNAMIC_LIGHTS`.
+    pub fn add_light(&mut self, light: PointLight) {
+        self.lights.push(light);
+    }
+
+    /// Light each volume with at most its `count` most significant lights
+    /// (`LIGHTS_PER_VOLUME` by default).
+    pub fn set_lights_per_volume(&mut self, count: usize) {
+        self.lights_per_volume = Some(count);
+    }
+
+    /// Register a resolver for an app-defined asset URI scheme.
+    ///
+    /// See the `asset_uri` module docs for an example.
+    pub fn register_asset_resolver(
+        &mut self,
+        scheme: &str,
+        resolver: impl AssetResolver + 'static,
+    ) -> Result<(), AssetUriError> {
+        self.asset_resolvers.register(scheme, resolver)
+    }
+
+    /// Add a texture atlas, whose regions materials can reference with
+    /// `SimpleMaterial::atlas_region`.
+    pub fn add_atlas(&mut self, atlas: TextureAtlas) {
+        self.atlases.add(atlas);
+    }
+
+    /// Have the shell load model files the scene doesn't show yet, once it
+    /// has loaded what it does, so `Entity::load` of them later is quick.
+    /// Files fetched over the network stay in the shell's asset cache for
+    /// later sessions too (see `CachePolicy`).
+    ///
+    /// ```rust,ignore
+    /// content.prefetch(["level-2.glb", "https://cdn.example.com/boss.glb"]);
+    /// ```
+    pub fn prefetch<I>(&mut self, paths: I)
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.prefetches.extend(paths.into_iter().map(Into::into));
+    }
+
+    /// Run `callback` when a key is pressed (also for auto-repeats, see
+    /// `key.repeat`).
+    pub fn on_key_down(&mut self, callback: impl Fn(&mut EventContext, &KeyEventData) + 'static) {
+        self.handlers.on_key_down(callback);
+    }
+
+    /// Run `callback` when a key is released.
+    pub fn on_key_up(&mut self, callback: impl Fn(&mut EventContext, &KeyEventData) + 'static) {
+        self.handlers.on_key_up(callback);
+    }
+
+    /// Run `callback` every frame, e.g. to advance app state by `frame.dt`.
+    pub fn on_frame(&mut self, callback: impl Fn(&mut EventContext, &FrameEvent) + 'static) {
+        self.handlers.on_frame(callback);
+    }
+
+    /// Run `callback` when the entity's volume is clicked, tapped or
+    /// activated by a screen reader.
+    pub fn on_tap(&mut self, volume_id: &str, callback: impl Fn(&mut EventContext) + 'static) {
+        self.handlers.on_tap(volume_id, callback);
+    }
+
+    /// Run `callback` for gestures that start on the entity's volume: taps,
+    /// double taps, long presses, drags and pinches (see the `gesture`
+    /// module).
+    pub fn on_gesture(&mut self, volume_id: &str, callback: impl Fn(&mut EventContext, &XrGestureData) + 'static) {
+        self.handlers.on_gesture(volume_id, callback);
+    }
+
+    /// Run `callback` for every gesture, on a volume (`gesture.volume_id`)
+    /// or not.
+    pub fn on_any_gesture(&mut self, callback: impl Fn(&mut EventContext, &XrGestureData) + 'static) {
+        self.handlers.on_any_gesture(callback);
+    }
+
+    /// Set how far a press may move and how long it may be held before it
+    /// is no longer a tap, and the other thresholds of recognized gestures.
+    pub fn set_gesture_thresholds(&mut self, thresholds: GestureThresholds) {
+        self.handlers.set_gesture_thresholds(thresholds);
+    }
+
+    /// Run `callback` when a pointer presses, moves over or releases a
+    /// panel. After a press on the panel, moves and the release reach it
+    /// wherever they happen.
+    pub fn on_panel_pointer(&mut self, panel_id: &str, callback: impl Fn(&mut EventContext, &PanelPointerData) + 'static) {
+        self.handlers.on_panel_pointer(panel_id, callback);
+    }
+
+    /// Run `callback` when a capture started with `ctx.capture_photo` or
+    /// `ctx.capture_video` is saved, or fails.
+    pub fn on_capture(
+        &mut self,
+        callback: impl Fn(&mut EventContext, Result<&CaptureSavedData, &CaptureFailedData>) + 'static,
+    ) {
+        self.handlers.on_capture(callback);
+    }
+
+    /// Let people drag an entity with a manipulation handle
+    /// (`TranslateHandle`, `RotateHandle` or `ScaleHandle`).
+    pub fn add_handle(&mut self, handle: impl Into<Handle>) {
+        self.handles.push(handle.into());
+    }
+
+    /// Run `callback` when the entity the user looks at during an XR
+    /// session changes, with where the gaze hits it (None when it leaves
+    /// every entity whose shape the core knows).
+    pub fn on_gaze(&mut self, callback: impl Fn(&mut EventContext, Option<&RaycastHit>) + 'static) {
+        self.handlers.on_gaze(callback);
+    }
+
+    /// Set how far and how precisely `Scene::raycast` casts rays, e.g. a
+    /// triangle budget on mobile devices.
+    pub fn set_raycast_options(&mut self, options: RaycastOptions) {
+        self.raycast_options = options;
+    }
+
+    /// Declare a feature flag; its type is the default's (`bool`, an integer,
+    /// a number or a string). Callbacks read it with `ctx.flags()`.
+    pub fn add_flag(&mut self, name: &str, default: impl Into<FlagValue>) {
+        self.flags.insert(name.to_string(), default.into());
+    }
+
+    /// Take flag values from a JSON document in a kosha
+    /// (`kosha://<hub>/<kosha>/<path>`), following its changes.
+    ///
+    /// See the `remote_config` module docs for the format.
+    pub fn set_remote_config(&mut self, uri: &str) -> Result<(), AssetUriError> {
+        let uri = AssetUri::parse(uri)?;
+        if uri.builtin_scheme() != Some(AssetScheme::Kosha) {
+            return Err(AssetUriError::Invalid {
+                uri: uri.to_string(),
+                reason: "remote config must be in a kosha (kosha://...)".to_string(),
+            });
+        }
+        self.remote_config = Some(uri.to_string());
+        Ok(())
+    }
+
+    /// Run `callback` when the remote config changes a flag, with its new
+    /// value.
+    pub fn on_flag_change(&mut self, name: &str, callback: impl Fn(&mut EventContext, &FlagValue) + 'static) {
+        self.handlers.on_flag_change(name, callback);
+    }
+
+    /// Start every launch from the app's initial camera instead of where
+    /// the user left off (see `SessionRestore`).
+    pub fn disable_resume(&mut self) {
+        self.resume_disabled = true;
+    }
+
+    /// Set the default camera controller's mode and key bindings, or turn
+    /// it off with `CameraControls::disabled()`.
+    pub fn set_camera_controls(&mut self, controls: CameraControls) {
+        self.camera_controls = controls;
+    }
+
+    /// Set the acceleration pulling physics bodies, in meters per second
+    /// squared (`DEFAULT_GRAVITY` unless set).
+    pub fn set_gravity(&mut self, gravity: [f32; 3]) {
+        self.gravity = Some(gravity);
+    }
+
+    /// Run `callback` when two entities with colliders start touching (at
+    /// least one of them a physics body).
+    pub fn on_collision_start(&mut self, callback: impl Fn(&mut EventContext, &CollisionData) + 'static) {
+        self.handlers.on_collision_start(callback);
+    }
+
+    /// Run `callback` when two entities that touched come apart.
+    pub fn on_collision_end(&mut self, callback: impl Fn(&mut EventContext, &CollisionData) + 'static) {
+        self.handlers.on_collision_end(callback);
+    }
+
+    /// Run `callback` for every event the shell sends, for anything the
+    /// other callbacks don't cover.
+    pub fn on_event(&mut self, callback: impl Fn(&mut EventContext, &Event) + 'static) {
+        self.handlers.on_event(callback);
+    }
+
     /// Convert all entities to commands.
     pub(crate) fn to_commands(&self) -> Vec<Command> {
         let mut commands = Vec::new();
+        for atlas in self.atlases.iter() {
+            match self.asset_resolvers.resolve(atlas.image()) {
+                Ok(uri) => commands.extend(atlas.to_commands(&uri)),
+                Err(e) => commands.push(Command::Debug(DebugCommand::Log {
+                    level: LogLevel::Error,
+                    message: format!("Skipping texture atlas {}: {}", atlas.id(), e),
+                })),
+            }
+        }
+        let mut loaded_assets = HashSet::new();
         for entity in &self.entities {
-            Self::collect_commands(entity, &mut commands);
+            entity.visit_volumes(&Placement::default(), &mut |entity, at| {
+                commands.extend(self.volume_commands(entity, at, &mut loaded_assets));
+            });
         }
+        commands.extend(self.prefetch_commands(&mut loaded_assets));
         commands
     }
 
-    fn collect_commands(entity: &EntityKind, commands: &mut Vec<Command>) {
-        match entity {
-            EntityKind::Entity(e) => {
-                // Empty entities don't produce commands, but their children do
-                for child in e.children() {
-                    Self::collect_commands(child, commands);
+    /// Low priority loads of the prefetched files the scene doesn't load
+    /// already. Invalid paths are reported and skipped.
+    fn prefetch_commands(&self, loaded_assets: &mut HashSet<String>) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for path in &self.prefetches {
+            let asset_id = path_asset_id(path);
+            if loaded_assets.contains(&asset_id) {
+                continue;
+            }
+            match self.asset_resolvers.resolve(path) {
+                Ok(uri) => {
+                    loaded_assets.insert(asset_id.clone());
+                    commands.push(Command::Asset(AssetCommand::Load {
+                        asset_id,
+                        path: uri.to_string(),
+                        priority: Priority::Low,
+                        cache: CachePolicy::Default,
+                    }));
                 }
+                Err(e) => commands.push(Command::Debug(DebugCommand::Log {
+                    level: LogLevel::Error,
+                    message: format!("Skipping prefetch of {}: {}", path, e),
+                })),
             }
+        }
+        commands
+    }
+
+    /// The audio sources, the content's and its entities', with their paths
+    /// resolved to asset URIs. Sources with invalid paths are reported and
+    /// skipped.
+    pub(crate) fn resolve_audio_sources(&self) -> (Vec<AudioSource>, Vec<Command>) {
+        let mut all = self.audio_sources.clone();
+        for entity in &self.entities {
+            entity.collect_audio(&Placement::default(), &mut all);
+        }
+        let mut sources = Vec::new();
+        let mut errors = Vec::new();
+        for source in &all {
+            match self.asset_resolvers.resolve(&source.path) {
+                Ok(uri) => sources.push(AudioSource {
+                    path: uri.to_string(),
+                    ..source.clone()
+                }),
+                Err(e) => errors.push(Command::Debug(DebugCommand::Log {
+                    level: LogLevel::Error,
+                    message: format!("Skipping audio source {}: {}", source.id, e),
+                })),
+            }
+        }
+        (sources, errors)
+    }
+
+    /// The commands creating an entity's volume, attached to its parent's
+    fn volume_commands(
+        &self,
+        entity: &EntityKind,
+        at: &Placement,
+        loaded_assets: &mut HashSet<String>,
+    ) -> Vec<Command> {
+        let mut commands = vec![];
+        let mut command = match entity {
+            // Plain entities have no volume
+            EntityKind::Entity(_) => return commands,
             EntityKind::ModelEntity(m) => {
-                commands.push(m.to_command());
-                for child in m.children() {
-                    Self::collect_commands(child, commands);
-                }
+                let mut command = m.to_command();
+                commands.extend(self.resolve_atlas_region(&mut command, Some(m.material())));
+                command
             }
             EntityKind::LoadedEntity(l) => {
-                // First emit asset load command, then create volume command
-                commands.push(l.to_load_command());
-                commands.push(l.to_create_command());
-                for child in l.children() {
-                    Self::collect_commands(child, commands);
+                // First emit asset load command (once per asset, shared by
+                // copies), then create volume command. Entities with invalid
+                // asset references are reported and skipped; their children
+                // end up at the top level.
+                match self.asset_resolvers.resolve(l.path()) {
+                    Ok(uri) => {
+                        if loaded_assets.insert(l.asset_id().to_string()) {
+                            commands.push(l.to_load_command(&uri));
+                        }
+                        let mut command = l.to_create_command();
+                        commands.extend(self.resolve_atlas_region(&mut command, l.material_override()));
+                        command
+                    }
+                    Err(e) => {
+                        commands.push(Command::Debug(DebugCommand::Log {
+                            level: LogLevel::Error,
+                            message: format!("Skipping entity {}: {}", l.id(), e),
+                        }));
+                        return commands;
+                    }
                 }
             }
+        };
+        if let Command::Scene(SceneCommand::CreateVolume(data)) = &mut command {
+            data.transform = at.local.clone();
+            data.parent_id = at.parent.map(str::to_string);
+        }
+        commands.push(command);
+        commands
+    }
+
+    /// Point a new volume's material at its atlas region. Unknown atlases
+    /// and regions are reported and the volume stays untextured.
+    fn resolve_atlas_region(&self, command: &mut Command, material: Option<&SimpleMaterial>) -> Option<Command> {
+        let (atlas, region) = material?.atlas_region.as_ref()?;
+        let Command::Scene(SceneCommand::CreateVolume(data)) = command else {
+            return None;
+        };
+        match self.atlases.uv_rect(atlas, region) {
+            Ok(uv_rect) => {
+                let material = data.material.as_mut()?;
+                material.texture_id = Some(atlas.clone());
+                material.uv_rect = Some(uv_rect);
+                None
+            }
+            Err(e) => Some(Command::Debug(DebugCommand::Log {
+                level: LogLevel::Error,
+                message: format!("Entity {}: {}", data.volume_id, e),
+            })),
         }
     }
 }
@@ -0,0 +1,249 @@
+//! Remote Configuration and Feature Flags
+//!
+//! Apps declare flags with defaults, and can then change them without
+//! shipping new WASM by editing a JSON document in a kosha:
+//!
+//! ```rust,ignore
+//! use fastn::{FlagValue, RealityViewContent};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     content.add_flag("new_lobby", false);
+//!     content.add_flag("spawn_rate", 2.5);
+//!     content.set_remote_config("kosha://home/my-app/flags.json").unwrap();
+//!
+//!     content.on_flag_change("new_lobby", |ctx, value| {
+//!         if value == &FlagValue::Bool(true) {
+//!             ctx.announce("The new lobby is open");
+//!         }
+//!     });
+//!     content.on_frame(|ctx, frame| {
+//!         let rate = ctx.flags().float("spawn_rate");
+//!         // ...
+//!     });
+//! }
+//! ```
+//!
+//! The document is a JSON object of flag values, e.g.
+//! `{"new_lobby": true, "spawn_rate": 4.0}`. Flags it leaves out keep their
+//! defaults; values of the wrong type and undeclared flags are ignored with
+//! a warning.
+//!
+//! Shells with `FEATURE_KOSHA` (a spoke connected to a hub) watch the
+//! document for the core, which applies every version it gets and runs the
+//! `on_flag_change` callbacks of the flags that changed. On other shells, or
+//! until the document arrives, flags have their defaults.
+
+use fastn_protocol::*;
+use std::collections::BTreeMap;
+
+/// The value of a flag; its type is the type of the flag's default.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlagValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl FlagValue {
+    /// Convert a document value to this value's type
+    fn coerce(&self, value: &serde_json::Value) -> Option<FlagValue> {
+        match self {
+            FlagValue::Bool(_) => value.as_bool().map(FlagValue::Bool),
+            FlagValue::Int(_) => value.as_i64().map(FlagValue::Int),
+            FlagValue::Float(_) => value.as_f64().map(FlagValue::Float),
+            FlagValue::String(_) => value.as_str().map(|s| FlagValue::String(s.to_string())),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            FlagValue::Bool(_) => "a boolean",
+            FlagValue::Int(_) => "an integer",
+            FlagValue::Float(_) => "a number",
+            FlagValue::String(_) => "a string",
+        }
+    }
+}
+
+impl From<bool> for FlagValue {
+    fn from(value: bool) -> Self {
+        FlagValue::Bool(value)
+    }
+}
+
+impl From<i64> for FlagValue {
+    fn from(value: i64) -> Self {
+        FlagValue::Int(value)
+    }
+}
+
+impl From<i32> for FlagValue {
+    fn from(value: i32) -> Self {
+        FlagValue::Int(value.into())
+    }
+}
+
+impl From<f64> for FlagValue {
+    fn from(value: f64) -> Self {
+        FlagValue::Float(value)
+    }
+}
+
+impl From<f32> for FlagValue {
+    fn from(value: f32) -> Self {
+        FlagValue::Float(value.into())
+    }
+}
+
+impl From<&str> for FlagValue {
+    fn from(value: &str) -> Self {
+        FlagValue::String(value.to_string())
+    }
+}
+
+impl From<String> for FlagValue {
+    fn from(value: String) -> Self {
+        FlagValue::String(value)
+    }
+}
+
+/// The app's flags: their defaults and current values.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags {
+    defaults: BTreeMap<String, FlagValue>,
+    values: BTreeMap<String, FlagValue>,
+    /// `kosha://` URI of the config document
+    source: Option<String>,
+    /// Whether a version of the document has been applied
+    remote: bool,
+    /// Flags whose value the last event changed
+    changed: Vec<String>,
+}
+
+impl FeatureFlags {
+    pub(crate) fn new(defaults: BTreeMap<String, FlagValue>, source: Option<String>) -> Self {
+        Self {
+            values: defaults.clone(),
+            defaults,
+            source,
+            ..Default::default()
+        }
+    }
+
+    /// The current value of a flag, None if it wasn't declared.
+    pub fn get(&self, name: &str) -> Option<&FlagValue> {
+        self.values.get(name)
+    }
+
+    /// A boolean flag's value; false for other flags.
+    pub fn bool(&self, name: &str) -> bool {
+        matches!(self.get(name), Some(FlagValue::Bool(true)))
+    }
+
+    /// An integer flag's value; 0 for other flags.
+    pub fn int(&self, name: &str) -> i64 {
+        match self.get(name) {
+            Some(FlagValue::Int(value)) => *value,
+            _ => 0,
+        }
+    }
+
+    /// A number flag's value (integer flags too); 0 for other flags.
+    pub fn float(&self, name: &str) -> f64 {
+        match self.get(name) {
+            Some(FlagValue::Float(value)) => *value,
+            Some(FlagValue::Int(value)) => *value as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// A string flag's value; empty for other flags.
+    pub fn string(&self, name: &str) -> &str {
+        match self.get(name) {
+            Some(FlagValue::String(value)) => value,
+            _ => "",
+        }
+    }
+
+    /// Whether the values come from the config document rather than the
+    /// defaults.
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    /// Flags the event being handled changed, for their callbacks
+    pub(crate) fn changed(&self) -> &[String] {
+        &self.changed
+    }
+
+    /// Process an event: watch the config document once the shell is ready
+    /// and apply the versions it sends.
+    pub fn handle_event(&mut self, event: &Event) -> Vec<Command> {
+        self.changed.clear();
+        let Some(source) = &self.source else {
+            return vec![];
+        };
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                if init.features.iter().any(|f| f == FEATURE_KOSHA) {
+                    vec![Command::Kosha(KoshaCommand::Watch { uri: source.clone() })]
+                } else {
+                    vec![log(LogLevel::Info, "Shell has no kosha connection, flags keep their defaults".to_string())]
+                }
+            }
+            Event::Kosha(KoshaEvent::FileLoaded { uri, content }) if uri == source => self.apply(content.as_deref()),
+            Event::Kosha(KoshaEvent::Error { uri, error }) if uri == source => {
+                vec![log(LogLevel::Warn, format!("Cannot read remote config {}: {}", uri, error))]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Apply a version of the config document; a missing document restores
+    /// the defaults, an invalid one is reported and skipped.
+    fn apply(&mut self, content: Option<&str>) -> Vec<Command> {
+        let document = match content.map(serde_json::from_str::<serde_json::Value>) {
+            None => serde_json::Map::new(),
+            Some(Ok(serde_json::Value::Object(document))) => document,
+            Some(Ok(_)) => return vec![self.invalid("expected a JSON object")],
+            Some(Err(e)) => return vec![self.invalid(&e.to_string())],
+        };
+
+        let mut commands = vec![];
+        for name in document.keys().filter(|name| !self.defaults.contains_key(*name)) {
+            commands.push(log(LogLevel::Warn, format!("Remote config sets undeclared flag {}", name)));
+        }
+        for (name, default) in &self.defaults {
+            let value = match document.get(name) {
+                None => default.clone(),
+                Some(value) => match default.coerce(value) {
+                    Some(value) => value,
+                    None => {
+                        commands.push(log(
+                            LogLevel::Warn,
+                            format!("Remote config flag {} must be {}, got {}", name, default.type_name(), value),
+                        ));
+                        default.clone()
+                    }
+                },
+            };
+            if self.values.get(name) != Some(&value) {
+                self.values.insert(name.clone(), value);
+                self.changed.push(name.clone());
+            }
+        }
+        self.remote = content.is_some();
+        commands
+    }
+
+    fn invalid(&self, reason: &str) -> Command {
+        let source = self.source.as_deref().unwrap_or_default();
+        log(LogLevel::Error, format!("Ignoring invalid remote config {}: {}", source, reason))
+    }
+}
+
+fn log(level: LogLevel, message: String) -> Command {
+    Command::Debug(DebugCommand::Log { level, message })
+}
@@ -0,0 +1,276 @@
+//! Resuming Where the User Left Off
+//!
+//! When the app is paused or shut down, the core saves the camera pose, the
+//! immersive session that was running (its mode, where the rig was and
+//! where the user stood in it) and whether the debug HUD was open, through
+//! the storage API. The next launch loads them back:
+//!
+//! - the camera returns to its pose and the debug HUD opens again,
+//! - a session of the same mode is requested if the shell can run one
+//!   (browsers may refuse without a user gesture; the rig is then restored
+//!   whenever the user enters one),
+//! - once the session runs and the head is tracked, the rig goes back where
+//!   it was if the spot the user stood on is inside the current play area.
+//!   Otherwise (another room, a smaller boundary, or a shell that doesn't
+//!   report one) the scene is moved so that the user stands where they
+//!   stood, and nobody has to walk out of their play area to get back.
+//!
+//! Apps get this without code; `RealityViewContent::disable_resume` turns
+//! it off. A shell reloading the app in place (`InitEvent::camera`) keeps
+//! its own state instead.
+
+use crate::camera::CameraController;
+use crate::debug_hud::DebugHud;
+use crate::portal::{rotate_y, sub, Portals};
+use fastn_protocol::*;
+use serde::{Deserialize, Serialize};
+
+/// Storage key under which the session is saved
+pub const RESUME_STORAGE_KEY: &str = "fastn.resume";
+
+/// What the core saves when the app is paused or shut down.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedSession {
+    /// Camera pose, as in `Bookmark`
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Mode of the immersive session the user was in
+    pub xr_mode: Option<XrMode>,
+    /// Where the rig was, if the head was tracked
+    pub rig: Option<SavedRig>,
+    pub debug_hud: bool,
+}
+
+/// The tracking origin's place in the scene, and where the user stood.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SavedRig {
+    pub position: [f32; 3],
+    /// Turn around +Y, in radians
+    pub yaw: f32,
+    /// Head position relative to the tracking origin
+    pub head: [f32; 3],
+}
+
+impl SavedSession {
+    fn is_finite(&self) -> bool {
+        let rig = self.rig.is_none_or(|rig| {
+            rig.position.iter().chain(&rig.head).chain([&rig.yaw]).all(|v| v.is_finite())
+        });
+        rig && self.position.iter().chain([&self.yaw, &self.pitch]).all(|v| v.is_finite())
+    }
+}
+
+/// Saves the session on `Pause` and `Shutdown`, and restores it at launch.
+#[derive(Debug, Default)]
+pub struct SessionRestore {
+    enabled: bool,
+    /// The shell's `Init`, None until it arrives
+    init: Option<InitEvent>,
+    /// Loaded before `Init` arrived, restored then
+    loaded: Option<SavedSession>,
+    /// Whether storage has answered; saving earlier would overwrite what
+    /// hasn't been loaded yet
+    answered: bool,
+    /// Rig to restore once a session of this mode runs with the head tracked
+    pending_rig: Option<(XrMode, SavedRig)>,
+    /// Mode of the running session
+    xr_mode: Option<XrMode>,
+    play_area: Option<PlayArea>,
+    /// Latest head position, in the scene
+    head: Option<[f32; 3]>,
+    /// The session that ended last, still the one to resume if the app
+    /// closes before going on without it
+    ended: Option<(XrMode, Option<SavedRig>)>,
+}
+
+impl SessionRestore {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    /// Commands to run at startup: a request for the saved session.
+    pub fn init_commands(&self) -> Vec<Command> {
+        if !self.enabled {
+            return vec![];
+        }
+        vec![Command::Storage(StorageCommand::Get {
+            key: RESUME_STORAGE_KEY.to_string(),
+        })]
+    }
+
+    /// Process an event, saving the session or restoring the saved one.
+    /// Call after the camera, the portals and the debug HUD have handled it.
+    pub fn handle_event(
+        &mut self,
+        event: &Event,
+        camera: &mut CameraController,
+        portals: &mut Portals,
+        debug_hud: &mut DebugHud,
+    ) -> Vec<Command> {
+        if !self.enabled {
+            return vec![];
+        }
+        match event {
+            Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                self.init = Some(init.clone());
+                match self.loaded.take() {
+                    Some(saved) => self.restore(saved, camera, debug_hud),
+                    None => vec![],
+                }
+            }
+            Event::Lifecycle(LifecycleEvent::Pause | LifecycleEvent::Shutdown) if self.answered => {
+                self.save(camera, portals, debug_hud).into_iter().collect()
+            }
+            Event::Lifecycle(LifecycleEvent::Frame(_)) if self.xr_mode.is_none() => {
+                self.ended = None;
+                vec![]
+            }
+            Event::Xr(XrEvent::SessionChanged(data)) => {
+                match data.state {
+                    XrSessionState::Ending => self.ended = self.xr_mode.map(|mode| (mode, self.current_rig(portals))),
+                    XrSessionState::None => {
+                        self.xr_mode = None;
+                        self.head = None;
+                    }
+                    _ => {
+                        self.xr_mode = data.mode;
+                        self.ended = None;
+                    }
+                }
+                self.play_area = data.play_area;
+                vec![]
+            }
+            Event::Xr(XrEvent::HeadPose(pose)) if self.xr_mode.is_some() => {
+                self.head = Some(pose.position);
+                self.restore_rig(pose.position, portals)
+            }
+            Event::Storage(StorageEvent::Loaded { key, value }) if key == RESUME_STORAGE_KEY => {
+                self.answered = true;
+                match value {
+                    Some(value) => self.handle_loaded(value, camera, debug_hud),
+                    None => vec![],
+                }
+            }
+            Event::Storage(StorageEvent::Error { key, .. }) if key == RESUME_STORAGE_KEY => {
+                self.answered = true;
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn handle_loaded(&mut self, value: &str, camera: &mut CameraController, debug_hud: &mut DebugHud) -> Vec<Command> {
+        let saved = serde_json::from_str::<SavedSession>(value)
+            .map_err(|e| e.to_string())
+            .and_then(|saved| match saved.is_finite() {
+                true => Ok(saved),
+                false => Err("not a finite pose".to_string()),
+            });
+        match saved {
+            Ok(saved) if self.init.is_some() => self.restore(saved, camera, debug_hud),
+            Ok(saved) => {
+                self.loaded = Some(saved);
+                vec![]
+            }
+            Err(e) => vec![Command::Debug(DebugCommand::Log {
+                level: LogLevel::Warn,
+                message: format!("Ignoring invalid saved session: {}", e),
+            })],
+        }
+    }
+
+    fn restore(&mut self, saved: SavedSession, camera: &mut CameraController, debug_hud: &mut DebugHud) -> Vec<Command> {
+        let Some(init) = &self.init else {
+            return vec![];
+        };
+        // A shell reloading the app in place has restored it already
+        if init.camera.is_some() {
+            return vec![];
+        }
+        camera.set_pose(saved.position, saved.yaw, saved.pitch);
+        let mut commands = vec![];
+        if saved.debug_hud && !debug_hud.is_visible() {
+            commands.extend(debug_hud.set_visible(true));
+        }
+        let Some(mode) = saved.xr_mode else {
+            return commands;
+        };
+        let supported = match mode {
+            XrMode::ImmersiveVr => init.xr_immersive_vr,
+            XrMode::ImmersiveAr => init.xr_immersive_ar,
+        };
+        if supported && self.xr_mode.is_none() {
+            commands.push(Command::Xr(XrCommand::Enter {
+                mode,
+                dom_overlay: false,
+            }));
+        }
+        self.pending_rig = saved.rig.map(|rig| (mode, rig));
+        commands
+    }
+
+    /// Put the rig back once the head is tracked in a session of the saved
+    /// mode
+    fn restore_rig(&mut self, head: [f32; 3], portals: &mut Portals) -> Vec<Command> {
+        let Some((mode, saved)) = self.pending_rig else {
+            return vec![];
+        };
+        if self.xr_mode != Some(mode) {
+            return vec![];
+        }
+        self.pending_rig = None;
+        let position = match self.play_area {
+            Some(area) if area.contains(saved.head[0], saved.head[2]) => saved.position,
+            // Move the scene so the saved spot is where the user is now,
+            // keeping the floor height
+            _ => {
+                let (rig, rig_yaw) = portals.rig();
+                let here = rotate_y(rotate_y(sub(head, rig), -rig_yaw), saved.yaw);
+                let there = rotate_y(saved.head, saved.yaw);
+                [
+                    saved.position[0] + there[0] - here[0],
+                    saved.position[1],
+                    saved.position[2] + there[2] - here[2],
+                ]
+            }
+        };
+        vec![portals.set_rig(position, saved.yaw)]
+    }
+
+    /// The rig and where the head is in it, if the head is tracked
+    fn current_rig(&self, portals: &Portals) -> Option<SavedRig> {
+        let head = self.head?;
+        let (position, yaw) = portals.rig();
+        Some(SavedRig {
+            position,
+            yaw,
+            head: rotate_y(sub(head, position), -yaw),
+        })
+    }
+
+    fn save(&self, camera: &CameraController, portals: &Portals, debug_hud: &DebugHud) -> Option<Command> {
+        // A rig not restored yet is still where the user left off
+        let (xr_mode, rig) = match (self.pending_rig, self.xr_mode) {
+            (Some((mode, rig)), _) => (Some(mode), Some(rig)),
+            (None, Some(mode)) => (Some(mode), self.current_rig(portals)),
+            (None, None) => self.ended.map_or((None, None), |(mode, rig)| (Some(mode), rig)),
+        };
+        let saved = SavedSession {
+            position: camera.position,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+            xr_mode,
+            rig,
+            debug_hud: debug_hud.is_visible(),
+        };
+        let value = serde_json::to_string(&saved).ok()?;
+        Some(Command::Storage(StorageCommand::Set {
+            key: RESUME_STORAGE_KEY.to_string(),
+            value,
+        }))
+    }
+}
@@ -0,0 +1,153 @@
+//! Deferred command scheduling
+//!
+//! Loading a large scene produces a burst of asset loads and volume
+//! creations. Shells that advertise `FEATURE_COMMAND_SCHEDULING` accept these
+//! as `ScheduleCommand::Defer` and spread them across frames within a time
+//! budget, acking each one with a `ScheduleEvent`.
+//!
+//! The core only learns about the shell from its `Init` event, which arrives
+//! after the startup commands have been sent. Deferrable startup commands are
+//! therefore held back until the first event: `Init` decides whether they are
+//! deferred, any other first event (shells that never send `Init`) releases
+//! them as plain commands.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{CommandScheduler, Priority};
+//!
+//! let mut scheduler = CommandScheduler::new();
+//! let mut commands = scheduler.hold(content.to_commands());
+//! // ... later, for every event from the shell
+//! commands.extend(scheduler.handle_event(&event));
+//! commands.extend(scheduler.defer(load_robot, Priority::Low));
+//! ```
+
+use fastn_protocol::*;
+use std::collections::HashSet;
+
+/// Defers expensive commands to shells that can spread them across frames.
+#[derive(Debug, Default)]
+pub struct CommandScheduler {
+    /// Whether the shell supports scheduling, None until its first event
+    supported: Option<bool>,
+    /// Commands waiting for the shell's first event
+    held: Vec<(Command, Priority)>,
+    /// Deferred commands the shell hasn't acked yet
+    pending: HashSet<CommandId>,
+    next_id: u64,
+}
+
+impl CommandScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a command is expensive enough to be worth spreading across
+    /// frames: asset fetches and decodes, mesh buffer uploads, textures.
+    pub fn is_deferrable(command: &Command) -> bool {
+        matches!(
+            command,
+            Command::Asset(AssetCommand::Load { .. })
+                | Command::Scene(SceneCommand::CreateVolume(CreateVolumeData {
+                    source: VolumeSource::Asset { .. },
+                    ..
+                }))
+                | Command::Material(MaterialCommand::CreateTexture(_) | MaterialCommand::UpdateTexture(_))
+        )
+    }
+
+    /// Split startup commands: deferrable ones are held until the shell's
+    /// first event, the rest are returned to be sent right away. Asset
+    /// loads keep their own priority (prefetches are `Low`), everything
+    /// else is `Normal`.
+    pub fn hold(&mut self, commands: Vec<Command>) -> Vec<Command> {
+        let mut immediate = vec![];
+        for command in commands {
+            if !Self::is_deferrable(&command) {
+                immediate.push(command);
+                continue;
+            }
+            let priority = match &command {
+                Command::Asset(AssetCommand::Load { priority, .. }) => *priority,
+                _ => Priority::Normal,
+            };
+            immediate.extend(self.defer(command, priority));
+        }
+        immediate
+    }
+
+    /// Defer a command if the shell supports scheduling, otherwise return it
+    /// unchanged. Returns None while it is held for the shell's first event.
+    pub fn defer(&mut self, command: Command, priority: Priority) -> Option<Command> {
+        match self.supported {
+            None => {
+                self.held.push((command, priority));
+                None
+            }
+            Some(false) => Some(command),
+            Some(true) => {
+                self.next_id += 1;
+                let command_id = format!("cmd-{}", self.next_id);
+                self.pending.insert(command_id.clone());
+                Some(Command::Schedule(ScheduleCommand::Defer(DeferredCommand {
+                    command_id,
+                    priority,
+                    command: Box::new(command),
+                })))
+            }
+        }
+    }
+
+    /// Drop a deferred command the shell hasn't run yet
+    pub fn cancel(&mut self, command_id: &str) -> Option<Command> {
+        self.pending.remove(command_id).then(|| {
+            Command::Schedule(ScheduleCommand::Cancel {
+                command_id: command_id.to_string(),
+            })
+        })
+    }
+
+    /// Deferred commands sent but not acked yet
+    pub fn pending(&self) -> usize {
+        self.pending.len() + self.held.len()
+    }
+
+    /// True once everything deferred so far has been executed
+    pub fn is_idle(&self) -> bool {
+        self.pending() == 0
+    }
+
+    /// Process an event: learn the shell's support from the first one and
+    /// release held commands, track acks and report failed commands.
+    pub fn handle_event(&mut self, event: &Event) -> Vec<Command> {
+        let mut commands = vec![];
+        if self.supported.is_none() {
+            self.supported = Some(match event {
+                Event::Lifecycle(LifecycleEvent::Init(init)) => {
+                    init.features.iter().any(|f| f == FEATURE_COMMAND_SCHEDULING)
+                }
+                _ => false,
+            });
+            for (command, priority) in std::mem::take(&mut self.held) {
+                commands.extend(self.defer(command, priority));
+            }
+        }
+
+        match event {
+            Event::Schedule(ScheduleEvent::Completed { command_id }) => {
+                self.pending.remove(command_id);
+            }
+            Event::Schedule(ScheduleEvent::Failed { command_id, error }) => {
+                if self.pending.remove(command_id) {
+                    commands.push(Command::Debug(DebugCommand::Log {
+                        level: LogLevel::Warn,
+                        message: format!("Deferred command {} failed: {}", command_id, error),
+                    }));
+                }
+            }
+            _ => {}
+        }
+        commands
+    }
+}
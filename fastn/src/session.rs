@@ -0,0 +1,640 @@
+//! Shared Sessions
+//!
+//! Pose broadcasting for multi-user sessions, layered on top of WebRTC data
+//! channels (`RtcCommand::SendData` / `RtcEvent::DataChannelMessage`).
+//!
+//! Sending every head pose at display rate to every peer doesn't scale, so
+//! each peer gets its own send rate:
+//!
+//! - **Interest management**: peers close to us get full rate, distant peers
+//!   progressively less, and peers we aren't facing are reduced further.
+//! - **Per-peer rate control**: a send happens only when the peer's interval
+//!   has elapsed *and* its byte budget allows it; unchanged poses are skipped
+//!   except for a periodic heartbeat.
+//! - **Delta encoding**: a full keyframe is sent periodically; in between,
+//!   poses are sent as small quantized deltas against the last keyframe, so a
+//!   lost packet never corrupts later ones.
+//!
+//! Bandwidth used per peer is tracked in [`PeerMetrics`].
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let mut session = SharedSession::new(BroadcastConfig::default());
+//! // in on_event:
+//! let commands = session.handle_event(&event);
+//! ```
+
+use fastn_protocol::*;
+use std::collections::HashMap;
+
+/// Data channel label used for pose traffic
+pub const POSE_CHANNEL_LABEL: &str = "fastn-pose";
+
+/// Wire tags
+const TAG_KEYFRAME: u8 = 0;
+const TAG_DELTA: u8 = 1;
+
+/// Keyframe: tag, seq, position (3 x f32), orientation (4 x f32)
+const KEYFRAME_LEN: usize = 1 + 2 + 12 + 16;
+/// Delta: tag, seq, base seq, position delta (3 x i16 mm), orientation (4 x i16)
+const DELTA_LEN: usize = 1 + 2 + 2 + 6 + 8;
+
+/// Position deltas are quantized to millimetres
+const POSITION_SCALE: f32 = 1000.0;
+/// Quaternion components are quantized to i16
+const ORIENTATION_SCALE: f32 = i16::MAX as f32;
+
+/// Frames arrive with jitter; a send is allowed when at least this fraction
+/// of the interval has passed, so a 90Hz rate on a 90Hz display isn't halved
+const INTERVAL_SLACK: f64 = 0.9;
+
+/// Window over which `PeerMetrics::bytes_per_second` is measured
+const METRICS_WINDOW: f64 = 1.0;
+
+/// Tuning for pose broadcasting.
+#[derive(Debug, Clone)]
+pub struct BroadcastConfig {
+    /// Send rate for peers within `near_distance` (Hz)
+    pub max_rate: f32,
+    /// Send rate for peers at or beyond `far_distance` (Hz)
+    pub min_rate: f32,
+    /// Distance (metres) up to which peers get `max_rate`
+    pub near_distance: f32,
+    /// Distance (metres) from which peers get `min_rate`
+    pub far_distance: f32,
+    /// Rate multiplier for peers outside our field of view
+    pub out_of_view_factor: f32,
+    /// Half-angle of the field of view used for visibility (radians)
+    pub view_half_angle: f32,
+    /// Seconds between keyframes
+    pub keyframe_interval: f32,
+    /// Send at least this often even if the pose hasn't changed (seconds)
+    pub heartbeat_interval: f32,
+    /// Position change (metres) below which a pose counts as unchanged
+    pub position_epsilon: f32,
+    /// Per-peer byte budget (bytes/second), `None` for unlimited
+    pub max_bytes_per_second: Option<u32>,
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        Self {
+            max_rate: 90.0,
+            min_rate: 5.0,
+            near_distance: 2.0,
+            far_distance: 20.0,
+            out_of_view_factor: 0.25,
+            view_half_angle: 1.0,
+            keyframe_interval: 1.0,
+            heartbeat_interval: 0.5,
+            position_epsilon: 0.001,
+            max_bytes_per_second: None,
+        }
+    }
+}
+
+/// Bandwidth accounting for one peer.
+#[derive(Debug, Clone, Default)]
+pub struct PeerMetrics {
+    pub bytes_sent: u64,
+    pub keyframes_sent: u64,
+    pub deltas_sent: u64,
+    /// Sends suppressed because the pose didn't change or the budget was spent
+    pub skipped: u64,
+    /// Bytes sent over the last second
+    pub bytes_per_second: f32,
+    /// Current target send rate (Hz) after interest management
+    pub rate: f32,
+}
+
+/// Sender-side state for one peer.
+struct Peer {
+    channel_id: ChannelId,
+    /// Last pose received from this peer
+    remote_pose: Option<PoseData>,
+    decoder: PoseDecoder,
+    seq: u16,
+    keyframe: Option<(u16, PoseData)>,
+    last_keyframe_time: f64,
+    last_sent: Option<PoseData>,
+    last_send_time: f64,
+    /// (time, bytes) of recent sends, for the byte budget and metrics
+    recent_sends: Vec<(f64, usize)>,
+    metrics: PeerMetrics,
+}
+
+impl Peer {
+    fn new(channel_id: ChannelId) -> Self {
+        Self {
+            channel_id,
+            remote_pose: None,
+            decoder: PoseDecoder::default(),
+            seq: 0,
+            keyframe: None,
+            last_keyframe_time: f64::NEG_INFINITY,
+            last_sent: None,
+            last_send_time: f64::NEG_INFINITY,
+            recent_sends: Vec::new(),
+            metrics: PeerMetrics::default(),
+        }
+    }
+
+    fn recent_bytes(&mut self, now: f64) -> usize {
+        self.recent_sends.retain(|(t, _)| now - t < METRICS_WINDOW);
+        self.recent_sends.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+/// Adaptive pose broadcaster for a shared session.
+pub struct SharedSession {
+    config: BroadcastConfig,
+    peers: HashMap<ConnectionId, Peer>,
+    local_pose: Option<PoseData>,
+}
+
+impl SharedSession {
+    pub fn new(config: BroadcastConfig) -> Self {
+        Self {
+            config,
+            peers: HashMap::new(),
+            local_pose: None,
+        }
+    }
+
+    /// Start broadcasting to a peer over an open data channel.
+    pub fn add_peer(&mut self, connection_id: impl Into<ConnectionId>, channel_id: impl Into<ChannelId>) {
+        self.peers.insert(connection_id.into(), Peer::new(channel_id.into()));
+    }
+
+    pub fn remove_peer(&mut self, connection_id: &str) {
+        self.peers.remove(connection_id);
+    }
+
+    /// Connected peers.
+    pub fn peers(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.peers.keys()
+    }
+
+    /// Update the pose to broadcast (e.g. from the camera when not in XR).
+    pub fn set_local_pose(&mut self, pose: PoseData) {
+        self.local_pose = Some(pose);
+    }
+
+    /// Latest pose received from a peer.
+    pub fn remote_pose(&self, connection_id: &str) -> Option<&PoseData> {
+        self.peers.get(connection_id)?.remote_pose.as_ref()
+    }
+
+    /// Bandwidth metrics for a peer.
+    pub fn metrics(&self, connection_id: &str) -> Option<&PeerMetrics> {
+        self.peers.get(connection_id).map(|p| &p.metrics)
+    }
+
+    /// Process an event: tracks peers, decodes incoming poses, picks up the
+    /// local head pose and broadcasts once per frame.
+    pub fn handle_event(&mut self, event: &Event) -> Vec<Command> {
+        match event {
+            Event::Network(NetworkEvent::Rtc(rtc)) => {
+                self.handle_rtc(rtc);
+                vec![]
+            }
+            Event::Xr(XrEvent::HeadPose(pose)) => {
+                self.set_local_pose(pose.clone());
+                vec![]
+            }
+            Event::Lifecycle(LifecycleEvent::Frame(frame)) => self.tick(frame.time),
+            _ => vec![],
+        }
+    }
+
+    fn handle_rtc(&mut self, event: &RtcEvent) {
+        match event {
+            RtcEvent::DataChannelOpened {
+                connection_id,
+                channel_id,
+                label,
+            } if label == POSE_CHANNEL_LABEL => self.add_peer(connection_id.clone(), channel_id.clone()),
+            RtcEvent::DataChannelClosed {
+                connection_id,
+                channel_id,
+            } => {
+                if self.peers.get(connection_id).is_some_and(|p| &p.channel_id == channel_id) {
+                    self.remove_peer(connection_id);
+                }
+            }
+            RtcEvent::DataChannelMessage {
+                connection_id,
+                channel_id,
+                data: DataPayload::Binary(bytes),
+            } => {
+                if let Some(peer) = self.peers.get_mut(connection_id)
+                    && &peer.channel_id == channel_id
+                    && let Some(pose) = peer.decoder.decode(bytes)
+                {
+                    peer.remote_pose = Some(pose);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Target send rate for a peer based on distance and visibility.
+    fn peer_rate(&self, local: &PoseData, remote: Option<&PoseData>) -> f32 {
+        let config = &self.config;
+        // Until we know where a peer is, treat it as near
+        let Some(remote) = remote else {
+            return config.max_rate;
+        };
+
+        let offset = sub(remote.position, local.position);
+        let distance = length(offset);
+        let t = ((distance - config.near_distance) / (config.far_distance - config.near_distance).max(f32::EPSILON))
+            .clamp(0.0, 1.0);
+        let mut rate = config.max_rate + (config.min_rate - config.max_rate) * t;
+
+        if distance > f32::EPSILON {
+            let forward = rotate(local.orientation, [0.0, 0.0, -1.0]);
+            let cos_angle = dot(forward, offset) / distance;
+            if cos_angle < config.view_half_angle.cos() {
+                rate *= config.out_of_view_factor;
+            }
+        }
+
+        rate.max(config.min_rate)
+    }
+
+    /// Broadcast the local pose to every peer whose interval has elapsed.
+    fn tick(&mut self, time: f64) -> Vec<Command> {
+        let Some(local) = self.local_pose.clone() else {
+            return vec![];
+        };
+
+        let rates: HashMap<ConnectionId, f32> = self
+            .peers
+            .iter()
+            .map(|(id, peer)| (id.clone(), self.peer_rate(&local, peer.remote_pose.as_ref())))
+            .collect();
+
+        let config = &self.config;
+        let mut commands = vec![];
+        for (connection_id, peer) in self.peers.iter_mut() {
+            let rate = rates[connection_id];
+            peer.metrics.rate = rate;
+
+            if time - peer.last_send_time < INTERVAL_SLACK / rate as f64 {
+                continue;
+            }
+
+            let changed = peer.last_sent.as_ref().is_none_or(|last| {
+                length(sub(last.position, local.position)) > config.position_epsilon
+                    || last.orientation != local.orientation
+            });
+            if !changed && time - peer.last_send_time < config.heartbeat_interval as f64 {
+                peer.metrics.skipped += 1;
+                continue;
+            }
+
+            let keyframe_due = time - peer.last_keyframe_time >= config.keyframe_interval as f64;
+            let delta = match &peer.keyframe {
+                Some((base_seq, base)) if !keyframe_due => encode_delta(peer.seq, *base_seq, base, &local),
+                _ => None,
+            };
+            let is_keyframe = delta.is_none();
+            let bytes = delta.unwrap_or_else(|| encode_keyframe(peer.seq, &local));
+
+            let recent = peer.recent_bytes(time);
+            if let Some(budget) = config.max_bytes_per_second
+                && recent + bytes.len() > budget as usize
+            {
+                peer.metrics.skipped += 1;
+                continue;
+            }
+
+            if is_keyframe {
+                peer.keyframe = Some((peer.seq, local.clone()));
+                peer.last_keyframe_time = time;
+                peer.metrics.keyframes_sent += 1;
+            } else {
+                peer.metrics.deltas_sent += 1;
+            }
+            peer.seq = peer.seq.wrapping_add(1);
+            peer.last_sent = Some(local.clone());
+            peer.last_send_time = time;
+            peer.recent_sends.push((time, bytes.len()));
+            peer.metrics.bytes_sent += bytes.len() as u64;
+            peer.metrics.bytes_per_second = (recent + bytes.len()) as f32 / METRICS_WINDOW as f32;
+
+            commands.push(Command::Network(NetworkCommand::Rtc(RtcCommand::SendData {
+                connection_id: connection_id.clone(),
+                channel_id: peer.channel_id.clone(),
+                data: DataPayload::Binary(bytes),
+            })));
+        }
+        commands
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Wire format
+// ----------------------------------------------------------------------------
+
+fn encode_keyframe(seq: u16, pose: &PoseData) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(KEYFRAME_LEN);
+    bytes.push(TAG_KEYFRAME);
+    bytes.extend_from_slice(&seq.to_le_bytes());
+    for v in pose.position.iter().chain(pose.orientation.iter()) {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    bytes
+}
+
+/// Encode a pose relative to a keyframe; `None` if the offset doesn't fit
+fn encode_delta(seq: u16, base_seq: u16, base: &PoseData, pose: &PoseData) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(DELTA_LEN);
+    bytes.push(TAG_DELTA);
+    bytes.extend_from_slice(&seq.to_le_bytes());
+    bytes.extend_from_slice(&base_seq.to_le_bytes());
+    for i in 0..3 {
+        let mm = ((pose.position[i] - base.position[i]) * POSITION_SCALE).round();
+        if mm.abs() > i16::MAX as f32 {
+            return None;
+        }
+        bytes.extend_from_slice(&(mm as i16).to_le_bytes());
+    }
+    for q in pose.orientation {
+        let q = (q.clamp(-1.0, 1.0) * ORIENTATION_SCALE).round() as i16;
+        bytes.extend_from_slice(&q.to_le_bytes());
+    }
+    Some(bytes)
+}
+
+/// Receiver-side state for decoding one peer's pose stream.
+#[derive(Debug, Default)]
+pub struct PoseDecoder {
+    keyframe: Option<(u16, PoseData)>,
+    last_seq: Option<u16>,
+}
+
+impl PoseDecoder {
+    /// Decode a message. Returns `None` for malformed or out-of-order
+    /// messages and for deltas whose keyframe was lost.
+    pub fn decode(&mut self, bytes: &[u8]) -> Option<PoseData> {
+        let seq = u16::from_le_bytes([*bytes.get(1)?, *bytes.get(2)?]);
+        // Wrapping comparison: drop anything not newer than the last pose
+        if let Some(last) = self.last_seq
+            && seq.wrapping_sub(last) as i16 <= 0
+        {
+            return None;
+        }
+
+        let pose = match (bytes[0], bytes.len()) {
+            (TAG_KEYFRAME, KEYFRAME_LEN) => {
+                let f = |i: usize| f32::from_le_bytes(bytes[3 + i * 4..7 + i * 4].try_into().unwrap());
+                let pose = PoseData {
+                    position: [f(0), f(1), f(2)],
+                    orientation: [f(3), f(4), f(5), f(6)],
+                };
+                self.keyframe = Some((seq, pose.clone()));
+                pose
+            }
+            (TAG_DELTA, DELTA_LEN) => {
+                let base_seq = u16::from_le_bytes([bytes[3], bytes[4]]);
+                let (keyframe_seq, base) = self.keyframe.as_ref()?;
+                if *keyframe_seq != base_seq {
+                    return None;
+                }
+                let s = |i: usize| i16::from_le_bytes([bytes[5 + i * 2], bytes[6 + i * 2]]) as f32;
+                PoseData {
+                    position: [
+                        base.position[0] + s(0) / POSITION_SCALE,
+                        base.position[1] + s(1) / POSITION_SCALE,
+                        base.position[2] + s(2) / POSITION_SCALE,
+                    ],
+                    orientation: normalize4([
+                        s(3) / ORIENTATION_SCALE,
+                        s(4) / ORIENTATION_SCALE,
+                        s(5) / ORIENTATION_SCALE,
+                        s(6) / ORIENTATION_SCALE,
+                    ]),
+                }
+            }
+            _ => return None,
+        };
+
+        self.last_seq = Some(seq);
+        Some(pose)
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Vector helpers
+// ----------------------------------------------------------------------------
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(v: [f32; 3]) -> f32 {
+    dot(v, v).sqrt()
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Rotate a vector by a unit quaternion [x, y, z, w]
+fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let u = [q[0], q[1], q[2]];
+    let t = cross(u, v).map(|c| 2.0 * c);
+    let ut = cross(u, t);
+    [
+        v[0] + q[3] * t[0] + ut[0],
+        v[1] + q[3] * t[1] + ut[1],
+        v[2] + q[3] * t[2] + ut[2],
+    ]
+}
+
+fn normalize4(q: [f32; 4]) -> [f32; 4] {
+    let len = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    if len > f32::EPSILON {
+        q.map(|c| c / len)
+    } else {
+        [0.0, 0.0, 0.0, 1.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pose(position: [f32; 3]) -> PoseData {
+        PoseData {
+            position,
+            orientation: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    fn sent(commands: &[Command]) -> Vec<&[u8]> {
+        commands
+            .iter()
+            .filter_map(|c| match c {
+                Command::Network(NetworkCommand::Rtc(RtcCommand::SendData {
+                    data: DataPayload::Binary(bytes),
+                    ..
+                })) => Some(bytes.as_slice()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_keyframe_round_trip() {
+        let pose = PoseData {
+            position: [1.25, -0.5, 3.0],
+            orientation: [0.0, std::f32::consts::FRAC_1_SQRT_2, 0.0, std::f32::consts::FRAC_1_SQRT_2],
+        };
+        let bytes = encode_keyframe(7, &pose);
+        assert_eq!(bytes.len(), KEYFRAME_LEN);
+
+        let decoded = PoseDecoder::default().decode(&bytes).unwrap();
+        assert_eq!(decoded.position, pose.position);
+        assert_eq!(decoded.orientation, pose.orientation);
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base = pose([1.0, 1.6, -2.0]);
+        let moved = PoseData {
+            position: [1.2345, 1.5, -2.75],
+            orientation: [0.0, 0.3826834, 0.0, 0.9238795],
+        };
+        let mut decoder = PoseDecoder::default();
+        decoder.decode(&encode_keyframe(1, &base)).unwrap();
+
+        let bytes = encode_delta(2, 1, &base, &moved).unwrap();
+        assert_eq!(bytes.len(), DELTA_LEN);
+        let decoded = decoder.decode(&bytes).unwrap();
+        for i in 0..3 {
+            // Positions are quantized to millimetres
+            assert!((decoded.position[i] - moved.position[i]).abs() <= 0.0005 + f32::EPSILON);
+        }
+        for i in 0..4 {
+            assert!((decoded.orientation[i] - moved.orientation[i]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_delta_quantization_bounds() {
+        let base = pose([0.0, 0.0, 0.0]);
+        // 32.767m is the largest offset a delta can carry
+        assert!(encode_delta(1, 0, &base, &pose([32.767, 0.0, -32.767])).is_some());
+        assert!(encode_delta(1, 0, &base, &pose([32.768, 0.0, 0.0])).is_none());
+        assert!(encode_delta(1, 0, &base, &pose([0.0, -40.0, 0.0])).is_none());
+
+        // Out of range quaternion components are clamped, then renormalized
+        let mut decoder = PoseDecoder::default();
+        decoder.decode(&encode_keyframe(0, &base)).unwrap();
+        let skewed = PoseData {
+            position: [0.0; 3],
+            orientation: [0.0, 0.0, 0.0, 2.0],
+        };
+        let decoded = decoder.decode(&encode_delta(1, 0, &base, &skewed).unwrap()).unwrap();
+        assert_eq!(decoded.orientation, [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_delta_without_its_keyframe() {
+        let base = pose([0.0, 1.6, 0.0]);
+        let delta = encode_delta(2, 1, &base, &pose([0.1, 1.6, 0.0])).unwrap();
+
+        // The keyframe was lost
+        assert!(PoseDecoder::default().decode(&delta).is_none());
+
+        // A different keyframe arrived: the delta isn't against it
+        let mut decoder = PoseDecoder::default();
+        decoder.decode(&encode_keyframe(0, &base)).unwrap();
+        assert!(decoder.decode(&delta).is_none());
+
+        // The next keyframe is decoded, and stale messages are dropped
+        assert!(decoder.decode(&encode_keyframe(3, &base)).is_some());
+        assert!(decoder.decode(&encode_keyframe(3, &base)).is_none());
+        assert!(decoder.decode(&delta).is_none());
+        assert!(decoder.decode(&[TAG_KEYFRAME, 4]).is_none());
+    }
+
+    #[test]
+    fn test_rate_follows_distance_and_view() {
+        let session = SharedSession::new(BroadcastConfig::default());
+        let config = BroadcastConfig::default();
+        let local = pose([0.0, 0.0, 0.0]);
+
+        assert_eq!(session.peer_rate(&local, None), config.max_rate);
+        assert_eq!(session.peer_rate(&local, Some(&pose([0.0, 0.0, -1.0]))), config.max_rate);
+        assert_eq!(session.peer_rate(&local, Some(&pose([0.0, 0.0, -50.0]))), config.min_rate);
+        let midway = session.peer_rate(&local, Some(&pose([0.0, 0.0, -11.0])));
+        assert!(midway < config.max_rate && midway > config.min_rate);
+
+        // Behind us: reduced, but never below the minimum
+        let behind = session.peer_rate(&local, Some(&pose([0.0, 0.0, 1.0])));
+        assert_eq!(behind, config.max_rate * config.out_of_view_factor);
+        assert_eq!(session.peer_rate(&local, Some(&pose([0.0, 0.0, 50.0]))), config.min_rate);
+    }
+
+    #[test]
+    fn test_sends_keyframes_deltas_and_heartbeats() {
+        let mut session = SharedSession::new(BroadcastConfig::default());
+        session.add_peer("peer", "pose");
+        session.set_local_pose(pose([0.0, 1.6, 0.0]));
+
+        let first = session.tick(0.0);
+        assert_eq!(sent(&first)[0][0], TAG_KEYFRAME);
+        // Within the peer's interval nothing is sent
+        assert!(session.tick(0.001).is_empty());
+
+        session.set_local_pose(pose([0.1, 1.6, 0.0]));
+        assert_eq!(sent(&session.tick(0.1))[0][0], TAG_DELTA);
+
+        // Unchanged: skipped until the heartbeat is due
+        assert!(session.tick(0.2).is_empty());
+        assert_eq!(sent(&session.tick(0.6)).len(), 1);
+
+        // Keyframes come back at the keyframe interval
+        session.set_local_pose(pose([0.2, 1.6, 0.0]));
+        assert_eq!(sent(&session.tick(1.1))[0][0], TAG_KEYFRAME);
+
+        let metrics = session.metrics("peer").unwrap();
+        assert_eq!((metrics.keyframes_sent, metrics.deltas_sent), (2, 2));
+        assert_eq!(metrics.skipped, 1);
+    }
+
+    #[test]
+    fn test_byte_budget_skips_sends() {
+        let mut session = SharedSession::new(BroadcastConfig {
+            max_bytes_per_second: Some(KEYFRAME_LEN as u32 + DELTA_LEN as u32),
+            ..Default::default()
+        });
+        session.add_peer("peer", "pose");
+
+        for (i, time) in [0.0, 0.1, 0.2, 0.3].into_iter().enumerate() {
+            session.set_local_pose(pose([i as f32 * 0.1, 1.6, 0.0]));
+            session.tick(time);
+        }
+        // A keyframe and one delta fit the budget; the rest wait
+        let metrics = session.metrics("peer").unwrap();
+        assert_eq!((metrics.keyframes_sent, metrics.deltas_sent, metrics.skipped), (1, 1, 2));
+        assert_eq!(metrics.bytes_sent, (KEYFRAME_LEN + DELTA_LEN) as u64);
+
+        // Once the window passes, sends resume
+        session.set_local_pose(pose([1.0, 1.6, 0.0]));
+        assert_eq!(sent(&session.tick(1.05)).len(), 1);
+    }
+}
@@ -0,0 +1,356 @@
+//! Running apps without a shell, for tests
+//!
+//! `TestShell` stands in for a shell: it creates the app's core, sends it
+//! events and keeps every command the core sends back, along with a
+//! `SceneSnapshot` of the volumes, camera and background those commands
+//! built. Nothing is drawn, so tests run anywhere `cargo test` does.
+//!
+//! Like a real shell, the test shell reports each volume ready once it is
+//! created. It doesn't advertise `FEATURE_TRANSFORM_ANIMATION`, so the core
+//! animates entities itself, one `SetTransform` per frame.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::test::TestShell;
+//! use fastn::{Command, EnvironmentCommand};
+//!
+//! #[test]
+//! fn walking_moves_the_camera() {
+//!     let mut shell = TestShell::new(make_content);
+//!     shell.init();
+//!     assert!(shell.scene().is_shown("cube"));
+//!
+//!     shell.key_down("KeyW");
+//!     let commands = shell.advance(0.5);
+//!     assert!(commands.iter().any(|c| matches!(c, Command::Environment(EnvironmentCommand::SetCamera(_)))));
+//! }
+//! ```
+//!
+//! `through_json()` sends events and reads commands as JSON, through the
+//! same bridge a WASM build uses, which also checks that everything the app
+//! sends survives the trip.
+
+use crate::app::App;
+use crate::wasm_bridge::CoreApp;
+use crate::RealityViewContent;
+use fastn_protocol::*;
+use std::collections::BTreeMap;
+
+/// Frame rate of `advance`
+pub const TEST_FPS: f64 = 60.0;
+
+/// A headless shell driving an app's core.
+pub struct TestShell {
+    core: Box<CoreApp>,
+    json: bool,
+    /// Features the shell advertises in `init`
+    features: Vec<String>,
+    /// Replies to commands (e.g. `VolumeReady`), sent after the next event
+    replies: Vec<Event>,
+    /// Every command received, in order
+    commands: Vec<Command>,
+    scene: SceneSnapshot,
+    time: f64,
+    frame: u64,
+}
+
+/// What the commands received so far built.
+#[derive(Debug, Clone, Default)]
+pub struct SceneSnapshot {
+    /// Volumes by ID
+    pub volumes: BTreeMap<VolumeId, VolumeSnapshot>,
+    /// The camera last set
+    pub camera: Option<CameraData>,
+    /// The background last set
+    pub background: Option<BackgroundData>,
+}
+
+/// A volume as the shell would show it.
+#[derive(Debug, Clone)]
+pub struct VolumeSnapshot {
+    pub source: VolumeSource,
+    /// Relative to the parent; animated transforms are at their end
+    pub transform: Transform,
+    /// The material override last set, if any
+    pub material: Option<MaterialOverride>,
+    pub parent_id: Option<VolumeId>,
+    /// Hidden itself (hidden ancestors don't change it)
+    pub visible: bool,
+}
+
+impl TestShell {
+    /// The app a `#[fastn::app]` function builds
+    pub fn new(make_content: impl FnOnce(&mut RealityViewContent)) -> Self {
+        let mut content = RealityViewContent::new();
+        make_content(&mut content);
+        Self::from_content(&content)
+    }
+
+    /// An app for content built beforehand
+    pub fn from_content(content: &RealityViewContent) -> Self {
+        Self::start(CoreApp::new(content))
+    }
+
+    /// A stateful app, which gets every event after the framework
+    pub fn for_app(app: impl App) -> Self {
+        Self::start(CoreApp::with_state(app))
+    }
+
+    fn start(core: Box<CoreApp>) -> Self {
+        let mut shell = Self {
+            core,
+            json: false,
+            features: [FEATURE_TEXT, FEATURE_SCENE_HIERARCHY].map(String::from).to_vec(),
+            replies: vec![],
+            commands: vec![],
+            scene: SceneSnapshot::default(),
+            time: 0.0,
+            frame: 0,
+        };
+        // The core keeps its initial commands as JSON, for the shell to read
+        let commands = shell.read_result();
+        shell.receive(commands);
+        shell
+    }
+
+    /// Send events and read commands as JSON (builder style)
+    pub fn through_json(mut self) -> Self {
+        self.json = true;
+        self
+    }
+
+    /// Features to advertise in `init` instead of text and the scene
+    /// hierarchy (builder style)
+    pub fn features(mut self, features: &[&str]) -> Self {
+        self.features = features.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    /// Send an event, then the replies to the commands it produced, and
+    /// return all the commands that came back
+    pub fn send(&mut self, event: Event) -> Vec<Command> {
+        let mut events = vec![event];
+        events.append(&mut self.replies);
+        let mut received = vec![];
+        while !events.is_empty() {
+            for event in std::mem::take(&mut events) {
+                let commands = self.dispatch(&event);
+                received.extend(commands.iter().cloned());
+                self.receive(commands);
+            }
+            events.append(&mut self.replies);
+        }
+        received
+    }
+
+    /// Send events in order
+    pub fn send_all(&mut self, events: impl IntoIterator<Item = Event>) -> Vec<Command> {
+        events.into_iter().flat_map(|event| self.send(event)).collect()
+    }
+
+    /// Send `Init` from a 1280x720 desktop window with the shell's features
+    pub fn init(&mut self) -> Vec<Command> {
+        self.send(Event::Lifecycle(LifecycleEvent::Init(InitEvent {
+            platform: Platform::Desktop,
+            viewport_width: 1280,
+            viewport_height: 720,
+            dpr: 1.0,
+            xr_supported: false,
+            xr_immersive_vr: false,
+            xr_immersive_ar: false,
+            webrtc_supported: false,
+            websocket_supported: false,
+            features: self.features.clone(),
+            accessibility: AccessibilityPreferences::default(),
+            conventions: Conventions::default(),
+            camera: None,
+        })))
+    }
+
+    /// Send one frame, `dt` seconds after the last
+    pub fn frame(&mut self, dt: f32) -> Vec<Command> {
+        self.time += dt as f64;
+        self.frame += 1;
+        self.send(Event::Lifecycle(LifecycleEvent::Frame(FrameEvent {
+            time: self.time,
+            dt,
+            frame: self.frame,
+        })))
+    }
+
+    /// Send frames at `TEST_FPS` covering `secs` seconds
+    pub fn advance(&mut self, secs: f64) -> Vec<Command> {
+        let frames = (secs * TEST_FPS).round() as u64;
+        (0..frames).flat_map(|_| self.frame((1.0 / TEST_FPS) as f32)).collect()
+    }
+
+    /// Press a key, by its code (e.g. `KeyW`, `Space`)
+    pub fn key_down(&mut self, code: &str) -> Vec<Command> {
+        self.send(Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyDown(key(code)))))
+    }
+
+    /// Release a key
+    pub fn key_up(&mut self, code: &str) -> Vec<Command> {
+        self.send(Event::Input(InputEvent::Keyboard(KeyboardEvent::KeyUp(key(code)))))
+    }
+
+    /// Press and release a key
+    pub fn tap_key(&mut self, code: &str) -> Vec<Command> {
+        let mut commands = self.key_down(code);
+        commands.extend(self.key_up(code));
+        commands
+    }
+
+    /// Every command received so far, the initial ones first
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// The commands received since the last call, leaving the scene as it
+    /// is
+    pub fn take_commands(&mut self) -> Vec<Command> {
+        std::mem::take(&mut self.commands)
+    }
+
+    /// What the commands built
+    pub fn scene(&self) -> &SceneSnapshot {
+        &self.scene
+    }
+
+    fn dispatch(&mut self, event: &Event) -> Vec<Command> {
+        if !self.json {
+            return self.core.on_event(event);
+        }
+        let json = serde_json::to_vec(event).expect("events serialize");
+        if let Err(e) = serde_json::from_slice::<Event>(&json) {
+            panic!("{:?} doesn't survive JSON: {}", event, e);
+        }
+        self.core.on_event_json(&json);
+        self.read_result()
+    }
+
+    fn read_result(&self) -> Vec<Command> {
+        serde_json::from_slice(self.core.result())
+            .unwrap_or_else(|e| panic!("The core's commands don't parse: {}", e))
+    }
+
+    fn receive(&mut self, commands: Vec<Command>) {
+        for command in &commands {
+            if let Command::Scene(SceneCommand::CreateVolume(data)) = command {
+                self.replies.push(Event::Scene(SceneEvent::VolumeReady {
+                    volume_id: data.volume_id.clone(),
+                }));
+            }
+            self.scene.apply(command);
+        }
+        self.commands.extend(commands);
+    }
+}
+
+fn key(code: &str) -> KeyEventData {
+    KeyEventData {
+        device_id: DeviceId::from("keyboard-0"),
+        key: code.to_string(),
+        code: code.to_string(),
+        shift: false,
+        ctrl: false,
+        alt: false,
+        meta: false,
+        repeat: false,
+    }
+}
+
+impl SceneSnapshot {
+    pub fn volume(&self, volume_id: &str) -> Option<&VolumeSnapshot> {
+        self.volumes.get(volume_id)
+    }
+
+    /// IDs of the volumes directly under `volume_id`
+    pub fn children(&self, volume_id: &str) -> Vec<&str> {
+        self.volumes
+            .iter()
+            .filter(|(_, volume)| volume.parent_id.as_deref() == Some(volume_id))
+            .map(|(id, _)| id.as_str())
+            .collect()
+    }
+
+    /// Whether the volume and all its ancestors are visible
+    pub fn is_shown(&self, volume_id: &str) -> bool {
+        let mut id = Some(volume_id);
+        while let Some(volume) = id.and_then(|id| self.volumes.get(id)) {
+            if !volume.visible {
+                return false;
+            }
+            id = volume.parent_id.as_deref();
+        }
+        id.is_none()
+    }
+
+    /// Update the snapshot for a command; commands that don't change the
+    /// scene are ignored
+    pub fn apply(&mut self, command: &Command) {
+        match command {
+            Command::Scene(SceneCommand::CreateVolume(data)) => {
+                let parent_id = data.parent_id.clone().filter(|parent| self.volumes.contains_key(parent));
+                self.volumes.insert(
+                    data.volume_id.clone(),
+                    VolumeSnapshot {
+                        source: data.source.clone(),
+                        transform: data.transform.clone(),
+                        material: data.material.clone(),
+                        parent_id,
+                        visible: true,
+                    },
+                );
+            }
+            Command::Scene(SceneCommand::DestroyVolume { volume_id }) => self.destroy(volume_id),
+            Command::Scene(SceneCommand::SetTransform(data)) => {
+                if let Some(volume) = self.volumes.get_mut(&data.volume_id) {
+                    volume.transform = data.transform.clone();
+                }
+            }
+            Command::Scene(SceneCommand::SetVisible { volume_id, visible }) => {
+                if let Some(volume) = self.volumes.get_mut(volume_id) {
+                    volume.visible = *visible;
+                }
+            }
+            Command::Scene(SceneCommand::SetParent { volume_id, parent_id }) => {
+                let cycle = parent_id.as_deref().is_some_and(|parent| self.is_within(parent, volume_id));
+                if !cycle && let Some(volume) = self.volumes.get_mut(volume_id) {
+                    volume.parent_id = parent_id.clone();
+                }
+            }
+            Command::Material(MaterialCommand::SetMaterial(data)) => {
+                if let Some(volume) = self.volumes.get_mut(&data.volume_id) {
+                    volume.material = Some(data.material.clone());
+                }
+            }
+            Command::Environment(EnvironmentCommand::SetCamera(camera)) => self.camera = Some(camera.clone()),
+            Command::Environment(EnvironmentCommand::SetBackground(background)) => {
+                self.background = Some(background.clone())
+            }
+            _ => {}
+        }
+    }
+
+    /// Remove a volume and its descendants
+    fn destroy(&mut self, volume_id: &str) {
+        for child in self.children(volume_id).into_iter().map(String::from).collect::<Vec<_>>() {
+            self.destroy(&child);
+        }
+        self.volumes.remove(volume_id);
+    }
+
+    /// Whether `volume_id` is `ancestor_id` or below it
+    fn is_within(&self, volume_id: &str, ancestor_id: &str) -> bool {
+        let mut id = Some(volume_id);
+        while let Some(current) = id {
+            if current == ancestor_id {
+                return true;
+            }
+            id = self.volumes.get(current).and_then(|volume| volume.parent_id.as_deref());
+        }
+        false
+    }
+}
@@ -0,0 +1,154 @@
+//! Text labels
+//!
+//! A `TextEntity` is a model entity whose mesh is text
+//! (`MeshResource::generate_text`): the shell lays the text out and draws it
+//! flat, facing +Z, in the given color. Billboard labels turn to face the
+//! viewer wherever they are.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use fastn::{RealityViewContent, TextAlignment, TextEntity};
+//!
+//! #[fastn::app]
+//! fn app(content: &mut RealityViewContent) {
+//!     content.add(TextEntity::new("Welcome").font_size(0.2).position(0.0, 2.0, -3.0));
+//!     content.add(
+//!         TextEntity::with_id("sign", "Press the red button\nto open the door")
+//!             .color(1.0, 0.9, 0.2)
+//!             .alignment(TextAlignment::Leading)
+//!             .max_width(0.8)
+//!             .billboard()
+//!             .position(1.0, 1.5, -2.0),
+//!     );
+//!     content.on_tap("sign", |ctx| ctx.announce("The door is locked"));
+//! }
+//! ```
+
+use crate::entity::{EntityKind, ModelEntity};
+use crate::material::SimpleMaterial;
+use crate::mesh::MeshResource;
+use fastn_protocol::TextAlignment;
+
+/// A text label in the scene.
+///
+/// Converts into a `ModelEntity` with the same ID, so handlers and
+/// animations address it like any other volume.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEntity {
+    pub id: Option<String>,
+    pub text: String,
+    /// Em size in meters
+    pub font_size: f32,
+    pub color: [f32; 4],
+    pub alignment: TextAlignment,
+    /// Width in meters at which lines wrap, None to only break at `\n`
+    pub max_width: Option<f32>,
+    pub billboard: bool,
+    pub position: [f32; 3],
+    pub orientation: [f32; 4],
+}
+
+impl TextEntity {
+    /// White, centered text, 10cm to the em, at the origin.
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            id: None,
+            text: text.into(),
+            font_size: 0.1,
+            color: [1.0, 1.0, 1.0, 1.0],
+            alignment: TextAlignment::Center,
+            max_width: None,
+            billboard: false,
+            position: [0.0, 0.0, 0.0],
+            orientation: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    /// Create a label with a specific ID.
+    pub fn with_id(id: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            id: Some(id.into()),
+            ..Self::new(text)
+        }
+    }
+
+    /// Set the em size in meters (builder style).
+    pub fn font_size(mut self, font_size: f32) -> Self {
+        self.font_size = font_size;
+        self
+    }
+
+    /// Set the text color (builder style).
+    pub fn color(mut self, r: f32, g: f32, b: f32) -> Self {
+        self.color = [r, g, b, 1.0];
+        self
+    }
+
+    /// Set the text color with transparency (builder style).
+    pub fn color_with_alpha(mut self, r: f32, g: f32, b: f32, a: f32) -> Self {
+        self.color = [r, g, b, a];
+        self
+    }
+
+    /// Set how lines line up (builder style).
+    pub fn alignment(mut self, alignment: TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Wrap lines wider than `width` meters (builder style).
+    pub fn max_width(mut self, width: f32) -> Self {
+        self.max_width = Some(width);
+        self
+    }
+
+    /// Always face the viewer, staying upright (builder style).
+    pub fn billboard(mut self) -> Self {
+        self.billboard = true;
+        self
+    }
+
+    /// Set position with individual components (builder style).
+    pub fn position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.position = [x, y, z];
+        self
+    }
+
+    /// Set the orientation as a quaternion; billboards ignore it (builder
+    /// style).
+    pub fn orientation(mut self, orientation: [f32; 4]) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    fn mesh(&self) -> MeshResource {
+        MeshResource::Text {
+            text: self.text.clone(),
+            font_size: self.font_size,
+            alignment: self.alignment,
+            max_width: self.max_width,
+            billboard: self.billboard,
+        }
+    }
+}
+
+impl From<TextEntity> for ModelEntity {
+    fn from(text: TextEntity) -> Self {
+        let [r, g, b, a] = text.color;
+        let material = SimpleMaterial::new().color_with_alpha(r, g, b, a);
+        let mut model = match &text.id {
+            Some(id) => ModelEntity::with_id(id.clone(), text.mesh(), material),
+            None => ModelEntity::new(text.mesh(), material),
+        };
+        model.set_position(text.position);
+        model.set_orientation(text.orientation);
+        model
+    }
+}
+
+impl From<TextEntity> for EntityKind {
+    fn from(text: TextEntity) -> Self {
+        EntityKind::ModelEntity(text.into())
+    }
+}
@@ -5,14 +5,77 @@
 //!
 //! Design: No global state. The shell owns a pointer to CoreApp which holds all state.
 
+use crate::accessibility::AccessibilityTree;
+use crate::animation::Animations;
+use crate::app::App;
+use crate::asset_uri::supported_schemes;
+use crate::audio::AudioSources;
+use crate::bookmark::Bookmarks;
 use crate::camera::CameraController;
-use fastn_protocol::{Command, Event};
+use crate::capture::Viewfinders;
+use crate::conventions::ShellConventions;
+use crate::debug_hud::DebugHud;
+use crate::gizmo::Gizmos;
+use crate::panel::Panels;
+use crate::handlers::EventHandlers;
+use crate::lighting::{Lights, LIGHTS_PER_VOLUME};
+use crate::physics::{Physics, DEFAULT_GRAVITY};
+use crate::portal::Portals;
+use crate::raycast::Scene;
+use crate::remote_config::FeatureFlags;
+use crate::resume::SessionRestore;
+use crate::schedule::CommandScheduler;
+use crate::AssetUri;
+use fastn_protocol::*;
+use std::rc::Rc;
 
 /// The core application state that the shell owns.
 /// This struct holds all state - no thread-locals or globals.
 pub struct CoreApp {
     /// Camera controller for default input handling
     camera: CameraController,
+    /// Spatial bookmarks and teleport transitions
+    bookmarks: Bookmarks,
+    /// Portals and crossing detection
+    portals: Portals,
+    /// Panels showing what captures see
+    viewfinders: Viewfinders,
+    /// The app's 2D panels
+    panels: Panels,
+    /// Sound sources and their per-frame mix
+    audio: AudioSources,
+    /// Point lights and the volumes each lights
+    lights: Lights,
+    /// Spreads the startup asset loads across frames
+    scheduler: CommandScheduler,
+    /// What screen readers see of the scene
+    accessibility: AccessibilityTree,
+    /// Debug overlay toggle
+    debug_hud: DebugHud,
+    /// Saves the session on pause and restores it at launch
+    resume: SessionRestore,
+    /// Entity animations and their callbacks
+    animations: Animations,
+    /// Rigid bodies, moved by the simulation
+    physics: Physics,
+    /// The app's event callbacks
+    handlers: EventHandlers,
+    /// The app's manipulation handles
+    gizmos: Gizmos,
+    /// Shapes of the entities, for ray casts
+    scene: Rc<Scene>,
+    /// Feature flags and the remote config setting them
+    flags: Rc<FeatureFlags>,
+    /// The shell's coordinate conventions
+    conventions: ShellConventions,
+    /// The app's own state, for apps implementing `App`
+    app: Option<Box<dyn App>>,
+    /// Asset URIs requested so far, checked against the shell's schemes
+    asset_uris: Vec<String>,
+    /// Text volumes of the scene, which some shells can't draw
+    text_volumes: usize,
+    /// Volumes attached to another, which some shells place at the top level
+    child_volumes: usize,
     /// Result buffer for returning JSON to the shell
     result_buffer: Vec<u8>,
 }
@@ -20,9 +83,87 @@ pub struct CoreApp {
 impl CoreApp {
     /// Create a new CoreApp and populate initial commands
     pub fn new(content: &crate::RealityViewContent) -> Box<Self> {
-        let commands = content.to_commands();
+        let mut commands = content.to_commands();
+        let bookmarks = Bookmarks::new(content.bookmarks.clone());
+        commands.extend(bookmarks.init_commands());
+        let portals = Portals::new(content.portals.clone());
+        commands.extend(portals.init_commands());
+        let resume = SessionRestore::new(!content.resume_disabled);
+        commands.extend(resume.init_commands());
+        let viewfinders = Viewfinders::new(content.viewfinders.clone());
+        commands.extend(viewfinders.init_commands());
+        let panels = Panels::new(content.panels.clone());
+        commands.extend(panels.init_commands());
+        let (audio_sources, audio_errors) = content.resolve_audio_sources();
+        commands.extend(audio_errors);
+        let audio = AudioSources::new(audio_sources, &content.entities);
+        let lights = Lights::new(
+            content.lights.clone(),
+            content.lights_per_volume.unwrap_or(LIGHTS_PER_VOLUME),
+            &content.entities,
+        );
+        let mut accessibility = AccessibilityTree::new(&content.entities);
+        accessibility.set_framework_nodes(bookmarks.accessibility_nodes());
+        commands.extend(accessibility.init_commands());
+        let asset_uris = commands
+            .iter()
+            .filter_map(|c| match c {
+                Command::Asset(AssetCommand::Load { path, .. }) => Some(path.clone()),
+                _ => None,
+            })
+            .collect();
+        let text_volumes = commands
+            .iter()
+            .filter(|c| {
+                matches!(
+                    c,
+                    Command::Scene(SceneCommand::CreateVolume(CreateVolumeData {
+                        source: VolumeSource::Primitive(Primitive::Text { .. }),
+                        ..
+                    }))
+                )
+            })
+            .count();
+        let child_volumes = commands
+            .iter()
+            .filter(|c| {
+                matches!(
+                    c,
+                    Command::Scene(SceneCommand::CreateVolume(CreateVolumeData { parent_id: Some(_), .. }))
+                )
+            })
+            .count();
+        let debug_hud = DebugHud::new(&content.entities);
+        let animations = Animations::new(&content.entities);
+        let physics = Physics::new(&content.entities, content.gravity.unwrap_or(DEFAULT_GRAVITY));
+        let scene = Rc::new(Scene::new(&content.entities, content.raycast_options));
+        let flags = Rc::new(FeatureFlags::new(content.flags.clone(), content.remote_config.clone()));
+        let mut scheduler = CommandScheduler::new();
+        let commands = scheduler.hold(commands);
+        let conventions = ShellConventions::new(&commands);
         let mut app = Box::new(Self {
-            camera: CameraController::new(),
+            camera: CameraController::with_controls(content.camera_controls.clone()),
+            bookmarks,
+            portals,
+            viewfinders,
+            panels,
+            audio,
+            lights,
+            scheduler,
+            accessibility,
+            debug_hud,
+            resume,
+            animations,
+            physics,
+            handlers: content.handlers.clone(),
+            gizmos: Gizmos::new(content.handles.clone()),
+            scene,
+            flags,
+            conventions,
+            app: None,
+            asset_uris,
+            text_volumes,
+            child_volumes,
             result_buffer: Vec::new(),
         });
         // Store initial commands in result buffer
@@ -32,7 +173,124 @@ impl CoreApp {
 
     /// Process an event and return commands
     pub fn on_event(&mut self, event: &Event) -> Vec<Command> {
-        self.camera.handle_event(event)
+        // Init sets the conventions, so it's seen before anything is converted
+        let mut commands = self.conventions.handle_event(event);
+        let event = self.conventions.event_from_shell(event);
+        let event = event.as_ref();
+        commands.extend(self.scheduler.handle_event(event));
+        commands.extend(self.bookmarks.handle_event(event, &mut self.camera));
+        // Saved or loaded bookmarks add markers
+        commands.extend(self.accessibility.set_framework_nodes(self.bookmarks.accessibility_nodes()));
+        commands.extend(self.camera.handle_event(event));
+        commands.extend(self.portals.handle_event(event, &mut self.camera));
+        commands.extend(self.viewfinders.handle_event(event));
+        commands.extend(self.panels.handle_event(event));
+        commands.extend(self.debug_hud.handle_event(event));
+        commands.extend(self.resume.handle_event(event, &mut self.camera, &mut self.portals, &mut self.debug_hud));
+        commands.extend(self.animations.handle_event(event));
+        let (moved, collisions) = self.physics.handle_event(event, &mut self.animations);
+        commands.extend(moved);
+        // Copied only while an earlier event's callbacks still hold the scene
+        // (or the flags)
+        Rc::make_mut(&mut self.scene).sync(&self.animations);
+        commands.extend(Rc::make_mut(&mut self.flags).handle_event(event));
+        commands.extend(self.gizmos.handle_event(event, &mut self.camera, &mut self.animations, &self.scene, &self.flags));
+        commands.extend(self.audio.handle_event(event, &self.camera, &self.animations));
+        commands.extend(self.lights.handle_event(event, &self.animations));
+        let (handled, animator) = self.handlers.handle_event(event, &self.scene, &self.flags, &mut self.camera);
+        commands.extend(handled);
+        commands.extend(self.animations.apply(animator));
+        if let Some(app) = &mut self.app {
+            commands.extend(app.update(event));
+        }
+        // Collisions the step found, for the same callbacks as shell events
+        for collision in &collisions {
+            let (handled, animator) = self.handlers.handle_event(collision, &self.scene, &self.flags, &mut self.camera);
+            commands.extend(handled);
+            commands.extend(self.animations.apply(animator));
+            if let Some(app) = &mut self.app {
+                commands.extend(app.update(collision));
+            }
+        }
+        if let Event::Lifecycle(LifecycleEvent::Init(init)) = event {
+            commands.extend(self.check_asset_schemes(&init.features));
+            commands.extend(self.check_text(&init.features));
+            commands.extend(self.check_hierarchy(&init.features));
+        }
+        // Callbacks start and stop sounds
+        self.audio.observe(&commands);
+        commands.into_iter().map(|c| self.conventions.command_to_shell(c)).collect()
+    }
+
+    /// Warn about requested assets whose scheme the shell can't load
+    fn check_asset_schemes(&self, features: &[String]) -> Vec<Command> {
+        let supported = supported_schemes(features);
+        self.asset_uris
+            .iter()
+            .filter_map(|uri| {
+                let scheme = AssetUri::parse(uri).ok()?.builtin_scheme()?;
+                (!supported.contains(&scheme)).then(|| {
+                    Command::Debug(DebugCommand::Log {
+                        level: LogLevel::Warn,
+                        message: format!("Shell does not support {}:// assets, cannot load {}", scheme.as_str(), uri),
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Warn when the shell can't draw the scene's text
+    fn check_text(&self, features: &[String]) -> Option<Command> {
+        if self.text_volumes == 0 || features.iter().any(|f| f == FEATURE_TEXT) {
+            return None;
+        }
+        Some(Command::Debug(DebugCommand::Log {
+            level: LogLevel::Warn,
+            message: format!("Shell cannot draw text, {} text entities stay invisible", self.text_volumes),
+        }))
+    }
+
+    /// Warn when the shell can't place volumes relative to their parents
+    fn check_hierarchy(&self, features: &[String]) -> Option<Command> {
+        if self.child_volumes == 0 || features.iter().any(|f| f == FEATURE_SCENE_HIERARCHY) {
+            return None;
+        }
+        Some(Command::Debug(DebugCommand::Log {
+            level: LogLevel::Warn,
+            message: format!(
+                "Shell has no scene hierarchy, {} child entities are placed as if at the top level",
+                self.child_volumes
+            ),
+        }))
+    }
+
+    /// Create a CoreApp for an `App`, which builds the content and then gets
+    /// every event
+    pub(crate) fn with_state(mut state: impl App) -> Box<Self> {
+        let mut content = crate::RealityViewContent::new();
+        state.init(&mut content);
+        let mut app = Self::new(&content);
+        app.app = Some(Box::new(state));
+        app
+    }
+
+    /// Process an event sent as JSON, like the shell does, and store the
+    /// commands as JSON in the result buffer. Events that don't parse get
+    /// no commands.
+    pub(crate) fn on_event_json(&mut self, event_bytes: &[u8]) {
+        let event = std::str::from_utf8(event_bytes)
+            .ok()
+            .and_then(|json| serde_json::from_str::<Event>(json).ok());
+        let commands = match event {
+            Some(event) => self.on_event(&event),
+            None => vec![],
+        };
+        self.store_commands_internal(&commands);
+    }
+
+    /// The initial commands or the last event's, as JSON
+    pub(crate) fn result(&self) -> &[u8] {
+        &self.result_buffer
     }
 
     /// Store commands as JSON in the result buffer
@@ -62,6 +320,13 @@ pub fn create_app(content: &crate::RealityViewContent) -> *mut CoreApp {
     Box::into_raw(CoreApp::new(content))
 }
 
+/// Create a CoreApp for an `App`, which builds the content and then gets
+/// every event
+#[doc(hidden)]
+pub fn create_stateful_app(state: impl App) -> *mut CoreApp {
+    Box::into_raw(CoreApp::with_state(state))
+}
+
 /// Get pointer to the result buffer (initial commands or last on_event result)
 ///
 /// # Safety
@@ -91,27 +356,8 @@ pub unsafe fn get_result_len(app_ptr: *const CoreApp) -> usize {
 #[doc(hidden)]
 pub unsafe fn app_on_event(app_ptr: *mut CoreApp, event_ptr: *const u8, event_len: usize) -> *const u8 {
     let app = unsafe { &mut *app_ptr };
-
-    // Parse the event JSON
     let event_bytes = unsafe { std::slice::from_raw_parts(event_ptr, event_len) };
-    let event_json = match std::str::from_utf8(event_bytes) {
-        Ok(s) => s,
-        Err(_) => {
-            app.store_commands_internal(&[]);
-            return app.result_ptr();
-        }
-    };
-
-    let event: Event = match serde_json::from_str(event_json) {
-        Ok(e) => e,
-        Err(_) => {
-            app.store_commands_internal(&[]);
-            return app.result_ptr();
-        }
-    };
-
-    let commands = app.on_event(&event);
-    app.store_commands_internal(&commands);
+    app.on_event_json(event_bytes);
     app.result_ptr()
 }
 
@@ -0,0 +1,241 @@
+//! Development tasks for the fastn workspace
+//!
+//! Usage: cargo xtask <task> [args]
+//!
+//! Tasks:
+//! - `golden [--bless] [SCENE...]` - render the canonical scenes with the
+//!   native shell and compare them with the stored golden images
+//! - `bench [--save] [--check] [--threshold PCT] [FILTER...]` - run the core's
+//!   pipeline benchmarks and compare them with the last saved run
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+/// Change (in percent of the median) beyond which a benchmark counts as
+/// regressed, unless `--threshold` says otherwise
+const DEFAULT_THRESHOLD: f64 = 10.0;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("golden") => golden(&args[1..]),
+        Some("bench") => bench(&args[1..]),
+        _ => {
+            eprintln!("Usage: cargo xtask <task> [args]");
+            eprintln!();
+            eprintln!("Tasks:");
+            eprintln!("  golden [--bless] [SCENE...]  Run the renderer's golden image tests");
+            eprintln!("                               (--bless stores the renders as the new goldens)");
+            eprintln!("  bench [--save] [--check] [--threshold PCT] [FILTER...]");
+            eprintln!("                               Run the pipeline benchmarks and compare them with the");
+            eprintln!("                               last saved run (--save records this one, --check fails");
+            eprintln!("                               on regressions beyond the threshold, {}% by default)", DEFAULT_THRESHOLD);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run the golden tests in the native shell, built in release mode so
+/// software renderers stay fast
+fn golden(args: &[String]) -> ExitCode {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args(["run", "--release", "--package", "fastn-shell", "--", "golden"])
+        .args(args)
+        .status();
+    match status {
+        Ok(status) if status.success() => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Failed to run cargo: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Median and fastest time per iteration of each benchmark, in ns
+type Results = BTreeMap<String, Timing>;
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+struct Timing {
+    median_ns: f64,
+    min_ns: f64,
+}
+
+/// A saved run, one per line of the history
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct Run {
+    /// Unix time of the run
+    time: u64,
+    /// Commit the run was made at, with `-dirty` for uncommitted changes
+    commit: String,
+    results: Results,
+}
+
+/// Run the pipeline benchmarks (fastn's `benches/pipeline.rs`), compare
+/// each one with the last saved run and optionally save this one
+///
+/// Timings depend on the machine, so the history stays in
+/// `target/bench/history.jsonl` rather than in the repository.
+fn bench(args: &[String]) -> ExitCode {
+    let (mut save, mut check, mut threshold, mut filters) = (false, false, DEFAULT_THRESHOLD, vec![]);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--save" => save = true,
+            "--check" => check = true,
+            "--threshold" => match args.next().and_then(|t| t.parse().ok()) {
+                Some(t) => threshold = t,
+                None => {
+                    eprintln!("--threshold takes a percentage");
+                    return ExitCode::FAILURE;
+                }
+            },
+            filter => filters.push(filter.to_string()),
+        }
+    }
+
+    let dir = workspace_root().join("target").join("bench");
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("Failed to create {}: {}", dir.display(), e);
+        return ExitCode::FAILURE;
+    }
+    let latest = dir.join("latest.json");
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args(["bench", "--package", "fastn", "--no-default-features", "--bench", "pipeline", "--"])
+        .args(&filters)
+        .arg("--output")
+        .arg(&latest)
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(_) => return ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Failed to run cargo: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    let results: Results = match std::fs::read_to_string(&latest).map(|json| serde_json::from_str(&json)) {
+        Ok(Ok(results)) => results,
+        Ok(Err(e)) => {
+            eprintln!("Failed to parse {}: {}", latest.display(), e);
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", latest.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let history = dir.join("history.jsonl");
+    let regressed = match last_run(&history) {
+        Some(baseline) => compare(&baseline, &results, threshold),
+        None => {
+            println!();
+            println!("No saved run to compare with yet (save one with --save)");
+            0
+        }
+    };
+
+    if save {
+        let run = Run {
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            commit: current_commit(),
+            results,
+        };
+        if let Err(e) = append_run(&history, &run) {
+            eprintln!("Failed to save the run to {}: {}", history.display(), e);
+            return ExitCode::FAILURE;
+        }
+        println!("Saved as the run to compare with next");
+    }
+
+    if check && regressed > 0 {
+        eprintln!("{} benchmark(s) regressed by more than {}%", regressed, threshold);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn workspace_root() -> PathBuf {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    manifest_dir.parent().unwrap_or(manifest_dir).to_path_buf()
+}
+
+/// The newest saved run, None if there is none (or the history is unreadable)
+fn last_run(history: &Path) -> Option<Run> {
+    let history = std::fs::read_to_string(history).ok()?;
+    let line = history.lines().rev().find(|line| !line.trim().is_empty())?;
+    match serde_json::from_str(line) {
+        Ok(run) => Some(run),
+        Err(e) => {
+            eprintln!("Ignoring the last saved run: {}", e);
+            None
+        }
+    }
+}
+
+fn append_run(history: &Path, run: &Run) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let line = serde_json::to_string(run).map_err(std::io::Error::other)?;
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(history)?;
+    writeln!(file, "{}", line)
+}
+
+fn current_commit() -> String {
+    let git = |args: &[&str]| Command::new("git").args(args).current_dir(workspace_root()).output().ok();
+    let Some(commit) = git(&["rev-parse", "--short", "HEAD"]).filter(|o| o.status.success()) else {
+        return "unknown".to_string();
+    };
+    let commit = String::from_utf8_lossy(&commit.stdout).trim().to_string();
+    match git(&["status", "--porcelain"]) {
+        Some(status) if !status.stdout.is_empty() => format!("{}-dirty", commit),
+        _ => commit,
+    }
+}
+
+/// Print how each benchmark moved since `baseline`, and return how many
+/// got slower by more than `threshold` percent
+fn compare(baseline: &Run, results: &Results, threshold: f64) -> usize {
+    println!();
+    println!("Compared with the run saved at {}:", baseline.commit);
+    let mut regressed = 0;
+    for (name, timing) in results {
+        let Some(before) = baseline.results.get(name) else {
+            println!("  {:<36} {:>12}  new", name, format_ns(timing.median_ns));
+            continue;
+        };
+        let change = (timing.median_ns - before.median_ns) / before.median_ns * 100.0;
+        let verdict = if change > threshold {
+            regressed += 1;
+            "REGRESSED"
+        } else if change < -threshold {
+            "improved"
+        } else {
+            ""
+        };
+        println!(
+            "  {:<36} {:>12} -> {:>12}  {:>+7.1}%  {}",
+            name,
+            format_ns(before.median_ns),
+            format_ns(timing.median_ns),
+            change,
+            verdict
+        );
+    }
+    regressed
+}
+
+fn format_ns(ns: f64) -> String {
+    match ns {
+        ns if ns >= 1e6 => format!("{:.2} ms", ns / 1e6),
+        ns if ns >= 1e3 => format!("{:.2} µs", ns / 1e3),
+        ns => format!("{:.1} ns", ns),
+    }
+}